@@ -11,6 +11,7 @@ use bevy::{
     reflect::ReflectFromPtr,
 };
 use bincode::{DefaultOptions, Options};
+use networking::Token;
 use thiserror::Error;
 
 use crate::reflect::ReflectEvent;
@@ -29,7 +30,11 @@ pub enum ComponentTypeAdapter {
 #[derive(Clone)]
 pub enum EventTypeAdapter {
     // TODO: Make a type to store this function, or come up with a better approch
-    Serde(ReflectSerdeAdapter, unsafe fn(&mut World, OwningPtr<'_>)),
+    /// The function sends the event along with the peer it was received from
+    Serde(
+        ReflectSerdeAdapter,
+        unsafe fn(&mut World, OwningPtr<'_>, Token),
+    ),
     Reflect(ReflectFromPtr, ReflectEvent),
 }
 
@@ -1,116 +1,426 @@
-use bevy::{core::Name, ecs::bundle::Bundle, transform::components::Transform};
-
-use crate::components::{
-    ActualForce, ActualMovement, Armed, Camera, Cores, CpuTotal, CurrentDraw, Depth, Disks,
-    Inertial, Leak, LoadAverage, Magnetic, MeasuredVoltage, Memory, MotorDefinition, Motors,
-    MovementAxisMaximums, MovementContribution, MovementCurrentCap, Networks, OperatingSystem,
-    Orientation, Processes, PwmChannel, PwmSignal, Robot, RobotId, RobotStatus, ServoDefinition,
-    ServoMode, ServoTargets, TargetForce, TargetMovement, Temperatures, Uptime,
+use bevy::{
+    app::App, core::Name, ecs::bundle::Bundle, reflect::TypePath, transform::components::Transform,
 };
 
-#[derive(Bundle, PartialEq)]
-pub struct RobotBundle {
-    pub core: RobotCoreBundle,
-    pub sensors: RobotSensorBundle,
-    pub system: RobotSystemBundle,
-    pub actuators: RobotActuatorBundle,
-    pub power: RobotPowerBundle,
-    // pub manual: Option<PwmManualControl>,
-}
+use crate::{
+    components::{
+        ActualForce, ActualMovement, Armed, Camera, Cores, CpuTotal, CurrentDraw, Depth, Disks,
+        Estimated, Inertial, Leak, LoadAverage, Magnetic, MeasuredVoltage, Memory, MotorDefinition,
+        Motors, MovementAxisMaximums, MovementContribution, MovementCurrentCap, Networks,
+        OperatingSystem, Orientation, Processes, PwmChannel, PwmSignal, Robot, RobotId,
+        RobotStatus, ServoDefinition, ServoMode, TargetForce, TargetMovement, Temperatures, Uptime,
+    },
+    ecs_sync::{
+        round_trip::default_sample, AppReplicateExt, ReplicableBundle, ReplicationDirection,
+    },
+};
 
-#[derive(Bundle, PartialEq)]
-pub struct RobotCoreBundle {
-    pub marker: Robot,
-    pub status: RobotStatus,
-    pub name: Name,
+/// Defines bundles that register every field for replication
+///
+/// Fields are replicated with the serde adapter by default, `=> reflect` uses the reflect adapter
+/// and `=> bundle` registers a nested [`ReplicableBundle`]. Any other `=> Direction` replicates
+/// with the serde adapter in only that [`ReplicationDirection`].
+macro_rules! replicable_bundles {
+    (@replicate $app:ident, $ty:ty, bundle) => {
+        <$ty as ReplicableBundle>::replicate_components($app);
+    };
+    (@replicate $app:ident, $ty:ty, reflect) => {
+        $app.replicate_reflect::<$ty>();
+        default_sample!($app, $ty);
+    };
+    (@replicate $app:ident, $ty:ty, $direction:ident) => {
+        $app.replicate_dir::<$ty>(ReplicationDirection::$direction);
+        default_sample!($app, $ty);
+    };
+    (@replicate $app:ident, $ty:ty) => {
+        $app.replicate::<$ty>();
+        default_sample!($app, $ty);
+    };
 
-    pub robot_id: RobotId,
-}
+    (@type_path $paths:ident, $ty:ty, bundle) => {
+        <$ty as ReplicableBundle>::component_type_paths($paths);
+    };
+    (@type_path $paths:ident, $ty:ty $(, $adapter:ident)?) => {
+        $paths.push(<$ty as TypePath>::type_path());
+    };
+
+    ($(
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                pub $field:ident: $ty:ty $(=> $adapter:ident)?
+            ),* $(,)?
+        }
+    )*) => {
+        $(
+            $(#[$meta])*
+            pub struct $name {
+                $(
+                    $(#[$field_meta])*
+                    pub $field: $ty,
+                )*
+            }
+
+            impl ReplicableBundle for $name {
+                fn replicate_components(app: &mut App) {
+                    $(replicable_bundles!(@replicate app, $ty $(, $adapter)?);)*
+                }
 
-#[derive(Bundle, PartialEq)]
-pub struct RobotSensorBundle {
-    pub orientation: Orientation,
-    pub inertial: Inertial,
-    pub mag: Magnetic,
-    pub depth: Depth,
-    pub leak: Leak,
+                fn component_type_paths(paths: &mut Vec<&'static str>) {
+                    $(replicable_bundles!(@type_path paths, $ty $(, $adapter)?);)*
+                }
+            }
+        )*
+    };
 }
 
-#[derive(Bundle, PartialEq)]
-pub struct RobotSystemBundle {
-    pub processes: Processes,
-    pub load_average: LoadAverage,
-    pub networks: Networks,
-    pub cpu: CpuTotal,
-    pub cores: Cores,
-    pub memory: Memory,
-    pub temps: Temperatures,
-    pub disks: Disks,
-    pub uptime: Uptime,
-    pub os: OperatingSystem,
+pub fn register_bundles(app: &mut App) {
+    app.replicate_bundle::<RobotBundle>()
+        .replicate_bundle::<CameraBundle>()
+        .replicate_bundle::<MotorBundle>()
+        .replicate_bundle::<ServoBundle>()
+        .replicate_bundle::<MovementContributionBundle>();
 }
 
-#[derive(Bundle, PartialEq)]
-pub struct RobotActuatorBundle {
-    pub movement_target: TargetMovement,
-    pub movement_actual: ActualMovement,
+replicable_bundles! {
+    #[derive(Bundle, PartialEq)]
+    pub struct RobotBundle {
+        pub core: RobotCoreBundle => bundle,
+        pub sensors: RobotSensorBundle => bundle,
+        pub system: RobotSystemBundle => bundle,
+        pub actuators: RobotActuatorBundle => bundle,
+        pub power: RobotPowerBundle => bundle,
+        // pub manual: Option<PwmManualControl>,
+    }
 
-    pub motor_config: Motors,
-    pub axis_maximums: MovementAxisMaximums,
-    pub current_cap: MovementCurrentCap,
+    #[derive(Bundle, PartialEq)]
+    pub struct RobotCoreBundle {
+        pub marker: Robot => ServerToClient,
+        pub status: RobotStatus => ServerToClient,
+        pub name: Name => reflect,
 
-    pub armed: Armed,
-}
+        pub robot_id: RobotId,
+    }
 
-// TODO(mid): Sensor not implemented
-#[derive(Bundle, PartialEq)]
-pub struct RobotPowerBundle {
-    pub voltage: MeasuredVoltage,
-    pub current_draw: CurrentDraw,
-}
+    #[derive(Bundle, PartialEq)]
+    pub struct RobotSensorBundle {
+        pub orientation: Orientation => ServerToClient,
+        pub inertial: Inertial => ServerToClient,
+        pub mag: Magnetic => ServerToClient,
+        pub depth: Depth => ServerToClient,
+        pub leak: Leak => ServerToClient,
+    }
 
-#[derive(Bundle, PartialEq)]
-pub struct CameraBundle {
-    pub name: Name,
-    pub camera: Camera,
-    pub transform: Transform,
+    #[derive(Bundle, PartialEq)]
+    pub struct RobotSystemBundle {
+        pub processes: Processes => ServerToClient,
+        pub load_average: LoadAverage => ServerToClient,
+        pub networks: Networks => ServerToClient,
+        pub cpu: CpuTotal => ServerToClient,
+        pub cores: Cores => ServerToClient,
+        pub memory: Memory => ServerToClient,
+        pub temps: Temperatures => ServerToClient,
+        pub disks: Disks => ServerToClient,
+        pub uptime: Uptime => ServerToClient,
+        pub os: OperatingSystem => ServerToClient,
+    }
 
-    pub robot: RobotId,
-}
+    #[derive(Bundle, PartialEq)]
+    pub struct RobotActuatorBundle {
+        pub movement_target: TargetMovement => ServerToClient,
+        pub movement_actual: ActualMovement => ServerToClient,
 
-#[derive(Bundle, PartialEq)]
-pub struct MotorBundle {
-    pub actuator: PwmActuatorBundle,
+        pub motor_config: Motors => ServerToClient,
+        pub axis_maximums: MovementAxisMaximums => ServerToClient,
+        pub current_cap: MovementCurrentCap => ServerToClient,
 
-    pub motor: MotorDefinition,
+        /// Only the robot changes this, surfaces send [`crate::events::ArmRequest`]s
+        pub armed: Armed => ServerToClient,
+    }
 
-    pub target_force: TargetForce,
-    pub actual_force: ActualForce,
-    pub current_draw: CurrentDraw,
-}
+    // TODO(mid): Sensor not implemented
+    #[derive(Bundle, PartialEq)]
+    pub struct RobotPowerBundle {
+        pub voltage: MeasuredVoltage => ServerToClient,
+        pub current_draw: CurrentDraw => ServerToClient,
+    }
 
-#[derive(Bundle, PartialEq)]
-pub struct ServoBundle {
-    pub actuator: PwmActuatorBundle,
+    #[derive(Bundle, PartialEq)]
+    pub struct CameraBundle {
+        pub name: Name => reflect,
+        pub camera: Camera => ServerToClient,
+        pub transform: Transform => ServerToClient,
 
-    pub servo: ServoDefinition,
-    pub servo_mode: ServoMode,
-}
+        pub robot: RobotId,
+    }
+
+    #[derive(Bundle, PartialEq)]
+    pub struct MotorBundle {
+        pub actuator: PwmActuatorBundle => bundle,
+
+        pub motor: MotorDefinition => ServerToClient,
+
+        pub target_force: TargetForce => ServerToClient,
+        pub actual_force: ActualForce => ServerToClient,
+        pub current_draw: CurrentDraw => ServerToClient,
+        pub estimated: Estimated => ServerToClient,
+    }
+
+    #[derive(Bundle, PartialEq)]
+    pub struct ServoBundle {
+        pub actuator: PwmActuatorBundle => bundle,
+
+        pub servo: ServoDefinition => ServerToClient,
+        pub servo_mode: ServoMode => ServerToClient,
+    }
+
+    #[derive(Bundle, PartialEq)]
+    pub struct PwmActuatorBundle {
+        pub name: Name => reflect,
+        pub pwm_channel: PwmChannel => ServerToClient,
+        pub pwm_signal: PwmSignal,
+
+        pub robot: RobotId,
+    }
 
-#[derive(Bundle, PartialEq)]
-pub struct PwmActuatorBundle {
-    pub name: Name,
-    pub pwm_channel: PwmChannel,
-    pub pwm_signal: PwmSignal,
+    #[derive(Bundle, PartialEq)]
+    pub struct MovementContributionBundle {
+        pub name: Name => reflect,
 
-    pub robot: RobotId,
+        // Robot side controllers contribute movement too, so this flows both ways
+        pub contribution: MovementContribution,
+
+        pub robot: RobotId,
+    }
 }
 
-#[derive(Bundle, PartialEq)]
-pub struct MovementContributionBundle {
-    pub name: Name,
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeSet, time::Duration};
+
+    use bevy::{
+        app::App,
+        core::Name,
+        ecs::bundle::Bundle,
+        math::{vec3a, Vec3A},
+        transform::components::Transform,
+    };
+    use motor_math::{
+        solve::reverse::Axis, x3d::X3dMotorId, Direction, Motor, MotorConfig, Movement,
+    };
+
+    use crate::{
+        components::{
+            ActualForce, ActualMovement, Armed, Camera, Cores, CpuTotal, CurrentDraw, Depth, Disks,
+            Estimated, Inertial, Leak, LoadAverage, Magnetic, MeasuredVoltage, Memory,
+            MotorDefinition, Motors, MovementAxisMaximums, MovementContribution,
+            MovementCurrentCap, Networks, OperatingSystem, Orientation, Processes, PwmChannel,
+            PwmSignal, Robot, RobotId, RobotStatus, ServoDefinition, ServoMode, TargetForce,
+            TargetMovement, Temperatures, Uptime,
+        },
+        ecs_sync::{
+            test_utils::{app, outbound, ROBOT},
+            AppReplicateExt, NetId, ReplicableBundle, Replicate, SerializedChange,
+        },
+        sync::SyncRole,
+        types::{
+            ids::CameraId,
+            system::Cpu,
+            units::{Amperes, Newtons, Volts},
+        },
+    };
+
+    use super::{
+        CameraBundle, MotorBundle, MovementContributionBundle, PwmActuatorBundle,
+        RobotActuatorBundle, RobotBundle, RobotCoreBundle, RobotPowerBundle, RobotSensorBundle,
+        RobotSystemBundle, ServoBundle,
+    };
+
+    fn assert_replicates<B: ReplicableBundle>(app: &mut App, bundle: B) {
+        app.replicate_bundle::<B>();
+        app.world.spawn((bundle, Replicate));
+        app.update();
+
+        let updated = outbound(app)
+            .into_iter()
+            .filter_map(|change| match change {
+                SerializedChange::ComponentUpdated(_, type_path, Some(_)) => Some(type_path),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+
+        let mut expected = Vec::new();
+        B::component_type_paths(&mut expected);
+        assert!(!expected.is_empty());
+
+        for type_path in expected {
+            assert!(
+                updated.contains(type_path),
+                "{type_path} was not replicated as part of {}",
+                std::any::type_name::<B>()
+            );
+        }
+    }
+
+    fn actuator(robot: RobotId) -> PwmActuatorBundle {
+        PwmActuatorBundle {
+            name: Name::new("Actuator"),
+            pwm_channel: PwmChannel(3),
+            pwm_signal: PwmSignal(Duration::from_micros(1500)),
+            robot,
+        }
+    }
+
+    fn motor() -> Motor {
+        Motor {
+            position: vec3a(0.2, 0.3, 0.1),
+            orientation: Vec3A::X,
+            direction: Direction::Clockwise,
+        }
+    }
+
+    fn robot_core(robot: RobotId) -> RobotCoreBundle {
+        RobotCoreBundle {
+            marker: Robot,
+            status: RobotStatus::default(),
+            name: Name::new("Robot"),
+            robot_id: robot,
+        }
+    }
+
+    fn robot_sensors() -> RobotSensorBundle {
+        RobotSensorBundle {
+            orientation: Orientation::default(),
+            inertial: Inertial(Default::default()),
+            mag: Magnetic(Default::default()),
+            depth: Depth(Default::default()),
+            leak: Leak(false),
+        }
+    }
+
+    fn robot_system() -> RobotSystemBundle {
+        RobotSystemBundle {
+            processes: Processes::default(),
+            load_average: LoadAverage {
+                one_min: 1.5,
+                five_min: 0.75,
+                fifteen_min: 0.25,
+            },
+            networks: Networks::default(),
+            cpu: CpuTotal(Cpu {
+                frequency: 1800,
+                usage: 42.5,
+                name: "cpu".to_owned(),
+            }),
+            cores: Cores::default(),
+            memory: Memory {
+                total_mem: 8 << 30,
+                used_mem: 3 << 30,
+                free_mem: 5 << 30,
+                total_swap: 0,
+                used_swap: 0,
+                free_swap: 0,
+            },
+            temps: Temperatures::default(),
+            disks: Disks::default(),
+            uptime: Uptime(Duration::from_secs(90)),
+            os: OperatingSystem {
+                name: Some("Linux".to_owned()),
+                kernel_version: None,
+                os_version: None,
+                distro: None,
+                host_name: Some("rov".to_owned()),
+            },
+        }
+    }
+
+    fn robot_actuators() -> RobotActuatorBundle {
+        RobotActuatorBundle {
+            movement_target: TargetMovement(Movement::default()),
+            movement_actual: ActualMovement(Movement::default()),
+            motor_config: Motors(
+                MotorConfig::<X3dMotorId>::new(motor(), vec3a(0.0, 0.0, -0.05)).erase(),
+            ),
+            axis_maximums: MovementAxisMaximums([(Axis::X, Newtons(40.0))].into()),
+            current_cap: MovementCurrentCap(Amperes(20.0)),
+            armed: Armed::Disarmed,
+        }
+    }
+
+    fn robot_power() -> RobotPowerBundle {
+        RobotPowerBundle {
+            voltage: MeasuredVoltage(Volts(12.0)),
+            current_draw: CurrentDraw(Amperes(3.0)),
+        }
+    }
+
+    /// Bundles are checked from the side that spawns them, the other side may not send every field
+    fn check<B: Bundle + ReplicableBundle>(role: SyncRole, bundle: B) {
+        let mut app = app(role, &[ROBOT]);
+        assert_replicates(&mut app, bundle);
+    }
 
-    pub contribution: MovementContribution,
+    #[test]
+    fn bundles_replicate_every_field() {
+        let robot = RobotId(NetId::random());
+        let server = SyncRole::Server { port: Some(0) };
 
-    pub robot: RobotId,
+        check(server, robot_core(robot));
+        check(server, robot_sensors());
+        check(server, robot_system());
+        check(server, robot_actuators());
+        check(server, robot_power());
+        check(
+            server,
+            RobotBundle {
+                core: robot_core(robot),
+                sensors: robot_sensors(),
+                system: robot_system(),
+                actuators: robot_actuators(),
+                power: robot_power(),
+            },
+        );
+        check(
+            server,
+            CameraBundle {
+                name: Name::new("Camera"),
+                camera: Camera {
+                    id: CameraId::from_static("Camera"),
+                    location: "127.0.0.1:1234".parse().unwrap(),
+                },
+                transform: Transform::default(),
+                robot,
+            },
+        );
+        check(
+            server,
+            MotorBundle {
+                actuator: actuator(robot),
+                motor: MotorDefinition(0, motor()),
+                target_force: TargetForce(Newtons(1.0)),
+                actual_force: ActualForce(Newtons(1.0)),
+                current_draw: CurrentDraw(Amperes(0.5)),
+                estimated: Estimated,
+            },
+        );
+        check(
+            server,
+            ServoBundle {
+                actuator: actuator(robot),
+                servo: ServoDefinition {
+                    cameras: vec![CameraId::from_static("Front")],
+                },
+                servo_mode: ServoMode::Position,
+            },
+        );
+        check(
+            SyncRole::Client,
+            MovementContributionBundle {
+                name: Name::new("Contribution"),
+                contribution: MovementContribution(Movement::default()),
+                robot,
+            },
+        );
+    }
 }
@@ -0,0 +1,228 @@
+//! Command line arguments shared by both binaries
+//!
+//! Settings are layered, a value given on the command line wins over the config file, which wins
+//! over the built in default.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use clap::Args;
+use networking::PresharedKey;
+use serde::de::DeserializeOwned;
+
+use crate::{over_run::OverRunSettings, sync::LinkKey};
+
+#[derive(Args, Debug, Clone, Default, PartialEq)]
+pub struct CommonArgs {
+    /// Config file to read instead of the default one
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Time a frame may take before it is reported as an over run
+    #[arg(long, value_name = "MS")]
+    pub frame_budget_ms: Option<f32>,
+    /// Pre-shared key file to encrypt the link with
+    #[arg(long, value_name = "PATH")]
+    pub link_key: Option<PathBuf>,
+}
+
+impl CommonArgs {
+    /// Reads the config file given on the command line or `default`
+    ///
+    /// A missing default config is only an error if `required` is set, an explicitly given one
+    /// must always exist
+    pub fn read_config<T>(&self, default: &Path, required: bool) -> anyhow::Result<Option<T>>
+    where
+        T: DeserializeOwned,
+    {
+        let path = match &self.config {
+            Some(path) => path.as_path(),
+            None if !required && !default.exists() => return Ok(None),
+            None => default,
+        };
+
+        let config = fs::read_to_string(path)
+            .with_context(|| format!("Read config at {}", path.display()))?;
+        let config = toml::from_str(&config)
+            .with_context(|| format!("Parse config at {}", path.display()))?;
+
+        Ok(Some(config))
+    }
+
+    /// Applies the frame budget from the command line or the config file to `default`
+    pub fn over_run_settings(
+        &self,
+        config: Option<f32>,
+        default: OverRunSettings,
+    ) -> anyhow::Result<OverRunSettings> {
+        let Some(budget_ms) = self.frame_budget_ms.or(config) else {
+            return Ok(default);
+        };
+
+        if !(budget_ms.is_finite() && budget_ms > 0.0) {
+            bail!("Frame budget must be a positive number of milliseconds, got {budget_ms}");
+        }
+
+        Ok(OverRunSettings {
+            max_time: Duration::from_micros((budget_ms as f64 * 1000.0).round() as u64),
+            ..default
+        })
+    }
+
+    /// Loads the link key from the file given on the command line or in the config file
+    pub fn link_key(&self, config: Option<&Path>) -> anyhow::Result<Option<LinkKey>> {
+        let Some(path) = self.link_key.as_deref().or(config) else {
+            return Ok(None);
+        };
+
+        let key = PresharedKey::load(path)
+            .with_context(|| format!("Read link key at {}", path.display()))?;
+
+        Ok(Some(LinkKey(key)))
+    }
+}
+
+/// The command line value if given, then the config file's, then `default`
+pub fn layered<T>(cli: Option<T>, config: Option<T>, default: T) -> T {
+    cli.or(config).unwrap_or(default)
+}
+
+/// Boolean flags can only be turned on from the command line
+pub fn layered_flag(cli: bool, config: Option<bool>, default: bool) -> bool {
+    layered(cli.then_some(true), config, default)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use clap::Parser;
+    use networking::PresharedKey;
+
+    use crate::over_run::OverRunSettings;
+
+    use super::{layered, layered_flag, CommonArgs};
+
+    #[derive(Parser)]
+    struct Args {
+        #[command(flatten)]
+        common: CommonArgs,
+    }
+
+    fn parse(args: &[&str]) -> CommonArgs {
+        Args::try_parse_from(["test"].iter().chain(args))
+            .unwrap()
+            .common
+    }
+
+    fn defaults() -> OverRunSettings {
+        OverRunSettings {
+            max_time: Duration::from_millis(10),
+            tracy_frame_mark: true,
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(layered(Some(1), Some(2), 3), 1);
+        assert_eq!(layered(None, Some(2), 3), 2);
+        assert_eq!(layered(None, None, 3), 3);
+
+        assert!(layered_flag(true, Some(false), false));
+        assert!(!layered_flag(false, Some(false), true));
+        assert!(layered_flag(false, Some(true), false));
+        assert!(layered_flag(false, None, true));
+    }
+
+    #[test]
+    fn frame_budget() {
+        let args = parse(&[]);
+        assert_eq!(
+            args.over_run_settings(None, defaults()).unwrap(),
+            defaults()
+        );
+        assert_eq!(
+            args.over_run_settings(Some(25.0), defaults())
+                .unwrap()
+                .max_time,
+            Duration::from_millis(25)
+        );
+
+        let args = parse(&["--frame-budget-ms", "20"]);
+        let settings = args.over_run_settings(Some(25.0), defaults()).unwrap();
+        assert_eq!(settings.max_time, Duration::from_millis(20));
+        assert!(settings.tracy_frame_mark);
+
+        for bad in ["0", "-5", "inf", "NaN"] {
+            let args = parse(&[&format!("--frame-budget-ms={bad}")]);
+            assert!(args.over_run_settings(None, defaults()).is_err(), "{bad}");
+        }
+        assert!(parse(&[]).over_run_settings(Some(0.0), defaults()).is_err());
+    }
+
+    #[test]
+    fn reads_config() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let missing = dir.path().join("missing.toml");
+        let present = dir.path().join("present.toml");
+        std::fs::write(&present, "value = 7").unwrap();
+
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Config {
+            value: u32,
+        }
+
+        let args = parse(&[]);
+        assert_eq!(args.read_config::<Config>(&missing, false).unwrap(), None);
+        assert!(args.read_config::<Config>(&missing, true).is_err());
+        assert_eq!(
+            args.read_config::<Config>(&present, true).unwrap(),
+            Some(Config { value: 7 })
+        );
+
+        // An explicit path has to exist
+        let args = parse(&["--config", missing.to_str().unwrap()]);
+        assert!(args.read_config::<Config>(&present, false).is_err());
+
+        let args = parse(&["--config", present.to_str().unwrap()]);
+        assert_eq!(
+            args.read_config::<Config>(&missing, false).unwrap(),
+            Some(Config { value: 7 })
+        );
+    }
+
+    #[test]
+    fn link_key() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let key_a = dir.path().join("a.key");
+        let key_b = dir.path().join("b.key");
+        let empty = dir.path().join("empty.key");
+        std::fs::write(&key_a, "first secret\n").unwrap();
+        std::fs::write(&key_b, "second secret\n").unwrap();
+        std::fs::write(&empty, "\n").unwrap();
+
+        let key = |args: &CommonArgs, config: Option<&std::path::Path>| {
+            args.link_key(config).unwrap().map(|it| it.0)
+        };
+        let a = PresharedKey::from_secret(b"first secret");
+        let b = PresharedKey::from_secret(b"second secret");
+
+        let args = parse(&[]);
+        assert_eq!(key(&args, None), None);
+        assert_eq!(key(&args, Some(&key_a)), a);
+
+        let args = parse(&["--link-key", key_b.to_str().unwrap()]);
+        assert_eq!(key(&args, Some(&key_a)), b);
+
+        // Missing or empty key files are never treated as no key
+        assert!(parse(&[])
+            .link_key(Some(&dir.path().join("missing.key")))
+            .is_err());
+        assert!(parse(&[]).link_key(Some(&empty)).is_err());
+    }
+}
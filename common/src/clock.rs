@@ -0,0 +1,155 @@
+//! Relates sensor timestamps taken on one peer to the clock of another
+//!
+//! Readings are stamped with [`monotonic_seconds`] when they are taken. Every pong carries the
+//! responder's clock, so each ping gives a sample of the offset between the two clocks, off by at
+//! most half the round trip. [`ClockOffsetEstimator`] keeps the tightest recent sample.
+
+use std::{collections::VecDeque, sync::OnceLock, time::Instant};
+
+use bevy::{ecs::component::Component, reflect::Reflect};
+
+/// Samples the estimator chooses from, old ones are dropped so drift is followed
+pub const OFFSET_WINDOW: usize = 16;
+
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// Seconds on this process's monotonic clock, counted from the first time it was read
+pub fn monotonic_seconds() -> f64 {
+    EPOCH.get_or_init(Instant::now).elapsed().as_secs_f64()
+}
+
+/// How far a peer's [`monotonic_seconds`] is ahead of ours, inserted on its `Peer` entity
+#[derive(Component, Debug, Clone, Copy, PartialEq, Reflect)]
+pub struct ClockOffset {
+    /// Seconds to subtract from a remote time to get the local time
+    pub offset: f64,
+    /// Most the offset can be wrong by, half the round trip it was measured over
+    pub uncertainty: f64,
+}
+
+impl ClockOffset {
+    /// Converts a time on the peer's clock to ours
+    pub fn to_local(&self, remote: f64) -> f64 {
+        remote - self.offset
+    }
+
+    /// Converts a time on our clock to the peer's
+    pub fn to_remote(&self, local: f64) -> f64 {
+        local + self.offset
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct OffsetSample {
+    offset: f64,
+    round_trip: f64,
+}
+
+/// Offset estimate from the recent ping round trips
+#[derive(Debug, Clone, Default)]
+pub struct ClockOffsetEstimator {
+    samples: VecDeque<OffsetSample>,
+}
+
+impl ClockOffsetEstimator {
+    /// Records a ping sent at `sent` and answered at `received`, both local, with the peer's
+    /// clock reading `remote` when it replied
+    ///
+    /// Returns the current best estimate, round trips that went backwards are ignored.
+    pub fn add_sample(&mut self, sent: f64, remote: f64, received: f64) -> Option<ClockOffset> {
+        let round_trip = received - sent;
+
+        if round_trip >= 0.0 && remote.is_finite() {
+            if self.samples.len() == OFFSET_WINDOW {
+                self.samples.pop_front();
+            }
+
+            self.samples.push_back(OffsetSample {
+                offset: remote - (sent + received) / 2.0,
+                round_trip,
+            });
+        }
+
+        self.estimate()
+    }
+
+    /// The sample with the shortest round trip, it was delayed the least either way
+    pub fn estimate(&self) -> Option<ClockOffset> {
+        self.samples
+            .iter()
+            .min_by(|a, b| a.round_trip.total_cmp(&b.round_trip))
+            .map(|it| ClockOffset {
+                offset: it.offset,
+                uncertainty: it.round_trip / 2.0,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClockOffset, ClockOffsetEstimator, OFFSET_WINDOW};
+
+    /// A peer 100s ahead, replying `reply_delay` into a round trip of `round_trip`
+    fn ping(estimator: &mut ClockOffsetEstimator, sent: f64, round_trip: f64, reply_delay: f64) {
+        estimator.add_sample(sent, sent + reply_delay + 100.0, sent + round_trip);
+    }
+
+    #[test]
+    fn shortest_round_trip_wins() {
+        let mut estimator = ClockOffsetEstimator::default();
+        assert_eq!(estimator.estimate(), None);
+
+        // Asymmetric delays on slow round trips
+        ping(&mut estimator, 1.0, 0.08, 0.07);
+        ping(&mut estimator, 2.0, 0.06, 0.01);
+        ping(&mut estimator, 3.0, 0.004, 0.002);
+        ping(&mut estimator, 4.0, 0.05, 0.045);
+
+        let estimate = estimator.estimate().unwrap();
+        assert!((estimate.offset - 100.0).abs() < 1e-9);
+        assert!((estimate.uncertainty - 0.002).abs() < 1e-9);
+
+        // A remote reading converts back within the uncertainty
+        assert!((estimate.to_local(105.0) - 5.0).abs() <= estimate.uncertainty);
+        assert!((estimate.to_local(estimate.to_remote(7.5)) - 7.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn error_is_bounded_by_uncertainty() {
+        let mut estimator = ClockOffsetEstimator::default();
+
+        for (idx, delay) in [0.0, 0.01, 0.03, 0.02, 0.005].into_iter().enumerate() {
+            ping(&mut estimator, idx as f64, 0.04, delay);
+
+            let estimate = estimator.estimate().unwrap();
+            assert!((estimate.offset - 100.0).abs() <= estimate.uncertainty + 1e-9);
+        }
+    }
+
+    #[test]
+    fn old_samples_expire() {
+        let mut estimator = ClockOffsetEstimator::default();
+
+        ping(&mut estimator, 0.0, 0.001, 0.0005);
+        for idx in 1..=OFFSET_WINDOW {
+            ping(&mut estimator, idx as f64, 0.02, 0.01);
+        }
+
+        let estimate = estimator.estimate().unwrap();
+        assert!((estimate.uncertainty - 0.01).abs() < 1e-9);
+    }
+
+    #[test]
+    fn backwards_round_trips_are_ignored() {
+        let mut estimator = ClockOffsetEstimator::default();
+
+        assert_eq!(estimator.add_sample(5.0, 105.0, 4.0), None);
+        assert_eq!(
+            estimator.add_sample(5.0, 105.0, 5.0),
+            Some(ClockOffset {
+                offset: 100.0,
+                uncertainty: 0.0
+            })
+        );
+    }
+}
@@ -1,29 +1,47 @@
-use std::{borrow::Cow, collections::BTreeMap, net::SocketAddr, time::Duration};
+use std::{
+    collections::{BTreeMap, HashSet},
+    net::SocketAddr,
+    time::Duration,
+};
 
 use bevy::{
     app::App,
     ecs::component::Component,
     reflect::{std_traits::ReflectDefault, Reflect, ReflectDeserialize, ReflectSerialize},
 };
-use glam::Quat;
+use glam::{Quat, Vec2, Vec3A};
 use motor_math::{solve::reverse::Axis, ErasedMotorId, Motor, MotorConfig, Movement};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     adapters::serde::ReflectSerdeAdapter,
-    ecs_sync::{AppReplicateExt, NetId},
+    ecs_sync::{round_trip::default_sample, AppReplicateExt, NetId, ReplicationDirection},
     types::{
-        hw::{DepthFrame, InertialFrame, MagneticFrame, PwmChannelId},
-        system::{ComponentTemperature, Cpu, Disk, Network, Process},
-        units::{Amperes, Mbar, Meters, Newtons, Volts},
+        bounded::{BoundedMap, BoundedVec},
+        checklist::Checklist,
+        hw::{DepthFrame, InertialFrame, MagneticFrame, PwmChannelId, Rgb8},
+        ids::{CameraId, ServoId},
+        system::{ComponentTemperature, Cpu, Disk, Network, Process, SyncTypeRate},
+        units::{Amperes, Celsius, Mbar, Meters, Newtons, Volts},
     },
 };
 
+/// Components that aren't part of a bundle, see [`crate::bundles`] for the rest
+///
+/// `=> Direction` limits which side may send updates, see [`ReplicationDirection`]
 macro_rules! components {
-    ($($name:ident),*) => {
+    (@direction) => {
+        ReplicationDirection::Both
+    };
+    (@direction $direction:ident) => {
+        ReplicationDirection::$direction
+    };
+
+    ($($name:ident $(=> $direction:ident)?),*) => {
         pub fn register_components(app: &mut App) {
             $(
-                app.replicate::<$name>();
+                app.replicate_dir::<$name>(components!(@direction $($direction)?));
+                default_sample!(app, $name);
             )*
         }
     }
@@ -31,53 +49,49 @@ macro_rules! components {
 
 components! {
     Singleton,
-    Robot,
-    Surface,
-    Orientation,
-    Inertial,
-    Magnetic,
-    Depth,
+    Surface => ClientToServer,
     DepthTarget,
-    DepthSettings,
+    DepthSettings => ServerToClient,
     OrientationTarget,
-    Leak,
-    RobotStatus,
-    Armed,
-    Camera,
-    RobotId,
-    Processes,
-    LoadAverage,
-    Networks,
-    CpuTotal,
-    Cores,
-    Memory,
-    Temperatures,
-    Disks,
-    Uptime,
-    OperatingSystem,
-    TargetForce,
-    ActualForce,
-    ServoTargets,
-    MotorDefinition,
-    ServoDefinition,
-    ServoMode,
-    Motors,
-    Servos,
-    TargetMovement,
-    ActualMovement,
-    MeasuredVoltage,
-    MovementContribution,
-    ServoContribution,
-    MotorContribution,
-    MovementAxisMaximums,
-    MovementCurrentCap,
-    CurrentDraw,
-    JerkLimit,
-    PwmChannel,
-    PwmSignal,
+    ServoTargets => ServerToClient,
+    Servos => ServerToClient,
+    ContributionMuted,
+    ActiveContributions => ServerToClient,
+    ServoContribution => ClientToServer,
+    MotorContribution => ServerToClient,
+    JerkLimit => ServerToClient,
     PwmManualControl,
-    PidConfig,
-    PidResult
+    PidConfig => ServerToClient,
+    PidResult => ServerToClient,
+    AuthorityLimit => ServerToClient,
+    ControlGated => ServerToClient,
+    LedMode,
+    LedBrightness,
+    PowerMode => ServerToClient,
+    PowerModeReason => ServerToClient,
+    PowerModeOverride,
+    RemoteSyncStats => ServerToClient,
+    TetherTurns => ServerToClient,
+    PositionEstimate => ServerToClient,
+    DepthEstimate => ServerToClient,
+    DepthTemperatureProfile => ServerToClient,
+    AvailableBehaviors => ServerToClient,
+    RunningBehavior => ServerToClient,
+    BuildInfo => ServerToClient,
+    CameraManagerState => ServerToClient,
+    CenterOfMassOffset,
+    DisabledMotors,
+    PreviousCrashReport => ServerToClient,
+    MissionChecklist => ServerToClient,
+    MotorDataStatus => ServerToClient,
+    ConfigSnapshot => ServerToClient,
+    FileRoots => ServerToClient,
+    CurrentTrim,
+    MotorConfigReport => ServerToClient,
+    CameraIntrinsics => ServerToClient,
+    CameraFallbackStream => ClientToServer,
+    PwmHealth => ServerToClient,
+    ArmAuthority => ServerToClient
 }
 
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
@@ -92,9 +106,15 @@ pub struct Robot;
 #[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Surface;
 
+/// Estimated attitude
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Copy, Clone, PartialEq, Default)]
 #[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
-pub struct Orientation(pub Quat);
+pub struct Orientation {
+    pub rotation: Quat,
+    /// The robot's [`crate::clock::monotonic_seconds`] when the IMU reading this was estimated
+    /// from was taken
+    pub capture_time: f64,
+}
 
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Copy, Clone, PartialEq)]
 #[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
@@ -150,15 +170,70 @@ pub enum Armed {
     Disarmed,
 }
 
+/// The surface allowed to arm and disarm the robot, see [`crate::events::RequestArmAuthority`]
+#[derive(
+    Component, Serialize, Deserialize, Reflect, Debug, Copy, Clone, PartialEq, Eq, Default,
+)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct ArmAuthority {
+    /// [`NetId`] of the holder's [`Surface`] singleton
+    pub holder: Option<NetId>,
+}
+
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Eq)]
 #[reflect(from_reflect = false)]
 #[reflect(SerdeAdapter, /*Serialize, Deserialize,*/ Debug, PartialEq)]
 pub struct Camera {
+    pub id: CameraId,
     // TODO(low): This bad
     #[reflect(ignore)]
     pub location: SocketAddr,
 }
 
+/// Asks the robot to send `camera` as JPEG frames over the sync connection instead of its UDP
+/// stream, see [`crate::video_frames`]
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Eq)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct CameraFallbackStream {
+    pub camera: CameraId,
+    pub fps: u32,
+    /// JPEG quality, 1 to 100
+    pub quality: u8,
+}
+
+/// Pinhole intrinsics of a camera in pixels at `resolution`, from its calibration
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct CameraIntrinsics {
+    pub resolution: (u32, u32),
+    pub fx: f64,
+    pub fy: f64,
+    pub cx: f64,
+    pub cy: f64,
+    /// k1, k2, p1, p2, k3
+    #[serde(default)]
+    pub distortion: [f64; 5],
+}
+
+impl CameraIntrinsics {
+    /// The same camera streaming at a different resolution
+    ///
+    /// Distortion is relative to the focal length so it doesn't change
+    pub fn scaled_to(&self, width: u32, height: u32) -> Self {
+        let sx = width as f64 / self.resolution.0 as f64;
+        let sy = height as f64 / self.resolution.1 as f64;
+
+        Self {
+            resolution: (width, height),
+            fx: self.fx * sx,
+            fy: self.fy * sy,
+            cx: self.cx * sx,
+            cy: self.cy * sy,
+            distortion: self.distortion,
+        }
+    }
+}
+
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Copy, Clone, PartialEq, Eq)]
 #[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
 pub struct RobotId(pub NetId);
@@ -202,7 +277,7 @@ pub struct Memory {
 
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
 #[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
-pub struct Temperatures(pub Vec<ComponentTemperature>);
+pub struct Temperatures(pub BoundedVec<ComponentTemperature, 64>);
 
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
 #[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
@@ -222,6 +297,152 @@ pub struct OperatingSystem {
     pub host_name: Option<String>,
 }
 
+/// Which build of the robot code is running
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct BuildInfo {
+    /// Short commit hash, suffixed with `-dirty` when built with uncommitted changes
+    pub git_hash: String,
+    /// UTC, `YYYY-MM-DD HH:MM:SS`
+    pub build_timestamp: String,
+    /// Cargo profile, `debug` or `release`
+    pub profile: String,
+}
+
+/// What the robot code recorded as it panicked
+#[derive(Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct CrashReport {
+    /// Where it panicked and the panic message
+    pub message: String,
+    pub backtrace: String,
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub build: BuildInfo,
+    /// How long the robot code had been running
+    pub uptime: Duration,
+}
+
+/// The last run of the robot code panicked, kept until the surface archives it
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct PreviousCrashReport(pub CrashReport);
+
+/// The competition task checklist, kept on the robot so every surface sees the same state
+///
+/// Surfaces change it through [`crate::events::LoadChecklist`] and
+/// [`crate::events::SetChecklistItem`]
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct MissionChecklist(pub Checklist);
+
+/// Where the robot's thrust calibration table was loaded from
+///
+/// Anything but a clean [`MotorDataSource::FileOk`] means thrust and current estimates are off,
+/// [`crate::events::ReloadMotorData`] retries the load
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Eq)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct MotorDataStatus {
+    pub source: MotorDataSource,
+    /// Why every file failed for [`MotorDataSource::Fallback`], rows that were skipped otherwise
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Eq)]
+#[reflect(Serialize, Deserialize, Debug, PartialEq)]
+pub enum MotorDataSource {
+    /// Loaded from the file at this path
+    FileOk(String),
+    /// No file could be loaded, using the compiled in table
+    Fallback,
+}
+
+impl MotorDataStatus {
+    pub fn is_degraded(&self) -> bool {
+        self.source == MotorDataSource::Fallback || self.error.is_some()
+    }
+}
+
+/// Whether the PWM chip's registers hold what the robot last wrote to them
+///
+/// Checked by reading the chip back a channel at a time, a [`PwmHealth::Failed`] chip keeps the
+/// robot from arming
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Eq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum PwmHealth {
+    #[default]
+    Healthy,
+    /// The chip lost its configuration this many times and was restored each time
+    Recovered(u32),
+    /// The chip lost its configuration and initializing it again didn't bring it back
+    Failed(String),
+}
+
+/// What the robot found checking its motor config at startup
+///
+/// Anything in `errors` keeps the robot from arming
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct MotorConfigReport {
+    pub motors: Vec<MotorReport>,
+    pub axes: Vec<AxisReport>,
+    /// Of the contribution matrix rows for the required axes, infinite when some combination of
+    /// them can't be driven
+    pub condition_number: f32,
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct MotorReport {
+    pub name: String,
+    pub pwm_channel: PwmChannelId,
+    /// From the center of mass to the motor, in meters
+    pub lever_arm: Vec3A,
+    /// Unit vector the motor pushes along
+    pub orientation: Vec3A,
+}
+
+#[derive(Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct AxisReport {
+    pub name: String,
+    /// Whether the robot's config needs this axis driven
+    pub required: bool,
+    /// Largest movement along the axis within the current budget
+    pub maximum: Newtons,
+    /// Share of a movement along the axis the motors can produce on their own, see
+    /// [`MotorConfig::axis_authority`]
+    pub authority: f32,
+}
+
+/// The robot's config file as of the last [`crate::events::RequestConfig`]
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct ConfigSnapshot {
+    /// Where the robot read its config from
+    pub path: String,
+    /// The file as is, at most [`crate::events::MAX_CONFIG_TEXT`] bytes
+    pub toml_text: String,
+    /// Labels and values picked out of the parsed config, empty when it doesn't parse
+    pub summary: Vec<(String, String)>,
+    /// Why the file couldn't be read or parsed
+    pub error: Option<String>,
+}
+
+/// What the robot's camera manager is doing, explains feeds that are momentarily missing
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Eq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum CameraManagerState {
+    #[default]
+    Idle,
+    /// Streams are being pointed at a new peer or stopped
+    Restarting,
+    /// The last restart or resync failed, holds the most recent error
+    Error(String),
+}
+
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
 #[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
 pub struct TargetForce(pub Newtons);
@@ -237,17 +458,13 @@ pub struct MotorDefinition(pub ErasedMotorId, pub Motor);
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
 #[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
 pub struct ServoDefinition {
-    // TODO: Make CameraId type
-    // TODO: Reevaluate if using Cow makes sense
-    pub cameras: Vec<Cow<'static, str>>,
+    pub cameras: Vec<CameraId>,
 }
 
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
 #[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Servos {
-    // TODO: Make ServoId type
-    // TODO: Reevaluate if using Cow makes sense
-    pub servos: Vec<Cow<'static, str>>,
+    pub servos: Vec<ServoId>,
 }
 
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
@@ -274,7 +491,7 @@ pub struct TargetMovement(pub Movement);
 #[reflect(from_reflect = false)]
 pub struct ServoTargets(
     // TODO(low): This bad
-    #[reflect(ignore)] pub BTreeMap<Cow<'static, str>, f32>,
+    #[reflect(ignore)] pub BoundedMap<ServoId, f32, 32>,
 );
 
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
@@ -289,12 +506,22 @@ pub struct MeasuredVoltage(pub Volts);
 #[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
 pub struct MovementContribution(pub Movement);
 
+/// Keeps a movement contribution from being applied without removing it
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct ContributionMuted;
+
+/// The named movement contributions the robot applied last frame
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct ActiveContributions(pub Vec<(String, Movement)>);
+
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
 #[reflect(SerdeAdapter, /*Serialize, Deserialize,*/ Debug, PartialEq, Default)]
 #[reflect(from_reflect = false)]
 pub struct MotorContribution(
     // TODO(low): This bad
-    #[reflect(ignore)] pub BTreeMap<ErasedMotorId, Newtons>,
+    #[reflect(ignore)] pub BoundedMap<ErasedMotorId, Newtons, 64>,
 );
 
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
@@ -302,14 +529,14 @@ pub struct MotorContribution(
 #[reflect(from_reflect = false)]
 pub struct ServoContribution(
     // TODO(low): This bad
-    #[reflect(ignore)] pub BTreeMap<Cow<'static, str>, f32>,
+    #[reflect(ignore)] pub BTreeMap<ServoId, f32>,
 );
 
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
 #[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
 pub struct MovementAxisMaximums(
     // TODO(low): This bad
-    #[reflect(ignore)] pub BTreeMap<Axis, Newtons>,
+    #[reflect(ignore)] pub BoundedMap<Axis, Newtons, { Axis::ALL.len() }>,
 );
 
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
@@ -361,3 +588,185 @@ pub struct PidResult {
 
     pub correction: f32,
 }
+
+/// Largest correction a controller may contribute, applied to the output of its [`PidResult`]
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct AuthorityLimit(pub Newtons);
+
+/// How far the real center of mass is from the one in the robot's config, in meters
+///
+/// The robot rebuilds its [`Motors`] about the shifted center of mass whenever this changes
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct CenterOfMassOffset(pub Vec3A);
+
+/// Constant force the robot adds to counter water current, world frame newtons
+///
+/// The robot rotates it into its body frame each tick and zeroes it when disarmed. Each axis is
+/// held to [`CurrentTrim::limit`] when it arrives.
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct CurrentTrim(pub Vec3A);
+
+impl CurrentTrim {
+    /// Limit on each axis before the robot reports its [`MovementAxisMaximums`]
+    pub const DEFAULT_LIMIT: f32 = 50.0;
+
+    /// Largest trim along each axis, past what the thrusters can push along any axis is wasted
+    pub fn limit(maximums: Option<&MovementAxisMaximums>) -> f32 {
+        maximums
+            .map(|MovementAxisMaximums(maximums)| {
+                [Axis::X, Axis::Y, Axis::Z]
+                    .iter()
+                    .filter_map(|axis| maximums.get(axis))
+                    .map(|it| it.0)
+                    .fold(0.0, f32::max)
+            })
+            .filter(|it| *it > 0.0)
+            .unwrap_or(Self::DEFAULT_LIMIT)
+    }
+}
+
+/// Motors the allocator leaves at neutral, the robot solves its [`Motors`] around the rest
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, /*Serialize, Deserialize,*/ Debug, PartialEq, Default)]
+#[reflect(from_reflect = false)]
+pub struct DisabledMotors(
+    // TODO(low): This bad
+    #[reflect(ignore)] pub HashSet<ErasedMotorId>,
+);
+
+/// Whether the robot is holding its thrusters at neutral because operator heartbeats stopped
+#[derive(
+    Component, Serialize, Deserialize, Reflect, Debug, Clone, Copy, Eq, PartialEq, Default,
+)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ControlGated(pub bool);
+
+/// What the robot's neopixels display
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum LedMode {
+    /// Thruster indicators over a rotating rainbow
+    #[default]
+    Status,
+    /// Only the thruster indicators
+    ThrusterViz,
+    Solid(Rgb8),
+    /// Flashes `color`, on for the first half of each period
+    Strobe {
+        color: Rgb8,
+        period: Duration,
+    },
+    Rainbow,
+    Off,
+}
+
+/// Neopixel brightness from 0 to 1
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct LedBrightness(pub f32);
+
+/// How much the robot is saving power to stretch what is left in the battery
+///
+/// Ordered from least to most severe
+#[derive(
+    Component,
+    Serialize,
+    Deserialize,
+    Reflect,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
+)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub enum PowerMode {
+    #[default]
+    Normal,
+    /// Dimmer LEDs, lighter camera streams, slower monitoring and less thrust
+    LowPower,
+    /// Only the primary cameras keep streaming and thrust is cut further
+    Critical,
+}
+
+/// Why the robot is in its [`PowerMode`]
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Eq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct PowerModeReason(pub String);
+
+/// [`PowerMode`] set from the surface, the battery voltage decides it when `None`
+#[derive(
+    Component, Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq, Eq, Default,
+)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct PowerModeOverride(pub Option<PowerMode>);
+
+/// The busiest replicated types of the peer that owns this entity
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct RemoteSyncStats(pub Vec<SyncTypeRate>);
+
+/// Net turns about the vertical axis since the last reset, positive is clockwise from above
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Copy, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct TetherTurns(pub f32);
+
+/// Horizontal position dead reckoned from thrust and heading since the last
+/// [`crate::events::ResetPosition`]
+///
+/// Nothing measures the robot's position, so this drifts without bound, trust it less the lower
+/// `confidence_decay` gets
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Copy, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct PositionEstimate {
+    /// Meters from where the estimate was reset, +Y is the heading the IMU calls north
+    pub xy: Vec2,
+    /// Share of the confidence at the last reset that is left, from 1 decaying toward 0
+    pub confidence_decay: f32,
+}
+
+/// Depth from the pressure sensor fused with the IMU's vertical acceleration
+///
+/// Smoother than [`Depth`] and quicker to follow changes. While pressure readings are missing it
+/// is carried forward on the IMU alone and `variance` grows.
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Copy, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct DepthEstimate {
+    pub depth: Meters,
+    /// Meters per second, positive is descending
+    pub velocity: f32,
+    /// Of `depth`, in square meters
+    pub variance: f32,
+}
+
+/// Values on this entity, like [`CurrentDraw`], are estimated rather than measured
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Copy, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct Estimated;
+
+/// Water temperature sampled at evenly spaced depths as the robot moves up and down, oldest first
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct DepthTemperatureProfile(pub Vec<(Meters, Celsius)>);
+
+/// Names of the autonomous behaviors a robot can run, see [`crate::events::StartBehavior`]
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct AvailableBehaviors(pub Vec<String>);
+
+/// Marks the movement contribution of a running autonomous behavior, holds the behavior's name
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct RunningBehavior(pub String);
+
+/// Names of the directories the surface can browse, see [`crate::events::ListFiles`]
+#[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct FileRoots(pub Vec<String>);
@@ -1,6 +1,16 @@
+//! Turns termination signals into an orderly [`AppExit`]
+//!
+//! A second signal while shutdown is already underway exits the process immediately, so a wedged
+//! shutdown can't leave the process unkillable short of SIGKILL.
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
 use anyhow::Context;
 use bevy::{app::AppExit, prelude::*};
-use crossbeam::channel::{self, Receiver};
+use crossbeam::channel::{self, Receiver, Sender};
 
 use super::error;
 
@@ -8,31 +18,249 @@ pub struct CtrlCPlugin;
 
 impl Plugin for CtrlCPlugin {
     fn build(&self, app: &mut App) {
+        app.init_resource::<ReceivedSignal>();
         app.add_systems(Startup, setup_handler.pipe(error::handle_errors));
-        app.add_systems(PreUpdate, check_handler);
+        app.add_systems(
+            PreUpdate,
+            check_handler.run_if(resource_exists::<CtrlcChannel>),
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminationSignal {
+    /// SIGINT, usually Ctrl-C
+    Interrupt,
+    /// SIGTERM, what systemd sends on stop
+    Terminate,
+    /// SIGHUP, the controlling terminal went away
+    Hangup,
+}
+
+/// The signal that started shutdown, if any
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReceivedSignal(pub Option<TerminationSignal>);
+
+/// Delivers termination signals to a callback
+///
+/// Backed by the OS outside of tests
+pub trait SignalSource {
+    fn register(
+        self: Box<Self>,
+        notify: Box<dyn Fn(TerminationSignal) + Send + Sync>,
+    ) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignalAction {
+    /// Start an orderly shutdown
+    Shutdown,
+    /// Shutdown was already requested, give up on it
+    ForceExit,
+}
+
+/// Tracks whether a termination signal has already been received
+#[derive(Debug, Default)]
+pub struct SignalState {
+    shutting_down: AtomicBool,
+}
+
+impl SignalState {
+    pub fn notify(&self) -> SignalAction {
+        if self.shutting_down.swap(true, Ordering::AcqRel) {
+            SignalAction::ForceExit
+        } else {
+            SignalAction::Shutdown
+        }
     }
 }
 
 #[derive(Resource)]
-struct CtrlcChannel(Receiver<()>);
+pub struct CtrlcChannel(Receiver<TerminationSignal>);
 
-pub fn setup_handler(mut cmds: Commands) -> anyhow::Result<()> {
+/// Registers `source` so the first signal is forwarded to the returned channel and any later one
+/// calls `force_exit`
+pub fn install(source: Box<dyn SignalSource>, force_exit: fn(i32)) -> anyhow::Result<CtrlcChannel> {
     let (tx, rx) = channel::bounded(1);
+    let state = Arc::new(SignalState::default());
 
-    cmds.insert_resource(CtrlcChannel(rx));
+    source.register(Box::new(move |signal| {
+        handle_signal(&state, &tx, signal, force_exit)
+    }))?;
 
-    ctrlc::set_handler(move || {
-        info!("Interrupt handler triggered");
+    Ok(CtrlcChannel(rx))
+}
+
+fn handle_signal(
+    state: &SignalState,
+    tx: &Sender<TerminationSignal>,
+    signal: TerminationSignal,
+    force_exit: fn(i32),
+) {
+    match state.notify() {
+        SignalAction::Shutdown => {
+            info!("Received {signal:?}, shutting down");
+
+            let _ = tx.try_send(signal);
+        }
+        SignalAction::ForceExit => {
+            error!("Received {signal:?} during shutdown, exiting immediately");
+
+            force_exit(1);
+        }
+    }
+}
 
-        let _ = tx.send(());
-    })
-    .context("Set ctrl-c")?;
+fn exit_process(code: i32) {
+    std::process::exit(code);
+}
+
+pub fn setup_handler(mut cmds: Commands) -> anyhow::Result<()> {
+    let channel = install(Box::new(OsSignals), exit_process).context("Set signal handlers")?;
+    cmds.insert_resource(channel);
 
     Ok(())
 }
 
-pub fn check_handler(channel: Res<CtrlcChannel>, mut exit: EventWriter<AppExit>) {
-    if let Ok(()) = channel.0.try_recv() {
+pub fn check_handler(
+    channel: Res<CtrlcChannel>,
+    mut received: ResMut<ReceivedSignal>,
+    mut exit: EventWriter<AppExit>,
+) {
+    if let Ok(signal) = channel.0.try_recv() {
+        received.0 = Some(signal);
         exit.send(AppExit);
     }
 }
+
+struct OsSignals;
+
+#[cfg(unix)]
+impl SignalSource for OsSignals {
+    fn register(
+        self: Box<Self>,
+        notify: Box<dyn Fn(TerminationSignal) + Send + Sync>,
+    ) -> anyhow::Result<()> {
+        use signal_hook::{
+            consts::{SIGHUP, SIGINT, SIGTERM},
+            iterator::Signals,
+        };
+
+        let mut signals =
+            Signals::new([SIGINT, SIGTERM, SIGHUP]).context("Register signal handlers")?;
+
+        std::thread::Builder::new()
+            .name("Signal Thread".to_owned())
+            .spawn(move || {
+                for signal in signals.forever() {
+                    let signal = match signal {
+                        SIGINT => TerminationSignal::Interrupt,
+                        SIGTERM => TerminationSignal::Terminate,
+                        SIGHUP => TerminationSignal::Hangup,
+                        _ => continue,
+                    };
+
+                    notify(signal);
+                }
+            })
+            .context("Spawn signal thread")?;
+
+        Ok(())
+    }
+}
+
+#[cfg(not(unix))]
+impl SignalSource for OsSignals {
+    fn register(
+        self: Box<Self>,
+        notify: Box<dyn Fn(TerminationSignal) + Send + Sync>,
+    ) -> anyhow::Result<()> {
+        ctrlc::set_handler(move || notify(TerminationSignal::Interrupt)).context("Set ctrl-c")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc, Mutex,
+    };
+
+    use bevy::{app::AppExit, prelude::*};
+
+    use super::{
+        check_handler, install, ReceivedSignal, SignalAction, SignalSource, SignalState,
+        TerminationSignal,
+    };
+
+    type Notify = Box<dyn Fn(TerminationSignal) + Send + Sync>;
+
+    /// Hands the registered callback back to the test so it can raise signals
+    #[derive(Clone, Default)]
+    struct InjectedSignals(Arc<Mutex<Option<Notify>>>);
+
+    impl InjectedSignals {
+        fn raise(&self, signal: TerminationSignal) {
+            let notify = self.0.lock().unwrap();
+            (notify.as_ref().expect("Registered"))(signal);
+        }
+    }
+
+    impl SignalSource for InjectedSignals {
+        fn register(self: Box<Self>, notify: Notify) -> anyhow::Result<()> {
+            *self.0.lock().unwrap() = Some(notify);
+            Ok(())
+        }
+    }
+
+    static EXIT_CODE: AtomicI32 = AtomicI32::new(-1);
+
+    fn record_exit(code: i32) {
+        EXIT_CODE.store(code, Ordering::SeqCst);
+    }
+
+    #[test]
+    fn second_signal_forces_exit() {
+        let state = SignalState::default();
+
+        assert_eq!(state.notify(), SignalAction::Shutdown);
+        assert_eq!(state.notify(), SignalAction::ForceExit);
+        assert_eq!(state.notify(), SignalAction::ForceExit);
+    }
+
+    #[test]
+    fn signals_become_app_exit() {
+        let signals = InjectedSignals::default();
+        let channel = install(Box::new(signals.clone()), record_exit).unwrap();
+
+        let mut app = App::new();
+        app.add_event::<AppExit>()
+            .init_resource::<ReceivedSignal>()
+            .insert_resource(channel)
+            .add_systems(Update, check_handler);
+
+        app.update();
+        assert_eq!(app.world.resource::<ReceivedSignal>().0, None);
+        assert!(app.world.resource::<Events<AppExit>>().is_empty());
+
+        signals.raise(TerminationSignal::Terminate);
+        app.update();
+
+        assert_eq!(
+            app.world.resource::<ReceivedSignal>().0,
+            Some(TerminationSignal::Terminate)
+        );
+        assert!(!app.world.resource::<Events<AppExit>>().is_empty());
+        assert_eq!(EXIT_CODE.load(Ordering::SeqCst), -1);
+
+        // Shutdown is wedged and the operator asks again
+        signals.raise(TerminationSignal::Interrupt);
+        assert_eq!(EXIT_CODE.load(Ordering::SeqCst), 1);
+
+        app.update();
+        assert_eq!(
+            app.world.resource::<ReceivedSignal>().0,
+            Some(TerminationSignal::Terminate)
+        );
+    }
+}
@@ -1,7 +1,19 @@
 pub mod apply_changes;
+pub mod coalesce;
 pub mod detect_changes;
+#[cfg(feature = "sync_instrumentation")]
+pub mod diagnostics;
+pub mod ownership;
+pub mod quarantine;
+pub mod round_trip;
+pub mod rpc;
+pub mod scoped;
+pub mod stats;
+#[cfg(test)]
+pub(crate) mod test_utils;
 
 use std::any::Any;
+use std::fmt::Debug;
 use std::sync::Arc;
 use std::{any::TypeId, borrow::Cow, marker::PhantomData};
 
@@ -9,14 +21,15 @@ use ahash::{HashMap, HashSet};
 use bevy::{
     app::App,
     ecs::{
+        bundle::Bundle,
         component::{Component, ComponentId, Tick},
         entity::Entity,
         event::{Event, Events, ManualEventReader},
         reflect::ReflectComponent,
         system::Resource,
-        world::{EntityWorldMut, FromWorld, World},
+        world::{EntityRef, EntityWorldMut, FromWorld, World},
     },
-    ptr::Ptr,
+    ptr::{OwningPtr, Ptr},
     reflect::{FromReflect, FromType, GetTypeRegistration, Reflect, ReflectFromPtr, Typed},
 };
 use networking::Token;
@@ -29,6 +42,13 @@ use crate::{
         ComponentTypeAdapter, EventTypeAdapter,
     },
     reflect::ReflectEvent,
+    sync::SyncRole,
+};
+
+use self::{
+    round_trip::SampleFn,
+    rpc::{RpcRequest, RpcResponse},
+    scoped::Scoped,
 };
 
 #[derive(Component, Serialize, Deserialize, Reflect, Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -56,12 +76,47 @@ pub enum SerializedChange {
     EntityDespawned(NetId),
     ComponentUpdated(NetId, NetTypeId, Option<adapters::BackingType>),
     EventEmitted(NetTypeId, adapters::BackingType),
+    OwnershipTransferred(NetId, NewOwner),
+    /// The sender stopped applying a type and asks not to be sent it, or lifted that request
+    TypeQuarantined(NetTypeId, bool),
+}
+
+impl SerializedChange {
+    /// Size of the serialized component or event carried by this change
+    pub fn payload_len(&self) -> usize {
+        match self {
+            SerializedChange::ComponentUpdated(_, _, data) => {
+                data.as_ref().map(|it| it.len()).unwrap_or(0)
+            }
+            SerializedChange::EventEmitted(_, data) => data.len(),
+            SerializedChange::EntitySpawned(_)
+            | SerializedChange::EntityDespawned(_)
+            | SerializedChange::OwnershipTransferred(..)
+            | SerializedChange::TypeQuarantined(..) => 0,
+        }
+    }
+}
+
+/// Who owns an entity after a [`SerializedChange::OwnershipTransferred`], relative to the peer
+/// that sent it
+///
+/// Tokens are only meaningful to the process that issued them, so the new owner is expressed
+/// relative to the connection the change arrived on
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum NewOwner {
+    /// The sending peer took ownership of the entity
+    Sender,
+    /// The sending peer handed ownership of the entity to the receiver
+    Receiver,
 }
 
 #[derive(Event, Debug)]
 pub struct SerializedChangeInEvent(pub SerializedChange, pub Token);
 #[derive(Event, Debug)]
 pub struct SerializedChangeOutEvent(pub SerializedChange);
+/// A change only sent to one peer, see [`PeerEvent`]
+#[derive(Event, Debug)]
+pub struct SerializedChangeTargetedOutEvent(pub SerializedChange, pub Token);
 
 #[derive(Resource, Default)]
 pub struct EntityMap {
@@ -73,6 +128,42 @@ pub struct EntityMap {
     pub(crate) local_modified: HashMap<Entity, Tick>,
 }
 
+impl EntityMap {
+    /// Returns the peer that owns `entity`, or `None` if it is owned locally
+    pub fn owner(&self, entity: Entity) -> Option<Token> {
+        self.forign_owned
+            .iter()
+            .find(|(_, owned)| owned.contains(&entity))
+            .map(|(token, _)| *token)
+    }
+
+    pub(crate) fn set_owner(&mut self, entity: Entity, owner: Option<Token>) {
+        for owned in self.forign_owned.values_mut() {
+            owned.remove(&entity);
+        }
+
+        if let Some(owner) = owner {
+            self.forign_owned.entry(owner).or_default().insert(entity);
+        }
+    }
+
+    /// Forgets about all entities owned by `token`, returning them so they can be despawned
+    pub(crate) fn remove_peer(&mut self, token: Token) -> HashSet<Entity> {
+        let owned_entities = self.forign_owned.remove(&token).unwrap_or_default();
+
+        for entity in &owned_entities {
+            let forign = self.local_to_forign.remove(entity);
+            if let Some(forign) = forign {
+                self.forign_to_local.remove(&forign);
+            };
+
+            self.local_modified.remove(entity);
+        }
+
+        owned_entities
+    }
+}
+
 #[derive(Resource)]
 pub struct SerializationSettings {
     marker_id: ComponentId,
@@ -94,6 +185,42 @@ pub struct ComponentInfo {
     type_adapter: ComponentTypeAdapter,
     ignore_component: ComponentId,
     remove_fn: RemoveFn,
+    /// Only set for types registered with a [`PartialEq`] impl
+    stage_fn: Option<StageFn>,
+    direction: ReplicationDirection,
+    /// Checks the type survives its adapter, see [`round_trip`]
+    sample: Option<SampleFn>,
+}
+
+/// Which side of a connection may send updates for a replicated component
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ReplicationDirection {
+    /// Only the server (robot) sends updates, eg. sensor readings
+    ServerToClient,
+    /// Only clients (surfaces) send updates, eg. pilot input
+    ClientToServer,
+    #[default]
+    Both,
+}
+
+impl ReplicationDirection {
+    /// Whether a peer with `role` may send updates
+    pub fn may_send(self, role: &SyncRole) -> bool {
+        match self {
+            ReplicationDirection::ServerToClient => matches!(role, SyncRole::Server { .. }),
+            ReplicationDirection::ClientToServer => matches!(role, SyncRole::Client),
+            ReplicationDirection::Both => true,
+        }
+    }
+
+    /// Whether a peer with `role` may accept updates from the other side of its connections
+    pub fn may_receive(self, role: &SyncRole) -> bool {
+        match self {
+            ReplicationDirection::ServerToClient => matches!(role, SyncRole::Client),
+            ReplicationDirection::ClientToServer => matches!(role, SyncRole::Server { .. }),
+            ReplicationDirection::Both => true,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -103,9 +230,48 @@ pub struct EventInfo {
     component_id: ComponentId,
     type_adapter: EventTypeAdapter,
     reader_factory: fn() -> ErasedManualEventReader,
+    /// Only set for types registered as a [`PeerEvent`]
+    target_fn: Option<TargetFn>,
+    /// Checks the type survives its adapter, see [`round_trip`]
+    sample: Option<SampleFn>,
 }
 
 pub type RemoveFn = fn(&mut EntityWorldMut);
+/// Boxes the component behind the pointer to be inserted later, or returns `None` if the entity
+/// already has an equal value
+///
+/// # Safety
+///
+/// Pointer must point to a value of the component type this function was registered for
+pub type StageFn = unsafe fn(EntityRef<'_>, OwningPtr<'_>) -> Option<Box<dyn Reflect>>;
+/// Returns where the event behind the pointer is sent
+///
+/// # Safety
+///
+/// Pointer must point to a value of the event type this function was registered for
+pub type TargetFn = unsafe fn(Ptr<'_>) -> EventTarget;
+
+/// Which peers a [`PeerEvent`] is sent to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventTarget {
+    /// Every peer, like any other event
+    Broadcast,
+    /// Only this peer
+    Peer(Token),
+    /// No peer, only readers in this app see it
+    Local,
+}
+
+/// A replicated event that is sent to a single peer, or wants to know which peer sent it
+pub trait PeerEvent: Event {
+    /// Where to send this event
+    ///
+    /// The target must not be serialized, events received from a peer have none
+    fn target(&self) -> EventTarget;
+
+    /// Called on received events with the connection they arrived on
+    fn set_origin(&mut self, origin: Token);
+}
 
 #[derive(Component, Reflect)]
 pub struct Replicate;
@@ -126,10 +292,37 @@ impl FromWorld for SerializationSettings {
     }
 }
 
+impl SerializationSettings {
+    /// Which way updates for a replicated component type may flow
+    pub fn direction(&self, token: &NetTypeId) -> Option<ReplicationDirection> {
+        self.component_by_token
+            .get(token)
+            .map(|info| info.direction)
+    }
+
+    /// Every replicated component and event type, sorted
+    pub fn type_manifest(&self) -> Vec<NetTypeId> {
+        let mut types = self
+            .component_by_token
+            .keys()
+            .chain(self.event_by_token.keys())
+            .cloned()
+            .collect::<Vec<_>>();
+        types.sort();
+
+        types
+    }
+}
+
 pub trait AppReplicateExt {
     fn replicate<C>(&mut self) -> &mut Self
     where
-        C: Component + Typed + GetTypeRegistration + SerdeAdapter;
+        C: Component + Typed + GetTypeRegistration + SerdeAdapter + PartialEq;
+
+    /// Replicates a component whose updates may only be sent in one direction
+    fn replicate_dir<C>(&mut self, direction: ReplicationDirection) -> &mut Self
+    where
+        C: Component + Typed + GetTypeRegistration + SerdeAdapter + PartialEq;
 
     fn replicate_reflect<C>(&mut self) -> &mut Self
     where
@@ -142,16 +335,75 @@ pub trait AppReplicateExt {
     fn replicate_event_reflect<C>(&mut self) -> &mut Self
     where
         C: Event + Typed + GetTypeRegistration + FromReflect;
+
+    /// Replicates an event that can be addressed to a single peer
+    fn replicate_peer_event<C>(&mut self) -> &mut Self
+    where
+        C: PeerEvent + Typed + GetTypeRegistration + SerdeAdapter;
+
+    /// Replicates an event that can be addressed to a single robot, see [`scoped`]
+    fn replicate_scoped_event<E>(&mut self) -> &mut Self
+    where
+        E: Event + Typed + GetTypeRegistration + SerdeAdapter,
+        Scoped<E>: Typed + GetTypeRegistration + SerdeAdapter;
+
+    /// Replicates requests of type `Req` and routes each `Resp` back to the peer that asked,
+    /// see [`rpc`]
+    fn replicate_rpc<Req, Resp>(&mut self) -> &mut Self
+    where
+        Req: Clone + Send + Sync + 'static,
+        Resp: Clone + Send + Sync + 'static,
+        RpcRequest<Req>: Typed + GetTypeRegistration + SerdeAdapter,
+        RpcResponse<Resp>: Typed + GetTypeRegistration + SerdeAdapter;
+
+    /// Replicates every component in a bundle
+    fn replicate_bundle<B>(&mut self) -> &mut Self
+    where
+        B: ReplicableBundle;
+
+    /// Gives an already replicated type a value to check its adapter with, see [`round_trip`]
+    fn replicate_sample<T>(&mut self, sample: impl Fn() -> T + Send + Sync + 'static) -> &mut Self
+    where
+        T: Typed + PartialEq + Debug;
+}
+
+/// A bundle that knows how to register each of its components for replication
+///
+/// Implemented by `replicable_bundles!` in [`crate::bundles`], so a new field can't be left
+/// unregistered
+pub trait ReplicableBundle: Bundle {
+    fn replicate_components(app: &mut App);
+
+    /// Type paths of every replicated component, including those of nested bundles
+    fn component_type_paths(paths: &mut Vec<&'static str>);
 }
 
 impl AppReplicateExt for App {
     fn replicate<C>(&mut self) -> &mut Self
     where
-        C: Component + Typed + GetTypeRegistration + SerdeAdapter,
+        C: Component + Typed + GetTypeRegistration + SerdeAdapter + PartialEq,
+    {
+        self.replicate_dir::<C>(ReplicationDirection::Both)
+    }
+
+    fn replicate_dir<C>(&mut self, direction: ReplicationDirection) -> &mut Self
+    where
+        C: Component + Typed + GetTypeRegistration + SerdeAdapter + PartialEq,
     {
         replicate_inner::<C>(
             self,
             ComponentTypeAdapter::Serde(<ReflectSerdeAdapter as FromType<C>>::from_type()),
+            Some(|entity, ptr| {
+                // SAFETY: Caller guarantees the pointer is a `C`
+                let value = unsafe { ptr.read::<C>() };
+
+                if entity.get::<C>() == Some(&value) {
+                    return None;
+                }
+
+                Some(Box::new(value))
+            }),
+            direction,
         );
 
         self
@@ -167,6 +419,8 @@ impl AppReplicateExt for App {
                 <ReflectFromPtr as FromType<C>>::from_type(),
                 <ReflectComponent as FromType<C>>::from_type(),
             ),
+            None,
+            ReplicationDirection::Both,
         );
 
         self
@@ -180,10 +434,11 @@ impl AppReplicateExt for App {
             self,
             EventTypeAdapter::Serde(
                 <ReflectSerdeAdapter as FromType<E>>::from_type(),
-                |world, ptr| unsafe {
+                |world, ptr, _| unsafe {
                     world.send_event(ptr.read::<E>());
                 },
             ),
+            None,
         );
 
         self
@@ -199,14 +454,83 @@ impl AppReplicateExt for App {
                 <ReflectFromPtr as FromType<E>>::from_type(),
                 <ReflectEvent as FromType<E>>::from_type(),
             ),
+            None,
+        );
+
+        self
+    }
+
+    fn replicate_peer_event<E>(&mut self) -> &mut Self
+    where
+        E: PeerEvent + Typed + GetTypeRegistration + SerdeAdapter,
+    {
+        replicate_event_inner::<E>(
+            self,
+            EventTypeAdapter::Serde(
+                <ReflectSerdeAdapter as FromType<E>>::from_type(),
+                |world, ptr, origin| unsafe {
+                    let mut event = ptr.read::<E>();
+                    event.set_origin(origin);
+
+                    world.send_event(event);
+                },
+            ),
+            Some(|ptr| unsafe { ptr.deref::<E>().target() }),
         );
 
         self
     }
+
+    fn replicate_scoped_event<E>(&mut self) -> &mut Self
+    where
+        E: Event + Typed + GetTypeRegistration + SerdeAdapter,
+        Scoped<E>: Typed + GetTypeRegistration + SerdeAdapter,
+    {
+        // Unscoped events are still replicated for senders that haven't moved over yet
+        self.replicate_event::<E>().replicate_event::<Scoped<E>>()
+    }
+
+    fn replicate_rpc<Req, Resp>(&mut self) -> &mut Self
+    where
+        Req: Clone + Send + Sync + 'static,
+        Resp: Clone + Send + Sync + 'static,
+        RpcRequest<Req>: Typed + GetTypeRegistration + SerdeAdapter,
+        RpcResponse<Resp>: Typed + GetTypeRegistration + SerdeAdapter,
+    {
+        self.replicate_peer_event::<RpcRequest<Req>>()
+            .replicate_peer_event::<RpcResponse<Resp>>();
+        rpc::add_rpc_systems::<Req, Resp>(self);
+
+        self
+    }
+
+    fn replicate_bundle<B>(&mut self) -> &mut Self
+    where
+        B: ReplicableBundle,
+    {
+        B::replicate_components(self);
+
+        self
+    }
+
+    fn replicate_sample<T>(&mut self, sample: impl Fn() -> T + Send + Sync + 'static) -> &mut Self
+    where
+        T: Typed + PartialEq + Debug,
+    {
+        self.world
+            .resource_mut::<SerializationSettings>()
+            .set_sample(T::type_path(), round_trip::sample_fn(sample));
+
+        self
+    }
 }
 
-fn replicate_inner<C>(app: &mut App, type_adapter: ComponentTypeAdapter)
-where
+fn replicate_inner<C>(
+    app: &mut App,
+    type_adapter: ComponentTypeAdapter,
+    stage_fn: Option<StageFn>,
+    direction: ReplicationDirection,
+) where
     C: Component + Typed + GetTypeRegistration,
 {
     app.register_type::<C>();
@@ -223,6 +547,9 @@ where
         remove_fn: |entity| {
             entity.remove::<C>();
         },
+        stage_fn,
+        direction,
+        sample: None,
     });
 
     let mut settings = app.world.resource_mut::<SerializationSettings>();
@@ -234,8 +561,11 @@ where
         .insert(component_id, component_info);
 }
 
-fn replicate_event_inner<E>(app: &mut App, type_adapter: EventTypeAdapter)
-where
+fn replicate_event_inner<E>(
+    app: &mut App,
+    type_adapter: EventTypeAdapter,
+    target_fn: Option<TargetFn>,
+) where
     E: Event + Typed + GetTypeRegistration,
 {
     app.register_type::<E>();
@@ -248,6 +578,8 @@ where
         component_id,
         type_adapter,
         reader_factory: ErasedManualEventReader::new::<E>,
+        target_fn,
+        sample: None,
     });
 
     let mut settings = app.world.resource_mut::<SerializationSettings>();
@@ -1,204 +1,1067 @@
+use std::{
+    alloc::{self, Layout},
+    collections::VecDeque,
+    mem,
+    ptr::NonNull,
+};
+
+use ahash::{HashMap, HashSet};
+use anyhow::Context;
 use bevy::{
     app::{App, Plugin, PreUpdate},
     ecs::{
+        component::{ComponentId, Tick},
+        entity::Entity,
         event::EventReader,
         reflect::AppTypeRegistry,
         schedule::{IntoSystemConfigs, SystemSet},
-        system::{Commands, Res, ResMut, SystemChangeTick},
-        world::{Mut, World},
+        system::{Local, ResMut, Resource},
+        world::{EntityRef, World},
     },
+    ptr::OwningPtr,
+    reflect::{Reflect, ReflectFromPtr, ReflectFromReflect},
 };
-use tracing::error;
+use networking::Token;
+use tracing::{error, warn};
 
 use crate::{
-    adapters::{dynamic::DynamicAdapter, ComponentTypeAdapter, EventTypeAdapter},
-    sync::Peers,
+    adapters::{dynamic::DynamicAdapter, BackingType, ComponentTypeAdapter, EventTypeAdapter},
+    sync::{Peers, SyncRole},
 };
 
 use super::{
-    EntityMap, ForignOwned, Replicate, SerializationSettings, SerializedChange,
-    SerializedChangeInEvent,
+    ownership,
+    quarantine::{self, clear_quarantine, ClearQuarantine, QuarantinedTypes},
+    ComponentInfo, EntityMap, EventInfo, ForignOwned, NetId, NetTypeId, NewOwner, Replicate,
+    SerializationSettings, SerializedChange, SerializedChangeInEvent, SerializedChangeOutEvent,
 };
 
+/// Default for [`ChangeApplicationSettings::max_entities_per_frame`]
+pub const DEFAULT_MAX_ENTITIES_PER_FRAME: usize = 200;
+
 pub struct ChangeApplicationPlugin;
 
 impl Plugin for ChangeApplicationPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(PreUpdate, apply_changes.in_set(ChangeApplicationSet));
+        app.init_resource::<QuarantinedTypes>()
+            .init_resource::<RedundantApplies>()
+            .init_resource::<ChangeApplicationSettings>()
+            .init_resource::<PendingChanges>()
+            .init_resource::<AppliedChanges>()
+            .init_resource::<ApplyStats>()
+            .add_event::<ClearQuarantine>()
+            .add_systems(
+                PreUpdate,
+                (clear_quarantine, queue_changes, apply_changes)
+                    .chain()
+                    .in_set(ChangeApplicationSet),
+            );
     }
 }
 
 #[derive(SystemSet, Hash, Debug, PartialEq, Eq, Clone, Copy)]
 pub struct ChangeApplicationSet;
 
-fn apply_changes(
-    mut cmds: Commands,
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct ChangeApplicationSettings {
+    /// Most entities changes are applied to in a single frame, changes to the rest wait for a
+    /// later frame
+    ///
+    /// Keeps the initial sync with a new peer from stalling the frame it arrives in
+    pub max_entities_per_frame: usize,
+}
+
+impl Default for ChangeApplicationSettings {
+    fn default() -> Self {
+        Self {
+            max_entities_per_frame: DEFAULT_MAX_ENTITIES_PER_FRAME,
+        }
+    }
+}
+
+/// Counts inbound updates that were dropped because the component already had an equal value
+///
+/// Each of these would have been detected as a local change and sent back out
+#[derive(Resource, Default, Debug)]
+pub struct RedundantApplies {
+    pub total: u64,
+    pub by_type: HashMap<NetTypeId, u64>,
+}
+
+impl RedundantApplies {
+    pub fn record(&mut self, token: &NetTypeId) {
+        self.total += 1;
+        *self.by_type.entry(token.clone()).or_default() += 1;
+    }
+}
+
+/// How far behind change application is
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ApplyStats {
+    /// Entities changed by the last frame
+    pub last_frame: usize,
+    /// Most entities changed in a single frame
+    pub peak: usize,
+    /// Changes carried over to a later frame
+    pub waiting: usize,
+}
+
+/// Inbound changes that have not been applied yet
+#[derive(Resource, Default, Debug)]
+pub struct PendingChanges(VecDeque<(SerializedChange, Token)>);
+
+/// Changes to one entity, or a single change not tied to an entity
+#[derive(Debug)]
+struct ChangeGroup {
+    entity: Option<NetId>,
+    changes: Vec<(SerializedChange, Token)>,
+}
+
+impl PendingChanges {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Drops changes from a peer that disconnected, its token may be reused by the next
+    /// connection
+    pub(crate) fn remove_peer(&mut self, token: Token) {
+        self.0.retain(|(_, from)| *from != token);
+    }
+
+    /// Takes the changes for at most `max_entities` entities, grouped by entity in the order
+    /// each entity was first seen
+    ///
+    /// Changes to entities past the cap stay queued along with every later change to the same
+    /// entity, so each entity still sees its changes in order. Changes that aren't tied to an
+    /// entity, like events, only wait if a change before them does.
+    fn take_batch(&mut self, max_entities: usize) -> Vec<ChangeGroup> {
+        let mut groups: Vec<ChangeGroup> = Vec::new();
+        let mut by_entity: HashMap<NetId, usize> = HashMap::default();
+        let mut deferred = HashSet::default();
+        let mut remaining = VecDeque::new();
+
+        for (change, token) in self.0.drain(..) {
+            let Some(net_id) = change_entity(&change) else {
+                // An event may depend on the changes sent before it
+                if !deferred.is_empty() {
+                    remaining.push_back((change, token));
+                    continue;
+                }
+
+                groups.push(ChangeGroup {
+                    entity: None,
+                    changes: vec![(change, token)],
+                });
+                continue;
+            };
 
-    ticks: SystemChangeTick,
-    settings: Res<SerializationSettings>,
-    mut entity_map: ResMut<EntityMap>,
-    peers: Res<Peers>,
+            if let Some(&idx) = by_entity.get(&net_id) {
+                groups[idx].changes.push((change, token));
+                continue;
+            }
+
+            if deferred.contains(&net_id) || by_entity.len() >= max_entities {
+                deferred.insert(net_id);
+                remaining.push_back((change, token));
+                continue;
+            }
+
+            by_entity.insert(net_id, groups.len());
+            groups.push(ChangeGroup {
+                entity: Some(net_id),
+                changes: vec![(change, token)],
+            });
+        }
+
+        self.0 = remaining;
+
+        groups
+    }
+}
+
+fn change_entity(change: &SerializedChange) -> Option<NetId> {
+    match change {
+        SerializedChange::EntitySpawned(net_id)
+        | SerializedChange::EntityDespawned(net_id)
+        | SerializedChange::ComponentUpdated(net_id, _, _)
+        | SerializedChange::OwnershipTransferred(net_id, _) => Some(*net_id),
+        SerializedChange::EventEmitted(..) | SerializedChange::TypeQuarantined(..) => None,
+    }
+}
+
+/// Inbound changes applied this frame
+///
+/// Applying them shows up as local changes, change detection uses this to avoid echoing them
+/// back out
+#[derive(Resource, Default, Debug)]
+pub(crate) struct AppliedChanges(pub(crate) Vec<SerializedChange>);
+
+#[cfg_attr(
+    feature = "sync_instrumentation",
+    tracing::instrument(level = "debug", skip_all)
+)]
+fn queue_changes(
+    mut pending: ResMut<PendingChanges>,
     mut reader: EventReader<SerializedChangeInEvent>,
 ) {
-    for SerializedChangeInEvent(change, token) in reader.read() {
-        if !peers.valid_tokens.contains(token) {
-            // The peer disconnected and has already been cleaned up
-            continue;
+    pending.0.extend(
+        reader
+            .read()
+            .map(|SerializedChangeInEvent(change, token)| (change.clone(), *token)),
+    );
+}
+
+/// Applies queued changes directly to the world, at most
+/// [`ChangeApplicationSettings::max_entities_per_frame`] entities at a time
+#[cfg_attr(
+    feature = "sync_instrumentation",
+    tracing::instrument(level = "debug", skip_all)
+)]
+fn apply_changes(world: &mut World, mut rejected: Local<HashSet<NetTypeId>>) {
+    let max_entities = world
+        .resource::<ChangeApplicationSettings>()
+        .max_entities_per_frame
+        // Nothing would ever be applied
+        .max(1);
+
+    let mut pending = world.resource_mut::<PendingChanges>();
+    let batch = pending.take_batch(max_entities);
+    let waiting = pending.len();
+
+    let entities = batch.iter().filter(|it| it.entity.is_some()).count();
+    let mut stats = world.resource_mut::<ApplyStats>();
+    stats.last_frame = entities;
+    stats.peak = stats.peak.max(entities);
+    stats.waiting = waiting;
+
+    let role = *world.resource::<SyncRole>();
+    let tick = world.change_tick();
+    let mut applied = Vec::new();
+
+    for group in batch {
+        let mut staged = StagedComponents::default();
+
+        for (change, token) in group.changes {
+            if !world.resource::<Peers>().valid_tokens.contains(&token) {
+                // The peer disconnected and has already been cleaned up
+                continue;
+            }
+
+            // Anything but another component update has to see the ones before it
+            if !matches!(change, SerializedChange::ComponentUpdated(_, _, Some(_))) {
+                staged.insert(world);
+            }
+
+            apply_change(
+                world,
+                &mut rejected,
+                &mut staged,
+                role,
+                tick,
+                &change,
+                token,
+            );
+            applied.push(change);
         }
 
-        match change {
-            SerializedChange::EntitySpawned(forign) => {
-                let local = cmds.spawn((Replicate, *forign, ForignOwned(token.0))).id();
+        staged.insert(world);
+    }
 
-                entity_map.local_to_forign.insert(local, *forign);
-                entity_map.forign_to_local.insert(*forign, local);
+    world.resource_mut::<AppliedChanges>().0 = applied;
+}
 
-                entity_map
-                    .forign_owned
-                    .entry(*token)
-                    .or_default()
-                    .insert(local);
+/// Component updates to one entity, inserted together so no system sees it half updated
+#[derive(Default)]
+struct StagedComponents {
+    entity: Option<Entity>,
+    /// The concrete component behind each id, never a dynamic value
+    components: Vec<(ComponentId, Box<dyn Reflect>)>,
+}
+
+impl StagedComponents {
+    /// Drops the value staged for `component_id`, a later update replaces it
+    fn discard(&mut self, component_id: ComponentId) {
+        self.components.retain(|(id, _)| *id != component_id);
+    }
+
+    fn stage(&mut self, entity: Entity, component_id: ComponentId, value: Box<dyn Reflect>) {
+        debug_assert!(self.entity.is_none() || self.entity == Some(entity));
 
-                entity_map.local_modified.insert(local, ticks.this_run());
+        self.entity = Some(entity);
+        self.components.push((component_id, value));
+    }
+
+    /// Inserts every staged component as one bundle
+    fn insert(&mut self, world: &mut World) {
+        let components = mem::take(&mut self.components);
+        let Some(entity) = self.entity.take() else {
+            return;
+        };
+        let Some(mut entity) = world.get_entity_mut(entity) else {
+            return;
+        };
+
+        let mut component_ids = Vec::with_capacity(components.len());
+        let mut values = Vec::with_capacity(components.len());
+        for (component_id, value) in components {
+            let layout = Layout::for_value(&*value);
+            component_ids.push(component_id);
+            values.push((Box::into_raw(value), layout));
+        }
+
+        // SAFETY: Each value was checked to be the component its id belongs to when it was
+        // staged, and is moved into the world here
+        unsafe {
+            entity.insert_by_ids(
+                &component_ids,
+                values
+                    .iter()
+                    .map(|(value, _)| OwningPtr::new(NonNull::new_unchecked(value.cast()))),
+            );
+        }
+
+        for (value, layout) in values {
+            if layout.size() != 0 {
+                // SAFETY: The box's value was moved out above, only its allocation is left
+                unsafe { alloc::dealloc(value.cast(), layout) };
             }
-            SerializedChange::EntityDespawned(forign) => {
-                let Some(local) = entity_map.forign_to_local.remove(forign) else {
-                    error!("Got despawn for unknown entity");
-                    continue;
-                };
+        }
+    }
+}
 
-                entity_map.local_to_forign.remove(&local);
-                entity_map.local_modified.remove(&local);
+fn apply_change(
+    world: &mut World,
+    rejected: &mut HashSet<NetTypeId>,
+    staged: &mut StagedComponents,
+    role: SyncRole,
+    tick: Tick,
+    change: &SerializedChange,
+    token: Token,
+) {
+    match change {
+        SerializedChange::EntitySpawned(forign) => {
+            let local = world.spawn((Replicate, *forign, ForignOwned(token.0))).id();
 
-                let owned_entities = entity_map.forign_owned.get_mut(token);
-                if let Some(owned_entities) = owned_entities {
-                    owned_entities.remove(&local);
-                }
+            let mut entity_map = world.resource_mut::<EntityMap>();
+            entity_map.local_to_forign.insert(local, *forign);
+            entity_map.forign_to_local.insert(*forign, local);
+
+            entity_map
+                .forign_owned
+                .entry(token)
+                .or_default()
+                .insert(local);
+
+            entity_map.local_modified.insert(local, tick);
+        }
+        SerializedChange::EntityDespawned(forign) => {
+            let mut entity_map = world.resource_mut::<EntityMap>();
+            let Some(local) = entity_map.forign_to_local.remove(forign) else {
+                error!("Got despawn for unknown entity");
+                return;
+            };
+
+            entity_map.local_to_forign.remove(&local);
+            entity_map.local_modified.remove(&local);
 
-                cmds.entity(local).despawn();
+            let owned_entities = entity_map.forign_owned.get_mut(&token);
+            if let Some(owned_entities) = owned_entities {
+                owned_entities.remove(&local);
             }
-            SerializedChange::ComponentUpdated(forign, token, Some(serialized)) => {
-                let Some(&local) = entity_map.forign_to_local.get(forign) else {
-                    error!("Got update for unknown entity: {token}");
-                    continue;
-                };
 
-                let Some(sync_info) = settings.component_by_token.get(token) else {
-                    error!("Got update for unknown entity token");
-                    continue;
-                };
-
-                let type_adapter = sync_info.type_adapter.clone();
-                let serialized = serialized.clone();
-                let token = token.clone();
-                let component_id = sync_info.component_id;
-
-                cmds.add(move |world: &mut World| {
-                    // TODO(mid): Error handling
-                    match type_adapter {
-                        ComponentTypeAdapter::Serde(adapter) => {
-                            adapter
-                                .deserialize(&serialized, |ptr|
-                                    // SAFETY: We used the type adapter associated with this component id
-                                    unsafe {
-                                        if let Some(mut entity) = world.get_entity_mut(local) {
-                                            entity.insert_by_id(component_id, ptr);
-                                        }
-                                    })
-                                .expect("Bad update");
-                        }
-                        ComponentTypeAdapter::Reflect(_, component) => {
-                            world.resource_scope(|world, registry: Mut<AppTypeRegistry>| {
-                                let registry = registry.read();
-
-                                let reflect = {
-                                    let registration = registry
-                                        .get_with_type_path(&token)
-                                        .expect("Update for unknown token");
-
-                                    DynamicAdapter::deserialize(
-                                        &serialized,
-                                        registration,
-                                        &registry,
-                                    )
-                                    .expect("Bad update")
-                                };
-
-                                if let Some(mut entity) = world.get_entity_mut(local) {
-                                    component.insert(&mut entity, &*reflect, &registry);
-                                }
-                            })
-                        }
-                    }
-                });
+            world.despawn(local);
+        }
+        SerializedChange::ComponentUpdated(forign, type_token, serialized) => {
+            let Some(&local) = world.resource::<EntityMap>().forign_to_local.get(forign) else {
+                error!("Got update for unknown entity: {type_token}");
+                return;
+            };
+
+            let Some(sync_info) = world
+                .resource::<SerializationSettings>()
+                .component_by_token
+                .get(type_token)
+                .cloned()
+            else {
+                error!("Got update for unknown entity token");
+                return;
+            };
 
-                entity_map.local_modified.insert(local, ticks.this_run());
+            if !sync_info.direction.may_receive(&role) {
+                if rejected.insert(type_token.clone()) {
+                    warn!(
+                        "Rejected update for {type_token}, it only replicates {:?}",
+                        sync_info.direction
+                    );
+                }
+                return;
             }
-            SerializedChange::ComponentUpdated(forign, token, None) => {
-                let Some(&local) = entity_map.forign_to_local.get(forign) else {
-                    error!("Got update for unknown entity");
-                    continue;
-                };
 
-                let Some(sync_info) = settings.component_by_token.get(token) else {
-                    error!("Got update for unknown entity token");
-                    continue;
-                };
+            match serialized {
+                Some(serialized) => {
+                    if world
+                        .resource::<QuarantinedTypes>()
+                        .is_quarantined(type_token)
+                    {
+                        return;
+                    }
+
+                    // The update is accepted before we know if it changes anything, an equal
+                    // value still counts as the latest word from the peer for `local_modified`.
+                    // Only the write to the world is skipped, so change detection doesn't send
+                    // it back out.
+                    let rst =
+                        stage_component(world, staged, local, &sync_info, type_token, serialized);
 
-                let remover = sync_info.remove_fn;
-                cmds.add(move |world: &mut World| {
+                    if let Ok(false) = rst {
+                        world.resource_mut::<RedundantApplies>().record(type_token);
+                    }
+
+                    quarantine::report_apply(world, type_token, rst.map(|_| ()));
+                }
+                None => {
                     if let Some(mut entity) = world.get_entity_mut(local) {
-                        (remover)(&mut entity);
+                        (sync_info.remove_fn)(&mut entity);
                     }
-                });
+                }
+            }
+
+            world
+                .resource_mut::<EntityMap>()
+                .local_modified
+                .insert(local, tick);
+        }
+        SerializedChange::EventEmitted(type_token, serialized) => {
+            let Some(sync_info) = world
+                .resource::<SerializationSettings>()
+                .event_by_token
+                .get(type_token)
+                .cloned()
+            else {
+                error!("Got unknown event");
+                return;
+            };
 
-                entity_map.local_modified.insert(local, ticks.this_run());
+            if world
+                .resource::<QuarantinedTypes>()
+                .is_quarantined(type_token)
+            {
+                return;
             }
-            SerializedChange::EventEmitted(token, serialized) => {
-                let Some(sync_info) = settings.event_by_token.get(token) else {
-                    error!("Got unknown event");
-                    continue;
-                };
-
-                let type_adapter = sync_info.type_adapter.clone();
-                let serialized = serialized.clone();
-                let token = token.clone();
-
-                cmds.add(move |world: &mut World| {
-                    // TODO(mid): Error handling
-                    match type_adapter {
-                        EventTypeAdapter::Serde(adapter, sender) => {
-                            adapter
-                                .deserialize(&serialized, |ptr|
-                                    // SAFETY: We used the type adapter associated with this component id
-                                    unsafe {
-                                        (sender)(world, ptr)
-                                    })
-                                .expect("Bad update");
+
+            let rst = send_event(world, &sync_info, type_token, serialized, token);
+            quarantine::report_apply(world, type_token, rst);
+        }
+        SerializedChange::OwnershipTransferred(forign, NewOwner::Sender) => {
+            let Some(&local) = world.resource::<EntityMap>().forign_to_local.get(forign) else {
+                // We never saw the original owner's copy of this entity, treat it as a spawn
+                let local = world.spawn((Replicate, *forign, ForignOwned(token.0))).id();
+
+                let mut entity_map = world.resource_mut::<EntityMap>();
+                entity_map.local_to_forign.insert(local, *forign);
+                entity_map.forign_to_local.insert(*forign, local);
+                entity_map.set_owner(local, Some(token));
+                entity_map.local_modified.insert(local, tick);
+
+                return;
+            };
+
+            // Only the current owner or the server may claim an entity
+            let mut entity_map = world.resource_mut::<EntityMap>();
+            let is_owner = entity_map.owner(local) == Some(token);
+            let from_server = matches!(role, SyncRole::Client);
+            if !is_owner && !from_server {
+                error!("Peer {token:?} tried to claim an entity it does not own");
+                return;
+            }
+
+            entity_map.set_owner(local, Some(token));
+            if let Some(mut entity) = world.get_entity_mut(local) {
+                entity.insert(ForignOwned(token.0));
+            }
+        }
+        SerializedChange::OwnershipTransferred(forign, NewOwner::Receiver) => {
+            let mut entity_map = world.resource_mut::<EntityMap>();
+            let Some(&local) = entity_map.forign_to_local.get(forign) else {
+                error!("Got ownership transfer for unknown entity");
+                return;
+            };
+
+            if entity_map.owner(local) != Some(token) {
+                error!("Peer {token:?} tried to transfer an entity it does not own");
+                return;
+            }
+
+            entity_map.set_owner(local, None);
+            if let Some(mut entity) = world.get_entity_mut(local) {
+                entity.remove::<ForignOwned>();
+            }
+            ownership::mark_replicated_changed(world, local);
+
+            // Let our other peers know who to expect updates from
+            world.send_event(SerializedChangeOutEvent(
+                SerializedChange::OwnershipTransferred(*forign, NewOwner::Sender),
+            ));
+        }
+        SerializedChange::TypeQuarantined(type_token, quarantined) => {
+            world.resource_mut::<QuarantinedTypes>().set_suppressed(
+                type_token.clone(),
+                token,
+                *quarantined,
+            );
+        }
+    }
+}
+
+/// Stages a serialized component for `local`, returns false if it already had an equal value
+fn stage_component(
+    world: &mut World,
+    staged: &mut StagedComponents,
+    local: Entity,
+    sync_info: &ComponentInfo,
+    token: &NetTypeId,
+    serialized: &BackingType,
+) -> anyhow::Result<bool> {
+    staged.discard(sync_info.component_id);
+
+    match &sync_info.type_adapter {
+        ComponentTypeAdapter::Serde(adapter) => {
+            let mut value = None;
+            let mut inserted = true;
+
+            adapter
+                .deserialize(serialized, |ptr|
+                    // SAFETY: We used the type adapter associated with this component id
+                    unsafe {
+                        if world.get_entity(local).is_none() {
+                            return;
                         }
-                        EventTypeAdapter::Reflect(_, event) => {
-                            world.resource_scope(|world, registry: Mut<AppTypeRegistry>| {
-                                let registry = registry.read();
-
-                                let reflect = {
-                                    let registration = registry
-                                        .get_with_type_path(&token)
-                                        .expect("Update for unknown token");
-
-                                    DynamicAdapter::deserialize(
-                                        &serialized,
-                                        registration,
-                                        &registry,
-                                    )
-                                    .expect("Bad update")
-                                };
-
-                                event.send(world, &*reflect)
-                            })
+
+                        if let Some(stage) = sync_info.stage_fn {
+                            value = (stage)(world.entity(local), ptr);
+                            inserted = value.is_some();
+                        } else {
+                            world
+                                .entity_mut(local)
+                                .insert_by_id(sync_info.component_id, ptr);
                         }
-                    }
-                });
+                    })
+                .context("Deserialize component")?;
+
+            if let Some(value) = value {
+                anyhow::ensure!(
+                    value.as_any().type_id() == sync_info.type_id,
+                    "Staged the wrong type for {token}"
+                );
+                staged.stage(local, sync_info.component_id, value);
+            }
+
+            Ok(inserted)
+        }
+        ComponentTypeAdapter::Reflect(from_ptr, _) => {
+            let registry = world.resource::<AppTypeRegistry>().clone();
+            let registry = registry.read();
+
+            let reflect = {
+                let registration = registry
+                    .get_with_type_path(token)
+                    .context("Update for unknown token")?;
+
+                DynamicAdapter::deserialize(serialized, registration, &registry)
+                    .context("Deserialize component")?
+            };
+
+            let Some(entity) = world.get_entity(local) else {
+                return Ok(true);
+            };
+
+            if reflect_unchanged(entity, sync_info.component_id, from_ptr, &*reflect) {
+                return Ok(false);
+            }
+
+            // Deserializing gives a dynamic value, bundles need the concrete type
+            let value = registry
+                .get_with_type_path(token)
+                .and_then(|it| it.data::<ReflectFromReflect>())
+                .and_then(|it| it.from_reflect(&*reflect))
+                .context("Convert component")?;
+            anyhow::ensure!(
+                value.as_any().type_id() == sync_info.type_id,
+                "Staged the wrong type for {token}"
+            );
+            staged.stage(local, sync_info.component_id, value);
+
+            Ok(true)
+        }
+    }
+}
+
+fn send_event(
+    world: &mut World,
+    sync_info: &EventInfo,
+    token: &NetTypeId,
+    serialized: &BackingType,
+    origin: Token,
+) -> anyhow::Result<()> {
+    match &sync_info.type_adapter {
+        EventTypeAdapter::Serde(adapter, sender) => {
+            adapter
+                .deserialize(serialized, |ptr|
+                    // SAFETY: We used the type adapter associated with this event
+                    unsafe {
+                        (sender)(world, ptr, origin)
+                    })
+                .context("Deserialize event")?;
+        }
+        EventTypeAdapter::Reflect(_, event) => {
+            let registry = world.resource::<AppTypeRegistry>().clone();
+            let registry = registry.read();
+
+            let reflect = {
+                let registration = registry
+                    .get_with_type_path(token)
+                    .context("Update for unknown token")?;
+
+                DynamicAdapter::deserialize(serialized, registration, &registry)
+                    .context("Deserialize event")?
+            };
+
+            event.send(world, &*reflect);
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the entity already has a value equal to `incoming`
+///
+/// Types that don't reflect [`PartialEq`] are never considered unchanged
+fn reflect_unchanged(
+    entity: EntityRef,
+    component_id: ComponentId,
+    from_ptr: &ReflectFromPtr,
+    incoming: &dyn Reflect,
+) -> bool {
+    let Some(existing) = entity.get_by_id(component_id) else {
+        return false;
+    };
+
+    // SAFETY: The pointer came from the component id this reflect data was registered with
+    let existing = unsafe { from_ptr.as_reflect(existing) };
+
+    existing.reflect_partial_eq(incoming) == Some(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, sync::Arc};
+
+    use bevy::{
+        app::App,
+        core::Name,
+        ecs::{change_detection::DetectChanges, component::Component},
+        reflect::Reflect,
+    };
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        ecs_sync::{
+            test_utils::{
+                app, deliver, errors, local, outbound, Other, Test, CLIENT_A, CLIENT_B, ROBOT,
+            },
+            AppReplicateExt, NetId, Replicate, ReplicationDirection, SerializedChange,
+        },
+        sync::SyncRole,
+    };
+
+    use super::{ApplyStats, ChangeApplicationSettings, PendingChanges, RedundantApplies};
+
+    /// Like a hash map, deserializing changes the order of the entries but not the value
+    #[derive(Component, Reflect, Serialize, Deserialize, Debug, Clone)]
+    #[serde(from = "Vec<u32>", into = "Vec<u32>")]
+    struct Unordered(Vec<u32>);
+
+    impl From<Vec<u32>> for Unordered {
+        fn from(mut value: Vec<u32>) -> Self {
+            value.reverse();
+            Self(value)
+        }
+    }
+
+    impl From<Unordered> for Vec<u32> {
+        fn from(value: Unordered) -> Self {
+            value.0
+        }
+    }
+
+    impl PartialEq for Unordered {
+        fn eq(&self, other: &Self) -> bool {
+            let mut this = self.0.clone();
+            let mut other = other.0.clone();
+            this.sort();
+            other.sort();
+
+            this == other
+        }
+    }
+
+    /// Sensor data only the robot may send
+    #[derive(Component, Reflect, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+    struct Reading(u32);
+
+    fn updates(changes: &[SerializedChange]) -> usize {
+        changes
+            .iter()
+            .filter(|it| matches!(it, SerializedChange::ComponentUpdated(..)))
+            .count()
+    }
+
+    /// Passes the robot's changes to both clients and theirs back, returns the updates sent
+    fn round(robot: &mut App, client_a: &mut App, client_b: &mut App) -> usize {
+        let from_robot = outbound(robot);
+        deliver(client_a, &from_robot, ROBOT);
+        deliver(client_b, &from_robot, ROBOT);
+
+        let from_a = outbound(client_a);
+        let from_b = outbound(client_b);
+        deliver(robot, &from_a, CLIENT_A);
+        deliver(robot, &from_b, CLIENT_B);
+
+        updates(&from_robot) + updates(&from_a) + updates(&from_b)
+    }
+
+    fn topology() -> (App, App, App) {
+        let mut robot = app(SyncRole::Server { port: Some(0) }, &[CLIENT_A, CLIENT_B]);
+        let mut client_a = app(SyncRole::Client, &[ROBOT]);
+        let mut client_b = app(SyncRole::Client, &[ROBOT]);
+
+        for app in [&mut robot, &mut client_a, &mut client_b] {
+            app.replicate::<Unordered>();
+        }
+
+        (robot, client_a, client_b)
+    }
+
+    #[test]
+    fn equal_values_are_not_echoed() {
+        let (mut robot, mut client_a, mut client_b) = topology();
+
+        let entity = robot
+            .world
+            .spawn((Unordered(vec![1, 2, 3]), Replicate))
+            .id();
+        robot.update();
+        let net_id = *robot.world.get::<NetId>(entity).unwrap();
+
+        // The clients echo the new component once since they had no value to compare to, the
+        // robot already has it and the echo stops there
+        assert_eq!(round(&mut robot, &mut client_a, &mut client_b), 3);
+        assert_eq!(robot.world.resource::<RedundantApplies>().total, 2);
+
+        for _ in 0..5 {
+            assert_eq!(round(&mut robot, &mut client_a, &mut client_b), 0);
+        }
+
+        // Genuine changes still propagate
+        robot.world.entity_mut(entity).insert(Unordered(vec![4, 5]));
+        robot.update();
+        assert_eq!(round(&mut robot, &mut client_a, &mut client_b), 3);
+        assert_eq!(round(&mut robot, &mut client_a, &mut client_b), 0);
+
+        for client in [&client_a, &client_b] {
+            let entity = local(client, net_id).unwrap();
+            assert_eq!(
+                client.world.get::<Unordered>(entity),
+                Some(&Unordered(vec![4, 5]))
+            );
+        }
+        assert_eq!(
+            robot.world.get::<Unordered>(entity),
+            Some(&Unordered(vec![4, 5]))
+        );
+    }
+
+    #[test]
+    fn redundant_updates_are_counted() {
+        let (mut robot, mut client_a, _) = topology();
+
+        let entity = client_a.world.spawn((Test(1), Replicate)).id();
+        client_a.update();
+        let net_id = *client_a.world.get::<NetId>(entity).unwrap();
+
+        let changes = outbound(&mut client_a);
+        deliver(&mut robot, &changes, CLIENT_A);
+        assert_eq!(robot.world.resource::<RedundantApplies>().total, 0);
+
+        // Resending the same value doesn't touch the component
+        let robot_entity = local(&robot, net_id).unwrap();
+        let update = changes
+            .into_iter()
+            .filter(|it| matches!(it, SerializedChange::ComponentUpdated(..)))
+            .collect::<Vec<_>>();
+        let changed_before = robot
+            .world
+            .entity(robot_entity)
+            .get_ref::<Test>()
+            .unwrap()
+            .last_changed();
+
+        deliver(&mut robot, &update, CLIENT_A);
+        let redundant = robot.world.resource::<RedundantApplies>();
+        assert_eq!(redundant.total, 1);
+        assert_eq!(redundant.by_type.values().sum::<u64>(), 1);
+        assert_eq!(
+            robot
+                .world
+                .entity(robot_entity)
+                .get_ref::<Test>()
+                .unwrap()
+                .last_changed(),
+            changed_before
+        );
+        assert!(outbound(&mut robot).is_empty());
+    }
+
+    #[test]
+    fn clients_cannot_push_server_to_client_components() {
+        let mut robot = app(SyncRole::Server { port: Some(0) }, &[CLIENT_A]);
+        let mut client = app(SyncRole::Client, &[ROBOT]);
+        // Registered both ways, as a buggy or malicious peer might
+        let mut rogue = app(SyncRole::Client, &[ROBOT]);
+
+        robot.replicate_dir::<Reading>(ReplicationDirection::ServerToClient);
+        client.replicate_dir::<Reading>(ReplicationDirection::ServerToClient);
+        rogue.replicate::<Reading>();
+
+        let entity = robot.world.spawn((Reading(1), Test(1), Replicate)).id();
+        robot.update();
+        let net_id = *robot.world.get::<NetId>(entity).unwrap();
+
+        let from_robot = outbound(&mut robot);
+        deliver(&mut client, &from_robot, ROBOT);
+        deliver(&mut rogue, &from_robot, ROBOT);
+        outbound(&mut client);
+        outbound(&mut rogue);
+
+        // Local edits on a client stay local
+        let client_entity = local(&client, net_id).unwrap();
+        assert_eq!(
+            client.world.get::<Reading>(client_entity),
+            Some(&Reading(1))
+        );
+        client.world.entity_mut(client_entity).insert(Reading(2));
+        client.update();
+        assert_eq!(updates(&outbound(&mut client)), 0);
+
+        // and pushing them anyway gets them dropped by the robot
+        let rogue_entity = local(&rogue, net_id).unwrap();
+        rogue
+            .world
+            .entity_mut(rogue_entity)
+            .insert((Reading(3), Test(3)));
+        rogue.update();
+        let from_rogue = outbound(&mut rogue);
+        assert_eq!(updates(&from_rogue), 2);
+
+        for _ in 0..3 {
+            deliver(&mut robot, &from_rogue, CLIENT_A);
+        }
+
+        assert_eq!(robot.world.get::<Reading>(entity), Some(&Reading(1)));
+        // Types the client may send are still applied
+        assert_eq!(robot.world.get::<Test>(entity), Some(&Test(3)));
+        assert_eq!(errors(&mut robot), 0);
+    }
+
+    #[test]
+    fn batches_keep_each_entity_in_order() {
+        let (a, b, c) = (NetId::random(), NetId::random(), NetId::random());
+        let update = |net_id, value: u8| {
+            SerializedChange::ComponentUpdated(
+                net_id,
+                Cow::Borrowed("Test"),
+                Some(Arc::new(vec![value])),
+            )
+        };
+        let event = SerializedChange::EventEmitted(Cow::Borrowed("Ping"), Arc::new(vec![]));
+
+        let mut pending = PendingChanges::default();
+        pending.0.extend(
+            [
+                SerializedChange::EntitySpawned(a),
+                SerializedChange::EntitySpawned(b),
+                update(a, 1),
+                SerializedChange::EntitySpawned(c),
+                update(b, 1),
+                event.clone(),
+                update(c, 1),
+                update(a, 2),
+            ]
+            .into_iter()
+            .map(|change| (change, ROBOT)),
+        );
+
+        let batch = pending.take_batch(2);
+        let batch = batch
+            .into_iter()
+            .map(|group| {
+                let changes = group.changes.into_iter().map(|(it, _)| it);
+                (group.entity, changes.collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+
+        // Changes before the event are applied first, it waits for the ones held back
+        assert_eq!(
+            batch,
+            [
+                (
+                    Some(a),
+                    vec![
+                        SerializedChange::EntitySpawned(a),
+                        update(a, 1),
+                        update(a, 2)
+                    ]
+                ),
+                (
+                    Some(b),
+                    vec![SerializedChange::EntitySpawned(b), update(b, 1)]
+                ),
+            ]
+        );
+
+        // Everything for the entity past the cap waits, in order
+        let rest = pending.take_batch(2);
+        assert!(pending.is_empty());
+        let rest = rest
+            .into_iter()
+            .map(|group| {
+                let changes = group.changes.into_iter().map(|(it, _)| it);
+                (group.entity, changes.collect::<Vec<_>>())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rest,
+            [
+                (
+                    Some(c),
+                    vec![SerializedChange::EntitySpawned(c), update(c, 1)]
+                ),
+                (None, vec![event]),
+            ]
+        );
+    }
+
+    #[test]
+    fn entities_get_their_components_at_once() {
+        let mut robot = app(SyncRole::Server { port: Some(0) }, &[CLIENT_A]);
+        let mut client = app(SyncRole::Client, &[ROBOT]);
+        for app in [&mut robot, &mut client] {
+            app.replicate_reflect::<Name>();
+        }
+
+        let entity = robot
+            .world
+            .spawn((Test(1), Other(2), Name::new("Thing"), Replicate))
+            .id();
+        robot.update();
+        let net_id = *robot.world.get::<NetId>(entity).unwrap();
+
+        // A second update to the same component in the batch replaces the first
+        let mut changes = outbound(&mut robot);
+        robot.world.entity_mut(entity).insert(Test(3));
+        robot.update();
+        changes.extend(outbound(&mut robot));
+        deliver(&mut client, &changes, ROBOT);
+
+        let local = local(&client, net_id).unwrap();
+        assert_eq!(client.world.get::<Test>(local), Some(&Test(3)));
+        assert_eq!(client.world.get::<Other>(local), Some(&Other(2)));
+        assert_eq!(
+            client.world.get::<Name>(local).map(Name::as_str),
+            Some("Thing")
+        );
+
+        // No archetype was ever made for a partly built entity
+        let components = client.world.components();
+        let ids = [
+            components.component_id::<Test>().unwrap(),
+            components.component_id::<Other>().unwrap(),
+            components.component_id::<Name>().unwrap(),
+        ];
+        for archetype in client.world.archetypes().iter() {
+            let present = ids.iter().filter(|&&id| archetype.contains(id)).count();
+            assert!(present == 0 || present == ids.len(), "{present}");
+        }
+        assert_eq!(errors(&mut client), 0);
+    }
+
+    #[test]
+    fn departed_peers_changes_are_dropped() {
+        let (a, b) = (NetId::random(), NetId::random());
+
+        let mut pending = PendingChanges::default();
+        pending.0.extend([
+            (SerializedChange::EntitySpawned(a), CLIENT_A),
+            (SerializedChange::EntitySpawned(b), CLIENT_B),
+        ]);
+
+        // Otherwise a reconnect reusing the token would pick up the old connection's backlog
+        pending.remove_peer(CLIENT_A);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending.take_batch(10)[0].entity, Some(b));
+    }
+
+    #[test]
+    fn initial_sync_is_spread_over_frames() {
+        const ENTITIES: u32 = 1000;
+        const CAP: usize = 64;
+
+        let mut robot = app(SyncRole::Server { port: Some(0) }, &[CLIENT_A]);
+        let mut client = app(SyncRole::Client, &[ROBOT]);
+        client
+            .world
+            .resource_mut::<ChangeApplicationSettings>()
+            .max_entities_per_frame = CAP;
+
+        let entities = (0..ENTITIES)
+            .map(|idx| robot.world.spawn((Test(idx), Other(idx), Replicate)).id())
+            .collect::<Vec<_>>();
+        robot.update();
+        let net_ids = entities
+            .iter()
+            .map(|&entity| *robot.world.get::<NetId>(entity).unwrap())
+            .collect::<Vec<_>>();
+
+        deliver(&mut client, &outbound(&mut robot), ROBOT);
+
+        let mut frames = 1;
+        loop {
+            let stats = *client.world.resource::<ApplyStats>();
+            assert!(stats.last_frame <= CAP);
+
+            // Applied changes are never echoed back, even when they wait a few frames
+            assert!(outbound(&mut client).is_empty());
+
+            if stats.waiting == 0 {
+                break;
             }
+
+            client.update();
+            frames += 1;
+        }
+
+        assert_eq!(frames, (ENTITIES as usize).div_ceil(CAP));
+        assert_eq!(client.world.resource::<ApplyStats>().peak, CAP);
+
+        for (idx, net_id) in net_ids.into_iter().enumerate() {
+            let entity = local(&client, net_id).unwrap();
+            assert_eq!(client.world.get::<Test>(entity), Some(&Test(idx as u32)));
+            assert_eq!(client.world.get::<Other>(entity), Some(&Other(idx as u32)));
         }
+        assert_eq!(errors(&mut client), 0);
     }
 }
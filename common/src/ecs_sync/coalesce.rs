@@ -0,0 +1,133 @@
+//! Drops outbound component updates superseded later in the same frame
+//!
+//! Only the last update per entity and type is kept, in its original place. Spawns, despawns and
+//! ownership transfers of an entity are barriers, updates are never merged across them so a peer
+//! still sees every component in the right lifetime of the entity.
+
+use ahash::{HashMap, HashSet};
+
+use super::{NetId, NetTypeId, SerializedChange};
+
+/// The changes left after coalescing, in their original order
+pub fn coalesce<'a>(
+    changes: impl DoubleEndedIterator<Item = &'a SerializedChange>,
+) -> Vec<&'a SerializedChange> {
+    // Walk backwards so the first update seen for a type is the one that survives
+    let mut superseded: HashMap<NetId, HashSet<&NetTypeId>> = HashMap::default();
+    let mut kept = Vec::new();
+
+    for change in changes.rev() {
+        match change {
+            SerializedChange::ComponentUpdated(net_id, token, _) => {
+                if !superseded.entry(*net_id).or_default().insert(token) {
+                    continue;
+                }
+            }
+            SerializedChange::EntitySpawned(net_id)
+            | SerializedChange::EntityDespawned(net_id)
+            | SerializedChange::OwnershipTransferred(net_id, _) => {
+                superseded.remove(net_id);
+            }
+            SerializedChange::EventEmitted(..) | SerializedChange::TypeQuarantined(..) => {}
+        }
+
+        kept.push(change);
+    }
+
+    kept.reverse();
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, sync::Arc};
+
+    use crate::ecs_sync::{NetId, NewOwner, SerializedChange};
+
+    use super::coalesce;
+
+    fn update(net_id: NetId, name: &'static str, value: u8) -> SerializedChange {
+        SerializedChange::ComponentUpdated(net_id, Cow::Borrowed(name), Some(Arc::new(vec![value])))
+    }
+
+    fn coalesced(changes: &[SerializedChange]) -> Vec<SerializedChange> {
+        coalesce(changes.iter()).into_iter().cloned().collect()
+    }
+
+    #[test]
+    fn last_update_wins() {
+        let robot = NetId::random();
+        let other = NetId::random();
+
+        let changes = [
+            update(robot, "MovementContribution", 1),
+            update(robot, "Depth", 1),
+            update(other, "MovementContribution", 1),
+            update(robot, "MovementContribution", 2),
+            update(robot, "MovementContribution", 3),
+        ];
+
+        assert_eq!(
+            coalesced(&changes),
+            [
+                update(robot, "Depth", 1),
+                update(other, "MovementContribution", 1),
+                update(robot, "MovementContribution", 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn removals_are_updates_too() {
+        let robot = NetId::random();
+        let removed = SerializedChange::ComponentUpdated(robot, Cow::Borrowed("Depth"), None);
+
+        let changes = [update(robot, "Depth", 1), removed.clone()];
+        assert_eq!(coalesced(&changes), changes[1..]);
+
+        let changes = [removed, update(robot, "Depth", 2)];
+        assert_eq!(coalesced(&changes), [update(robot, "Depth", 2)]);
+    }
+
+    #[test]
+    fn lifetimes_are_barriers() {
+        let robot = NetId::random();
+
+        // The update before the despawn belongs to the old entity
+        let changes = [
+            SerializedChange::EntitySpawned(robot),
+            update(robot, "Depth", 1),
+            update(robot, "Depth", 2),
+            SerializedChange::EntityDespawned(robot),
+            SerializedChange::EntitySpawned(robot),
+            update(robot, "Depth", 3),
+            update(robot, "Depth", 4),
+        ];
+        assert_eq!(
+            coalesced(&changes),
+            [
+                SerializedChange::EntitySpawned(robot),
+                update(robot, "Depth", 2),
+                SerializedChange::EntityDespawned(robot),
+                SerializedChange::EntitySpawned(robot),
+                update(robot, "Depth", 4),
+            ]
+        );
+
+        // The new owner has to get the component after the transfer
+        let changes = [
+            update(robot, "Depth", 1),
+            SerializedChange::OwnershipTransferred(robot, NewOwner::Receiver),
+            update(robot, "Depth", 2),
+        ];
+        assert_eq!(coalesced(&changes), changes);
+    }
+
+    #[test]
+    fn events_are_never_coalesced() {
+        let event = SerializedChange::EventEmitted(Cow::Borrowed("Beep"), Arc::new(vec![1]));
+        let changes = [event.clone(), event.clone()];
+
+        assert_eq!(coalesced(&changes), changes);
+    }
+}
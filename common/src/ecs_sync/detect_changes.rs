@@ -23,13 +23,14 @@ use bevy::utils::HashSet;
 
 use crate::adapters::dynamic::DynamicAdapter;
 use crate::adapters::{ComponentTypeAdapter, EventTypeAdapter};
+use crate::sync::SyncRole;
 
 use super::{
-    EntityMap, ErasedManualEventReader, EventInfo, NetId, Replicate, SerializationSettings,
-    SerializedChange, SerializedChangeInEvent, SerializedChangeOutEvent,
+    apply_changes::AppliedChanges, EntityMap, ErasedManualEventReader, EventInfo, EventTarget,
+    NetId, Replicate, SerializationSettings, SerializedChange, SerializedChangeOutEvent,
+    SerializedChangeTargetedOutEvent,
 };
 
-// TODO(mid): Events as RPC
 pub struct ChangeDetectionPlugin;
 
 impl Plugin for ChangeDetectionPlugin {
@@ -58,6 +59,10 @@ struct SerializedChangeOutRawEvent(pub SerializedChange);
 
 // Detect new entities
 // query for added sync component
+#[cfg_attr(
+    feature = "sync_instrumentation",
+    tracing::instrument(level = "debug", skip_all)
+)]
 fn detect_new_entities(
     mut cmds: Commands,
     new_entities: Query<(Entity, Option<&NetId>), Added<Replicate>>,
@@ -101,6 +106,10 @@ impl FromWorld for EventReaders {
 // filter for the ones we care about
 // check for ignore components
 // if any non ignored components have changed, sync them
+#[cfg_attr(
+    feature = "sync_instrumentation",
+    tracing::instrument(level = "debug", skip_all)
+)]
 fn detect_changes(
     mut readers: Local<EventReaders>,
 
@@ -110,14 +119,17 @@ fn detect_changes(
             Res<SerializationSettings>,
             Res<EntityMap>,
             Res<AppTypeRegistry>,
+            Res<SyncRole>,
             SystemChangeTick,
         ),
         EventWriter<SerializedChangeOutRawEvent>,
+        EventWriter<SerializedChangeTargetedOutEvent>,
     )>,
 ) {
     let mut changes = Vec::new();
+    let mut targeted = Vec::new();
 
-    let (world, settings, entity_map, registry, ticks) = set.p0();
+    let (world, settings, entity_map, registry, role, ticks) = set.p0();
     for archetype in world
         .archetypes()
         .iter()
@@ -142,6 +154,7 @@ fn detect_changes(
                 .components()
                 .filter_map(|it| Some(it).zip(settings.component_by_id.get(&it)))
                 .filter(|(_, sync_info)| !archetype.contains(sync_info.ignore_component))
+                .filter(|(_, sync_info)| sync_info.direction.may_send(&role))
             {
                 let (ptr, tick) = match archetype
                     .get_storage_type(component_id)
@@ -200,6 +213,17 @@ fn detect_changes(
 
     for (reader, sync_info) in &mut readers.0 {
         while let Some(ptr) = reader.read_event(world) {
+            // SAFETY: The target function was registered for the same event as the reader
+            let target = match sync_info.target_fn {
+                Some(target) => unsafe { target(ptr) },
+                None => EventTarget::Broadcast,
+            };
+            let peer = match target {
+                EventTarget::Broadcast => None,
+                EventTarget::Peer(peer) => Some(peer),
+                EventTarget::Local => continue,
+            };
+
             let serialized = match &sync_info.type_adapter {
                 EventTypeAdapter::Serde(adapter, _) => unsafe { adapter.serialize(ptr) },
                 EventTypeAdapter::Reflect(from_ptr, _) => {
@@ -211,18 +235,25 @@ fn detect_changes(
             }
             .expect("serialize error");
 
-            changes.push(SerializedChangeOutRawEvent(SerializedChange::EventEmitted(
-                sync_info.type_name.into(),
-                serialized,
-            )));
+            let change = SerializedChange::EventEmitted(sync_info.type_name.into(), serialized);
+
+            match peer {
+                // Received events never have a target, so there is nothing to filter
+                Some(peer) => targeted.push(SerializedChangeTargetedOutEvent(change, peer)),
+                None => changes.push(SerializedChangeOutRawEvent(change)),
+            }
         }
     }
 
-    let mut events = set.p1();
-    events.send_batch(changes);
+    set.p1().send_batch(changes);
+    set.p2().send_batch(targeted);
 }
 
 // Detect when components are removed
+#[cfg_attr(
+    feature = "sync_instrumentation",
+    tracing::instrument(level = "debug", skip_all)
+)]
 fn detect_removals(
     mut set: ParamSet<(
         (
@@ -230,14 +261,19 @@ fn detect_removals(
             Res<EntityMap>,
             &RemovedComponentEvents,
             Query<EntityRef, With<Replicate>>,
+            Res<SyncRole>,
         ),
         EventWriter<SerializedChangeOutRawEvent>,
     )>,
 ) {
     let mut changes = Vec::new();
 
-    let (settings, entity_map, removals, entities) = set.p0();
+    let (settings, entity_map, removals, entities, role) = set.p0();
     for (component_id, sync_info) in &settings.component_by_id {
+        if !sync_info.direction.may_send(&role) {
+            continue;
+        }
+
         let Some(removal_events) = removals.get(*component_id) else {
             continue;
         };
@@ -278,6 +314,10 @@ fn detect_removals(
 
 // Detect when entities despawn
 // listen for removal of sync component
+#[cfg_attr(
+    feature = "sync_instrumentation",
+    tracing::instrument(level = "debug", skip_all)
+)]
 fn detect_despawns(
     mut entity_map: ResMut<EntityMap>,
     mut despawns: RemovedComponents<Replicate>,
@@ -296,17 +336,22 @@ fn detect_despawns(
     }
 }
 
+#[cfg_attr(
+    feature = "sync_instrumentation",
+    tracing::instrument(level = "debug", skip_all)
+)]
 fn filter_detections(
     mut raw: EventReader<SerializedChangeOutRawEvent>,
-    mut inbound: EventReader<SerializedChangeInEvent>,
+    applied: Res<AppliedChanges>,
     mut events: EventWriter<SerializedChangeOutEvent>,
 ) {
-    let inbound = inbound.read().map(|it| &it.0).collect::<HashSet<_>>();
+    // Changes applied this frame look like local changes, don't echo them
+    let applied = applied.0.iter().collect::<HashSet<_>>();
 
     events.send_batch(
         raw.read()
             .map(|it| it.0.clone())
-            .filter(|it| !inbound.contains(it))
+            .filter(|it| !applied.contains(it))
             .map(SerializedChangeOutEvent),
     );
 }
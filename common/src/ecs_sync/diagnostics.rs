@@ -0,0 +1,124 @@
+//! Per frame counters for the replication pipeline
+//!
+//! Only built with the `sync_instrumentation` feature, along with the debug spans on the
+//! pipeline systems, so release builds on the robot pay nothing for them
+
+use bevy::{
+    app::{App, Plugin, PostUpdate},
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    ecs::{event::EventReader, schedule::IntoSystemConfigs, system::Res},
+};
+
+use super::{
+    apply_changes::ApplyStats, detect_changes::ChangeDetectionSet, SerializedChangeOutEvent,
+};
+
+/// Outbound changes left after filtering out echoes
+pub const CHANGES_EMITTED: DiagnosticPath = DiagnosticPath::const_new("sync/changes_emitted");
+/// Serialized component and event data in the outbound changes
+pub const BYTES_SERIALIZED: DiagnosticPath = DiagnosticPath::const_new("sync/bytes_serialized");
+/// Entities inbound changes were applied to
+pub const ENTITIES_APPLIED: DiagnosticPath = DiagnosticPath::const_new("sync/entities_applied");
+
+pub const SYNC_DIAGNOSTICS: [DiagnosticPath; 3] =
+    [CHANGES_EMITTED, BYTES_SERIALIZED, ENTITIES_APPLIED];
+
+/// Needs [`ChangeDetectionPlugin`](super::detect_changes::ChangeDetectionPlugin) and
+/// [`ChangeApplicationPlugin`](super::apply_changes::ChangeApplicationPlugin)
+pub struct SyncDiagnosticsPlugin;
+
+impl Plugin for SyncDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_diagnostic(Diagnostic::new(CHANGES_EMITTED).with_suffix(" changes"))
+            .register_diagnostic(Diagnostic::new(BYTES_SERIALIZED).with_suffix(" B"))
+            .register_diagnostic(Diagnostic::new(ENTITIES_APPLIED).with_suffix(" entities"))
+            .add_systems(PostUpdate, measure_sync.after(ChangeDetectionSet));
+    }
+}
+
+fn measure_sync(
+    mut diagnostics: Diagnostics,
+    applied: Res<ApplyStats>,
+    mut changes: EventReader<SerializedChangeOutEvent>,
+) {
+    let (count, bytes) = changes.read().fold(
+        (0, 0),
+        |(count, bytes), SerializedChangeOutEvent(change)| {
+            (count + 1, bytes + change.payload_len())
+        },
+    );
+
+    diagnostics.add_measurement(&CHANGES_EMITTED, || count as f64);
+    diagnostics.add_measurement(&BYTES_SERIALIZED, || bytes as f64);
+    diagnostics.add_measurement(&ENTITIES_APPLIED, || applied.last_frame as f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        app::App,
+        diagnostic::{DiagnosticPath, DiagnosticsStore},
+    };
+
+    use crate::{
+        ecs_sync::{
+            test_utils::{app, deliver, outbound, Test, CLIENT_A, ROBOT},
+            Replicate,
+        },
+        sync::SyncRole,
+    };
+
+    use super::{
+        SyncDiagnosticsPlugin, BYTES_SERIALIZED, CHANGES_EMITTED, ENTITIES_APPLIED,
+        SYNC_DIAGNOSTICS,
+    };
+
+    const FRAMES: u32 = 10;
+
+    fn total(app: &App, path: &DiagnosticPath) -> f64 {
+        app.world
+            .resource::<DiagnosticsStore>()
+            .get(path)
+            .expect("Diagnostic registered")
+            .values()
+            .sum()
+    }
+
+    #[test]
+    fn diagnostics_register() {
+        let mut robot = app(SyncRole::Server { port: Some(0) }, &[CLIENT_A]);
+        robot.add_plugins(SyncDiagnosticsPlugin);
+        robot.update();
+
+        let store = robot.world.resource::<DiagnosticsStore>();
+        for path in &SYNC_DIAGNOSTICS {
+            let diagnostic = store.get(path).expect("Diagnostic registered");
+            assert_eq!(diagnostic.value(), Some(0.0));
+        }
+    }
+
+    #[test]
+    fn diagnostics_follow_changes() {
+        let mut robot = app(SyncRole::Server { port: Some(0) }, &[CLIENT_A]);
+        let mut client = app(SyncRole::Client, &[ROBOT]);
+        robot.add_plugins(SyncDiagnosticsPlugin);
+        client.add_plugins(SyncDiagnosticsPlugin);
+
+        let entity = robot.world.spawn((Replicate, Test(0))).id();
+
+        for frame in 1..=FRAMES {
+            robot.world.get_mut::<Test>(entity).unwrap().0 = frame;
+            robot.update();
+
+            let changes = outbound(&mut robot);
+            deliver(&mut client, &changes, ROBOT);
+        }
+
+        // At least one update per frame, the first frame also carries the spawn
+        assert!(total(&robot, &CHANGES_EMITTED) > FRAMES as f64);
+        assert!(total(&robot, &BYTES_SERIALIZED) > 0.0);
+        assert_eq!(total(&robot, &ENTITIES_APPLIED), 0.0);
+
+        assert_eq!(total(&client, &ENTITIES_APPLIED), FRAMES as f64);
+    }
+}
@@ -0,0 +1,289 @@
+//! Ownership transfer for replicated entities
+//!
+//! Entities spawned by a peer are owned by that peer and get despawned when it disconnects.
+//! Ownership can be moved around to keep an entity alive past its original owner, subject to
+//! the following rule: only the current owner of an entity or the server may initiate a
+//! transfer. Transfers that violate this rule are rejected by both the initiator and the
+//! receiving peers.
+//!
+//! - The server may take ownership of any entity with [`EntityOwnershipExt::transfer_ownership_to_local`]
+//! - A client may hand an entity it owns to the server with [`EntityOwnershipExt::release_ownership`]
+
+use anyhow::bail;
+use bevy::ecs::{
+    change_detection::DetectChangesMut, component::ComponentId, entity::Entity,
+    system::EntityCommands, world::World,
+};
+
+use crate::{
+    error::ErrorEvent,
+    sync::{Peers, SyncRole},
+};
+
+use super::{
+    EntityMap, ForignOwned, NewOwner, SerializationSettings, SerializedChange,
+    SerializedChangeOutEvent,
+};
+
+pub trait EntityOwnershipExt {
+    /// Makes the local peer the owner of this entity so it survives its current owner
+    /// disconnecting
+    ///
+    /// Only allowed on the server, or on the current owner where it is a no-op
+    fn transfer_ownership_to_local(&mut self) -> &mut Self;
+
+    /// Hands ownership of a locally owned entity to the server
+    ///
+    /// Only allowed on clients, since the server has no single peer to hand the entity to
+    fn release_ownership(&mut self) -> &mut Self;
+}
+
+impl EntityOwnershipExt for EntityCommands<'_> {
+    fn transfer_ownership_to_local(&mut self) -> &mut Self {
+        self.add(|entity: Entity, world: &mut World| {
+            if let Err(err) = take_ownership(world, entity) {
+                world.send_event(ErrorEvent(err.context("Transfer ownership to local")));
+            }
+        })
+    }
+
+    fn release_ownership(&mut self) -> &mut Self {
+        self.add(|entity: Entity, world: &mut World| {
+            if let Err(err) = release_ownership(world, entity) {
+                world.send_event(ErrorEvent(err.context("Release ownership")));
+            }
+        })
+    }
+}
+
+fn take_ownership(world: &mut World, entity: Entity) -> anyhow::Result<()> {
+    let role = *world.resource::<SyncRole>();
+    let mut entity_map = world.resource_mut::<EntityMap>();
+
+    let Some(&net_id) = entity_map.local_to_forign.get(&entity) else {
+        bail!("Entity {entity:?} is not replicated");
+    };
+
+    if entity_map.owner(entity).is_none() {
+        // Already owned locally
+        return Ok(());
+    }
+
+    if !matches!(role, SyncRole::Server { .. }) {
+        bail!("Only the owner or the server can transfer ownership of {entity:?}");
+    }
+
+    entity_map.set_owner(entity, None);
+
+    world.entity_mut(entity).remove::<ForignOwned>();
+    mark_replicated_changed(world, entity);
+
+    world.send_event(SerializedChangeOutEvent(
+        SerializedChange::OwnershipTransferred(net_id, NewOwner::Sender),
+    ));
+
+    Ok(())
+}
+
+fn release_ownership(world: &mut World, entity: Entity) -> anyhow::Result<()> {
+    let role = *world.resource::<SyncRole>();
+
+    if !matches!(role, SyncRole::Client) {
+        bail!("The server cannot release ownership of {entity:?}");
+    }
+
+    let server = {
+        let peers = world.resource::<Peers>();
+        let mut tokens = peers.valid_tokens.iter();
+
+        match (tokens.next(), tokens.next()) {
+            (Some(&token), None) => token,
+            (None, _) => bail!("No peer to release ownership to"),
+            (Some(_), Some(_)) => bail!("Client is connected to more than one peer"),
+        }
+    };
+
+    let mut entity_map = world.resource_mut::<EntityMap>();
+
+    let Some(&net_id) = entity_map.local_to_forign.get(&entity) else {
+        bail!("Entity {entity:?} is not replicated");
+    };
+
+    if let Some(owner) = entity_map.owner(entity) {
+        bail!("Only the owner can release ownership of {entity:?}, owned by {owner:?}");
+    }
+
+    entity_map.set_owner(entity, Some(server));
+
+    world.entity_mut(entity).insert(ForignOwned(server.0));
+
+    world.send_event(SerializedChangeOutEvent(
+        SerializedChange::OwnershipTransferred(net_id, NewOwner::Receiver),
+    ));
+
+    Ok(())
+}
+
+/// Flags every replicated component on `entity` as changed so the new owner resends them
+pub(crate) fn mark_replicated_changed(world: &mut World, entity: Entity) {
+    let component_ids: Vec<ComponentId> = world
+        .resource::<SerializationSettings>()
+        .component_by_id
+        .keys()
+        .copied()
+        .collect();
+
+    let Some(mut entity) = world.get_entity_mut(entity) else {
+        return;
+    };
+
+    for component_id in component_ids {
+        if let Some(mut component) = entity.get_mut_by_id(component_id) {
+            component.set_changed();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::{Commands, RunSystemOnce};
+
+    use crate::{
+        ecs_sync::{
+            test_utils::{app, deliver, errors, local, outbound, Test, CLIENT_A, CLIENT_B, ROBOT},
+            EntityMap, NetId, Replicate, SerializedChange,
+        },
+        sync::{Peers, SyncRole},
+    };
+
+    use super::EntityOwnershipExt;
+
+    #[test]
+    fn transferred_entity_survives_owner_disconnect() {
+        let mut robot = app(SyncRole::Server { port: Some(0) }, &[CLIENT_A, CLIENT_B]);
+        let mut client_a = app(SyncRole::Client, &[ROBOT]);
+        let mut client_b = app(SyncRole::Client, &[ROBOT]);
+
+        // Client A spawns a controller-like entity
+        let entity_a = client_a.world.spawn((Test(1), Replicate)).id();
+        client_a.update();
+        let net_id = *client_a.world.get::<NetId>(entity_a).unwrap();
+
+        let changes = outbound(&mut client_a);
+        deliver(&mut robot, &changes, CLIENT_A);
+
+        let entity_robot = local(&robot, net_id).expect("Robot knows about entity");
+        assert_eq!(
+            robot.world.resource::<EntityMap>().owner(entity_robot),
+            Some(CLIENT_A)
+        );
+
+        let changes = outbound(&mut robot);
+        deliver(&mut client_b, &changes, ROBOT);
+        assert_eq!(
+            local(&client_b, net_id),
+            None,
+            "Robot does not relay entities"
+        );
+
+        client_a.world.run_system_once(move |mut cmds: Commands| {
+            cmds.entity(entity_a).transfer_ownership_to_local();
+        });
+        assert_eq!(errors(&mut client_a), 0, "Owner transfer is a no-op");
+
+        // The robot adopts the entity
+        robot.world.run_system_once(move |mut cmds: Commands| {
+            cmds.entity(entity_robot).transfer_ownership_to_local();
+        });
+        robot.update();
+        assert_eq!(errors(&mut robot), 0);
+        assert_eq!(
+            robot.world.resource::<EntityMap>().owner(entity_robot),
+            None
+        );
+
+        let changes = outbound(&mut robot);
+        deliver(&mut client_a, &changes, ROBOT);
+        deliver(&mut client_b, &changes, ROBOT);
+
+        assert_eq!(
+            client_a.world.resource::<EntityMap>().owner(entity_a),
+            Some(ROBOT)
+        );
+
+        let entity_b = local(&client_b, net_id).expect("Client B learned about entity");
+        client_b.update();
+        assert_eq!(client_b.world.get::<Test>(entity_b), Some(&Test(1)));
+        assert_eq!(
+            client_b.world.resource::<EntityMap>().owner(entity_b),
+            Some(ROBOT)
+        );
+
+        // Client B still may not take the entity from the robot
+        client_b.world.run_system_once(move |mut cmds: Commands| {
+            cmds.entity(entity_b).transfer_ownership_to_local();
+        });
+        assert_eq!(errors(&mut client_b), 1);
+        assert_eq!(
+            client_b.world.resource::<EntityMap>().owner(entity_b),
+            Some(ROBOT)
+        );
+
+        // Client A disconnects
+        robot
+            .world
+            .resource_mut::<Peers>()
+            .valid_tokens
+            .remove(&CLIENT_A);
+        let owned = robot
+            .world
+            .resource_mut::<EntityMap>()
+            .remove_peer(CLIENT_A);
+        assert!(owned.is_empty());
+        robot.update();
+
+        assert_eq!(robot.world.get::<Test>(entity_robot), Some(&Test(1)));
+        assert_eq!(local(&robot, net_id), Some(entity_robot));
+    }
+
+    #[test]
+    fn receivers_reject_claims_from_non_owners() {
+        let mut robot = app(SyncRole::Server { port: Some(0) }, &[CLIENT_A, CLIENT_B]);
+        let mut client_a = app(SyncRole::Client, &[ROBOT]);
+
+        let entity_a = client_a.world.spawn((Test(1), Replicate)).id();
+        client_a.update();
+        let net_id = *client_a.world.get::<NetId>(entity_a).unwrap();
+
+        let changes = outbound(&mut client_a);
+        deliver(&mut robot, &changes, CLIENT_A);
+        let entity_robot = local(&robot, net_id).unwrap();
+
+        // A forged claim from client B is ignored
+        deliver(
+            &mut robot,
+            &[SerializedChange::OwnershipTransferred(
+                net_id,
+                super::NewOwner::Sender,
+            )],
+            CLIENT_B,
+        );
+        assert_eq!(
+            robot.world.resource::<EntityMap>().owner(entity_robot),
+            Some(CLIENT_A)
+        );
+
+        // Client A hands the entity to the robot
+        client_a.world.run_system_once(move |mut cmds: Commands| {
+            cmds.entity(entity_a).release_ownership();
+        });
+        assert_eq!(errors(&mut client_a), 0);
+
+        let changes = outbound(&mut client_a);
+        deliver(&mut robot, &changes, CLIENT_A);
+        assert_eq!(
+            robot.world.resource::<EntityMap>().owner(entity_robot),
+            None
+        );
+    }
+}
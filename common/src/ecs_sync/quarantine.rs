@@ -0,0 +1,435 @@
+//! Quarantine for replicated types that repeatedly fail to apply
+//!
+//! A layout mismatch in a single type (ex. a field added on one side) would otherwise fail on
+//! every update forever. After [`QUARANTINE_THRESHOLD`] consecutive failures the type stops
+//! being applied and the peer is asked to stop sending it until the quarantine is cleared.
+
+use ahash::{HashMap, HashSet};
+use bevy::ecs::{
+    event::{Event, EventReader, EventWriter},
+    system::{ResMut, Resource},
+    world::World,
+};
+use networking::Token;
+use tracing::warn;
+
+use crate::error::ErrorEvent;
+
+use super::{NetTypeId, SerializedChange, SerializedChangeOutEvent};
+
+/// Consecutive failures before a type gets quarantined
+pub const QUARANTINE_THRESHOLD: u32 = 5;
+
+#[derive(Resource, Default, Debug)]
+pub struct QuarantinedTypes {
+    failures: HashMap<NetTypeId, u32>,
+    quarantined: HashMap<NetTypeId, QuarantineInfo>,
+
+    /// Types peers have asked us to stop sending them
+    suppressed: HashMap<NetTypeId, HashSet<Token>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct QuarantineInfo {
+    pub failures: u32,
+    pub last_error: String,
+}
+
+/// Lifts the quarantine on a type and asks peers to resume sending it
+#[derive(Event, Debug, Clone)]
+pub struct ClearQuarantine(pub NetTypeId);
+
+impl QuarantinedTypes {
+    pub fn is_quarantined(&self, token: &NetTypeId) -> bool {
+        self.quarantined.contains_key(token)
+    }
+
+    pub fn quarantined(&self) -> impl Iterator<Item = (&NetTypeId, &QuarantineInfo)> {
+        self.quarantined.iter()
+    }
+
+    pub fn record_success(&mut self, token: &NetTypeId) {
+        self.failures.remove(token);
+    }
+
+    /// Returns true if this failure caused the type to be quarantined
+    pub fn record_failure(&mut self, token: &NetTypeId, error: &anyhow::Error) -> bool {
+        if let Some(info) = self.quarantined.get_mut(token) {
+            info.failures += 1;
+            info.last_error = format!("{error:?}");
+
+            return false;
+        }
+
+        let failures = self.failures.entry(token.clone()).or_default();
+        *failures += 1;
+
+        if *failures < QUARANTINE_THRESHOLD {
+            return false;
+        }
+
+        let failures = *failures;
+        self.failures.remove(token);
+        self.quarantined.insert(
+            token.clone(),
+            QuarantineInfo {
+                failures,
+                last_error: format!("{error:?}"),
+            },
+        );
+
+        true
+    }
+
+    pub fn clear(&mut self, token: &NetTypeId) -> bool {
+        self.failures.remove(token);
+        self.quarantined.remove(token).is_some()
+    }
+
+    pub(crate) fn set_suppressed(&mut self, token: NetTypeId, peer: Token, suppressed: bool) {
+        if suppressed {
+            self.suppressed.entry(token).or_default().insert(peer);
+        } else if let Some(peers) = self.suppressed.get_mut(&token) {
+            peers.remove(&peer);
+
+            if peers.is_empty() {
+                self.suppressed.remove(&token);
+            }
+        }
+    }
+
+    pub(crate) fn remove_peer(&mut self, peer: Token) {
+        self.suppressed.retain(|_, peers| {
+            peers.remove(&peer);
+            !peers.is_empty()
+        });
+    }
+
+    /// Whether any peer asked not to receive this change
+    pub fn is_suppressed(&self, change: &SerializedChange) -> bool {
+        change_type(change).is_some_and(|token| self.suppressed.contains_key(token))
+    }
+
+    /// Whether `peer` should be sent this change
+    pub fn should_send(&self, change: &SerializedChange, peer: Token) -> bool {
+        let Some(token) = change_type(change) else {
+            return true;
+        };
+
+        self.suppressed
+            .get(token)
+            .map(|peers| !peers.contains(&peer))
+            .unwrap_or(true)
+    }
+}
+
+fn change_type(change: &SerializedChange) -> Option<&NetTypeId> {
+    match change {
+        SerializedChange::ComponentUpdated(_, token, _)
+        | SerializedChange::EventEmitted(token, _) => Some(token),
+        _ => None,
+    }
+}
+
+/// Feeds the result of applying an update for `token` into the quarantine
+pub(crate) fn report_apply(world: &mut World, token: &NetTypeId, rst: anyhow::Result<()>) {
+    match rst {
+        Ok(()) => {
+            world
+                .resource_mut::<QuarantinedTypes>()
+                .record_success(token);
+        }
+        Err(err) => {
+            let mut quarantine = world.resource_mut::<QuarantinedTypes>();
+            let already_quarantined = quarantine.is_quarantined(token);
+            let newly_quarantined = quarantine.record_failure(token, &err);
+
+            if newly_quarantined {
+                warn!(
+                    "Quarantined replicated type {token} after {QUARANTINE_THRESHOLD} failed updates, last error: {err:?}"
+                );
+
+                world.send_event(SerializedChangeOutEvent(SerializedChange::TypeQuarantined(
+                    token.clone(),
+                    true,
+                )));
+            } else if !already_quarantined {
+                world.send_event(ErrorEvent(err.context(format!("Apply update for {token}"))));
+            }
+        }
+    }
+}
+
+pub(crate) fn clear_quarantine(
+    mut quarantine: ResMut<QuarantinedTypes>,
+    mut events: EventReader<ClearQuarantine>,
+    mut changes: EventWriter<SerializedChangeOutEvent>,
+) {
+    for ClearQuarantine(token) in events.read() {
+        if quarantine.clear(token) {
+            changes.send(SerializedChangeOutEvent(SerializedChange::TypeQuarantined(
+                token.clone(),
+                false,
+            )));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{slice, sync::Arc};
+
+    use bevy::{
+        app::App,
+        ecs::{component::Component, entity::Entity},
+        reflect::Reflect,
+    };
+    use bincode::Options;
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        ecs_sync::{
+            test_utils::{
+                app, deliver, errors, local, outbound, Other, Test, CLIENT_A, CLIENT_B, ROBOT,
+            },
+            AppReplicateExt, NetId, NetTypeId, Replicate, SerializedChange,
+        },
+        sync::SyncRole,
+        types::bounded::BoundedVec,
+    };
+
+    use super::{ClearQuarantine, QuarantinedTypes, QUARANTINE_THRESHOLD};
+
+    fn malformed(net_id: NetId, token: &NetTypeId) -> SerializedChange {
+        SerializedChange::ComponentUpdated(net_id, token.clone(), Some(Arc::new(vec![0xff])))
+    }
+
+    fn component_updates(changes: &[SerializedChange]) -> Vec<SerializedChange> {
+        changes
+            .iter()
+            .filter(|it| matches!(it, SerializedChange::ComponentUpdated(..)))
+            .cloned()
+            .collect()
+    }
+
+    fn type_token(changes: &[SerializedChange], name: &str) -> NetTypeId {
+        changes
+            .iter()
+            .find_map(|it| match it {
+                SerializedChange::ComponentUpdated(_, token, _) if token.ends_with(name) => {
+                    Some(token.clone())
+                }
+                _ => None,
+            })
+            .expect("Type was replicated")
+    }
+
+    /// Spawns an entity on the robot and mirrors it to a client
+    fn setup() -> (App, App, Entity, NetId, Vec<SerializedChange>) {
+        let mut robot = app(SyncRole::Server { port: Some(0) }, &[CLIENT_A, CLIENT_B]);
+        let mut client = app(SyncRole::Client, &[ROBOT]);
+
+        let entity = robot.world.spawn((Test(1), Other(1), Replicate)).id();
+        robot.update();
+        let net_id = *robot.world.get::<NetId>(entity).unwrap();
+
+        let changes = outbound(&mut robot);
+        deliver(&mut client, &changes, ROBOT);
+
+        (robot, client, entity, net_id, changes)
+    }
+
+    #[test]
+    fn quarantine_engages_after_repeated_failures() {
+        let (mut robot, mut client, robot_entity, net_id, changes) = setup();
+        let test_token = type_token(&changes, "::Test");
+        let entity = local(&client, net_id).unwrap();
+        outbound(&mut client);
+
+        let mut reported = 0;
+        let mut notice = Vec::new();
+        for _ in 0..QUARANTINE_THRESHOLD {
+            deliver(&mut client, &[malformed(net_id, &test_token)], ROBOT);
+            reported += errors(&mut client);
+            notice.extend(outbound(&mut client));
+        }
+
+        // Every failure but the one that triggered the quarantine is reported individually
+        assert_eq!(reported, QUARANTINE_THRESHOLD as usize - 1);
+        assert!(client
+            .world
+            .resource::<QuarantinedTypes>()
+            .is_quarantined(&test_token));
+
+        // Further failures are silent
+        deliver(&mut client, &[malformed(net_id, &test_token)], ROBOT);
+        assert_eq!(errors(&mut client), 0);
+
+        // Other types keep applying while valid updates to the quarantined type are dropped
+        robot
+            .world
+            .entity_mut(robot_entity)
+            .insert((Test(2), Other(2)));
+        robot.update();
+        let changes = component_updates(&outbound(&mut robot));
+        deliver(&mut client, &changes, ROBOT);
+
+        assert_eq!(client.world.get::<Other>(entity), Some(&Other(2)));
+        assert_eq!(client.world.get::<Test>(entity), Some(&Test(1)));
+
+        assert_eq!(
+            notice,
+            vec![SerializedChange::TypeQuarantined(test_token.clone(), true)]
+        );
+
+        // The robot stops sending the type to this client only
+        deliver(&mut robot, &notice, CLIENT_A);
+        let test_update = changes
+            .iter()
+            .find(|it| matches!(it, SerializedChange::ComponentUpdated(_, token, _) if *token == test_token))
+            .unwrap();
+        let quarantine = robot.world.resource::<QuarantinedTypes>();
+        assert!(quarantine.is_suppressed(test_update));
+        assert!(!quarantine.should_send(test_update, CLIENT_A));
+        assert!(quarantine.should_send(test_update, CLIENT_B));
+
+        // Clearing lifts the suppression
+        client.world.send_event(ClearQuarantine(test_token.clone()));
+        client.update();
+        assert!(!client
+            .world
+            .resource::<QuarantinedTypes>()
+            .is_quarantined(&test_token));
+
+        let notice = outbound(&mut client);
+        assert_eq!(
+            notice,
+            vec![SerializedChange::TypeQuarantined(test_token.clone(), false)]
+        );
+
+        deliver(&mut robot, &notice, CLIENT_A);
+        let quarantine = robot.world.resource::<QuarantinedTypes>();
+        assert!(quarantine.should_send(test_update, CLIENT_A));
+        assert!(!quarantine.is_suppressed(test_update));
+    }
+
+    #[test]
+    fn successful_apply_resets_failures() {
+        let (mut robot, mut client, robot_entity, net_id, changes) = setup();
+        let test_token = type_token(&changes, "::Test");
+
+        for _ in 0..QUARANTINE_THRESHOLD - 1 {
+            deliver(&mut client, &[malformed(net_id, &test_token)], ROBOT);
+        }
+
+        robot.world.entity_mut(robot_entity).insert(Test(2));
+        robot.update();
+        let changes = component_updates(&outbound(&mut robot));
+        deliver(&mut client, &changes, ROBOT);
+
+        for _ in 0..QUARANTINE_THRESHOLD - 1 {
+            deliver(&mut client, &[malformed(net_id, &test_token)], ROBOT);
+        }
+
+        assert!(!client
+            .world
+            .resource::<QuarantinedTypes>()
+            .is_quarantined(&test_token));
+    }
+
+    #[derive(Component, Reflect, Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct Readings(BoundedVec<u32, 4>);
+
+    #[test]
+    fn oversized_payload_only_quarantines_its_type() {
+        let mut robot = app(SyncRole::Server { port: Some(0) }, &[CLIENT_A]);
+        let mut client = app(SyncRole::Client, &[ROBOT]);
+        robot.replicate::<Readings>();
+        client.replicate::<Readings>();
+
+        let robot_entity = robot
+            .world
+            .spawn((Test(1), Readings([1, 2].into()), Replicate))
+            .id();
+        robot.update();
+        let net_id = *robot.world.get::<NetId>(robot_entity).unwrap();
+        let changes = outbound(&mut robot);
+        deliver(&mut client, &changes, ROBOT);
+
+        let readings_token = type_token(&changes, "::Readings");
+        let entity = local(&client, net_id).unwrap();
+
+        // Encoded exactly like a plain Vec, just far longer than the bound
+        let oversized = bincode::DefaultOptions::new()
+            .serialize(&vec![0u32; 10_000])
+            .unwrap();
+        let oversized = SerializedChange::ComponentUpdated(
+            net_id,
+            readings_token.clone(),
+            Some(Arc::new(oversized)),
+        );
+
+        for _ in 0..QUARANTINE_THRESHOLD {
+            deliver(&mut client, slice::from_ref(&oversized), ROBOT);
+        }
+
+        let quarantine = client.world.resource::<QuarantinedTypes>();
+        assert!(quarantine.is_quarantined(&readings_token));
+        assert_eq!(quarantine.quarantined().count(), 1);
+        assert_eq!(
+            client.world.get::<Readings>(entity),
+            Some(&Readings([1, 2].into()))
+        );
+
+        // Everything else keeps replicating
+        robot.world.entity_mut(robot_entity).insert(Test(2));
+        robot.update();
+        let changes = component_updates(&outbound(&mut robot));
+        deliver(&mut client, &changes, ROBOT);
+
+        assert_eq!(client.world.get::<Test>(entity), Some(&Test(2)));
+    }
+
+    #[test]
+    fn over_bound_local_component_replicates_without_panicking() {
+        let mut robot = app(SyncRole::Server { port: Some(0) }, &[CLIENT_A]);
+        let mut client = app(SyncRole::Client, &[ROBOT]);
+        robot.replicate::<Readings>();
+        client.replicate::<Readings>();
+
+        // Built right at the bound
+        let robot_entity = robot
+            .world
+            .spawn((Readings([1, 2, 3, 4].into()), Replicate))
+            .id();
+        robot.update();
+        let net_id = *robot.world.get::<NetId>(robot_entity).unwrap();
+        deliver(&mut client, &outbound(&mut robot), ROBOT);
+
+        let entity = local(&client, net_id).unwrap();
+        assert_eq!(
+            client.world.get::<Readings>(entity),
+            Some(&Readings([1, 2, 3, 4].into()))
+        );
+
+        // Grown past the bound in place, the sender still encodes it and the receiver rejects it
+        robot
+            .world
+            .get_mut::<Readings>(robot_entity)
+            .unwrap()
+            .0
+            .extend([5, 6]);
+        robot.update();
+        deliver(
+            &mut client,
+            &component_updates(&outbound(&mut robot)),
+            ROBOT,
+        );
+
+        assert_eq!(errors(&mut client), 1);
+        assert_eq!(
+            client.world.get::<Readings>(entity),
+            Some(&Readings([1, 2, 3, 4].into()))
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! Checks that replicated types come back unchanged from their adapter
+//!
+//! Every replicated type can carry a sample value, the registration macros take it from the
+//! type's [`Default`] and [`AppReplicateExt::replicate_sample`] sets it for everything else. In
+//! debug builds the samples are serialized and deserialized at startup and anything that comes
+//! back different is logged, types without a sample are warned about.
+
+use std::{any::TypeId, fmt::Debug, marker::PhantomData, sync::Arc};
+
+use bevy::{
+    app::App,
+    ecs::{reflect::AppTypeRegistry, system::Res},
+    ptr::Ptr,
+    reflect::{TypeRegistry, Typed},
+};
+use tracing::{error, warn};
+
+use crate::adapters::{
+    dynamic::DynamicAdapter, serde::ReflectSerdeAdapter, ComponentTypeAdapter, EventTypeAdapter,
+};
+
+use super::{AppReplicateExt, ComponentInfo, EventInfo, SerializationSettings};
+
+/// Round trips a sample value through the serde adapter if given one, otherwise through the
+/// reflect adapter
+pub type SampleFn =
+    Arc<dyn Fn(Option<&ReflectSerdeAdapter>, &TypeRegistry) -> Result<(), String> + Send + Sync>;
+
+/// Outcome of [`SerializationSettings::check_round_trips`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RoundTripReport {
+    /// Types whose sample didn't come back unchanged, and what came back instead
+    pub mismatches: Vec<(&'static str, String)>,
+    /// Types that weren't checked because they have no sample
+    pub unsampled: Vec<&'static str>,
+}
+
+impl SerializationSettings {
+    /// Round trips the sample of every replicated type
+    pub fn check_round_trips(&self, registry: &TypeRegistry) -> RoundTripReport {
+        let components = self.component_by_token.values().map(|info| {
+            let serde = match &info.type_adapter {
+                ComponentTypeAdapter::Serde(adapter) => Some(adapter),
+                ComponentTypeAdapter::Reflect(..) => None,
+            };
+
+            (info.type_name, info.sample.as_ref(), serde)
+        });
+        let events = self.event_by_token.values().map(|info| {
+            let serde = match &info.type_adapter {
+                EventTypeAdapter::Serde(adapter, _) => Some(adapter),
+                EventTypeAdapter::Reflect(..) => None,
+            };
+
+            (info.type_name, info.sample.as_ref(), serde)
+        });
+
+        let mut report = RoundTripReport::default();
+        for (type_name, sample, serde) in components.chain(events) {
+            let Some(sample) = sample else {
+                report.unsampled.push(type_name);
+                continue;
+            };
+
+            if let Err(err) = sample(serde, registry) {
+                report.mismatches.push((type_name, err));
+            }
+        }
+
+        report.mismatches.sort();
+        report.unsampled.sort();
+
+        report
+    }
+
+    pub(super) fn set_sample(&mut self, type_name: &'static str, sample: SampleFn) {
+        if let Some(info) = self.component_by_token.get(type_name) {
+            let info = Arc::new(ComponentInfo {
+                sample: Some(sample),
+                ..ComponentInfo::clone(info)
+            });
+
+            self.component_by_id.insert(info.component_id, info.clone());
+            self.component_by_token.insert(type_name.into(), info);
+        } else if let Some(info) = self.event_by_token.get(type_name) {
+            let info = Arc::new(EventInfo {
+                sample: Some(sample),
+                ..EventInfo::clone(info)
+            });
+
+            self.event_by_id.insert(info.component_id, info.clone());
+            self.event_by_token.insert(type_name.into(), info);
+        } else {
+            panic!("{type_name} must be replicated before it can be given a sample");
+        }
+    }
+}
+
+/// Wraps a sample factory for a type registered with the adapters in [`SerializationSettings`]
+pub(super) fn sample_fn<T>(factory: impl Fn() -> T + Send + Sync + 'static) -> SampleFn
+where
+    T: Typed + PartialEq + Debug,
+{
+    Arc::new(move |serde, registry| round_trip(&factory(), serde, registry))
+}
+
+fn round_trip<T>(
+    sample: &T,
+    serde: Option<&ReflectSerdeAdapter>,
+    registry: &TypeRegistry,
+) -> Result<(), String>
+where
+    T: Typed + PartialEq + Debug,
+{
+    match serde {
+        Some(adapter) => {
+            // SAFETY: The adapter was registered for `T`
+            let data = unsafe { adapter.serialize(Ptr::from(sample)) }
+                .map_err(|err| format!("Could not serialize {sample:?}: {err}"))?;
+
+            let mut received = None;
+            adapter
+                .deserialize(&data, |ptr| {
+                    // SAFETY: The adapter was registered for `T`
+                    received = Some(unsafe { ptr.read::<T>() });
+                })
+                .map_err(|err| format!("Could not deserialize {sample:?}: {err}"))?;
+
+            match received {
+                Some(received) if received == *sample => Ok(()),
+                Some(received) => Err(format!("Sent {sample:?}, received {received:?}")),
+                None => Err(format!("Sent {sample:?}, received nothing")),
+            }
+        }
+        None => {
+            let registration = registry
+                .get(TypeId::of::<T>())
+                .ok_or("Not in the type registry")?;
+
+            let data = DynamicAdapter::serialize(sample, registry)
+                .map_err(|err| format!("Could not serialize {sample:?}: {err}"))?;
+            let received = DynamicAdapter::deserialize(&data, registration, registry)
+                .map_err(|err| format!("Could not deserialize {sample:?}: {err}"))?;
+
+            match sample.reflect_partial_eq(&*received) {
+                Some(true) => Ok(()),
+                _ => Err(format!("Sent {sample:?}, received {received:?}")),
+            }
+        }
+    }
+}
+
+pub fn validate_round_trips(settings: Res<SerializationSettings>, registry: Res<AppTypeRegistry>) {
+    let report = settings.check_round_trips(&registry.read());
+
+    for (type_name, err) in &report.mismatches {
+        error!("{type_name} does not survive replication: {err}");
+    }
+
+    for type_name in &report.unsampled {
+        warn!("{type_name} has no sample, give it one with `replicate_sample` so it is checked");
+    }
+}
+
+/// Gives `$ty` its [`Default`] as a sample when it has one, see
+/// [`AppReplicateExt::replicate_sample`]
+///
+/// Picks the impl by autoref, so `$ty` must be a concrete type
+macro_rules! default_sample {
+    ($app:expr, $ty:ty) => {{
+        #[allow(unused_imports)]
+        use $crate::ecs_sync::round_trip::{DefaultSample as _, NoSample as _};
+
+        (&&$crate::ecs_sync::round_trip::SampleProbe::<$ty>(::std::marker::PhantomData))
+            .register_sample($app);
+    }};
+}
+pub(crate) use default_sample;
+
+#[doc(hidden)]
+pub struct SampleProbe<T>(pub PhantomData<fn() -> T>);
+
+#[doc(hidden)]
+pub trait DefaultSample {
+    fn register_sample(&self, app: &mut App);
+}
+
+impl<T> DefaultSample for &SampleProbe<T>
+where
+    T: Typed + Default + PartialEq + Debug,
+{
+    fn register_sample(&self, app: &mut App) {
+        app.replicate_sample(T::default);
+    }
+}
+
+#[doc(hidden)]
+pub trait NoSample {
+    fn register_sample(&self, app: &mut App);
+}
+
+impl<T> NoSample for SampleProbe<T> {
+    fn register_sample(&self, _app: &mut App) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        app::App,
+        ecs::{component::Component, event::Event, reflect::AppTypeRegistry},
+        reflect::Reflect,
+    };
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        adapters::serde::ReflectSerdeAdapter,
+        ecs_sync::{AppReplicateExt, SerializationSettings},
+    };
+
+    use super::RoundTripReport;
+
+    #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+    #[reflect(SerdeAdapter)]
+    struct Healthy(f32);
+
+    /// Loses its label on the way over
+    #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+    #[reflect(SerdeAdapter)]
+    struct Broken {
+        value: f32,
+        #[serde(skip)]
+        label: String,
+    }
+
+    #[derive(Component, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
+    #[reflect(SerdeAdapter)]
+    struct NoDefault(u8);
+
+    #[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+    #[reflect(SerdeAdapter)]
+    struct Ping(u32);
+
+    #[derive(Component, Reflect, Debug, Clone, PartialEq, Default)]
+    struct Reflected(String);
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.init_resource::<SerializationSettings>();
+
+        app
+    }
+
+    fn check(app: &App) -> RoundTripReport {
+        let registry = app.world.resource::<AppTypeRegistry>().read();
+
+        app.world
+            .resource::<SerializationSettings>()
+            .check_round_trips(&registry)
+    }
+
+    #[test]
+    fn broken_components_are_reported() {
+        let mut app = app();
+        app.replicate::<Healthy>()
+            .replicate::<Broken>()
+            .replicate_event::<Ping>()
+            .replicate_reflect::<Reflected>();
+
+        default_sample!(&mut app, Healthy);
+        default_sample!(&mut app, Ping);
+        default_sample!(&mut app, Reflected);
+        app.replicate_sample(|| Broken {
+            value: 2.0,
+            label: "Lost".to_owned(),
+        });
+
+        let report = check(&app);
+        assert_eq!(report.unsampled, Vec::<&str>::new());
+        assert_eq!(report.mismatches.len(), 1, "{report:?}");
+
+        let (type_name, err) = &report.mismatches[0];
+        assert!(type_name.ends_with("Broken"));
+        assert!(err.contains("Lost"), "{err}");
+    }
+
+    #[test]
+    fn types_without_default_need_a_sample() {
+        let mut app = app();
+        app.replicate::<Healthy>().replicate::<NoDefault>();
+
+        default_sample!(&mut app, Healthy);
+        default_sample!(&mut app, NoDefault);
+
+        let report = check(&app);
+        assert!(report.mismatches.is_empty(), "{report:?}");
+        assert_eq!(report.unsampled.len(), 1);
+        assert!(report.unsampled[0].ends_with("NoDefault"));
+
+        app.replicate_sample(|| NoDefault(7));
+        assert_eq!(check(&app), RoundTripReport::default());
+    }
+}
@@ -0,0 +1,468 @@
+//! Typed request/response calls built on replicated events
+//!
+//! Requests carry a [`RequestId`] so responses can be matched to them, and the server answers
+//! only the peer a request came from. Register a pair with
+//! [`AppReplicateExt::replicate_rpc`](super::AppReplicateExt::replicate_rpc), send with
+//! [`RpcSender`] and answer with [`RpcRequests`]. Each request ends in exactly one
+//! [`RpcResult`] on the requesting side, either the response or [`RpcError::TimedOut`].
+
+use std::{marker::PhantomData, time::Duration};
+
+use ahash::HashMap;
+use bevy::{
+    app::{App, PreUpdate},
+    ecs::{
+        event::{Event, EventReader, EventWriter},
+        schedule::IntoSystemConfigs,
+        system::{Res, ResMut, Resource, SystemParam},
+    },
+    reflect::Reflect,
+    time::{Real, Time},
+};
+use networking::Token;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::debug;
+
+use super::{apply_changes::ChangeApplicationSet, EventTarget, PeerEvent};
+
+/// How long a requester waits for a response unless told otherwise
+pub const DEFAULT_RPC_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Serialize, Deserialize, Reflect, Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct RequestId(u64);
+
+impl RequestId {
+    pub fn random() -> Self {
+        Self(rand::random())
+    }
+}
+
+/// Wire envelope for a request
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+pub struct RpcRequest<Req> {
+    pub id: RequestId,
+    pub request: Req,
+
+    /// Peer the request arrived from, only set on the receiving side
+    #[serde(skip)]
+    #[reflect(ignore)]
+    origin: Option<Token>,
+}
+
+impl<Req: Send + Sync + 'static> PeerEvent for RpcRequest<Req> {
+    fn target(&self) -> EventTarget {
+        EventTarget::Broadcast
+    }
+
+    fn set_origin(&mut self, origin: Token) {
+        self.origin = Some(origin);
+    }
+}
+
+/// Wire envelope for a response
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+pub struct RpcResponse<Resp> {
+    pub id: RequestId,
+    pub response: Resp,
+
+    /// Peer that sent the request, only set on the responding side
+    #[serde(skip)]
+    #[reflect(ignore)]
+    target: Option<Token>,
+}
+
+impl<Resp: Send + Sync + 'static> PeerEvent for RpcResponse<Resp> {
+    /// Answers to local requests never leave this app
+    fn target(&self) -> EventTarget {
+        match self.target {
+            Some(peer) => EventTarget::Peer(peer),
+            None => EventTarget::Local,
+        }
+    }
+
+    fn set_origin(&mut self, _origin: Token) {}
+}
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcError {
+    #[error("No response within {0:.1?}")]
+    TimedOut(Duration),
+}
+
+/// Outcome of a request sent with [`RpcSender`]
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct RpcResult<Resp> {
+    pub id: RequestId,
+    pub result: Result<Resp, RpcError>,
+}
+
+/// Identifies the [`RpcResult`] for a request
+#[derive(Debug)]
+pub struct RpcHandle<Resp> {
+    id: RequestId,
+    _marker: PhantomData<fn() -> Resp>,
+}
+
+impl<Resp> RpcHandle<Resp> {
+    pub fn id(&self) -> RequestId {
+        self.id
+    }
+
+    pub fn is_for(&self, result: &RpcResult<Resp>) -> bool {
+        self.id == result.id
+    }
+}
+
+/// Requests sent with [`RpcSender`] that are waiting on a response
+#[derive(Resource)]
+pub struct PendingRequests<Req, Resp> {
+    /// When each request gives up, along with the timeout it was sent with
+    deadlines: HashMap<RequestId, (Duration, Duration)>,
+    _marker: PhantomData<fn() -> (Req, Resp)>,
+}
+
+impl<Req, Resp> PendingRequests<Req, Resp> {
+    pub fn len(&self) -> usize {
+        self.deadlines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deadlines.is_empty()
+    }
+}
+
+impl<Req, Resp> Default for PendingRequests<Req, Resp> {
+    fn default() -> Self {
+        Self {
+            deadlines: Default::default(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Sends `Req` requests to the other side of the connection
+#[derive(SystemParam)]
+pub struct RpcSender<'w, Req: Send + Sync + 'static, Resp: Send + Sync + 'static> {
+    pending: ResMut<'w, PendingRequests<Req, Resp>>,
+    requests: EventWriter<'w, RpcRequest<Req>>,
+    time: Res<'w, Time<Real>>,
+}
+
+impl<Req: Send + Sync + 'static, Resp: Send + Sync + 'static> RpcSender<'_, Req, Resp> {
+    pub fn send(&mut self, request: Req) -> RpcHandle<Resp> {
+        self.send_with_timeout(request, DEFAULT_RPC_TIMEOUT)
+    }
+
+    pub fn send_with_timeout(&mut self, request: Req, timeout: Duration) -> RpcHandle<Resp> {
+        let id = RequestId::random();
+        let deadline = self.time.elapsed() + timeout;
+
+        self.pending.deadlines.insert(id, (deadline, timeout));
+        self.requests.send(RpcRequest {
+            id,
+            request,
+            origin: None,
+        });
+
+        RpcHandle {
+            id,
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// Answers a single request, see [`RpcRequests::respond`]
+///
+/// Dropping it leaves the requester to time out
+#[must_use = "The requester times out unless the request is answered"]
+#[derive(Debug)]
+pub struct Responder<Resp> {
+    id: RequestId,
+    origin: Option<Token>,
+    _marker: PhantomData<fn(Resp)>,
+}
+
+impl<Resp> Responder<Resp> {
+    /// Peer that sent the request, `None` if it was sent locally
+    pub fn origin(&self) -> Option<Token> {
+        self.origin
+    }
+}
+
+/// Receives `Req` requests and answers them with a `Resp`
+#[derive(SystemParam)]
+pub struct RpcRequests<'w, 's, Req: Send + Sync + 'static, Resp: Send + Sync + 'static> {
+    requests: EventReader<'w, 's, RpcRequest<Req>>,
+    responses: EventWriter<'w, RpcResponse<Resp>>,
+}
+
+impl<Req, Resp> RpcRequests<'_, '_, Req, Resp>
+where
+    Req: Clone + Send + Sync + 'static,
+    Resp: Send + Sync + 'static,
+{
+    /// Requests received since the last read, each with the responder that answers it
+    pub fn read(&mut self) -> Vec<(Req, Responder<Resp>)> {
+        self.requests
+            .read()
+            .map(|request| {
+                let responder = Responder {
+                    id: request.id,
+                    origin: request.origin,
+                    _marker: PhantomData,
+                };
+
+                (request.request.clone(), responder)
+            })
+            .collect()
+    }
+
+    pub fn respond(&mut self, responder: Responder<Resp>, response: Resp) {
+        self.responses.send(RpcResponse {
+            id: responder.id,
+            response,
+            target: responder.origin,
+        });
+    }
+}
+
+pub(crate) fn add_rpc_systems<Req, Resp>(app: &mut App)
+where
+    Req: Send + Sync + 'static,
+    Resp: Clone + Send + Sync + 'static,
+{
+    app.init_resource::<PendingRequests<Req, Resp>>()
+        .add_event::<RpcResult<Resp>>()
+        .add_systems(
+            PreUpdate,
+            resolve_requests::<Req, Resp>.after(ChangeApplicationSet),
+        );
+}
+
+/// Turns responses and expired deadlines into [`RpcResult`]s
+fn resolve_requests<Req, Resp>(
+    mut pending: ResMut<PendingRequests<Req, Resp>>,
+    mut responses: EventReader<RpcResponse<Resp>>,
+    mut results: EventWriter<RpcResult<Resp>>,
+    time: Res<Time<Real>>,
+) where
+    Req: Send + Sync + 'static,
+    Resp: Clone + Send + Sync + 'static,
+{
+    for response in responses.read() {
+        if pending.deadlines.remove(&response.id).is_none() {
+            debug!(id = ?response.id, "Got response to an unknown or expired request");
+            continue;
+        }
+
+        results.send(RpcResult {
+            id: response.id,
+            result: Ok(response.response.clone()),
+        });
+    }
+
+    let now = time.elapsed();
+    pending.deadlines.retain(|&id, &mut (deadline, timeout)| {
+        if now < deadline {
+            return true;
+        }
+
+        results.send(RpcResult {
+            id,
+            result: Err(RpcError::TimedOut(timeout)),
+        });
+
+        false
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{
+        app::{App, Update},
+        ecs::{
+            event::Events,
+            system::{In, RunSystemOnce},
+        },
+        time::{Real, Time},
+    };
+    use networking::Token;
+
+    use crate::{
+        ecs_sync::{
+            test_utils::{
+                app, deliver, outbound, targeted, Other, Test, CLIENT_A, CLIENT_B, ROBOT,
+            },
+            AppReplicateExt, SerializedChange, SerializedChangeInEvent,
+        },
+        sync::SyncRole,
+    };
+
+    use super::{
+        PendingRequests, RpcError, RpcHandle, RpcRequests, RpcResponse, RpcResult, RpcSender,
+    };
+
+    fn rpc_app(role: SyncRole, peers: &[Token]) -> App {
+        let mut app = app(role, peers);
+        app.init_resource::<Time<Real>>()
+            .replicate_rpc::<Test, Other>();
+
+        // The first update only starts the clock
+        advance(&mut app, Duration::ZERO);
+
+        app
+    }
+
+    fn advance(app: &mut App, duration: Duration) {
+        app.world
+            .resource_mut::<Time<Real>>()
+            .update_with_duration(duration);
+    }
+
+    /// Answers every `Test(n)` with `Other(2 * n)`
+    fn double(mut rpc: RpcRequests<Test, Other>) {
+        for (request, responder) in rpc.read() {
+            rpc.respond(responder, Other(request.0 * 2));
+        }
+    }
+
+    fn send(app: &mut App, request: Test, timeout: Duration) -> RpcHandle<Other> {
+        app.world.run_system_once_with(
+            (request, timeout),
+            |In((request, timeout)), mut rpc: RpcSender<Test, Other>| {
+                rpc.send_with_timeout(request, timeout)
+            },
+        )
+    }
+
+    fn results(app: &mut App) -> Vec<RpcResult<Other>> {
+        app.world
+            .resource_mut::<Events<RpcResult<Other>>>()
+            .drain()
+            .collect()
+    }
+
+    fn responses_to(changes: &[(SerializedChange, Token)], peer: Token) -> Vec<SerializedChange> {
+        changes
+            .iter()
+            .filter(|(_, target)| *target == peer)
+            .map(|(change, _)| change.clone())
+            .collect()
+    }
+
+    #[test]
+    fn concurrent_requests_get_their_own_responses() {
+        let mut robot = rpc_app(SyncRole::Server { port: Some(0) }, &[CLIENT_A, CLIENT_B]);
+        let mut client_a = rpc_app(SyncRole::Client, &[ROBOT]);
+        let mut client_b = rpc_app(SyncRole::Client, &[ROBOT]);
+        robot.add_systems(Update, double);
+
+        let timeout = Duration::from_secs(1);
+        let first_a = send(&mut client_a, Test(1), timeout);
+        let second_a = send(&mut client_a, Test(3), timeout);
+        let only_b = send(&mut client_b, Test(2), timeout);
+        client_a.update();
+        client_b.update();
+
+        // Both clients' requests are answered in the same frame
+        let from_a = outbound(&mut client_a);
+        let from_b = outbound(&mut client_b);
+        robot.world.send_event_batch(
+            from_a
+                .into_iter()
+                .map(|change| SerializedChangeInEvent(change, CLIENT_A)),
+        );
+        deliver(&mut robot, &from_b, CLIENT_B);
+
+        // Responses are only sent to the peer that asked
+        assert!(outbound(&mut robot).is_empty());
+        let responses = targeted(&mut robot);
+        assert_eq!(responses.len(), 3);
+
+        deliver(&mut client_a, &responses_to(&responses, CLIENT_A), ROBOT);
+        deliver(&mut client_b, &responses_to(&responses, CLIENT_B), ROBOT);
+
+        let results_a = results(&mut client_a);
+        assert_eq!(results_a.len(), 2);
+        for result in &results_a {
+            if first_a.is_for(result) {
+                assert_eq!(result.result, Ok(Other(2)));
+            } else {
+                assert!(second_a.is_for(result));
+                assert_eq!(result.result, Ok(Other(6)));
+            }
+        }
+
+        let results_b = results(&mut client_b);
+        assert_eq!(results_b.len(), 1);
+        assert!(only_b.is_for(&results_b[0]));
+        assert_eq!(results_b[0].result, Ok(Other(4)));
+
+        // Answered requests don't time out later
+        advance(&mut client_a, Duration::from_secs(2));
+        client_a.update();
+        assert!(results(&mut client_a).is_empty());
+
+        // Someone else's response is ignored
+        deliver(&mut client_a, &responses_to(&responses, CLIENT_B), ROBOT);
+        assert!(results(&mut client_a).is_empty());
+    }
+
+    #[test]
+    fn local_requests_are_answered_locally() {
+        let mut robot = rpc_app(SyncRole::Server { port: Some(0) }, &[CLIENT_A]);
+        robot.add_systems(Update, double);
+
+        let handle = send(&mut robot, Test(1), Duration::from_secs(1));
+        robot.update();
+        robot.update();
+
+        let results = results(&mut robot);
+        assert_eq!(results.len(), 1);
+        assert!(handle.is_for(&results[0]));
+        assert_eq!(results[0].result, Ok(Other(2)));
+
+        // Peers still see the request, but the response has nowhere to go
+        assert_eq!(outbound(&mut robot).len(), 1);
+        assert!(targeted(&mut robot).is_empty());
+    }
+
+    #[test]
+    fn unanswered_requests_time_out() {
+        let mut client = rpc_app(SyncRole::Client, &[ROBOT]);
+
+        let handle = send(&mut client, Test(1), Duration::from_secs(1));
+        client.update();
+        assert_eq!(outbound(&mut client).len(), 1);
+
+        advance(&mut client, Duration::from_millis(900));
+        client.update();
+        assert!(results(&mut client).is_empty());
+
+        advance(&mut client, Duration::from_millis(200));
+        client.update();
+        let timed_out = results(&mut client);
+        assert_eq!(timed_out.len(), 1);
+        assert!(handle.is_for(&timed_out[0]));
+        assert_eq!(
+            timed_out[0].result,
+            Err(RpcError::TimedOut(Duration::from_secs(1)))
+        );
+        assert!(client
+            .world
+            .resource::<PendingRequests<Test, Other>>()
+            .is_empty());
+
+        // A late answer doesn't produce a second result
+        client.world.send_event(RpcResponse {
+            id: handle.id(),
+            response: Other(2),
+            target: None,
+        });
+        client.update();
+        assert!(results(&mut client).is_empty());
+    }
+}
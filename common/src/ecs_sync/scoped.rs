@@ -0,0 +1,167 @@
+//! Replicated events addressed to a single robot
+//!
+//! Plain replicated events reach every robot, so with two robots connected a command meant for
+//! one acts on both. Register an event with
+//! [`AppReplicateExt::replicate_scoped_event`](super::AppReplicateExt::replicate_scoped_event),
+//! send it wrapped in [`Scoped`] and read it with [`ScopedEvents`], which only yields events for
+//! robots owned by this app. Unscoped events are still accepted while senders move over.
+
+use bevy::{
+    ecs::{
+        event::{Event, EventReader},
+        query::{With, Without},
+        system::{Local, Query, SystemParam},
+    },
+    reflect::Reflect,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::components::{Robot, RobotId};
+
+use super::{ForignOwned, NetId};
+
+/// Wire envelope for an event meant for one robot
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
+pub struct Scoped<E> {
+    pub robot: RobotId,
+    pub event: E,
+}
+
+impl<E> Scoped<E> {
+    pub fn new(robot: RobotId, event: E) -> Self {
+        Self { robot, event }
+    }
+}
+
+/// Addressed to no robot, only useful as a placeholder
+impl<E: Default> Default for Scoped<E> {
+    fn default() -> Self {
+        Self::new(RobotId(NetId::invalid()), E::default())
+    }
+}
+
+/// Reads `E` events addressed to a robot owned by this app
+#[derive(SystemParam)]
+pub struct ScopedEvents<'w, 's, E: Event> {
+    scoped: EventReader<'w, 's, Scoped<E>>,
+    unscoped: EventReader<'w, 's, E>,
+    robots: Query<'w, 's, &'static RobotId, (With<Robot>, Without<ForignOwned>)>,
+    warned: Local<'s, bool>,
+}
+
+impl<E: Event + Clone> ScopedEvents<'_, '_, E> {
+    /// Events received since the last read that target this robot
+    pub fn read(&mut self) -> Vec<E> {
+        let mut events = Vec::new();
+
+        for Scoped { robot, event } in self.scoped.read() {
+            if self.robots.iter().any(|it| it == robot) {
+                events.push(event.clone());
+            }
+        }
+
+        let unscoped = self.unscoped.read().cloned().collect::<Vec<_>>();
+        if !unscoped.is_empty() && !*self.warned {
+            warn!(
+                "Got unscoped {}, send it as a Scoped event instead",
+                std::any::type_name::<E>()
+            );
+            *self.warned = true;
+        }
+        events.extend(unscoped);
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        app::{App, Update},
+        ecs::{
+            event::Event,
+            system::{ResMut, Resource},
+        },
+        reflect::Reflect,
+    };
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        components::{Robot, RobotId},
+        ecs_sync::{
+            test_utils::{app, deliver, outbound, CLIENT_A, ROBOT},
+            AppReplicateExt, NetId,
+        },
+        sync::SyncRole,
+    };
+
+    use super::{Scoped, ScopedEvents};
+
+    #[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+    struct ResetThing;
+
+    #[derive(Resource, Default)]
+    struct Resets(usize);
+
+    fn count_resets(mut events: ScopedEvents<ResetThing>, mut resets: ResMut<Resets>) {
+        resets.0 += events.read().len();
+    }
+
+    fn robot_app() -> (App, RobotId) {
+        let mut app = app(SyncRole::Server { port: Some(0) }, &[CLIENT_A]);
+        app.replicate_scoped_event::<ResetThing>()
+            .init_resource::<Resets>()
+            .add_systems(Update, count_resets);
+
+        let robot = RobotId(NetId::random());
+        app.world.spawn((Robot, robot));
+
+        (app, robot)
+    }
+
+    fn surface_app() -> App {
+        let mut app = app(SyncRole::Client, &[ROBOT]);
+        app.replicate_scoped_event::<ResetThing>();
+
+        app
+    }
+
+    fn resets(app: &App) -> usize {
+        app.world.resource::<Resets>().0
+    }
+
+    #[test]
+    fn only_the_targeted_robot_acts() {
+        let (mut robot_a, id_a) = robot_app();
+        let (mut robot_b, _) = robot_app();
+        let mut surface = surface_app();
+
+        surface.world.send_event(Scoped::new(id_a, ResetThing));
+        surface.update();
+
+        let changes = outbound(&mut surface);
+        deliver(&mut robot_a, &changes, CLIENT_A);
+        deliver(&mut robot_b, &changes, CLIENT_A);
+
+        assert_eq!(resets(&robot_a), 1);
+        assert_eq!(resets(&robot_b), 0);
+    }
+
+    #[test]
+    fn unscoped_events_still_reach_every_robot() {
+        let (mut robot_a, _) = robot_app();
+        let (mut robot_b, _) = robot_app();
+        let mut surface = surface_app();
+
+        surface.world.send_event(ResetThing);
+        surface.update();
+
+        let changes = outbound(&mut surface);
+        deliver(&mut robot_a, &changes, CLIENT_A);
+        deliver(&mut robot_b, &changes, CLIENT_A);
+
+        assert_eq!(resets(&robot_a), 1);
+        assert_eq!(resets(&robot_b), 1);
+    }
+}
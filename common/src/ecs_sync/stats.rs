@@ -0,0 +1,314 @@
+//! Per type counts of outbound replication traffic
+//!
+//! Meant for finding replication storms, usually a component inserted every frame without
+//! checking if it changed.
+
+use std::{borrow::Cow, collections::VecDeque, time::Duration};
+
+use ahash::HashMap;
+use bevy::{
+    ecs::system::{Res, ResMut, Resource},
+    time::{Real, Time},
+};
+use tracing::warn;
+
+use crate::types::system::SyncTypeRate;
+
+use super::{NetTypeId, SerializedChange};
+
+/// Length of the window changes are counted over
+pub const STATS_WINDOW: Duration = Duration::from_secs(1);
+/// Windows of history kept per type
+pub const STATS_HISTORY: usize = 30;
+/// Types exceeding this many updates per second are logged by default
+pub const DEFAULT_WARN_RATE: u32 = 2000;
+
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct SyncStatsSettings {
+    /// Log a warning when a single type sends more than this many updates per second
+    pub warn_rate: Option<u32>,
+}
+
+impl Default for SyncStatsSettings {
+    fn default() -> Self {
+        Self {
+            warn_rate: Some(DEFAULT_WARN_RATE),
+        }
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct SyncStats {
+    window_start: Option<Duration>,
+    windows: u64,
+    coalesced: u64,
+
+    types: HashMap<NetTypeId, TypeStats>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TypeStats {
+    pending_count: u32,
+    pending_bytes: u64,
+
+    /// Rates over the last completed window
+    pub per_second: u32,
+    pub bytes_per_second: u64,
+    /// Updates per second of the last [`STATS_HISTORY`] windows, oldest first
+    pub history: VecDeque<u32>,
+}
+
+impl SyncStats {
+    pub fn record(&mut self, change: &SerializedChange) {
+        let token = match change {
+            SerializedChange::ComponentUpdated(_, token, _)
+            | SerializedChange::EventEmitted(token, _) => token.clone(),
+            SerializedChange::EntitySpawned(_) => Cow::Borrowed("<Entity Spawned>"),
+            SerializedChange::EntityDespawned(_) => Cow::Borrowed("<Entity Despawned>"),
+            SerializedChange::OwnershipTransferred(..) => Cow::Borrowed("<Ownership Transferred>"),
+            SerializedChange::TypeQuarantined(..) => Cow::Borrowed("<Type Quarantined>"),
+        };
+
+        let stats = self.types.entry(token).or_default();
+        stats.pending_count += 1;
+        stats.pending_bytes += change.payload_len() as u64;
+    }
+
+    /// Counts updates dropped by [`coalesce`](super::coalesce::coalesce) before being sent
+    pub fn record_coalesced(&mut self, count: usize) {
+        self.coalesced += count as u64;
+    }
+
+    /// Updates superseded within the same frame and never sent, since startup
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced
+    }
+
+    /// Closes the current window once [`STATS_WINDOW`] has passed, returns true if it did
+    pub fn roll_over(&mut self, now: Duration) -> bool {
+        let start = *self.window_start.get_or_insert(now);
+        let elapsed = now.saturating_sub(start);
+
+        if elapsed < STATS_WINDOW {
+            return false;
+        }
+
+        // Frames rarely line up with the window exactly
+        let seconds = elapsed.as_secs_f64();
+
+        for stats in self.types.values_mut() {
+            stats.per_second = (stats.pending_count as f64 / seconds).round() as u32;
+            stats.bytes_per_second = (stats.pending_bytes as f64 / seconds).round() as u64;
+            stats.pending_count = 0;
+            stats.pending_bytes = 0;
+
+            stats.history.push_back(stats.per_second);
+            while stats.history.len() > STATS_HISTORY {
+                stats.history.pop_front();
+            }
+        }
+
+        // Forget types that have been quiet for the whole history
+        self.types
+            .retain(|_, stats| stats.history.iter().any(|&it| it > 0));
+
+        self.window_start = Some(now);
+        self.windows += 1;
+
+        true
+    }
+
+    /// Number of completed windows, changes once per [`STATS_WINDOW`]
+    pub fn windows(&self) -> u64 {
+        self.windows
+    }
+
+    pub fn get(&self, token: &str) -> Option<&TypeStats> {
+        self.types.get(token)
+    }
+
+    /// Every type seen recently, busiest first
+    pub fn by_rate(&self) -> Vec<(&NetTypeId, &TypeStats)> {
+        let mut types = self.types.iter().collect::<Vec<_>>();
+        types.sort_by(|(a_name, a), (b_name, b)| {
+            b.per_second
+                .cmp(&a.per_second)
+                .then(b.bytes_per_second.cmp(&a.bytes_per_second))
+                .then(a_name.cmp(b_name))
+        });
+
+        types
+    }
+
+    /// The `count` busiest types in a form that can be replicated
+    pub fn top_offenders(&self, count: usize) -> Vec<SyncTypeRate> {
+        self.by_rate()
+            .into_iter()
+            .take(count)
+            .map(|(name, stats)| SyncTypeRate {
+                name: name.to_string(),
+                per_second: stats.per_second,
+                bytes_per_second: stats.bytes_per_second,
+                history: stats.history.iter().copied().collect(),
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn roll_sync_stats(
+    mut stats: ResMut<SyncStats>,
+    settings: Res<SyncStatsSettings>,
+    time: Res<Time<Real>>,
+) {
+    if !stats.roll_over(time.elapsed()) {
+        return;
+    }
+
+    let Some(warn_rate) = settings.warn_rate else {
+        return;
+    };
+
+    for (name, type_stats) in stats.by_rate() {
+        if type_stats.per_second <= warn_rate {
+            break;
+        }
+
+        warn!(
+            "Replication storm: {name} sent {} updates ({} bytes) in the last second",
+            type_stats.per_second, type_stats.bytes_per_second
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, sync::Arc, time::Duration};
+
+    use crate::ecs_sync::{NetId, SerializedChange};
+
+    use super::{SyncStats, STATS_HISTORY};
+
+    fn update(name: &'static str, bytes: usize) -> SerializedChange {
+        SerializedChange::ComponentUpdated(
+            NetId::random(),
+            Cow::Borrowed(name),
+            Some(Arc::new(vec![0; bytes])),
+        )
+    }
+
+    fn secs(secs: f32) -> Duration {
+        Duration::from_secs_f32(secs)
+    }
+
+    #[test]
+    fn aggregates_per_type() {
+        let mut stats = SyncStats::default();
+        assert!(!stats.roll_over(secs(0.0)));
+
+        for _ in 0..100 {
+            stats.record(&update("Depth", 8));
+        }
+        for _ in 0..3 {
+            stats.record(&update("Armed", 1));
+        }
+        stats.record(&SerializedChange::EntitySpawned(NetId::random()));
+        stats.record(&SerializedChange::ComponentUpdated(
+            NetId::random(),
+            Cow::Borrowed("Armed"),
+            None,
+        ));
+
+        // Nothing is reported until the window closes
+        assert_eq!(stats.get("Depth").unwrap().per_second, 0);
+        assert!(!stats.roll_over(secs(0.5)));
+        assert!(stats.roll_over(secs(1.0)));
+        assert_eq!(stats.windows(), 1);
+
+        let depth = stats.get("Depth").unwrap();
+        assert_eq!(depth.per_second, 100);
+        assert_eq!(depth.bytes_per_second, 800);
+
+        let armed = stats.get("Armed").unwrap();
+        assert_eq!(armed.per_second, 4);
+        assert_eq!(armed.bytes_per_second, 3);
+
+        assert_eq!(stats.get("<Entity Spawned>").unwrap().per_second, 1);
+
+        let order = stats
+            .by_rate()
+            .into_iter()
+            .map(|(name, _)| name.as_ref())
+            .collect::<Vec<_>>();
+        assert_eq!(order, ["Depth", "Armed", "<Entity Spawned>"]);
+
+        let top = stats.top_offenders(1);
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].name, "Depth");
+        assert_eq!(top[0].history, [100]);
+    }
+
+    #[test]
+    fn rolls_over_each_second() {
+        let mut stats = SyncStats::default();
+        stats.roll_over(secs(0.0));
+
+        for _ in 0..10 {
+            stats.record(&update("Depth", 4));
+        }
+        assert!(stats.roll_over(secs(1.0)));
+
+        for _ in 0..20 {
+            stats.record(&update("Depth", 4));
+        }
+        assert!(stats.roll_over(secs(2.0)));
+
+        // Counts reset between windows
+        let depth = stats.get("Depth").unwrap();
+        assert_eq!(depth.per_second, 20);
+        assert_eq!(depth.history, [10, 20]);
+
+        // A late frame stretches the window, rates stay per second
+        for _ in 0..30 {
+            stats.record(&update("Depth", 4));
+        }
+        assert!(stats.roll_over(secs(3.5)));
+        assert_eq!(stats.get("Depth").unwrap().per_second, 20);
+    }
+
+    #[test]
+    fn coalesced_updates_are_counted_separately() {
+        let mut stats = SyncStats::default();
+        stats.roll_over(secs(0.0));
+
+        stats.record(&update("Depth", 4));
+        stats.record_coalesced(2);
+        stats.record_coalesced(0);
+        stats.roll_over(secs(1.0));
+
+        assert_eq!(stats.coalesced(), 2);
+        // Only what was sent counts towards the rates
+        assert_eq!(stats.get("Depth").unwrap().per_second, 1);
+    }
+
+    #[test]
+    fn history_is_bounded_and_quiet_types_are_dropped() {
+        let mut stats = SyncStats::default();
+        stats.roll_over(secs(0.0));
+
+        for window in 1..=STATS_HISTORY + 5 {
+            stats.record(&update("Depth", 4));
+            if window == 1 {
+                stats.record(&update("Leak", 1));
+            }
+
+            stats.roll_over(secs(window as f32));
+
+            if window < STATS_HISTORY {
+                assert!(stats.get("Leak").is_some());
+            }
+        }
+
+        assert_eq!(stats.get("Depth").unwrap().history.len(), STATS_HISTORY);
+        assert!(stats.get("Leak").is_none());
+    }
+}
@@ -0,0 +1,94 @@
+//! Shared harness for exercising replication between in-process apps
+
+use bevy::{
+    app::App,
+    ecs::{component::Component, entity::Entity, event::Events},
+    reflect::Reflect,
+};
+use networking::Token;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ecs_sync::{
+        apply_changes::ChangeApplicationPlugin, detect_changes::ChangeDetectionPlugin,
+        AppReplicateExt, EntityMap, NetId, SerializationSettings, SerializedChange,
+        SerializedChangeInEvent, SerializedChangeOutEvent, SerializedChangeTargetedOutEvent,
+    },
+    error::ErrorEvent,
+    sync::{Peers, SyncRole},
+};
+
+#[derive(Component, Reflect, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Test(pub u32);
+
+#[derive(Component, Reflect, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Other(pub u32);
+
+pub const ROBOT: Token = Token(0);
+pub const CLIENT_A: Token = Token(1);
+pub const CLIENT_B: Token = Token(2);
+
+pub fn app(role: SyncRole, peers: &[Token]) -> App {
+    let mut app = App::new();
+
+    app.add_event::<SerializedChangeInEvent>()
+        .add_event::<SerializedChangeOutEvent>()
+        .add_event::<SerializedChangeTargetedOutEvent>()
+        .add_event::<ErrorEvent>()
+        .init_resource::<SerializationSettings>()
+        .init_resource::<EntityMap>()
+        .init_resource::<Peers>()
+        .insert_resource(role)
+        .add_plugins((ChangeDetectionPlugin, ChangeApplicationPlugin))
+        .replicate::<Test>()
+        .replicate::<Other>();
+
+    app.world
+        .resource_mut::<Peers>()
+        .valid_tokens
+        .extend(peers.iter().copied());
+
+    app
+}
+
+pub fn outbound(app: &mut App) -> Vec<SerializedChange> {
+    app.world
+        .resource_mut::<Events<SerializedChangeOutEvent>>()
+        .drain()
+        .map(|it| it.0)
+        .collect()
+}
+
+/// Changes sent to a single peer, along with that peer
+pub fn targeted(app: &mut App) -> Vec<(SerializedChange, Token)> {
+    app.world
+        .resource_mut::<Events<SerializedChangeTargetedOutEvent>>()
+        .drain()
+        .map(|it| (it.0, it.1))
+        .collect()
+}
+
+pub fn deliver(app: &mut App, changes: &[SerializedChange], from: Token) {
+    app.world.send_event_batch(
+        changes
+            .iter()
+            .cloned()
+            .map(|change| SerializedChangeInEvent(change, from)),
+    );
+    app.update();
+}
+
+pub fn local(app: &App, net_id: NetId) -> Option<Entity> {
+    app.world
+        .resource::<EntityMap>()
+        .forign_to_local
+        .get(&net_id)
+        .copied()
+}
+
+pub fn errors(app: &mut App) -> usize {
+    app.world
+        .resource_mut::<Events<ErrorEvent>>()
+        .drain()
+        .count()
+}
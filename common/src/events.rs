@@ -1,32 +1,103 @@
-use std::borrow::Cow;
+use std::time::Duration;
 
 use bevy::{
     app::App,
     ecs::event::Event,
     reflect::{Reflect, ReflectDeserialize, ReflectSerialize},
 };
+use networking::Token;
 use serde::{Deserialize, Serialize};
 
-use crate::{adapters::serde::ReflectSerdeAdapter, ecs_sync::AppReplicateExt};
+use crate::{
+    adapters::serde::ReflectSerdeAdapter,
+    components::RobotId,
+    ecs_sync::{
+        round_trip::default_sample,
+        rpc::{RpcRequest, RpcResponse},
+        scoped::Scoped,
+        AppReplicateExt, EventTarget, PeerEvent,
+    },
+    types::{
+        checklist::{Checklist, ChecklistItem},
+        files::FileEntry,
+        ids::ServoId,
+    },
+};
 
 macro_rules! events {
-    ($($name:ident),*) => {
+    (
+        $($name:ident),*;
+        peer: $($peer:ident),*;
+        scoped: $($scoped:ident),*;
+        $($request:ident => $response:ident),*
+    ) => {
         pub fn register_events(app: &mut App) {
             $(
                 app.replicate_event::<$name>();
+                default_sample!(app, $name);
+            )*
+            $(
+                app.replicate_peer_event::<$peer>();
+                default_sample!(app, $peer);
+            )*
+            $(
+                app.replicate_scoped_event::<$scoped>();
+                default_sample!(app, $scoped);
+                default_sample!(app, Scoped<$scoped>);
+            )*
+            $(
+                app.replicate_rpc::<$request, $response>();
+                default_sample!(app, RpcRequest<$request>);
+                default_sample!(app, RpcResponse<$response>);
             )*
         }
     }
 }
 
 events! {
+    ResetServo,
+    ServoCommand,
+    CaptureStill,
+    RemoteLogRecord,
+    OperatorHeartbeat,
+    ArmRejected,
+    StartBehavior,
+    StopBehavior,
+    ArchiveCrashReport,
+    ReloadMotorData,
+    CommandComplete;
+
+    // The robot needs to know which surface sent them
+    peer:
+    ArmRequest,
+    DisarmRequest,
+    RequestArmAuthority,
+    ReleaseArmAuthority,
+    RebindServer;
+
+    // Sent wrapped in a `Scoped` so they only reach one robot
+    scoped:
     ResyncCameras,
     CalibrateSeaLevel,
     ResetYaw,
     ResetServos,
-    ResetServo
+    ResetTetherTurns,
+    ResetPosition,
+    ClearProfile,
+    LoadChecklist,
+    SetChecklistItem,
+    RequestConfig;
+
+    UploadConfig => ConfigUploadResult,
+    ListFiles => FileListing,
+    DownloadFile => DownloadStarted,
+    DeleteFile => FileDeleted
 }
 
+/// Largest robot config carried by [`UploadConfig`] or [`crate::components::ConfigSnapshot`], in
+/// bytes
+pub const MAX_CONFIG_TEXT: usize = 64 * 1024;
+
 #[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
 #[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
 pub struct ResyncCameras;
@@ -43,6 +114,360 @@ pub struct ResetYaw;
 #[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
 pub struct ResetServos;
 
+/// One-shot commands the robot answers with a [`CommandComplete`] once it is done with them
+#[derive(
+    Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[reflect(Serialize, Deserialize, Debug, PartialEq)]
+pub enum AckedCommand {
+    /// [`CalibrateSeaLevel`]
+    CalibrateSeaLevel,
+    /// [`ResetYaw`]
+    ResetYaw,
+    /// [`ResyncCameras`], also sent for resyncs the robot starts itself
+    ResyncCameras,
+}
+
+impl AckedCommand {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AckedCommand::CalibrateSeaLevel => "Calibrate Sea Level",
+            AckedCommand::ResetYaw => "Reset Yaw",
+            AckedCommand::ResyncCameras => "Resync Cameras",
+        }
+    }
+}
+
+/// Sent by the robot when it finished an [`AckedCommand`], whether or not it worked
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct CommandComplete {
+    pub robot: RobotId,
+    pub command: AckedCommand,
+    pub ok: bool,
+    /// What was done, or why it couldn't be
+    pub detail: String,
+}
+
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ResetTetherTurns;
+
+/// Makes the robot's current position the origin of its [`crate::components::PositionEstimate`]
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ResetPosition;
+
+/// Moves the robot's [`crate::components::PreviousCrashReport`] out of the way once it was read
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ArchiveCrashReport;
+
+/// Replaces the robot's [`crate::components::MissionChecklist`]
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct LoadChecklist(pub Checklist);
+
+/// Checks or unchecks an item of the robot's [`crate::components::MissionChecklist`]
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SetChecklistItem {
+    pub item: ChecklistItem,
+    pub checked: bool,
+    /// Time since the unix epoch on the surface when the operator clicked it
+    pub timestamp: Duration,
+}
+
+/// Empties the robot's [`crate::components::DepthTemperatureProfile`]
 #[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
 #[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
-pub struct ResetServo(pub Cow<'static, str>);
+pub struct ClearProfile;
+
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ResetServo(pub ServoId);
+
+/// Moves a servo to an absolute position
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ServoCommand {
+    pub servo: ServoId,
+    /// Position from -1 to 1
+    pub target: f32,
+}
+
+/// Takes a full resolution still from a camera, the robot sends it back as a file
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct CaptureStill {
+    /// Device or display name of the camera
+    pub camera: String,
+}
+
+/// Sent periodically by a surface while an operator is actively in control
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct OperatorHeartbeat {
+    pub seq: u32,
+}
+
+/// Implements [`PeerEvent`] for events that only need to know which peer sent them
+macro_rules! origin_events {
+    ($($name:ident),*) => {
+        $(
+            impl $name {
+                /// Peer the event arrived from, `None` if it was sent locally
+                pub fn origin(&self) -> Option<Token> {
+                    self.origin
+                }
+            }
+
+            impl PeerEvent for $name {
+                fn target(&self) -> EventTarget {
+                    EventTarget::Broadcast
+                }
+
+                fn set_origin(&mut self, origin: Token) {
+                    self.origin = Some(origin);
+                }
+            }
+        )*
+    };
+}
+
+origin_events!(
+    ArmRequest,
+    DisarmRequest,
+    RequestArmAuthority,
+    ReleaseArmAuthority,
+    RebindServer
+);
+
+/// Asks the robot to arm, it only does so if its pre-arm checks pass and the sender holds
+/// [`crate::components::ArmAuthority`]
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ArmRequest {
+    /// Arm even if non-critical checks fail
+    pub force: bool,
+
+    #[serde(skip)]
+    #[reflect(ignore)]
+    origin: Option<Token>,
+}
+
+impl ArmRequest {
+    pub fn new(force: bool) -> Self {
+        Self {
+            force,
+            origin: None,
+        }
+    }
+}
+
+/// Asks the robot to disarm, surfaces without [`crate::components::ArmAuthority`] may be refused
+/// depending on the robot's config
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct DisarmRequest {
+    #[serde(skip)]
+    #[reflect(ignore)]
+    origin: Option<Token>,
+}
+
+/// Asks for the robot's [`crate::components::ArmAuthority`], taking it from another surface
+/// unless the robot is armed
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct RequestArmAuthority {
+    #[serde(skip)]
+    #[reflect(ignore)]
+    origin: Option<Token>,
+}
+
+/// Gives up the robot's [`crate::components::ArmAuthority`] if the sender holds it
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ReleaseArmAuthority {
+    #[serde(skip)]
+    #[reflect(ignore)]
+    origin: Option<Token>,
+}
+
+/// Moves the robot's listener to another port, connected surfaces stay connected
+///
+/// Only accepted while disarmed from the surface holding [`crate::components::ArmAuthority`]
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct RebindServer {
+    pub port: u16,
+
+    #[serde(skip)]
+    #[reflect(ignore)]
+    origin: Option<Token>,
+}
+
+impl RebindServer {
+    pub fn new(port: u16) -> Self {
+        Self { port, origin: None }
+    }
+}
+
+/// Sent by the robot when an [`ArmRequest`] failed its pre-arm checks, or when an arm or disarm
+/// was refused for lack of [`crate::components::ArmAuthority`]
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ArmRejected {
+    pub reasons: Vec<String>,
+}
+
+/// Starts one of the robot's [`crate::components::AvailableBehaviors`], restarting it if it is
+/// already running
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
+#[reflect(from_reflect = false)]
+#[reflect(SerdeAdapter, /*Serialize, Deserialize,*/ Debug, PartialEq)]
+pub struct StartBehavior {
+    pub name: String,
+    /// Behavior specific settings, `null` uses the behavior's defaults
+    #[serde(with = "json_text")]
+    #[reflect(ignore)]
+    pub params: serde_json::Value,
+}
+
+/// Stops a running behavior
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct StopBehavior {
+    pub name: String,
+}
+
+/// Bincode can't carry self describing values, JSON parameters are sent as text instead
+mod json_text {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        value: &serde_json::Value,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<serde_json::Value, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        serde_json::from_str(&text).map_err(D::Error::custom)
+    }
+}
+
+/// A log record forwarded from the robot
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct RemoteLogRecord {
+    pub level: LogLevel,
+    pub target: String,
+    pub message: String,
+    /// Time since the unix epoch on the robot
+    pub timestamp: Duration,
+    /// Records dropped before this one because the robot was logging too fast
+    pub dropped: u64,
+}
+
+#[derive(Serialize, Deserialize, Reflect, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[reflect(Serialize, Deserialize, Debug, PartialEq)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+/// Retries loading the robot's motor data, see [`crate::components::MotorDataStatus`]
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ReloadMotorData;
+
+/// Asks the robot to publish its config file as a [`crate::components::ConfigSnapshot`]
+#[derive(Event, Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct RequestConfig;
+
+/// Replaces the robot's config file, sent as a request the robot answers with a
+/// [`ConfigUploadResult`]
+///
+/// Rejected while armed. The robot keeps running with its old config until it is restarted.
+#[derive(Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct UploadConfig {
+    pub toml_text: String,
+}
+
+#[derive(Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ConfigUploadResult {
+    /// The new config was written and is used from the next restart
+    pub ok: bool,
+    pub errors: Vec<String>,
+}
+
+/// Lists one of the directories the robot lets the surface browse, see
+/// [`crate::components::FileRoots`]
+#[derive(Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ListFiles {
+    pub dir: String,
+}
+
+#[derive(Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct FileListing {
+    pub dir: String,
+    /// Newest first
+    pub entries: Vec<FileEntry>,
+    /// More files than [`crate::types::files::MAX_LISTING_ENTRIES`] were found, the oldest were
+    /// left out
+    pub truncated: bool,
+    pub error: Option<String>,
+}
+
+/// Sends a file from a browsable directory, answered with a [`DownloadStarted`]
+///
+/// The file arrives as a [`crate::file_transfer::TransferKind::Download`] transfer starting at
+/// chunk `first_chunk`, so an interrupted download can pick up where it left off.
+#[derive(Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct DownloadFile {
+    pub dir: String,
+    pub name: String,
+    pub first_chunk: u32,
+}
+
+impl DownloadFile {
+    /// Name the file is streamed under, unique across the browsable directories
+    pub fn transfer_name(&self) -> String {
+        format!("{}/{}", self.dir, self.name)
+    }
+}
+
+#[derive(Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct DownloadStarted {
+    /// Size of the whole file in bytes
+    pub size: u64,
+    pub error: Option<String>,
+}
+
+/// Deletes a file from a browsable directory, answered with a [`FileDeleted`]
+#[derive(Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct DeleteFile {
+    pub dir: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Reflect, Debug, Clone, PartialEq, Default)]
+#[reflect(SerdeAdapter, Serialize, Deserialize, Debug, PartialEq)]
+pub struct FileDeleted {
+    pub error: Option<String>,
+}
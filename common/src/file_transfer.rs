@@ -0,0 +1,1021 @@
+//! Chunked transfer of files between peers over the sync connection
+//!
+//! Files are either pushed to every peer from memory with [`SendFile`], or streamed from disk to
+//! one peer with [`StreamFile`]. Streamed files are read on a worker thread a few chunks ahead of
+//! the connection, so large files are never held in memory. Partial downloads survive a
+//! disconnect and are picked up again by asking for the chunks that are missing. Only downloads
+//! that were asked for with [`Reassembler::expect_download`] are received.
+
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    mem,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use ahash::HashMap;
+use anyhow::{anyhow, bail, Context};
+use bevy::prelude::*;
+use crossbeam::channel::{self, Receiver, Sender, TryRecvError};
+use networking::Token as NetToken;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::{self, ErrorEvent},
+    protocol::Protocol,
+    sync::{Net, Peers},
+};
+
+/// Size of the data carried by each chunk
+pub const CHUNK_SIZE: usize = 32 * 1024;
+/// Limits how much of the connection a transfer can take up each frame
+const CHUNKS_PER_FRAME: usize = 4;
+/// Chunks a streamed file is read ahead of the connection
+const STREAM_BUFFER: usize = 2 * CHUNKS_PER_FRAME;
+
+pub struct FileTransferPlugin;
+
+impl Plugin for FileTransferPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SendFile>()
+            .add_event::<StreamFile>()
+            .add_event::<FileReceived>()
+            .add_event::<FileChunkIn>()
+            .init_resource::<OutgoingFiles>()
+            .init_resource::<Reassembler>()
+            .add_systems(
+                Update,
+                (
+                    queue_files,
+                    send_chunks.pipe(error::handle_errors).after(queue_files),
+                    (receive_chunks, cleanup_transfers).chain(),
+                ),
+            );
+    }
+}
+
+/// Sends a file to all connected peers
+#[derive(Event, Debug, Clone)]
+pub struct SendFile {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Streams the file at `path` to `peer` from chunk `first_chunk` on, as a
+/// [`TransferKind::Download`] named `name`
+#[derive(Event, Debug, Clone)]
+pub struct StreamFile {
+    pub path: PathBuf,
+    pub name: String,
+    pub first_chunk: u32,
+    pub peer: NetToken,
+}
+
+/// A file from a peer was completely received
+#[derive(Event, Debug, Clone)]
+pub struct FileReceived {
+    pub path: PathBuf,
+    pub peer: NetToken,
+    pub kind: TransferKind,
+}
+
+/// Where files received from peers are stored, files are rejected when this is not present
+#[derive(Resource, Debug, Clone)]
+pub struct FileTransferDir(pub PathBuf);
+
+/// Where downloads are stored, see [`Reassembler::expect_download`]
+#[derive(Resource, Debug, Clone)]
+pub struct DownloadDir(pub PathBuf);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TransferKind {
+    /// Sent with [`SendFile`] and stored in the [`FileTransferDir`], partial files are removed
+    /// when the sender disconnects
+    #[default]
+    Push,
+    /// Asked for by the receiver and stored where it said when it asked, partial files are kept
+    /// so the download can resume
+    Download,
+}
+
+#[derive(Event, Debug)]
+pub(crate) struct FileChunkIn(pub NetToken, pub FileChunk);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChunk {
+    pub id: u32,
+    pub kind: TransferKind,
+    pub name: String,
+    pub chunk_index: u32,
+    pub total: u32,
+    pub data: Vec<u8>,
+}
+
+impl FileChunk {
+    pub fn into_packet(self) -> Protocol {
+        let FileChunk {
+            id,
+            kind,
+            name,
+            chunk_index,
+            total,
+            data,
+        } = self;
+
+        Protocol::FileTransfer {
+            id,
+            kind,
+            name,
+            chunk_index,
+            total,
+            data,
+        }
+    }
+}
+
+/// Splits `data` into the chunks that make up a transfer
+pub fn chunk_file<'a>(id: u32, name: &str, data: &'a [u8]) -> impl Iterator<Item = FileChunk> + 'a {
+    let total = chunk_count(data.len() as u64);
+    let name = name.to_owned();
+
+    (0..total).map(move |chunk_index| {
+        let start = chunk_index as usize * CHUNK_SIZE;
+        let end = (start + CHUNK_SIZE).min(data.len());
+
+        FileChunk {
+            id,
+            kind: TransferKind::Push,
+            name: name.clone(),
+            chunk_index,
+            total,
+            data: data[start..end].to_vec(),
+        }
+    })
+}
+
+/// Number of chunks a file of `size` bytes is sent in
+pub fn chunk_count(size: u64) -> u32 {
+    // Empty files still need one chunk to be created on the other side
+    size.div_ceil(CHUNK_SIZE as u64).max(1) as u32
+}
+
+/// Reads the chunks of the file at `path` from `first_chunk` on into `chunks`
+///
+/// Stops early once the receiving side is dropped. A `first_chunk` past the end resends the last
+/// chunk, so a download that was cut off just before finishing still finishes.
+pub fn stream_chunks(
+    path: &Path,
+    id: u32,
+    name: &str,
+    first_chunk: u32,
+    chunks: &Sender<anyhow::Result<FileChunk>>,
+) {
+    if let Err(err) = read_chunks(path, id, name, first_chunk, chunks) {
+        let _ = chunks.send(Err(err.context(format!("Stream {}", path.display()))));
+    }
+}
+
+fn read_chunks(
+    path: &Path,
+    id: u32,
+    name: &str,
+    first_chunk: u32,
+    chunks: &Sender<anyhow::Result<FileChunk>>,
+) -> anyhow::Result<()> {
+    let mut file = File::open(path).context("Open file")?;
+    let size = file.metadata().context("Read file size")?.len();
+
+    let total = chunk_count(size);
+    let first_chunk = first_chunk.min(total - 1);
+
+    let start = first_chunk as u64 * CHUNK_SIZE as u64;
+    file.seek(SeekFrom::Start(start)).context("Seek")?;
+
+    for chunk_index in first_chunk..total {
+        // Only the size seen at the start is sent, so a file being appended to stays consistent
+        // with `total`
+        let offset = chunk_index as u64 * CHUNK_SIZE as u64;
+        let len = (size - offset).min(CHUNK_SIZE as u64);
+
+        let mut data = Vec::with_capacity(len as usize);
+        (&mut file)
+            .take(len)
+            .read_to_end(&mut data)
+            .context("Read chunk")?;
+
+        if data.len() as u64 != len {
+            bail!("File shrank while it was being sent");
+        }
+
+        let chunk = FileChunk {
+            id,
+            kind: TransferKind::Download,
+            name: name.to_owned(),
+            chunk_index,
+            total,
+            data,
+        };
+
+        if chunks.send(Ok(chunk)).is_err() {
+            // Nobody is waiting on the rest
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Resource, Default)]
+struct OutgoingFiles {
+    next_id: u32,
+    queue: VecDeque<FileChunk>,
+    streams: VecDeque<OutgoingStream>,
+    /// Started with the first stream, exits once this is dropped
+    worker: Option<Sender<StreamJob>>,
+}
+
+impl OutgoingFiles {
+    fn next_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        id
+    }
+
+    fn stream(&mut self, job: StreamJob) {
+        let worker = self.worker.get_or_insert_with(spawn_stream_worker);
+
+        if worker.send(job).is_err() {
+            // The job's sender is dropped with it, so that stream ends as if it couldn't be read
+            error!("File stream worker is gone");
+            self.worker = None;
+        }
+    }
+}
+
+/// A file being read by the worker thread for a single peer
+struct OutgoingStream {
+    peer: NetToken,
+    chunks: Receiver<anyhow::Result<FileChunk>>,
+}
+
+/// A file for the worker thread to read into `chunks`
+struct StreamJob {
+    path: PathBuf,
+    id: u32,
+    name: String,
+    first_chunk: u32,
+    chunks: Sender<anyhow::Result<FileChunk>>,
+}
+
+/// Starts the thread streams are read on
+///
+/// Streams are read one at a time in the order they were queued, the same order
+/// [`send_chunks`] sends them in. A stream whose peer left fails its next send and the worker
+/// moves on.
+fn spawn_stream_worker() -> Sender<StreamJob> {
+    let (tx, rx) = channel::unbounded::<StreamJob>();
+
+    thread::Builder::new()
+        .name("File Stream".to_owned())
+        .spawn(move || {
+            for job in rx {
+                stream_chunks(&job.path, job.id, &job.name, job.first_chunk, &job.chunks);
+            }
+        })
+        .expect("Spawn thread");
+
+    tx
+}
+
+/// Reassembles chunks into files, chunks from each transfer must arrive in order
+///
+/// A download may start past chunk 0 if its partial file already holds the chunks before it.
+#[derive(Resource, Default)]
+pub struct Reassembler {
+    transfers: HashMap<(NetToken, u32), PartialFile>,
+    /// Downloads that were asked for by peer and name, along with where they are saved
+    requested: HashMap<(NetToken, String), PathBuf>,
+}
+
+struct PartialFile {
+    file: File,
+    kind: TransferKind,
+    part_path: PathBuf,
+    final_path: PathBuf,
+    next_chunk: u32,
+    total: u32,
+}
+
+/// Progress of a transfer being received
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferProgress {
+    pub peer: NetToken,
+    pub kind: TransferKind,
+    /// Where the file ends up once complete
+    pub path: PathBuf,
+    pub received: u32,
+    pub total: u32,
+}
+
+/// Where a transfer named `name` is written while it is received and once it is complete
+///
+/// Peers only get to pick the name, not the directory
+fn transfer_paths(dir: &Path, name: &str) -> anyhow::Result<(PathBuf, PathBuf)> {
+    let name = Path::new(name)
+        .file_name()
+        .with_context(|| format!("Bad file name: {name:?}"))?;
+
+    let final_path = dir.join(name);
+    let mut part_path = final_path.clone().into_os_string();
+    part_path.push(".part");
+
+    Ok((PathBuf::from(part_path), final_path))
+}
+
+/// How many whole chunks of the download named `name` are already on disk in `dir`, the chunk to
+/// ask for to resume it
+pub fn partial_chunks(dir: &Path, name: &str) -> u32 {
+    let Ok((part_path, _)) = transfer_paths(dir, name) else {
+        return 0;
+    };
+
+    fs::metadata(part_path)
+        .map(|it| (it.len() / CHUNK_SIZE as u64) as u32)
+        .unwrap_or(0)
+}
+
+impl Reassembler {
+    /// Receives the download named `name` from `peer` into `dir`
+    ///
+    /// Download chunks that were not asked for are dropped. Asking again replaces where the
+    /// download is saved.
+    pub fn expect_download(&mut self, peer: NetToken, name: String, dir: PathBuf) {
+        self.requested.insert((peer, name), dir);
+    }
+
+    /// Where the download named `name` from `peer` is saved, if it was asked for
+    pub fn download_dir(&self, peer: NetToken, name: &str) -> Option<&Path> {
+        self.requested
+            .get(&(peer, name.to_owned()))
+            .map(PathBuf::as_path)
+    }
+
+    /// Writes `chunk` to disk, returns the path of the file once all chunks have arrived
+    pub fn accept(
+        &mut self,
+        dir: &Path,
+        peer: NetToken,
+        chunk: FileChunk,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        let key = (peer, chunk.id);
+
+        if chunk.chunk_index == 0 || !self.transfers.contains_key(&key) {
+            if chunk.chunk_index != 0 && chunk.kind == TransferKind::Push {
+                bail!(
+                    "Got chunk {} of unknown transfer {}",
+                    chunk.chunk_index,
+                    chunk.id
+                );
+            }
+
+            // A restarted transfer replaces whatever was left of the old one
+            if let Some(stale) = self.transfers.remove(&key) {
+                stale.discard();
+            }
+
+            if chunk.total == 0 {
+                bail!("Transfer {} has no chunks", chunk.id);
+            }
+
+            let (part_path, final_path) = transfer_paths(dir, &chunk.name)?;
+            fs::create_dir_all(dir).context("Create transfer dir")?;
+
+            let file = if chunk.chunk_index == 0 {
+                File::create(&part_path).context("Create partial file")?
+            } else {
+                resume_partial(&part_path, chunk.chunk_index)?
+            };
+
+            self.transfers.insert(
+                key,
+                PartialFile {
+                    file,
+                    kind: chunk.kind,
+                    part_path,
+                    final_path,
+                    next_chunk: chunk.chunk_index,
+                    total: chunk.total,
+                },
+            );
+        }
+
+        let partial = self.transfers.get_mut(&key).unwrap();
+
+        if partial.next_chunk != chunk.chunk_index || partial.total != chunk.total {
+            let expected = partial.next_chunk;
+            self.abort(key);
+
+            bail!(
+                "Transfer {} got chunk {} but expected {expected}",
+                chunk.id,
+                chunk.chunk_index
+            );
+        }
+
+        let rst = partial.file.write_all(&chunk.data);
+        if let Err(err) = rst {
+            self.abort(key);
+
+            return Err(anyhow!(err).context("Write chunk"));
+        }
+
+        partial.next_chunk += 1;
+        if partial.next_chunk < partial.total {
+            return Ok(None);
+        }
+
+        let partial = self.transfers.remove(&key).unwrap();
+        let PartialFile {
+            file,
+            kind,
+            part_path,
+            final_path,
+            ..
+        } = partial;
+
+        if kind == TransferKind::Download {
+            self.requested.remove(&(peer, chunk.name));
+        }
+
+        mem::drop(file);
+        fs::rename(&part_path, &final_path).context("Finish file")?;
+
+        Ok(Some(final_path))
+    }
+
+    /// Stops every transfer from `peer`, see [`Reassembler::retain_peers`]
+    pub fn abort_peer(&mut self, peer: NetToken) {
+        self.retain_peers(|it| it != peer);
+    }
+
+    /// Stops transfers from peers that do not match `keep`
+    ///
+    /// Partial pushes are removed, partial downloads are kept to be resumed. Resuming asks for the
+    /// download again, since the peer comes back under a new token.
+    pub fn retain_peers(&mut self, mut keep: impl FnMut(NetToken) -> bool) {
+        self.requested.retain(|(peer, _), _| keep(*peer));
+
+        let aborted = self
+            .transfers
+            .extract_if(|(peer, _), _| !keep(*peer))
+            .map(|(_, partial)| partial)
+            .collect::<Vec<_>>();
+
+        for partial in aborted {
+            match partial.kind {
+                TransferKind::Push => partial.discard(),
+                TransferKind::Download => {
+                    info!("Keeping {} to resume later", partial.part_path.display());
+                }
+            }
+        }
+    }
+
+    pub fn in_progress(&self) -> usize {
+        self.transfers.len()
+    }
+
+    pub fn progress(&self) -> impl Iterator<Item = TransferProgress> + '_ {
+        self.transfers
+            .iter()
+            .map(|(&(peer, _), partial)| TransferProgress {
+                peer,
+                kind: partial.kind,
+                path: partial.final_path.clone(),
+                received: partial.next_chunk,
+                total: partial.total,
+            })
+    }
+
+    fn abort(&mut self, key: (NetToken, u32)) {
+        if let Some(partial) = self.transfers.remove(&key) {
+            partial.discard();
+        }
+    }
+}
+
+/// Opens the partial file of a download to append `next_chunk` onto
+///
+/// Anything past the last whole chunk, like a chunk that was only partly written, is cut off.
+fn resume_partial(part_path: &Path, next_chunk: u32) -> anyhow::Result<File> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(part_path)
+        .with_context(|| format!("Nothing to resume at chunk {next_chunk}"))?;
+
+    let len = next_chunk as u64 * CHUNK_SIZE as u64;
+    let on_disk = file.metadata().context("Read partial file size")?.len();
+    if on_disk < len {
+        bail!("Can't resume at chunk {next_chunk}, only {on_disk} bytes were received");
+    }
+
+    file.set_len(len).context("Trim partial file")?;
+    file.seek(SeekFrom::End(0)).context("Seek")?;
+
+    Ok(file)
+}
+
+impl PartialFile {
+    fn discard(self) {
+        mem::drop(self.file);
+
+        if let Err(err) = fs::remove_file(&self.part_path) {
+            warn!("Could not remove {}: {err}", self.part_path.display());
+        }
+    }
+}
+
+fn queue_files(
+    mut outgoing: ResMut<OutgoingFiles>,
+    mut files: EventReader<SendFile>,
+    mut streams: EventReader<StreamFile>,
+) {
+    for file in files.read() {
+        let id = outgoing.next_id();
+
+        info!("Sending {} ({} bytes)", file.name, file.data.len());
+
+        let chunks = chunk_file(id, &file.name, &file.data);
+        outgoing.queue.extend(chunks);
+    }
+
+    for stream in streams.read() {
+        let id = outgoing.next_id();
+        let (tx, rx) = channel::bounded(STREAM_BUFFER);
+
+        info!(
+            "Streaming {} from chunk {}",
+            stream.path.display(),
+            stream.first_chunk
+        );
+
+        let StreamFile {
+            path,
+            name,
+            first_chunk,
+            peer,
+        } = stream.clone();
+
+        outgoing.stream(StreamJob {
+            path,
+            id,
+            name,
+            first_chunk,
+            chunks: tx,
+        });
+        outgoing
+            .streams
+            .push_back(OutgoingStream { peer, chunks: rx });
+    }
+}
+
+fn send_chunks(
+    net: Res<Net>,
+    peers: Res<Peers>,
+    mut outgoing: ResMut<OutgoingFiles>,
+) -> anyhow::Result<()> {
+    if outgoing.queue.is_empty() && outgoing.streams.is_empty() {
+        return Ok(());
+    }
+
+    // Streams to peers that left are dropped, which stops their workers
+    outgoing
+        .streams
+        .retain(|it| peers.valid_tokens.contains(&it.peer));
+
+    if peers.valid_tokens.is_empty() && !outgoing.queue.is_empty() {
+        let dropped = outgoing.queue.len();
+        outgoing.queue.clear();
+
+        bail!("No peers to send files to, dropped {dropped} chunks");
+    }
+
+    let count = outgoing.queue.len().min(CHUNKS_PER_FRAME);
+    for chunk in outgoing.queue.drain(..count) {
+        net.0
+            .brodcast_packet(chunk.into_packet())
+            .map_err(|_| anyhow!("Could not send file chunk"))?;
+    }
+
+    let mut budget = CHUNKS_PER_FRAME - count;
+    while budget > 0 {
+        let Some(stream) = outgoing.streams.front() else {
+            break;
+        };
+
+        match stream.chunks.try_recv() {
+            Ok(Ok(chunk)) => {
+                net.0
+                    .send_packet(stream.peer, chunk.into_packet())
+                    .map_err(|_| anyhow!("Could not send file chunk"))?;
+                budget -= 1;
+            }
+            Ok(Err(err)) => {
+                outgoing.streams.pop_front();
+                return Err(err);
+            }
+            // The worker is still reading
+            Err(TryRecvError::Empty) => break,
+            Err(TryRecvError::Disconnected) => {
+                outgoing.streams.pop_front();
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn receive_chunks(
+    push_dir: Option<Res<FileTransferDir>>,
+    mut reassembler: ResMut<Reassembler>,
+    mut chunks: EventReader<FileChunkIn>,
+    mut received: EventWriter<FileReceived>,
+    mut errors: EventWriter<ErrorEvent>,
+) {
+    for FileChunkIn(peer, chunk) in chunks.read() {
+        let dir = match chunk.kind {
+            TransferKind::Push => push_dir.as_ref().map(|it| it.0.clone()),
+            TransferKind::Download => reassembler
+                .download_dir(*peer, &chunk.name)
+                .map(Path::to_owned),
+        };
+        let Some(dir) = dir else {
+            debug!("Dropped chunk of {} nobody asked for", chunk.name);
+            continue;
+        };
+
+        let rst = reassembler.accept(&dir, *peer, chunk.clone());
+
+        match rst {
+            Ok(Some(path)) => {
+                info!("Received {}", path.display());
+
+                received.send(FileReceived {
+                    path,
+                    peer: *peer,
+                    kind: chunk.kind,
+                });
+            }
+            Ok(None) => {}
+            Err(err) => {
+                errors.send(err.context("Receive file").into());
+            }
+        }
+    }
+}
+
+fn cleanup_transfers(peers: Res<Peers>, mut reassembler: ResMut<Reassembler>) {
+    if !peers.is_changed() || reassembler.in_progress() == 0 {
+        return;
+    }
+
+    reassembler.retain_peers(|peer| peers.valid_tokens.contains(&peer));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use bevy::prelude::*;
+    use crossbeam::channel;
+    use networking::Token as NetToken;
+
+    use super::{
+        chunk_file, partial_chunks, receive_chunks, stream_chunks, FileChunk, FileChunkIn,
+        FileReceived, OutgoingFiles, Reassembler, StreamJob, TransferKind, CHUNK_SIZE,
+    };
+    use crate::error::ErrorEvent;
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|it| (it % 251) as u8).collect()
+    }
+
+    #[test]
+    fn chunking() {
+        let data = test_data(CHUNK_SIZE * 2 + 10);
+        let chunks = chunk_file(7, "still.jpg", &data).collect::<Vec<_>>();
+
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks.iter().all(|it| it.total == 3 && it.id == 7));
+        assert_eq!(chunks[2].data.len(), 10);
+
+        let joined = chunks
+            .iter()
+            .flat_map(|it| it.data.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(joined, data);
+
+        // Empty files still produce a chunk
+        assert_eq!(chunk_file(0, "empty", &[]).count(), 1);
+    }
+
+    #[test]
+    fn reassembly() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = test_data(CHUNK_SIZE * 3 + 1);
+
+        let mut reassembler = Reassembler::default();
+        let mut completed = None;
+
+        for chunk in chunk_file(1, "still.jpg", &data) {
+            assert!(completed.is_none());
+            completed = reassembler.accept(dir.path(), NetToken(1), chunk).unwrap();
+        }
+
+        let path = completed.expect("Transfer completed");
+        assert_eq!(path, dir.path().join("still.jpg"));
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert_eq!(reassembler.in_progress(), 0);
+        assert!(!dir.path().join("still.jpg.part").exists());
+    }
+
+    #[test]
+    fn out_of_order_chunks_abort() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = test_data(CHUNK_SIZE * 3);
+
+        let mut chunks = chunk_file(1, "still.jpg", &data);
+        let mut reassembler = Reassembler::default();
+
+        let first = chunks.next().unwrap();
+        let _skipped = chunks.next().unwrap();
+        let third = chunks.next().unwrap();
+
+        assert_eq!(
+            reassembler.accept(dir.path(), NetToken(1), first).unwrap(),
+            None
+        );
+        assert!(reassembler.accept(dir.path(), NetToken(1), third).is_err());
+
+        assert_eq!(reassembler.in_progress(), 0);
+        assert!(!dir.path().join("still.jpg.part").exists());
+    }
+
+    #[test]
+    fn disconnect_removes_partial_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = test_data(CHUNK_SIZE * 2);
+
+        let mut reassembler = Reassembler::default();
+
+        for (peer, name) in [(NetToken(1), "a.jpg"), (NetToken(2), "b.jpg")] {
+            let first = chunk_file(1, name, &data).next().unwrap();
+            assert_eq!(reassembler.accept(dir.path(), peer, first).unwrap(), None);
+        }
+
+        assert!(dir.path().join("a.jpg.part").exists());
+        assert!(dir.path().join("b.jpg.part").exists());
+
+        reassembler.abort_peer(NetToken(1));
+
+        assert_eq!(reassembler.in_progress(), 1);
+        assert!(!dir.path().join("a.jpg.part").exists());
+        assert!(dir.path().join("b.jpg.part").exists());
+    }
+
+    #[test]
+    fn names_cannot_escape_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = test_data(10);
+
+        let mut reassembler = Reassembler::default();
+        let chunk = chunk_file(1, "../../escape.jpg", &data).next().unwrap();
+
+        let path = reassembler.accept(dir.path(), NetToken(1), chunk).unwrap();
+        assert_eq!(path, Some(dir.path().join("escape.jpg")));
+
+        let chunk = chunk_file(2, "..", &data).next().unwrap();
+        assert!(reassembler.accept(dir.path(), NetToken(1), chunk).is_err());
+    }
+
+    fn streamed(path: &Path, id: u32, first_chunk: u32) -> Vec<FileChunk> {
+        let (tx, rx) = channel::bounded(2);
+        let path = path.to_owned();
+
+        let worker =
+            std::thread::spawn(move || stream_chunks(&path, id, "log.csv", first_chunk, &tx));
+        let chunks = rx.iter().map(|it| it.unwrap()).collect();
+        worker.join().unwrap();
+
+        chunks
+    }
+
+    #[test]
+    fn streaming_matches_chunking() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let data = test_data(CHUNK_SIZE * 2 + 10);
+        let path = dir.path().join("log.csv");
+        fs::write(&path, &data).unwrap();
+
+        let all = streamed(&path, 4, 0);
+        let chunked = chunk_file(4, "log.csv", &data).collect::<Vec<_>>();
+
+        assert_eq!(all.len(), chunked.len());
+        for (streamed, chunked) in all.iter().zip(&chunked) {
+            assert_eq!(streamed.kind, TransferKind::Download);
+            assert_eq!(
+                (streamed.chunk_index, streamed.total, &streamed.data),
+                (chunked.chunk_index, chunked.total, &chunked.data)
+            );
+        }
+
+        // Starting part way through skips what came before
+        let rest = streamed(&path, 5, 2);
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].chunk_index, 2);
+
+        // Past the end the last chunk is sent again
+        let past = streamed(&path, 6, 9);
+        assert_eq!(past.len(), 1);
+        assert_eq!(past[0].chunk_index, 2);
+    }
+
+    #[test]
+    fn downloads_resume_after_disconnect() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = tempfile::tempdir().unwrap();
+
+        let data = test_data(CHUNK_SIZE * 4 + 100);
+        let source = source_dir.path().join("log.csv");
+        fs::write(&source, &data).unwrap();
+
+        let mut reassembler = Reassembler::default();
+
+        // The connection drops after two chunks
+        for chunk in streamed(&source, 1, 0).into_iter().take(2) {
+            assert_eq!(
+                reassembler.accept(dir.path(), NetToken(1), chunk).unwrap(),
+                None
+            );
+        }
+        reassembler.abort_peer(NetToken(1));
+
+        assert_eq!(reassembler.in_progress(), 0);
+        assert!(dir.path().join("log.csv.part").exists());
+        assert_eq!(partial_chunks(dir.path(), "log.csv"), 2);
+
+        // Reconnected under a new token, asking for the rest as a new transfer
+        let mut completed = None;
+        for chunk in streamed(&source, 7, partial_chunks(dir.path(), "log.csv")) {
+            assert!(completed.is_none());
+            completed = reassembler.accept(dir.path(), NetToken(2), chunk).unwrap();
+        }
+
+        let path = completed.expect("Transfer completed");
+        assert_eq!(fs::read(&path).unwrap(), data);
+        assert!(!dir.path().join("log.csv.part").exists());
+        assert_eq!(partial_chunks(dir.path(), "log.csv"), 0);
+    }
+
+    #[test]
+    fn resume_trims_partly_written_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let data = test_data(CHUNK_SIZE * 3);
+        let chunks = chunk_file(1, "log.csv", &data)
+            .map(|it| FileChunk {
+                kind: TransferKind::Download,
+                ..it
+            })
+            .collect::<Vec<_>>();
+
+        // One whole chunk and half of the next made it to disk
+        fs::write(
+            dir.path().join("log.csv.part"),
+            &data[..CHUNK_SIZE + CHUNK_SIZE / 2],
+        )
+        .unwrap();
+        assert_eq!(partial_chunks(dir.path(), "log.csv"), 1);
+
+        let mut reassembler = Reassembler::default();
+        let mut completed = None;
+        for chunk in chunks[1..].iter().cloned() {
+            completed = reassembler.accept(dir.path(), NetToken(1), chunk).unwrap();
+        }
+        assert_eq!(fs::read(completed.unwrap()).unwrap(), data);
+
+        // Resuming past what is on disk fails instead of leaving a gap
+        fs::write(dir.path().join("log.csv.part"), &data[..CHUNK_SIZE]).unwrap();
+        assert!(reassembler
+            .accept(dir.path(), NetToken(1), chunks[2].clone())
+            .is_err());
+
+        // Pushes can't be resumed
+        let push = chunk_file(2, "still.jpg", &data).nth(1).unwrap();
+        assert!(reassembler.accept(dir.path(), NetToken(1), push).is_err());
+    }
+
+    #[test]
+    fn one_worker_streams_files_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut outgoing = OutgoingFiles::default();
+        let mut streams = Vec::new();
+
+        for (id, len) in [(1, CHUNK_SIZE * 2 + 5), (2, 10), (3, CHUNK_SIZE)] {
+            let path = dir.path().join(format!("{id}.csv"));
+            fs::write(&path, test_data(len)).unwrap();
+
+            let (tx, rx) = channel::bounded(2);
+            outgoing.stream(StreamJob {
+                path,
+                id,
+                name: format!("{id}.csv"),
+                first_chunk: 0,
+                chunks: tx,
+            });
+            streams.push((id, len, rx));
+        }
+
+        // Each stream is finished before the next one starts
+        for (id, len, rx) in streams {
+            let data = rx
+                .iter()
+                .map(|it| it.unwrap())
+                .inspect(|it| assert_eq!(it.id, id))
+                .flat_map(|it| it.data)
+                .collect::<Vec<_>>();
+            assert_eq!(data, test_data(len));
+        }
+
+        // A missing file ends its stream with an error and the worker carries on
+        let (tx, rx) = channel::bounded(2);
+        outgoing.stream(StreamJob {
+            path: dir.path().join("missing.csv"),
+            id: 4,
+            name: "missing.csv".to_owned(),
+            first_chunk: 0,
+            chunks: tx,
+        });
+        assert!(rx.recv().unwrap().is_err());
+        assert!(outgoing.worker.is_some());
+    }
+
+    #[test]
+    fn unrequested_downloads_are_dropped() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("downloads");
+        let data = test_data(10);
+
+        let mut app = App::new();
+        app.add_event::<FileChunkIn>()
+            .add_event::<FileReceived>()
+            .add_event::<ErrorEvent>()
+            .init_resource::<Reassembler>()
+            .add_systems(Update, receive_chunks);
+
+        let chunk = FileChunk {
+            kind: TransferKind::Download,
+            ..chunk_file(1, "logs/run.csv", &data).next().unwrap()
+        };
+        let received = |app: &mut App| {
+            app.world
+                .resource_mut::<Events<FileReceived>>()
+                .drain()
+                .map(|it| (it.peer, it.path))
+                .collect::<Vec<_>>()
+        };
+
+        // Nobody asked for it
+        app.world
+            .send_event(FileChunkIn(NetToken(1), chunk.clone()));
+        app.update();
+        assert!(received(&mut app).is_empty());
+        assert!(!dir.exists());
+
+        // Asked for from another peer
+        app.world.resource_mut::<Reassembler>().expect_download(
+            NetToken(2),
+            "logs/run.csv".to_owned(),
+            dir.clone(),
+        );
+        app.world
+            .send_event(FileChunkIn(NetToken(1), chunk.clone()));
+        app.update();
+        assert!(received(&mut app).is_empty());
+
+        app.world
+            .send_event(FileChunkIn(NetToken(2), chunk.clone()));
+        app.update();
+        assert_eq!(received(&mut app), [(NetToken(2), dir.join("run.csv"))]);
+        assert_eq!(fs::read(dir.join("run.csv")).unwrap(), data);
+
+        // Finishing it uses up the request
+        let reassembler = app.world.resource::<Reassembler>();
+        assert_eq!(reassembler.download_dir(NetToken(2), "logs/run.csv"), None);
+    }
+}
@@ -8,33 +8,40 @@
 #![allow(clippy::type_complexity)]
 
 use bevy::{
-    app::{Plugin, PluginGroup, PluginGroupBuilder},
-    core::Name,
+    app::{Plugin, PluginGroup, PluginGroupBuilder, Startup},
     ecs::system::Resource,
     prelude::App,
-    transform::components::Transform,
 };
+use clock::ClockOffset;
 use ctrlc::CtrlCPlugin;
 use ecs_sync::{
-    apply_changes::ChangeApplicationPlugin, detect_changes::ChangeDetectionPlugin, AppReplicateExt,
-    NetId, Replicate,
+    apply_changes::ChangeApplicationPlugin, detect_changes::ChangeDetectionPlugin, NetId, Replicate,
 };
 use error::ErrorPlugin;
+use file_transfer::FileTransferPlugin;
 use over_run::OverRunPligin;
-use sync::{Latency, SyncPlugin, SyncRole};
+use shutdown::ShutdownPlugin;
+use sync::{Latency, PeerTickRate, PeerTypeMismatch, SyncPlugin, SyncRole};
+use video_frames::VideoFramePlugin;
 
 pub mod adapters;
 pub mod bundles;
+pub mod cli;
+pub mod clock;
 pub mod components;
 pub mod ctrlc;
 pub mod ecs_sync;
 pub mod error;
 pub mod events;
+pub mod file_transfer;
 pub mod over_run;
 pub mod protocol;
 pub mod reflect;
+pub mod samples;
+pub mod shutdown;
 pub mod sync;
 pub mod types;
+pub mod video_frames;
 
 pub struct CommunicationTypes;
 
@@ -42,14 +49,20 @@ impl Plugin for CommunicationTypes {
     fn build(&self, app: &mut App) {
         types::register_types(app);
         components::register_components(app);
+        bundles::register_bundles(app);
         events::register_events(app);
+        samples::register_samples(app);
 
         app.register_type::<NetId>()
             .register_type::<Replicate>()
-            .register_type::<Latency>();
+            .register_type::<Latency>()
+            .register_type::<PeerTickRate>()
+            .register_type::<PeerTypeMismatch>()
+            .register_type::<ClockOffset>();
         // .register_type::<Peer>();
 
-        app.replicate::<Transform>().replicate_reflect::<Name>();
+        #[cfg(debug_assertions)]
+        app.add_systems(Startup, ecs_sync::round_trip::validate_round_trips);
     }
 }
 
@@ -70,11 +83,14 @@ impl PluginGroup for CommonPlugins {
                 app.insert_resource(InstanceName(name.clone()));
             })
             .add(SyncPlugin(self.role))
+            .add(FileTransferPlugin)
+            .add(VideoFramePlugin)
             .add(CommunicationTypes)
             .add(ChangeDetectionPlugin)
             .add(ChangeApplicationPlugin)
             .add(CtrlCPlugin)
             .add(ErrorPlugin)
             .add(OverRunPligin)
+            .add(ShutdownPlugin)
     }
 }
@@ -10,13 +10,14 @@ pub struct OverRunPligin;
 impl Plugin for OverRunPligin {
     fn build(&self, app: &mut App) {
         app.init_resource::<OverRunSettings>()
+            .init_resource::<OverRunState>()
             .add_systems(First, begin_tick)
             // TODO(low): run before error system
             .add_systems(Last, detect_overrun);
     }
 }
 
-#[derive(Resource)]
+#[derive(Resource, Debug, Clone, PartialEq)]
 pub struct OverRunSettings {
     pub max_time: Duration,
     pub tracy_frame_mark: bool,
@@ -31,6 +32,16 @@ impl Default for OverRunSettings {
     }
 }
 
+/// Whether the last tick exceeded [`OverRunSettings::max_time`]
+#[derive(Resource, Default, Debug)]
+pub struct OverRunState {
+    pub over_running: bool,
+}
+
+pub fn not_over_running(state: Res<OverRunState>) -> bool {
+    !state.over_running
+}
+
 #[derive(Resource)]
 pub struct TickStart(Instant);
 
@@ -43,12 +54,14 @@ const TOLERANCE: Duration = Duration::from_micros(300);
 fn detect_overrun(
     settings: Res<OverRunSettings>,
     start: Option<Res<TickStart>>,
+    mut state: ResMut<OverRunState>,
     mut errors: EventWriter<ErrorEvent>,
 ) {
     if let Some(start) = start {
         let frame_time = start.0.elapsed();
 
-        if frame_time > settings.max_time + TOLERANCE {
+        state.over_running = frame_time > settings.max_time + TOLERANCE;
+        if state.over_running {
             errors.send(
                 anyhow!(
                     "Max loop time over run. Last tick took {:.4}, exceeding limit of {:.4}",
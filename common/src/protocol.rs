@@ -1,11 +1,30 @@
 //! Repersents the protocol used for two way communication
 
-use anyhow::Context;
+use std::time::Duration;
+
+use anyhow::{bail, Context};
 use bincode::{DefaultOptions, Options};
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
-use crate::ecs_sync::SerializedChange;
+use crate::{
+    ecs_sync::{NetTypeId, SerializedChange},
+    file_transfer::{TransferKind, CHUNK_SIZE},
+    types::ids::CameraId,
+    video_frames::MAX_VIDEO_FRAME_LEN,
+};
+
+/// Longest type or file name accepted from a peer
+pub const MAX_NAME_LEN: usize = 256;
+/// Largest serialized component or event accepted from a peer
+pub const MAX_PAYLOAD_LEN: usize = 1024 * 1024;
+/// Most peers a [`Protocol::ProbeReply`] may list
+pub const MAX_PROBE_PEERS: usize = 64;
+/// Most types a [`Protocol::TypeManifest`] may list
+pub const MAX_MANIFEST_TYPES: usize = 4096;
+
+/// Sent in [`Protocol::Hello`], peers on another version can not understand each other
+pub const PROTOCOL_VERSION: u32 = 3;
 
 /// Representation of all messages that can be communicated between peers
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -14,11 +33,145 @@ pub enum Protocol {
     /// Asks the peer to reply with a Pong, used to measure communication latency
     Ping {
         payload: u32,
+        /// The sender's recent average time between frames
+        frame_time: Duration,
     },
     /// Response to a Ping, used to measure communication latency
     Pong {
         payload: u32,
+        /// The responder's [`crate::clock::monotonic_seconds`] when it replied
+        time: f64,
+    },
+    /// Sent once on connecting so the peer knows what to call us
+    Hello {
+        /// The sender's [`crate::InstanceName`]
+        name: String,
+        /// The sender's [`PROTOCOL_VERSION`]
+        version: u32,
+    },
+    /// Sent before an orderly shutdown so the peer does not treat the disconnect as an error
+    Goodbye,
+    /// One piece of a file, chunks of a transfer are sent in order
+    FileTransfer {
+        id: u32,
+        kind: TransferKind,
+        name: String,
+        chunk_index: u32,
+        total: u32,
+        data: Vec<u8>,
+    },
+    /// Asks whether anyone else is connected, sent by surfaces diagnosing a connection
+    Probe,
+    /// Response to a Probe
+    ProbeReply {
+        /// Names of the responder's other peers
+        peers: Vec<String>,
+    },
+    /// Sent once on connecting, every type the sender replicates
+    TypeManifest {
+        types: Vec<NetTypeId>,
+    },
+    /// The server moved its listener, the connection stays up but reconnects go to the new port
+    Rebinding {
+        new_port: u16,
     },
+    /// A JPEG frame from a camera, for when its UDP stream can't get through
+    VideoFrame {
+        camera_id: CameraId,
+        seq: u32,
+        jpeg: Vec<u8>,
+    },
+    /// Sent for every VideoFrame as it arrives, the sender holds back while frames are unacked
+    VideoFrameAck {
+        camera_id: CameraId,
+        seq: u32,
+    },
+}
+
+impl Protocol {
+    /// Rejects packets with fields larger than a well behaved peer would send
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            Protocol::EcsUpdate(change) => match change {
+                SerializedChange::ComponentUpdated(_, token, data) => {
+                    check_name(token)?;
+
+                    if let Some(data) = data {
+                        check_payload(data)?;
+                    }
+                }
+                SerializedChange::EventEmitted(token, data) => {
+                    check_name(token)?;
+                    check_payload(data)?;
+                }
+                SerializedChange::TypeQuarantined(token, _) => {
+                    check_name(token)?;
+                }
+                SerializedChange::EntitySpawned(_)
+                | SerializedChange::EntityDespawned(_)
+                | SerializedChange::OwnershipTransferred(_, _) => {}
+            },
+            Protocol::FileTransfer { name, data, .. } => {
+                check_name(name)?;
+
+                if data.len() > CHUNK_SIZE {
+                    bail!("File chunk of {} bytes is too large", data.len());
+                }
+            }
+            Protocol::Hello { name, .. } => check_name(name)?,
+            Protocol::VideoFrame {
+                camera_id, jpeg, ..
+            } => {
+                check_name(camera_id.as_str())?;
+
+                if jpeg.len() > MAX_VIDEO_FRAME_LEN {
+                    bail!("Video frame of {} bytes is too large", jpeg.len());
+                }
+            }
+            Protocol::VideoFrameAck { camera_id, .. } => check_name(camera_id.as_str())?,
+            Protocol::ProbeReply { peers } => {
+                if peers.len() > MAX_PROBE_PEERS {
+                    bail!("Probe reply listing {} peers is too long", peers.len());
+                }
+
+                for name in peers {
+                    check_name(name)?;
+                }
+            }
+            Protocol::TypeManifest { types } => {
+                if types.len() > MAX_MANIFEST_TYPES {
+                    bail!("Type manifest listing {} types is too long", types.len());
+                }
+
+                for token in types {
+                    check_name(token)?;
+                }
+            }
+            Protocol::Ping { .. }
+            | Protocol::Pong { .. }
+            | Protocol::Goodbye
+            | Protocol::Probe
+            | Protocol::Rebinding { .. } => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn check_name(name: &str) -> anyhow::Result<()> {
+    if name.len() > MAX_NAME_LEN {
+        bail!("Name of {} bytes is too long", name.len());
+    }
+
+    Ok(())
+}
+
+fn check_payload(data: &[u8]) -> anyhow::Result<()> {
+    if data.len() > MAX_PAYLOAD_LEN {
+        bail!("Payload of {} bytes is too large", data.len());
+    }
+
+    Ok(())
 }
 
 impl networking::Packet for Protocol {
@@ -38,9 +191,15 @@ impl networking::Packet for Protocol {
 
     #[instrument(level = "trace", skip(buffer), ret)]
     fn read_buf(buffer: &mut &[u8]) -> anyhow::Result<Self> {
-        options()
+        // Length prefixes are checked against the bytes actually available before allocating
+        let packet: Protocol = options()
+            .with_limit(buffer.len() as u64)
             .deserialize_from(buffer)
-            .context("Could not deserialize packet")
+            .context("Could not deserialize packet")?;
+
+        packet.validate().context("Invalid packet")?;
+
+        Ok(packet)
     }
 }
 
@@ -0,0 +1,236 @@
+//! Sample values of replicated types without a [`Default`], see [`crate::ecs_sync::round_trip`]
+//!
+//! Fields are set away from their zero values where possible so a field the adapter drops shows
+//! up as a mismatch.
+
+use std::time::Duration;
+
+use bevy::app::App;
+use glam::{vec3a, Quat};
+use motor_math::{solve::reverse::Axis, x3d::X3dMotorId, Direction, Motor, MotorConfig, Movement};
+
+use crate::{
+    components::{
+        ActualForce, ActualMovement, AuthorityLimit, BuildInfo, Camera, CameraFallbackStream,
+        CameraIntrinsics, CpuTotal, CrashReport, CurrentDraw, Depth, DepthSettings, DepthTarget,
+        Inertial, JerkLimit, LedBrightness, LoadAverage, Magnetic, MeasuredVoltage, Memory,
+        MotorDataSource, MotorDataStatus, MotorDefinition, Motors, MovementAxisMaximums,
+        MovementCurrentCap, OperatingSystem, Orientation, OrientationTarget, PidResult,
+        PreviousCrashReport, PwmChannel, PwmSignal, RobotId, ServoDefinition, ServoMode, Servos,
+        TargetForce, TargetMovement, Uptime,
+    },
+    ecs_sync::{scoped::Scoped, AppReplicateExt, NetId},
+    events::{
+        AckedCommand, CaptureStill, CommandComplete, LogLevel, RemoteLogRecord, ServoCommand,
+        SetChecklistItem, StartBehavior,
+    },
+    types::{
+        checklist::ChecklistItem,
+        hw::{DepthFrame, InertialFrame, MagneticFrame},
+        ids::{CameraId, ServoId},
+        system::Cpu,
+        units::{Amperes, Celsius, Dps, GForce, Gauss, Mbar, Meters, Newtons, Volts},
+    },
+};
+
+pub fn register_samples(app: &mut App) {
+    register_sensor_samples(app);
+    register_system_samples(app);
+    register_actuator_samples(app);
+    register_event_samples(app);
+
+    app.replicate_sample(|| RobotId(NetId::random()))
+        .replicate_sample(|| Camera {
+            id: CameraId::from_static("Front"),
+            location: "10.0.0.2:5600".parse().unwrap(),
+        })
+        .replicate_sample(|| CameraIntrinsics {
+            resolution: (1920, 1080),
+            fx: 1400.5,
+            fy: 1398.25,
+            cx: 960.5,
+            cy: 540.25,
+            distortion: [-0.3, 0.1, 0.001, -0.002, 0.05],
+        })
+        .replicate_sample(|| CameraFallbackStream {
+            camera: CameraId::from_static("Front"),
+            fps: 5,
+            quality: 60,
+        });
+}
+
+fn register_sensor_samples(app: &mut App) {
+    app.replicate_sample(|| {
+        Inertial(InertialFrame {
+            gyro_x: Dps(1.5),
+            gyro_y: Dps(-2.5),
+            gyro_z: Dps(0.25),
+            accel_x: GForce(0.1),
+            accel_y: GForce(-0.2),
+            accel_z: GForce(1.0),
+            tempature: Celsius(24.5),
+            capture_time: 1234.5625,
+        })
+    })
+    .replicate_sample(|| {
+        Magnetic(MagneticFrame {
+            mag_x: Gauss(0.2),
+            mag_y: Gauss(-0.4),
+            mag_z: Gauss(0.6),
+            capture_time: 1234.5,
+        })
+    })
+    .replicate_sample(|| {
+        Depth(DepthFrame {
+            depth: Meters(2.5),
+            altitude: Meters(-1.0),
+            pressure: Mbar(1260.5),
+            temperature: Celsius(12.5),
+            capture_time: 1234.75,
+        })
+    })
+    .replicate_sample(|| DepthTarget(Meters(1.5)))
+    .replicate_sample(|| DepthSettings {
+        sea_level: Mbar(1013.25),
+        fluid_density: 997.0,
+    })
+    .replicate_sample(|| Orientation {
+        rotation: Quat::from_rotation_x(0.2),
+        capture_time: 1234.5625,
+    })
+    .replicate_sample(|| OrientationTarget(Quat::from_rotation_x(0.3)));
+}
+
+fn register_system_samples(app: &mut App) {
+    app.replicate_sample(|| LoadAverage {
+        one_min: 1.5,
+        five_min: 0.75,
+        fifteen_min: 0.25,
+    })
+    .replicate_sample(|| {
+        CpuTotal(Cpu {
+            frequency: 1800,
+            usage: 42.5,
+            name: "cpu".to_owned(),
+        })
+    })
+    .replicate_sample(|| Memory {
+        total_mem: 8 << 30,
+        used_mem: 3 << 30,
+        free_mem: 5 << 30,
+        total_swap: 1 << 30,
+        used_swap: 1 << 20,
+        free_swap: (1 << 30) - (1 << 20),
+    })
+    .replicate_sample(|| Uptime(Duration::from_millis(5_400_250)))
+    .replicate_sample(|| OperatingSystem {
+        name: Some("Linux".to_owned()),
+        kernel_version: Some("6.1.21".to_owned()),
+        os_version: None,
+        distro: Some("Raspbian".to_owned()),
+        host_name: Some("rov".to_owned()),
+    })
+    .replicate_sample(|| {
+        PreviousCrashReport(CrashReport {
+            message: "panicked at src/main.rs:1:1".to_owned(),
+            backtrace: "0: main".to_owned(),
+            timestamp: 1_700_000_000,
+            build: BuildInfo {
+                git_hash: "abc1234-dirty".to_owned(),
+                build_timestamp: "2024-06-01 12:00:00".to_owned(),
+                profile: "release".to_owned(),
+            },
+            uptime: Duration::from_secs(90),
+        })
+    })
+    .replicate_sample(|| MotorDataStatus {
+        source: MotorDataSource::FileOk("motor_data.csv".to_owned()),
+        error: Some("Skipped row 3".to_owned()),
+    });
+}
+
+fn register_actuator_samples(app: &mut App) {
+    let motor = Motor {
+        position: vec3a(0.2, -0.15, 0.05),
+        orientation: vec3a(0.0, 0.6, 0.8),
+        direction: Direction::CounterClockwise,
+    };
+    let movement = Movement {
+        force: vec3a(10.0, -5.0, 2.5),
+        torque: vec3a(0.5, 0.0, -1.5),
+    };
+
+    app.replicate_sample(move || MotorDefinition(3, motor))
+        .replicate_sample(move || {
+            Motors(MotorConfig::<X3dMotorId>::new(motor, vec3a(0.0, 0.0, -0.05)).erase())
+        })
+        .replicate_sample(move || TargetMovement(movement))
+        .replicate_sample(move || ActualMovement(movement))
+        .replicate_sample(|| {
+            MovementAxisMaximums([(Axis::X, Newtons(40.0)), (Axis::ZRot, Newtons(8.5))].into())
+        })
+        .replicate_sample(|| MovementCurrentCap(Amperes(20.0)))
+        .replicate_sample(|| TargetForce(Newtons(12.5)))
+        .replicate_sample(|| ActualForce(Newtons(-3.25)))
+        .replicate_sample(|| CurrentDraw(Amperes(3.5)))
+        .replicate_sample(|| MeasuredVoltage(Volts(14.8)))
+        .replicate_sample(|| JerkLimit(0.5))
+        .replicate_sample(|| ServoDefinition {
+            cameras: vec![CameraId::from_static("Claw")],
+        })
+        .replicate_sample(|| Servos {
+            servos: vec![ServoId::from_static("Claw1"), ServoId::from_static("Tilt")],
+        })
+        .replicate_sample(|| ServoMode::Velocity)
+        .replicate_sample(|| PwmChannel(7))
+        .replicate_sample(|| PwmSignal(Duration::from_micros(1650)))
+        .replicate_sample(|| PidResult {
+            p: 1.5,
+            i: -0.25,
+            d: 0.125,
+            td: -2.0,
+            correction: 3.0,
+        })
+        .replicate_sample(|| AuthorityLimit(Newtons(30.0)))
+        .replicate_sample(|| LedBrightness(0.4));
+}
+
+fn register_event_samples(app: &mut App) {
+    app.replicate_sample(|| CaptureStill {
+        camera: "Front".to_owned(),
+    })
+    .replicate_sample(|| CommandComplete {
+        robot: RobotId(NetId::random()),
+        command: AckedCommand::ResetYaw,
+        ok: false,
+        detail: "No IMU".to_owned(),
+    })
+    .replicate_sample(|| RemoteLogRecord {
+        level: LogLevel::Warn,
+        target: "robot::plugins".to_owned(),
+        message: "Leak detected".to_owned(),
+        timestamp: Duration::from_millis(1_700_000_000_250),
+        dropped: 3,
+    })
+    .replicate_sample(|| ServoCommand {
+        servo: ServoId::from_static("Claw1"),
+        target: -0.5,
+    })
+    .replicate_sample(set_checklist_item)
+    .replicate_sample(|| Scoped::new(RobotId(NetId::random()), set_checklist_item()))
+    .replicate_sample(|| StartBehavior {
+        name: "Hover".to_owned(),
+        params: serde_json::json!({ "depth": 1.5, "heading": null }),
+    });
+}
+
+fn set_checklist_item() -> SetChecklistItem {
+    SetChecklistItem {
+        item: ChecklistItem {
+            task: 2,
+            step: Some(1),
+        },
+        checked: true,
+        timestamp: Duration::from_millis(1_700_000_000_500),
+    }
+}
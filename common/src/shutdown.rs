@@ -0,0 +1,160 @@
+//! Orderly shutdown
+//!
+//! Shutdown work runs in [`Last`] on the frame an [`AppExit`](bevy::app::AppExit) is sent, the
+//! runner exits right after. Systems doing shutdown work belong in one of the
+//! [`AppShutdownSet`]s so the robot is made safe before peers are told we left, and peers are
+//! told before the threads they depend on are stopped.
+
+use std::{
+    io,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use crossbeam::channel::{self, Receiver, RecvTimeoutError};
+
+pub struct ShutdownPlugin;
+
+impl Plugin for ShutdownPlugin {
+    fn build(&self, app: &mut App) {
+        app.configure_sets(
+            Last,
+            (
+                AppShutdownSet::Actuators,
+                AppShutdownSet::Network,
+                AppShutdownSet::Threads,
+            )
+                .chain(),
+        );
+    }
+}
+
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppShutdownSet {
+    /// Disarm and bring actuators to a safe state
+    Actuators,
+    /// Say goodbye to peers and flush pending writes
+    Network,
+    /// Stop worker threads
+    Threads,
+}
+
+/// A thread that can be waited on with a timeout, see [`SpawnWorkerExt`]
+pub struct WorkerThread<T> {
+    handle: JoinHandle<T>,
+    /// Disconnects once the thread's closure returns or unwinds
+    done: Receiver<()>,
+}
+
+pub trait SpawnWorkerExt {
+    /// Spawns a thread that [`join_timeout`] can wait on
+    fn spawn_worker<T, F>(self, f: F) -> io::Result<WorkerThread<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static;
+}
+
+impl SpawnWorkerExt for thread::Builder {
+    fn spawn_worker<T, F>(self, f: F) -> io::Result<WorkerThread<T>>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (tx_done, rx_done) = channel::bounded::<()>(0);
+
+        let handle = self.spawn(move || {
+            let _done = tx_done;
+
+            f()
+        })?;
+
+        Ok(WorkerThread {
+            handle,
+            done: rx_done,
+        })
+    }
+}
+
+/// How long shutdown waits on a worker thread unless it needs longer
+pub const JOIN_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Waits for a thread to exit, returns false if it did not within `timeout`
+pub fn join_timeout<T>(thread: WorkerThread<T>, timeout: Duration) -> bool {
+    if let Err(RecvTimeoutError::Timeout) = thread.done.recv_timeout(timeout) {
+        return false;
+    }
+
+    let _ = thread.handle.join();
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use bevy::{app::AppExit, prelude::*};
+
+    use super::{join_timeout, AppShutdownSet, ShutdownPlugin, SpawnWorkerExt};
+
+    #[derive(Resource, Default)]
+    struct Order(Vec<AppShutdownSet>);
+
+    fn record(set: AppShutdownSet) -> impl Fn(EventReader<AppExit>, ResMut<Order>) {
+        move |mut exit: EventReader<AppExit>, mut order: ResMut<Order>| {
+            for _event in exit.read() {
+                order.0.push(set);
+            }
+        }
+    }
+
+    #[test]
+    fn shutdown_sets_run_in_order() {
+        let mut app = App::new();
+
+        // Registered backwards to make sure the order comes from the sets
+        app.add_plugins(ShutdownPlugin)
+            .init_resource::<Order>()
+            .add_systems(
+                Last,
+                (
+                    record(AppShutdownSet::Threads).in_set(AppShutdownSet::Threads),
+                    record(AppShutdownSet::Network).in_set(AppShutdownSet::Network),
+                    record(AppShutdownSet::Actuators).in_set(AppShutdownSet::Actuators),
+                ),
+            );
+
+        app.update();
+        assert!(app.world.resource::<Order>().0.is_empty());
+
+        app.world.send_event(AppExit);
+        app.update();
+
+        assert_eq!(
+            app.world.resource::<Order>().0,
+            [
+                AppShutdownSet::Actuators,
+                AppShutdownSet::Network,
+                AppShutdownSet::Threads
+            ]
+        );
+    }
+
+    #[test]
+    fn join_times_out() {
+        let quick = thread::Builder::new().spawn_worker(|| {}).unwrap();
+        assert!(join_timeout(quick, Duration::from_secs(1)));
+
+        let slow = thread::Builder::new()
+            .spawn_worker(|| thread::sleep(Duration::from_millis(500)))
+            .unwrap();
+        assert!(!join_timeout(slow, Duration::from_millis(10)));
+
+        // Panicking counts as exiting
+        let panicked = thread::Builder::new()
+            .spawn_worker(|| panic!("Worker failed"))
+            .unwrap();
+        assert!(join_timeout(panicked, Duration::from_secs(1)));
+    }
+}
@@ -1,17 +1,27 @@
 use std::{
     net::{Ipv4Addr, SocketAddr, ToSocketAddrs},
+    path::PathBuf,
     thread,
+    time::Duration,
 };
 
 use crate::{
     adapters,
+    clock::{self, ClockOffsetEstimator},
     components::Singleton,
     ecs_sync::{
-        apply_changes::ChangeApplicationSet, detect_changes::ChangeDetectionSet, EntityMap,
-        ForignOwned, NetId, NetTypeId, SerializationSettings, SerializedChange,
-        SerializedChangeInEvent, SerializedChangeOutEvent,
+        apply_changes::{ChangeApplicationSet, PendingChanges},
+        coalesce::coalesce,
+        detect_changes::ChangeDetectionSet,
+        quarantine::QuarantinedTypes,
+        stats::{self, SyncStats, SyncStatsSettings},
+        EntityMap, ForignOwned, NetId, NetTypeId, SerializationSettings, SerializedChange,
+        SerializedChangeInEvent, SerializedChangeOutEvent, SerializedChangeTargetedOutEvent,
     },
-    protocol::Protocol,
+    file_transfer::{FileChunk, FileChunkIn},
+    protocol::{Protocol, MAX_PROBE_PEERS, PROTOCOL_VERSION},
+    shutdown::{self, AppShutdownSet, SpawnWorkerExt, WorkerThread},
+    video_frames::{VideoFrameAckIn, VideoFrameIn},
     InstanceName,
 };
 use ahash::{HashMap, HashSet};
@@ -19,17 +29,35 @@ use anyhow::{anyhow, Context};
 use bevy::{app::AppExit, core::FrameCount, prelude::*};
 use crossbeam::channel::{self, Receiver};
 use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
-use networking::{Event as NetEvent, Messenger, Networking, Token as NetToken};
+use networking::{
+    error::{ErrorClass, NetError},
+    Event as NetEvent, Messenger, Networking, NetworkingOptions, PeerAddr, PresharedKey,
+    SocketTuning, TcpOptions, Token as NetToken,
+};
 
 use crate::error::{self, ErrorEvent, Errors};
 
 const SERVICE_TYPE: &str = "_bevy_ecs_sync._tcp.local.";
+/// How long shutdown waits for pending writes to reach peers
+const FLUSH_TIMEOUT: Duration = Duration::from_millis(500);
+/// Assumed time between frames until one has been measured
+pub const NOMINAL_FRAME_TIME: Duration = Duration::from_millis(10);
+/// Weight of the newest frame in [`LocalFrameTime`]
+const FRAME_TIME_SMOOTHING: f32 = 0.1;
+/// Peers taking longer than this between frames are reported as over running
+pub const SLOW_PEER_FRAME_TIME: Duration = Duration::from_millis(50);
+/// Wait before the first reconnect to a lost peer, doubled after every failed attempt
+const RECONNECT_MIN_BACKOFF: Duration = Duration::from_millis(500);
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(10);
 
 pub struct SyncPlugin(pub SyncRole);
 
 #[derive(Resource, Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum SyncRole {
-    Server { port: u16 },
+    /// Without a port connections are only accepted on the [`LocalSocket`]
+    Server {
+        port: Option<u16>,
+    },
     Client,
 }
 
@@ -37,15 +65,26 @@ impl Plugin for SyncPlugin {
     fn build(&self, app: &mut App) {
         app.add_event::<SerializedChangeInEvent>()
             .add_event::<SerializedChangeOutEvent>()
+            .add_event::<SerializedChangeTargetedOutEvent>()
             .init_resource::<SerializationSettings>()
             .init_resource::<EntityMap>()
             .init_resource::<Deltas>()
             .init_resource::<Peers>()
+            .init_resource::<QuarantinedTypes>()
+            .init_resource::<SyncStats>()
+            .init_resource::<SyncStatsSettings>()
+            .init_resource::<LocalFrameTime>()
+            .init_resource::<Reconnects>()
+            .init_resource::<PeerSettings>()
+            .init_resource::<Listeners>()
             .insert_resource(self.0)
             .add_event::<ConnectToPeer>()
             .add_event::<DisconnectPeer>()
             .add_event::<SyncPeer>()
+            .add_event::<NetErrorReport>()
+            .add_event::<RebindListener>()
             .add_systems(Startup, setup_networking.pipe(error::handle_errors))
+            .add_systems(First, measure_frame_time)
             .add_systems(PreUpdate, net_read.before(ChangeApplicationSet))
             .add_systems(
                 Update,
@@ -57,14 +96,28 @@ impl Plugin for SyncPlugin {
                     disconnect.pipe(error::handle_errors),
                 ),
             )
-            .add_systems(PostUpdate, net_write.after(ChangeDetectionSet))
-            .add_systems(Last, shutdown);
+            .add_systems(
+                PostUpdate,
+                (
+                    net_write.after(ChangeDetectionSet),
+                    stats::roll_sync_stats.after(net_write),
+                ),
+            )
+            .add_systems(Last, shutdown.in_set(AppShutdownSet::Network));
+
+        #[cfg(feature = "sync_instrumentation")]
+        app.add_plugins(crate::ecs_sync::diagnostics::SyncDiagnosticsPlugin);
+
+        if let SyncRole::Server { .. } = self.0 {
+            app.add_systems(Update, rebind_listener.pipe(error::handle_errors));
+        }
 
         if let SyncRole::Client = self.0 {
             app.add_systems(
                 Update,
                 (
                     connect.pipe(error::handle_errors),
+                    reconnect.pipe(error::handle_errors),
                     discover_peers.run_if(resource_exists::<MdnsBrowse>),
                 ),
             );
@@ -73,32 +126,225 @@ impl Plugin for SyncPlugin {
 }
 
 #[derive(Resource)]
-struct Net(Messenger<Protocol>, Receiver<NetEvent<Protocol>>);
+pub(crate) struct Net(
+    pub(crate) Messenger<Protocol>,
+    Receiver<NetEvent<Protocol>>,
+    Option<WorkerThread<()>>,
+);
 
 #[derive(Resource, Default)]
 pub struct Peers {
     by_token: HashMap<NetToken, Entity>,
-    by_addrs: HashMap<SocketAddr, Entity>,
+    by_addrs: HashMap<PeerAddr, Entity>,
 
     // In frames
-    pending: HashMap<NetToken, (SocketAddr, u32)>,
+    pending: HashMap<NetToken, (PeerAddr, u32)>,
 
     // TODO: This is kinda bad
     pub(crate) valid_tokens: HashSet<NetToken>,
+    /// Peers that said goodbye, their disconnect is expected
+    leaving: HashSet<NetToken>,
+    /// Instance names peers sent in their `Hello`
+    names: HashMap<NetToken, String>,
+    /// Socket options TCP peers ended up with
+    tuning: HashMap<NetToken, SocketTuning>,
+    /// How the types peers sent in their `TypeManifest` differ from ours
+    mismatches: HashMap<NetToken, PeerTypeMismatch>,
+}
+
+impl Peers {
+    /// Remembers what `token` calls itself, returns its entity if it already has one
+    fn hello(&mut self, token: NetToken, name: String) -> Option<Entity> {
+        self.names.insert(token, name);
+        self.by_token.get(&token).copied()
+    }
+
+    /// Remembers which types `token` registered differently, returns its entity if it has one
+    fn manifest(&mut self, token: NetToken, mismatch: PeerTypeMismatch) -> Option<Entity> {
+        self.mismatches.insert(token, mismatch);
+        self.by_token.get(&token).copied()
+    }
+
+    /// Whether `token` has sent its `TypeManifest` yet
+    fn has_manifest(&self, token: NetToken) -> bool {
+        self.mismatches.contains_key(&token)
+    }
+
+    /// Remembers the socket options `token` ended up with, returns its entity if it has one
+    fn tuned(&mut self, token: NetToken, tuning: SocketTuning) -> Option<Entity> {
+        self.tuning.insert(token, tuning);
+        self.by_token.get(&token).copied()
+    }
+
+    /// The socket options a TCP peer's connection ended up with
+    pub fn tuning(&self, token: NetToken) -> Option<&SocketTuning> {
+        self.tuning.get(&token)
+    }
+
+    /// The peer's name if it sent one, otherwise its token
+    pub fn describe(&self, token: NetToken) -> String {
+        match self.names.get(&token) {
+            Some(name) => format!("{name} ({token:?})"),
+            None => format!("{token:?}"),
+        }
+    }
 }
 
 #[derive(Component, Debug)]
 pub struct Peer {
-    pub addrs: SocketAddr,
+    pub addrs: PeerAddr,
     pub token: NetToken,
 }
 
-#[derive(Component, Debug, Default, Reflect)]
+impl Peer {
+    /// The peer's name with its address as detail, just the address before it said who it is
+    pub fn describe(&self, name: Option<&Name>) -> String {
+        match name {
+            Some(name) => format!("{name} ({})", self.addrs),
+            None => self.addrs.to_string(),
+        }
+    }
+}
+
+/// The socket options a TCP peer's connection ended up with, for checking the link is tuned
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct PeerSocketTuning(pub SocketTuning);
+
+/// Replicated types only one side of a connection registered
+///
+/// Types the peer doesn't know are not sent to it, it does the same for the ones we don't know
+#[derive(Component, Debug, Clone, Default, PartialEq, Eq, Reflect)]
+pub struct PeerTypeMismatch {
+    /// Registered here but not by the peer
+    pub missing_remotely: Vec<NetTypeId>,
+    /// Registered by the peer but not here
+    pub missing_locally: Vec<NetTypeId>,
+}
+
+impl PeerTypeMismatch {
+    pub fn compare(local: &[NetTypeId], remote: &[NetTypeId]) -> Self {
+        let missing = |from: &[NetTypeId], other: &[NetTypeId]| {
+            let other = other.iter().collect::<HashSet<_>>();
+            from.iter()
+                .filter(|it| !other.contains(it))
+                .cloned()
+                .collect()
+        };
+
+        Self {
+            missing_remotely: missing(local, remote),
+            missing_locally: missing(remote, local),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.missing_remotely.is_empty() && self.missing_locally.is_empty()
+    }
+}
+
+/// Compares a peer's manifest with our own registrations and stops sending it types it lacks
+pub(crate) fn receive_manifest(
+    settings: &SerializationSettings,
+    quarantine: &mut QuarantinedTypes,
+    token: NetToken,
+    types: &[NetTypeId],
+) -> PeerTypeMismatch {
+    let mismatch = PeerTypeMismatch::compare(&settings.type_manifest(), types);
+
+    for missing in &mismatch.missing_remotely {
+        quarantine.set_suppressed(missing.clone(), token, true);
+    }
+
+    mismatch
+}
+
+/// Stands in for a peer whose `Singleton` missed [`PeerSettings::singleton_deadline`], replaced
+/// by the singleton if it shows up later
+#[derive(Component, Debug)]
+pub struct FallbackPeer;
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct PeerSettings {
+    /// How long a new peer has to replicate its `Singleton` before it gets an entity without one
+    pub singleton_deadline: Duration,
+}
+
+impl Default for PeerSettings {
+    fn default() -> Self {
+        Self {
+            singleton_deadline: SINGLETON_DEADLINE,
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, Default, Reflect)]
 pub struct Latency {
     // In frames
     pub last_ping_sent: Option<u32>,
     pub last_acknowledged: Option<u32>,
     pub ping: Option<u32>,
+
+    /// [`clock::monotonic_seconds`] when the last ping was sent
+    pub last_ping_time: Option<f64>,
+    #[reflect(ignore)]
+    pub clock: ClockOffsetEstimator,
+}
+
+/// How long the peer reported taking between its frames, updated with every ping it sends
+#[derive(Component, Debug, Clone, Copy, PartialEq, Reflect)]
+pub struct PeerTickRate {
+    pub frame_time: Duration,
+}
+
+impl PeerTickRate {
+    pub fn is_slow(&self) -> bool {
+        self.frame_time > SLOW_PEER_FRAME_TIME
+    }
+}
+
+/// Smoothed wall clock time between our own frames
+///
+/// Deadlines are durations, this converts them to the frame counts they are tracked in
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct LocalFrameTime(pub Duration);
+
+impl Default for LocalFrameTime {
+    fn default() -> Self {
+        Self(NOMINAL_FRAME_TIME)
+    }
+}
+
+impl LocalFrameTime {
+    pub fn record(&mut self, delta: Duration) {
+        // The first frame has no delta
+        if delta.is_zero() {
+            return;
+        }
+
+        self.0 = self.0.mul_f32(1.0 - FRAME_TIME_SMOOTHING) + delta.mul_f32(FRAME_TIME_SMOOTHING);
+    }
+
+    /// Frames it takes for `duration` to pass at the current frame time, at least one
+    pub fn frames(&self, duration: Duration) -> u32 {
+        frames_for(duration, self.0)
+    }
+
+    /// Whether more than `deadline` has passed between frames `since` and `now`
+    pub fn expired(&self, now: u32, since: u32, deadline: Duration) -> bool {
+        now.wrapping_sub(since) > self.frames(deadline)
+    }
+}
+
+/// Frames it takes for `duration` to pass when each frame takes `frame_time`, at least one
+pub fn frames_for(duration: Duration, frame_time: Duration) -> u32 {
+    let frames = (duration.as_secs_f64() / frame_time.as_secs_f64()).ceil();
+
+    // Saturates, a zero frame time never expires anything
+    (frames as u32).max(1)
+}
+
+fn measure_frame_time(mut frame_time: ResMut<LocalFrameTime>, time: Res<Time<Real>>) {
+    frame_time.record(time.delta());
 }
 
 #[derive(Resource)]
@@ -115,8 +361,31 @@ pub struct DiscoveredPeer {
     pub addresses: Vec<SocketAddr>,
 }
 
+/// Also accept connections on this unix domain socket
+///
+/// Lets the robot and surface talk without going through TCP when run on the same machine
+#[derive(Resource, Debug, Clone)]
+pub struct LocalSocket(pub PathBuf);
+
+pub const DEFAULT_LOCAL_SOCKET: &str = "/tmp/mate-rov.sock";
+
+/// Encrypts and authenticates the link, the robot and surface must be given the same key
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkKey(pub PresharedKey);
+
+/// Socket options for TCP connections, [`TcpOptions::default`] when not inserted
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinkTcpOptions(pub TcpOptions);
+
+/// Use this instance instead of opening sockets, see [`Networking::loopback_pair`]
+///
+/// Taken when networking starts. The instance's peers are used as is, nothing is bound and mdns
+/// isn't started.
+#[derive(Resource)]
+pub struct SyncTransport(pub Option<Networking<Protocol>>);
+
 #[derive(Event)]
-pub struct ConnectToPeer(pub SocketAddr);
+pub struct ConnectToPeer(pub PeerAddr);
 
 #[derive(Event)]
 pub struct DisconnectPeer(pub NetToken);
@@ -124,27 +393,254 @@ pub struct DisconnectPeer(pub NetToken);
 #[derive(Event)]
 pub struct SyncPeer(pub NetToken);
 
+/// Moves the server's TCP listener to this port without dropping connected peers
+///
+/// The old listener is only closed once the new one is accepting connections, peers are then
+/// told about the new port with [`Protocol::Rebinding`]
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RebindListener(pub u16);
+
+/// The sockets accepting connections, and the listener a [`RebindListener`] is waiting on
+#[derive(Resource, Debug, Default)]
+pub struct Listeners {
+    bound: HashMap<NetToken, PeerAddr>,
+    rebinding: Option<SocketAddr>,
+    /// The new listener's token, or `None` if it could not bind
+    outcome: Option<Option<NetToken>>,
+}
+
+impl Listeners {
+    fn bound(&mut self, token: NetToken, addrs: PeerAddr) {
+        if matches!(addrs, PeerAddr::Tcp(addr) if self.rebinding == Some(addr)) {
+            self.outcome = Some(Some(token));
+        }
+
+        self.bound.insert(token, addrs);
+    }
+
+    fn bind_failed(&mut self, addrs: &PeerAddr) {
+        if matches!(addrs, PeerAddr::Tcp(addr) if self.rebinding == Some(*addr)) {
+            self.outcome = Some(None);
+        }
+    }
+
+    /// Forgets a closed listener, returns whether `token` was one
+    fn closed(&mut self, token: NetToken) -> bool {
+        self.bound.remove(&token).is_some()
+    }
+
+    fn start_rebind(&mut self, addr: SocketAddr) -> Result<(), String> {
+        if let Some(pending) = self.rebinding {
+            return Err(format!("Already moving the listener to {pending}"));
+        }
+
+        if self.tcp().any(|(_, it)| it == addr) {
+            return Err(format!("Already listening at {addr}"));
+        }
+
+        self.rebinding = Some(addr);
+
+        Ok(())
+    }
+
+    /// The address of a finished rebind and the listeners it replaces, or the address that could
+    /// not be bound
+    fn finish_rebind(&mut self) -> Option<Result<(SocketAddr, Vec<NetToken>), SocketAddr>> {
+        let outcome = self.outcome.take()?;
+        let addr = self.rebinding.take()?;
+
+        let Some(token) = outcome else {
+            return Some(Err(addr));
+        };
+
+        let replaced = self
+            .tcp()
+            .filter(|(it, _)| *it != token)
+            .map(|(it, _)| it)
+            .collect();
+
+        Some(Ok((addr, replaced)))
+    }
+
+    /// TCP listeners by token
+    pub fn tcp(&self) -> impl Iterator<Item = (NetToken, SocketAddr)> + '_ {
+        self.bound.iter().filter_map(|(token, addrs)| match addrs {
+            PeerAddr::Tcp(addr) => Some((*token, *addr)),
+            _ => None,
+        })
+    }
+}
+
+/// A network error for the operator, sent alongside the [`ErrorEvent`]
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct NetErrorReport {
+    /// Description of the peer it happened on, if any
+    pub peer: Option<String>,
+    pub class: ErrorClass,
+    pub message: String,
+}
+
+impl NetErrorReport {
+    fn new(peer: Option<String>, error: &NetError) -> Self {
+        Self {
+            peer,
+            class: error.class(),
+            message: error.to_string(),
+        }
+    }
+
+    /// One line including what to do about it
+    pub fn describe(&self) -> String {
+        let peer = match &self.peer {
+            Some(peer) => format!(" with {peer}"),
+            None => String::new(),
+        };
+
+        format!(
+            "{} error{peer}: {}. {}",
+            self.class,
+            self.message,
+            self.class.hint()
+        )
+    }
+}
+
+/// What happens to an outgoing connection once it drops
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReconnectAction {
+    /// Connect to `addr` again after `delay`
+    Retry { addr: PeerAddr, delay: Duration },
+    /// The last error was not worth retrying
+    GiveUp { addr: PeerAddr, class: ErrorClass },
+}
+
+/// Connections we started ourselves, reconnected after errors that may clear up
+#[derive(Resource, Debug, Default)]
+pub struct Reconnects {
+    /// Outgoing connections by the token the net thread gave them
+    attempts: HashMap<NetToken, PeerAddr>,
+    /// Class of the last error on each outgoing connection
+    failures: HashMap<NetToken, ErrorClass>,
+    /// Attempts in a row that never connected, per address
+    failed_attempts: HashMap<PeerAddr, u32>,
+    /// Real time each address is due to be retried at
+    scheduled: HashMap<PeerAddr, Duration>,
+}
+
+impl Reconnects {
+    fn connecting(&mut self, token: NetToken, addrs: PeerAddr) {
+        self.scheduled.remove(&addrs);
+        self.attempts.insert(token, addrs);
+    }
+
+    fn connected(&mut self, token: NetToken) {
+        if let Some(addrs) = self.attempts.get(&token) {
+            self.failed_attempts.remove(addrs);
+        }
+    }
+
+    fn failed(&mut self, token: NetToken, class: ErrorClass) {
+        if self.attempts.contains_key(&token) {
+            self.failures.insert(token, class);
+        }
+    }
+
+    /// Reconnects to the port the server behind `token` moved its listener to
+    fn rebound(&mut self, token: NetToken, port: u16) {
+        if let Some(PeerAddr::Tcp(addr)) = self.attempts.get_mut(&token) {
+            addr.set_port(port);
+        }
+    }
+
+    /// Forgets a connection the operator closed so it isn't reconnected
+    fn cancel(&mut self, token: NetToken) {
+        if let Some(addrs) = self.attempts.remove(&token) {
+            self.failed_attempts.remove(&addrs);
+        }
+        self.failures.remove(&token);
+    }
+
+    /// Schedules a retry if the connection dropped to a retryable error, `None` for connections
+    /// we didn't start
+    fn disconnected(&mut self, token: NetToken, now: Duration) -> Option<ReconnectAction> {
+        let addr = self.attempts.remove(&token)?;
+        // Dropped without an error, the peer most likely shut down
+        let class = self.failures.remove(&token).unwrap_or(ErrorClass::PeerGone);
+
+        if !class.is_retryable() {
+            self.failed_attempts.remove(&addr);
+            return Some(ReconnectAction::GiveUp { addr, class });
+        }
+
+        let attempts = self.failed_attempts.entry(addr.clone()).or_default();
+        let delay = reconnect_backoff(*attempts);
+        *attempts += 1;
+
+        self.scheduled.insert(addr.clone(), now + delay);
+
+        Some(ReconnectAction::Retry { addr, delay })
+    }
+
+    /// Addresses due to be connected to again
+    fn due(&mut self, now: Duration) -> Vec<PeerAddr> {
+        self.scheduled
+            .extract_if(|_, at| *at <= now)
+            .map(|(addr, _)| addr)
+            .collect()
+    }
+
+    pub fn is_attempt(&self, token: NetToken) -> bool {
+        self.attempts.contains_key(&token)
+    }
+}
+
+/// Wait before retrying after `attempts` failed attempts in a row
+pub fn reconnect_backoff(attempts: u32) -> Duration {
+    RECONNECT_MIN_BACKOFF
+        .saturating_mul(1 << attempts.min(16))
+        .min(RECONNECT_MAX_BACKOFF)
+}
+
 fn setup_networking(
     mut cmds: Commands,
 
     role: Res<SyncRole>,
     name: Res<InstanceName>,
+    local_socket: Option<Res<LocalSocket>>,
+    (link_key, tcp): (Option<Res<LinkKey>>, Option<Res<LinkTcpOptions>>),
+    transport: Option<ResMut<SyncTransport>>,
 
     errors: Res<Errors>,
 ) -> anyhow::Result<()> {
     info!("Init networking");
 
-    let networking = Networking::new().context("Start networking")?;
+    let injected = transport.and_then(|mut it| it.0.take());
+    let is_injected = injected.is_some();
+
+    let networking = if let Some(networking) = injected {
+        info!("Using injected transport");
+
+        networking
+    } else {
+        let options = NetworkingOptions {
+            preshared_key: link_key.map(|it| it.0),
+            tcp: tcp.map(|it| it.0).unwrap_or_default(),
+            ..default()
+        };
+        if options.preshared_key.is_some() {
+            info!("Link encryption enabled");
+        }
+
+        Networking::with_options(options).context("Start networking")?
+    };
     let handle = networking.messenger();
 
     let (tx, rx) = channel::bounded(1000);
 
-    cmds.insert_resource(Net(handle.clone(), rx));
-
     let errors = errors.0.clone();
-    thread::Builder::new()
+    let net_thread = thread::Builder::new()
         .name("Net Thread".to_owned())
-        .spawn(move || {
+        .spawn_worker(move || {
             info!("Starting networking thread");
 
             networking.start(|event| {
@@ -161,33 +657,43 @@ fn setup_networking(
         })
         .context("Spawn thread")?;
 
+    cmds.insert_resource(Net(handle.clone(), rx, Some(net_thread)));
+
+    if is_injected {
+        if let SyncRole::Client = &*role {
+            cmds.init_resource::<MdnsPeers>();
+        }
+
+        return Ok(());
+    }
+
     let mdns = ServiceDaemon::new().context("Could not create mdns daemon")?;
 
     match &*role {
         SyncRole::Server { port } => {
-            // Bind server socket
-            let bind = (Ipv4Addr::new(0, 0, 0, 0), *port)
-                .to_socket_addrs()
-                .context("Resolve bind ip")?
-                .next()
-                .context("Take first bind ip")?;
-
-            info!("Binding server acceptor");
-            handle.bind_at(bind).context("Contact net thread")?;
-
-            // Set up mdns service broadcasting
-            let hostname = hostname::get().context("Lookup hostname")?;
-            let hostname = hostname.to_str().unwrap();
-            let instance_name = &name.0;
-
-            let service_info =
-                ServiceInfo::new(SERVICE_TYPE, instance_name, hostname, (), *port, None)
-                    .context("Create service info")?
-                    .enable_addr_auto();
-
-            info!("Begin broadcasting service");
-            mdns.register(service_info)
-                .context("Register mdns service")?;
+            if let Some(port) = *port {
+                // Bind server socket
+                let bind = (Ipv4Addr::new(0, 0, 0, 0), port)
+                    .to_socket_addrs()
+                    .context("Resolve bind ip")?
+                    .next()
+                    .context("Take first bind ip")?;
+
+                info!("Binding server acceptor");
+                handle.bind_at(bind).context("Contact net thread")?;
+
+                // Set up mdns service broadcasting
+                info!("Begin broadcasting service");
+                mdns.register(service_info(&name.0, port)?)
+                    .context("Register mdns service")?;
+            }
+
+            if let Some(local_socket) = &local_socket {
+                info!("Binding local socket at {}", local_socket.0.display());
+                handle
+                    .bind_unix(local_socket.0.clone())
+                    .context("Contact net thread")?;
+            }
         }
         SyncRole::Client => {
             // Set up mdns service discovery
@@ -203,24 +709,113 @@ fn setup_networking(
     Ok(())
 }
 
+fn service_info(instance_name: &str, port: u16) -> anyhow::Result<ServiceInfo> {
+    let hostname = hostname::get().context("Lookup hostname")?;
+    let hostname = hostname.to_str().unwrap();
+
+    let service_info = ServiceInfo::new(SERVICE_TYPE, instance_name, hostname, (), port, None)
+        .context("Create service info")?
+        .enable_addr_auto();
+
+    Ok(service_info)
+}
+
+fn rebind_listener(
+    net: Res<Net>,
+    name: Res<InstanceName>,
+    mdns: Option<Res<MdnsDaemon>>,
+    mut role: ResMut<SyncRole>,
+    mut listeners: ResMut<Listeners>,
+    mut requests: EventReader<RebindListener>,
+    mut errors: EventWriter<ErrorEvent>,
+) -> anyhow::Result<()> {
+    for &RebindListener(port) in requests.read() {
+        let bind = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+
+        if let Err(reason) = listeners.start_rebind(bind) {
+            errors.send(anyhow!("Could not move listener to port {port}: {reason}").into());
+            continue;
+        }
+
+        info!("Moving listener to {bind}");
+        net.0.bind_at(bind).context("Contact net thread")?;
+    }
+
+    match listeners.finish_rebind() {
+        Some(Ok((addr, replaced))) => {
+            // Peers hear about the new port before the old one closes
+            let rebinding = Protocol::Rebinding {
+                new_port: addr.port(),
+            };
+            net.0
+                .brodcast_packet(rebinding)
+                .context("Contact net thread")?;
+
+            for token in replaced {
+                net.0.disconnect(token).context("Contact net thread")?;
+            }
+
+            info!("Listening at {addr}, existing connections were kept");
+            *role = SyncRole::Server {
+                port: Some(addr.port()),
+            };
+
+            // The move already happened, a stale announcement shouldn't undo it
+            if let Some(mdns) = &mdns {
+                let registered = service_info(&name.0, addr.port())
+                    .and_then(|info| mdns.0.register(info).context("Update mdns service"));
+                if let Err(err) = registered {
+                    errors.send(err.context("Announce new listener port").into());
+                }
+            }
+        }
+        Some(Err(addr)) => {
+            warn!("Could not listen at {addr}, still listening on the old port");
+        }
+        None => {}
+    }
+
+    Ok(())
+}
+
 fn connect(net: Res<Net>, mut events: EventReader<ConnectToPeer>) -> anyhow::Result<()> {
     for event in events.read() {
         info!("Connecting to {}", event.0);
-        net.0.connect_to(event.0).context("Contact net thread")?;
+        net.0
+            .connect_peer(event.0.clone())
+            .context("Contact net thread")?;
     }
 
     Ok(())
 }
 
-fn disconnect(net: Res<Net>, mut events: EventReader<DisconnectPeer>) -> anyhow::Result<()> {
+fn disconnect(
+    net: Res<Net>,
+    mut reconnects: ResMut<Reconnects>,
+    mut events: EventReader<DisconnectPeer>,
+) -> anyhow::Result<()> {
     for event in events.read() {
         info!("Disconnecting from {:?}", event.0);
+        reconnects.cancel(event.0);
         net.0.disconnect(event.0).context("Contact net thread")?;
     }
 
     Ok(())
 }
 
+fn reconnect(
+    net: Res<Net>,
+    time: Res<Time<Real>>,
+    mut reconnects: ResMut<Reconnects>,
+) -> anyhow::Result<()> {
+    for addrs in reconnects.due(time.elapsed()) {
+        info!("Reconnecting to {addrs}");
+        net.0.connect_peer(addrs).context("Contact net thread")?;
+    }
+
+    Ok(())
+}
+
 fn discover_peers(mut peers: ResMut<MdnsPeers>, browse: Res<MdnsBrowse>) {
     for event in browse.0.try_iter() {
         match event {
@@ -254,52 +849,187 @@ fn discover_peers(mut peers: ResMut<MdnsPeers>, browse: Res<MdnsBrowse>) {
     }
 }
 
+#[cfg_attr(
+    feature = "sync_instrumentation",
+    tracing::instrument(level = "debug", skip_all)
+)]
+#[allow(clippy::too_many_arguments)]
 fn net_read(
     mut cmds: Commands,
 
     net: Res<Net>,
     frame: Res<FrameCount>,
+    time: Res<Time<Real>>,
+    name: Res<InstanceName>,
 
     mut peers: ResMut<Peers>,
-    mut entity_map: ResMut<EntityMap>,
+    mut reconnects: ResMut<Reconnects>,
+    (mut entity_map, mut pending_changes, mut listeners): (
+        ResMut<EntityMap>,
+        ResMut<PendingChanges>,
+        ResMut<Listeners>,
+    ),
+    (mut quarantine, settings): (ResMut<QuarantinedTypes>, Res<SerializationSettings>),
     mut changes: EventWriter<SerializedChangeInEvent>,
     mut new_peers: EventWriter<SyncPeer>,
+    (mut file_chunks, mut video_frames, mut video_acks): (
+        EventWriter<FileChunkIn>,
+        EventWriter<VideoFrameIn>,
+        EventWriter<VideoFrameAckIn>,
+    ),
+    mut reports: EventWriter<NetErrorReport>,
 
-    mut peer_query: Query<(&Peer, &mut Latency)>,
+    mut peer_query: Query<(&Peer, &mut Latency, Option<&PeerTickRate>)>,
+    named: Query<(), With<Name>>,
 
     mut errors: EventWriter<ErrorEvent>,
 ) {
     for event in net.1.try_iter() {
         match event {
+            NetEvent::Connecting(token, addrs) => {
+                debug!(?token, ?addrs, "Connecting to peer");
+
+                reconnects.connecting(token, addrs);
+            }
             NetEvent::Conected(token, addrs) | NetEvent::Accepted(token, addrs) => {
                 info!(?token, ?addrs, "Peer connected");
 
-                new_peers.send(SyncPeer(token));
+                reconnects.connected(token);
+
+                let hello = Protocol::Hello {
+                    name: name.0.clone(),
+                    version: PROTOCOL_VERSION,
+                };
+                let rst = net.0.send_packet(token, hello);
+                if rst.is_err() {
+                    errors.send(anyhow!("Could not send hello").into());
+                }
+
+                let manifest = Protocol::TypeManifest {
+                    types: settings.type_manifest(),
+                };
+                let rst = net.0.send_packet(token, manifest);
+                if rst.is_err() {
+                    errors.send(anyhow!("Could not send type manifest").into());
+                }
+
+                // The initial sync waits for the peer's manifest so it can skip types the peer
+                // doesn't know
                 peers.pending.insert(token, (addrs, frame.0));
 
                 peers.valid_tokens.insert(token);
             }
+            NetEvent::SocketTuned(token, tuning) => {
+                debug!(?token, ?tuning, "Peer socket tuned");
+
+                if !tuning.failed.is_empty() {
+                    warn!(
+                        "Could not set {} on the connection to {}",
+                        tuning.failed.join(", "),
+                        peers.describe(token)
+                    );
+                }
+
+                if let Some(entity) = peers.tuned(token, tuning.clone()) {
+                    cmds.entity(entity).insert(PeerSocketTuning(tuning));
+                }
+            }
             NetEvent::Data(token, packet) => match packet {
                 Protocol::EcsUpdate(update) => {
                     changes.send(SerializedChangeInEvent(update, token));
                 }
-                Protocol::Ping { payload } => {
-                    let response = Protocol::Pong { payload };
+                Protocol::Hello { name, version } => {
+                    info!(?token, "Peer is {name}");
+
+                    if version != PROTOCOL_VERSION {
+                        errors.send(
+                            anyhow!(
+                                "Peer {name} speaks protocol version {version}, \
+                                 we speak {PROTOCOL_VERSION}"
+                            )
+                            .into(),
+                        );
+
+                        let rst = net.0.disconnect(token);
+                        if rst.is_err() {
+                            errors.send(anyhow!("Could not disconnect peer").into());
+                        }
+                        continue;
+                    }
+
+                    // Otherwise named once `spawn_peer_entities` gets to it
+                    if let Some(entity) = peers.hello(token, name.clone()) {
+                        if !named.contains(entity) {
+                            cmds.entity(entity).insert(Name::new(name));
+                        }
+                    }
+                }
+                Protocol::TypeManifest { types } => {
+                    let first = !peers.has_manifest(token);
+                    let mismatch = receive_manifest(&settings, &mut quarantine, token, &types);
+
+                    if !mismatch.is_empty() {
+                        warn!(
+                            "Peer {} is missing types {:?}, we are missing types {:?}",
+                            peers.describe(token),
+                            mismatch.missing_remotely,
+                            mismatch.missing_locally
+                        );
+                    }
+
+                    if let Some(entity) = peers.manifest(token, mismatch.clone()) {
+                        cmds.entity(entity).insert(mismatch);
+                    }
+
+                    if first {
+                        new_peers.send(SyncPeer(token));
+                    }
+                }
+                Protocol::Ping {
+                    payload,
+                    frame_time,
+                } => {
+                    let response = Protocol::Pong {
+                        payload,
+                        time: clock::monotonic_seconds(),
+                    };
 
                     let rst = net.0.send_packet(token, response);
 
                     if rst.is_err() {
                         errors.send(anyhow!("Could not reply to ping").into());
                     }
+
+                    // Peers still waiting on their `Singleton` report again next ping
+                    let Some(&entity) = peers.by_token.get(&token) else {
+                        continue;
+                    };
+
+                    let tick_rate = PeerTickRate { frame_time };
+                    let was_slow = peer_query
+                        .get(entity)
+                        .ok()
+                        .and_then(|(_, _, last)| last)
+                        .is_some_and(PeerTickRate::is_slow);
+
+                    if tick_rate.is_slow() && !was_slow {
+                        warn!(
+                            "Peer {} is over running, taking {frame_time:.1?} per frame",
+                            peers.describe(token)
+                        );
+                    }
+
+                    cmds.entity(entity).insert(tick_rate);
                 }
-                Protocol::Pong { payload } => {
+                Protocol::Pong { payload, time } => {
+                    let received = clock::monotonic_seconds();
                     let peer = peers
                         .by_token
                         .get(&token)
-                        .and_then(|it| peer_query.get_mut(*it).ok());
+                        .and_then(|&entity| Some((entity, peer_query.get_mut(entity).ok()?)));
 
-                    let Some((_, mut latency)) = peer else {
-                        errors.send(anyhow!("Got pong from unknown peer").into());
+                    let Some((entity, (_, mut latency, _))) = peer else {
+                        errors.send(anyhow!("Got pong from unknown peer {token:?}").into());
                         continue;
                     };
 
@@ -308,62 +1038,231 @@ fn net_read(
 
                     latency.last_acknowledged = sent.into();
                     latency.ping = Some(frame.wrapping_sub(sent));
+
+                    let offset = latency
+                        .last_ping_time
+                        .take()
+                        .and_then(|sent_at| latency.clock.add_sample(sent_at, time, received));
+                    if let Some(offset) = offset {
+                        cmds.entity(entity).insert(offset);
+                    }
+                }
+                Protocol::Goodbye => {
+                    info!(?token, "Peer is shutting down");
+
+                    peers.leaving.insert(token);
+                }
+                Protocol::Probe => {
+                    info!(?token, "Peer is probing the connection");
+
+                    let others = peers
+                        .names
+                        .iter()
+                        .filter(|(other, _)| **other != token)
+                        .map(|(_, name)| name.clone())
+                        .take(MAX_PROBE_PEERS)
+                        .collect();
+                    let rst = net
+                        .0
+                        .send_packet(token, Protocol::ProbeReply { peers: others });
+                    if rst.is_err() {
+                        errors.send(anyhow!("Could not reply to probe").into());
+                    }
+
+                    // Probes hang up as soon as they have their answer
+                    peers.leaving.insert(token);
+                }
+                Protocol::ProbeReply { peers: others } => {
+                    debug!(?token, ?others, "Unexpected probe reply");
+                }
+                Protocol::Rebinding { new_port } => {
+                    info!("Peer {} moved to port {new_port}", peers.describe(token));
+
+                    reconnects.rebound(token, new_port);
+                }
+                Protocol::FileTransfer {
+                    id,
+                    kind,
+                    name,
+                    chunk_index,
+                    total,
+                    data,
+                } => {
+                    let chunk = FileChunk {
+                        id,
+                        kind,
+                        name,
+                        chunk_index,
+                        total,
+                        data,
+                    };
+
+                    file_chunks.send(FileChunkIn(token, chunk));
+                }
+                Protocol::VideoFrame {
+                    camera_id,
+                    seq,
+                    jpeg,
+                } => {
+                    let ack = Protocol::VideoFrameAck {
+                        camera_id: camera_id.clone(),
+                        seq,
+                    };
+                    let rst = net.0.send_packet(token, ack);
+                    if rst.is_err() {
+                        errors.send(anyhow!("Could not acknowledge video frame").into());
+                    }
+
+                    video_frames.send(VideoFrameIn {
+                        peer: token,
+                        camera: camera_id,
+                        seq,
+                        jpeg,
+                    });
+                }
+                Protocol::VideoFrameAck { camera_id, seq } => {
+                    video_acks.send(VideoFrameAckIn(token, camera_id, seq));
                 }
             },
-            NetEvent::Error(token, error) => {
-                errors.send(
-                    anyhow!(error)
-                        .context(format!("Network Error: Token: {token:?}"))
-                        .into(),
+            NetEvent::Bound(token, addrs) => {
+                info!(?token, "Listening at {addrs}");
+
+                listeners.bound(token, addrs);
+            }
+            NetEvent::BindFailed(addrs) => {
+                listeners.bind_failed(&addrs);
+            }
+            NetEvent::Error(Some(token), error) if peers.leaving.contains(&token) => {
+                debug!(
+                    "Network error from departing peer {}: {error}",
+                    peers.describe(token)
                 );
             }
+            NetEvent::Error(token, error) => {
+                // Decides whether the disconnect that follows is retried
+                if let Some(token) = token {
+                    reconnects.failed(token, error.class());
+                }
+
+                let report = NetErrorReport::new(token.map(|it| peers.describe(it)), &error);
+                let context = match report.class {
+                    ErrorClass::AddrInUse => {
+                        format!("Could not listen for connections: {}", report.class.hint())
+                    }
+                    class => format!(
+                        "Network Error ({class}): Peer: {}: {}",
+                        report.peer.as_deref().unwrap_or("None"),
+                        class.hint()
+                    ),
+                };
+
+                errors.send(anyhow!(error).context(context).into());
+                reports.send(report);
+            }
+            NetEvent::Disconnect(token) if listeners.closed(token) => {
+                info!(?token, "Stopped listening");
+            }
             NetEvent::Disconnect(token) => {
+                let description = peers.describe(token);
+                let attempt = reconnects.is_attempt(token);
+
+                match reconnects.disconnected(token, time.elapsed()) {
+                    Some(ReconnectAction::Retry { addr, delay }) => {
+                        info!("Reconnecting to {addr} in {delay:.1?}");
+                    }
+                    Some(ReconnectAction::GiveUp { addr, class }) => {
+                        warn!("Not reconnecting to {addr} after a {class} error");
+                    }
+                    None => {}
+                }
+
                 peers.valid_tokens.remove(&token);
+                peers.leaving.remove(&token);
+                peers.names.remove(&token);
+                peers.tuning.remove(&token);
+                peers.mismatches.remove(&token);
+                quarantine.remove_peer(token);
+                pending_changes.remove_peer(token);
 
                 let Some(entity) = peers.by_token.remove(&token) else {
-                    errors.send(anyhow!("Unknown peer disconnected").into());
+                    peers.pending.remove(&token);
+
+                    // Our own attempts that never connected have no entity
+                    if !attempt {
+                        errors.send(anyhow!("Unknown peer {description} disconnected").into());
+                    }
                     continue;
                 };
-                let Ok((peer, _)) = peer_query.get(entity) else {
-                    errors.send(anyhow!("Unknown peer disconnected").into());
+                let Ok((peer, ..)) = peer_query.get(entity) else {
+                    errors.send(anyhow!("Unknown peer {description} disconnected").into());
                     continue;
                 };
 
                 peers.by_addrs.remove(&peer.addrs);
 
                 cmds.entity(entity).despawn();
-                if let Some(owned_entities) = entity_map.forign_owned.remove(&token) {
-                    for entity in owned_entities {
-                        let forign = entity_map.local_to_forign.remove(&entity);
-                        if let Some(forign) = forign {
-                            entity_map.forign_to_local.remove(&forign);
-                        };
-
-                        entity_map.local_modified.remove(&entity);
-
-                        let Some(mut entity) = cmds.get_entity(entity) else {
-                            continue;
-                        };
+                for entity in entity_map.remove_peer(token) {
+                    let Some(mut entity) = cmds.get_entity(entity) else {
+                        continue;
+                    };
 
-                        entity.despawn();
-                    }
+                    entity.despawn();
                 }
 
-                info!("Peer ({token:?}) at {} disconnected", peer.addrs);
+                info!("Peer {description} at {} disconnected", peer.addrs);
             }
         }
     }
 }
+#[cfg_attr(
+    feature = "sync_instrumentation",
+    tracing::instrument(level = "debug", skip_all)
+)]
 fn net_write(
     net: Res<Net>,
+    peers: Res<Peers>,
+    quarantine: Res<QuarantinedTypes>,
+    mut stats: ResMut<SyncStats>,
     mut changes: EventReader<SerializedChangeOutEvent>,
+    mut targeted: EventReader<SerializedChangeTargetedOutEvent>,
     mut errors: EventWriter<ErrorEvent>,
 ) {
-    for change in changes.read() {
-        let rst = net.0.brodcast_packet(Protocol::EcsUpdate(change.0.clone()));
+    let changes = changes.read().map(|it| &it.0).collect::<Vec<_>>();
+    let coalesced = coalesce(changes.iter().copied());
+    stats.record_coalesced(changes.len() - coalesced.len());
+
+    for change in coalesced {
+        stats.record(change);
+
+        // Sent peer by peer, a broadcast would also reach connections `net_read` hasn't seen
+        // yet. Peers still waiting on their manifest get the change with their initial sync.
+        for &peer in &peers.valid_tokens {
+            if !peers.has_manifest(peer) || !quarantine.should_send(change, peer) {
+                continue;
+            }
+
+            let rst = net.0.send_packet(peer, Protocol::EcsUpdate(change.clone()));
+
+            if rst.is_err() {
+                errors.send(anyhow!("Could not send ECS update").into());
+            }
+        }
+    }
+
+    for SerializedChangeTargetedOutEvent(change, peer) in targeted.read() {
+        stats.record(change);
+
+        // The peer may have left while the event was in flight
+        if !peers.valid_tokens.contains(peer) || !quarantine.should_send(change, *peer) {
+            continue;
+        }
+
+        let rst = net
+            .0
+            .send_packet(*peer, Protocol::EcsUpdate(change.clone()));
 
         if rst.is_err() {
-            errors.send(anyhow!("Could not brodcast ECS update").into());
+            errors.send(anyhow!("Could not send ECS update").into());
         }
     }
 
@@ -373,56 +1272,118 @@ fn net_write(
     }
 }
 
-const SINGLETON_DEADLINE: u32 = 3;
+/// Default for [`PeerSettings::singleton_deadline`]
+pub const SINGLETON_DEADLINE: Duration = Duration::from_millis(100);
 
 fn spawn_peer_entities(
     mut cmds: Commands,
     frame: Res<FrameCount>,
+    frame_time: Res<LocalFrameTime>,
+    settings: Res<PeerSettings>,
     mut peers: ResMut<Peers>,
-    query: Query<(Entity, &ForignOwned), Added<Singleton>>,
+    query: Query<(Entity, &ForignOwned, Has<Name>), Added<Singleton>>,
+    fallbacks: Query<(&Peer, &Latency, Option<&PeerTickRate>), With<FallbackPeer>>,
 ) {
     let peers = &mut *peers;
 
-    for (entity, owner) in &query {
+    for (entity, owner, named) in &query {
         let token = NetToken(owner.0);
-        let data = peers.pending.remove(&token);
 
-        if let Some((addrs, _)) = data {
-            cmds.entity(entity)
-                .insert((Peer { addrs, token }, Latency::default()));
+        let (addrs, latency, tick_rate) = if let Some((addrs, _)) = peers.pending.remove(&token) {
+            (addrs, Latency::default(), None)
+        } else {
+            // Anything else is already paired, or belongs to a peer that has left
+            let Some(&fallback) = peers.by_token.get(&token) else {
+                continue;
+            };
+            let Ok((peer, latency, tick_rate)) = fallbacks.get(fallback) else {
+                continue;
+            };
+
+            // The deadline gave up on the singleton, move the peer over to it now it is here
+            debug!("Late singleton from {}", peers.describe(token));
+            cmds.entity(fallback).despawn();
+
+            (peer.addrs.clone(), latency.clone(), tick_rate.copied())
+        };
 
-            peers.by_token.insert(token, entity);
-            peers.by_addrs.insert(addrs, entity);
+        peers.by_token.insert(token, entity);
+        peers.by_addrs.insert(addrs.clone(), entity);
+
+        let mut entity = cmds.entity(entity);
+        entity.insert((Peer { addrs, token }, latency));
+        if let Some(tick_rate) = tick_rate {
+            entity.insert(tick_rate);
+        }
+        if let Some(mismatch) = peers.mismatches.get(&token) {
+            entity.insert(mismatch.clone());
+        }
+        if let Some(tuning) = peers.tuning.get(&token) {
+            entity.insert(PeerSocketTuning(tuning.clone()));
+        }
+
+        // A replicated name came from the peer itself, replacing it would send it back
+        if let (Some(name), false) = (peers.names.get(&token), named) {
+            entity.insert(Name::new(name.clone()));
         }
     }
 
     let frame = frame.0;
+    let deadline = settings.singleton_deadline;
+    let names = &peers.names;
+    let mismatches = &peers.mismatches;
+    let tuning = &peers.tuning;
     peers
         .pending
-        .extract_if(|_, (_, time)| frame.wrapping_sub(*time) > SINGLETON_DEADLINE)
+        .extract_if(|_, (_, since)| frame_time.expired(frame, *since, deadline))
         .for_each(|(token, (addrs, _))| {
-            let entity = cmds.spawn((Peer { addrs, token }, Latency::default())).id();
+            let mut entity = cmds.spawn((
+                Peer {
+                    addrs: addrs.clone(),
+                    token,
+                },
+                Latency::default(),
+                FallbackPeer,
+            ));
+
+            if let Some(name) = names.get(&token) {
+                entity.insert(Name::new(name.clone()));
+            }
+            if let Some(mismatch) = mismatches.get(&token) {
+                entity.insert(mismatch.clone());
+            }
+            if let Some(tuning) = tuning.get(&token) {
+                entity.insert(PeerSocketTuning(tuning.clone()));
+            }
 
+            let entity = entity.id();
             peers.by_token.insert(token, entity);
             peers.by_addrs.insert(addrs, entity);
         });
 }
 
 fn shutdown(
-    net: Res<Net>,
+    mut net: ResMut<Net>,
     mut exit: EventReader<AppExit>,
     mdns: Option<Res<MdnsDaemon>>,
     mut errors: EventWriter<ErrorEvent>,
 ) {
     for _event in exit.read() {
-        let rst = net.0.shutdown();
+        let rst = net.0.brodcast_packet(Protocol::Goodbye);
         if rst.is_err() {
-            errors.send(anyhow!("Could not send shutdown event to net thread").into());
+            errors.send(anyhow!("Could not send goodbye to net thread").into());
         }
 
-        let rst = net.0.wake();
+        let rst = net.0.flush_and_shutdown(FLUSH_TIMEOUT);
         if rst.is_err() {
-            errors.send(anyhow!("Could not wake net thread").into());
+            errors.send(anyhow!("Could not send shutdown event to net thread").into());
+        }
+
+        if let Some(net_thread) = net.2.take() {
+            // The worker gives up on its own after `FLUSH_TIMEOUT`
+            if !shutdown::join_timeout(net_thread, FLUSH_TIMEOUT * 2) {
+                warn!("Net thread did not shut down in time");
+            }
         }
 
         if let Some(mdns) = &mdns {
@@ -434,27 +1395,34 @@ fn shutdown(
     }
 }
 
-const PING_INTERVAL: u32 = 50;
-const MAX_LATENCY: u32 = 15;
+const PING_INTERVAL: Duration = Duration::from_millis(500);
+/// Longest round trip before a peer is disconnected, on top of the time the peer takes to
+/// notice the ping
+pub(crate) const MAX_LATENCY: Duration = Duration::from_millis(150);
 
-// TODO(high): Auto Reconnect
 fn ping(
     net: Res<Net>,
     frame: Res<FrameCount>,
-    mut query: Query<(&Peer, &mut Latency)>,
+    frame_time: Res<LocalFrameTime>,
+    mut query: Query<(&Peer, &mut Latency, Option<&PeerTickRate>, Option<&Name>)>,
     mut errors: EventWriter<ErrorEvent>,
 ) {
     let frame = frame.0;
+    let ping_interval = frame_time.frames(PING_INTERVAL);
+
+    for (peer, mut latency, tick_rate, name) in &mut query {
+        // A slow peer only answers once per frame
+        let remote_frame = tick_rate.map(|it| it.frame_time).unwrap_or_default();
+        let max_latency = frame_time.frames(MAX_LATENCY.saturating_add(remote_frame));
 
-    for (peer, mut latency) in &mut query {
         let should_disconnect = match (
             latency.last_ping_sent,
             latency.last_acknowledged,
             latency.ping,
         ) {
-            (_, _, Some(ping)) if ping > MAX_LATENCY => true,
+            (_, _, Some(ping)) if ping > max_latency => true,
             (Some(last_ping), last_ack, _)
-                if Some(last_ping) != last_ack && frame.wrapping_sub(last_ping) > MAX_LATENCY =>
+                if Some(last_ping) != last_ack && frame.wrapping_sub(last_ping) > max_latency =>
             {
                 true
             }
@@ -463,7 +1431,8 @@ fn ping(
 
         if should_disconnect {
             error!(
-                "Peer at {:?} timed out, now: {:?} lp: {:?}, la: {:?}, elapsed_since: {:?}",
+                "Peer {} ({:?}) timed out, now: {:?} lp: {:?}, la: {:?}, elapsed_since: {:?}",
+                peer.describe(name),
                 peer.token,
                 frame,
                 latency.last_ping_sent,
@@ -480,14 +1449,17 @@ fn ping(
 
         let should_ping = match (latency.last_ping_sent, latency.last_acknowledged) {
             (Some(last_ping), Some(last_ack)) => {
-                last_ping == last_ack && frame >= PING_INTERVAL + last_ping
+                last_ping == last_ack && frame >= ping_interval + last_ping
             }
             (Some(_), None) => false,
             _ => true,
         };
 
         if should_ping {
-            let ping = Protocol::Ping { payload: frame };
+            let ping = Protocol::Ping {
+                payload: frame,
+                frame_time: frame_time.0,
+            };
             let rst = net.0.send_packet(peer.token, ping);
 
             if rst.is_err() {
@@ -495,6 +1467,7 @@ fn ping(
             }
 
             latency.last_ping_sent = frame.into();
+            latency.last_ping_time = Some(clock::monotonic_seconds());
         }
     }
 }
@@ -504,9 +1477,15 @@ struct Deltas {
     entities: HashMap<NetId, HashMap<NetTypeId, adapters::BackingType>>,
 }
 
+#[cfg_attr(
+    feature = "sync_instrumentation",
+    tracing::instrument(level = "debug", skip_all)
+)]
 fn flatten_deltas(
     mut deltas: ResMut<Deltas>,
     entity_map: Res<EntityMap>,
+    settings: Res<SerializationSettings>,
+    role: Res<SyncRole>,
 
     mut inbound: EventReader<SerializedChangeInEvent>,
     mut outbound: EventReader<SerializedChangeOutEvent>,
@@ -540,6 +1519,13 @@ fn flatten_deltas(
                 let Some(entity) = entity_map.forign_to_local.get(net_id) else {
                     continue;
                 };
+                // New peers only get the types we are allowed to send
+                let may_send = settings
+                    .direction(token)
+                    .is_none_or(|direction| direction.may_send(&role));
+                if !may_send {
+                    continue;
+                }
                 let forign_owned = entity_map
                     .forign_owned
                     .values()
@@ -560,13 +1546,33 @@ fn flatten_deltas(
             SerializedChange::EventEmitted(_, _) => {
                 // New clients should not recieve old events
             }
+            SerializedChange::TypeQuarantined(_, _) => {
+                // Quarantines are between two specific peers
+            }
+            SerializedChange::OwnershipTransferred(net_id, _) => {
+                let Some(entity) = entity_map.forign_to_local.get(net_id) else {
+                    continue;
+                };
+
+                // Components get resent by the new owner after a transfer
+                if entity_map.owner(*entity).is_none() {
+                    deltas.entities.entry(*net_id).or_default();
+                } else {
+                    deltas.entities.remove(net_id);
+                }
+            }
         }
     }
 }
 
+/// Sends peers the world once their `TypeManifest` arrives, leaving out the types they lack
+///
+/// `net_write` sends nothing to a peer before this, changes made in the meantime are part of the
+/// world it gets here. Events emitted in that window are not replayed.
 fn sync_new_peers(
     net: Res<Net>,
     deltas: Res<Deltas>,
+    quarantine: Res<QuarantinedTypes>,
     mut new_peers: EventReader<SyncPeer>,
     mut errors: EventWriter<ErrorEvent>,
 ) {
@@ -585,14 +1591,13 @@ fn sync_new_peers(
 
         for (entity, components) in &deltas.entities {
             for (token, raw) in components {
-                let rst = net.0.send_packet(
-                    peer,
-                    Protocol::EcsUpdate(SerializedChange::ComponentUpdated(
-                        *entity,
-                        token.clone(),
-                        Some(raw.clone()),
-                    )),
-                );
+                let change =
+                    SerializedChange::ComponentUpdated(*entity, token.clone(), Some(raw.clone()));
+                if !quarantine.should_send(&change, peer) {
+                    continue;
+                }
+
+                let rst = net.0.send_packet(peer, Protocol::EcsUpdate(change));
 
                 if rst.is_err() {
                     errors.send(anyhow!("Could not send sync packet").into());
@@ -602,3 +1607,719 @@ fn sync_new_peers(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        slice, thread,
+        time::{Duration, Instant},
+    };
+
+    use bevy::{app::App, core::FrameCount, prelude::*};
+    use networking::{
+        error::{ErrorClass, NetError},
+        Networking, PeerAddr, SocketTuning, Token as NetToken,
+    };
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        components::Singleton,
+        ctrlc::CtrlCPlugin,
+        ecs_sync::{
+            quarantine::QuarantinedTypes,
+            test_utils::{self, Test, CLIENT_A, CLIENT_B, ROBOT},
+            AppReplicateExt, ForignOwned, NetId, NetTypeId, Replicate, SerializationSettings,
+            SerializedChange, SerializedChangeInEvent,
+        },
+        protocol::Protocol,
+        CommonPlugins,
+    };
+
+    use super::{
+        frames_for, receive_manifest, reconnect_backoff, spawn_peer_entities, ConnectToPeer,
+        FallbackPeer, Latency, Listeners, LocalFrameTime, NetErrorReport, Peer, PeerSettings,
+        PeerSocketTuning, PeerTickRate, PeerTypeMismatch, Peers, ReconnectAction, Reconnects,
+        SyncRole, SyncTransport, NOMINAL_FRAME_TIME, RECONNECT_MAX_BACKOFF, RECONNECT_MIN_BACKOFF,
+        SINGLETON_DEADLINE,
+    };
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn durations_round_up_to_whole_frames() {
+        assert_eq!(frames_for(ms(100), ms(10)), 10);
+        assert_eq!(frames_for(ms(100), Duration::from_secs_f64(1.0 / 60.0)), 6);
+        assert_eq!(frames_for(ms(150), ms(40)), 4);
+
+        // Never less than a frame
+        assert_eq!(frames_for(ms(100), ms(500)), 1);
+        assert_eq!(frames_for(Duration::ZERO, ms(10)), 1);
+        assert_eq!(frames_for(ms(100), Duration::ZERO), u32::MAX);
+    }
+
+    #[test]
+    fn frame_time_follows_measured_frames() {
+        let mut frame_time = LocalFrameTime::default();
+        assert_eq!(frame_time.0, NOMINAL_FRAME_TIME);
+
+        // Bevy's first frame has no delta
+        frame_time.record(Duration::ZERO);
+        assert_eq!(frame_time.0, NOMINAL_FRAME_TIME);
+
+        for _ in 0..200 {
+            frame_time.record(ms(50));
+        }
+        assert!(frame_time.0.abs_diff(ms(50)) < ms(1), "{frame_time:?}");
+
+        // A single hitch doesn't swing it far
+        frame_time.record(ms(500));
+        assert!(frame_time.0 < ms(100), "{frame_time:?}");
+    }
+
+    #[test]
+    fn deadlines_hold_in_wall_clock_time_under_slow_frames() {
+        let nominal = LocalFrameTime(ms(10));
+        let slow = LocalFrameTime(ms(50));
+
+        // 100 ms is 10 frames at the nominal rate
+        assert!(!nominal.expired(105, 100, SINGLETON_DEADLINE));
+        assert!(!nominal.expired(110, 100, SINGLETON_DEADLINE));
+        assert!(nominal.expired(111, 100, SINGLETON_DEADLINE));
+
+        // but only 2 when each frame takes 50 ms, so the deadline can't stretch to half a second
+        assert!(!slow.expired(102, 100, SINGLETON_DEADLINE));
+        assert!(slow.expired(103, 100, SINGLETON_DEADLINE));
+
+        // Frame counters wrap
+        assert!(!nominal.expired(4, u32::MAX - 4, SINGLETON_DEADLINE));
+        assert!(nominal.expired(20, u32::MAX - 4, SINGLETON_DEADLINE));
+    }
+
+    #[test]
+    fn slow_peers() {
+        let peer = |ms| PeerTickRate {
+            frame_time: Duration::from_millis(ms),
+        };
+
+        assert!(!peer(10).is_slow());
+        assert!(!peer(17).is_slow());
+        assert!(peer(120).is_slow());
+    }
+
+    fn addrs() -> PeerAddr {
+        PeerAddr::Tcp("10.0.0.2:44444".parse().unwrap())
+    }
+
+    fn peer_app() -> App {
+        let mut app = App::new();
+        app.init_resource::<Peers>()
+            .init_resource::<PeerSettings>()
+            .init_resource::<LocalFrameTime>()
+            .init_resource::<FrameCount>()
+            .add_systems(Update, spawn_peer_entities);
+
+        app
+    }
+
+    fn connect(app: &mut App, token: NetToken) {
+        let frame = app.world.resource::<FrameCount>().0;
+        app.world
+            .resource_mut::<Peers>()
+            .pending
+            .insert(token, (addrs(), frame));
+    }
+
+    fn peer_name(app: &mut App, token: NetToken) -> Option<String> {
+        let entity = app.world.resource::<Peers>().by_token[&token];
+        app.world.get::<Name>(entity).map(|it| it.to_string())
+    }
+
+    #[test]
+    fn peers_are_named_after_their_hello() {
+        let mut app = peer_app();
+
+        // Never replicates a `Singleton`, so gets an entity of its own once the deadline passes
+        let token = NetToken(1);
+        connect(&mut app, token);
+        assert_eq!(
+            app.world
+                .resource_mut::<Peers>()
+                .hello(token, "Surface".to_owned()),
+            None
+        );
+
+        app.world.resource_mut::<FrameCount>().0 += 100;
+        app.update();
+        assert_eq!(peer_name(&mut app, token).as_deref(), Some("Surface"));
+
+        // Its singleton shows up with a name of its own, which is left alone
+        let token = NetToken(2);
+        connect(&mut app, token);
+        app.world
+            .resource_mut::<Peers>()
+            .hello(token, "Robot".to_owned());
+        app.world
+            .spawn((Singleton, ForignOwned(token.0), Name::new("Replicated")));
+        app.update();
+        assert_eq!(peer_name(&mut app, token).as_deref(), Some("Replicated"));
+
+        // Its singleton has no name
+        let token = NetToken(3);
+        connect(&mut app, token);
+        app.world
+            .resource_mut::<Peers>()
+            .hello(token, "Robot".to_owned());
+        app.world.spawn((Singleton, ForignOwned(token.0)));
+        app.update();
+        assert_eq!(peer_name(&mut app, token).as_deref(), Some("Robot"));
+
+        // Hello arriving after the entity exists
+        let token = NetToken(4);
+        connect(&mut app, token);
+        let entity = app.world.spawn((Singleton, ForignOwned(token.0))).id();
+        app.update();
+        assert_eq!(peer_name(&mut app, token), None);
+        assert_eq!(
+            app.world
+                .resource_mut::<Peers>()
+                .hello(token, "Robot".to_owned()),
+            Some(entity)
+        );
+    }
+
+    fn peer_entities(app: &mut App) -> Vec<(Entity, NetToken)> {
+        app.world
+            .query::<(Entity, &Peer)>()
+            .iter(&app.world)
+            .map(|(entity, peer)| (entity, peer.token))
+            .collect()
+    }
+
+    #[test]
+    fn late_singletons_replace_their_fallback() {
+        let mut app = peer_app();
+
+        let token = NetToken(1);
+        connect(&mut app, token);
+        app.world
+            .resource_mut::<Peers>()
+            .hello(token, "Robot".to_owned());
+
+        // A slow initial sync misses the deadline
+        app.world.resource_mut::<FrameCount>().0 += 100;
+        app.update();
+        let fallback = app.world.resource::<Peers>().by_token[&token];
+        assert!(app.world.get::<FallbackPeer>(fallback).is_some());
+        app.world.get_mut::<Latency>(fallback).unwrap().ping = Some(3);
+        app.world
+            .entity_mut(fallback)
+            .insert(PeerTickRate { frame_time: ms(20) });
+
+        let singleton = app.world.spawn((Singleton, ForignOwned(token.0))).id();
+        app.update();
+
+        assert_eq!(peer_entities(&mut app), [(singleton, token)]);
+        assert!(app.world.get_entity(fallback).is_none());
+
+        let peers = app.world.resource::<Peers>();
+        assert_eq!(peers.by_token[&token], singleton);
+        assert_eq!(peers.by_addrs[&addrs()], singleton);
+
+        // Everything learned about the peer so far moves with it
+        assert_eq!(app.world.get::<Latency>(singleton).unwrap().ping, Some(3));
+        assert_eq!(
+            app.world.get::<PeerTickRate>(singleton),
+            Some(&PeerTickRate { frame_time: ms(20) })
+        );
+        assert!(app.world.get::<FallbackPeer>(singleton).is_none());
+        assert_eq!(peer_name(&mut app, token).as_deref(), Some("Robot"));
+
+        // A singleton from a peer with no entity is left alone
+        app.world.spawn((Singleton, ForignOwned(5)));
+        app.update();
+        assert_eq!(peer_entities(&mut app), [(singleton, token)]);
+    }
+
+    #[test]
+    fn singleton_deadline_is_configurable() {
+        let mut app = peer_app();
+        app.insert_resource(PeerSettings {
+            singleton_deadline: Duration::from_secs(1),
+        });
+
+        let token = NetToken(1);
+        connect(&mut app, token);
+
+        // 50 frames at the nominal 10 ms is only half the deadline
+        app.world.resource_mut::<FrameCount>().0 += 50;
+        app.update();
+        assert!(peer_entities(&mut app).is_empty());
+
+        app.world.resource_mut::<FrameCount>().0 += 60;
+        app.update();
+        assert_eq!(peer_entities(&mut app).len(), 1);
+    }
+
+    #[test]
+    fn peers_are_described_by_name() {
+        let peer = Peer {
+            addrs: addrs(),
+            token: NetToken(7),
+        };
+
+        assert_eq!(peer.describe(None), "10.0.0.2:44444");
+        assert_eq!(
+            peer.describe(Some(&Name::new("Robot"))),
+            "Robot (10.0.0.2:44444)"
+        );
+
+        let mut peers = Peers::default();
+        assert_eq!(peers.describe(NetToken(7)), "Token(7)");
+        peers.hello(NetToken(7), "Robot".to_owned());
+        assert_eq!(peers.describe(NetToken(7)), "Robot (Token(7))");
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        assert_eq!(reconnect_backoff(0), RECONNECT_MIN_BACKOFF);
+        assert_eq!(reconnect_backoff(1), RECONNECT_MIN_BACKOFF * 2);
+        assert_eq!(reconnect_backoff(2), RECONNECT_MIN_BACKOFF * 4);
+        assert_eq!(reconnect_backoff(10), RECONNECT_MAX_BACKOFF);
+        assert_eq!(reconnect_backoff(u32::MAX), RECONNECT_MAX_BACKOFF);
+    }
+
+    /// An attempt to connect that fails with an error of `class`
+    fn fail(reconnects: &mut Reconnects, token: usize, class: ErrorClass) {
+        let token = NetToken(token);
+
+        reconnects.connecting(token, addrs());
+        reconnects.failed(token, class);
+    }
+
+    #[test]
+    fn retryable_errors_reconnect_with_backoff() {
+        for class in [
+            ErrorClass::TransientIo,
+            ErrorClass::PeerGone,
+            ErrorClass::Refused,
+        ] {
+            let mut reconnects = Reconnects::default();
+
+            fail(&mut reconnects, 1, class);
+            assert_eq!(
+                reconnects.disconnected(NetToken(1), ms(1000)),
+                Some(ReconnectAction::Retry {
+                    addr: addrs(),
+                    delay: RECONNECT_MIN_BACKOFF,
+                }),
+                "{class}"
+            );
+
+            // Not due until the backoff passed
+            assert!(reconnects.due(ms(1400)).is_empty());
+            assert_eq!(reconnects.due(ms(1500)), [addrs()]);
+            assert!(reconnects.due(ms(1600)).is_empty());
+
+            // Failing again waits longer
+            fail(&mut reconnects, 2, class);
+            assert_eq!(
+                reconnects.disconnected(NetToken(2), ms(2000)),
+                Some(ReconnectAction::Retry {
+                    addr: addrs(),
+                    delay: RECONNECT_MIN_BACKOFF * 2,
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn successful_connections_reset_the_backoff() {
+        let mut reconnects = Reconnects::default();
+
+        fail(&mut reconnects, 1, ErrorClass::Refused);
+        reconnects.disconnected(NetToken(1), ms(0));
+        assert_eq!(reconnects.due(ms(500)), [addrs()]);
+
+        reconnects.connecting(NetToken(2), addrs());
+        reconnects.connected(NetToken(2));
+
+        // Dropped without an error after connecting
+        assert_eq!(
+            reconnects.disconnected(NetToken(2), ms(10_000)),
+            Some(ReconnectAction::Retry {
+                addr: addrs(),
+                delay: RECONNECT_MIN_BACKOFF,
+            })
+        );
+    }
+
+    #[test]
+    fn unretryable_errors_give_up() {
+        for class in [
+            ErrorClass::Protocol,
+            ErrorClass::Fatal,
+            ErrorClass::AddrInUse,
+        ] {
+            let mut reconnects = Reconnects::default();
+
+            fail(&mut reconnects, 1, class);
+            assert_eq!(
+                reconnects.disconnected(NetToken(1), ms(0)),
+                Some(ReconnectAction::GiveUp {
+                    addr: addrs(),
+                    class
+                })
+            );
+            assert!(reconnects.due(ms(60_000)).is_empty());
+        }
+    }
+
+    #[test]
+    fn only_our_connections_are_retried() {
+        let mut reconnects = Reconnects::default();
+
+        // Accepted peers and errors without a connection are left alone
+        reconnects.failed(NetToken(5), ErrorClass::PeerGone);
+        assert_eq!(reconnects.disconnected(NetToken(5), ms(0)), None);
+
+        // As are connections the operator closed
+        reconnects.connecting(NetToken(1), addrs());
+        reconnects.connected(NetToken(1));
+        reconnects.cancel(NetToken(1));
+        assert_eq!(reconnects.disconnected(NetToken(1), ms(0)), None);
+        assert!(reconnects.due(ms(60_000)).is_empty());
+    }
+
+    #[test]
+    fn reconnects_follow_a_rebind() {
+        let mut reconnects = Reconnects::default();
+        reconnects.connecting(NetToken(1), addrs());
+        reconnects.connected(NetToken(1));
+
+        // Accepted peers have nowhere to reconnect to
+        reconnects.rebound(NetToken(2), 5000);
+
+        reconnects.rebound(NetToken(1), 5000);
+        let action = reconnects.disconnected(NetToken(1), ms(0));
+        assert_eq!(
+            action,
+            Some(ReconnectAction::Retry {
+                addr: PeerAddr::Tcp("10.0.0.2:5000".parse().unwrap()),
+                delay: RECONNECT_MIN_BACKOFF,
+            })
+        );
+    }
+
+    #[test]
+    fn failed_rebinds_keep_the_old_listener() {
+        let old: SocketAddr = "0.0.0.0:44444".parse().unwrap();
+        let new: SocketAddr = "0.0.0.0:5000".parse().unwrap();
+        let socket = PeerAddr::Unix("/tmp/rov.sock".into());
+
+        let mut listeners = Listeners::default();
+        listeners.bound(NetToken(1), PeerAddr::Tcp(old));
+        listeners.bound(NetToken(2), socket.clone());
+
+        assert!(listeners.start_rebind(old).is_err());
+        listeners.start_rebind(new).unwrap();
+        assert!(listeners.start_rebind(new).is_err());
+        assert_eq!(listeners.finish_rebind(), None);
+
+        // Some other bind failing is not ours
+        listeners.bind_failed(&socket);
+        assert_eq!(listeners.finish_rebind(), None);
+
+        listeners.bind_failed(&PeerAddr::Tcp(new));
+        assert_eq!(listeners.finish_rebind(), Some(Err(new)));
+        assert_eq!(listeners.tcp().collect::<Vec<_>>(), [(NetToken(1), old)]);
+
+        // Can be tried again
+        listeners.start_rebind(new).unwrap();
+        listeners.bound(NetToken(3), PeerAddr::Tcp(new));
+        assert_eq!(
+            listeners.finish_rebind(),
+            Some(Ok((new, vec![NetToken(1)])))
+        );
+
+        assert!(listeners.closed(NetToken(1)));
+        assert!(!listeners.closed(NetToken(4)));
+        assert_eq!(listeners.tcp().collect::<Vec<_>>(), [(NetToken(3), new)]);
+    }
+
+    #[test]
+    fn reports_include_the_hint() {
+        let error = NetError::from(std::io::Error::from(std::io::ErrorKind::AddrInUse))
+            .chain("Bind listner".to_owned());
+        let report = NetErrorReport::new(None, &error);
+
+        assert_eq!(report.class, ErrorClass::AddrInUse);
+        assert!(report.describe().ends_with(ErrorClass::AddrInUse.hint()));
+
+        let report =
+            NetErrorReport::new(Some("robot (Token(3))".to_owned()), &NetError::BadHandshake);
+        assert_eq!(
+            report.describe(),
+            format!(
+                "Protocol error with robot (Token(3)): Peer did not send a valid handshake. {}",
+                ErrorClass::Protocol.hint()
+            )
+        );
+    }
+
+    #[derive(Component, Reflect, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+    struct SurfaceOnly(u32);
+
+    #[derive(Event, Reflect, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+    struct RobotOnly(u32);
+
+    fn manifest(app: &App) -> Vec<NetTypeId> {
+        app.world
+            .resource::<SerializationSettings>()
+            .type_manifest()
+    }
+
+    #[test]
+    fn registration_mismatches_are_reported() {
+        let mut robot = test_utils::app(SyncRole::Server { port: None }, &[CLIENT_A, CLIENT_B]);
+        robot.replicate_event::<RobotOnly>();
+        let mut surface = test_utils::app(SyncRole::Client, &[ROBOT]);
+        surface.replicate::<SurfaceOnly>();
+
+        let robot_only: NetTypeId = RobotOnly::type_path().into();
+        let surface_only: NetTypeId = SurfaceOnly::type_path().into();
+
+        let mut robot_quarantine = QuarantinedTypes::default();
+        let mismatch = receive_manifest(
+            robot.world.resource(),
+            &mut robot_quarantine,
+            CLIENT_A,
+            &manifest(&surface),
+        );
+        assert_eq!(mismatch.missing_remotely, slice::from_ref(&robot_only));
+        assert_eq!(mismatch.missing_locally, slice::from_ref(&surface_only));
+
+        let mut surface_quarantine = QuarantinedTypes::default();
+        let mismatch = receive_manifest(
+            surface.world.resource(),
+            &mut surface_quarantine,
+            ROBOT,
+            &manifest(&robot),
+        );
+        assert_eq!(mismatch.missing_remotely, slice::from_ref(&surface_only));
+        assert_eq!(mismatch.missing_locally, slice::from_ref(&robot_only));
+
+        // Only the peer lacking a type is skipped
+        let event = SerializedChange::EventEmitted(robot_only, Default::default());
+        assert!(!robot_quarantine.should_send(&event, CLIENT_A));
+        assert!(robot_quarantine.should_send(&event, CLIENT_B));
+
+        let update = SerializedChange::ComponentUpdated(NetId::random(), surface_only, None);
+        assert!(!surface_quarantine.should_send(&update, ROBOT));
+
+        let shared = SerializedChange::ComponentUpdated(
+            NetId::random(),
+            Test::type_path().into(),
+            Some(Default::default()),
+        );
+        assert!(robot_quarantine.should_send(&shared, CLIENT_A));
+        assert!(surface_quarantine.should_send(&shared, ROBOT));
+
+        // Matching registrations have nothing to report
+        let mut quarantine = QuarantinedTypes::default();
+        let robot_types = manifest(&robot);
+        let mismatch = receive_manifest(
+            robot.world.resource(),
+            &mut quarantine,
+            CLIENT_B,
+            &robot_types,
+        );
+        assert!(mismatch.is_empty());
+        assert!(quarantine.should_send(&event, CLIENT_B));
+    }
+
+    #[derive(Component, Reflect, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+    struct RobotState(u32);
+
+    /// Types of the component updates that reached the app
+    #[derive(Resource, Default)]
+    struct Received(Vec<NetTypeId>);
+
+    fn record_received(
+        mut received: ResMut<Received>,
+        mut changes: EventReader<SerializedChangeInEvent>,
+    ) {
+        for SerializedChangeInEvent(change, _) in changes.read() {
+            if let SerializedChange::ComponentUpdated(_, token, _) = change {
+                received.0.push(token.clone());
+            }
+        }
+    }
+
+    fn loopback_app(name: &str, role: SyncRole, transport: Networking<Protocol>) -> App {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugins(
+                CommonPlugins {
+                    name: name.to_owned(),
+                    role,
+                }
+                .build()
+                .disable::<CtrlCPlugin>(),
+            )
+            .insert_resource(SyncTransport(Some(transport)))
+            .replicate::<Test>();
+
+        app
+    }
+
+    #[test]
+    fn initial_sync_waits_for_the_manifest() {
+        let (acceptor, connector) = Networking::loopback_pair().unwrap();
+        let mut robot = loopback_app("robot", SyncRole::Server { port: None }, acceptor);
+        robot.replicate::<RobotState>();
+        let mut surface = loopback_app("surface", SyncRole::Client, connector);
+        surface
+            .init_resource::<Received>()
+            .add_systems(Update, record_received);
+
+        robot.world.spawn((Replicate, Test(1), RobotState(2)));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut synced = surface.world.query::<&Test>();
+        while synced.iter(&surface.world).next().is_none() {
+            assert!(Instant::now() < deadline, "Surface was never synced");
+
+            robot.update();
+            surface.update();
+            thread::sleep(ms(1));
+        }
+
+        // The robot knew the surface lacks the type before sending it anything
+        let robot_state: NetTypeId = RobotState::type_path().into();
+        let received = &surface.world.resource::<Received>().0;
+        assert!(received.contains(&Test::type_path().into()));
+        assert!(!received.contains(&robot_state), "{received:?}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn syncs_over_a_unix_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("robot.sock");
+
+        // Injected transports aren't bound by the plugin, so the robot's socket is bound here
+        let acceptor = Networking::new().unwrap();
+        acceptor.messenger().bind_unix(path.clone()).unwrap();
+        let mut robot = loopback_app("robot", SyncRole::Server { port: None }, acceptor);
+        let mut surface = loopback_app("surface", SyncRole::Client, Networking::new().unwrap());
+
+        robot.world.spawn((Replicate, Test(1)));
+        robot.update();
+        surface.update();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !path.exists() {
+            assert!(Instant::now() < deadline, "Socket was never bound");
+            thread::sleep(ms(1));
+        }
+        surface
+            .world
+            .send_event(ConnectToPeer(PeerAddr::Unix(path.clone())));
+
+        let mut synced = surface.world.query::<&Test>();
+        let mut peers = surface.world.query::<&Peer>();
+        while synced.iter(&surface.world).next().is_none()
+            || peers.iter(&surface.world).next().is_none()
+        {
+            assert!(Instant::now() < deadline, "Surface was never synced");
+
+            robot.update();
+            surface.update();
+            thread::sleep(ms(1));
+        }
+
+        let addrs = peers
+            .iter(&surface.world)
+            .map(|it| it.addrs.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(addrs, [PeerAddr::Unix(path)]);
+    }
+
+    #[test]
+    fn mismatches_are_attached_to_peer_entities() {
+        let mut app = peer_app();
+        let mismatch = PeerTypeMismatch {
+            missing_remotely: vec!["common::components::Depth".into()],
+            missing_locally: Vec::new(),
+        };
+
+        // Manifest arriving before the singleton
+        let token = NetToken(1);
+        connect(&mut app, token);
+        assert_eq!(
+            app.world
+                .resource_mut::<Peers>()
+                .manifest(token, mismatch.clone()),
+            None
+        );
+        let entity = app.world.spawn((Singleton, ForignOwned(token.0))).id();
+        app.update();
+        assert_eq!(app.world.get::<PeerTypeMismatch>(entity), Some(&mismatch));
+
+        // And after it
+        let token = NetToken(2);
+        connect(&mut app, token);
+        let entity = app.world.spawn((Singleton, ForignOwned(token.0))).id();
+        app.update();
+        assert!(app.world.get::<PeerTypeMismatch>(entity).is_none());
+        assert_eq!(
+            app.world.resource_mut::<Peers>().manifest(token, mismatch),
+            Some(entity)
+        );
+    }
+
+    #[test]
+    fn socket_tuning_is_attached_to_peer_entities() {
+        let mut app = peer_app();
+        let tuning = SocketTuning {
+            nodelay: Some(true),
+            failed: vec!["SO_SNDBUF"],
+            ..default()
+        };
+
+        // Tuned before the singleton arrives
+        let token = NetToken(1);
+        connect(&mut app, token);
+        assert_eq!(
+            app.world
+                .resource_mut::<Peers>()
+                .tuned(token, tuning.clone()),
+            None
+        );
+        let entity = app.world.spawn((Singleton, ForignOwned(token.0))).id();
+        app.update();
+        assert_eq!(
+            app.world.get::<PeerSocketTuning>(entity),
+            Some(&PeerSocketTuning(tuning.clone()))
+        );
+
+        // Peers that missed the deadline still report it
+        let token = NetToken(2);
+        connect(&mut app, token);
+        app.world
+            .resource_mut::<Peers>()
+            .tuned(token, tuning.clone());
+        app.world.resource_mut::<FrameCount>().0 += 100;
+        app.update();
+
+        let entity = app.world.resource::<Peers>().by_token[&token];
+        assert_eq!(
+            app.world.get::<PeerSocketTuning>(entity),
+            Some(&PeerSocketTuning(tuning))
+        );
+    }
+}
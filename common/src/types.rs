@@ -1,12 +1,19 @@
 use bevy::app::App;
 
+pub mod bounded;
+pub mod checklist;
+pub mod files;
 pub mod hw;
+pub mod ids;
 pub mod system;
 pub mod units;
 pub mod utils;
 
 pub fn register_types(app: &mut App) {
+    checklist::register_types(app);
+    files::register_types(app);
     hw::register_types(app);
+    ids::register_types(app);
     system::register_types(app);
     units::register_types(app);
     utils::register_types(app);
@@ -0,0 +1,414 @@
+//! Collections with a maximum length enforced on the wire
+//!
+//! A peer could otherwise make us allocate and deserialize as many entries as it likes. The
+//! encoding is the same as the plain [`BTreeMap`] or [`Vec`], so switching a component over is
+//! not a protocol change as long as senders stay within the bound. Maps are encoded in key order.
+//!
+//! Deserializing and the conversions below enforce the bound, anything grown past it afterwards
+//! still serializes and is rejected by the receiver instead of failing here.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug, Formatter},
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+};
+
+use bevy::reflect::{Reflect, ReflectDeserialize, ReflectSerialize};
+use serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use thiserror::Error;
+use tracing::warn;
+
+/// A collection had more entries than its bound allows
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("{len} entries is over the limit of {max}")]
+pub struct TooLong {
+    pub len: usize,
+    pub max: usize,
+}
+
+/// A [`BTreeMap`] that refuses to deserialize more than `MAX` entries
+#[derive(Reflect, Clone, PartialEq, Eq)]
+#[reflect_value(
+    Debug,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    where
+        K: Ord + Clone + Debug + PartialEq + Serialize + for<'de> Deserialize<'de>,
+        V: Clone + Debug + PartialEq + Serialize + for<'de> Deserialize<'de>
+)]
+pub struct BoundedMap<K, V, const MAX: usize>(BTreeMap<K, V>);
+
+/// A [`Vec`] that refuses to deserialize more than `MAX` elements
+#[derive(Reflect, Clone, PartialEq, Eq)]
+#[reflect_value(
+    Debug,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    where T: Clone + Debug + PartialEq + Serialize + for<'de> Deserialize<'de>
+)]
+pub struct BoundedVec<T, const MAX: usize>(Vec<T>);
+
+impl<K, V, const MAX: usize> BoundedMap<K, V, MAX> {
+    pub const MAX: usize = MAX;
+
+    pub fn new() -> Self {
+        Self(BTreeMap::new())
+    }
+
+    pub fn into_inner(self) -> BTreeMap<K, V> {
+        self.0
+    }
+}
+
+impl<T, const MAX: usize> BoundedVec<T, MAX> {
+    pub const MAX: usize = MAX;
+
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn into_inner(self) -> Vec<T> {
+        self.0
+    }
+
+    /// Keeps the first `MAX` elements, for values where losing the rest is better than failing
+    pub fn truncating_from(values: impl IntoIterator<Item = T>) -> Self {
+        let mut values = values.into_iter().collect::<Vec<_>>();
+        if values.len() > MAX {
+            warn!(
+                "Dropped {} {} past the limit of {MAX}",
+                values.len() - MAX,
+                std::any::type_name::<T>()
+            );
+            values.truncate(MAX);
+        }
+
+        Self(values)
+    }
+}
+
+impl<K, V, const MAX: usize> Default for BoundedMap<K, V, MAX> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const MAX: usize> Default for BoundedVec<T, MAX> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Debug, V: Debug, const MAX: usize> Debug for BoundedMap<K, V, MAX> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T: Debug, const MAX: usize> Debug for BoundedVec<T, MAX> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<K, V, const MAX: usize> Deref for BoundedMap<K, V, MAX> {
+    type Target = BTreeMap<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<K, V, const MAX: usize> DerefMut for BoundedMap<K, V, MAX> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T, const MAX: usize> Deref for BoundedVec<T, MAX> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T, const MAX: usize> DerefMut for BoundedVec<T, MAX> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<K, V, const MAX: usize> TryFrom<BTreeMap<K, V>> for BoundedMap<K, V, MAX> {
+    type Error = TooLong;
+
+    fn try_from(value: BTreeMap<K, V>) -> Result<Self, Self::Error> {
+        check_len(value.len(), MAX)?;
+
+        Ok(Self(value))
+    }
+}
+
+/// Fails to compile when `N` is over `MAX`
+impl<K: Ord, V, const MAX: usize, const N: usize> From<[(K, V); N]> for BoundedMap<K, V, MAX> {
+    fn from(value: [(K, V); N]) -> Self {
+        const { assert!(N <= MAX, "array is longer than the bound") };
+
+        Self(BTreeMap::from(value))
+    }
+}
+
+impl<T, const MAX: usize> TryFrom<Vec<T>> for BoundedVec<T, MAX> {
+    type Error = TooLong;
+
+    fn try_from(value: Vec<T>) -> Result<Self, Self::Error> {
+        check_len(value.len(), MAX)?;
+
+        Ok(Self(value))
+    }
+}
+
+/// Fails to compile when `N` is over `MAX`
+impl<T, const MAX: usize, const N: usize> From<[T; N]> for BoundedVec<T, MAX> {
+    fn from(value: [T; N]) -> Self {
+        const { assert!(N <= MAX, "array is longer than the bound") };
+
+        Self(Vec::from(value))
+    }
+}
+
+impl<'a, K, V, const MAX: usize> IntoIterator for &'a BoundedMap<K, V, MAX> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::collections::btree_map::Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<K, V, const MAX: usize> IntoIterator for BoundedMap<K, V, MAX> {
+    type Item = (K, V);
+    type IntoIter = std::collections::btree_map::IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a, T, const MAX: usize> IntoIterator for &'a BoundedVec<T, MAX> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<T, const MAX: usize> IntoIterator for BoundedVec<T, MAX> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+fn check_len(len: usize, max: usize) -> Result<(), TooLong> {
+    if len > max {
+        return Err(TooLong { len, max });
+    }
+
+    Ok(())
+}
+
+impl<K: Serialize, V: Serialize, const MAX: usize> Serialize for BoundedMap<K, V, MAX> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(&self.0)
+    }
+}
+
+impl<T: Serialize, const MAX: usize> Serialize for BoundedVec<T, MAX> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(&self.0)
+    }
+}
+
+struct BoundedMapVisitor<K, V, const MAX: usize>(PhantomData<(K, V)>);
+
+impl<'de, K, V, const MAX: usize> Visitor<'de> for BoundedMapVisitor<K, V, MAX>
+where
+    K: Ord + Deserialize<'de>,
+    V: Deserialize<'de>,
+{
+    type Value = BoundedMap<K, V, MAX>;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "a map with at most {MAX} entries")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        // Reject before reading anything when the length is known up front
+        if let Some(len) = map.size_hint().filter(|it| *it > MAX) {
+            return Err(de::Error::custom(TooLong { len, max: MAX }));
+        }
+
+        let mut values = BTreeMap::new();
+        let mut len = 0;
+        while let Some((key, value)) = map.next_entry()? {
+            len += 1;
+            if len > MAX {
+                return Err(de::Error::custom(TooLong { len, max: MAX }));
+            }
+
+            values.insert(key, value);
+        }
+
+        Ok(BoundedMap(values))
+    }
+}
+
+impl<'de, K, V, const MAX: usize> Deserialize<'de> for BoundedMap<K, V, MAX>
+where
+    K: Ord + Deserialize<'de>,
+    V: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(BoundedMapVisitor(PhantomData))
+    }
+}
+
+struct BoundedVecVisitor<T, const MAX: usize>(PhantomData<T>);
+
+impl<'de, T: Deserialize<'de>, const MAX: usize> Visitor<'de> for BoundedVecVisitor<T, MAX> {
+    type Value = BoundedVec<T, MAX>;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "a sequence with at most {MAX} elements")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        if let Some(len) = seq.size_hint().filter(|it| *it > MAX) {
+            return Err(de::Error::custom(TooLong { len, max: MAX }));
+        }
+
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(value) = seq.next_element()? {
+            if values.len() == MAX {
+                return Err(de::Error::custom(TooLong {
+                    len: MAX + 1,
+                    max: MAX,
+                }));
+            }
+
+            values.push(value);
+        }
+
+        Ok(BoundedVec(values))
+    }
+}
+
+impl<'de, T: Deserialize<'de>, const MAX: usize> Deserialize<'de> for BoundedVec<T, MAX> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(BoundedVecVisitor(PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bincode::Options;
+    use serde::{de::DeserializeOwned, Serialize};
+
+    use super::{BoundedMap, BoundedVec, TooLong};
+
+    fn encode<T: Serialize>(value: &T) -> bincode::Result<Vec<u8>> {
+        bincode::DefaultOptions::new().serialize(value)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> bincode::Result<T> {
+        bincode::DefaultOptions::new().deserialize(bytes)
+    }
+
+    fn map(len: u8) -> BTreeMap<u8, f32> {
+        (0..len).map(|it| (it, it as f32 * 0.5)).collect()
+    }
+
+    #[test]
+    fn round_trips() {
+        let bounded: BoundedMap<u8, f32, 8> = map(8).try_into().unwrap();
+        let decoded: BoundedMap<u8, f32, 8> = decode(&encode(&bounded).unwrap()).unwrap();
+        assert_eq!(decoded, bounded);
+
+        let bounded: BoundedVec<String, 4> = ["a".to_owned(), "b".to_owned()].into();
+        let decoded: BoundedVec<String, 4> = decode(&encode(&bounded).unwrap()).unwrap();
+        assert_eq!(decoded, bounded);
+
+        let empty = BoundedVec::<u8, 4>::new();
+        assert_eq!(
+            decode::<BoundedVec<u8, 4>>(&encode(&empty).unwrap()).unwrap(),
+            empty
+        );
+    }
+
+    #[test]
+    fn wire_encoding_matches_the_plain_collections() {
+        let plain = map(8);
+        let bounded: BoundedMap<u8, f32, 8> = plain.clone().try_into().unwrap();
+        assert_eq!(encode(&bounded).unwrap(), encode(&plain).unwrap());
+
+        let plain = vec![1u32, 300, 70_000];
+        let bounded: BoundedVec<u32, 8> = plain.clone().try_into().unwrap();
+        assert_eq!(encode(&bounded).unwrap(), encode(&plain).unwrap());
+
+        // Old senders can still be read
+        assert_eq!(
+            decode::<BoundedMap<u8, f32, 8>>(&encode(&map(3)).unwrap()).unwrap(),
+            map(3).try_into().unwrap()
+        );
+    }
+
+    #[test]
+    fn over_limit_is_rejected() {
+        let bytes = encode(&map(9)).unwrap();
+        assert!(decode::<BoundedMap<u8, f32, 8>>(&bytes).is_err());
+
+        let bytes = encode(&vec![0u8; 5]).unwrap();
+        assert!(decode::<BoundedVec<u8, 4>>(&bytes).is_err());
+
+        // Grown past the limit after construction still encodes, the receiver rejects it
+        let mut bounded = BoundedVec::<u8, 4>::new();
+        bounded.extend([0; 5]);
+        assert_eq!(encode(&bounded).unwrap(), encode(&vec![0u8; 5]).unwrap());
+    }
+
+    #[test]
+    fn conversions_past_the_limit_fail() {
+        let too_long = TooLong { len: 12, max: 8 };
+        assert_eq!(BoundedMap::<u8, f32, 8>::try_from(map(12)), Err(too_long));
+
+        let too_long = TooLong { len: 6, max: 4 };
+        assert_eq!(
+            BoundedVec::<u8, 4>::try_from(vec![1, 2, 3, 4, 5, 6]),
+            Err(too_long)
+        );
+
+        // Only done on purpose
+        let bounded = BoundedVec::<u8, 4>::truncating_from(1..=6);
+        assert_eq!(*bounded, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn huge_length_prefix_is_rejected_without_reading_entries() {
+        // A length prefix claiming 10k entries with no data behind it
+        let bytes = encode(&10_000u64).unwrap();
+        let err = decode::<BoundedMap<u8, f32, 64>>(&bytes).unwrap_err();
+        assert!(err.to_string().contains("over the limit"), "{err}");
+    }
+}
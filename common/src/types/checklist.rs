@@ -0,0 +1,196 @@
+use std::time::Duration;
+
+use bevy::{
+    app::App,
+    reflect::{std_traits::ReflectDefault, Reflect, ReflectDeserialize, ReflectSerialize},
+};
+use serde::{Deserialize, Serialize};
+
+/// The scored tasks of a mission run and when each was done
+#[derive(Debug, Clone, Serialize, Deserialize, Reflect, PartialEq, Default)]
+#[reflect(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct Checklist {
+    pub tasks: Vec<ChecklistTask>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Reflect, PartialEq, Default)]
+#[reflect(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct ChecklistTask {
+    pub name: String,
+    /// Awarded once the task itself is checked, steps are only there to keep track
+    pub points: u32,
+    pub steps: Vec<ChecklistStep>,
+    /// Time since the unix epoch it was checked, on the station that checked it
+    pub checked: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Reflect, PartialEq, Default)]
+#[reflect(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct ChecklistStep {
+    pub name: String,
+    /// Time since the unix epoch it was checked, on the station that checked it
+    pub checked: Option<Duration>,
+}
+
+/// A task or one of its steps
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Reflect, PartialEq, Eq, Hash)]
+#[reflect(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ChecklistItem {
+    pub task: usize,
+    pub step: Option<usize>,
+}
+
+impl Checklist {
+    /// Points of every checked task
+    pub fn points(&self) -> u32 {
+        self.tasks
+            .iter()
+            .filter(|task| task.checked.is_some())
+            .map(|task| task.points)
+            .sum()
+    }
+
+    pub fn max_points(&self) -> u32 {
+        self.tasks.iter().map(|task| task.points).sum()
+    }
+
+    /// When `item` was checked, `None` if it doesn't exist
+    pub fn checked(&self, item: ChecklistItem) -> Option<Option<Duration>> {
+        let task = self.tasks.get(item.task)?;
+
+        match item.step {
+            Some(step) => task.steps.get(step).map(|step| step.checked),
+            None => Some(task.checked),
+        }
+    }
+
+    /// Checks or unchecks `item` at `timestamp`, returns whether anything changed
+    ///
+    /// Items already in the requested state are left alone, so when two stations check the same
+    /// item at once the first one to arrive sets the timestamp and the other is dropped
+    pub fn set(&mut self, item: ChecklistItem, checked: bool, timestamp: Duration) -> bool {
+        let Some(task) = self.tasks.get_mut(item.task) else {
+            return false;
+        };
+
+        let slot = match item.step {
+            Some(step) => match task.steps.get_mut(step) {
+                Some(step) => &mut step.checked,
+                None => return false,
+            },
+            None => &mut task.checked,
+        };
+
+        if slot.is_some() == checked {
+            return false;
+        }
+
+        *slot = checked.then_some(timestamp);
+
+        true
+    }
+}
+
+pub fn register_types(app: &mut App) {
+    app.register_type::<Checklist>()
+        .register_type::<ChecklistTask>()
+        .register_type::<ChecklistStep>()
+        .register_type::<ChecklistItem>();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Checklist, ChecklistItem, ChecklistStep, ChecklistTask};
+
+    fn checklist() -> Checklist {
+        let task = |name: &str, points, steps: &[&str]| ChecklistTask {
+            name: name.to_owned(),
+            points,
+            steps: steps
+                .iter()
+                .map(|&name| ChecklistStep {
+                    name: name.to_owned(),
+                    checked: None,
+                })
+                .collect(),
+            checked: None,
+        };
+
+        Checklist {
+            tasks: vec![
+                task("Deploy the float", 10, &[]),
+                task(
+                    "Replace the filter",
+                    20,
+                    &["Remove the old filter", "Install"],
+                ),
+            ],
+        }
+    }
+
+    fn task(task: usize) -> ChecklistItem {
+        ChecklistItem { task, step: None }
+    }
+
+    fn step(task: usize, step: usize) -> ChecklistItem {
+        ChecklistItem {
+            task,
+            step: Some(step),
+        }
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn points_follow_checked_tasks() {
+        let mut checklist = checklist();
+        assert_eq!(checklist.points(), 0);
+        assert_eq!(checklist.max_points(), 30);
+
+        // Steps don't score on their own
+        assert!(checklist.set(step(1, 0), true, secs(5)));
+        assert!(checklist.set(step(1, 1), true, secs(6)));
+        assert_eq!(checklist.points(), 0);
+
+        assert!(checklist.set(task(1), true, secs(7)));
+        assert_eq!(checklist.points(), 20);
+
+        assert!(checklist.set(task(1), false, secs(8)));
+        assert_eq!(checklist.points(), 0);
+        assert_eq!(checklist.checked(task(1)), Some(None));
+        assert_eq!(checklist.checked(step(1, 1)), Some(Some(secs(6))));
+    }
+
+    #[test]
+    fn racing_toggles_keep_the_first_timestamp() {
+        let mut checklist = checklist();
+
+        // Both stations check the float, the second is a no-op
+        assert!(checklist.set(task(0), true, secs(100)));
+        assert!(!checklist.set(task(0), true, secs(101)));
+        assert_eq!(checklist.checked(task(0)), Some(Some(secs(100))));
+
+        // Unchecking wins if it arrives last
+        assert!(checklist.set(task(0), false, secs(102)));
+        assert!(!checklist.set(task(0), false, secs(103)));
+        assert_eq!(checklist.checked(task(0)), Some(None));
+    }
+
+    #[test]
+    fn unknown_items_are_ignored() {
+        let mut checklist = checklist();
+        let before = checklist.clone();
+
+        assert!(!checklist.set(task(2), true, secs(1)));
+        assert!(!checklist.set(step(0, 0), true, secs(1)));
+        assert!(!checklist.set(step(1, 2), true, secs(1)));
+        assert_eq!(checklist, before);
+
+        assert_eq!(checklist.checked(task(2)), None);
+        assert_eq!(checklist.checked(step(1, 2)), None);
+    }
+}
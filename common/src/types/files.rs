@@ -0,0 +1,107 @@
+use std::path::{Component, Path};
+
+use bevy::{
+    app::App,
+    reflect::{std_traits::ReflectDefault, Reflect, ReflectDeserialize, ReflectSerialize},
+};
+use serde::{Deserialize, Serialize};
+
+/// Most entries sent in one [`crate::events::FileListing`], the most recently modified are kept
+pub const MAX_LISTING_ENTRIES: usize = 1000;
+
+/// A file in one of the directories the robot lets the surface browse
+#[derive(Debug, Clone, Serialize, Deserialize, Reflect, PartialEq, Eq, Default)]
+#[reflect(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct FileEntry {
+    pub name: String,
+    /// In bytes
+    pub size: u64,
+    /// Seconds since the unix epoch
+    pub modified: u64,
+}
+
+/// Whether `name` is a single file name, with no separators or `.` and `..` to reach out of the
+/// directory it is looked up in
+pub fn is_plain_file_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(first)), None) => first == name,
+        _ => false,
+    }
+}
+
+pub fn register_types(app: &mut App) {
+    app.register_type::<FileEntry>();
+}
+
+#[cfg(test)]
+mod tests {
+    use bincode::Options;
+
+    use crate::{
+        events::FileListing,
+        protocol::{MAX_NAME_LEN, MAX_PAYLOAD_LEN},
+    };
+
+    use super::{is_plain_file_name, FileEntry, MAX_LISTING_ENTRIES};
+
+    #[test]
+    fn plain_file_names() {
+        assert!(is_plain_file_name("still_1712345678.jpg"));
+        assert!(is_plain_file_name(".hidden"));
+        assert!(is_plain_file_name("with space.txt"));
+
+        assert!(!is_plain_file_name(""));
+        assert!(!is_plain_file_name("."));
+        assert!(!is_plain_file_name(".."));
+        assert!(!is_plain_file_name("../robot.toml"));
+        assert!(!is_plain_file_name("logs/today.csv"));
+        assert!(!is_plain_file_name("/etc/passwd"));
+        assert!(!is_plain_file_name("trailing/"));
+        assert!(!is_plain_file_name("./still.jpg"));
+    }
+
+    #[test]
+    fn listing_round_trips() {
+        let listing = FileListing {
+            dir: "stills".to_owned(),
+            entries: vec![
+                FileEntry {
+                    name: "Front_1712345678.jpg".to_owned(),
+                    size: 183_402,
+                    modified: 1_712_345_678,
+                },
+                FileEntry {
+                    name: "empty".to_owned(),
+                    size: 0,
+                    modified: 0,
+                },
+            ],
+            truncated: false,
+            error: None,
+        };
+
+        let options = bincode::DefaultOptions::new();
+        let bytes = options.serialize(&listing).unwrap();
+        assert_eq!(options.deserialize::<FileListing>(&bytes).unwrap(), listing);
+    }
+
+    #[test]
+    fn full_listing_fits_in_a_payload() {
+        let entry = FileEntry {
+            name: "x".repeat(MAX_NAME_LEN),
+            size: u64::MAX,
+            modified: u64::MAX,
+        };
+        let listing = FileListing {
+            dir: "x".repeat(MAX_NAME_LEN),
+            entries: vec![entry; MAX_LISTING_ENTRIES],
+            truncated: true,
+            error: None,
+        };
+
+        let bytes = bincode::DefaultOptions::new().serialize(&listing).unwrap();
+        assert!(bytes.len() < MAX_PAYLOAD_LEN, "{} bytes", bytes.len());
+    }
+}
@@ -12,6 +12,20 @@ use super::units::{Celsius, Dps, GForce, Gauss, Mbar, Meters};
 
 pub type PwmChannelId = u8;
 
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, Reflect, PartialEq, Eq, Default)]
+#[reflect(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct Rgb8 {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb8 {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
 //
 // Input
 //
@@ -28,6 +42,9 @@ pub struct InertialFrame {
     pub accel_z: GForce,
 
     pub tempature: Celsius,
+
+    /// [`crate::clock::monotonic_seconds`] on the robot when the reading was taken
+    pub capture_time: f64,
 }
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, Reflect, PartialEq, Default)]
@@ -36,6 +53,9 @@ pub struct MagneticFrame {
     pub mag_x: Gauss,
     pub mag_y: Gauss,
     pub mag_z: Gauss,
+
+    /// [`crate::clock::monotonic_seconds`] on the robot when the reading was taken
+    pub capture_time: f64,
 }
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, Reflect, PartialEq, Default)]
@@ -46,10 +66,14 @@ pub struct DepthFrame {
     pub pressure: Mbar,
 
     pub temperature: Celsius,
+
+    /// [`crate::clock::monotonic_seconds`] on the robot when the reading was taken
+    pub capture_time: f64,
 }
 
 pub fn register_types(app: &mut App) {
-    app.register_type::<InertialFrame>()
+    app.register_type::<Rgb8>()
+        .register_type::<InertialFrame>()
         .register_type::<MagneticFrame>()
         .register_type::<DepthFrame>();
 }
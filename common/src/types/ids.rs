@@ -0,0 +1,169 @@
+//! Typed names for the robot's servos and cameras
+//!
+//! Both serialize as plain strings. There are deliberately no conversions from `&str` or
+//! `String`, so every id in the code base is spelled out with [`ServoId::new`] or
+//! [`ServoId::from_static`] and can be found by searching for its type.
+//!
+//! ```
+//! use common::{components::ServoContribution, events::ResetServo, types::ids::ServoId};
+//!
+//! let _ = ResetServo(ServoId::from_static("Claw1"));
+//! let _ = ServoContribution([(ServoId::from_static("Claw1"), 1.0)].into());
+//! ```
+//!
+//! ```compile_fail
+//! use common::events::ResetServo;
+//!
+//! let _ = ResetServo("Claw1".into());
+//! ```
+//!
+//! ```compile_fail
+//! use common::components::ServoContribution;
+//!
+//! let _ = ServoContribution([("Claw1".into(), 1.0)].into());
+//! ```
+
+use std::{
+    borrow::Cow,
+    collections::BTreeSet,
+    fmt::{Display, Formatter},
+};
+
+use bevy::{
+    app::App,
+    reflect::{std_traits::ReflectDefault, Reflect, ReflectDeserialize, ReflectSerialize},
+};
+use serde::{Deserialize, Serialize};
+
+macro_rules! id {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(
+            Debug,
+            Clone,
+            Default,
+            Serialize,
+            Deserialize,
+            Reflect,
+            PartialEq,
+            Eq,
+            PartialOrd,
+            Ord,
+            Hash,
+        )]
+        #[serde(transparent)]
+        #[reflect(Serialize, Deserialize, Debug, PartialEq, Default)]
+        pub struct $name(pub Cow<'static, str>);
+
+        impl $name {
+            pub fn new(id: impl Into<Cow<'static, str>>) -> Self {
+                Self(id.into())
+            }
+
+            pub const fn from_static(id: &'static str) -> Self {
+                Self(Cow::Borrowed(id))
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+                f.pad(&self.0)
+            }
+        }
+    };
+}
+
+id! {
+    /// Name of a servo, as given in the robot's servo config
+    ServoId
+}
+
+id! {
+    /// Name of a camera, its display name from the robot's config or its device when it has none
+    CameraId
+}
+
+/// Finds references to ids the robot doesn't have, reporting each only once
+#[derive(Debug, Clone)]
+pub struct UnknownIds<T> {
+    reported: BTreeSet<T>,
+}
+
+impl<T> Default for UnknownIds<T> {
+    fn default() -> Self {
+        Self {
+            reported: BTreeSet::new(),
+        }
+    }
+}
+
+impl<T: Ord + Clone> UnknownIds<T> {
+    /// Ids in `referenced` that aren't in `known` and weren't returned by an earlier call
+    pub fn check<'a>(&mut self, known: &[T], referenced: impl IntoIterator<Item = &'a T>) -> Vec<T>
+    where
+        T: 'a,
+    {
+        let mut unknown = Vec::new();
+
+        for id in referenced {
+            if !known.contains(id) && self.reported.insert(id.clone()) {
+                unknown.push(id.clone());
+            }
+        }
+
+        unknown
+    }
+}
+
+pub fn register_types(app: &mut App) {
+    app.register_type::<ServoId>().register_type::<CameraId>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CameraId, ServoId, UnknownIds};
+
+    const CLAW: ServoId = ServoId::from_static("Claw1");
+    const TILT: ServoId = ServoId::from_static("FrontCameraRotate");
+
+    #[test]
+    fn known_ids_pass() {
+        let mut unknown = UnknownIds::default();
+
+        assert!(unknown.check(&[CLAW, TILT], [&CLAW, &TILT]).is_empty());
+    }
+
+    #[test]
+    fn unknown_ids_are_reported_once() {
+        let mut unknown = UnknownIds::default();
+        let typo = ServoId::new("Claw 1".to_owned());
+
+        assert_eq!(
+            unknown.check(&[CLAW], [&typo, &CLAW, &typo]),
+            vec![typo.clone()]
+        );
+        assert!(unknown.check(&[CLAW], [&typo]).is_empty());
+
+        // Another bad id is still reported
+        assert_eq!(unknown.check(&[CLAW], [&TILT]), vec![TILT]);
+    }
+
+    #[test]
+    fn ids_serialize_as_strings() {
+        let camera = CameraId::from_static("Front");
+
+        assert_eq!(serde_json::to_string(&camera).unwrap(), "\"Front\"");
+        assert_eq!(
+            serde_json::from_str::<CameraId>("\"Front\"").unwrap(),
+            camera
+        );
+        assert_eq!(
+            bincode::serialize(&camera).unwrap(),
+            bincode::serialize("Front").unwrap()
+        );
+    }
+}
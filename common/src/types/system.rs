@@ -55,10 +55,22 @@ pub struct Network {
     pub tx_errors: u64,
 }
 
+/// Outbound replication rate of a single type, see [`crate::ecs_sync::stats::SyncStats`]
+#[derive(Debug, Clone, Serialize, Deserialize, Reflect, PartialEq)]
+#[reflect(Serialize, Deserialize, Debug, PartialEq)]
+pub struct SyncTypeRate {
+    pub name: String,
+    pub per_second: u32,
+    pub bytes_per_second: u64,
+    /// Updates per second of recent windows, oldest first
+    pub history: Vec<u32>,
+}
+
 pub fn register_types(app: &mut App) {
     app.register_type::<Process>()
         .register_type::<Cpu>()
         .register_type::<ComponentTemperature>()
         .register_type::<Disk>()
-        .register_type::<Network>();
+        .register_type::<Network>()
+        .register_type::<SyncTypeRate>();
 }
@@ -34,6 +34,22 @@ impl PidController {
         delta_target: f32,
         config: &PidConfig,
         interval: Duration,
+    ) -> PidResult {
+        let dt = interval.as_secs_f32();
+        let derivative = (error - self.last_error.unwrap_or(error)) / dt;
+
+        self.update_with_rate(error, derivative, delta_target, config, interval)
+    }
+
+    /// Like [`PidController::update`] with a measured rate of change of `error`, rather than
+    /// differentiating noisy errors
+    pub fn update_with_rate(
+        &mut self,
+        error: f32,
+        error_rate: f32,
+        delta_target: f32,
+        config: &PidConfig,
+        interval: Duration,
     ) -> PidResult {
         let cfg = config;
         let interval = interval.as_secs_f32();
@@ -43,7 +59,7 @@ impl PidController {
 
         let proportional = error;
         let integral = self.integral;
-        let derivative = (error - self.last_error.unwrap_or(error)) / interval;
+        let derivative = error_rate;
 
         self.last_deltas[self.delta_idx % self.last_deltas.len()] = delta_target;
         let avg_delta_target = self.last_deltas.iter().sum::<f32>() / self.last_deltas.len() as f32;
@@ -0,0 +1,442 @@
+//! Low rate video sent as JPEG frames over the sync connection
+//!
+//! A fallback for networks that drop the UDP streams cameras normally use. The surface asks for
+//! it with a [`CameraFallbackStream`], the robot then sends a [`SendVideoFrame`] for every frame it
+//! captures. Frames are acknowledged as they arrive and at most [`MAX_FRAMES_IN_FLIGHT`] may be
+//! unacknowledged per peer and camera, any more are dropped rather than queued behind a saturated
+//! link.
+//!
+//! Frames share the TCP stream with everything else, including pings. Unacknowledged frames are
+//! also capped at [`MAX_BYTES_IN_FLIGHT`] per peer, so a ping never waits behind more than the
+//! link can drain well within the latency a peer is allowed before it is disconnected.
+//!
+//! [`CameraFallbackStream`]: crate::components::CameraFallbackStream
+
+use std::collections::VecDeque;
+
+use ahash::HashMap;
+use anyhow::anyhow;
+use bevy::prelude::*;
+use networking::Token as NetToken;
+
+use crate::{
+    error,
+    protocol::Protocol,
+    sync::{Net, Peers},
+    types::ids::CameraId,
+};
+
+/// Largest encoded frame sent or accepted
+pub const MAX_VIDEO_FRAME_LEN: usize = 48 * 1024;
+/// Frames sent to a peer that it hasn't acknowledged yet, per camera
+pub const MAX_FRAMES_IN_FLIGHT: u32 = 2;
+/// Bytes of frames sent to a peer that it hasn't acknowledged yet, for all cameras together
+///
+/// Drains in under 100ms on the 4 Mbit/s links fallback video is meant for
+pub const MAX_BYTES_IN_FLIGHT: usize = 48 * 1024;
+
+pub struct VideoFramePlugin;
+
+impl Plugin for VideoFramePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SendVideoFrame>()
+            .add_event::<VideoFrameIn>()
+            .add_event::<VideoFrameAckIn>()
+            .init_resource::<VideoFrameWindow>()
+            .add_systems(Update, send_frames.pipe(error::handle_errors));
+    }
+}
+
+/// Sends a JPEG frame from `camera` to all connected peers
+#[derive(Event, Debug, Clone)]
+pub struct SendVideoFrame {
+    pub camera: CameraId,
+    pub jpeg: Vec<u8>,
+}
+
+/// A JPEG frame arrived from a peer
+#[derive(Event, Debug, Clone)]
+pub struct VideoFrameIn {
+    pub peer: NetToken,
+    pub camera: CameraId,
+    pub seq: u32,
+    pub jpeg: Vec<u8>,
+}
+
+#[derive(Event, Debug)]
+pub(crate) struct VideoFrameAckIn(pub NetToken, pub CameraId, pub u32);
+
+/// Why a frame was not sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameDrop {
+    /// Larger than [`MAX_VIDEO_FRAME_LEN`]
+    TooLarge,
+    /// The peer hasn't caught up with the frames already sent
+    Saturated,
+}
+
+/// Frames sent to each peer and how many of them were acknowledged
+#[derive(Resource, Debug, Default)]
+pub struct VideoFrameWindow {
+    streams: HashMap<(NetToken, CameraId), FrameCounts>,
+    /// Unacknowledged bytes per peer
+    bytes_in_flight: HashMap<NetToken, usize>,
+    dropped: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct FrameCounts {
+    sent: u32,
+    acked: u32,
+    /// Length of every unacknowledged frame, oldest first
+    in_flight: VecDeque<usize>,
+}
+
+impl VideoFrameWindow {
+    /// Claims the sequence number of a `len` byte frame from `camera` to `peer`
+    pub fn reserve(
+        &mut self,
+        peer: NetToken,
+        camera: &CameraId,
+        len: usize,
+    ) -> Result<u32, FrameDrop> {
+        let rst = if len > MAX_VIDEO_FRAME_LEN {
+            Err(FrameDrop::TooLarge)
+        } else {
+            let counts = self.streams.entry((peer, camera.clone())).or_default();
+            let bytes = self.bytes_in_flight.entry(peer).or_default();
+
+            if counts.sent.wrapping_sub(counts.acked) >= MAX_FRAMES_IN_FLIGHT
+                || *bytes + len > MAX_BYTES_IN_FLIGHT
+            {
+                Err(FrameDrop::Saturated)
+            } else {
+                let seq = counts.sent;
+                counts.sent = seq.wrapping_add(1);
+                counts.in_flight.push_back(len);
+                *bytes += len;
+
+                Ok(seq)
+            }
+        };
+
+        if rst.is_err() {
+            self.dropped += 1;
+        }
+
+        rst
+    }
+
+    /// Marks every frame up to `seq` as received, acks for frames never sent are ignored
+    pub fn ack(&mut self, peer: NetToken, camera: &CameraId, seq: u32) {
+        let Some(counts) = self.streams.get_mut(&(peer, camera.clone())) else {
+            return;
+        };
+
+        let through = seq.wrapping_add(1);
+        let newly_acked = through.wrapping_sub(counts.acked);
+        if newly_acked <= counts.sent.wrapping_sub(counts.acked) {
+            counts.acked = through;
+
+            let acked_bytes: usize = counts.in_flight.drain(..newly_acked as usize).sum();
+            if let Some(bytes) = self.bytes_in_flight.get_mut(&peer) {
+                *bytes -= acked_bytes;
+            }
+        }
+    }
+
+    /// Gives back the newest reservation, `seq`, after the frame could not be sent
+    pub fn release(&mut self, peer: NetToken, camera: &CameraId, seq: u32) {
+        let Some(counts) = self.streams.get_mut(&(peer, camera.clone())) else {
+            return;
+        };
+
+        if seq.wrapping_add(1) != counts.sent || counts.sent == counts.acked {
+            return;
+        }
+
+        counts.sent = seq;
+        let len = counts.in_flight.pop_back().unwrap_or_default();
+        if let Some(bytes) = self.bytes_in_flight.get_mut(&peer) {
+            *bytes -= len;
+        }
+    }
+
+    /// Forgets peers that do not match `keep`
+    pub fn retain_peers(&mut self, mut keep: impl FnMut(NetToken) -> bool) {
+        self.streams.retain(|(peer, _), _| keep(*peer));
+        self.bytes_in_flight.retain(|peer, _| keep(*peer));
+    }
+
+    /// Frames dropped since startup
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+fn send_frames(
+    net: Res<Net>,
+    peers: Res<Peers>,
+    mut window: ResMut<VideoFrameWindow>,
+    mut frames: EventReader<SendVideoFrame>,
+    mut acks: EventReader<VideoFrameAckIn>,
+) -> anyhow::Result<()> {
+    window.retain_peers(|it| peers.valid_tokens.contains(&it));
+
+    for VideoFrameAckIn(peer, camera, seq) in acks.read() {
+        window.ack(*peer, camera, *seq);
+    }
+
+    for frame in frames.read() {
+        for &peer in &peers.valid_tokens {
+            let seq = match window.reserve(peer, &frame.camera, frame.jpeg.len()) {
+                Ok(seq) => seq,
+                Err(FrameDrop::TooLarge) => {
+                    warn!(
+                        "Dropped a {} byte frame from {}, too large to send",
+                        frame.jpeg.len(),
+                        frame.camera
+                    );
+                    break;
+                }
+                Err(FrameDrop::Saturated) => continue,
+            };
+
+            let packet = Protocol::VideoFrame {
+                camera_id: frame.camera.clone(),
+                seq,
+                jpeg: frame.jpeg.clone(),
+            };
+            let rst = net.0.send_packet(peer, packet);
+            if rst.is_err() {
+                // Otherwise the window waits for an ack that will never come
+                window.release(peer, &frame.camera, seq);
+                return Err(anyhow!("Could not send video frame"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, time::Duration};
+
+    use networking::Token as NetToken;
+
+    use crate::{sync::MAX_LATENCY, types::ids::CameraId};
+
+    use super::{
+        FrameDrop, VideoFrameWindow, MAX_BYTES_IN_FLIGHT, MAX_FRAMES_IN_FLIGHT, MAX_VIDEO_FRAME_LEN,
+    };
+
+    const FRONT: CameraId = CameraId::from_static("Front");
+
+    #[test]
+    fn frames_past_the_window_are_dropped() {
+        let mut window = VideoFrameWindow::default();
+        let peer = NetToken(1);
+
+        for seq in 0..MAX_FRAMES_IN_FLIGHT {
+            assert_eq!(window.reserve(peer, &FRONT, 1000), Ok(seq));
+        }
+        assert_eq!(
+            window.reserve(peer, &FRONT, 1000),
+            Err(FrameDrop::Saturated)
+        );
+
+        // Other cameras and peers have their own windows
+        let down = CameraId::from_static("Down");
+        assert_eq!(window.reserve(peer, &down, 1000), Ok(0));
+        assert_eq!(window.reserve(NetToken(2), &FRONT, 1000), Ok(0));
+
+        // Acking the oldest frame makes room for one more, sequence numbers carry on
+        window.ack(peer, &FRONT, 0);
+        assert_eq!(window.reserve(peer, &FRONT, 1000), Ok(2));
+        assert_eq!(
+            window.reserve(peer, &FRONT, 1000),
+            Err(FrameDrop::Saturated)
+        );
+
+        // Acks cover every frame before them
+        window.ack(peer, &FRONT, 2);
+        assert_eq!(window.reserve(peer, &FRONT, 1000), Ok(3));
+
+        assert_eq!(window.dropped(), 2);
+    }
+
+    #[test]
+    fn bogus_acks_are_ignored() {
+        let mut window = VideoFrameWindow::default();
+        let peer = NetToken(1);
+
+        window.ack(peer, &FRONT, 5);
+        for seq in 0..MAX_FRAMES_IN_FLIGHT {
+            assert_eq!(window.reserve(peer, &FRONT, 1000), Ok(seq));
+        }
+
+        // Frames that were never sent, and old acks arriving late
+        window.ack(peer, &FRONT, MAX_FRAMES_IN_FLIGHT + 3);
+        assert_eq!(
+            window.reserve(peer, &FRONT, 1000),
+            Err(FrameDrop::Saturated)
+        );
+
+        window.ack(peer, &FRONT, 1);
+        window.ack(peer, &FRONT, 0);
+        assert_eq!(window.reserve(peer, &FRONT, 1000), Ok(2));
+        assert_eq!(window.reserve(peer, &FRONT, 1000), Ok(3));
+        assert_eq!(
+            window.reserve(peer, &FRONT, 1000),
+            Err(FrameDrop::Saturated)
+        );
+    }
+
+    #[test]
+    fn large_frames_are_dropped() {
+        let mut window = VideoFrameWindow::default();
+        let peer = NetToken(1);
+
+        assert_eq!(
+            window.reserve(peer, &FRONT, MAX_VIDEO_FRAME_LEN + 1),
+            Err(FrameDrop::TooLarge)
+        );
+        // without using up the window
+        assert_eq!(window.reserve(peer, &FRONT, MAX_VIDEO_FRAME_LEN), Ok(0));
+        assert_eq!(window.dropped(), 1);
+    }
+
+    #[test]
+    fn departed_peers_are_forgotten() {
+        let mut window = VideoFrameWindow::default();
+
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            window.reserve(NetToken(1), &FRONT, 1000).unwrap();
+        }
+        window.reserve(NetToken(2), &FRONT, 1000).unwrap();
+
+        // A reconnect reusing the token starts over
+        window.retain_peers(|it| it == NetToken(2));
+        assert_eq!(window.reserve(NetToken(1), &FRONT, 1000), Ok(0));
+        assert_eq!(window.reserve(NetToken(2), &FRONT, 1000), Ok(1));
+    }
+
+    #[test]
+    fn cameras_share_the_byte_budget() {
+        let mut window = VideoFrameWindow::default();
+        let peer = NetToken(1);
+        let down = CameraId::from_static("Down");
+        let len = MAX_BYTES_IN_FLIGHT / 2 + 1;
+
+        assert_eq!(window.reserve(peer, &FRONT, len), Ok(0));
+        assert_eq!(window.reserve(peer, &down, len), Err(FrameDrop::Saturated));
+        // Other peers have their own budget
+        assert_eq!(window.reserve(NetToken(2), &down, len), Ok(0));
+
+        window.ack(peer, &FRONT, 0);
+        assert_eq!(window.reserve(peer, &down, len), Ok(0));
+    }
+
+    #[test]
+    fn released_frames_free_the_window() {
+        let mut window = VideoFrameWindow::default();
+        let peer = NetToken(1);
+
+        for seq in 0..MAX_FRAMES_IN_FLIGHT {
+            assert_eq!(
+                window.reserve(peer, &FRONT, MAX_BYTES_IN_FLIGHT / 2),
+                Ok(seq)
+            );
+        }
+
+        // Only the newest reservation can be given back
+        window.release(peer, &FRONT, 0);
+        assert_eq!(
+            window.reserve(peer, &FRONT, 1000),
+            Err(FrameDrop::Saturated)
+        );
+
+        let newest = MAX_FRAMES_IN_FLIGHT - 1;
+        window.release(peer, &FRONT, newest);
+        assert_eq!(
+            window.reserve(peer, &FRONT, MAX_BYTES_IN_FLIGHT / 2),
+            Ok(newest)
+        );
+
+        window.ack(peer, &FRONT, newest);
+        assert_eq!(
+            window.reserve(peer, &FRONT, MAX_BYTES_IN_FLIGHT),
+            Ok(newest + 1)
+        );
+    }
+
+    #[test]
+    fn pings_are_not_held_up_by_frames() {
+        enum Packet {
+            Frame(CameraId, u32),
+            Ping(u64),
+        }
+
+        // A poor 4 Mbit/s link, both cameras sending frames as large as allowed
+        const BYTES_PER_MS: usize = 500;
+        const ONE_WAY_MS: u64 = 20;
+        const FRAME_MS: u64 = 1000 / 15;
+        const PING_MS: u64 = 500;
+
+        let peer = NetToken(1);
+        let cameras = [FRONT, CameraId::from_static("Down")];
+
+        let mut window = VideoFrameWindow::default();
+        // Packets still being written to the link and how much of each is left
+        let mut link: VecDeque<(Packet, usize)> = VecDeque::new();
+        // Acks and pongs on their way back, with the time they arrive
+        let mut acks = Vec::new();
+        let mut worst_ping = 0;
+        let mut frames_sent = 0;
+
+        for now in 0..10_000 {
+            for (camera, seq, arrives) in acks.clone() {
+                if arrives == now {
+                    window.ack(peer, &camera, seq);
+                }
+            }
+            acks.retain(|(_, _, arrives)| *arrives > now);
+
+            if now % PING_MS == 0 {
+                link.push_back((Packet::Ping(now), 16));
+            }
+            if now % FRAME_MS == 0 {
+                for camera in &cameras {
+                    if let Ok(seq) = window.reserve(peer, camera, MAX_VIDEO_FRAME_LEN) {
+                        link.push_back((Packet::Frame(camera.clone(), seq), MAX_VIDEO_FRAME_LEN));
+                        frames_sent += 1;
+                    }
+                }
+            }
+
+            let mut budget = BYTES_PER_MS;
+            while let Some((_, left)) = link.front_mut() {
+                let written = budget.min(*left);
+                *left -= written;
+                budget -= written;
+                if *left > 0 {
+                    break;
+                }
+
+                let returns = now + 2 * ONE_WAY_MS;
+                match link.pop_front().unwrap().0 {
+                    Packet::Frame(camera, seq) => acks.push((camera, seq, returns)),
+                    Packet::Ping(sent) => worst_ping = worst_ping.max(returns - sent),
+                }
+            }
+        }
+
+        assert!(
+            Duration::from_millis(worst_ping) < MAX_LATENCY,
+            "{worst_ping}ms"
+        );
+        // Video still gets about half of the link
+        assert!(frames_sent >= 40, "{frames_sent}");
+    }
+}
@@ -0,0 +1,207 @@
+//! Feeds hostile and corrupted packets to the protocol reader
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    borrow::Cow,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use bincode::Options;
+use common::{
+    ecs_sync::{NetId, SerializedChange},
+    file_transfer::TransferKind,
+    protocol::{
+        Protocol, MAX_MANIFEST_TYPES, MAX_NAME_LEN, MAX_PAYLOAD_LEN, MAX_PROBE_PEERS,
+        PROTOCOL_VERSION,
+    },
+    types::ids::CameraId,
+    video_frames::MAX_VIDEO_FRAME_LEN,
+};
+use networking::Packet;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+/// Tracks the largest single allocation made while `TRACKING` is set
+struct PeakAlloc;
+
+static TRACKING: AtomicBool = AtomicBool::new(false);
+static LARGEST: AtomicUsize = AtomicUsize::new(0);
+/// Only one test may track allocations at a time
+static TRACKING_LOCK: Mutex<()> = Mutex::new(());
+
+unsafe impl GlobalAlloc for PeakAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if TRACKING.load(Ordering::Relaxed) {
+            LARGEST.fetch_max(layout.size(), Ordering::Relaxed);
+        }
+
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if TRACKING.load(Ordering::Relaxed) {
+            LARGEST.fetch_max(new_size, Ordering::Relaxed);
+        }
+
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: PeakAlloc = PeakAlloc;
+
+/// Runs `read` and returns the largest allocation it made
+fn largest_allocation(read: impl FnOnce()) -> usize {
+    let _guard = TRACKING_LOCK.lock().unwrap_or_else(|it| it.into_inner());
+
+    LARGEST.store(0, Ordering::SeqCst);
+    TRACKING.store(true, Ordering::SeqCst);
+    read();
+    TRACKING.store(false, Ordering::SeqCst);
+
+    LARGEST.load(Ordering::SeqCst)
+}
+
+fn serialize(packet: &Protocol) -> Vec<u8> {
+    bincode::DefaultOptions::new().serialize(packet).unwrap()
+}
+
+fn read(bytes: &[u8]) -> anyhow::Result<Protocol> {
+    Protocol::read_buf(&mut &bytes[..])
+}
+
+#[test]
+fn huge_length_prefixes_do_not_allocate() {
+    // EcsUpdate, EventEmitted, then a varint type name length claiming u64::MAX bytes
+    let mut bytes = vec![0, 3, 253];
+    bytes.extend_from_slice(&u64::MAX.to_le_bytes());
+    bytes.extend_from_slice(b"Not nearly enough data");
+
+    let largest = largest_allocation(|| {
+        assert!(read(&bytes).is_err());
+    });
+
+    // Only the error message should have been allocated
+    assert!(largest < 4096, "Allocated {largest} bytes");
+}
+
+#[test]
+fn oversized_fields_rejected() {
+    let long_name = Protocol::EcsUpdate(SerializedChange::ComponentUpdated(
+        NetId::random(),
+        Cow::Owned("a".repeat(MAX_NAME_LEN + 1)),
+        None,
+    ));
+    assert!(read(&serialize(&long_name)).is_err());
+
+    let big_payload = Protocol::EcsUpdate(SerializedChange::EventEmitted(
+        Cow::Borrowed("SomeEvent"),
+        Arc::new(vec![0; MAX_PAYLOAD_LEN + 1]),
+    ));
+    assert!(read(&serialize(&big_payload)).is_err());
+
+    let long_hello = Protocol::Hello {
+        name: "a".repeat(MAX_NAME_LEN + 1),
+        version: PROTOCOL_VERSION,
+    };
+    assert!(read(&serialize(&long_hello)).is_err());
+
+    let crowded_probe = Protocol::ProbeReply {
+        peers: vec!["Surface".to_owned(); MAX_PROBE_PEERS + 1],
+    };
+    assert!(read(&serialize(&crowded_probe)).is_err());
+
+    let crowded_manifest = Protocol::TypeManifest {
+        types: vec![Cow::Borrowed("common::components::Depth"); MAX_MANIFEST_TYPES + 1],
+    };
+    assert!(read(&serialize(&crowded_manifest)).is_err());
+
+    let big_frame = Protocol::VideoFrame {
+        camera_id: CameraId::from_static("Front"),
+        seq: 0,
+        jpeg: vec![0; MAX_VIDEO_FRAME_LEN + 1],
+    };
+    assert!(read(&serialize(&big_frame)).is_err());
+
+    let at_limit = Protocol::EcsUpdate(SerializedChange::EventEmitted(
+        Cow::Owned("a".repeat(MAX_NAME_LEN)),
+        Arc::new(vec![0; MAX_PAYLOAD_LEN]),
+    ));
+    assert!(read(&serialize(&at_limit)).is_ok());
+}
+
+#[test]
+fn arbitrary_bytes_never_panic() {
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+
+    let valid = [
+        Protocol::Ping {
+            payload: 42,
+            frame_time: Duration::from_millis(10),
+        },
+        Protocol::Pong {
+            payload: 42,
+            time: 12.5,
+        },
+        Protocol::Hello {
+            name: "Robot".to_owned(),
+            version: PROTOCOL_VERSION,
+        },
+        Protocol::Goodbye,
+        Protocol::VideoFrame {
+            camera_id: CameraId::from_static("Front"),
+            seq: 7,
+            jpeg: vec![0xff, 0xd8, 0xff, 0xe0, 0, 0x10, 0xff, 0xd9],
+        },
+        Protocol::TypeManifest {
+            types: vec![
+                Cow::Borrowed("common::components::Depth"),
+                Cow::Borrowed("common::events::ResetYaw"),
+            ],
+        },
+        Protocol::EcsUpdate(SerializedChange::ComponentUpdated(
+            NetId::random(),
+            Cow::Borrowed("common::components::Depth"),
+            Some(Arc::new(vec![1, 2, 3, 4, 5, 6, 7, 8])),
+        )),
+        Protocol::FileTransfer {
+            id: 3,
+            kind: TransferKind::Download,
+            name: "still.jpg".to_owned(),
+            chunk_index: 0,
+            total: 2,
+            data: vec![7; 64],
+        },
+    ]
+    .iter()
+    .map(serialize)
+    .collect::<Vec<_>>();
+
+    let largest = largest_allocation(|| {
+        for round in 0..20_000 {
+            let bytes = if round % 2 == 0 {
+                let len = rng.gen_range(0..128);
+                (0..len).map(|_| rng.gen()).collect::<Vec<u8>>()
+            } else {
+                let mut bytes = valid[rng.gen_range(0..valid.len())].clone();
+                for _ in 0..rng.gen_range(1..4) {
+                    let idx = rng.gen_range(0..bytes.len());
+                    bytes[idx] = rng.gen();
+                }
+                bytes
+            };
+
+            let _ = read(&bytes);
+        }
+    });
+
+    // Serde preallocates sequences up to 1MiB from their claimed length, nothing may exceed that
+    assert!(largest <= MAX_PAYLOAD_LEN, "Allocated {largest} bytes");
+}
@@ -0,0 +1,22 @@
+//! Every replicated type must come back unchanged from its adapter
+
+use bevy::{app::App, ecs::reflect::AppTypeRegistry};
+use common::{
+    ecs_sync::{round_trip::RoundTripReport, SerializationSettings},
+    CommunicationTypes,
+};
+
+#[test]
+fn replicated_types_round_trip() {
+    let mut app = App::new();
+    app.init_resource::<SerializationSettings>()
+        .add_plugins(CommunicationTypes);
+
+    let registry = app.world.resource::<AppTypeRegistry>().read();
+    let report = app
+        .world
+        .resource::<SerializationSettings>()
+        .check_round_trips(&registry);
+
+    assert_eq!(report, RoundTripReport::default());
+}
@@ -5,34 +5,81 @@
 
 pub mod blue_rov;
 pub mod motor_preformance;
+pub mod replay;
 pub mod solve;
 pub mod utils;
 pub mod x3d;
 
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashSet},
     fmt::Debug,
+    hash::Hash,
     ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign},
 };
 
 use bevy_reflect::{Reflect, ReflectDeserialize, ReflectSerialize};
 use glam::Vec3A;
-use nalgebra::{Matrix6xX, MatrixXx6};
+use nalgebra::{DMatrix, Matrix6xX, MatrixXx6};
 use serde::{Deserialize, Serialize};
+use solve::reverse::Axis;
 use tracing::instrument;
 
+/// Singular values this far below the largest are treated as zero by
+/// [`MotorConfig::condition_number`]
+const RANK_TOLERANCE: f32 = 1e-4;
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(
+    into = "MotorConfigData<MotorId>",
+    try_from = "MotorConfigData<MotorId>",
+    bound(
+        serialize = "MotorId: Ord + Clone + Serialize",
+        deserialize = "MotorId: Ord + Debug + Deserialize<'de>"
+    )
+)]
 pub struct MotorConfig<MotorId: Ord> {
     // FIXME(low): Is there any reason this isnt a Vec?
     motors: BTreeMap<MotorId, Motor>,
+    center_mass: Vec3A,
 
     matrix: Matrix6xX<f32>,
     pseudo_inverse: MatrixXx6<f32>,
 }
 
+/// Serialized form of a [`MotorConfig`], the matrices are rebuilt from this on deserialize
+#[derive(Serialize, Deserialize)]
+struct MotorConfigData<MotorId: Ord> {
+    motors: BTreeMap<MotorId, Motor>,
+    center_mass: Vec3A,
+}
+
+impl<MotorId: Ord> From<MotorConfig<MotorId>> for MotorConfigData<MotorId> {
+    fn from(config: MotorConfig<MotorId>) -> Self {
+        Self {
+            motors: config.motors,
+            center_mass: config.center_mass,
+        }
+    }
+}
+
+impl<MotorId: Ord + Debug> TryFrom<MotorConfigData<MotorId>> for MotorConfig<MotorId> {
+    type Error = &'static str;
+
+    fn try_from(data: MotorConfigData<MotorId>) -> Result<Self, Self::Error> {
+        Self::try_new_raw(data.motors, data.center_mass)
+    }
+}
+
 impl<MotorId: Ord + Debug> MotorConfig<MotorId> {
-    #[instrument(level = "trace", skip_all, ret)]
     pub fn new_raw(motors: impl IntoIterator<Item = (MotorId, Motor)>, center_mass: Vec3A) -> Self {
+        Self::try_new_raw(motors, center_mass).expect("Compute pseudo inverse")
+    }
+
+    #[instrument(level = "trace", skip_all, ret)]
+    pub fn try_new_raw(
+        motors: impl IntoIterator<Item = (MotorId, Motor)>,
+        center_mass: Vec3A,
+    ) -> Result<Self, &'static str> {
         let motors: BTreeMap<_, _> = motors.into_iter().collect();
 
         let matrix = Matrix6xX::from_iterator(
@@ -49,13 +96,60 @@ impl<MotorId: Ord + Debug> MotorConfig<MotorId> {
                 .flat_map(|it| it.to_array().into_iter()),
         );
 
-        let pseudo_inverse = matrix.clone().pseudo_inverse(0.0001).unwrap();
+        let pseudo_inverse = matrix.clone().pseudo_inverse(0.0001)?;
 
-        Self {
+        Ok(Self {
             motors,
+            center_mass,
             matrix,
             pseudo_inverse,
-        }
+        })
+    }
+
+    /// Point the motor torques are computed about, in the same frame as the motor positions
+    pub fn center_mass(&self) -> Vec3A {
+        self.center_mass
+    }
+
+    /// Moves the origin to `new_origin` and makes it the center of mass
+    ///
+    /// Every motor position is translated so the torque arms are measured from `new_origin`,
+    /// which is given in this config's frame. Only the torque rows of the matrix change.
+    pub fn recenter(&self, new_origin: Vec3A) -> MotorConfig<MotorId>
+    where
+        MotorId: Clone,
+    {
+        let motors = self.motors.iter().map(|(id, motor)| {
+            (
+                id.clone(),
+                Motor {
+                    position: motor.position - new_origin,
+                    ..*motor
+                },
+            )
+        });
+
+        // Translating the motors is an invertible change of the torque rows, the rank is kept
+        Self::new_raw(motors, Vec3A::ZERO)
+    }
+
+    /// Drops the `disabled` motors and rebuilds the matrices from the ones left
+    ///
+    /// The solvers then spread movements across the remaining motors as best they can, axes the
+    /// removed motors were needed for lose authority instead of failing to solve. At least one
+    /// motor has to be left.
+    pub fn without_motors(&self, disabled: &HashSet<MotorId>) -> MotorConfig<MotorId>
+    where
+        MotorId: Clone + Hash,
+    {
+        let motors = self
+            .motors
+            .iter()
+            .filter(|(id, _)| !disabled.contains(id))
+            .map(|(id, motor)| (id.clone(), *motor));
+
+        // Singular values that vanish with the removed motors are dropped by the pseudo inverse
+        Self::new_raw(motors, self.center_mass)
     }
 
     pub fn motor(&self, motor: &MotorId) -> Option<&Motor> {
@@ -65,6 +159,105 @@ impl<MotorId: Ord + Debug> MotorConfig<MotorId> {
     pub fn motors(&self) -> impl Iterator<Item = (&MotorId, &Motor)> {
         self.motors.iter()
     }
+
+    pub fn motor_count(&self) -> usize {
+        self.motors.len()
+    }
+
+    /// Position of `motor` in the slices taken by the index based solvers
+    ///
+    /// Motors are ordered by `MotorId`, the same order as [`MotorConfig::motors`]
+    pub fn index_of(&self, motor: &MotorId) -> Option<usize> {
+        self.motors.keys().position(|it| it == motor)
+    }
+
+    /// Maps motor forces to the resulting movement, one column per motor in `MotorId` order
+    ///
+    /// Rows are X, Y, Z force followed by X, Y, Z torque
+    pub fn contribution_matrix(&self) -> &Matrix6xX<f32> {
+        &self.matrix
+    }
+
+    /// How much one newton from each motor contributes to `axis`
+    pub fn axis_row(&self, axis: Axis) -> impl Iterator<Item = (&MotorId, f32)> + '_ {
+        let row = axis.index();
+
+        self.motors
+            .keys()
+            .enumerate()
+            .map(move |(col, motor)| (motor, self.matrix[(row, col)]))
+    }
+
+    /// Share of a movement along `axis` the motors can produce without also moving along others
+    ///
+    /// 1 when the axis can be driven on its own, 0 when no combination of motors moves along it
+    pub fn axis_authority(&self, axis: Axis) -> f32 {
+        let row = axis.index();
+
+        (0..self.motors.len())
+            .map(|col| self.matrix[(row, col)] * self.pseudo_inverse[(col, row)])
+            .sum()
+    }
+
+    /// Ratio of the largest to the smallest singular value of the contribution matrix rows for
+    /// `axes`
+    ///
+    /// The larger it is the more thrust some movements take compared to others. Infinite when
+    /// some combination of `axes` can't be driven at all.
+    pub fn condition_number(&self, axes: &[Axis]) -> f32 {
+        if axes.is_empty() {
+            return 1.0;
+        }
+
+        // Fewer motors than axes always leaves some combination out
+        if self.motors.len() < axes.len() {
+            return f32::INFINITY;
+        }
+
+        let rows = DMatrix::from_fn(axes.len(), self.motors.len(), |row, col| {
+            self.matrix[(axes[row].index(), col)]
+        });
+        let singular_values = rows.singular_values();
+        let (max, min) = (singular_values.max(), singular_values.min());
+
+        if min <= max * RANK_TOLERANCE {
+            f32::INFINITY
+        } else {
+            max / min
+        }
+    }
+
+    pub fn summary(&self) -> MotorConfigSummary<MotorId>
+    where
+        MotorId: Clone,
+    {
+        let motors = self
+            .motors
+            .iter()
+            .enumerate()
+            .map(|(col, (id, motor))| MotorSummary {
+                id: id.clone(),
+                motor: *motor,
+                contribution: std::array::from_fn(|row| self.matrix[(row, col)]),
+            })
+            .collect();
+
+        MotorConfigSummary { motors }
+    }
+}
+
+/// Plain data view of a [`MotorConfig`] for display purposes
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MotorConfigSummary<MotorId> {
+    pub motors: Vec<MotorSummary<MotorId>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct MotorSummary<MotorId> {
+    pub id: MotorId,
+    pub motor: Motor,
+    /// Column of the contribution matrix for this motor
+    pub contribution: [f32; 6],
 }
 
 pub type ErasedMotorId = u8;
@@ -74,6 +267,7 @@ impl<MotorId: Ord + Into<ErasedMotorId> + Clone> MotorConfig<MotorId> {
     pub fn erase(self) -> MotorConfig<ErasedMotorId> {
         let MotorConfig {
             motors,
+            center_mass,
             matrix,
             pseudo_inverse,
         } = self;
@@ -85,6 +279,7 @@ impl<MotorId: Ord + Into<ErasedMotorId> + Clone> MotorConfig<MotorId> {
 
         MotorConfig {
             motors,
+            center_mass,
             matrix,
             pseudo_inverse,
         }
@@ -98,6 +293,7 @@ impl MotorConfig<ErasedMotorId> {
     ) -> Result<MotorConfig<MotorId>, <MotorId as TryFrom<ErasedMotorId>>::Error> {
         let MotorConfig {
             motors,
+            center_mass,
             matrix,
             pseudo_inverse,
         } = self;
@@ -109,6 +305,7 @@ impl MotorConfig<ErasedMotorId> {
 
         Ok(MotorConfig {
             motors,
+            center_mass,
             matrix,
             pseudo_inverse,
         })
@@ -141,21 +338,51 @@ impl Direction {
         }
     }
 
-    pub fn from_sign(sign: f32) -> Self {
-        if sign.signum() == 1.0 {
-            Direction::Clockwise
+    /// The direction `sign` points in, `None` for zero and NaN which have no direction
+    pub fn from_sign(sign: f32) -> Option<Self> {
+        if sign > 0.0 {
+            Some(Direction::Clockwise)
+        } else if sign < 0.0 {
+            Some(Direction::CounterClockwise)
         } else {
-            Direction::CounterClockwise
+            None
+        }
+    }
+
+    pub fn flip(&self) -> Self {
+        match self {
+            Direction::Clockwise => Direction::CounterClockwise,
+            Direction::CounterClockwise => Direction::Clockwise,
         }
     }
 
     pub fn flip_n(&self, count: i32) -> Self {
-        let sign = self.get_sign();
-        let new_sign = sign * (-1.0f32).powi(count);
-        Self::from_sign(new_sign)
+        if count % 2 == 0 {
+            *self
+        } else {
+            self.flip()
+        }
     }
 }
 
+/// PWM the motor tables are centered on, the motor is stopped here
+const NEUTRAL_PWM: f32 = 1500.0;
+
+/// Which way a motor is wired from `(pwm, measured_force)` pairs taken while running it
+///
+/// A clockwise motor pushes forwards above neutral. Each sample votes with its force weighted by
+/// how far it is from neutral, so readings in the deadband that flip sign on noise barely count.
+/// Returns `None` when the samples do not point either way.
+pub fn detect_direction_from_samples(samples: &[(f32, f32)]) -> Option<Direction> {
+    let score = samples
+        .iter()
+        .filter(|(pwm, force)| pwm.is_finite() && force.is_finite())
+        .map(|(pwm, force)| (pwm - NEUTRAL_PWM) * force)
+        .sum();
+
+    Direction::from_sign(score)
+}
+
 #[derive(Debug, Copy, Clone, Default, Serialize, Deserialize, Reflect, PartialEq)]
 #[reflect(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Movement {
@@ -230,3 +457,82 @@ impl DivAssign<f32> for Movement {
         *self = *self / rhs;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{detect_direction_from_samples, Direction};
+
+    use Direction::{Clockwise, CounterClockwise};
+
+    #[test]
+    fn signs_without_direction() {
+        assert_eq!(Direction::from_sign(2.5), Some(Clockwise));
+        assert_eq!(
+            Direction::from_sign(-f32::MIN_POSITIVE),
+            Some(CounterClockwise)
+        );
+        assert_eq!(Direction::from_sign(f32::INFINITY), Some(Clockwise));
+
+        assert_eq!(Direction::from_sign(0.0), None);
+        assert_eq!(Direction::from_sign(-0.0), None);
+        assert_eq!(Direction::from_sign(f32::NAN), None);
+        assert_eq!(Direction::from_sign(-f32::NAN), None);
+    }
+
+    #[test]
+    fn flip_n_is_parity() {
+        for direction in [Clockwise, CounterClockwise] {
+            assert_eq!(direction.flip_n(0), direction);
+            assert_eq!(direction.flip_n(1), direction.flip());
+            assert_eq!(direction.flip_n(2), direction);
+            assert_eq!(direction.flip_n(-1), direction.flip());
+            assert_eq!(direction.flip_n(i32::MAX), direction.flip());
+            assert_eq!(direction.flip_n(i32::MIN), direction);
+        }
+    }
+
+    /// A sweep across the table with `gain` newtons per microsecond and some deadband noise
+    fn sweep(gain: f32) -> Vec<(f32, f32)> {
+        (1100..=1900)
+            .step_by(25)
+            .map(|pwm| {
+                let pwm = pwm as f32;
+                let offset = pwm - 1500.0;
+
+                // Inside the deadband the load cell reads noise with either sign
+                let force = if offset.abs() < 50.0 {
+                    0.3 * (pwm * 0.37).sin()
+                } else {
+                    gain * offset
+                };
+
+                (pwm, force)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detects_direction_from_sweeps() {
+        assert_eq!(detect_direction_from_samples(&sweep(0.1)), Some(Clockwise));
+        assert_eq!(
+            detect_direction_from_samples(&sweep(-0.1)),
+            Some(CounterClockwise)
+        );
+
+        // A few readings with the wrong sign, and some garbage, don't outvote the rest
+        let mut noisy = sweep(0.1);
+        noisy[0].1 *= -1.0;
+        noisy.last_mut().unwrap().1 *= -1.0;
+        noisy.push((1600.0, f32::NAN));
+        noisy.push((f32::NAN, -100.0));
+        assert_eq!(detect_direction_from_samples(&noisy), Some(Clockwise));
+    }
+
+    #[test]
+    fn no_direction_without_thrust() {
+        assert_eq!(detect_direction_from_samples(&[]), None);
+        assert_eq!(detect_direction_from_samples(&[(1500.0, 3.0)]), None);
+        assert_eq!(detect_direction_from_samples(&[(1700.0, 0.0)]), None);
+        assert_eq!(detect_direction_from_samples(&[(1700.0, f32::NAN)]), None);
+    }
+}
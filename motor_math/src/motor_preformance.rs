@@ -1,28 +1,39 @@
-use std::path::Path;
+use std::{array, fs::File, io::Read, path::Path};
 
-use anyhow::Context;
-use serde::Deserialize;
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
 use crate::Direction;
 
 pub struct MotorData {
     force_index: Vec<MotorRecord>,
+    /// Slope of every field against force at each record in `force_index`
+    force_slopes: Vec<MotorRecord>,
     current_index: Vec<MotorRecord>,
+    /// Slope of every field against signed current at each record in `current_index`
+    current_slopes: Vec<MotorRecord>,
+    extrapolation: Extrapolation,
 }
 
 impl MotorData {
     #[instrument(level = "trace", skip(self), ret)]
     pub fn lookup_by_force(&self, force: f32, interpolation: Interpolation) -> MotorRecord {
-        let partition_point = self.force_index.partition_point(|x| x.force < force);
-
-        let idx_b = partition_point.max(1).min(self.force_index.len() - 1);
-        let idx_a = idx_b - 1;
+        self.lookup(
+            &self.force_index,
+            &self.force_slopes,
+            |it| it.force,
+            force,
+            interpolation,
+        )
+    }
 
-        let a = &self.force_index[idx_a];
-        let b = &self.force_index[idx_b];
+    /// Largest force the table covers in both directions, lookups past it are extrapolated
+    pub fn max_force(&self) -> f32 {
+        let reverse = self.force_index.first().map(|it| -it.force).unwrap_or(0.0);
+        let forward = self.force_index.last().map(|it| it.force).unwrap_or(0.0);
 
-        Self::interpolate(a, b, force, a.force, b.force, interpolation)
+        reverse.min(forward).max(0.0)
     }
 
     #[instrument(level = "trace", skip(self), ret)]
@@ -31,38 +42,71 @@ impl MotorData {
         signed_current: f32,
         interpolation: Interpolation,
     ) -> MotorRecord {
-        let partition_point = self
-            .current_index
-            .partition_point(|x| x.current.copysign(x.force) < signed_current);
-
-        let idx_b = partition_point.max(1).min(self.current_index.len() - 1);
-        let idx_a = idx_b - 1;
-
-        let a = &self.current_index[idx_a];
-        let b = &self.current_index[idx_b];
-
-        Self::interpolate(
-            a,
-            b,
+        self.lookup(
+            &self.current_index,
+            &self.current_slopes,
+            signed_current_of,
             signed_current,
-            a.current.copysign(a.force),
-            b.current.copysign(b.force),
             interpolation,
         )
     }
 
-    fn interpolate(
-        a: &MotorRecord,
-        b: &MotorRecord,
+    /// What lookups past the ends of the table return
+    pub fn with_extrapolation(mut self, extrapolation: Extrapolation) -> Self {
+        self.extrapolation = extrapolation;
+        self
+    }
+
+    pub fn extrapolation(&self) -> Extrapolation {
+        self.extrapolation
+    }
+
+    fn lookup(
+        &self,
+        index: &[MotorRecord],
+        slopes: &[MotorRecord],
+        key: impl Fn(&MotorRecord) -> f32,
         value: f32,
-        value_a: f32,
-        value_b: f32,
         interpolation: Interpolation,
     ) -> MotorRecord {
+        let value = match self.extrapolation {
+            Extrapolation::LinearExtrapolate => value,
+            Extrapolation::ClampToEndpoints => {
+                value.clamp(key(&index[0]), key(&index[index.len() - 1]))
+            }
+        };
+
+        let partition_point = index.partition_point(|x| key(x) < value);
+
+        let idx_b = partition_point.max(1).min(index.len() - 1);
+        let idx_a = idx_b - 1;
+
+        let a = &index[idx_a];
+        let b = &index[idx_b];
+        let (value_a, value_b) = (key(a), key(b));
+
         let record = match interpolation {
             Interpolation::LerpDirection(_) | Interpolation::Lerp => {
                 let alpha = (value - value_a) / (value_b - value_a);
-                a.lerp(b, alpha)
+                a.extrapolate(b, alpha)
+            }
+            Interpolation::CubicDirection(_) | Interpolation::Cubic => {
+                if (value_a..=value_b).contains(&value) {
+                    hermite(
+                        a,
+                        b,
+                        &slopes[idx_a],
+                        &slopes[idx_b],
+                        value_a,
+                        value_b,
+                        value,
+                    )
+                } else {
+                    // Past the ends of the table, continue the line through the last two records
+                    // like the linear lookups do
+                    let alpha = (value - value_a) / (value_b - value_a);
+                    a.extrapolate(b, alpha)
+                }
             }
             Interpolation::Direction(_) | Interpolation::OriginalData => {
                 let dist_a = (value_a - value).abs();
@@ -77,7 +121,9 @@ impl MotorData {
         };
 
         match interpolation {
-            Interpolation::LerpDirection(direction) | Interpolation::Direction(direction) => {
+            Interpolation::LerpDirection(direction)
+            | Interpolation::CubicDirection(direction)
+            | Interpolation::Direction(direction) => {
                 if let Direction::CounterClockwise = direction {
                     MotorRecord {
                         pwm: 3000.0 - record.pwm,
@@ -87,11 +133,15 @@ impl MotorData {
                     record
                 }
             }
-            Interpolation::Lerp | Interpolation::OriginalData => record,
+            Interpolation::Lerp | Interpolation::Cubic | Interpolation::OriginalData => record,
         }
     }
 }
 
+fn signed_current_of(record: &MotorRecord) -> f32 {
+    record.current.copysign(record.force)
+}
+
 impl From<Vec<MotorRecord>> for MotorData {
     fn from(value: Vec<MotorRecord>) -> Self {
         let mut force_index = value.clone();
@@ -101,35 +151,163 @@ impl From<Vec<MotorRecord>> for MotorData {
 
         let mut current_index = value.clone();
 
-        current_index.sort_by(|a, b| {
-            f32::total_cmp(&a.current.copysign(a.force), &b.current.copysign(b.force))
-        });
-        current_index.dedup_by_key(|it| it.current.copysign(it.force));
+        current_index.sort_by(|a, b| f32::total_cmp(&signed_current_of(a), &signed_current_of(b)));
+        current_index.dedup_by_key(|it| signed_current_of(it));
 
         Self {
+            force_slopes: monotone_slopes(&force_index, |it| it.force),
             force_index,
+            current_slopes: monotone_slopes(&current_index, signed_current_of),
             current_index,
+            extrapolation: Extrapolation::default(),
         }
     }
 }
 
+/// Slopes for a monotone cubic through `index` using the Fritsch–Carlson method
+///
+/// Each field is fit against `key` on its own. Where a field is monotone between two records the
+/// spline is too, so it never overshoots the data near the deadband like an unconstrained spline
+/// would.
+fn monotone_slopes(index: &[MotorRecord], key: impl Fn(&MotorRecord) -> f32) -> Vec<MotorRecord> {
+    if index.len() < 2 {
+        return vec![MotorRecord::default(); index.len()];
+    }
+
+    let secants: Vec<[f32; FIELDS]> = index
+        .windows(2)
+        .map(|pair| {
+            let (a, b) = (pair[0].fields(), pair[1].fields());
+            let run = key(&pair[1]) - key(&pair[0]);
+
+            array::from_fn(|field| (b[field] - a[field]) / run)
+        })
+        .collect();
+
+    let last = secants.len();
+    let mut slopes: Vec<[f32; FIELDS]> = (0..=last)
+        .map(|idx| {
+            if idx == 0 {
+                secants[0]
+            } else if idx == last {
+                secants[last - 1]
+            } else {
+                let (before, after) = (secants[idx - 1], secants[idx]);
+
+                // Flat at local extrema, otherwise the average of the neighbouring secants
+                array::from_fn(|field| {
+                    if before[field] * after[field] <= 0.0 {
+                        0.0
+                    } else {
+                        (before[field] + after[field]) / 2.0
+                    }
+                })
+            }
+        })
+        .collect();
+
+    for (idx, secant) in secants.iter().enumerate() {
+        for (field, &secant) in secant.iter().enumerate() {
+            if secant == 0.0 {
+                slopes[idx][field] = 0.0;
+                slopes[idx + 1][field] = 0.0;
+                continue;
+            }
+
+            let alpha = slopes[idx][field] / secant;
+            let beta = slopes[idx + 1][field] / secant;
+
+            // Outside this circle the spline overshoots one of the records
+            let magnitude = alpha * alpha + beta * beta;
+            if magnitude > 9.0 {
+                let tau = 3.0 / magnitude.sqrt();
+                slopes[idx][field] = tau * alpha * secant;
+                slopes[idx + 1][field] = tau * beta * secant;
+            }
+        }
+    }
+
+    slopes.into_iter().map(MotorRecord::from_fields).collect()
+}
+
+/// Cubic hermite between `a` and `b` with the given slopes, `value` must be between their keys
+fn hermite(
+    a: &MotorRecord,
+    b: &MotorRecord,
+    slope_a: &MotorRecord,
+    slope_b: &MotorRecord,
+    value_a: f32,
+    value_b: f32,
+    value: f32,
+) -> MotorRecord {
+    let run = value_b - value_a;
+    let t = (value - value_a) / run;
+    let (t2, t3) = (t * t, t * t * t);
+
+    let h00 = 2.0 * t3 - 3.0 * t2 + 1.0;
+    let h10 = t3 - 2.0 * t2 + t;
+    let h01 = -2.0 * t3 + 3.0 * t2;
+    let h11 = t3 - t2;
+
+    let (a, b) = (a.fields(), b.fields());
+    let (slope_a, slope_b) = (slope_a.fields(), slope_b.fields());
+
+    MotorRecord::from_fields(array::from_fn(|field| {
+        h00 * a[field] + h10 * run * slope_a[field] + h01 * b[field] + h11 * run * slope_b[field]
+    }))
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
 pub enum Interpolation {
     /// Return the linear interpolation betwwn the two data entries closest to the the requested data point
     /// and modifies the pwm field to match the direction of the propeller
     LerpDirection(Direction),
+    /// Return a monotone cubic through the data entries around the requested data point
+    /// and modifies the pwm field to match the direction of the propeller
+    ///
+    /// Smoother than [`Interpolation::LerpDirection`] where the data is flat near the deadband
+    CubicDirection(Direction),
     /// Return the raw data entry closest to the the requested data point
     /// Only modifies the pwm field to match the direction of the propeller
     Direction(Direction),
     /// Return the linear interpolation betwwn the two data entries closest to the the requested data point
     #[default]
     Lerp,
+    /// Return a monotone cubic through the data entries around the requested data point
+    Cubic,
     /// Return the raw data entry closest to the the requested data point
     /// Make no modifications to the data
     OriginalData,
 }
 
-#[derive(Deserialize, Debug, Clone, Copy)]
+impl Interpolation {
+    /// The same kind of interpolation, modifying the pwm field to match `direction`
+    pub fn with_direction(self, direction: Direction) -> Self {
+        match self {
+            Interpolation::LerpDirection(_) | Interpolation::Lerp => {
+                Interpolation::LerpDirection(direction)
+            }
+            Interpolation::CubicDirection(_) | Interpolation::Cubic => {
+                Interpolation::CubicDirection(direction)
+            }
+            Interpolation::Direction(_) | Interpolation::OriginalData => {
+                Interpolation::Direction(direction)
+            }
+        }
+    }
+}
+
+/// What a lookup past the ends of the motor data returns
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Extrapolation {
+    /// The record at the nearest end of the table
+    ClampToEndpoints,
+    /// Continue the line through the two records at the nearest end of the table
+    #[default]
+    LinearExtrapolate,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
 pub struct MotorRecord {
     pub pwm: f32,
     pub rpm: f32,
@@ -140,10 +318,18 @@ pub struct MotorRecord {
     pub efficiency: f32,
 }
 
+/// Number of fields in a [`MotorRecord`]
+const FIELDS: usize = 7;
+
 impl MotorRecord {
     pub fn lerp(&self, other: &Self, alpha: f32) -> Self {
         debug_assert!((0.0..=1.0).contains(&alpha));
 
+        self.extrapolate(other, alpha)
+    }
+
+    /// Like [`MotorRecord::lerp`], but `alpha` may be outside of `0..=1`
+    pub fn extrapolate(&self, other: &Self, alpha: f32) -> Self {
         Self {
             pwm: lerp(self.pwm, other.pwm, alpha),
             rpm: lerp(self.rpm, other.rpm, alpha),
@@ -156,6 +342,34 @@ impl MotorRecord {
     }
 }
 
+impl MotorRecord {
+    fn fields(&self) -> [f32; FIELDS] {
+        [
+            self.pwm,
+            self.rpm,
+            self.current,
+            self.voltage,
+            self.power,
+            self.force,
+            self.efficiency,
+        ]
+    }
+
+    fn from_fields(fields: [f32; FIELDS]) -> Self {
+        let [pwm, rpm, current, voltage, power, force, efficiency] = fields;
+
+        Self {
+            pwm,
+            rpm,
+            current,
+            voltage,
+            power,
+            force,
+            efficiency,
+        }
+    }
+}
+
 fn lerp(a: f32, b: f32, alpha: f32) -> f32 {
     (1.0 - alpha) * a + alpha * b
 }
@@ -171,3 +385,260 @@ pub fn read_motor_data<P: AsRef<Path>>(path: P) -> anyhow::Result<MotorData> {
 
     Ok(data.into())
 }
+
+/// A row [`read_motor_data_lenient`] couldn't use
+#[derive(Debug, Clone, PartialEq)]
+pub struct SkippedRecord {
+    /// 1 based line in the file, the header is line 1
+    pub line: u64,
+    pub error: String,
+}
+
+/// Motor data read from a file that may have had bad rows
+pub struct MotorDataFile {
+    pub data: MotorData,
+    pub skipped: Vec<SkippedRecord>,
+}
+
+/// Like [`read_motor_data`] but skips rows that fail to parse instead of failing the whole file
+pub fn read_motor_data_lenient<P: AsRef<Path>>(path: P) -> anyhow::Result<MotorDataFile> {
+    let file = File::open(path).context("Read data")?;
+
+    parse_motor_data_lenient(file)
+}
+
+/// Parses motor data csv, skipping and recording unusable rows
+///
+/// Fails if the header is unreadable or fewer than two rows are usable, the lookups need at least
+/// two records to interpolate between
+pub fn parse_motor_data_lenient<R: Read>(reader: R) -> anyhow::Result<MotorDataFile> {
+    let mut csv = csv::Reader::from_reader(reader);
+    let headers = csv.headers().context("Read header")?.clone();
+
+    let mut data = Vec::default();
+    let mut skipped = Vec::default();
+
+    let mut record = csv::StringRecord::new();
+    loop {
+        match csv.read_record(&mut record) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(err) => {
+                // Io errors won't get better by reading further
+                if let csv::ErrorKind::Io(_) = err.kind() {
+                    return Err(err).context("Read motor record");
+                }
+
+                skipped.push(SkippedRecord {
+                    line: err.position().map(|it| it.line()).unwrap_or_default(),
+                    error: err.to_string(),
+                });
+                continue;
+            }
+        }
+
+        let line = record.position().map(|it| it.line()).unwrap_or_default();
+
+        match record.deserialize::<MotorRecord>(Some(&headers)) {
+            Ok(parsed) if parsed.is_finite() => data.push(parsed),
+            Ok(_) => skipped.push(SkippedRecord {
+                line,
+                error: "Non finite value".to_owned(),
+            }),
+            Err(err) => skipped.push(SkippedRecord {
+                line,
+                error: err.to_string(),
+            }),
+        }
+    }
+
+    if data.len() < 2 {
+        bail!(
+            "Only {} usable motor records, {} skipped",
+            data.len(),
+            skipped.len()
+        );
+    }
+
+    Ok(MotorDataFile {
+        data: data.into(),
+        skipped,
+    })
+}
+
+impl MotorRecord {
+    fn is_finite(&self) -> bool {
+        self.fields().iter().all(|it| it.is_finite())
+    }
+}
+
+/// Coarse samples of the measured T200 curve at 12V with current and power padded by a quarter
+///
+/// Overestimating current keeps the current cap conservative when the real table is unavailable
+const FALLBACK_MOTOR_DATA: &str = "\
+pwm,rpm,current,voltage,power,force,efficiency
+1100,2976,21.3,12,255.5,-28.449,14.2
+1200,2457,11.1,12,133.5,-19.1295,18.2
+1300,1815,4.1,12,49.5,-10.0062,25.8
+1400,1023,0.6,12,7.5,-3.1392,52.9
+1460,346,0.06,12,0.75,-0.3924,60.5
+1500,0,0,12,0,0,0
+1540,342,0.06,12,0.75,0.3924,68
+1600,1009,0.6,12,7.5,3.8259,65.8
+1700,1796,4.1,12,49.5,12.5568,32.3
+1800,2456,11.1,12,133.5,24.1326,23.1
+1900,2995,21.1,12,253.6,36.3951,18.3
+";
+
+/// A minimal compiled in motor table for when the real one can't be loaded
+pub fn fallback_motor_data() -> MotorData {
+    parse_motor_data_lenient(FALLBACK_MOTOR_DATA.as_bytes())
+        .expect("Parse fallback motor data")
+        .data
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Direction;
+
+    use super::{
+        fallback_motor_data, parse_motor_data_lenient, read_motor_data, Extrapolation,
+        Interpolation, SkippedRecord,
+    };
+
+    const HEADER: &str = "pwm,rpm,current,voltage,power,force,efficiency\n";
+
+    #[test]
+    fn skips_malformed_rows() {
+        let csv = format!(
+            "{HEADER}\
+             1100,2976,17.03,12,204.4,-28.449,14.2\n\
+             1200,2457,eight,12,106.8,-19.1295,18.2\n\
+             1500,0,0,12,0,0,0\n\
+             1600,1009,0.5,12\n\
+             1700,1796,3.3,12,39.6,NaN,32.3\n\
+             1900,2995,16.91,12,202.9,36.3951,18.3\n"
+        );
+
+        let file = parse_motor_data_lenient(csv.as_bytes()).unwrap();
+
+        let lines: Vec<u64> = file.skipped.iter().map(|it| it.line).collect();
+        assert_eq!(lines, [3, 5, 6]);
+        assert_eq!(
+            file.skipped[2],
+            SkippedRecord {
+                line: 6,
+                error: "Non finite value".to_owned()
+            }
+        );
+
+        let record = file.data.lookup_by_force(0.0, Interpolation::OriginalData);
+        assert_eq!(record.pwm, 1500.0);
+        let record = file.data.lookup_by_force(36.0, Interpolation::OriginalData);
+        assert_eq!(record.pwm, 1900.0);
+    }
+
+    #[test]
+    fn rejects_files_without_enough_rows() {
+        for csv in [
+            String::new(),
+            HEADER.to_owned(),
+            format!("{HEADER}1500,0,0,12,0,0,0\n"),
+            format!("{HEADER}bad\nrows,only\n"),
+        ] {
+            assert!(parse_motor_data_lenient(csv.as_bytes()).is_err(), "{csv:?}");
+        }
+    }
+
+    #[test]
+    fn fallback_is_usable() {
+        let data = fallback_motor_data();
+
+        let stopped = data.lookup_by_force(0.0, Interpolation::Lerp);
+        assert_eq!(stopped.pwm, 1500.0);
+        assert_eq!(stopped.current, 0.0);
+
+        let forward = data.lookup_by_force(20.0, Interpolation::Lerp);
+        assert!(forward.pwm > 1700.0 && forward.pwm < 1900.0);
+        let reverse = data.lookup_by_current(-5.0, Interpolation::Lerp);
+        assert!(reverse.force < 0.0);
+    }
+
+    #[test]
+    fn cubic_matches_the_data_at_knots() {
+        let data = read_motor_data("../robot/motor_data.csv").unwrap();
+
+        for record in &data.force_index {
+            let cubic = data.lookup_by_force(record.force, Interpolation::Cubic);
+
+            assert_eq!(cubic.force, record.force);
+            assert!(
+                (cubic.pwm - record.pwm).abs() < 1e-3,
+                "{cubic:?} != {record:?}"
+            );
+            assert!(
+                (cubic.current - record.current).abs() < 1e-4,
+                "{cubic:?} != {record:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn cubic_is_monotone_between_knots() {
+        let data = read_motor_data("../robot/motor_data.csv").unwrap();
+
+        for pair in data.force_index.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+
+            let mut last = a;
+            for step in 1..=100 {
+                let force = a.force + (b.force - a.force) * step as f32 / 100.0;
+                let record = data.lookup_by_force(force, Interpolation::Cubic);
+
+                // Moves the same way as the data between the two knots without overshooting
+                for (value, last, a, b) in [
+                    (record.pwm, last.pwm, a.pwm, b.pwm),
+                    (record.current, last.current, a.current, b.current),
+                ] {
+                    let direction = (b - a).signum();
+                    assert!((value - last) * direction >= -1e-4, "{force}: {record:?}");
+                    assert!((value - b) * direction <= 1e-4, "{force}: {record:?}");
+                }
+
+                last = record;
+            }
+        }
+
+        // The direction flips pwm the same way as with linear lookups
+        let forward = data.lookup_by_force(10.0, Interpolation::Cubic);
+        let flipped = data.lookup_by_force(
+            10.0,
+            Interpolation::CubicDirection(Direction::CounterClockwise),
+        );
+        assert_eq!(flipped.pwm, 3000.0 - forward.pwm);
+    }
+
+    #[test]
+    fn extrapolation_policy() {
+        let data = fallback_motor_data();
+        let max = data.force_index.last().copied().unwrap();
+        let past = max.force + 10.0;
+
+        for interpolation in [Interpolation::Lerp, Interpolation::Cubic] {
+            let extrapolated = data.lookup_by_force(past, interpolation);
+            assert!((extrapolated.force - past).abs() < 1e-4);
+            assert!(extrapolated.current > max.current);
+        }
+
+        let data = data.with_extrapolation(Extrapolation::ClampToEndpoints);
+        assert_eq!(data.extrapolation(), Extrapolation::ClampToEndpoints);
+
+        for interpolation in [Interpolation::Lerp, Interpolation::Cubic] {
+            assert_eq!(data.lookup_by_force(past, interpolation), max);
+            assert_eq!(
+                data.lookup_by_current(-100.0, interpolation),
+                data.current_index[0]
+            );
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Recorded Movements -> Motor Commands
+//!
+//! Runs recorded movement totals through the same steps the robot takes each frame, so changes to
+//! [`crate::solve`] can be compared against real runs without a robot
+
+use std::{fmt::Debug, path::Path};
+
+use anyhow::Context;
+use glam::vec3a;
+use serde::Deserialize;
+
+use crate::{
+    motor_preformance::{Interpolation, MotorData, MotorRecord},
+    solve::reverse,
+    MotorConfig, Movement,
+};
+
+/// How close the clamped current must get to the cap, matches the robot
+pub const AMPERAGE_EPSILON: f32 = 0.05;
+
+/// The total movement requested in one frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MovementSample {
+    /// Seconds since the recording started
+    pub time: f32,
+    pub movement: Movement,
+}
+
+#[derive(Deserialize)]
+struct MovementRow {
+    time: f32,
+    force_x: f32,
+    force_y: f32,
+    force_z: f32,
+    torque_x: f32,
+    torque_y: f32,
+    torque_z: f32,
+}
+
+/// Reads a movement log, a CSV with `time,force_x,force_y,force_z,torque_x,torque_y,torque_z`
+/// columns in seconds, newtons and newton meters
+pub fn read_movement_log<P: AsRef<Path>>(path: P) -> anyhow::Result<Vec<MovementSample>> {
+    let csv = csv::Reader::from_path(path).context("Read movement log")?;
+
+    let mut samples = Vec::default();
+    for result in csv.into_deserialize() {
+        let row: MovementRow = result.context("Parse movement sample")?;
+
+        samples.push(MovementSample {
+            time: row.time,
+            movement: Movement {
+                force: vec3a(row.force_x, row.force_y, row.force_z),
+                torque: vec3a(row.torque_x, row.torque_y, row.torque_z),
+            },
+        });
+    }
+
+    Ok(samples)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AllocatorSettings {
+    /// Amps all motors may draw together
+    pub amperage_cap: f32,
+    /// Newtons per second a motor's force may change by, `None` doesn't limit it
+    pub jerk_limit: Option<f32>,
+    /// How the motor data is looked up, the direction is filled in per motor
+    pub interpolation: Interpolation,
+}
+
+/// Motor commands for one sample
+#[derive(Debug, Clone, PartialEq)]
+pub struct Allocation {
+    pub time: f32,
+    /// One entry per motor in [`MotorConfig::index_of`] order
+    pub motor_cmds: Vec<MotorRecord>,
+    /// The requested movement would have drawn more than the amperage cap
+    pub clamped: bool,
+}
+
+/// Allocates each sample in order, the jerk limit carries over from one sample to the next
+pub fn replay<MotorId: Ord + Debug>(
+    samples: &[MovementSample],
+    motor_config: &MotorConfig<MotorId>,
+    motor_data: &MotorData,
+    settings: AllocatorSettings,
+) -> Vec<Allocation> {
+    let motor_count = motor_config.motor_count();
+    let mut forces = vec![0.0; motor_count];
+    let mut allocations: Vec<Allocation> = Vec::with_capacity(samples.len());
+
+    for sample in samples {
+        let mut motor_cmds = vec![MotorRecord::default(); motor_count];
+
+        reverse::reverse_solve_into(sample.movement, motor_config, &mut forces);
+        reverse::forces_to_cmds_into(
+            &forces,
+            motor_config,
+            motor_data,
+            &mut motor_cmds,
+            settings.interpolation,
+        );
+
+        let requested = motor_cmds.iter().map(|it| it.current).sum::<f32>();
+        let clamped = requested > settings.amperage_cap;
+
+        reverse::clamp_amperage_in_place(
+            &mut motor_cmds,
+            motor_config,
+            motor_data,
+            settings.amperage_cap,
+            AMPERAGE_EPSILON,
+            settings.interpolation,
+        );
+
+        if let (Some(jerk_limit), Some(last)) = (settings.jerk_limit, allocations.last()) {
+            let elapsed = (sample.time - last.time).max(0.0);
+
+            reverse::limit_jerk_in_place(
+                &mut motor_cmds,
+                &last.motor_cmds,
+                motor_config,
+                motor_data,
+                jerk_limit * elapsed,
+                settings.interpolation,
+            );
+            reverse::clamp_amperage_in_place(
+                &mut motor_cmds,
+                motor_config,
+                motor_data,
+                settings.amperage_cap,
+                AMPERAGE_EPSILON,
+                settings.interpolation,
+            );
+        }
+
+        allocations.push(Allocation {
+            time: sample.time,
+            motor_cmds,
+            clamped,
+        });
+    }
+
+    allocations
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ReplaySummary {
+    pub samples: usize,
+    /// Joules drawn by the motors, each sample's commands are held until the next sample
+    pub energy: f32,
+    /// Most amps drawn by all motors together in a single sample
+    pub max_current: f32,
+    /// Samples that would have drawn more than the amperage cap
+    pub clamped_samples: usize,
+}
+
+impl ReplaySummary {
+    pub fn new(allocations: &[Allocation]) -> Self {
+        let mut summary = ReplaySummary {
+            samples: allocations.len(),
+            ..Default::default()
+        };
+
+        for (idx, allocation) in allocations.iter().enumerate() {
+            let current = allocation
+                .motor_cmds
+                .iter()
+                .map(|it| it.current)
+                .sum::<f32>();
+            summary.max_current = summary.max_current.max(current);
+
+            if allocation.clamped {
+                summary.clamped_samples += 1;
+            }
+
+            if let Some(next) = allocations.get(idx + 1) {
+                let power = allocation.motor_cmds.iter().map(|it| it.power).sum::<f32>();
+                summary.energy += power * (next.time - allocation.time).max(0.0);
+            }
+        }
+
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::motor_preformance::MotorRecord;
+
+    use super::{Allocation, ReplaySummary};
+
+    fn allocation(time: f32, currents: &[f32], clamped: bool) -> Allocation {
+        Allocation {
+            time,
+            motor_cmds: currents
+                .iter()
+                .map(|&current| MotorRecord {
+                    current,
+                    voltage: 12.0,
+                    power: current * 12.0,
+                    ..Default::default()
+                })
+                .collect(),
+            clamped,
+        }
+    }
+
+    #[test]
+    fn summary_holds_each_sample_until_the_next() {
+        let allocations = [
+            allocation(0.0, &[1.0, 1.0], false),
+            allocation(0.5, &[5.0, 5.0], true),
+            allocation(2.5, &[0.0, 0.0], false),
+            // The last sample has no duration
+            allocation(3.0, &[20.0, 20.0], true),
+        ];
+
+        let summary = ReplaySummary::new(&allocations);
+        assert_eq!(summary.samples, 4);
+        assert_eq!(summary.clamped_samples, 2);
+        assert_eq!(summary.max_current, 40.0);
+        assert_eq!(summary.energy, 24.0 * 0.5 + 120.0 * 2.0);
+
+        assert_eq!(ReplaySummary::new(&[]), ReplaySummary::default());
+    }
+}
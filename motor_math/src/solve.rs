@@ -5,21 +5,21 @@ pub mod reverse;
 mod tests {
     extern crate test;
     use ahash::HashMap;
-    use std::time::Instant;
+    use std::{collections::HashSet, fmt::Debug, hash::Hash, time::Instant};
     use test::Bencher;
 
     use glam::{vec3a, Vec3A};
 
     use crate::{
         blue_rov::HeavyMotorId,
-        motor_preformance::{self},
+        motor_preformance::{self, Interpolation, MotorData, MotorRecord},
         solve::forward,
-        utils::vec_from_angles,
+        utils::{self, vec_from_angles},
         x3d::X3dMotorId,
-        Direction, Motor, MotorConfig, Movement,
+        Direction, ErasedMotorId, Motor, MotorConfig, Movement,
     };
 
-    use super::reverse;
+    use super::reverse::{self, Axis};
 
     #[test]
     fn solve_roundtrip_x3d() {
@@ -198,6 +198,431 @@ mod tests {
         assert!(movement_error.torque.length_squared() < 0.0001);
     }
 
+    #[test]
+    fn axis_row_matches_matrix_x3d() {
+        let seed_motor = Motor {
+            position: vec3a(1.0, 1.0, 1.0).normalize(),
+            orientation: vec_from_angles(60.0, 40.0),
+            direction: Direction::Clockwise,
+        };
+        let motor_config = MotorConfig::<X3dMotorId>::new(seed_motor, Vec3A::ZERO);
+
+        assert_axis_rows_match(&motor_config);
+    }
+
+    #[test]
+    fn axis_row_matches_matrix_blue_rov() {
+        let lateral = Motor {
+            position: vec3a(1.0, 1.0, 0.0),
+            orientation: vec3a(-1.0, 1.0, 0.0).normalize(),
+            direction: Direction::Clockwise,
+        };
+        let vertical = Motor {
+            position: vec3a(1.0, 1.0, 0.0),
+            orientation: vec3a(0.0, 0.0, 1.0).normalize(),
+            direction: Direction::Clockwise,
+        };
+        let motor_config = MotorConfig::<HeavyMotorId>::new(lateral, vertical, Vec3A::ZERO);
+
+        assert_axis_rows_match(&motor_config);
+    }
+
+    #[test]
+    fn vectored_configs_drive_every_axis() {
+        let seed_motor = Motor {
+            position: vec3a(1.0, 1.0, 1.0).normalize(),
+            orientation: vec_from_angles(60.0, 40.0),
+            direction: Direction::Clockwise,
+        };
+        let motor_config = MotorConfig::<X3dMotorId>::new(seed_motor, Vec3A::ZERO);
+
+        for axis in Axis::ALL {
+            let authority = motor_config.axis_authority(axis);
+            assert!((authority - 1.0).abs() < 1e-4, "{axis:?}: {authority}");
+        }
+
+        let condition = motor_config.condition_number(&Axis::ALL);
+        assert!(condition.is_finite() && condition >= 1.0, "{condition}");
+        assert_eq!(motor_config.condition_number(&[]), 1.0);
+    }
+
+    #[test]
+    fn planar_configs_lack_out_of_plane_axes() {
+        // Three horizontal thrusters, nothing pushes up or tilts the robot
+        let motors = [
+            (0u8, vec3a(0.2, 0.2, 0.0), vec3a(0.0, 1.0, 0.0)),
+            (1, vec3a(-0.2, 0.2, 0.0), vec3a(0.0, 1.0, 0.0)),
+            (2, vec3a(0.0, -0.2, 0.0), vec3a(1.0, 0.0, 0.0)),
+        ]
+        .map(|(id, position, orientation)| {
+            (
+                id,
+                Motor {
+                    position,
+                    orientation,
+                    direction: Direction::Clockwise,
+                },
+            )
+        });
+        let motor_config = MotorConfig::new_raw(motors, Vec3A::ZERO);
+
+        for axis in [Axis::X, Axis::Y, Axis::ZRot] {
+            let authority = motor_config.axis_authority(axis);
+            assert!((authority - 1.0).abs() < 1e-4, "{axis:?}: {authority}");
+        }
+        for axis in [Axis::Z, Axis::XRot, Axis::YRot] {
+            let authority = motor_config.axis_authority(axis);
+            assert!(authority.abs() < 1e-4, "{axis:?}: {authority}");
+        }
+
+        assert!(motor_config
+            .condition_number(&[Axis::X, Axis::Y, Axis::ZRot])
+            .is_finite());
+        assert_eq!(motor_config.condition_number(&Axis::ALL), f32::INFINITY);
+        assert_eq!(
+            motor_config.condition_number(&[Axis::Y, Axis::Z]),
+            f32::INFINITY
+        );
+
+        // Both forward thrusters pointing the same way in the same spot can't yaw
+        let mut stacked = motor_config
+            .motors()
+            .map(|(id, motor)| (*id, *motor))
+            .collect::<Vec<_>>();
+        stacked[1].1.position = stacked[0].1.position;
+        let stacked = MotorConfig::new_raw(stacked, Vec3A::ZERO);
+        assert_eq!(
+            stacked.condition_number(&[Axis::X, Axis::Y, Axis::ZRot]),
+            f32::INFINITY
+        );
+    }
+
+    #[test]
+    fn recenter_only_moves_torque_rows() {
+        let seed_motor = Motor {
+            position: vec3a(1.0, 1.0, 1.0).normalize(),
+            orientation: vec_from_angles(60.0, 40.0),
+            direction: Direction::Clockwise,
+        };
+        let motor_config = MotorConfig::<X3dMotorId>::new(seed_motor, vec3a(0.0, -0.035, 0.0));
+
+        let offset = vec3a(0.01, 0.02, -0.015);
+        let recentered = motor_config.recenter(motor_config.center_mass() + offset);
+        assert_eq!(recentered.center_mass(), Vec3A::ZERO);
+
+        let before = motor_config.contribution_matrix();
+        let after = recentered.contribution_matrix();
+
+        for (col, (_, motor)) in motor_config.motors().enumerate() {
+            // Forces don't depend on where the motors are
+            for row in 0..3 {
+                assert_eq!(before[(row, col)], after[(row, col)]);
+            }
+
+            // Torques lose the moment of the force about the offset
+            let shift = -offset.cross(motor.orientation);
+            for axis in 0..3 {
+                let delta = after[(3 + axis, col)] - before[(3 + axis, col)];
+                assert!((delta - shift[axis]).abs() < 1e-6, "{delta} {shift}");
+            }
+        }
+
+        // The same center of mass gives the same matrices
+        let same = motor_config.recenter(motor_config.center_mass());
+        assert!((same.contribution_matrix() - before).abs().max() < 1e-6);
+        assert!(
+            (&same.pseudo_inverse - &motor_config.pseudo_inverse)
+                .abs()
+                .max()
+                < 1e-5
+        );
+    }
+
+    #[test]
+    fn disabled_motor_is_solved_around() {
+        let motor_data =
+            motor_preformance::read_motor_data("../robot/motor_data.csv").expect("Read motor data");
+
+        let seed_motor = Motor {
+            position: vec3a(1.0, 1.0, 1.0).normalize(),
+            orientation: vec_from_angles(60.0, 40.0),
+            direction: Direction::Clockwise,
+        };
+        let motor_config = MotorConfig::<X3dMotorId>::new(seed_motor, vec3a(0.0, -0.035, 0.0));
+
+        let disabled = HashSet::from([X3dMotorId::FrontRightTop]);
+        let degraded = motor_config.without_motors(&disabled);
+
+        assert_eq!(degraded.motor_count(), 7);
+        assert_eq!(degraded.motor(&X3dMotorId::FrontRightTop), None);
+        assert_eq!(degraded.center_mass(), motor_config.center_mass());
+
+        // Seven vectored motors still reach every axis
+        let movement = Movement {
+            force: vec3a(-0.6, 0.5, 0.3),
+            torque: vec3a(0.2, 0.1, 0.4),
+        };
+        let forces = reverse::reverse_solve(movement, &degraded);
+        assert!(!forces.contains_key(&X3dMotorId::FrontRightTop));
+
+        let movement_error = movement - forward::forward_solve(&degraded, &forces);
+        assert!(movement_error.force.length_squared() < 0.0001);
+        assert!(movement_error.torque.length_squared() < 0.0001);
+
+        // With one less motor sharing the load, every axis tops out sooner
+        let before = reverse::axis_maximums(&motor_config, &motor_data, 10.0, 0.01);
+        let after = reverse::axis_maximums(&degraded, &motor_data, 10.0, 0.01);
+
+        for axis in Axis::ALL {
+            assert!(
+                after[&axis] < before[&axis],
+                "{axis:?}: {} -> {}",
+                before[&axis],
+                after[&axis]
+            );
+        }
+
+        // Nothing disabled is the same config
+        let same = motor_config.without_motors(&HashSet::new());
+        assert_eq!(
+            same.contribution_matrix(),
+            motor_config.contribution_matrix()
+        );
+        assert!(
+            (&same.pseudo_inverse - &motor_config.pseudo_inverse)
+                .abs()
+                .max()
+                < 1e-6
+        );
+    }
+
+    #[test]
+    fn estimate_recovers_center_of_mass_offset() {
+        let offset = vec3a(0.012, -0.02, 0.0);
+
+        // Level, the torque that cancels gravity pulling on the offset
+        let gravity = vec3a(0.0, 0.0, -15.0);
+        let holding_torque = gravity.cross(offset);
+
+        let estimate = utils::estimate_center_of_mass_offset(holding_torque, gravity);
+        assert!((estimate - offset).length() < 1e-6, "{estimate}");
+
+        // Tilted, only the part perpendicular to gravity is observable
+        let offset = vec3a(0.012, -0.02, 0.03);
+        let gravity = vec3a(0.0, 3.0, -14.0);
+        let holding_torque = gravity.cross(offset);
+
+        let estimate = utils::estimate_center_of_mass_offset(holding_torque, gravity);
+        assert!(estimate.dot(gravity).abs() < 1e-5, "{estimate}");
+        assert!(
+            (estimate - offset).cross(gravity).length() < 1e-5,
+            "{estimate}"
+        );
+
+        // Nothing to estimate from without gravity
+        assert_eq!(
+            utils::estimate_center_of_mass_offset(holding_torque, Vec3A::ZERO),
+            Vec3A::ZERO
+        );
+    }
+
+    #[test]
+    fn serialization_skips_matrices() {
+        let seed_motor = Motor {
+            position: vec3a(1.0, 1.0, 1.0).normalize(),
+            orientation: vec_from_angles(60.0, 40.0),
+            direction: Direction::Clockwise,
+        };
+        let motor_config =
+            MotorConfig::<X3dMotorId>::new(seed_motor, vec3a(0.0, -0.035, 0.0)).erase();
+
+        let serialized = bincode::serialize(&motor_config).unwrap();
+        let matrices_size = bincode::serialized_size(&motor_config.matrix).unwrap()
+            + bincode::serialized_size(&motor_config.pseudo_inverse).unwrap();
+        let motors_size = bincode::serialized_size(&motor_config.motors).unwrap();
+
+        // Only the motors and the center of mass are sent
+        assert!((serialized.len() as u64) < motors_size + matrices_size);
+        assert_eq!(serialized.len() as u64, motors_size + 12);
+
+        let deserialized: MotorConfig<ErasedMotorId> = bincode::deserialize(&serialized).unwrap();
+
+        assert_eq!(deserialized.motors, motor_config.motors);
+        assert!((&deserialized.matrix - &motor_config.matrix).abs().max() < 1e-6);
+        assert!(
+            (&deserialized.pseudo_inverse - &motor_config.pseudo_inverse)
+                .abs()
+                .max()
+                < 1e-5
+        );
+    }
+
+    #[test]
+    fn slice_solvers_match_map_solvers() {
+        let motor_data =
+            motor_preformance::read_motor_data("../robot/motor_data.csv").expect("Read motor data");
+
+        let seed_motor = Motor {
+            position: vec3a(1.0, 1.0, 1.0).normalize(),
+            orientation: vec_from_angles(60.0, 40.0),
+            direction: Direction::Clockwise,
+        };
+        let x3d = MotorConfig::<X3dMotorId>::new(seed_motor, vec3a(0.0, -0.035, 0.0));
+
+        let lateral = Motor {
+            position: vec3a(1.0, 1.0, 0.0),
+            orientation: vec3a(-1.0, 1.0, 0.0).normalize(),
+            direction: Direction::Clockwise,
+        };
+        let vertical = Motor {
+            position: vec3a(1.0, 1.0, 0.0),
+            orientation: vec3a(0.0, 0.0, 1.0).normalize(),
+            direction: Direction::CounterClockwise,
+        };
+        let blue_rov = MotorConfig::<HeavyMotorId>::new(lateral, vertical, Vec3A::ZERO);
+
+        let movements = [
+            Movement::default(),
+            Movement {
+                force: vec3a(-0.6, 0.5, 0.3),
+                torque: vec3a(0.2, 0.1, 0.4),
+            },
+            // Large enough to hit the current limit
+            Movement {
+                force: vec3a(6.0, -8.0, 5.0),
+                torque: vec3a(1.0, -0.5, 1.5),
+            },
+        ];
+
+        for movement in movements {
+            assert_slices_match(movement, &x3d, &motor_data);
+            assert_slices_match(movement, &blue_rov, &motor_data);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Bad motor id")]
+    fn clamping_unknown_motors_fails() {
+        let motor_data =
+            motor_preformance::read_motor_data("../robot/motor_data.csv").expect("Read motor data");
+
+        let seed_motor = Motor {
+            position: vec3a(1.0, 1.0, 1.0).normalize(),
+            orientation: vec_from_angles(60.0, 40.0),
+            direction: Direction::Clockwise,
+        };
+        let x3d = MotorConfig::<X3dMotorId>::new(seed_motor, Vec3A::ZERO);
+        let missing_one = MotorConfig::new_raw(
+            x3d.motors()
+                .filter(|(id, _)| **id != X3dMotorId::BackLeftBottom)
+                .map(|(id, motor)| (*id, *motor)),
+            Vec3A::ZERO,
+        );
+
+        let movement = Movement {
+            force: vec3a(6.0, -8.0, 5.0),
+            torque: vec3a(1.0, -0.5, 1.5),
+        };
+        let forces = reverse::reverse_solve(movement, &x3d);
+        let motor_cmds = reverse::forces_to_cmds(forces, &x3d, &motor_data);
+
+        reverse::clamp_amperage(
+            motor_cmds,
+            &missing_one,
+            &motor_data,
+            5.0,
+            0.05,
+            Interpolation::Lerp,
+        );
+    }
+
+    fn assert_slices_match<MotorId: Hash + Ord + Clone + Debug>(
+        movement: Movement,
+        motor_config: &MotorConfig<MotorId>,
+        motor_data: &MotorData,
+    ) {
+        let forces = reverse::reverse_solve(movement, motor_config);
+        let motor_cmds = reverse::forces_to_cmds(forces.clone(), motor_config, motor_data);
+        let clamped = reverse::clamp_amperage(
+            motor_cmds.clone(),
+            motor_config,
+            motor_data,
+            5.0,
+            0.05,
+            Interpolation::Lerp,
+        );
+
+        let mut force_slice = vec![0.0; motor_config.motor_count()];
+        let mut cmd_slice = vec![MotorRecord::default(); motor_config.motor_count()];
+        reverse::reverse_solve_into(movement, motor_config, &mut force_slice);
+        reverse::forces_to_cmds_into(
+            &force_slice,
+            motor_config,
+            motor_data,
+            &mut cmd_slice,
+            Interpolation::Lerp,
+        );
+
+        for (id, _) in motor_config.motors() {
+            let idx = motor_config.index_of(id).unwrap();
+
+            assert_eq!(forces[id], force_slice[idx]);
+            assert_eq!(motor_cmds[id], cmd_slice[idx]);
+        }
+
+        reverse::clamp_amperage_in_place(
+            &mut cmd_slice,
+            motor_config,
+            motor_data,
+            5.0,
+            0.05,
+            Interpolation::Lerp,
+        );
+
+        for (id, _) in motor_config.motors() {
+            let idx = motor_config.index_of(id).unwrap();
+
+            assert_eq!(clamped[id], cmd_slice[idx]);
+        }
+
+        let forces = clamped
+            .iter()
+            .map(|(id, it)| (id.clone(), it.force))
+            .collect();
+        let force_slice = cmd_slice.iter().map(|it| it.force).collect::<Vec<_>>();
+        assert_eq!(
+            forward::forward_solve(motor_config, &forces),
+            forward::forward_solve_slice(motor_config, &force_slice)
+        );
+    }
+
+    fn assert_axis_rows_match<MotorId: Ord + Clone + Debug>(motor_config: &MotorConfig<MotorId>) {
+        let matrix = motor_config.contribution_matrix();
+        assert_eq!(matrix.ncols(), motor_config.motor_count());
+
+        for axis in Axis::ALL {
+            let row = motor_config.axis_row(axis).collect::<Vec<_>>();
+            assert_eq!(row.len(), motor_config.motor_count());
+
+            for (col, ((motor_id, value), (expected_id, _))) in
+                row.iter().zip(motor_config.motors()).enumerate()
+            {
+                assert_eq!(*motor_id, expected_id);
+                assert_eq!(*value, matrix[(axis.index(), col)]);
+            }
+        }
+
+        let summary = motor_config.summary();
+        for (col, motor) in summary.motors.iter().enumerate() {
+            for axis in Axis::ALL {
+                assert_eq!(
+                    motor.contribution[axis.index()],
+                    matrix[(axis.index(), col)]
+                );
+            }
+        }
+    }
+
     #[bench]
     fn bench_reverse_solver_x3d(b: &mut Bencher) {
         let seed_motor = Motor {
@@ -250,4 +675,37 @@ mod tests {
             motor_cmds
         });
     }
+
+    #[bench]
+    fn bench_reverse_solver_slice_x3d(b: &mut Bencher) {
+        let seed_motor = Motor {
+            position: vec3a(0.3, 0.5, 0.4).normalize(),
+            orientation: vec_from_angles(60.0, 40.0),
+            direction: Direction::Clockwise,
+        };
+
+        let motor_data =
+            motor_preformance::read_motor_data("../robot/motor_data.csv").expect("Read motor data");
+        let motor_config = MotorConfig::<X3dMotorId>::new(seed_motor, Vec3A::ZERO);
+
+        let movement = Movement {
+            force: vec3a(0.6, 0.0, 0.3),
+            torque: vec3a(0.2, 0.1, 0.3),
+        };
+
+        let mut forces = vec![0.0; motor_config.motor_count()];
+        let mut motor_cmds = vec![MotorRecord::default(); motor_config.motor_count()];
+
+        b.iter(|| {
+            reverse::reverse_solve_into(movement, &motor_config, &mut forces);
+            reverse::forces_to_cmds_into(
+                &forces,
+                &motor_config,
+                &motor_data,
+                &mut motor_cmds,
+                Interpolation::Lerp,
+            );
+            test::black_box(&motor_cmds);
+        });
+    }
 }
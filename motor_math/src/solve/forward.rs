@@ -2,8 +2,7 @@
 
 use ahash::HashMap;
 use glam::Vec3A;
-use nalgebra::DVector;
-use std::{fmt::Debug, hash::Hash};
+use std::{array, fmt::Debug, hash::Hash};
 use tracing::instrument;
 
 use crate::{MotorConfig, Movement};
@@ -13,16 +12,36 @@ pub fn forward_solve<MotorId: Hash + Ord + Debug>(
     motor_config: &MotorConfig<MotorId>,
     motor_forces: &HashMap<MotorId, f32>,
 ) -> Movement {
-    let force_vec = DVector::from_iterator(
+    let forces = motor_config
+        .motors
+        .keys()
+        .map(|id| motor_forces.get(id).copied().unwrap_or(0.0))
+        .collect::<Vec<_>>();
+
+    forward_solve_slice(motor_config, &forces)
+}
+
+/// Same as [`forward_solve`] with one force per motor in [`MotorConfig::index_of`] order
+///
+/// Does not allocate
+pub fn forward_solve_slice<MotorId: Ord>(
+    motor_config: &MotorConfig<MotorId>,
+    motor_forces: &[f32],
+) -> Movement {
+    assert_eq!(
+        motor_forces.len(),
         motor_config.motors.len(),
-        motor_config
-            .motors
-            .keys()
-            .map(|id| motor_forces.get(id).copied().unwrap_or(0.0)),
+        "Wrong number of motors"
     );
 
-    let movement = motor_config.matrix.clone() * force_vec;
-    let movement = movement.as_slice();
+    let matrix = &motor_config.matrix;
+    let movement: [f32; 6] = array::from_fn(|axis| {
+        motor_forces
+            .iter()
+            .enumerate()
+            .map(|(motor, force)| matrix[(axis, motor)] * force)
+            .sum()
+    });
 
     Movement {
         force: Vec3A::from_slice(&movement[0..3]),
@@ -5,13 +5,12 @@ use std::hash::Hash;
 
 use ahash::{HashMap, HashMapExt};
 use glam::vec3a;
-use nalgebra::Vector6;
 use serde::{Deserialize, Serialize};
 use tracing::instrument;
 
 use crate::{
     motor_preformance::{Interpolation, MotorData, MotorRecord},
-    MotorConfig, Movement,
+    Direction, MotorConfig, Movement,
 };
 
 #[instrument(level = "trace", skip(motor_config), ret)]
@@ -19,20 +18,47 @@ pub fn reverse_solve<MotorId: Hash + Ord + Clone + Debug>(
     movement: Movement,
     motor_config: &MotorConfig<MotorId>,
 ) -> HashMap<MotorId, f32> {
-    let movement_vec = Vector6::from_iterator(
-        [movement.force, movement.torque]
-            .into_iter()
-            .flat_map(|it| it.to_array().into_iter()),
+    let mut forces = vec![0.0; motor_config.motor_count()];
+    reverse_solve_into(movement, motor_config, &mut forces);
+
+    motor_config.motors.keys().cloned().zip(forces).collect()
+}
+
+/// Same as [`reverse_solve`], writes one force per motor in [`MotorConfig::index_of`] order
+///
+/// Does not allocate
+pub fn reverse_solve_into<MotorId: Ord>(
+    movement: Movement,
+    motor_config: &MotorConfig<MotorId>,
+    motor_forces: &mut [f32],
+) {
+    assert_eq!(
+        motor_forces.len(),
+        motor_config.motors.len(),
+        "Wrong number of motors"
     );
 
-    let forces = motor_config.pseudo_inverse.clone() * movement_vec;
+    let mut movement_vec = [0.0; 6];
+    movement_vec[0..3].copy_from_slice(&movement.force.to_array());
+    movement_vec[3..6].copy_from_slice(&movement.torque.to_array());
 
-    let mut motor_forces = HashMap::new();
-    for (idx, (motor_id, _motor)) in motor_config.motors.iter().enumerate() {
-        motor_forces.insert(motor_id.clone(), forces[idx]);
+    let pseudo_inverse = &motor_config.pseudo_inverse;
+    for (motor, force) in motor_forces.iter_mut().enumerate() {
+        *force = movement_vec
+            .iter()
+            .enumerate()
+            .map(|(axis, value)| pseudo_inverse[(motor, axis)] * value)
+            .sum();
     }
+}
 
-    motor_forces
+/// Forces without a direction to look up, like NaN from a bad input, are treated as stopped
+fn lookup_force(force: f32) -> f32 {
+    if force.is_finite() {
+        force
+    } else {
+        0.0
+    }
 }
 
 #[instrument(level = "trace", skip(motor_config, motor_data), ret)]
@@ -44,7 +70,10 @@ pub fn forces_to_cmds<MotorId: Hash + Ord + Clone + Debug>(
     let mut motor_cmds = HashMap::new();
     for (motor_id, force) in forces {
         let motor = motor_config.motor(&motor_id).expect("Bad motor id");
-        let data = motor_data.lookup_by_force(force, Interpolation::LerpDirection(motor.direction));
+        let data = motor_data.lookup_by_force(
+            lookup_force(force),
+            Interpolation::LerpDirection(motor.direction),
+        );
 
         motor_cmds.insert(motor_id.clone(), data);
     }
@@ -52,6 +81,32 @@ pub fn forces_to_cmds<MotorId: Hash + Ord + Clone + Debug>(
     motor_cmds
 }
 
+/// Same as [`forces_to_cmds`] with one entry per motor in [`MotorConfig::index_of`] order,
+/// looked up with `interpolation` in each motor's direction
+///
+/// Does not allocate
+pub fn forces_to_cmds_into<MotorId: Ord>(
+    forces: &[f32],
+    motor_config: &MotorConfig<MotorId>,
+    motor_data: &MotorData,
+    motor_cmds: &mut [MotorRecord],
+    interpolation: Interpolation,
+) {
+    assert_eq!(
+        forces.len(),
+        motor_config.motors.len(),
+        "Wrong number of motors"
+    );
+    assert_eq!(motor_cmds.len(), forces.len(), "Wrong number of motors");
+
+    for ((motor, force), cmd) in motor_config.motors.values().zip(forces).zip(motor_cmds) {
+        *cmd = motor_data.lookup_by_force(
+            lookup_force(*force),
+            interpolation.with_direction(motor.direction),
+        );
+    }
+}
+
 /// Does not preserve force ratios
 /// Runs in constant time
 #[instrument(level = "trace", skip(motor_config, motor_data), ret)]
@@ -77,7 +132,7 @@ pub fn clamp_amperage_fast<MotorId: Hash + Ord + Clone + Debug>(
         let direction = motor_config
             .motor(&motor_id)
             .map(|it| it.direction)
-            .unwrap_or(crate::Direction::Clockwise);
+            .unwrap_or(Direction::Clockwise);
 
         let adjusted_current = data.current.copysign(data.force) * amperage_ratio;
         let data_adjusted =
@@ -89,41 +144,126 @@ pub fn clamp_amperage_fast<MotorId: Hash + Ord + Clone + Debug>(
     adjusted_motor_cmds
 }
 
+/// Scales every motor's force by the same ratio until the current is within `epsilon` of
+/// `amperage_cap`, looked up with `interpolation` in each motor's direction
 #[instrument(level = "trace", skip(motor_config, motor_data), ret)]
 pub fn clamp_amperage<MotorId: Hash + Ord + Clone + Debug>(
-    motor_cmds: HashMap<MotorId, MotorRecord>,
+    mut motor_cmds: HashMap<MotorId, MotorRecord>,
     motor_config: &MotorConfig<MotorId>,
     motor_data: &MotorData,
     amperage_cap: f32,
     epsilon: f32,
+    interpolation: Interpolation,
 ) -> HashMap<MotorId, MotorRecord> {
-    let amperage_total = motor_cmds.values().map(|it| it.current).sum::<f32>();
+    for motor_id in motor_cmds.keys() {
+        motor_config.motor(motor_id).expect("Bad motor id");
+    }
+
+    // Sum in index order so the result matches `clamp_amperage_in_place` exactly
+    let amperage_total = known_cmds(&motor_cmds, motor_config)
+        .map(|(_, data)| data.current)
+        .sum::<f32>();
 
     if amperage_total <= amperage_cap {
         return motor_cmds;
-    } else {
-        // TODO remove?
-        // println!("CURRENT LIMIT HIT");
     }
 
-    let force_ratio =
-        binary_search_force_ratio(&motor_cmds, motor_config, motor_data, amperage_cap, epsilon);
+    let force_ratio = binary_search_force_ratio(
+        &motor_cmds,
+        motor_config,
+        motor_data,
+        amperage_cap,
+        epsilon,
+        interpolation,
+    );
 
-    let mut adjusted_motor_cmds = HashMap::default();
-    for (motor_id, data) in motor_cmds {
-        let direction = motor_config
-            .motor(&motor_id)
-            .map(|it| it.direction)
-            .unwrap_or(crate::Direction::Clockwise);
+    for (motor_id, motor) in motor_config.motors() {
+        if let Some(data) = motor_cmds.get_mut(motor_id) {
+            *data = scale_force(
+                data,
+                interpolation.with_direction(motor.direction),
+                force_ratio,
+                motor_data,
+            );
+        }
+    }
 
-        let force_current = data.force * force_ratio;
-        let data_adjusted =
-            motor_data.lookup_by_force(force_current, Interpolation::LerpDirection(direction));
+    motor_cmds
+}
 
-        adjusted_motor_cmds.insert(motor_id.clone(), data_adjusted);
+/// Same as [`clamp_amperage`] with one entry per motor in [`MotorConfig::index_of`] order,
+/// looked up with `interpolation` in each motor's direction
+///
+/// Does not allocate
+pub fn clamp_amperage_in_place<MotorId: Ord>(
+    motor_cmds: &mut [MotorRecord],
+    motor_config: &MotorConfig<MotorId>,
+    motor_data: &MotorData,
+    amperage_cap: f32,
+    epsilon: f32,
+    interpolation: Interpolation,
+) {
+    assert_eq!(
+        motor_cmds.len(),
+        motor_config.motors.len(),
+        "Wrong number of motors"
+    );
+
+    let amperage_total = motor_cmds.iter().map(|it| it.current).sum::<f32>();
+
+    if amperage_total <= amperage_cap {
+        return;
     }
 
-    adjusted_motor_cmds
+    let interpolations = motor_config
+        .motors
+        .values()
+        .map(|it| interpolation.with_direction(it.direction));
+    let (force_ratio, _) = search_force_ratio(
+        || interpolations.clone().zip(motor_cmds.iter()),
+        motor_data,
+        amperage_cap,
+        epsilon,
+    );
+
+    for (interpolation, data) in interpolations.zip(motor_cmds.iter_mut()) {
+        *data = scale_force(data, interpolation, force_ratio, motor_data);
+    }
+}
+
+/// Limits how far each motor's force may move from `last` to `max_delta` newtons
+///
+/// Both slices have one entry per motor in [`MotorConfig::index_of`] order. Does not allocate
+pub fn limit_jerk_in_place<MotorId: Ord>(
+    motor_cmds: &mut [MotorRecord],
+    last: &[MotorRecord],
+    motor_config: &MotorConfig<MotorId>,
+    motor_data: &MotorData,
+    max_delta: f32,
+    interpolation: Interpolation,
+) {
+    assert_eq!(
+        motor_cmds.len(),
+        motor_config.motors.len(),
+        "Wrong number of motors"
+    );
+    assert_eq!(last.len(), motor_cmds.len(), "Wrong number of motors");
+
+    for ((record, last), motor) in motor_cmds
+        .iter_mut()
+        .zip(last)
+        .zip(motor_config.motors.values())
+    {
+        let delta = record.force - last.force;
+
+        if delta.abs() > max_delta {
+            let clamped = delta.clamp(-max_delta, max_delta);
+            *record = motor_data.lookup_by_force(
+                clamped + last.force,
+                interpolation.with_direction(motor.direction),
+            );
+        }
+    }
 }
 
 pub fn binary_search_force_ratio<MotorId: Hash + Ord + Clone + Debug>(
@@ -132,30 +272,68 @@ pub fn binary_search_force_ratio<MotorId: Hash + Ord + Clone + Debug>(
     motor_data: &MotorData,
     amperage_cap: f32,
     epsilon: f32,
+    interpolation: Interpolation,
 ) -> f32 {
+    let (force_ratio, _) = search_force_ratio(
+        || {
+            known_cmds(motor_cmds, motor_config)
+                .map(|(direction, data)| (interpolation.with_direction(direction), data))
+        },
+        motor_data,
+        amperage_cap,
+        epsilon,
+    );
+
+    force_ratio
+}
+
+/// The commands of the motors in `motor_config`, in index order
+fn known_cmds<'a, MotorId: Hash + Ord>(
+    motor_cmds: &'a HashMap<MotorId, MotorRecord>,
+    motor_config: &'a MotorConfig<MotorId>,
+) -> impl Iterator<Item = (Direction, &'a MotorRecord)> + 'a {
+    motor_config
+        .motors
+        .iter()
+        .filter_map(|(id, motor)| Some((motor.direction, motor_cmds.get(id)?)))
+}
+
+fn scale_force(
+    data: &MotorRecord,
+    interpolation: Interpolation,
+    force_ratio: f32,
+    motor_data: &MotorData,
+) -> MotorRecord {
+    let force_current = data.force * force_ratio;
+    motor_data.lookup_by_force(force_current, interpolation)
+}
+
+/// Returns the force ratio along with the number of current sums it took to find it
+fn search_force_ratio<'a, I: Iterator<Item = (Interpolation, &'a MotorRecord)>>(
+    motor_cmds: impl Fn() -> I,
+    motor_data: &MotorData,
+    amperage_cap: f32,
+    epsilon: f32,
+) -> (f32, usize) {
     let (mut lower_bound, mut lower_current) = (0.0, 0.0);
     let (mut upper_bound, mut upper_current) = (f32::INFINITY, f32::INFINITY);
     let mut mid = 1.0;
+    let mut iterations = 0;
 
     loop {
-        let mid_current = motor_cmds
-            .iter()
-            .map(|(motor_id, data)| {
-                let direction = motor_config
-                    .motor(motor_id)
-                    .map(|it| it.direction)
-                    .unwrap_or(crate::Direction::Clockwise);
+        iterations += 1;
 
+        let mid_current = motor_cmds()
+            .map(|(interpolation, data)| {
                 let adjusted_force = data.force.copysign(data.force) * mid;
-                let data = motor_data
-                    .lookup_by_force(adjusted_force, Interpolation::LerpDirection(direction));
+                let data = motor_data.lookup_by_force(adjusted_force, interpolation);
 
                 data.current
             })
             .sum::<f32>();
 
         if (mid_current - amperage_cap).abs() < epsilon {
-            return mid;
+            return (mid, iterations);
         }
 
         if mid_current >= amperage_cap {
@@ -188,6 +366,27 @@ pub enum Axis {
 }
 
 impl Axis {
+    pub const ALL: [Axis; 6] = [
+        Axis::X,
+        Axis::Y,
+        Axis::Z,
+        Axis::XRot,
+        Axis::YRot,
+        Axis::ZRot,
+    ];
+
+    /// Row of this axis in [`MotorConfig::contribution_matrix`]
+    pub fn index(&self) -> usize {
+        match self {
+            Axis::X => 0,
+            Axis::Y => 1,
+            Axis::Z => 2,
+            Axis::XRot => 3,
+            Axis::YRot => 4,
+            Axis::ZRot => 5,
+        }
+    }
+
     pub fn movement(&self) -> Movement {
         match self {
             Axis::X => Movement {
@@ -218,6 +417,9 @@ impl Axis {
     }
 }
 
+/// Largest movement along an axis [`axis_maximums`] starts its search from
+const MAX_PROBE: f32 = 25.0;
+
 pub fn axis_maximums<MotorId: Hash + Ord + Clone + Debug>(
     motor_config: &MotorConfig<MotorId>,
     motor_data: &MotorData,
@@ -235,15 +437,227 @@ pub fn axis_maximums<MotorId: Hash + Ord + Clone + Debug>(
     .into_iter()
     .map(|it| (it, it.movement()))
     .map(|(axis, movement)| {
-        let initial = 25.0;
+        // Keep the probe inside the motor data, with motors removed a single one can be asked
+        // for more than the table covers and the extrapolated currents throw off the search
+        let unit_forces = reverse_solve(movement, motor_config);
+        let peak = unit_forces
+            .values()
+            .fold(0.0f32, |peak, force| peak.max(force.abs()));
+        let initial = if peak > 0.0 {
+            (motor_data.max_force() * 0.5 / peak).min(MAX_PROBE)
+        } else {
+            MAX_PROBE
+        };
 
         let forces = reverse_solve(movement * initial, motor_config);
         let cmds = forces_to_cmds(forces, motor_config, motor_data);
-        let scale =
-            binary_search_force_ratio(&cmds, motor_config, motor_data, amperage_cap, epsilon);
+        // Matches the linear lookups of `forces_to_cmds`
+        let scale = binary_search_force_ratio(
+            &cmds,
+            motor_config,
+            motor_data,
+            amperage_cap,
+            epsilon,
+            Interpolation::Lerp,
+        );
 
         let value = scale * initial;
         (axis, value)
     })
     .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3a, Vec3A};
+    use serde::Deserialize;
+
+    use crate::{
+        motor_preformance::{self, Extrapolation, Interpolation, MotorData, MotorRecord},
+        x3d::X3dMotorId,
+        Direction, Motor, MotorConfig,
+    };
+
+    use super::{clamp_amperage, clamp_amperage_in_place, forces_to_cmds_into, search_force_ratio};
+
+    /// The robot's amperage budget, the fixture's forces are well past it
+    const AMPERAGE_CAP: f32 = 25.0;
+
+    #[derive(Deserialize)]
+    struct ForceRow {
+        motor: X3dMotorId,
+        force: f32,
+    }
+
+    fn motor_config() -> MotorConfig<X3dMotorId> {
+        MotorConfig::<X3dMotorId>::new(
+            Motor {
+                position: vec3a(0.19, 0.21, 0.09),
+                orientation: vec3a(-0.254, 0.571, -0.781).normalize(),
+                direction: Direction::CounterClockwise,
+            },
+            Vec3A::ZERO,
+        )
+    }
+
+    /// The measured table and the coarse compiled in one
+    fn motor_tables() -> [(&'static str, MotorData); 2] {
+        [
+            (
+                "measured",
+                motor_preformance::read_motor_data("../robot/motor_data.csv")
+                    .expect("Read motor data"),
+            ),
+            ("fallback", motor_preformance::fallback_motor_data()),
+        ]
+    }
+
+    /// The fixture's motor commands looked up in `motor_data` with `interpolation`
+    fn worst_case_cmds(
+        motor_config: &MotorConfig<X3dMotorId>,
+        motor_data: &MotorData,
+        interpolation: Interpolation,
+    ) -> Vec<MotorRecord> {
+        let mut forces = vec![0.0; motor_config.motor_count()];
+        let csv = csv::Reader::from_path("fixtures/clamp_worst_case.csv").unwrap();
+        for row in csv.into_deserialize() {
+            let row: ForceRow = row.unwrap();
+            forces[motor_config.index_of(&row.motor).unwrap()] = row.force;
+        }
+
+        let mut motor_cmds = vec![MotorRecord::default(); motor_config.motor_count()];
+        forces_to_cmds_into(
+            &forces,
+            motor_config,
+            motor_data,
+            &mut motor_cmds,
+            interpolation,
+        );
+        assert!(motor_cmds.iter().map(|it| it.current).sum::<f32>() > AMPERAGE_CAP);
+
+        motor_cmds
+    }
+
+    /// Iterations the current clamp takes on the fixture with `interpolation`
+    fn clamp_iterations(motor_data: &MotorData, interpolation: Interpolation) -> usize {
+        let motor_config = motor_config();
+        let motor_cmds = worst_case_cmds(&motor_config, motor_data, interpolation);
+
+        let interpolations = motor_config
+            .motors
+            .values()
+            .map(|it| interpolation.with_direction(it.direction));
+        let (_, iterations) = search_force_ratio(
+            || interpolations.clone().zip(motor_cmds.iter()),
+            motor_data,
+            AMPERAGE_CAP,
+            0.05,
+        );
+
+        iterations
+    }
+
+    #[test]
+    fn invalid_forces_stop_the_motor() {
+        let motor_data =
+            motor_preformance::read_motor_data("../robot/motor_data.csv").expect("Read motor data");
+        let motor_config = MotorConfig::<X3dMotorId>::new(
+            Motor {
+                position: vec3a(0.19, 0.21, 0.09),
+                orientation: vec3a(-0.254, 0.571, -0.781).normalize(),
+                direction: Direction::CounterClockwise,
+            },
+            Vec3A::ZERO,
+        );
+
+        let count = motor_config.motor_count();
+        let mut stopped = vec![MotorRecord::default(); count];
+        let mut invalid = vec![MotorRecord::default(); count];
+        for (forces, cmds) in [
+            (vec![0.0; count], &mut stopped),
+            (vec![f32::NAN; count], &mut invalid),
+        ] {
+            forces_to_cmds_into(
+                &forces,
+                &motor_config,
+                &motor_data,
+                cmds,
+                Interpolation::Lerp,
+            );
+        }
+
+        assert_eq!(invalid, stopped);
+        assert!(invalid.iter().all(|it| it.pwm.is_finite()));
+    }
+
+    #[test]
+    fn cubic_clamp_converges_faster_on_worst_case() {
+        let [(_, measured), (_, fallback)] = motor_tables();
+
+        // The slowest of 20000 random requests to converge with linear lookups
+        let lerp = clamp_iterations(&measured, Interpolation::Lerp);
+        let cubic = clamp_iterations(&measured, Interpolation::Cubic);
+        assert!(cubic < lerp, "cubic took {cubic}, lerp took {lerp}");
+
+        // The coarse table has little to smooth over, the cubic still shouldn't be slower
+        let lerp = clamp_iterations(&fallback, Interpolation::Lerp);
+        let cubic = clamp_iterations(&fallback, Interpolation::Cubic);
+        assert!(
+            cubic <= lerp,
+            "fallback: cubic took {cubic}, lerp took {lerp}"
+        );
+    }
+
+    #[test]
+    fn clamps_agree_with_every_table_and_lookup() {
+        let motor_config = motor_config();
+
+        for extrapolation in [
+            Extrapolation::LinearExtrapolate,
+            Extrapolation::ClampToEndpoints,
+        ] {
+            for (table, motor_data) in motor_tables() {
+                let motor_data = motor_data.with_extrapolation(extrapolation);
+
+                for interpolation in [Interpolation::Lerp, Interpolation::Cubic] {
+                    let mut slice = worst_case_cmds(&motor_config, &motor_data, interpolation);
+                    let map = motor_config
+                        .motors
+                        .keys()
+                        .copied()
+                        .zip(slice.iter().copied())
+                        .collect();
+
+                    let map = clamp_amperage(
+                        map,
+                        &motor_config,
+                        &motor_data,
+                        AMPERAGE_CAP,
+                        0.05,
+                        interpolation,
+                    );
+                    clamp_amperage_in_place(
+                        &mut slice,
+                        &motor_config,
+                        &motor_data,
+                        AMPERAGE_CAP,
+                        0.05,
+                        interpolation,
+                    );
+
+                    let case = format!("{table}, {extrapolation:?}, {interpolation:?}");
+                    let current = slice.iter().map(|it| it.current).sum::<f32>();
+                    assert!(
+                        (current - AMPERAGE_CAP).abs() < 0.05,
+                        "{case}: clamped to {current}A"
+                    );
+
+                    for (id, _) in motor_config.motors() {
+                        let idx = motor_config.index_of(id).unwrap();
+                        assert_eq!(map[id], slice[idx], "{case}: {id:?}");
+                    }
+                }
+            }
+        }
+    }
+}
@@ -25,3 +25,17 @@ impl VectorTransform {
         }
     }
 }
+
+/// Estimates how far the center of mass is from the origin the motor torques are computed about
+///
+/// At rest the orientation controller has to hold a torque equal to `gravity × offset`, where
+/// `gravity` is the body frame force the offset mass is pulled with (net of buoyancy). Only the
+/// part of the offset perpendicular to `gravity` produces torque, so the part along it is zero.
+pub fn estimate_center_of_mass_offset(holding_torque: Vec3A, gravity: Vec3A) -> Vec3A {
+    let length_squared = gravity.length_squared();
+    if length_squared == 0.0 {
+        return Vec3A::ZERO;
+    }
+
+    -gravity.cross(holding_torque) / length_squared
+}
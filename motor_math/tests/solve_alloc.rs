@@ -0,0 +1,138 @@
+//! Counts the allocations made by the solvers used in the robot's per frame hot path
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use glam::{vec3a, Vec3A};
+use motor_math::{
+    motor_preformance::{self, Interpolation, MotorRecord},
+    solve::{forward, reverse},
+    utils::vec_from_angles,
+    x3d::X3dMotorId,
+    Direction, Motor, MotorConfig, Movement,
+};
+
+/// Counts allocations made while `TRACKING` is set
+struct CountingAlloc;
+
+static TRACKING: AtomicBool = AtomicBool::new(false);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+/// Only one test may count allocations at a time
+static TRACKING_LOCK: Mutex<()> = Mutex::new(());
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if TRACKING.load(Ordering::Relaxed) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if TRACKING.load(Ordering::Relaxed) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+/// Runs `frame` and returns how many allocations it made
+fn count_allocations(frame: impl FnOnce()) -> usize {
+    let _guard = TRACKING_LOCK.lock().unwrap_or_else(|it| it.into_inner());
+
+    ALLOCATIONS.store(0, Ordering::SeqCst);
+    TRACKING.store(true, Ordering::SeqCst);
+    frame();
+    TRACKING.store(false, Ordering::SeqCst);
+
+    ALLOCATIONS.load(Ordering::SeqCst)
+}
+
+fn x3d() -> MotorConfig<X3dMotorId> {
+    let seed_motor = Motor {
+        position: vec3a(0.3, 0.5, 0.4).normalize(),
+        orientation: vec_from_angles(60.0, 40.0),
+        direction: Direction::Clockwise,
+    };
+
+    MotorConfig::<X3dMotorId>::new(seed_motor, Vec3A::ZERO)
+}
+
+/// Shared by both paths so they do the same work
+const AMPERAGE_CAP: f32 = 5.0;
+const EPSILON: f32 = 0.05;
+
+#[test]
+fn slice_solvers_do_not_allocate() {
+    let motor_data =
+        motor_preformance::read_motor_data("../robot/motor_data.csv").expect("Read motor data");
+    let motor_config = x3d();
+
+    // Big enough to hit the current limit
+    let movement = Movement {
+        force: vec3a(6.0, -8.0, 5.0),
+        torque: vec3a(1.0, -0.5, 1.5),
+    };
+
+    let map_allocations = count_allocations(|| {
+        for _ in 0..100 {
+            let forces = reverse::reverse_solve(movement, &motor_config);
+            let motor_cmds = reverse::forces_to_cmds(forces, &motor_config, &motor_data);
+            let motor_cmds = reverse::clamp_amperage(
+                motor_cmds,
+                &motor_config,
+                &motor_data,
+                AMPERAGE_CAP,
+                EPSILON,
+                Interpolation::Lerp,
+            );
+            let forces = motor_cmds.iter().map(|(id, it)| (*id, it.force)).collect();
+            forward::forward_solve(&motor_config, &forces);
+        }
+    });
+
+    let mut forces = vec![0.0; motor_config.motor_count()];
+    let mut motor_cmds = vec![MotorRecord::default(); motor_config.motor_count()];
+
+    let slice_allocations = count_allocations(|| {
+        for _ in 0..100 {
+            reverse::reverse_solve_into(movement, &motor_config, &mut forces);
+            reverse::forces_to_cmds_into(
+                &forces,
+                &motor_config,
+                &motor_data,
+                &mut motor_cmds,
+                Interpolation::Lerp,
+            );
+            reverse::clamp_amperage_in_place(
+                &mut motor_cmds,
+                &motor_config,
+                &motor_data,
+                AMPERAGE_CAP,
+                EPSILON,
+                Interpolation::Lerp,
+            );
+            for (force, cmd) in forces.iter_mut().zip(&motor_cmds) {
+                *force = cmd.force;
+            }
+            forward::forward_solve_slice(&motor_config, &forces);
+        }
+    });
+
+    assert!(map_allocations > 0);
+    assert_eq!(slice_allocations, 0);
+}
@@ -0,0 +1,162 @@
+//! Round trip time of small packets over localhost TCP, with and without `TCP_NODELAY`
+//!
+//! Each round sends a burst of small pings, the way a frame's worth of updates goes out, and
+//! waits for every pong. With Nagle's algorithm on, the rest of a burst waits for the first
+//! packet to be acknowledged.
+//!
+//! ```sh
+//! cargo bench -p networking --bench latency
+//! ```
+
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, SocketAddr},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use crossbeam::channel;
+use networking::{Event, Networking, NetworkingOptions, Packet, TcpOptions, Token};
+
+const ROUNDS: usize = 500;
+const BURST: u32 = 4;
+const PORT: u16 = 7300;
+
+fn main() -> anyhow::Result<()> {
+    for (idx, nodelay) in [false, true].into_iter().enumerate() {
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, PORT + idx as u16));
+        let mut samples = round_trips(addr, nodelay)?;
+        samples.sort();
+
+        let percentile = |p: f64| samples[((samples.len() - 1) as f64 * p) as usize];
+        println!(
+            "nodelay: {nodelay:<5}  p50: {:>10.1?}  p90: {:>10.1?}  p99: {:>10.1?}  max: {:>10.1?}",
+            percentile(0.5),
+            percentile(0.9),
+            percentile(0.99),
+            samples[samples.len() - 1],
+        );
+    }
+
+    Ok(())
+}
+
+/// Time from sending each burst to receiving its last pong
+fn round_trips(addr: SocketAddr, nodelay: bool) -> anyhow::Result<Vec<Duration>> {
+    let options = NetworkingOptions {
+        tcp: TcpOptions {
+            nodelay,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let server = Networking::<Small>::with_options(options)?;
+    let client = Networking::<Small>::with_options(options)?;
+    let messenger_server = server.messenger();
+    let messenger_client = client.messenger();
+
+    let (connected_tx, connected_rx) = channel::bounded(1);
+    let (pong_tx, pong_rx) = channel::unbounded();
+
+    thread::scope(|scope| -> anyhow::Result<Vec<Duration>> {
+        let replies = messenger_server.clone();
+        scope.spawn(move || {
+            server.start(|event| {
+                if let Event::Data(token, Small::Ping(id)) = event {
+                    replies.send_packet(token, Small::Pong(id)).unwrap();
+                    replies.wake().unwrap();
+                }
+            });
+        });
+
+        scope.spawn(move || {
+            client.start(|event| match event {
+                Event::Conected(token, _) => {
+                    let _ = connected_tx.send(token);
+                }
+                Event::Data(_, Small::Pong(id)) => {
+                    let _ = pong_tx.send(id);
+                }
+                _ => {}
+            });
+        });
+
+        messenger_server.bind_at(addr)?;
+        messenger_server.wake()?;
+        thread::sleep(Duration::from_millis(50));
+
+        messenger_client.connect_to(addr)?;
+        messenger_client.wake()?;
+
+        let token: Token = connected_rx
+            .recv_timeout(Duration::from_secs(5))
+            .context("Connect")?;
+
+        let mut samples = Vec::with_capacity(ROUNDS);
+        for round in 0..ROUNDS as u32 {
+            let start = Instant::now();
+
+            // Separate wake ups so each ping is its own write
+            for idx in 0..BURST {
+                messenger_client.send_packet(token, Small::Ping(round * BURST + idx))?;
+                messenger_client.wake()?;
+            }
+
+            for _ in 0..BURST {
+                pong_rx
+                    .recv_timeout(Duration::from_secs(5))
+                    .context("Pong")?;
+            }
+
+            samples.push(start.elapsed());
+        }
+
+        messenger_client.shutdown()?;
+        messenger_client.wake()?;
+        messenger_server.shutdown()?;
+        messenger_server.wake()?;
+
+        Ok(samples)
+    })
+}
+
+/// Written by hand so serialization doesn't show up in the timings
+#[derive(Clone, Copy, Debug)]
+enum Small {
+    Ping(u32),
+    Pong(u32),
+}
+
+impl Packet for Small {
+    fn expected_size(&self) -> anyhow::Result<u64> {
+        Ok(5)
+    }
+
+    fn write_buf(&self, buffer: &mut &mut [u8]) -> anyhow::Result<()> {
+        let (kind, id) = match *self {
+            Small::Ping(id) => (0, id),
+            Small::Pong(id) => (1, id),
+        };
+
+        buffer.write_all(&[kind])?;
+        buffer.write_all(&id.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    fn read_buf(buffer: &mut &[u8]) -> anyhow::Result<Self> {
+        let mut kind = [0];
+        let mut id = [0; 4];
+        buffer.read_exact(&mut kind)?;
+        buffer.read_exact(&mut id)?;
+
+        let id = u32::from_le_bytes(id);
+        match kind[0] {
+            0 => Ok(Small::Ping(id)),
+            1 => Ok(Small::Pong(id)),
+            kind => bail!("Unknown packet kind {kind}"),
+        }
+    }
+}
@@ -48,6 +48,12 @@ impl Buffer {
         unsafe { std::slice::from_raw_parts(ptr.add(self.read_index), self.len()) }
     }
 
+    #[instrument(level = "trace")]
+    pub fn get_written_mut(&mut self) -> &mut [u8] {
+        let ptr = self.vec.as_mut_ptr();
+        unsafe { std::slice::from_raw_parts_mut(ptr.add(self.read_index), self.len()) }
+    }
+
     #[instrument(level = "trace")]
     pub fn get_unwritten(&mut self, capacity: usize) -> &mut [u8] {
         self.vec.reserve(self.write_index + capacity);
@@ -133,6 +139,26 @@ impl Buffer {
 
         slice
     }
+
+    #[instrument(level = "trace")]
+    pub fn advance_read_mut(&mut self, advance: usize) -> &mut [u8] {
+        assert!(
+            self.read_index + advance <= self.write_index,
+            "Can not advance read idx ({}) past write ({}), A: {}",
+            self.read_index,
+            self.write_index,
+            advance
+        );
+
+        let slice = unsafe {
+            let ptr = self.vec.as_mut_ptr().add(self.read_index);
+            std::slice::from_raw_parts_mut(ptr, advance)
+        };
+
+        self.read_index += advance;
+
+        slice
+    }
 }
 
 impl Default for Buffer {
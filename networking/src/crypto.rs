@@ -0,0 +1,341 @@
+//! Optional authenticated encryption of the link between peers
+//!
+//! Right after connecting both sides send a hello saying whether they encrypt, a random salt, and
+//! proof they hold the pre-shared key. Each direction then gets its own key derived from the
+//! pre-shared key and both salts, and every frame's body is sealed with XChaCha20-Poly1305 using a
+//! counter as the nonce. The frame header is left readable but is authenticated along with the
+//! body, so a frame that was corrupted, reordered, replayed or cut short fails to open.
+//!
+//! Without a key on either side the hello is still exchanged, so a peer with a key never silently
+//! talks in cleartext to one without.
+
+use std::{
+    fmt::{self, Debug},
+    fs, io,
+    path::Path,
+};
+
+use chacha20poly1305::{AeadInPlace, Key, KeyInit, Tag, XChaCha20Poly1305, XNonce};
+
+use crate::error::{NetError, NetResult};
+
+/// Bytes a sealed frame is longer than the packet it carries
+pub const TAG_LEN: usize = 16;
+
+const MAGIC: [u8; 4] = *b"MRV1";
+const SALT_LEN: usize = 32;
+const PROOF_LEN: usize = 32;
+pub const HELLO_LEN: usize = MAGIC.len() + 1 + SALT_LEN + PROOF_LEN;
+
+const MODE_CLEARTEXT: u8 = 0;
+const MODE_PSK: u8 = 1;
+
+const KEY_CONTEXT: &str = "mate-rov 2024 link pre-shared key";
+const PROOF_CONTEXT: &str = "mate-rov 2024 link hello proof";
+const SESSION_CONTEXT: &str = "mate-rov 2024 link session key";
+
+/// Secret shared ahead of time by both ends of an encrypted link
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PresharedKey([u8; 32]);
+
+impl PresharedKey {
+    /// Derives a key from arbitrary secret bytes, such as a passphrase or random bytes
+    ///
+    /// Surrounding whitespace is ignored so a trailing newline in a key file doesn't matter
+    pub fn from_secret(secret: &[u8]) -> Option<Self> {
+        let secret = secret.trim_ascii();
+        if secret.is_empty() {
+            return None;
+        }
+
+        Some(Self(blake3::derive_key(KEY_CONTEXT, secret)))
+    }
+
+    /// Reads a key file, its whole contents are the secret
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let secret = fs::read(path)?;
+
+        Self::from_secret(&secret)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Key file is empty"))
+    }
+
+    fn proof(&self, salt: &[u8; SALT_LEN]) -> blake3::Hash {
+        let key = blake3::derive_key(PROOF_CONTEXT, &self.0);
+
+        blake3::keyed_hash(&key, salt)
+    }
+
+    fn session_key(&self, from: &[u8; SALT_LEN], to: &[u8; SALT_LEN]) -> XChaCha20Poly1305 {
+        let mut material = [0; 32 + 2 * SALT_LEN];
+        material[..32].copy_from_slice(&self.0);
+        material[32..32 + SALT_LEN].copy_from_slice(from);
+        material[32 + SALT_LEN..].copy_from_slice(to);
+
+        let key = blake3::derive_key(SESSION_CONTEXT, &material);
+
+        XChaCha20Poly1305::new(Key::from_slice(&key))
+    }
+}
+
+impl Debug for PresharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never log the key itself
+        f.write_str("PresharedKey(..)")
+    }
+}
+
+/// One side of the hello exchange
+pub struct Handshake {
+    key: Option<PresharedKey>,
+    salt: [u8; SALT_LEN],
+}
+
+impl Handshake {
+    pub fn new(key: Option<PresharedKey>) -> io::Result<Self> {
+        let mut salt = [0; SALT_LEN];
+        if key.is_some() {
+            getrandom::getrandom(&mut salt)?;
+        }
+
+        Ok(Self { key, salt })
+    }
+
+    /// What to send the peer
+    pub fn hello(&self) -> [u8; HELLO_LEN] {
+        let mut hello = [0; HELLO_LEN];
+        let (magic, rest) = hello.split_at_mut(MAGIC.len());
+        let (mode, rest) = rest.split_at_mut(1);
+        let (salt, proof) = rest.split_at_mut(SALT_LEN);
+
+        magic.copy_from_slice(&MAGIC);
+
+        if let Some(key) = &self.key {
+            mode[0] = MODE_PSK;
+            salt.copy_from_slice(&self.salt);
+            proof.copy_from_slice(key.proof(&self.salt).as_bytes());
+        } else {
+            mode[0] = MODE_CLEARTEXT;
+        }
+
+        hello
+    }
+
+    /// Checks the peer's hello, returns the cipher for the link or `None` if it is cleartext
+    pub fn finish(&self, hello: &[u8; HELLO_LEN]) -> NetResult<Option<FrameCipher>> {
+        let (magic, rest) = hello.split_at(MAGIC.len());
+        let (mode, rest) = rest.split_at(1);
+        let (salt, proof) = rest.split_at(SALT_LEN);
+
+        if magic != MAGIC {
+            return Err(NetError::BadHandshake);
+        }
+
+        match (&self.key, mode[0]) {
+            (None, MODE_CLEARTEXT) => Ok(None),
+            (None, MODE_PSK) => Err(NetError::KeyRequired),
+            (Some(_), MODE_CLEARTEXT) => Err(NetError::PeerUnencrypted),
+            (Some(key), MODE_PSK) => {
+                let salt: &[u8; SALT_LEN] = salt.try_into().expect("Split at SALT_LEN");
+                let proof: &[u8; PROOF_LEN] = proof.try_into().expect("Rest is PROOF_LEN");
+
+                // Comparing hashes is constant time
+                if key.proof(salt) != blake3::Hash::from(*proof) {
+                    return Err(NetError::KeyMismatch);
+                }
+
+                // Our own hello reflected back, both directions would share a key
+                if *salt == self.salt {
+                    return Err(NetError::BadHandshake);
+                }
+
+                Ok(Some(FrameCipher {
+                    send: key.session_key(&self.salt, salt),
+                    recv: key.session_key(salt, &self.salt),
+                    sent: 0,
+                    received: 0,
+                }))
+            }
+            _ => Err(NetError::BadHandshake),
+        }
+    }
+}
+
+/// Seals and opens frame bodies for one link
+pub struct FrameCipher {
+    send: XChaCha20Poly1305,
+    recv: XChaCha20Poly1305,
+    /// Nonces are counters, each direction has its own key so they never repeat
+    sent: u64,
+    received: u64,
+}
+
+impl FrameCipher {
+    fn nonce(counter: u64) -> XNonce {
+        let mut nonce = XNonce::default();
+        nonce[..8].copy_from_slice(&counter.to_le_bytes());
+
+        nonce
+    }
+
+    /// Encrypts `body` in place and returns the tag to send after it
+    ///
+    /// `header` is authenticated along with the body
+    pub fn seal(&mut self, header: &[u8], body: &mut [u8]) -> NetResult<[u8; TAG_LEN]> {
+        let nonce = Self::nonce(self.sent);
+        self.sent = self.sent.checked_add(1).ok_or(NetError::Unauthenticated)?;
+
+        let tag = self
+            .send
+            .encrypt_in_place_detached(&nonce, header, body)
+            .map_err(|_| NetError::Unauthenticated)?;
+
+        Ok(tag.into())
+    }
+
+    /// Decrypts a sealed `frame` in place, returns the length of the packet at its start
+    pub fn open(&mut self, header: &[u8], frame: &mut [u8]) -> NetResult<usize> {
+        let Some(len) = frame.len().checked_sub(TAG_LEN) else {
+            return Err(NetError::Unauthenticated);
+        };
+        let (body, tag) = frame.split_at_mut(len);
+
+        let nonce = Self::nonce(self.received);
+        self.recv
+            .decrypt_in_place_detached(&nonce, header, body, Tag::from_slice(tag))
+            .map_err(|_| NetError::Unauthenticated)?;
+        self.received += 1;
+
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::NetError;
+
+    use super::{FrameCipher, Handshake, PresharedKey, HELLO_LEN};
+
+    fn key(secret: &str) -> Option<PresharedKey> {
+        PresharedKey::from_secret(secret.as_bytes())
+    }
+
+    fn exchange(
+        a: Option<PresharedKey>,
+        b: Option<PresharedKey>,
+    ) -> (
+        Result<Option<FrameCipher>, NetError>,
+        Result<Option<FrameCipher>, NetError>,
+    ) {
+        let a = Handshake::new(a).unwrap();
+        let b = Handshake::new(b).unwrap();
+
+        (a.finish(&b.hello()), b.finish(&a.hello()))
+    }
+
+    #[test]
+    fn keys_ignore_surrounding_whitespace() {
+        assert_eq!(key("hunter2\n"), key("  hunter2"));
+        assert_ne!(key("hunter2"), key("hunter3"));
+        assert_eq!(key(" \n"), None);
+
+        // The key never ends up in logs
+        assert_eq!(format!("{:?}", key("hunter2").unwrap()), "PresharedKey(..)");
+    }
+
+    #[test]
+    fn matching_keys_talk() {
+        let (a, b) = exchange(key("secret"), key("secret"));
+        let mut a = a.unwrap().unwrap();
+        let mut b = b.unwrap().unwrap();
+
+        for message in ["hello", "", "a longer message to the other side"] {
+            let header = (message.len() as u32).to_le_bytes();
+
+            let mut frame = message.as_bytes().to_vec();
+            let tag = a.seal(&header, &mut frame).unwrap();
+            assert!(message.is_empty() || frame != message.as_bytes());
+            frame.extend_from_slice(&tag);
+
+            let len = b.open(&header, &mut frame).unwrap();
+            assert_eq!(&frame[..len], message.as_bytes());
+        }
+
+        // And back the other way
+        let mut frame = b"reply".to_vec();
+        let tag = b.seal(&[], &mut frame).unwrap();
+        frame.extend_from_slice(&tag);
+        assert_eq!(a.open(&[], &mut frame).unwrap(), 5);
+    }
+
+    #[test]
+    fn cleartext_needs_both_sides() {
+        let (a, b) = exchange(None, None);
+        assert!(a.unwrap().is_none());
+        assert!(b.unwrap().is_none());
+
+        let (a, b) = exchange(key("secret"), None);
+        assert!(matches!(a, Err(NetError::PeerUnencrypted)));
+        assert!(matches!(b, Err(NetError::KeyRequired)));
+    }
+
+    #[test]
+    fn mismatched_keys_fail() {
+        let (a, b) = exchange(key("secret"), key("other secret"));
+        assert!(matches!(a, Err(NetError::KeyMismatch)));
+        assert!(matches!(b, Err(NetError::KeyMismatch)));
+    }
+
+    #[test]
+    fn bad_hellos_fail() {
+        let handshake = Handshake::new(key("secret")).unwrap();
+
+        // Something other than a peer
+        let mut hello = [0; HELLO_LEN];
+        hello[..4].copy_from_slice(b"GET ");
+        assert!(matches!(
+            handshake.finish(&hello),
+            Err(NetError::BadHandshake)
+        ));
+
+        // Our own hello sent back to us
+        assert!(matches!(
+            handshake.finish(&handshake.hello()),
+            Err(NetError::BadHandshake)
+        ));
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let (a, b) = exchange(key("secret"), key("secret"));
+        let mut a = a.unwrap().unwrap();
+        let mut b = b.unwrap().unwrap();
+
+        let header = 5u32.to_le_bytes();
+        let mut sealed = b"hello".to_vec();
+        let tag = a.seal(&header, &mut sealed).unwrap();
+        sealed.extend_from_slice(&tag);
+
+        // Any flipped bit
+        for idx in 0..sealed.len() {
+            let mut frame = sealed.clone();
+            frame[idx] ^= 0x01;
+            assert!(matches!(
+                b.open(&header, &mut frame),
+                Err(NetError::Unauthenticated)
+            ));
+        }
+
+        // A different header
+        let mut frame = sealed.clone();
+        assert!(b.open(&6u32.to_le_bytes(), &mut frame).is_err());
+
+        // Too short to hold a tag
+        assert!(b.open(&header, &mut [0; 4]).is_err());
+
+        // The real frame still opens, then can't be replayed
+        let mut frame = sealed.clone();
+        assert_eq!(b.open(&header, &mut frame).unwrap(), 5);
+        let mut frame = sealed.clone();
+        assert!(b.open(&header, &mut frame).is_err());
+    }
+}
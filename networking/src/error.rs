@@ -1,7 +1,7 @@
 use mio::Token;
 use thiserror::Error;
 
-use std::io;
+use std::{fmt, io};
 
 pub type NetResult<T> = Result<T, NetError>;
 
@@ -13,6 +13,18 @@ pub enum NetError {
     PeerClosed,
     #[error("Tried to write packet with len {0} which does not fit in header")]
     OversizedPacket(usize),
+    #[error("Peer sent a frame of {0} bytes which exceeds the limit of {1}")]
+    FrameTooLarge(usize, usize),
+    #[error("Peer did not send a valid handshake")]
+    BadHandshake,
+    #[error("Peer encrypts the link but no pre-shared key is configured")]
+    KeyRequired,
+    #[error("A pre-shared key is configured but the peer does not encrypt the link")]
+    PeerUnencrypted,
+    #[error("Peer's pre-shared key does not match ours")]
+    KeyMismatch,
+    #[error("Frame failed authentication, it was corrupted or tampered with")]
+    Unauthenticated,
     #[error("Messenging Error: {0}")]
     Message(#[from] MessageError),
     #[error("Tried to send packet to unknown peer: {0:?}")]
@@ -29,8 +41,185 @@ impl NetError {
     pub fn chain(self, message: String) -> Self {
         NetError::Chain(message, Box::new(self))
     }
+
+    /// What this error means for the connection it happened on
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            NetError::Io(err) => ErrorClass::from_io_kind(err.kind()),
+            NetError::PeerClosed | NetError::UnknownPeer(_) => ErrorClass::PeerGone,
+            NetError::FrameTooLarge(..)
+            | NetError::BadHandshake
+            | NetError::Unauthenticated
+            | NetError::ParsingError(_) => ErrorClass::Protocol,
+            // Both ends need the same key, retrying won't change that
+            NetError::KeyRequired | NetError::PeerUnencrypted | NetError::KeyMismatch => {
+                ErrorClass::Fatal
+            }
+            NetError::OversizedPacket(_) | NetError::WritingError(_) | NetError::Message(_) => {
+                ErrorClass::Fatal
+            }
+            NetError::Chain(_, err) => err.class(),
+        }
+    }
+}
+
+/// Whether an error is worth retrying and what the operator can do about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    /// Likely to clear up on its own
+    TransientIo,
+    /// The connection was lost, reconnecting may work
+    PeerGone,
+    /// Nothing accepted the connection, the peer may not be running yet
+    Refused,
+    /// Something else is already bound to the address
+    AddrInUse,
+    /// The peer sent something we don't understand, retrying won't help
+    Protocol,
+    /// A configuration problem or a bug, retrying won't help
+    Fatal,
+}
+
+impl ErrorClass {
+    pub fn from_io_kind(kind: io::ErrorKind) -> Self {
+        use io::ErrorKind;
+
+        match kind {
+            ErrorKind::ConnectionRefused | ErrorKind::NotFound => ErrorClass::Refused,
+            ErrorKind::AddrInUse => ErrorClass::AddrInUse,
+            ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::BrokenPipe
+            | ErrorKind::NotConnected
+            | ErrorKind::UnexpectedEof => ErrorClass::PeerGone,
+            ErrorKind::InvalidData => ErrorClass::Protocol,
+            ErrorKind::AddrNotAvailable
+            | ErrorKind::PermissionDenied
+            | ErrorKind::InvalidInput
+            | ErrorKind::Unsupported => ErrorClass::Fatal,
+            // Timeouts, unreachable networks and anything unexpected
+            _ => ErrorClass::TransientIo,
+        }
+    }
+
+    /// Whether connecting again could succeed
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorClass::TransientIo | ErrorClass::PeerGone | ErrorClass::Refused
+        )
+    }
+
+    /// What the operator should do about it
+    pub fn hint(self) -> &'static str {
+        match self {
+            ErrorClass::TransientIo => "Temporary network problem, retrying",
+            ErrorClass::PeerGone => "Connection lost, reconnecting",
+            ErrorClass::Refused => {
+                "Nothing is accepting connections there, check the peer is running and the address is right"
+            }
+            ErrorClass::AddrInUse => {
+                "Address already in use, stop the other instance or configure a different port"
+            }
+            ErrorClass::Protocol => {
+                "Peer sent invalid data, check both ends run the same version"
+            }
+            ErrorClass::Fatal => "Check the configuration, retrying won't help",
+        }
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorClass::TransientIo => "Transient IO",
+            ErrorClass::PeerGone => "Peer gone",
+            ErrorClass::Refused => "Refused",
+            ErrorClass::AddrInUse => "Address in use",
+            ErrorClass::Protocol => "Protocol",
+            ErrorClass::Fatal => "Fatal",
+        };
+
+        f.write_str(name)
+    }
 }
 
 #[derive(Error, Debug, Default)]
 #[error("Failed to send message to worker")]
 pub struct MessageError;
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use mio::Token;
+
+    use super::{ErrorClass, MessageError, NetError};
+
+    fn io(kind: io::ErrorKind) -> NetError {
+        NetError::Io(io::Error::from(kind))
+    }
+
+    #[test]
+    fn io_kinds_are_classified() {
+        for (kind, class) in [
+            (io::ErrorKind::ConnectionRefused, ErrorClass::Refused),
+            (io::ErrorKind::NotFound, ErrorClass::Refused),
+            (io::ErrorKind::AddrInUse, ErrorClass::AddrInUse),
+            (io::ErrorKind::ConnectionReset, ErrorClass::PeerGone),
+            (io::ErrorKind::BrokenPipe, ErrorClass::PeerGone),
+            (io::ErrorKind::UnexpectedEof, ErrorClass::PeerGone),
+            (io::ErrorKind::TimedOut, ErrorClass::TransientIo),
+            (io::ErrorKind::Interrupted, ErrorClass::TransientIo),
+            (io::ErrorKind::HostUnreachable, ErrorClass::TransientIo),
+            (io::ErrorKind::InvalidData, ErrorClass::Protocol),
+            (io::ErrorKind::AddrNotAvailable, ErrorClass::Fatal),
+            (io::ErrorKind::PermissionDenied, ErrorClass::Fatal),
+        ] {
+            assert_eq!(io(kind).class(), class, "{kind:?}");
+        }
+    }
+
+    #[test]
+    fn custom_errors_are_classified() {
+        for (error, class) in [
+            (NetError::PeerClosed, ErrorClass::PeerGone),
+            (NetError::UnknownPeer(Token(3)), ErrorClass::PeerGone),
+            (NetError::FrameTooLarge(10, 5), ErrorClass::Protocol),
+            (NetError::BadHandshake, ErrorClass::Protocol),
+            (NetError::Unauthenticated, ErrorClass::Protocol),
+            (
+                NetError::ParsingError(anyhow::anyhow!("Bad packet")),
+                ErrorClass::Protocol,
+            ),
+            (NetError::KeyMismatch, ErrorClass::Fatal),
+            (NetError::KeyRequired, ErrorClass::Fatal),
+            (NetError::OversizedPacket(1 << 20), ErrorClass::Fatal),
+            (NetError::Message(MessageError), ErrorClass::Fatal),
+        ] {
+            assert_eq!(error.class(), class, "{error}");
+        }
+    }
+
+    #[test]
+    fn chains_keep_the_inner_class() {
+        let error = io(io::ErrorKind::AddrInUse)
+            .chain("Bind listner".to_owned())
+            .chain("Setup".to_owned());
+        assert_eq!(error.class(), ErrorClass::AddrInUse);
+
+        let error = NetError::BadHandshake.chain("Handshake with peer".to_owned());
+        assert_eq!(error.class(), ErrorClass::Protocol);
+        assert!(!error.class().is_retryable());
+    }
+
+    #[test]
+    fn retryable_classes() {
+        assert!(ErrorClass::TransientIo.is_retryable());
+        assert!(ErrorClass::PeerGone.is_retryable());
+        assert!(ErrorClass::Refused.is_retryable());
+        assert!(!ErrorClass::AddrInUse.is_retryable());
+        assert!(!ErrorClass::Protocol.is_retryable());
+        assert!(!ErrorClass::Fatal.is_retryable());
+    }
+}
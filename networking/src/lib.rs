@@ -2,31 +2,71 @@ pub mod error;
 
 pub(crate) mod acceptor;
 pub(crate) mod buf;
+pub(crate) mod crypto;
 pub(crate) mod header;
+pub(crate) mod loopback;
 pub(crate) mod peer;
 pub(crate) mod raw;
+pub(crate) mod stream;
+pub(crate) mod tuning;
 pub(crate) mod worker;
 
 use crossbeam::channel::{self, Receiver, Sender};
+pub use crypto::PresharedKey;
+use loopback::LoopbackEnd;
 pub use mio::Token;
 use mio::{Poll, Waker};
 use tracing::instrument;
+pub use tuning::{Keepalive, SocketTuning, TcpOptions};
 
-use std::{fmt::Debug, net::SocketAddr, sync::Arc};
+use std::{
+    fmt::{self, Debug, Display},
+    net::{AddrParseError, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 const WAKER_TOKEN: Token = Token(0);
 
 const PROBE_LENGTH: usize = 4096;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkingOptions {
+    /// Largest frame accepted from a peer, peers that send larger frames are disconnected
+    pub max_frame_size: usize,
+    /// Encrypts and authenticates the link, peers must be configured with the same key
+    pub preshared_key: Option<PresharedKey>,
+    /// Socket options for TCP connections, ignored by other transports
+    pub tcp: TcpOptions,
+}
+
+impl Default for NetworkingOptions {
+    fn default() -> Self {
+        Self {
+            max_frame_size: 4 * 1024 * 1024,
+            preshared_key: None,
+            tcp: TcpOptions::default(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Networking<P> {
     poll: Poll,
     waker: Arc<Waker>,
     queue: (Sender<Message<P>>, Receiver<Message<P>>),
+    options: NetworkingOptions,
+    loopback: Option<LoopbackEnd>,
 }
 
 impl<P: Packet> Networking<P> {
     pub fn new() -> error::NetResult<Self> {
+        Self::with_options(NetworkingOptions::default())
+    }
+
+    pub fn with_options(options: NetworkingOptions) -> error::NetResult<Self> {
         let poll = Poll::new()?;
 
         let waker = Waker::new(poll.registry(), WAKER_TOKEN)?;
@@ -34,7 +74,36 @@ impl<P: Packet> Networking<P> {
 
         let queue = channel::bounded(1000);
 
-        Ok(Networking { poll, waker, queue })
+        Ok(Networking {
+            poll,
+            waker,
+            queue,
+            options,
+            loopback: None,
+        })
+    }
+
+    /// Two instances linked in-process, for tests that shouldn't depend on sockets
+    ///
+    /// Packets go through the same framing, handshake and frame size checks as over a socket. Once
+    /// started the first reports the link as `Accepted` and the second as `Conected`.
+    pub fn loopback_pair() -> error::NetResult<(Self, Self)> {
+        Self::loopback_pair_with_options(NetworkingOptions::default(), NetworkingOptions::default())
+    }
+
+    pub fn loopback_pair_with_options(
+        acceptor: NetworkingOptions,
+        connector: NetworkingOptions,
+    ) -> error::NetResult<(Self, Self)> {
+        let mut acceptor = Self::with_options(acceptor)?;
+        let mut connector = Self::with_options(connector)?;
+
+        let (acceptor_end, connector_end) =
+            loopback::pair(acceptor.waker.clone(), connector.waker.clone());
+        acceptor.loopback = Some(acceptor_end);
+        connector.loopback = Some(connector_end);
+
+        Ok((acceptor, connector))
     }
 
     pub fn messenger(&self) -> Messenger<P> {
@@ -45,10 +114,16 @@ impl<P: Packet> Networking<P> {
     }
 
     pub fn start(self, handler: impl FnMut(Event<P>)) {
-        let Networking { poll, waker, queue } = self;
+        let Networking {
+            poll,
+            waker,
+            queue,
+            options,
+            loopback,
+        } = self;
         let _ = waker;
 
-        worker::start_worker(poll, queue.1, handler);
+        worker::start_worker(poll, queue.1, options, loopback, handler);
     }
 }
 
@@ -58,13 +133,69 @@ pub trait Packet: Debug + Clone {
     fn read_buf(buffer: &mut &[u8]) -> anyhow::Result<Self>;
 }
 
+/// Where a peer is reachable
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    /// Unix domain socket, only supported on unix platforms
+    Unix(PathBuf),
+    /// One end of a [`Networking::loopback_pair`], can't be connected to
+    Loopback(u64),
+}
+
+impl PeerAddr {
+    const UNIX_PREFIX: &'static str = "unix:";
+    const LOOPBACK_PREFIX: &'static str = "loopback:";
+}
+
+impl Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PeerAddr::Tcp(addr) => write!(f, "{addr}"),
+            PeerAddr::Unix(path) => write!(f, "{}{}", Self::UNIX_PREFIX, path.display()),
+            PeerAddr::Loopback(id) => write!(f, "{}{id}", Self::LOOPBACK_PREFIX),
+        }
+    }
+}
+
+/// Parses the format written by `Display`, a socket address or a path prefixed with `unix:`
+///
+/// Loopback addresses aren't parsed since there is nothing to connect to
+impl FromStr for PeerAddr {
+    type Err = AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix(Self::UNIX_PREFIX) {
+            Some(path) => Ok(PeerAddr::Unix(path.into())),
+            None => s.parse().map(PeerAddr::Tcp),
+        }
+    }
+}
+
+impl From<SocketAddr> for PeerAddr {
+    fn from(addr: SocketAddr) -> Self {
+        PeerAddr::Tcp(addr)
+    }
+}
+
 #[derive(Debug)]
 pub enum Event<P> {
-    Conected(Token, SocketAddr),
-    Accepted(Token, SocketAddr),
+    /// An outgoing connection was started, it ends in `Conected` or `Disconnect`
+    Connecting(Token, PeerAddr),
+    Conected(Token, PeerAddr),
+    Accepted(Token, PeerAddr),
+    /// What a TCP peer's socket options ended up as, sent before the peer is announced
+    SocketTuned(Token, SocketTuning),
 
     Data(Token, P),
 
+    /// A listener is accepting connections, disconnect its token to close it
+    ///
+    /// Closing a listener leaves the connections it accepted open
+    Bound(Token, PeerAddr),
+    /// Nothing is listening at the address, sent after the error
+    BindFailed(PeerAddr),
+
     Disconnect(Token),
     Error(Option<Token>, error::NetError),
 }
@@ -73,10 +204,16 @@ pub enum Event<P> {
 pub enum Message<P> {
     Connect(SocketAddr),
     Bind(SocketAddr),
+    ConnectUnix(PathBuf),
+    BindUnix(PathBuf),
+    /// Always fails, loopback links only exist between the pair they were created as
+    ConnectLoopback(u64),
     Disconect(Token),
     Packet(Token, P),
     PacketBrodcast(P),
     Shutdown,
+    /// Writes out all buffered packets before shutting down, gives up after the timeout
+    FlushShutdown(Duration),
 }
 
 #[derive(Debug, Clone)]
@@ -121,6 +258,30 @@ impl<P: Debug> Messenger<P> {
         self.send_message(message)
     }
 
+    #[instrument(level = "trace", skip(self))]
+    pub fn connect_unix(&self, path: PathBuf) -> Result<(), error::MessageError> {
+        let message = Message::ConnectUnix(path);
+
+        self.send_message(message)
+    }
+
+    #[instrument(level = "trace", skip(self))]
+    pub fn bind_unix(&self, path: PathBuf) -> Result<(), error::MessageError> {
+        let message = Message::BindUnix(path);
+
+        self.send_message(message)
+    }
+
+    /// Connects over whichever transport `addr` uses
+    #[instrument(level = "trace", skip(self))]
+    pub fn connect_peer(&self, addr: PeerAddr) -> Result<(), error::MessageError> {
+        match addr {
+            PeerAddr::Tcp(addr) => self.connect_to(addr),
+            PeerAddr::Unix(path) => self.connect_unix(path),
+            PeerAddr::Loopback(id) => self.send_message(Message::ConnectLoopback(id)),
+        }
+    }
+
     #[instrument(level = "trace", skip(self))]
     pub fn shutdown(&self) -> Result<(), error::MessageError> {
         let message = Message::Shutdown;
@@ -128,6 +289,17 @@ impl<P: Debug> Messenger<P> {
         self.send_message(message)
     }
 
+    /// Shuts down the worker once every peer's pending writes have been sent
+    ///
+    /// Unlike the other methods this also wakes the worker
+    #[instrument(level = "trace", skip(self))]
+    pub fn flush_and_shutdown(&self, timeout: Duration) -> Result<(), error::MessageError> {
+        let message = Message::FlushShutdown(timeout);
+
+        self.send_message(message)?;
+        self.wake()
+    }
+
     #[instrument(level = "trace", skip(self))]
     pub fn send_message(&self, message: Message<P>) -> Result<(), error::MessageError> {
         self.sender
@@ -0,0 +1,207 @@
+use std::{
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use crossbeam::channel::{self, Receiver, Sender, TryRecvError};
+use mio::{event::Source, Interest, Registry, Token, Waker};
+
+use crate::{
+    stream::Stream,
+    tuning::{SocketTuning, TcpOptions},
+    PeerAddr,
+};
+
+static NEXT_PAIR: AtomicU64 = AtomicU64::new(0);
+
+/// One end of an in-process link, created by [`crate::Networking::loopback_pair`]
+#[derive(Debug)]
+pub struct LoopbackEnd {
+    pub stream: LoopbackStream,
+    /// Whether this end reports the link as accepted rather than connected
+    pub accepted: bool,
+}
+
+/// Creates both ends of a link, each wakes the other's worker when it writes or is dropped
+pub fn pair(acceptor: Arc<Waker>, connector: Arc<Waker>) -> (LoopbackEnd, LoopbackEnd) {
+    let id = NEXT_PAIR.fetch_add(1, Ordering::Relaxed);
+
+    let (to_acceptor, from_connector) = channel::unbounded();
+    let (to_connector, from_acceptor) = channel::unbounded();
+
+    let acceptor_end = LoopbackEnd {
+        stream: LoopbackStream {
+            id,
+            tx: to_connector,
+            rx: from_connector,
+            partial: Vec::new(),
+            read_index: 0,
+            remote: connector,
+        },
+        accepted: true,
+    };
+    let connector_end = LoopbackEnd {
+        stream: LoopbackStream {
+            id,
+            tx: to_acceptor,
+            rx: from_acceptor,
+            partial: Vec::new(),
+            read_index: 0,
+            remote: acceptor,
+        },
+        accepted: false,
+    };
+
+    (acceptor_end, connector_end)
+}
+
+/// Carries bytes to the other end over a channel
+///
+/// mio can't poll it, so the worker treats it as always writable and reads it after every wake
+/// up. Reads report end of stream once the other end is dropped, like a closed socket.
+#[derive(Debug)]
+pub struct LoopbackStream {
+    id: u64,
+    tx: Sender<Vec<u8>>,
+    rx: Receiver<Vec<u8>>,
+
+    /// The chunk currently being read
+    partial: Vec<u8>,
+    read_index: usize,
+
+    /// Wakes the worker of the other end
+    remote: Arc<Waker>,
+}
+
+impl Read for LoopbackStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.read_index == self.partial.len() {
+            match self.rx.try_recv() {
+                Ok(chunk) => {
+                    self.partial = chunk;
+                    self.read_index = 0;
+                }
+                Err(TryRecvError::Empty) => return Err(io::ErrorKind::WouldBlock.into()),
+                Err(TryRecvError::Disconnected) => return Ok(0),
+            }
+        }
+
+        let available = &self.partial[self.read_index..];
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.read_index += count;
+
+        Ok(count)
+    }
+}
+
+impl Write for LoopbackStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.tx.send(buf.to_vec()).is_err() {
+            // The other end is gone
+            return Ok(0);
+        }
+
+        self.remote.wake()?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Source for LoopbackStream {
+    fn register(&mut self, _: &Registry, _: Token, _: Interest) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn reregister(&mut self, _: &Registry, _: Token, _: Interest) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn deregister(&mut self, _: &Registry) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Stream for LoopbackStream {
+    fn peer_addr(&self) -> io::Result<PeerAddr> {
+        Ok(PeerAddr::Loopback(self.id))
+    }
+
+    fn setup(&mut self, _options: &TcpOptions) -> Option<SocketTuning> {
+        None
+    }
+}
+
+impl Drop for LoopbackStream {
+    fn drop(&mut self) {
+        // Lets the other end notice the link closed
+        let _ = self.remote.wake();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{ErrorKind, Read, Write},
+        sync::Arc,
+    };
+
+    use mio::{Poll, Token, Waker};
+
+    use super::pair;
+    use crate::{stream::Stream, PeerAddr};
+
+    fn waker(poll: &Poll) -> Arc<Waker> {
+        Arc::new(Waker::new(poll.registry(), Token(0)).unwrap())
+    }
+
+    #[test]
+    fn bytes_cross_in_order() {
+        let (poll_a, poll_b) = (Poll::new().unwrap(), Poll::new().unwrap());
+        let (mut a, mut b) = pair(waker(&poll_a), waker(&poll_b));
+        assert!(a.accepted && !b.accepted);
+
+        b.stream.write_all(b"hello").unwrap();
+        b.stream.write_all(b" world").unwrap();
+
+        let mut buf = [0; 8];
+        assert_eq!(a.stream.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+
+        // Chunks larger than the read are split across reads
+        assert_eq!(a.stream.read(&mut buf[..4]).unwrap(), 4);
+        assert_eq!(a.stream.read(&mut buf).unwrap(), 2);
+        assert_eq!(
+            a.stream.read(&mut buf).unwrap_err().kind(),
+            ErrorKind::WouldBlock
+        );
+
+        assert_eq!(a.stream.peer_addr().unwrap(), b.stream.peer_addr().unwrap());
+        assert!(matches!(
+            a.stream.peer_addr().unwrap(),
+            PeerAddr::Loopback(_)
+        ));
+    }
+
+    #[test]
+    fn dropping_an_end_closes_the_link() {
+        let (poll_a, poll_b) = (Poll::new().unwrap(), Poll::new().unwrap());
+        let (mut a, mut b) = pair(waker(&poll_a), waker(&poll_b));
+
+        a.stream.write_all(b"bye").unwrap();
+        drop(a);
+
+        let mut buf = [0; 8];
+        // Whatever was sent first is still delivered
+        assert_eq!(b.stream.read(&mut buf).unwrap(), 3);
+        assert_eq!(b.stream.read(&mut buf).unwrap(), 0);
+        assert_eq!(b.stream.write(b"anyone?").unwrap(), 0);
+    }
+}
@@ -1,22 +1,39 @@
-use mio::net::TcpStream;
 use tracing::{instrument, trace, warn};
 
 use std::{
     fmt::{self, Debug},
     io::{Read, Write},
+    mem,
 };
 
 use crate::{
     buf::Buffer,
+    crypto::{FrameCipher, Handshake, PresharedKey, HELLO_LEN, TAG_LEN},
     error::{NetError, NetResult},
-    header, raw, Packet,
+    header, raw,
+    stream::Stream,
+    tuning::{SocketTuning, TcpOptions},
+    Packet, PeerAddr,
 };
 
 pub struct Peer<S> {
     pub conected: bool,
+    /// Set once the hello exchange is done and packets can flow
+    pub established: bool,
 
     pub writeable: bool,
 
+    /// Where the peer is, reported once the link is established
+    pub addr: Option<PeerAddr>,
+    /// Whether the peer connected to one of our acceptors
+    pub accepted: bool,
+
+    key: Option<PresharedKey>,
+    handshake: Option<Handshake>,
+    cipher: Option<FrameCipher>,
+
+    /// Frames written before the link was established, sealed once it is
+    pub pending: Buffer,
     pub write_buffer: Buffer,
     pub read_buffer: Buffer,
 
@@ -24,10 +41,17 @@ pub struct Peer<S> {
 }
 
 impl<S> Peer<S> {
-    pub fn new(socket: S) -> Self {
+    pub fn new(socket: S, key: Option<PresharedKey>) -> Self {
         Peer {
             conected: false,
+            established: false,
             writeable: false,
+            addr: None,
+            accepted: false,
+            key,
+            handshake: None,
+            cipher: None,
+            pending: Buffer::new(),
             write_buffer: Buffer::new(),
             read_buffer: Buffer::new(),
             socket,
@@ -39,19 +63,28 @@ impl<S> Debug for Peer<S> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Peer")
             .field("connected", &self.conected)
+            .field("established", &self.established)
+            .field("encrypted", &self.cipher.is_some())
             .field("writeable", &self.writeable)
+            .field("pending", &self.pending)
             .field("write_buffer", &self.write_buffer)
             .field("read_buffer", &self.read_buffer)
             .finish_non_exhaustive()
     }
 }
 
-impl Peer<TcpStream> {
-    pub fn connect(&mut self) -> NetResult<()> {
+impl<S: Stream> Peer<S> {
+    /// Returns the options a TCP socket ended up with
+    pub fn connect(&mut self, tcp: &TcpOptions) -> NetResult<Option<SocketTuning>> {
         self.conected = true;
-        self.socket.set_nodelay(true)?;
+        let tuning = self.socket.setup(tcp);
 
-        Ok(())
+        // Sent before anything else, see `crypto`
+        let handshake = Handshake::new(self.key)?;
+        self.write_buffer.copy_from(&handshake.hello());
+        self.handshake = Some(handshake);
+
+        Ok(tuning)
     }
 }
 
@@ -67,6 +100,18 @@ where
         // Write the packet to the buffer
         write_packet_to_buffer(packet, temp)?;
 
+        // Sealed once the hello exchange decides how
+        if !self.established {
+            trace!("Peer not established, data buffered");
+            self.pending.copy_from(temp.get_written());
+
+            return Ok(());
+        }
+
+        if let Some(cipher) = &mut self.cipher {
+            seal_frame(temp, cipher)?;
+        }
+
         // Write the buffer to the socket
         {
             if self.conected && self.writeable {
@@ -103,7 +148,11 @@ where
 
 impl<S: Read> Peer<S> {
     #[instrument(level = "trace")]
-    pub fn read_packet<P: Packet>(&mut self, temp: &mut Buffer) -> NetResult<Option<P>> {
+    pub fn read_packet<P: Packet>(
+        &mut self,
+        temp: &mut Buffer,
+        max_frame_size: usize,
+    ) -> NetResult<Option<P>> {
         temp.reset();
 
         // Copy any unprocessed data from last read
@@ -114,7 +163,8 @@ impl<S: Read> Peer<S> {
         // And a single read call may return multiple packets
         let packet = loop {
             // Attempt to parse a packet
-            if let Some(packet) = try_read_one_packet_from_buffer(temp)? {
+            let cipher = self.cipher.as_mut();
+            if let Some(packet) = try_read_one_packet_from_buffer(temp, max_frame_size, cipher)? {
                 trace!("Full packet");
                 break Some(packet);
             }
@@ -141,6 +191,69 @@ impl<S: Read> Peer<S> {
     }
 }
 
+impl<S: Read> Peer<S>
+where
+    for<'a> &'a mut S: Write,
+{
+    /// Reads and checks the peer's hello, returns true once the link is established
+    #[instrument(level = "trace")]
+    pub fn read_handshake(&mut self, temp: &mut Buffer) -> NetResult<bool> {
+        if self.established {
+            return Ok(true);
+        }
+
+        temp.reset();
+        temp.copy_from(self.read_buffer.get_written());
+        self.read_buffer.reset();
+
+        while temp.len() < HELLO_LEN {
+            let readable = raw::raw_read_once(&mut self.socket, temp)?;
+
+            if !readable {
+                trace!("Incomplete hello");
+                self.read_buffer.copy_from(temp.get_written());
+                return Ok(false);
+            }
+        }
+
+        let hello: &[u8; HELLO_LEN] = temp
+            .advance_read(HELLO_LEN)
+            .try_into()
+            .expect("Read HELLO_LEN bytes");
+        let handshake = self.handshake.take().ok_or(NetError::BadHandshake)?;
+        self.cipher = handshake.finish(hello)?;
+        self.established = true;
+
+        // Packets may have followed the hello
+        self.read_buffer.copy_from(temp.get_written());
+
+        trace!(encrypted = self.cipher.is_some(), "Link established");
+
+        // Send what was written while waiting on the peer
+        let pending = mem::take(&mut self.pending);
+        let mut remaining = pending.get_written();
+        while !remaining.is_empty() {
+            let mut body = remaining;
+            let len = header::Header::read(&mut body).expect("Pending frames are complete");
+            let (frame, rest) = remaining.split_at(header::HEADER_SIZE + len);
+            remaining = rest;
+
+            temp.reset();
+            temp.copy_from(frame);
+            if let Some(cipher) = &mut self.cipher {
+                seal_frame(temp, cipher)?;
+            }
+            self.write_buffer.copy_from(temp.get_written());
+        }
+
+        if self.writeable && !self.write_buffer.is_empty() {
+            self.write_remaining()?;
+        }
+
+        Ok(true)
+    }
+}
+
 #[instrument(level = "trace", skip_all)]
 fn write_packet_to_buffer<P: Packet>(packet: &P, temp: &mut Buffer) -> NetResult<()> {
     // Get a write slice of the correct size
@@ -183,13 +296,43 @@ fn write_packet_to_buffer<P: Packet>(packet: &P, temp: &mut Buffer) -> NetResult
     Ok(())
 }
 
+/// Encrypts the single frame in `temp`, growing it by [`TAG_LEN`]
 #[instrument(level = "trace", skip_all)]
-fn try_read_one_packet_from_buffer<P: Packet>(temp: &mut Buffer) -> NetResult<Option<P>> {
+fn seal_frame(temp: &mut Buffer, cipher: &mut FrameCipher) -> NetResult<()> {
+    let frame = temp.get_written_mut();
+    let (header, body) = frame.split_at_mut(header::HEADER_SIZE);
+
+    // The header covers the tag too
+    let sealed_size = body.len() + TAG_LEN;
+    header::Header::new(&mut &mut header[..])
+        .write(sealed_size)
+        .map_err(|_| NetError::OversizedPacket(sealed_size))?;
+
+    let tag = cipher.seal(header, body)?;
+    temp.copy_from(&tag);
+
+    Ok(())
+}
+
+#[instrument(level = "trace", skip_all)]
+fn try_read_one_packet_from_buffer<P: Packet>(
+    temp: &mut Buffer,
+    max_frame_size: usize,
+    cipher: Option<&mut FrameCipher>,
+) -> NetResult<Option<P>> {
+    // Sealed frames carry a tag on top of the packet
+    let overhead = if cipher.is_some() { TAG_LEN } else { 0 };
+
     let mut maybe_complete_packet_buf = temp.get_written();
 
     // Check if a complete packet is available
     let len = header::Header::read(&mut maybe_complete_packet_buf);
     if let Some(len) = len {
+        // Reject before buffering the rest of the frame
+        if len > max_frame_size + overhead {
+            return Err(NetError::FrameTooLarge(len, max_frame_size));
+        }
+
         trace!(len, "Good header");
 
         let available = maybe_complete_packet_buf.len();
@@ -199,10 +342,20 @@ fn try_read_one_packet_from_buffer<P: Packet>(temp: &mut Buffer) -> NetResult<Op
         if available >= len {
             trace!(available, len, "Readable Packet");
 
-            // We've already read the header, discard it
-            temp.advance_read(header::HEADER_SIZE);
+            // We've already read the header, keep it to authenticate sealed frames
+            let frame_header: [u8; header::HEADER_SIZE] = temp
+                .advance_read(header::HEADER_SIZE)
+                .try_into()
+                .expect("Read HEADER_SIZE bytes");
             // Get the packet slice
-            let mut complete_packet_buf = temp.advance_read(len);
+            let frame = temp.advance_read_mut(len);
+            let mut complete_packet_buf: &[u8] = match cipher {
+                Some(cipher) => {
+                    let len = cipher.open(&frame_header, frame)?;
+                    &frame[..len]
+                }
+                None => frame,
+            };
 
             // Try to parse the packet
             let packet = P::read_buf(&mut complete_packet_buf).map_err(NetError::ParsingError)?;
@@ -233,43 +386,49 @@ mod tests {
 
     use crate::{
         buf::Buffer,
-        peer::{try_read_one_packet_from_buffer, write_packet_to_buffer},
+        crypto::{FrameCipher, Handshake, PresharedKey, TAG_LEN},
+        error::NetError,
+        header::{Header, HEADER_SIZE},
+        peer::{seal_frame, try_read_one_packet_from_buffer, write_packet_to_buffer},
         Packet,
     };
 
-    #[test]
-    fn roundtrip_packet() {
-        #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
-        struct Proto {
-            int: u64,
-            float: f64,
-            string: String,
-        }
+    const MAX_FRAME: usize = 1024;
 
-        impl Packet for Proto {
-            fn expected_size(&self) -> anyhow::Result<u64> {
-                options()
-                    .serialized_size(self)
-                    .context("Could not compute expected size")
-            }
+    #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+    struct Proto {
+        int: u64,
+        float: f64,
+        string: String,
+    }
 
-            fn write_buf(&self, buffer: &mut &mut [u8]) -> anyhow::Result<()> {
-                options()
-                    .serialize_into(buffer, self)
-                    .context("Could not serialize packet")
-            }
+    impl Packet for Proto {
+        fn expected_size(&self) -> anyhow::Result<u64> {
+            options()
+                .serialized_size(self)
+                .context("Could not compute expected size")
+        }
 
-            fn read_buf(buffer: &mut &[u8]) -> anyhow::Result<Self> {
-                options()
-                    .deserialize_from(buffer)
-                    .context("Could not deserialize packet")
-            }
+        fn write_buf(&self, buffer: &mut &mut [u8]) -> anyhow::Result<()> {
+            options()
+                .serialize_into(buffer, self)
+                .context("Could not serialize packet")
         }
 
-        fn options() -> impl Options {
-            DefaultOptions::new()
+        fn read_buf(buffer: &mut &[u8]) -> anyhow::Result<Self> {
+            options()
+                .with_limit(buffer.len() as u64)
+                .deserialize_from(buffer)
+                .context("Could not deserialize packet")
         }
+    }
 
+    fn options() -> impl Options {
+        DefaultOptions::new()
+    }
+
+    #[test]
+    fn roundtrip_packet() {
         let mut buffer = Buffer::new();
 
         let packet_1 = Proto {
@@ -294,19 +453,187 @@ mod tests {
         write_packet_to_buffer(&packet_2, &mut buffer).expect("Write packet");
         write_packet_to_buffer(&packet_3, &mut buffer).expect("Write packet");
 
-        let packet: Proto = try_read_one_packet_from_buffer(&mut buffer)
+        let packet: Proto = try_read_one_packet_from_buffer(&mut buffer, MAX_FRAME, None)
             .expect("Read packet")
             .expect("Parse packet");
         assert_eq!(packet, packet_1, "Packet 1");
 
-        let packet: Proto = try_read_one_packet_from_buffer(&mut buffer)
+        let packet: Proto = try_read_one_packet_from_buffer(&mut buffer, MAX_FRAME, None)
             .expect("Read packet")
             .expect("Parse packet");
         assert_eq!(packet, packet_2, "Packet 2");
 
-        let packet: Proto = try_read_one_packet_from_buffer(&mut buffer)
+        let packet: Proto = try_read_one_packet_from_buffer(&mut buffer, MAX_FRAME, None)
             .expect("Read packet")
             .expect("Parse packet");
         assert_eq!(packet, packet_3, "Packet 3");
     }
+
+    #[test]
+    fn oversized_frame_rejected() {
+        let mut buffer = Buffer::new();
+
+        let mut header = [0; HEADER_SIZE];
+        Header::new(&mut &mut header[..])
+            .write(MAX_FRAME + 1)
+            .unwrap();
+        buffer.copy_from(&header);
+
+        let rst = try_read_one_packet_from_buffer::<Proto>(&mut buffer, MAX_FRAME, None);
+        assert!(
+            matches!(rst, Err(NetError::FrameTooLarge(len, MAX_FRAME)) if len == MAX_FRAME + 1)
+        );
+
+        // Frames at the limit wait for the rest of their data
+        let mut buffer = Buffer::new();
+        Header::new(&mut &mut header[..]).write(MAX_FRAME).unwrap();
+        buffer.copy_from(&header);
+
+        let rst = try_read_one_packet_from_buffer::<Proto>(&mut buffer, MAX_FRAME, None);
+        assert!(matches!(rst, Ok(None)));
+    }
+
+    /// Feeds arbitrary and corrupted byte streams to the reader, it must never panic
+    #[test]
+    fn arbitrary_streams_never_panic() {
+        // xorshift, keeps the test deterministic without extra dependencies
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let mut valid = Buffer::new();
+        let packet = Proto {
+            int: 7,
+            float: 1.5,
+            string: "Some packet".to_owned(),
+        };
+        write_packet_to_buffer(&packet, &mut valid).unwrap();
+        let valid = valid.get_written().to_vec();
+
+        for round in 0..10_000 {
+            let mut bytes = if round % 2 == 0 {
+                // Pure noise
+                let len = next() as usize % 64;
+                (0..len).map(|_| next() as u8).collect::<Vec<_>>()
+            } else {
+                // A real packet with a few bytes flipped
+                let mut bytes = valid.clone();
+                for _ in 0..1 + next() % 4 {
+                    let idx = next() as usize % bytes.len();
+                    bytes[idx] = next() as u8;
+                }
+                bytes
+            };
+
+            // Sometimes chop the end off
+            if next() % 4 == 0 {
+                let len = next() as usize % (bytes.len() + 1);
+                bytes.truncate(len);
+            }
+
+            let mut buffer = Buffer::new();
+            buffer.copy_from(&bytes);
+
+            while let Ok(Some(_)) =
+                try_read_one_packet_from_buffer::<Proto>(&mut buffer, MAX_FRAME, None)
+            {}
+        }
+    }
+
+    fn ciphers() -> (FrameCipher, FrameCipher) {
+        let key = PresharedKey::from_secret(b"secret");
+        let a = Handshake::new(key).unwrap();
+        let b = Handshake::new(key).unwrap();
+
+        (
+            a.finish(&b.hello()).unwrap().unwrap(),
+            b.finish(&a.hello()).unwrap().unwrap(),
+        )
+    }
+
+    fn sealed(packet: &Proto, cipher: &mut FrameCipher) -> Buffer {
+        let mut buffer = Buffer::new();
+        write_packet_to_buffer(packet, &mut buffer).unwrap();
+        seal_frame(&mut buffer, cipher).unwrap();
+
+        buffer
+    }
+
+    #[test]
+    fn roundtrip_sealed_packet() {
+        let (mut send, mut recv) = ciphers();
+
+        let packet = Proto {
+            int: 42,
+            float: core::f64::consts::PI,
+            string: "Hello world".to_owned(),
+        };
+
+        let mut plain = Buffer::new();
+        write_packet_to_buffer(&packet, &mut plain).unwrap();
+
+        let mut buffer = Buffer::new();
+        for _ in 0..3 {
+            let frame = sealed(&packet, &mut send);
+            assert_eq!(frame.len(), plain.len() + TAG_LEN);
+            buffer.copy_from(frame.get_written());
+        }
+
+        for _ in 0..3 {
+            let read: Proto =
+                try_read_one_packet_from_buffer(&mut buffer, MAX_FRAME, Some(&mut recv))
+                    .expect("Read packet")
+                    .expect("Parse packet");
+            assert_eq!(read, packet);
+        }
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn tampered_frame_rejected() {
+        let (mut send, mut recv) = ciphers();
+
+        let packet = Proto {
+            int: 7,
+            float: 1.5,
+            string: "Some packet".to_owned(),
+        };
+
+        // Flip a bit in the body
+        let mut buffer = sealed(&packet, &mut send);
+        buffer.get_written_mut()[HEADER_SIZE + 2] ^= 0x10;
+        let rst = try_read_one_packet_from_buffer::<Proto>(&mut buffer, MAX_FRAME, Some(&mut recv));
+        assert!(matches!(rst, Err(NetError::Unauthenticated)));
+
+        // Cleartext where a sealed frame was expected
+        let (_, mut recv) = ciphers();
+        let mut buffer = Buffer::new();
+        write_packet_to_buffer(&packet, &mut buffer).unwrap();
+        let rst = try_read_one_packet_from_buffer::<Proto>(&mut buffer, MAX_FRAME, Some(&mut recv));
+        assert!(matches!(rst, Err(NetError::Unauthenticated)));
+    }
+
+    #[test]
+    fn frame_limit_allows_for_the_tag() {
+        let (_, mut recv) = ciphers();
+
+        let mut header = [0; HEADER_SIZE];
+        Header::new(&mut &mut header[..])
+            .write(MAX_FRAME + TAG_LEN)
+            .unwrap();
+
+        let mut buffer = Buffer::new();
+        buffer.copy_from(&header);
+        let rst = try_read_one_packet_from_buffer::<Proto>(&mut buffer, MAX_FRAME, Some(&mut recv));
+        assert!(matches!(rst, Ok(None)));
+
+        let mut buffer = Buffer::new();
+        buffer.copy_from(&header);
+        let rst = try_read_one_packet_from_buffer::<Proto>(&mut buffer, MAX_FRAME, None);
+        assert!(matches!(rst, Err(NetError::FrameTooLarge(..))));
+    }
 }
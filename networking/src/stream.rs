@@ -0,0 +1,177 @@
+use std::io::{self, Read, Write};
+
+use mio::{
+    event::Source,
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{
+    tuning::{self, SocketTuning, TcpOptions},
+    PeerAddr,
+};
+
+/// A socket carrying a connection to a single peer
+pub trait Stream: Read + Write + Source {
+    /// Fails with `NotConnected` until the connection is established
+    fn peer_addr(&self) -> io::Result<PeerAddr>;
+
+    /// Configures the socket once it is connected, returns what a TCP socket ended up with
+    fn setup(&mut self, options: &TcpOptions) -> Option<SocketTuning>;
+}
+
+/// A socket accepting connections from peers
+pub trait Listener: Source {
+    fn accept(&self) -> io::Result<(Box<dyn Stream>, PeerAddr)>;
+}
+
+impl<S: Stream + ?Sized> Stream for Box<S> {
+    fn peer_addr(&self) -> io::Result<PeerAddr> {
+        (**self).peer_addr()
+    }
+
+    fn setup(&mut self, options: &TcpOptions) -> Option<SocketTuning> {
+        (**self).setup(options)
+    }
+}
+
+impl Stream for TcpStream {
+    fn peer_addr(&self) -> io::Result<PeerAddr> {
+        TcpStream::peer_addr(self).map(PeerAddr::Tcp)
+    }
+
+    fn setup(&mut self, options: &TcpOptions) -> Option<SocketTuning> {
+        Some(with_sock_ref(self, |socket| tuning::apply(socket, options)))
+    }
+}
+
+/// mio's sockets predate `AsFd`, so socket2 is handed a borrow of the raw descriptor
+#[cfg(unix)]
+fn with_sock_ref<T>(stream: &TcpStream, f: impl FnOnce(socket2::SockRef<'_>) -> T) -> T {
+    use std::os::fd::{AsRawFd, BorrowedFd};
+
+    // SAFETY: `stream` owns the descriptor and outlives the borrow
+    let fd = unsafe { BorrowedFd::borrow_raw(stream.as_raw_fd()) };
+    f(socket2::SockRef::from(&fd))
+}
+
+#[cfg(windows)]
+fn with_sock_ref<T>(stream: &TcpStream, f: impl FnOnce(socket2::SockRef<'_>) -> T) -> T {
+    use std::os::windows::io::{AsRawSocket, BorrowedSocket};
+
+    // SAFETY: `stream` owns the socket and outlives the borrow
+    let socket = unsafe { BorrowedSocket::borrow_raw(stream.as_raw_socket()) };
+    f(socket2::SockRef::from(&socket))
+}
+
+impl Listener for TcpListener {
+    fn accept(&self) -> io::Result<(Box<dyn Stream>, PeerAddr)> {
+        let (socket, addr) = TcpListener::accept(self)?;
+
+        Ok((Box::new(socket), PeerAddr::Tcp(addr)))
+    }
+}
+
+/// Starts connecting to `addr`, the connection is established once the socket becomes writable
+pub fn connect(addr: &PeerAddr) -> io::Result<Box<dyn Stream>> {
+    match addr {
+        PeerAddr::Tcp(addr) => Ok(Box::new(TcpStream::connect(*addr)?)),
+        PeerAddr::Unix(path) => unix::connect(path),
+        PeerAddr::Loopback(_) => Err(loopback_unsupported()),
+    }
+}
+
+pub fn bind(addr: &PeerAddr) -> io::Result<Box<dyn Listener>> {
+    match addr {
+        PeerAddr::Tcp(addr) => Ok(Box::new(TcpListener::bind(*addr)?)),
+        PeerAddr::Unix(path) => unix::bind(path),
+        PeerAddr::Loopback(_) => Err(loopback_unsupported()),
+    }
+}
+
+fn loopback_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Loopback links are made with Networking::loopback_pair",
+    )
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{fs, io, os::unix::fs::FileTypeExt, path::Path};
+
+    use mio::net::{SocketAddr, UnixListener, UnixStream};
+
+    use super::{Listener, Stream};
+    use crate::{
+        tuning::{SocketTuning, TcpOptions},
+        PeerAddr,
+    };
+
+    fn to_peer_addr(addr: &SocketAddr) -> Option<PeerAddr> {
+        addr.as_pathname()
+            .map(|path| PeerAddr::Unix(path.to_owned()))
+    }
+
+    impl Stream for UnixStream {
+        fn peer_addr(&self) -> io::Result<PeerAddr> {
+            let addr = UnixStream::peer_addr(self)?;
+
+            Ok(to_peer_addr(&addr).unwrap_or_else(|| PeerAddr::Unix(Default::default())))
+        }
+
+        fn setup(&mut self, _options: &TcpOptions) -> Option<SocketTuning> {
+            None
+        }
+    }
+
+    impl Listener for UnixListener {
+        fn accept(&self) -> io::Result<(Box<dyn Stream>, PeerAddr)> {
+            let (socket, addr) = UnixListener::accept(self)?;
+
+            // The connecting side is almost always unnamed, identify it by the path it connected to
+            let addr = match to_peer_addr(&addr) {
+                Some(addr) => addr,
+                None => to_peer_addr(&self.local_addr()?)
+                    .unwrap_or_else(|| PeerAddr::Unix(Default::default())),
+            };
+
+            Ok((Box::new(socket), addr))
+        }
+    }
+
+    pub fn connect(path: &Path) -> io::Result<Box<dyn Stream>> {
+        Ok(Box::new(UnixStream::connect(path)?))
+    }
+
+    pub fn bind(path: &Path) -> io::Result<Box<dyn Listener>> {
+        // A previous run that didn't exit cleanly leaves its socket file behind
+        let stale = fs::symlink_metadata(path).is_ok_and(|it| it.file_type().is_socket());
+        if stale {
+            fs::remove_file(path)?;
+        }
+
+        Ok(Box::new(UnixListener::bind(path)?))
+    }
+}
+
+#[cfg(not(unix))]
+mod unix {
+    use std::{io, path::Path};
+
+    use super::{Listener, Stream};
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Unix domain sockets are not supported on this platform",
+        )
+    }
+
+    pub fn connect(_path: &Path) -> io::Result<Box<dyn Stream>> {
+        Err(unsupported())
+    }
+
+    pub fn bind(_path: &Path) -> io::Result<Box<dyn Listener>> {
+        Err(unsupported())
+    }
+}
@@ -0,0 +1,248 @@
+//! Socket options applied to every TCP connection
+//!
+//! Nagle's algorithm holds small writes back until the previous one is acknowledged, which is
+//! most of what we send. Keepalive lets the OS notice a peer that vanished without closing the
+//! connection, like a pulled tether, even when nothing else is being sent.
+
+use std::{io, time::Duration};
+
+use socket2::{SockRef, TcpKeepalive};
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpOptions {
+    /// Disables Nagle's algorithm so small packets are sent right away
+    pub nodelay: bool,
+    /// Probes idle connections, `None` turns keepalive off
+    pub keepalive: Option<Keepalive>,
+    /// `SO_SNDBUF` in bytes, `None` keeps the OS default
+    pub send_buffer: Option<usize>,
+    /// `SO_RCVBUF` in bytes, `None` keeps the OS default
+    pub recv_buffer: Option<usize>,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            keepalive: Some(Keepalive::default()),
+            send_buffer: None,
+            recv_buffer: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keepalive {
+    /// How long a connection is idle before it is first probed
+    pub idle: Duration,
+    /// Time between unanswered probes, ignored where the platform doesn't allow setting it
+    pub interval: Duration,
+    /// Unanswered probes before the connection is dropped, ignored where the platform doesn't
+    /// allow setting it
+    pub retries: u32,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            idle: Duration::from_secs(5),
+            interval: Duration::from_secs(1),
+            retries: 3,
+        }
+    }
+}
+
+impl Keepalive {
+    fn params(&self) -> TcpKeepalive {
+        let params = TcpKeepalive::new().with_time(self.idle);
+
+        #[cfg(any(
+            target_os = "linux",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "freebsd"
+        ))]
+        let params = params
+            .with_interval(self.interval)
+            .with_retries(self.retries);
+
+        params
+    }
+}
+
+/// What a socket reported after [`TcpOptions`] were applied, `None` where it couldn't be read
+///
+/// Linux reports buffer sizes doubled, it counts its own bookkeeping against them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SocketTuning {
+    pub nodelay: Option<bool>,
+    pub keepalive: Option<bool>,
+    pub keepalive_idle: Option<Duration>,
+    pub keepalive_interval: Option<Duration>,
+    pub keepalive_retries: Option<u32>,
+    pub send_buffer: Option<usize>,
+    pub recv_buffer: Option<usize>,
+
+    /// Options that could not be set, the socket keeps its previous value for them
+    pub failed: Vec<&'static str>,
+}
+
+/// Applies `options` to `socket`, failing to set one is only a warning
+pub fn apply(socket: SockRef<'_>, options: &TcpOptions) -> SocketTuning {
+    let mut failed = Vec::new();
+    let mut check = |option, res: io::Result<()>| {
+        if let Err(err) = res {
+            warn!("Could not set {option}: {err}");
+            failed.push(option);
+        }
+    };
+
+    check("TCP_NODELAY", socket.set_nodelay(options.nodelay));
+
+    match options.keepalive {
+        Some(keepalive) => check(
+            "SO_KEEPALIVE",
+            socket.set_tcp_keepalive(&keepalive.params()),
+        ),
+        None => check("SO_KEEPALIVE", socket.set_keepalive(false)),
+    }
+
+    if let Some(size) = options.send_buffer {
+        check("SO_SNDBUF", socket.set_send_buffer_size(size));
+    }
+    if let Some(size) = options.recv_buffer {
+        check("SO_RCVBUF", socket.set_recv_buffer_size(size));
+    }
+
+    SocketTuning {
+        failed,
+        ..read(&socket)
+    }
+}
+
+/// Reads back the options a socket is using
+pub fn read(socket: &SockRef<'_>) -> SocketTuning {
+    let keepalive = socket.keepalive().ok();
+    let (keepalive_idle, keepalive_interval, keepalive_retries) = if keepalive == Some(true) {
+        read_keepalive(socket)
+    } else {
+        (None, None, None)
+    };
+
+    SocketTuning {
+        nodelay: socket.nodelay().ok(),
+        keepalive,
+        keepalive_idle,
+        keepalive_interval,
+        keepalive_retries,
+        send_buffer: socket.send_buffer_size().ok(),
+        recv_buffer: socket.recv_buffer_size().ok(),
+        failed: Vec::new(),
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+))]
+fn read_keepalive(socket: &SockRef<'_>) -> (Option<Duration>, Option<Duration>, Option<u32>) {
+    (
+        socket.keepalive_time().ok(),
+        socket.keepalive_interval().ok(),
+        socket.keepalive_retries().ok(),
+    )
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd"
+)))]
+fn read_keepalive(_socket: &SockRef<'_>) -> (Option<Duration>, Option<Duration>, Option<u32>) {
+    (None, None, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{TcpListener, TcpStream},
+        time::Duration,
+    };
+
+    use socket2::SockRef;
+
+    use super::{apply, Keepalive, TcpOptions};
+
+    fn pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        (client, server)
+    }
+
+    #[test]
+    fn options_are_applied() {
+        let (client, server) = pair();
+
+        let options = TcpOptions {
+            keepalive: Some(Keepalive {
+                idle: Duration::from_secs(7),
+                interval: Duration::from_secs(2),
+                retries: 4,
+            }),
+            send_buffer: Some(64 * 1024),
+            recv_buffer: Some(64 * 1024),
+            ..Default::default()
+        };
+
+        for socket in [&client, &server] {
+            let tuning = apply(SockRef::from(socket), &options);
+            assert!(tuning.failed.is_empty(), "{:?}", tuning.failed);
+
+            // Checked with getsockopt rather than trusting what `apply` reports
+            let socket = SockRef::from(socket);
+            assert!(socket.nodelay().unwrap());
+            assert!(socket.keepalive().unwrap());
+            assert_eq!(tuning.nodelay, Some(true));
+
+            // Linux doubles whatever it is given
+            assert!(socket.send_buffer_size().unwrap() >= 64 * 1024);
+            assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+
+            #[cfg(target_os = "linux")]
+            {
+                assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(7));
+                assert_eq!(socket.keepalive_interval().unwrap(), Duration::from_secs(2));
+                assert_eq!(socket.keepalive_retries().unwrap(), 4);
+                assert_eq!(tuning.keepalive_retries, Some(4));
+            }
+        }
+    }
+
+    #[test]
+    fn options_can_be_turned_off() {
+        let (client, _server) = pair();
+
+        let options = TcpOptions {
+            nodelay: false,
+            keepalive: None,
+            ..Default::default()
+        };
+        let tuning = apply(SockRef::from(&client), &options);
+
+        let socket = SockRef::from(&client);
+        assert!(!socket.nodelay().unwrap());
+        assert!(!socket.keepalive().unwrap());
+        assert_eq!(tuning.nodelay, Some(false));
+        assert_eq!(tuning.keepalive, Some(false));
+        assert_eq!(tuning.keepalive_idle, None);
+    }
+}
@@ -1,18 +1,20 @@
 use crate::{
-    acceptor::Acceptor, buf::Buffer, error::NetError, peer::Peer, Event, Message, Packet,
-    PROBE_LENGTH, WAKER_TOKEN,
+    acceptor::Acceptor,
+    buf::Buffer,
+    error::NetError,
+    loopback::LoopbackEnd,
+    peer::Peer,
+    stream::{self, Listener, Stream},
+    Event, Message, NetworkingOptions, Packet, PeerAddr, PresharedKey, PROBE_LENGTH, WAKER_TOKEN,
 };
 use ahash::HashMap;
 use crossbeam::channel::Receiver;
-use mio::{
-    net::{TcpListener, TcpStream},
-    Events, Interest, Poll, Token,
-};
+use mio::{Events, Interest, Poll, Token};
 use std::{
     io::ErrorKind,
     sync::atomic::{AtomicUsize, Ordering},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tracing::{error, instrument, trace, trace_span, warn};
 
@@ -22,16 +24,42 @@ static NEXT_TOKEN: AtomicUsize = AtomicUsize::new(1);
 pub fn start_worker<P: Packet>(
     mut poll: Poll,
     receiver: Receiver<Message<P>>,
+    options: NetworkingOptions,
+    loopback: Option<LoopbackEnd>,
     mut handler: impl FnMut(Event<P>),
 ) {
-    let mut peers = HashMap::default();
-    let mut accptors = HashMap::default();
+    let mut peers: HashMap<Token, Peer<Box<dyn Stream>>> = HashMap::default();
+    let mut accptors: HashMap<Token, Acceptor<Box<dyn Listener>>> = HashMap::default();
     let mut temp_buf = Buffer::with_capacity(PROBE_LENGTH * 2);
 
+    // Loopback peers aren't polled by mio, they are serviced after every wake up instead
+    let mut loopbacks = Vec::new();
+    if let Some(end) = loopback {
+        if let Some(token) = add_loopback(&mut peers, end, options.preshared_key, &mut handler) {
+            loopbacks.push(token);
+        }
+    }
+
     let mut events = Events::with_capacity(2048);
 
+    // Set once a flush shutdown is requested
+    let mut flush_deadline: Option<Instant> = None;
+
     'outer: loop {
-        let res = poll.poll(&mut events, None);
+        loopbacks.retain(|token| peers.contains_key(token));
+        for token in loopbacks.clone() {
+            handle_peer(
+                &mut peers,
+                token,
+                Readiness::LOOPBACK,
+                &mut temp_buf,
+                &options,
+                &mut handler,
+            );
+        }
+
+        let timeout = flush_deadline.map(|it| it.saturating_duration_since(Instant::now()));
+        let res = poll.poll(&mut events, timeout);
 
         if let Err(err) = res {
             error!("Could not poll, sleeping 300ms");
@@ -42,7 +70,7 @@ pub fn start_worker<P: Packet>(
             continue 'outer;
         }
 
-        'event: for event in &events {
+        for event in &events {
             trace!(?event, "Got event");
             let _span = trace_span!("Handle event").entered();
 
@@ -53,93 +81,38 @@ pub fn start_worker<P: Packet>(
                     trace!(?message, "Got control message");
 
                     match message {
-                        Message::Connect(peer) => {
-                            let _span = trace_span!("Connect to peer", ?peer).entered();
-
-                            // Create socket
-                            let res = TcpStream::connect(peer);
-                            let mut socket = match res {
-                                Ok(socket) => socket,
-                                Err(err) => {
-                                    trace!("Could not create TcpStream");
-
-                                    (handler)(Event::Error(
-                                        None,
-                                        NetError::from(err).chain("Connect to peer".to_owned()),
-                                    ));
-                                    continue 'message;
-                                }
-                            };
-
-                            // Assign Token
-                            let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
-                            let token = Token(token);
-
-                            trace!(?token, "Assigned token");
-
-                            // Register event intreast
-                            let res = poll.registry().register(
-                                &mut socket,
-                                token,
-                                Interest::READABLE | Interest::WRITABLE,
+                        Message::Connect(addr) => {
+                            connect_peer(
+                                &poll,
+                                &mut peers,
+                                PeerAddr::Tcp(addr),
+                                options.preshared_key,
+                                &mut handler,
+                            );
+                        }
+                        Message::ConnectUnix(path) => {
+                            connect_peer(
+                                &poll,
+                                &mut peers,
+                                PeerAddr::Unix(path),
+                                options.preshared_key,
+                                &mut handler,
+                            );
+                        }
+                        Message::ConnectLoopback(id) => {
+                            connect_peer(
+                                &poll,
+                                &mut peers,
+                                PeerAddr::Loopback(id),
+                                options.preshared_key,
+                                &mut handler,
                             );
-                            if let Err(err) = res {
-                                trace!("Could not add to registry");
-
-                                (handler)(Event::Error(
-                                    Some(token),
-                                    NetError::from(err).chain("Register socket".to_owned()),
-                                ));
-                                (handler)(Event::Disconnect(token));
-                                continue 'message;
-                            }
-
-                            let peer = Peer::new(socket);
-
-                            // Register peer
-                            peers.insert(token, peer);
                         }
                         Message::Bind(addr) => {
-                            let _span = trace_span!("Bind to address", ?addr).entered();
-
-                            // Create listner
-                            let listener = TcpListener::bind(addr);
-                            let mut listener = match listener {
-                                Ok(socket) => socket,
-                                Err(err) => {
-                                    trace!("Could not create TcpListener");
-
-                                    (handler)(Event::Error(
-                                        None,
-                                        NetError::from(err).chain("Bind listner".to_owned()),
-                                    ));
-                                    continue 'message;
-                                }
-                            };
-
-                            // Assign token
-                            let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
-                            let token = Token(token);
-
-                            trace!(?token, "Assigned token");
-
-                            // Register event intreast
-                            let res =
-                                poll.registry()
-                                    .register(&mut listener, token, Interest::READABLE);
-                            if let Err(err) = res {
-                                trace!("Could not add to registry");
-
-                                (handler)(Event::Error(
-                                    Some(token),
-                                    NetError::from(err).chain("Register listner".to_owned()),
-                                ));
-                                (handler)(Event::Disconnect(token));
-                                continue 'message;
-                            }
-
-                            // Register acceptor
-                            accptors.insert(token, Acceptor { listener });
+                            bind_acceptor(&poll, &mut accptors, PeerAddr::Tcp(addr), &mut handler);
+                        }
+                        Message::BindUnix(path) => {
+                            bind_acceptor(&poll, &mut accptors, PeerAddr::Unix(path), &mut handler);
                         }
                         Message::Disconect(token) => {
                             let _span = trace_span!("Disconnect", ?token).entered();
@@ -208,119 +181,22 @@ pub fn start_worker<P: Packet>(
                         Message::Shutdown => {
                             break 'outer;
                         }
-                    }
-                }
-            } else if let Some(peer) = peers.get_mut(&event.token()) {
-                trace!(?peer, "Got peer event");
-                let _span = trace_span!("Handle peer event", ?peer).entered();
-
-                // Peers don't connect instantly
-                // Set up the socket if the peer just connected
-                // else ignore events for unconected peers
-                if !peer.conected && !event.is_error() {
-                    if event.is_writable() {
-                        let _span = trace_span!("Connect to peer").entered();
-
-                        match peer.socket.peer_addr() {
-                            Ok(addr) => {
-                                let res = peer.connect();
-                                match res {
-                                    Ok(()) => {
-                                        trace!("Connection established with peer");
-                                        (handler)(Event::Conected(event.token(), addr));
-
-                                        // Happy path
-                                    }
-                                    Err(err) => {
-                                        // Couldnt setup the peer's socket
-                                        trace!("Connection with peer failed");
-
-                                        (handler)(Event::Error(
-                                            Some(event.token()),
-                                            err.chain("Setup peer socket".to_owned()),
-                                        ));
-                                        (handler)(Event::Disconnect(event.token()));
-                                        peers.remove(&event.token());
-                                        continue 'event;
-                                    }
-                                }
-                            }
-                            Err(err) if err.kind() == ErrorKind::NotConnected => {
-                                // Try again on the next event
-                                trace!("Connection remains unestablished");
-
-                                continue 'event;
-                            }
-                            Err(err) => {
-                                // Couldnt connect for whatever reason
-                                trace!("Connection with peer failed");
-
-                                (handler)(Event::Error(
-                                    Some(event.token()),
-                                    NetError::from(err).chain("Connect to peer".to_owned()),
-                                ));
-                                (handler)(Event::Disconnect(event.token()));
-                                peers.remove(&event.token());
-                                continue 'event;
-                            }
-                        }
-                    } else {
-                        // Shouldn't be hit but this is not guranetted
-                        // Ignore false event
-                        trace!("Bad event");
-                        continue 'event;
-                    }
-                }
-
-                // Handle the socket being newly writeable
-                if event.is_writable() {
-                    let _span = trace_span!("Peer writable").entered();
-
-                    // Write any buffered packets
-                    // Also marks peer as writeable if it preaviously wasnt
-                    let res = peer.write_remaining();
-                    if let Err(err) = res {
-                        trace!("Write failed");
-
-                        (handler)(Event::Error(
-                            Some(event.token()),
-                            err.chain("Write packets".to_owned()),
-                        ));
-                        (handler)(Event::Disconnect(event.token()));
-                        peers.remove(&event.token());
-                        continue 'event;
-                    }
-                }
-
-                // Handle the socket being newly readable
-                if event.is_readable() {
-                    let _span = trace_span!("Peer readable").entered();
+                        Message::FlushShutdown(timeout) => {
+                            trace!(?timeout, "Flushing before shutdown");
 
-                    // Read all incomming packets from peer
-                    'packets: loop {
-                        let res = peer.read_packet(&mut temp_buf);
-                        trace!(result = ?res, "Read packet");
-                        match res {
-                            Ok(Some(packet)) => {
-                                (handler)(Event::Data(event.token(), packet));
-                            }
-                            Ok(None) => {
-                                break 'packets;
-                            }
-                            Err(err) => {
-                                trace!("Read packet failed");
-
-                                (handler)(Event::Error(
-                                    Some(event.token()),
-                                    err.chain("Read packets".to_owned()),
-                                ));
-                                (handler)(Event::Disconnect(event.token()));
-                                peers.remove(&event.token());
-                                continue 'event;
-                            }
+                            flush_deadline = Some(Instant::now() + timeout);
                         }
                     }
                 }
+            } else if peers.contains_key(&event.token()) {
+                handle_peer(
+                    &mut peers,
+                    event.token(),
+                    Readiness::from(event),
+                    &mut temp_buf,
+                    &options,
+                    &mut handler,
+                );
             } else if let Some(acceptor) = accptors.get_mut(&event.token()) {
                 trace!("Got acceptor event");
                 let _span = trace_span!("Handle acceptor event").entered();
@@ -369,24 +245,32 @@ pub fn start_worker<P: Packet>(
                             continue 'accept;
                         }
 
-                        let mut peer = Peer::new(socket);
+                        let mut peer = Peer::new(socket, options.preshared_key);
 
                         // Should already be connected
                         // Setup the socket
-                        let res = peer.connect();
-                        if let Err(err) = res {
-                            trace!("Could not connect to new peer");
+                        let res = peer.connect(&options.tcp);
+                        match res {
+                            Ok(Some(tuning)) => {
+                                (handler)(Event::SocketTuned(token, tuning));
+                            }
+                            Ok(None) => {}
+                            Err(err) => {
+                                trace!("Could not connect to new peer");
 
-                            (handler)(Event::Error(
-                                Some(token),
-                                err.chain("Setup accepted socket".to_owned()),
-                            ));
-                            (handler)(Event::Disconnect(token));
-                            continue 'accept;
+                                (handler)(Event::Error(
+                                    Some(token),
+                                    err.chain("Setup accepted socket".to_owned()),
+                                ));
+                                (handler)(Event::Disconnect(token));
+                                continue 'accept;
+                            }
                         }
 
+                        // Announced once the hello exchange is done
                         trace!("New peer accepted");
-                        (handler)(Event::Accepted(token, addr));
+                        peer.addr = Some(addr);
+                        peer.accepted = true;
 
                         // Register peer
                         peers.insert(token, peer);
@@ -396,5 +280,353 @@ pub fn start_worker<P: Packet>(
                 warn!("Got event for unknown token");
             }
         }
+
+        if let Some(deadline) = flush_deadline {
+            let pending = peers.values().any(|peer| {
+                peer.conected && (!peer.write_buffer.is_empty() || !peer.pending.is_empty())
+            });
+
+            if !pending {
+                trace!("Flushed all peers");
+                break 'outer;
+            }
+
+            if Instant::now() >= deadline {
+                warn!("Timed out flushing peers, pending writes will be lost");
+                break 'outer;
+            }
+        }
     }
 }
+
+/// Readiness of a peer's stream, from mio or assumed for loopback streams
+#[derive(Debug, Clone, Copy)]
+struct Readiness {
+    readable: bool,
+    writable: bool,
+    error: bool,
+}
+
+impl Readiness {
+    /// Loopback streams can always be written and are read until they would block
+    const LOOPBACK: Readiness = Readiness {
+        readable: true,
+        writable: true,
+        error: false,
+    };
+}
+
+impl From<&mio::event::Event> for Readiness {
+    fn from(event: &mio::event::Event) -> Self {
+        Self {
+            readable: event.is_readable(),
+            writable: event.is_writable(),
+            error: event.is_error(),
+        }
+    }
+}
+
+fn handle_peer<P: Packet>(
+    peers: &mut HashMap<Token, Peer<Box<dyn Stream>>>,
+    token: Token,
+    readiness: Readiness,
+    temp_buf: &mut Buffer,
+    options: &NetworkingOptions,
+    handler: &mut impl FnMut(Event<P>),
+) {
+    let Some(peer) = peers.get_mut(&token) else {
+        return;
+    };
+
+    trace!(?peer, "Got peer event");
+    let _span = trace_span!("Handle peer event", ?peer).entered();
+
+    // Peers don't connect instantly
+    // Set up the socket if the peer just connected
+    // else ignore events for unconected peers
+    if !peer.conected && !readiness.error {
+        if readiness.writable {
+            let _span = trace_span!("Connect to peer").entered();
+
+            match peer.socket.peer_addr() {
+                Ok(addr) => {
+                    let res = peer.connect(&options.tcp);
+                    match res {
+                        Ok(tuning) => {
+                            // Announced once the hello exchange is done
+                            trace!("Connection established with peer");
+                            peer.addr = Some(addr);
+
+                            if let Some(tuning) = tuning {
+                                (handler)(Event::SocketTuned(token, tuning));
+                            }
+
+                            // Happy path
+                        }
+                        Err(err) => {
+                            // Couldnt setup the peer's socket
+                            trace!("Connection with peer failed");
+
+                            (handler)(Event::Error(
+                                Some(token),
+                                err.chain("Setup peer socket".to_owned()),
+                            ));
+                            (handler)(Event::Disconnect(token));
+                            peers.remove(&token);
+                            return;
+                        }
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::NotConnected => {
+                    // Try again on the next event
+                    trace!("Connection remains unestablished");
+
+                    return;
+                }
+                Err(err) => {
+                    // Couldnt connect for whatever reason
+                    trace!("Connection with peer failed");
+
+                    (handler)(Event::Error(
+                        Some(token),
+                        NetError::from(err).chain("Connect to peer".to_owned()),
+                    ));
+                    (handler)(Event::Disconnect(token));
+                    peers.remove(&token);
+                    return;
+                }
+            }
+        } else {
+            // Shouldn't be hit but this is not guranetted
+            // Ignore false event
+            trace!("Bad event");
+            return;
+        }
+    }
+
+    // Handle the socket being newly writeable
+    if readiness.writable {
+        let _span = trace_span!("Peer writable").entered();
+
+        // Write any buffered packets
+        // Also marks peer as writeable if it preaviously wasnt
+        let res = peer.write_remaining();
+        if let Err(err) = res {
+            trace!("Write failed");
+
+            (handler)(Event::Error(
+                Some(token),
+                err.chain("Write packets".to_owned()),
+            ));
+            (handler)(Event::Disconnect(token));
+            peers.remove(&token);
+            return;
+        }
+    }
+
+    // Handle the socket being newly readable
+    if readiness.readable {
+        let _span = trace_span!("Peer readable").entered();
+
+        // The peer's hello comes before any packets
+        if !peer.established {
+            let res = peer.read_handshake(temp_buf);
+            match res {
+                Ok(true) => {
+                    trace!("Link established with peer");
+
+                    let addr = peer.addr.clone().expect("Connected peer has addr");
+                    if peer.accepted {
+                        (handler)(Event::Accepted(token, addr));
+                    } else {
+                        (handler)(Event::Conected(token, addr));
+                    }
+                }
+                Ok(false) => {
+                    return;
+                }
+                Err(err) => {
+                    trace!("Handshake failed");
+
+                    (handler)(Event::Error(
+                        Some(token),
+                        err.chain("Handshake with peer".to_owned()),
+                    ));
+                    (handler)(Event::Disconnect(token));
+                    peers.remove(&token);
+                    return;
+                }
+            }
+        }
+
+        // Read all incomming packets from peer
+        'packets: loop {
+            let res = peer.read_packet(temp_buf, options.max_frame_size);
+            trace!(result = ?res, "Read packet");
+            match res {
+                Ok(Some(packet)) => {
+                    (handler)(Event::Data(token, packet));
+                }
+                Ok(None) => {
+                    break 'packets;
+                }
+                Err(err) => {
+                    trace!("Read packet failed");
+
+                    (handler)(Event::Error(
+                        Some(token),
+                        err.chain("Read packets".to_owned()),
+                    ));
+                    (handler)(Event::Disconnect(token));
+                    peers.remove(&token);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Adds this worker's end of a loopback link as a peer
+///
+/// The accepting end is set up right away like an accepted socket, the connecting end finishes
+/// connecting on its first service like a socket that became writable. Neither emits
+/// `Connecting` since there is nothing to dial again.
+fn add_loopback<P>(
+    peers: &mut HashMap<Token, Peer<Box<dyn Stream>>>,
+    end: LoopbackEnd,
+    key: Option<PresharedKey>,
+    handler: &mut impl FnMut(Event<P>),
+) -> Option<Token> {
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+    let token = Token(token);
+
+    let _span = trace_span!("Add loopback peer", ?token).entered();
+
+    let addr = end.stream.peer_addr();
+    let mut peer = Peer::new(Box::new(end.stream) as Box<dyn Stream>, key);
+
+    if end.accepted {
+        // Loopback streams have no socket options, nothing to report
+        let res = peer.connect(&Default::default());
+        if let Err(err) = res {
+            trace!("Could not set up loopback peer");
+
+            (handler)(Event::Error(
+                Some(token),
+                err.chain("Setup loopback peer".to_owned()),
+            ));
+            (handler)(Event::Disconnect(token));
+            return None;
+        }
+
+        peer.addr = addr.ok();
+        peer.accepted = true;
+    }
+
+    peers.insert(token, peer);
+
+    Some(token)
+}
+
+fn connect_peer<P>(
+    poll: &Poll,
+    peers: &mut HashMap<Token, Peer<Box<dyn Stream>>>,
+    addr: PeerAddr,
+    key: Option<PresharedKey>,
+    handler: &mut impl FnMut(Event<P>),
+) {
+    let _span = trace_span!("Connect to peer", ?addr).entered();
+
+    // Assign Token, before creating the socket so failures can be tied to the address
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+    let token = Token(token);
+
+    trace!(?token, "Assigned token");
+    (handler)(Event::Connecting(token, addr.clone()));
+
+    // Create socket
+    let res = stream::connect(&addr);
+    let mut socket = match res {
+        Ok(socket) => socket,
+        Err(err) => {
+            trace!("Could not create stream");
+
+            (handler)(Event::Error(
+                Some(token),
+                NetError::from(err).chain("Connect to peer".to_owned()),
+            ));
+            (handler)(Event::Disconnect(token));
+            return;
+        }
+    };
+
+    // Register event intreast
+    let res = poll
+        .registry()
+        .register(&mut socket, token, Interest::READABLE | Interest::WRITABLE);
+    if let Err(err) = res {
+        trace!("Could not add to registry");
+
+        (handler)(Event::Error(
+            Some(token),
+            NetError::from(err).chain("Register socket".to_owned()),
+        ));
+        (handler)(Event::Disconnect(token));
+        return;
+    }
+
+    let peer = Peer::new(socket, key);
+
+    // Register peer
+    peers.insert(token, peer);
+}
+
+fn bind_acceptor<P>(
+    poll: &Poll,
+    accptors: &mut HashMap<Token, Acceptor<Box<dyn Listener>>>,
+    addr: PeerAddr,
+    handler: &mut impl FnMut(Event<P>),
+) {
+    let _span = trace_span!("Bind to address", ?addr).entered();
+
+    // Create listner
+    let listener = stream::bind(&addr);
+    let mut listener = match listener {
+        Ok(socket) => socket,
+        Err(err) => {
+            trace!("Could not create listener");
+
+            (handler)(Event::Error(
+                None,
+                NetError::from(err).chain("Bind listner".to_owned()),
+            ));
+            (handler)(Event::BindFailed(addr));
+            return;
+        }
+    };
+
+    // Assign token
+    let token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+    let token = Token(token);
+
+    trace!(?token, "Assigned token");
+
+    // Register event intreast
+    let res = poll
+        .registry()
+        .register(&mut listener, token, Interest::READABLE);
+    if let Err(err) = res {
+        trace!("Could not add to registry");
+
+        (handler)(Event::Error(
+            Some(token),
+            NetError::from(err).chain("Register listner".to_owned()),
+        ));
+        (handler)(Event::BindFailed(addr));
+        return;
+    }
+
+    // Register acceptor
+    accptors.insert(token, Acceptor { listener });
+    (handler)(Event::Bound(token, addr));
+}
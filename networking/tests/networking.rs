@@ -1,7 +1,8 @@
 use std::{
+    io::{Read, Write},
     net::ToSocketAddrs,
     sync::{
-        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering},
         Barrier,
     },
     thread,
@@ -10,7 +11,7 @@ use std::{
 
 use anyhow::Context;
 use bincode::{DefaultOptions, Options};
-use networking::{Event, Networking, Packet};
+use networking::{Event, Messenger, Networking, NetworkingOptions, Packet, PresharedKey, Token};
 use serde::{Deserialize, Serialize};
 
 #[test]
@@ -36,6 +37,12 @@ fn test_real_server_client() -> anyhow::Result<()> {
             .name("Peer A".to_owned())
             .spawn_scoped(scope, || {
                 peer_a.start(|event| match event {
+                    Event::Connecting(_token, _addr) => {
+                        // Dont care
+                    }
+                    Event::SocketTuned(_token, _tuning) => {
+                        // Dont care
+                    }
                     Event::Conected(_token, _socket) => {
                         connected.fetch_add(1, Ordering::Relaxed);
                     }
@@ -50,6 +57,9 @@ fn test_real_server_client() -> anyhow::Result<()> {
                             pong.fetch_add(id, Ordering::Relaxed);
                         }
                     },
+                    Event::Bound(..) | Event::BindFailed(..) => {
+                        // Dont care
+                    }
                     Event::Disconnect(_token) => {
                         // Dont care
                     }
@@ -68,6 +78,12 @@ fn test_real_server_client() -> anyhow::Result<()> {
             .name("Peer B".to_owned())
             .spawn_scoped(scope, || {
                 peer_b.start(|event| match event {
+                    Event::Connecting(_token, _addr) => {
+                        // Dont care
+                    }
+                    Event::SocketTuned(_token, _tuning) => {
+                        // Dont care
+                    }
                     Event::Conected(_token, _socket) => {
                         connected.fetch_add(1, Ordering::Relaxed);
                     }
@@ -82,6 +98,9 @@ fn test_real_server_client() -> anyhow::Result<()> {
                             pong.fetch_add(id, Ordering::Relaxed);
                         }
                     },
+                    Event::Bound(..) | Event::BindFailed(..) => {
+                        // Dont care
+                    }
                     Event::Disconnect(_token) => {
                         // Dont care
                     }
@@ -209,6 +228,848 @@ fn test_real_server_client() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_flush_before_shutdown() -> anyhow::Result<()> {
+    flush_before_shutdown(Transport::Tcp(7168))
+}
+
+#[test]
+fn test_flush_before_shutdown_loopback() -> anyhow::Result<()> {
+    flush_before_shutdown(Transport::Loopback)
+}
+
+fn flush_before_shutdown(transport: Transport) -> anyhow::Result<()> {
+    const PACKETS: u64 = 64;
+    const PAYLOAD: usize = 256 * 1024;
+
+    let received = AtomicU64::new(0);
+    let client_token = AtomicUsize::new(usize::MAX);
+
+    let (server, client) = transport.pair::<Bulk>(Default::default(), Default::default())?;
+    let messenger_server = server.messenger();
+    let messenger_client = client.messenger();
+
+    thread::scope(|scope| -> anyhow::Result<()> {
+        thread::Builder::new()
+            .name("Server".to_owned())
+            .spawn_scoped(scope, || {
+                server.start(|event| {
+                    if let Event::Data(_token, Bulk(_, payload)) = event {
+                        assert_eq!(payload.len(), PAYLOAD);
+                        received.fetch_add(1, Ordering::SeqCst);
+
+                        // Slow reader so the client has to buffer
+                        thread::sleep(Duration::from_millis(5));
+                    }
+                });
+            })?;
+
+        let client_worker = thread::Builder::new()
+            .name("Client".to_owned())
+            .spawn_scoped(scope, || {
+                client.start(|event| {
+                    if let Event::Conected(token, _) = event {
+                        client_token.store(token.0, Ordering::SeqCst);
+                    }
+                });
+            })?;
+
+        transport.connect(&messenger_server, &messenger_client)?;
+
+        let token = wait_for(|| {
+            let token = client_token.load(Ordering::SeqCst);
+            (token != usize::MAX).then_some(Token(token))
+        })
+        .context("Connect")?;
+
+        // Far more than the socket can take at once, most of this ends up buffered
+        for idx in 0..PACKETS {
+            messenger_client.send_packet(token, Bulk(idx, vec![0; PAYLOAD]))?;
+        }
+        messenger_client.flush_and_shutdown(Duration::from_secs(10))?;
+
+        // The worker only returns once everything is written
+        client_worker.join().expect("Client worker panicked");
+
+        let all_received =
+            wait_for(|| (received.load(Ordering::SeqCst) == PACKETS).then_some(()));
+
+        messenger_server.shutdown()?;
+        messenger_server.wake()?;
+
+        all_received.context("Not all packets were received")
+    })
+}
+
+#[test]
+fn test_tcp_socket_options() -> anyhow::Result<()> {
+    use std::sync::Mutex;
+
+    use networking::{SocketTuning, TcpOptions};
+
+    let server_tuning: Mutex<Option<SocketTuning>> = Mutex::new(None);
+    let client_tuning: Mutex<Option<SocketTuning>> = Mutex::new(None);
+
+    let transport = Transport::Tcp(7232);
+    let (server, client) = transport.pair::<Protocol>(
+        Default::default(),
+        NetworkingOptions {
+            tcp: TcpOptions {
+                keepalive: None,
+                recv_buffer: Some(128 * 1024),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )?;
+    let messenger_server = server.messenger();
+    let messenger_client = client.messenger();
+
+    thread::scope(|scope| -> anyhow::Result<()> {
+        thread::Builder::new()
+            .name("Server".to_owned())
+            .spawn_scoped(scope, || {
+                server.start(|event| {
+                    if let Event::SocketTuned(_, tuning) = event {
+                        *server_tuning.lock().unwrap() = Some(tuning);
+                    }
+                });
+            })?;
+
+        thread::Builder::new()
+            .name("Client".to_owned())
+            .spawn_scoped(scope, || {
+                client.start(|event| {
+                    if let Event::SocketTuned(_, tuning) = event {
+                        *client_tuning.lock().unwrap() = Some(tuning);
+                    }
+                });
+            })?;
+
+        transport.connect(&messenger_server, &messenger_client)?;
+
+        let server = wait_for(|| server_tuning.lock().unwrap().clone()).context("Accept")?;
+        let client = wait_for(|| client_tuning.lock().unwrap().clone()).context("Connect")?;
+
+        // Read back from the sockets with getsockopt
+        assert_eq!(server.nodelay, Some(true));
+        assert_eq!(server.keepalive, Some(true));
+        assert!(server.failed.is_empty(), "{:?}", server.failed);
+
+        assert_eq!(client.nodelay, Some(true));
+        assert_eq!(client.keepalive, Some(false));
+        assert!(client.recv_buffer.is_some_and(|it| it >= 128 * 1024));
+        assert!(client.failed.is_empty(), "{:?}", client.failed);
+
+        messenger_client.shutdown()?;
+        messenger_client.wake()?;
+        messenger_server.shutdown()?;
+        messenger_server.wake()?;
+
+        Ok(())
+    })
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix_socket() -> anyhow::Result<()> {
+    use std::{os::unix::fs::FileTypeExt, sync::Mutex};
+
+    use networking::{error::NetError, PeerAddr};
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("robot.sock");
+
+    let server_peer: Mutex<Option<(Token, PeerAddr)>> = Mutex::new(None);
+    let client_peer: Mutex<Option<(Token, PeerAddr)>> = Mutex::new(None);
+    let bound = AtomicBool::new(false);
+    let pong = AtomicU64::new(0);
+    let client_disconnected = AtomicBool::new(false);
+    let shutting_down = AtomicBool::new(false);
+
+    let server = Networking::<Protocol>::new()?;
+    let messenger_server = server.messenger();
+
+    let client = Networking::<Protocol>::new()?;
+    let messenger_client = client.messenger();
+
+    // Left behind by a run that didn't exit cleanly, binding must replace it
+    drop(std::os::unix::net::UnixListener::bind(&path)?);
+    assert!(std::fs::symlink_metadata(&path)?.file_type().is_socket());
+
+    let on_error = |error: NetError| {
+        if !shutting_down.load(Ordering::SeqCst) {
+            panic!("Error: {error}");
+        }
+    };
+
+    thread::scope(|scope| -> anyhow::Result<()> {
+        thread::Builder::new()
+            .name("Server".to_owned())
+            .spawn_scoped(scope, || {
+                server.start(|event| match event {
+                    Event::Bound(_, _) => {
+                        bound.store(true, Ordering::SeqCst);
+                    }
+                    Event::Accepted(token, addr) => {
+                        *server_peer.lock().unwrap() = Some((token, addr));
+                    }
+                    Event::Data(token, Protocol::Ping(id)) => {
+                        messenger_server
+                            .send_packet(token, Protocol::Pong(id))
+                            .unwrap();
+                        messenger_server.wake().unwrap();
+                    }
+                    Event::Error(_, error) => on_error(error),
+                    _ => {}
+                });
+            })?;
+
+        thread::Builder::new()
+            .name("Client".to_owned())
+            .spawn_scoped(scope, || {
+                client.start(|event| match event {
+                    Event::Conected(token, addr) => {
+                        *client_peer.lock().unwrap() = Some((token, addr));
+                    }
+                    Event::Data(_, Protocol::Pong(id)) => {
+                        pong.fetch_add(id, Ordering::SeqCst);
+                    }
+                    Event::Disconnect(_) => {
+                        client_disconnected.store(true, Ordering::SeqCst);
+                    }
+                    Event::Error(_, error) => on_error(error),
+                    _ => {}
+                });
+            })?;
+
+        messenger_server.bind_unix(path.clone())?;
+        messenger_server.wake()?;
+        wait_for(|| bound.load(Ordering::SeqCst).then_some(())).context("Bind")?;
+
+        messenger_client.connect_peer(format!("unix:{}", path.display()).parse()?)?;
+        messenger_client.wake()?;
+
+        // Both sides name the peer by the socket's path
+        let (token, addr) = wait_for(|| client_peer.lock().unwrap().clone()).context("Connect")?;
+        assert_eq!(addr, PeerAddr::Unix(path.clone()));
+
+        let (_, addr) = wait_for(|| server_peer.lock().unwrap().clone()).context("Accept")?;
+        assert_eq!(addr, PeerAddr::Unix(path.clone()));
+
+        for id in 0..100 {
+            messenger_client.send_packet(token, Protocol::Ping(id))?;
+        }
+        messenger_client.wake()?;
+
+        let all_received = wait_for(|| (pong.load(Ordering::SeqCst) == 4950).then_some(()));
+
+        // The client notices the server going away
+        shutting_down.store(true, Ordering::SeqCst);
+        messenger_server.shutdown()?;
+        messenger_server.wake()?;
+        let disconnected = wait_for(|| client_disconnected.load(Ordering::SeqCst).then_some(()));
+
+        messenger_client.shutdown()?;
+        messenger_client.wake()?;
+
+        all_received.context("Not all pongs were received")?;
+        disconnected.context("Client never saw the server leave")
+    })
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unix_socket_spares_other_files() -> anyhow::Result<()> {
+    use std::sync::Mutex;
+
+    use networking::PeerAddr;
+
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("robot.toml");
+    std::fs::write(&path, "port = 44445")?;
+
+    let failed: Mutex<Option<PeerAddr>> = Mutex::new(None);
+
+    let server = Networking::<Protocol>::new()?;
+    let messenger_server = server.messenger();
+
+    thread::scope(|scope| -> anyhow::Result<()> {
+        thread::Builder::new()
+            .name("Server".to_owned())
+            .spawn_scoped(scope, || {
+                server.start(|event| {
+                    if let Event::BindFailed(addr) = event {
+                        *failed.lock().unwrap() = Some(addr);
+                    }
+                });
+            })?;
+
+        messenger_server.bind_unix(path.clone())?;
+        messenger_server.wake()?;
+
+        let failed = wait_for(|| failed.lock().unwrap().clone());
+
+        messenger_server.shutdown()?;
+        messenger_server.wake()?;
+
+        assert_eq!(failed.context("Bind never failed")?, PeerAddr::Unix(path.clone()));
+
+        Ok(())
+    })?;
+
+    // Only stale sockets are removed
+    assert_eq!(std::fs::read_to_string(&path)?, "port = 44445");
+
+    Ok(())
+}
+
+#[test]
+fn test_preshared_key() -> anyhow::Result<()> {
+    let key = PresharedKey::from_secret(b"correct horse battery staple");
+    for transport in [Transport::Tcp(7200), Transport::Loopback] {
+        let (connected, pong, errors) = ping_with_keys(transport, key, key)?;
+
+        assert!(connected);
+        assert_eq!(pong, 4950);
+        assert_eq!(errors, 0);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_mismatched_keys_fail_closed() -> anyhow::Result<()> {
+    let key = PresharedKey::from_secret(b"correct horse battery staple");
+    let other = PresharedKey::from_secret(b"some other key");
+
+    for (transport, fallback_transport) in [
+        (Transport::Tcp(7201), Transport::Tcp(7202)),
+        (Transport::Loopback, Transport::Loopback),
+    ] {
+        // Neither side ever sees the other connect
+        let (connected, pong, errors) = ping_with_keys(transport, key, other)?;
+        assert!(!connected);
+        assert_eq!(pong, 0);
+        assert!(errors > 0);
+
+        // A keyed side never falls back to cleartext
+        let (connected, pong, errors) = ping_with_keys(fallback_transport, key, None)?;
+        assert!(!connected);
+        assert_eq!(pong, 0);
+        assert!(errors > 0);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_loopback() -> anyhow::Result<()> {
+    use std::sync::Mutex;
+
+    use networking::{error::NetError, PeerAddr};
+
+    let server_peer: Mutex<Option<(Token, PeerAddr)>> = Mutex::new(None);
+    let client_peer: Mutex<Option<(Token, PeerAddr)>> = Mutex::new(None);
+    let disconnected = AtomicBool::new(false);
+    let pong = AtomicU64::new(0);
+    let shutting_down = AtomicBool::new(false);
+
+    let (server, client) = Networking::<Protocol>::loopback_pair()?;
+    let messenger_server = server.messenger();
+    let messenger_client = client.messenger();
+
+    let on_error = |error: NetError| {
+        if !shutting_down.load(Ordering::SeqCst) {
+            panic!("Error: {error}");
+        }
+    };
+
+    thread::scope(|scope| -> anyhow::Result<()> {
+        thread::Builder::new()
+            .name("Server".to_owned())
+            .spawn_scoped(scope, || {
+                server.start(|event| match event {
+                    Event::Connecting(..) => panic!("Loopback peers are never dialed"),
+                    Event::Accepted(token, addr) => {
+                        *server_peer.lock().unwrap() = Some((token, addr));
+                    }
+                    Event::Data(token, Protocol::Ping(id)) => {
+                        messenger_server
+                            .send_packet(token, Protocol::Pong(id))
+                            .unwrap();
+                        messenger_server.wake().unwrap();
+                    }
+                    Event::Disconnect(_) => {
+                        disconnected.store(true, Ordering::SeqCst);
+                    }
+                    Event::Error(_, error) => on_error(error),
+                    _ => {}
+                });
+            })?;
+
+        let client_worker = thread::Builder::new()
+            .name("Client".to_owned())
+            .spawn_scoped(scope, || {
+                client.start(|event| match event {
+                    Event::Connecting(..) => panic!("Loopback peers are never dialed"),
+                    Event::Conected(token, addr) => {
+                        *client_peer.lock().unwrap() = Some((token, addr));
+                    }
+                    Event::Data(_, Protocol::Pong(id)) => {
+                        pong.fetch_add(id, Ordering::SeqCst);
+                    }
+                    Event::Error(_, error) => panic!("Error: {error}"),
+                    _ => {}
+                });
+            })?;
+
+        let (token, client_addr) =
+            wait_for(|| client_peer.lock().unwrap().clone()).context("Connect")?;
+        let (_, server_addr) =
+            wait_for(|| server_peer.lock().unwrap().clone()).context("Accept")?;
+        assert!(matches!(client_addr, PeerAddr::Loopback(_)));
+        assert_eq!(client_addr, server_addr);
+
+        for id in 0..100 {
+            messenger_client.send_packet(token, Protocol::Ping(id))?;
+        }
+        messenger_client.wake()?;
+
+        let all_received = wait_for(|| (pong.load(Ordering::SeqCst) == 4950).then_some(()));
+
+        // Closing one end disconnects the other, like a socket
+        shutting_down.store(true, Ordering::SeqCst);
+        messenger_client.shutdown()?;
+        messenger_client.wake()?;
+        client_worker.join().expect("Client worker panicked");
+
+        let other_disconnected = wait_for(|| disconnected.load(Ordering::SeqCst).then_some(()));
+
+        messenger_server.shutdown()?;
+        messenger_server.wake()?;
+
+        all_received.context("Not all pongs were received")?;
+        other_disconnected.context("Server never saw the client leave")
+    })
+}
+
+#[test]
+fn test_loopback_frame_size_limit() -> anyhow::Result<()> {
+    use std::sync::Mutex;
+
+    use networking::error::NetError;
+
+    let (server, client) = Networking::<Bulk>::loopback_pair_with_options(
+        NetworkingOptions {
+            max_frame_size: 1024,
+            ..Default::default()
+        },
+        Default::default(),
+    )?;
+    let messenger_server = server.messenger();
+    let messenger_client = client.messenger();
+
+    let client_token = AtomicUsize::new(usize::MAX);
+    let server_events: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    let received = AtomicU64::new(0);
+
+    thread::scope(|scope| -> anyhow::Result<()> {
+        thread::Builder::new()
+            .name("Server".to_owned())
+            .spawn_scoped(scope, || {
+                server.start(|event| {
+                    let event = match event {
+                        Event::Data(..) => {
+                            received.fetch_add(1, Ordering::SeqCst);
+                            return;
+                        }
+                        Event::Error(_, error) => match root_cause(&error) {
+                            NetError::FrameTooLarge(_, 1024) => "FrameTooLarge",
+                            _ => panic!("Error: {error}"),
+                        },
+                        Event::Disconnect(_) => "Disconnect",
+                        _ => return,
+                    };
+
+                    server_events.lock().unwrap().push(event.to_owned());
+                });
+            })?;
+
+        thread::Builder::new()
+            .name("Client".to_owned())
+            .spawn_scoped(scope, || {
+                client.start(|event| {
+                    if let Event::Conected(token, _) = event {
+                        client_token.store(token.0, Ordering::SeqCst);
+                    }
+                });
+            })?;
+
+        let token = wait_for(|| {
+            let token = client_token.load(Ordering::SeqCst);
+            (token != usize::MAX).then_some(Token(token))
+        })
+        .context("Connect")?;
+
+        // Small frames still get through
+        messenger_client.send_packet(token, Bulk(0, vec![0; 16]))?;
+        messenger_client.send_packet(token, Bulk(1, vec![0; 4096]))?;
+        messenger_client.wake()?;
+
+        let events = wait_for(|| {
+            let events = server_events.lock().unwrap();
+            (events.len() >= 2).then(|| events.clone())
+        });
+
+        messenger_client.shutdown()?;
+        messenger_client.wake()?;
+        messenger_server.shutdown()?;
+        messenger_server.wake()?;
+
+        assert_eq!(
+            events.context("Oversized frame was not rejected")?,
+            ["FrameTooLarge", "Disconnect"]
+        );
+        assert_eq!(received.load(Ordering::SeqCst), 1);
+
+        Ok(())
+    })
+}
+
+#[test]
+fn test_rebind_keeps_connections() -> anyhow::Result<()> {
+    use std::sync::Mutex;
+
+    use networking::{error::ErrorClass, PeerAddr};
+
+    let old = ("127.0.0.1", 7260)
+        .to_socket_addrs()?
+        .next()
+        .context("Find SocketAddr")?;
+    let new = ("127.0.0.1", 7261)
+        .to_socket_addrs()?
+        .next()
+        .context("Find SocketAddr")?;
+
+    let listeners: Mutex<Vec<(Token, PeerAddr)>> = Mutex::new(Vec::new());
+    let bind_failures: Mutex<Vec<PeerAddr>> = Mutex::new(Vec::new());
+    let connected: Mutex<Vec<(Token, PeerAddr)>> = Mutex::new(Vec::new());
+    let refused = AtomicBool::new(false);
+    let pong = AtomicU64::new(0);
+    let shutting_down = AtomicBool::new(false);
+
+    let server = Networking::<Protocol>::new()?;
+    let messenger_server = server.messenger();
+
+    let client = Networking::<Protocol>::new()?;
+    let messenger_client = client.messenger();
+
+    thread::scope(|scope| -> anyhow::Result<()> {
+        thread::Builder::new()
+            .name("Server".to_owned())
+            .spawn_scoped(scope, || {
+                server.start(|event| match event {
+                    Event::Bound(token, addr) => {
+                        listeners.lock().unwrap().push((token, addr));
+                    }
+                    Event::BindFailed(addr) => {
+                        bind_failures.lock().unwrap().push(addr);
+                    }
+                    Event::Data(token, Protocol::Ping(id)) => {
+                        messenger_server
+                            .send_packet(token, Protocol::Pong(id))
+                            .unwrap();
+                        messenger_server.wake().unwrap();
+                    }
+                    Event::Error(_, error) => {
+                        let expected = error.class() == ErrorClass::AddrInUse;
+                        if !expected && !shutting_down.load(Ordering::SeqCst) {
+                            panic!("Error: {error}");
+                        }
+                    }
+                    _ => {}
+                });
+            })?;
+
+        thread::Builder::new()
+            .name("Client".to_owned())
+            .spawn_scoped(scope, || {
+                client.start(|event| match event {
+                    Event::Conected(token, addr) => {
+                        connected.lock().unwrap().push((token, addr));
+                    }
+                    Event::Data(_, Protocol::Pong(id)) => {
+                        pong.fetch_add(id, Ordering::SeqCst);
+                    }
+                    Event::Error(..) => {
+                        refused.store(true, Ordering::SeqCst);
+                    }
+                    _ => {}
+                });
+            })?;
+
+        messenger_server.bind_at(old)?;
+        messenger_server.wake()?;
+        let (old_listener, _) =
+            wait_for(|| listeners.lock().unwrap().first().cloned()).context("Bind old")?;
+
+        messenger_client.connect_to(old)?;
+        messenger_client.wake()?;
+        let (token, _) =
+            wait_for(|| connected.lock().unwrap().first().cloned()).context("Connect old")?;
+
+        // Taken ports fail without touching the listener already there
+        messenger_server.bind_at(old)?;
+        messenger_server.wake()?;
+        let failed = wait_for(|| bind_failures.lock().unwrap().first().cloned());
+        assert_eq!(failed, Some(PeerAddr::Tcp(old)));
+        assert_eq!(listeners.lock().unwrap().len(), 1);
+
+        messenger_server.bind_at(new)?;
+        messenger_server.wake()?;
+        let (_, bound) =
+            wait_for(|| listeners.lock().unwrap().get(1).cloned()).context("Bind new")?;
+        assert_eq!(bound, PeerAddr::Tcp(new));
+
+        messenger_server.disconnect(old_listener)?;
+        messenger_server.wake()?;
+        thread::sleep(Duration::from_millis(50));
+
+        // Still connected through the closed listener
+        for id in 0..100 {
+            messenger_client.send_packet(token, Protocol::Ping(id))?;
+        }
+        messenger_client.wake()?;
+        let all_received = wait_for(|| (pong.load(Ordering::SeqCst) == 4950).then_some(()));
+
+        messenger_client.connect_to(new)?;
+        messenger_client.wake()?;
+        let reconnected = wait_for(|| connected.lock().unwrap().get(1).cloned());
+
+        messenger_client.connect_to(old)?;
+        messenger_client.wake()?;
+        let old_refused = wait_for(|| refused.load(Ordering::SeqCst).then_some(()));
+
+        shutting_down.store(true, Ordering::SeqCst);
+        messenger_client.shutdown()?;
+        messenger_client.wake()?;
+        messenger_server.shutdown()?;
+        messenger_server.wake()?;
+
+        all_received.context("Not all pongs were received")?;
+        let (_, addr) = reconnected.context("Could not connect to the new port")?;
+        assert_eq!(addr, PeerAddr::Tcp(new));
+        old_refused.context("Old port still accepted connections")?;
+        assert_eq!(connected.lock().unwrap().len(), 2);
+
+        Ok(())
+    })
+}
+
+fn root_cause(mut error: &networking::error::NetError) -> &networking::error::NetError {
+    while let networking::error::NetError::Chain(_, inner) = error {
+        error = inner;
+    }
+
+    error
+}
+
+/// How a test's peers are linked
+#[derive(Debug, Clone, Copy)]
+enum Transport {
+    /// Over localhost on this port
+    Tcp(u16),
+    Loopback,
+}
+
+impl Transport {
+    /// Creates the server and client
+    fn pair<P: Packet>(
+        self,
+        server: NetworkingOptions,
+        client: NetworkingOptions,
+    ) -> anyhow::Result<(Networking<P>, Networking<P>)> {
+        match self {
+            Transport::Tcp(_) => Ok((
+                Networking::with_options(server)?,
+                Networking::with_options(client)?,
+            )),
+            Transport::Loopback => Ok(Networking::loopback_pair_with_options(server, client)?),
+        }
+    }
+
+    /// Connects the client to the server, loopback pairs are linked from the start
+    fn connect<P: Packet>(
+        self,
+        server: &Messenger<P>,
+        client: &Messenger<P>,
+    ) -> anyhow::Result<()> {
+        if let Transport::Tcp(port) = self {
+            let addr = ("127.0.0.1", port)
+                .to_socket_addrs()?
+                .next()
+                .context("Find SocketAddr")?;
+
+            server.bind_at(addr)?;
+            server.wake()?;
+            thread::sleep(Duration::from_millis(50));
+
+            client.connect_to(addr)?;
+            client.wake()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Connects a client to a server then pings it 100 times
+///
+/// Returns whether either side saw the other connect, the sum of the pongs and how many errors the
+/// peers saw
+fn ping_with_keys(
+    transport: Transport,
+    server_key: Option<PresharedKey>,
+    client_key: Option<PresharedKey>,
+) -> anyhow::Result<(bool, u64, usize)> {
+    let connected = AtomicBool::new(false);
+    let client_token = AtomicUsize::new(usize::MAX);
+    let pong = AtomicU64::new(0);
+    let errors = AtomicUsize::new(0);
+    let shutting_down = AtomicBool::new(false);
+
+    let (server, client) = transport.pair::<Protocol>(
+        NetworkingOptions {
+            preshared_key: server_key,
+            ..Default::default()
+        },
+        NetworkingOptions {
+            preshared_key: client_key,
+            ..Default::default()
+        },
+    )?;
+    let messenger_server = server.messenger();
+    let messenger_client = client.messenger();
+
+    // Whichever side shuts down first is seen leaving by the other
+    let on_error = || {
+        if !shutting_down.load(Ordering::SeqCst) {
+            errors.fetch_add(1, Ordering::SeqCst);
+        }
+    };
+
+    thread::scope(|scope| -> anyhow::Result<()> {
+        thread::Builder::new()
+            .name("Server".to_owned())
+            .spawn_scoped(scope, || {
+                server.start(|event| match event {
+                    Event::Accepted(..) => {
+                        connected.store(true, Ordering::SeqCst);
+                    }
+                    Event::Data(token, Protocol::Ping(id)) => {
+                        messenger_server
+                            .send_packet(token, Protocol::Pong(id))
+                            .unwrap();
+                        messenger_server.wake().unwrap();
+                    }
+                    Event::Error(..) => on_error(),
+                    _ => {}
+                });
+            })?;
+
+        thread::Builder::new()
+            .name("Client".to_owned())
+            .spawn_scoped(scope, || {
+                client.start(|event| match event {
+                    Event::Conected(token, _) => {
+                        connected.store(true, Ordering::SeqCst);
+                        client_token.store(token.0, Ordering::SeqCst);
+                    }
+                    Event::Data(_, Protocol::Pong(id)) => {
+                        pong.fetch_add(id, Ordering::SeqCst);
+                    }
+                    Event::Error(..) => on_error(),
+                    _ => {}
+                });
+            })?;
+
+        transport.connect(&messenger_server, &messenger_client)?;
+
+        let token = wait_for(|| {
+            // Both sides report the failed handshake
+            if errors.load(Ordering::SeqCst) >= 2 {
+                return Some(None);
+            }
+
+            let token = client_token.load(Ordering::SeqCst);
+            (token != usize::MAX).then_some(Some(Token(token)))
+        })
+        .context("Connect")?;
+
+        if let Some(token) = token {
+            for id in 0..100 {
+                messenger_client.send_packet(token, Protocol::Ping(id))?;
+            }
+            messenger_client.wake()?;
+
+            wait_for(|| (pong.load(Ordering::SeqCst) == 4950).then_some(()));
+        }
+
+        shutting_down.store(true, Ordering::SeqCst);
+        messenger_client.shutdown()?;
+        messenger_client.wake()?;
+        messenger_server.shutdown()?;
+        messenger_server.wake()?;
+
+        Ok(())
+    })?;
+
+    Ok((
+        connected.into_inner(),
+        pong.into_inner(),
+        errors.into_inner(),
+    ))
+}
+
+fn wait_for<T>(mut condition: impl FnMut() -> Option<T>) -> Option<T> {
+    for _ in 0..1000 {
+        if let Some(value) = condition() {
+            return Some(value);
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    None
+}
+
+/// Written by hand so the test is limited by the socket rather than by serialization speed
+#[derive(Clone, Debug)]
+struct Bulk(u64, Vec<u8>);
+
+impl Packet for Bulk {
+    fn expected_size(&self) -> anyhow::Result<u64> {
+        Ok(8 + self.1.len() as u64)
+    }
+
+    fn write_buf(&self, buffer: &mut &mut [u8]) -> anyhow::Result<()> {
+        buffer.write_all(&self.0.to_le_bytes())?;
+        buffer.write_all(&self.1)?;
+
+        Ok(())
+    }
+
+    fn read_buf(buffer: &mut &[u8]) -> anyhow::Result<Self> {
+        let mut idx = [0; 8];
+        buffer.read_exact(&mut idx)?;
+
+        let payload = buffer.to_vec();
+        *buffer = &[];
+
+        Ok(Bulk(u64::from_le_bytes(idx), payload))
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 enum Protocol {
     Ping(u64),
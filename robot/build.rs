@@ -1,3 +1,10 @@
+use std::{
+    env,
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
 fn main() {
     let arch = std::env::var("CARGO_CFG_TARGET_ARCH");
     if let Ok(arch) = arch {
@@ -5,4 +12,107 @@ fn main() {
             println!("cargo:rustc-cfg=rpi");
         }
     }
+
+    // Embedded in the robot's `BuildInfo`
+    watch_git();
+    println!("cargo:rustc-env=ROBOT_GIT_HASH={}", git_hash());
+    println!(
+        "cargo:rustc-env=ROBOT_BUILD_TIMESTAMP={}",
+        build_timestamp()
+    );
+    println!(
+        "cargo:rustc-env=ROBOT_BUILD_PROFILE={}",
+        env::var("PROFILE").unwrap_or_else(|_| "unknown".to_owned())
+    );
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Reruns the build script when a commit is made or checked out
+///
+/// Listing any file replaces cargo's default of rerunning on every change in the package, so the
+/// sources are listed too to keep the dirty flag current
+fn watch_git() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src");
+
+    let Some(git_dir) = git(&["rev-parse", "--git-dir"]) else {
+        return;
+    };
+    let git_dir = Path::new(&git_dir);
+
+    println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+    println!("cargo:rerun-if-changed={}", git_dir.join("index").display());
+    println!(
+        "cargo:rerun-if-changed={}",
+        git_dir.join("packed-refs").display()
+    );
+
+    // Detached heads have no ref to watch, HEAD itself changes instead
+    if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+        println!(
+            "cargo:rerun-if-changed={}",
+            git_dir.join(head_ref).display()
+        );
+    }
+}
+
+fn git_hash() -> String {
+    let Some(hash) = git(&["rev-parse", "--short", "HEAD"]) else {
+        return "unknown".to_owned();
+    };
+
+    let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+        .is_some_and(|status| !status.is_empty());
+
+    if dirty {
+        format!("{hash}-dirty")
+    } else {
+        hash
+    }
+}
+
+/// Honors `SOURCE_DATE_EPOCH` for reproducible builds
+fn build_timestamp() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|it| it.parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|it| it.as_secs())
+                .unwrap_or_default()
+        });
+
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let (year, month, day) = civil_from_days(days as i64);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// Converts days since the unix epoch to a date, from Howard Hinnant's `civil_from_days`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
 }
@@ -0,0 +1,219 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use clap::Parser;
+use common::{
+    cli::{layered, CommonArgs},
+    over_run::OverRunSettings,
+    sync::{LinkKey, LinkTcpOptions, SyncRole, DEFAULT_LOCAL_SOCKET},
+};
+
+use crate::config::RobotConfig;
+
+pub const DEFAULT_CONFIG: &str = "robot.toml";
+
+#[derive(Parser, Debug, Clone, Default, PartialEq)]
+#[command(name = "robot", about = "Runs the robot")]
+pub struct RobotArgs {
+    #[command(flatten)]
+    pub common: CommonArgs,
+    /// Port to accept connections from the surface on
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Run without the robot's hardware, only accepting connections on the local socket
+    #[arg(long)]
+    pub sim_robot: bool,
+}
+
+/// How the robot runs after layering the command line over the config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct RobotOptions {
+    pub role: SyncRole,
+    pub local_socket: Option<PathBuf>,
+    pub link_key: Option<LinkKey>,
+    pub tcp: LinkTcpOptions,
+    /// Whether plugins driving the robot's hardware are enabled
+    pub hardware: bool,
+    pub over_run: OverRunSettings,
+}
+
+impl RobotArgs {
+    /// Rejects combinations of arguments that don't make sense together
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.sim_robot && self.port.is_some() {
+            bail!("--sim-robot only accepts connections on the local socket, it can't be combined with --port");
+        }
+
+        Ok(())
+    }
+
+    /// Where the config is read from
+    pub fn config_path(&self) -> PathBuf {
+        self.common
+            .config
+            .clone()
+            .unwrap_or_else(|| DEFAULT_CONFIG.into())
+    }
+
+    pub fn read_config(&self) -> anyhow::Result<RobotConfig> {
+        let config: RobotConfig = self
+            .common
+            .read_config(Path::new(DEFAULT_CONFIG), true)?
+            .context("No robot config")?;
+
+        // Shared PWM channels are left to the pre-arm checks so the robot can still be reached
+        let errors = config.startup_errors();
+        if !errors.is_empty() {
+            bail!("Invalid robot config: {}", errors.join(", "));
+        }
+
+        Ok(config)
+    }
+
+    pub fn options(&self, config: &RobotConfig) -> anyhow::Result<RobotOptions> {
+        self.validate()?;
+
+        let over_run = self
+            .common
+            .over_run_settings(config.frame_budget_ms, OverRunSettings::default())?;
+        let link_key = self.common.link_key(config.link_key_file.as_deref())?;
+        let tcp = LinkTcpOptions(config.tcp.options());
+
+        if self.sim_robot {
+            let local_socket = layered(
+                None,
+                config.local_socket.clone(),
+                DEFAULT_LOCAL_SOCKET.into(),
+            );
+
+            return Ok(RobotOptions {
+                role: SyncRole::Server { port: None },
+                local_socket: Some(local_socket),
+                link_key,
+                tcp,
+                hardware: false,
+                over_run,
+            });
+        }
+
+        Ok(RobotOptions {
+            role: SyncRole::Server {
+                port: Some(self.port.unwrap_or(config.port)),
+            },
+            local_socket: config.local_socket.clone(),
+            link_key,
+            tcp,
+            hardware: true,
+            over_run,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use clap::{CommandFactory, Parser};
+    use common::{
+        over_run::OverRunSettings,
+        sync::{SyncRole, DEFAULT_LOCAL_SOCKET},
+    };
+
+    use networking::TcpOptions;
+
+    use crate::config::RobotConfig;
+
+    use super::RobotArgs;
+
+    fn parse(args: &[&str]) -> anyhow::Result<RobotArgs> {
+        Ok(RobotArgs::try_parse_from(["robot"].iter().chain(args))?)
+    }
+
+    fn config() -> RobotConfig {
+        toml::from_str(include_str!("../robot.toml")).unwrap()
+    }
+
+    #[test]
+    fn cli_overrides_config() {
+        let mut config = config();
+        config.port = 1234;
+        config.frame_budget_ms = Some(20.0);
+
+        let options = parse(&[]).unwrap().options(&config).unwrap();
+        assert_eq!(options.role, SyncRole::Server { port: Some(1234) });
+        assert_eq!(options.over_run.max_time, Duration::from_millis(20));
+        assert_eq!(options.local_socket, None);
+        assert_eq!(options.link_key, None);
+        assert_eq!(options.tcp.0, config.tcp.options());
+        assert!(options.hardware);
+
+        let options = parse(&["--port", "4321", "--frame-budget-ms", "15"])
+            .unwrap()
+            .options(&config)
+            .unwrap();
+        assert_eq!(options.role, SyncRole::Server { port: Some(4321) });
+        assert_eq!(options.over_run.max_time, Duration::from_millis(15));
+
+        config.frame_budget_ms = None;
+        let options = parse(&[]).unwrap().options(&config).unwrap();
+        assert_eq!(options.over_run, OverRunSettings::default());
+    }
+
+    #[test]
+    fn tcp_options_come_from_config() {
+        let mut config = config();
+        let options = parse(&[]).unwrap().options(&config).unwrap();
+        assert_eq!(options.tcp.0, TcpOptions::default());
+
+        config.tcp.nodelay = false;
+        config.tcp.keepalive_idle = None;
+        config.tcp.send_buffer = Some(4096);
+        let options = parse(&["--sim-robot"]).unwrap().options(&config).unwrap();
+        assert!(!options.tcp.0.nodelay);
+        assert_eq!(options.tcp.0.keepalive, None);
+        assert_eq!(options.tcp.0.send_buffer, Some(4096));
+
+        config.tcp.keepalive_idle = Some(0.0);
+        assert_eq!(
+            config.startup_errors(),
+            ["TCP keepalive needs a positive idle time"]
+        );
+    }
+
+    #[test]
+    fn sim_robot() {
+        let mut config = config();
+        config.local_socket = None;
+
+        let options = parse(&["--sim-robot"]).unwrap().options(&config).unwrap();
+        assert_eq!(options.role, SyncRole::Server { port: None });
+        assert_eq!(options.local_socket, Some(DEFAULT_LOCAL_SOCKET.into()));
+        assert!(!options.hardware);
+
+        config.local_socket = Some("/run/rov.sock".into());
+        let options = parse(&["--sim-robot"]).unwrap().options(&config).unwrap();
+        assert_eq!(options.local_socket, Some("/run/rov.sock".into()));
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        let args = parse(&["--sim-robot", "--port", "44445"]).unwrap();
+        let err = args.validate().unwrap_err();
+        assert!(err.to_string().contains("--port"), "{err}");
+        assert!(args.options(&config()).is_err());
+
+        assert!(parse(&["--port", "70000"]).is_err());
+        assert!(parse(&["--frame-budget-ms", "fast"]).is_err());
+        assert!(parse(&["--unknown"]).is_err());
+
+        let args = parse(&["--frame-budget-ms=-1"]).unwrap();
+        assert!(args.options(&config()).is_err());
+    }
+
+    #[test]
+    fn help_is_stable() {
+        let help = RobotArgs::command().render_help().to_string();
+
+        assert_eq!(help, include_str!("../snapshots/help.txt"));
+    }
+}
@@ -1,23 +1,529 @@
+use std::{cmp::Ordering, net::Ipv4Addr, path::PathBuf, time::Duration};
+
 use ahash::{HashMap, HashSet};
 use bevy::{ecs::system::Resource, transform::components::Transform};
 use common::types::hw::PwmChannelId;
 use glam::{vec3, EulerRot, Quat, Vec3A};
-use motor_math::{blue_rov::HeavyMotorId, x3d::X3dMotorId, ErasedMotorId, Motor, MotorConfig};
+use motor_math::{
+    blue_rov::HeavyMotorId,
+    motor_preformance::{Extrapolation, Interpolation},
+    solve::reverse::Axis,
+    x3d::X3dMotorId,
+    ErasedMotorId, Motor, MotorConfig,
+};
+use networking::{Keepalive, TcpOptions};
 use serde::{Deserialize, Serialize};
 
 #[derive(Resource, Debug, Clone, Serialize, Deserialize)]
 pub struct RobotConfig {
     pub name: String,
     pub port: u16,
+    /// Unix domain socket to accept connections on alongside `port`, for bench testing on one
+    /// machine
+    #[serde(default)]
+    pub local_socket: Option<PathBuf>,
+    /// Milliseconds a tick may take before it is reported as an over run
+    #[serde(default)]
+    pub frame_budget_ms: Option<f32>,
+    #[serde(default)]
+    pub loop_rates: LoopRateConfig,
+    /// Pre-shared key file encrypting the link to the surface, the surface must use the same key
+    #[serde(default)]
+    pub link_key_file: Option<PathBuf>,
+    #[serde(default)]
+    pub tcp: TcpConfig,
 
     pub motor_config: MotorConfigDefinition,
+    /// Axes the motors have to be able to drive for the robot to arm, all of them when unset
+    #[serde(default)]
+    pub required_axes: Option<Vec<Axis>>,
     pub servo_config: ServoConfigDefinition,
+    /// Motor data table tried before the default locations
+    #[serde(default)]
+    pub motor_data: Option<PathBuf>,
 
     pub motor_amperage_budget: f32,
+    /// Look up the motor data with a monotone cubic instead of linearly while clamping the motor
+    /// current, the estimates are smoother near the deadband
+    #[serde(default)]
+    pub smooth_motor_curves: bool,
+    /// What motor data lookups past the ends of the table return
+    #[serde(default)]
+    pub motor_extrapolation: Extrapolation,
     pub jerk_limit: f32,
     pub center_of_mass: Vec3A,
+    /// Seconds without an operator heartbeat before thrusters are held at neutral
+    pub heartbeat_timeout: f32,
+    #[serde(default)]
+    pub arming: ArmingConfig,
 
     pub cameras: HashMap<String, CameraDefinition>,
+    /// How camera streams are delivered to the surface
+    pub camera_transport: CameraTransport,
+
+    #[serde(default)]
+    pub current_estimation: CurrentEstimationConfig,
+    #[serde(default)]
+    pub depth_profile: DepthProfileConfig,
+    #[serde(default)]
+    pub orientation_authority: OrientationAuthorityConfig,
+    #[serde(default)]
+    pub thruster_dynamics: ThrusterDynamicsConfig,
+    #[serde(default)]
+    pub position_estimate: PositionEstimateConfig,
+    #[serde(default)]
+    pub depth_estimate: DepthEstimateConfig,
+    #[serde(default)]
+    pub power_mode: PowerModeConfig,
+    /// PWM channels written on their own schedule, by group name
+    ///
+    /// Channels in no group are written every cycle
+    #[serde(default)]
+    pub pwm_groups: HashMap<String, PwmGroupConfig>,
+    /// Directories the surface can list, download from and delete in, by the name it is shown
+    #[serde(default)]
+    pub file_roots: HashMap<String, PathBuf>,
+}
+
+impl RobotConfig {
+    /// How the thrusters look up the motor data, see [`RobotConfig::smooth_motor_curves`]
+    pub fn motor_interpolation(&self) -> Interpolation {
+        if self.smooth_motor_curves {
+            Interpolation::Cubic
+        } else {
+            Interpolation::Lerp
+        }
+    }
+
+    /// See [`RobotConfig::required_axes`]
+    pub fn required_axes(&self) -> Vec<Axis> {
+        self.required_axes
+            .clone()
+            .unwrap_or_else(|| Axis::ALL.to_vec())
+    }
+
+    /// Every problem with this config, sorted
+    ///
+    /// Uploaded configs are refused with any, see [`RobotConfig::startup_errors`] for the ones
+    /// the robot can't start with
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = self.startup_errors();
+        errors.extend(self.channel_conflicts());
+
+        errors.sort();
+        errors
+    }
+
+    /// Problems that would stop the robot from starting with this config, sorted
+    pub fn startup_errors(&self) -> Vec<String> {
+        let (_, mut errors) = self.motor_config.channels();
+        errors.extend(pwm_group_errors(&self.pwm_groups));
+        errors.extend(self.loop_rates.errors());
+        errors.extend(self.tcp.errors());
+        errors.extend(self.power_mode.errors());
+        errors.extend(self.depth_profile.errors());
+
+        errors.sort();
+        errors
+    }
+
+    /// PWM channels used by more than one motor or servo, sorted
+    ///
+    /// The robot still starts with these but refuses to arm
+    pub fn channel_conflicts(&self) -> Vec<String> {
+        let (motors, _) = self.motor_config.channels();
+        let servos = self
+            .servo_config
+            .servos
+            .iter()
+            .map(|(name, servo)| (name.to_owned(), servo.pwm_channel));
+
+        let mut users: Vec<(String, PwmChannelId)> = motors.into_iter().chain(servos).collect();
+        users.sort();
+
+        let mut errors = Vec::new();
+        let mut channels: HashMap<PwmChannelId, String> = HashMap::default();
+        for (user, channel) in users {
+            if let Some(other) = channels.insert(channel, user.clone()) {
+                errors.push(format!(
+                    "PWM channel {channel} is used by both {other} and {user}"
+                ));
+            }
+        }
+
+        errors.sort();
+        errors
+    }
+}
+
+/// Ticks per second of the main loop and of the systems that don't need every tick
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LoopRateConfig {
+    /// Rate of the main loop, control systems run every tick
+    pub control: f32,
+    /// Rate of system monitoring, LEDs and camera management
+    pub telemetry: f32,
+}
+
+impl Default for LoopRateConfig {
+    fn default() -> Self {
+        Self {
+            control: 100.0,
+            telemetry: 5.0,
+        }
+    }
+}
+
+impl LoopRateConfig {
+    pub fn control_period(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.control as f64)
+    }
+
+    pub fn telemetry_period(&self) -> Duration {
+        Duration::from_secs_f64(1.0 / self.telemetry as f64)
+    }
+
+    fn errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if !(self.control.is_finite() && self.control > 0.0) {
+            errors.push("Control loop needs a positive rate".to_owned());
+        }
+        if !(self.telemetry.is_finite() && self.telemetry > 0.0) {
+            errors.push("Telemetry needs a positive rate".to_owned());
+        }
+
+        errors
+    }
+}
+
+/// Socket options for TCP connections to the surface
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TcpConfig {
+    /// Send small packets right away instead of batching them
+    pub nodelay: bool,
+    /// Seconds a connection is idle before it is probed, keepalive is off when unset
+    pub keepalive_idle: Option<f32>,
+    /// `SO_SNDBUF` in bytes, the OS default when unset
+    pub send_buffer: Option<usize>,
+    /// `SO_RCVBUF` in bytes, the OS default when unset
+    pub recv_buffer: Option<usize>,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        let options = TcpOptions::default();
+
+        Self {
+            nodelay: options.nodelay,
+            keepalive_idle: options.keepalive.map(|it| it.idle.as_secs_f32()),
+            send_buffer: options.send_buffer,
+            recv_buffer: options.recv_buffer,
+        }
+    }
+}
+
+impl TcpConfig {
+    pub fn options(&self) -> TcpOptions {
+        TcpOptions {
+            nodelay: self.nodelay,
+            keepalive: self.keepalive_idle.map(|idle| Keepalive {
+                idle: Duration::from_secs_f32(idle),
+                ..Keepalive::default()
+            }),
+            send_buffer: self.send_buffer,
+            recv_buffer: self.recv_buffer,
+        }
+    }
+
+    fn errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if let Some(idle) = self.keepalive_idle {
+            if !(idle.is_finite() && idle > 0.0) {
+                errors.push("TCP keepalive needs a positive idle time".to_owned());
+            }
+        }
+
+        errors
+    }
+}
+
+/// Channels on the PWM chip
+pub const PWM_CHANNELS: PwmChannelId = 16;
+
+/// PWM channels written together at their own rate
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PwmGroupConfig {
+    pub channels: Vec<PwmChannelId>,
+    /// Writes per second
+    pub rate: f32,
+    #[serde(default)]
+    pub policy: PwmUpdatePolicy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum PwmUpdatePolicy {
+    /// Written at every update
+    #[default]
+    Always,
+    /// Only written when a channel changed, or once `keepalive` seconds passed since the last
+    /// write
+    OnChange { keepalive: f32 },
+}
+
+/// Problems with the PWM output groups, sorted
+pub fn pwm_group_errors(groups: &HashMap<String, PwmGroupConfig>) -> Vec<String> {
+    let mut groups = groups.iter().collect::<Vec<_>>();
+    groups.sort_by_key(|(name, _)| *name);
+
+    let mut errors = Vec::new();
+    let mut owners: HashMap<PwmChannelId, &str> = HashMap::default();
+
+    for (name, group) in groups {
+        if !(group.rate.is_finite() && group.rate > 0.0) {
+            errors.push(format!("PWM group {name} needs a positive rate"));
+        }
+
+        if let PwmUpdatePolicy::OnChange { keepalive } = group.policy {
+            if !(keepalive.is_finite() && keepalive > 0.0) {
+                errors.push(format!("PWM group {name} needs a positive keepalive"));
+            }
+        }
+
+        for &channel in &group.channels {
+            if channel >= PWM_CHANNELS {
+                errors.push(format!(
+                    "PWM group {name} has channel {channel}, there are only {PWM_CHANNELS}"
+                ));
+                continue;
+            }
+
+            match owners.insert(channel, name) {
+                Some(other) if other == name => {
+                    errors.push(format!("PWM channel {channel} is in group {name} twice"));
+                }
+                Some(other) => {
+                    errors.push(format!(
+                        "PWM channel {channel} is in both groups {other} and {name}"
+                    ));
+                }
+                None => {}
+            }
+        }
+    }
+
+    errors.sort();
+    errors
+}
+
+/// Who may arm and disarm while several surfaces are connected
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ArmingConfig {
+    /// Let surfaces without arm authority disarm, as a safety exception
+    pub anyone_can_disarm: bool,
+}
+
+impl Default for ArmingConfig {
+    fn default() -> Self {
+        Self {
+            anyone_can_disarm: true,
+        }
+    }
+}
+
+/// How the measured battery current is split between the motors
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CurrentEstimationConfig {
+    /// Amps drawn by everything other than the motors
+    pub electronics_baseline: f32,
+    /// Scale the motor amperage budget by how far the motor data table is off from the measured
+    /// current
+    pub clamp_with_estimates: bool,
+}
+
+impl Default for CurrentEstimationConfig {
+    fn default() -> Self {
+        Self {
+            electronics_baseline: 1.5,
+            clamp_with_estimates: false,
+        }
+    }
+}
+
+/// How water temperature is recorded against depth
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DepthProfileConfig {
+    /// Meters between samples
+    pub resolution: f32,
+    /// The oldest samples are dropped past this
+    pub max_samples: usize,
+}
+
+impl Default for DepthProfileConfig {
+    fn default() -> Self {
+        Self {
+            resolution: 0.1,
+            max_samples: 1000,
+        }
+    }
+}
+
+impl DepthProfileConfig {
+    fn errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if !(self.resolution.is_finite() && self.resolution > 0.0) {
+            errors.push("Depth profile needs a positive resolution".to_owned());
+        }
+
+        errors
+    }
+}
+
+/// Most torque orientation hold may use on each axis, in newton meters
+///
+/// Leaves thrust for translation when maneuvering near the robot's maximums, unlimited by default
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OrientationAuthorityConfig {
+    pub pitch: f32,
+    pub roll: f32,
+    pub yaw: f32,
+}
+
+impl Default for OrientationAuthorityConfig {
+    fn default() -> Self {
+        Self {
+            pitch: f32::INFINITY,
+            roll: f32::INFINITY,
+            yaw: f32::INFINITY,
+        }
+    }
+}
+
+/// How quickly the thrusters reach a commanded force, reported forces follow this model
+///
+/// Each time constant is the seconds a thruster takes to get 63% of the way to its command
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ThrusterDynamicsConfig {
+    /// Report the commanded forces as is when disabled
+    pub enabled: bool,
+    /// Used when the commanded force is larger in the same direction
+    pub spool_up: f32,
+    /// Used when the commanded force is smaller or in the other direction
+    pub spool_down: f32,
+}
+
+impl Default for ThrusterDynamicsConfig {
+    fn default() -> Self {
+        // From bench testing a T200 at 16V
+        Self {
+            enabled: true,
+            spool_up: 0.1,
+            spool_down: 0.06,
+        }
+    }
+}
+
+/// How the position estimate turns thrust into speed
+///
+/// Drag is modeled as quadratic, so a constant force `F` settles at `sqrt(F / drag)` meters per
+/// second. Coefficients are in newtons per (meter per second) squared.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PositionEstimateConfig {
+    /// Drag along the robot's forward axis
+    pub forward_drag: f32,
+    /// Drag along the robot's right axis
+    pub lateral_drag: f32,
+    /// Seconds for the estimate's confidence to halve
+    pub confidence_half_life: f32,
+}
+
+impl Default for PositionEstimateConfig {
+    fn default() -> Self {
+        // Rough numbers for a frame this size, tune by timing runs across the pool
+        Self {
+            forward_drag: 45.0,
+            lateral_drag: 60.0,
+            confidence_half_life: 60.0,
+        }
+    }
+}
+
+/// How much the depth estimate trusts the pressure sensor over the IMU
+///
+/// Lower noise is trusted more. The acceleration noise also covers orientation error and
+/// accelerometer bias, raise it if the estimate overshoots during quick depth changes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DepthEstimateConfig {
+    /// Standard deviation of the pressure sensor's depth, in meters
+    pub pressure_noise: f32,
+    /// Standard deviation of the vertical acceleration, in meters per second squared
+    pub accel_noise: f32,
+}
+
+impl Default for DepthEstimateConfig {
+    fn default() -> Self {
+        // The MS5837 reads about 2 cm of noise at rest
+        Self {
+            pressure_noise: 0.02,
+            accel_noise: 0.3,
+        }
+    }
+}
+
+/// When the robot starts saving power, see [`common::components::PowerMode`]
+///
+/// Each mode is entered as soon as the battery drops below its voltage and left once the battery
+/// recovers `hysteresis` volts above it, so sag under thrust doesn't flip the mode back and forth
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct PowerModeConfig {
+    /// Volts below which the robot goes to low power
+    pub low_voltage: f32,
+    /// Volts below which the robot goes to critical
+    pub critical_voltage: f32,
+    pub hysteresis: f32,
+    /// Share of the motor amperage budget left in low power
+    pub low_power_current: f32,
+    /// Share of the motor amperage budget left in critical
+    pub critical_current: f32,
+}
+
+impl Default for PowerModeConfig {
+    fn default() -> Self {
+        Self {
+            low_voltage: 10.5,
+            critical_voltage: 10.0,
+            hysteresis: 0.3,
+            low_power_current: 0.7,
+            critical_current: 0.4,
+        }
+    }
+}
+
+impl PowerModeConfig {
+    fn errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if self.critical_voltage.partial_cmp(&self.low_voltage) != Some(Ordering::Less) {
+            errors.push("Critical power voltage needs to be below low power voltage".to_owned());
+        }
+        if !(self.hysteresis.is_finite() && self.hysteresis >= 0.0) {
+            errors.push("Power mode hysteresis can't be negative".to_owned());
+        }
+        for (mode, share) in [
+            ("Low power", self.low_power_current),
+            ("Critical power", self.critical_current),
+        ] {
+            if !(share > 0.0 && share <= 1.0) {
+                errors.push(format!(
+                    "{mode} current share needs to be above 0 and at most 1"
+                ));
+            }
+        }
+
+        errors
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -81,6 +587,50 @@ impl CustomDefinition {
 }
 
 impl MotorConfigDefinition {
+    /// The PWM channel of every motor the definition generates, by name
+    ///
+    /// Also returns the motors missing a channel, [`Self::flatten`] panics on those
+    pub fn channels(&self) -> (Vec<(String, PwmChannelId)>, Vec<String>) {
+        fn lookup<Id: std::fmt::Debug + Eq + std::hash::Hash>(
+            ids: impl Iterator<Item = Id>,
+            motors: &HashMap<Id, PwmChannelId>,
+        ) -> (Vec<(String, PwmChannelId)>, Vec<String>) {
+            let mut channels = Vec::new();
+            let mut errors = Vec::new();
+
+            for id in ids {
+                match motors.get(&id) {
+                    Some(&channel) => channels.push((format!("{id:?}"), channel)),
+                    None => errors.push(format!("Motor {id:?} has no PWM channel")),
+                }
+            }
+
+            (channels, errors)
+        }
+
+        match self {
+            MotorConfigDefinition::X3d(x3d) => lookup(
+                x3d.to_motor_config(Vec3A::ZERO).motors().map(|(id, _)| *id),
+                &x3d.motors,
+            ),
+            MotorConfigDefinition::BlueRov(blue_rov) => lookup(
+                blue_rov
+                    .to_motor_config(Vec3A::ZERO)
+                    .motors()
+                    .map(|(id, _)| *id),
+                &blue_rov.motors,
+            ),
+            MotorConfigDefinition::Custom(custom) => (
+                custom
+                    .motors
+                    .iter()
+                    .map(|(name, motor)| (name.to_owned(), motor.pwm_channel))
+                    .collect(),
+                Vec::new(),
+            ),
+        }
+    }
+
     // TODO(low): Rename and make less bad
     pub fn flatten(
         &self,
@@ -179,6 +729,29 @@ pub struct Servo {
 pub struct CameraDefinition {
     pub name: String,
     pub transform: ConfigTransform,
+    /// TOML file holding the camera's intrinsics, shipped to the surface with the camera
+    #[serde(default)]
+    pub calibration: Option<PathBuf>,
+    /// Keeps streaming in critical power mode
+    #[serde(default)]
+    pub primary: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CameraTransport {
+    /// Streams are sent to the connected surface and restarted whenever it changes
+    Unicast,
+    /// Streams are sent to a multicast group that any number of surfaces can join
+    Multicast(MulticastDefinition),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MulticastDefinition {
+    pub group: Ipv4Addr,
+    pub base_port: u16,
+    /// Deliver streams back to the sending host, needed when testing on a single machine
+    #[serde(default)]
+    pub loopback: bool,
 }
 
 #[derive(Resource, Debug, Clone, Serialize, Deserialize)]
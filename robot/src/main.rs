@@ -1,44 +1,67 @@
 #![feature(coroutines, iter_from_coroutine)]
 #![allow(private_interfaces, clippy::redundant_pattern_matching)]
 
+pub mod cli;
 pub mod config;
 pub mod peripheral;
 pub mod plugins;
 
-use std::{fs, time::Duration};
-
-use anyhow::Context;
 use bevy::{
     app::ScheduleRunnerPlugin,
     diagnostic::{DiagnosticsPlugin, EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin},
     log::LogPlugin,
     prelude::*,
 };
-use common::{sync::SyncRole, CommonPlugins};
-use config::RobotConfig;
-use plugins::{actuators::MovementPlugins, core::CorePlugins, monitor::MonitorPlugins};
-
-#[cfg(rpi)]
-use crate::plugins::sensors::SensorPlugins;
+use clap::Parser;
+use cli::RobotArgs;
+use common::{sync::LocalSocket, CommonPlugins};
+use plugins::{
+    actuators::MovementPlugins,
+    autonomy::AutonomyPlugin,
+    core::{remote_config::ConfigFile, CorePlugins},
+    monitor::{
+        crash_report::{self, CrashReportFile, CRASH_REPORT_PATH},
+        logs::install_log_forwarding,
+        MonitorPlugins,
+    },
+    sensors::SensorPlugins,
+};
 
-// TODO: LogPlugin now exposes a way to play with the tracing subscriber
 fn main() -> anyhow::Result<()> {
+    // Installed first so even bad configs leave a report
+    crash_report::install_panic_hook(CRASH_REPORT_PATH.into());
+
+    let args = RobotArgs::parse();
+    // Report bad arguments before touching anything
+    args.validate()?;
+
     info!("---------- Starting Robot Code ----------");
 
     info!("Reading config");
-    let config = fs::read_to_string("robot.toml").context("Read config")?;
-    let config: RobotConfig = toml::from_str(&config).context("Parse config")?;
+    let config = args.read_config()?;
+    let config_file = ConfigFile(args.config_path());
+    let options = args.options(&config)?;
 
     let name = config.name.clone();
-    let port = config.port;
+    let control_period = config.loop_rates.control_period();
 
     info!("Starting bevy");
-    App::new()
-        .insert_resource(config)
+    let mut app = App::new();
+
+    if let Some(path) = options.local_socket {
+        app.insert_resource(LocalSocket(path));
+    }
+    if let Some(key) = options.link_key {
+        app.insert_resource(key);
+    }
+    app.insert_resource(options.tcp);
+
+    app.insert_resource(config)
+        .insert_resource(config_file)
+        .insert_resource(CrashReportFile(CRASH_REPORT_PATH.into()))
+        .insert_resource(options.over_run)
         .add_plugins((
-            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
-                1.0 / 100.0,
-            ))),
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(control_period)),
             // .set(TaskPoolPlugin {
             //     task_pool_options: TaskPoolOptions {
             //         compute: TaskPoolThreadAssignmentPolicy {
@@ -53,7 +76,10 @@ fn main() -> anyhow::Result<()> {
             //     },
             // })
             // Logging
-            LogPlugin::default(),
+            LogPlugin {
+                update_subscriber: Some(install_log_forwarding),
+                ..default()
+            },
             // Diagnostics
             (
                 DiagnosticsPlugin,
@@ -63,17 +89,23 @@ fn main() -> anyhow::Result<()> {
             // MATE
             (
                 CommonPlugins {
-                    role: SyncRole::Server { port },
+                    role: options.role,
                     name,
                 },
                 CorePlugins,
-                #[cfg(rpi)]
-                SensorPlugins,
-                MovementPlugins,
-                MonitorPlugins,
             ),
-        ))
-        .run();
+        ));
+
+    // The robot's peripherals are only reachable on the pi itself
+    let hardware = cfg!(rpi) && options.hardware;
+
+    app.add_plugins((
+        SensorPlugins { hardware },
+        MovementPlugins { hardware },
+        AutonomyPlugin,
+        MonitorPlugins,
+    ))
+    .run();
 
     info!("---------- Robot Code Exited Cleanly ----------");
 
@@ -1,6 +1,14 @@
+pub mod hal;
+
+#[cfg(any(feature = "hw-sensors", test))]
 pub mod ads1115;
+#[cfg(any(feature = "hw-sensors", test))]
 pub mod icm20602;
+#[cfg(any(feature = "hw-sensors", test))]
 pub mod mmc5983;
+#[cfg(any(feature = "hw-sensors", test))]
 pub mod ms5937;
+#[cfg(any(feature = "hw-leds", test))]
 pub mod neopixel;
+#[cfg(any(feature = "hw-pwm", test))]
 pub mod pca9685;
@@ -1,24 +1,22 @@
-use rppal::i2c::I2c;
 use tracing::{info, instrument};
 
 use anyhow::Context;
 
-pub struct Ads1115 {
-    i2c: I2c,
-}
+use super::hal::{Hal, I2cBus};
 
-impl Ads1115 {
-    pub const I2C_BUS: u8 = 1;
-    pub const I2C_ADDRESS: u8 = 0x48;
+pub const I2C_BUS: u8 = 1;
+pub const I2C_ADDRESS: u8 = 0x48;
 
-    #[instrument(level = "debug")]
-    pub fn new(bus: u8, address: u8) -> anyhow::Result<Self> {
-        info!("Setting up ADS1115 (ADC)");
+pub struct Ads1115<H: Hal> {
+    i2c: H::I2c,
+}
 
-        let mut i2c = I2c::with_bus(bus).context("Open i2c")?;
+impl<H: Hal> Ads1115<H> {
+    #[instrument(level = "debug", skip(hal))]
+    pub fn new(hal: &H, bus: u8, address: u8) -> anyhow::Result<Self> {
+        info!("Setting up ADS1115 (ADC)");
 
-        i2c.set_slave_address(address as u16)
-            .context("Set address for ADS1115")?;
+        let i2c = hal.i2c(bus, address).context("Open i2c for ADS1115")?;
 
         Ok(Self { i2c })
     }
@@ -44,16 +42,16 @@ impl AnalogChannel {
 }
 
 // Implementation based on https://github.com/bluerobotics/ads1115-python
-impl Ads1115 {
-    const POINTER_CONVERSION: u8 = 0x00;
-    const POINTER_CONFIG: u8 = 0x01;
+const POINTER_CONVERSION: u8 = 0x00;
+const POINTER_CONFIG: u8 = 0x01;
 
+impl<H: Hal> Ads1115<H> {
     #[instrument(level = "trace", skip(self), ret)]
     pub fn request_conversion(&mut self, channel: AnalogChannel) -> anyhow::Result<()> {
         let config = 1 << 15 | channel.selector() << 12 | 0b001 << 9 | 1 << 8 | 0b111 << 5;
 
         self.i2c
-            .block_write(Self::POINTER_CONFIG, &config.to_be_bytes())
+            .block_write(POINTER_CONFIG, &config.to_be_bytes())
             .context("Begin ADC convert")?;
 
         Ok(())
@@ -64,7 +62,7 @@ impl Ads1115 {
         let mut buffer = [0u8; 2];
 
         self.i2c
-            .block_read(Self::POINTER_CONFIG, &mut buffer)
+            .block_read(POINTER_CONFIG, &mut buffer)
             .context("Check ADC conversion status")?;
 
         let value = i16::from_be_bytes(buffer);
@@ -77,7 +75,7 @@ impl Ads1115 {
         let mut buffer = [0u8; 2];
 
         self.i2c
-            .block_read(Self::POINTER_CONVERSION, &mut buffer)
+            .block_read(POINTER_CONVERSION, &mut buffer)
             .context("Check ADC conversion status")?;
 
         let value = u16::from_be_bytes(buffer);
@@ -0,0 +1,79 @@
+//! The buses and pins the peripheral drivers talk to their chips through
+//!
+//! [`PiHal`](pi::PiHal) drives the pi's hardware, [`MockHal`](mock::MockHal) records what the
+//! drivers do so they can be tested anywhere
+
+#[cfg(test)]
+pub mod mock;
+#[cfg(any(feature = "hw-pwm", feature = "hw-leds", feature = "hw-sensors"))]
+pub mod pi;
+
+/// Opens handles to the buses and pins peripherals are attached to
+pub trait Hal {
+    type I2c: I2cBus;
+    type Spi: SpiBus;
+    type Pin: GpioPin;
+
+    /// Opens an I2C bus with every transfer addressed to `address`
+    fn i2c(&self, bus: u8, address: u8) -> anyhow::Result<Self::I2c>;
+
+    /// Opens an SPI bus with the chip on `slave_select` selected, in mode 0
+    fn spi(&self, bus: u8, slave_select: u8, clock_speed: u32) -> anyhow::Result<Self::Spi>;
+
+    /// Takes a GPIO pin and switches it to `mode`
+    fn pin(&self, pin: u8, mode: PinMode) -> anyhow::Result<Self::Pin>;
+}
+
+pub trait I2cBus: Send + 'static {
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()>;
+
+    fn read(&mut self, buffer: &mut [u8]) -> anyhow::Result<()>;
+
+    /// Writes `data` then reads into `buffer` without releasing the bus in between
+    fn write_read(&mut self, data: &[u8], buffer: &mut [u8]) -> anyhow::Result<()>;
+
+    /// SMBus block write of `data` to `command`
+    fn block_write(&mut self, command: u8, data: &[u8]) -> anyhow::Result<()>;
+
+    /// SMBus block read of `command` into `buffer`
+    fn block_read(&mut self, command: u8, buffer: &mut [u8]) -> anyhow::Result<()>;
+}
+
+pub trait SpiBus: Send + 'static {
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()>;
+
+    /// Clocks out `data` while reading the same number of bytes into `buffer`
+    fn transfer(&mut self, buffer: &mut [u8], data: &[u8]) -> anyhow::Result<()>;
+}
+
+pub trait GpioPin: Send + 'static {
+    fn set_mode(&mut self, mode: PinMode);
+
+    /// Drives the pin, it has to be an output for the level to show
+    fn set_level(&mut self, level: Level);
+
+    fn set_bias(&mut self, bias: Bias);
+
+    /// Whether the pin goes back to how it was found when the handle is dropped
+    fn set_reset_on_drop(&mut self, reset: bool);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinMode {
+    Input,
+    /// The level is latched before the pin starts driving so it never glitches
+    Output(Level),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Low,
+    High,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bias {
+    Off,
+    PullDown,
+    PullUp,
+}
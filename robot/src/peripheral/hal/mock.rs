@@ -0,0 +1,311 @@
+//! In memory [`Hal`] for testing the drivers off the robot
+//!
+//! Every bus and pin operation is recorded in order. I2C devices behave like a register file with
+//! auto increment, so whatever a driver writes to a register it reads back, reads can also be
+//! scripted per device with [`MockHal::queue_read`].
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::anyhow;
+
+use super::{Bias, GpioPin, Hal, I2cBus, Level, PinMode, SpiBus};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Device {
+    I2c { bus: u8, address: u8 },
+    Spi { bus: u8, slave_select: u8 },
+    Pin(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Op {
+    Write(Vec<u8>),
+    /// Number of bytes read
+    Read(usize),
+    WriteRead(Vec<u8>, usize),
+    BlockWrite(u8, Vec<u8>),
+    BlockRead(u8, usize),
+    /// The bytes clocked out
+    Transfer(Vec<u8>),
+    SetMode(PinMode),
+    SetLevel(Level),
+    SetBias(Bias),
+    SetResetOnDrop(bool),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    pub device: Device,
+    pub op: Op,
+}
+
+#[derive(Clone, Default)]
+pub struct MockHal(Arc<Mutex<MockState>>);
+
+#[derive(Default)]
+struct MockState {
+    transactions: Vec<Transaction>,
+    reads: HashMap<Device, VecDeque<Vec<u8>>>,
+    registers: HashMap<Device, [u8; 256]>,
+    /// Registers that ignore writes
+    held: HashMap<Device, HashMap<u8, u8>>,
+    failures: HashMap<Device, VecDeque<String>>,
+    latency: Duration,
+}
+
+impl MockHal {
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.0.lock().unwrap().transactions.clone()
+    }
+
+    /// The operations on `device` in order
+    pub fn ops(&self, device: Device) -> Vec<Op> {
+        self.0
+            .lock()
+            .unwrap()
+            .transactions
+            .iter()
+            .filter(|it| it.device == device)
+            .map(|it| it.op.clone())
+            .collect()
+    }
+
+    pub fn clear_transactions(&self) {
+        self.0.lock().unwrap().transactions.clear();
+    }
+
+    /// Queues the bytes the next read from `device` returns, before any register contents
+    pub fn queue_read(&self, device: Device, data: impl Into<Vec<u8>>) {
+        let mut state = self.0.lock().unwrap();
+        state
+            .reads
+            .entry(device)
+            .or_default()
+            .push_back(data.into());
+    }
+
+    /// Sets the contents of `device`'s registers starting at `first`
+    pub fn set_registers(&self, device: Device, first: u8, data: &[u8]) {
+        let mut state = self.0.lock().unwrap();
+        write_registers(
+            state.registers.entry(device).or_insert([0; 256]),
+            first,
+            data,
+        );
+    }
+
+    /// Pins `device`'s registers starting at `first` to `data`, writes to them are ignored like
+    /// on a chip that keeps resetting
+    pub fn hold_registers(&self, device: Device, first: u8, data: &[u8]) {
+        self.set_registers(device, first, data);
+
+        let mut state = self.0.lock().unwrap();
+        let held = state.held.entry(device).or_default();
+        for (idx, byte) in data.iter().enumerate() {
+            held.insert(first.wrapping_add(idx as u8), *byte);
+        }
+    }
+
+    pub fn register(&self, device: Device, register: u8) -> u8 {
+        let state = self.0.lock().unwrap();
+        state
+            .registers
+            .get(&device)
+            .map(|it| it[register as usize])
+            .unwrap_or(0)
+    }
+
+    /// Makes the next open of or operation on `device` fail with `error`
+    pub fn fail_next(&self, device: Device, error: &str) {
+        let mut state = self.0.lock().unwrap();
+        state
+            .failures
+            .entry(device)
+            .or_default()
+            .push_back(error.to_owned());
+    }
+
+    /// Delays every operation by `latency`, like a slow bus would
+    pub fn set_latency(&self, latency: Duration) {
+        self.0.lock().unwrap().latency = latency;
+    }
+
+    fn handle(&self, device: Device) -> anyhow::Result<MockHandle> {
+        take_failure(&mut self.0.lock().unwrap(), device)?;
+
+        Ok(MockHandle {
+            hal: self.clone(),
+            device,
+        })
+    }
+
+    /// Records `op` and fills `buffer` with what the device would have answered
+    fn perform(&self, device: Device, op: Op, buffer: &mut [u8]) -> anyhow::Result<()> {
+        let latency = self.0.lock().unwrap().latency;
+        if !latency.is_zero() {
+            thread::sleep(latency);
+        }
+
+        let mut state = self.0.lock().unwrap();
+        state.transactions.push(Transaction {
+            device,
+            op: op.clone(),
+        });
+        take_failure(&mut state, device)?;
+
+        let MockState {
+            registers, held, ..
+        } = &mut *state;
+        let registers = registers.entry(device).or_insert([0; 256]);
+        match &op {
+            Op::Write(data) if matches!(device, Device::I2c { .. }) && data.len() > 1 => {
+                write_registers(registers, data[0], &data[1..]);
+            }
+            Op::BlockWrite(command, data) => write_registers(registers, *command, data),
+            _ => {}
+        }
+        if let Some(held) = held.get(&device) {
+            for (&register, &byte) in held {
+                registers[register as usize] = byte;
+            }
+        }
+
+        if buffer.is_empty() {
+            return Ok(());
+        }
+
+        let scripted = state.reads.get_mut(&device).and_then(VecDeque::pop_front);
+        if let Some(data) = scripted {
+            buffer.fill(0);
+            let len = data.len().min(buffer.len());
+            buffer[..len].copy_from_slice(&data[..len]);
+
+            return Ok(());
+        }
+
+        let first = match &op {
+            Op::WriteRead(data, _) if matches!(device, Device::I2c { .. }) => data.first(),
+            Op::BlockRead(command, _) => Some(command),
+            _ => None,
+        };
+        match first {
+            Some(&first) => {
+                let registers = &state.registers[&device];
+                for (idx, byte) in buffer.iter_mut().enumerate() {
+                    *byte = registers[first.wrapping_add(idx as u8) as usize];
+                }
+            }
+            None => buffer.fill(0),
+        }
+
+        Ok(())
+    }
+}
+
+fn take_failure(state: &mut MockState, device: Device) -> anyhow::Result<()> {
+    match state
+        .failures
+        .get_mut(&device)
+        .and_then(VecDeque::pop_front)
+    {
+        Some(error) => Err(anyhow!(error)),
+        None => Ok(()),
+    }
+}
+
+fn write_registers(registers: &mut [u8; 256], first: u8, data: &[u8]) {
+    for (idx, byte) in data.iter().enumerate() {
+        registers[first.wrapping_add(idx as u8) as usize] = *byte;
+    }
+}
+
+impl Hal for MockHal {
+    type I2c = MockHandle;
+    type Spi = MockHandle;
+    type Pin = MockHandle;
+
+    fn i2c(&self, bus: u8, address: u8) -> anyhow::Result<Self::I2c> {
+        self.handle(Device::I2c { bus, address })
+    }
+
+    fn spi(&self, bus: u8, slave_select: u8, _clock_speed: u32) -> anyhow::Result<Self::Spi> {
+        self.handle(Device::Spi { bus, slave_select })
+    }
+
+    fn pin(&self, pin: u8, mode: PinMode) -> anyhow::Result<Self::Pin> {
+        let mut handle = self.handle(Device::Pin(pin))?;
+        handle.set_mode(mode);
+
+        Ok(handle)
+    }
+}
+
+/// A bus or pin opened on a [`MockHal`]
+pub struct MockHandle {
+    hal: MockHal,
+    device: Device,
+}
+
+impl I2cBus for MockHandle {
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.hal
+            .perform(self.device, Op::Write(data.to_vec()), &mut [])
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> anyhow::Result<()> {
+        self.hal
+            .perform(self.device, Op::Read(buffer.len()), buffer)
+    }
+
+    fn write_read(&mut self, data: &[u8], buffer: &mut [u8]) -> anyhow::Result<()> {
+        let op = Op::WriteRead(data.to_vec(), buffer.len());
+        self.hal.perform(self.device, op, buffer)
+    }
+
+    fn block_write(&mut self, command: u8, data: &[u8]) -> anyhow::Result<()> {
+        let op = Op::BlockWrite(command, data.to_vec());
+        self.hal.perform(self.device, op, &mut [])
+    }
+
+    fn block_read(&mut self, command: u8, buffer: &mut [u8]) -> anyhow::Result<()> {
+        let op = Op::BlockRead(command, buffer.len());
+        self.hal.perform(self.device, op, buffer)
+    }
+}
+
+impl SpiBus for MockHandle {
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        self.hal
+            .perform(self.device, Op::Write(data.to_vec()), &mut [])
+    }
+
+    fn transfer(&mut self, buffer: &mut [u8], data: &[u8]) -> anyhow::Result<()> {
+        self.hal
+            .perform(self.device, Op::Transfer(data.to_vec()), buffer)
+    }
+}
+
+impl GpioPin for MockHandle {
+    fn set_mode(&mut self, mode: PinMode) {
+        let _ = self.hal.perform(self.device, Op::SetMode(mode), &mut []);
+    }
+
+    fn set_level(&mut self, level: Level) {
+        let _ = self.hal.perform(self.device, Op::SetLevel(level), &mut []);
+    }
+
+    fn set_bias(&mut self, bias: Bias) {
+        let _ = self.hal.perform(self.device, Op::SetBias(bias), &mut []);
+    }
+
+    fn set_reset_on_drop(&mut self, reset: bool) {
+        let _ = self
+            .hal
+            .perform(self.device, Op::SetResetOnDrop(reset), &mut []);
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{bail, Context};
+use rppal::{
+    gpio::{self, Gpio, IoPin},
+    i2c::I2c,
+    spi::{self, Spi},
+};
+
+use super::{Bias, GpioPin, Hal, I2cBus, Level, PinMode, SpiBus};
+
+/// The pi's own buses and pins, through rppal
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PiHal;
+
+impl Hal for PiHal {
+    type I2c = I2c;
+    type Spi = Spi;
+    type Pin = IoPin;
+
+    fn i2c(&self, bus: u8, address: u8) -> anyhow::Result<Self::I2c> {
+        let mut i2c = I2c::with_bus(bus).context("Open i2c")?;
+        i2c.set_slave_address(address as u16)
+            .context("Set slave address")?;
+
+        Ok(i2c)
+    }
+
+    fn spi(&self, bus: u8, slave_select: u8, clock_speed: u32) -> anyhow::Result<Self::Spi> {
+        let bus = match bus {
+            0 => spi::Bus::Spi0,
+            1 => spi::Bus::Spi1,
+            2 => spi::Bus::Spi2,
+            3 => spi::Bus::Spi3,
+            4 => spi::Bus::Spi4,
+            5 => spi::Bus::Spi5,
+            6 => spi::Bus::Spi6,
+            _ => bail!("No spi bus {bus}"),
+        };
+        let slave_select = match slave_select {
+            0 => spi::SlaveSelect::Ss0,
+            1 => spi::SlaveSelect::Ss1,
+            2 => spi::SlaveSelect::Ss2,
+            3 => spi::SlaveSelect::Ss3,
+            4 => spi::SlaveSelect::Ss4,
+            5 => spi::SlaveSelect::Ss5,
+            6 => spi::SlaveSelect::Ss6,
+            7 => spi::SlaveSelect::Ss7,
+            _ => bail!("No slave select {slave_select}"),
+        };
+
+        Spi::new(bus, slave_select, clock_speed, spi::Mode::Mode0).context("Open spi")
+    }
+
+    fn pin(&self, pin: u8, mode: PinMode) -> anyhow::Result<Self::Pin> {
+        let gpio = Gpio::new().context("Open gpio")?;
+        let pin = gpio.get(pin).context("Get pin")?;
+
+        // Left as it was until the level is latched
+        let current = pin.mode();
+        let mut pin = pin.into_io(current);
+        GpioPin::set_mode(&mut pin, mode);
+
+        Ok(pin)
+    }
+}
+
+impl I2cBus for I2c {
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        I2c::write(self, data)?;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> anyhow::Result<()> {
+        I2c::read(self, buffer)?;
+        Ok(())
+    }
+
+    fn write_read(&mut self, data: &[u8], buffer: &mut [u8]) -> anyhow::Result<()> {
+        I2c::write_read(self, data, buffer)?;
+        Ok(())
+    }
+
+    fn block_write(&mut self, command: u8, data: &[u8]) -> anyhow::Result<()> {
+        I2c::block_write(self, command, data)?;
+        Ok(())
+    }
+
+    fn block_read(&mut self, command: u8, buffer: &mut [u8]) -> anyhow::Result<()> {
+        I2c::block_read(self, command, buffer)?;
+        Ok(())
+    }
+}
+
+impl SpiBus for Spi {
+    fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        Spi::write(self, data)?;
+        Ok(())
+    }
+
+    fn transfer(&mut self, buffer: &mut [u8], data: &[u8]) -> anyhow::Result<()> {
+        Spi::transfer(self, buffer, data)?;
+        Ok(())
+    }
+}
+
+impl GpioPin for IoPin {
+    fn set_mode(&mut self, mode: PinMode) {
+        match mode {
+            PinMode::Input => IoPin::set_mode(self, gpio::Mode::Input),
+            PinMode::Output(level) => {
+                GpioPin::set_level(self, level);
+                IoPin::set_mode(self, gpio::Mode::Output);
+            }
+        }
+    }
+
+    fn set_level(&mut self, level: Level) {
+        match level {
+            Level::Low => self.set_low(),
+            Level::High => self.set_high(),
+        }
+    }
+
+    fn set_bias(&mut self, bias: Bias) {
+        let bias = match bias {
+            Bias::Off => gpio::Bias::Off,
+            Bias::PullDown => gpio::Bias::PullDown,
+            Bias::PullUp => gpio::Bias::PullUp,
+        };
+
+        IoPin::set_bias(self, bias);
+    }
+
+    fn set_reset_on_drop(&mut self, reset: bool) {
+        IoPin::set_reset_on_drop(self, reset);
+    }
+}
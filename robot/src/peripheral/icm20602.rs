@@ -1,27 +1,33 @@
-use common::types::{
-    hw::InertialFrame,
-    units::{Celsius, Dps, GForce},
+use common::{
+    clock,
+    types::{
+        hw::InertialFrame,
+        units::{Celsius, Dps, GForce},
+    },
 };
 use std::{thread, time::Duration};
 use tracing::{debug, info, instrument};
 
 use anyhow::Context;
-use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
 
-pub struct Icm20602 {
-    spi: Spi,
-}
+use super::hal::{Hal, SpiBus};
+
+pub const SPI_BUS: u8 = 1;
+pub const SPI_SELECT: u8 = 2;
+pub const SPI_CLOCK: u32 = 10_000_000;
 
-impl Icm20602 {
-    pub const SPI_BUS: Bus = Bus::Spi1;
-    pub const SPI_SELECT: SlaveSelect = SlaveSelect::Ss2;
-    pub const SPI_CLOCK: u32 = 10_000_000;
+pub struct Icm20602<H: Hal> {
+    spi: H::Spi,
+}
 
-    #[instrument(level = "debug")]
-    pub fn new(bus: Bus, slave_select: SlaveSelect, clock_speed: u32) -> anyhow::Result<Self> {
+impl<H: Hal> Icm20602<H> {
+    #[instrument(level = "debug", skip(hal))]
+    pub fn new(hal: &H, bus: u8, slave_select: u8, clock_speed: u32) -> anyhow::Result<Self> {
         info!("Setting up ICM20602 (Gyro and Accelerometer)");
 
-        let spi = Spi::new(bus, slave_select, clock_speed, Mode::Mode0).context("Open spi")?;
+        let spi = hal
+            .spi(bus, slave_select, clock_speed)
+            .context("Open spi")?;
 
         let mut this = Self { spi };
         this.initialize().context("Initialize")?;
@@ -32,6 +38,7 @@ impl Icm20602 {
     #[instrument(level = "trace", skip(self), ret)]
     pub fn read_frame(&mut self) -> anyhow::Result<InertialFrame> {
         let raw = self.read_raw_frame().context("Read raw frame")?;
+        let capture_time = clock::monotonic_seconds();
 
         // The first byte is junk
         let raw = &raw[1..];
@@ -72,65 +79,64 @@ impl Icm20602 {
             accel_y: GForce(accel_y),
             accel_z: GForce(accel_z),
             tempature: Celsius(tempature),
+            capture_time,
         })
     }
 }
 
 // Implementation based on https://github.com/bluerobotics/icm20602-python
-impl Icm20602 {
-    const REG_I2C_IF: u8 = 0x70;
-    const REG_CONFIG: u8 = 0x1A;
-    const REG_GYRO_CONFIG: u8 = 0x1B;
-    const REG_ACCEL_CONFIG: u8 = 0x1C;
-    const REG_ACCEL_CONFIG_2: u8 = 0x1D;
-    const REG_ACCEL_INTEL_CTRL: u8 = 0x69;
-    const REG_PWR_MGMT_1: u8 = 0x6B;
-    const REG_WHO_AM_I: u8 = 0x75;
-    const REG_ACCEL_XOUT_H: u8 = 0x3B;
-
-    const READ: u8 = 0x80;
-
+const REG_I2C_IF: u8 = 0x70;
+const REG_CONFIG: u8 = 0x1A;
+const REG_GYRO_CONFIG: u8 = 0x1B;
+const REG_ACCEL_CONFIG: u8 = 0x1C;
+const REG_ACCEL_CONFIG_2: u8 = 0x1D;
+const REG_ACCEL_INTEL_CTRL: u8 = 0x69;
+const REG_PWR_MGMT_1: u8 = 0x6B;
+const REG_WHO_AM_I: u8 = 0x75;
+const REG_ACCEL_XOUT_H: u8 = 0x3B;
+
+const READ: u8 = 0x80;
+
+impl<H: Hal> Icm20602<H> {
     fn initialize(&mut self) -> anyhow::Result<()> {
         debug!("Initializing ICM20602 (gyro + accelerometer)");
 
         let mut id = [0, 0];
         self.spi
-            .transfer(&mut id, &[Self::REG_WHO_AM_I | Self::READ, 0])
+            .transfer(&mut id, &[REG_WHO_AM_I | READ, 0])
             .context("Request id")?;
         assert_eq!(id[1], 0x12);
 
-        self.spi
-            .write(&[Self::REG_I2C_IF, 0x40])
-            .context("Disable i2c")?;
+        self.spi.write(&[REG_I2C_IF, 0x40]).context("Disable i2c")?;
 
         // 1Hz sample rate
         self.spi
-            .write(&[Self::REG_CONFIG, 0x1])
+            .write(&[REG_CONFIG, 0x1])
             .context("Setup lowpass filter")?;
 
         // 2000 deg range, lowpass filter
         self.spi
-            .write(&[Self::REG_GYRO_CONFIG, 0b11 << 3])
+            .write(&[REG_GYRO_CONFIG, 0b11 << 3])
             .context("Setup gyro")?;
 
         // 8g range
         self.spi
-            .write(&[Self::REG_ACCEL_CONFIG, 0b10 << 3])
+            .write(&[REG_ACCEL_CONFIG, 0b10 << 3])
             .context("Setup accel")?;
 
         // lowpass filter
         self.spi
-            .write(&[Self::REG_ACCEL_CONFIG_2, 0x0])
+            .write(&[REG_ACCEL_CONFIG_2, 0x0])
             .context("Setup accel")?;
 
         // Disable output limit
         self.spi
-            .write(&[Self::REG_ACCEL_INTEL_CTRL, 0x2])
+            .write(&[REG_ACCEL_INTEL_CTRL, 0x2])
             .context("Setup accel")?;
 
         // Exit sleep mode
         self.spi
-            .write(&[Self::REG_PWR_MGMT_1, 0x1])
+            .write(&[REG_PWR_MGMT_1, 0x1])
             .context("Exit sleep")?;
 
         // Delay to allow sensors to start up and stabilize
@@ -145,7 +151,7 @@ impl Icm20602 {
         let mut output = [0; 15];
         let mut input = [0; 15];
 
-        output[0] = Self::REG_ACCEL_XOUT_H | Self::READ;
+        output[0] = REG_ACCEL_XOUT_H | READ;
 
         self.spi
             .transfer(&mut input, &output)
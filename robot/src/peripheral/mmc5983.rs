@@ -1,26 +1,30 @@
+use common::clock;
 use common::types::hw::MagneticFrame;
 use common::types::units::Gauss;
 use std::{thread, time::Duration};
 use tracing::{debug, info, instrument, trace};
 
 use anyhow::Context;
-use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
 
-pub struct Mcc5983 {
-    spi: Spi,
+use super::hal::{Hal, SpiBus};
+
+pub const SPI_BUS: u8 = 1;
+pub const SPI_SELECT: u8 = 1;
+pub const SPI_CLOCK: u32 = 10_000_000;
+
+pub struct Mcc5983<H: Hal> {
+    spi: H::Spi,
     offset: [f32; 3],
 }
 
-impl Mcc5983 {
-    pub const SPI_BUS: Bus = Bus::Spi1;
-    pub const SPI_SELECT: SlaveSelect = SlaveSelect::Ss1;
-    pub const SPI_CLOCK: u32 = 10_000_000;
-
-    #[instrument(level = "debug")]
-    pub fn new(bus: Bus, slave_select: SlaveSelect, clock_speed: u32) -> anyhow::Result<Self> {
+impl<H: Hal> Mcc5983<H> {
+    #[instrument(level = "debug", skip(hal))]
+    pub fn new(hal: &H, bus: u8, slave_select: u8, clock_speed: u32) -> anyhow::Result<Self> {
         info!("Setting up MCC5983 (Magnetometer)");
 
-        let spi = Spi::new(bus, slave_select, clock_speed, Mode::Mode0).context("Open spi")?;
+        let spi = hal
+            .spi(bus, slave_select, clock_speed)
+            .context("Open spi")?;
 
         let mut this = Self {
             spi,
@@ -36,6 +40,7 @@ impl Mcc5983 {
     #[instrument(level = "trace", skip(self), ret)]
     pub fn read_frame(&mut self) -> anyhow::Result<MagneticFrame> {
         let raw = self.read_raw_frame().context("Read raw frame")?;
+        let capture_time = clock::monotonic_seconds();
 
         // The first byte is junk
         let raw = &raw[1..];
@@ -59,34 +64,35 @@ impl Mcc5983 {
             mag_x: Gauss(mag_x),
             mag_y: Gauss(mag_y),
             mag_z: Gauss(mag_z),
+            capture_time,
         })
     }
 }
 
 // Implementation based on https://github.com/bluerobotics/icm20602-python
-impl Mcc5983 {
-    const REG_XOUT_L: u8 = 0x00;
-    const REG_STATUS: u8 = 0x08;
-    const REG_CONTROL0: u8 = 0x09;
-    const REG_CONTROL1: u8 = 0x0A;
-    const REG_CONTROL2: u8 = 0x0B;
-    const REG_WHO_AM_I: u8 = 0x2F;
+const REG_XOUT_L: u8 = 0x00;
+const REG_STATUS: u8 = 0x08;
+const REG_CONTROL0: u8 = 0x09;
+const REG_CONTROL1: u8 = 0x0A;
+const REG_CONTROL2: u8 = 0x0B;
+const REG_WHO_AM_I: u8 = 0x2F;
 
-    const READ: u8 = 0x80;
+const READ: u8 = 0x80;
 
+impl<H: Hal> Mcc5983<H> {
     fn initialize(&mut self) -> anyhow::Result<()> {
         debug!("Initializing MCC5982 (magnetometer)");
 
         // Software reset
         self.spi
-            .write(&[Self::REG_CONTROL1, 0x80])
+            .write(&[REG_CONTROL1, 0x80])
             .context("Software reset")?;
         thread::sleep(Duration::from_millis(15));
 
         // Read chip id
         let mut id = [0, 0];
         self.spi
-            .transfer(&mut id, &[Self::REG_WHO_AM_I | Self::READ, 0])
+            .transfer(&mut id, &[REG_WHO_AM_I | READ, 0])
             .context("Request id")?;
         assert_eq!(id[1], 0x30);
 
@@ -97,7 +103,7 @@ impl Mcc5983 {
 
         // Enable continous mode @ 100 Hz
         self.spi
-            .write(&[Self::REG_CONTROL2, 0x0D])
+            .write(&[REG_CONTROL2, 0x0D])
             .context("Continous mode")?;
 
         debug!("Initializing MCC5982 complete");
@@ -111,39 +117,27 @@ impl Mcc5983 {
         self.offset = [0.0; 3];
 
         // SET
-        self.spi
-            .write(&[Self::REG_CONTROL0, 0x08])
-            .context("Set mode")?;
+        self.spi.write(&[REG_CONTROL0, 0x08]).context("Set mode")?;
         thread::sleep(Duration::from_millis(1));
 
         // Measure
-        self.spi
-            .write(&[Self::REG_CONTROL0, 0x01])
-            .context("Measure")?;
+        self.spi.write(&[REG_CONTROL0, 0x01]).context("Measure")?;
         thread::sleep(Duration::from_millis(10));
-        assert_eq!(
-            self.read_reg(Self::REG_STATUS).context("Read status")? & 1,
-            1
-        );
+        assert_eq!(self.read_reg(REG_STATUS).context("Read status")? & 1, 1);
 
         let set = self.read_frame().context("Read Set")?;
         trace!(?set, "Set calibration");
 
         // RESET
         self.spi
-            .write(&[Self::REG_CONTROL0, 0x10])
+            .write(&[REG_CONTROL0, 0x10])
             .context("Reset mode")?;
         thread::sleep(Duration::from_millis(1));
 
         // Measure
-        self.spi
-            .write(&[Self::REG_CONTROL0, 0x01])
-            .context("Measure")?;
+        self.spi.write(&[REG_CONTROL0, 0x01]).context("Measure")?;
         thread::sleep(Duration::from_millis(10));
-        assert_eq!(
-            self.read_reg(Self::REG_STATUS).context("Read status")? & 1,
-            1
-        );
+        assert_eq!(self.read_reg(REG_STATUS).context("Read status")? & 1, 1);
 
         let reset = self.read_frame().context("Read Reset")?;
         trace!(?reset, "Reset calibration");
@@ -165,7 +159,7 @@ impl Mcc5983 {
         let mut output = [0; 2];
         let mut input = [0; 2];
 
-        output[0] = reg | Self::READ;
+        output[0] = reg | READ;
 
         self.spi
             .transfer(&mut input, &output)
@@ -178,7 +172,7 @@ impl Mcc5983 {
         let mut output = [0; 8];
         let mut input = [0; 8];
 
-        output[0] = Self::REG_XOUT_L | Self::READ;
+        output[0] = REG_XOUT_L | READ;
 
         self.spi
             .transfer(&mut input, &output)
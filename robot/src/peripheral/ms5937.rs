@@ -1,33 +1,34 @@
 use std::{thread, time::Duration};
 
 use anyhow::{bail, Context};
-use common::types::{
-    hw::DepthFrame,
-    units::{Celsius, Mbar, Meters},
+use common::{
+    clock,
+    types::{
+        hw::DepthFrame,
+        units::{Celsius, Mbar, Meters},
+    },
 };
-use rppal::i2c::I2c;
 use tracing::{debug, info, instrument};
 
-pub struct Ms5837 {
-    i2c: I2c,
+use super::hal::{Hal, I2cBus};
+
+pub const I2C_BUS: u8 = 6;
+pub const I2C_ADDRESS: u8 = 0x76;
+
+pub struct Ms5837<H: Hal> {
+    i2c: H::I2c,
     calibration: [u16; 8],
 
     pub fluid_density: f32,
     pub sea_level: Mbar,
 }
 
-impl Ms5837 {
-    pub const I2C_BUS: u8 = 6;
-    pub const I2C_ADDRESS: u8 = 0x76;
-
-    #[instrument(level = "debug")]
-    pub fn new(bus: u8, address: u8) -> anyhow::Result<Self> {
+impl<H: Hal> Ms5837<H> {
+    #[instrument(level = "debug", skip(hal))]
+    pub fn new(hal: &H, bus: u8, address: u8) -> anyhow::Result<Self> {
         info!("Setting up MS5837 (Depth Sensor)");
 
-        let mut i2c = I2c::with_bus(bus).context("Open i2c")?;
-
-        i2c.set_slave_address(address as u16)
-            .context("Set address for MS5837")?;
+        let i2c = hal.i2c(bus, address).context("Open i2c for MS5837")?;
 
         let mut this = Self {
             i2c,
@@ -44,6 +45,7 @@ impl Ms5837 {
     #[instrument(level = "trace", skip(self), ret)]
     pub fn read_frame(&mut self) -> anyhow::Result<DepthFrame> {
         let raw = self.read_raw().context("Read raw frame")?;
+        let capture_time = clock::monotonic_seconds();
 
         let (pressure, temperature) = calculate_pressure_and_temperature(raw, &self.calibration);
         let altitude = pressure_to_altitude(pressure, self.sea_level.0);
@@ -54,27 +56,28 @@ impl Ms5837 {
             altitude,
             pressure,
             temperature,
+            capture_time,
         })
     }
 }
 
-impl Ms5837 {
-    const CMD_RESET: u8 = 0x1e;
-    const CMD_READ_PROM: u8 = 0xA0;
-    const CMD_CONVERT_D1_OSR1024: u8 = 0x44;
-    const CMD_CONVERT_D2_OSR1024: u8 = 0x54;
-    const CMD_READ_ADC: u8 = 0x00;
+const CMD_RESET: u8 = 0x1e;
+const CMD_READ_PROM: u8 = 0xA0;
+const CMD_CONVERT_D1_OSR1024: u8 = 0x44;
+const CMD_CONVERT_D2_OSR1024: u8 = 0x54;
+const CMD_READ_ADC: u8 = 0x00;
 
+impl<H: Hal> Ms5837<H> {
     fn initialize(&mut self) -> anyhow::Result<()> {
         debug!("Initializing MS5837 (depth sensor)");
 
-        self.i2c.write(&[Self::CMD_RESET]).context("Reset MS5837")?;
+        self.i2c.write(&[CMD_RESET]).context("Reset MS5837")?;
         thread::sleep(Duration::from_millis(10));
 
         for prom_addrs in 0..7 {
             let mut buffer = [0, 0];
             self.i2c
-                .write(&[Self::CMD_READ_PROM | (prom_addrs as u8) << 1])
+                .write(&[CMD_READ_PROM | (prom_addrs as u8) << 1])
                 .context("Read prom cmd")?;
             self.i2c.read(&mut buffer).context("Read prom")?;
 
@@ -103,28 +106,24 @@ impl Ms5837 {
         let mut buffer = [0, 0, 0];
 
         self.i2c
-            .write(&[Self::CMD_CONVERT_D1_OSR1024])
+            .write(&[CMD_CONVERT_D1_OSR1024])
             .context("Begin d1 convert")?;
         thread::sleep(Duration::from_millis(3));
 
-        self.i2c
-            .write(&[Self::CMD_READ_ADC])
-            .context("Begin d1 read")?;
+        self.i2c.write(&[CMD_READ_ADC]).context("Begin d1 read")?;
         self.i2c.read(&mut buffer).context("D1 read")?;
 
-        let d1 = (buffer[0] as u32) << 16 | (buffer[1] as u32) << 8 | buffer[0] as u32;
+        let d1 = (buffer[0] as u32) << 16 | (buffer[1] as u32) << 8 | buffer[2] as u32;
 
         self.i2c
-            .write(&[Self::CMD_CONVERT_D2_OSR1024])
+            .write(&[CMD_CONVERT_D2_OSR1024])
             .context("Begin d2 convert")?;
         thread::sleep(Duration::from_millis(3));
 
-        self.i2c
-            .write(&[Self::CMD_READ_ADC])
-            .context("Begin d2 read")?;
+        self.i2c.write(&[CMD_READ_ADC]).context("Begin d2 read")?;
         self.i2c.read(&mut buffer).context("D2 read")?;
 
-        let d2 = (buffer[0] as u32) << 16 | (buffer[1] as u32) << 8 | buffer[0] as u32;
+        let d2 = (buffer[0] as u32) << 16 | (buffer[1] as u32) << 8 | buffer[2] as u32;
 
         Ok((d1, d2))
     }
@@ -210,3 +209,76 @@ fn crc4(mut data: [u16; 8]) -> u8 {
 
     (n_rem >> 12) as u8
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::peripheral::hal::mock::{Device, MockHal, Op};
+
+    use super::{
+        crc4, Ms5837, CMD_CONVERT_D1_OSR1024, CMD_CONVERT_D2_OSR1024, CMD_READ_ADC, I2C_ADDRESS,
+        I2C_BUS,
+    };
+
+    const DEPTH: Device = Device::I2c {
+        bus: I2C_BUS,
+        address: I2C_ADDRESS,
+    };
+
+    /// Calibration and readings from the worked example in the MS5837-30BA datasheet
+    fn datasheet_sensor() -> (MockHal, Ms5837<MockHal>) {
+        let mut prom = [0x1A << 5, 34982, 36352, 20328, 22354, 26646, 26146, 0];
+        prom[0] |= (crc4(prom) as u16) << 12;
+
+        let hal = MockHal::default();
+        for word in &prom[..7] {
+            hal.queue_read(DEPTH, word.to_be_bytes());
+        }
+
+        let sensor = Ms5837::new(&hal, I2C_BUS, I2C_ADDRESS).unwrap();
+
+        hal.queue_read(DEPTH, &4958179u32.to_be_bytes()[1..]);
+        hal.queue_read(DEPTH, &6815414u32.to_be_bytes()[1..]);
+
+        (hal, sensor)
+    }
+
+    #[test]
+    fn depth_from_raw_pressure() {
+        let (hal, mut sensor) = datasheet_sensor();
+        hal.clear_transactions();
+
+        let frame = sensor.read_frame().unwrap();
+
+        assert_eq!(frame.pressure.0, 3999.8);
+        assert_eq!(frame.temperature.0, 19.82);
+        assert!((frame.depth.0 - 30.454).abs() < 0.001, "{frame:?}");
+
+        assert_eq!(
+            hal.ops(DEPTH),
+            [
+                Op::Write(vec![CMD_CONVERT_D1_OSR1024]),
+                Op::Write(vec![CMD_READ_ADC]),
+                Op::Read(3),
+                Op::Write(vec![CMD_CONVERT_D2_OSR1024]),
+                Op::Write(vec![CMD_READ_ADC]),
+                Op::Read(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn bad_prom_crc_is_rejected() {
+        let hal = MockHal::default();
+        hal.queue_read(DEPTH, [0xff, 0xff]);
+
+        assert!(Ms5837::new(&hal, I2C_BUS, I2C_ADDRESS).is_err());
+    }
+
+    #[test]
+    fn bus_errors_are_reported() {
+        let (hal, mut sensor) = datasheet_sensor();
+        hal.fail_next(DEPTH, "nack");
+
+        assert!(sensor.read_frame().is_err());
+    }
+}
@@ -3,29 +3,33 @@ use std::{fmt::Debug, iter, ops::DerefMut, slice::SliceIndex};
 
 use anyhow::Context;
 use rgb::{ComponentMap, RGB8};
-use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
 use tracing::{info, instrument};
 
-pub struct Neopixel {
-    pub spi: Spi,
+use super::hal::{Hal, SpiBus};
+
+pub const SPI_BUS: u8 = 0;
+pub const SPI_SELECT: u8 = 0;
+pub const SPI_CLOCK: u32 = 6_000_000;
+
+pub struct Neopixel<H: Hal> {
+    pub spi: H::Spi,
     pub buffer: NeopixelBuffer,
 }
 
-impl Neopixel {
-    pub const SPI_BUS: Bus = Bus::Spi0;
-    pub const SPI_SELECT: SlaveSelect = SlaveSelect::Ss0;
-    pub const SPI_CLOCK: u32 = 6_000_000;
-
-    #[instrument(level = "debug")]
+impl<H: Hal> Neopixel<H> {
+    #[instrument(level = "debug", skip(hal))]
     pub fn new(
+        hal: &H,
         len: usize,
-        bus: Bus,
-        slave_select: SlaveSelect,
+        bus: u8,
+        slave_select: u8,
         clock_speed: u32,
     ) -> anyhow::Result<Self> {
         info!("Setting up Neopixels");
 
-        let spi = Spi::new(bus, slave_select, clock_speed, Mode::Mode0).context("Open spi")?;
+        let spi = hal
+            .spi(bus, slave_select, clock_speed)
+            .context("Open spi")?;
         let buffer = NeopixelBuffer::new(len);
 
         let mut this = Self { spi, buffer };
@@ -39,8 +43,8 @@ impl Neopixel {
 
     pub fn fill<T, I>(&mut self, idx: I, color: RGB8, gamma_correction: bool)
     where
-        T: AsSlice<u8> + ?Sized,
-        I: SliceIndex<[u8], Output = T> + Debug + Copy,
+        T: AsSlice<LedData> + ?Sized,
+        I: SliceIndex<[LedData], Output = T> + Debug + Copy,
     {
         self.buffer.fill(idx, color, gamma_correction)
     }
@@ -49,8 +53,8 @@ impl Neopixel {
     /// If the colors iterator finishes before the end of the range, no more LEDs will be set
     pub fn set<T, I, C>(&mut self, idx: I, colors: C, gamma_correction: bool)
     where
-        T: AsSlice<u8> + ?Sized,
-        I: SliceIndex<[u8], Output = T> + Debug + Copy,
+        T: AsSlice<LedData> + ?Sized,
+        I: SliceIndex<[LedData], Output = T> + Debug + Copy,
         C: Iterator<Item = RGB8>,
     {
         self.buffer.set(idx, colors, gamma_correction)
@@ -74,13 +78,17 @@ impl Neopixel {
     }
 }
 
-impl Drop for Neopixel {
+impl<H: Hal> Drop for Neopixel<H> {
     fn drop(&mut self) {
         self.fill(.., RGB8::default(), false);
         let _ = self.show();
     }
 }
 
+/// Every bit of an LED's 3 color bytes takes one byte on the wire
+const LED_BYTES: usize = 3 * 8;
+type LedData = [u8; LED_BYTES];
+
 #[derive(Clone)]
 pub struct NeopixelBuffer {
     buffer: Vec<u8>,
@@ -88,19 +96,23 @@ pub struct NeopixelBuffer {
 
 impl NeopixelBuffer {
     pub fn new(len: usize) -> Self {
-        Self {
-            buffer: vec![0; len * 3],
-        }
+        let mut this = Self {
+            buffer: vec![0; len * LED_BYTES],
+        };
+        // All zeros is no signal at all, the LEDs would keep their old colors
+        this.fill(.., RGB8::default(), false);
+
+        this
     }
 
     pub fn len(&self) -> usize {
-        self.buffer.len() / 3
+        self.buffer.len() / LED_BYTES
     }
 
     pub fn fill<T, I>(&mut self, idx: I, color: RGB8, gamma_correction: bool)
     where
-        T: AsSlice<u8> + ?Sized,
-        I: SliceIndex<[u8], Output = T> + Debug + Copy,
+        T: AsSlice<LedData> + ?Sized,
+        I: SliceIndex<[LedData], Output = T> + Debug + Copy,
     {
         self.set(idx, iter::repeat(color), gamma_correction)
     }
@@ -109,17 +121,18 @@ impl NeopixelBuffer {
     /// If the colors iterator finishes before the end of the range, no more LEDs will be set
     pub fn set<T, I, C>(&mut self, idx: I, colors: C, gamma_correction: bool)
     where
-        T: AsSlice<u8> + ?Sized,
-        I: SliceIndex<[u8], Output = T> + Debug + Copy,
+        T: AsSlice<LedData> + ?Sized,
+        I: SliceIndex<[LedData], Output = T> + Debug + Copy,
         C: Iterator<Item = RGB8>,
     {
-        let Some(buffer) = self.buffer.deref_mut().get_mut(idx) else {
+        let (leds, _) = self.buffer.deref_mut().as_chunks_mut::<LED_BYTES>();
+        let Some(buffer) = leds.get_mut(idx) else {
             panic!(
                 "Could not set neopixel. index ({idx:?}) out of bounds for length {}",
                 self.len()
             );
         };
-        let dst_iter = buffer.as_slice_mut().iter_mut();
+        let dst_iter = buffer.as_slice_mut().iter_mut().flatten();
         let src_iter = colors
             .map(|color| {
                 if gamma_correction {
@@ -212,3 +225,87 @@ impl<T> AsSlice<T> for [T] {
         self
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rgb::RGB8;
+
+    use crate::peripheral::hal::mock::{Device, MockHal, Op};
+
+    use super::{Neopixel, NeopixelBuffer, SPI_BUS, SPI_CLOCK, SPI_SELECT};
+
+    const T0: u8 = 0b1100_0000;
+    const T1: u8 = 0b1111_1000;
+
+    const NEOPIXEL: Device = Device::Spi {
+        bus: SPI_BUS,
+        slave_select: SPI_SELECT,
+    };
+
+    fn encode(byte: u8) -> [u8; 8] {
+        std::array::from_fn(|bit| if byte & (0x80 >> bit) != 0 { T1 } else { T0 })
+    }
+
+    #[test]
+    fn colors_are_sent_grb_msb_first() {
+        let mut buffer = NeopixelBuffer::new(2);
+        buffer.set(
+            ..,
+            [RGB8::new(0x12, 0x34, 0x56), RGB8::new(0xff, 0, 0x80)].into_iter(),
+            false,
+        );
+
+        let expected = [
+            encode(0x34),
+            encode(0x12),
+            encode(0x56),
+            encode(0),
+            encode(0xff),
+            encode(0x80),
+        ]
+        .concat();
+        assert_eq!(buffer.to_slice(), expected);
+    }
+
+    #[test]
+    fn fill_covers_only_the_index() {
+        let mut buffer = NeopixelBuffer::new(3);
+        buffer.fill(1, RGB8::new(0, 0xff, 0), false);
+
+        let expected = [
+            [encode(0); 3].concat(),
+            [encode(0xff), encode(0), encode(0)].concat(),
+            [encode(0); 3].concat(),
+        ]
+        .concat();
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.to_slice(), expected);
+    }
+
+    #[test]
+    fn gamma_correction() {
+        let mut buffer = NeopixelBuffer::new(1);
+        buffer.fill(.., RGB8::new(128, 255, 0), true);
+
+        assert_eq!(
+            buffer.to_slice(),
+            [encode(255), encode(37), encode(0)].concat()
+        );
+    }
+
+    #[test]
+    fn show_writes_the_buffer() {
+        let hal = MockHal::default();
+        let mut neopixel = Neopixel::new(&hal, 1, SPI_BUS, SPI_SELECT, SPI_CLOCK).unwrap();
+        neopixel.fill(.., RGB8::new(1, 2, 3), false);
+        neopixel.show().unwrap();
+        drop(neopixel);
+
+        let off = [encode(0); 3].concat();
+        let on = [encode(2), encode(1), encode(3)].concat();
+        assert_eq!(
+            hal.ops(NEOPIXEL),
+            [Op::Write(off.clone()), Op::Write(on), Op::Write(off)]
+        );
+    }
+}
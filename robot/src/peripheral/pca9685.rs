@@ -1,47 +1,83 @@
 use core::slice;
-use std::{array, thread, time::Duration};
+use std::{
+    array,
+    fmt::{self, Display},
+    thread,
+    time::Duration,
+};
 
 use anyhow::{bail, Context};
-use rppal::{
-    gpio::{Gpio, OutputPin},
-    i2c::I2c,
-};
-use tracing::{debug, info, instrument};
+use tracing::{debug, info, instrument, warn};
+
+use super::hal::{GpioPin, Hal, I2cBus, Level, PinMode};
 
 // PWM_OE (GPIO66) is active low
 // pwm chip is on i2c4 at address 0x40
 // See https://bluerobotics.com/wp-content/uploads/2022/05/PCA9685-DATASHEET.pdf
 
-pub struct Pca9685 {
-    i2c: I2c,
-    output_enable: OutputPin,
+// Pi 5
+pub const I2C_BUS: u8 = 3;
+// Pi 4
+// pub const I2C_BUS: u8 = 4;
+pub const I2C_ADDRESS: u8 = 0x40;
+pub const OUTPUT_ENABLE_PIN: u8 = 26;
+
+pub struct Pca9685<H: Hal> {
+    i2c: H::I2c,
+    output_enable: H::Pin,
     period: Duration,
+
+    /// Raw off time last written to each channel
+    outputs: [Option<u16>; 16],
+    /// Channel the next [`Pca9685::verify_outputs`] reads back
+    next_verify: u8,
+}
+
+/// A register that doesn't hold what was last written to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputMismatch {
+    pub register: u8,
+    pub expected: u8,
+    pub observed: u8,
 }
 
-impl Pca9685 {
-    // Pi 5
-    pub const I2C_BUS: u8 = 3;
-    // Pi 4
-    // pub const I2C_BUS: u8 = 4;
-    pub const I2C_ADDRESS: u8 = 0x40;
+impl Display for OutputMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "register {:#04x} holds {:#04x} instead of {:#04x}",
+            self.register, self.observed, self.expected
+        )
+    }
+}
+
+/// Outcome of a [`Pca9685::verify_outputs`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputCheck {
+    /// The sampled registers hold what was written
+    Verified,
+    /// The chip had lost its configuration, it was initialized and written again
+    Restored(OutputMismatch),
+    /// The chip still doesn't hold what was written after initializing it again
+    Failed(OutputMismatch),
+}
 
-    #[instrument(level = "debug")]
-    pub fn new(bus: u8, address: u8, period: Duration) -> anyhow::Result<Self> {
+impl<H: Hal> Pca9685<H> {
+    #[instrument(level = "debug", skip(hal))]
+    pub fn new(hal: &H, bus: u8, address: u8, period: Duration) -> anyhow::Result<Self> {
         info!("Setting up PCA9685 (PWM Controller)");
 
-        let gpio = Gpio::new().context("Open gpio")?;
-        let mut i2c = I2c::with_bus(bus).context("Open i2c")?;
-        let output_enable = gpio
-            .get(26)
-            .context("Get PWM Output Enable pin")?
-            .into_output_high();
-        i2c.set_slave_address(address as u16)
-            .context("Set addres for PCA9685")?;
+        let output_enable = hal
+            .pin(OUTPUT_ENABLE_PIN, PinMode::Output(Level::High))
+            .context("Get PWM Output Enable pin")?;
+        let i2c = hal.i2c(bus, address).context("Open i2c for PCA9685")?;
 
         let mut this = Self {
             i2c,
             output_enable,
             period,
+            outputs: [None; 16],
+            next_verify: 0,
         };
 
         this.initialize().context("Init PCA9685")?;
@@ -51,12 +87,12 @@ impl Pca9685 {
 
     #[instrument(level = "trace", skip(self))]
     pub fn output_enable(&mut self) {
-        self.output_enable.set_low();
+        self.output_enable.set_level(Level::Low);
     }
 
     #[instrument(level = "trace", skip(self))]
     pub fn output_disable(&mut self) {
-        self.output_enable.set_high();
+        self.output_enable.set_level(Level::High);
     }
 
     #[instrument(level = "trace", skip(self), ret)]
@@ -68,6 +104,7 @@ impl Pca9685 {
         let register = channel_to_reg(channel);
         let message = [register, lower, upper];
         self.i2c.write(&message).context("Write pwm")?;
+        self.outputs[channel as usize] = Some(raw);
 
         if cfg!(debug_assertions) {
             let mut observed = [0; 2];
@@ -83,12 +120,52 @@ impl Pca9685 {
         Ok(())
     }
 
+    /// Writes `pwms` to consecutive channels starting at `first` in a single transfer
+    #[instrument(level = "trace", skip(self), ret)]
+    pub fn set_pwm_run(&mut self, first: u8, pwms: &[Duration]) -> anyhow::Result<()> {
+        if first as usize + pwms.len() > 16 {
+            bail!("{} channels starting at {first} do not exist", pwms.len());
+        }
+
+        let raw = pwms
+            .iter()
+            .map(|pwm| pwm_to_raw(*pwm, self.period))
+            .collect::<Vec<_>>();
+
+        let mut message = Vec::with_capacity(1 + pwms.len() * 4);
+        message.push(REG_LED0_ON_L + (first << 2));
+
+        for raw in &raw {
+            let upper = ((raw & 0x0f00) >> 8) as u8;
+            let lower = ((raw & 0x00ff) >> 0) as u8;
+
+            message.extend([0, 0, lower, upper]);
+        }
+
+        self.i2c.write(&message).context("Write pwm")?;
+        for (idx, raw) in raw.into_iter().enumerate() {
+            self.outputs[first as usize + idx] = Some(raw);
+        }
+
+        if cfg!(debug_assertions) {
+            let mut observed = vec![0; message.len() - 1];
+            self.i2c
+                .write_read(&message[..1], &mut observed)
+                .context("Validate pwm")?;
+            if observed != message[1..] {
+                bail!("Attempted to set pwm to {message:?}. Instead, {observed:?} was read");
+            }
+        }
+
+        Ok(())
+    }
+
     #[instrument(level = "trace", skip(self), ret)]
     pub fn set_pwms(&mut self, pwm: [Duration; 16]) -> anyhow::Result<()> {
         let raw: [u16; 16] = array::from_fn(|idx| pwm_to_raw(pwm[idx], self.period));
 
         let mut message: [u8; 65] = [0; 65];
-        message[0] = Self::REG_LED0_ON_L;
+        message[0] = REG_LED0_ON_L;
 
         for idx in 0..16 {
             let upper = ((raw[idx] & 0x0f00) >> 8) as u8;
@@ -99,11 +176,12 @@ impl Pca9685 {
         }
 
         self.i2c.write(&message).context("Write pwm")?;
+        self.outputs = raw.map(Some);
 
         if cfg!(debug_assertions) {
             let mut observed = [0; 64];
             self.i2c
-                .write_read(&[Self::REG_LED0_ON_L], &mut observed)
+                .write_read(&[REG_LED0_ON_L], &mut observed)
                 .context("Validate pwm")?;
             if observed != message[1..] {
                 bail!("Attempted to set pwm to {message:?}. Instead, {observed:?} was read");
@@ -112,27 +190,106 @@ impl Pca9685 {
 
         Ok(())
     }
+
+    /// Reads back the prescale and the next channel in turn, comparing them to what was written
+    ///
+    /// Cheap enough to run every cycle. A chip that reset to its defaults is initialized and
+    /// written again once, only errors reading the registers in the first place are returned.
+    #[instrument(level = "trace", skip(self), ret)]
+    pub fn verify_outputs(&mut self) -> anyhow::Result<OutputCheck> {
+        let channel = self.next_verify;
+        self.next_verify = (self.next_verify + 1) % 16;
+
+        let Some(mismatch) = self.check_channel(channel)? else {
+            return Ok(OutputCheck::Verified);
+        };
+
+        warn!(%mismatch, "PCA9685 lost its outputs, initializing it again");
+
+        let restored = self
+            .initialize()
+            .and_then(|()| self.rewrite())
+            .and_then(|()| self.check_channel(channel));
+        match restored {
+            Ok(None) => Ok(OutputCheck::Restored(mismatch)),
+            Ok(Some(mismatch)) => Ok(OutputCheck::Failed(mismatch)),
+            Err(err) => {
+                warn!("Could not restore PCA9685: {err:?}");
+                Ok(OutputCheck::Failed(mismatch))
+            }
+        }
+    }
+
+    /// The first register of the prescale and `channel` that doesn't hold what was written
+    fn check_channel(&mut self, channel: u8) -> anyhow::Result<Option<OutputMismatch>> {
+        let prescale = calc_prescale(self.period);
+        let observed = self.read_reg(REG_PRESCALE).context("Read prescale")?;
+        if observed != prescale {
+            return Ok(Some(OutputMismatch {
+                register: REG_PRESCALE,
+                expected: prescale,
+                observed,
+            }));
+        }
+
+        let Some(raw) = self.outputs[channel as usize] else {
+            return Ok(None);
+        };
+
+        let register = REG_LED0_ON_L + (channel << 2);
+        let expected = [0, 0, (raw & 0x00ff) as u8, ((raw & 0x0f00) >> 8) as u8];
+        let mut observed = [0; 4];
+        self.i2c
+            .write_read(&[register], &mut observed)
+            .context("Read channel")?;
+
+        let mismatch = (0..4).find(|&idx| observed[idx] != expected[idx]);
+        Ok(mismatch.map(|idx| OutputMismatch {
+            register: register + idx as u8,
+            expected: expected[idx],
+            observed: observed[idx],
+        }))
+    }
+
+    /// Writes every channel's last output again
+    fn rewrite(&mut self) -> anyhow::Result<()> {
+        for (channel, raw) in self.outputs.into_iter().enumerate() {
+            let Some(raw) = raw else {
+                continue;
+            };
+
+            let upper = ((raw & 0x0f00) >> 8) as u8;
+            let lower = ((raw & 0x00ff) >> 0) as u8;
+            let register = REG_LED0_ON_L + ((channel as u8) << 2);
+
+            self.i2c
+                .write(&[register, 0, 0, lower, upper])
+                .context("Rewrite pwm")?;
+        }
+
+        Ok(())
+    }
 }
 
 // Implementation based on https://github.com/bluerobotics/pca9685-python
-impl Pca9685 {
-    const REG_MODE1: u8 = 0x00;
-    const REG_PRESCALE: u8 = 0xfe;
-    const REG_LED0_ON_L: u8 = 0x06;
-    const REG_LED0_OFF_L: u8 = 0x08;
-    const REG_ALL_LED_OFF_H: u8 = 0xfd;
+const REG_MODE1: u8 = 0x00;
+const REG_PRESCALE: u8 = 0xfe;
+const REG_LED0_ON_L: u8 = 0x06;
+const REG_LED0_OFF_L: u8 = 0x08;
+const REG_ALL_LED_OFF_H: u8 = 0xfd;
 
-    const MODE1_SLEEP: u8 = 1 << 4;
-    const MODE1_EXTCLK: u8 = 1 << 6;
-    const MODE1_AI: u8 = 1 << 5;
+const MODE1_SLEEP: u8 = 1 << 4;
+const MODE1_EXTCLK: u8 = 1 << 6;
+const MODE1_AI: u8 = 1 << 5;
 
-    const EXT_CLOCK: f64 = 24.576e6;
+const EXT_CLOCK: f64 = 24.576e6;
 
+impl<H: Hal> Pca9685<H> {
     fn initialize(&mut self) -> anyhow::Result<()> {
         debug!("Initializing PCA9685 (pwm controller)");
 
         self.i2c
-            .write(&[Self::REG_MODE1, Self::MODE1_SLEEP | Self::MODE1_AI])
+            .write(&[REG_MODE1, MODE1_SLEEP | MODE1_AI])
             .context("Init PCA9685")?;
         self.set_prescale().context("Set prescale")?;
 
@@ -150,23 +307,18 @@ impl Pca9685 {
         debug!(prescale, "Setting prescale");
 
         self.i2c
-            .write(&[
-                Self::REG_MODE1,
-                Self::MODE1_EXTCLK | Self::MODE1_SLEEP | Self::MODE1_AI,
-            ])
+            .write(&[REG_MODE1, MODE1_EXTCLK | MODE1_SLEEP | MODE1_AI])
             .context("Setup for prescale")?;
 
         self.i2c
-            .write(&[Self::REG_PRESCALE, prescale])
+            .write(&[REG_PRESCALE, prescale])
             .context("Write prescale")?;
 
         self.i2c
-            .write(&[Self::REG_MODE1, Self::MODE1_EXTCLK | Self::MODE1_AI])
+            .write(&[REG_MODE1, MODE1_EXTCLK | MODE1_AI])
             .context("Unsleep")?;
 
-        let observed_prescale = self
-            .read_reg(Self::REG_PRESCALE)
-            .context("Verify prescale")?;
+        let observed_prescale = self.read_reg(REG_PRESCALE).context("Verify prescale")?;
         if observed_prescale != prescale {
             bail!("Attempted to set prescale to {prescale}. Instead, {observed_prescale} was read");
         }
@@ -174,7 +326,7 @@ impl Pca9685 {
         Ok(())
     }
 
-    fn read_reg(&self, reg: u8) -> anyhow::Result<u8> {
+    fn read_reg(&mut self, reg: u8) -> anyhow::Result<u8> {
         let mut out = 0;
         self.i2c
             .write_read(&[reg], slice::from_mut(&mut out))
@@ -183,7 +335,7 @@ impl Pca9685 {
     }
 }
 
-impl Drop for Pca9685 {
+impl<H: Hal> Drop for Pca9685<H> {
     fn drop(&mut self) {
         let _ = self.set_pwms([Duration::ZERO; 16]);
 
@@ -193,21 +345,278 @@ impl Drop for Pca9685 {
         self.output_disable();
         thread::sleep(Duration::from_millis(5));
 
-        let _ = self.i2c.write(&[Self::REG_ALL_LED_OFF_H, 0x08]);
-        let _ = self.i2c.write(&[Self::REG_MODE1, Self::MODE1_SLEEP]);
+        let _ = self.i2c.write(&[REG_ALL_LED_OFF_H, 0x08]);
+        let _ = self.i2c.write(&[REG_MODE1, MODE1_SLEEP]);
     }
 }
 
 fn calc_prescale(period: Duration) -> u8 {
     let update_rate = 1.0 / period.as_secs_f64();
-    ((Pca9685::EXT_CLOCK / (4096.0 * update_rate)).round() - 1.0) as u8
+    ((EXT_CLOCK / (4096.0 * update_rate)).round() - 1.0) as u8
 }
 
 const fn pwm_to_raw(pwm: Duration, period: Duration) -> u16 {
-    (pwm.as_micros() as u32 * 4096 / period.as_micros() as u32 - 1) as u16
+    // A zero length pulse must not wrap around to a full one
+    (pwm.as_micros() as u32 * 4096 / period.as_micros() as u32).saturating_sub(1) as u16
 }
 
 const fn channel_to_reg(channel: u8) -> u8 {
     assert!(channel < 16);
-    Pca9685::REG_LED0_OFF_L + (4 * channel)
+    REG_LED0_OFF_L + (4 * channel)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use common::components::PwmSignal;
+
+    use crate::peripheral::hal::{
+        mock::{Device, MockHal, Op},
+        Level, PinMode,
+    };
+
+    use super::{OutputCheck, OutputMismatch, Pca9685, I2C_ADDRESS, I2C_BUS, OUTPUT_ENABLE_PIN};
+
+    const PWM: Device = Device::I2c {
+        bus: I2C_BUS,
+        address: I2C_ADDRESS,
+    };
+    const OUTPUT_ENABLE: Device = Device::Pin(OUTPUT_ENABLE_PIN);
+
+    const PERIOD: Duration = Duration::from_millis(10);
+
+    fn writes(hal: &MockHal) -> Vec<Vec<u8>> {
+        hal.ops(PWM)
+            .into_iter()
+            .filter_map(|op| match op {
+                Op::Write(data) => Some(data),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn initialize_sequence() {
+        let hal = MockHal::default();
+        let _pwm = Pca9685::new(&hal, I2C_BUS, I2C_ADDRESS, PERIOD).unwrap();
+
+        assert_eq!(
+            writes(&hal),
+            [
+                vec![0x00, 0x30],
+                vec![0x00, 0x70],
+                // 24.576 MHz / (4096 * 100 Hz) - 1
+                vec![0xfe, 59],
+                vec![0x00, 0x60],
+            ]
+        );
+        assert_eq!(hal.register(PWM, 0xfe), 59);
+
+        // Outputs stay disabled until asked
+        assert_eq!(
+            hal.ops(OUTPUT_ENABLE),
+            [Op::SetMode(PinMode::Output(Level::High))]
+        );
+    }
+
+    #[test]
+    fn set_pwm_registers() {
+        let hal = MockHal::default();
+        let mut pwm = Pca9685::new(&hal, I2C_BUS, I2C_ADDRESS, PERIOD).unwrap();
+        hal.clear_transactions();
+
+        let signal = PwmSignal(Duration::from_micros(1500));
+        pwm.set_pwm(2, signal.0).unwrap();
+
+        // LED2_OFF_L, 1500 / 10000 * 4096 - 1 = 613 = 0x265
+        assert_eq!(writes(&hal), [vec![0x10, 0x65, 0x02]]);
+    }
+
+    #[test]
+    fn set_pwms_registers() {
+        let hal = MockHal::default();
+        let mut pwm = Pca9685::new(&hal, I2C_BUS, I2C_ADDRESS, PERIOD).unwrap();
+        hal.clear_transactions();
+
+        let signals: [PwmSignal; 16] =
+            std::array::from_fn(|idx| PwmSignal(Duration::from_micros(1100 + 50 * idx as u64)));
+        pwm.set_pwms(signals.map(|it| it.0)).unwrap();
+
+        let writes = writes(&hal);
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0][0], 0x06);
+        assert_eq!(writes[0].len(), 65);
+
+        for (channel, signal) in signals.iter().enumerate() {
+            let on = 0x06 + 4 * channel as u8;
+            let raw = (signal.0.as_micros() * 4096 / PERIOD.as_micros() - 1) as u16;
+
+            assert_eq!(hal.register(PWM, on), 0);
+            assert_eq!(hal.register(PWM, on + 1), 0);
+            assert_eq!(hal.register(PWM, on + 2), raw as u8);
+            assert_eq!(hal.register(PWM, on + 3), (raw >> 8) as u8);
+        }
+    }
+
+    #[test]
+    fn output_enable_is_active_low() {
+        let hal = MockHal::default();
+        let mut pwm = Pca9685::new(&hal, I2C_BUS, I2C_ADDRESS, PERIOD).unwrap();
+
+        pwm.output_enable();
+        pwm.output_disable();
+
+        assert_eq!(
+            hal.ops(OUTPUT_ENABLE)[1..],
+            [Op::SetLevel(Level::Low), Op::SetLevel(Level::High)]
+        );
+    }
+
+    #[test]
+    fn drop_stops_outputs() {
+        let hal = MockHal::default();
+        let pwm = Pca9685::new(&hal, I2C_BUS, I2C_ADDRESS, PERIOD).unwrap();
+        hal.clear_transactions();
+
+        drop(pwm);
+
+        let writes = writes(&hal);
+        assert!(writes[0][1..].iter().all(|&it| it == 0));
+        assert_eq!(writes[1..], [vec![0xfd, 0x08], vec![0x00, 0x10]]);
+        assert_eq!(hal.ops(OUTPUT_ENABLE), [Op::SetLevel(Level::High)]);
+    }
+
+    #[test]
+    fn mismatched_prescale_fails_init() {
+        let hal = MockHal::default();
+        hal.queue_read(PWM, [60]);
+
+        assert!(Pca9685::new(&hal, I2C_BUS, I2C_ADDRESS, PERIOD).is_err());
+    }
+
+    fn running_pwm(hal: &MockHal) -> Pca9685<MockHal> {
+        let mut pwm = Pca9685::new(hal, I2C_BUS, I2C_ADDRESS, PERIOD).unwrap();
+        pwm.set_pwms(std::array::from_fn(|idx| {
+            Duration::from_micros(1100 + 50 * idx as u64)
+        }))
+        .unwrap();
+        hal.clear_transactions();
+
+        pwm
+    }
+
+    /// The registers of a chip that browned out and came back with its power on defaults
+    fn reset_chip(hal: &MockHal) {
+        hal.set_registers(PWM, 0x00, &[0x11]);
+        hal.set_registers(PWM, 0x06, &[0, 0, 0, 0x10].repeat(16));
+        hal.set_registers(PWM, 0xfe, &[30]);
+    }
+
+    #[test]
+    fn verification_reads_one_channel_per_call() {
+        let hal = MockHal::default();
+        let mut pwm = running_pwm(&hal);
+
+        assert_eq!(pwm.verify_outputs().unwrap(), OutputCheck::Verified);
+        assert_eq!(pwm.verify_outputs().unwrap(), OutputCheck::Verified);
+        assert_eq!(
+            hal.ops(PWM),
+            [
+                Op::WriteRead(vec![0xfe], 1),
+                Op::WriteRead(vec![0x06], 4),
+                Op::WriteRead(vec![0xfe], 1),
+                Op::WriteRead(vec![0x0a], 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn reset_chip_is_restored() {
+        let hal = MockHal::default();
+        let mut pwm = running_pwm(&hal);
+        let channels = (0..64)
+            .map(|idx| hal.register(PWM, 0x06 + idx))
+            .collect::<Vec<_>>();
+
+        assert_eq!(pwm.verify_outputs().unwrap(), OutputCheck::Verified);
+        reset_chip(&hal);
+
+        assert_eq!(
+            pwm.verify_outputs().unwrap(),
+            OutputCheck::Restored(OutputMismatch {
+                register: 0xfe,
+                expected: 59,
+                observed: 30,
+            })
+        );
+
+        // Configured and holding every output again
+        assert_eq!(hal.register(PWM, 0xfe), 59);
+        assert_eq!(hal.register(PWM, 0x00), 0x60);
+        for (idx, expected) in channels.into_iter().enumerate() {
+            assert_eq!(hal.register(PWM, 0x06 + idx as u8), expected);
+        }
+
+        assert_eq!(pwm.verify_outputs().unwrap(), OutputCheck::Verified);
+    }
+
+    #[test]
+    fn lost_channel_is_found_in_turn() {
+        let hal = MockHal::default();
+        let mut pwm = running_pwm(&hal);
+
+        // Channel 5's off time drops out while the prescale survives
+        hal.set_registers(PWM, 0x06 + 4 * 5 + 2, &[0]);
+
+        for _ in 0..5 {
+            assert_eq!(pwm.verify_outputs().unwrap(), OutputCheck::Verified);
+        }
+        assert_eq!(
+            pwm.verify_outputs().unwrap(),
+            OutputCheck::Restored(OutputMismatch {
+                register: 0x1c,
+                // 1350 / 10000 * 4096 - 1 = 551 = 0x227
+                expected: 0x27,
+                observed: 0,
+            })
+        );
+        assert_eq!(hal.register(PWM, 0x1c), 0x27);
+    }
+
+    #[test]
+    fn chip_that_stays_reset_fails() {
+        let hal = MockHal::default();
+        let mut pwm = running_pwm(&hal);
+        hal.hold_registers(PWM, 0xfe, &[30]);
+
+        let failed = OutputCheck::Failed(OutputMismatch {
+            register: 0xfe,
+            expected: 59,
+            observed: 30,
+        });
+        assert_eq!(pwm.verify_outputs().unwrap(), failed);
+
+        // Still tries again every time rather than giving up on the chip
+        hal.clear_transactions();
+        assert_eq!(pwm.verify_outputs().unwrap(), failed);
+        assert!(writes(&hal).contains(&vec![0xfe, 59]));
+    }
+
+    #[test]
+    fn unreadable_chip_is_an_error() {
+        let hal = MockHal::default();
+        let mut pwm = running_pwm(&hal);
+        hal.fail_next(PWM, "Remote I/O error");
+
+        assert!(pwm.verify_outputs().is_err());
+    }
+
+    #[test]
+    fn missing_chip_fails_init() {
+        let hal = MockHal::default();
+        hal.fail_next(PWM, "No such device");
+
+        assert!(Pca9685::new(&hal, I2C_BUS, I2C_ADDRESS, PERIOD).is_err());
+    }
 }
@@ -1,4 +1,5 @@
 pub mod actuators;
+pub mod autonomy;
 pub mod core;
 pub mod monitor;
 pub mod sensors;
@@ -1,13 +1,24 @@
+pub mod current_trim;
 pub mod depth_hold;
+#[cfg(feature = "hw-leds")]
 pub mod leds;
+pub mod motor_report;
+#[cfg(feature = "hw-pwm")]
 pub mod pwm;
+#[cfg(feature = "hw-pwm")]
+pub mod pwm_groups;
 pub mod servo;
+#[cfg(feature = "sim")]
+pub mod sim;
 pub mod stabilize;
 pub mod thruster;
 
 use bevy::{app::PluginGroupBuilder, prelude::PluginGroup};
 
-pub struct MovementPlugins;
+pub struct MovementPlugins {
+    /// Drive the robot's hardware, only possible on the robot itself
+    pub hardware: bool,
+}
 
 impl PluginGroup for MovementPlugins {
     fn build(self) -> PluginGroupBuilder {
@@ -15,13 +26,30 @@ impl PluginGroup for MovementPlugins {
             .add(servo::ServoPlugin)
             .add(thruster::ThrusterPlugin)
             .add(stabilize::StabilizePlugin)
-            .add(depth_hold::DepthHoldPlugin);
+            .add(depth_hold::DepthHoldPlugin)
+            .add(current_trim::CurrentTrimPlugin);
+
+        // Plugins depending on robot hardware
+        #[cfg(feature = "hw-pwm")]
+        let plugins = if self.hardware {
+            plugins.add(pwm::PwmOutputPlugin)
+        } else {
+            plugins
+        };
+
+        #[cfg(feature = "hw-leds")]
+        let plugins = if self.hardware {
+            plugins.add(leds::LedPlugin)
+        } else {
+            plugins
+        };
 
-        #[cfg(rpi)]
-        let plugins = plugins
-            // Plugins depending on robot hardware
-            .add(pwm::PwmOutputPlugin)
-            .add(leds::LedPlugin);
+        #[cfg(feature = "sim")]
+        let plugins = if !(cfg!(feature = "hw-pwm") && self.hardware) {
+            plugins.add(sim::SimPwmPlugin)
+        } else {
+            plugins
+        };
 
         plugins
     }
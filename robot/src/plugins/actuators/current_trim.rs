@@ -0,0 +1,181 @@
+//! Counters steady water current with a constant force the pilot doesn't have to hold
+//!
+//! [`CurrentTrim`] is set from the surface in world frame so it keeps pushing against the current
+//! as the robot turns. It is zeroed on disarm so a trim for one run doesn't carry into the next.
+//! Trims that aren't finite are dropped and the rest are held to [`CurrentTrim::limit`].
+
+use anyhow::anyhow;
+use bevy::prelude::*;
+use common::{
+    bundles::MovementContributionBundle,
+    components::{
+        Armed, CurrentTrim, MovementAxisMaximums, MovementContribution, Orientation, RobotId,
+    },
+    ecs_sync::Replicate,
+    error::ErrorEvent,
+};
+use glam::{Quat, Vec3A};
+use motor_math::Movement;
+
+use crate::plugins::core::robot::LocalRobot;
+
+pub struct CurrentTrimPlugin;
+
+impl Plugin for CurrentTrimPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_current_trim).add_systems(
+            Update,
+            (validate_trim, zero_trim_on_disarm, current_trim_system).chain(),
+        );
+    }
+}
+
+#[derive(Resource)]
+struct CurrentTrimState(Entity);
+
+fn setup_current_trim(mut cmds: Commands, robot: Res<LocalRobot>) {
+    let entity = cmds
+        .spawn((
+            MovementContributionBundle {
+                name: Name::new("Current Trim"),
+                contribution: MovementContribution(Movement::default()),
+                robot: RobotId(robot.net_id),
+            },
+            Replicate,
+        ))
+        .id();
+
+    cmds.entity(robot.entity).insert(CurrentTrim::default());
+    cmds.insert_resource(CurrentTrimState(entity));
+}
+
+fn validate_trim(
+    mut robot: Query<(&mut CurrentTrim, Option<&MovementAxisMaximums>), Changed<CurrentTrim>>,
+    mut errors: EventWriter<ErrorEvent>,
+) {
+    for (mut trim, maximums) in &mut robot {
+        match checked_trim(trim.0, CurrentTrim::limit(maximums)) {
+            Some(checked) if checked == trim.0 => {}
+            Some(checked) => {
+                warn!("Current trim of {} clamped to {checked}", trim.0);
+                trim.0 = checked;
+            }
+            None => {
+                errors.send(anyhow!("Current trim of {} is not finite", trim.0).into());
+                trim.0 = Vec3A::ZERO;
+            }
+        }
+    }
+}
+
+/// `trim` held to `limit` along each axis, `None` if it isn't finite
+fn checked_trim(trim: Vec3A, limit: f32) -> Option<Vec3A> {
+    trim.is_finite()
+        .then(|| trim.clamp(Vec3A::splat(-limit), Vec3A::splat(limit)))
+}
+
+fn zero_trim_on_disarm(mut robot: Query<(&Armed, &mut CurrentTrim), Changed<Armed>>) {
+    for (armed, mut trim) in &mut robot {
+        if *armed == Armed::Disarmed && trim.0 != Vec3A::ZERO {
+            info!("Disarmed, cleared current trim");
+            trim.0 = Vec3A::ZERO;
+        }
+    }
+}
+
+fn current_trim_system(
+    mut warned: Local<bool>,
+    mut cmds: Commands,
+    robot: Res<LocalRobot>,
+    state: Res<CurrentTrimState>,
+    robot_query: Query<(&Armed, &CurrentTrim, Option<&Orientation>)>,
+) {
+    let robot = robot_query.get(robot.entity);
+
+    match robot {
+        Ok((&Armed::Armed, &CurrentTrim(trim), orientation)) if trim != Vec3A::ZERO => {
+            if orientation.is_none() && !*warned {
+                warn!("No orientation, applying current trim in body frame");
+            }
+            *warned = orientation.is_none();
+
+            let movement = Movement {
+                force: trim_to_body(trim, orientation.map(|it| it.rotation)),
+                torque: Vec3A::ZERO,
+            };
+
+            cmds.entity(state.0).insert(MovementContribution(movement));
+        }
+        _ => {
+            cmds.entity(state.0).remove::<MovementContribution>();
+        }
+    }
+}
+
+/// The body frame force that pushes along world frame `trim` at `orientation`
+///
+/// Without an orientation the trim is taken to already be in body frame
+fn trim_to_body(trim: Vec3A, orientation: Option<Quat>) -> Vec3A {
+    match orientation {
+        Some(orientation) => orientation.inverse() * trim,
+        None => trim,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Quat, Vec3A};
+
+    use super::{checked_trim, trim_to_body};
+
+    fn assert_close(a: Vec3A, b: Vec3A) {
+        assert!(a.abs_diff_eq(b, 1e-5), "{a} != {b}");
+    }
+
+    #[test]
+    fn trim_rotates_into_body_frame() {
+        let trim = Vec3A::new(0.0, 5.0, 0.0);
+
+        // Level and facing north, world and body agree
+        assert_close(trim_to_body(trim, Some(Quat::IDENTITY)), trim);
+
+        // Yawed 90 degrees counter clockwise, north is now off the robot's right side
+        let yawed = Quat::from_rotation_z(90f32.to_radians());
+        assert_close(trim_to_body(trim, Some(yawed)), Vec3A::new(5.0, 0.0, 0.0));
+
+        // Facing south, pushing north means pushing backwards
+        let reversed = Quat::from_rotation_z(180f32.to_radians());
+        assert_close(
+            trim_to_body(trim, Some(reversed)),
+            Vec3A::new(0.0, -5.0, 0.0),
+        );
+
+        // Rotating back out gives the world frame trim again
+        let tilted = Quat::from_euler(glam::EulerRot::ZXY, 0.7, 0.2, -0.4);
+        assert_close(tilted * trim_to_body(trim, Some(tilted)), trim);
+    }
+
+    #[test]
+    fn trim_without_orientation_stays_in_body_frame() {
+        let trim = Vec3A::new(1.0, -2.0, 0.5);
+        assert_close(trim_to_body(trim, None), trim);
+    }
+
+    #[test]
+    fn trims_are_checked() {
+        let trim = Vec3A::new(10.0, -20.0, 5.0);
+        assert_eq!(checked_trim(trim, 50.0), Some(trim));
+
+        // Past the limit each axis is held to it
+        assert_eq!(
+            checked_trim(Vec3A::new(80.0, -500.0, 5.0), 50.0),
+            Some(Vec3A::new(50.0, -50.0, 5.0))
+        );
+
+        assert_eq!(checked_trim(Vec3A::new(f32::NAN, 0.0, 0.0), 50.0), None);
+        assert_eq!(
+            checked_trim(Vec3A::new(0.0, f32::INFINITY, 0.0), 50.0),
+            None
+        );
+    }
+}
@@ -2,7 +2,8 @@ use bevy::prelude::*;
 use common::{
     bundles::MovementContributionBundle,
     components::{
-        Armed, Depth, DepthTarget, MovementContribution, Orientation, PidConfig, PidResult, RobotId,
+        Armed, Depth, DepthEstimate, DepthTarget, MovementContribution, Orientation, PidConfig,
+        PidResult, RobotId,
     },
     ecs_sync::Replicate,
     types::{units::Meters, utils::PidController},
@@ -10,14 +11,14 @@ use common::{
 use glam::Vec3A;
 use motor_math::Movement;
 
-use crate::plugins::core::robot::LocalRobot;
+use crate::plugins::{actuators::thruster::AccumulateMovementsSet, core::robot::LocalRobot};
 
 pub struct DepthHoldPlugin;
 
 impl Plugin for DepthHoldPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, setup_depth_hold)
-            .add_systems(Update, depth_hold_system);
+            .add_systems(Update, depth_hold_system.before(AccumulateMovementsSet));
     }
 }
 
@@ -28,7 +29,7 @@ fn setup_depth_hold(mut cmds: Commands, robot: Res<LocalRobot>) {
     let entity = cmds
         .spawn((
             MovementContributionBundle {
-                name: Name::new("Depth Hold"),
+                name: Name::new("Depth Hold Controller"),
                 contribution: MovementContribution(Movement::default()),
                 robot: RobotId(robot.net_id),
             },
@@ -44,6 +45,7 @@ fn setup_depth_hold(mut cmds: Commands, robot: Res<LocalRobot>) {
             Replicate,
         ))
         .id();
+    cmds.entity(robot.entity).add_child(entity);
 
     cmds.insert_resource(DepthHoldState(entity, PidController::default()));
 }
@@ -53,33 +55,55 @@ fn depth_hold_system(
     mut cmds: Commands,
     robot: Res<LocalRobot>,
     mut state: ResMut<DepthHoldState>,
-    robot_query: Query<(&Armed, &Depth, &DepthTarget, &Orientation)>,
-    entity_query: Query<&PidConfig>,
+    robot_query: Query<(
+        &Armed,
+        &Depth,
+        Option<&DepthEstimate>,
+        &DepthTarget,
+        &Orientation,
+    )>,
+    mut entity_query: Query<(&PidConfig, &mut MovementContribution)>,
     time: Res<Time<Real>>,
 ) {
     let robot = robot_query.get(robot.entity);
-    let pid_config = entity_query.get(state.0).unwrap();
+    let (pid_config, mut contribution) = entity_query.get_mut(state.0).unwrap();
 
-    if let Ok((&Armed::Armed, depth, depth_target, orientation)) = robot {
-        let depth_error = depth_target.0 - depth.0.depth;
+    if let Ok((&Armed::Armed, depth, estimate, depth_target, orientation)) = robot {
         let depth_td = depth_target.0 - last_target.unwrap_or(depth_target.0);
 
         let pid = &mut state.1;
         // Depth increases as Z decreases, flip the sign
-        let res = pid.update(-depth_error.0, -depth_td.0, pid_config, time.delta());
+        let res = match estimate {
+            // The estimate's speed is measured, target changes are left to the td term
+            Some(estimate) => {
+                let depth_error = depth_target.0 - estimate.depth;
+                pid.update_with_rate(
+                    -depth_error.0,
+                    estimate.velocity,
+                    -depth_td.0,
+                    pid_config,
+                    time.delta(),
+                )
+            }
+            None => {
+                let depth_error = depth_target.0 - depth.0.depth;
+                pid.update(-depth_error.0, -depth_td.0, pid_config, time.delta())
+            }
+        };
 
-        let correction = orientation.0.inverse() * Vec3A::Z * res.correction;
+        let correction = orientation.rotation.inverse() * Vec3A::Z * res.correction;
         let movement = Movement {
             force: correction,
             torque: Vec3A::ZERO,
         };
 
-        cmds.entity(state.0)
-            .insert((MovementContribution(movement), res));
+        // Written in place so the thrusters see it this frame
+        contribution.set_if_neq(MovementContribution(movement));
+        cmds.entity(state.0).insert(res);
         *last_target = Some(depth_target.0);
     } else {
-        cmds.entity(state.0)
-            .remove::<(MovementContribution, PidResult)>();
+        contribution.set_if_neq(MovementContribution(Movement::default()));
+        cmds.entity(state.0).remove::<PidResult>();
         *last_target = None;
     }
 }
@@ -3,22 +3,32 @@ use std::{
     iter::{self, zip},
     sync::Arc,
     thread,
+    time::Duration,
 };
 
 use anyhow::Context;
 use bevy::{app::AppExit, prelude::*, utils::HashMap};
 use common::{
-    components::{PwmChannel, PwmSignal, RobotId, RobotStatus},
+    components::{
+        DepthTarget, Leak, LedBrightness, LedMode, OrientationTarget, PowerMode, PwmChannel,
+        PwmSignal, RobotId, RobotStatus,
+    },
     error::{self, ErrorEvent, Errors},
+    shutdown::{self, AppShutdownSet, SpawnWorkerExt, WorkerThread},
 };
 use crossbeam::channel::{self, Sender};
 use rgb::RGB8;
-use rppal::gpio::{Bias, Gpio, IoPin, Mode};
 use tracing::{span, Level};
 
 use crate::{
-    peripheral::neopixel::{Neopixel, NeopixelBuffer},
-    plugins::core::robot::LocalRobotMarker,
+    peripheral::{
+        hal::{self, pi::PiHal, Bias, GpioPin, Hal, PinMode},
+        neopixel::{self, Neopixel, NeopixelBuffer},
+    },
+    plugins::core::{
+        robot::{LocalRobot, LocalRobotMarker},
+        schedule::run_every,
+    },
 };
 
 pub struct LedPlugin;
@@ -26,30 +36,63 @@ pub struct LedPlugin;
 impl Plugin for LedPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, start_leds.pipe(error::handle_errors))
-            .add_systems(Update, update_leds.run_if(resource_exists::<LedChannels>))
+            .add_systems(
+                Update,
+                (update_leds.run_if(run_every(LED_FRAME)), flash_errors)
+                    .chain()
+                    .run_if(resource_exists::<LedChannels>),
+            )
             .add_systems(
                 PostUpdate,
-                write_state.run_if(resource_exists::<LedChannels>),
+                write_state.run_if(
+                    resource_exists::<LedChannels>.and_then(resource_changed::<LedChannels>),
+                ),
             )
-            .add_systems(Last, shutdown.run_if(resource_exists::<LedChannels>));
+            .add_systems(
+                Last,
+                shutdown
+                    .in_set(AppShutdownSet::Threads)
+                    .run_if(resource_exists::<LedChannels>),
+            );
     }
 }
 
 #[derive(Resource)]
-struct LedChannels(Sender<LedUpdate>, Arc<NeopixelBuffer>, [LedState; 3]);
-
-struct Leds([IoPin; 3]);
-
-impl Drop for Leds {
+struct LedChannels(
+    Sender<LedUpdate>,
+    Arc<NeopixelBuffer>,
+    [LedState; 3],
+    Option<WorkerThread<()>>,
+);
+
+const DEFAULT_BRIGHTNESS: f32 = 0.5;
+/// Brightest the neopixels get in low power
+const LOW_POWER_BRIGHTNESS: f32 = 0.15;
+/// Brightest the neopixels get in critical power
+const CRITICAL_BRIGHTNESS: f32 = 0.05;
+/// How often the LED colors are recomputed, fast enough for the fault strobe
+const LED_FRAME: Duration = Duration::from_millis(20);
+
+/// Period of the strobe shown on a leak, in seconds
+const FAULT_STROBE_PERIOD: f32 = 0.1;
+/// Period of the double blink shown without a surface peer, in seconds
+const NO_PEER_PERIOD: f32 = 2.0;
+/// Length of each of the double blink's flashes and of the gap between them, in seconds
+const NO_PEER_FLASH: f32 = 0.15;
+/// Period of the blue pulse shown while holding depth or orientation, in seconds
+const HOLD_PULSE_PERIOD: f32 = 1.0;
+
+struct Leds<P: GpioPin>([P; 3]);
+
+impl<P: GpioPin> Drop for Leds<P> {
     fn drop(&mut self) {
         for led in &mut self.0 {
-            led.set_mode(Mode::Input);
+            led.set_mode(PinMode::Input);
             led.set_bias(Bias::PullDown);
             led.set_reset_on_drop(false);
         }
 
-        self.0[2].set_mode(Mode::Output);
-        self.0[2].set_low();
+        self.0[2].set_mode(PinMode::Output(hal::Level::Low));
     }
 }
 
@@ -59,7 +102,7 @@ enum LedUpdate {
     Shutdown,
 }
 
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
 enum LedState {
     On,
     Dim,
@@ -74,37 +117,68 @@ enum LedType {
     Side(u8),
 }
 
-fn start_leds(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Result<()> {
-    let (tx_data, rx_data) = channel::bounded(30);
+/// The parts of the robot's state shown on the status LEDs
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+struct StatusInputs {
+    /// A surface peer is connected
+    peer: bool,
+    armed: bool,
+    /// Depth or orientation hold is active
+    hold: bool,
+    /// A leak was detected
+    ///
+    /// Losing the surface is the only failsafe, it disarms the robot and shows as no peer
+    fault: bool,
+}
 
-    let gpio = Gpio::new().context("Open GPIO")?;
+impl StatusInputs {
+    fn from_components(
+        status: RobotStatus,
+        depth_target: bool,
+        orientation_target: bool,
+        leak: Option<Leak>,
+    ) -> Self {
+        Self {
+            peer: status != RobotStatus::NoPeer,
+            armed: status == RobotStatus::Armed,
+            hold: depth_target || orientation_target,
+            fault: leak.is_some_and(|it| it.0),
+        }
+    }
+}
+
+fn start_leds(
+    mut cmds: Commands,
+    errors: Res<Errors>,
+    robot: Res<LocalRobot>,
+) -> anyhow::Result<()> {
+    let (tx_data, rx_data) = channel::bounded(30);
 
     let mut neopixel = Neopixel::new(
+        &PiHal,
         80,
-        Neopixel::SPI_BUS,
-        Neopixel::SPI_SELECT,
-        Neopixel::SPI_CLOCK,
+        neopixel::SPI_BUS,
+        neopixel::SPI_SELECT,
+        neopixel::SPI_CLOCK,
     )
     .context("Open Neopixel")?;
 
+    // The LEDs are active low, start them off
+    let off = PinMode::Output(hal::Level::High);
     // Green
-    let led_1 = gpio.get(24).context("Open led 1")?.into_io(Mode::Output);
+    let led_1 = PiHal.pin(24, off).context("Open led 1")?;
     // Blue
-    let led_2 = gpio.get(25).context("Open led 2")?.into_io(Mode::Output);
+    let led_2 = PiHal.pin(25, off).context("Open led 2")?;
     // Red
-    let led_3 = gpio.get(11).context("Open led 3")?.into_io(Mode::Output);
+    let led_3 = PiHal.pin(11, off).context("Open led 3")?;
     let mut leds = Leds([led_1, led_2, led_3]);
 
-    cmds.insert_resource(LedChannels(
-        tx_data,
-        neopixel.buffer.clone().into(),
-        [LedState::default(); 3],
-    ));
+    let buffer = neopixel.buffer.clone().into();
 
     let errors = errors.0.clone();
-    thread::Builder::new()
+    let led_thread = thread::Builder::new()
         .name("LED Thread".to_owned())
-        .spawn(move || {
+        .spawn_worker(move || {
             let _span = span!(Level::INFO, "LED Thread").entered();
 
             for event in rx_data {
@@ -122,16 +196,14 @@ fn start_leds(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Result<()> {
                         for (led, state) in zip(&mut leds.0, states) {
                             match state {
                                 LedState::On => {
-                                    led.set_mode(Mode::Output);
-                                    led.set_low();
+                                    GpioPin::set_mode(led, PinMode::Output(hal::Level::Low));
                                 }
                                 LedState::Dim => {
-                                    led.set_mode(Mode::Input);
-                                    led.set_bias(Bias::PullDown);
+                                    GpioPin::set_mode(led, PinMode::Input);
+                                    GpioPin::set_bias(led, Bias::PullDown);
                                 }
                                 LedState::Off => {
-                                    led.set_mode(Mode::Output);
-                                    led.set_high();
+                                    GpioPin::set_mode(led, PinMode::Output(hal::Level::High));
                                 }
                             }
                         }
@@ -142,118 +214,253 @@ fn start_leds(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Result<()> {
         })
         .context("Spawn thread")?;
 
+    cmds.insert_resource(LedChannels(
+        tx_data,
+        buffer,
+        [LedState::default(); 3],
+        Some(led_thread),
+    ));
+
+    cmds.entity(robot.entity)
+        .insert((LedMode::default(), LedBrightness(DEFAULT_BRIGHTNESS)));
+
     Ok(())
 }
 
+type LedSources<'a> = (
+    &'a RobotStatus,
+    &'a RobotId,
+    Option<&'a LedMode>,
+    Option<&'a LedBrightness>,
+    Has<DepthTarget>,
+    Has<OrientationTarget>,
+    Option<&'a Leak>,
+    Option<&'a PowerMode>,
+);
+
 fn update_leds(
     mut leds: ResMut<LedChannels>,
-    robot: Query<(&RobotStatus, &RobotId), With<LocalRobotMarker>>,
+    robot: Query<LedSources<'static>, With<LocalRobotMarker>>,
     thrusters: Query<(&PwmChannel, &PwmSignal, &RobotId)>,
     time: Res<Time<Real>>,
-    mut errors: EventReader<ErrorEvent>,
 ) {
     let now = time.elapsed_seconds_wrapped();
 
-    let (status, id) = robot.single();
+    let (status, id, mode, brightness, depth_target, orientation_target, leak, power) =
+        robot.single();
+    let status =
+        StatusInputs::from_components(*status, depth_target, orientation_target, leak.copied());
     let thrusters = thrusters
         .iter()
         .filter(|(_, _, robot)| **robot == *id)
         .map(|(&channel, &signal, _)| (channel, signal))
         .collect::<HashMap<_, _>>();
 
-    let brightness = 0.5;
+    let mode = mode.copied().unwrap_or_default();
+    let brightness = brightness.map(|it| it.0).unwrap_or(DEFAULT_BRIGHTNESS);
+    let power = power.copied().unwrap_or_default();
+    let brightness = power_brightness(brightness, power);
 
     let colors = neopixels().map(|led| {
-        match led {
-            // Choose color besed on ROV status
-            LedType::Status => {
-                // TODO(high): Figure out what we want to display here
-                // RGB8::default()
-                RGB8::new(255, 0, 0)
-            }
-            // Choose color based on thruster speed
-            LedType::Thruster(id) => {
-                let signal = thrusters.get(&PwmChannel(id));
-
-                if let Some(signal) = signal {
-                    let micros = signal.0.as_micros();
-
-                    if micros >= 1500 {
-                        // Forward
-                        let green = (micros as u32 - 1500) * 255 / 400;
-                        RGB8::new(0, green as u8, 0)
-                    } else {
-                        // Backward
-                        let red = (1500 - micros as u32) * 255 / 400;
-                        RGB8::new(red as u8, 0, 0)
-                    }
-                } else {
-                    RGB8::new(0, 0, 127)
-                }
-            }
-            // Rotate
-            LedType::Circle(id) => {
-                let red = (((now + 0.0 * TAU / 3.0 + TAU * (id as f32 / 11.0)).sin() / 2.0 + 0.5)
-                    * 255.0
-                    * brightness) as u8;
-                let green = (((now + 1.0 * TAU / 3.0 + TAU * (id as f32 / 11.0)).sin() / 2.0 + 0.5)
-                    * 255.0) as u8;
-                let blue = (((now + 2.0 * TAU / 3.0 + TAU * (id as f32 / 11.0)).sin() / 2.0 + 0.5)
-                    * 255.0
-                    * brightness) as u8;
-
-                RGB8::new(red, green, blue)
-            }
-            LedType::Side(id) => {
-                let offset = 0.1;
-
-                let red = (((now + 0.0 * TAU / 3.0 + (id as f32 * offset)).sin() / 2.0 + 0.5)
-                    * 255.0
-                    * brightness) as u8;
-                let green = (((now + 1.0 * TAU / 3.0 + (id as f32 * offset)).sin() / 2.0 + 0.5)
-                    * 255.0
-                    * brightness) as u8;
-                let blue = (((now + 2.0 * TAU / 3.0 + (id as f32 * offset)).sin() / 2.0 + 0.5)
-                    * 255.0
-                    * brightness) as u8;
-
-                RGB8::new(red, green, blue)
-            }
+        if power != PowerMode::Normal && is_rainbow(mode, &led) {
+            return RGB8::default();
         }
+
+        let color = led_color(mode, &led, status, now, |channel| {
+            thrusters.get(&PwmChannel(channel)).map(|it| it.0)
+        });
+
+        dim(color, brightness)
     });
 
     let neopixel = Arc::make_mut(&mut leds.1);
     neopixel.set(.., colors, true);
 
-    // Blue for connected
-    // Green for armed
-    // Red on error
+    leds.2 = status_leds(status, now);
+}
 
-    leds.2 = [LedState::Dim; 3];
-    match status {
-        RobotStatus::NoPeer => {
-            if (now * TAU).sin() < 0.0 {
-                leds.2[1] = LedState::Off;
+/// Errors light the fault LED until the next LED frame
+///
+/// Runs every tick, errors sent between LED frames would be missed otherwise
+fn flash_errors(mut leds: ResMut<LedChannels>, mut errors: EventReader<ErrorEvent>) {
+    if !errors.is_empty() {
+        leds.2[2] = LedState::On;
+        errors.clear();
+    }
+}
+
+/// Color of `led` in `mode`, `now` is in seconds
+fn led_color(
+    mode: LedMode,
+    led: &LedType,
+    status: StatusInputs,
+    now: f32,
+    thruster: impl Fn(u8) -> Option<Duration>,
+) -> RGB8 {
+    match mode {
+        LedMode::Status => match *led {
+            LedType::Status => status_color(status, now),
+            LedType::Thruster(id) => thruster_color(thruster(id)),
+            // Rotate
+            LedType::Circle(id) => rainbow(now + TAU * (id as f32 / 11.0)),
+            LedType::Side(id) => rainbow(now + id as f32 * 0.1),
+        },
+        LedMode::ThrusterViz => match *led {
+            LedType::Thruster(id) => thruster_color(thruster(id)),
+            _ => RGB8::default(),
+        },
+        LedMode::Solid(color) => RGB8::new(color.r, color.g, color.b),
+        LedMode::Strobe { color, period } => {
+            let period = period.as_secs_f32();
+
+            if period > 0.0 && now.rem_euclid(period) < period / 2.0 {
+                RGB8::new(color.r, color.g, color.b)
+            } else {
+                RGB8::default()
             }
         }
-        RobotStatus::Disarmed => {
-            leds.2[1] = LedState::On;
-        }
-        RobotStatus::Armed => {
-            leds.2[0] = LedState::On;
-            leds.2[1] = LedState::On;
-        }
+        LedMode::Rainbow => match *led {
+            LedType::Circle(id) => rainbow(now + TAU * (id as f32 / 11.0)),
+            LedType::Side(id) => rainbow(now + id as f32 * 0.1),
+            LedType::Thruster(id) => rainbow(now + id as f32 * 0.1),
+            LedType::Status => rainbow(now),
+        },
+        LedMode::Off => RGB8::default(),
     }
+}
 
-    if !errors.is_empty() {
-        leds.2[2] = LedState::On;
-        errors.clear();
+/// Whether `led` only shows the rainbow in `mode`, these are turned off to save power
+fn is_rainbow(mode: LedMode, led: &LedType) -> bool {
+    match mode {
+        LedMode::Status => matches!(led, LedType::Circle(_) | LedType::Side(_)),
+        LedMode::Rainbow => true,
+        _ => false,
     }
 }
 
-fn shutdown(channels: Res<LedChannels>, mut exit: EventReader<AppExit>) {
+/// `brightness` capped to what `power` allows
+fn power_brightness(brightness: f32, power: PowerMode) -> f32 {
+    match power {
+        PowerMode::Normal => brightness,
+        PowerMode::LowPower => brightness.min(LOW_POWER_BRIGHTNESS),
+        PowerMode::Critical => brightness.min(CRITICAL_BRIGHTNESS),
+    }
+}
+
+/// Color of the status pixels
+///
+/// Red when disarmed and green when armed, with a blue pulse while holding depth or orientation.
+/// Without a surface peer it double blinks white every two seconds. A leak overrides everything
+/// with a fast red strobe.
+fn status_color(status: StatusInputs, now: f32) -> RGB8 {
+    if status.fault {
+        return if fault_strobe(now) {
+            RGB8::new(255, 0, 0)
+        } else {
+            RGB8::default()
+        };
+    }
+
+    if !status.peer && no_peer_flash(now) {
+        return RGB8::new(255, 255, 255);
+    }
+
+    let mut color = if status.armed {
+        RGB8::new(0, 255, 0)
+    } else {
+        RGB8::new(255, 0, 0)
+    };
+
+    if status.hold {
+        let pulse = (now / HOLD_PULSE_PERIOD * TAU).sin() / 2.0 + 0.5;
+        color.b = (pulse * 255.0) as u8;
+    }
+
+    color
+}
+
+/// States of the green, blue and red discrete LEDs
+///
+/// Green while armed, blue while a surface peer is connected and red on a leak. The blue LED
+/// double blinks like the status pixels without a peer and the red LED strobes with them.
+fn status_leds(status: StatusInputs, now: f32) -> [LedState; 3] {
+    let armed = if status.armed {
+        LedState::On
+    } else {
+        LedState::Dim
+    };
+    let link = if status.peer || no_peer_flash(now) {
+        LedState::On
+    } else {
+        LedState::Off
+    };
+    let fault = if !status.fault {
+        LedState::Dim
+    } else if fault_strobe(now) {
+        LedState::On
+    } else {
+        LedState::Off
+    };
+
+    [armed, link, fault]
+}
+
+/// Whether the fault strobe is lit at `now`
+fn fault_strobe(now: f32) -> bool {
+    now.rem_euclid(FAULT_STROBE_PERIOD) < FAULT_STROBE_PERIOD / 2.0
+}
+
+/// Whether one of the no peer double blink's flashes is lit at `now`
+fn no_peer_flash(now: f32) -> bool {
+    let phase = now.rem_euclid(NO_PEER_PERIOD);
+
+    phase < NO_PEER_FLASH || (2.0 * NO_PEER_FLASH..3.0 * NO_PEER_FLASH).contains(&phase)
+}
+
+/// Green for forward, red for backward, blue when the thruster is unknown
+fn thruster_color(signal: Option<Duration>) -> RGB8 {
+    let Some(signal) = signal else {
+        return RGB8::new(0, 0, 127);
+    };
+
+    let micros = (signal.as_micros() as u32).clamp(1100, 1900);
+
+    if micros >= 1500 {
+        // Forward
+        let green = (micros - 1500) * 255 / 400;
+        RGB8::new(0, green as u8, 0)
+    } else {
+        // Backward
+        let red = (1500 - micros) * 255 / 400;
+        RGB8::new(red as u8, 0, 0)
+    }
+}
+
+/// Each channel follows a sine wave a third of a turn apart from the others
+fn rainbow(phase: f32) -> RGB8 {
+    let channel = |offset: f32| (((phase + offset * TAU / 3.0).sin() / 2.0 + 0.5) * 255.0) as u8;
+
+    RGB8::new(channel(0.0), channel(1.0), channel(2.0))
+}
+
+fn dim(color: RGB8, brightness: f32) -> RGB8 {
+    let brightness = brightness.clamp(0.0, 1.0);
+
+    let dim = |it: u8| (it as f32 * brightness) as u8;
+
+    RGB8::new(dim(color.r), dim(color.g), dim(color.b))
+}
+
+fn shutdown(mut channels: ResMut<LedChannels>, mut exit: EventReader<AppExit>) {
     for _event in exit.read() {
         let _ = channels.0.send(LedUpdate::Shutdown);
+
+        // The final led state is set when the thread drops its pins
+        if let Some(led_thread) = channels.3.take() {
+            if !shutdown::join_timeout(led_thread, shutdown::JOIN_TIMEOUT) {
+                warn!("LED thread did not shut down in time");
+            }
+        }
     }
 }
 
@@ -267,8 +474,7 @@ fn neopixels() -> impl Iterator<Item = LedType> {
         #[coroutine]
         || {
             for board in 0..2 {
-                yield LedType::Side(0);
-                // yield LedType::Status;
+                yield LedType::Status;
 
                 for led in 0..11 {
                     yield LedType::Circle(led);
@@ -289,3 +495,288 @@ fn neopixels() -> impl Iterator<Item = LedType> {
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{f32::consts::TAU, time::Duration};
+
+    use common::{
+        components::{LedMode, PowerMode},
+        types::hw::Rgb8,
+    };
+    use rgb::RGB8;
+
+    use super::{
+        dim, is_rainbow, led_color, power_brightness, rainbow, status_color, status_leds,
+        thruster_color, LedState, LedType, StatusInputs, CRITICAL_BRIGHTNESS, LOW_POWER_BRIGHTNESS,
+    };
+
+    const ORANGE: Rgb8 = Rgb8::new(255, 128, 0);
+    const IDLE: StatusInputs = StatusInputs {
+        peer: true,
+        armed: false,
+        hold: false,
+        fault: false,
+    };
+
+    const RED: RGB8 = RGB8::new(255, 0, 0);
+    const GREEN: RGB8 = RGB8::new(0, 255, 0);
+    const WHITE: RGB8 = RGB8::new(255, 255, 255);
+    const DARK: RGB8 = RGB8::new(0, 0, 0);
+
+    fn no_thrusters(_: u8) -> Option<Duration> {
+        None
+    }
+
+    #[test]
+    fn thruster_colors() {
+        let micros = |it| Some(Duration::from_micros(it));
+
+        assert_eq!(thruster_color(micros(1500)), RGB8::new(0, 0, 0));
+        assert_eq!(thruster_color(micros(1900)), RGB8::new(0, 255, 0));
+        assert_eq!(thruster_color(micros(1100)), RGB8::new(255, 0, 0));
+        // Out of range signals saturate instead of wrapping
+        assert_eq!(thruster_color(micros(2500)), RGB8::new(0, 255, 0));
+        assert_eq!(thruster_color(None), RGB8::new(0, 0, 127));
+    }
+
+    #[test]
+    fn rainbow_phases() {
+        assert_eq!(rainbow(0.0), RGB8::new(127, 237, 17));
+        assert_eq!(rainbow(TAU / 4.0), RGB8::new(255, 63, 63));
+        assert_eq!(rainbow(TAU), rainbow(0.0));
+    }
+
+    #[test]
+    fn solid_and_off() {
+        for led in [LedType::Status, LedType::Circle(3), LedType::Thruster(1)] {
+            assert_eq!(
+                led_color(LedMode::Solid(ORANGE), &led, IDLE, 12.0, no_thrusters),
+                RGB8::new(255, 128, 0)
+            );
+            assert_eq!(
+                led_color(LedMode::Off, &led, IDLE, 12.0, no_thrusters),
+                RGB8::default()
+            );
+        }
+    }
+
+    #[test]
+    fn strobe_timing() {
+        let mode = LedMode::Strobe {
+            color: ORANGE,
+            period: Duration::from_millis(500),
+        };
+        let at = |now| led_color(mode, &LedType::Side(2), IDLE, now, no_thrusters);
+
+        assert_eq!(at(0.0), RGB8::new(255, 128, 0));
+        assert_eq!(at(0.2), RGB8::new(255, 128, 0));
+        assert_eq!(at(0.3), RGB8::default());
+        assert_eq!(at(10.1), RGB8::new(255, 128, 0));
+        assert_eq!(at(10.4), RGB8::default());
+
+        // A zero period never lights up instead of dividing by zero
+        let mode = LedMode::Strobe {
+            color: ORANGE,
+            period: Duration::ZERO,
+        };
+        assert_eq!(
+            led_color(mode, &LedType::Side(2), IDLE, 1.0, no_thrusters),
+            RGB8::default()
+        );
+    }
+
+    #[test]
+    fn thruster_viz_only_lights_thrusters() {
+        let thrusters = |id| (id == 2).then_some(Duration::from_micros(1900));
+
+        assert_eq!(
+            led_color(
+                LedMode::ThrusterViz,
+                &LedType::Thruster(2),
+                IDLE,
+                0.0,
+                thrusters
+            ),
+            RGB8::new(0, 255, 0)
+        );
+        assert_eq!(
+            led_color(
+                LedMode::ThrusterViz,
+                &LedType::Circle(2),
+                IDLE,
+                0.0,
+                thrusters
+            ),
+            RGB8::default()
+        );
+
+        // The default mode shows thrusters alongside the rainbow
+        assert_eq!(
+            led_color(LedMode::Status, &LedType::Thruster(2), IDLE, 0.0, thrusters),
+            RGB8::new(0, 255, 0)
+        );
+        assert_eq!(
+            led_color(LedMode::Status, &LedType::Side(0), IDLE, 0.0, thrusters),
+            rainbow(0.0)
+        );
+    }
+
+    #[test]
+    fn brightness_is_clamped() {
+        let color = RGB8::new(200, 100, 50);
+
+        assert_eq!(dim(color, 0.5), RGB8::new(100, 50, 25));
+        assert_eq!(dim(color, 2.0), color);
+        assert_eq!(dim(color, -1.0), RGB8::default());
+    }
+
+    /// Every combination of the status inputs
+    fn all_statuses() -> impl Iterator<Item = StatusInputs> {
+        (0..16).map(|bits| StatusInputs {
+            peer: bits & 1 != 0,
+            armed: bits & 2 != 0,
+            hold: bits & 4 != 0,
+            fault: bits & 8 != 0,
+        })
+    }
+
+    #[test]
+    fn status_from_components() {
+        use common::components::{Leak, RobotStatus};
+
+        let status = StatusInputs::from_components(RobotStatus::NoPeer, false, false, None);
+        assert_eq!(status, StatusInputs::default());
+
+        let status =
+            StatusInputs::from_components(RobotStatus::Armed, false, true, Some(Leak(false)));
+        assert_eq!(
+            status,
+            StatusInputs {
+                peer: true,
+                armed: true,
+                hold: true,
+                fault: false,
+            }
+        );
+
+        let status =
+            StatusInputs::from_components(RobotStatus::Disarmed, true, false, Some(Leak(true)));
+        assert_eq!(
+            status,
+            StatusInputs {
+                peer: true,
+                armed: false,
+                hold: true,
+                fault: true,
+            }
+        );
+    }
+
+    #[test]
+    fn status_colors() {
+        for status in all_statuses() {
+            // Sampled outside of the double blink with the hold pulse at its trough and peak
+            for (now, pulse) in [(0.75, 0), (1.25, 255)] {
+                let color = status_color(status, now);
+                let base = if status.armed { GREEN } else { RED };
+
+                if status.fault {
+                    // The strobe takes over
+                    assert!(color == RED || color == DARK, "{status:?} at {now}");
+                } else {
+                    assert_eq!((color.r, color.g), (base.r, base.g), "{status:?} at {now}");
+
+                    let blue = if status.hold { pulse } else { 0 };
+                    assert!(color.b.abs_diff(blue) <= 1, "{status:?} at {now}");
+                }
+            }
+
+            // Fast strobe
+            if status.fault {
+                assert_eq!(status_color(status, 0.72), RED, "{status:?}");
+                assert_eq!(status_color(status, 0.87), DARK, "{status:?}");
+            }
+
+            // Double blink every two seconds
+            for now in [0.05, 0.35, 2.05, 4.35] {
+                let color = status_color(status, now);
+
+                if status.fault {
+                    assert!(color == RED || color == DARK, "{status:?} at {now}");
+                } else if !status.peer {
+                    assert_eq!(color, WHITE, "{status:?} at {now}");
+                } else {
+                    assert_ne!(color, WHITE, "{status:?} at {now}");
+                }
+            }
+            if !status.fault && !status.peer {
+                assert_ne!(status_color(status, 0.2), WHITE, "{status:?}");
+                assert_ne!(status_color(status, 0.5), WHITE, "{status:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn status_discrete_leds() {
+        use LedState::{Dim, Off, On};
+
+        for status in all_statuses() {
+            let [armed, link, fault] = status_leds(status, 0.72);
+
+            assert_eq!(armed, if status.armed { On } else { Dim }, "{status:?}");
+            assert_eq!(link, if status.peer { On } else { Off }, "{status:?}");
+            assert_eq!(fault, if status.fault { On } else { Dim }, "{status:?}");
+
+            // The link LED double blinks with the status pixels
+            let [_, link, _] = status_leds(status, 0.05);
+            assert_eq!(link, On, "{status:?}");
+            let [_, link, _] = status_leds(status, 0.2);
+            assert_eq!(link, if status.peer { On } else { Off }, "{status:?}");
+
+            // The fault LED strobes
+            let [_, _, fault] = status_leds(status, 0.87);
+            assert_eq!(fault, if status.fault { Off } else { Dim }, "{status:?}");
+        }
+    }
+
+    #[test]
+    fn status_pixel_follows_mode() {
+        let armed = StatusInputs {
+            armed: true,
+            ..IDLE
+        };
+
+        assert_eq!(
+            led_color(LedMode::Status, &LedType::Status, armed, 0.72, no_thrusters),
+            GREEN
+        );
+        assert_eq!(
+            led_color(LedMode::Off, &LedType::Status, armed, 0.75, no_thrusters),
+            DARK
+        );
+    }
+
+    #[test]
+    fn power_modes_dim_and_drop_the_rainbow() {
+        assert_eq!(power_brightness(0.5, PowerMode::Normal), 0.5);
+        assert_eq!(
+            power_brightness(0.5, PowerMode::LowPower),
+            LOW_POWER_BRIGHTNESS
+        );
+        assert_eq!(
+            power_brightness(0.5, PowerMode::Critical),
+            CRITICAL_BRIGHTNESS
+        );
+        // Already dimmer than the cap
+        assert_eq!(power_brightness(0.01, PowerMode::LowPower), 0.01);
+
+        assert!(is_rainbow(LedMode::Status, &LedType::Circle(3)));
+        assert!(is_rainbow(LedMode::Status, &LedType::Side(3)));
+        assert!(is_rainbow(LedMode::Rainbow, &LedType::Status));
+        // Indicators stay lit
+        assert!(!is_rainbow(LedMode::Status, &LedType::Status));
+        assert!(!is_rainbow(LedMode::Status, &LedType::Thruster(1)));
+        assert!(!is_rainbow(LedMode::Solid(ORANGE), &LedType::Side(3)));
+    }
+}
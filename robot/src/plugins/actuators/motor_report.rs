@@ -0,0 +1,479 @@
+//! Checks the motor config the robot was started with and builds the [`MotorConfigReport`]
+//!
+//! Problems that would make the robot drive wrong are errors and keep it from arming, the rest
+//! are warnings. The checks only look at the config, the motor data and the axis maximums so they
+//! can run before anything is driven.
+
+use std::fmt::Write;
+
+use ahash::HashMap;
+use common::{
+    components::{AxisReport, MotorConfigReport, MotorReport},
+    types::{hw::PwmChannelId, units::Newtons},
+};
+use glam::Vec3A;
+use motor_math::{
+    motor_preformance::{Interpolation, MotorData},
+    solve::reverse::Axis,
+    ErasedMotorId, Motor, MotorConfig,
+};
+
+/// How far a motor orientation's length can be from 1 before it's normalized
+const ORIENTATION_TOLERANCE: f32 = 0.01;
+
+/// Condition number of the required axes above which some movements take far more thrust than
+/// others
+const MAX_CONDITION_NUMBER: f32 = 50.0;
+
+/// Authority below which an axis can't be driven
+const MIN_AUTHORITY: f32 = 0.5;
+
+/// Authority below which driving an axis also moves the robot along others
+const FULL_AUTHORITY: f32 = 0.99;
+
+/// Share of the strongest axis of the same kind below which an axis is close to unachievable
+const WEAK_AXIS_RATIO: f32 = 0.1;
+
+/// PWM the motors are stopped at
+const NEUTRAL_PWM: f32 = 1500.0;
+
+/// A motor from the robot config along with what the surface knows it by
+#[derive(Debug, Clone)]
+pub struct NamedMotor {
+    pub name: String,
+    pub id: ErasedMotorId,
+    pub motor: Motor,
+    pub pwm_channel: PwmChannelId,
+}
+
+/// Normalizes every motor's orientation, warning about the ones that weren't unit vectors
+///
+/// Motors without an orientation can't be fixed and are errors
+pub fn normalize_orientations(motors: &mut [NamedMotor], report: &mut MotorConfigReport) {
+    for NamedMotor { name, motor, .. } in motors {
+        let length = motor.orientation.length();
+
+        if length < ORIENTATION_TOLERANCE {
+            report
+                .errors
+                .push(format!("{name} has no orientation ({})", motor.orientation));
+        } else if (length - 1.0).abs() > ORIENTATION_TOLERANCE {
+            report.warnings.push(format!(
+                "{name} orientation {} has length {length:.3}, normalized",
+                motor.orientation
+            ));
+            motor.orientation /= length;
+        }
+    }
+}
+
+/// Adds the lever arm and orientation of every motor about `center_mass`
+pub fn report_motors(motors: &[NamedMotor], center_mass: Vec3A, report: &mut MotorConfigReport) {
+    report.motors = motors
+        .iter()
+        .map(|it| MotorReport {
+            name: it.name.clone(),
+            pwm_channel: it.pwm_channel,
+            lever_arm: it.motor.position - center_mass,
+            orientation: it.motor.orientation,
+        })
+        .collect();
+}
+
+/// Adds every axis along with how well it can be driven, `maximums` are from
+/// [`motor_math::solve::reverse::axis_maximums`]
+///
+/// Required axes that can't be driven on their own are errors
+pub fn check_axes(
+    motor_config: &MotorConfig<ErasedMotorId>,
+    maximums: &HashMap<Axis, f32>,
+    required: &[Axis],
+    report: &mut MotorConfigReport,
+) {
+    report.axes = Axis::ALL
+        .iter()
+        .map(|&axis| {
+            let authority = motor_config.axis_authority(axis);
+
+            // The solver still finds something along axes it can't reach, it just isn't that axis
+            let maximum = if authority < MIN_AUTHORITY {
+                0.0
+            } else {
+                maximums.get(&axis).copied().unwrap_or_default()
+            };
+
+            AxisReport {
+                name: format!("{axis:?}"),
+                required: required.contains(&axis),
+                maximum: Newtons(maximum),
+                authority,
+            }
+        })
+        .collect();
+
+    report.condition_number = motor_config.condition_number(required);
+
+    let strongest = |torque: bool| {
+        report
+            .axes
+            .iter()
+            .zip(Axis::ALL)
+            .filter(|(_, axis)| is_torque(*axis) == torque)
+            .map(|(it, _)| it.maximum.0)
+            .fold(0.0, f32::max)
+    };
+    let (strongest_force, strongest_torque) = (strongest(false), strongest(true));
+
+    for (axis_report, axis) in report.axes.iter().zip(Axis::ALL) {
+        let AxisReport {
+            name,
+            required,
+            maximum,
+            authority,
+        } = axis_report;
+
+        if !required {
+            continue;
+        }
+
+        if *authority < MIN_AUTHORITY {
+            report
+                .errors
+                .push(format!("Motors can't drive required axis {name}"));
+            continue;
+        }
+
+        if *authority < FULL_AUTHORITY {
+            report.warnings.push(format!(
+                "Driving {name} also moves the robot along other axes, {:.0}% authority",
+                authority * 100.0
+            ));
+        }
+
+        let strongest = if is_torque(axis) {
+            strongest_torque
+        } else {
+            strongest_force
+        };
+        if maximum.0 < strongest * WEAK_AXIS_RATIO {
+            report.warnings.push(format!(
+                "{name} is nearly unachievable, {:.1} against {strongest:.1} on the strongest axis",
+                maximum.0
+            ));
+        }
+    }
+
+    if report.condition_number.is_infinite() {
+        report.errors.push(format!(
+            "Motors can't drive the required axes {required:?} independently"
+        ));
+    } else if report.condition_number > MAX_CONDITION_NUMBER {
+        report.warnings.push(format!(
+            "Required axes are poorly conditioned ({:.1}), some movements take far more thrust",
+            report.condition_number
+        ));
+    }
+}
+
+/// Checks that the motor data needs PWM above neutral for positive thrust and below for negative
+pub fn check_motor_data(motor_data: &MotorData, report: &mut MotorConfigReport) {
+    let probe = motor_data.max_force() / 2.0;
+
+    if probe <= 0.0 {
+        report
+            .errors
+            .push("Motor data doesn't produce any thrust".to_owned());
+        return;
+    }
+
+    let forward = motor_data.lookup_by_force(probe, Interpolation::Lerp).pwm;
+    let reverse = motor_data.lookup_by_force(-probe, Interpolation::Lerp).pwm;
+
+    if forward <= NEUTRAL_PWM {
+        report.errors.push(format!(
+            "Motor data gives {probe:.1}N at {forward:.0}us, expected above {NEUTRAL_PWM:.0}us"
+        ));
+    }
+    if reverse >= NEUTRAL_PWM {
+        report.errors.push(format!(
+            "Motor data gives -{probe:.1}N at {reverse:.0}us, expected below {NEUTRAL_PWM:.0}us"
+        ));
+    }
+}
+
+/// The report as a table for the robot log
+pub fn format_report(report: &MotorConfigReport) -> String {
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "{:<24} {:>4} {:>24} {:>24}",
+        "Motor", "PWM", "Lever arm (m)", "Orientation"
+    );
+    for motor in &report.motors {
+        let _ = writeln!(
+            out,
+            "{:<24} {:>4} {:>24} {:>24}",
+            motor.name,
+            motor.pwm_channel,
+            format_vec(motor.lever_arm),
+            format_vec(motor.orientation)
+        );
+    }
+
+    let _ = writeln!(out);
+    let _ = writeln!(
+        out,
+        "{:<8} {:>8} {:>10} {:>10}",
+        "Axis", "Required", "Maximum", "Authority"
+    );
+    for axis in &report.axes {
+        let _ = writeln!(
+            out,
+            "{:<8} {:>8} {:>10.1} {:>9.0}%",
+            axis.name,
+            if axis.required { "yes" } else { "no" },
+            axis.maximum.0,
+            axis.authority * 100.0
+        );
+    }
+
+    let _ = write!(out, "Condition number: {:.1}", report.condition_number);
+
+    out
+}
+
+fn format_vec(vec: Vec3A) -> String {
+    format!("({:.2}, {:.2}, {:.2})", vec.x, vec.y, vec.z)
+}
+
+fn is_torque(axis: Axis) -> bool {
+    matches!(axis, Axis::XRot | Axis::YRot | Axis::ZRot)
+}
+
+#[cfg(test)]
+mod tests {
+    use ahash::HashMap;
+    use common::components::MotorConfigReport;
+    use glam::{vec3a, Vec3A};
+    use motor_math::{
+        motor_preformance::{self, MotorData, MotorRecord},
+        solve::reverse::Axis,
+        utils::vec_from_angles,
+        x3d::X3dMotorId,
+        Direction, ErasedMotorId, Motor, MotorConfig,
+    };
+
+    use super::{
+        check_axes, check_motor_data, format_report, normalize_orientations, report_motors,
+        NamedMotor,
+    };
+
+    fn named(id: ErasedMotorId, position: Vec3A, orientation: Vec3A) -> NamedMotor {
+        NamedMotor {
+            name: format!("Motor {id}"),
+            id,
+            motor: Motor {
+                position,
+                orientation,
+                direction: Direction::Clockwise,
+            },
+            pwm_channel: id,
+        }
+    }
+
+    fn x3d() -> MotorConfig<ErasedMotorId> {
+        let seed_motor = Motor {
+            position: vec3a(1.0, 1.0, 1.0).normalize(),
+            orientation: vec_from_angles(60.0, 40.0),
+            direction: Direction::Clockwise,
+        };
+
+        MotorConfig::<X3dMotorId>::new(seed_motor, Vec3A::ZERO).erase()
+    }
+
+    /// Three horizontal thrusters, can't heave, roll or pitch
+    fn planar() -> MotorConfig<ErasedMotorId> {
+        let motors = [
+            named(0, vec3a(0.2, 0.2, 0.0), Vec3A::Y),
+            named(1, vec3a(-0.2, 0.2, 0.0), Vec3A::Y),
+            named(2, vec3a(0.0, -0.2, 0.0), Vec3A::X),
+        ];
+
+        MotorConfig::new_raw(motors.map(|it| (it.id, it.motor)), Vec3A::ZERO)
+    }
+
+    fn maximums(value: f32) -> HashMap<Axis, f32> {
+        Axis::ALL.iter().map(|&axis| (axis, value)).collect()
+    }
+
+    #[test]
+    fn orientations_are_normalized() {
+        let mut motors = [
+            named(0, Vec3A::ZERO, Vec3A::X),
+            named(1, Vec3A::ZERO, vec3a(0.0, 2.0, 0.0)),
+            named(2, Vec3A::ZERO, Vec3A::ZERO),
+        ];
+        let mut report = MotorConfigReport::default();
+
+        normalize_orientations(&mut motors, &mut report);
+
+        assert_eq!(motors[0].motor.orientation, Vec3A::X);
+        assert_eq!(motors[1].motor.orientation, Vec3A::Y);
+        assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+        assert!(report.warnings[0].contains("Motor 1"));
+
+        // Nothing to normalize
+        assert_eq!(motors[2].motor.orientation, Vec3A::ZERO);
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.errors[0].contains("Motor 2"));
+    }
+
+    #[test]
+    fn lever_arms_are_from_the_center_of_mass() {
+        let motors = [named(3, vec3a(0.2, 0.1, 0.0), Vec3A::Z)];
+        let mut report = MotorConfigReport::default();
+
+        report_motors(&motors, vec3a(0.0, 0.1, -0.1), &mut report);
+
+        assert_eq!(report.motors.len(), 1);
+        assert_eq!(report.motors[0].name, "Motor 3");
+        assert_eq!(report.motors[0].pwm_channel, 3);
+        assert!(report.motors[0]
+            .lever_arm
+            .abs_diff_eq(vec3a(0.2, 0.0, 0.1), 1e-6));
+    }
+
+    #[test]
+    fn full_configs_pass() {
+        let mut report = MotorConfigReport::default();
+
+        check_axes(&x3d(), &maximums(10.0), &Axis::ALL, &mut report);
+
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report.warnings.is_empty(), "{:?}", report.warnings);
+        assert!(report.condition_number.is_finite());
+        assert_eq!(report.axes.len(), 6);
+        assert!(report.axes.iter().all(|it| it.required));
+        assert!(report.axes.iter().all(|it| it.maximum.0 == 10.0));
+    }
+
+    #[test]
+    fn rank_deficient_required_axes_are_errors() {
+        let mut report = MotorConfigReport::default();
+
+        check_axes(&planar(), &maximums(10.0), &Axis::ALL, &mut report);
+
+        // Heave, roll and pitch plus the condition number
+        assert_eq!(report.errors.len(), 4, "{:?}", report.errors);
+        assert_eq!(report.condition_number, f32::INFINITY);
+
+        let heave = &report.axes[Axis::Z.index()];
+        assert_eq!(heave.maximum.0, 0.0);
+        assert!(heave.authority < 1e-4);
+    }
+
+    #[test]
+    fn unrequired_axes_are_not_errors() {
+        let mut report = MotorConfigReport::default();
+
+        check_axes(
+            &planar(),
+            &maximums(10.0),
+            &[Axis::X, Axis::Y, Axis::ZRot],
+            &mut report,
+        );
+
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report.condition_number.is_finite());
+        assert!(!report.axes[Axis::Z.index()].required);
+    }
+
+    #[test]
+    fn weak_axes_are_warnings() {
+        let mut maximums = maximums(10.0);
+        maximums.insert(Axis::Y, 0.5);
+        // Torque is compared against torque
+        maximums.insert(Axis::XRot, 0.5);
+        maximums.insert(Axis::YRot, 0.5);
+        maximums.insert(Axis::ZRot, 0.5);
+
+        let mut report = MotorConfigReport::default();
+        check_axes(&x3d(), &maximums, &Axis::ALL, &mut report);
+
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(report.warnings.len(), 1, "{:?}", report.warnings);
+        assert!(report.warnings[0].starts_with("Y "));
+    }
+
+    #[test]
+    fn poorly_conditioned_axes_are_warnings() {
+        // The forward thrusters nearly line up, yawing takes a lot of thrust
+        let motors = [
+            named(0, vec3a(0.001, 0.2, 0.0), Vec3A::Y),
+            named(1, vec3a(-0.001, 0.2, 0.0), Vec3A::Y),
+            named(2, vec3a(0.0, -0.2, 0.0), Vec3A::X),
+        ];
+        let motor_config = MotorConfig::new_raw(motors.map(|it| (it.id, it.motor)), Vec3A::ZERO);
+
+        let mut report = MotorConfigReport::default();
+        check_axes(
+            &motor_config,
+            &maximums(10.0),
+            &[Axis::X, Axis::Y, Axis::ZRot],
+            &mut report,
+        );
+
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(report.condition_number > 50.0);
+        assert!(report
+            .warnings
+            .iter()
+            .any(|it| it.contains("poorly conditioned")));
+    }
+
+    #[test]
+    fn motor_data_signs_are_checked() {
+        let mut report = MotorConfigReport::default();
+        check_motor_data(&motor_preformance::fallback_motor_data(), &mut report);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+
+        // Forward and reverse swapped
+        let swapped = MotorData::from(vec![
+            MotorRecord {
+                pwm: 1900.0,
+                force: -5.0,
+                ..Default::default()
+            },
+            MotorRecord {
+                pwm: 1500.0,
+                force: 0.0,
+                ..Default::default()
+            },
+            MotorRecord {
+                pwm: 1100.0,
+                force: 5.0,
+                ..Default::default()
+            },
+        ]);
+
+        let mut report = MotorConfigReport::default();
+        check_motor_data(&swapped, &mut report);
+        assert_eq!(report.errors.len(), 2, "{:?}", report.errors);
+    }
+
+    #[test]
+    fn report_formats_as_a_table() {
+        let motors = [named(0, vec3a(0.2, 0.0, 0.0), Vec3A::Y)];
+        let mut report = MotorConfigReport::default();
+
+        report_motors(&motors, Vec3A::ZERO, &mut report);
+        check_axes(&x3d(), &maximums(10.0), &Axis::ALL, &mut report);
+
+        let table = format_report(&report);
+        assert!(table.contains("Motor 0"));
+        assert!(table.contains("(0.20, 0.00, 0.00)"));
+        assert!(table.contains("ZRot"));
+        assert!(table.contains("Condition number"));
+    }
+}
@@ -1,5 +1,10 @@
 use std::{
-    mem, thread,
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
     time::{Duration, Instant},
 };
 
@@ -7,50 +12,94 @@ use ahash::HashMap;
 use anyhow::{anyhow, Context};
 use bevy::{app::AppExit, prelude::*};
 use common::{
-    components::{Armed, PwmChannel, PwmSignal, RobotId},
+    components::{Armed, ControlGated, MotorDefinition, PwmChannel, PwmHealth, PwmSignal, RobotId},
     ecs_sync::NetId,
     error::{self, Errors},
+    shutdown::{self, AppShutdownSet},
     types::hw::PwmChannelId,
 };
-use crossbeam::channel::{self, Sender};
+use crossbeam::channel::{self, Receiver, Sender};
 use tracing::{span, Level};
 
-use crate::{peripheral::pca9685::Pca9685, plugins::core::robot::LocalRobotMarker};
+use crate::{
+    config::RobotConfig,
+    peripheral::{
+        hal::pi::PiHal,
+        pca9685::{self, OutputCheck, Pca9685},
+    },
+    plugins::{
+        actuators::pwm_groups::{channel_runs, PwmGroupWrites, PwmScheduler},
+        core::{
+            arming::{AppPreArmCheckExt, CheckResult, CheckSeverity},
+            robot::{LocalRobot, LocalRobotMarker},
+        },
+    },
+};
 
 pub struct PwmOutputPlugin;
 
 impl Plugin for PwmOutputPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, start_pwm_thread.pipe(error::handle_errors));
+        app.add_pre_arm_check("PWM chip", CheckSeverity::Critical, pwm_chip_responding);
+        app.add_pre_arm_check(
+            "PWM output groups",
+            CheckSeverity::Advisory,
+            pwm_groups_written,
+        );
+        app.add_systems(
+            PreUpdate,
+            read_pwm_health.run_if(resource_exists::<PwmChannels>),
+        );
         app.add_systems(
             PostUpdate,
             listen_to_pwms
                 .pipe(error::handle_errors)
                 .run_if(resource_exists::<PwmChannels>),
         );
-        app.add_systems(Last, shutdown.run_if(resource_exists::<PwmChannels>));
+        app.add_systems(
+            Last,
+            shutdown
+                .in_set(AppShutdownSet::Actuators)
+                .run_if(resource_exists::<PwmChannels>),
+        );
     }
 }
 
 #[derive(Resource)]
-struct PwmChannels(Sender<PwmEvent>);
+struct PwmChannels(Sender<PwmEvent>, Arc<AtomicBool>, Receiver<PwmHealth>);
 
 #[derive(Debug)]
 enum PwmEvent {
     Arm(Armed),
     UpdateChannel(PwmChannelId, Duration),
     BatchComplete,
-    Shutdown,
+    /// Disarms and writes neutral pwms, then replies on the channel
+    Shutdown(Sender<()>),
 }
 
-fn start_pwm_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Result<()> {
+/// Output groups are reported once they go this many times their longest expected gap without a
+/// write
+const STALE_GROUP_TOLERANCE: u32 = 3;
+
+fn start_pwm_thread(
+    mut cmds: Commands,
+    config: Res<RobotConfig>,
+    errors: Res<Errors>,
+) -> anyhow::Result<()> {
     let interval = Duration::from_secs_f32(1.0 / 100.0);
     let max_inactive = Duration::from_secs_f32(1.0 / 10.0);
 
+    let mut scheduler = PwmScheduler::new(&config.pwm_groups).context("PWM output groups")?;
+    // Writes faster than the pwm period take effect at the start of the next period
+    let cycle = scheduler.cycle();
+
     let (tx_data, rx_data) = channel::bounded(30);
+    let (tx_health, rx_health) = channel::bounded(10);
+    let _ = tx_health.send(PwmHealth::Healthy);
 
-    let mut pwm_controller =
-        Pca9685::new(Pca9685::I2C_BUS, Pca9685::I2C_ADDRESS, interval).context("PCA9685")?;
+    let mut pwm_controller = Pca9685::new(&PiHal, pca9685::I2C_BUS, pca9685::I2C_ADDRESS, interval)
+        .context("PCA9685")?;
 
     const STOP_PWMS: [Duration; 16] = [Duration::from_micros(1500); 16];
     pwm_controller
@@ -59,7 +108,12 @@ fn start_pwm_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Result<(
 
     pwm_controller.output_disable();
 
-    cmds.insert_resource(PwmChannels(tx_data));
+    // Set by the pwm thread after every write
+    let responding = Arc::new(AtomicBool::new(true));
+    cmds.insert_resource(PwmChannels(tx_data, responding.clone(), rx_health));
+
+    let group_writes = PwmGroupWrites::new(&scheduler);
+    cmds.insert_resource(group_writes.clone());
 
     let errors = errors.0.clone();
     thread::Builder::new()
@@ -67,7 +121,8 @@ fn start_pwm_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Result<(
         .spawn(move || {
             let _span = span!(Level::INFO, "Pwm Output Thread").entered();
 
-            let mut deadline = Instant::now();
+            let start = Instant::now();
+            let mut deadline = start;
 
             let mut next_channel_pwms = HashMap::default();
             let mut batch_started = false;
@@ -78,6 +133,9 @@ fn start_pwm_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Result<(
             let mut last_batch = Instant::now();
 
             let mut do_shutdown = false;
+            let mut shutdown_ack = None;
+
+            let mut health = PwmHealth::Healthy;
 
             while !do_shutdown {
                 let span = span!(Level::INFO, "Pwm Output Cycle").entered();
@@ -109,9 +167,10 @@ fn start_pwm_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Result<(
                                 last_batch = Instant::now();
                             }
                         }
-                        PwmEvent::Shutdown => {
+                        PwmEvent::Shutdown(ack) => {
                             armed = Armed::Disarmed;
                             do_shutdown = true;
+                            shutdown_ack = Some(ack);
 
                             break;
                         }
@@ -159,17 +218,89 @@ fn start_pwm_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Result<(
                     pwms
                 };
 
-                trace!(?armed, ?channel_pwms, ?pwms, "Writing Pwms");
+                let now = Instant::now();
+                let since_start = now - start;
+
+                // Arming changes and shutdown can't wait for each group's next slot
+                let flush = shutdown_ack.is_some() || last_armed != armed;
 
                 // Write the current pwms to the pwm chip
-                let rst = pwm_controller
-                    .set_pwms(pwms)
-                    .context("Could not communicate with PCA9685");
+                let written = if flush {
+                    trace!(?armed, ?channel_pwms, ?pwms, "Writing all Pwms");
+
+                    let rst = pwm_controller
+                        .set_pwms(pwms)
+                        .context("Could not communicate with PCA9685");
+
+                    let written = report_write(rst, &errors);
+                    if written {
+                        scheduler.record_all(since_start, &pwms);
+                        for group in 0..scheduler.groups().len() {
+                            group_writes.record(group, now);
+                        }
+                    }
 
-                if let Err(err) = rst {
-                    warn!("Could not write pwms");
+                    Some(written)
+                } else {
+                    let due = scheduler.due(since_start, &pwms);
+                    let mut written = None;
+
+                    for group in due {
+                        trace!(group = %scheduler.groups()[group].name, ?armed, ?pwms, "Writing Pwms");
+
+                        let rst = channel_runs(&scheduler.groups()[group].channels)
+                            .into_iter()
+                            .try_for_each(|(first, len)| {
+                                let first_idx = first as usize;
+                                pwm_controller.set_pwm_run(first, &pwms[first_idx..first_idx + len])
+                            })
+                            .context("Could not communicate with PCA9685");
+
+                        let group_written = report_write(rst, &errors);
+                        if group_written {
+                            scheduler.record(group, since_start, &pwms);
+                            group_writes.record(group, now);
+                        }
+
+                        written = Some(written.unwrap_or(true) && group_written);
+                    }
 
-                    let _ = errors.send(err);
+                    written
+                };
+
+                // Cycles where no group was due say nothing about the chip
+                if let Some(written) = written {
+                    responding.store(written, Ordering::Relaxed);
+                }
+                let written = written.unwrap_or(false);
+
+                // Catches the chip resetting to its defaults while the writes keep succeeding
+                if shutdown_ack.is_none() {
+                    match pwm_controller.verify_outputs() {
+                        Ok(check) => {
+                            if let Some(next) = next_health(&health, check) {
+                                if let PwmHealth::Failed(ref mismatch) = next {
+                                    let _ = errors.send(anyhow!(
+                                        "PCA9685 outputs could not be restored: {mismatch}"
+                                    ));
+                                }
+
+                                health = next;
+                                let _ = tx_health.send(health.clone());
+                            }
+                        }
+                        Err(_) => {
+                            // Failed writes are already reported
+                            responding.store(false, Ordering::Relaxed);
+                        }
+                    }
+                }
+
+                if let Some(ack) = shutdown_ack.take() {
+                    // Dropping the sender without replying tells bevy the write failed
+                    if written {
+                        let _ = ack.send(());
+                    }
                 }
 
                 if last_armed != armed {
@@ -180,8 +311,8 @@ fn start_pwm_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Result<(
 
                 span.exit();
 
-                deadline += interval;
-                let remaining = deadline - Instant::now();
+                deadline += cycle;
+                let remaining = deadline.saturating_duration_since(Instant::now());
                 thread::sleep(remaining);
             }
         })
@@ -192,17 +323,23 @@ fn start_pwm_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Result<(
 
 fn listen_to_pwms(
     channels: Res<PwmChannels>,
-    robot: Query<(&NetId, &Armed), With<LocalRobotMarker>>,
-    pwms: Query<(&RobotId, &PwmChannel, &PwmSignal)>,
+    robot: Query<(&NetId, &Armed, Option<&ControlGated>), With<LocalRobotMarker>>,
+    pwms: Query<(&RobotId, &PwmChannel, &PwmSignal, Has<MotorDefinition>)>,
 ) -> anyhow::Result<()> {
-    let (net_id, armed) = robot.single();
+    let (net_id, armed, gated) = robot.single();
+    let gated = gated.map(|it| it.0).unwrap_or(false);
 
     channels
         .0
         .send(PwmEvent::Arm(*armed))
         .context("Send data to pwm thread")?;
 
-    for (RobotId(robot_net_id), pwm_channel, pwm) in &pwms {
+    for (RobotId(robot_net_id), pwm_channel, pwm, is_motor) in &pwms {
+        // Channels left out of the batch are set to neutral
+        if gated && is_motor {
+            continue;
+        }
+
         if robot_net_id == net_id {
             channels
                 .0
@@ -219,8 +356,201 @@ fn listen_to_pwms(
     Ok(())
 }
 
+/// The health after a verification of the chip's outputs, `None` if it didn't change
+///
+/// A failed chip stays failed until initializing it again brings it back
+fn next_health(health: &PwmHealth, check: OutputCheck) -> Option<PwmHealth> {
+    match (health, check) {
+        (_, OutputCheck::Verified) => None,
+        (PwmHealth::Recovered(count), OutputCheck::Restored(mismatch)) => {
+            warn!(%mismatch, "PCA9685 reset and was restored");
+            Some(PwmHealth::Recovered(count + 1))
+        }
+        (_, OutputCheck::Restored(mismatch)) => {
+            warn!(%mismatch, "PCA9685 reset and was restored");
+            Some(PwmHealth::Recovered(1))
+        }
+        (PwmHealth::Failed(_), OutputCheck::Failed(_)) => None,
+        (_, OutputCheck::Failed(mismatch)) => Some(PwmHealth::Failed(mismatch.to_string())),
+    }
+}
+
+/// Mirrors the pwm thread's view of the chip onto the robot, disarming once it fails
+fn read_pwm_health(
+    mut cmds: Commands,
+    channels: Res<PwmChannels>,
+    robot: Res<LocalRobot>,
+    armed: Query<&Armed, With<LocalRobotMarker>>,
+) {
+    let Some(health) = channels.2.try_iter().last() else {
+        return;
+    };
+
+    let mut robot = cmds.entity(robot.entity);
+    if matches!(health, PwmHealth::Failed(_)) && matches!(armed.get_single(), Ok(Armed::Armed)) {
+        error!("PWM outputs lost, disarming");
+        robot.insert(Armed::Disarmed);
+    }
+
+    robot.insert(health);
+}
+
+/// Logs a failed write, returns whether the write went through
+fn report_write(rst: anyhow::Result<()>, errors: &Sender<anyhow::Error>) -> bool {
+    match rst {
+        Ok(()) => true,
+        Err(err) => {
+            warn!("Could not write pwms");
+
+            let _ = errors.send(err);
+            false
+        }
+    }
+}
+
+fn pwm_chip_responding(
+    channels: Option<Res<PwmChannels>>,
+    health: Query<&PwmHealth, With<LocalRobotMarker>>,
+) -> CheckResult {
+    let Some(channels) = channels else {
+        return Err("PWM thread is not running".to_owned());
+    };
+
+    if !channels.1.load(Ordering::Relaxed) {
+        return Err("PCA9685 is not responding".to_owned());
+    }
+
+    if let Ok(PwmHealth::Failed(mismatch)) = health.get_single() {
+        return Err(format!("PCA9685 outputs lost, {mismatch}"));
+    }
+
+    Ok(())
+}
+
+fn pwm_groups_written(writes: Option<Res<PwmGroupWrites>>) -> CheckResult {
+    let Some(writes) = writes else {
+        return Err("PWM thread is not running".to_owned());
+    };
+
+    let stale = writes.stale(Instant::now(), STALE_GROUP_TOLERANCE);
+    if !stale.is_empty() {
+        return Err(format!("Not being written: {}", stale.join(", ")));
+    }
+
+    Ok(())
+}
+
 fn shutdown(channels: Res<PwmChannels>, mut exit: EventReader<AppExit>) {
     for _event in exit.read() {
-        let _ = channels.0.send(PwmEvent::Shutdown);
+        let (tx, rx) = channel::bounded(1);
+
+        let _ = channels.0.send(PwmEvent::Shutdown(tx));
+
+        // Nothing else may shut down until the thrusters are confirmed neutral
+        match rx.recv_timeout(shutdown::JOIN_TIMEOUT) {
+            Ok(()) => info!("Thrusters neutral"),
+            Err(_) => error!("Could not confirm thrusters were set to neutral"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{atomic::AtomicBool, Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+
+    use bevy::{app::AppExit, prelude::*};
+    use common::{
+        components::PwmHealth,
+        shutdown::{AppShutdownSet, ShutdownPlugin},
+    };
+    use crossbeam::channel;
+
+    use crate::peripheral::pca9685::{OutputCheck, OutputMismatch};
+
+    use super::{next_health, shutdown, PwmChannels, PwmEvent};
+
+    const RESET: OutputMismatch = OutputMismatch {
+        register: 0xfe,
+        expected: 59,
+        observed: 30,
+    };
+
+    #[test]
+    fn health_follows_verification() {
+        let healthy = PwmHealth::Healthy;
+        assert_eq!(next_health(&healthy, OutputCheck::Verified), None);
+
+        // Every restore is counted
+        let recovered = next_health(&healthy, OutputCheck::Restored(RESET)).unwrap();
+        assert_eq!(recovered, PwmHealth::Recovered(1));
+        assert_eq!(
+            next_health(&recovered, OutputCheck::Restored(RESET)),
+            Some(PwmHealth::Recovered(2))
+        );
+
+        // Failures are only reported once and latch until the chip is restored
+        let failed = next_health(&recovered, OutputCheck::Failed(RESET)).unwrap();
+        assert!(matches!(failed, PwmHealth::Failed(_)));
+        assert_eq!(next_health(&failed, OutputCheck::Failed(RESET)), None);
+        assert_eq!(next_health(&failed, OutputCheck::Verified), None);
+        assert_eq!(
+            next_health(&failed, OutputCheck::Restored(RESET)),
+            Some(PwmHealth::Recovered(1))
+        );
+    }
+
+    #[test]
+    fn disarms_before_the_network_shuts_down() {
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        // Stands in for the pwm thread, the write to the chip takes a moment
+        let (tx_pwm, rx_pwm) = channel::bounded(30);
+        let pwm_order = order.clone();
+        let pwm_thread = thread::spawn(move || {
+            for event in rx_pwm {
+                if let PwmEvent::Shutdown(ack) = event {
+                    thread::sleep(Duration::from_millis(20));
+                    pwm_order.lock().unwrap().push("neutral");
+                    let _ = ack.send(());
+                }
+            }
+        });
+
+        let network_order = order.clone();
+        let goodbye = move |mut exit: EventReader<AppExit>| {
+            for _event in exit.read() {
+                network_order.lock().unwrap().push("goodbye");
+            }
+        };
+
+        let (_tx_health, rx_health) = channel::bounded(1);
+        let mut app = App::new();
+        app.add_plugins(ShutdownPlugin)
+            .insert_resource(PwmChannels(
+                tx_pwm,
+                Arc::new(AtomicBool::new(true)),
+                rx_health,
+            ))
+            .add_systems(
+                Last,
+                (
+                    goodbye.in_set(AppShutdownSet::Network),
+                    shutdown.in_set(AppShutdownSet::Actuators),
+                ),
+            );
+
+        app.update();
+        assert!(order.lock().unwrap().is_empty());
+
+        app.world.send_event(AppExit);
+        app.update();
+        assert_eq!(*order.lock().unwrap(), ["neutral", "goodbye"]);
+
+        drop(app);
+        pwm_thread.join().unwrap();
     }
 }
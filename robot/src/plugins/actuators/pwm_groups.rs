@@ -0,0 +1,471 @@
+//! Splits the PWM channels into groups written on their own schedule
+//!
+//! Thrusters want a fresh pulse width every cycle, servos jitter when rewritten constantly, and
+//! anything else driven from a PWM channel rarely changes at all.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use ahash::{HashMap, HashSet};
+use anyhow::bail;
+use bevy::ecs::system::Resource;
+use common::types::hw::PwmChannelId;
+
+use crate::config::{pwm_group_errors, PwmGroupConfig, PwmUpdatePolicy, PWM_CHANNELS};
+
+/// Name of the group holding the channels not assigned to one
+pub const DEFAULT_GROUP: &str = "default";
+/// Writes per second of [`DEFAULT_GROUP`]
+pub const DEFAULT_RATE: f32 = 100.0;
+
+#[derive(Debug, Clone)]
+pub struct PwmGroup {
+    pub name: String,
+    /// Sorted
+    pub channels: Vec<PwmChannelId>,
+    pub period: Duration,
+    /// Set for groups only written when a channel changes
+    pub keepalive: Option<Duration>,
+    /// Relative to when the scheduler was started
+    pub last_write: Option<Duration>,
+
+    next_slot: Duration,
+    /// Pulse widths of `channels` at the last write
+    written: Vec<Duration>,
+}
+
+impl PwmGroup {
+    fn new(
+        name: &str,
+        mut channels: Vec<PwmChannelId>,
+        rate: f32,
+        policy: PwmUpdatePolicy,
+    ) -> Self {
+        channels.sort();
+
+        Self {
+            name: name.to_owned(),
+            channels,
+            period: Duration::from_secs_f64(1.0 / rate as f64),
+            keepalive: match policy {
+                PwmUpdatePolicy::Always => None,
+                PwmUpdatePolicy::OnChange { keepalive } => Some(Duration::from_secs_f32(keepalive)),
+            },
+            last_write: None,
+            next_slot: Duration::ZERO,
+            written: Vec::new(),
+        }
+    }
+
+    /// Longest a healthy group goes without a write
+    pub fn max_gap(&self) -> Duration {
+        self.keepalive.unwrap_or_default().max(self.period)
+    }
+
+    fn pwms<'a>(&'a self, pwms: &'a [Duration; 16]) -> impl Iterator<Item = Duration> + 'a {
+        self.channels.iter().map(|&channel| pwms[channel as usize])
+    }
+
+    /// Whether the group should be written at `now`, moving it on to its next slot if one came
+    fn poll(&mut self, now: Duration, pwms: &[Duration; 16]) -> bool {
+        if now < self.next_slot {
+            return false;
+        }
+
+        self.next_slot += self.period;
+        if self.next_slot <= now {
+            // Skip the slots that were missed instead of writing them in a burst
+            self.next_slot = now + self.period;
+        }
+
+        let Some(keepalive) = self.keepalive else {
+            return true;
+        };
+
+        let changed = !self.pwms(pwms).eq(self.written.iter().copied());
+        let expired = self
+            .last_write
+            .is_none_or(|last| now.saturating_sub(last) >= keepalive);
+
+        changed || expired
+    }
+
+    fn record(&mut self, now: Duration, pwms: &[Duration; 16]) {
+        self.last_write = Some(now);
+        self.written = self.pwms(pwms).collect();
+    }
+}
+
+/// Decides which output groups are written each cycle
+///
+/// Works off of time since the scheduler started so it can be driven by a virtual clock
+#[derive(Debug, Clone)]
+pub struct PwmScheduler {
+    groups: Vec<PwmGroup>,
+}
+
+impl PwmScheduler {
+    pub fn new(config: &HashMap<String, PwmGroupConfig>) -> anyhow::Result<Self> {
+        let errors = pwm_group_errors(config);
+        if !errors.is_empty() {
+            bail!("Invalid PWM groups: {}", errors.join(", "));
+        }
+
+        let mut groups = config
+            .iter()
+            .map(|(name, group)| {
+                PwmGroup::new(name, group.channels.clone(), group.rate, group.policy)
+            })
+            .collect::<Vec<_>>();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let assigned = config
+            .values()
+            .flat_map(|group| group.channels.iter().copied())
+            .collect::<HashSet<_>>();
+        let rest = (0..PWM_CHANNELS)
+            .filter(|channel| !assigned.contains(channel))
+            .collect::<Vec<_>>();
+
+        if !rest.is_empty() {
+            groups.push(PwmGroup::new(
+                DEFAULT_GROUP,
+                rest,
+                DEFAULT_RATE,
+                PwmUpdatePolicy::Always,
+            ));
+        }
+
+        Ok(Self { groups })
+    }
+
+    pub fn groups(&self) -> &[PwmGroup] {
+        &self.groups
+    }
+
+    /// Shortest period of any group, how often the scheduler needs to be polled
+    pub fn cycle(&self) -> Duration {
+        self.groups
+            .iter()
+            .map(|group| group.period)
+            .min()
+            .unwrap_or(Duration::from_secs_f32(1.0 / DEFAULT_RATE))
+    }
+
+    /// Indices of the groups to write at `now`
+    ///
+    /// Groups stay due until [`Self::record`] is called for them, so failed writes are retried at
+    /// the group's next slot
+    pub fn due(&mut self, now: Duration, pwms: &[Duration; 16]) -> Vec<usize> {
+        self.groups
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(idx, group)| group.poll(now, pwms).then_some(idx))
+            .collect()
+    }
+
+    /// Records that `group` was written with `pwms` at `now`
+    pub fn record(&mut self, group: usize, now: Duration, pwms: &[Duration; 16]) {
+        self.groups[group].record(now, pwms);
+    }
+
+    /// Records that every group was written, used when the outputs had to change immediately
+    pub fn record_all(&mut self, now: Duration, pwms: &[Duration; 16]) {
+        for group in &mut self.groups {
+            group.record(now, pwms);
+        }
+    }
+}
+
+/// Splits sorted channels into runs of consecutive channels, as the first channel and length
+///
+/// Each run can be written to the PWM chip in a single transfer
+pub fn channel_runs(channels: &[PwmChannelId]) -> Vec<(PwmChannelId, usize)> {
+    let mut runs: Vec<(PwmChannelId, usize)> = Vec::new();
+
+    for &channel in channels {
+        match runs.last_mut() {
+            Some((first, len)) if *first as usize + *len == channel as usize => *len += 1,
+            _ => runs.push((channel, 1)),
+        }
+    }
+
+    runs
+}
+
+/// When each output group was last written, kept up to date by the PWM thread
+#[derive(Resource, Debug, Clone)]
+pub struct PwmGroupWrites(Arc<Mutex<Vec<GroupWrite>>>);
+
+#[derive(Debug, Clone)]
+pub struct GroupWrite {
+    pub name: String,
+    pub max_gap: Duration,
+    pub last_write: Option<Instant>,
+}
+
+impl PwmGroupWrites {
+    pub fn new(scheduler: &PwmScheduler) -> Self {
+        Self(Arc::new(Mutex::new(
+            scheduler
+                .groups()
+                .iter()
+                .map(|group| GroupWrite {
+                    name: group.name.clone(),
+                    max_gap: group.max_gap(),
+                    last_write: None,
+                })
+                .collect(),
+        )))
+    }
+
+    pub fn record(&self, group: usize, at: Instant) {
+        if let Ok(mut writes) = self.0.lock() {
+            if let Some(write) = writes.get_mut(group) {
+                write.last_write = Some(at);
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<GroupWrite> {
+        self.0.lock().map(|it| it.clone()).unwrap_or_default()
+    }
+
+    /// Groups that went more than `tolerance` times their longest expected gap without a write
+    pub fn stale(&self, now: Instant, tolerance: u32) -> Vec<String> {
+        self.snapshot()
+            .into_iter()
+            .filter(|write| {
+                write.last_write.is_none_or(|last| {
+                    now.saturating_duration_since(last) > write.max_gap * tolerance
+                })
+            })
+            .map(|write| write.name)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use ahash::HashMap;
+
+    use crate::config::{pwm_group_errors, PwmGroupConfig, PwmUpdatePolicy};
+
+    use super::{channel_runs, PwmScheduler, DEFAULT_GROUP};
+
+    const NEUTRAL: Duration = Duration::from_micros(1500);
+
+    fn group(channels: &[u8], rate: f32, policy: PwmUpdatePolicy) -> PwmGroupConfig {
+        PwmGroupConfig {
+            channels: channels.to_vec(),
+            rate,
+            policy,
+        }
+    }
+
+    fn config(groups: &[(&str, PwmGroupConfig)]) -> HashMap<String, PwmGroupConfig> {
+        groups
+            .iter()
+            .map(|(name, group)| (name.to_string(), group.clone()))
+            .collect()
+    }
+
+    fn index(scheduler: &PwmScheduler, name: &str) -> usize {
+        scheduler
+            .groups()
+            .iter()
+            .position(|it| it.name == name)
+            .unwrap()
+    }
+
+    /// Polls the scheduler every millisecond for `millis`, recording every due write
+    ///
+    /// `pwms` gives the outputs at each millisecond, returns the times each group was written at
+    fn run(
+        scheduler: &mut PwmScheduler,
+        millis: u64,
+        mut pwms: impl FnMut(u64) -> [Duration; 16],
+    ) -> Vec<Vec<u64>> {
+        let mut writes = vec![Vec::new(); scheduler.groups().len()];
+
+        for millis in 0..millis {
+            let now = Duration::from_millis(millis);
+            let pwms = pwms(millis);
+
+            for group in scheduler.due(now, &pwms) {
+                scheduler.record(group, now, &pwms);
+                writes[group].push(millis);
+            }
+        }
+
+        writes
+    }
+
+    fn changing(millis: u64) -> [Duration; 16] {
+        [NEUTRAL + Duration::from_micros(millis % 400); 16]
+    }
+
+    #[test]
+    fn groups_write_at_their_rate() {
+        let mut scheduler = PwmScheduler::new(&config(&[
+            (
+                "thrusters",
+                group(&[0, 1, 2, 3], 400.0, PwmUpdatePolicy::Always),
+            ),
+            ("servos", group(&[15, 14], 50.0, PwmUpdatePolicy::Always)),
+        ]))
+        .unwrap();
+        assert_eq!(scheduler.cycle(), Duration::from_micros(2500));
+
+        let writes = run(&mut scheduler, 1000, changing);
+
+        assert_eq!(writes[index(&scheduler, "thrusters")].len(), 400);
+        assert_eq!(writes[index(&scheduler, "servos")].len(), 50);
+        assert_eq!(writes[index(&scheduler, DEFAULT_GROUP)].len(), 100);
+
+        // Slots stay evenly spaced
+        let servos = &writes[index(&scheduler, "servos")];
+        assert!(servos.windows(2).all(|it| it[1] - it[0] == 20));
+    }
+
+    #[test]
+    fn missed_slots_are_not_made_up() {
+        let mut scheduler = PwmScheduler::new(&config(&[(
+            "thrusters",
+            group(&[0, 1], 100.0, PwmUpdatePolicy::Always),
+        )]))
+        .unwrap();
+        let thrusters = index(&scheduler, "thrusters");
+        let pwms = [NEUTRAL; 16];
+
+        assert!(scheduler.due(Duration::ZERO, &pwms).contains(&thrusters));
+
+        // A stall of ten periods gets one write, not ten
+        let late = Duration::from_millis(100);
+        assert!(scheduler.due(late, &pwms).contains(&thrusters));
+        assert!(!scheduler.due(late, &pwms).contains(&thrusters));
+        assert!(!scheduler
+            .due(late + Duration::from_millis(5), &pwms)
+            .contains(&thrusters));
+        assert!(scheduler
+            .due(late + Duration::from_millis(10), &pwms)
+            .contains(&thrusters));
+    }
+
+    #[test]
+    fn unchanged_groups_only_keep_alive() {
+        let mut scheduler = PwmScheduler::new(&config(&[(
+            "servos",
+            group(
+                &[12, 13],
+                50.0,
+                PwmUpdatePolicy::OnChange { keepalive: 0.5 },
+            ),
+        )]))
+        .unwrap();
+        let servos = index(&scheduler, "servos");
+
+        let writes = run(&mut scheduler, 2000, |millis| {
+            let mut pwms = [NEUTRAL; 16];
+            // Other groups changing does not count
+            pwms[0] += Duration::from_micros(millis);
+            // Moved once, 700 is not a keepalive time
+            if millis >= 690 {
+                pwms[13] = Duration::from_micros(1600);
+            }
+            pwms
+        });
+
+        assert_eq!(writes[servos], [0, 500, 700, 1200, 1700]);
+    }
+
+    #[test]
+    fn failed_writes_are_retried() {
+        let mut scheduler = PwmScheduler::new(&config(&[(
+            "servos",
+            group(&[12], 50.0, PwmUpdatePolicy::OnChange { keepalive: 1.0 }),
+        )]))
+        .unwrap();
+        let servos = index(&scheduler, "servos");
+        let pwms = [NEUTRAL; 16];
+
+        // Never recorded as written
+        assert!(scheduler.due(Duration::ZERO, &pwms).contains(&servos));
+        assert!(scheduler
+            .due(Duration::from_millis(20), &pwms)
+            .contains(&servos));
+
+        scheduler.record(servos, Duration::from_millis(20), &pwms);
+        assert!(!scheduler
+            .due(Duration::from_millis(40), &pwms)
+            .contains(&servos));
+
+        // Flushes count as writes
+        let mut moved = pwms;
+        moved[12] = Duration::from_micros(1700);
+        scheduler.record_all(Duration::from_millis(50), &moved);
+        assert!(!scheduler
+            .due(Duration::from_millis(60), &moved)
+            .contains(&servos));
+    }
+
+    #[test]
+    fn unassigned_channels_get_the_default_group() {
+        let scheduler = PwmScheduler::new(&config(&[(
+            "servos",
+            group(&[15, 12], 50.0, PwmUpdatePolicy::Always),
+        )]))
+        .unwrap();
+
+        let groups = scheduler.groups();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].channels, [12, 15]);
+        assert_eq!(groups[1].name, DEFAULT_GROUP);
+        assert_eq!(
+            groups[1].channels,
+            [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 14]
+        );
+
+        let scheduler = PwmScheduler::new(&HashMap::default()).unwrap();
+        assert_eq!(scheduler.groups().len(), 1);
+        assert_eq!(scheduler.groups()[0].channels.len(), 16);
+    }
+
+    #[test]
+    fn misconfigured_groups_are_rejected() {
+        let groups = config(&[
+            ("servos", group(&[12, 13], 50.0, PwmUpdatePolicy::Always)),
+            (
+                "lights",
+                group(&[13, 16, 11, 11], 0.0, PwmUpdatePolicy::Always),
+            ),
+            (
+                "tilt",
+                group(&[10], 50.0, PwmUpdatePolicy::OnChange { keepalive: -1.0 }),
+            ),
+        ]);
+
+        assert_eq!(
+            pwm_group_errors(&groups),
+            [
+                "PWM channel 11 is in group lights twice",
+                "PWM channel 13 is in both groups lights and servos",
+                "PWM group lights has channel 16, there are only 16",
+                "PWM group lights needs a positive rate",
+                "PWM group tilt needs a positive keepalive",
+            ]
+        );
+        assert!(PwmScheduler::new(&groups).is_err());
+    }
+
+    #[test]
+    fn runs_of_channels() {
+        assert_eq!(channel_runs(&[0, 1, 2, 5, 6, 9]), [(0, 3), (5, 2), (9, 1)]);
+        assert_eq!(channel_runs(&[15]), [(15, 1)]);
+        assert!(channel_runs(&[]).is_empty());
+    }
+}
@@ -1,4 +1,4 @@
-use std::time::Duration;
+use std::{collections::BTreeMap, time::Duration};
 
 use ahash::{HashMap, HashSet};
 use bevy::prelude::*;
@@ -8,8 +8,12 @@ use common::{
         PwmChannel, PwmManualControl, PwmSignal, RobotId, ServoContribution, ServoDefinition,
         ServoMode, ServoTargets, Servos,
     },
-    ecs_sync::{NetId, Replicate},
-    events::{ResetServo, ResetServos},
+    ecs_sync::{scoped::ScopedEvents, NetId, Replicate},
+    events::{ResetServo, ResetServos, ServoCommand},
+    types::{
+        bounded::BoundedMap,
+        ids::{CameraId, ServoId, UnknownIds},
+    },
 };
 use motor_math::motor_preformance::MotorData;
 
@@ -24,7 +28,7 @@ impl Plugin for ServoPlugin {
     fn build(&self, app: &mut App) {
         // TODO(mid): Update motor config when motor definitions change
         app.add_systems(Startup, create_servos)
-            .add_systems(Update, handle_servo_input);
+            .add_systems(Update, (handle_servo_input, warn_unknown_servos));
     }
 }
 
@@ -37,7 +41,10 @@ fn create_servos(mut cmds: Commands, robot: Res<LocalRobot>, config: Res<RobotCo
     // TODO: Make this a bundle
     cmds.entity(robot.entity).insert((
         Servos {
-            servos: servos.iter().map(|(name, _)| name.clone().into()).collect(),
+            servos: servos
+                .keys()
+                .map(|name| ServoId::new(name.clone()))
+                .collect(),
         },
         ServoTargets::default(),
     ));
@@ -59,7 +66,7 @@ fn create_servos(mut cmds: Commands, robot: Res<LocalRobot>, config: Res<RobotCo
                     robot: RobotId(robot.net_id),
                 },
                 servo: ServoDefinition {
-                    cameras: cameras.iter().map(|it| it.clone().into()).collect(),
+                    cameras: cameras.iter().cloned().map(CameraId::new).collect(),
                 },
                 servo_mode: ServoMode::Velocity,
             },
@@ -68,19 +75,19 @@ fn create_servos(mut cmds: Commands, robot: Res<LocalRobot>, config: Res<RobotCo
     }
 }
 
+type ServoState<'a> = (Entity, &'a NetId, &'a ServoTargets);
+type AutomaticControl = (With<LocalRobotMarker>, Without<PwmManualControl>);
+
 fn handle_servo_input(
     mut cmds: Commands,
 
-    robot: Query<
-        (Entity, &NetId, &ServoTargets),
-        (With<LocalRobotMarker>, Without<PwmManualControl>),
-    >,
+    robot: Query<ServoState<'static>, AutomaticControl>,
     servo_inputs: Query<(&RobotId, &ServoContribution)>,
     // TODO
     servos: Query<(Entity, &Name, &ServoMode, &ServoDefinition, &RobotId)>,
 
-    mut reset: EventReader<ResetServos>,
-    mut reset_single: EventReader<ResetServo>,
+    mut reset: ScopedEvents<ResetServos>,
+    (mut reset_single, mut servo_commands): (EventReader<ResetServo>, EventReader<ServoCommand>),
 
     time: Res<Time<Real>>,
 ) {
@@ -88,7 +95,7 @@ fn handle_servo_input(
         return;
     };
 
-    let mut all_inputs = HashMap::<_, f32>::default();
+    let mut inputs = ServoInputs::default();
 
     for (&RobotId(robot_net_id), servo_contribution) in &servo_inputs {
         if robot_net_id != net_id {
@@ -96,51 +103,39 @@ fn handle_servo_input(
         }
 
         for (motor, input) in &servo_contribution.0 {
-            *all_inputs.entry(motor.clone()).or_default() += *input;
+            *inputs.contributions.entry(motor.clone()).or_default() += *input;
         }
     }
 
     let servos_by_id = servos
         .iter()
-        .map(|it| (it.1.as_str(), it))
+        .map(|it| (ServoId::new(it.1.as_str().to_owned()), it))
         .collect::<HashMap<_, _>>();
 
-    let mut full_reset = false;
-
-    if !reset.is_empty() {
-        full_reset = true;
-        reset.clear();
+    if !reset.read().is_empty() {
+        inputs.full_reset = true;
     }
 
-    let mut new_positions = last_positions.0.clone();
-    let mut should_reset = HashSet::default();
-
     for event in reset_single.read() {
-        new_positions.insert(event.0.clone(), 0.0);
-        should_reset.insert(event.0.clone());
-    }
-
-    new_positions.extend(all_inputs.into_iter().flat_map(|(id, input)| {
-        let (_, _, mode, _, _) = servos_by_id.get(&*id)?;
-
-        match mode {
-            ServoMode::Position => Some((id, input)),
-            ServoMode::Velocity => {
-                let last_position = if !full_reset && !should_reset.contains(&id) {
-                    last_positions.0.get(&id).copied().unwrap_or(0.0)
-                } else {
-                    0.0
-                };
-                Some((
-                    id,
-                    (last_position + input * time.delta_seconds()).clamp(-1.0, 1.0),
-                ))
-            }
+        inputs.resets.insert(event.0.clone());
+    }
+
+    for event in servo_commands.read() {
+        if servos_by_id.contains_key(&event.servo) {
+            inputs.commands.insert(event.servo.clone(), event.target);
+        } else {
+            warn!("Got command for unknown servo {}", event.servo);
         }
-    }));
+    }
+
+    let modes = servos_by_id
+        .iter()
+        .map(|(id, (_, _, mode, _, _))| (id.clone(), (*mode).clone()))
+        .collect();
+    let new_positions = inputs.apply(&last_positions.0, &modes, time.delta_seconds());
 
     for (id, position) in &new_positions {
-        let Some((servo, ..)) = servos_by_id.get(&**id) else {
+        let Some((servo, ..)) = servos_by_id.get(id) else {
             continue;
         };
 
@@ -150,5 +145,176 @@ fn handle_servo_input(
             .insert(PwmSignal(Duration::from_micros(micros as u64)));
     }
 
-    cmds.entity(robot).insert(ServoTargets(new_positions));
+    // Only replicate actual changes
+    if new_positions != *last_positions.0 {
+        match BoundedMap::try_from(new_positions) {
+            Ok(targets) => {
+                cmds.entity(robot).insert(ServoTargets(targets));
+            }
+            Err(err) => error!("Could not replicate servo targets: {err}"),
+        }
+    }
+}
+
+/// Warns once about each servo a contribution names that the robot doesn't have
+fn warn_unknown_servos(
+    robot: Query<(&NetId, &Servos), With<LocalRobotMarker>>,
+    contributions: Query<(&RobotId, &ServoContribution), Changed<ServoContribution>>,
+    mut unknown: Local<UnknownIds<ServoId>>,
+) {
+    let Ok((&net_id, servos)) = robot.get_single() else {
+        return;
+    };
+
+    let referenced = contributions
+        .iter()
+        .filter(|(robot, _)| robot.0 == net_id)
+        .flat_map(|(_, contribution)| contribution.0.keys());
+
+    for servo in unknown.check(&servos.servos, referenced) {
+        warn!("Got contribution for unknown servo {servo}");
+    }
+}
+
+/// Everything that can move the servos in a single frame
+#[derive(Default, Debug)]
+struct ServoInputs {
+    full_reset: bool,
+    resets: HashSet<ServoId>,
+    /// Absolute positions, these set the base position contributions adjust from
+    commands: HashMap<ServoId, f32>,
+    contributions: HashMap<ServoId, f32>,
+}
+
+impl ServoInputs {
+    /// Computes the next position of every servo
+    ///
+    /// Resets apply first, then absolute commands, then contributions. Position mode
+    /// contributions replace the position, velocity mode contributions move from it.
+    fn apply(
+        self,
+        last_positions: &BTreeMap<ServoId, f32>,
+        modes: &HashMap<ServoId, ServoMode>,
+        delta_seconds: f32,
+    ) -> BTreeMap<ServoId, f32> {
+        let mut positions = last_positions.clone();
+
+        if self.full_reset {
+            positions.values_mut().for_each(|it| *it = 0.0);
+        }
+
+        for id in self.resets {
+            positions.insert(id, 0.0);
+        }
+
+        for (id, target) in self.commands {
+            positions.insert(id, target.clamp(-1.0, 1.0));
+        }
+
+        for (id, input) in self.contributions {
+            let Some(mode) = modes.get(&id) else {
+                continue;
+            };
+
+            let position = match mode {
+                ServoMode::Position => input,
+                ServoMode::Velocity => {
+                    let base = positions.get(&id).copied().unwrap_or(0.0);
+                    base + input * delta_seconds
+                }
+            };
+
+            positions.insert(id, position.clamp(-1.0, 1.0));
+        }
+
+        positions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use ahash::HashMap;
+    use common::{components::ServoMode, types::ids::ServoId};
+
+    use super::ServoInputs;
+
+    const TILT: ServoId = ServoId::from_static("Tilt");
+    const CLAW: ServoId = ServoId::from_static("Claw");
+
+    fn modes() -> HashMap<ServoId, ServoMode> {
+        [(TILT, ServoMode::Velocity), (CLAW, ServoMode::Position)]
+            .into_iter()
+            .collect()
+    }
+
+    fn positions(tilt: f32, claw: f32) -> BTreeMap<ServoId, f32> {
+        [(TILT, tilt), (CLAW, claw)].into_iter().collect()
+    }
+
+    #[test]
+    fn command_sets_base_for_contributions() {
+        let mut inputs = ServoInputs::default();
+        inputs.commands.insert(TILT, 0.5);
+        inputs.contributions.insert(TILT, 1.0);
+
+        let next = inputs.apply(&positions(-0.5, 0.0), &modes(), 0.1);
+        assert!((next[&TILT] - 0.6).abs() < 1e-6);
+
+        // Position contributions are absolute and win over commands
+        let mut inputs = ServoInputs::default();
+        inputs.commands.insert(CLAW, 0.5);
+        inputs.contributions.insert(CLAW, -0.25);
+
+        let next = inputs.apply(&positions(0.0, 0.0), &modes(), 0.1);
+        assert_eq!(next[&CLAW], -0.25);
+    }
+
+    #[test]
+    fn resets_apply_before_commands() {
+        let mut inputs = ServoInputs {
+            full_reset: true,
+            ..Default::default()
+        };
+        inputs.commands.insert(CLAW, 0.3);
+
+        let next = inputs.apply(&positions(0.7, 0.9), &modes(), 0.1);
+        assert_eq!(next, positions(0.0, 0.3));
+
+        let mut inputs = ServoInputs::default();
+        inputs.resets.insert(TILT);
+        inputs.contributions.insert(TILT, 1.0);
+
+        let next = inputs.apply(&positions(0.7, 0.9), &modes(), 0.1);
+        assert!((next[&TILT] - 0.1).abs() < 1e-6);
+        assert_eq!(next[&CLAW], 0.9);
+    }
+
+    #[test]
+    fn positions_are_clamped() {
+        let mut inputs = ServoInputs::default();
+        inputs.commands.insert(CLAW, 3.0);
+        inputs.contributions.insert(TILT, -50.0);
+
+        let next = inputs.apply(&positions(0.0, 0.0), &modes(), 0.1);
+        assert_eq!(next, positions(-1.0, 1.0));
+
+        let mut inputs = ServoInputs::default();
+        inputs.contributions.insert(CLAW, -2.0);
+
+        let next = inputs.apply(&positions(0.0, 0.0), &modes(), 0.1);
+        assert_eq!(next[&CLAW], -1.0);
+    }
+
+    #[test]
+    fn unknown_servos_are_ignored() {
+        let mut inputs = ServoInputs::default();
+        inputs
+            .contributions
+            .insert(ServoId::from_static("Missing"), 1.0);
+
+        let next = inputs.apply(&positions(0.2, 0.4), &modes(), 0.1);
+        assert_eq!(next, positions(0.2, 0.4));
+    }
 }
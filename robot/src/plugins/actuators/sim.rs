@@ -0,0 +1,34 @@
+//! Stands in for the PWM chip when it isn't compiled in or the robot runs without hardware
+//!
+//! Nothing is driven, the signals the chip would have received are only logged.
+
+use bevy::prelude::*;
+use common::components::{Armed, PwmChannel, PwmSignal, RobotId};
+
+use crate::plugins::core::robot::LocalRobot;
+
+pub struct SimPwmPlugin;
+
+impl Plugin for SimPwmPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, log_pwms);
+    }
+}
+
+fn log_pwms(
+    robot: Res<LocalRobot>,
+    armed: Query<Ref<Armed>>,
+    pwms: Query<(&RobotId, &PwmChannel, &PwmSignal), Changed<PwmSignal>>,
+) {
+    if let Ok(armed) = armed.get(robot.entity) {
+        if armed.is_changed() {
+            info!("Simulated PWM Chip: {:?}", *armed);
+        }
+    }
+
+    for (&RobotId(robot_net_id), channel, signal) in &pwms {
+        if robot_net_id == robot.net_id {
+            trace!(channel = ?channel.0, pwm = ?signal.0, "Simulated PWM");
+        }
+    }
+}
@@ -1,206 +1,351 @@
-use std::f32::consts::{PI, TAU};
+//! Orientation hold
+//!
+//! Pitch, roll and yaw each have their own PID and authority limit, but their torques are summed
+//! into one movement contribution so the robot reports it as a single source. The contribution is
+//! zeroed rather than removed without a target, so it can be muted at any time.
+
+use std::time::Duration;
 
 use bevy::prelude::*;
 use common::{
     bundles::MovementContributionBundle,
     components::{
-        Armed, MovementContribution, Orientation, OrientationTarget, PidConfig, PidResult, RobotId,
+        Armed, AuthorityLimit, MovementContribution, Orientation, OrientationTarget, PidConfig,
+        PidResult, RobotId,
     },
     ecs_sync::Replicate,
-    types::utils::PidController,
+    types::{units::Newtons, utils::PidController},
 };
-use glam::{vec3a, Vec3A};
+use glam::Vec3A;
 use motor_math::Movement;
 
-use crate::plugins::core::robot::LocalRobot;
+use crate::{
+    config::RobotConfig,
+    plugins::{actuators::thruster::AccumulateMovementsSet, core::robot::LocalRobot},
+};
 
 pub struct StabilizePlugin;
 
 impl Plugin for StabilizePlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, setup_stabalize);
-        app.add_systems(Update, stabalize_system);
+        app.add_systems(Update, stabalize_system.before(AccumulateMovementsSet));
     }
 }
 
-#[derive(Resource)]
-struct StabilizeState {
-    pitch: Entity,
-    pitch_controller: PidController,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StabilizeAxis {
+    Pitch,
+    Roll,
+    Yaw,
+}
 
-    roll: Entity,
-    roll_controller: PidController,
+impl StabilizeAxis {
+    const ALL: [StabilizeAxis; 3] = [
+        StabilizeAxis::Pitch,
+        StabilizeAxis::Roll,
+        StabilizeAxis::Yaw,
+    ];
 
-    yaw: Entity,
-    yaw_controller: PidController,
-}
+    fn name(&self) -> &'static str {
+        match self {
+            StabilizeAxis::Pitch => "Stabalize Pitch",
+            StabilizeAxis::Roll => "Stabalize Roll",
+            StabilizeAxis::Yaw => "Stabalize Yaw",
+        }
+    }
 
-fn setup_stabalize(mut cmds: Commands, robot: Res<LocalRobot>) {
-    let pitch = cmds
-        .spawn((
-            MovementContributionBundle {
-                name: Name::new("Stabalize Pitch"),
-                contribution: MovementContribution(Movement::default()),
-                robot: RobotId(robot.net_id),
-            },
-            // TODO(high): Tune
-            // TODO(low): Load from disk?
-            PidConfig {
+    /// The body axis this corrects rotation about
+    fn body_axis(&self) -> Vec3A {
+        match self {
+            StabilizeAxis::Pitch => Vec3A::X,
+            StabilizeAxis::Roll => Vec3A::Y,
+            StabilizeAxis::Yaw => Vec3A::Z,
+        }
+    }
+
+    // TODO(high): Tune
+    // TODO(low): Load from disk?
+    fn default_pid(&self) -> PidConfig {
+        match self {
+            StabilizeAxis::Pitch => PidConfig {
                 kp: 0.5,
                 ki: 0.25,
                 kd: 0.15,
                 kt: 5.0,
                 max_integral: 60.0,
             },
-            Replicate,
-        ))
-        .id();
-
-    let roll = cmds
-        .spawn((
-            MovementContributionBundle {
-                name: Name::new("Stabalize Roll"),
-                contribution: MovementContribution(Movement::default()),
-                robot: RobotId(robot.net_id),
-            },
-            // TODO(high): Tune
-            // TODO(low): Load from disk?
-            PidConfig {
+            StabilizeAxis::Roll => PidConfig {
                 kp: 0.3,
                 ki: 0.15,
                 kd: 0.1,
                 kt: 3.5,
                 max_integral: 30.0,
             },
-            Replicate,
-        ))
-        .id();
-
-    let yaw = cmds
-        .spawn((
-            MovementContributionBundle {
-                name: Name::new("Stabalize Yaw"),
-                contribution: MovementContribution(Movement::default()),
-                robot: RobotId(robot.net_id),
-            },
-            // TODO(high): Tune
-            // TODO(low): Load from disk?
-            PidConfig {
+            StabilizeAxis::Yaw => PidConfig {
                 kp: 0.15,
                 ki: 0.07,
                 kd: 0.12,
                 kt: 5.0,
                 max_integral: 20.0,
             },
+        }
+    }
+}
+
+#[derive(Resource)]
+struct StabilizeState {
+    /// Owns the contribution of all three axes
+    controller: Entity,
+    /// Holds each axis' config and result, in the order of [`StabilizeAxis::ALL`]
+    axes: [(Entity, PidController); 3],
+}
+
+fn setup_stabalize(mut cmds: Commands, robot: Res<LocalRobot>, config: Res<RobotConfig>) {
+    let controller = cmds
+        .spawn((
+            MovementContributionBundle {
+                name: Name::new("Orientation Hold Controller"),
+                contribution: MovementContribution(Movement::default()),
+                robot: RobotId(robot.net_id),
+            },
             Replicate,
         ))
         .id();
+    cmds.entity(robot.entity).add_child(controller);
 
-    cmds.insert_resource(StabilizeState {
-        pitch,
-        pitch_controller: PidController::default(),
-        roll,
-        roll_controller: PidController::default(),
-        yaw,
-        yaw_controller: PidController::default(),
+    let authority = config.orientation_authority;
+    let axes = StabilizeAxis::ALL.map(|axis| {
+        let limit = match axis {
+            StabilizeAxis::Pitch => authority.pitch,
+            StabilizeAxis::Roll => authority.roll,
+            StabilizeAxis::Yaw => authority.yaw,
+        };
+
+        let entity = cmds
+            .spawn((
+                Name::new(axis.name()),
+                RobotId(robot.net_id),
+                axis.default_pid(),
+                AuthorityLimit(Newtons(limit)),
+                Replicate,
+            ))
+            .id();
+
+        (entity, PidController::default())
     });
+
+    cmds.insert_resource(StabilizeState { controller, axes });
 }
 
 fn stabalize_system(
     mut last_target: Local<Option<Quat>>,
     mut cmds: Commands,
-    robot: Res<LocalRobot>,
-    mut state: ResMut<StabilizeState>,
+    (robot, mut state): (Res<LocalRobot>, ResMut<StabilizeState>),
     robot_query: Query<(&Armed, &Orientation, &OrientationTarget)>,
-    entity_query: Query<&PidConfig>,
+    axis_query: Query<(&PidConfig, &AuthorityLimit)>,
+    mut contributions: Query<&mut MovementContribution>,
     time: Res<Time<Real>>,
 ) {
     let robot = robot_query.get(robot.entity);
-    let pitch_pid_config = entity_query.get(state.pitch).unwrap();
-    let roll_pid_config = entity_query.get(state.roll).unwrap();
-    let yaw_pid_config = entity_query.get(state.yaw).unwrap();
+    let StabilizeState { controller, axes } = &mut *state;
+    let mut contribution = contributions.get_mut(*controller).unwrap();
 
     if let Ok((&Armed::Armed, orientation, orientation_target)) = robot {
-        let error = orientation_target.0 * orientation.0.inverse();
-        let delta_target =
-            orientation_target.0 * last_target.unwrap_or(orientation_target.0).inverse();
-
-        // FIXME: Prefer roll over pitch
-        let pitch_error = instant_twist(error, orientation.0 * Vec3A::X).to_degrees();
-        let pitch_td = instant_twist(delta_target, orientation.0 * Vec3A::X).to_degrees();
-        let roll_error = instant_twist(error, orientation.0 * Vec3A::Y).to_degrees();
-        let roll_td = instant_twist(delta_target, orientation.0 * Vec3A::Y).to_degrees();
-        let yaw_error = instant_twist(error, orientation.0 * Vec3A::Z).to_degrees();
-        let yaw_td = instant_twist(delta_target, orientation.0 * Vec3A::Z).to_degrees();
-
-        let res_pitch =
-            state
-                .pitch_controller
-                .update(pitch_error, pitch_td, pitch_pid_config, time.delta());
-        let res_roll =
-            state
-                .roll_controller
-                .update(roll_error, roll_td, roll_pid_config, time.delta());
-        let res_yaw = state
-            .yaw_controller
-            .update(yaw_error, yaw_td, yaw_pid_config, time.delta());
-
-        let pitch_movement = Movement {
-            force: Vec3A::ZERO,
-            torque: /*orientation.0.inverse() **/ Vec3A::X * res_pitch.correction,
-        };
+        let mut controllers = axes.each_mut().map(|(entity, pid)| {
+            let (config, limit) = axis_query.get(*entity).unwrap();
+            (pid, config, *limit)
+        });
 
-        let roll_movement = Movement {
-            force: Vec3A::ZERO,
-            torque: /*orientation.0.inverse() **/ Vec3A::Y * res_roll.correction,
-        };
+        let (torque, results) = stabilize_torque(
+            orientation.rotation,
+            orientation_target.0,
+            *last_target,
+            &mut controllers,
+            time.delta(),
+        );
 
-        let yaw_movement = Movement {
+        let movement = Movement {
             force: Vec3A::ZERO,
-            torque: /*orientation.0.inverse() **/ Vec3A::Z * res_yaw.correction,
+            torque,
         };
 
-        cmds.entity(state.pitch)
-            .insert((MovementContribution(pitch_movement), res_pitch));
-        cmds.entity(state.roll)
-            .insert((MovementContribution(roll_movement), res_roll));
-        cmds.entity(state.yaw)
-            .insert((MovementContribution(yaw_movement), res_yaw));
+        // Written in place so the thrusters see it this frame
+        contribution.set_if_neq(MovementContribution(movement));
+        for ((entity, _), result) in axes.iter().zip(results) {
+            cmds.entity(*entity).insert(result);
+        }
+
         *last_target = Some(orientation_target.0);
     } else {
-        cmds.entity(state.pitch)
-            .remove::<(MovementContribution, PidResult)>();
-        cmds.entity(state.roll)
-            .remove::<(MovementContribution, PidResult)>();
-        cmds.entity(state.yaw)
-            .remove::<(MovementContribution, PidResult)>();
-
-        state.pitch_controller.reset_i();
-        state.roll_controller.reset_i();
-        state.yaw_controller.reset_i();
+        contribution.set_if_neq(MovementContribution(Movement::default()));
+
+        for (entity, pid) in axes.iter_mut() {
+            cmds.entity(*entity).remove::<PidResult>();
+            pid.reset_i();
+        }
+
         *last_target = None;
     }
 }
-fn instant_twist(q: Quat, twist_axis: Vec3A) -> f32 {
-    let rotation_axis = vec3a(q.x, q.y, q.z);
 
-    let sign = rotation_axis.dot(twist_axis).signum();
-    let projected = rotation_axis.project_onto(twist_axis);
-    let twist = Quat::from_xyzw(projected.x, projected.y, projected.z, q.w).normalize() * sign;
+/// Runs each axis' controller, returning the body frame torque towards `target` and each result
+///
+/// Axes are in the order of [`StabilizeAxis::ALL`]. Every axis' torque is clamped to its own
+/// authority limit so one saturated axis doesn't take authority from the others.
+fn stabilize_torque(
+    orientation: Quat,
+    target: Quat,
+    last_target: Option<Quat>,
+    axes: &mut [(&mut PidController, &PidConfig, AuthorityLimit); 3],
+    interval: Duration,
+) -> (Vec3A, [PidResult; 3]) {
+    // The rotation from where the robot is to the target, about the robot's own axes
+    let error = rotation_vector(orientation.inverse() * target);
 
-    let angle = twist.w.acos() * 2.0;
-    normalize_angle(angle)
+    // How far the target moved since last frame, also about the robot's axes
+    let target_delta = target * last_target.unwrap_or(target).inverse();
+    let target_delta = rotation_vector(orientation.inverse() * target_delta * orientation);
+
+    let mut torque = Vec3A::ZERO;
+    let results = StabilizeAxis::ALL.map(|axis| {
+        let (pid, config, AuthorityLimit(limit)) = &mut axes[axis as usize];
+
+        let body_axis = axis.body_axis();
+        let error = error.dot(body_axis).to_degrees();
+        let delta = target_delta.dot(body_axis).to_degrees();
+
+        let result = pid.update(error, delta, config, interval);
+        torque += body_axis * result.correction.clamp(-limit.0, limit.0);
+
+        result
+    });
+
+    (torque, results)
 }
 
-fn normalize_angle(angle: f32) -> f32 {
-    let wrapped_angle = modf(angle, TAU);
-    if wrapped_angle > PI {
-        wrapped_angle - TAU
-    } else {
-        wrapped_angle
-    }
+/// The shortest rotation `q` represents as an axis scaled by its angle in radians
+fn rotation_vector(q: Quat) -> Vec3A {
+    let q = if q.w < 0.0 { -q } else { q };
+
+    q.to_scaled_axis().into()
 }
 
-fn modf(a: f32, b: f32) -> f32 {
-    (a % b + b) % b
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use common::{
+        components::{AuthorityLimit, PidResult},
+        types::{units::Newtons, utils::PidController},
+    };
+    use glam::{EulerRot, Quat, Vec3A};
+
+    use super::{stabilize_torque, StabilizeAxis};
+
+    const STEP: Duration = Duration::from_millis(10);
+    /// Kilogram square meters about every axis
+    const INERTIA: f32 = 0.5;
+    /// Newton meters of drag per radian per second
+    const DRAG: f32 = 2.0;
+
+    /// Runs the controller against a rigid body for `seconds`, returning its final orientation
+    ///
+    /// `on_step` sees every torque the controller asks for
+    fn simulate(
+        start: Quat,
+        target: Quat,
+        limits: [f32; 3],
+        seconds: f32,
+        mut on_step: impl FnMut(Vec3A, &[PidResult; 3]),
+    ) -> Quat {
+        let configs = StabilizeAxis::ALL.map(|axis| axis.default_pid());
+        let mut pids = [PidController::default(); 3];
+
+        let mut orientation = start;
+        let mut velocity = Vec3A::ZERO;
+        let mut last_target = None;
+
+        for _ in 0..(seconds / STEP.as_secs_f32()) as usize {
+            let [pitch, roll, yaw] = &mut pids;
+            let mut axes = [
+                (pitch, &configs[0], AuthorityLimit(Newtons(limits[0]))),
+                (roll, &configs[1], AuthorityLimit(Newtons(limits[1]))),
+                (yaw, &configs[2], AuthorityLimit(Newtons(limits[2]))),
+            ];
+
+            let (torque, results) =
+                stabilize_torque(orientation, target, last_target, &mut axes, STEP);
+            on_step(torque, &results);
+            last_target = Some(target);
+
+            // Body frame, the axes are decoupled
+            let acceleration = (torque - velocity * DRAG) / INERTIA;
+            velocity += acceleration * STEP.as_secs_f32();
+            orientation = (orientation
+                * Quat::from_scaled_axis((velocity * STEP.as_secs_f32()).into()))
+            .normalize();
+        }
+
+        orientation
+    }
+
+    fn tilted() -> Quat {
+        Quat::from_euler(
+            EulerRot::ZYX,
+            45f32.to_radians(),
+            20f32.to_radians(),
+            30f32.to_radians(),
+        )
+    }
+
+    #[test]
+    fn converges_on_the_target() {
+        let target = Quat::from_rotation_z(90f32.to_radians());
+        let end = simulate(tilted(), target, [f32::INFINITY; 3], 15.0, |_, _| {});
+
+        assert!(end.angle_between(target).to_degrees() < 1.0, "{end:?}");
+    }
+
+    #[test]
+    fn torque_stays_within_authority() {
+        let limits = [2.0, 1.5, 1.0];
+        let target = Quat::IDENTITY;
+
+        let mut saturated = [false; 3];
+        let end = simulate(tilted(), target, limits, 20.0, |torque, results| {
+            for (axis, limit) in limits.into_iter().enumerate() {
+                assert!(torque[axis].abs() <= limit, "{torque:?}");
+
+                if results[axis].correction.abs() > limit {
+                    saturated[axis] = true;
+                }
+            }
+        });
+
+        // The limits were actually hit, yet it still gets there
+        assert_eq!(saturated, [true; 3]);
+        assert!(end.angle_between(target).to_degrees() < 1.0, "{end:?}");
+    }
+
+    #[test]
+    fn axes_are_limited_independently() {
+        // Mostly a yaw error with a little pitch
+        let start = Quat::from_euler(EulerRot::ZYX, 60f32.to_radians(), 0.0, 5f32.to_radians());
+
+        let mut first = None;
+        simulate(
+            start,
+            Quat::IDENTITY,
+            [f32::INFINITY, f32::INFINITY, 0.5],
+            STEP.as_secs_f32(),
+            |torque, results| first = Some((torque, results.clone())),
+        );
+        let (torque, results) = first.unwrap();
+
+        // Yaw is saturated, pitch still gets all it asks for
+        assert_eq!(torque.z.abs(), 0.5);
+        assert!(results[2].correction.abs() > 0.5);
+        assert_eq!(torque.x, results[0].correction);
+    }
 }
@@ -1,86 +1,310 @@
-use std::time::Duration;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use ahash::HashMap;
+use anyhow::anyhow;
 use bevy::prelude::*;
 use common::{
     bundles::{MotorBundle, PwmActuatorBundle, RobotActuatorBundle},
     components::{
-        ActualForce, ActualMovement, Armed, CurrentDraw, JerkLimit, MotorContribution,
-        MotorDefinition, Motors, MovementAxisMaximums, MovementContribution, MovementCurrentCap,
-        PwmChannel, PwmManualControl, PwmSignal, RobotId, TargetForce, TargetMovement,
+        ActiveContributions, ActualForce, ActualMovement, Armed, CenterOfMassOffset,
+        ContributionMuted, CurrentDraw, DisabledMotors, Estimated, JerkLimit, MotorConfigReport,
+        MotorContribution, MotorDataSource, MotorDataStatus, MotorDefinition, Motors,
+        MovementAxisMaximums, MovementContribution, MovementCurrentCap, PowerMode, PwmChannel,
+        PwmManualControl, PwmSignal, RobotId, TargetForce, TargetMovement,
     },
     ecs_sync::{NetId, Replicate},
-    types::units::Newtons,
+    error::ErrorEvent,
+    events::ReloadMotorData,
+    types::{
+        bounded::BoundedMap,
+        units::{Amperes, Newtons},
+    },
 };
+use glam::Vec3A;
 use motor_math::{
     blue_rov::HeavyMotorId,
-    motor_preformance::{self, Interpolation, MotorData, MotorRecord},
+    motor_preformance::{self, Interpolation, MotorData, MotorRecord, SkippedRecord},
     solve::{self, reverse},
     x3d::X3dMotorId,
-    Direction, ErasedMotorId, Movement,
+    ErasedMotorId, MotorConfig, Movement,
 };
 
 use crate::{
-    config::{MotorConfigDefinition, RobotConfig},
-    plugins::core::robot::{LocalRobot, LocalRobotMarker},
+    config::{MotorConfigDefinition, PowerModeConfig, RobotConfig, ThrusterDynamicsConfig},
+    plugins::{
+        actuators::motor_report::{self, NamedMotor},
+        core::{
+            arming::{AppPreArmCheckExt, CheckResult, CheckSeverity},
+            robot::{LocalRobot, LocalRobotMarker},
+        },
+        sensors::motor_current::CurrentCorrection,
+    },
 };
 
 pub struct ThrusterPlugin;
 
 impl Plugin for ThrusterPlugin {
     fn build(&self, app: &mut App) {
-        // FIXME(low): This is kinda bad
-        let motor_data =
-            motor_preformance::read_motor_data("motor_data.csv").expect("Read motor data");
-
         // TODO(mid): Update motor config when motor definitions change
-        app.add_systems(Startup, (create_motors, setup_motor_math))
+        app.add_systems(Startup, (create_motors, setup_motor_math, setup_motor_data))
             .add_systems(
                 Update,
                 (
-                    update_axis_maximums,
-                    accumulate_movements,
-                    accumulate_motor_forces.after(accumulate_movements),
+                    rebuild_motor_config,
+                    reload_motor_data,
+                    limit_current_for_power,
+                    update_axis_maximums
+                        .after(rebuild_motor_config)
+                        .after(reload_motor_data)
+                        .after(limit_current_for_power),
+                    report_motor_config.after(reload_motor_data),
+                    accumulate_movements.in_set(AccumulateMovementsSet),
+                    accumulate_motor_forces.after(AccumulateMovementsSet),
                 ),
             )
-            .insert_resource(MotorDataRes(motor_data));
+            .add_pre_arm_check(
+                "Motor config",
+                CheckSeverity::Critical,
+                motor_config_present,
+            )
+            .add_pre_arm_check(
+                "Motor config checks",
+                CheckSeverity::Critical,
+                motor_config_checked,
+            )
+            .add_pre_arm_check("Motor data", CheckSeverity::Advisory, motor_data_loaded);
+    }
+}
+
+/// Sums the movement contributions, sources should update theirs before it to avoid a frame of
+/// latency
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AccumulateMovementsSet;
+
+/// Where motor data is looked for after the path from the robot config, first usable file wins
+const MOTOR_DATA_PATHS: &[&str] = &[
+    "motor_data.csv",
+    "robot/motor_data.csv",
+    "/home/pi/mate/motor_data.csv",
+];
+
+/// Skipped rows listed in [`MotorDataStatus::error`] before the rest are summarized
+const MAX_REPORTED_SKIPS: usize = 3;
+
+fn motor_data_candidates(config: &RobotConfig) -> Vec<PathBuf> {
+    config
+        .motor_data
+        .iter()
+        .cloned()
+        .chain(MOTOR_DATA_PATHS.iter().map(PathBuf::from))
+        .collect()
+}
+
+/// Loads the first usable motor data file from `candidates`
+///
+/// Falls back to the compiled in table rather than failing, the robot should still drive with
+/// degraded thrust estimates
+fn load_motor_data(candidates: &[PathBuf]) -> (MotorData, MotorDataStatus) {
+    let mut errors = Vec::new();
+
+    for path in candidates {
+        match motor_preformance::read_motor_data_lenient(path) {
+            Ok(file) => {
+                let error = describe_skipped(path, &file.skipped);
+                if let Some(error) = &error {
+                    warn!("{error}");
+                }
+
+                info!("Loaded motor data from {}", path.display());
+
+                let status = MotorDataStatus {
+                    source: MotorDataSource::FileOk(path.display().to_string()),
+                    error,
+                };
+
+                return (file.data, status);
+            }
+            Err(err) => {
+                warn!("Could not load motor data from {}: {err:#}", path.display());
+                errors.push(format!("{}: {err:#}", path.display()));
+            }
+        }
+    }
+
+    error!("No usable motor data, using the built in table. Thrust calibration is degraded");
+
+    let status = MotorDataStatus {
+        source: MotorDataSource::Fallback,
+        error: Some(errors.join("\n")),
+    };
+
+    (motor_preformance::fallback_motor_data(), status)
+}
+
+fn describe_skipped(path: &Path, skipped: &[SkippedRecord]) -> Option<String> {
+    if skipped.is_empty() {
+        return None;
+    }
+
+    let mut error = format!(
+        "Skipped {} malformed rows in {}",
+        skipped.len(),
+        path.display()
+    );
+    for record in skipped.iter().take(MAX_REPORTED_SKIPS) {
+        error.push_str(&format!("\nLine {}: {}", record.line, record.error));
+    }
+    if skipped.len() > MAX_REPORTED_SKIPS {
+        error.push_str(&format!("\n{} more", skipped.len() - MAX_REPORTED_SKIPS));
+    }
+
+    Some(error)
+}
+
+fn setup_motor_data(mut cmds: Commands, config: Res<RobotConfig>, robot: Res<LocalRobot>) {
+    let (motor_data, status) = load_motor_data(&motor_data_candidates(&config));
+    let motor_data = motor_data.with_extrapolation(config.motor_extrapolation);
+
+    cmds.insert_resource(MotorDataRes(motor_data));
+    cmds.entity(robot.entity).insert(status);
+}
+
+fn reload_motor_data(
+    mut cmds: Commands,
+    mut events: EventReader<ReloadMotorData>,
+    config: Res<RobotConfig>,
+    robot: Res<LocalRobot>,
+) {
+    if events.read().count() == 0 {
+        return;
+    }
+
+    info!("Reloading motor data");
+
+    let (motor_data, status) = load_motor_data(&motor_data_candidates(&config));
+    let motor_data = motor_data.with_extrapolation(config.motor_extrapolation);
+
+    cmds.insert_resource(MotorDataRes(motor_data));
+    cmds.entity(robot.entity).insert(status);
+}
+
+fn motor_data_loaded(robot: Query<&MotorDataStatus, With<LocalRobotMarker>>) -> CheckResult {
+    match robot.get_single() {
+        Ok(MotorDataStatus {
+            source: MotorDataSource::Fallback,
+            ..
+        }) => Err("Using built in motor data, thrust calibration is degraded".to_owned()),
+        Ok(_) => Ok(()),
+        Err(_) => Err("Motor data status missing".to_owned()),
+    }
+}
+
+fn motor_config_present(robot: Query<&Motors, With<LocalRobotMarker>>) -> CheckResult {
+    match robot.get_single() {
+        Ok(Motors(motor_config)) if motor_config.motor_count() > 0 => Ok(()),
+        Ok(_) => Err("No motors configured".to_owned()),
+        Err(_) => Err("Motor config missing".to_owned()),
+    }
+}
+
+fn motor_config_checked(robot: Query<&MotorConfigReport, With<LocalRobotMarker>>) -> CheckResult {
+    match robot.get_single() {
+        Ok(report) if report.errors.is_empty() => Ok(()),
+        Ok(report) => Err(report.errors.join(", ")),
+        Err(_) => Err("Motor config not checked yet".to_owned()),
     }
 }
 
 #[derive(Resource)]
 pub struct MotorDataRes(pub MotorData);
 
+/// Motor config as generated from the robot config, before any [`CenterOfMassOffset`] or
+/// [`DisabledMotors`]
+///
+/// Kept so re-enabled motors can be added back
+#[derive(Resource)]
+struct BaseMotorConfig(MotorConfig<ErasedMotorId>);
+
+/// The parts of the [`MotorConfigReport`] that only depend on the robot config
+#[derive(Resource)]
+struct BaseMotorReport(MotorConfigReport);
+
+/// Current the motor data table predicts for the motor's current command
+///
+/// The replicated [`CurrentDraw`] is estimated from this and the measured battery current
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct ExpectedCurrent(pub Amperes);
+
 fn create_motors(mut cmds: Commands, robot: Res<LocalRobot>, config: Res<RobotConfig>) {
     let (motors, motor_config) = config.motor_config.flatten(config.center_of_mass);
 
     info!("Generating motor config");
 
-    cmds.entity(robot.entity).insert(RobotActuatorBundle {
-        movement_target: TargetMovement(Default::default()),
-        movement_actual: ActualMovement(Default::default()),
-        motor_config: Motors(motor_config),
-        axis_maximums: MovementAxisMaximums(Default::default()),
-        current_cap: MovementCurrentCap(config.motor_amperage_budget.into()),
-        armed: Armed::Disarmed,
-    });
-
-    for (motor_id, motor, pwm_channel) in motors {
-        let name = match config.motor_config {
-            MotorConfigDefinition::X3d(_) => {
-                format!(
-                    "{:?} ({motor_id})",
-                    X3dMotorId::try_from(motor_id).expect("Bad motor id for config")
-                )
-            }
-            MotorConfigDefinition::BlueRov(_) => {
-                format!(
-                    "{:?} ({motor_id})",
-                    HeavyMotorId::try_from(motor_id).expect("Bad motor id for config")
-                )
+    let mut motors = motors
+        .map(|(motor_id, motor, pwm_channel)| {
+            let name = match config.motor_config {
+                MotorConfigDefinition::X3d(_) => {
+                    format!(
+                        "{:?} ({motor_id})",
+                        X3dMotorId::try_from(motor_id).expect("Bad motor id for config")
+                    )
+                }
+                MotorConfigDefinition::BlueRov(_) => {
+                    format!(
+                        "{:?} ({motor_id})",
+                        HeavyMotorId::try_from(motor_id).expect("Bad motor id for config")
+                    )
+                }
+                MotorConfigDefinition::Custom(_) => format!("Motor {motor_id}"),
+            };
+
+            NamedMotor {
+                name,
+                id: motor_id,
+                motor,
+                pwm_channel,
             }
-            MotorConfigDefinition::Custom(_) => format!("Motor {motor_id}"),
-        };
+        })
+        .collect::<Vec<_>>();
+
+    // Problems are kept for the pre-arm checks instead of stopping the robot
+    let mut report = MotorConfigReport {
+        errors: config.channel_conflicts(),
+        ..default()
+    };
+    motor_report::normalize_orientations(&mut motors, &mut report);
+    motor_report::report_motors(&motors, motor_config.center_mass(), &mut report);
+
+    let motor_config = MotorConfig::new_raw(
+        motors.iter().map(|it| (it.id, it.motor)),
+        motor_config.center_mass(),
+    );
+
+    cmds.entity(robot.entity).insert((
+        RobotActuatorBundle {
+            movement_target: TargetMovement(Default::default()),
+            movement_actual: ActualMovement(Default::default()),
+            motor_config: Motors(motor_config.clone()),
+            axis_maximums: MovementAxisMaximums(Default::default()),
+            current_cap: MovementCurrentCap(config.motor_amperage_budget.into()),
+            armed: Armed::Disarmed,
+        },
+        CenterOfMassOffset::default(),
+        DisabledMotors::default(),
+    ));
+    cmds.insert_resource(BaseMotorConfig(motor_config));
+    cmds.insert_resource(BaseMotorReport(report));
 
+    for NamedMotor {
+        name,
+        id: motor_id,
+        motor,
+        pwm_channel,
+    } in motors
+    {
         cmds.spawn((
             MotorBundle {
                 actuator: PwmActuatorBundle {
@@ -93,7 +317,9 @@ fn create_motors(mut cmds: Commands, robot: Res<LocalRobot>, config: Res<RobotCo
                 target_force: TargetForce(0.0f32.into()),
                 actual_force: ActualForce(0.0f32.into()),
                 current_draw: CurrentDraw(0.0f32.into()),
+                estimated: Estimated,
             },
+            ExpectedCurrent(0.0f32.into()),
             Replicate,
         ));
     }
@@ -104,15 +330,91 @@ fn setup_motor_math(mut cmds: Commands, config: Res<RobotConfig>, robot: Res<Loc
         .insert(JerkLimit(config.jerk_limit));
 }
 
+type MotorTrim<'a> = (Entity, &'a CenterOfMassOffset, Option<&'a DisabledMotors>);
+type MotorTrimChanged = (
+    With<LocalRobotMarker>,
+    Or<(Changed<CenterOfMassOffset>, Changed<DisabledMotors>)>,
+);
+
+/// Rebuilds the motor config about the trimmed center of mass and without the disabled motors
+fn rebuild_motor_config(
+    mut cmds: Commands,
+    robot: Query<MotorTrim<'static>, MotorTrimChanged>,
+    base: Res<BaseMotorConfig>,
+    mut errors: EventWriter<ErrorEvent>,
+) {
+    for (entity, &CenterOfMassOffset(offset), disabled) in &robot {
+        let base = &base.0;
+
+        // The solver can't do anything with a motor at NaN, the last motor config is kept
+        if !offset.is_finite() {
+            errors.send(anyhow!("Center of mass offset {offset} is not finite").into());
+            continue;
+        }
+
+        // Keep the configured frame until there is something to trim
+        let mut motor_config = if offset == Vec3A::ZERO {
+            base.clone()
+        } else {
+            base.recenter(base.center_mass() + offset)
+        };
+
+        info!("Center of mass offset set to {offset}");
+
+        if let Some(DisabledMotors(disabled)) = disabled {
+            if !base.motors().any(|(id, _)| !disabled.contains(id)) {
+                // Nothing left to solve with, the allocator still holds every motor at neutral
+                warn!("Every motor is disabled, keeping the full motor config");
+            } else if !disabled.is_empty() {
+                motor_config = motor_config.without_motors(disabled);
+
+                let mut disabled: Vec<_> = disabled.iter().collect();
+                disabled.sort();
+                warn!("Motors {disabled:?} disabled, solving around them");
+            }
+        }
+
+        cmds.entity(entity).insert(Motors(motor_config));
+    }
+}
+
+/// Share of the motor amperage budget available in `power`
+fn power_current_cap(budget: f32, power: PowerMode, config: &PowerModeConfig) -> Amperes {
+    let share = match power {
+        PowerMode::Normal => 1.0,
+        PowerMode::LowPower => config.low_power_current,
+        PowerMode::Critical => config.critical_current,
+    };
+
+    Amperes(budget * share)
+}
+
+type PowerModeChanged = (With<LocalRobotMarker>, Changed<PowerMode>);
+
+fn limit_current_for_power(
+    mut robot: Query<(&PowerMode, &mut MovementCurrentCap), PowerModeChanged>,
+    config: Res<RobotConfig>,
+) {
+    for (&power, mut current_cap) in &mut robot {
+        let cap = power_current_cap(config.motor_amperage_budget, power, &config.power_mode);
+
+        if current_cap.0 != cap {
+            info!("Motor current cap set to {cap} for {power:?}");
+            current_cap.0 = cap;
+        }
+    }
+}
+
 fn update_axis_maximums(
     mut cmds: Commands,
-    robot: Query<
-        (Entity, &MovementCurrentCap, &Motors),
-        (With<LocalRobotMarker>, Changed<MovementCurrentCap>),
-    >,
+    robot: Query<(Entity, Ref<MovementCurrentCap>, Ref<Motors>), With<LocalRobotMarker>>,
     motor_data: Res<MotorDataRes>,
 ) {
     for (entity, current_cap, motor_config) in &robot {
+        if !(motor_data.is_changed() || current_cap.is_changed() || motor_config.is_changed()) {
+            continue;
+        }
+
         let motor_config = &motor_config.0;
         let motor_data = &motor_data.0;
         let current_cap = current_cap.0 .0;
@@ -120,58 +422,257 @@ fn update_axis_maximums(
         let maximums = reverse::axis_maximums(motor_config, motor_data, current_cap, 0.01)
             .into_iter()
             .map(|(key, value)| (key, Newtons(value)))
-            .collect();
+            .collect::<BTreeMap<_, _>>();
 
         info!("Updated motor axis maximums to {maximums:?} at {current_cap:.2}A");
 
-        cmds.entity(entity).insert(MovementAxisMaximums(maximums));
+        match BoundedMap::try_from(maximums) {
+            Ok(maximums) => {
+                cmds.entity(entity).insert(MovementAxisMaximums(maximums));
+            }
+            Err(err) => error!("Could not replicate motor axis maximums: {err}"),
+        }
+    }
+}
+
+/// Finishes the [`MotorConfigReport`] with the configured motors and the current motor data
+fn report_motor_config(
+    mut cmds: Commands,
+    robot: Res<LocalRobot>,
+    config: Res<RobotConfig>,
+    base_config: Res<BaseMotorConfig>,
+    base_report: Res<BaseMotorReport>,
+    motor_data: Res<MotorDataRes>,
+) {
+    if !motor_data.is_changed() {
+        return;
+    }
+
+    let maximums = reverse::axis_maximums(
+        &base_config.0,
+        &motor_data.0,
+        config.motor_amperage_budget,
+        0.01,
+    );
+
+    let mut report = base_report.0.clone();
+    motor_report::check_axes(
+        &base_config.0,
+        &maximums,
+        &config.required_axes(),
+        &mut report,
+    );
+    motor_report::check_motor_data(&motor_data.0, &mut report);
+
+    info!(
+        "Motor config at {:.2}A:\n{}",
+        config.motor_amperage_budget,
+        motor_report::format_report(&report)
+    );
+    for warning in &report.warnings {
+        warn!("Motor config: {warning}");
+    }
+    for error in &report.errors {
+        error!("Motor config: {error}");
+    }
+
+    cmds.entity(robot.entity).insert(report);
+}
+
+/// Buffers reused every frame by the solvers, one entry per motor in `MotorConfig::index_of` order
+#[derive(Default)]
+struct SolverScratch {
+    forces: Vec<f32>,
+    actual_forces: Vec<f32>,
+    motor_cmds: Vec<MotorRecord>,
+    /// Motors with at least one contribution this frame
+    contributed: Vec<bool>,
+}
+
+impl SolverScratch {
+    fn reset(&mut self, motor_count: usize) {
+        self.forces.clear();
+        self.forces.resize(motor_count, 0.0);
+        self.actual_forces.clear();
+        self.actual_forces.resize(motor_count, 0.0);
+        self.motor_cmds.clear();
+        self.motor_cmds.resize(motor_count, MotorRecord::default());
+        self.contributed.clear();
+        self.contributed.resize(motor_count, false);
     }
 }
 
+type AutomaticControl = (With<LocalRobotMarker>, Without<PwmManualControl>);
+
+type MovementState<'a> = (
+    Entity,
+    &'a NetId,
+    &'a Motors,
+    Option<&'a ActiveContributions>,
+);
+type MovementSources<'a> = (
+    Entity,
+    &'a RobotId,
+    &'a MovementContribution,
+    Option<&'a Name>,
+    Has<ContributionMuted>,
+);
+
 fn accumulate_movements(
     mut cmds: Commands,
-    robot: Query<(Entity, &NetId, &Motors), (With<LocalRobotMarker>, Without<PwmManualControl>)>,
-    movements: Query<(&RobotId, &MovementContribution)>,
+    mut scratch: Local<SolverScratch>,
+    robot: Query<MovementState<'static>, AutomaticControl>,
+    movements: Query<MovementSources<'static>>,
 
     motor_data: Res<MotorDataRes>,
 ) {
-    let Ok((entity, net_id, Motors(motor_config))) = robot.get_single() else {
+    let Ok((entity, net_id, Motors(motor_config), last_active)) = robot.get_single() else {
         return;
     };
     let mut robot = cmds.entity(entity);
 
+    let contributions = movements
+        .iter()
+        .filter(|(_, RobotId(robot_net_id), ..)| robot_net_id == net_id)
+        .map(|(entity, _, movement, name, muted)| {
+            let name = match name {
+                Some(name) => name.as_str().to_owned(),
+                None => format!("Unnamed ({entity:?})"),
+            };
+
+            (name, movement.0, muted)
+        });
+    let (total_movement, active) = sum_contributions(contributions);
+
+    // Avoid replicating the summary when nothing changed
+    if last_active.map(|it| &it.0) != Some(&active) {
+        robot.insert(ActiveContributions(active));
+    }
+
+    scratch.reset(motor_config.motor_count());
+    let SolverScratch {
+        forces, motor_cmds, ..
+    } = &mut *scratch;
+
+    reverse::reverse_solve_into(total_movement, motor_config, forces);
+    reverse::forces_to_cmds_into(
+        forces,
+        motor_config,
+        &motor_data.0,
+        motor_cmds,
+        Interpolation::Lerp,
+    );
+    let forces = motor_config
+        .motors()
+        .zip(motor_cmds.iter())
+        .map(|((motor, _), cmd)| (*motor, cmd.force.into()))
+        .collect::<BTreeMap<_, _>>();
+
+    match BoundedMap::try_from(forces) {
+        Ok(forces) => {
+            robot.insert(MotorContribution(forces));
+        }
+        Err(err) => error!("Could not replicate motor forces: {err}"),
+    }
+}
+
+/// Sums the unmuted contributions, also returns the ones that were applied sorted by name
+fn sum_contributions(
+    contributions: impl IntoIterator<Item = (String, Movement, bool)>,
+) -> (Movement, Vec<(String, Movement)>) {
     let mut total_movement = Movement::default();
+    let mut active = Vec::new();
 
-    for (RobotId(robot_net_id), movement) in &movements {
-        if robot_net_id == net_id {
-            total_movement += movement.0;
+    for (name, movement, muted) in contributions {
+        if muted {
+            continue;
         }
+
+        total_movement += movement;
+        active.push((name, movement));
     }
 
-    let forces = solve::reverse::reverse_solve(total_movement, motor_config);
-    let motor_cmds = solve::reverse::forces_to_cmds(forces, motor_config, &motor_data.0);
-    let forces = motor_cmds
-        .into_iter()
-        .map(|(motor, cmd)| (motor, cmd.force.into()))
-        .collect();
+    // Query order is not stable
+    active.sort_by(|(a, _), (b, _)| a.cmp(b));
 
-    robot.insert(MotorContribution(forces));
+    (total_movement, active)
 }
 
+/// First order model of the force each thruster is producing, one entry per motor in
+/// `MotorConfig::index_of` order
+///
+/// Thrusters take around 100ms to spool, reporting the modeled forces keeps the surface's view of
+/// the robot from leading what it is actually doing
+#[derive(Default)]
+struct ThrusterDynamics {
+    forces: Vec<f32>,
+}
+
+impl ThrusterDynamics {
+    /// Moves each modeled force `dt` seconds towards its command and returns the modeled forces
+    fn step(&mut self, commanded: &[f32], config: &ThrusterDynamicsConfig, dt: f32) -> &[f32] {
+        if !config.enabled || self.forces.len() != commanded.len() {
+            self.forces.clear();
+            self.forces.resize(commanded.len(), 0.0);
+        }
+
+        if !config.enabled {
+            self.forces.copy_from_slice(commanded);
+            return &self.forces;
+        }
+
+        for (force, &command) in self.forces.iter_mut().zip(commanded) {
+            // Reversing has to slow the propeller down first
+            let spooling_up = command * *force >= 0.0 && command.abs() > force.abs();
+            let time_constant = if spooling_up {
+                config.spool_up
+            } else {
+                config.spool_down
+            };
+
+            let blend = if time_constant > 0.0 {
+                1.0 - (-dt / time_constant).exp()
+            } else {
+                1.0
+            };
+
+            *force += (command - *force) * blend;
+        }
+
+        &self.forces
+    }
+
+    /// Disarmed thrusters stop spinning
+    fn reset(&mut self) {
+        self.forces.fill(0.0);
+    }
+}
+
+type MotorState<'a> = (
+    Entity,
+    &'a NetId,
+    &'a Motors,
+    &'a MovementCurrentCap,
+    &'a JerkLimit,
+    &'a Armed,
+    Option<&'a DisabledMotors>,
+);
+
 // TODO(mid): Split into smaller systems
 fn accumulate_motor_forces(
     mut cmds: Commands,
-    mut last_movement: Local<HashMap<ErasedMotorId, MotorRecord>>,
+    (mut scratch, mut last_movement, mut dynamics): (
+        Local<SolverScratch>,
+        Local<Vec<MotorRecord>>,
+        Local<ThrusterDynamics>,
+    ),
 
-    robot: Query<
-        (Entity, &NetId, &Motors, &MovementCurrentCap, &JerkLimit),
-        (With<LocalRobotMarker>, Without<PwmManualControl>),
-    >,
+    robot: Query<MotorState<'static>, AutomaticControl>,
     motor_forces: Query<(&RobotId, &MotorContribution)>,
     motors: Query<(Entity, &MotorDefinition, &RobotId)>,
 
-    time: Res<Time<Real>>,
-    motor_data: Res<MotorDataRes>,
+    (time, config, motor_data): (Res<Time<Real>>, Res<RobotConfig>, Res<MotorDataRes>),
+    correction: Option<Res<CurrentCorrection>>,
 ) {
     let Ok((
         entity,
@@ -179,123 +680,729 @@ fn accumulate_motor_forces(
         Motors(motor_config),
         &MovementCurrentCap(current_cap),
         &JerkLimit(jerk_limit),
+        armed,
+        disabled,
     )) = robot.get_single()
     else {
         return;
     };
     let mut robot = cmds.entity(entity);
 
-    let mut all_forces = HashMap::default();
+    // The table's currents are scaled to match what was actually measured
+    let current_cap = match correction {
+        Some(correction) => current_cap.0 / correction.0,
+        None => current_cap.0,
+    };
+
+    let motor_count = motor_config.motor_count();
+    scratch.reset(motor_count);
+    let SolverScratch {
+        forces,
+        actual_forces,
+        motor_cmds,
+        contributed,
+    } = &mut *scratch;
 
     for (&RobotId(robot_net_id), motor_force_contributions) in &motor_forces {
         if robot_net_id == net_id {
             for (motor, force) in &motor_force_contributions.0 {
-                *all_forces.entry(*motor).or_default() += force.0;
+                if let Some(idx) = motor_config.index_of(motor) {
+                    forces[idx] += force.0;
+                    contributed[idx] = true;
+                }
             }
         }
     }
 
-    let target_movement = solve::forward::forward_solve(motor_config, &all_forces);
+    let target_movement = solve::forward::forward_solve_slice(motor_config, forces);
     robot.insert(TargetMovement(target_movement));
 
-    let motor_cmds = all_forces
-        .iter()
-        .map(|(motor, force)| {
-            let direction = motor_config
-                .motor(motor)
-                .map(|it| it.direction)
-                .unwrap_or(Direction::Clockwise);
-
-            (
-                *motor,
-                motor_data
-                    .0
-                    .lookup_by_force(*force, Interpolation::LerpDirection(direction)),
-            )
-        })
-        .collect();
-
-    let motor_cmds = solve::reverse::clamp_amperage(
+    let interpolation = config.motor_interpolation();
+    reverse::forces_to_cmds_into(
+        forces,
+        motor_config,
+        &motor_data.0,
+        motor_cmds,
+        interpolation,
+    );
+    reverse::clamp_amperage_in_place(
         motor_cmds,
         motor_config,
         &motor_data.0,
-        current_cap.0,
+        current_cap,
         0.05,
+        interpolation,
     );
 
     // Implement slew rate limiting
-    let motor_cmds = {
-        let slew_motor_cmds = motor_cmds
-            .iter()
-            .map(|(motor, record)| {
-                if let Some(last) = last_movement.get(motor) {
-                    let jerk_limit = jerk_limit * time.delta_seconds();
-                    let delta = record.force - last.force;
-
-                    if delta.abs() > jerk_limit {
-                        let direction = motor_config
-                            .motor(motor)
-                            .map(|it| it.direction)
-                            .unwrap_or(Direction::Clockwise);
-
-                        let clamped = delta.clamp(-jerk_limit, jerk_limit);
-                        let new_record = motor_data.0.lookup_by_force(
-                            clamped + last.force,
-                            Interpolation::LerpDirection(direction),
-                        );
-
-                        return (*motor, new_record);
-                    }
-                };
-
-                (*motor, *record)
-            })
-            .collect();
+    if last_movement.len() == motor_count {
+        reverse::limit_jerk_in_place(
+            motor_cmds,
+            &last_movement,
+            motor_config,
+            &motor_data.0,
+            jerk_limit * time.delta_seconds(),
+            interpolation,
+        );
 
-        solve::reverse::clamp_amperage(
-            slew_motor_cmds,
+        reverse::clamp_amperage_in_place(
+            motor_cmds,
             motor_config,
             &motor_data.0,
-            current_cap.0,
+            current_cap,
             0.05,
-        )
-    };
+            interpolation,
+        );
+    }
 
-    let motor_forces = motor_cmds
-        .iter()
-        .map(|(motor, data)| (*motor, data.force))
-        .collect();
+    for (force, data) in actual_forces.iter_mut().zip(motor_cmds.iter()) {
+        *force = data.force;
+    }
+
+    let dynamics_config = &config.thruster_dynamics;
+    if dynamics_config.enabled && *armed == Armed::Disarmed {
+        dynamics.reset();
+        actual_forces.fill(0.0);
+    } else {
+        let modeled = dynamics.step(actual_forces, dynamics_config, time.delta_seconds());
+        actual_forces.copy_from_slice(modeled);
+    }
 
-    let actual_movement = solve::forward::forward_solve(motor_config, &motor_forces);
+    let actual_movement = solve::forward::forward_solve_slice(motor_config, actual_forces);
     robot.insert(ActualMovement(actual_movement));
 
     for (motor_entity, MotorDefinition(id, _motor), &RobotId(robot_net_id)) in &motors {
         if robot_net_id == net_id {
             let mut motor = cmds.entity(motor_entity);
 
-            // FIXME(mid): panics
-            let target_force = all_forces.get(id);
-            let actual_data = motor_cmds.get(id);
-
             // TODO(mid): Special case for 0
 
-            if let (Some(target_force), Some(actual_data)) = (target_force, actual_data) {
+            // The motor config lags a frame behind a change to the disabled motors
+            let is_disabled = disabled.is_some_and(|DisabledMotors(it)| it.contains(id));
+            let idx = motor_config
+                .index_of(id)
+                .filter(|&idx| contributed[idx] && !is_disabled);
+
+            if let Some(idx) = idx {
+                let actual_data = motor_cmds[idx];
+
                 motor.insert((
-                    TargetForce((*target_force).into()),
-                    ActualForce(actual_data.force.into()),
-                    CurrentDraw(actual_data.current.into()),
+                    TargetForce(forces[idx].into()),
+                    ActualForce(actual_forces[idx].into()),
+                    ExpectedCurrent(actual_data.current.into()),
                     PwmSignal(Duration::from_micros(actual_data.pwm as u64)),
                 ));
             } else {
                 motor.insert((
                     TargetForce(0.0.into()),
                     ActualForce(0.0.into()),
-                    CurrentDraw(0.0.into()),
+                    ExpectedCurrent(0.0.into()),
                     PwmSignal(Duration::from_micros(1500)),
                 ));
             }
         }
     }
 
-    *last_movement = motor_cmds;
+    last_movement.clear();
+    last_movement.extend_from_slice(motor_cmds);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, HashSet},
+        fs,
+        time::{Duration, Instant},
+    };
+
+    use bevy::{app::App, ecs::system::RunSystemOnce, prelude::*};
+    use common::{
+        components::{
+            ActiveContributions, Armed, CenterOfMassOffset, Depth, DepthTarget, DisabledMotors,
+            MotorContribution, MotorDataSource, MotorDataStatus, MotorDefinition, Motors,
+            MovementAxisMaximums, MovementCurrentCap, Orientation, OrientationTarget, PowerMode,
+            PwmSignal, RobotId,
+        },
+        ecs_sync::NetId,
+        error::ErrorEvent,
+        types::{
+            hw::DepthFrame,
+            units::{Amperes, Meters, Newtons},
+        },
+    };
+    use glam::{vec3a, EulerRot, Vec3A};
+    use motor_math::{
+        motor_preformance::{self, Extrapolation, Interpolation},
+        solve::reverse::Axis,
+        x3d::X3dMotorId,
+        ErasedMotorId, Movement,
+    };
+
+    use super::{
+        accumulate_motor_forces, accumulate_movements, create_motors, limit_current_for_power,
+        load_motor_data, rebuild_motor_config, setup_motor_data, setup_motor_math,
+        sum_contributions, update_axis_maximums, AccumulateMovementsSet, MotorDataRes,
+        ThrusterDynamics,
+    };
+    use crate::{
+        config::{RobotConfig, ThrusterDynamicsConfig},
+        plugins::{
+            actuators::{depth_hold::DepthHoldPlugin, stabilize::StabilizePlugin},
+            core::robot::{LocalRobot, LocalRobotMarker},
+        },
+    };
+
+    const MOTOR_DATA: &str = "\
+        pwm,rpm,current,voltage,power,force,efficiency\n\
+        1100,2976,17.03,12,204.4,-28.449,14.2\n\
+        1500,0,0,12,0,0,0\n\
+        1900,2995,16.91,12,202.9,36.3951,18.3\n";
+
+    fn movement(x: f32, yaw: f32) -> Movement {
+        Movement {
+            force: vec3a(x, 0.0, 0.0),
+            torque: vec3a(0.0, 0.0, yaw),
+        }
+    }
+
+    #[test]
+    fn muted_contributions_are_skipped() {
+        let (total, active) = sum_contributions([
+            ("Gamepad".to_owned(), movement(0.5, 0.0), false),
+            ("Pipeline".to_owned(), movement(10.0, 3.0), true),
+            ("Stabilize Yaw".to_owned(), movement(0.0, 0.25), false),
+        ]);
+
+        assert_eq!(total, movement(0.5, 0.25));
+        assert!(active.iter().all(|(name, _)| name != "Pipeline"));
+    }
+
+    #[test]
+    fn summary_lists_applied_sources() {
+        let (total, active) = sum_contributions([
+            ("Stabilize Yaw".to_owned(), movement(0.0, 0.25), false),
+            ("Gamepad".to_owned(), movement(0.5, 0.0), false),
+            ("Muted".to_owned(), movement(1.0, 0.0), true),
+        ]);
+
+        assert_eq!(total, movement(0.5, 0.25));
+        assert_eq!(
+            active,
+            [
+                ("Gamepad".to_owned(), movement(0.5, 0.0)),
+                ("Stabilize Yaw".to_owned(), movement(0.0, 0.25)),
+            ]
+        );
+
+        let (total, active) = sum_contributions([]);
+        assert_eq!(total, Movement::default());
+        assert!(active.is_empty());
+    }
+
+    #[test]
+    fn motor_data_is_loaded_from_the_first_usable_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.csv");
+        let malformed = dir.path().join("malformed.csv");
+        let good = dir.path().join("good.csv");
+        let later = dir.path().join("later.csv");
+        fs::write(&malformed, "pwm,rpm\n1500,oops\n").unwrap();
+        fs::write(&good, MOTOR_DATA).unwrap();
+        fs::write(&later, MOTOR_DATA).unwrap();
+
+        let (_, status) = load_motor_data(&[missing, malformed, good.clone(), later]);
+
+        assert_eq!(
+            status,
+            MotorDataStatus {
+                source: MotorDataSource::FileOk(good.display().to_string()),
+                error: None,
+            }
+        );
+    }
+
+    #[test]
+    fn skipped_rows_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("motor_data.csv");
+        fs::write(&path, format!("{MOTOR_DATA}1600,bad,0.5,12,6,3.8,65.8\n")).unwrap();
+
+        let (_, status) = load_motor_data(std::slice::from_ref(&path));
+
+        assert_eq!(
+            status.source,
+            MotorDataSource::FileOk(path.display().to_string())
+        );
+        let error = status.error.unwrap();
+        assert!(error.starts_with("Skipped 1 malformed rows"), "{error}");
+        assert!(error.contains("Line 5:"), "{error}");
+    }
+
+    #[test]
+    fn falls_back_when_nothing_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.csv");
+        let empty = dir.path().join("empty.csv");
+        fs::write(&empty, "").unwrap();
+
+        let (motor_data, status) = load_motor_data(&[missing.clone(), empty.clone()]);
+
+        assert_eq!(status.source, MotorDataSource::Fallback);
+        assert!(status.is_degraded());
+        let error = status.error.unwrap();
+        assert!(error.contains(&missing.display().to_string()), "{error}");
+        assert!(error.contains(&empty.display().to_string()), "{error}");
+
+        // The fallback table still drives the thrusters
+        let record = motor_data.lookup_by_force(10.0, Interpolation::Lerp);
+        assert!(record.pwm > 1600.0 && record.pwm < 1700.0);
+    }
+
+    #[test]
+    fn motor_data_uses_the_configured_extrapolation() {
+        let config: RobotConfig = toml::from_str(include_str!("../../../robot.toml")).unwrap();
+        assert_eq!(config.motor_extrapolation, Extrapolation::LinearExtrapolate);
+
+        let config: RobotConfig = toml::from_str(&format!(
+            "motor_extrapolation = \"ClampToEndpoints\"\n{}",
+            include_str!("../../../robot.toml")
+        ))
+        .unwrap();
+
+        let mut app = App::new();
+        app.insert_resource(config);
+        let robot = app.world.spawn_empty().id();
+        app.world.insert_resource(LocalRobot {
+            net_id: NetId::random(),
+            entity: robot,
+        });
+
+        app.world.run_system_once(setup_motor_data);
+
+        let motor_data = &app.world.resource::<MotorDataRes>().0;
+        assert_eq!(motor_data.extrapolation(), Extrapolation::ClampToEndpoints);
+    }
+
+    const DYNAMICS: ThrusterDynamicsConfig = ThrusterDynamicsConfig {
+        enabled: true,
+        spool_up: 0.1,
+        spool_down: 0.05,
+    };
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3, "{a} != {b}");
+    }
+
+    #[test]
+    fn step_command_spools_exponentially() {
+        let mut dynamics = ThrusterDynamics::default();
+
+        for step in 1..=30 {
+            let forces = dynamics.step(&[10.0, -4.0], &DYNAMICS, 0.01);
+            let t = step as f32 * 0.01;
+
+            assert_close(forces[0], 10.0 * (1.0 - (-t / 0.1).exp()));
+            assert_close(forces[1], -4.0 * (1.0 - (-t / 0.1).exp()));
+        }
+
+        // One time constant in the force is 63% of the way there
+        let mut dynamics = ThrusterDynamics::default();
+        let forces = dynamics.step(&[10.0], &DYNAMICS, 0.1);
+        assert_close(forces[0], 6.321);
+
+        // Spooling down uses its own time constant
+        let forces = dynamics.step(&[0.0], &DYNAMICS, 0.05);
+        assert_close(forces[0], 6.321 * (-1.0f32).exp());
+    }
+
+    #[test]
+    fn reversing_spools_down_first() {
+        let mut dynamics = ThrusterDynamics::default();
+        dynamics.step(&[10.0], &DYNAMICS, 10.0);
+
+        let forces = dynamics.step(&[-10.0], &DYNAMICS, 0.05);
+        assert_close(forces[0], -10.0 + 20.0 * (-1.0f32).exp());
+    }
+
+    #[test]
+    fn disabled_dynamics_pass_through() {
+        let disabled = ThrusterDynamicsConfig {
+            enabled: false,
+            ..DYNAMICS
+        };
+        let mut dynamics = ThrusterDynamics::default();
+
+        assert_eq!(dynamics.step(&[10.0, -3.0], &disabled, 0.01), [10.0, -3.0]);
+        assert_eq!(dynamics.step(&[0.0, 2.0], &disabled, 0.01), [0.0, 2.0]);
+    }
+
+    #[test]
+    fn disarm_resets_the_model() {
+        let mut dynamics = ThrusterDynamics::default();
+        dynamics.step(&[10.0, 5.0], &DYNAMICS, 10.0);
+        assert_eq!(dynamics.forces, [10.0, 5.0]);
+
+        dynamics.reset();
+        assert_eq!(dynamics.forces, [0.0, 0.0]);
+
+        // Rearming spools up from rest
+        let forces = dynamics.step(&[10.0, 5.0], &DYNAMICS, 0.1);
+        assert_close(forces[0], 6.321);
+        assert_close(forces[1], 3.161);
+
+        // A new motor config starts from rest too
+        let forces = dynamics.step(&[10.0], &DYNAMICS, 0.1);
+        assert_close(forces[0], 6.321);
+    }
+
+    fn robot_app() -> (App, Entity, Instant) {
+        let start = Instant::now();
+        let config: RobotConfig = toml::from_str(include_str!("../../../robot.toml")).unwrap();
+
+        let mut app = App::new();
+        app.insert_resource(config)
+            .insert_resource(MotorDataRes(motor_preformance::fallback_motor_data()))
+            .insert_resource(Time::<Real>::new(start))
+            .add_event::<ErrorEvent>()
+            .add_systems(Startup, (create_motors, setup_motor_math))
+            .add_systems(
+                Update,
+                (
+                    rebuild_motor_config,
+                    limit_current_for_power,
+                    update_axis_maximums,
+                    accumulate_motor_forces,
+                )
+                    .chain(),
+            );
+
+        let net_id = NetId::random();
+        let robot = app.world.spawn((LocalRobotMarker, net_id)).id();
+        app.world.insert_resource(LocalRobot {
+            net_id,
+            entity: robot,
+        });
+
+        (app, robot, start)
+    }
+
+    fn step(app: &mut App, start: Instant, at_ms: u64) {
+        // Long enough frames that the jerk limit doesn't hold the commands back
+        app.world
+            .resource_mut::<Time<Real>>()
+            .update_with_instant(start + Duration::from_millis(at_ms));
+
+        app.update();
+    }
+
+    fn pwm_signals(app: &mut App) -> BTreeMap<ErasedMotorId, Duration> {
+        app.world
+            .query::<(&MotorDefinition, &PwmSignal)>()
+            .iter(&app.world)
+            .map(|(MotorDefinition(id, _), PwmSignal(pwm))| (*id, *pwm))
+            .collect()
+    }
+
+    fn axis_maximums(app: &App, robot: Entity) -> BTreeMap<Axis, f32> {
+        app.world
+            .get::<MovementAxisMaximums>(robot)
+            .unwrap()
+            .0
+            .iter()
+            .map(|(axis, force)| (*axis, force.0))
+            .collect()
+    }
+
+    #[test]
+    fn power_mode_scales_the_current_cap() {
+        let (mut app, robot, start) = robot_app();
+        step(&mut app, start, 0);
+
+        let current_cap = |app: &App| app.world.get::<MovementCurrentCap>(robot).unwrap().0;
+        let full = axis_maximums(&app, robot);
+        assert_eq!(current_cap(&app), Amperes(25.0));
+
+        app.world.entity_mut(robot).insert(PowerMode::LowPower);
+        step(&mut app, start, 100);
+        assert_eq!(current_cap(&app), Amperes(25.0 * 0.7));
+        let reduced = axis_maximums(&app, robot);
+        assert!(reduced[&Axis::X] < full[&Axis::X], "{reduced:?}");
+
+        app.world.entity_mut(robot).insert(PowerMode::Critical);
+        step(&mut app, start, 200);
+        assert_eq!(current_cap(&app), Amperes(25.0 * 0.4));
+
+        // The full budget comes back
+        app.world.entity_mut(robot).insert(PowerMode::Normal);
+        step(&mut app, start, 300);
+        assert_eq!(current_cap(&app), Amperes(25.0));
+        assert_eq!(axis_maximums(&app, robot), full);
+    }
+
+    #[test]
+    fn disabled_motor_is_held_at_neutral() {
+        let (mut app, robot, start) = robot_app();
+        step(&mut app, start, 0);
+
+        let net_id = app.world.resource::<LocalRobot>().net_id;
+        let Motors(motor_config) = app.world.get::<Motors>(robot).unwrap().clone();
+        assert_eq!(motor_config.motor_count(), 8);
+
+        // Every motor is asked to push, including the one about to be disabled
+        let forces = motor_config
+            .motors()
+            .map(|(id, _)| (*id, Newtons(5.0)))
+            .collect::<BTreeMap<_, _>>();
+        app.world.spawn((
+            RobotId(net_id),
+            MotorContribution(forces.try_into().unwrap()),
+        ));
+
+        step(&mut app, start, 1_000);
+        let full_maximums = axis_maximums(&app, robot);
+        let neutral = Duration::from_micros(1500);
+        assert!(pwm_signals(&mut app).values().all(|&pwm| pwm != neutral));
+
+        let disabled: ErasedMotorId = X3dMotorId::FrontRightTop.into();
+        app.world
+            .entity_mut(robot)
+            .insert(DisabledMotors(HashSet::from([disabled])));
+
+        step(&mut app, start, 2_000);
+
+        let Motors(degraded) = app.world.get::<Motors>(robot).unwrap();
+        assert_eq!(degraded.motor_count(), 7);
+        assert!(degraded.index_of(&disabled).is_none());
+
+        // Losing a thruster costs authority on every axis
+        let degraded_maximums = axis_maximums(&app, robot);
+        for axis in Axis::ALL {
+            assert!(
+                degraded_maximums[&axis] < full_maximums[&axis],
+                "{axis:?}: {} -> {}",
+                full_maximums[&axis],
+                degraded_maximums[&axis]
+            );
+        }
+
+        for frame in 3..6 {
+            let signals = pwm_signals(&mut app);
+            assert_eq!(signals[&disabled], neutral);
+            assert!(signals
+                .iter()
+                .filter(|(id, _)| **id != disabled)
+                .all(|(_, &pwm)| pwm != neutral));
+
+            step(&mut app, start, frame * 1_000);
+        }
+
+        // Re-enabling restores the configured motors
+        app.world
+            .entity_mut(robot)
+            .insert(DisabledMotors::default());
+        step(&mut app, start, 6_000);
+
+        let Motors(restored) = app.world.get::<Motors>(robot).unwrap();
+        assert_eq!(restored, &motor_config);
+        assert_eq!(axis_maximums(&app, robot), full_maximums);
+        assert_ne!(pwm_signals(&mut app)[&disabled], neutral);
+    }
+
+    #[test]
+    fn non_finite_center_of_mass_offset_is_rejected() {
+        let (mut app, robot, start) = robot_app();
+        step(&mut app, start, 0);
+
+        app.world
+            .entity_mut(robot)
+            .insert(CenterOfMassOffset(Vec3A::new(0.0, 0.0, 0.01)));
+        step(&mut app, start, 100);
+        let Motors(trimmed) = app.world.get::<Motors>(robot).unwrap().clone();
+        let maximums = axis_maximums(&app, robot);
+        app.world.resource_mut::<Events<ErrorEvent>>().clear();
+
+        app.world
+            .entity_mut(robot)
+            .insert(CenterOfMassOffset(Vec3A::new(f32::NAN, 0.0, 0.0)));
+        step(&mut app, start, 200);
+
+        let errors = app
+            .world
+            .resource_mut::<Events<ErrorEvent>>()
+            .drain()
+            .count();
+        assert_eq!(errors, 1);
+        assert_eq!(app.world.get::<Motors>(robot).unwrap().0, trimmed);
+        assert_eq!(axis_maximums(&app, robot), maximums);
+    }
+
+    const DEPTH_HOLD: &str = "Depth Hold Controller";
+    const ORIENTATION_HOLD: &str = "Orientation Hold Controller";
+
+    /// The robot app with both hold controllers feeding the movement accumulation
+    fn hold_app() -> (App, Entity, Instant) {
+        let (mut app, robot, start) = robot_app();
+        app.add_plugins((DepthHoldPlugin, StabilizePlugin))
+            .add_systems(
+                Update,
+                accumulate_movements
+                    .in_set(AccumulateMovementsSet)
+                    .after(rebuild_motor_config),
+            );
+        // Startup disarms the robot
+        step(&mut app, start, 0);
+
+        app.world.entity_mut(robot).insert((
+            Armed::Armed,
+            Depth(DepthFrame {
+                depth: Meters(1.0),
+                ..default()
+            }),
+            Orientation {
+                rotation: Quat::IDENTITY,
+                capture_time: 0.0,
+            },
+        ));
+
+        (app, robot, start)
+    }
+
+    fn active(app: &App, robot: Entity, name: &str) -> Option<Movement> {
+        app.world
+            .get::<ActiveContributions>(robot)?
+            .0
+            .iter()
+            .find(|(it, _)| it == name)
+            .map(|(_, movement)| *movement)
+    }
+
+    #[test]
+    fn hold_contributions_follow_their_targets() {
+        let (mut app, robot, start) = hold_app();
+        step(&mut app, start, 0);
+
+        // Always there, zero without a target
+        assert_eq!(active(&app, robot, DEPTH_HOLD), Some(Movement::default()));
+        assert_eq!(
+            active(&app, robot, ORIENTATION_HOLD),
+            Some(Movement::default())
+        );
+
+        let children = app.world.get::<Children>(robot).unwrap();
+        let names = children
+            .iter()
+            .filter_map(|&it| app.world.get::<Name>(it))
+            .map(|it| it.as_str())
+            .collect::<Vec<_>>();
+        assert!(names.contains(&DEPTH_HOLD) && names.contains(&ORIENTATION_HOLD));
+
+        // Applied the frame the target is set
+        app.world.entity_mut(robot).insert(DepthTarget(Meters(2.0)));
+        step(&mut app, start, 100);
+        let depth = active(&app, robot, DEPTH_HOLD).unwrap();
+        assert!(depth.force.z < 0.0, "{depth:?}");
+        assert_eq!(
+            active(&app, robot, ORIENTATION_HOLD),
+            Some(Movement::default())
+        );
+
+        app.world
+            .entity_mut(robot)
+            .insert(OrientationTarget(Quat::from_rotation_z(0.5)));
+        step(&mut app, start, 200);
+        let orientation = active(&app, robot, ORIENTATION_HOLD).unwrap();
+        assert!(orientation.torque.z > 0.0, "{orientation:?}");
+
+        // And zeroed the frame it is cleared
+        app.world.entity_mut(robot).remove::<DepthTarget>();
+        step(&mut app, start, 300);
+        assert_eq!(active(&app, robot, DEPTH_HOLD), Some(Movement::default()));
+        assert_ne!(
+            active(&app, robot, ORIENTATION_HOLD),
+            Some(Movement::default())
+        );
+
+        // Disarming zeroes both
+        app.world.entity_mut(robot).insert(Armed::Disarmed);
+        step(&mut app, start, 400);
+        assert_eq!(active(&app, robot, DEPTH_HOLD), Some(Movement::default()));
+        assert_eq!(
+            active(&app, robot, ORIENTATION_HOLD),
+            Some(Movement::default())
+        );
+    }
+
+    #[test]
+    fn recorded_dive_thrust_matches_the_old_output() {
+        let (mut app, robot, start) = hold_app();
+        app.world.entity_mut(robot).insert((
+            DepthTarget(Meters(1.5)),
+            OrientationTarget(Quat::from_rotation_z(0.3)),
+        ));
+
+        // Depth and attitude as logged while settling onto a hold
+        let recording: [(f32, f32, f32, f32); 8] = [
+            (1.00, 0.0, 0.0, 0.00),
+            (1.08, 2.0, -1.0, 0.05),
+            (1.19, 3.5, -1.5, 0.12),
+            (1.31, 2.0, 0.5, 0.20),
+            (1.42, 0.5, 1.0, 0.26),
+            (1.49, -0.5, 0.5, 0.29),
+            (1.52, -1.0, 0.0, 0.31),
+            (1.51, 0.0, -0.5, 0.30),
+        ];
+
+        // Force then torque summed over the contributions, as applied before the hold controllers
+        // published their corrections as contributions
+        #[rustfmt::skip]
+        let applied: [[f32; 6]; 8] = [
+            [0.0, 0.0, -50.025, 0.0, 0.0, 2.5903423],
+            [-0.52437484, -1.0484304, -30.023113, -28.904758, 12.821974, -32.441017],
+            [-0.38117546, -0.8886532, -14.529352, -24.053074, 6.2054324, -46.923744],
+            [0.009346313, -0.037376706, -1.070329, 18.950617, -22.215635, -53.41736],
+            [-0.14703654, 0.07350987, 8.423397, 22.271626, -6.197734, -40.919525],
+            [-0.08224331, -0.08224018, 9.423791, 15.491784, 4.72468, -20.583374],
+            [0.0, -0.112140335, 6.4245152, 8.021819, 5.07818, -13.775614],
+            [-0.0050090277, 0.0, -0.57397765, -15.015541, 5.1025033, 6.9101686],
+        ];
+
+        for (frame, ((depth, pitch, roll, yaw), expected)) in
+            recording.into_iter().zip(applied).enumerate()
+        {
+            let orientation =
+                Quat::from_euler(EulerRot::ZYX, yaw, roll.to_radians(), pitch.to_radians());
+            app.world.entity_mut(robot).insert((
+                Depth(DepthFrame {
+                    depth: Meters(depth),
+                    ..default()
+                }),
+                Orientation {
+                    rotation: orientation,
+                    capture_time: 0.0,
+                },
+            ));
+            step(&mut app, start, (frame as u64 + 1) * 10);
+
+            let contributions = app.world.get::<ActiveContributions>(robot).unwrap();
+            let total = contributions
+                .0
+                .iter()
+                .fold(Movement::default(), |total, (_, it)| total + *it);
+            let total = [
+                total.force.x,
+                total.force.y,
+                total.force.z,
+                total.torque.x,
+                total.torque.y,
+                total.torque.z,
+            ];
+
+            for (axis, (total, expected)) in total.into_iter().zip(expected).enumerate() {
+                assert!(
+                    (total - expected).abs() < 1e-3,
+                    "frame {frame} axis {axis}: {total} != {expected}"
+                );
+            }
+        }
+    }
 }
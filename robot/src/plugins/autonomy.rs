@@ -0,0 +1,448 @@
+//! Robot side autonomous behaviors
+//!
+//! Behaviors run on the robot so they keep working when the video link to the surface degrades.
+//! Each one is registered by name and started or stopped by the surface with [`StartBehavior`] and
+//! [`StopBehavior`]. A running behavior owns a movement contribution entity, which is despawned
+//! when the behavior stops, the robot disarms or the last peer disconnects.
+
+pub mod station_keep;
+
+use std::{collections::BTreeMap, time::Duration};
+
+use bevy::prelude::*;
+use common::{
+    bundles::MovementContributionBundle,
+    components::{
+        Armed, AvailableBehaviors, Depth, DepthTarget, MovementContribution, Orientation,
+        OrientationTarget, RobotId, RobotStatus, RunningBehavior,
+    },
+    ecs_sync::Replicate,
+    events::{StartBehavior, StopBehavior},
+    types::units::Meters,
+};
+use glam::Quat;
+use motor_math::Movement;
+
+use crate::plugins::core::robot::{LocalRobot, LocalRobotMarker};
+
+pub struct AutonomyPlugin;
+
+impl Plugin for AutonomyPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BehaviorRegistry>()
+            .init_resource::<RunningBehaviors>()
+            .register_behavior("Station Keep", station_keep::StationKeep::from_params)
+            .add_systems(Startup, publish_behaviors)
+            .add_systems(
+                Update,
+                (handle_behavior_events, stop_behaviors, update_behaviors).chain(),
+            );
+    }
+}
+
+/// A robot side autonomous behavior
+pub trait Behavior: Send + Sync + 'static {
+    /// Called once per frame while the behavior runs
+    fn update(&mut self, ctx: BehaviorCtx<'_>) -> BehaviorOutput;
+}
+
+/// What a behavior knows about the robot
+pub struct BehaviorCtx<'a> {
+    pub orientation: Option<&'a Orientation>,
+    pub depth: Option<&'a Depth>,
+    /// Real time since the behavior started
+    pub elapsed: Duration,
+    /// Real time since the last update
+    pub delta: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct BehaviorOutput {
+    /// Applied through the behavior's movement contribution
+    pub movement: Movement,
+    /// Handed to depth hold
+    pub depth_target: Option<Meters>,
+    /// Handed to stabilize
+    pub orientation_target: Option<Quat>,
+}
+
+/// Builds a behavior from the parameters of a [`StartBehavior`]
+pub type BehaviorFactory = fn(&serde_json::Value) -> anyhow::Result<Box<dyn Behavior>>;
+
+#[derive(Resource, Default)]
+pub struct BehaviorRegistry(BTreeMap<String, BehaviorFactory>);
+
+pub trait AppBehaviorExt {
+    /// Makes a behavior available to the surface under `name`
+    fn register_behavior(&mut self, name: &str, factory: BehaviorFactory) -> &mut Self;
+}
+
+impl AppBehaviorExt for App {
+    fn register_behavior(&mut self, name: &str, factory: BehaviorFactory) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(BehaviorRegistry::default)
+            .0
+            .insert(name.to_owned(), factory);
+
+        self
+    }
+}
+
+#[derive(Resource, Default)]
+struct RunningBehaviors(BTreeMap<String, RunningState>);
+
+struct RunningState {
+    entity: Entity,
+    behavior: Box<dyn Behavior>,
+    /// Real time the behavior started
+    started: Duration,
+
+    /// Last targets the behavior set, they are cleared on stop unless something else changed them
+    depth_target: Option<Meters>,
+    orientation_target: Option<Quat>,
+}
+
+type RobotState<'a> = (
+    Option<&'a Armed>,
+    Option<&'a RobotStatus>,
+    Option<&'a Orientation>,
+    Option<&'a Depth>,
+    Option<&'a DepthTarget>,
+    Option<&'a OrientationTarget>,
+);
+
+fn publish_behaviors(mut cmds: Commands, robot: Res<LocalRobot>, registry: Res<BehaviorRegistry>) {
+    let names = registry.0.keys().cloned().collect();
+    cmds.entity(robot.entity).insert(AvailableBehaviors(names));
+}
+
+fn handle_behavior_events(
+    mut cmds: Commands,
+    (mut starts, mut stops): (EventReader<StartBehavior>, EventReader<StopBehavior>),
+    registry: Res<BehaviorRegistry>,
+    mut running: ResMut<RunningBehaviors>,
+    robot: Res<LocalRobot>,
+    robot_query: Query<RobotState<'static>, With<LocalRobotMarker>>,
+    time: Res<Time<Real>>,
+) {
+    let Ok(robot_state) = robot_query.get_single() else {
+        return;
+    };
+
+    for StopBehavior { name } in stops.read() {
+        if let Some(state) = running.0.remove(name) {
+            info!("Stopping behavior {name}");
+            stop(&mut cmds, robot.entity, &robot_state, state);
+        }
+    }
+
+    for StartBehavior { name, params } in starts.read() {
+        if !is_active(&robot_state) {
+            warn!("Cannot start behavior {name} while disarmed");
+            continue;
+        }
+
+        let Some(factory) = registry.0.get(name) else {
+            warn!("Cannot start unknown behavior {name}");
+            continue;
+        };
+
+        let behavior = match factory(params) {
+            Ok(behavior) => behavior,
+            Err(err) => {
+                warn!("Cannot start behavior {name}: {err:?}");
+                continue;
+            }
+        };
+
+        if let Some(state) = running.0.remove(name) {
+            info!("Restarting behavior {name}");
+            stop(&mut cmds, robot.entity, &robot_state, state);
+        } else {
+            info!("Starting behavior {name}");
+        }
+
+        let entity = cmds
+            .spawn((
+                MovementContributionBundle {
+                    name: Name::new(name.clone()),
+                    contribution: MovementContribution(Movement::default()),
+                    robot: RobotId(robot.net_id),
+                },
+                RunningBehavior(name.clone()),
+                Replicate,
+            ))
+            .id();
+
+        running.0.insert(
+            name.clone(),
+            RunningState {
+                entity,
+                behavior,
+                started: time.elapsed(),
+                depth_target: None,
+                orientation_target: None,
+            },
+        );
+    }
+}
+
+fn stop_behaviors(
+    mut cmds: Commands,
+    mut running: ResMut<RunningBehaviors>,
+    robot: Res<LocalRobot>,
+    robot_query: Query<RobotState<'static>, With<LocalRobotMarker>>,
+) {
+    let Ok(robot_state) = robot_query.get_single() else {
+        return;
+    };
+
+    if running.0.is_empty() || is_active(&robot_state) {
+        return;
+    }
+
+    for (name, state) in std::mem::take(&mut running.0) {
+        info!("Stopping behavior {name}, robot disarmed or lost its peer");
+        stop(&mut cmds, robot.entity, &robot_state, state);
+    }
+}
+
+fn update_behaviors(
+    mut cmds: Commands,
+    mut running: ResMut<RunningBehaviors>,
+    robot: Res<LocalRobot>,
+    robot_query: Query<RobotState<'static>, With<LocalRobotMarker>>,
+    time: Res<Time<Real>>,
+) {
+    let Ok((_, _, orientation, depth, depth_target, orientation_target)) = robot_query.get_single()
+    else {
+        return;
+    };
+
+    for state in running.0.values_mut() {
+        let output = state.behavior.update(BehaviorCtx {
+            orientation,
+            depth,
+            elapsed: time.elapsed().saturating_sub(state.started),
+            delta: time.delta(),
+        });
+
+        cmds.entity(state.entity)
+            .insert(MovementContribution(output.movement));
+
+        if let Some(target) = output.depth_target {
+            if depth_target.map(|it| it.0) != Some(target) {
+                cmds.entity(robot.entity).insert(DepthTarget(target));
+            }
+            state.depth_target = Some(target);
+        }
+
+        if let Some(target) = output.orientation_target {
+            if orientation_target.map(|it| it.0) != Some(target) {
+                cmds.entity(robot.entity).insert(OrientationTarget(target));
+            }
+            state.orientation_target = Some(target);
+        }
+    }
+}
+
+/// Behaviors only run while armed with a peer connected
+fn is_active(robot_state: &RobotState) -> bool {
+    let (armed, status, ..) = robot_state;
+
+    armed == &Some(&Armed::Armed) && status != &Some(&RobotStatus::NoPeer)
+}
+
+fn stop(cmds: &mut Commands, robot: Entity, robot_state: &RobotState, state: RunningState) {
+    let (_, _, _, _, depth_target, orientation_target) = robot_state;
+
+    cmds.entity(state.entity).despawn_recursive();
+
+    if state.depth_target.is_some() && depth_target.map(|it| it.0) == state.depth_target {
+        cmds.entity(robot).remove::<DepthTarget>();
+    }
+
+    if state.orientation_target.is_some()
+        && orientation_target.map(|it| it.0) == state.orientation_target
+    {
+        cmds.entity(robot).remove::<OrientationTarget>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::App, prelude::*};
+    use common::{
+        components::{
+            Armed, AvailableBehaviors, Depth, DepthTarget, MovementContribution, Orientation,
+            OrientationTarget, RobotId, RobotStatus, RunningBehavior,
+        },
+        ecs_sync::NetId,
+        events::{StartBehavior, StopBehavior},
+        types::{
+            hw::DepthFrame,
+            units::{Celsius, Mbar, Meters},
+        },
+    };
+    use glam::Quat;
+
+    use crate::plugins::core::robot::{LocalRobot, LocalRobotMarker};
+
+    use super::AutonomyPlugin;
+
+    fn depth(depth: f32) -> Depth {
+        Depth(DepthFrame {
+            depth: Meters(depth),
+            altitude: Meters(-depth),
+            pressure: Mbar(1013.25 + depth * 98.0665),
+            temperature: Celsius(20.0),
+            capture_time: 0.0,
+        })
+    }
+
+    fn app() -> (App, Entity) {
+        let mut app = App::new();
+        app.add_event::<StartBehavior>()
+            .add_event::<StopBehavior>()
+            .init_resource::<Time<Real>>();
+
+        let robot = app
+            .world
+            .spawn((
+                LocalRobotMarker,
+                Armed::Armed,
+                RobotStatus::Armed,
+                Orientation {
+                    rotation: Quat::from_rotation_z(0.5),
+                    capture_time: 0.0,
+                },
+                depth(1.5),
+            ))
+            .id();
+        app.world.insert_resource(LocalRobot {
+            net_id: NetId::random(),
+            entity: robot,
+        });
+
+        app.add_plugins(AutonomyPlugin);
+        app.update();
+
+        (app, robot)
+    }
+
+    fn start(app: &mut App, name: &str) {
+        app.world.send_event(StartBehavior {
+            name: name.to_owned(),
+            params: serde_json::Value::Null,
+        });
+        app.update();
+    }
+
+    fn running(app: &mut App) -> Vec<(String, Entity)> {
+        app.world
+            .query::<(Entity, &RunningBehavior, &MovementContribution, &RobotId)>()
+            .iter(&app.world)
+            .map(|(entity, behavior, _, _)| (behavior.0.clone(), entity))
+            .collect()
+    }
+
+    #[test]
+    fn behaviors_are_published() {
+        let (app, robot) = app();
+
+        assert_eq!(
+            app.world.get::<AvailableBehaviors>(robot),
+            Some(&AvailableBehaviors(vec!["Station Keep".to_owned()]))
+        );
+    }
+
+    #[test]
+    fn stopping_despawns_the_contribution() {
+        let (mut app, robot) = app();
+
+        start(&mut app, "Station Keep");
+
+        let running = running(&mut app);
+        assert_eq!(running.len(), 1);
+        assert_eq!(running[0].0, "Station Keep");
+        let entity = running[0].1;
+
+        app.update();
+        assert_eq!(
+            app.world.get::<DepthTarget>(robot),
+            Some(&DepthTarget(Meters(1.5)))
+        );
+        assert!(app.world.get::<OrientationTarget>(robot).is_some());
+
+        // The robot moving doesn't move the setpoint
+        app.world.entity_mut(robot).insert(depth(1.8));
+        app.update();
+        assert_eq!(
+            app.world.get::<DepthTarget>(robot),
+            Some(&DepthTarget(Meters(1.5)))
+        );
+
+        app.world.send_event(StopBehavior {
+            name: "Station Keep".to_owned(),
+        });
+        app.update();
+
+        assert!(app.world.get_entity(entity).is_none());
+        assert!(app.world.get::<DepthTarget>(robot).is_none());
+        assert!(app.world.get::<OrientationTarget>(robot).is_none());
+    }
+
+    #[test]
+    fn disarming_or_losing_the_peer_stops_behaviors() {
+        let (mut app, robot) = app();
+
+        start(&mut app, "Station Keep");
+        assert_eq!(running(&mut app).len(), 1);
+
+        app.world.entity_mut(robot).insert(Armed::Disarmed);
+        app.update();
+        assert!(running(&mut app).is_empty());
+
+        // Can't start again until armed
+        start(&mut app, "Station Keep");
+        assert!(running(&mut app).is_empty());
+
+        app.world.entity_mut(robot).insert(Armed::Armed);
+        start(&mut app, "Station Keep");
+        assert_eq!(running(&mut app).len(), 1);
+
+        app.world.entity_mut(robot).insert(RobotStatus::NoPeer);
+        app.update();
+        assert!(running(&mut app).is_empty());
+    }
+
+    #[test]
+    fn restarting_replaces_the_contribution() {
+        let (mut app, _) = app();
+
+        start(&mut app, "Station Keep");
+        let first = running(&mut app);
+
+        start(&mut app, "Station Keep");
+        let second = running(&mut app);
+
+        assert_eq!(second.len(), 1);
+        assert_ne!(first[0].1, second[0].1);
+        assert!(app.world.get_entity(first[0].1).is_none());
+    }
+
+    #[test]
+    fn unknown_behaviors_are_ignored() {
+        let (mut app, _) = app();
+
+        start(&mut app, "Lawnmower");
+        assert!(running(&mut app).is_empty());
+
+        app.world.send_event(StartBehavior {
+            name: "Station Keep".to_owned(),
+            params: serde_json::json!({ "depth": "deep" }),
+        });
+        app.update();
+        assert!(running(&mut app).is_empty());
+    }
+}
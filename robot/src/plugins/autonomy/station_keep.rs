@@ -0,0 +1,151 @@
+//! Holds the robot at a depth and heading
+//!
+//! The robot has no position sensors, so station keeping hands its setpoint to depth hold and
+//! stabilize rather than moving the robot itself. Without parameters the setpoint is wherever the
+//! robot was when the behavior started.
+
+use anyhow::Context;
+use common::types::units::Meters;
+use glam::{EulerRot, Quat};
+use serde::Deserialize;
+
+use super::{Behavior, BehaviorCtx, BehaviorOutput};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+struct StationKeepParams {
+    /// Meters
+    depth: Option<f32>,
+    /// Degrees, clockwise from the robot's zero yaw when viewed from above
+    heading: Option<f32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct StationKeep {
+    depth: Option<Meters>,
+    /// Radians about the vertical axis
+    yaw: Option<f32>,
+}
+
+impl StationKeep {
+    pub fn from_params(params: &serde_json::Value) -> anyhow::Result<Box<dyn Behavior>> {
+        let params: Option<StationKeepParams> =
+            serde_json::from_value(params.clone()).context("Parse station keep parameters")?;
+        let params = params.unwrap_or_default();
+
+        Ok(Box::new(StationKeep {
+            depth: params.depth.map(Meters),
+            yaw: params.heading.map(|it| -it.to_radians()),
+        }))
+    }
+}
+
+impl Behavior for StationKeep {
+    fn update(&mut self, ctx: BehaviorCtx<'_>) -> BehaviorOutput {
+        // Latch whatever wasn't given as a parameter as soon as the sensors report it
+        if self.depth.is_none() {
+            self.depth = ctx.depth.map(|it| it.0.depth);
+        }
+        if self.yaw.is_none() {
+            self.yaw = ctx
+                .orientation
+                .map(|it| it.rotation.to_euler(EulerRot::ZYX).0);
+        }
+
+        BehaviorOutput {
+            depth_target: self.depth,
+            // Level, only the heading is held
+            orientation_target: self.yaw.map(Quat::from_rotation_z),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use common::{
+        components::{Depth, Orientation},
+        types::{
+            hw::DepthFrame,
+            units::{Celsius, Mbar, Meters},
+        },
+    };
+    use glam::{EulerRot, Quat};
+    use motor_math::Movement;
+
+    use crate::plugins::autonomy::BehaviorCtx;
+
+    use super::StationKeep;
+
+    fn depth(depth: f32) -> Depth {
+        Depth(DepthFrame {
+            depth: Meters(depth),
+            altitude: Meters(-depth),
+            pressure: Mbar(1013.25 + depth * 98.0665),
+            temperature: Celsius(20.0),
+            capture_time: 0.0,
+        })
+    }
+
+    fn ctx<'a>(orientation: Option<&'a Orientation>, depth: Option<&'a Depth>) -> BehaviorCtx<'a> {
+        BehaviorCtx {
+            orientation,
+            depth,
+            elapsed: Duration::ZERO,
+            delta: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn holds_the_starting_setpoint() {
+        let mut behavior = StationKeep::from_params(&serde_json::Value::Null).unwrap();
+
+        // Nothing to hold until the sensors report
+        let output = behavior.update(ctx(None, None));
+        assert_eq!(output.depth_target, None);
+        assert_eq!(output.orientation_target, None);
+
+        let tilted = Orientation {
+            rotation: Quat::from_euler(EulerRot::ZYX, 0.5, 0.1, -0.2),
+            capture_time: 0.0,
+        };
+        let output = behavior.update(ctx(Some(&tilted), Some(&depth(1.2))));
+        assert_eq!(output.depth_target, Some(Meters(1.2)));
+        assert_eq!(output.movement, Movement::default());
+
+        let target = output.orientation_target.unwrap();
+        assert!(target.abs_diff_eq(Quat::from_rotation_z(0.5), 1e-5));
+
+        // Drifting away doesn't move the setpoint
+        let drifted = Orientation {
+            rotation: Quat::from_rotation_z(1.0),
+            capture_time: 0.0,
+        };
+        let output = behavior.update(ctx(Some(&drifted), Some(&depth(2.0))));
+        assert_eq!(output.depth_target, Some(Meters(1.2)));
+        assert_eq!(output.orientation_target, Some(target));
+    }
+
+    #[test]
+    fn parameters_set_the_setpoint() {
+        let params = serde_json::json!({ "depth": 3.0, "heading": 90.0 });
+        let mut behavior = StationKeep::from_params(&params).unwrap();
+
+        let output = behavior.update(ctx(
+            Some(&Orientation {
+                rotation: Quat::IDENTITY,
+                capture_time: 0.0,
+            }),
+            Some(&depth(0.5)),
+        ));
+        assert_eq!(output.depth_target, Some(Meters(3.0)));
+        assert!(output
+            .orientation_target
+            .unwrap()
+            .abs_diff_eq(Quat::from_rotation_z(-90f32.to_radians()), 1e-5));
+
+        assert!(StationKeep::from_params(&serde_json::json!({ "speed": 1.0 })).is_err());
+    }
+}
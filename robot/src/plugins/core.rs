@@ -1,6 +1,13 @@
 use bevy::{app::PluginGroupBuilder, prelude::PluginGroup};
 
+pub mod arming;
+pub mod checklist;
+pub mod file_browser;
+pub mod heartbeat;
+pub mod rebind;
+pub mod remote_config;
 pub mod robot;
+pub mod schedule;
 pub mod state;
 
 pub struct CorePlugins;
@@ -10,5 +17,12 @@ impl PluginGroup for CorePlugins {
         PluginGroupBuilder::start::<Self>()
             .add(robot::RobotPlugin)
             .add(state::StatePlugin)
+            .add(heartbeat::HeartbeatPlugin)
+            .add(arming::ArmingPlugin)
+            .add(checklist::ChecklistPlugin)
+            .add(remote_config::RemoteConfigPlugin)
+            .add(file_browser::FileBrowserPlugin)
+            .add(rebind::RebindPlugin)
+            .add(schedule::SchedulePlugin)
     }
 }
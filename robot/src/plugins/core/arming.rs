@@ -0,0 +1,815 @@
+//! Pre-arm checks and arm authority
+//!
+//! The surface asks to arm with an [`ArmRequest`] and the robot only arms once every registered
+//! check passes. Anything else that sets [`Armed::Armed`], like a peer inserting it directly, is
+//! reverted.
+//!
+//! With several surfaces connected only the one holding [`ArmAuthority`] may arm. A surface takes
+//! it with a [`RequestArmAuthority`], from another surface too unless the robot is armed, and it
+//! is released with a [`ReleaseArmAuthority`] or when the holder disconnects. Disarming is allowed
+//! from any surface unless [`ArmingSettings::anyone_can_disarm`] is turned off.
+
+use bevy::{
+    ecs::{event::ManualEventReader, system::BoxedSystem},
+    prelude::*,
+};
+use common::{
+    components::{ArmAuthority, Armed, Surface},
+    ecs_sync::{apply_changes::ChangeApplicationSet, NetId},
+    events::{ArmRejected, ArmRequest, DisarmRequest, ReleaseArmAuthority, RequestArmAuthority},
+    sync::Peer,
+};
+use networking::Token;
+
+use crate::config::RobotConfig;
+
+use super::robot::{LocalRobot, LocalRobotMarker};
+
+pub struct ArmingPlugin;
+
+impl Plugin for ArmingPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PreArmChecks>()
+            .init_resource::<ArmApproval>()
+            .init_resource::<ArmingSettings>()
+            .add_systems(Startup, setup_arming)
+            .add_systems(
+                PreUpdate,
+                (
+                    handle_authority_requests,
+                    handle_arm_requests.run_if(resource_exists::<LocalRobot>),
+                    guard_armed,
+                )
+                    .chain()
+                    .after(ChangeApplicationSet),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckSeverity {
+    /// Can never be bypassed
+    Critical,
+    /// Bypassed by a forced arm
+    Advisory,
+}
+
+/// A check returns the reason it failed as its error
+pub type CheckResult = Result<(), String>;
+
+pub trait AppPreArmCheckExt {
+    /// Registers a system that must pass before the robot arms
+    fn add_pre_arm_check<M>(
+        &mut self,
+        name: &'static str,
+        severity: CheckSeverity,
+        check: impl IntoSystem<(), CheckResult, M>,
+    ) -> &mut Self;
+}
+
+impl AppPreArmCheckExt for App {
+    fn add_pre_arm_check<M>(
+        &mut self,
+        name: &'static str,
+        severity: CheckSeverity,
+        check: impl IntoSystem<(), CheckResult, M>,
+    ) -> &mut Self {
+        let mut system: BoxedSystem<(), CheckResult> = Box::new(IntoSystem::into_system(check));
+        system.initialize(&mut self.world);
+
+        self.world
+            .get_resource_or_insert_with(PreArmChecks::default)
+            .0
+            .push(PreArmCheck {
+                name,
+                severity,
+                system,
+            });
+
+        self
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct PreArmChecks(Vec<PreArmCheck>);
+
+struct PreArmCheck {
+    name: &'static str,
+    severity: CheckSeverity,
+    system: BoxedSystem<(), CheckResult>,
+}
+
+impl PreArmChecks {
+    fn run(&mut self, world: &mut World) -> Vec<CheckOutcome> {
+        self.0
+            .iter_mut()
+            .map(|check| CheckOutcome {
+                name: check.name,
+                severity: check.severity,
+                result: check.system.run((), world),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckOutcome {
+    pub name: &'static str,
+    pub severity: CheckSeverity,
+    pub result: CheckResult,
+}
+
+/// Whether the current [`Armed::Armed`] was granted by the pre-arm checks
+#[derive(Resource, Default, Debug)]
+pub struct ArmApproval {
+    pub approved: bool,
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArmingSettings {
+    /// Surfaces without arm authority may still disarm
+    pub anyone_can_disarm: bool,
+}
+
+impl Default for ArmingSettings {
+    fn default() -> Self {
+        Self {
+            anyone_can_disarm: true,
+        }
+    }
+}
+
+/// Who sent an arming related event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Requester {
+    /// Sent by the robot itself, never held to arm authority
+    Local,
+    /// A connected surface, by the [`NetId`] of its [`Surface`] singleton
+    Surface(NetId),
+    /// A peer without a surface singleton
+    Unknown,
+}
+
+/// Finds the surface behind an event's origin in the connected `surfaces`
+pub fn requester(surfaces: &[(NetId, Token)], origin: Option<Token>) -> Requester {
+    let Some(origin) = origin else {
+        return Requester::Local;
+    };
+
+    surfaces
+        .iter()
+        .find(|(_, token)| *token == origin)
+        .map(|(surface, _)| Requester::Surface(*surface))
+        .unwrap_or(Requester::Unknown)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthorityChange {
+    Request(NetId),
+    Release(NetId),
+    /// The surface's peer went away
+    Disconnected(NetId),
+}
+
+/// The holder of arm authority after `change`, or why a request was refused
+pub fn change_authority(
+    holder: Option<NetId>,
+    change: AuthorityChange,
+    armed: Armed,
+) -> Result<Option<NetId>, String> {
+    match change {
+        AuthorityChange::Request(surface) => match holder {
+            // Taking over mid dive would leave the pilot without a way to stop
+            Some(other) if other != surface && armed == Armed::Armed => {
+                Err("Another station holds arm authority while armed".to_owned())
+            }
+            _ => Ok(Some(surface)),
+        },
+        AuthorityChange::Release(surface) | AuthorityChange::Disconnected(surface) => {
+            if holder == Some(surface) {
+                Ok(None)
+            } else {
+                Ok(holder)
+            }
+        }
+    }
+}
+
+/// Whether `requester` may arm, or disarm when `disarm` is set, while `holder` holds authority
+pub fn check_authority(
+    holder: Option<NetId>,
+    requester: Requester,
+    disarm: bool,
+    settings: &ArmingSettings,
+) -> Result<(), String> {
+    if disarm && (settings.anyone_can_disarm || holder.is_none()) {
+        return Ok(());
+    }
+
+    match (requester, holder) {
+        (Requester::Local, _) => Ok(()),
+        (Requester::Surface(surface), Some(holder)) if surface == holder => Ok(()),
+        (_, None) => Err("No station holds arm authority".to_owned()),
+        (_, Some(_)) => Err("Another station holds arm authority".to_owned()),
+    }
+}
+
+/// Decides whether the robot may arm
+///
+/// Returns the failures that were bypassed on success and every failure that blocked arming
+/// otherwise
+pub fn evaluate_checks(outcomes: &[CheckOutcome], force: bool) -> Result<Vec<String>, Vec<String>> {
+    let mut blocking = Vec::new();
+    let mut bypassed = Vec::new();
+
+    for outcome in outcomes {
+        let Err(reason) = &outcome.result else {
+            continue;
+        };
+
+        let reason = format!("{}: {reason}", outcome.name);
+        if force && outcome.severity == CheckSeverity::Advisory {
+            bypassed.push(reason);
+        } else {
+            blocking.push(reason);
+        }
+    }
+
+    if blocking.is_empty() {
+        Ok(bypassed)
+    } else {
+        Err(blocking)
+    }
+}
+
+/// The state [`Armed`] should be forced to, if it was changed without approval
+pub fn guard(armed: Armed, approved: bool) -> Option<Armed> {
+    if armed == Armed::Armed && !approved {
+        Some(Armed::Disarmed)
+    } else {
+        None
+    }
+}
+
+fn setup_arming(
+    mut cmds: Commands,
+    config: Res<RobotConfig>,
+    robot: Res<LocalRobot>,
+    mut settings: ResMut<ArmingSettings>,
+) {
+    settings.anyone_can_disarm = config.arming.anyone_can_disarm;
+    cmds.entity(robot.entity).insert(ArmAuthority::default());
+}
+
+/// The [`NetId`] and token of every connected surface
+pub fn connected_surfaces<'a>(
+    surfaces: impl IntoIterator<Item = (&'a NetId, &'a Peer)>,
+) -> Vec<(NetId, Token)> {
+    surfaces
+        .into_iter()
+        .map(|(surface, peer)| (*surface, peer.token))
+        .collect()
+}
+
+type AuthorityState<'a> = (Entity, Option<&'a ArmAuthority>, Option<&'a Armed>);
+
+fn handle_authority_requests(
+    mut cmds: Commands,
+    mut requests: EventReader<RequestArmAuthority>,
+    mut releases: EventReader<ReleaseArmAuthority>,
+    mut rejections: EventWriter<ArmRejected>,
+    surfaces: Query<(&NetId, &Peer), With<Surface>>,
+    robot: Query<AuthorityState<'static>, With<LocalRobotMarker>>,
+) {
+    let Ok((robot, authority, armed)) = robot.get_single() else {
+        return;
+    };
+
+    let armed = armed.copied().unwrap_or_default();
+    let current = authority.copied().unwrap_or_default().holder;
+    let surfaces = connected_surfaces(&surfaces);
+
+    let mut changes = Vec::new();
+    if let Some(holder) = current {
+        if !surfaces.iter().any(|(surface, _)| *surface == holder) {
+            changes.push(AuthorityChange::Disconnected(holder));
+        }
+    }
+    for release in releases.read() {
+        if let Requester::Surface(surface) = requester(&surfaces, release.origin()) {
+            changes.push(AuthorityChange::Release(surface));
+        }
+    }
+    for request in requests.read() {
+        if let Requester::Surface(surface) = requester(&surfaces, request.origin()) {
+            changes.push(AuthorityChange::Request(surface));
+        } else {
+            warn!("Arm authority requested by a peer that isn't a surface");
+        }
+    }
+
+    let mut holder = current;
+    for change in changes {
+        match change_authority(holder, change, armed) {
+            Ok(new_holder) => holder = new_holder,
+            Err(reason) => {
+                warn!("Arm authority refused: {reason}");
+                rejections.send(ArmRejected {
+                    reasons: vec![reason],
+                });
+            }
+        }
+    }
+
+    if holder != current || authority.is_none() {
+        info!("Arm authority: {current:?} -> {holder:?}");
+        cmds.entity(robot).insert(ArmAuthority { holder });
+    }
+}
+
+fn handle_arm_requests(
+    world: &mut World,
+    mut arm_reader: Local<ManualEventReader<ArmRequest>>,
+    mut disarm_reader: Local<ManualEventReader<DisarmRequest>>,
+) {
+    let robot = world.resource::<LocalRobot>().entity;
+    let holder = world
+        .get::<ArmAuthority>(robot)
+        .copied()
+        .unwrap_or_default()
+        .holder;
+    let settings = *world.resource::<ArmingSettings>();
+    let surfaces = connected_surfaces(
+        world
+            .query_filtered::<(&NetId, &Peer), With<Surface>>()
+            .iter(world),
+    );
+
+    let mut rejected = Vec::new();
+
+    let mut disarm = false;
+    for request in disarm_reader.read(world.resource::<Events<DisarmRequest>>()) {
+        let from = requester(&surfaces, request.origin());
+
+        match check_authority(holder, from, true, &settings) {
+            Ok(()) => disarm = true,
+            Err(reason) => rejected.push(reason),
+        }
+    }
+
+    let mut force = None;
+    for request in arm_reader.read(world.resource::<Events<ArmRequest>>()) {
+        let from = requester(&surfaces, request.origin());
+
+        match check_authority(holder, from, false, &settings) {
+            Ok(()) => force = Some(force.unwrap_or(false) || request.force),
+            Err(reason) => rejected.push(reason),
+        }
+    }
+
+    for reason in rejected {
+        warn!("Arm authority check failed: {reason}");
+        world.send_event(ArmRejected {
+            reasons: vec![reason],
+        });
+    }
+
+    // A disarm wins over an arm sent in the same tick
+    if disarm {
+        info!("Disarming");
+        world.entity_mut(robot).insert(Armed::Disarmed);
+        return;
+    }
+
+    let Some(force) = force else {
+        return;
+    };
+
+    let outcomes = world.resource_scope(|world, mut checks: Mut<PreArmChecks>| checks.run(world));
+
+    match evaluate_checks(&outcomes, force) {
+        Ok(bypassed) => {
+            for reason in bypassed {
+                warn!("Force arming despite failed check, {reason}");
+            }
+
+            info!("Pre-arm checks passed, arming");
+
+            world.resource_mut::<ArmApproval>().approved = true;
+            world.entity_mut(robot).insert(Armed::Armed);
+        }
+        Err(reasons) => {
+            warn!("Arming rejected: {}", reasons.join(", "));
+
+            world.send_event(ArmRejected { reasons });
+        }
+    }
+}
+
+fn guard_armed(
+    mut cmds: Commands,
+    mut approval: ResMut<ArmApproval>,
+    robot: Query<(Entity, Ref<Armed>), With<LocalRobotMarker>>,
+) {
+    let Ok((robot, armed)) = robot.get_single() else {
+        return;
+    };
+
+    if !armed.is_changed() {
+        return;
+    }
+
+    if let Some(armed) = guard(*armed, approval.approved) {
+        warn!("Armed without passing pre-arm checks, disarming");
+        cmds.entity(robot).insert(armed);
+    }
+
+    if *armed == Armed::Disarmed {
+        approval.approved = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::App, prelude::*};
+    use common::{
+        components::{ArmAuthority, Armed, Leak, Surface},
+        ecs_sync::{NetId, PeerEvent},
+        events::{
+            ArmRejected, ArmRequest, DisarmRequest, ReleaseArmAuthority, RequestArmAuthority,
+        },
+        sync::Peer,
+    };
+    use networking::{PeerAddr, Token};
+
+    use crate::plugins::core::robot::{LocalRobot, LocalRobotMarker};
+
+    use super::{
+        change_authority, check_authority, evaluate_checks, guard, guard_armed,
+        handle_arm_requests, handle_authority_requests, requester, AppPreArmCheckExt, ArmApproval,
+        ArmingSettings, AuthorityChange, CheckOutcome, CheckResult, CheckSeverity, PreArmChecks,
+        Requester,
+    };
+
+    fn outcome(name: &'static str, severity: CheckSeverity, result: CheckResult) -> CheckOutcome {
+        CheckOutcome {
+            name,
+            severity,
+            result,
+        }
+    }
+
+    #[test]
+    fn aggregates_checks() {
+        let outcomes = [
+            outcome("Leak", CheckSeverity::Critical, Ok(())),
+            outcome("IMU", CheckSeverity::Advisory, Err("Stale".to_owned())),
+            outcome("Voltage", CheckSeverity::Advisory, Ok(())),
+        ];
+
+        assert_eq!(
+            evaluate_checks(&outcomes, false),
+            Err(vec!["IMU: Stale".to_owned()])
+        );
+        assert_eq!(
+            evaluate_checks(&outcomes, true),
+            Ok(vec!["IMU: Stale".to_owned()])
+        );
+        assert_eq!(evaluate_checks(&outcomes[..1], false), Ok(vec![]));
+        assert_eq!(evaluate_checks(&[], false), Ok(vec![]));
+    }
+
+    #[test]
+    fn critical_checks_cannot_be_forced() {
+        let outcomes = [
+            outcome("Leak", CheckSeverity::Critical, Err("Leak".to_owned())),
+            outcome("IMU", CheckSeverity::Advisory, Err("Stale".to_owned())),
+            outcome("PWM", CheckSeverity::Critical, Err("No chip".to_owned())),
+        ];
+
+        assert_eq!(
+            evaluate_checks(&outcomes, true),
+            Err(vec!["Leak: Leak".to_owned(), "PWM: No chip".to_owned()])
+        );
+    }
+
+    #[test]
+    fn guard_only_reverts_unapproved_arms() {
+        assert_eq!(guard(Armed::Armed, false), Some(Armed::Disarmed));
+        assert_eq!(guard(Armed::Armed, true), None);
+        assert_eq!(guard(Armed::Disarmed, false), None);
+        assert_eq!(guard(Armed::Disarmed, true), None);
+    }
+
+    fn no_leak(robot: Query<&Leak, With<LocalRobotMarker>>) -> CheckResult {
+        match robot.get_single() {
+            Ok(Leak(true)) => Err("Leak detected".to_owned()),
+            _ => Ok(()),
+        }
+    }
+
+    fn app() -> (App, Entity) {
+        let mut app = App::new();
+        app.add_event::<ArmRequest>()
+            .add_event::<DisarmRequest>()
+            .add_event::<RequestArmAuthority>()
+            .add_event::<ReleaseArmAuthority>()
+            .add_event::<ArmRejected>()
+            .init_resource::<PreArmChecks>()
+            .init_resource::<ArmApproval>()
+            .init_resource::<ArmingSettings>()
+            .add_pre_arm_check("Leak", CheckSeverity::Critical, no_leak)
+            .add_systems(
+                Update,
+                (handle_authority_requests, handle_arm_requests, guard_armed).chain(),
+            );
+
+        let robot = app
+            .world
+            .spawn((
+                LocalRobotMarker,
+                Armed::Disarmed,
+                Leak(false),
+                ArmAuthority::default(),
+            ))
+            .id();
+        app.world.insert_resource(LocalRobot {
+            net_id: NetId::random(),
+            entity: robot,
+        });
+
+        (app, robot)
+    }
+
+    fn rejections(app: &App) -> Vec<ArmRejected> {
+        let events = app.world.resource::<Events<ArmRejected>>();
+        events.get_reader().read(events).cloned().collect()
+    }
+
+    #[test]
+    fn arm_request_runs_checks() {
+        let (mut app, robot) = app();
+
+        app.world.entity_mut(robot).insert(Leak(true));
+        app.world.send_event(ArmRequest::new(true));
+        app.update();
+
+        assert_eq!(app.world.get::<Armed>(robot), Some(&Armed::Disarmed));
+        assert_eq!(
+            rejections(&app),
+            [ArmRejected {
+                reasons: vec!["Leak: Leak detected".to_owned()]
+            }]
+        );
+
+        app.world.entity_mut(robot).insert(Leak(false));
+        app.world.send_event(ArmRequest::new(false));
+        app.update();
+
+        assert_eq!(app.world.get::<Armed>(robot), Some(&Armed::Armed));
+        assert!(app.world.resource::<ArmApproval>().approved);
+    }
+
+    #[test]
+    fn direct_arming_is_reverted() {
+        let (mut app, robot) = app();
+        app.update();
+
+        // What a remote insert looks like once applied
+        app.world.entity_mut(robot).insert(Armed::Armed);
+        app.update();
+        app.update();
+
+        assert_eq!(app.world.get::<Armed>(robot), Some(&Armed::Disarmed));
+
+        // Once approved the arm sticks until something disarms
+        app.world.send_event(ArmRequest::default());
+        app.update();
+        app.update();
+        assert_eq!(app.world.get::<Armed>(robot), Some(&Armed::Armed));
+
+        app.world.entity_mut(robot).insert(Armed::Disarmed);
+        app.update();
+        assert!(!app.world.resource::<ArmApproval>().approved);
+
+        app.world.entity_mut(robot).insert(Armed::Armed);
+        app.update();
+        app.update();
+        assert_eq!(app.world.get::<Armed>(robot), Some(&Armed::Disarmed));
+    }
+
+    #[test]
+    fn authority_transitions() {
+        let (a, b) = (NetId::random(), NetId::random());
+        let request = AuthorityChange::Request;
+
+        // Grant
+        assert_eq!(
+            change_authority(None, request(a), Armed::Disarmed),
+            Ok(Some(a))
+        );
+        assert_eq!(
+            change_authority(Some(a), request(a), Armed::Armed),
+            Ok(Some(a))
+        );
+
+        // Steal, only while disarmed
+        assert_eq!(
+            change_authority(Some(a), request(b), Armed::Disarmed),
+            Ok(Some(b))
+        );
+        assert!(change_authority(Some(a), request(b), Armed::Armed).is_err());
+
+        // Release, only by the holder
+        assert_eq!(
+            change_authority(Some(a), AuthorityChange::Release(b), Armed::Armed),
+            Ok(Some(a))
+        );
+        assert_eq!(
+            change_authority(Some(a), AuthorityChange::Release(a), Armed::Armed),
+            Ok(None)
+        );
+
+        // Disconnect
+        assert_eq!(
+            change_authority(Some(a), AuthorityChange::Disconnected(a), Armed::Armed),
+            Ok(None)
+        );
+        assert_eq!(
+            change_authority(Some(b), AuthorityChange::Disconnected(a), Armed::Armed),
+            Ok(Some(b))
+        );
+    }
+
+    #[test]
+    fn only_the_holder_arms() {
+        let (a, b) = (NetId::random(), NetId::random());
+        let settings = ArmingSettings::default();
+
+        assert_eq!(
+            check_authority(Some(a), Requester::Surface(a), false, &settings),
+            Ok(())
+        );
+        assert!(check_authority(Some(a), Requester::Surface(b), false, &settings).is_err());
+        assert!(check_authority(Some(a), Requester::Unknown, false, &settings).is_err());
+        assert!(check_authority(None, Requester::Surface(a), false, &settings).is_err());
+
+        // The robot's own requests aren't held to it
+        assert_eq!(
+            check_authority(Some(a), Requester::Local, false, &settings),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn disarm_exception() {
+        let (a, b) = (NetId::random(), NetId::random());
+        let anyone = ArmingSettings {
+            anyone_can_disarm: true,
+        };
+        let holder_only = ArmingSettings {
+            anyone_can_disarm: false,
+        };
+
+        assert_eq!(
+            check_authority(Some(a), Requester::Surface(b), true, &anyone),
+            Ok(())
+        );
+        assert!(check_authority(Some(a), Requester::Surface(b), true, &holder_only).is_err());
+        assert_eq!(
+            check_authority(Some(a), Requester::Surface(a), true, &holder_only),
+            Ok(())
+        );
+
+        // Nobody to step on
+        assert_eq!(
+            check_authority(None, Requester::Surface(b), true, &holder_only),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn requesters_are_found_by_token() {
+        let a = NetId::random();
+        let surfaces = [(a, Token(3))];
+
+        assert_eq!(requester(&surfaces, None), Requester::Local);
+        assert_eq!(requester(&surfaces, Some(Token(3))), Requester::Surface(a));
+        assert_eq!(requester(&surfaces, Some(Token(4))), Requester::Unknown);
+    }
+
+    fn connect_surface(app: &mut App, token: Token) -> (Entity, NetId) {
+        let net_id = NetId::random();
+        let entity = app
+            .world
+            .spawn((
+                Surface,
+                net_id,
+                Peer {
+                    addrs: PeerAddr::Tcp("10.0.0.2:44444".parse().unwrap()),
+                    token,
+                },
+            ))
+            .id();
+
+        (entity, net_id)
+    }
+
+    fn from<E: PeerEvent>(mut event: E, token: Token) -> E {
+        event.set_origin(token);
+        event
+    }
+
+    fn holder(app: &App, robot: Entity) -> Option<NetId> {
+        app.world.get::<ArmAuthority>(robot).unwrap().holder
+    }
+
+    #[test]
+    fn arming_follows_authority() {
+        let (mut app, robot) = app();
+        let (pilot, copilot) = (Token(1), Token(2));
+        let (pilot_entity, pilot_id) = connect_surface(&mut app, pilot);
+        let (_, copilot_id) = connect_surface(&mut app, copilot);
+
+        // Nobody holds it yet
+        app.world.send_event(from(ArmRequest::new(false), pilot));
+        app.update();
+        assert_eq!(app.world.get::<Armed>(robot), Some(&Armed::Disarmed));
+
+        // Requesting and arming in one go, like the gamepad does
+        app.world
+            .send_event(from(RequestArmAuthority::default(), pilot));
+        app.world.send_event(from(ArmRequest::new(false), pilot));
+        app.update();
+        assert_eq!(holder(&app, robot), Some(pilot_id));
+        assert_eq!(app.world.get::<Armed>(robot), Some(&Armed::Armed));
+
+        // No stealing while armed
+        app.world
+            .send_event(from(RequestArmAuthority::default(), copilot));
+        app.update();
+        assert_eq!(holder(&app, robot), Some(pilot_id));
+
+        // Anyone may disarm by default
+        app.world
+            .send_event(from(DisarmRequest::default(), copilot));
+        app.update();
+        assert_eq!(app.world.get::<Armed>(robot), Some(&Armed::Disarmed));
+
+        // Once disarmed it can be taken
+        app.world
+            .send_event(from(RequestArmAuthority::default(), copilot));
+        app.update();
+        assert_eq!(holder(&app, robot), Some(copilot_id));
+
+        // Released by its holder only
+        app.world
+            .send_event(from(ReleaseArmAuthority::default(), pilot));
+        app.update();
+        assert_eq!(holder(&app, robot), Some(copilot_id));
+
+        app.world
+            .send_event(from(ReleaseArmAuthority::default(), copilot));
+        app.update();
+        assert_eq!(holder(&app, robot), None);
+
+        // And when the holder disconnects
+        app.world
+            .send_event(from(RequestArmAuthority::default(), pilot));
+        app.update();
+        assert_eq!(holder(&app, robot), Some(pilot_id));
+
+        app.world.despawn(pilot_entity);
+        app.update();
+        assert_eq!(holder(&app, robot), None);
+    }
+
+    #[test]
+    fn holder_only_disarm() {
+        let (mut app, robot) = app();
+        app.world.resource_mut::<ArmingSettings>().anyone_can_disarm = false;
+
+        let (pilot, copilot) = (Token(1), Token(2));
+        connect_surface(&mut app, pilot);
+        connect_surface(&mut app, copilot);
+
+        app.world
+            .send_event(from(RequestArmAuthority::default(), pilot));
+        app.world.send_event(from(ArmRequest::new(false), pilot));
+        app.update();
+        assert_eq!(app.world.get::<Armed>(robot), Some(&Armed::Armed));
+
+        app.world
+            .send_event(from(DisarmRequest::default(), copilot));
+        app.update();
+        assert_eq!(app.world.get::<Armed>(robot), Some(&Armed::Armed));
+        assert_eq!(
+            rejections(&app).last().map(|it| it.reasons.clone()),
+            Some(vec!["Another station holds arm authority".to_owned()])
+        );
+
+        app.world.send_event(from(DisarmRequest::default(), pilot));
+        app.update();
+        assert_eq!(app.world.get::<Armed>(robot), Some(&Armed::Disarmed));
+    }
+}
@@ -0,0 +1,64 @@
+//! Hosts the mission checklist so every connected surface sees the same state
+//!
+//! Surfaces only ever send requests, the robot applies them in the order they arrive and the
+//! result is replicated back out to all of them
+
+use bevy::prelude::*;
+use common::{
+    components::MissionChecklist,
+    ecs_sync::scoped::ScopedEvents,
+    events::{LoadChecklist, SetChecklistItem},
+};
+
+use crate::plugins::core::robot::{LocalRobot, LocalRobotMarker};
+
+pub struct ChecklistPlugin;
+
+impl Plugin for ChecklistPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, update_checklist);
+    }
+}
+
+fn update_checklist(
+    mut cmds: Commands,
+    robot: Res<LocalRobot>,
+    mut checklist: Query<&mut MissionChecklist, With<LocalRobotMarker>>,
+    mut loads: ScopedEvents<LoadChecklist>,
+    mut sets: ScopedEvents<SetChecklistItem>,
+) {
+    let mut checklist = checklist.get_single_mut().ok();
+    let sets = sets.read();
+
+    if let Some(LoadChecklist(loaded)) = loads.read().pop() {
+        info!("Loaded checklist with {} tasks", loaded.tasks.len());
+
+        match &mut checklist {
+            Some(checklist) => checklist.0 = loaded,
+            None => {
+                cmds.entity(robot.entity).insert(MissionChecklist(loaded));
+            }
+        }
+    }
+
+    let Some(mut checklist) = checklist else {
+        if !sets.is_empty() {
+            warn!("Got checklist update without a checklist");
+        }
+
+        return;
+    };
+
+    for set in sets {
+        // Only mark it changed when something did, repeats from racing surfaces are dropped here
+        let changed =
+            checklist
+                .bypass_change_detection()
+                .0
+                .set(set.item, set.checked, set.timestamp);
+
+        if changed {
+            checklist.set_changed();
+        }
+    }
+}
@@ -0,0 +1,290 @@
+//! Lets the surface list, download and delete files without SSH
+//!
+//! Only the directories under `file_roots` in the config can be reached. Requests name one of
+//! them and a plain file name, and the file has to stay inside the directory even after following
+//! symlinks. Downloads are streamed from disk by [`common::file_transfer`].
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use ahash::HashMap;
+use anyhow::{bail, Context};
+use bevy::prelude::*;
+use common::{
+    components::FileRoots,
+    ecs_sync::rpc::RpcRequests,
+    events::{DeleteFile, DownloadFile, DownloadStarted, FileDeleted, FileListing, ListFiles},
+    file_transfer::StreamFile,
+    protocol::MAX_NAME_LEN,
+    types::files::{is_plain_file_name, FileEntry, MAX_LISTING_ENTRIES},
+};
+
+use crate::{config::RobotConfig, plugins::core::robot::LocalRobot};
+
+pub struct FileBrowserPlugin;
+
+impl Plugin for FileBrowserPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, publish_roots)
+            .add_systems(Update, (list_files, download_files, delete_files));
+    }
+}
+
+/// The file called `name` in the browsable directory called `dir`
+pub fn resolve(roots: &HashMap<String, PathBuf>, dir: &str, name: &str) -> anyhow::Result<PathBuf> {
+    let root = roots
+        .get(dir)
+        .with_context(|| format!("{dir:?} is not a browsable directory"))?;
+
+    if !is_plain_file_name(name) {
+        bail!("{name:?} is not a plain file name");
+    }
+
+    let root = root
+        .canonicalize()
+        .with_context(|| format!("Resolve {}", root.display()))?;
+    let path = root
+        .join(name)
+        .canonicalize()
+        .with_context(|| format!("Resolve {name:?}"))?;
+
+    // A symlink could still lead out
+    if !path.starts_with(&root) {
+        bail!("{name:?} leads outside of {dir:?}");
+    }
+
+    if !path.is_file() {
+        bail!("{name:?} is not a file");
+    }
+
+    Ok(path)
+}
+
+/// Files directly in `dir`, newest first, along with whether some were left out to stay under
+/// `max_entries`
+///
+/// Subdirectories, symlinks and names that can't be sent are skipped.
+pub fn list_dir(dir: &Path, max_entries: usize) -> anyhow::Result<(Vec<FileEntry>, bool)> {
+    let mut entries = Vec::new();
+
+    for entry in fs::read_dir(dir).with_context(|| format!("Read {}", dir.display()))? {
+        let entry = entry.context("Read entry")?;
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+
+        if !metadata.is_file() || name.len() > MAX_NAME_LEN {
+            continue;
+        }
+
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|it| it.duration_since(UNIX_EPOCH).ok())
+            .map(|it| it.as_secs())
+            .unwrap_or_default();
+
+        entries.push(FileEntry {
+            name,
+            size: metadata.len(),
+            modified,
+        });
+    }
+
+    entries.sort_by(|a, b| {
+        b.modified
+            .cmp(&a.modified)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+
+    let truncated = entries.len() > max_entries;
+    entries.truncate(max_entries);
+
+    Ok((entries, truncated))
+}
+
+fn publish_roots(mut cmds: Commands, robot: Res<LocalRobot>, config: Res<RobotConfig>) {
+    let mut roots = config.file_roots.keys().cloned().collect::<Vec<_>>();
+    roots.sort();
+
+    cmds.entity(robot.entity).insert(FileRoots(roots));
+}
+
+fn list_files(config: Res<RobotConfig>, mut requests: RpcRequests<ListFiles, FileListing>) {
+    for (request, responder) in requests.read() {
+        let mut listing = FileListing {
+            dir: request.dir.clone(),
+            ..default()
+        };
+
+        let rst = config
+            .file_roots
+            .get(&request.dir)
+            .with_context(|| format!("{:?} is not a browsable directory", request.dir))
+            .and_then(|root| list_dir(root, MAX_LISTING_ENTRIES));
+
+        match rst {
+            Ok((entries, truncated)) => {
+                listing.entries = entries;
+                listing.truncated = truncated;
+            }
+            Err(err) => listing.error = Some(format!("{err:#}")),
+        }
+
+        requests.respond(responder, listing);
+    }
+}
+
+fn download_files(
+    config: Res<RobotConfig>,
+    mut requests: RpcRequests<DownloadFile, DownloadStarted>,
+    mut streams: EventWriter<StreamFile>,
+) {
+    for (request, responder) in requests.read() {
+        let rst = resolve(&config.file_roots, &request.dir, &request.name).and_then(|path| {
+            let size = fs::metadata(&path).context("Read file size")?.len();
+            Ok((path, size))
+        });
+
+        let response = match (rst, responder.origin()) {
+            (Ok((path, size)), Some(peer)) => {
+                streams.send(StreamFile {
+                    path,
+                    name: request.transfer_name(),
+                    first_chunk: request.first_chunk,
+                    peer,
+                });
+
+                DownloadStarted { size, error: None }
+            }
+            (Ok(_), None) => DownloadStarted {
+                size: 0,
+                error: Some("Downloads have to be asked for by a peer".to_owned()),
+            },
+            (Err(err), _) => {
+                warn!("Rejected download of {}: {err:#}", request.name);
+
+                DownloadStarted {
+                    size: 0,
+                    error: Some(format!("{err:#}")),
+                }
+            }
+        };
+
+        requests.respond(responder, response);
+    }
+}
+
+fn delete_files(config: Res<RobotConfig>, mut requests: RpcRequests<DeleteFile, FileDeleted>) {
+    for (request, responder) in requests.read() {
+        let rst = resolve(&config.file_roots, &request.dir, &request.name).and_then(|path| {
+            fs::remove_file(&path).with_context(|| format!("Remove {}", path.display()))
+        });
+
+        let error = match rst {
+            Ok(()) => {
+                info!("Deleted {} from {}", request.name, request.dir);
+                None
+            }
+            Err(err) => {
+                warn!("Could not delete {}: {err:#}", request.name);
+                Some(format!("{err:#}"))
+            }
+        };
+
+        requests.respond(responder, FileDeleted { error });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, time::Duration};
+
+    use ahash::HashMap;
+
+    use super::{list_dir, resolve};
+
+    #[test]
+    fn paths_stay_inside_their_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let stills = dir.path().join("stills");
+        fs::create_dir_all(stills.join("nested")).unwrap();
+        fs::write(stills.join("Front_1.jpg"), "jpg").unwrap();
+        fs::write(dir.path().join("robot.toml"), "secret").unwrap();
+
+        let roots: HashMap<String, PathBuf> = [("stills".to_owned(), stills.clone())]
+            .into_iter()
+            .collect();
+
+        assert_eq!(
+            resolve(&roots, "stills", "Front_1.jpg").unwrap(),
+            stills.join("Front_1.jpg").canonicalize().unwrap()
+        );
+
+        // Only configured directories
+        assert!(resolve(&roots, "logs", "Front_1.jpg").is_err());
+        assert!(resolve(&roots, "..", "robot.toml").is_err());
+
+        // Only plain names
+        assert!(resolve(&roots, "stills", "../robot.toml").is_err());
+        assert!(resolve(&roots, "stills", "..").is_err());
+        assert!(resolve(&roots, "stills", "").is_err());
+        assert!(resolve(&roots, "stills", "nested/../Front_1.jpg").is_err());
+        let absolute = dir.path().join("robot.toml").display().to_string();
+        assert!(resolve(&roots, "stills", &absolute).is_err());
+
+        // Only files that exist
+        assert!(resolve(&roots, "stills", "nested").is_err());
+        assert!(resolve(&roots, "stills", "missing.jpg").is_err());
+
+        // Symlinks can't lead out
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.path().join("robot.toml"), stills.join("escape.jpg"))
+                .unwrap();
+            assert!(resolve(&roots, "stills", "escape.jpg").is_err());
+        }
+    }
+
+    #[test]
+    fn listing_is_newest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("nested")).unwrap();
+
+        for (name, age) in [("old.csv", 300), ("new.csv", 0), ("middle.csv", 100)] {
+            let path = dir.path().join(name);
+            fs::write(&path, name).unwrap();
+
+            let modified = std::time::SystemTime::now() - Duration::from_secs(age);
+            fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        }
+
+        let (entries, truncated) = list_dir(dir.path(), 10).unwrap();
+        let names = entries
+            .iter()
+            .map(|it| it.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["new.csv", "middle.csv", "old.csv"]);
+        assert_eq!(entries[0].size, "new.csv".len() as u64);
+        assert!(!truncated);
+
+        // The oldest are left out
+        let (entries, truncated) = list_dir(dir.path(), 2).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].name, "middle.csv");
+        assert!(truncated);
+
+        assert!(list_dir(&dir.path().join("missing"), 10).is_err());
+    }
+}
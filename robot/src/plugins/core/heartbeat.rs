@@ -0,0 +1,182 @@
+//! Operator presence interlock
+//!
+//! Thrusters are only driven while the surface keeps sending [`OperatorHeartbeat`]s. A surface
+//! with a live connection but a frozen main loop stops sending them, and the robot holds its
+//! thrusters at neutral without touching [`Armed`] so control resumes as soon as they return.
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use common::{
+    components::{Armed, ControlGated},
+    events::OperatorHeartbeat,
+};
+
+use crate::config::RobotConfig;
+
+use super::robot::LocalRobotMarker;
+
+pub struct HeartbeatPlugin;
+
+impl Plugin for HeartbeatPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LastHeartbeat>()
+            .add_systems(Startup, setup_heartbeat)
+            .add_systems(PreUpdate, track_heartbeats)
+            .add_systems(
+                Update,
+                update_gate.run_if(resource_exists::<HeartbeatSettings>),
+            );
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct HeartbeatSettings {
+    /// How long the robot stays in control without a heartbeat
+    pub timeout: Duration,
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct LastHeartbeat {
+    /// Real time the last heartbeat was received
+    pub at: Option<Duration>,
+    pub seq: u32,
+}
+
+fn setup_heartbeat(mut cmds: Commands, config: Res<RobotConfig>) {
+    cmds.insert_resource(HeartbeatSettings {
+        timeout: Duration::from_secs_f32(config.heartbeat_timeout),
+    });
+}
+
+fn track_heartbeats(
+    time: Res<Time<Real>>,
+    mut last: ResMut<LastHeartbeat>,
+    mut heartbeats: EventReader<OperatorHeartbeat>,
+) {
+    for heartbeat in heartbeats.read() {
+        last.at = Some(time.elapsed());
+        last.seq = heartbeat.seq;
+    }
+}
+
+type GateState<'a> = (Entity, Option<&'a Armed>, Option<&'a ControlGated>);
+
+fn update_gate(
+    mut cmds: Commands,
+    time: Res<Time<Real>>,
+    settings: Res<HeartbeatSettings>,
+    last: Res<LastHeartbeat>,
+    robot: Query<GateState<'static>, With<LocalRobotMarker>>,
+) {
+    let Ok((robot, armed, gated)) = robot.get_single() else {
+        return;
+    };
+
+    let armed = armed.copied().unwrap_or_default();
+    let should_gate = is_gated(armed, time.elapsed(), last.at, settings.timeout);
+
+    if gated.map(|it| it.0) != Some(should_gate) {
+        if should_gate {
+            warn!("No operator heartbeat, holding thrusters at neutral");
+        } else if gated.is_some() {
+            info!("Operator heartbeat resumed");
+        }
+
+        cmds.entity(robot).insert(ControlGated(should_gate));
+    }
+}
+
+/// Whether thrusters should be held at neutral
+pub fn is_gated(
+    armed: Armed,
+    now: Duration,
+    last_heartbeat: Option<Duration>,
+    timeout: Duration,
+) -> bool {
+    if armed != Armed::Armed {
+        // Nothing to gate
+        return false;
+    }
+
+    match last_heartbeat {
+        Some(last) => now.saturating_sub(last) > timeout,
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bevy::{app::App, prelude::*};
+    use common::{
+        components::{Armed, ControlGated},
+        events::OperatorHeartbeat,
+    };
+
+    use crate::plugins::core::robot::LocalRobotMarker;
+
+    use super::{is_gated, track_heartbeats, update_gate, HeartbeatSettings, LastHeartbeat};
+
+    const TIMEOUT: Duration = Duration::from_millis(500);
+
+    #[test]
+    fn gate_timing() {
+        let ms = Duration::from_millis;
+
+        assert!(!is_gated(Armed::Disarmed, ms(10_000), None, TIMEOUT));
+        assert!(is_gated(Armed::Armed, ms(0), None, TIMEOUT));
+
+        assert!(!is_gated(Armed::Armed, ms(1_000), Some(ms(600)), TIMEOUT));
+        assert!(!is_gated(Armed::Armed, ms(1_100), Some(ms(600)), TIMEOUT));
+        assert!(is_gated(Armed::Armed, ms(1_101), Some(ms(600)), TIMEOUT));
+
+        // A heartbeat newer than the current time is not stale
+        assert!(!is_gated(Armed::Armed, ms(500), Some(ms(600)), TIMEOUT));
+    }
+
+    #[test]
+    fn heartbeat_gap_gates_and_ungates() {
+        let start = Instant::now();
+
+        let mut app = App::new();
+        app.add_event::<OperatorHeartbeat>()
+            .insert_resource(Time::<Real>::new(start))
+            .insert_resource(HeartbeatSettings { timeout: TIMEOUT })
+            .init_resource::<LastHeartbeat>()
+            .add_systems(Update, (track_heartbeats, update_gate).chain());
+
+        let robot = app.world.spawn((LocalRobotMarker, Armed::Armed)).id();
+
+        let step = |app: &mut App, at_ms: u64, heartbeat: Option<u32>| {
+            app.world
+                .resource_mut::<Time<Real>>()
+                .update_with_instant(start + Duration::from_millis(at_ms));
+
+            if let Some(seq) = heartbeat {
+                app.world.send_event(OperatorHeartbeat { seq });
+            }
+
+            app.update();
+
+            app.world.get::<ControlGated>(robot).copied()
+        };
+
+        assert_eq!(step(&mut app, 0, Some(0)), Some(ControlGated(false)));
+        assert_eq!(step(&mut app, 200, Some(1)), Some(ControlGated(false)));
+
+        // Heartbeats stop
+        assert_eq!(step(&mut app, 600, None), Some(ControlGated(false)));
+        assert_eq!(step(&mut app, 701, None), Some(ControlGated(true)));
+        assert_eq!(app.world.get::<Armed>(robot), Some(&Armed::Armed));
+
+        // Recovery is immediate
+        assert_eq!(step(&mut app, 900, Some(2)), Some(ControlGated(false)));
+        assert_eq!(app.world.resource::<LastHeartbeat>().seq, 2);
+
+        // Disarming clears the gate
+        app.world.entity_mut(robot).insert(Armed::Disarmed);
+        assert_eq!(step(&mut app, 5_000, None), Some(ControlGated(false)));
+    }
+}
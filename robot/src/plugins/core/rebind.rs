@@ -0,0 +1,168 @@
+//! Moves the robot's listener to another port without a restart
+//!
+//! For venue networks that block the configured port. A [`RebindServer`] is only honoured while
+//! disarmed and from the surface holding [`ArmAuthority`], the sync plugin then moves the listener
+//! while keeping connected surfaces.
+
+use bevy::prelude::*;
+use common::{
+    components::{ArmAuthority, Armed, Surface},
+    ecs_sync::NetId,
+    events::RebindServer,
+    sync::{Peer, RebindListener},
+};
+
+use super::{
+    arming::{check_authority, connected_surfaces, requester, ArmingSettings, Requester},
+    robot::{LocalRobot, LocalRobotMarker},
+};
+
+pub struct RebindPlugin;
+
+impl Plugin for RebindPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            handle_rebind_requests.run_if(resource_exists::<LocalRobot>),
+        );
+    }
+}
+
+/// Whether `requester` may move the listener to `port`
+pub fn check_rebind(
+    port: u16,
+    armed: Armed,
+    holder: Option<NetId>,
+    requester: Requester,
+    settings: &ArmingSettings,
+) -> Result<(), String> {
+    if port == 0 {
+        return Err("Port 0 can't be connected to".to_owned());
+    }
+
+    // Surfaces lose their link for a moment if anything goes wrong
+    if armed == Armed::Armed {
+        return Err("Can't move the listener while armed".to_owned());
+    }
+
+    check_authority(holder, requester, false, settings)
+}
+
+fn handle_rebind_requests(
+    mut requests: EventReader<RebindServer>,
+    mut rebinds: EventWriter<RebindListener>,
+    settings: Res<ArmingSettings>,
+    surfaces: Query<(&NetId, &Peer), With<Surface>>,
+    robot: Query<(Option<&Armed>, Option<&ArmAuthority>), With<LocalRobotMarker>>,
+) {
+    let Ok((armed, authority)) = robot.get_single() else {
+        return;
+    };
+
+    let armed = armed.copied().unwrap_or_default();
+    let holder = authority.copied().unwrap_or_default().holder;
+    let surfaces = connected_surfaces(&surfaces);
+
+    for request in requests.read() {
+        let from = requester(&surfaces, request.origin());
+
+        match check_rebind(request.port, armed, holder, from, &settings) {
+            Ok(()) => {
+                info!("Moving listener to port {}", request.port);
+                rebinds.send(RebindListener(request.port));
+            }
+            Err(reason) => warn!(
+                "Refused to move listener to port {}: {reason}",
+                request.port
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::App, prelude::*};
+    use common::{
+        components::{ArmAuthority, Armed, Surface},
+        ecs_sync::{NetId, PeerEvent},
+        events::RebindServer,
+        sync::{Peer, RebindListener},
+    };
+    use networking::{PeerAddr, Token};
+
+    use crate::plugins::core::{
+        arming::ArmingSettings,
+        robot::{LocalRobot, LocalRobotMarker},
+    };
+
+    use super::handle_rebind_requests;
+
+    const PILOT: Token = Token(1);
+    const COPILOT: Token = Token(2);
+
+    fn app() -> (App, Entity) {
+        let mut app = App::new();
+        app.add_event::<RebindServer>()
+            .add_event::<RebindListener>()
+            .init_resource::<ArmingSettings>()
+            .add_systems(Update, handle_rebind_requests);
+
+        let mut surface = |token| {
+            let net_id = NetId::random();
+            app.world.spawn((
+                Surface,
+                net_id,
+                Peer {
+                    addrs: PeerAddr::Tcp("10.0.0.2:44444".parse().unwrap()),
+                    token,
+                },
+            ));
+
+            net_id
+        };
+        let pilot = surface(PILOT);
+        surface(COPILOT);
+
+        let robot = app
+            .world
+            .spawn((
+                LocalRobotMarker,
+                Armed::Disarmed,
+                ArmAuthority {
+                    holder: Some(pilot),
+                },
+            ))
+            .id();
+        app.world.insert_resource(LocalRobot {
+            net_id: NetId::random(),
+            entity: robot,
+        });
+
+        (app, robot)
+    }
+
+    fn request(app: &mut App, port: u16, from: Token) -> Vec<RebindListener> {
+        let mut request = RebindServer::new(port);
+        request.set_origin(from);
+
+        app.world.send_event(request);
+        app.update();
+
+        app.world
+            .resource_mut::<Events<RebindListener>>()
+            .drain()
+            .collect()
+    }
+
+    #[test]
+    fn only_the_authority_holder_rebinds_while_disarmed() {
+        let (mut app, robot) = app();
+
+        assert_eq!(request(&mut app, 5000, PILOT), [RebindListener(5000)]);
+        assert!(request(&mut app, 5001, COPILOT).is_empty());
+        assert!(request(&mut app, 0, PILOT).is_empty());
+
+        app.world.entity_mut(robot).insert(Armed::Armed);
+        assert!(request(&mut app, 5002, PILOT).is_empty());
+    }
+}
@@ -0,0 +1,347 @@
+//! Lets the surface read and replace the robot's config file without SSH
+//!
+//! Uploads go through the same checks as the config at startup and only take effect once the
+//! robot code restarts. The file they replace is kept as a timestamped backup.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use bevy::prelude::*;
+use common::{
+    components::{Armed, ConfigSnapshot},
+    ecs_sync::{rpc::RpcRequests, scoped::ScopedEvents},
+    events::{ConfigUploadResult, RequestConfig, UploadConfig, MAX_CONFIG_TEXT},
+};
+
+use crate::{
+    config::{MotorConfigDefinition, RobotConfig},
+    plugins::{
+        core::robot::{LocalRobot, LocalRobotMarker},
+        monitor::crash_report::{archived_path, prune_archives},
+    },
+};
+
+/// How many replaced configs are kept, the oldest are deleted first
+pub const MAX_CONFIG_BACKUPS: usize = 10;
+
+/// The config file the robot was started with
+#[derive(Resource, Debug, Clone)]
+pub struct ConfigFile(pub PathBuf);
+
+pub struct RemoteConfigPlugin;
+
+impl Plugin for RemoteConfigPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (handle_uploads, publish_config).run_if(resource_exists::<ConfigFile>),
+        );
+    }
+}
+
+/// Parses `toml_text` and runs the startup checks on it
+pub fn validate_config(toml_text: &str) -> Result<RobotConfig, Vec<String>> {
+    if toml_text.len() > MAX_CONFIG_TEXT {
+        return Err(vec![format!(
+            "Config is {} bytes, at most {MAX_CONFIG_TEXT} are accepted",
+            toml_text.len()
+        )]);
+    }
+
+    let config: RobotConfig =
+        toml::from_str(toml_text).map_err(|err| vec![format!("Parse config: {err}")])?;
+
+    let errors = config.validate();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(config)
+}
+
+/// Writes `toml_text` to `path`, first copying the old file to a backup named after `timestamp`
+///
+/// The new file is moved into place so a crash mid write never leaves a partial config. Returns
+/// the backup's path, `None` if there was no old file.
+pub fn replace_config(
+    path: &Path,
+    toml_text: &str,
+    timestamp: u64,
+    keep: usize,
+) -> anyhow::Result<Option<PathBuf>> {
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, toml_text).context("Write config")?;
+
+    let backup = if path.exists() {
+        let backup = archived_path(path, timestamp);
+        fs::copy(path, &backup).context("Back up old config")?;
+
+        Some(backup)
+    } else {
+        None
+    };
+
+    fs::rename(&temp, path).context("Move config into place")?;
+    prune_archives(path, keep)?;
+
+    Ok(backup)
+}
+
+/// Validates and writes an uploaded config, refusing while the robot is armed
+fn upload_config(armed: Armed, toml_text: &str, path: &Path, timestamp: u64) -> ConfigUploadResult {
+    let rejected = |errors| ConfigUploadResult { ok: false, errors };
+
+    if armed == Armed::Armed {
+        return rejected(vec!["Disarm before uploading a config".to_owned()]);
+    }
+
+    if let Err(errors) = validate_config(toml_text) {
+        return rejected(errors);
+    }
+
+    match replace_config(path, toml_text, timestamp, MAX_CONFIG_BACKUPS) {
+        Ok(backup) => {
+            if let Some(backup) = backup {
+                info!("Backed up old config to {}", backup.display());
+            }
+
+            ConfigUploadResult {
+                ok: true,
+                errors: Vec::new(),
+            }
+        }
+        Err(err) => rejected(vec![format!("{err:#}")]),
+    }
+}
+
+/// What the surface is shown for the config file at `path`
+fn read_snapshot(path: &Path) -> ConfigSnapshot {
+    let mut snapshot = ConfigSnapshot {
+        path: path.display().to_string(),
+        ..default()
+    };
+
+    let toml_text = match fs::read_to_string(path) {
+        Ok(toml_text) => toml_text,
+        Err(err) => {
+            snapshot.error = Some(format!("Read config: {err}"));
+            return snapshot;
+        }
+    };
+
+    if toml_text.len() > MAX_CONFIG_TEXT {
+        snapshot.error = Some(format!(
+            "Config is {} bytes, too large to send",
+            toml_text.len()
+        ));
+        return snapshot;
+    }
+
+    match toml::from_str::<RobotConfig>(&toml_text) {
+        Ok(config) => snapshot.summary = config_summary(&config),
+        Err(err) => snapshot.error = Some(format!("Parse config: {err}")),
+    }
+    snapshot.toml_text = toml_text;
+
+    snapshot
+}
+
+fn config_summary(config: &RobotConfig) -> Vec<(String, String)> {
+    let kind = match config.motor_config {
+        MotorConfigDefinition::X3d(_) => "X3d",
+        MotorConfigDefinition::BlueRov(_) => "BlueRov",
+        MotorConfigDefinition::Custom(_) => "Custom",
+    };
+    let (motors, _) = config.motor_config.channels();
+
+    [
+        ("Name", config.name.clone()),
+        ("Port", config.port.to_string()),
+        ("Motors", format!("{} ({kind})", motors.len())),
+        ("Servos", config.servo_config.servos.len().to_string()),
+        ("Cameras", config.cameras.len().to_string()),
+        (
+            "Amperage Budget",
+            format!("{:.1} A", config.motor_amperage_budget),
+        ),
+        ("Jerk Limit", config.jerk_limit.to_string()),
+        (
+            "Heartbeat Timeout",
+            format!("{:.2} s", config.heartbeat_timeout),
+        ),
+    ]
+    .into_iter()
+    .map(|(label, value)| (label.to_owned(), value))
+    .collect()
+}
+
+fn handle_uploads(
+    mut cmds: Commands,
+    mut uploads: RpcRequests<UploadConfig, ConfigUploadResult>,
+    robot: Query<(Entity, &Armed), With<LocalRobotMarker>>,
+    file: Res<ConfigFile>,
+) {
+    for (upload, responder) in uploads.read() {
+        let (entity, &armed) = match robot.get_single() {
+            Ok(robot) => robot,
+            Err(_) => {
+                uploads.respond(
+                    responder,
+                    ConfigUploadResult {
+                        ok: false,
+                        errors: vec!["Robot isn't set up yet".to_owned()],
+                    },
+                );
+                continue;
+            }
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|it| it.as_secs())
+            .unwrap_or_default();
+
+        let result = upload_config(armed, &upload.toml_text, &file.0, timestamp);
+        if result.ok {
+            info!("Config replaced, restart the robot code to apply it");
+
+            // Show the surface what it will get after the restart
+            cmds.entity(entity).insert(read_snapshot(&file.0));
+        } else {
+            warn!("Rejected config upload: {}", result.errors.join(", "));
+        }
+
+        uploads.respond(responder, result);
+    }
+}
+
+fn publish_config(
+    mut cmds: Commands,
+    mut requests: ScopedEvents<RequestConfig>,
+    robot: Res<LocalRobot>,
+    file: Res<ConfigFile>,
+) {
+    if requests.read().is_empty() {
+        return;
+    }
+
+    cmds.entity(robot.entity).insert(read_snapshot(&file.0));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use common::{components::Armed, events::MAX_CONFIG_TEXT};
+
+    use super::{read_snapshot, replace_config, upload_config, validate_config};
+
+    const CONFIG: &str = include_str!("../../../robot.toml");
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files = fs::read_dir(dir)
+            .unwrap()
+            .map(|it| it.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+
+        files
+    }
+
+    #[test]
+    fn validation() {
+        assert!(validate_config(CONFIG).is_ok());
+
+        let errors = validate_config("name = ").unwrap_err();
+        assert!(errors[0].starts_with("Parse config"), "{errors:?}");
+
+        let errors =
+            validate_config(&format!("{CONFIG}#{}", "x".repeat(MAX_CONFIG_TEXT))).unwrap_err();
+        assert!(errors[0].contains("at most"), "{errors:?}");
+
+        // Startup would panic flattening this
+        let incomplete = CONFIG.replace("FrontRightTop = 7\n", "");
+        assert_eq!(
+            validate_config(&incomplete).unwrap_err(),
+            ["Motor FrontRightTop has no PWM channel"]
+        );
+
+        let shared = CONFIG.replace("pwm_channel = 15", "pwm_channel = 0");
+        assert_eq!(
+            validate_config(&shared).unwrap_err(),
+            ["PWM channel 0 is used by both FrontCameraRotate and FrontRightBottom"]
+        );
+    }
+
+    #[test]
+    fn backups_rotate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("robot.toml");
+        fs::write(dir.path().join("robot_notes.toml"), "").unwrap();
+
+        // Nothing to back up the first time
+        assert_eq!(replace_config(&path, "port = 0", 0, 3).unwrap(), None);
+
+        for timestamp in 1..=5 {
+            let text = format!("port = {timestamp}");
+            let backup = replace_config(&path, &text, timestamp, 3).unwrap();
+
+            assert_eq!(
+                backup,
+                Some(dir.path().join(format!("robot_{timestamp}.toml")))
+            );
+            assert_eq!(
+                fs::read_to_string(backup.unwrap()).unwrap(),
+                format!("port = {}", timestamp - 1)
+            );
+            assert_eq!(fs::read_to_string(&path).unwrap(), text);
+        }
+
+        // Only the newest backups are kept, other files are left alone
+        assert_eq!(
+            files(dir.path()),
+            [
+                "robot.toml",
+                "robot_3.toml",
+                "robot_4.toml",
+                "robot_5.toml",
+                "robot_notes.toml"
+            ]
+        );
+    }
+
+    #[test]
+    fn uploads_rejected_while_armed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("robot.toml");
+        fs::write(&path, CONFIG).unwrap();
+
+        let new = CONFIG.replace("port = 44445", "port = 44446");
+
+        let result = upload_config(Armed::Armed, &new, &path, 1);
+        assert!(!result.ok);
+        assert_eq!(result.errors, ["Disarm before uploading a config"]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), CONFIG);
+        assert_eq!(files(dir.path()), ["robot.toml"]);
+
+        // Bad configs are never written
+        let result = upload_config(Armed::Disarmed, "port = ", &path, 2);
+        assert!(!result.ok);
+        assert_eq!(files(dir.path()), ["robot.toml"]);
+
+        let result = upload_config(Armed::Disarmed, &new, &path, 3);
+        assert!(result.ok, "{:?}", result.errors);
+        assert_eq!(fs::read_to_string(&path).unwrap(), new);
+        assert_eq!(files(dir.path()), ["robot.toml", "robot_3.toml"]);
+
+        let snapshot = read_snapshot(&path);
+        assert_eq!(snapshot.toml_text, new);
+        assert_eq!(snapshot.error, None);
+        assert!(snapshot
+            .summary
+            .contains(&("Port".to_owned(), "44446".to_owned())));
+    }
+}
@@ -0,0 +1,288 @@
+//! Scheduling tiers for the main loop
+//!
+//! The main loop ticks at the configured control rate and control systems run every tick.
+//! Telemetry and cosmetic systems that would waste that time use [`telemetry_due`] or
+//! [`run_every`] instead. How close the loop keeps to its rate is logged by [`LoopJitter`].
+
+use std::time::Duration;
+
+use bevy::{prelude::*, time::TimeSystem};
+
+use crate::config::RobotConfig;
+
+/// How often the achieved loop timing is logged
+pub const JITTER_REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+pub struct SchedulePlugin;
+
+impl Plugin for SchedulePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TelemetryTick>()
+            .init_resource::<LoopJitter>()
+            .add_systems(Startup, setup_loop_rates)
+            .add_systems(
+                First,
+                (update_telemetry_tick, measure_loop_jitter)
+                    .after(TimeSystem)
+                    .run_if(resource_exists::<LoopRates>),
+            );
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct LoopRates {
+    /// Period of the main loop
+    pub control: Duration,
+    pub telemetry: Duration,
+}
+
+/// Tracks the slots of something that should happen every period
+///
+/// Slots stay on the same grid so the rate doesn't drift, slots that were missed are skipped
+/// instead of all being run at once.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Every {
+    next_slot: Option<Duration>,
+}
+
+impl Every {
+    /// Whether a slot came by `now`, the first call always runs
+    pub fn tick(&mut self, now: Duration, period: Duration) -> bool {
+        match self.next_slot {
+            Some(next_slot) if now < next_slot => false,
+            Some(next_slot) => {
+                let next_slot = next_slot + period;
+                self.next_slot = Some(if next_slot <= now {
+                    now + period
+                } else {
+                    next_slot
+                });
+
+                true
+            }
+            None => {
+                self.next_slot = Some(now + period);
+
+                true
+            }
+        }
+    }
+}
+
+/// Run condition passing once every `period` of real time
+pub fn run_every(period: Duration) -> impl FnMut(Local<Every>, Res<Time<Real>>) -> bool {
+    move |mut every: Local<Every>, time: Res<Time<Real>>| every.tick(time.elapsed(), period)
+}
+
+/// Whether telemetry runs this tick, shared so all telemetry systems run on the same ticks
+#[derive(Resource, Debug, Clone, Copy, Default)]
+pub struct TelemetryTick {
+    every: Every,
+    due: bool,
+}
+
+/// Run condition for systems on the telemetry rate
+pub fn telemetry_due(tick: Res<TelemetryTick>) -> bool {
+    tick.due
+}
+
+/// Intervals between ticks of the main loop since the last report
+#[derive(Resource, Debug, Clone, Default)]
+pub struct LoopJitter {
+    last_tick: Option<Duration>,
+
+    ticks: u32,
+    total: Duration,
+    /// Sum of the squared deviations from the target period, in seconds squared
+    deviation_squared: f64,
+    worst: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct JitterSummary {
+    pub ticks: u32,
+    pub mean: Duration,
+    /// RMS deviation from the target period
+    pub jitter: Duration,
+    /// Largest deviation from the target period
+    pub worst: Duration,
+}
+
+impl LoopJitter {
+    /// Records a tick at `now` of a loop meant to tick every `target`
+    pub fn tick(&mut self, now: Duration, target: Duration) {
+        let Some(last_tick) = self.last_tick.replace(now) else {
+            return;
+        };
+
+        let interval = now.saturating_sub(last_tick);
+        let deviation = interval.abs_diff(target);
+
+        self.ticks += 1;
+        self.total += interval;
+        self.deviation_squared += deviation.as_secs_f64().powi(2);
+        self.worst = self.worst.max(deviation);
+    }
+
+    pub fn summary(&self) -> Option<JitterSummary> {
+        if self.ticks == 0 {
+            return None;
+        }
+
+        Some(JitterSummary {
+            ticks: self.ticks,
+            mean: self.total / self.ticks,
+            jitter: Duration::from_secs_f64((self.deviation_squared / self.ticks as f64).sqrt()),
+            worst: self.worst,
+        })
+    }
+
+    /// Starts a new window, the interval from the last tick still counts towards it
+    pub fn reset(&mut self) {
+        *self = Self {
+            last_tick: self.last_tick,
+            ..default()
+        };
+    }
+}
+
+fn setup_loop_rates(mut cmds: Commands, config: Res<RobotConfig>) {
+    cmds.insert_resource(LoopRates {
+        control: config.loop_rates.control_period(),
+        telemetry: config.loop_rates.telemetry_period(),
+    });
+}
+
+fn update_telemetry_tick(
+    mut tick: ResMut<TelemetryTick>,
+    rates: Res<LoopRates>,
+    time: Res<Time<Real>>,
+) {
+    tick.due = tick.every.tick(time.elapsed(), rates.telemetry);
+}
+
+fn measure_loop_jitter(
+    mut jitter: ResMut<LoopJitter>,
+    rates: Res<LoopRates>,
+    time: Res<Time<Real>>,
+    mut report: Local<Every>,
+) {
+    let now = time.elapsed();
+    jitter.tick(now, rates.control);
+
+    if !report.tick(now, JITTER_REPORT_INTERVAL) {
+        return;
+    }
+
+    if let Some(summary) = jitter.summary() {
+        info!(
+            "Loop took {:.2?} on average over {} ticks for a {:.2?} period, \
+             jitter {:.2?}, worst {:.2?}",
+            summary.mean, summary.ticks, rates.control, summary.jitter, summary.worst
+        );
+    }
+
+    jitter.reset();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use bevy::prelude::*;
+
+    use super::{
+        run_every, telemetry_due, update_telemetry_tick, Every, LoopJitter, LoopRates,
+        TelemetryTick,
+    };
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[derive(Resource, Default)]
+    struct Runs(Vec<u64>);
+
+    #[test]
+    fn every_keeps_to_its_grid() {
+        let mut every = Every::default();
+        let ran = [0, 5, 10, 19, 20, 31, 40, 95, 100, 104, 105]
+            .into_iter()
+            .filter(|&now| every.tick(ms(now), ms(10)))
+            .collect::<Vec<_>>();
+
+        // Late ticks don't push the next slot back, long gaps don't cause a burst
+        assert_eq!(ran, [0, 10, 20, 31, 40, 95, 105]);
+    }
+
+    #[test]
+    fn run_conditions_follow_real_time() {
+        let start = Instant::now();
+
+        let mut app = App::new();
+        app.insert_resource(Time::<Real>::new(start))
+            .insert_resource(LoopRates {
+                control: ms(10),
+                telemetry: ms(200),
+            })
+            .init_resource::<TelemetryTick>()
+            .init_resource::<Runs>()
+            .add_systems(First, update_telemetry_tick)
+            .add_systems(
+                Update,
+                (|mut runs: ResMut<Runs>, time: Res<Time<Real>>| {
+                    runs.0.push(time.elapsed().as_millis() as u64)
+                })
+                .run_if(telemetry_due),
+            )
+            .add_systems(
+                Update,
+                (|mut runs: ResMut<Runs>| runs.0.push(u64::MAX)).run_if(run_every(ms(50))),
+            );
+
+        for tick in 0..=50 {
+            app.world
+                .resource_mut::<Time<Real>>()
+                .update_with_instant(start + ms(tick * 10));
+            app.update();
+        }
+
+        let runs = &app.world.resource::<Runs>().0;
+        let telemetry = runs
+            .iter()
+            .copied()
+            .filter(|&it| it != u64::MAX)
+            .collect::<Vec<_>>();
+        let every = runs.iter().filter(|&&it| it == u64::MAX).count();
+
+        assert_eq!(telemetry, [0, 200, 400]);
+        assert_eq!(every, 11);
+    }
+
+    #[test]
+    fn jitter_is_measured_against_the_target() {
+        let mut jitter = LoopJitter::default();
+        assert_eq!(jitter.summary(), None);
+
+        // 3 ms late, then 3 ms early, then on time
+        for now in [0, 13, 20, 30] {
+            jitter.tick(ms(now), ms(10));
+        }
+
+        let summary = jitter.summary().unwrap();
+        assert_eq!(summary.ticks, 3);
+        assert_eq!(summary.mean, ms(10));
+        assert_eq!(summary.worst, ms(3));
+        assert!((summary.jitter.as_secs_f64() - (6e-6f64).sqrt()).abs() < 1e-9);
+
+        // The interval spanning a reset goes into the new window
+        jitter.reset();
+        assert_eq!(jitter.summary(), None);
+        jitter.tick(ms(45), ms(10));
+
+        let summary = jitter.summary().unwrap();
+        assert_eq!(summary.ticks, 1);
+        assert_eq!(summary.mean, ms(15));
+        assert_eq!(summary.worst, ms(5));
+    }
+}
@@ -15,11 +15,13 @@ impl Plugin for StatePlugin {
     }
 }
 
+type StatusState<'a> = (Entity, Option<&'a RobotStatus>, Option<&'a Armed>);
+
 // TODO(high): More nuanced state to drive the neopixels
 fn update_state(
     mut cmds: Commands,
     peers: Query<&Peer>,
-    robot: Query<(Entity, Option<&RobotStatus>, Option<&Armed>), With<LocalRobotMarker>>,
+    robot: Query<StatusState<'static>, With<LocalRobotMarker>>,
 ) {
     let (robot, status, armed) = robot.single();
     let mut robot = cmds.entity(robot);
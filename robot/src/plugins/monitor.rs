@@ -1,6 +1,11 @@
 use bevy::{app::PluginGroupBuilder, prelude::PluginGroup};
 
+pub mod build_info;
+pub mod crash_report;
 pub mod hw_stat;
+pub mod logs;
+pub mod power_mode;
+pub mod sync_stats;
 pub mod voltage;
 
 pub struct MonitorPlugins;
@@ -8,7 +13,12 @@ pub struct MonitorPlugins;
 impl PluginGroup for MonitorPlugins {
     fn build(self) -> PluginGroupBuilder {
         PluginGroupBuilder::start::<Self>()
+            .add(build_info::BuildInfoPlugin)
+            .add(crash_report::CrashReportPlugin)
             .add(hw_stat::HwStatPlugin)
+            .add(logs::LogForwardingPlugin)
+            .add(power_mode::PowerModePlugin)
+            .add(sync_stats::SyncStatsPlugin)
             .add(voltage::VoltagePlugin)
     }
 }
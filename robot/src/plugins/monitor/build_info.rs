@@ -0,0 +1,61 @@
+//! Publishes which build of the robot code is running, see `build.rs`
+
+use bevy::prelude::*;
+use common::components::BuildInfo;
+
+use crate::plugins::core::robot::LocalRobot;
+
+pub struct BuildInfoPlugin;
+
+impl Plugin for BuildInfoPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, publish_build_info);
+    }
+}
+
+/// The build info embedded at compile time
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        git_hash: env!("ROBOT_GIT_HASH").to_owned(),
+        build_timestamp: env!("ROBOT_BUILD_TIMESTAMP").to_owned(),
+        profile: env!("ROBOT_BUILD_PROFILE").to_owned(),
+    }
+}
+
+fn publish_build_info(mut cmds: Commands, robot: Res<LocalRobot>) {
+    let build = build_info();
+    info!(
+        "Robot build {} ({}), built {} UTC",
+        build.git_hash, build.profile, build.build_timestamp
+    );
+
+    cmds.entity(robot.entity).insert(build);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build_info;
+
+    #[test]
+    fn build_info_is_embedded() {
+        let build = build_info();
+
+        assert!(!build.git_hash.is_empty());
+        assert!(!build.profile.is_empty());
+
+        // YYYY-MM-DD HH:MM:SS
+        let timestamp = build.build_timestamp.as_bytes();
+        assert_eq!(timestamp.len(), 19, "{}", build.build_timestamp);
+        assert_eq!(
+            [
+                timestamp[4],
+                timestamp[7],
+                timestamp[10],
+                timestamp[13],
+                timestamp[16]
+            ],
+            *b"-- ::"
+        );
+        assert!(build.build_timestamp.starts_with("20"));
+    }
+}
@@ -0,0 +1,486 @@
+//! Keeps the details of a panic around until someone on the surface has seen them
+//!
+//! The panic hook writes a [`CrashReport`] next to the robot code, the next run publishes it as a
+//! [`PreviousCrashReport`] until the surface archives it.
+
+use std::{
+    fmt::{self, Display, Write as _},
+    fs, io,
+    panic::{self, PanicHookInfo},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use bevy::prelude::*;
+use common::{
+    components::{CrashReport, PreviousCrashReport},
+    error::ErrorEvent,
+    events::ArchiveCrashReport,
+};
+
+use crate::plugins::{
+    core::robot::{LocalRobot, LocalRobotMarker},
+    monitor::build_info::build_info,
+};
+
+/// Where the panic hook writes the report
+pub const CRASH_REPORT_PATH: &str = "/home/pi/mate/crash_report.json";
+/// How many archived reports are kept, the oldest are deleted first
+pub const MAX_ARCHIVED_REPORTS: usize = 10;
+
+/// Longest panic message kept, in bytes
+const MAX_MESSAGE_LEN: usize = 4 * 1024;
+/// Longest backtrace kept, in bytes
+const MAX_BACKTRACE_LEN: usize = 64 * 1024;
+const TRUNCATED: &str = "\n... (truncated)";
+
+pub struct CrashReportPlugin;
+
+impl Plugin for CrashReportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Startup,
+            publish_crash_report.run_if(resource_exists::<CrashReportFile>),
+        );
+        app.add_systems(
+            Update,
+            archive_crash_report.run_if(resource_exists::<CrashReportFile>),
+        );
+    }
+}
+
+/// The report the panic hook writes, see [`install_panic_hook`]
+#[derive(Resource, Debug, Clone)]
+pub struct CrashReportFile(pub PathBuf);
+
+/// Only the first panic is recorded, the rest are usually fallout from it
+static PANICKED: AtomicBool = AtomicBool::new(false);
+
+/// Writes a crash report to `path` when anything panics, then runs the previous hook
+pub fn install_panic_hook(path: PathBuf) {
+    let started = Instant::now();
+    let default_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        if !PANICKED.swap(true, Ordering::SeqCst) {
+            let report = panic_report(info, started);
+
+            if let Err(err) = write_report(&path, &report) {
+                eprintln!(
+                    "Could not write crash report to {}: {err:?}",
+                    path.display()
+                );
+            }
+        }
+
+        default_hook(info);
+    }));
+}
+
+fn panic_report(info: &PanicHookInfo, started: Instant) -> CrashReport {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|it| it.as_secs())
+        .unwrap_or_default();
+
+    CrashReport {
+        message: bounded(info, MAX_MESSAGE_LEN),
+        backtrace: bounded_backtrace(MAX_BACKTRACE_LEN),
+        timestamp,
+        build: build_info(),
+        uptime: started.elapsed(),
+    }
+}
+
+/// Text that stops growing once it reaches `limit` bytes, writes past it fail
+struct Bounded {
+    text: String,
+    limit: usize,
+    truncated: bool,
+}
+
+impl Bounded {
+    fn new(limit: usize) -> Self {
+        Self {
+            text: String::new(),
+            limit,
+            truncated: false,
+        }
+    }
+
+    fn finish(mut self) -> String {
+        if self.truncated {
+            self.text.push_str(TRUNCATED);
+        }
+
+        self.text
+    }
+}
+
+impl fmt::Write for Bounded {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let room = self.limit - self.text.len();
+        if s.len() <= room {
+            self.text.push_str(s);
+            return Ok(());
+        }
+
+        let mut end = room;
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.text.push_str(&s[..end]);
+        self.truncated = true;
+
+        Err(fmt::Error)
+    }
+}
+
+/// Formats `value`, stopping once it reaches `limit` bytes
+///
+/// Formatting is cut short rather than truncated afterwards so a huge value is never held in full
+fn bounded(value: impl Display, limit: usize) -> String {
+    let mut bounded = Bounded::new(limit);
+    let _ = write!(bounded, "{value}");
+
+    bounded.finish()
+}
+
+/// Formats the current stack, stopping once it reaches `limit` bytes
+///
+/// [`std::backtrace::Backtrace`] symbolizes every frame before printing any of them, here frames
+/// are resolved one at a time and the walk stops when the limit is hit
+fn bounded_backtrace(limit: usize) -> String {
+    let mut bounded = Bounded::new(limit);
+    let mut index = 0;
+
+    backtrace::trace(|frame| {
+        let mut full = false;
+
+        backtrace::resolve_frame(frame, |symbol| {
+            if full {
+                return;
+            }
+
+            let name = symbol
+                .name()
+                .map(|it| it.to_string())
+                .unwrap_or_else(|| "<unknown>".to_owned());
+            full |= writeln!(bounded, "{index:>4}: {name}").is_err();
+
+            if let (Some(file), Some(line)) = (symbol.filename(), symbol.lineno()) {
+                full |= writeln!(bounded, "             at {}:{line}", file.display()).is_err();
+            }
+        });
+        index += 1;
+
+        !full
+    });
+
+    bounded.finish()
+}
+
+/// Writes to a temporary file first so a crash mid write never leaves a partial report
+pub fn write_report(path: &Path, report: &CrashReport) -> anyhow::Result<()> {
+    let json = serde_json::to_vec_pretty(report).context("Serialize crash report")?;
+
+    let temp = path.with_extension("tmp");
+    fs::write(&temp, json).context("Write crash report")?;
+    fs::rename(&temp, path).context("Move crash report into place")?;
+
+    Ok(())
+}
+
+/// The report left by the previous run, if it crashed
+pub fn read_report(path: &Path) -> anyhow::Result<Option<CrashReport>> {
+    let json = match fs::read(path) {
+        Ok(json) => json,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err).context("Read crash report"),
+    };
+
+    let report = serde_json::from_slice(&json).context("Parse crash report")?;
+
+    Ok(Some(report))
+}
+
+/// Renames the report at `path` so it isn't published again, then deletes all but the newest
+/// `keep` archived reports
+///
+/// Archived reports are named after the report with the crash's `timestamp` appended
+pub fn archive_report(path: &Path, timestamp: u64, keep: usize) -> anyhow::Result<PathBuf> {
+    let archived = archived_path(path, timestamp);
+    fs::rename(path, &archived).context("Archive crash report")?;
+
+    prune_archives(path, keep)?;
+
+    Ok(archived)
+}
+
+/// Deletes all but the newest `keep` archived copies of `path`
+pub(crate) fn prune_archives(path: &Path, keep: usize) -> anyhow::Result<()> {
+    let mut archives = archived_copies(path)?;
+    // Newest first
+    archives.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+    for (_, old) in archives.into_iter().skip(keep) {
+        fs::remove_file(&old).context("Delete old archive")?;
+    }
+
+    Ok(())
+}
+
+/// `path` with `timestamp` appended to its name
+pub(crate) fn archived_path(path: &Path, timestamp: u64) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!(
+            "{stem}_{timestamp}.{}",
+            extension.to_string_lossy()
+        )),
+        None => path.with_file_name(format!("{stem}_{timestamp}")),
+    }
+}
+
+/// Every archived copy of `path` next to it with its timestamp
+fn archived_copies(path: &Path) -> anyhow::Result<Vec<(u64, PathBuf)>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let prefix = format!("{stem}_");
+    let extension = path.extension();
+
+    let mut archives = Vec::new();
+    for entry in fs::read_dir(dir).context("List archives")? {
+        let entry = entry.context("List archives")?;
+        let path = entry.path();
+
+        if path.extension() != extension {
+            continue;
+        }
+
+        let name = path.file_stem().unwrap_or_default().to_string_lossy();
+        let timestamp = name
+            .strip_prefix(&prefix)
+            .and_then(|it| it.parse::<u64>().ok());
+
+        if let Some(timestamp) = timestamp {
+            archives.push((timestamp, path));
+        }
+    }
+
+    Ok(archives)
+}
+
+fn publish_crash_report(
+    mut cmds: Commands,
+    robot: Res<LocalRobot>,
+    file: Res<CrashReportFile>,
+    mut errors: EventWriter<ErrorEvent>,
+) {
+    let path = &file.0;
+
+    match read_report(path) {
+        Ok(Some(report)) => {
+            warn!(
+                "Previous run crashed {:.0?} after starting: {}",
+                report.uptime, report.message
+            );
+
+            cmds.entity(robot.entity)
+                .insert(PreviousCrashReport(report));
+        }
+        Ok(None) => {}
+        Err(err) => {
+            errors.send(err.into());
+
+            // Keep it for a person to look at, but don't trip over it every start
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|it| it.as_secs())
+                .unwrap_or_default();
+            if let Err(err) = archive_report(path, timestamp, MAX_ARCHIVED_REPORTS) {
+                errors.send(err.into());
+            }
+        }
+    }
+}
+
+fn archive_crash_report(
+    mut cmds: Commands,
+    mut archive: EventReader<ArchiveCrashReport>,
+    robot: Query<(Entity, &PreviousCrashReport), With<LocalRobotMarker>>,
+    file: Res<CrashReportFile>,
+    mut errors: EventWriter<ErrorEvent>,
+) {
+    if archive.read().count() == 0 {
+        return;
+    }
+
+    let Ok((entity, PreviousCrashReport(report))) = robot.get_single() else {
+        return;
+    };
+
+    match archive_report(&file.0, report.timestamp, MAX_ARCHIVED_REPORTS) {
+        Ok(archived) => info!("Archived crash report to {}", archived.display()),
+        Err(err) => {
+            errors.send(err.into());
+        }
+    }
+
+    cmds.entity(entity).remove::<PreviousCrashReport>();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use bevy::prelude::*;
+    use common::{
+        components::{BuildInfo, CrashReport, PreviousCrashReport},
+        ecs_sync::NetId,
+        error::ErrorEvent,
+        events::ArchiveCrashReport,
+    };
+
+    use crate::plugins::core::robot::{LocalRobot, LocalRobotMarker};
+
+    use super::{
+        archive_report, bounded, bounded_backtrace, read_report, write_report, CrashReportFile,
+        CrashReportPlugin, MAX_ARCHIVED_REPORTS, TRUNCATED,
+    };
+
+    fn report(timestamp: u64) -> CrashReport {
+        CrashReport {
+            message: "panicked at src/main.rs:1:1:\nOh no".to_owned(),
+            backtrace: "0: robot::main".to_owned(),
+            timestamp,
+            build: BuildInfo {
+                git_hash: "abc1234".to_owned(),
+                build_timestamp: "2024-05-01 12:00:00".to_owned(),
+                profile: "release".to_owned(),
+            },
+            uptime: Duration::from_secs(90),
+        }
+    }
+
+    #[test]
+    fn reports_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crash_report.json");
+
+        // A clean previous run
+        assert_eq!(read_report(&path).unwrap(), None);
+
+        write_report(&path, &report(1_700_000_000)).unwrap();
+        assert_eq!(read_report(&path).unwrap(), Some(report(1_700_000_000)));
+
+        // Nothing left behind from writing
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Unreadable reports are errors, not a clean run
+        fs::write(&path, "{ not json").unwrap();
+        assert!(read_report(&path).is_err());
+    }
+
+    #[test]
+    fn archiving_rotates_old_reports() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crash_report.json");
+
+        for timestamp in 0..MAX_ARCHIVED_REPORTS as u64 + 3 {
+            write_report(&path, &report(timestamp)).unwrap();
+
+            let archived = archive_report(&path, timestamp, MAX_ARCHIVED_REPORTS).unwrap();
+            assert_eq!(
+                archived,
+                dir.path().join(format!("crash_report_{timestamp}.json"))
+            );
+
+            // Archived reports aren't published again
+            assert_eq!(read_report(&path).unwrap(), None);
+        }
+
+        let mut remaining = fs::read_dir(&dir)
+            .unwrap()
+            .map(|it| it.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        remaining.sort();
+
+        // The oldest three are gone, files that aren't reports are left alone
+        let mut expected = (3..MAX_ARCHIVED_REPORTS as u64 + 3)
+            .map(|it| format!("crash_report_{it}.json"))
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(remaining, expected);
+    }
+
+    #[test]
+    fn unrelated_files_survive_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crash_report.json");
+        fs::write(dir.path().join("crash_report_notes.json"), "").unwrap();
+        fs::write(dir.path().join("robot.toml"), "").unwrap();
+
+        write_report(&path, &report(5)).unwrap();
+        archive_report(&path, 5, 0).unwrap();
+
+        assert!(dir.path().join("crash_report_notes.json").exists());
+        assert!(dir.path().join("robot.toml").exists());
+        assert!(!dir.path().join("crash_report_5.json").exists());
+    }
+
+    #[test]
+    fn previous_crash_is_published_at_startup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("crash_report.json");
+        write_report(&path, &report(1_700_000_000)).unwrap();
+
+        let mut app = App::new();
+        app.add_event::<ErrorEvent>()
+            .add_event::<ArchiveCrashReport>()
+            .insert_resource(CrashReportFile(path.clone()))
+            .add_plugins(CrashReportPlugin);
+
+        let robot = app.world.spawn(LocalRobotMarker).id();
+        app.world.insert_resource(LocalRobot {
+            net_id: NetId::random(),
+            entity: robot,
+        });
+
+        app.update();
+        assert_eq!(
+            app.world.get::<PreviousCrashReport>(robot),
+            Some(&PreviousCrashReport(report(1_700_000_000)))
+        );
+
+        // Archiving it clears it for the next start
+        app.world.send_event(ArchiveCrashReport);
+        app.update();
+        assert_eq!(app.world.get::<PreviousCrashReport>(robot), None);
+        assert_eq!(read_report(&path).unwrap(), None);
+        assert!(dir.path().join("crash_report_1700000000.json").exists());
+    }
+
+    #[test]
+    fn long_values_are_cut_short() {
+        assert_eq!(bounded("short", 16), "short");
+
+        let long = "x".repeat(100);
+        let text = bounded(&long, 10);
+        assert_eq!(text, format!("{}{TRUNCATED}", "x".repeat(10)));
+
+        // Never splits a character
+        let text = bounded("ééé", 3);
+        assert_eq!(text, format!("é{TRUNCATED}"));
+
+        let backtrace = bounded_backtrace(64);
+        assert!(backtrace.ends_with(TRUNCATED), "{backtrace}");
+        assert_eq!(backtrace.len(), 64 + TRUNCATED.len());
+    }
+}
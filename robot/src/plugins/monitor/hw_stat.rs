@@ -5,51 +5,78 @@ use bevy::{app::AppExit, prelude::*};
 use common::{
     bundles::RobotSystemBundle,
     components::{
-        Cores, CpuTotal, Disks, LoadAverage, Memory, Networks, OperatingSystem, Processes,
-        Temperatures, Uptime,
+        Cores, CpuTotal, Disks, LoadAverage, Memory, Networks, OperatingSystem, PowerMode,
+        Processes, Temperatures, Uptime,
     },
     error::{self, Errors},
+    shutdown::{self, AppShutdownSet, SpawnWorkerExt, WorkerThread},
     types::{
+        bounded::BoundedVec,
         system::{ComponentTemperature, Cpu, Disk, Network, Process},
         units::Celsius,
     },
 };
-use crossbeam::channel::{self, Receiver, Sender};
+use crossbeam::{
+    channel::{self, Receiver, Sender},
+    select,
+};
 use sysinfo::{
     ComponentExt, CpuExt, DiskExt, NetworkExt, NetworksExt, PidExt, ProcessExt, System, SystemExt,
     UserExt,
 };
 use tracing::{span, Level};
 
-use crate::plugins::core::robot::LocalRobot;
+use crate::plugins::core::{
+    robot::{LocalRobot, LocalRobotMarker},
+    schedule::telemetry_due,
+};
 
 pub struct HwStatPlugin;
 
 impl Plugin for HwStatPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, start_hw_stat_thread.pipe(error::handle_errors));
-        app.add_systems(PreUpdate, read_new_data);
-        app.add_systems(Last, shutdown);
+        app.add_systems(PreUpdate, read_new_data.run_if(telemetry_due));
+        app.add_systems(Update, follow_power_mode);
+        app.add_systems(Last, shutdown.in_set(AppShutdownSet::Threads));
     }
 }
 
 #[derive(Resource)]
-struct HwStatChannels(Receiver<RobotSystemBundle>, Sender<()>);
+struct HwStatChannels(
+    Receiver<RobotSystemBundle>,
+    Sender<()>,
+    /// Time between samples
+    Sender<Duration>,
+    Option<WorkerThread<()>>,
+);
+
+/// How long shutdown waits for the monitor thread, a sample can take a while
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Time between samples, each one refreshes everything sysinfo knows about
+fn sample_interval(power: PowerMode) -> Duration {
+    match power {
+        PowerMode::Normal => Duration::from_secs(1),
+        PowerMode::LowPower => Duration::from_secs(5),
+        PowerMode::Critical => Duration::from_secs(15),
+    }
+}
 
 fn start_hw_stat_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Result<()> {
     let (tx_data, rx_data) = channel::bounded(10);
     let (tx_exit, rx_exit) = channel::bounded(1);
-
-    cmds.insert_resource(HwStatChannels(rx_data, tx_exit));
+    let (tx_interval, rx_interval) = channel::bounded(5);
 
     let errors = errors.0.clone();
-    thread::Builder::new()
+    let monitor_thread = thread::Builder::new()
         .name("Hardware monitor thread".to_owned())
-        .spawn(move || {
+        .spawn_worker(move || {
             let span = span!(Level::INFO, "System Monitor Thread");
             let _enter = span.enter();
 
             let mut system = System::new();
+            let mut interval = sample_interval(PowerMode::Normal);
             loop {
                 let span = span!(Level::INFO, "System Monitor Cycle").entered();
 
@@ -81,11 +108,26 @@ fn start_hw_stat_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Resu
 
                 span.exit();
 
-                thread::sleep(Duration::from_secs(1));
+                // A new interval takes effect right away, an exit request wakes the thread
+                select! {
+                    recv(rx_interval) -> next_interval => match next_interval {
+                        Ok(next_interval) => interval = next_interval,
+                        Err(_) => return,
+                    },
+                    recv(rx_exit) -> _ => return,
+                    default(interval) => {}
+                }
             }
         })
         .context("Spawn thread")?;
 
+    cmds.insert_resource(HwStatChannels(
+        rx_data,
+        tx_exit,
+        tx_interval,
+        Some(monitor_thread),
+    ));
+
     Ok(())
 }
 
@@ -96,9 +138,24 @@ fn read_new_data(mut cmds: Commands, channels: Res<HwStatChannels>, robot: Res<L
     }
 }
 
-fn shutdown(channels: Res<HwStatChannels>, mut exit: EventReader<AppExit>) {
+fn follow_power_mode(
+    channels: Res<HwStatChannels>,
+    robot: Query<&PowerMode, (With<LocalRobotMarker>, Changed<PowerMode>)>,
+) {
+    for &power in &robot {
+        let _ = channels.2.send(sample_interval(power));
+    }
+}
+
+fn shutdown(mut channels: ResMut<HwStatChannels>, mut exit: EventReader<AppExit>) {
     for _event in exit.read() {
         let _ = channels.1.send(());
+
+        if let Some(monitor_thread) = channels.3.take() {
+            if !shutdown::join_timeout(monitor_thread, SHUTDOWN_TIMEOUT) {
+                warn!("Hardware monitor thread did not shut down in time");
+            }
+        }
     }
 }
 
@@ -166,18 +223,15 @@ fn collect_system_state(system: &System) -> anyhow::Result<RobotSystemBundle> {
             used_swap: system.used_swap(),
             free_swap: system.free_swap(),
         },
-        temps: Temperatures(
-            system
-                .components()
-                .iter()
-                .map(|component| ComponentTemperature {
-                    tempature: Celsius(component.temperature()),
-                    tempature_max: Celsius(component.max()),
-                    tempature_critical: component.critical().map(Celsius),
-                    name: component.label().to_owned(),
-                })
-                .collect(),
-        ),
+        // Losing a few sensors is better than losing the whole sample
+        temps: Temperatures(BoundedVec::truncating_from(system.components().iter().map(
+            |component| ComponentTemperature {
+                tempature: Celsius(component.temperature()),
+                tempature_max: Celsius(component.max()),
+                tempature_critical: component.critical().map(Celsius),
+                name: component.label().to_owned(),
+            },
+        ))),
         disks: Disks(
             system
                 .disks()
@@ -203,3 +257,43 @@ fn collect_system_state(system: &System) -> anyhow::Result<RobotSystemBundle> {
 
     Ok(hw_state)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{app::App, prelude::*};
+    use common::components::PowerMode;
+    use crossbeam::channel;
+
+    use crate::plugins::core::robot::LocalRobotMarker;
+
+    use super::{follow_power_mode, HwStatChannels};
+
+    #[test]
+    fn sampling_slows_with_power_mode() {
+        let (_tx_data, rx_data) = channel::bounded(1);
+        let (tx_exit, _rx_exit) = channel::bounded(1);
+        let (tx_interval, rx_interval) = channel::bounded(5);
+
+        let mut app = App::new();
+        app.insert_resource(HwStatChannels(rx_data, tx_exit, tx_interval, None))
+            .add_systems(Update, follow_power_mode);
+        let robot = app.world.spawn((LocalRobotMarker, PowerMode::Normal)).id();
+
+        app.update();
+        assert_eq!(rx_interval.try_recv(), Ok(Duration::from_secs(1)));
+
+        // Only sent on changes
+        app.update();
+        assert!(rx_interval.try_recv().is_err());
+
+        app.world.entity_mut(robot).insert(PowerMode::LowPower);
+        app.update();
+        assert_eq!(rx_interval.try_recv(), Ok(Duration::from_secs(5)));
+
+        app.world.entity_mut(robot).insert(PowerMode::Critical);
+        app.update();
+        assert_eq!(rx_interval.try_recv(), Ok(Duration::from_secs(15)));
+    }
+}
@@ -0,0 +1,247 @@
+//! Forwards robot log records to the surface so they can be read without ssh
+
+use std::{
+    fmt::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+    time::{Duration, SystemTime},
+};
+
+use bevy::{log::BoxedSubscriber, prelude::*};
+use common::events::{LogLevel, RemoteLogRecord};
+use crossbeam::queue::ArrayQueue;
+use tracing::{
+    field::{Field, Visit},
+    Level, Subscriber,
+};
+use tracing_subscriber::{
+    layer::{Context, SubscriberExt},
+    Layer,
+};
+
+/// Records buffered between frames, anything past this is dropped
+const QUEUE_CAPACITY: usize = 256;
+/// Sustained records forwarded per second
+const RECORDS_PER_SECOND: f32 = 20.0;
+/// Records that may be forwarded at once after a quiet period
+const RECORD_BURST: f32 = 40.0;
+
+/// Targets whose logs are never forwarded, forwarding them would generate more of them
+const IGNORED_TARGETS: &[&str] = &[
+    "common::sync",
+    "common::ecs_sync",
+    "networking",
+    module_path!(),
+];
+
+static LOG_QUEUE: OnceLock<LogQueue> = OnceLock::new();
+
+fn log_queue() -> &'static LogQueue {
+    LOG_QUEUE.get_or_init(|| LogQueue::new(QUEUE_CAPACITY))
+}
+
+/// Installs the forwarding layer, meant to be passed to `LogPlugin::update_subscriber`
+pub fn install_log_forwarding(subscriber: BoxedSubscriber) -> BoxedSubscriber {
+    Box::new(subscriber.with(ForwardingLayer { queue: log_queue() }))
+}
+
+pub struct LogForwardingPlugin;
+
+impl Plugin for LogForwardingPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ForwardingBudget(RECORD_BURST))
+            .add_systems(Update, forward_logs);
+    }
+}
+
+#[derive(Resource)]
+struct ForwardingBudget(f32);
+
+fn forward_logs(
+    time: Res<Time<Real>>,
+    mut budget: ResMut<ForwardingBudget>,
+    mut records: EventWriter<RemoteLogRecord>,
+) {
+    let Some(queue) = LOG_QUEUE.get() else {
+        // Forwarding layer was not installed
+        return;
+    };
+
+    budget.0 = (budget.0 + time.delta_seconds() * RECORDS_PER_SECOND).min(RECORD_BURST);
+
+    while budget.0 >= 1.0 {
+        let Some(record) = queue.pop() else {
+            break;
+        };
+
+        budget.0 -= 1.0;
+        records.send(record);
+    }
+}
+
+/// Bounded lock free queue of captured records
+///
+/// Pushing never blocks, records that do not fit are counted and the count is attached to the
+/// next record that makes it through
+pub struct LogQueue {
+    records: ArrayQueue<RemoteLogRecord>,
+    dropped: AtomicU64,
+}
+
+impl LogQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: ArrayQueue::new(capacity),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns false if the record was dropped
+    pub fn push(&self, mut record: RemoteLogRecord) -> bool {
+        record.dropped = self.dropped.swap(0, Ordering::Relaxed);
+
+        match self.records.push(record) {
+            Ok(()) => true,
+            Err(record) => {
+                self.dropped
+                    .fetch_add(record.dropped + 1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<RemoteLogRecord> {
+        self.records.pop()
+    }
+
+    /// Records dropped since the last one that made it into the queue
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+pub fn should_forward(level: &Level, target: &str) -> bool {
+    // More verbose levels compare greater
+    *level <= Level::WARN && !IGNORED_TARGETS.iter().any(|it| target.starts_with(it))
+}
+
+struct ForwardingLayer {
+    queue: &'static LogQueue,
+}
+
+impl<S: Subscriber> Layer<S> for ForwardingLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if !should_forward(metadata.level(), metadata.target()) {
+            return;
+        }
+
+        let mut visitor = MessageVisitor::default();
+        event.record(&mut visitor);
+
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+
+        self.queue.push(RemoteLogRecord {
+            level: log_level(metadata.level()),
+            target: metadata.target().to_owned(),
+            message: visitor.0,
+            timestamp,
+            dropped: 0,
+        });
+    }
+}
+
+fn log_level(level: &Level) -> LogLevel {
+    match *level {
+        Level::ERROR => LogLevel::Error,
+        Level::WARN => LogLevel::Warn,
+        Level::INFO => LogLevel::Info,
+        Level::DEBUG => LogLevel::Debug,
+        Level::TRACE => LogLevel::Trace,
+    }
+}
+
+#[derive(Default)]
+struct MessageVisitor(String);
+
+impl Visit for MessageVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+
+        if field.name() == "message" {
+            let _ = write!(self.0, "{value:?}");
+        } else {
+            let _ = write!(self.0, "{}={value:?}", field.name());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use common::events::{LogLevel, RemoteLogRecord};
+    use tracing::Level;
+
+    use super::{should_forward, LogQueue};
+
+    fn record(message: &str) -> RemoteLogRecord {
+        RemoteLogRecord {
+            level: LogLevel::Warn,
+            target: "robot".to_owned(),
+            message: message.to_owned(),
+            timestamp: Duration::ZERO,
+            dropped: 0,
+        }
+    }
+
+    #[test]
+    fn queue_counts_drops() {
+        let queue = LogQueue::new(2);
+
+        assert!(queue.push(record("a")));
+        assert!(queue.push(record("b")));
+        assert!(!queue.push(record("c")));
+        assert!(!queue.push(record("d")));
+        assert_eq!(queue.dropped(), 2);
+
+        assert_eq!(queue.pop().unwrap().message, "a");
+
+        // The next record through carries the drop count
+        assert!(queue.push(record("e")));
+        assert_eq!(queue.dropped(), 0);
+
+        assert_eq!(queue.pop().unwrap().message, "b");
+        let e = queue.pop().unwrap();
+        assert_eq!(e.message, "e");
+        assert_eq!(e.dropped, 2);
+
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn filters_by_level_and_target() {
+        assert!(should_forward(&Level::ERROR, "robot::plugins::actuators"));
+        assert!(should_forward(&Level::WARN, "robot::plugins::actuators"));
+        assert!(!should_forward(&Level::INFO, "robot::plugins::actuators"));
+
+        assert!(!should_forward(&Level::WARN, "common::sync"));
+        assert!(!should_forward(
+            &Level::ERROR,
+            "common::ecs_sync::apply_changes"
+        ));
+        assert!(!should_forward(&Level::WARN, "networking::peer"));
+        assert!(!should_forward(
+            &Level::WARN,
+            module_path!().trim_end_matches("::tests")
+        ));
+
+        assert!(should_forward(&Level::WARN, "common::error"));
+    }
+}
@@ -0,0 +1,246 @@
+//! Picks the robot's [`PowerMode`] from the battery voltage
+//!
+//! The other plugins read the mode off the robot and cut back on their own. The surface can pin
+//! a mode with [`PowerModeOverride`], the voltage is still tracked meanwhile so clearing it goes
+//! straight back to whatever the battery calls for.
+
+use bevy::prelude::*;
+use common::components::{MeasuredVoltage, PowerMode, PowerModeOverride, PowerModeReason};
+
+use crate::{
+    config::{PowerModeConfig, RobotConfig},
+    plugins::core::{
+        robot::{LocalRobot, LocalRobotMarker},
+        schedule::telemetry_due,
+    },
+};
+
+pub struct PowerModePlugin;
+
+impl Plugin for PowerModePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_power_mode).add_systems(
+            Update,
+            update_power_mode
+                .run_if(telemetry_due)
+                .run_if(resource_exists::<LocalRobot>),
+        );
+    }
+}
+
+/// Readings this low mean the voltage sense isn't connected
+const NO_BATTERY: f32 = 1.0;
+
+/// The mode the battery calls for coming from `current`
+///
+/// Worse modes are entered as soon as the voltage drops below their threshold, better ones only
+/// once it climbs `hysteresis` above it
+pub fn next_mode(current: PowerMode, voltage: f32, config: &PowerModeConfig) -> PowerMode {
+    let mode_below = |offset: f32| {
+        if voltage < config.critical_voltage + offset {
+            PowerMode::Critical
+        } else if voltage < config.low_voltage + offset {
+            PowerMode::LowPower
+        } else {
+            PowerMode::Normal
+        }
+    };
+
+    let falling = mode_below(0.0);
+    let rising = mode_below(config.hysteresis);
+
+    if falling > current {
+        falling
+    } else if rising < current {
+        rising
+    } else {
+        current
+    }
+}
+
+/// Why the battery put the robot in `mode`
+fn battery_reason(mode: PowerMode, config: &PowerModeConfig) -> String {
+    match mode {
+        PowerMode::Normal => "Battery ok".to_owned(),
+        PowerMode::LowPower => format!("Battery below {:.1}V", config.low_voltage),
+        PowerMode::Critical => format!("Battery below {:.1}V", config.critical_voltage),
+    }
+}
+
+fn setup_power_mode(mut cmds: Commands, robot: Res<LocalRobot>, config: Res<RobotConfig>) {
+    cmds.entity(robot.entity).insert((
+        PowerMode::Normal,
+        PowerModeReason(battery_reason(PowerMode::Normal, &config.power_mode)),
+    ));
+}
+
+type PowerModeState<'a> = (
+    Entity,
+    Option<&'a MeasuredVoltage>,
+    Option<&'a PowerModeOverride>,
+    Option<&'a PowerMode>,
+    Option<&'a PowerModeReason>,
+);
+
+fn update_power_mode(
+    mut cmds: Commands,
+    config: Res<RobotConfig>,
+    mut battery: Local<PowerMode>,
+    robot: Query<PowerModeState<'static>, With<LocalRobotMarker>>,
+) {
+    let Ok((entity, voltage, mode_override, mode, reason)) = robot.get_single() else {
+        return;
+    };
+
+    let config = &config.power_mode;
+
+    if let Some(voltage) = voltage.filter(|it| it.0 .0 > NO_BATTERY) {
+        *battery = next_mode(*battery, voltage.0 .0, config);
+    }
+
+    let (next, next_reason) = match mode_override.and_then(|it| it.0) {
+        Some(mode) => (mode, "Set from the surface".to_owned()),
+        None => (*battery, battery_reason(*battery, config)),
+    };
+
+    if mode == Some(&next) && reason.is_some_and(|it| it.0 == next_reason) {
+        return;
+    }
+
+    let previous = mode.copied().unwrap_or_default();
+    if next > previous {
+        warn!("Power mode {previous:?} -> {next:?}: {next_reason}");
+    } else {
+        info!("Power mode {previous:?} -> {next:?}: {next_reason}");
+    }
+
+    cmds.entity(entity)
+        .insert((next, PowerModeReason(next_reason)));
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::App, prelude::*};
+    use common::{
+        components::{MeasuredVoltage, PowerMode, PowerModeOverride, PowerModeReason},
+        ecs_sync::NetId,
+        types::units::Volts,
+    };
+
+    use crate::{
+        config::{PowerModeConfig, RobotConfig},
+        plugins::core::robot::{LocalRobot, LocalRobotMarker},
+    };
+
+    use super::{next_mode, update_power_mode};
+
+    use PowerMode::{Critical, LowPower, Normal};
+
+    /// Runs `voltages` through the state machine from `Normal`
+    fn modes(voltages: &[f32]) -> Vec<PowerMode> {
+        let config = PowerModeConfig::default();
+
+        voltages
+            .iter()
+            .scan(Normal, |mode, &voltage| {
+                *mode = next_mode(*mode, voltage, &config);
+                Some(*mode)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn modes_follow_thresholds() {
+        assert_eq!(modes(&[12.0, 10.4, 9.9]), [Normal, LowPower, Critical]);
+        // Straight to critical
+        assert_eq!(modes(&[9.5]), [Critical]);
+    }
+
+    #[test]
+    fn recovery_needs_hysteresis() {
+        // Sag under thrust hovering around the threshold doesn't flap
+        assert_eq!(
+            modes(&[10.4, 10.6, 10.4, 10.7, 10.81]),
+            [LowPower, LowPower, LowPower, LowPower, Normal]
+        );
+
+        assert_eq!(
+            modes(&[9.9, 10.2, 10.31, 10.7, 10.9]),
+            [Critical, Critical, LowPower, LowPower, Normal]
+        );
+
+        // A big recovery skips low power
+        assert_eq!(modes(&[9.9, 12.0]), [Critical, Normal]);
+    }
+
+    fn app() -> (App, Entity) {
+        let mut app = App::new();
+
+        let config: RobotConfig = toml::from_str(include_str!("../../../robot.toml")).unwrap();
+        app.insert_resource(config)
+            .add_systems(Update, update_power_mode);
+
+        let robot = app
+            .world
+            .spawn((LocalRobotMarker, MeasuredVoltage(Volts(12.0))))
+            .id();
+        app.world.insert_resource(LocalRobot {
+            net_id: NetId::random(),
+            entity: robot,
+        });
+
+        (app, robot)
+    }
+
+    fn state(app: &App, robot: Entity) -> (PowerMode, String) {
+        let robot = app.world.entity(robot);
+
+        (
+            *robot.get::<PowerMode>().unwrap(),
+            robot.get::<PowerModeReason>().unwrap().0.clone(),
+        )
+    }
+
+    #[test]
+    fn mode_follows_battery_and_override() {
+        let (mut app, robot) = app();
+
+        app.update();
+        assert_eq!(state(&app, robot), (Normal, "Battery ok".to_owned()));
+
+        app.world
+            .entity_mut(robot)
+            .insert(MeasuredVoltage(Volts(10.2)));
+        app.update();
+        assert_eq!(
+            state(&app, robot),
+            (LowPower, "Battery below 10.5V".to_owned())
+        );
+
+        // Without a voltage sense the last mode is kept
+        app.world
+            .entity_mut(robot)
+            .insert(MeasuredVoltage(Volts(0.0)));
+        app.update();
+        assert_eq!(state(&app, robot).0, LowPower);
+
+        app.world
+            .entity_mut(robot)
+            .insert(PowerModeOverride(Some(Normal)));
+        app.update();
+        assert_eq!(
+            state(&app, robot),
+            (Normal, "Set from the surface".to_owned())
+        );
+
+        // The battery kept being tracked under the override
+        app.world
+            .entity_mut(robot)
+            .insert((PowerModeOverride(None), MeasuredVoltage(Volts(9.0))));
+        app.update();
+        assert_eq!(
+            state(&app, robot),
+            (Critical, "Battery below 10.0V".to_owned())
+        );
+    }
+}
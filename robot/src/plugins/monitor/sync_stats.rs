@@ -0,0 +1,30 @@
+use bevy::prelude::*;
+use common::{components::RemoteSyncStats, ecs_sync::stats::SyncStats};
+
+use crate::plugins::core::robot::LocalRobot;
+
+pub struct SyncStatsPlugin;
+
+impl Plugin for SyncStatsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Last, publish_sync_stats);
+    }
+}
+
+/// Types sent to the surface, the rest are only visible in the robot's logs
+const PUBLISHED_TYPES: usize = 15;
+
+fn publish_sync_stats(
+    mut cmds: Commands,
+    robot: Res<LocalRobot>,
+    stats: Res<SyncStats>,
+    mut last_window: Local<u64>,
+) {
+    if stats.windows() == *last_window {
+        return;
+    }
+    *last_window = stats.windows();
+
+    cmds.entity(robot.entity)
+        .insert(RemoteSyncStats(stats.top_offenders(PUBLISHED_TYPES)));
+}
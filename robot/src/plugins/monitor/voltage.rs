@@ -1,21 +1,41 @@
 use bevy::prelude::*;
 use common::components::{CurrentDraw, MeasuredVoltage};
 
-use crate::plugins::core::robot::LocalRobotMarker;
+use crate::plugins::core::{
+    arming::{AppPreArmCheckExt, CheckResult, CheckSeverity},
+    robot::LocalRobotMarker,
+    schedule::telemetry_due,
+};
 
 pub struct VoltagePlugin;
 
 impl Plugin for VoltagePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, check_voltage);
+        app.add_systems(Update, check_voltage.run_if(telemetry_due))
+            .add_pre_arm_check("Voltage", CheckSeverity::Advisory, voltage_ok);
     }
 }
 
+/// Below this the battery is nearly flat
+const LOW_VOLTAGE: f32 = 10.0;
+
 fn check_voltage(robot: Query<(&MeasuredVoltage, &CurrentDraw), With<LocalRobotMarker>>) {
     for (voltage, current) in &robot {
         let raw_voltage = voltage.0 .0;
-        if raw_voltage < 10.0 && raw_voltage > 1.0 {
+        if raw_voltage < LOW_VOLTAGE && raw_voltage > 1.0 {
             warn!("Low Voltage: {}, {}", voltage.0, current.0);
         }
     }
 }
+
+fn voltage_ok(robot: Query<&MeasuredVoltage, With<LocalRobotMarker>>) -> CheckResult {
+    let Ok(voltage) = robot.get_single() else {
+        return Err("No voltage reading".to_owned());
+    };
+
+    if voltage.0 .0 < LOW_VOLTAGE {
+        return Err(format!("{} is below {LOW_VOLTAGE}V", voltage.0));
+    }
+
+    Ok(())
+}
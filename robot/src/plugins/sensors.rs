@@ -1,20 +1,87 @@
-use bevy::{app::PluginGroupBuilder, prelude::PluginGroup};
+use std::time::Duration;
 
+use bevy::{
+    app::PluginGroupBuilder,
+    prelude::{PluginGroup, Resource},
+};
+
+#[cfg(feature = "hw-cameras")]
 pub mod cameras;
+#[cfg(feature = "hw-sensors")]
 pub mod depth;
+pub mod depth_estimate;
+#[cfg(feature = "hw-sensors")]
 pub mod leak;
+pub mod motor_current;
+#[cfg(feature = "hw-sensors")]
 pub mod orientation;
+pub mod position;
+#[cfg(feature = "hw-sensors")]
 pub mod power;
+pub mod profile;
+#[cfg(feature = "sim")]
+pub mod sim;
+pub mod tether;
 
-pub struct SensorPlugins;
+pub struct SensorPlugins {
+    /// Read the robot's sensors, only possible on the robot itself
+    pub hardware: bool,
+}
 
 impl PluginGroup for SensorPlugins {
     fn build(self) -> PluginGroupBuilder {
-        PluginGroupBuilder::start::<Self>()
-            .add(cameras::CameraPlugin)
-            .add(orientation::OrientationPlugin)
-            .add(power::PowerPlugin)
-            .add(depth::DepthPlugin)
-            .add(leak::LeakPlugin)
+        let plugins = PluginGroupBuilder::start::<Self>()
+            .add(motor_current::MotorCurrentPlugin)
+            .add(tether::TetherPlugin)
+            .add(position::PositionPlugin)
+            .add(depth_estimate::DepthEstimatePlugin)
+            .add(profile::ProfilePlugin);
+
+        #[cfg(feature = "hw-cameras")]
+        let plugins = if self.hardware {
+            plugins.add(cameras::CameraPlugin)
+        } else {
+            plugins
+        };
+
+        #[cfg(feature = "hw-sensors")]
+        let plugins = if self.hardware {
+            plugins
+                .add(orientation::OrientationPlugin)
+                .add(power::PowerPlugin)
+                .add(depth::DepthPlugin)
+                .add(leak::LeakPlugin)
+        } else {
+            plugins
+        };
+
+        #[cfg(feature = "sim")]
+        let plugins = if !(cfg!(feature = "hw-sensors") && self.hardware) {
+            plugins.add(sim::SimSensorPlugin)
+        } else {
+            plugins
+        };
+
+        plugins
+    }
+}
+
+/// IMU data older than this is considered stale
+const IMU_STALE_AFTER: Duration = Duration::from_millis(500);
+
+#[derive(Resource, Default)]
+pub struct ImuHealth {
+    /// Real time the last batch of inertial frames arrived
+    last_frame: Option<Duration>,
+    /// A read in the last batch failed and the batch reused old frames
+    read_failed: bool,
+}
+
+impl ImuHealth {
+    pub fn is_healthy(&self, now: Duration) -> bool {
+        !self.read_failed
+            && self
+                .last_frame
+                .is_some_and(|last| now.saturating_sub(last) <= IMU_STALE_AFTER)
     }
 }
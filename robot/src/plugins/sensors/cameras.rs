@@ -1,10 +1,14 @@
+mod fallback;
+
 use core::str;
 use std::{
-    io,
-    net::{IpAddr, SocketAddr},
+    collections::VecDeque,
+    fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    path::{Path, PathBuf},
     process::{Child, Command},
     thread,
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 
 use ahash::{HashMap, HashSet};
@@ -12,43 +16,231 @@ use anyhow::{anyhow, bail, Context};
 use bevy::{app::AppExit, prelude::*};
 use common::{
     bundles::CameraBundle,
-    components::{Camera, RobotId},
-    ecs_sync::{NetId, Replicate},
+    components::{
+        Camera, CameraFallbackStream, CameraIntrinsics, CameraManagerState, PowerMode, RobotId,
+    },
+    ecs_sync::{scoped::ScopedEvents, NetId, Replicate},
     error::{self, Errors},
-    events::ResyncCameras,
+    events::{AckedCommand, CaptureStill, CommandComplete, ResyncCameras},
+    file_transfer::SendFile,
+    shutdown::{self, AppShutdownSet, SpawnWorkerExt, WorkerThread},
     sync::Peer,
+    types::ids::CameraId,
+    video_frames::SendVideoFrame,
 };
-use crossbeam::channel::{self, Receiver, Sender};
+use crossbeam::channel::{self, Receiver, RecvTimeoutError, Sender};
+use networking::PeerAddr;
 use tracing::{span, Level};
 
+use self::fallback::FallbackWorker;
 use crate::{
-    config::RobotConfig,
-    plugins::core::robot::{LocalRobot, LocalRobotMarker},
+    config::{CameraTransport, MulticastDefinition, RobotConfig},
+    plugins::core::{
+        robot::{LocalRobot, LocalRobotMarker},
+        schedule::telemetry_due,
+    },
 };
 
-// TODO(low): Use multicast udp
 pub struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, start_camera_thread.pipe(error::handle_errors));
-        app.add_systems(PreUpdate, read_new_data);
-        app.add_systems(Update, handle_peers);
-        app.add_systems(Last, shutdown);
+        app.add_systems(
+            PreUpdate,
+            (
+                read_new_data,
+                read_state.run_if(telemetry_due),
+                forward_stills,
+                forward_resyncs,
+                forward_video_frames,
+            ),
+        );
+        app.add_systems(
+            Update,
+            (
+                handle_peers,
+                handle_still_requests,
+                handle_power_mode,
+                handle_fallback_requests,
+            ),
+        );
+        app.add_systems(Last, shutdown.in_set(AppShutdownSet::Threads));
     }
 }
 
 #[derive(Resource)]
-struct CameraChannels(Sender<CameraEvent>, Receiver<Vec<CameraBundle>>);
+struct CameraChannels(
+    Sender<CameraEvent>,
+    Receiver<Vec<(CameraBundle, Option<CameraIntrinsics>)>>,
+    Receiver<SendFile>,
+    Receiver<CameraManagerState>,
+    Option<WorkerThread<()>>,
+    /// Outcome of each resync, the number of cameras running or what went wrong
+    Receiver<Result<usize, String>>,
+    Receiver<SendVideoFrame>,
+);
+
+/// How long shutdown waits for gstreamer to be stopped
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long peer changes are held so bursts of them restart gstreamer once
+const PEER_DEBOUNCE: Duration = Duration::from_secs(1);
+/// Minimum time between stills from the same camera, each one interrupts the stream
+const STILL_INTERVAL: Duration = Duration::from_secs(2);
+/// Where stills are kept on the robot
+const STILLS_DIR: &str = "/home/pi/mate/stills";
+/// Location listed for cameras sending fallback video, they have no stream
+const FALLBACK_LOCATION: SocketAddr = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0));
+/// Encoded frames waiting for the main thread, later ones are dropped
+const FALLBACK_BACKLOG: usize = 2;
 
 enum CameraEvent {
-    NewPeer(SocketAddr),
+    NewPeer(IpAddr),
     LostPeer,
     // TODO(low): Some way to trigger this from the surface or on an interval
     Resync,
+    /// Takes a full resolution still from a camera, by device or display name
+    CaptureStill(String),
+    /// Restarts streams with the settings for the robot's new power mode
+    PowerMode(PowerMode),
+    /// Every fallback stream the surface wants from this robot, replacing the previous requests
+    Fallback(Vec<CameraFallbackStream>),
+    Shutdown,
+}
+
+/// What the camera thread does next, see [`CameraEventQueue`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum CameraAction {
+    /// Points every stream at a new peer
+    Restart(IpAddr),
+    /// Stops every stream, the peer is gone
+    Stop,
+    Resync,
+    CaptureStill(String),
+    PowerMode(PowerMode),
+    Fallback(Vec<CameraFallbackStream>),
     Shutdown,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PeerChange {
+    New(IpAddr),
+    Lost,
+}
+
+/// Merges camera events into the actions worth taking
+///
+/// Peer changes wait out [`PEER_DEBOUNCE`] and only the last one of a burst is acted on, so a
+/// surface that reconnects repeatedly restarts gstreamer once. A peer that is lost and found again
+/// on the same address leaves the streams alone. Resyncs, power mode and fallback changes are held
+/// until a pending restart is done so they never kill the same gstreamers, and any number of them
+/// run once.
+#[derive(Debug, Default)]
+struct CameraEventQueue {
+    /// Where streams point as of the last restart
+    peer: Option<IpAddr>,
+    /// The latest peer change and when it may be acted on
+    pending: Option<(PeerChange, Instant)>,
+    resync: bool,
+    /// The latest power mode not acted on yet
+    power: Option<PowerMode>,
+    /// The latest fallback requests not acted on yet
+    fallback: Option<Vec<CameraFallbackStream>>,
+    stills: VecDeque<String>,
+    shutdown: bool,
+}
+
+impl CameraEventQueue {
+    fn push(&mut self, event: CameraEvent, now: Instant) {
+        match event {
+            CameraEvent::NewPeer(ip) => self.peer_changed(PeerChange::New(ip), now),
+            CameraEvent::LostPeer => self.peer_changed(PeerChange::Lost, now),
+            CameraEvent::Resync => self.resync = true,
+            CameraEvent::CaptureStill(camera) => self.stills.push_back(camera),
+            CameraEvent::PowerMode(power) => self.power = Some(power),
+            CameraEvent::Fallback(requests) => self.fallback = Some(requests),
+            CameraEvent::Shutdown => self.shutdown = true,
+        }
+    }
+
+    fn peer_changed(&mut self, change: PeerChange, now: Instant) {
+        let peer = match change {
+            PeerChange::New(ip) => Some(ip),
+            PeerChange::Lost => None,
+        };
+
+        if peer == self.peer {
+            // Back to where the streams already point
+            self.pending = None;
+            return;
+        }
+
+        // The first change of a burst decides when it is acted on
+        let deadline = match self.pending {
+            Some((_, deadline)) => deadline,
+            None => now + PEER_DEBOUNCE,
+        };
+
+        self.pending = Some((change, deadline));
+    }
+
+    /// When the pending peer change may be acted on
+    fn deadline(&self) -> Option<Instant> {
+        self.pending.map(|(_, deadline)| deadline)
+    }
+
+    /// Whether streams are about to be restarted or stopped
+    fn is_restarting(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    fn pop(&mut self, now: Instant) -> Option<CameraAction> {
+        if self.shutdown {
+            self.shutdown = false;
+            return Some(CameraAction::Shutdown);
+        }
+
+        if let Some(camera) = self.stills.pop_front() {
+            return Some(CameraAction::CaptureStill(camera));
+        }
+
+        if let Some((change, deadline)) = self.pending {
+            if now < deadline {
+                // Resyncs and power changes wait for the restart
+                return None;
+            }
+
+            self.pending = None;
+
+            return Some(match change {
+                PeerChange::New(ip) => {
+                    self.peer = Some(ip);
+                    CameraAction::Restart(ip)
+                }
+                PeerChange::Lost => {
+                    self.peer = None;
+                    CameraAction::Stop
+                }
+            });
+        }
+
+        if let Some(power) = self.power.take() {
+            return Some(CameraAction::PowerMode(power));
+        }
+
+        if let Some(requests) = self.fallback.take() {
+            return Some(CameraAction::Fallback(requests));
+        }
+
+        if self.resync {
+            self.resync = false;
+            return Some(CameraAction::Resync);
+        }
+
+        None
+    }
+}
+
 fn start_camera_thread(
     mut cmds: Commands,
     errors: Res<Errors>,
@@ -57,208 +249,358 @@ fn start_camera_thread(
 ) -> anyhow::Result<()> {
     let (tx_events, rx_events) = channel::bounded(10);
     let (tx_camreas, rx_cameras) = channel::bounded(10);
+    let (tx_stills, rx_stills) = channel::bounded(10);
+    let (tx_state, rx_state) = channel::bounded(10);
+    let (tx_resyncs, rx_resyncs) = channel::bounded(10);
+    let (tx_frames, rx_frames) = channel::bounded(FALLBACK_BACKLOG);
 
     info!("Setting up cameras");
 
     let _ = tx_events.send(CameraEvent::Resync);
 
-    cmds.insert_resource(CameraChannels(tx_events, rx_cameras));
+    cmds.entity(robot.entity).insert(CameraManagerState::Idle);
 
     let errors = errors.0.clone();
     let robot = RobotId(robot.net_id);
     let config = config.clone();
+    let intrinsics = load_intrinsics(&config);
 
-    thread::Builder::new()
+    let camera_thread = thread::Builder::new()
         .name("Camera Thread".to_owned())
-        .spawn(move || {
+        .spawn_worker(move || {
             let _span = span!(Level::INFO, "Camera manager").entered();
 
             let mut last_cameras: HashSet<String> = HashSet::default();
             let mut cameras: HashMap<String, (Child, SocketAddr)> = HashMap::default();
-            let mut target_ip = None;
-            let mut port = 1024u16;
-
-            for event in rx_events {
-                match event {
-                    // Respawns all instances of gstreamer and points the new ones towards the new peer
-                    CameraEvent::NewPeer(addrs) => {
-                        info!("Camera thread new peer");
+            let mut target = StreamTarget::new(config.camera_transport);
+            let mut last_stills: HashMap<String, Instant> = HashMap::default();
+            let mut power = PowerMode::Normal;
+            // Devices sending JPEG frames instead of streaming, and the requests for them
+            let mut fallback: HashMap<String, FallbackWorker> = HashMap::default();
+            let mut fallback_requests: Vec<CameraFallbackStream> = Vec::new();
+
+            let mut queue = CameraEventQueue::default();
+            let mut state = CameraManagerState::Idle;
+            // Most recent failure of the last restart or resync
+            let mut failure: Option<String> = None;
+
+            loop {
+                // Wake up when a pending peer change has settled
+                let event = match queue.deadline() {
+                    Some(deadline) => match rx_events.recv_deadline(deadline) {
+                        Ok(event) => Some(event),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    },
+                    None => match rx_events.recv() {
+                        Ok(event) => Some(event),
+                        Err(_) => return,
+                    },
+                };
+
+                // Everything already queued is merged so a burst is handled as one
+                let now = Instant::now();
+                for event in event.into_iter().chain(rx_events.try_iter()) {
+                    queue.push(event, now);
+                }
 
-                        target_ip = Some(addrs.ip());
+                while let Some(action) = queue.pop(Instant::now()) {
+                    match action {
+                        // Respawns all instances of gstreamer and points the new ones towards the new peer
+                        CameraAction::Restart(ip) => {
+                            if !target.new_peer(ip) {
+                                // Multicast streams are not tied to a peer
+                                continue;
+                            }
 
-                        for (camera, (mut child, _)) in cameras.drain() {
-                            let rst = child.kill();
+                            info!("Camera thread new peer");
 
-                            if let Err(err) = rst {
-                                let _ = errors.send(
-                                    anyhow!(err).context(format!("Kill gstreamer for {camera}")),
-                                );
+                            if state != CameraManagerState::Restarting {
+                                state = CameraManagerState::Restarting;
+                                if tx_state.send(state.clone()).is_err() {
+                                    // Peer disconected
+                                    return;
+                                }
                             }
 
-                            let rst = child.wait();
-
-                            if let Err(err) = rst {
-                                let _ = errors.send(
-                                    anyhow!(err).context(format!("Wait gstreamer for {camera}")),
-                                );
+                            failure = None;
+                            restart_streams(
+                                &streamed(&last_cameras, &fallback),
+                                power,
+                                &config,
+                                &mut target,
+                                &mut cameras,
+                                |err| report(&errors, &mut failure, err),
+                            );
+
+                            let camera_list =
+                                camera_list(&cameras, &fallback, robot, &config, &intrinsics);
+
+                            let res = tx_camreas.send(camera_list);
+                            if res.is_err() {
+                                // Peer disconected
+                                return;
                             }
                         }
+                        CameraAction::PowerMode(next_power) => {
+                            if next_power == power {
+                                continue;
+                            }
 
-                        thread::sleep(Duration::from_millis(500));
-
-                        for camera in &last_cameras {
-                            let rst = add_camera(camera, addrs.ip(), &mut cameras, &mut port);
+                            info!("Camera streams following power mode {next_power:?}");
+                            power = next_power;
 
-                            if let Err(err) = rst {
-                                let _ = errors.send(
-                                    anyhow!(err).context(format!("Start gstreamer for {camera}")),
-                                );
+                            if !target.is_available() {
+                                // Streams start with the new settings once there is a peer
+                                continue;
                             }
-                        }
 
-                        let camera_list = camera_list(&cameras, robot, &config);
-
-                        let res = tx_camreas.send(camera_list);
-                        if res.is_err() {
-                            // Peer disconected
-                            return;
+                            failure = None;
+                            restart_streams(
+                                &streamed(&last_cameras, &fallback),
+                                power,
+                                &config,
+                                &mut target,
+                                &mut cameras,
+                                |err| report(&errors, &mut failure, err),
+                            );
+
+                            let camera_list =
+                                camera_list(&cameras, &fallback, robot, &config, &intrinsics);
+
+                            let res = tx_camreas.send(camera_list);
+                            if res.is_err() {
+                                // Peer disconected
+                                return;
+                            }
                         }
-                    }
-                    CameraEvent::LostPeer => {
-                        info!("Camera thread lost peer");
-
-                        target_ip = None;
-
-                        for (camera, (mut child, _)) in cameras.drain() {
-                            let rst = child.kill();
-
-                            if let Err(err) = rst {
-                                let _ = errors.send(
-                                    anyhow!(err).context(format!("Kill gstreamer for {camera}")),
-                                );
+                        CameraAction::Stop => {
+                            if !target.lost_peer() {
+                                continue;
                             }
 
-                            let rst = child.wait();
+                            info!("Camera thread lost peer");
 
-                            if let Err(err) = rst {
-                                let _ = errors.send(
-                                    anyhow!(err).context(format!("Wait gstreamer for {camera}")),
-                                );
-                            }
-                        }
+                            failure = None;
+                            stop_cameras(&mut cameras, |err| report(&errors, &mut failure, err));
 
-                        let res = tx_camreas.send(Default::default());
-                        if res.is_err() {
-                            // Peer disconected
-                            return;
+                            let res = tx_camreas.send(Default::default());
+                            if res.is_err() {
+                                // Peer disconected
+                                return;
+                            }
                         }
-                    }
-                    // Reruns detect cameras script and start or kill instances of gstreamer as needed
-                    CameraEvent::Resync => {
-                        info!("Checking for new cameras");
+                        // Reruns detect cameras script and start or kill instances of gstreamer as needed
+                        CameraAction::Resync => {
+                            info!("Checking for new cameras");
 
-                        let camera_detect =
-                            Command::new("/home/pi/mate/detect_cameras.sh").output();
+                            failure = None;
 
-                        match camera_detect {
-                            Ok(output) => {
-                                if !output.status.success() {
+                            let next_cameras = match detect_cameras() {
+                                Ok(next_cameras) => next_cameras,
+                                Err(err) => {
+                                    report(&errors, &mut failure, err.context("Collect cameras"));
                                     let _ =
-                                        errors.send(anyhow!("Collect cameras: {}", output.status));
+                                        tx_resyncs.send(Err(failure.clone().unwrap_or_default()));
                                     continue;
                                 }
-
-                                match str::from_utf8(&output.stdout) {
-                                    Ok(data) => {
-                                        let next_cameras: HashSet<String> =
-                                            data.lines().map(ToOwned::to_owned).collect();
-
-                                        for old_camera in last_cameras.difference(&next_cameras) {
-                                            if let Some(mut child) = cameras.remove(old_camera) {
-                                                let rst = child.0.kill();
-
-                                                if let Err(err) = rst {
-                                                    let _ = errors.send(anyhow!(err).context(
-                                                        format!("Kill gstreamer for {old_camera}"),
-                                                    ));
-                                                }
-
-                                                let rst = child.0.wait();
-
-                                                if let Err(err) = rst {
-                                                    let _ = errors.send(anyhow!(err).context(
-                                                        format!("Wait gstreamer for {old_camera}"),
-                                                    ));
-                                                }
-                                            } else {
-                                                error!("Attempted to remove a nonexistant camera");
-                                            }
-                                        }
-
-                                        for new_camera in next_cameras.difference(&last_cameras) {
-                                            if let Some(ip) = target_ip {
-                                                let rst = add_camera(
-                                                    new_camera,
-                                                    ip,
-                                                    &mut cameras,
-                                                    &mut port,
-                                                );
-
-                                                if let Err(err) = rst {
-                                                    let _ = errors.send(anyhow!(err).context(
-                                                        format!("Start gstreamer for {new_camera}"),
-                                                    ));
-                                                }
-                                            } else {
-                                                error!("Tried to update cameras without a peer");
-                                            }
-                                        }
-
-                                        last_cameras = next_cameras;
-
-                                        let camera_list = camera_list(&cameras, robot, &config);
-                                        let res = tx_camreas.send(camera_list);
-                                        if res.is_err() {
-                                            // Peer disconected
-                                            return;
-                                        }
+                            };
+
+                            for old_camera in last_cameras.difference(&next_cameras) {
+                                if fallback.remove(old_camera).is_some() {
+                                    info!("Stopped fallback video for {old_camera}");
+                                } else if let Some(mut child) = cameras.remove(old_camera) {
+                                    let rst = child.0.stop();
+
+                                    if let Err(err) = rst {
+                                        report(
+                                            &errors,
+                                            &mut failure,
+                                            anyhow!(err).context(format!(
+                                                "Stop gstreamer for {old_camera}"
+                                            )),
+                                        );
                                     }
-                                    Err(err) => {
-                                        let _ =
-                                            errors.send(anyhow!(err).context("Collect cameras"));
+                                } else if streams_in(old_camera, power, &config) {
+                                    error!("Attempted to remove a nonexistant camera");
+                                }
+                            }
+
+                            let requested =
+                                fallback_devices(&fallback_requests, &next_cameras, &config);
+                            for new_camera in next_cameras.difference(&last_cameras) {
+                                if !streams_in(new_camera, power, &config) {
+                                    info!("Not streaming {new_camera} in {power:?}");
+                                } else if requested.contains_key(new_camera) {
+                                    // Started with the rest of the fallback video below
+                                } else if target.is_available() {
+                                    let rst = add_camera(
+                                        new_camera,
+                                        &mut target,
+                                        &mut cameras,
+                                        StreamSettings::for_power(power),
+                                    );
+
+                                    if let Err(err) = rst {
+                                        report(
+                                            &errors,
+                                            &mut failure,
+                                            err.context(format!(
+                                                "Start gstreamer for {new_camera}"
+                                            )),
+                                        );
                                     }
+                                } else {
+                                    error!("Tried to update cameras without a peer");
                                 }
                             }
-                            Err(err) => {
-                                let _ = errors.send(anyhow!(err).context("Collect cameras"));
+
+                            last_cameras = next_cameras;
+
+                            // Requested cameras that just showed up
+                            apply_fallback(
+                                &fallback_requests,
+                                &last_cameras,
+                                power,
+                                &config,
+                                &mut target,
+                                &mut cameras,
+                                &mut fallback,
+                                &tx_frames,
+                                &errors,
+                                |err| report(&errors, &mut failure, err),
+                            );
+
+                            let camera_list =
+                                camera_list(&cameras, &fallback, robot, &config, &intrinsics);
+                            let res = tx_camreas.send(camera_list);
+                            if res.is_err() {
+                                // Peer disconected
+                                return;
                             }
-                        }
-                    }
-                    CameraEvent::Shutdown => {
-                        for (camera, (mut child, _)) in cameras.drain() {
-                            let rst = child.kill();
 
-                            if let Err(err) = rst {
-                                let _ = errors.send(
-                                    anyhow!(err).context(format!("Kill gstreamer for {camera}")),
-                                );
+                            let outcome = match &failure {
+                                Some(failure) => Err(failure.clone()),
+                                None => Ok(cameras.len() + fallback.len()),
+                            };
+                            let _ = tx_resyncs.send(outcome);
+                        }
+                        CameraAction::CaptureStill(requested) => {
+                            let Some(camera) = resolve_camera(&requested, cameras.keys(), &config)
+                            else {
+                                let _ =
+                                    errors.send(anyhow!("Unknown camera for still: {requested}"));
+                                continue;
+                            };
+
+                            let last = last_stills.get(&camera);
+                            if last.is_some_and(|it| it.elapsed() < STILL_INTERVAL) {
+                                warn!("Still from {camera} requested too soon, ignoring");
+                                continue;
                             }
+                            last_stills.insert(camera.clone(), Instant::now());
+
+                            info!("Capturing still from {camera}");
+
+                            let path = still_path(&camera);
+                            let loopback = target.multicast_loopback();
+                            let settings = StreamSettings::for_power(power);
+                            let (still, resumed) = capture_still(
+                                &camera,
+                                &mut cameras,
+                                |camera| take_still(camera, &path),
+                                |camera, addrs| start_gstreamer(camera, addrs, loopback, settings),
+                            );
+
+                            if let Err(err) = resumed {
+                                report(
+                                    &errors,
+                                    &mut failure,
+                                    err.context(format!("Resume {camera}")),
+                                );
 
-                            let rst = child.wait();
+                                // The surface needs to know the stream is gone
+                                let camera_list =
+                                    camera_list(&cameras, &fallback, robot, &config, &intrinsics);
+                                let res = tx_camreas.send(camera_list);
+                                if res.is_err() {
+                                    // Peer disconected
+                                    return;
+                                }
+                            }
 
-                            if let Err(err) = rst {
-                                let _ = errors.send(
-                                    anyhow!(err).context(format!("Wait gstreamer for {camera}")),
-                                );
+                            match still {
+                                Ok(data) => {
+                                    let name = path
+                                        .file_name()
+                                        .map(|it| it.to_string_lossy().into_owned())
+                                        .unwrap_or_default();
+
+                                    let res = tx_stills.send(SendFile { name, data });
+                                    if res.is_err() {
+                                        // Peer disconected
+                                        return;
+                                    }
+                                }
+                                Err(err) => {
+                                    let _ =
+                                        errors.send(err.context(format!("Still from {camera}")));
+                                }
+                            }
+                        }
+                        CameraAction::Fallback(requests) => {
+                            info!("Fallback video requested for {} cameras", requests.len());
+
+                            failure = None;
+                            fallback_requests = requests;
+                            apply_fallback(
+                                &fallback_requests,
+                                &last_cameras,
+                                power,
+                                &config,
+                                &mut target,
+                                &mut cameras,
+                                &mut fallback,
+                                &tx_frames,
+                                &errors,
+                                |err| report(&errors, &mut failure, err),
+                            );
+
+                            let camera_list =
+                                camera_list(&cameras, &fallback, robot, &config, &intrinsics);
+                            let res = tx_camreas.send(camera_list);
+                            if res.is_err() {
+                                // Peer disconected
+                                return;
                             }
                         }
+                        CameraAction::Shutdown => {
+                            stop_cameras(&mut cameras, |err| {
+                                let _ = errors.send(err);
+                            });
+                            fallback.clear();
+
+                            let _ = tx_camreas.send(Default::default());
 
-                        let res = tx_camreas.send(Default::default());
-                        if res.is_err() {
-                            // Peer disconected
                             return;
                         }
+                    }
+                }
 
+                let next_state = if queue.is_restarting() {
+                    CameraManagerState::Restarting
+                } else if let Some(failure) = &failure {
+                    CameraManagerState::Error(failure.clone())
+                } else {
+                    CameraManagerState::Idle
+                };
+
+                if next_state != state {
+                    state = next_state;
+
+                    let res = tx_state.send(state.clone());
+                    if res.is_err() {
+                        // Peer disconected
                         return;
                     }
                 }
@@ -266,37 +608,164 @@ fn start_camera_thread(
         })
         .context("Spawn thread")?;
 
+    cmds.insert_resource(CameraChannels(
+        tx_events,
+        rx_cameras,
+        rx_stills,
+        rx_state,
+        Some(camera_thread),
+        rx_resyncs,
+        rx_frames,
+    ));
+
     Ok(())
 }
 
+/// Sends `err` to be logged and remembers it for [`CameraManagerState::Error`]
+fn report(errors: &Sender<anyhow::Error>, failure: &mut Option<String>, err: anyhow::Error) {
+    *failure = Some(format!("{err:#}"));
+    let _ = errors.send(err);
+}
+
+/// Stops every instance of gstreamer
+fn stop_cameras<P: StreamProcess>(
+    cameras: &mut HashMap<String, (P, SocketAddr)>,
+    mut on_error: impl FnMut(anyhow::Error),
+) {
+    for (camera, (mut child, _)) in cameras.drain() {
+        let rst = child.stop();
+
+        if let Err(err) = rst {
+            on_error(anyhow!(err).context(format!("Stop gstreamer for {camera}")));
+        }
+    }
+}
+
+/// Runs the detect cameras script, returning the devices it found
+fn detect_cameras() -> anyhow::Result<HashSet<String>> {
+    let output = Command::new("/home/pi/mate/detect_cameras.sh")
+        .output()
+        .context("Run detect cameras")?;
+    if !output.status.success() {
+        bail!("Detect cameras failed: {}", output.status);
+    }
+
+    let data = str::from_utf8(&output.stdout).context("Read detected cameras")?;
+
+    Ok(data.lines().map(ToOwned::to_owned).collect())
+}
+
 fn handle_peers(
     channels: Res<CameraChannels>,
     mut disconnected: RemovedComponents<Peer>,
     connected: Query<&Peer, Changed<Peer>>,
-    mut resync_events: EventReader<ResyncCameras>,
+    mut resync_events: ScopedEvents<ResyncCameras>,
 ) {
-    let mut event = None;
+    let mut events = Vec::new();
 
     for _resync in resync_events.read() {
-        event = Some(CameraEvent::Resync);
+        events.push(CameraEvent::Resync);
     }
 
+    // Disconnects go first so a peer that reconnected this frame ends up connected
     for _disconnection in disconnected.read() {
-        event = Some(CameraEvent::LostPeer);
+        events.push(CameraEvent::LostPeer);
     }
 
     for peer in connected.iter() {
-        event = Some(CameraEvent::NewPeer(peer.addrs));
+        let ip = match &peer.addrs {
+            PeerAddr::Tcp(addrs) => addrs.ip(),
+            // Peers on a local socket or loopback link are on this machine
+            PeerAddr::Unix(_) | PeerAddr::Loopback(_) => Ipv4Addr::LOCALHOST.into(),
+        };
+
+        events.push(CameraEvent::NewPeer(ip));
     }
 
-    if let Some(event) = event {
+    for event in events {
         let res = channels.0.send(event);
+        if let Err(_) = res {
+            error!("Camera thread dead");
+            return;
+        }
+    }
+}
+
+fn handle_still_requests(channels: Res<CameraChannels>, mut requests: EventReader<CaptureStill>) {
+    for request in requests.read() {
+        let res = channels
+            .0
+            .send(CameraEvent::CaptureStill(request.camera.clone()));
+        if let Err(_) = res {
+            error!("Camera thread dead");
+        }
+    }
+}
+
+fn handle_power_mode(
+    channels: Res<CameraChannels>,
+    robot: Query<&PowerMode, (With<LocalRobotMarker>, Changed<PowerMode>)>,
+) {
+    for &power in &robot {
+        let res = channels.0.send(CameraEvent::PowerMode(power));
         if let Err(_) = res {
             error!("Camera thread dead");
         }
     }
 }
 
+fn handle_fallback_requests(
+    channels: Res<CameraChannels>,
+    robot: Res<LocalRobot>,
+    requests: Query<(Ref<CameraFallbackStream>, &RobotId)>,
+    mut removed: RemovedComponents<CameraFallbackStream>,
+) {
+    let removed = removed.read().count() > 0;
+    if !removed && !requests.iter().any(|(request, _)| request.is_changed()) {
+        return;
+    }
+
+    let requests = requests
+        .iter()
+        .filter(|(_, robot_id)| robot_id.0 == robot.net_id)
+        .map(|(request, _)| (*request).clone())
+        .collect();
+
+    let res = channels.0.send(CameraEvent::Fallback(requests));
+    if let Err(_) = res {
+        error!("Camera thread dead");
+    }
+}
+
+fn forward_stills(channels: Res<CameraChannels>, mut files: EventWriter<SendFile>) {
+    files.send_batch(channels.2.try_iter());
+}
+
+fn forward_video_frames(channels: Res<CameraChannels>, mut frames: EventWriter<SendVideoFrame>) {
+    frames.send_batch(channels.6.try_iter());
+}
+
+fn forward_resyncs(
+    channels: Res<CameraChannels>,
+    robot: Res<LocalRobot>,
+    mut completions: EventWriter<CommandComplete>,
+) {
+    for outcome in channels.5.try_iter() {
+        let (ok, detail) = match outcome {
+            Ok(1) => (true, "1 camera running".to_owned()),
+            Ok(count) => (true, format!("{count} cameras running")),
+            Err(err) => (false, err),
+        };
+
+        completions.send(CommandComplete {
+            robot: RobotId(robot.net_id),
+            command: AckedCommand::ResyncCameras,
+            ok,
+            detail,
+        });
+    }
+}
+
 // TODO(low): Only update the cameras that changed
 fn read_new_data(
     mut cmds: Commands,
@@ -318,47 +787,452 @@ fn read_new_data(
             }
         }
 
-        for camera in new_cameras {
-            cmds.spawn((camera, Replicate));
+        for (camera, intrinsics) in new_cameras {
+            let mut camera = cmds.spawn((camera, Replicate));
+
+            if let Some(intrinsics) = intrinsics {
+                camera.insert(intrinsics);
+            }
         }
     }
 }
 
-fn shutdown(channels: Res<CameraChannels>, mut exit: EventReader<AppExit>) {
+fn read_state(mut cmds: Commands, channels: Res<CameraChannels>, robot: Res<LocalRobot>) {
+    if let Some(state) = channels.3.try_iter().last() {
+        cmds.entity(robot.entity).insert(state);
+    }
+}
+
+fn shutdown(mut channels: ResMut<CameraChannels>, mut exit: EventReader<AppExit>) {
     for _event in exit.read() {
         let _ = channels.0.send(CameraEvent::Shutdown);
+
+        if let Some(camera_thread) = channels.4.take() {
+            if !shutdown::join_timeout(camera_thread, SHUTDOWN_TIMEOUT) {
+                warn!("Camera thread did not shut down in time");
+            }
+        }
+    }
+}
+
+/// Where streams are sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StreamTarget {
+    /// Sent directly to the connected surface, streams follow the peer
+    Peer { ip: Option<IpAddr>, next_port: u16 },
+    /// Sent to a multicast group, streams persist across peer changes
+    Multicast {
+        config: MulticastDefinition,
+        next_port: u16,
+    },
+}
+
+impl StreamTarget {
+    fn new(transport: CameraTransport) -> Self {
+        match transport {
+            CameraTransport::Unicast => StreamTarget::Peer {
+                ip: None,
+                next_port: 1024,
+            },
+            CameraTransport::Multicast(config) => StreamTarget::Multicast {
+                config,
+                next_port: config.base_port,
+            },
+        }
+    }
+
+    /// Returns true if running streams need to be restarted
+    fn new_peer(&mut self, peer: IpAddr) -> bool {
+        match self {
+            StreamTarget::Peer { ip, .. } => {
+                *ip = Some(peer);
+                true
+            }
+            StreamTarget::Multicast { .. } => false,
+        }
+    }
+
+    /// Returns true if running streams need to be stopped
+    fn lost_peer(&mut self) -> bool {
+        match self {
+            StreamTarget::Peer { ip, .. } => {
+                *ip = None;
+                true
+            }
+            StreamTarget::Multicast { .. } => false,
+        }
+    }
+
+    fn is_available(&self) -> bool {
+        match self {
+            StreamTarget::Peer { ip, .. } => ip.is_some(),
+            StreamTarget::Multicast { .. } => true,
+        }
+    }
+
+    /// Allocates the address for a new stream
+    fn next_addrs(&mut self) -> Option<SocketAddr> {
+        let (ip, next_port) = match self {
+            StreamTarget::Peer { ip, next_port } => ((*ip)?, next_port),
+            StreamTarget::Multicast { config, next_port } => (config.group.into(), next_port),
+        };
+
+        let addrs = (ip, *next_port).into();
+        *next_port += 1;
+
+        Some(addrs)
+    }
+
+    /// Multicast loopback setting, `None` for unicast streams
+    fn multicast_loopback(&self) -> Option<bool> {
+        match self {
+            StreamTarget::Peer { .. } => None,
+            StreamTarget::Multicast { config, .. } => Some(config.loopback),
+        }
+    }
+}
+
+/// What the cameras are asked to encode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct StreamSettings {
+    width: u32,
+    height: u32,
+    framerate: u32,
+    /// Bits per second for the camera's encoder, its own default when `None`
+    bitrate: Option<u32>,
+}
+
+impl StreamSettings {
+    fn for_power(power: PowerMode) -> Self {
+        match power {
+            PowerMode::Normal => Self {
+                width: 1920,
+                height: 1080,
+                framerate: 30,
+                bitrate: None,
+            },
+            PowerMode::LowPower | PowerMode::Critical => Self {
+                width: 1280,
+                height: 720,
+                framerate: 15,
+                bitrate: Some(1_500_000),
+            },
+        }
+    }
+}
+
+/// Whether `camera` streams in `power`, only primary cameras are kept in critical
+fn streams_in(camera: &str, power: PowerMode, config: &RobotConfig) -> bool {
+    match power {
+        PowerMode::Normal | PowerMode::LowPower => true,
+        PowerMode::Critical => config.cameras.get(camera).is_some_and(|it| it.primary),
     }
 }
 
 /// Spawns a gstreamer with the args necessary
-fn start_gstreamer(camera: &str, addrs: SocketAddr) -> io::Result<Child> {
+fn start_gstreamer(
+    camera: &str,
+    addrs: SocketAddr,
+    multicast_loopback: Option<bool>,
+    settings: StreamSettings,
+) -> io::Result<Child> {
     Command::new("gst-launch-1.0")
-        .arg("v4l2src")
-        .arg(format!("device={camera}"))
-        .arg("do-timestamp=true")
-        .arg("!")
-        .arg("h264parse")
-        .arg("!")
-        .arg("video/x-h264,stream-format=avc,alignment=au,width=1920,height=1080,framerate=30/1")
-        .arg("!")
-        .arg("rtph264pay")
-        .arg("aggregate-mode=zero-latency")
-        .arg("config-interval=10")
-        .arg("pt=96")
-        .arg("!")
-        .arg("udpsink")
-        .arg("sync=false")
-        .arg(format!("host={}", addrs.ip()))
-        .arg(format!("port={}", addrs.port()))
+        .args(gstreamer_args(camera, addrs, multicast_loopback, settings))
         .spawn()
 }
 
+/// Builds the gstreamer pipeline that streams `camera` to `addrs`
+fn gstreamer_args(
+    camera: &str,
+    addrs: SocketAddr,
+    multicast_loopback: Option<bool>,
+    settings: StreamSettings,
+) -> Vec<String> {
+    let StreamSettings {
+        width,
+        height,
+        framerate,
+        bitrate,
+    } = settings;
+
+    let mut args: Vec<String> = vec![
+        "v4l2src".to_owned(),
+        format!("device={camera}"),
+        "do-timestamp=true".to_owned(),
+    ];
+
+    if let Some(bitrate) = bitrate {
+        args.push(format!("extra-controls=c,video_bitrate={bitrate}"));
+    }
+
+    args.extend(
+        [
+            "!",
+            "h264parse",
+            "!",
+            &format!(
+                "video/x-h264,stream-format=avc,alignment=au,width={width},height={height},\
+                 framerate={framerate}/1"
+            ),
+            "!",
+            "rtph264pay",
+            "aggregate-mode=zero-latency",
+            "config-interval=10",
+            "pt=96",
+            "!",
+            "udpsink",
+            "sync=false",
+            &format!("host={}", addrs.ip()),
+            &format!("port={}", addrs.port()),
+        ]
+        .into_iter()
+        .map(ToOwned::to_owned),
+    );
+
+    if let Some(loopback) = multicast_loopback {
+        // Some platforms default this to off which hides the stream from receivers on this host
+        args.push("auto-multicast=true".to_owned());
+        args.push(format!("loop={loopback}"));
+    }
+
+    args
+}
+
+/// Builds the one shot pipeline that saves a full resolution frame from `camera` to `path`
+fn still_args(camera: &str, path: &Path) -> Vec<String> {
+    [
+        "-q",
+        "v4l2src",
+        &format!("device={camera}"),
+        "num-buffers=1",
+        "!",
+        "videoconvert",
+        "!",
+        "jpegenc",
+        "quality=95",
+        "!",
+        "filesink",
+        &format!("location={}", path.display()),
+    ]
+    .into_iter()
+    .map(ToOwned::to_owned)
+    .collect()
+}
+
+/// Where the next still from `camera` is saved
+fn still_path(camera: &str) -> PathBuf {
+    let device = Path::new(camera)
+        .file_name()
+        .map(|it| it.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "camera".to_owned());
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+
+    Path::new(STILLS_DIR).join(format!("{device}_{timestamp}.jpg"))
+}
+
+/// Saves a still from `camera` to `path` and reads it back
+fn take_still(camera: &str, path: &Path) -> anyhow::Result<Vec<u8>> {
+    fs::create_dir_all(STILLS_DIR).context("Create stills dir")?;
+
+    let status = Command::new("gst-launch-1.0")
+        .args(still_args(camera, path))
+        .status()
+        .context("Run still pipeline")?;
+    if !status.success() {
+        bail!("Still pipeline failed: {status}");
+    }
+
+    fs::read(path).context("Read still")
+}
+
+/// A running stream that can be stopped
+trait StreamProcess {
+    fn stop(&mut self) -> io::Result<()>;
+}
+
+impl StreamProcess for Child {
+    fn stop(&mut self) -> io::Result<()> {
+        self.kill()?;
+        self.wait()?;
+
+        Ok(())
+    }
+}
+
+/// Pauses the stream from `camera` while `snapshot` has the device, then restarts it on the same
+/// address so the surface keeps receiving it
+///
+/// Returns the still and whether the stream could be resumed
+fn capture_still<P: StreamProcess>(
+    camera: &str,
+    cameras: &mut HashMap<String, (P, SocketAddr)>,
+    snapshot: impl FnOnce(&str) -> anyhow::Result<Vec<u8>>,
+    restart: impl FnOnce(&str, SocketAddr) -> io::Result<P>,
+) -> (anyhow::Result<Vec<u8>>, anyhow::Result<()>) {
+    let Some((mut child, addrs)) = cameras.remove(camera) else {
+        return (Err(anyhow!("{camera} is not streaming")), Ok(()));
+    };
+
+    // The device can only be opened by one pipeline at a time
+    if let Err(err) = child.stop() {
+        cameras.insert(camera.to_owned(), (child, addrs));
+
+        return (Err(anyhow!(err).context("Pause stream")), Ok(()));
+    }
+
+    let still = snapshot(camera);
+
+    // Resume even if the still failed
+    let resumed = match restart(camera, addrs) {
+        Ok(child) => {
+            cameras.insert(camera.to_owned(), (child, addrs));
+            Ok(())
+        }
+        Err(err) => Err(anyhow!(err).context("Restart stream")),
+    };
+
+    (still, resumed)
+}
+
+/// Finds the streaming camera referred to by `requested`, either its device or display name
+fn resolve_camera<'a>(
+    requested: &str,
+    mut streaming: impl Iterator<Item = &'a String>,
+    config: &RobotConfig,
+) -> Option<String> {
+    streaming
+        .find(|camera| {
+            if *camera == requested {
+                return true;
+            }
+
+            let Some(definition) = config.cameras.get(*camera) else {
+                return false;
+            };
+
+            definition.name == requested || format!("{} ({})", definition.name, camera) == requested
+        })
+        .cloned()
+}
+
+/// Stops every stream and starts the ones `power` allows again with its settings
+fn restart_streams(
+    last_cameras: &HashSet<String>,
+    power: PowerMode,
+    config: &RobotConfig,
+    target: &mut StreamTarget,
+    cameras: &mut HashMap<String, (Child, SocketAddr)>,
+    mut on_error: impl FnMut(anyhow::Error),
+) {
+    stop_cameras(cameras, &mut on_error);
+
+    thread::sleep(Duration::from_millis(500));
+
+    let settings = StreamSettings::for_power(power);
+    for camera in last_cameras {
+        if !streams_in(camera, power, config) {
+            continue;
+        }
+
+        let rst = add_camera(camera, target, cameras, settings);
+
+        if let Err(err) = rst {
+            on_error(err.context(format!("Start gstreamer for {camera}")));
+        }
+    }
+}
+
+/// Cameras that stream, the rest are sending fallback video
+fn streamed<W>(last_cameras: &HashSet<String>, fallback: &HashMap<String, W>) -> HashSet<String> {
+    last_cameras
+        .iter()
+        .filter(|camera| !fallback.contains_key(*camera))
+        .cloned()
+        .collect()
+}
+
+/// The device each fallback request refers to, requests for cameras that aren't detected wait
+/// for a resync to find them
+fn fallback_devices<'a>(
+    requests: &'a [CameraFallbackStream],
+    detected: &HashSet<String>,
+    config: &RobotConfig,
+) -> HashMap<String, &'a CameraFallbackStream> {
+    requests
+        .iter()
+        .filter_map(|request| {
+            let device = resolve_camera(request.camera.as_str(), detected.iter(), config)?;
+            Some((device, request))
+        })
+        .collect()
+}
+
+/// Hands devices between gstreamer and fallback workers so exactly the requested cameras send
+/// fallback video
+fn apply_fallback(
+    requests: &[CameraFallbackStream],
+    last_cameras: &HashSet<String>,
+    power: PowerMode,
+    config: &RobotConfig,
+    target: &mut StreamTarget,
+    cameras: &mut HashMap<String, (Child, SocketAddr)>,
+    fallback: &mut HashMap<String, FallbackWorker>,
+    frames: &Sender<SendVideoFrame>,
+    errors: &Sender<anyhow::Error>,
+    mut on_error: impl FnMut(anyhow::Error),
+) {
+    let wanted = fallback_devices(requests, last_cameras, config);
+
+    // Dropping a worker waits for it to release the device
+    fallback.retain(|device, worker| wanted.get(device) == Some(&worker.request()));
+
+    for camera in last_cameras {
+        if wanted.contains_key(camera) || cameras.contains_key(camera) {
+            continue;
+        }
+
+        if !streams_in(camera, power, config) || !target.is_available() {
+            continue;
+        }
+
+        let settings = StreamSettings::for_power(power);
+        if let Err(err) = add_camera(camera, target, cameras, settings) {
+            on_error(err.context(format!("Start gstreamer for {camera}")));
+        }
+    }
+
+    for (device, request) in wanted {
+        if fallback.contains_key(&device) {
+            continue;
+        }
+
+        // The device can only be opened by one pipeline at a time
+        if let Some((mut child, _)) = cameras.remove(&device) {
+            if let Err(err) = child.stop() {
+                on_error(anyhow!(err).context(format!("Stop gstreamer for {device}")));
+            }
+        }
+
+        let worker =
+            FallbackWorker::start(&device, request.clone(), frames.clone(), errors.clone());
+        match worker {
+            Ok(worker) => {
+                fallback.insert(device, worker);
+            }
+            Err(err) => on_error(err.context(format!("Start fallback video for {device}"))),
+        }
+    }
+}
+
 /// Starts a gstreamer and updates state
 fn add_camera(
     camera: &str,
-    ip: IpAddr,
+    target: &mut StreamTarget,
     cameras: &mut HashMap<String, (Child, SocketAddr)>,
-    port: &mut u16,
+    settings: StreamSettings,
 ) -> anyhow::Result<()> {
     let setup_exit = Command::new("/home/pi/mate/setup_camera.sh")
         .arg(camera)
@@ -370,40 +1244,793 @@ fn add_camera(
         bail!("Could not setup cameras");
     }
 
-    let bind = (ip, *port).into();
-    let child =
-        start_gstreamer(camera, bind).with_context(|| format!("Spawn gstreamer for {camera}"))?;
-    *port += 1;
+    let addrs = target.next_addrs().context("No stream target")?;
+    let child = start_gstreamer(camera, addrs, target.multicast_loopback(), settings)
+        .with_context(|| format!("Spawn gstreamer for {camera}"))?;
 
-    cameras.insert((*camera).to_owned(), (child, bind));
+    cameras.insert((*camera).to_owned(), (child, addrs));
 
     Ok(())
 }
 
 /// Converts internal repersentation of cameras to what the protocol calls for
-fn camera_list(
+fn camera_list<W>(
     cameras: &HashMap<String, (Child, SocketAddr)>,
+    fallback: &HashMap<String, W>,
     robot: RobotId,
     config: &RobotConfig,
-) -> Vec<CameraBundle> {
+    intrinsics: &HashMap<String, CameraIntrinsics>,
+) -> Vec<(CameraBundle, Option<CameraIntrinsics>)> {
     let mut list = Vec::new();
 
-    for (name, &(_, location)) in cameras {
-        let (name, transform) = match config.cameras.get(name) {
+    let streams = cameras
+        .iter()
+        .map(|(device, &(_, location))| (device, location));
+    let fallback = fallback.keys().map(|device| (device, FALLBACK_LOCATION));
+
+    for (device, location) in streams.chain(fallback) {
+        let (id, name, transform) = match config.cameras.get(device) {
             Some(definition) => (
-                format!("{} ({})", definition.name, name),
+                CameraId::new(definition.name.clone()),
+                format!("{} ({})", definition.name, device),
                 definition.transform.flatten(),
             ),
-            None => (name.to_owned(), Transform::default()),
+            None => (
+                CameraId::new(device.clone()),
+                device.to_owned(),
+                Transform::default(),
+            ),
         };
 
-        list.push(CameraBundle {
+        let bundle = CameraBundle {
             name: Name::new(name),
-            camera: Camera { location },
+            camera: Camera { id, location },
             robot,
             transform,
-        });
+        };
+
+        list.push((bundle, intrinsics.get(device).copied()));
     }
 
     list
 }
+
+/// Reads the calibration of every camera that references one, keyed by device
+///
+/// Cameras whose calibration can't be read are left uncalibrated so the surface falls back to
+/// its own calibration
+fn load_intrinsics(config: &RobotConfig) -> HashMap<String, CameraIntrinsics> {
+    let mut intrinsics = HashMap::default();
+
+    for (device, definition) in &config.cameras {
+        let Some(ref path) = definition.calibration else {
+            continue;
+        };
+
+        match read_intrinsics(path) {
+            Ok(calibration) => {
+                intrinsics.insert(device.clone(), calibration);
+            }
+            Err(err) => {
+                warn!(
+                    "Could not load calibration for {}: {err:?}",
+                    definition.name
+                );
+            }
+        }
+    }
+
+    intrinsics
+}
+
+fn read_intrinsics(path: &Path) -> anyhow::Result<CameraIntrinsics> {
+    let data =
+        fs::read_to_string(path).with_context(|| format!("Read calibration {}", path.display()))?;
+
+    toml::from_str(&data).with_context(|| format!("Parse calibration {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        fs, io,
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        path::Path,
+        time::{Duration, Instant},
+    };
+
+    use ahash::{HashMap, HashSet};
+    use anyhow::anyhow;
+    use bevy::app::{App, Update};
+    use common::{
+        components::{CameraFallbackStream, CameraIntrinsics, PowerMode, RobotId},
+        ecs_sync::NetId,
+        types::ids::CameraId,
+    };
+    use crossbeam::channel;
+
+    use crate::{
+        config::{CameraTransport, MulticastDefinition, RobotConfig},
+        plugins::core::robot::{LocalRobot, LocalRobotMarker},
+    };
+
+    use super::{
+        capture_still, fallback_devices, gstreamer_args, handle_fallback_requests,
+        handle_power_mode, load_intrinsics, still_args, stop_cameras, streamed, streams_in,
+        CameraAction, CameraChannels, CameraEvent, CameraEventQueue, StreamProcess, StreamSettings,
+        StreamTarget, PEER_DEBOUNCE,
+    };
+
+    const SURFACE: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 2));
+    const MULTICAST: MulticastDefinition = MulticastDefinition {
+        group: Ipv4Addr::new(239, 255, 42, 1),
+        base_port: 5600,
+        loopback: true,
+    };
+
+    #[test]
+    fn unicast_pipeline() {
+        let settings = StreamSettings::for_power(PowerMode::Normal);
+        let args = gstreamer_args("/dev/video2", (SURFACE, 1024).into(), None, settings);
+        let pipeline = args.join(" ");
+
+        assert!(pipeline.starts_with("v4l2src device=/dev/video2 do-timestamp=true ! "));
+        assert!(pipeline.contains("width=1920,height=1080,framerate=30/1 "));
+        assert!(pipeline.ends_with("udpsink sync=false host=192.168.1.2 port=1024"));
+    }
+
+    #[test]
+    fn low_power_pipeline() {
+        let low_power = StreamSettings::for_power(PowerMode::LowPower);
+        assert_eq!(StreamSettings::for_power(PowerMode::Critical), low_power);
+
+        let args = gstreamer_args("/dev/video2", (SURFACE, 1024).into(), None, low_power);
+        let pipeline = args.join(" ");
+
+        assert!(pipeline.starts_with(
+            "v4l2src device=/dev/video2 do-timestamp=true extra-controls=c,video_bitrate=1500000 "
+        ));
+        assert!(pipeline.contains("width=1280,height=720,framerate=15/1 "));
+    }
+
+    #[test]
+    fn critical_power_keeps_primary_cameras() {
+        let config: RobotConfig = toml::from_str(include_str!("../../../robot.toml")).unwrap();
+
+        for power in [PowerMode::Normal, PowerMode::LowPower] {
+            assert!(streams_in("/dev/video2", power, &config));
+            assert!(streams_in("/dev/video6", power, &config));
+            assert!(streams_in("/dev/video99", power, &config));
+        }
+
+        assert!(streams_in("/dev/video2", PowerMode::Critical, &config));
+        assert!(!streams_in("/dev/video6", PowerMode::Critical, &config));
+        // Unconfigured cameras aren't primary
+        assert!(!streams_in("/dev/video99", PowerMode::Critical, &config));
+    }
+
+    #[test]
+    fn multicast_pipeline() {
+        let settings = StreamSettings::for_power(PowerMode::Normal);
+        let args = gstreamer_args(
+            "/dev/video2",
+            (MULTICAST.group, 5600).into(),
+            Some(false),
+            settings,
+        );
+        let pipeline = args.join(" ");
+
+        assert!(pipeline.ends_with(
+            "udpsink sync=false host=239.255.42.1 port=5600 auto-multicast=true loop=false"
+        ));
+    }
+
+    #[test]
+    fn unicast_follows_peer() {
+        let mut target = StreamTarget::new(CameraTransport::Unicast);
+
+        assert!(!target.is_available());
+        assert_eq!(target.next_addrs(), None);
+
+        assert!(target.new_peer(SURFACE));
+        assert_eq!(target.next_addrs(), Some((SURFACE, 1024).into()));
+        assert_eq!(target.next_addrs(), Some((SURFACE, 1025).into()));
+        assert_eq!(target.multicast_loopback(), None);
+
+        assert!(target.lost_peer());
+        assert!(!target.is_available());
+    }
+
+    #[test]
+    fn multicast_ignores_peers() {
+        let mut target = StreamTarget::new(CameraTransport::Multicast(MULTICAST));
+        let group = |port| Some(SocketAddr::from((MULTICAST.group, port)));
+
+        // Streams can start before anyone connects
+        assert!(target.is_available());
+        assert_eq!(target.next_addrs(), group(5600));
+
+        assert!(!target.new_peer(SURFACE));
+        assert!(!target.lost_peer());
+
+        assert!(target.is_available());
+        assert_eq!(target.next_addrs(), group(5601));
+        assert_eq!(target.multicast_loopback(), Some(true));
+    }
+
+    #[test]
+    fn still_pipeline() {
+        let args = still_args("/dev/video2", Path::new("/tmp/still.jpg"));
+        let pipeline = args.join(" ");
+
+        assert!(pipeline.starts_with("-q v4l2src device=/dev/video2 num-buffers=1 "));
+        assert!(pipeline.ends_with("filesink location=/tmp/still.jpg"));
+    }
+
+    struct MockStream<'a> {
+        id: u32,
+        log: &'a RefCell<Vec<String>>,
+        fail_stop: bool,
+    }
+
+    impl StreamProcess for MockStream<'_> {
+        fn stop(&mut self) -> io::Result<()> {
+            if self.fail_stop {
+                return Err(io::ErrorKind::Other.into());
+            }
+
+            self.log.borrow_mut().push(format!("stop {}", self.id));
+            Ok(())
+        }
+    }
+
+    fn streams<'a>(
+        log: &'a RefCell<Vec<String>>,
+        fail_stop: bool,
+    ) -> HashMap<String, (MockStream<'a>, SocketAddr)> {
+        let mut cameras = HashMap::default();
+        cameras.insert(
+            "/dev/video2".to_owned(),
+            (
+                MockStream {
+                    id: 0,
+                    log,
+                    fail_stop,
+                },
+                (SURFACE, 1024).into(),
+            ),
+        );
+
+        cameras
+    }
+
+    #[test]
+    fn still_pauses_and_resumes_stream() {
+        let log = RefCell::new(Vec::new());
+        let mut cameras = streams(&log, false);
+
+        let (still, resumed) = capture_still(
+            "/dev/video2",
+            &mut cameras,
+            |camera| {
+                log.borrow_mut().push(format!("snapshot {camera}"));
+                Ok(vec![1, 2, 3])
+            },
+            |camera, addrs| {
+                log.borrow_mut().push(format!("start {camera} {addrs}"));
+                Ok(MockStream {
+                    id: 1,
+                    log: &log,
+                    fail_stop: false,
+                })
+            },
+        );
+
+        assert_eq!(still.unwrap(), vec![1, 2, 3]);
+        assert!(resumed.is_ok());
+        assert_eq!(
+            *log.borrow(),
+            [
+                "stop 0",
+                "snapshot /dev/video2",
+                "start /dev/video2 192.168.1.2:1024"
+            ]
+        );
+
+        // The new stream replaces the old one on the same address
+        let (stream, addrs) = &cameras["/dev/video2"];
+        assert_eq!(stream.id, 1);
+        assert_eq!(*addrs, (SURFACE, 1024).into());
+    }
+
+    #[test]
+    fn stream_resumes_after_failed_still() {
+        let log = RefCell::new(Vec::new());
+        let mut cameras = streams(&log, false);
+
+        let (still, resumed) = capture_still(
+            "/dev/video2",
+            &mut cameras,
+            |_| Err(anyhow!("No frame")),
+            |_, _| {
+                log.borrow_mut().push("start".to_owned());
+                Ok(MockStream {
+                    id: 1,
+                    log: &log,
+                    fail_stop: false,
+                })
+            },
+        );
+
+        assert!(still.is_err());
+        assert!(resumed.is_ok());
+        assert_eq!(*log.borrow(), ["stop 0", "start"]);
+        assert!(cameras.contains_key("/dev/video2"));
+    }
+
+    #[test]
+    fn still_skipped_when_stream_cannot_pause() {
+        let log = RefCell::new(Vec::new());
+        let mut cameras = streams(&log, true);
+
+        let (still, resumed) = capture_still(
+            "/dev/video2",
+            &mut cameras,
+            |_| panic!("Snapshot while streaming"),
+            |_, _| panic!("Restarted a running stream"),
+        );
+
+        assert!(still.is_err());
+        assert!(resumed.is_ok());
+        assert_eq!(cameras["/dev/video2"].0.id, 0);
+
+        let (still, _) = capture_still(
+            "/dev/video9",
+            &mut cameras,
+            |_| panic!("Snapshot of unknown camera"),
+            |_, _| panic!("Started unknown camera"),
+        );
+        assert!(still.is_err());
+    }
+
+    #[test]
+    fn stream_lost_when_restart_fails() {
+        let log = RefCell::new(Vec::new());
+        let mut cameras = streams(&log, false);
+
+        let (still, resumed) = capture_still(
+            "/dev/video2",
+            &mut cameras,
+            |_| Ok(vec![1]),
+            |_, _| Err(io::ErrorKind::NotFound.into()),
+        );
+
+        // The still is kept even though the stream is gone
+        assert!(still.is_ok());
+        assert!(resumed.is_err());
+        assert!(cameras.is_empty());
+    }
+
+    #[test]
+    fn stop_reports_each_failure() {
+        let log = RefCell::new(Vec::new());
+        let mut cameras = streams(&log, true);
+
+        let mut failures = Vec::new();
+        stop_cameras(&mut cameras, |err| failures.push(err));
+
+        assert_eq!(failures.len(), 1);
+        assert!(cameras.is_empty());
+    }
+
+    const OTHER_SURFACE: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 3));
+
+    /// Feeds `events` in at the given offsets, then collects every action ready at `at`
+    fn script(
+        queue: &mut CameraEventQueue,
+        start: Instant,
+        events: impl IntoIterator<Item = (Duration, CameraEvent)>,
+        at: Duration,
+    ) -> Vec<CameraAction> {
+        for (offset, event) in events {
+            queue.push(event, start + offset);
+        }
+
+        let mut actions = Vec::new();
+        while let Some(action) = queue.pop(start + at) {
+            actions.push(action);
+        }
+
+        actions
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn reconnect_bursts_restart_once() {
+        let start = Instant::now();
+        let mut queue = CameraEventQueue::default();
+
+        let burst = [
+            (ms(0), CameraEvent::NewPeer(SURFACE)),
+            (ms(300), CameraEvent::NewPeer(OTHER_SURFACE)),
+            (ms(600), CameraEvent::NewPeer(SURFACE)),
+        ];
+
+        // Nothing happens until the burst has had time to settle
+        assert!(script(&mut queue, start, burst, ms(900)).is_empty());
+        assert!(queue.is_restarting());
+        assert_eq!(queue.deadline(), Some(start + PEER_DEBOUNCE));
+
+        // Only the last peer is streamed to
+        assert_eq!(
+            script(&mut queue, start, [], PEER_DEBOUNCE),
+            [CameraAction::Restart(SURFACE)]
+        );
+        assert!(!queue.is_restarting());
+        assert_eq!(queue.deadline(), None);
+    }
+
+    #[test]
+    fn flapping_peer_waits_until_it_settles() {
+        let start = Instant::now();
+        let mut queue = CameraEventQueue::default();
+
+        let flapping = [
+            (ms(0), CameraEvent::NewPeer(SURFACE)),
+            (ms(200), CameraEvent::LostPeer),
+            (ms(300), CameraEvent::NewPeer(OTHER_SURFACE)),
+            (ms(600), CameraEvent::LostPeer),
+            (ms(700), CameraEvent::NewPeer(SURFACE)),
+        ];
+
+        // Each disconnect puts streams back where they were, so the wait starts over
+        assert!(script(&mut queue, start, flapping, PEER_DEBOUNCE).is_empty());
+        assert_eq!(queue.deadline(), Some(start + ms(700) + PEER_DEBOUNCE));
+
+        assert_eq!(
+            script(&mut queue, start, [], ms(700) + PEER_DEBOUNCE),
+            [CameraAction::Restart(SURFACE)]
+        );
+    }
+
+    #[test]
+    fn same_address_reconnect_is_ignored() {
+        let start = Instant::now();
+        let mut queue = CameraEventQueue::default();
+
+        assert_eq!(
+            script(
+                &mut queue,
+                start,
+                [(ms(0), CameraEvent::NewPeer(SURFACE))],
+                PEER_DEBOUNCE
+            ),
+            [CameraAction::Restart(SURFACE)]
+        );
+
+        // The surface restarting shows up as both in the same frame
+        let restart = [
+            (ms(5000), CameraEvent::LostPeer),
+            (ms(5000), CameraEvent::NewPeer(SURFACE)),
+        ];
+        assert!(script(&mut queue, start, restart, ms(5000)).is_empty());
+        assert!(!queue.is_restarting());
+        assert!(script(&mut queue, start, [], ms(10000)).is_empty());
+
+        // A different address is still followed
+        let moved = [
+            (ms(20000), CameraEvent::LostPeer),
+            (ms(20000), CameraEvent::NewPeer(OTHER_SURFACE)),
+        ];
+        assert_eq!(
+            script(&mut queue, start, moved, ms(20000) + PEER_DEBOUNCE),
+            [CameraAction::Restart(OTHER_SURFACE)]
+        );
+    }
+
+    #[test]
+    fn lost_peer_stops_streams() {
+        let start = Instant::now();
+        let mut queue = CameraEventQueue::default();
+
+        script(
+            &mut queue,
+            start,
+            [(ms(0), CameraEvent::NewPeer(SURFACE))],
+            PEER_DEBOUNCE,
+        );
+
+        assert_eq!(
+            script(
+                &mut queue,
+                start,
+                [(ms(2000), CameraEvent::LostPeer)],
+                ms(2000) + PEER_DEBOUNCE
+            ),
+            [CameraAction::Stop]
+        );
+
+        // Already stopped
+        assert!(script(
+            &mut queue,
+            start,
+            [(ms(4000), CameraEvent::LostPeer)],
+            ms(4000)
+        )
+        .is_empty());
+        assert!(!queue.is_restarting());
+    }
+
+    #[test]
+    fn resyncs_wait_for_restarts() {
+        let start = Instant::now();
+        let mut queue = CameraEventQueue::default();
+
+        let events = [
+            (ms(0), CameraEvent::Resync),
+            (ms(0), CameraEvent::NewPeer(SURFACE)),
+            (ms(100), CameraEvent::Resync),
+            (ms(100), CameraEvent::CaptureStill("/dev/video2".to_owned())),
+        ];
+
+        // Stills aren't held back, resyncs are
+        assert_eq!(
+            script(&mut queue, start, events, ms(100)),
+            [CameraAction::CaptureStill("/dev/video2".to_owned())]
+        );
+
+        // Then run once, after the restart
+        assert_eq!(
+            script(&mut queue, start, [], PEER_DEBOUNCE),
+            [CameraAction::Restart(SURFACE), CameraAction::Resync]
+        );
+
+        // Without a restart pending they are immediate
+        assert_eq!(
+            script(
+                &mut queue,
+                start,
+                [(ms(2000), CameraEvent::Resync)],
+                ms(2000)
+            ),
+            [CameraAction::Resync]
+        );
+    }
+
+    #[test]
+    fn power_changes_wait_for_restarts() {
+        let start = Instant::now();
+        let mut queue = CameraEventQueue::default();
+
+        let events = [
+            (ms(0), CameraEvent::NewPeer(SURFACE)),
+            (ms(0), CameraEvent::PowerMode(PowerMode::LowPower)),
+            (ms(100), CameraEvent::PowerMode(PowerMode::Critical)),
+            (ms(100), CameraEvent::Resync),
+        ];
+        assert!(script(&mut queue, start, events, ms(100)).is_empty());
+
+        // Only the latest mode is applied
+        assert_eq!(
+            script(&mut queue, start, [], PEER_DEBOUNCE),
+            [
+                CameraAction::Restart(SURFACE),
+                CameraAction::PowerMode(PowerMode::Critical),
+                CameraAction::Resync
+            ]
+        );
+
+        assert_eq!(
+            script(
+                &mut queue,
+                start,
+                [(ms(2000), CameraEvent::PowerMode(PowerMode::Normal))],
+                ms(2000)
+            ),
+            [CameraAction::PowerMode(PowerMode::Normal)]
+        );
+    }
+
+    #[test]
+    fn power_mode_changes_reach_the_camera_thread() {
+        let (tx_events, rx_events) = channel::bounded(10);
+        let (_tx_cameras, rx_cameras) = channel::bounded(1);
+        let (_tx_stills, rx_stills) = channel::bounded(1);
+        let (_tx_state, rx_state) = channel::bounded(1);
+        let (_tx_resyncs, rx_resyncs) = channel::bounded(1);
+        let (_tx_frames, rx_frames) = channel::bounded(1);
+
+        let mut app = App::new();
+        app.insert_resource(CameraChannels(
+            tx_events, rx_cameras, rx_stills, rx_state, None, rx_resyncs, rx_frames,
+        ))
+        .add_systems(Update, handle_power_mode);
+        let robot = app.world.spawn((LocalRobotMarker, PowerMode::Normal)).id();
+
+        app.update();
+        app.world.entity_mut(robot).insert(PowerMode::Critical);
+        app.update();
+        app.update();
+
+        let events = rx_events.try_iter().collect::<Vec<_>>();
+        assert!(matches!(
+            events[..],
+            [
+                CameraEvent::PowerMode(PowerMode::Normal),
+                CameraEvent::PowerMode(PowerMode::Critical)
+            ]
+        ));
+    }
+
+    fn fallback(camera: &'static str, fps: u32) -> CameraFallbackStream {
+        CameraFallbackStream {
+            camera: CameraId::from_static(camera),
+            fps,
+            quality: 60,
+        }
+    }
+
+    #[test]
+    fn fallback_changes_wait_for_restarts() {
+        let start = Instant::now();
+        let mut queue = CameraEventQueue::default();
+
+        let events = [
+            (ms(0), CameraEvent::NewPeer(SURFACE)),
+            (ms(0), CameraEvent::Fallback(vec![fallback("Front", 5)])),
+            (ms(100), CameraEvent::Fallback(vec![fallback("Front", 2)])),
+        ];
+        assert!(script(&mut queue, start, events, ms(100)).is_empty());
+
+        // The newest requests replace the older ones
+        assert_eq!(
+            script(&mut queue, start, [], PEER_DEBOUNCE),
+            [
+                CameraAction::Restart(SURFACE),
+                CameraAction::Fallback(vec![fallback("Front", 2)])
+            ]
+        );
+
+        assert_eq!(
+            script(
+                &mut queue,
+                start,
+                [(ms(2000), CameraEvent::Fallback(vec![]))],
+                ms(2000)
+            ),
+            [CameraAction::Fallback(vec![])]
+        );
+    }
+
+    #[test]
+    fn fallback_requests_reach_the_camera_thread() {
+        let (tx_events, rx_events) = channel::bounded(10);
+        let (_tx_cameras, rx_cameras) = channel::bounded(1);
+        let (_tx_stills, rx_stills) = channel::bounded(1);
+        let (_tx_state, rx_state) = channel::bounded(1);
+        let (_tx_resyncs, rx_resyncs) = channel::bounded(1);
+        let (_tx_frames, rx_frames) = channel::bounded(1);
+
+        let mut app = App::new();
+        app.insert_resource(CameraChannels(
+            tx_events, rx_cameras, rx_stills, rx_state, None, rx_resyncs, rx_frames,
+        ))
+        .add_systems(Update, handle_fallback_requests);
+
+        let robot = app.world.spawn(LocalRobotMarker).id();
+        let net_id = NetId::random();
+        app.world.insert_resource(LocalRobot {
+            net_id,
+            entity: robot,
+        });
+
+        // Nothing is sent until the surface asks
+        app.update();
+        assert!(rx_events.try_recv().is_err());
+
+        let request = app
+            .world
+            .spawn((fallback("Front", 5), RobotId(net_id)))
+            .id();
+        // Requests for other robots are ignored
+        app.world
+            .spawn((fallback("Top", 5), RobotId(NetId::random())));
+        app.update();
+        app.update();
+
+        app.world.despawn(request);
+        app.update();
+
+        let events = rx_events.try_iter().collect::<Vec<_>>();
+        let [CameraEvent::Fallback(added), CameraEvent::Fallback(removed)] = &events[..] else {
+            panic!("Expected two fallback events");
+        };
+        assert_eq!(added, &[fallback("Front", 5)]);
+        assert!(removed.is_empty());
+    }
+
+    #[test]
+    fn fallback_cameras_do_not_stream() {
+        let config: RobotConfig = toml::from_str(include_str!("../../../robot.toml")).unwrap();
+        let detected: HashSet<String> = ["/dev/video2", "/dev/video6", "/dev/video30"]
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect();
+
+        // By display name or device, cameras that aren't plugged in are skipped
+        let requests = [
+            fallback("Front", 5),
+            fallback("/dev/video30", 2),
+            fallback("A", 5),
+        ];
+        let devices = fallback_devices(&requests, &detected, &config);
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices["/dev/video2"], &requests[0]);
+        assert_eq!(devices["/dev/video30"], &requests[1]);
+
+        let streaming = streamed(&detected, &devices);
+        assert_eq!(streaming, HashSet::from_iter(["/dev/video6".to_owned()]));
+    }
+
+    #[test]
+    fn shutdown_is_not_delayed() {
+        let start = Instant::now();
+        let mut queue = CameraEventQueue::default();
+
+        let events = [
+            (ms(0), CameraEvent::NewPeer(SURFACE)),
+            (ms(0), CameraEvent::Resync),
+            (ms(10), CameraEvent::Shutdown),
+        ];
+
+        assert_eq!(
+            script(&mut queue, start, events, ms(10)),
+            [CameraAction::Shutdown]
+        );
+    }
+
+    #[test]
+    fn calibration_files_are_loaded_per_device() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let front = dir.path().join("front.toml");
+        fs::write(
+            &front,
+            "resolution = [1280, 720]\nfx = 900.0\nfy = 905.0\ncx = 640.0\ncy = 360.0\n",
+        )
+        .unwrap();
+        let broken = dir.path().join("broken.toml");
+        fs::write(&broken, "fx = \"fast\"").unwrap();
+
+        let mut config: RobotConfig = toml::from_str(include_str!("../../../robot.toml")).unwrap();
+        let calibrations = [
+            ("/dev/video2", Some(front)),
+            ("/dev/video6", Some(broken)),
+            ("/dev/video10", Some(dir.path().join("missing.toml"))),
+            ("/dev/video14", None),
+        ];
+        for (device, calibration) in calibrations {
+            config.cameras.get_mut(device).unwrap().calibration = calibration;
+        }
+
+        let intrinsics = load_intrinsics(&config);
+
+        // Unreadable calibrations are skipped rather than failing every camera
+        assert_eq!(intrinsics.len(), 1);
+        assert_eq!(
+            intrinsics["/dev/video2"],
+            CameraIntrinsics {
+                resolution: (1280, 720),
+                fx: 900.0,
+                fy: 905.0,
+                cx: 640.0,
+                cy: 360.0,
+                distortion: [0.0; 5],
+            }
+        );
+    }
+}
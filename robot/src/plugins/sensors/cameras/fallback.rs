@@ -0,0 +1,242 @@
+//! Low rate JPEG capture for cameras whose stream can't reach the surface
+//!
+//! While a camera is in fallback its gstreamer is stopped and a worker owns the device instead.
+//! Frames are shrunk to [`FALLBACK_WIDTH`] and encoded at the requested quality. When the main
+//! thread falls behind frames are dropped, never queued.
+
+use std::{
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use bevy::log::info;
+use common::{components::CameraFallbackStream, video_frames::SendVideoFrame};
+use crossbeam::channel::{self, Receiver, RecvTimeoutError, Sender, TrySendError};
+use opencv::{
+    core::{Size, Vector},
+    imgcodecs, imgproc,
+    prelude::*,
+    videoio::{self, VideoCapture},
+};
+
+/// Width frames are shrunk to, taller ones keep their aspect ratio
+pub const FALLBACK_WIDTH: i32 = 480;
+/// Fastest rate frames are captured at, the link is assumed to be poor
+pub const MAX_FALLBACK_FPS: u32 = 15;
+/// How many times opening the device is tried, gstreamer may still be releasing it
+const OPEN_ATTEMPTS: u32 = 5;
+const OPEN_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Captures frames from one device until dropped
+pub struct FallbackWorker {
+    request: CameraFallbackStream,
+    /// Dropped to stop the worker
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FallbackWorker {
+    /// Starts capturing from `device`, frames tagged with the requested camera go to `frames`
+    pub fn start(
+        device: &str,
+        request: CameraFallbackStream,
+        frames: Sender<SendVideoFrame>,
+        errors: Sender<anyhow::Error>,
+    ) -> anyhow::Result<Self> {
+        let (tx_stop, rx_stop) = channel::bounded(1);
+
+        let device = device.to_owned();
+        let worker_request = request.clone();
+        let thread = thread::Builder::new()
+            .name(format!("Fallback {device}"))
+            .spawn(move || {
+                if let Err(err) = capture_frames(&device, &worker_request, &rx_stop, &frames) {
+                    let _ = errors.send(err.context(format!("Fallback video for {device}")));
+                }
+            })
+            .context("Spawn thread")?;
+
+        Ok(Self {
+            request,
+            stop: Some(tx_stop),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn request(&self) -> &CameraFallbackStream {
+        &self.request
+    }
+}
+
+impl Drop for FallbackWorker {
+    fn drop(&mut self) {
+        self.stop.take();
+
+        // The device has to be released before gstreamer can have it back
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn capture_frames(
+    device: &str,
+    request: &CameraFallbackStream,
+    stop: &Receiver<()>,
+    frames: &Sender<SendVideoFrame>,
+) -> anyhow::Result<()> {
+    let mut capture = open_device(device, stop)?;
+    // Always encode the newest frame rather than one buffered while waiting
+    capture
+        .set(videoio::CAP_PROP_BUFFERSIZE, 1.0)
+        .context("Set buffer size")?;
+
+    info!("Sending {device} as fallback video");
+
+    let interval = frame_interval(request.fps);
+    let mut frame = Mat::default();
+    let mut scratch = Mat::default();
+
+    loop {
+        let start = Instant::now();
+
+        if !capture.read(&mut frame).context("Read frame")? {
+            bail!("Device stopped producing frames");
+        }
+
+        let jpeg = encode_frame(shrink(&frame, &mut scratch)?, request.quality)?;
+        let frame = SendVideoFrame {
+            camera: request.camera.clone(),
+            jpeg,
+        };
+
+        match frames.try_send(frame) {
+            Ok(()) | Err(TrySendError::Full(_)) => {}
+            Err(TrySendError::Disconnected(_)) => return Ok(()),
+        }
+
+        match stop.recv_timeout(interval.saturating_sub(start.elapsed())) {
+            Err(RecvTimeoutError::Timeout) => {}
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+fn open_device(device: &str, stop: &Receiver<()>) -> anyhow::Result<VideoCapture> {
+    for _ in 0..OPEN_ATTEMPTS {
+        let capture =
+            VideoCapture::from_file(device, videoio::CAP_V4L2).context("Create capture")?;
+        if capture.is_opened().context("Check capture")? {
+            return Ok(capture);
+        }
+
+        if !matches!(
+            stop.recv_timeout(OPEN_RETRY_DELAY),
+            Err(RecvTimeoutError::Timeout)
+        ) {
+            bail!("Stopped before the device opened");
+        }
+    }
+
+    bail!("Could not open {device}")
+}
+
+/// Time between captures at `fps`, clamped to 1 through [`MAX_FALLBACK_FPS`]
+pub fn frame_interval(fps: u32) -> Duration {
+    Duration::from_secs(1) / fps.clamp(1, MAX_FALLBACK_FPS)
+}
+
+/// Shrinks frames wider than [`FALLBACK_WIDTH`] into `scratch`, returning the frame to encode
+pub fn shrink<'a>(frame: &'a Mat, scratch: &'a mut Mat) -> anyhow::Result<&'a Mat> {
+    let (width, height) = (frame.cols(), frame.rows());
+    if width <= FALLBACK_WIDTH {
+        return Ok(frame);
+    }
+
+    let size = Size::new(FALLBACK_WIDTH, (height * FALLBACK_WIDTH / width).max(1));
+    imgproc::resize(frame, scratch, size, 0.0, 0.0, imgproc::INTER_AREA).context("Shrink frame")?;
+
+    Ok(scratch)
+}
+
+/// Encodes `frame` as a JPEG, `quality` is clamped to 1 through 100
+pub fn encode_frame(frame: &Mat, quality: u8) -> anyhow::Result<Vec<u8>> {
+    let params = Vector::from_slice(&[
+        imgcodecs::IMWRITE_JPEG_QUALITY,
+        quality.clamp(1, 100) as i32,
+    ]);
+
+    let mut jpeg = Vector::<u8>::new();
+    if !imgcodecs::imencode(".jpg", frame, &mut jpeg, &params).context("Encode frame")? {
+        bail!("Frame could not be encoded");
+    }
+
+    Ok(jpeg.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use common::video_frames::MAX_VIDEO_FRAME_LEN;
+    use opencv::{
+        core::{self, Scalar, Vec3b, Vector, CV_8UC3},
+        imgcodecs,
+        prelude::*,
+    };
+
+    use super::{encode_frame, frame_interval, shrink, FALLBACK_WIDTH};
+
+    #[test]
+    fn frames_survive_the_round_trip() {
+        let color = Scalar::new(40.0, 120.0, 200.0, 0.0);
+        let frame = Mat::new_rows_cols_with_default(1080, 1920, CV_8UC3, color).unwrap();
+
+        let mut scratch = Mat::default();
+        let small = shrink(&frame, &mut scratch).unwrap();
+        let jpeg = encode_frame(small, 60).unwrap();
+        assert!(jpeg.len() <= MAX_VIDEO_FRAME_LEN);
+
+        let decoded =
+            imgcodecs::imdecode(&Vector::from_slice(&jpeg), imgcodecs::IMREAD_COLOR).unwrap();
+        assert_eq!((decoded.cols(), decoded.rows()), (FALLBACK_WIDTH, 270));
+
+        let pixel = decoded.at_2d::<Vec3b>(135, 240).unwrap();
+        for (actual, expected) in pixel.0.into_iter().zip([40, 120, 200]) {
+            assert!(actual.abs_diff(expected) <= 4, "{pixel:?}");
+        }
+    }
+
+    #[test]
+    fn small_frames_are_not_resized() {
+        let frame = Mat::new_rows_cols_with_default(240, 320, CV_8UC3, Scalar::all(0.0)).unwrap();
+
+        let mut scratch = Mat::default();
+        let small = shrink(&frame, &mut scratch).unwrap();
+        assert_eq!((small.cols(), small.rows()), (320, 240));
+        assert!(scratch.empty());
+    }
+
+    #[test]
+    fn quality_trades_size() {
+        let mut frame =
+            Mat::new_rows_cols_with_default(270, 480, CV_8UC3, Scalar::all(0.0)).unwrap();
+        core::randu(&mut frame, &Scalar::all(0.0), &Scalar::all(255.0)).unwrap();
+
+        let low = encode_frame(&frame, 20).unwrap();
+        let high = encode_frame(&frame, 90).unwrap();
+        assert!(low.len() < high.len());
+
+        // Out of range qualities still encode
+        assert!(!encode_frame(&frame, 0).unwrap().is_empty());
+        assert!(!encode_frame(&frame, 255).unwrap().is_empty());
+    }
+
+    #[test]
+    fn frame_rate_is_clamped() {
+        assert_eq!(frame_interval(0), Duration::from_secs(1));
+        assert_eq!(frame_interval(5), Duration::from_millis(200));
+        assert_eq!(frame_interval(1000), frame_interval(15));
+    }
+}
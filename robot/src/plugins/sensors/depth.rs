@@ -6,16 +6,21 @@ use std::{
 use anyhow::Context;
 use bevy::{app::AppExit, prelude::*};
 use common::{
-    components::{Depth, DepthSettings},
+    components::{Depth, DepthSettings, RobotId},
+    ecs_sync::scoped::ScopedEvents,
     error::{self, Errors},
-    events::CalibrateSeaLevel,
+    events::{AckedCommand, CalibrateSeaLevel, CommandComplete},
+    shutdown::{self, AppShutdownSet, SpawnWorkerExt, WorkerThread},
     types::hw::DepthFrame,
 };
 use crossbeam::channel::{self, Receiver, Sender};
 use tracing::{span, Level};
 
 use crate::{
-    peripheral::ms5937::Ms5837,
+    peripheral::{
+        hal::pi::PiHal,
+        ms5937::{self, Ms5837},
+    },
     plugins::core::robot::{LocalRobot, LocalRobotMarker},
 };
 
@@ -38,12 +43,21 @@ impl Plugin for DepthPlugin {
                     .after(calibrate_sea_level),
             ),
         );
-        app.add_systems(Last, shutdown.run_if(resource_exists::<DepthChannels>));
+        app.add_systems(
+            Last,
+            shutdown
+                .in_set(AppShutdownSet::Threads)
+                .run_if(resource_exists::<DepthChannels>),
+        );
     }
 }
 
 #[derive(Resource)]
-struct DepthChannels(Receiver<DepthFrame>, Sender<Message>);
+struct DepthChannels(
+    Receiver<DepthFrame>,
+    Sender<Message>,
+    Option<WorkerThread<()>>,
+);
 
 enum Message {
     Settings(DepthSettings),
@@ -58,10 +72,8 @@ fn start_depth_thread(
     let (tx_data, rx_data) = channel::bounded(5);
     let (tx_exit, rx_msg) = channel::bounded(1);
 
-    let mut depth =
-        Ms5837::new(Ms5837::I2C_BUS, Ms5837::I2C_ADDRESS).context("Depth sensor (Ms5837)")?;
-
-    cmds.insert_resource(DepthChannels(rx_data, tx_exit));
+    let mut depth = Ms5837::new(&PiHal, ms5937::I2C_BUS, ms5937::I2C_ADDRESS)
+        .context("Depth sensor (Ms5837)")?;
 
     let sea_level = depth.read_frame().context("Read Sea Level")?;
     depth.sea_level = sea_level.pressure;
@@ -72,9 +84,9 @@ fn start_depth_thread(
     });
 
     let errors = errors.0.clone();
-    thread::Builder::new()
+    let depth_thread = thread::Builder::new()
         .name("Depth Thread".to_owned())
-        .spawn(move || {
+        .spawn_worker(move || {
             let _span = span!(Level::INFO, "Depth sensor thread").entered();
 
             let interval = Duration::from_secs_f64(1.0 / 100.0);
@@ -118,6 +130,8 @@ fn start_depth_thread(
         })
         .context("Start thread")?;
 
+    cmds.insert_resource(DepthChannels(rx_data, tx_exit, Some(depth_thread)));
+
     Ok(())
 }
 
@@ -130,16 +144,31 @@ fn read_new_data(mut cmds: Commands, channels: Res<DepthChannels>, robot: Res<Lo
 }
 
 fn calibrate_sea_level(
-    mut cmds: Commands,
-    mut events: EventReader<CalibrateSeaLevel>,
+    mut events: ScopedEvents<CalibrateSeaLevel>,
     mut robot: Query<(&Depth, &mut DepthSettings), With<LocalRobotMarker>>,
+    local: Res<LocalRobot>,
+    mut completions: EventWriter<CommandComplete>,
 ) {
     for _ in events.read() {
         info!("Calibrating Sea Level");
 
-        for (depth, mut settings) in &mut robot {
-            settings.sea_level = depth.0.pressure;
-        }
+        let (ok, detail) = match robot.get_single_mut() {
+            Ok((depth, mut settings)) => {
+                settings.sea_level = depth.0.pressure;
+                (true, format!("Sea level set to {}", depth.0.pressure))
+            }
+            Err(_) => {
+                warn!("Could not calibrate sea level, no depth reading yet");
+                (false, "No depth reading yet".to_owned())
+            }
+        };
+
+        completions.send(CommandComplete {
+            robot: RobotId(local.net_id),
+            command: AckedCommand::CalibrateSeaLevel,
+            ok,
+            detail,
+        });
     }
 }
 
@@ -157,8 +186,125 @@ fn listen_for_settings(
     Ok(())
 }
 
-fn shutdown(channels: Res<DepthChannels>, mut exit: EventReader<AppExit>) {
+fn shutdown(mut channels: ResMut<DepthChannels>, mut exit: EventReader<AppExit>) {
     for _event in exit.read() {
         let _ = channels.1.send(Message::Shutdown);
+
+        if let Some(depth_thread) = channels.2.take() {
+            if !shutdown::join_timeout(depth_thread, shutdown::JOIN_TIMEOUT) {
+                warn!("Depth thread did not shut down in time");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::App, prelude::*};
+    use common::{
+        components::{Depth, DepthSettings, Robot, RobotId},
+        ecs_sync::{scoped::Scoped, NetId},
+        events::{AckedCommand, CalibrateSeaLevel, CommandComplete},
+        types::{
+            hw::DepthFrame,
+            units::{Celsius, Mbar, Meters},
+        },
+    };
+
+    use crate::plugins::core::robot::{LocalRobot, LocalRobotMarker};
+
+    use super::calibrate_sea_level;
+
+    fn app() -> (App, Entity, RobotId) {
+        let mut app = App::new();
+        app.add_event::<CalibrateSeaLevel>()
+            .add_event::<Scoped<CalibrateSeaLevel>>()
+            .add_event::<CommandComplete>()
+            .add_systems(Update, calibrate_sea_level);
+
+        let net_id = NetId::random();
+        let robot = app
+            .world
+            .spawn((
+                LocalRobotMarker,
+                Robot,
+                RobotId(net_id),
+                DepthSettings {
+                    sea_level: Mbar(1013.25),
+                    fluid_density: 997.0,
+                },
+            ))
+            .id();
+        app.world.insert_resource(LocalRobot {
+            net_id,
+            entity: robot,
+        });
+
+        (app, robot, RobotId(net_id))
+    }
+
+    fn completions(app: &App) -> Vec<CommandComplete> {
+        let events = app.world.resource::<Events<CommandComplete>>();
+        events.get_reader().read(events).cloned().collect()
+    }
+
+    #[test]
+    fn calibration_reports_completion() {
+        let (mut app, robot, robot_id) = app();
+
+        app.world.entity_mut(robot).insert(Depth(DepthFrame {
+            depth: Meters(1.5),
+            altitude: Meters(0.0),
+            pressure: Mbar(1020.0),
+            temperature: Celsius(12.0),
+            capture_time: 0.0,
+        }));
+        app.world
+            .send_event(Scoped::new(robot_id, CalibrateSeaLevel));
+        app.update();
+
+        assert_eq!(
+            app.world.get::<DepthSettings>(robot).unwrap().sea_level,
+            Mbar(1020.0)
+        );
+        assert_eq!(
+            completions(&app),
+            [CommandComplete {
+                robot: robot_id,
+                command: AckedCommand::CalibrateSeaLevel,
+                ok: true,
+                detail: "Sea level set to 1020.00mbar".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn calibration_without_depth_fails() {
+        let (mut app, robot, robot_id) = app();
+
+        app.world
+            .send_event(Scoped::new(robot_id, CalibrateSeaLevel));
+        app.update();
+
+        assert_eq!(
+            app.world.get::<DepthSettings>(robot).unwrap().sea_level,
+            Mbar(1013.25)
+        );
+
+        let completions = completions(&app);
+        assert_eq!(completions.len(), 1);
+        assert!(!completions[0].ok);
+        assert_eq!(completions[0].command, AckedCommand::CalibrateSeaLevel);
+    }
+
+    #[test]
+    fn other_robots_are_ignored() {
+        let (mut app, _, _) = app();
+
+        app.world
+            .send_event(Scoped::new(RobotId(NetId::random()), CalibrateSeaLevel));
+        app.update();
+
+        assert!(completions(&app).is_empty());
     }
 }
@@ -0,0 +1,395 @@
+//! Fuses the pressure sensor's depth with the IMU's vertical acceleration
+//!
+//! A two state Kalman filter over depth and vertical speed. Every cycle is predicted forward with
+//! the world frame vertical acceleration, and new pressure readings pull it back toward the
+//! measured depth. When pressure readings stop the filter keeps going on the IMU alone, its
+//! variance grows until readings return and it converges again.
+//!
+//! Depth and speed are positive going down.
+
+use bevy::prelude::*;
+use common::{
+    components::{Depth, DepthEstimate, Inertial, Orientation},
+    types::units::Meters,
+};
+use glam::{Quat, Vec3};
+
+use crate::{
+    config::{DepthEstimateConfig, RobotConfig},
+    plugins::core::robot::{LocalRobot, LocalRobotMarker},
+};
+
+use super::ImuHealth;
+
+pub struct DepthEstimatePlugin;
+
+impl Plugin for DepthEstimatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_filter).add_systems(
+            Update,
+            estimate_depth.run_if(resource_exists::<DepthFilter>),
+        );
+    }
+}
+
+const GRAVITY: f32 = 9.80665;
+/// Longest step predicted at once, a stalled cycle shouldn't throw the estimate off
+const MAX_STEP: f32 = 0.1;
+/// Of the speed the filter starts out with, in square meters per second squared
+const INITIAL_VELOCITY_VARIANCE: f32 = 0.25;
+
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct DepthFilter {
+    config: DepthEstimateConfig,
+
+    /// `None` until the first pressure reading
+    state: Option<FilterState>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct FilterState {
+    depth: f32,
+    velocity: f32,
+    /// Covariance of depth and velocity
+    covariance: [[f32; 2]; 2],
+}
+
+impl DepthFilter {
+    pub fn new(config: DepthEstimateConfig) -> Self {
+        Self {
+            config,
+            state: None,
+        }
+    }
+
+    /// Moves the estimate `dt` seconds forward, `accel` is in meters per second squared
+    pub fn predict(&mut self, accel: f32, dt: f32) {
+        let Some(state) = &mut self.state else {
+            return;
+        };
+
+        state.depth += state.velocity * dt + 0.5 * accel * dt * dt;
+        state.velocity += accel * dt;
+
+        // P = F P F' + Q with F = [[1, dt], [0, 1]]
+        let [[p00, p01], [p10, p11]] = state.covariance;
+        let q = self.config.accel_noise.powi(2);
+
+        state.covariance = [
+            [
+                p00 + dt * (p10 + p01) + dt * dt * p11 + q * dt.powi(4) / 4.0,
+                p01 + dt * p11 + q * dt.powi(3) / 2.0,
+            ],
+            [p10 + dt * p11 + q * dt.powi(3) / 2.0, p11 + q * dt * dt],
+        ];
+    }
+
+    /// Folds in a depth measured by the pressure sensor
+    pub fn correct(&mut self, depth: f32) {
+        let r = self.config.pressure_noise.powi(2);
+
+        let Some(state) = &mut self.state else {
+            self.state = Some(FilterState {
+                depth,
+                velocity: 0.0,
+                covariance: [[r, 0.0], [0.0, INITIAL_VELOCITY_VARIANCE]],
+            });
+
+            return;
+        };
+
+        let [[p00, p01], [p10, p11]] = state.covariance;
+        let innovation = depth - state.depth;
+        let s = p00 + r;
+        let (k0, k1) = (p00 / s, p10 / s);
+
+        state.depth += k0 * innovation;
+        state.velocity += k1 * innovation;
+        state.covariance = [
+            [(1.0 - k0) * p00, (1.0 - k0) * p01],
+            [p10 - k1 * p00, p11 - k1 * p01],
+        ];
+    }
+
+    pub fn depth(&self) -> Option<f32> {
+        self.state.map(|it| it.depth)
+    }
+
+    pub fn velocity(&self) -> Option<f32> {
+        self.state.map(|it| it.velocity)
+    }
+
+    pub fn variance(&self) -> Option<f32> {
+        self.state.map(|it| it.covariance[0][0])
+    }
+
+    pub fn estimate(&self) -> Option<DepthEstimate> {
+        self.state.map(|it| DepthEstimate {
+            depth: Meters(it.depth),
+            velocity: it.velocity,
+            variance: it.covariance[0][0],
+        })
+    }
+}
+
+/// Acceleration toward the bottom in meters per second squared, without gravity
+///
+/// `accel` is what the accelerometer read in g, `orientation` takes it to the world frame
+pub fn vertical_accel(accel: Vec3, orientation: Quat) -> f32 {
+    let up = (orientation * accel).z - 1.0;
+
+    -up * GRAVITY
+}
+
+fn setup_filter(mut cmds: Commands, config: Res<RobotConfig>) {
+    cmds.insert_resource(DepthFilter::new(config.depth_estimate));
+}
+
+type DepthSources<'a> = (
+    Option<Ref<'a, Depth>>,
+    Option<&'a Inertial>,
+    Option<&'a Orientation>,
+);
+
+fn estimate_depth(
+    mut cmds: Commands,
+    robot: Res<LocalRobot>,
+    mut filter: ResMut<DepthFilter>,
+    state: Query<DepthSources<'static>, With<LocalRobotMarker>>,
+    imu_health: Option<Res<ImuHealth>>,
+    time: Res<Time<Real>>,
+) {
+    let Ok((depth, inertial, orientation)) = state.get_single() else {
+        return;
+    };
+
+    let imu_healthy = imu_health.is_some_and(|it| it.is_healthy(time.elapsed()));
+    let accel = match (inertial, orientation) {
+        (Some(inertial), Some(orientation)) if imu_healthy => {
+            let frame = &inertial.0;
+            let accel = Vec3::new(frame.accel_x.0, frame.accel_y.0, frame.accel_z.0);
+
+            vertical_accel(accel, orientation.rotation)
+        }
+        // Coast at the current speed
+        _ => 0.0,
+    };
+
+    filter.predict(accel, time.delta_seconds().min(MAX_STEP));
+
+    if let Some(depth) = depth.filter(|it| it.is_changed()) {
+        filter.correct(depth.0.depth.0);
+    }
+
+    if let Some(estimate) = filter.estimate() {
+        cmds.entity(robot.entity).insert(estimate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_PI_2, TAU};
+
+    use glam::{Quat, Vec3};
+
+    use crate::config::DepthEstimateConfig;
+
+    use super::{vertical_accel, DepthFilter};
+
+    const DT: f32 = 0.01;
+
+    /// Deterministic normally distributed noise
+    struct Noise(u64);
+
+    impl Noise {
+        fn uniform(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+
+            ((self.0 >> 40) as f32 + 0.5) / (1u64 << 24) as f32
+        }
+
+        fn normal(&mut self, std_dev: f32) -> f32 {
+            let (a, b) = (self.uniform(), self.uniform());
+
+            std_dev * (-2.0 * a.ln()).sqrt() * (TAU * b).cos()
+        }
+    }
+
+    /// Acceleration of a trajectory at `t` seconds, starting out still at 1 m
+    type Trajectory = fn(f32) -> f32;
+
+    struct Run {
+        truth: Vec<(f32, f32)>,
+        raw: Vec<Option<f32>>,
+        estimated: Vec<(f32, f32, f32)>,
+    }
+
+    /// Samples `trajectory` every `DT` for `seconds`, pressure readings are missing while
+    /// `dropout` holds
+    fn run(trajectory: Trajectory, seconds: f32, dropout: impl Fn(f32) -> bool) -> Run {
+        let mut noise = Noise(0x2545_f491_4f6c_dd1d);
+        let mut filter = DepthFilter::new(DepthEstimateConfig::default());
+        let mut run = Run {
+            truth: Vec::new(),
+            raw: Vec::new(),
+            estimated: Vec::new(),
+        };
+
+        // A slightly biased accelerometer, like a real one
+        let bias = 0.02;
+        let (mut depth, mut velocity) = (1.0, 0.0);
+
+        for step in 0..(seconds / DT) as usize {
+            let t = step as f32 * DT;
+            let accel = trajectory(t);
+
+            depth += velocity * DT + 0.5 * accel * DT * DT;
+            velocity += accel * DT;
+
+            filter.predict(accel + bias + noise.normal(0.05), DT);
+
+            let raw = (!dropout(t)).then(|| depth + noise.normal(0.02));
+            if let Some(raw) = raw {
+                filter.correct(raw);
+            }
+
+            run.truth.push((depth, velocity));
+            run.raw.push(raw);
+            run.estimated.push((
+                filter.depth().unwrap(),
+                filter.velocity().unwrap(),
+                filter.variance().unwrap(),
+            ));
+        }
+
+        run
+    }
+
+    fn rms(errors: impl Iterator<Item = f32>) -> f32 {
+        let (sum, count) = errors.fold((0.0, 0), |(sum, count), it| (sum + it * it, count + 1));
+
+        (sum / count as f32).sqrt()
+    }
+
+    /// Holding still, then diving at 0.5 m/s and leveling off again
+    fn dive(t: f32) -> f32 {
+        match t {
+            t if (2.0..3.0).contains(&t) => 0.5,
+            t if (5.0..6.0).contains(&t) => -0.5,
+            _ => 0.0,
+        }
+    }
+
+    #[test]
+    fn smoother_than_raw() {
+        let run = run(|_| 0.0, 10.0, |_| false);
+
+        // After settling
+        let settled = 200;
+        let raw = rms(run.raw[settled..]
+            .iter()
+            .zip(&run.truth[settled..])
+            .map(|(raw, truth)| raw.unwrap() - truth.0));
+        let estimated = rms(run.estimated[settled..]
+            .iter()
+            .zip(&run.truth[settled..])
+            .map(|(estimated, truth)| estimated.0 - truth.0));
+
+        assert!(estimated < raw / 3.0, "{estimated} vs {raw}");
+
+        let speed = rms(run.estimated[settled..].iter().map(|it| it.1));
+        assert!(speed < 0.02, "{speed}");
+    }
+
+    #[test]
+    fn follows_motion_without_lag() {
+        let run = run(dive, 8.0, |_| false);
+
+        let moving = 200..650;
+        let estimated = rms(run.estimated[moving.clone()]
+            .iter()
+            .zip(&run.truth[moving.clone()])
+            .map(|(estimated, truth)| estimated.0 - truth.0));
+
+        // Averaging the raw depth smooths it as much but falls behind
+        let window = 20;
+        let averaged = rms(moving.clone().map(|idx| {
+            let average = run.raw[idx + 1 - window..=idx]
+                .iter()
+                .map(|it| it.unwrap())
+                .sum::<f32>()
+                / window as f32;
+
+            average - run.truth[idx].0
+        }));
+        assert!(estimated < averaged / 2.0, "{estimated} vs {averaged}");
+
+        // Differentiating the raw depth is useless for the rate term
+        let velocity = rms(run.estimated[moving.clone()]
+            .iter()
+            .zip(&run.truth[moving.clone()])
+            .map(|(estimated, truth)| estimated.1 - truth.1));
+        let differentiated = rms(moving.map(|idx| {
+            (run.raw[idx].unwrap() - run.raw[idx - 1].unwrap()) / DT - run.truth[idx].1
+        }));
+        assert!(velocity < 0.05, "{velocity}");
+        assert!(
+            velocity < differentiated / 20.0,
+            "{velocity} vs {differentiated}"
+        );
+    }
+
+    #[test]
+    fn coasts_through_dropouts() {
+        let dropout = 3.0..4.0;
+        let run = run(dive, 8.0, |t| dropout.contains(&t));
+
+        let (start, end) = (300, 400);
+
+        // Variance only grows without pressure
+        for pair in run.estimated[start..end].windows(2) {
+            assert!(pair[1].2 > pair[0].2);
+        }
+        let settled = run.estimated[start - 1].2;
+        assert!(run.estimated[end - 1].2 > settled * 10.0);
+
+        // Still close on the IMU alone
+        let error = (run.estimated[end - 1].0 - run.truth[end - 1].0).abs();
+        assert!(error < 0.05, "{error}");
+
+        // And back to normal soon after readings return
+        let recovered = end + 50;
+        let error = (run.estimated[recovered].0 - run.truth[recovered].0).abs();
+        assert!(error < 0.02, "{error}");
+        assert!(run.estimated[recovered].2 < settled * 2.0);
+    }
+
+    #[test]
+    fn waits_for_pressure() {
+        let mut filter = DepthFilter::new(DepthEstimateConfig::default());
+
+        filter.predict(1.0, DT);
+        assert_eq!(filter.estimate(), None);
+
+        filter.correct(2.0);
+        assert_eq!(filter.depth(), Some(2.0));
+        assert_eq!(filter.velocity(), Some(0.0));
+    }
+
+    #[test]
+    fn removes_gravity() {
+        let close = |a: f32, b: f32| assert!((a - b).abs() < 1e-4, "{a} != {b}");
+
+        // Level and still
+        close(vertical_accel(Vec3::Z, Quat::IDENTITY), 0.0);
+        // Pushed up at 0.5 g
+        close(vertical_accel(Vec3::Z * 1.5, Quat::IDENTITY), -4.903325);
+        // Pitched nose up, gravity reads along the body's forward axis
+        let pitched = Quat::from_rotation_x(FRAC_PI_2);
+        close(vertical_accel(pitched.inverse() * Vec3::Z, pitched), 0.0);
+        // Free falling
+        close(vertical_accel(Vec3::ZERO, Quat::IDENTITY), super::GRAVITY);
+    }
+}
@@ -1,21 +1,38 @@
 use anyhow::Context;
 use bevy::{app::AppExit, prelude::*};
-use common::{components::Leak, error};
+use common::{components::Leak, error, shutdown::AppShutdownSet};
 use crossbeam::channel::Receiver;
 use rppal::gpio::{Gpio, InputPin, Level, Trigger};
 
-use crate::plugins::core::robot::LocalRobot;
+use crate::plugins::core::{
+    arming::{AppPreArmCheckExt, CheckResult, CheckSeverity},
+    robot::{LocalRobot, LocalRobotMarker},
+};
 
 pub struct LeakPlugin;
 
 impl Plugin for LeakPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, setup_leak_interupt.pipe(error::handle_errors));
+        app.add_pre_arm_check("Leak", CheckSeverity::Critical, no_leak);
         app.add_systems(
             PreUpdate,
             read_new_data.run_if(resource_exists::<LeakChannels>),
         );
-        app.add_systems(Last, shutdown.run_if(resource_exists::<LeakChannels>));
+        app.add_systems(
+            Last,
+            shutdown
+                .in_set(AppShutdownSet::Threads)
+                .run_if(resource_exists::<LeakChannels>),
+        );
+    }
+}
+
+fn no_leak(robot: Query<&Leak, With<LocalRobotMarker>>) -> CheckResult {
+    match robot.get_single() {
+        Ok(Leak(true)) => Err("Leak detected".to_owned()),
+        // Without a leak sensor there is nothing to check, its setup error was already reported
+        _ => Ok(()),
     }
 }
 
@@ -68,6 +85,7 @@ fn read_new_data(mut cmds: Commands, channels: Res<LeakChannels>, robot: Res<Loc
 
 fn shutdown(mut channels: ResMut<LeakChannels>, mut exit: EventReader<AppExit>) {
     for _event in exit.read() {
+        // Stops and joins rppal's interrupt thread
         let _ = channels.1.clear_async_interrupt();
     }
 }
@@ -0,0 +1,179 @@
+//! Splits the single measured battery current between the motors
+//!
+//! Each motor's share is weighted by what the motor data table expects it to draw, then scaled so
+//! the estimates add up to the measured current minus what the electronics draw.
+
+use bevy::prelude::*;
+use common::{
+    components::{CurrentDraw, RobotId},
+    ecs_sync::NetId,
+};
+
+use crate::{
+    config::RobotConfig,
+    plugins::{actuators::thruster::ExpectedCurrent, core::robot::LocalRobotMarker},
+};
+
+pub struct MotorCurrentPlugin;
+
+impl Plugin for MotorCurrentPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CurrentCorrection>()
+            .add_systems(PostUpdate, estimate_currents);
+    }
+}
+
+/// Below this the expected motor current is too small to learn a correction from
+const MIN_CORRECTION_CURRENT: f32 = 1.0;
+const CORRECTION_SMOOTHING: f32 = 0.05;
+const CORRECTION_RANGE: (f32, f32) = (0.5, 2.0);
+
+/// Ratio of the estimated motor current to what the motor data table expects
+///
+/// Stays at 1 unless `clamp_with_estimates` is enabled
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct CurrentCorrection(pub f32);
+
+impl Default for CurrentCorrection {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+/// Scales the expected per motor currents so they add up to `measured - baseline`
+///
+/// When the motors are idle or the measurement is below the baseline, all of the current is
+/// attributed to the electronics and every estimate is zero
+pub fn estimate_motor_currents(expected: &[f32], measured: f32, baseline: f32, out: &mut Vec<f32>) {
+    out.clear();
+
+    let available = measured - baseline;
+    let expected_total: f32 = expected.iter().map(|it| it.abs()).sum();
+
+    if available <= 0.0 || expected_total <= f32::EPSILON {
+        out.resize(expected.len(), 0.0);
+        return;
+    }
+
+    let scale = available / expected_total;
+    out.extend(expected.iter().map(|it| it.abs() * scale));
+}
+
+/// How far off the motor data table is, if enough current is flowing to tell
+pub fn correction_factor(expected: &[f32], measured: f32, baseline: f32) -> Option<f32> {
+    let expected_total: f32 = expected.iter().map(|it| it.abs()).sum();
+
+    if expected_total < MIN_CORRECTION_CURRENT {
+        return None;
+    }
+
+    let available = (measured - baseline).max(0.0);
+    Some((available / expected_total).clamp(CORRECTION_RANGE.0, CORRECTION_RANGE.1))
+}
+
+fn estimate_currents(
+    mut cmds: Commands,
+    mut expected: Local<Vec<f32>>,
+    mut estimates: Local<Vec<f32>>,
+    mut entities: Local<Vec<Entity>>,
+
+    (config, mut correction): (Res<RobotConfig>, ResMut<CurrentCorrection>),
+    robot: Query<(&NetId, Option<&CurrentDraw>), With<LocalRobotMarker>>,
+    motors: Query<(Entity, &ExpectedCurrent, &RobotId)>,
+) {
+    let Ok((&net_id, measured)) = robot.get_single() else {
+        return;
+    };
+
+    expected.clear();
+    entities.clear();
+    for (entity, ExpectedCurrent(current), &RobotId(robot_net_id)) in &motors {
+        if robot_net_id == net_id {
+            expected.push(current.0);
+            entities.push(entity);
+        }
+    }
+
+    let baseline = config.current_estimation.electronics_baseline;
+
+    match measured {
+        Some(&CurrentDraw(measured)) => {
+            estimate_motor_currents(&expected, measured.0, baseline, &mut estimates);
+
+            if config.current_estimation.clamp_with_estimates {
+                if let Some(factor) = correction_factor(&expected, measured.0, baseline) {
+                    correction.0 += (factor - correction.0) * CORRECTION_SMOOTHING;
+                }
+            }
+        }
+        None => {
+            // No current sensor, the table is the best we have
+            estimates.clear();
+            estimates.extend_from_slice(&expected);
+        }
+    }
+
+    for (&entity, &estimate) in entities.iter().zip(estimates.iter()) {
+        cmds.entity(entity).insert(CurrentDraw(estimate.into()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{correction_factor, estimate_motor_currents};
+
+    fn estimate(expected: &[f32], measured: f32, baseline: f32) -> Vec<f32> {
+        let mut out = Vec::new();
+        estimate_motor_currents(expected, measured, baseline, &mut out);
+        out
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{a} != {b}");
+    }
+
+    #[test]
+    fn scales_to_measured_current() {
+        let estimates = estimate(&[2.0, 4.0, 0.0, 2.0], 17.5, 1.5);
+
+        assert_eq!(estimates.len(), 4);
+        assert_close(estimates[0], 4.0);
+        assert_close(estimates[1], 8.0);
+        assert_close(estimates[2], 0.0);
+        assert_close(estimates[3], 4.0);
+    }
+
+    #[test]
+    fn estimates_sum_to_measured_minus_baseline() {
+        let expected = [0.3, 5.2, 1.7, 0.05, 9.1, 2.2, 0.0, 4.4];
+
+        for (measured, baseline) in [(5.0, 1.5), (12.3, 0.8), (40.0, 2.0), (2.0, 0.0)] {
+            let sum: f32 = estimate(&expected, measured, baseline).iter().sum();
+            assert_close(sum, measured - baseline);
+        }
+    }
+
+    #[test]
+    fn idle_motors_attribute_everything_to_baseline() {
+        assert_eq!(estimate(&[0.0; 4], 3.0, 1.5), [0.0; 4]);
+        assert_eq!(estimate(&[], 3.0, 1.5), Vec::<f32>::new());
+    }
+
+    #[test]
+    fn measured_below_baseline() {
+        assert_eq!(estimate(&[2.0, 3.0], 1.0, 1.5), [0.0; 2]);
+        assert_eq!(estimate(&[2.0, 3.0], 1.5, 1.5), [0.0; 2]);
+        assert_eq!(estimate(&[2.0, 3.0], -0.2, 1.5), [0.0; 2]);
+    }
+
+    #[test]
+    fn correction_needs_current_flowing() {
+        assert_eq!(correction_factor(&[0.1, 0.2], 5.0, 1.5), None);
+        assert_close(correction_factor(&[2.0, 2.0], 9.5, 1.5).unwrap(), 2.0);
+        assert_close(correction_factor(&[2.0, 2.0], 4.5, 1.5).unwrap(), 0.75);
+
+        // Bad readings can't push the budget arbitrarily far
+        assert_close(correction_factor(&[2.0, 2.0], 100.0, 1.5).unwrap(), 2.0);
+        assert_close(correction_factor(&[2.0, 2.0], 0.0, 1.5).unwrap(), 0.5);
+    }
+}
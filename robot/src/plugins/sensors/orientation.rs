@@ -7,9 +7,11 @@ use ahrs::{Ahrs, Madgwick};
 use anyhow::{anyhow, Context};
 use bevy::{app::AppExit, prelude::*};
 use common::{
-    components::{Inertial, Magnetic, Orientation},
+    components::{Inertial, Magnetic, Orientation, RobotId},
+    ecs_sync::scoped::ScopedEvents,
     error::{self, ErrorEvent, Errors},
-    events::ResetYaw,
+    events::{AckedCommand, CommandComplete, ResetYaw},
+    shutdown::{self, AppShutdownSet, SpawnWorkerExt, WorkerThread},
     types::hw::{InertialFrame, MagneticFrame},
 };
 use crossbeam::channel::{self, Receiver, Sender};
@@ -17,15 +19,25 @@ use nalgebra::Vector3;
 use tracing::{span, Level};
 
 use crate::{
-    peripheral::{icm20602::Icm20602, mmc5983::Mcc5983},
-    plugins::core::robot::{LocalRobot, LocalRobotMarker},
+    peripheral::{
+        hal::pi::PiHal,
+        icm20602::{self, Icm20602},
+        mmc5983::{self, Mcc5983},
+    },
+    plugins::core::{
+        arming::{AppPreArmCheckExt, CheckResult, CheckSeverity},
+        robot::{LocalRobot, LocalRobotMarker},
+    },
 };
 
+use super::{ImuHealth, IMU_STALE_AFTER};
+
 pub struct OrientationPlugin;
 
 impl Plugin for OrientationPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(MadgwickFilter(Madgwick::new(1.0 / 1000.0, 0.041)));
+        app.insert_resource(MadgwickFilter(Madgwick::new(1.0 / 1000.0, 0.041)))
+            .init_resource::<ImuHealth>();
 
         app.add_systems(Startup, start_inertial_thread.pipe(error::handle_errors));
         app.add_systems(
@@ -35,14 +47,21 @@ impl Plugin for OrientationPlugin {
                 read_new_data.run_if(resource_exists::<InertialChannels>),
             ),
         );
-        app.add_systems(Last, shutdown.run_if(resource_exists::<InertialChannels>));
+        app.add_systems(
+            Last,
+            shutdown
+                .in_set(AppShutdownSet::Threads)
+                .run_if(resource_exists::<InertialChannels>),
+        );
+        app.add_pre_arm_check("IMU", CheckSeverity::Advisory, imu_fresh);
     }
 }
 
 #[derive(Resource)]
 struct InertialChannels(
-    Receiver<([InertialFrame; 10], [MagneticFrame; 1])>,
+    Receiver<([InertialFrame; 10], [MagneticFrame; 1], bool)>,
     Sender<()>,
+    Option<WorkerThread<()>>,
 );
 
 #[derive(Resource)]
@@ -52,17 +71,25 @@ fn start_inertial_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Res
     let (tx_data, rx_data) = channel::bounded(5);
     let (tx_exit, rx_exit) = channel::bounded(1);
 
-    let mut imu = Icm20602::new(Icm20602::SPI_BUS, Icm20602::SPI_SELECT, Icm20602::SPI_CLOCK)
-        .context("Inerital Sensor (ICM20602)")?;
-    let mut mag = Mcc5983::new(Mcc5983::SPI_BUS, Mcc5983::SPI_SELECT, Mcc5983::SPI_CLOCK)
-        .context("Magnmetic Sensor (MCC5983)")?;
-
-    cmds.insert_resource(InertialChannels(rx_data, tx_exit));
+    let mut imu = Icm20602::new(
+        &PiHal,
+        icm20602::SPI_BUS,
+        icm20602::SPI_SELECT,
+        icm20602::SPI_CLOCK,
+    )
+    .context("Inerital Sensor (ICM20602)")?;
+    let mut mag = Mcc5983::new(
+        &PiHal,
+        mmc5983::SPI_BUS,
+        mmc5983::SPI_SELECT,
+        mmc5983::SPI_CLOCK,
+    )
+    .context("Magnmetic Sensor (MCC5983)")?;
 
     let errors = errors.0.clone();
-    thread::Builder::new()
+    let imu_thread = thread::Builder::new()
         .name("IMU Thread".to_owned())
-        .spawn(move || {
+        .spawn_worker(move || {
             let _span = span!(Level::INFO, "IMU sensor thread").entered();
 
             let interval = Duration::from_secs_f32(1.0 / 1000.0);
@@ -79,12 +106,15 @@ fn start_inertial_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Res
             let mut deadline = Instant::now();
 
             let mut first_run = true;
+            let mut read_failed = false;
 
             loop {
                 let span = span!(Level::INFO, "IMU sensor cycle").entered();
 
                 if counter == 0 && !first_run {
-                    let res = tx_data.send((inertial_buffer, mag_buffer));
+                    let res = tx_data.send((inertial_buffer, mag_buffer, read_failed));
+                    read_failed = false;
+
                     if res.is_err() {
                         // Peer disconnected
                         return;
@@ -99,6 +129,7 @@ fn start_inertial_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Res
                             inertial_buffer[counter / inertial_divisor] = frame;
                         }
                         Err(err) => {
+                            read_failed = true;
                             let _ = errors.send(err);
                         }
                     }
@@ -134,6 +165,8 @@ fn start_inertial_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Res
         })
         .context("Spawn thread")?;
 
+    cmds.insert_resource(InertialChannels(rx_data, tx_exit, Some(imu_thread)));
+
     Ok(())
 }
 
@@ -143,8 +176,13 @@ fn read_new_data(
     mut madgwick_filter: ResMut<MadgwickFilter>,
     robot: Res<LocalRobot>,
     mut errors: EventWriter<ErrorEvent>,
+    mut health: ResMut<ImuHealth>,
+    time: Res<Time<Real>>,
 ) {
-    for (inertial, magnetic) in channels.0.try_iter() {
+    for (inertial, magnetic, read_failed) in channels.0.try_iter() {
+        health.last_frame = Some(time.elapsed());
+        health.read_failed = read_failed;
+
         // We currently ignore mag updates as the compass is not calibrated
         // TODO(high): Calibrate the compass
         for inertial in inertial {
@@ -159,9 +197,11 @@ fn read_new_data(
         }
 
         let quat: glam::Quat = madgwick_filter.0.quat.into();
-        let orientation = Orientation(quat);
-
         let inertial = inertial.last().unwrap();
+        let orientation = Orientation {
+            rotation: quat,
+            capture_time: inertial.capture_time,
+        };
         let inertial = Inertial(*inertial);
 
         let magnetic = magnetic.last().unwrap();
@@ -172,20 +212,69 @@ fn read_new_data(
     }
 }
 
+fn imu_fresh(health: Res<ImuHealth>, time: Res<Time<Real>>) -> CheckResult {
+    imu_status(&health, time.elapsed())
+}
+
+fn imu_status(health: &ImuHealth, now: Duration) -> CheckResult {
+    let Some(last_frame) = health.last_frame else {
+        return Err("No IMU data".to_owned());
+    };
+
+    if health.read_failed {
+        return Err("IMU reads are failing".to_owned());
+    }
+
+    let age = now.saturating_sub(last_frame);
+    if age > IMU_STALE_AFTER {
+        return Err(format!("IMU data is {:.1}s old", age.as_secs_f32()));
+    }
+
+    Ok(())
+}
+
 fn reset_yaw_handler(
-    mut events: EventReader<ResetYaw>,
+    mut events: ScopedEvents<ResetYaw>,
     mut madgwick_filter: ResMut<MadgwickFilter>,
+    health: Res<ImuHealth>,
+    time: Res<Time<Real>>,
+    robot: Res<LocalRobot>,
+    mut completions: EventWriter<CommandComplete>,
 ) {
     for _ in events.read() {
         info!("Resetting Yaw");
 
-        madgwick_filter.0.quat.as_mut_unchecked().vector_mut()[2] = 0.0;
-        madgwick_filter.0.quat.renormalize();
+        // Without fresh data the reset would never make it into the published orientation
+        let (ok, detail) = match imu_status(&health, time.elapsed()) {
+            Ok(()) => {
+                madgwick_filter.0.quat.as_mut_unchecked().vector_mut()[2] = 0.0;
+                madgwick_filter.0.quat.renormalize();
+
+                (true, "Yaw reset".to_owned())
+            }
+            Err(err) => {
+                warn!("Could not reset yaw: {err}");
+                (false, err)
+            }
+        };
+
+        completions.send(CommandComplete {
+            robot: RobotId(robot.net_id),
+            command: AckedCommand::ResetYaw,
+            ok,
+            detail,
+        });
     }
 }
 
-fn shutdown(channels: Res<InertialChannels>, mut exit: EventReader<AppExit>) {
+fn shutdown(mut channels: ResMut<InertialChannels>, mut exit: EventReader<AppExit>) {
     for _event in exit.read() {
         let _ = channels.1.send(());
+
+        if let Some(imu_thread) = channels.2.take() {
+            if !shutdown::join_timeout(imu_thread, shutdown::JOIN_TIMEOUT) {
+                warn!("IMU thread did not shut down in time");
+            }
+        }
     }
 }
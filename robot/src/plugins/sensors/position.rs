@@ -0,0 +1,261 @@
+//! Dead reckons the robot's horizontal position from the thrust it applies and its heading
+//!
+//! Nothing here measures position. Speed comes from a steady state drag model, so currents,
+//! tether drag and model error all add up as drift. The published confidence only says how long
+//! it has been since the last reset.
+
+use bevy::prelude::*;
+use common::{
+    components::{ActualMovement, Orientation, PositionEstimate},
+    ecs_sync::scoped::ScopedEvents,
+    events::ResetPosition,
+};
+use glam::{Quat, Vec2, Vec3, Vec3A};
+
+use crate::{
+    config::{PositionEstimateConfig, RobotConfig},
+    plugins::core::robot::{LocalRobot, LocalRobotMarker},
+};
+
+pub struct PositionPlugin;
+
+impl Plugin for PositionPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_estimator).add_systems(
+            Update,
+            estimate_position.run_if(resource_exists::<DeadReckoning>),
+        );
+    }
+}
+
+/// Smallest move in meters that gets replicated
+const PUBLISH_DISTANCE: f32 = 0.01;
+/// Smallest change in confidence that gets replicated
+const PUBLISH_CONFIDENCE: f32 = 0.01;
+
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct DeadReckoning {
+    config: PositionEstimateConfig,
+
+    /// Meters from the origin, +Y is north
+    xy: Vec2,
+    /// Seconds since the last reset
+    age: f32,
+    published: Option<PositionEstimate>,
+}
+
+impl DeadReckoning {
+    pub fn new(config: PositionEstimateConfig) -> Self {
+        Self {
+            config,
+            xy: Vec2::ZERO,
+            age: 0.0,
+            published: None,
+        }
+    }
+
+    /// Moves the estimate by `dt` seconds at the speed `force` settles at
+    ///
+    /// `force` is in the robot's frame, `orientation` takes it to the world frame
+    pub fn step(&mut self, force: Vec3A, orientation: Quat, dt: f32) -> Vec2 {
+        let velocity = world_velocity(body_velocity(force, &self.config), orientation);
+
+        self.xy += velocity * dt;
+        self.age += dt;
+
+        self.xy
+    }
+
+    /// Makes the current position the origin and restores full confidence
+    pub fn reset(&mut self) {
+        self.xy = Vec2::ZERO;
+        self.age = 0.0;
+        self.published = None;
+    }
+
+    pub fn xy(&self) -> Vec2 {
+        self.xy
+    }
+
+    /// Halves every `confidence_half_life` seconds since the last reset
+    pub fn confidence(&self) -> f32 {
+        0.5f32.powf(self.age / self.config.confidence_half_life)
+    }
+
+    pub fn estimate(&self) -> PositionEstimate {
+        PositionEstimate {
+            xy: self.xy,
+            confidence_decay: self.confidence(),
+        }
+    }
+
+    /// Returns the estimate if it changed enough to be worth replicating
+    fn publish(&mut self) -> Option<PositionEstimate> {
+        let estimate = self.estimate();
+
+        let changed = !self.published.is_some_and(|published| {
+            published.xy.distance(estimate.xy) < PUBLISH_DISTANCE
+                && (published.confidence_decay - estimate.confidence_decay).abs()
+                    < PUBLISH_CONFIDENCE
+        });
+
+        if changed {
+            self.published = Some(estimate);
+            Some(estimate)
+        } else {
+            None
+        }
+    }
+}
+
+/// Speed in the robot's frame that `force` settles at against quadratic drag, vertical is ignored
+pub fn body_velocity(force: Vec3A, config: &PositionEstimateConfig) -> Vec2 {
+    let settle = |force: f32, drag: f32| force.signum() * (force.abs() / drag).sqrt();
+
+    Vec2::new(
+        settle(force.x, config.lateral_drag),
+        settle(force.y, config.forward_drag),
+    )
+}
+
+/// Takes a horizontal velocity in the robot's frame to the world's horizontal plane
+///
+/// While pitched or rolled only the horizontal part of the motion counts
+pub fn world_velocity(body: Vec2, orientation: Quat) -> Vec2 {
+    (orientation * Vec3::new(body.x, body.y, 0.0)).truncate()
+}
+
+fn setup_estimator(mut cmds: Commands, config: Res<RobotConfig>) {
+    cmds.insert_resource(DeadReckoning::new(config.position_estimate));
+}
+
+fn estimate_position(
+    mut cmds: Commands,
+    robot: Res<LocalRobot>,
+    mut estimator: ResMut<DeadReckoning>,
+    state: Query<(&ActualMovement, &Orientation), With<LocalRobotMarker>>,
+    time: Res<Time<Real>>,
+    mut resets: ScopedEvents<ResetPosition>,
+) {
+    if !resets.read().is_empty() {
+        info!("Reset position estimate");
+        estimator.reset();
+    }
+
+    if let Ok((movement, orientation)) = state.get_single() {
+        estimator.step(movement.0.force, orientation.rotation, time.delta_seconds());
+    }
+
+    if let Some(estimate) = estimator.publish() {
+        cmds.entity(robot.entity).insert(estimate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use glam::{vec2, vec3a, Quat, Vec2};
+
+    use crate::config::PositionEstimateConfig;
+
+    use super::{body_velocity, world_velocity, DeadReckoning};
+
+    const CONFIG: PositionEstimateConfig = PositionEstimateConfig {
+        forward_drag: 40.0,
+        lateral_drag: 10.0,
+        confidence_half_life: 10.0,
+    };
+
+    fn assert_close(a: Vec2, b: Vec2) {
+        assert!(a.abs_diff_eq(b, 1e-4), "{a} != {b}");
+    }
+
+    #[test]
+    fn velocity_settles_against_drag() {
+        // 40 N forward against 40 N/(m/s)^2 settles at 1 m/s, 2.5 N right against 10 at 0.5 m/s
+        assert_close(
+            body_velocity(vec3a(2.5, 40.0, 0.0), &CONFIG),
+            vec2(0.5, 1.0),
+        );
+        assert_close(
+            body_velocity(vec3a(-2.5, -160.0, 0.0), &CONFIG),
+            vec2(-0.5, -2.0),
+        );
+
+        // Vertical thrust doesn't move the robot horizontally
+        assert_close(body_velocity(vec3a(0.0, 0.0, 50.0), &CONFIG), Vec2::ZERO);
+    }
+
+    #[test]
+    fn heading_rotates_into_the_world() {
+        let forward = vec2(0.0, 1.0);
+
+        assert_close(world_velocity(forward, Quat::IDENTITY), forward);
+        // Yawed 90 degrees counter clockwise, forward is west
+        assert_close(
+            world_velocity(forward, Quat::from_rotation_z(FRAC_PI_2)),
+            vec2(-1.0, 0.0),
+        );
+        // Pitched straight up, nothing is left horizontally
+        assert_close(
+            world_velocity(forward, Quat::from_rotation_x(FRAC_PI_2)),
+            Vec2::ZERO,
+        );
+    }
+
+    #[test]
+    fn integrates_a_square() {
+        let mut estimator = DeadReckoning::new(CONFIG);
+
+        // 1 m/s forward for 2 s on each heading, turning 90 degrees counter clockwise each time
+        for side in 0..4 {
+            let heading = Quat::from_rotation_z(FRAC_PI_2 * side as f32);
+            for _ in 0..200 {
+                estimator.step(vec3a(0.0, 40.0, 0.0), heading, 0.01);
+            }
+
+            let expected = [vec2(0.0, 2.0), vec2(-2.0, 2.0), vec2(-2.0, 0.0), Vec2::ZERO];
+            assert!(estimator.xy().abs_diff_eq(expected[side], 1e-3));
+        }
+    }
+
+    #[test]
+    fn confidence_decays_and_resets() {
+        let mut estimator = DeadReckoning::new(CONFIG);
+        assert_eq!(estimator.confidence(), 1.0);
+
+        for _ in 0..100 {
+            estimator.step(vec3a(0.0, 10.0, 0.0), Quat::IDENTITY, 0.1);
+        }
+        assert!((estimator.confidence() - 0.5).abs() < 1e-4);
+
+        for _ in 0..100 {
+            estimator.step(vec3a(0.0, 10.0, 0.0), Quat::IDENTITY, 0.1);
+        }
+        assert!((estimator.confidence() - 0.25).abs() < 1e-4);
+        assert!(estimator.xy().y > 9.0);
+
+        estimator.reset();
+        assert_eq!(estimator.estimate().xy, Vec2::ZERO);
+        assert_eq!(estimator.estimate().confidence_decay, 1.0);
+    }
+
+    #[test]
+    fn publishes_only_meaningful_changes() {
+        let mut estimator = DeadReckoning::new(CONFIG);
+        assert!(estimator.publish().is_some());
+
+        // Sitting still barely changes the confidence
+        estimator.step(vec3a(0.0, 0.0, 0.0), Quat::IDENTITY, 0.01);
+        assert!(estimator.publish().is_none());
+
+        // 5 cm is worth sending
+        estimator.step(vec3a(0.0, 40.0, 0.0), Quat::IDENTITY, 0.05);
+        assert!(estimator.publish().is_some());
+
+        // A reset always is
+        estimator.reset();
+        assert!(estimator.publish().is_some());
+    }
+}
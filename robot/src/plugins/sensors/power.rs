@@ -8,12 +8,16 @@ use bevy::{app::AppExit, prelude::*};
 use common::{
     components::{CurrentDraw, MeasuredVoltage},
     error::{self, Errors},
+    shutdown::{self, AppShutdownSet, SpawnWorkerExt, WorkerThread},
 };
 use crossbeam::channel::{self, Receiver, Sender};
 use tracing::{span, Level};
 
 use crate::{
-    peripheral::ads1115::{Ads1115, AnalogChannel},
+    peripheral::{
+        ads1115::{self, Ads1115, AnalogChannel},
+        hal::pi::PiHal,
+    },
     plugins::core::robot::LocalRobot,
 };
 
@@ -26,12 +30,17 @@ impl Plugin for PowerPlugin {
             PreUpdate,
             read_new_data.run_if(resource_exists::<PowerChannels>),
         );
-        app.add_systems(Last, shutdown.run_if(resource_exists::<PowerChannels>));
+        app.add_systems(
+            Last,
+            shutdown
+                .in_set(AppShutdownSet::Threads)
+                .run_if(resource_exists::<PowerChannels>),
+        );
     }
 }
 
 #[derive(Resource)]
-struct PowerChannels(Receiver<PowerEvent>, Sender<()>);
+struct PowerChannels(Receiver<PowerEvent>, Sender<()>, Option<WorkerThread<()>>);
 
 enum PowerEvent {
     Voltage(f32),
@@ -42,15 +51,13 @@ fn start_power_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Result
     let (tx_data, rx_data) = channel::bounded(5);
     let (tx_exit, rx_exit) = channel::bounded(1);
 
-    let mut adc = Ads1115::new(Ads1115::I2C_BUS, Ads1115::I2C_ADDRESS)
+    let mut adc = Ads1115::new(&PiHal, ads1115::I2C_BUS, ads1115::I2C_ADDRESS)
         .context("Analog to Digital converter (Ads1115)")?;
 
-    cmds.insert_resource(PowerChannels(rx_data, tx_exit));
-
     let errors = errors.0.clone();
-    thread::Builder::new()
+    let power_thread = thread::Builder::new()
         .name("Power Thread".to_owned())
-        .spawn(move || {
+        .spawn_worker(move || {
             let _span = span!(Level::INFO, "Power sense thread").entered();
 
             let interval = Duration::from_secs_f64(1.0 / 100.0);
@@ -124,6 +131,8 @@ fn start_power_thread(mut cmds: Commands, errors: Res<Errors>) -> anyhow::Result
         })
         .context("Start thread")?;
 
+    cmds.insert_resource(PowerChannels(rx_data, tx_exit, Some(power_thread)));
+
     Ok(())
 }
 
@@ -142,8 +151,14 @@ fn read_new_data(mut cmds: Commands, channels: Res<PowerChannels>, robot: Res<Lo
     }
 }
 
-fn shutdown(channels: Res<PowerChannels>, mut exit: EventReader<AppExit>) {
+fn shutdown(mut channels: ResMut<PowerChannels>, mut exit: EventReader<AppExit>) {
     for _event in exit.read() {
         let _ = channels.1.send(());
+
+        if let Some(power_thread) = channels.2.take() {
+            if !shutdown::join_timeout(power_thread, shutdown::JOIN_TIMEOUT) {
+                warn!("Power thread did not shut down in time");
+            }
+        }
     }
 }
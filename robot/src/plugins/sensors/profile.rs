@@ -0,0 +1,326 @@
+//! Records water temperature against depth for finding the thermocline
+//!
+//! A sample is taken every time the robot crosses a multiple of the configured resolution, so
+//! hovering at one depth doesn't fill the profile with duplicates.
+
+use bevy::prelude::*;
+use common::{
+    components::{Depth, DepthTemperatureProfile},
+    ecs_sync::scoped::ScopedEvents,
+    events::ClearProfile,
+    types::units::{Celsius, Meters},
+};
+
+use crate::{
+    config::RobotConfig,
+    plugins::core::robot::{LocalRobot, LocalRobotMarker},
+};
+
+pub struct ProfilePlugin;
+
+impl Plugin for ProfilePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup_sampler).add_systems(
+            Update,
+            sample_profile.run_if(resource_exists::<ProfileSampler>),
+        );
+    }
+}
+
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct ProfileSampler {
+    /// Meters between samples
+    resolution: f32,
+    max_samples: usize,
+
+    /// Last raw reading as (depth, temperature)
+    last: Option<(f32, f32)>,
+    /// Resolution step the newest sample was taken at
+    last_step: Option<i64>,
+    samples: Vec<(Meters, Celsius)>,
+    changed: bool,
+}
+
+impl ProfileSampler {
+    pub fn new(resolution: f32, max_samples: usize) -> Self {
+        Self {
+            resolution,
+            max_samples,
+            last: None,
+            last_step: None,
+            samples: Vec::new(),
+            changed: false,
+        }
+    }
+
+    /// Records a sample for every resolution step crossed since the last reading
+    ///
+    /// Temperatures between two readings are interpolated, so moving faster than one step per
+    /// reading leaves no gaps. Crossing the same step again replaces its sample instead of adding
+    /// another. Only the newest `max_samples` steps are walked, the rest would be dropped anyways,
+    /// so a glitched reading far from the last one can't stall the robot.
+    pub fn sample(&mut self, depth: Meters, temperature: Celsius) {
+        let (depth, temperature) = (depth.0, temperature.0);
+
+        if !depth.is_finite() || !temperature.is_finite() {
+            return;
+        }
+
+        let Some((last_depth, last_temperature)) = self.last.replace((depth, temperature)) else {
+            return;
+        };
+
+        if depth == last_depth {
+            return;
+        }
+
+        let resolution = self.resolution;
+        let keep = i64::try_from(self.max_samples).unwrap_or(i64::MAX);
+        let (first, last, direction) = if depth > last_depth {
+            // Descending, last_depth < step <= depth
+            let last = (depth / resolution).floor() as i64;
+            let first = ((last_depth / resolution).floor() as i64)
+                .saturating_add(1)
+                .max(last.saturating_sub(keep - 1));
+            (first, last, 1)
+        } else {
+            // Ascending, depth <= step < last_depth
+            let last = (depth / resolution).ceil() as i64;
+            let first = ((last_depth / resolution).ceil() as i64)
+                .saturating_sub(1)
+                .min(last.saturating_add(keep - 1));
+            (first, last, -1)
+        };
+        let count = ((last - first) * direction).saturating_add(1).max(0);
+
+        for step in (0..count).map(|it| first + it * direction) {
+            let step_depth = step as f32 * resolution;
+            let progress = (step_depth - last_depth) / (depth - last_depth);
+            let step_temperature = last_temperature + (temperature - last_temperature) * progress;
+
+            self.record(step, Meters(step_depth), Celsius(step_temperature));
+        }
+    }
+
+    fn record(&mut self, step: i64, depth: Meters, temperature: Celsius) {
+        if self.last_step == Some(step) {
+            if let Some(last) = self.samples.last_mut() {
+                last.1 = temperature;
+                self.changed = true;
+                return;
+            }
+        }
+
+        self.samples.push((depth, temperature));
+        if self.samples.len() > self.max_samples {
+            let excess = self.samples.len() - self.max_samples;
+            self.samples.drain(..excess);
+        }
+
+        self.last_step = Some(step);
+        self.changed = true;
+    }
+
+    /// Drops every sample, the next one is taken at the next step crossed
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.last_step = None;
+        self.changed = true;
+    }
+
+    pub fn samples(&self) -> &[(Meters, Celsius)] {
+        &self.samples
+    }
+
+    /// Returns the samples if they changed since the last call
+    fn publish(&mut self) -> Option<&[(Meters, Celsius)]> {
+        if self.changed {
+            self.changed = false;
+            Some(&self.samples)
+        } else {
+            None
+        }
+    }
+}
+
+fn setup_sampler(mut cmds: Commands, config: Res<RobotConfig>) {
+    let config = config.depth_profile;
+
+    cmds.insert_resource(ProfileSampler::new(config.resolution, config.max_samples));
+}
+
+fn sample_profile(
+    mut cmds: Commands,
+    robot: Res<LocalRobot>,
+    mut sampler: ResMut<ProfileSampler>,
+    depth: Query<Ref<Depth>, With<LocalRobotMarker>>,
+    mut clears: ScopedEvents<ClearProfile>,
+) {
+    if !clears.read().is_empty() {
+        info!("Cleared depth profile");
+        sampler.clear();
+    }
+
+    if let Ok(depth) = depth.get_single() {
+        if depth.is_changed() {
+            sampler.sample(depth.0.depth, depth.0.temperature);
+        }
+    }
+
+    if let Some(samples) = sampler.publish() {
+        cmds.entity(robot.entity)
+            .insert(DepthTemperatureProfile(samples.to_vec()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::types::units::{Celsius, Meters};
+
+    use super::ProfileSampler;
+
+    fn run(sampler: &mut ProfileSampler, trace: &[(f32, f32)]) {
+        for &(depth, temperature) in trace {
+            sampler.sample(Meters(depth), Celsius(temperature));
+        }
+    }
+
+    fn depths(sampler: &ProfileSampler) -> Vec<f32> {
+        sampler
+            .samples()
+            .iter()
+            .map(|(depth, _)| (depth.0 * 100.0).round() / 100.0)
+            .collect()
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3, "{a} != {b}");
+    }
+
+    #[test]
+    fn samples_each_step_on_descent() {
+        let mut sampler = ProfileSampler::new(0.1, 100);
+
+        run(
+            &mut sampler,
+            &[
+                (0.0, 20.0),
+                (0.05, 20.0),
+                (0.12, 19.0),
+                (0.21, 18.0),
+                (0.31, 17.0),
+            ],
+        );
+
+        assert_eq!(depths(&sampler), [0.1, 0.2, 0.3]);
+        assert_close(sampler.samples()[2].1 .0, 17.1);
+    }
+
+    #[test]
+    fn hovering_dedupes() {
+        let mut sampler = ProfileSampler::new(0.1, 100);
+
+        // Sitting still
+        run(&mut sampler, &[(1.0, 10.0); 50]);
+        assert!(sampler.samples().is_empty());
+
+        // Bobbing across a step keeps only the latest reading at it
+        run(
+            &mut sampler,
+            &[
+                (1.04, 10.0),
+                (1.11, 9.0),
+                (1.09, 9.5),
+                (1.12, 9.2),
+                (1.08, 9.6),
+            ],
+        );
+        assert_eq!(depths(&sampler), [1.1]);
+        assert_close(sampler.samples()[0].1 .0, 9.4);
+    }
+
+    #[test]
+    fn fast_moves_interpolate() {
+        let mut sampler = ProfileSampler::new(0.1, 100);
+
+        run(&mut sampler, &[(0.0, 20.0), (0.55, 14.5)]);
+
+        assert_eq!(depths(&sampler), [0.1, 0.2, 0.3, 0.4, 0.5]);
+        for (idx, (_, temperature)) in sampler.samples().iter().enumerate() {
+            assert_close(temperature.0, 19.0 - idx as f32);
+        }
+    }
+
+    #[test]
+    fn ascent_is_sampled() {
+        let mut sampler = ProfileSampler::new(0.1, 100);
+
+        run(&mut sampler, &[(0.35, 10.0), (0.05, 13.0)]);
+
+        assert_eq!(depths(&sampler), [0.3, 0.2, 0.1]);
+        assert_close(sampler.samples()[0].1 .0, 10.5);
+        assert_close(sampler.samples()[2].1 .0, 12.5);
+    }
+
+    #[test]
+    fn turning_around_dedupes() {
+        let mut sampler = ProfileSampler::new(0.1, 100);
+
+        // Down past 0.2 and back up past it again
+        run(&mut sampler, &[(0.15, 10.0), (0.25, 9.0), (0.15, 11.0)]);
+
+        assert_eq!(depths(&sampler), [0.2]);
+        assert_close(sampler.samples()[0].1 .0, 10.0);
+    }
+
+    #[test]
+    fn bounded_and_clearable() {
+        let mut sampler = ProfileSampler::new(0.1, 3);
+
+        run(&mut sampler, &[(0.0, 20.0), (0.55, 14.5)]);
+        assert_eq!(depths(&sampler), [0.3, 0.4, 0.5]);
+
+        sampler.clear();
+        assert!(sampler.samples().is_empty());
+
+        // Samples resume from the last reading
+        run(&mut sampler, &[(0.61, 14.0)]);
+        assert_eq!(depths(&sampler), [0.6]);
+    }
+
+    #[test]
+    fn glitches_only_walk_kept_steps() {
+        let mut sampler = ProfileSampler::new(0.1, 4);
+
+        // Would be ten million steps if every one was walked
+        run(&mut sampler, &[(0.0, 20.0), (1e6, 20.0)]);
+        assert_eq!(sampler.samples().len(), 4);
+
+        run(&mut sampler, &[(0.05, 20.0)]);
+        assert_eq!(sampler.samples().len(), 4);
+        assert_eq!(depths(&sampler)[3], 0.1);
+
+        // Unusable readings are skipped entirely
+        run(
+            &mut sampler,
+            &[(f32::NAN, 20.0), (f32::INFINITY, 20.0), (0.25, f32::NAN)],
+        );
+        run(&mut sampler, &[(0.15, 19.0)]);
+        assert_eq!(depths(&sampler)[3], 0.1);
+    }
+
+    #[test]
+    fn publishes_only_changes() {
+        let mut sampler = ProfileSampler::new(0.1, 100);
+
+        run(&mut sampler, &[(0.0, 20.0), (0.05, 20.0)]);
+        assert!(sampler.publish().is_none());
+
+        run(&mut sampler, &[(0.15, 19.0)]);
+        assert_eq!(sampler.publish().map(<[_]>::len), Some(1));
+        assert!(sampler.publish().is_none());
+
+        sampler.clear();
+        assert_eq!(sampler.publish().map(<[_]>::len), Some(0));
+    }
+}
@@ -0,0 +1,215 @@
+//! Stands in for the robot's sensors when they aren't compiled in or the robot runs without
+//! hardware
+//!
+//! The robot slowly sways and bobs between the surface and a couple meters down through water
+//! that cools with depth, so everything downstream of the sensors has plausible data to work with.
+
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use common::{
+    clock,
+    components::{
+        CurrentDraw, Depth, DepthSettings, Inertial, Leak, Magnetic, MeasuredVoltage, Orientation,
+        RobotId,
+    },
+    ecs_sync::scoped::ScopedEvents,
+    events::{AckedCommand, CalibrateSeaLevel, CommandComplete, ResetYaw},
+    types::{
+        hw::{DepthFrame, InertialFrame, MagneticFrame},
+        units::{Celsius, Dps, GForce, Gauss, Mbar, Meters},
+    },
+};
+use glam::{EulerRot, Quat, Vec3};
+
+use crate::{
+    config::RobotConfig,
+    plugins::{
+        actuators::thruster::ExpectedCurrent,
+        core::robot::{LocalRobot, LocalRobotMarker},
+    },
+};
+
+use super::ImuHealth;
+
+pub struct SimSensorPlugin;
+
+impl Plugin for SimSensorPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ImuHealth>()
+            .add_systems(Startup, setup_sim)
+            .add_systems(PreUpdate, (simulate_imu, simulate_depth, simulate_power))
+            .add_systems(Update, (calibrate_sea_level, reset_yaw));
+    }
+}
+
+const ATMOSPHERE: Mbar = Mbar(1013.25);
+const FLUID_DENSITY: f32 = 1000.0;
+const GRAVITY: f32 = 9.80665;
+
+/// Seconds for one trip down and back up
+const DIVE_PERIOD: f32 = 60.0;
+const MIN_DEPTH: f32 = 0.2;
+const MAX_DEPTH: f32 = 2.0;
+
+const SURFACE_TEMPERATURE: f32 = 22.0;
+/// Degrees lost per meter of depth
+const TEMPERATURE_GRADIENT: f32 = 1.5;
+
+/// Seconds for one roll and pitch sway
+const SWAY_PERIOD: f32 = 8.0;
+/// Radians
+const SWAY_AMPLITUDE: f32 = 0.05;
+
+const BATTERY_VOLTAGE: f32 = 15.2;
+/// Volts lost per amp drawn
+const BATTERY_SAG: f32 = 0.02;
+
+fn setup_sim(mut cmds: Commands, robot: Res<LocalRobot>) {
+    info!("Simulating sensors");
+
+    cmds.entity(robot.entity).insert((
+        Leak(false),
+        DepthSettings {
+            sea_level: ATMOSPHERE,
+            fluid_density: FLUID_DENSITY,
+        },
+    ));
+}
+
+fn simulate_imu(
+    mut cmds: Commands,
+    robot: Res<LocalRobot>,
+    mut health: ResMut<ImuHealth>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed();
+    let phase = now.as_secs_f32() * TAU / SWAY_PERIOD;
+
+    let roll = SWAY_AMPLITUDE * phase.sin();
+    let pitch = SWAY_AMPLITUDE * phase.cos();
+    let orientation = Quat::from_euler(EulerRot::ZYX, 0.0, pitch, roll);
+
+    // Rates of the sway above, in degrees per second
+    let rate = (SWAY_AMPLITUDE * TAU / SWAY_PERIOD).to_degrees();
+    let gravity = orientation.inverse() * Vec3::Z;
+    // Roughly north and down into the earth
+    let field = orientation.inverse() * Vec3::new(0.2, 0.0, -0.4);
+
+    let capture_time = clock::monotonic_seconds();
+    let inertial = InertialFrame {
+        gyro_x: Dps(rate * phase.cos()),
+        gyro_y: Dps(-rate * phase.sin()),
+        gyro_z: Dps(0.0),
+        accel_x: GForce(gravity.x),
+        accel_y: GForce(gravity.y),
+        accel_z: GForce(gravity.z),
+        tempature: Celsius(SURFACE_TEMPERATURE),
+        capture_time,
+    };
+    let magnetic = MagneticFrame {
+        mag_x: Gauss(field.x),
+        mag_y: Gauss(field.y),
+        mag_z: Gauss(field.z),
+        capture_time,
+    };
+
+    health.last_frame = Some(now);
+    health.read_failed = false;
+
+    cmds.entity(robot.entity).insert((
+        Orientation {
+            rotation: orientation,
+            capture_time,
+        },
+        Inertial(inertial),
+        Magnetic(magnetic),
+    ));
+}
+
+fn simulate_depth(
+    mut cmds: Commands,
+    robot: Res<LocalRobot>,
+    settings: Query<&DepthSettings, With<LocalRobotMarker>>,
+    time: Res<Time<Real>>,
+) {
+    let Ok(settings) = settings.get_single() else {
+        return;
+    };
+
+    let phase = time.elapsed_seconds() * TAU / DIVE_PERIOD;
+    let true_depth = MIN_DEPTH + (MAX_DEPTH - MIN_DEPTH) * (1.0 - phase.cos()) / 2.0;
+
+    let pressure = ATMOSPHERE.0 + true_depth * FLUID_DENSITY * GRAVITY / 100.0;
+    // Reported the same way the real sensor does, so calibration still has an effect
+    let depth = (pressure - settings.sea_level.0) * 100.0 / (settings.fluid_density * GRAVITY);
+
+    cmds.entity(robot.entity).insert(Depth(DepthFrame {
+        depth: Meters(depth),
+        altitude: Meters(-depth),
+        pressure: Mbar(pressure),
+        temperature: Celsius(SURFACE_TEMPERATURE - TEMPERATURE_GRADIENT * true_depth),
+        capture_time: clock::monotonic_seconds(),
+    }));
+}
+
+fn simulate_power(
+    mut cmds: Commands,
+    robot: Res<LocalRobot>,
+    config: Res<RobotConfig>,
+    motors: Query<(&ExpectedCurrent, &RobotId)>,
+) {
+    let motor_current: f32 = motors
+        .iter()
+        .filter(|(_, robot_id)| robot_id.0 == robot.net_id)
+        .map(|(ExpectedCurrent(current), _)| current.0.abs())
+        .sum();
+    let current = config.current_estimation.electronics_baseline + motor_current;
+
+    cmds.entity(robot.entity).insert((
+        MeasuredVoltage((BATTERY_VOLTAGE - BATTERY_SAG * current).into()),
+        CurrentDraw(current.into()),
+    ));
+}
+
+fn calibrate_sea_level(
+    mut events: ScopedEvents<CalibrateSeaLevel>,
+    mut robot: Query<(&Depth, &mut DepthSettings), With<LocalRobotMarker>>,
+    local: Res<LocalRobot>,
+    mut completions: EventWriter<CommandComplete>,
+) {
+    for _ in events.read() {
+        info!("Calibrating Sea Level");
+
+        let (ok, detail) = match robot.get_single_mut() {
+            Ok((depth, mut settings)) => {
+                settings.sea_level = depth.0.pressure;
+                (true, format!("Sea level set to {}", depth.0.pressure))
+            }
+            Err(_) => (false, "No depth reading yet".to_owned()),
+        };
+
+        completions.send(CommandComplete {
+            robot: RobotId(local.net_id),
+            command: AckedCommand::CalibrateSeaLevel,
+            ok,
+            detail,
+        });
+    }
+}
+
+/// The simulated heading follows a fixed pattern, there is no drift to reset
+fn reset_yaw(
+    mut events: ScopedEvents<ResetYaw>,
+    local: Res<LocalRobot>,
+    mut completions: EventWriter<CommandComplete>,
+) {
+    for _ in events.read() {
+        completions.send(CommandComplete {
+            robot: RobotId(local.net_id),
+            command: AckedCommand::ResetYaw,
+            ok: false,
+            detail: "The simulated heading can't be reset".to_owned(),
+        });
+    }
+}
@@ -0,0 +1,196 @@
+//! Counts net turns about the vertical axis so the pilot knows when the tether is wrapping
+
+use std::f32::consts::{PI, TAU};
+
+use bevy::prelude::*;
+use common::{
+    components::{Orientation, TetherTurns},
+    ecs_sync::scoped::ScopedEvents,
+    events::ResetTetherTurns,
+};
+use glam::{Quat, Vec3};
+
+use crate::plugins::{
+    core::robot::{LocalRobot, LocalRobotMarker},
+    sensors::ImuHealth,
+};
+
+pub struct TetherPlugin;
+
+impl Plugin for TetherPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TurnCounter>()
+            .init_resource::<ImuHealth>()
+            .add_systems(Update, count_turns);
+    }
+}
+
+/// Smallest change in turns that gets replicated
+const PUBLISH_THRESHOLD: f32 = 0.01;
+
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq)]
+pub struct TurnCounter {
+    /// Radians, counter clockwise from above
+    last_yaw: Option<f32>,
+    turns: f32,
+    published: Option<f32>,
+}
+
+impl TurnCounter {
+    /// Integrates a new yaw sample in radians, counter clockwise positive
+    ///
+    /// Each step takes the shortest way around, so a few missed samples don't lose any turns
+    pub fn sample(&mut self, yaw: f32) -> f32 {
+        if let Some(last_yaw) = self.last_yaw {
+            // Clockwise turns are positive
+            self.turns -= shortest_delta(last_yaw, yaw) / TAU;
+        }
+        self.last_yaw = Some(yaw);
+
+        self.turns
+    }
+
+    pub fn reset(&mut self) {
+        self.turns = 0.0;
+        self.published = None;
+    }
+
+    pub fn turns(&self) -> f32 {
+        self.turns
+    }
+
+    /// Returns the count if it moved enough to be worth replicating
+    fn publish(&mut self) -> Option<f32> {
+        let changed = !self
+            .published
+            .is_some_and(|published| (self.turns - published).abs() < PUBLISH_THRESHOLD);
+
+        if changed {
+            self.published = Some(self.turns);
+            Some(self.turns)
+        } else {
+            None
+        }
+    }
+}
+
+/// Signed angle from `from` to `to` in `[-PI, PI)`
+pub fn shortest_delta(from: f32, to: f32) -> f32 {
+    (to - from + PI).rem_euclid(TAU) - PI
+}
+
+/// Heading of the robot's forward axis, counter clockwise from above
+pub fn yaw(orientation: Quat) -> f32 {
+    let forward = orientation * Vec3::Y;
+    (-forward.x).atan2(forward.y)
+}
+
+fn count_turns(
+    mut cmds: Commands,
+    robot: Res<LocalRobot>,
+    mut counter: ResMut<TurnCounter>,
+    orientation: Query<Ref<Orientation>, With<LocalRobotMarker>>,
+    health: Res<ImuHealth>,
+    time: Res<Time<Real>>,
+    mut resets: ScopedEvents<ResetTetherTurns>,
+) {
+    if !resets.read().is_empty() {
+        info!("Reset tether turns");
+        counter.reset();
+    }
+
+    if let Ok(orientation) = orientation.get_single() {
+        // Samples from an unhealthy sensor are skipped, the next good one picks up from the last
+        if orientation.is_changed() && health.is_healthy(time.elapsed()) {
+            counter.sample(yaw(orientation.rotation));
+        }
+    }
+
+    if let Some(turns) = counter.publish() {
+        cmds.entity(robot.entity).insert(TetherTurns(turns));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::{FRAC_PI_2, PI};
+
+    use glam::Quat;
+
+    use super::{shortest_delta, yaw, TurnCounter};
+
+    fn deg(deg: f32) -> f32 {
+        deg.to_radians()
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{a} != {b}");
+    }
+
+    #[test]
+    fn shortest_delta_wraps() {
+        assert_close(shortest_delta(deg(10.0), deg(30.0)), deg(20.0));
+        assert_close(shortest_delta(deg(170.0), deg(-170.0)), deg(20.0));
+        assert_close(shortest_delta(deg(-170.0), deg(170.0)), deg(-20.0));
+        assert_close(shortest_delta(deg(0.0), deg(270.0)), deg(-90.0));
+        assert_close(shortest_delta(deg(45.0), deg(45.0)), 0.0);
+    }
+
+    #[test]
+    fn counts_across_the_boundary() {
+        let mut counter = TurnCounter::default();
+
+        // Three full clockwise turns in 10 degree steps, yaw wraps at +-180
+        for step in 0..=108 {
+            let yaw = shortest_delta(0.0, deg(-10.0 * step as f32));
+            counter.sample(yaw);
+        }
+        assert_close(counter.turns(), 3.0);
+
+        // Back one counter clockwise
+        for step in 1..=36 {
+            counter.sample(shortest_delta(0.0, deg(10.0 * step as f32)));
+        }
+        assert_close(counter.turns(), 2.0);
+    }
+
+    #[test]
+    fn sample_gaps_take_the_short_way() {
+        let mut counter = TurnCounter::default();
+
+        counter.sample(deg(170.0));
+        // A big gap that crossed the boundary going counter clockwise
+        counter.sample(deg(-100.0));
+        assert_close(counter.turns(), -90.0 / 360.0);
+
+        // Gaps over half a turn are ambiguous and taken the short way
+        counter.sample(deg(100.0));
+        assert_close(counter.turns(), -90.0 / 360.0 + 160.0 / 360.0);
+    }
+
+    #[test]
+    fn reset_keeps_heading() {
+        let mut counter = TurnCounter::default();
+
+        counter.sample(0.0);
+        counter.sample(-FRAC_PI_2);
+        assert_close(counter.turns(), 0.25);
+
+        counter.reset();
+        assert_eq!(counter.turns(), 0.0);
+
+        counter.sample(-PI + 0.001);
+        assert!((counter.turns() - 0.25).abs() < 1e-3);
+    }
+
+    #[test]
+    fn yaw_from_orientation() {
+        assert_close(yaw(Quat::IDENTITY), 0.0);
+        assert_close(yaw(Quat::from_rotation_z(deg(30.0))), deg(30.0));
+        assert_close(yaw(Quat::from_rotation_z(deg(-150.0))), deg(-150.0));
+
+        // Pitch doesnt change the heading
+        let pitched = Quat::from_rotation_z(deg(60.0)) * Quat::from_rotation_x(deg(20.0));
+        assert_close(yaw(pitched), deg(60.0));
+    }
+}
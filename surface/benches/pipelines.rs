@@ -0,0 +1,451 @@
+//! Times video pipelines outside of the app
+//!
+//! Every pipeline is run on its fixture, or on `--source` (a directory of images or a video
+//! file), as fast as it goes. Reports per frame timing percentiles, allocations, and the time
+//! spent in each of the pipeline's tracing spans.
+//!
+//! ```sh
+//! cargo bench -p surface --bench pipelines
+//! cargo bench -p surface --bench pipelines -- square --source recording.mkv
+//! ```
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use bevy::{ecs::world::World, math::Vec2};
+use clap::Parser;
+use opencv::{imgcodecs, prelude::*, videoio};
+use surface::video_pipelines::{
+    edges::EdgesPipeline,
+    harness::{self, PipelineHarness},
+    marker::MarkerPipeline,
+    measure::{MeasurePipeline, MeasurementTarget},
+    scale::ScalePipeline,
+    squares::SquareTrackingPipeline,
+    station_keep::StationKeepPipeline,
+    stereo::StereoMeasurePipeline,
+    undistort::UndistortPipeline,
+    FromWorldEntity, Pipeline,
+};
+use tracing::{span, Subscriber};
+use tracing_subscriber::{
+    filter::LevelFilter,
+    layer::{Context as LayerContext, SubscriberExt},
+    registry::LookupSpan,
+    Layer,
+};
+
+#[derive(Parser)]
+struct Args {
+    /// Only run pipelines whose name contains this
+    filter: Option<String>,
+
+    /// Directory of images or a video file to use instead of the fixtures
+    #[arg(long)]
+    source: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 300)]
+    frames: usize,
+    /// Frames run before measuring, lets pipelines build their caches
+    #[arg(long, default_value_t = 10)]
+    warmup: usize,
+
+    /// Size of the generated fixtures
+    #[arg(long, default_value_t = 1280)]
+    width: i32,
+    #[arg(long, default_value_t = 720)]
+    height: i32,
+
+    /// Passed by `cargo bench`
+    #[arg(long, hide = true)]
+    bench: bool,
+}
+
+#[derive(Clone, Copy)]
+enum Fixture {
+    Checkerboard,
+    RedSquare,
+    Marker,
+}
+
+impl Fixture {
+    fn generate(self, args: &Args) -> anyhow::Result<Mat> {
+        match self {
+            Fixture::Checkerboard => harness::checkerboard(args.width, args.height, 40),
+            Fixture::RedSquare => harness::red_square_scene(args.width, args.height),
+            Fixture::Marker => harness::marker_scene(args.width, args.height, 7),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Fixture::Checkerboard => "checkerboard",
+            Fixture::RedSquare => "red square",
+            Fixture::Marker => "marker",
+        }
+    }
+}
+
+struct Bench {
+    name: &'static str,
+    fixture: Fixture,
+    run: fn(&Args, &[Mat]) -> anyhow::Result<Report>,
+}
+
+/// Every pipeline that can run without a live camera, the save pipeline only writes files
+const BENCHES: &[Bench] = &[
+    Bench {
+        name: "Edge Detection Pipeline",
+        fixture: Fixture::Checkerboard,
+        run: |args, frames| run::<EdgesPipeline>(args, frames, |_, _| {}),
+    },
+    Bench {
+        name: "1/4 Scale Pipeline",
+        fixture: Fixture::Checkerboard,
+        run: |args, frames| run::<ScalePipeline<2, -2>>(args, frames, |_, _| {}),
+    },
+    Bench {
+        name: "Undistort Pipeline",
+        fixture: Fixture::Checkerboard,
+        run: |args, frames| run::<UndistortPipeline>(args, frames, |_, _| {}),
+    },
+    Bench {
+        name: "Marker Pipeline",
+        fixture: Fixture::Marker,
+        run: |args, frames| run::<MarkerPipeline>(args, frames, |_, _| {}),
+    },
+    Bench {
+        name: "Square Tracking Pipeline",
+        fixture: Fixture::RedSquare,
+        run: |args, frames| run::<SquareTrackingPipeline>(args, frames, |_, _| {}),
+    },
+    Bench {
+        name: "Station Keep Pipeline",
+        fixture: Fixture::Checkerboard,
+        run: |args, frames| run::<StationKeepPipeline>(args, frames, |_, _| {}),
+    },
+    Bench {
+        name: "Stereo Measure Pipeline",
+        fixture: Fixture::Checkerboard,
+        run: |args, frames| run::<StereoMeasurePipeline>(args, frames, |_, _| {}),
+    },
+    Bench {
+        name: "Measure Pipeline",
+        fixture: Fixture::Checkerboard,
+        run: |args, frames| {
+            run::<MeasurePipeline>(args, frames, |world, pipeline| {
+                world.entity_mut(pipeline).insert(MeasurementTarget {
+                    poi: Vec2::new(0.5, 0.5),
+                    left: Vec2::new(0.25, 0.5),
+                    right: Vec2::new(0.75, 0.5),
+                });
+            })
+        },
+    },
+];
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let source = match &args.source {
+        Some(path) => Some(load_source(path, args.frames).context("Load source")?),
+        None => None,
+    };
+
+    for bench in BENCHES {
+        if let Some(filter) = &args.filter {
+            if !bench.name.to_lowercase().contains(&filter.to_lowercase()) {
+                continue;
+            }
+        }
+
+        let (frames, source_name) = match &source {
+            Some(frames) => (frames.clone(), "source".to_owned()),
+            None => (
+                vec![bench.fixture.generate(&args)?],
+                bench.fixture.name().to_owned(),
+            ),
+        };
+
+        match (bench.run)(&args, &frames) {
+            Ok(report) => report.print(bench.name, &source_name),
+            Err(err) => println!("{}: {err:?}\n", bench.name),
+        }
+    }
+
+    Ok(())
+}
+
+/// Frames from a directory of images, sorted by file name, or from a video file
+fn load_source(path: &Path, max_frames: usize) -> anyhow::Result<Vec<Mat>> {
+    let mut frames = Vec::new();
+
+    if path.is_dir() {
+        let mut paths = fs::read_dir(path)
+            .context("Read directory")?
+            .map(|it| it.map(|it| it.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .context("Read directory entry")?;
+        paths.retain(|it| {
+            it.extension()
+                .and_then(|it| it.to_str())
+                .is_some_and(|it| ["png", "jpg", "jpeg", "bmp"].contains(&it))
+        });
+        paths.sort();
+
+        for path in paths.into_iter().take(max_frames) {
+            let frame = imgcodecs::imread_def(&path.to_string_lossy()).context("Read image")?;
+            frames.push(frame);
+        }
+    } else {
+        let mut capture =
+            videoio::VideoCapture::from_file(&path.to_string_lossy(), videoio::CAP_ANY)
+                .context("Open video")?;
+
+        let mut frame = Mat::default();
+        while frames.len() < max_frames && capture.read(&mut frame).context("Read frame")? {
+            frames.push(frame.clone());
+        }
+    }
+
+    if frames.is_empty() {
+        bail!("No frames in {}", path.display());
+    }
+
+    Ok(frames)
+}
+
+fn run<P: Pipeline + FromWorldEntity>(
+    args: &Args,
+    frames: &[Mat],
+    setup: impl FnOnce(&mut World, bevy::ecs::entity::Entity),
+) -> anyhow::Result<Report> {
+    let mut harness = PipelineHarness::<P>::new()?;
+    let pipeline = harness.pipeline_entity();
+    setup(harness.world_mut(), pipeline);
+
+    let mut frame = Mat::default();
+    let mut frame_times = Vec::with_capacity(args.frames);
+    let mut allocations = Allocations::default();
+
+    // Timing and allocations are measured without a subscriber so the spans cost nothing
+    for idx in 0..args.warmup + args.frames {
+        // Pipelines draw on the frame, every run starts from a fresh copy
+        frames[idx % frames.len()]
+            .copy_to(&mut frame)
+            .context("Copy frame")?;
+
+        let input = harness.collect_inputs();
+
+        let measured = idx >= args.warmup;
+        let start = Instant::now();
+        let (rst, allocated) = count_allocations(measured, || harness.process(&input, &mut frame));
+        let elapsed = start.elapsed();
+        rst?;
+
+        harness.apply_callbacks();
+
+        if measured {
+            frame_times.push(elapsed);
+            allocations.add(allocated);
+        }
+
+        if harness.should_end() {
+            break;
+        }
+    }
+
+    // A second pass with the span timings collected
+    let spans = SpanTimes::default();
+    let subscriber = tracing_subscriber::registry()
+        .with(spans.clone().with_filter(LevelFilter::DEBUG))
+        .with(tracing_subscriber::fmt::layer().with_filter(LevelFilter::WARN));
+    let span_frames = frame_times.len();
+    tracing::subscriber::with_default(subscriber, || -> anyhow::Result<()> {
+        for idx in 0..span_frames {
+            frames[idx % frames.len()]
+                .copy_to(&mut frame)
+                .context("Copy frame")?;
+            harness.run_frame(&mut frame)?;
+        }
+
+        Ok(())
+    })?;
+
+    Ok(Report {
+        frame_times,
+        allocations,
+        spans: spans.totals(span_frames),
+    })
+}
+
+struct Report {
+    frame_times: Vec<Duration>,
+    allocations: Allocations,
+    /// Mean time per frame spent in each span
+    spans: Vec<(String, Duration)>,
+}
+
+impl Report {
+    fn print(mut self, name: &str, source: &str) {
+        let frames = self.frame_times.len();
+        println!("{name} ({source}, {frames} frames)");
+        if frames == 0 {
+            println!("  pipeline ended before any frame was measured\n");
+            return;
+        }
+
+        self.frame_times.sort();
+        let percentile = |p: f64| {
+            let idx = ((frames - 1) as f64 * p).round() as usize;
+            self.frame_times[idx]
+        };
+        let mean = self.frame_times.iter().sum::<Duration>() / frames as u32;
+
+        println!(
+            "  frame time   p50 {:>9.3?}  p90 {:>9.3?}  p99 {:>9.3?}  max {:>9.3?}  ({:.0} fps)",
+            percentile(0.5),
+            percentile(0.9),
+            percentile(0.99),
+            percentile(1.0),
+            1.0 / mean.as_secs_f64(),
+        );
+        println!(
+            "  allocations  {:.1} per frame, {:.1} KiB per frame",
+            self.allocations.count as f64 / frames as f64,
+            self.allocations.bytes as f64 / frames as f64 / 1024.0,
+        );
+
+        if !self.spans.is_empty() {
+            println!("  spans (mean per frame)");
+        }
+        for (span, time) in &self.spans {
+            let share = time.as_secs_f64() / mean.as_secs_f64() * 100.0;
+            println!("    {span:<48} {time:>9.3?}  {share:>5.1}%");
+        }
+
+        println!();
+    }
+}
+
+/// Counts allocations made while `TRACKING` is set
+struct CountingAlloc;
+
+static TRACKING: AtomicBool = AtomicBool::new(false);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if TRACKING.load(Ordering::Relaxed) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if TRACKING.load(Ordering::Relaxed) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            ALLOCATED_BYTES.fetch_add(new_size, Ordering::Relaxed);
+        }
+
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+#[derive(Default)]
+struct Allocations {
+    count: usize,
+    bytes: usize,
+}
+
+impl Allocations {
+    fn add(&mut self, other: Allocations) {
+        self.count += other.count;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Runs `frame` and returns how many allocations it made, OpenCV's own allocator isn't seen
+fn count_allocations<T>(track: bool, frame: impl FnOnce() -> T) -> (T, Allocations) {
+    ALLOCATIONS.store(0, Ordering::SeqCst);
+    ALLOCATED_BYTES.store(0, Ordering::SeqCst);
+    TRACKING.store(track, Ordering::SeqCst);
+    let rst = frame();
+    TRACKING.store(false, Ordering::SeqCst);
+
+    let allocations = Allocations {
+        count: ALLOCATIONS.load(Ordering::SeqCst),
+        bytes: ALLOCATED_BYTES.load(Ordering::SeqCst),
+    };
+
+    (rst, allocations)
+}
+
+/// Adds up the time spent inside each span, keyed by module and span name
+#[derive(Clone, Default)]
+struct SpanTimes(std::sync::Arc<Mutex<HashMap<(&'static str, &'static str), Duration>>>);
+
+struct Entered(Instant);
+
+impl SpanTimes {
+    fn totals(&self, frames: usize) -> Vec<(String, Duration)> {
+        let times = self.0.lock().expect("Lock span times");
+
+        let mut totals = times
+            .iter()
+            .map(|((target, name), time)| {
+                let target = target.trim_start_matches("surface::video_pipelines::");
+                (format!("{target}::{name}"), *time / frames.max(1) as u32)
+            })
+            .collect::<Vec<_>>();
+        totals.sort_by(|a, b| b.1.cmp(&a.1));
+
+        totals
+    }
+}
+
+impl<S> Layer<S> for SpanTimes
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_enter(&self, id: &span::Id, ctx: LayerContext<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().replace(Entered(Instant::now()));
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: LayerContext<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        let Some(Entered(start)) = span.extensions_mut().remove::<Entered>() else {
+            return;
+        };
+
+        let metadata = span.metadata();
+        let mut times = self.0.lock().expect("Lock span times");
+        *times
+            .entry((metadata.target(), metadata.name()))
+            .or_default() += start.elapsed();
+    }
+}
@@ -0,0 +1,650 @@
+use std::time::Duration;
+
+use ahash::{HashMap, HashSet};
+use bevy::{
+    audio::{Pitch, PitchBundle, PlaybackSettings, Volume},
+    input::gamepad::{GamepadRumbleIntensity, GamepadRumbleRequest},
+    prelude::*,
+};
+use common::{
+    components::{Armed, Leak, Robot, Temperatures},
+    types::units::Celsius,
+};
+use leafwing_input_manager::action_state::ActionState;
+
+use crate::input::{Action, InputMarker};
+
+/// How long after a manual disarm a disarm is still attributed to the pilot
+const MANUAL_DISARM_WINDOW: Duration = Duration::from_secs(1);
+
+pub struct AlertPlugin;
+
+impl Plugin for AlertPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<AlertEvent>()
+            .init_resource::<AlertSettings>()
+            .init_resource::<AlertRouter>()
+            .init_resource::<RumblePlayback>()
+            .add_systems(
+                Update,
+                (
+                    (detect_leaks, detect_failsafe, detect_thermal, detect_link),
+                    route_alerts,
+                    play_rumble,
+                )
+                    .chain(),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AlertClass {
+    Leak,
+    FailsafeDisarm,
+    ThermalTrip,
+    LinkLost,
+    LinkRestored,
+}
+
+impl AlertClass {
+    pub const ALL: [AlertClass; 5] = [
+        AlertClass::Leak,
+        AlertClass::FailsafeDisarm,
+        AlertClass::ThermalTrip,
+        AlertClass::LinkLost,
+        AlertClass::LinkRestored,
+    ];
+
+    /// Latched alerts stay active until cleared and get repeated every cooldown
+    pub fn is_latched(&self) -> bool {
+        match self {
+            AlertClass::Leak | AlertClass::ThermalTrip | AlertClass::LinkLost => true,
+            AlertClass::FailsafeDisarm | AlertClass::LinkRestored => false,
+        }
+    }
+
+    pub fn tone(&self) -> Tone {
+        match self {
+            AlertClass::Leak => Tone {
+                frequency: 880.0,
+                duration: Duration::from_millis(600),
+            },
+            AlertClass::FailsafeDisarm => Tone {
+                frequency: 660.0,
+                duration: Duration::from_millis(400),
+            },
+            AlertClass::ThermalTrip => Tone {
+                frequency: 520.0,
+                duration: Duration::from_millis(400),
+            },
+            AlertClass::LinkLost => Tone {
+                frequency: 330.0,
+                duration: Duration::from_millis(500),
+            },
+            AlertClass::LinkRestored => Tone {
+                frequency: 990.0,
+                duration: Duration::from_millis(150),
+            },
+        }
+    }
+
+    pub fn rumble_pattern(&self) -> RumblePattern {
+        match self {
+            AlertClass::Leak => RumblePattern {
+                pulses: 3,
+                on: Duration::from_millis(300),
+                off: Duration::from_millis(150),
+                intensity: GamepadRumbleIntensity::MAX,
+            },
+            AlertClass::FailsafeDisarm => RumblePattern {
+                pulses: 1,
+                on: Duration::from_millis(800),
+                off: Duration::ZERO,
+                intensity: GamepadRumbleIntensity::MAX,
+            },
+            AlertClass::ThermalTrip => RumblePattern {
+                pulses: 2,
+                on: Duration::from_millis(200),
+                off: Duration::from_millis(200),
+                intensity: GamepadRumbleIntensity::strong_motor(0.6),
+            },
+            AlertClass::LinkLost => RumblePattern {
+                pulses: 2,
+                on: Duration::from_millis(500),
+                off: Duration::from_millis(250),
+                intensity: GamepadRumbleIntensity::MAX,
+            },
+            AlertClass::LinkRestored => RumblePattern {
+                pulses: 1,
+                on: Duration::from_millis(150),
+                off: Duration::ZERO,
+                intensity: GamepadRumbleIntensity::WEAK_MAX,
+            },
+        }
+    }
+}
+
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertEvent {
+    Raised(AlertClass),
+    Cleared(AlertClass),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    pub frequency: f32,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RumblePattern {
+    pub pulses: u32,
+    pub on: Duration,
+    pub off: Duration,
+    pub intensity: GamepadRumbleIntensity,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AlertClassSettings {
+    pub enabled: bool,
+    pub rumble: bool,
+    /// Zero mutes the audio cue
+    pub volume: f32,
+}
+
+impl Default for AlertClassSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rumble: true,
+            volume: 1.0,
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct AlertSettings {
+    pub cooldown: Duration,
+    /// Used for sensors that dont report a critical temperature
+    pub thermal_limit: Celsius,
+    pub classes: HashMap<AlertClass, AlertClassSettings>,
+}
+
+impl AlertSettings {
+    pub fn class(&self, class: AlertClass) -> AlertClassSettings {
+        self.classes.get(&class).copied().unwrap_or_default()
+    }
+}
+
+impl Default for AlertSettings {
+    fn default() -> Self {
+        Self {
+            cooldown: Duration::from_secs(10),
+            thermal_limit: Celsius(80.0),
+            classes: AlertClass::ALL
+                .into_iter()
+                .map(|class| (class, AlertClassSettings::default()))
+                .collect(),
+        }
+    }
+}
+
+/// Where alerts end up, kept behind a trait so routing can be tested without audio or gamepads
+pub trait AlertOutput {
+    fn play_tone(&mut self, tone: Tone, volume: f32);
+    fn rumble(&mut self, pattern: RumblePattern);
+    fn stop_rumble(&mut self);
+}
+
+#[derive(Resource, Default, Debug)]
+pub struct AlertRouter {
+    last_fired: HashMap<AlertClass, Duration>,
+    active: HashSet<AlertClass>,
+}
+
+impl AlertRouter {
+    pub fn is_active(&self, class: AlertClass) -> bool {
+        self.active.contains(&class)
+    }
+
+    pub fn handle(
+        &mut self,
+        event: AlertEvent,
+        now: Duration,
+        settings: &AlertSettings,
+        output: &mut dyn AlertOutput,
+    ) {
+        match event {
+            AlertEvent::Raised(class) => {
+                if class.is_latched() {
+                    self.active.insert(class);
+                }
+
+                self.fire(class, now, settings, output);
+            }
+            AlertEvent::Cleared(class) => {
+                let was_active = self.active.remove(&class);
+
+                if was_active && settings.class(class).rumble {
+                    output.stop_rumble();
+                }
+            }
+        }
+    }
+
+    /// Repeats latched alerts that are still active
+    pub fn tick(&mut self, now: Duration, settings: &AlertSettings, output: &mut dyn AlertOutput) {
+        let active = self.active.iter().copied().collect::<Vec<_>>();
+
+        for class in active {
+            self.fire(class, now, settings, output);
+        }
+    }
+
+    fn fire(
+        &mut self,
+        class: AlertClass,
+        now: Duration,
+        settings: &AlertSettings,
+        output: &mut dyn AlertOutput,
+    ) {
+        let class_settings = settings.class(class);
+        if !class_settings.enabled {
+            return;
+        }
+
+        if let Some(&last) = self.last_fired.get(&class) {
+            if now.saturating_sub(last) < settings.cooldown {
+                return;
+            }
+        }
+
+        self.last_fired.insert(class, now);
+
+        if class_settings.volume > 0.0 {
+            output.play_tone(class.tone(), class_settings.volume);
+        }
+
+        if class_settings.rumble {
+            output.rumble(class.rumble_pattern());
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct RumblePlayback {
+    pattern: Option<(RumblePattern, Duration)>,
+    stop: bool,
+}
+
+struct BevyAlertOutput<'a, 'w, 's> {
+    cmds: &'a mut Commands<'w, 's>,
+    pitches: Option<&'a mut Assets<Pitch>>,
+    rumble: &'a mut RumblePlayback,
+    now: Duration,
+}
+
+impl AlertOutput for BevyAlertOutput<'_, '_, '_> {
+    fn play_tone(&mut self, tone: Tone, volume: f32) {
+        // The audio plugin is only present with the `audio` feature
+        let Some(pitches) = &mut self.pitches else {
+            return;
+        };
+
+        self.cmds.spawn(PitchBundle {
+            source: pitches.add(Pitch::new(tone.frequency, tone.duration)),
+            settings: PlaybackSettings::DESPAWN.with_volume(Volume::new(volume)),
+        });
+    }
+
+    fn rumble(&mut self, pattern: RumblePattern) {
+        self.rumble.pattern = Some((pattern, self.now));
+    }
+
+    fn stop_rumble(&mut self) {
+        self.rumble.pattern = None;
+        self.rumble.stop = true;
+    }
+}
+
+fn route_alerts(
+    mut cmds: Commands,
+    mut events: EventReader<AlertEvent>,
+    mut router: ResMut<AlertRouter>,
+    mut rumble: ResMut<RumblePlayback>,
+    mut pitches: Option<ResMut<Assets<Pitch>>>,
+    settings: Res<AlertSettings>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed();
+    let mut output = BevyAlertOutput {
+        cmds: &mut cmds,
+        pitches: pitches.as_deref_mut(),
+        rumble: &mut rumble,
+        now,
+    };
+
+    for event in events.read() {
+        router.handle(*event, now, &settings, &mut output);
+    }
+
+    router.tick(now, &settings, &mut output);
+}
+
+fn play_rumble(
+    mut last_pulse: Local<Option<u32>>,
+    mut playback: ResMut<RumblePlayback>,
+    mut requests: EventWriter<GamepadRumbleRequest>,
+    gamepads: Res<Gamepads>,
+    time: Res<Time<Real>>,
+) {
+    if playback.stop {
+        playback.stop = false;
+        *last_pulse = None;
+
+        for gamepad in gamepads.iter() {
+            requests.send(GamepadRumbleRequest::Stop { gamepad });
+        }
+    }
+
+    let Some((pattern, start)) = playback.pattern else {
+        return;
+    };
+
+    let period = pattern.on + pattern.off;
+    let elapsed = time.elapsed().saturating_sub(start);
+    let pulse = if period.is_zero() {
+        0
+    } else {
+        (elapsed.as_secs_f32() / period.as_secs_f32()) as u32
+    };
+
+    if pulse >= pattern.pulses {
+        playback.pattern = None;
+        *last_pulse = None;
+
+        return;
+    }
+
+    if *last_pulse != Some(pulse) {
+        *last_pulse = Some(pulse);
+
+        for gamepad in gamepads.iter() {
+            requests.send(GamepadRumbleRequest::Add {
+                duration: pattern.on,
+                intensity: pattern.intensity,
+                gamepad,
+            });
+        }
+    }
+}
+
+fn detect_leaks(
+    robots: Query<&Leak, (With<Robot>, Changed<Leak>)>,
+    mut alerts: EventWriter<AlertEvent>,
+) {
+    for leak in &robots {
+        if leak.0 {
+            alerts.send(AlertEvent::Raised(AlertClass::Leak));
+        } else {
+            alerts.send(AlertEvent::Cleared(AlertClass::Leak));
+        }
+    }
+}
+
+fn detect_failsafe(
+    mut last_manual_disarm: Local<Option<Duration>>,
+    mut last_armed: Local<HashMap<Entity, Armed>>,
+    robots: Query<(Entity, &Armed), (With<Robot>, Changed<Armed>)>,
+    inputs: Query<&ActionState<Action>, With<InputMarker>>,
+    mut alerts: EventWriter<AlertEvent>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed();
+
+    if inputs
+        .iter()
+        .any(|action_state| action_state.just_pressed(&Action::Disarm))
+    {
+        *last_manual_disarm = Some(now);
+    }
+
+    for (robot, &armed) in &robots {
+        let previous = last_armed.insert(robot, armed);
+
+        if previous == Some(Armed::Armed) && armed == Armed::Disarmed {
+            let manual = last_manual_disarm
+                .is_some_and(|last| now.saturating_sub(last) < MANUAL_DISARM_WINDOW);
+
+            if !manual {
+                alerts.send(AlertEvent::Raised(AlertClass::FailsafeDisarm));
+            }
+        }
+    }
+}
+
+fn detect_thermal(
+    mut tripped: Local<bool>,
+    robots: Query<&Temperatures, (With<Robot>, Changed<Temperatures>)>,
+    settings: Res<AlertSettings>,
+    mut alerts: EventWriter<AlertEvent>,
+) {
+    for temps in &robots {
+        let over_limit = temps.0.iter().any(|temp| {
+            let limit = temp.tempature_critical.unwrap_or(settings.thermal_limit);
+            temp.tempature.0 >= limit.0
+        });
+
+        if over_limit != *tripped {
+            *tripped = over_limit;
+
+            if over_limit {
+                alerts.send(AlertEvent::Raised(AlertClass::ThermalTrip));
+            } else {
+                alerts.send(AlertEvent::Cleared(AlertClass::ThermalTrip));
+            }
+        }
+    }
+}
+
+fn detect_link(
+    new_robots: Query<Entity, Added<Robot>>,
+    mut removed_robots: RemovedComponents<Robot>,
+    router: Res<AlertRouter>,
+    mut alerts: EventWriter<AlertEvent>,
+) {
+    for _robot in removed_robots.read() {
+        // Whatever we knew about the robot is stale now
+        alerts.send(AlertEvent::Cleared(AlertClass::Leak));
+        alerts.send(AlertEvent::Cleared(AlertClass::ThermalTrip));
+        alerts.send(AlertEvent::Raised(AlertClass::LinkLost));
+    }
+
+    for _robot in &new_robots {
+        if router.is_active(AlertClass::LinkLost) {
+            alerts.send(AlertEvent::Cleared(AlertClass::LinkLost));
+            alerts.send(AlertEvent::Raised(AlertClass::LinkRestored));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        AlertClass, AlertClassSettings, AlertEvent, AlertOutput, AlertRouter, AlertSettings,
+        RumblePattern, Tone,
+    };
+
+    #[derive(Default)]
+    struct Recorder {
+        tones: Vec<Tone>,
+        rumbles: Vec<RumblePattern>,
+        stops: usize,
+    }
+
+    impl AlertOutput for Recorder {
+        fn play_tone(&mut self, tone: Tone, _volume: f32) {
+            self.tones.push(tone);
+        }
+
+        fn rumble(&mut self, pattern: RumblePattern) {
+            self.rumbles.push(pattern);
+        }
+
+        fn stop_rumble(&mut self) {
+            self.stops += 1;
+        }
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn routes_to_tone_and_rumble() {
+        let settings = AlertSettings::default();
+        let mut router = AlertRouter::default();
+        let mut output = Recorder::default();
+
+        router.handle(
+            AlertEvent::Raised(AlertClass::Leak),
+            secs(0),
+            &settings,
+            &mut output,
+        );
+
+        assert_eq!(output.tones, vec![AlertClass::Leak.tone()]);
+        assert_eq!(output.rumbles, vec![AlertClass::Leak.rumble_pattern()]);
+    }
+
+    #[test]
+    fn respects_cooldown_per_class() {
+        let settings = AlertSettings::default();
+        let mut router = AlertRouter::default();
+        let mut output = Recorder::default();
+
+        router.handle(
+            AlertEvent::Raised(AlertClass::FailsafeDisarm),
+            secs(0),
+            &settings,
+            &mut output,
+        );
+        router.handle(
+            AlertEvent::Raised(AlertClass::FailsafeDisarm),
+            secs(1),
+            &settings,
+            &mut output,
+        );
+        // Other classes have their own cooldown
+        router.handle(
+            AlertEvent::Raised(AlertClass::LinkLost),
+            secs(1),
+            &settings,
+            &mut output,
+        );
+        router.handle(
+            AlertEvent::Raised(AlertClass::FailsafeDisarm),
+            settings.cooldown + secs(1),
+            &settings,
+            &mut output,
+        );
+
+        assert_eq!(output.tones.len(), 3);
+    }
+
+    #[test]
+    fn latched_alerts_repeat_until_cleared() {
+        let settings = AlertSettings::default();
+        let mut router = AlertRouter::default();
+        let mut output = Recorder::default();
+
+        router.handle(
+            AlertEvent::Raised(AlertClass::Leak),
+            secs(0),
+            &settings,
+            &mut output,
+        );
+        router.tick(secs(1), &settings, &mut output);
+        assert_eq!(output.rumbles.len(), 1);
+
+        router.tick(settings.cooldown, &settings, &mut output);
+        assert_eq!(output.rumbles.len(), 2);
+
+        router.handle(
+            AlertEvent::Cleared(AlertClass::Leak),
+            settings.cooldown,
+            &settings,
+            &mut output,
+        );
+        assert_eq!(output.stops, 1);
+
+        router.tick(settings.cooldown * 3, &settings, &mut output);
+        assert_eq!(output.rumbles.len(), 2);
+    }
+
+    #[test]
+    fn momentary_alerts_do_not_latch() {
+        let settings = AlertSettings::default();
+        let mut router = AlertRouter::default();
+        let mut output = Recorder::default();
+
+        router.handle(
+            AlertEvent::Raised(AlertClass::LinkRestored),
+            secs(0),
+            &settings,
+            &mut output,
+        );
+        router.tick(settings.cooldown * 2, &settings, &mut output);
+
+        assert!(!router.is_active(AlertClass::LinkRestored));
+        assert_eq!(output.tones.len(), 1);
+    }
+
+    #[test]
+    fn per_class_settings() {
+        let mut settings = AlertSettings::default();
+        settings.classes.insert(
+            AlertClass::Leak,
+            AlertClassSettings {
+                enabled: true,
+                rumble: false,
+                volume: 0.0,
+            },
+        );
+        settings.classes.insert(
+            AlertClass::LinkLost,
+            AlertClassSettings {
+                enabled: false,
+                ..Default::default()
+            },
+        );
+
+        let mut router = AlertRouter::default();
+        let mut output = Recorder::default();
+
+        router.handle(
+            AlertEvent::Raised(AlertClass::Leak),
+            secs(0),
+            &settings,
+            &mut output,
+        );
+        router.handle(
+            AlertEvent::Raised(AlertClass::LinkLost),
+            secs(0),
+            &settings,
+            &mut output,
+        );
+        router.handle(
+            AlertEvent::Cleared(AlertClass::Leak),
+            secs(0),
+            &settings,
+            &mut output,
+        );
+
+        assert!(output.tones.is_empty());
+        assert!(output.rumbles.is_empty());
+        assert_eq!(output.stops, 0);
+    }
+}
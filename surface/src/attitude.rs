@@ -1,3 +1,8 @@
+//! The HUD's attitude display and its numeric yaw, pitch and roll readout
+//!
+//! The readout can be given relative to a camera's mounting instead of the IMU, so "pitch 0"
+//! means that camera looks level. See [`AttitudeAngles`] for the sign conventions.
+
 use bevy::{
     math::{vec3, Vec3A},
     prelude::*,
@@ -10,20 +15,42 @@ use bevy::{
     },
 };
 use bevy_egui::EguiContexts;
-use common::components::{Motors, Orientation, OrientationTarget, Robot};
+use common::{
+    components::{Camera as RobotCamera, Motors, Orientation, OrientationTarget, Robot, RobotId},
+    ecs_sync::NetId,
+    types::ids::CameraId,
+};
 use egui::TextureId;
 use motor_math::{x3d::X3dMotorId, Direction, ErasedMotorId, Motor, MotorConfig};
+use serde::{Deserialize, Serialize};
 
-use crate::DARK_MODE;
+use crate::theme::{Palette, Theme};
 
 const RENDER_LAYERS: RenderLayers = RenderLayers::layer(1);
 
+/// Pitch, in degrees, past which the readout switches to [`EulerConvention::YawRollPitch`]
+pub const GIMBAL_ENTER: f32 = 80.0;
+/// Pitch, in degrees, below which the readout switches back to [`EulerConvention::YawPitchRoll`]
+pub const GIMBAL_EXIT: f32 = 70.0;
+/// Degrees past ±180 an angle keeps counting before it wraps around
+pub const WRAP_HYSTERESIS: f32 = 10.0;
+
 pub struct AttitudePlugin;
 
 impl Plugin for AttitudePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup)
-            .add_systems(Update, (update_motor_conf, rotator_system))
+        app.init_resource::<AttitudeReferenceFrame>()
+            .init_resource::<AttitudeReadout>()
+            .add_systems(Startup, setup)
+            .add_systems(
+                Update,
+                (
+                    update_motor_conf,
+                    rotator_system,
+                    update_readout,
+                    relight.run_if(resource_changed::<Theme>),
+                ),
+            )
             .insert_gizmo_group(
                 AttitudeGizmo,
                 GizmoConfig {
@@ -43,6 +70,20 @@ pub struct OrientationDisplay(pub Handle<Image>, pub TextureId);
 struct OrientationDisplayMarker;
 #[derive(Component)]
 struct MotorMarker(ErasedMotorId);
+#[derive(Component)]
+struct AttitudeLight;
+
+/// Point light intensity and ambient brightness, the light theme needs more to match its
+/// background
+fn lighting(theme: &Theme) -> (f32, f32) {
+    let ambient = AmbientLight::default().brightness;
+
+    if theme.is_dark() {
+        (1_000_000.0, ambient)
+    } else {
+        (4_000_000.0, ambient * 7.0)
+    }
+}
 
 fn setup(
     mut commands: Commands,
@@ -50,6 +91,7 @@ fn setup(
     mut egui_context: EguiContexts,
 
     mut ambient_light: ResMut<AmbientLight>,
+    theme: Res<Theme>,
 
     mut materials: ResMut<Assets<StandardMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
@@ -87,21 +129,21 @@ fn setup(
     let image_handle = images.add(image);
 
     // light
+    let (intensity, ambient) = lighting(&theme);
     commands.spawn((
         PointLightBundle {
             point_light: PointLight {
                 shadows_enabled: true,
-                intensity: if DARK_MODE { 1_000_000.0 } else { 4_000_000.0 },
+                intensity,
                 ..default()
             },
             transform: Transform::from_xyz(4.0, 4.0, 8.0),
             ..default()
         },
+        AttitudeLight,
         RENDER_LAYERS,
     ));
-    if !DARK_MODE {
-        ambient_light.brightness *= 7.0;
-    }
+    ambient_light.brightness = ambient;
 
     // camera
     commands.spawn((
@@ -140,6 +182,19 @@ fn setup(
     commands.insert_resource(OrientationDisplay(image_handle, texture));
 }
 
+fn relight(
+    theme: Res<Theme>,
+    mut ambient_light: ResMut<AmbientLight>,
+    mut lights: Query<&mut PointLight, With<AttitudeLight>>,
+) {
+    let (intensity, ambient) = lighting(&theme);
+
+    ambient_light.brightness = ambient;
+    for mut light in &mut lights {
+        light.intensity = intensity;
+    }
+}
+
 fn add_motor_conf(
     motor_conf: &MotorConfig<ErasedMotorId>,
 
@@ -246,12 +301,12 @@ fn rotator_system(
 ) {
     if let Ok((orientation, target)) = robot.get_single() {
         for mut transform in &mut query {
-            transform.rotation = orientation.0;
+            transform.rotation = orientation.rotation;
         }
 
         gizmos.rect(
             Vec3::ZERO,
-            orientation.0,
+            orientation.rotation,
             Vec2::splat(5.0),
             Color::DARK_GRAY,
         );
@@ -260,8 +315,8 @@ fn rotator_system(
             let y = i as f32 / 2.0 - 2.5;
 
             gizmos.line(
-                orientation.0 * vec3(-2.5, y, 0.0),
-                orientation.0 * vec3(2.5, y, 0.0),
+                orientation.rotation * vec3(-2.5, y, 0.0),
+                orientation.rotation * vec3(2.5, y, 0.0),
                 if y != 0.0 {
                     Color::DARK_GRAY
                 } else {
@@ -274,8 +329,8 @@ fn rotator_system(
             let x = i as f32 / 2.0 - 2.5;
 
             gizmos.line(
-                orientation.0 * vec3(x, -2.5, 0.0),
-                orientation.0 * vec3(x, 2.5, 0.0),
+                orientation.rotation * vec3(x, -2.5, 0.0),
+                orientation.rotation * vec3(x, 2.5, 0.0),
                 if x != 0.0 {
                     Color::DARK_GRAY
                 } else {
@@ -285,8 +340,8 @@ fn rotator_system(
         }
 
         gizmos.line(
-            orientation.0 * vec3(0.0, 0.0, -2.5),
-            orientation.0 * vec3(0.0, 0.0, 2.5),
+            orientation.rotation * vec3(0.0, 0.0, -2.5),
+            orientation.rotation * vec3(0.0, 0.0, 2.5),
             Color::BLUE,
         );
 
@@ -295,7 +350,7 @@ fn rotator_system(
             //
             // gizmos.line(
             //     vec3(0.0, 0.0, 0.0),
-            //     orientation.0 * (Vec3::from(up) * 5.0),
+            //     orientation.rotation * (Vec3::from(up) * 5.0),
             //     Color::YELLOW,
             // );
 
@@ -305,3 +360,441 @@ fn rotator_system(
         }
     }
 }
+
+/// What the attitude readout is relative to
+#[derive(Resource, Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttitudeReferenceFrame {
+    /// The IMU, which is aligned with the robot's body
+    #[default]
+    ImuBody,
+    /// The mounting of the named camera, falls back to the IMU while it's missing
+    Camera(CameraId),
+}
+
+/// Order the readout's angles are applied in, both start with yaw about the vertical
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EulerConvention {
+    /// Then pitch about the right axis and roll about the forward axis, ambiguous when the nose
+    /// points straight up or down
+    #[default]
+    YawPitchRoll,
+    /// Then roll about the forward axis and pitch about the right axis, ambiguous when the right
+    /// side points straight up or down
+    YawRollPitch,
+}
+
+/// Intrinsic yaw, pitch and roll in degrees of a frame with X right, Y forward and Z up
+///
+/// Yaw is positive turning right, like a compass heading. Pitch is positive nose up and roll is
+/// positive right side down. Pitch is within ±90 in [`EulerConvention::YawPitchRoll`] and roll is
+/// within ±90 in [`EulerConvention::YawRollPitch`], the rest are within ±180.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AttitudeAngles {
+    pub yaw: f32,
+    pub pitch: f32,
+    pub roll: f32,
+    pub convention: EulerConvention,
+}
+
+impl AttitudeAngles {
+    /// Decomposes `orientation` in `convention`, `None` if it isn't a usable rotation
+    pub fn decompose(orientation: Quat, convention: EulerConvention) -> Option<Self> {
+        if !orientation.is_finite() || orientation.length_squared() < f32::EPSILON {
+            return None;
+        }
+
+        let orientation = orientation.normalize();
+        let right = orientation * Vec3::X;
+        let forward = orientation * Vec3::Y;
+        let up = orientation * Vec3::Z;
+
+        // Clamped as rounding can leave the vectors a little longer than one
+        let (yaw, pitch, roll) = match convention {
+            // Rz(yaw) * Rx(pitch) * Ry(roll)
+            EulerConvention::YawPitchRoll => (
+                f32::atan2(-forward.x, forward.y),
+                forward.z.clamp(-1.0, 1.0).asin(),
+                f32::atan2(-right.z, up.z),
+            ),
+            // Rz(yaw) * Ry(roll) * Rx(pitch)
+            EulerConvention::YawRollPitch => (
+                f32::atan2(right.y, right.x),
+                f32::atan2(forward.z, up.z),
+                (-right.z).clamp(-1.0, 1.0).asin(),
+            ),
+        };
+
+        Some(Self {
+            // Counter clockwise about Z turns left
+            yaw: -yaw.to_degrees(),
+            pitch: pitch.to_degrees(),
+            roll: roll.to_degrees(),
+            convention,
+        })
+    }
+}
+
+/// The readout's latest angles, kept steady near the ambiguous orientations
+#[derive(Resource, Debug, Clone, Default)]
+pub struct AttitudeReadout {
+    pub angles: Option<AttitudeAngles>,
+    /// Cameras of the robot the readout can be relative to
+    pub cameras: Vec<CameraId>,
+    /// The camera the readout should be relative to isn't on the robot
+    pub camera_missing: bool,
+}
+
+impl AttitudeReadout {
+    /// Decomposes a new orientation
+    ///
+    /// The convention changes as pitch nears ±90, with [`GIMBAL_ENTER`] and [`GIMBAL_EXIT`] apart
+    /// so it doesn't flip back and forth. Angles that can reach ±180 keep counting up to
+    /// [`WRAP_HYSTERESIS`] past it before they wrap around.
+    pub fn update(&mut self, orientation: Quat) -> Option<AttitudeAngles> {
+        let Some(body) = AttitudeAngles::decompose(orientation, EulerConvention::YawPitchRoll)
+        else {
+            self.angles = None;
+            return None;
+        };
+
+        let previous = self.angles;
+        let convention = match previous.map(|it| it.convention) {
+            Some(EulerConvention::YawRollPitch) if body.pitch.abs() >= GIMBAL_EXIT => {
+                EulerConvention::YawRollPitch
+            }
+            _ if body.pitch.abs() > GIMBAL_ENTER => EulerConvention::YawRollPitch,
+            _ => EulerConvention::YawPitchRoll,
+        };
+
+        let mut angles = match convention {
+            EulerConvention::YawPitchRoll => body,
+            EulerConvention::YawRollPitch => AttitudeAngles::decompose(orientation, convention)?,
+        };
+
+        if let Some(previous) = previous.filter(|it| it.convention == convention) {
+            angles.yaw = unwrap_near(previous.yaw, angles.yaw);
+            match convention {
+                EulerConvention::YawPitchRoll => {
+                    angles.roll = unwrap_near(previous.roll, angles.roll)
+                }
+                EulerConvention::YawRollPitch => {
+                    angles.pitch = unwrap_near(previous.pitch, angles.pitch)
+                }
+            }
+        }
+
+        self.angles = Some(angles);
+        self.angles
+    }
+}
+
+/// `angle` moved by whole turns to be closest to `previous`, while that's within
+/// [`WRAP_HYSTERESIS`] of ±180
+fn unwrap_near(previous: f32, angle: f32) -> f32 {
+    let turns = ((previous - angle) / 360.0).round();
+    let unwrapped = angle + turns * 360.0;
+
+    if unwrapped.abs() <= 180.0 + WRAP_HYSTERESIS {
+        unwrapped
+    } else {
+        angle
+    }
+}
+
+/// Rotation of a camera's mounting in the robot's X right, Y forward and Z up frame
+///
+/// Camera transforms from the robot's config are in bevy's frame, with robot X, Y and Z sent to
+/// X, -Z and -Y. That mapping is a reflection so the axis is mapped and the angle negated.
+pub fn camera_mount(rotation: Quat) -> Quat {
+    Quat::from_xyzw(-rotation.x, rotation.z, rotation.y, rotation.w)
+}
+
+/// The orientation the readout decomposes, relative to `mount` when there is one
+pub fn relative_orientation(orientation: Quat, mount: Option<Quat>) -> Quat {
+    match mount {
+        Some(mount) => orientation * camera_mount(mount),
+        None => orientation,
+    }
+}
+
+fn update_readout(
+    frame: Res<AttitudeReferenceFrame>,
+    mut readout: ResMut<AttitudeReadout>,
+    robot: Query<(&NetId, Ref<Orientation>), With<Robot>>,
+    cameras: Query<(&RobotCamera, &Transform, &RobotId)>,
+) {
+    if frame.is_changed() {
+        // Hysteresis from another frame means nothing in this one
+        readout.angles = None;
+    }
+
+    let Ok((net_id, orientation)) = robot.get_single() else {
+        readout.angles = None;
+        readout.cameras.clear();
+        return;
+    };
+
+    let robot_cameras = || cameras.iter().filter(|(.., robot)| robot.0 == *net_id);
+
+    let mut names: Vec<CameraId> = robot_cameras()
+        .map(|(camera, ..)| camera.id.clone())
+        .collect();
+    names.sort();
+    if readout.cameras != names {
+        readout.cameras = names;
+    }
+
+    let mount = match &*frame {
+        AttitudeReferenceFrame::ImuBody => None,
+        AttitudeReferenceFrame::Camera(id) => robot_cameras()
+            .find(|(camera, ..)| camera.id == *id)
+            .map(|(_, transform, _)| transform.rotation),
+    };
+    let camera_missing = matches!(*frame, AttitudeReferenceFrame::Camera(_)) && mount.is_none();
+    if readout.camera_missing != camera_missing {
+        readout.camera_missing = camera_missing;
+        readout.angles = None;
+    }
+
+    if orientation.is_changed() || frame.is_changed() || readout.angles.is_none() {
+        readout.update(relative_orientation(orientation.rotation, mount));
+    }
+}
+
+/// The numeric readout and the frame it's relative to, shown under the attitude display
+pub fn readout_ui(
+    ui: &mut egui::Ui,
+    readout: &AttitudeReadout,
+    frame: &mut ResMut<AttitudeReferenceFrame>,
+    palette: &Palette,
+) {
+    let angles = readout.angles;
+    for (label, angle) in [
+        ("Yaw", angles.map(|it| it.yaw)),
+        ("Pitch", angles.map(|it| it.pitch)),
+        ("Roll", angles.map(|it| it.roll)),
+    ] {
+        let text = match angle {
+            Some(angle) => format!("{label}: {angle:.1}°"),
+            None => format!("{label}: -"),
+        };
+        ui.label(text);
+    }
+
+    if angles.is_some_and(|it| it.convention == EulerConvention::YawRollPitch) {
+        ui.label("Near vertical, roll applied before pitch")
+            .on_hover_text("Yaw, pitch and roll are ambiguous when the nose points up or down");
+    }
+
+    let frame_name = |frame: &AttitudeReferenceFrame| match frame {
+        AttitudeReferenceFrame::ImuBody => "IMU".to_owned(),
+        AttitudeReferenceFrame::Camera(camera) => camera.to_string(),
+    };
+
+    let mut selected = (**frame).clone();
+    egui::ComboBox::from_id_source("Attitude Frame")
+        .selected_text(frame_name(&selected))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut selected, AttitudeReferenceFrame::ImuBody, "IMU");
+
+            for camera in &readout.cameras {
+                let camera = AttitudeReferenceFrame::Camera(camera.clone());
+                let name = frame_name(&camera);
+                ui.selectable_value(&mut selected, camera, name);
+            }
+        });
+    frame.set_if_neq(selected);
+
+    if readout.camera_missing {
+        ui.colored_label(palette.warning, "Camera missing, showing IMU");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{EulerRot, Quat, Vec3};
+
+    use super::{
+        camera_mount, relative_orientation, AttitudeAngles, AttitudeReadout, EulerConvention,
+        GIMBAL_ENTER, GIMBAL_EXIT,
+    };
+
+    const TOLERANCE: f32 = 1e-3;
+
+    fn assert_angles(angles: Option<AttitudeAngles>, yaw: f32, pitch: f32, roll: f32) {
+        let angles = angles.expect("Angles");
+
+        for (actual, expected) in [
+            (angles.yaw, yaw),
+            (angles.pitch, pitch),
+            (angles.roll, roll),
+        ] {
+            assert!((actual - expected).abs() < TOLERANCE, "{angles:?}");
+        }
+    }
+
+    fn rotation(yaw: f32, pitch: f32, roll: f32) -> Quat {
+        Quat::from_euler(
+            EulerRot::ZXY,
+            -yaw.to_radians(),
+            pitch.to_radians(),
+            roll.to_radians(),
+        )
+    }
+
+    #[test]
+    fn known_orientations_decompose() {
+        let ypr = EulerConvention::YawPitchRoll;
+
+        assert_angles(
+            AttitudeAngles::decompose(Quat::IDENTITY, ypr),
+            0.0,
+            0.0,
+            0.0,
+        );
+        // Turning left about Z is a negative heading change
+        assert_angles(
+            AttitudeAngles::decompose(Quat::from_rotation_z(30f32.to_radians()), ypr),
+            -30.0,
+            0.0,
+            0.0,
+        );
+        // Rotating Y towards Z lifts the nose
+        assert_angles(
+            AttitudeAngles::decompose(Quat::from_rotation_x(20f32.to_radians()), ypr),
+            0.0,
+            20.0,
+            0.0,
+        );
+        // Rotating X away from Z drops the right side
+        assert_angles(
+            AttitudeAngles::decompose(Quat::from_rotation_y(15f32.to_radians()), ypr),
+            0.0,
+            0.0,
+            15.0,
+        );
+
+        assert_angles(
+            AttitudeAngles::decompose(rotation(120.0, -35.0, 150.0), ypr),
+            120.0,
+            -35.0,
+            150.0,
+        );
+
+        let tilted = Quat::from_euler(
+            EulerRot::ZYX,
+            -40f32.to_radians(),
+            25f32.to_radians(),
+            -10f32.to_radians(),
+        );
+        assert_angles(
+            AttitudeAngles::decompose(tilted, EulerConvention::YawRollPitch),
+            40.0,
+            -10.0,
+            25.0,
+        );
+    }
+
+    #[test]
+    fn bad_orientations_do_not_decompose() {
+        let ypr = EulerConvention::YawPitchRoll;
+
+        assert!(AttitudeAngles::decompose(Quat::from_xyzw(f32::NAN, 0.0, 0.0, 1.0), ypr).is_none());
+        assert!(AttitudeAngles::decompose(Quat::from_xyzw(0.0, 0.0, 0.0, 0.0), ypr).is_none());
+
+        // Unnormalized and exactly vertical still give finite angles
+        let up = Quat::from_rotation_x(90f32.to_radians()) * 3.0;
+        let angles = AttitudeAngles::decompose(up, ypr).unwrap();
+        assert!(angles.yaw.is_finite() && angles.roll.is_finite());
+        assert!((angles.pitch - 90.0).abs() < TOLERANCE);
+    }
+
+    #[test]
+    fn camera_frame_composes_the_mounting() {
+        // The top camera from the robot's config, pitched up in bevy's frame
+        let top = Quat::from_rotation_x(-90f32.to_radians());
+        let mount = camera_mount(top);
+        assert!((mount * Vec3::Y).abs_diff_eq(Vec3::Z, TOLERANCE));
+
+        // A yawed camera in bevy's frame is yawed the same way about the robot's Z
+        let left = camera_mount(Quat::from_rotation_y(90f32.to_radians()));
+        assert!((left * Vec3::Y).abs_diff_eq(-Vec3::X, TOLERANCE));
+
+        // Level robot, the top camera looks straight up
+        let mut readout = AttitudeReadout::default();
+        let angles = readout.update(relative_orientation(Quat::IDENTITY, Some(top)));
+        assert!((angles.unwrap().pitch - 90.0).abs() < TOLERANCE);
+
+        // Pitched 90 down, the top camera looks level and forward
+        let mut readout = AttitudeReadout::default();
+        let nose_down = Quat::from_rotation_x(-90f32.to_radians());
+        assert_angles(
+            readout.update(relative_orientation(nose_down, Some(top))),
+            0.0,
+            0.0,
+            0.0,
+        );
+
+        // The front camera is mounted straight, it reads the same as the IMU
+        let orientation = rotation(30.0, 10.0, -5.0);
+        assert_angles(
+            readout.update(relative_orientation(orientation, Some(Quat::IDENTITY))),
+            30.0,
+            10.0,
+            -5.0,
+        );
+    }
+
+    #[test]
+    fn gimbal_lock_switches_convention_with_hysteresis() {
+        let mut readout = AttitudeReadout::default();
+
+        let mut pitch_to = |pitch: f32| {
+            let angles = readout.update(rotation(20.0, pitch, 5.0)).unwrap();
+            assert!(angles.yaw.is_finite() && angles.pitch.is_finite() && angles.roll.is_finite());
+            angles.convention
+        };
+
+        assert_eq!(pitch_to(60.0), EulerConvention::YawPitchRoll);
+        assert_eq!(pitch_to(GIMBAL_ENTER - 1.0), EulerConvention::YawPitchRoll);
+        assert_eq!(pitch_to(GIMBAL_ENTER + 1.0), EulerConvention::YawRollPitch);
+
+        // Straight up and noise around it stays in one convention
+        for pitch in [
+            90.0,
+            89.9,
+            90.0,
+            85.0,
+            GIMBAL_ENTER - 1.0,
+            GIMBAL_EXIT + 1.0,
+        ] {
+            assert_eq!(pitch_to(pitch), EulerConvention::YawRollPitch);
+        }
+
+        assert_eq!(pitch_to(GIMBAL_EXIT - 1.0), EulerConvention::YawPitchRoll);
+        assert_eq!(pitch_to(GIMBAL_ENTER - 1.0), EulerConvention::YawPitchRoll);
+    }
+
+    #[test]
+    fn angles_do_not_flap_at_180() {
+        let mut readout = AttitudeReadout::default();
+
+        // Jitter around due south keeps one sign
+        for yaw in [179.0, -179.5, 179.8, -178.0] {
+            let angles = readout.update(rotation(yaw, 0.0, 0.0)).unwrap();
+            assert!(angles.yaw > 175.0, "{angles:?}");
+        }
+
+        // Until it is well past
+        let angles = readout.update(rotation(-165.0, 0.0, 0.0)).unwrap();
+        assert!((angles.yaw + 165.0).abs() < TOLERANCE, "{angles:?}");
+
+        // Upside down, roll does the same
+        let mut readout = AttitudeReadout::default();
+        for roll in [-179.0, 179.0, -179.9] {
+            let angles = readout.update(rotation(0.0, 0.0, roll)).unwrap();
+            assert!(angles.roll < -175.0, "{angles:?}");
+        }
+    }
+}
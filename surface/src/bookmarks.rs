@@ -0,0 +1,775 @@
+//! Saved connections to robots, with the preferences to apply once each one connects
+//!
+//! A bookmark points at an address, an mDNS instance name or both. The typed address wins when
+//! connecting, otherwise the address the instance is currently announced at is used, falling back
+//! to where it was last seen since robots on DHCP move between sessions.
+//!
+//! Once a robot whose [`Name`] matches a bookmark appears, the bookmark's pilot profile is
+//! assigned to every connected gamepad and its master camera is pinned as soon as the feed shows
+//! up.
+
+use std::{collections::BTreeMap, fs, net::SocketAddr, path::Path, time::Duration};
+
+use anyhow::Context;
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+use bevy_tokio_tasks::TokioTasksRuntime;
+use common::{
+    components::{Robot, RobotId},
+    ecs_sync::NetId,
+    error::ErrorEvent,
+    sync::MdnsPeers,
+};
+use egui::RichText;
+use networking::PeerAddr;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    command_palette::{AppCommandExt, SurfaceCommand},
+    device_profiles::{DeviceAssignments, DeviceProfiles},
+    ui,
+    video_display_2d_master::{DisplayMarker, MakeMaster},
+};
+
+/// Where bookmarks are kept between runs
+pub const BOOKMARKS_PATH: &str = "surface_bookmarks.toml";
+
+/// How long auto connect waits for a bookmarked mDNS instance to be announced before using the
+/// address it was last seen at
+pub const AUTO_CONNECT_GRACE: Duration = Duration::from_secs(3);
+
+pub struct BookmarksPlugin;
+
+impl Plugin for BookmarksPlugin {
+    fn build(&self, app: &mut App) {
+        let bookmarks = match Bookmarks::load(BOOKMARKS_PATH) {
+            Ok(bookmarks) => bookmarks,
+            Err(err) => {
+                warn!("Could not restore bookmarks: {err:?}");
+                Bookmarks::default()
+            }
+        };
+
+        app.insert_resource(bookmarks)
+            .init_resource::<BookmarksWindow>()
+            .init_resource::<PendingMasterCameras>()
+            .add_event::<ConnectBookmark>()
+            .add_systems(
+                Update,
+                (
+                    auto_connect.before(connect_bookmarks),
+                    connect_bookmarks,
+                    remember_mdns_addresses.run_if(resource_exists::<MdnsPeers>),
+                    apply_bookmark_preferences,
+                    pin_preferred_cameras.after(apply_bookmark_preferences),
+                    bookmarks_window,
+                    save_bookmarks.run_if(resource_changed::<Bookmarks>),
+                ),
+            );
+
+        app.add_command(
+            SurfaceCommand::new("Connection Bookmarks", |world| {
+                world.resource_mut::<BookmarksWindow>().open = true;
+            })
+            .in_menu("File"),
+        );
+    }
+}
+
+#[derive(Resource, Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct Bookmarks {
+    #[serde(default)]
+    pub entries: Vec<Bookmark>,
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct Bookmark {
+    /// Also the [`Name`] of the robot the preferences apply to
+    pub name: String,
+    /// Socket address, `unix:` path or `host:port`, connected to before anything else
+    #[serde(default)]
+    pub address: Option<String>,
+    /// mDNS instance the robot announces itself as
+    #[serde(default)]
+    pub mdns_name: Option<String>,
+    /// Where `mdns_name` was last announced
+    #[serde(default)]
+    pub last_seen: Option<SocketAddr>,
+    /// Connect on startup
+    #[serde(default)]
+    pub auto_connect: bool,
+    /// [`crate::device_profiles::DeviceProfile`] assigned to every gamepad once connected
+    #[serde(default)]
+    pub pilot_profile: Option<String>,
+    /// Camera made the master feed once connected
+    #[serde(default)]
+    pub master_camera: Option<String>,
+}
+
+/// Where a bookmark connects to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BookmarkTarget {
+    Addr(PeerAddr),
+    /// Needs a DNS lookup first
+    Host(String),
+}
+
+impl Bookmark {
+    /// Bookmarks a peer found through mDNS
+    pub fn from_mdns(instance: &str, addr: Option<SocketAddr>) -> Self {
+        Self {
+            name: instance.to_owned(),
+            mdns_name: Some(instance.to_owned()),
+            last_seen: addr,
+            ..default()
+        }
+    }
+
+    /// Where to connect given the addresses mDNS instances are currently announced at
+    pub fn resolve(&self, discovered: &BTreeMap<String, SocketAddr>) -> Option<BookmarkTarget> {
+        if let Some(address) = self.address.as_deref().filter(|it| !it.is_empty()) {
+            return Some(match address.parse::<PeerAddr>() {
+                Ok(addr) => BookmarkTarget::Addr(addr),
+                Err(_) => BookmarkTarget::Host(address.to_owned()),
+            });
+        }
+
+        let announced = self
+            .mdns_name
+            .as_ref()
+            .and_then(|name| discovered.get(name));
+
+        announced
+            .or(self.last_seen.as_ref())
+            .map(|&addr| BookmarkTarget::Addr(addr.into()))
+    }
+
+    /// Whether this bookmark would rather wait for its mDNS instance to be announced
+    fn waits_for_mdns(&self, discovered: &BTreeMap<String, SocketAddr>) -> bool {
+        self.address.as_deref().unwrap_or_default().is_empty()
+            && self
+                .mdns_name
+                .as_ref()
+                .is_some_and(|name| !discovered.contains_key(name))
+    }
+
+    fn describe_target(&self) -> String {
+        match (&self.address, &self.mdns_name) {
+            (Some(address), _) if !address.is_empty() => address.clone(),
+            (_, Some(name)) => format!("{name} (mDNS)"),
+            _ => "No address".to_owned(),
+        }
+    }
+}
+
+impl Bookmarks {
+    pub fn get(&self, name: &str) -> Option<&Bookmark> {
+        self.entries.iter().find(|it| it.name == name)
+    }
+
+    /// Replaces the bookmark at `index`, or adds a new one
+    pub fn put(&mut self, index: Option<usize>, bookmark: Bookmark) {
+        match index.and_then(|index| self.entries.get_mut(index)) {
+            Some(entry) => *entry = bookmark,
+            None => self.entries.push(bookmark),
+        }
+    }
+
+    pub fn is_bookmarked(&self, instance: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|it| it.mdns_name.as_deref() == Some(instance))
+    }
+
+    /// Records where bookmarked mDNS instances are announced, returns whether anything moved
+    pub fn update_last_seen(&mut self, discovered: &BTreeMap<String, SocketAddr>) -> bool {
+        let mut changed = false;
+
+        for bookmark in &mut self.entries {
+            let announced = bookmark
+                .mdns_name
+                .as_ref()
+                .and_then(|name| discovered.get(name));
+
+            if let Some(&addr) = announced {
+                if bookmark.last_seen != Some(addr) {
+                    bookmark.last_seen = Some(addr);
+                    changed = true;
+                }
+            }
+        }
+
+        changed
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let state = fs::read_to_string(path).context("Read bookmarks")?;
+        toml::from_str(&state).context("Parse bookmarks")
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let state = toml::to_string(self).context("Serialize bookmarks")?;
+        fs::write(path, state).context("Write bookmarks")
+    }
+}
+
+/// Connects to the bookmark with this name
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct ConnectBookmark(pub String);
+
+/// Cameras to pin as the master feed once they appear, with the robot they belong to
+#[derive(Resource, Default, Debug)]
+pub struct PendingMasterCameras(pub Vec<(NetId, String)>);
+
+#[derive(Resource, Default)]
+struct BookmarksWindow {
+    open: bool,
+    draft: BookmarkDraft,
+}
+
+/// A bookmark being added or edited, nothing is saved until it is submitted
+#[derive(Default)]
+pub struct BookmarkDraft(Option<(Option<usize>, Bookmark)>);
+
+/// Each announced mDNS instance and its first address
+pub fn discovered(peers: Option<&MdnsPeers>) -> BTreeMap<String, SocketAddr> {
+    let Some(peers) = peers else {
+        return BTreeMap::new();
+    };
+
+    peers
+        .0
+        .values()
+        .filter_map(|peer| {
+            let instance = peer.info.get_fullname().split('.').next()?;
+            let addr = peer.addresses.first()?;
+
+            Some((instance.to_owned(), *addr))
+        })
+        .collect()
+}
+
+fn connect_bookmarks(
+    mut cmds: Commands,
+    mut events: EventReader<ConnectBookmark>,
+    bookmarks: Res<Bookmarks>,
+    peers: Option<Res<MdnsPeers>>,
+    runtime: Res<TokioTasksRuntime>,
+) {
+    let discovered = discovered(peers.as_deref());
+
+    for ConnectBookmark(name) in events.read() {
+        let Some(bookmark) = bookmarks.get(name) else {
+            warn!("No bookmark named {name}");
+            continue;
+        };
+
+        match bookmark.resolve(&discovered) {
+            Some(BookmarkTarget::Addr(addrs)) => {
+                info!("Connecting to {name} at {addrs}");
+                cmds.add(move |world: &mut World| ui::connect_if_idle(world, addrs));
+            }
+            Some(BookmarkTarget::Host(host)) => {
+                info!("Connecting to {name} at {host}");
+                ui::connect_host(&runtime, host);
+            }
+            None => error!("{name} has no address and has not been announced over mDNS"),
+        }
+    }
+}
+
+fn auto_connect(
+    mut done: Local<bool>,
+    time: Res<Time<Real>>,
+    bookmarks: Res<Bookmarks>,
+    peers: Option<Res<MdnsPeers>>,
+    robots: Query<(), With<Robot>>,
+    mut connect: EventWriter<ConnectBookmark>,
+) {
+    if *done {
+        return;
+    }
+
+    let Some(bookmark) = bookmarks.entries.iter().find(|it| it.auto_connect) else {
+        *done = true;
+        return;
+    };
+
+    if !robots.is_empty() {
+        *done = true;
+        return;
+    }
+
+    let discovered = discovered(peers.as_deref());
+    if bookmark.waits_for_mdns(&discovered) && time.elapsed() < AUTO_CONNECT_GRACE {
+        return;
+    }
+
+    connect.send(ConnectBookmark(bookmark.name.clone()));
+    *done = true;
+}
+
+fn remember_mdns_addresses(mut bookmarks: ResMut<Bookmarks>, peers: Res<MdnsPeers>) {
+    if !peers.is_changed() {
+        return;
+    }
+
+    let discovered = discovered(Some(&peers));
+    if bookmarks
+        .bypass_change_detection()
+        .update_last_seen(&discovered)
+    {
+        bookmarks.set_changed();
+    }
+}
+
+fn apply_bookmark_preferences(
+    robots: Query<(&NetId, &Name), (With<Robot>, Or<(Added<Robot>, Added<Name>)>)>,
+    bookmarks: Res<Bookmarks>,
+    gamepads: Res<Gamepads>,
+    profiles: Res<DeviceProfiles>,
+    mut assignments: ResMut<DeviceAssignments>,
+    mut pending: ResMut<PendingMasterCameras>,
+) {
+    for (&robot, name) in &robots {
+        let Some(bookmark) = bookmarks.get(name.as_str()) else {
+            continue;
+        };
+
+        info!("Applying preferences of bookmark {}", bookmark.name);
+
+        if let Some(profile) = &bookmark.pilot_profile {
+            if profiles.0.contains_key(profile) {
+                for gamepad in gamepads.iter() {
+                    assignments.0.insert(gamepad, profile.clone());
+                }
+            } else {
+                warn!(
+                    "Bookmark {} uses unknown pilot profile {profile}",
+                    bookmark.name
+                );
+            }
+        }
+
+        if let Some(camera) = &bookmark.master_camera {
+            pending.0.retain(|(it, _)| *it != robot);
+            pending.0.push((robot, camera.clone()));
+        }
+    }
+}
+
+fn pin_preferred_cameras(
+    mut pending: ResMut<PendingMasterCameras>,
+    robots: Query<&NetId, With<Robot>>,
+    cameras: Query<(Entity, &Name, &RobotId), With<DisplayMarker>>,
+    mut make_master: EventWriter<MakeMaster>,
+) {
+    if pending.0.is_empty() {
+        return;
+    }
+
+    pending.0.retain(|(robot, camera_name)| {
+        if !robots.iter().any(|it| it == robot) {
+            return false;
+        }
+
+        let camera = cameras
+            .iter()
+            .find(|(_, name, &RobotId(id))| name.as_str() == camera_name && id == *robot);
+
+        match camera {
+            Some((camera, _, _)) => {
+                make_master.send(MakeMaster(camera));
+                false
+            }
+            None => true,
+        }
+    });
+}
+
+fn save_bookmarks(bookmarks: Res<Bookmarks>, mut errors: EventWriter<ErrorEvent>) {
+    if let Err(err) = bookmarks.save(BOOKMARKS_PATH) {
+        errors.send(err.context("Save bookmarks").into());
+    }
+}
+
+fn bookmarks_window(
+    mut cmds: Commands,
+    mut contexts: EguiContexts,
+    mut window: ResMut<BookmarksWindow>,
+    bookmarks: Res<Bookmarks>,
+    profiles: Res<DeviceProfiles>,
+) {
+    if !window.open {
+        return;
+    }
+
+    let context = contexts.ctx_mut();
+    let BookmarksWindow { open, draft } = &mut *window;
+
+    egui::Window::new("Connection Bookmarks")
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(open)
+        .show(context, |ui| {
+            draw(ui, &bookmarks, &profiles, draft, &mut cmds);
+        });
+}
+
+/// Lists the bookmarks with buttons to connect to, add and edit them
+pub fn draw(
+    ui: &mut egui::Ui,
+    bookmarks: &Bookmarks,
+    profiles: &DeviceProfiles,
+    draft: &mut BookmarkDraft,
+    cmds: &mut Commands,
+) {
+    ui.horizontal(|ui| {
+        ui.heading("Bookmarks:");
+
+        if draft.0.is_none() && ui.button("Add").clicked() {
+            draft.0 = Some((None, Bookmark::default()));
+        }
+    });
+
+    if bookmarks.entries.is_empty() {
+        ui.label(RichText::new("No bookmarks").weak());
+    }
+
+    for (index, bookmark) in bookmarks.entries.iter().enumerate() {
+        ui.horizontal(|ui| {
+            if ui.button("Connect").clicked() {
+                let name = bookmark.name.clone();
+                cmds.add(move |world: &mut World| world.send_event(ConnectBookmark(name)));
+            }
+
+            ui.label(&bookmark.name);
+            ui.label(RichText::new(bookmark.describe_target()).weak());
+
+            if bookmark.auto_connect {
+                ui.label(RichText::new("Auto").weak());
+            }
+
+            if ui.button("Edit").clicked() {
+                draft.0 = Some((Some(index), bookmark.clone()));
+            }
+
+            if ui.button("Remove").clicked() {
+                cmds.add(move |world: &mut World| {
+                    let mut bookmarks = world.resource_mut::<Bookmarks>();
+                    if index < bookmarks.entries.len() {
+                        bookmarks.entries.remove(index);
+                    }
+                });
+            }
+        });
+    }
+
+    let Some((index, bookmark)) = &mut draft.0 else {
+        return;
+    };
+
+    ui.add_space(10.0);
+
+    let mut submitted = false;
+    let mut cancelled = false;
+
+    egui::Grid::new("Bookmark Draft")
+        .num_columns(2)
+        .show(ui, |ui| {
+            ui.label("Name");
+            ui.text_edit_singleline(&mut bookmark.name);
+            ui.end_row();
+
+            ui.label("Address");
+            optional_text(ui, &mut bookmark.address, "robot.local:44444");
+            ui.end_row();
+
+            ui.label("mDNS Name");
+            optional_text(ui, &mut bookmark.mdns_name, "Instance name");
+            ui.end_row();
+
+            ui.label("Pilot Profile");
+            egui::ComboBox::from_id_source("Bookmark Profile")
+                .selected_text(bookmark.pilot_profile.as_deref().unwrap_or("None"))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut bookmark.pilot_profile, None, "None");
+
+                    for name in profiles.0.keys() {
+                        ui.selectable_value(&mut bookmark.pilot_profile, Some(name.clone()), name);
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Master Camera");
+            optional_text(ui, &mut bookmark.master_camera, "Camera name");
+            ui.end_row();
+
+            ui.label("Auto Connect");
+            ui.checkbox(&mut bookmark.auto_connect, "");
+            ui.end_row();
+        });
+
+    ui.horizontal(|ui| {
+        let valid = !bookmark.name.trim().is_empty();
+        submitted = ui.add_enabled(valid, egui::Button::new("Save")).clicked();
+        cancelled = ui.button("Cancel").clicked();
+    });
+
+    if submitted {
+        let index = *index;
+        let bookmark = bookmark.clone();
+
+        cmds.add(move |world: &mut World| {
+            world.resource_mut::<Bookmarks>().put(index, bookmark);
+        });
+    }
+
+    if submitted || cancelled {
+        draft.0 = None;
+    }
+}
+
+/// Bookmarks an mDNS peer, disabled once it has been
+pub fn bookmark_button(
+    ui: &mut egui::Ui,
+    bookmarks: &Bookmarks,
+    instance: &str,
+    addr: Option<SocketAddr>,
+    cmds: &mut Commands,
+) {
+    let bookmarked = bookmarks.is_bookmarked(instance);
+    let text = if bookmarked { "Bookmarked" } else { "Bookmark" };
+
+    if ui
+        .add_enabled(!bookmarked, egui::Button::new(text))
+        .clicked()
+    {
+        let bookmark = Bookmark::from_mdns(instance, addr);
+
+        cmds.add(move |world: &mut World| {
+            world.resource_mut::<Bookmarks>().put(None, bookmark);
+        });
+    }
+}
+
+/// Text field for an optional value, empty text clears it
+fn optional_text(ui: &mut egui::Ui, value: &mut Option<String>, hint: &str) {
+    let mut text = value.clone().unwrap_or_default();
+
+    if ui
+        .add(egui::TextEdit::singleline(&mut text).hint_text(hint))
+        .changed()
+    {
+        *value = Some(text).filter(|it| !it.trim().is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, net::SocketAddr};
+
+    use bevy::{
+        input::{
+            gamepad::{Gamepad, GamepadConnection, GamepadConnectionEvent, GamepadInfo, Gamepads},
+            InputPlugin,
+        },
+        prelude::*,
+    };
+    use common::{
+        components::{Robot, RobotId},
+        ecs_sync::NetId,
+    };
+    use networking::PeerAddr;
+
+    use crate::{
+        device_profiles::{DeviceAssignments, DeviceProfile, DeviceProfiles},
+        video_display_2d_master::{DisplayMarker, MakeMaster},
+    };
+
+    use super::{
+        apply_bookmark_preferences, pin_preferred_cameras, Bookmark, BookmarkTarget, Bookmarks,
+        PendingMasterCameras,
+    };
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    fn pool_robot() -> Bookmark {
+        Bookmark {
+            name: "Pool".to_owned(),
+            mdns_name: Some("pool-rov".to_owned()),
+            last_seen: Some(addr("10.0.0.5:44444")),
+            auto_connect: true,
+            pilot_profile: Some("Flight Stick".to_owned()),
+            master_camera: Some("Front".to_owned()),
+            ..default()
+        }
+    }
+
+    #[test]
+    fn bookmarks_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("bookmarks.toml");
+
+        let bookmarks = Bookmarks {
+            entries: vec![
+                pool_robot(),
+                Bookmark {
+                    name: "Sim".to_owned(),
+                    address: Some("unix:/tmp/rov.sock".to_owned()),
+                    ..default()
+                },
+            ],
+        };
+        bookmarks.save(&path).unwrap();
+        let loaded = Bookmarks::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), bookmarks);
+        assert_eq!(Bookmarks::load(&path).unwrap(), Bookmarks::default());
+    }
+
+    #[test]
+    fn typed_address_wins_over_mdns() {
+        let discovered = BTreeMap::from([("pool-rov".to_owned(), addr("10.0.0.9:44444"))]);
+
+        // Announced at a new address this session
+        let bookmark = pool_robot();
+        assert_eq!(
+            bookmark.resolve(&discovered),
+            Some(BookmarkTarget::Addr(addr("10.0.0.9:44444").into()))
+        );
+
+        // Not announced yet
+        assert_eq!(
+            bookmark.resolve(&BTreeMap::new()),
+            Some(BookmarkTarget::Addr(addr("10.0.0.5:44444").into()))
+        );
+
+        let typed = Bookmark {
+            address: Some("bench.local:44444".to_owned()),
+            ..pool_robot()
+        };
+        assert_eq!(
+            typed.resolve(&discovered),
+            Some(BookmarkTarget::Host("bench.local:44444".to_owned()))
+        );
+
+        let unix = Bookmark {
+            address: Some("unix:/tmp/rov.sock".to_owned()),
+            ..default()
+        };
+        assert_eq!(
+            unix.resolve(&discovered),
+            Some(BookmarkTarget::Addr(PeerAddr::Unix("/tmp/rov.sock".into())))
+        );
+
+        let unknown = Bookmark {
+            last_seen: None,
+            ..pool_robot()
+        };
+        assert_eq!(unknown.resolve(&BTreeMap::new()), None);
+    }
+
+    #[test]
+    fn last_seen_follows_the_announcement() {
+        let mut bookmarks = Bookmarks {
+            entries: vec![pool_robot()],
+        };
+        let discovered = BTreeMap::from([("pool-rov".to_owned(), addr("10.0.0.9:44444"))]);
+
+        assert!(bookmarks.update_last_seen(&discovered));
+        assert_eq!(bookmarks.entries[0].last_seen, Some(addr("10.0.0.9:44444")));
+        assert!(!bookmarks.update_last_seen(&discovered));
+    }
+
+    fn app() -> App {
+        let profile = DeviceProfile {
+            name: "Flight Stick".to_owned(),
+            devices: Vec::new(),
+            axes: Vec::new(),
+            buttons: Vec::new(),
+        };
+
+        let mut app = App::new();
+        app.add_plugins((MinimalPlugins, InputPlugin))
+            .insert_resource(Bookmarks {
+                entries: vec![pool_robot()],
+            })
+            .insert_resource(DeviceProfiles([(profile.name.clone(), profile)].into()))
+            .init_resource::<DeviceAssignments>()
+            .init_resource::<PendingMasterCameras>()
+            .add_event::<MakeMaster>()
+            .add_systems(
+                Update,
+                (apply_bookmark_preferences, pin_preferred_cameras).chain(),
+            );
+
+        app.world.send_event(GamepadConnectionEvent::new(
+            Gamepad::new(0),
+            GamepadConnection::Connected(GamepadInfo {
+                name: "Stick".to_owned(),
+            }),
+        ));
+        app.update();
+        assert!(app.world.resource::<Gamepads>().contains(Gamepad::new(0)));
+
+        app
+    }
+
+    fn masters(app: &mut App) -> Vec<Entity> {
+        app.world
+            .resource_mut::<Events<MakeMaster>>()
+            .drain()
+            .map(|it| it.0)
+            .collect()
+    }
+
+    #[test]
+    fn preferences_apply_when_the_robot_appears() {
+        let mut app = app();
+
+        let other = NetId::random();
+        app.world.spawn((Robot, other, Name::new("Bench")));
+        app.update();
+        assert!(app.world.resource::<DeviceAssignments>().0.is_empty());
+        assert!(app.world.resource::<PendingMasterCameras>().0.is_empty());
+
+        let robot = NetId::random();
+        app.world.spawn((Robot, robot, Name::new("Pool")));
+        app.update();
+
+        let assignments = &app.world.resource::<DeviceAssignments>().0;
+        assert_eq!(
+            assignments.get(&Gamepad::new(0)).map(String::as_str),
+            Some("Flight Stick")
+        );
+
+        // The feed shows up later
+        app.world
+            .spawn((Name::new("Front"), RobotId(other), DisplayMarker(1)));
+        app.world
+            .spawn((Name::new("Down"), RobotId(robot), DisplayMarker(1)));
+        app.update();
+        assert!(masters(&mut app).is_empty());
+
+        let front = app
+            .world
+            .spawn((Name::new("Front"), RobotId(robot), DisplayMarker(2)))
+            .id();
+        app.update();
+        assert_eq!(masters(&mut app), [front]);
+
+        // Only pinned once
+        app.update();
+        assert!(masters(&mut app).is_empty());
+        assert!(app.world.resource::<PendingMasterCameras>().0.is_empty());
+    }
+}
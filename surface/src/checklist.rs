@@ -0,0 +1,318 @@
+//! Loads the competition task checklist and exports it for the post-run report
+//!
+//! The checklist itself lives on the robot as a [`MissionChecklist`] so every surface sees the
+//! same state, this only builds the requests sent to it.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use bevy::prelude::*;
+use common::{
+    components::MissionChecklist,
+    types::checklist::{Checklist, ChecklistStep, ChecklistTask},
+};
+use serde::Deserialize;
+
+/// Where the checklist is loaded from by default
+pub const CHECKLIST_PATH: &str = "checklist.toml";
+/// Where exported checklists are saved
+pub const CHECKLISTS_DIR: &str = "checklists";
+
+pub struct ChecklistPlugin;
+
+impl Plugin for ChecklistPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ChecklistFile>();
+    }
+}
+
+/// Where the checklist panel loads the checklist from
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct ChecklistFile {
+    pub path: String,
+}
+
+impl Default for ChecklistFile {
+    fn default() -> Self {
+        Self {
+            path: CHECKLIST_PATH.to_owned(),
+        }
+    }
+}
+
+/// The checklist as written by hand, nothing checked yet
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ChecklistDefinition {
+    pub tasks: Vec<TaskDefinition>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TaskDefinition {
+    pub name: String,
+    pub points: u32,
+    #[serde(default)]
+    pub steps: Vec<String>,
+}
+
+impl ChecklistDefinition {
+    pub fn parse(toml: &str) -> anyhow::Result<Self> {
+        let definition: Self = toml::from_str(toml).context("Parse checklist")?;
+        definition.validate()?;
+
+        Ok(definition)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let toml = fs::read_to_string(path).context("Read checklist")?;
+
+        Self::parse(&toml)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.tasks.is_empty() {
+            bail!("Checklist has no tasks");
+        }
+
+        for (idx, task) in self.tasks.iter().enumerate() {
+            let number = idx + 1;
+
+            if task.name.trim().is_empty() {
+                bail!("Task {number} has no name");
+            }
+            if task.steps.iter().any(|step| step.trim().is_empty()) {
+                bail!("Task {number} has a step with no name");
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn to_checklist(&self) -> Checklist {
+        Checklist {
+            tasks: self
+                .tasks
+                .iter()
+                .map(|task| ChecklistTask {
+                    name: task.name.clone(),
+                    points: task.points,
+                    steps: task
+                        .steps
+                        .iter()
+                        .map(|step| ChecklistStep {
+                            name: step.clone(),
+                            checked: None,
+                        })
+                        .collect(),
+                    checked: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Time since the unix epoch, what checklist timestamps are measured in
+pub fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// `YYYY-MM-DD HH:MM:SS UTC`
+pub fn format_timestamp(timestamp: Duration) -> String {
+    time::OffsetDateTime::from_unix_timestamp(timestamp.as_secs() as i64)
+        .map(|it| {
+            format!(
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+                it.year(),
+                u8::from(it.month()),
+                it.day(),
+                it.hour(),
+                it.minute(),
+                it.second()
+            )
+        })
+        .unwrap_or_else(|_| "Unknown".to_owned())
+}
+
+/// One row per task followed by its steps, then the point total
+///
+/// `elapsed_s` counts from the first item checked so runs can be compared
+pub fn checklist_csv(checklist: &Checklist) -> String {
+    let first = checklist
+        .tasks
+        .iter()
+        .flat_map(|task| {
+            task.steps
+                .iter()
+                .map(|step| step.checked)
+                .chain([task.checked])
+        })
+        .flatten()
+        .min();
+
+    let mut csv = String::from("task,step,points,checked_at,elapsed_s\n");
+
+    let mut row = |task: &str, step: &str, points: Option<u32>, checked: Option<Duration>| {
+        let points = points.map(|it| it.to_string()).unwrap_or_default();
+        let (checked_at, elapsed) = match (checked, first) {
+            (Some(checked), Some(first)) => (
+                format_timestamp(checked),
+                format!("{:.1}", checked.saturating_sub(first).as_secs_f32()),
+            ),
+            _ => Default::default(),
+        };
+
+        let _ = writeln!(
+            csv,
+            "{},{},{points},{checked_at},{elapsed}",
+            csv_field(task),
+            csv_field(step)
+        );
+    };
+
+    for task in &checklist.tasks {
+        row(&task.name, "", Some(task.points), task.checked);
+
+        for step in &task.steps {
+            row(&task.name, &step.name, None, step.checked);
+        }
+    }
+
+    let _ = writeln!(
+        csv,
+        "Total,,{}/{},,",
+        checklist.points(),
+        checklist.max_points()
+    );
+
+    csv
+}
+
+/// Quotes fields that would otherwise break the row
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Writes the checklist to a new file in `dir`, named after the current time
+pub fn export_checklist(dir: &Path, checklist: &MissionChecklist) -> anyhow::Result<PathBuf> {
+    let path = dir.join(format!("checklist_{}.csv", now().as_secs()));
+
+    fs::create_dir_all(dir).context("Create checklists dir")?;
+    fs::write(&path, checklist_csv(&checklist.0)).context("Write checklist")?;
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use common::types::checklist::{ChecklistItem, ChecklistStep, ChecklistTask};
+
+    use super::{checklist_csv, ChecklistDefinition, TaskDefinition};
+
+    const CHECKLIST: &str = r#"
+        [[tasks]]
+        name = "Deploy the float"
+        points = 10
+
+        [[tasks]]
+        name = "Replace the filter"
+        points = 20
+        steps = ["Remove the old filter", "Install the new one"]
+    "#;
+
+    #[test]
+    fn parses_checklists() {
+        let definition = ChecklistDefinition::parse(CHECKLIST).unwrap();
+
+        assert_eq!(
+            definition.tasks,
+            vec![
+                TaskDefinition {
+                    name: "Deploy the float".to_owned(),
+                    points: 10,
+                    steps: vec![],
+                },
+                TaskDefinition {
+                    name: "Replace the filter".to_owned(),
+                    points: 20,
+                    steps: vec![
+                        "Remove the old filter".to_owned(),
+                        "Install the new one".to_owned()
+                    ],
+                },
+            ]
+        );
+
+        let checklist = definition.to_checklist();
+        assert_eq!(checklist.max_points(), 30);
+        assert_eq!(checklist.points(), 0);
+        assert_eq!(
+            checklist.tasks[1].steps[0],
+            ChecklistStep {
+                name: "Remove the old filter".to_owned(),
+                checked: None,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_bad_checklists() {
+        for bad in [
+            // Nothing to do
+            "tasks = []",
+            // Missing points
+            "[[tasks]]\nname = \"Float\"",
+            // Typo'd field
+            "[[tasks]]\nname = \"Float\"\npoints = 10\nstep = [\"Deploy\"]",
+            "[[tasks]]\nname = \" \"\npoints = 10",
+            "[[tasks]]\nname = \"Float\"\npoints = 10\nsteps = [\"\"]",
+            "[[tasks]]\nname = \"Float\"\npoints = -10",
+        ] {
+            assert!(ChecklistDefinition::parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn exports_checklists() {
+        let mut checklist = ChecklistDefinition::parse(CHECKLIST)
+            .unwrap()
+            .to_checklist();
+        checklist.tasks.push(ChecklistTask {
+            name: "Map the wreck, \"roughly\"".to_owned(),
+            points: 5,
+            steps: vec![],
+            checked: None,
+        });
+
+        // 2024-06-20 14:00:00 UTC
+        let start = Duration::from_secs(1_718_892_000);
+        let item = |task, step| ChecklistItem { task, step };
+        checklist.set(item(1, Some(0)), true, start);
+        checklist.set(item(1, None), true, start + Duration::from_millis(95_300));
+        checklist.set(item(2, None), true, start + Duration::from_secs(120));
+
+        assert_eq!(
+            checklist_csv(&checklist),
+            "task,step,points,checked_at,elapsed_s\n\
+             Deploy the float,,10,,\n\
+             Replace the filter,,20,2024-06-20 14:01:35 UTC,95.3\n\
+             Replace the filter,Remove the old filter,,2024-06-20 14:00:00 UTC,0.0\n\
+             Replace the filter,Install the new one,,,\n\
+             \"Map the wreck, \"\"roughly\"\"\",,5,2024-06-20 14:02:00 UTC,120.0\n\
+             Total,,25/35,,\n"
+        );
+    }
+}
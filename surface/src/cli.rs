@@ -0,0 +1,248 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use bevy::ecs::system::Resource;
+use clap::Parser;
+use common::{
+    cli::{layered_flag, CommonArgs},
+    over_run::OverRunSettings,
+    sync::LinkKey,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attitude::AttitudeReferenceFrame,
+    hud_layout::HudSettings,
+    toggle_feedback::{FeedbackClass, ToggleFeedbackSettings},
+};
+
+pub const DEFAULT_CONFIG: &str = "surface.toml";
+
+#[derive(Parser, Debug, Clone, Default, PartialEq)]
+#[command(name = "surface", about = "Runs the control station")]
+pub struct SurfaceArgs {
+    #[command(flatten)]
+    pub common: CommonArgs,
+    /// Use the dark theme
+    #[arg(long)]
+    pub dark_mode: bool,
+    /// Run without video, for operating the robot over a slow link
+    #[arg(long)]
+    pub headless: bool,
+}
+
+/// Optional config file, every setting falls back to its default when left out
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SurfaceConfig {
+    /// Milliseconds a frame may take before it is reported as an over run
+    pub frame_budget_ms: Option<f32>,
+    pub dark_mode: Option<bool>,
+    pub headless: Option<bool>,
+    /// Largest size of the HUD's attitude display, in points
+    pub hud_attitude_size: Option<f32>,
+    /// Frame the HUD's yaw, pitch and roll start out relative to, `"imu_body"` or
+    /// `{ camera = "<name>" }`
+    pub hud_attitude_frame: Option<AttitudeReferenceFrame>,
+    /// Pre-shared key file encrypting the link to the robot, the robot must use the same key
+    pub link_key_file: Option<PathBuf>,
+    /// Show an overlay confirming mode toggles
+    pub toggle_feedback: Option<bool>,
+    /// Rumble strength from 0 to 1 confirming each kind of toggle, zero disables it
+    pub toggle_rumble: BTreeMap<FeedbackClass, f32>,
+}
+
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SurfaceSettings {
+    pub dark_mode: bool,
+    /// Video plugins are disabled
+    pub headless: bool,
+}
+
+impl SurfaceArgs {
+    pub fn read_config(&self) -> anyhow::Result<SurfaceConfig> {
+        let config = self.common.read_config(Path::new(DEFAULT_CONFIG), false)?;
+
+        Ok(config.unwrap_or_default())
+    }
+
+    pub fn settings(&self, config: &SurfaceConfig) -> SurfaceSettings {
+        SurfaceSettings {
+            dark_mode: layered_flag(self.dark_mode, config.dark_mode, false),
+            headless: layered_flag(self.headless, config.headless, false),
+        }
+    }
+
+    pub fn hud_settings(&self, config: &SurfaceConfig) -> HudSettings {
+        let defaults = HudSettings::default();
+
+        HudSettings {
+            attitude_size: config.hud_attitude_size.unwrap_or(defaults.attitude_size),
+        }
+    }
+
+    pub fn attitude_frame(&self, config: &SurfaceConfig) -> AttitudeReferenceFrame {
+        config.hud_attitude_frame.clone().unwrap_or_default()
+    }
+
+    pub fn toggle_feedback_settings(&self, config: &SurfaceConfig) -> ToggleFeedbackSettings {
+        let mut settings = ToggleFeedbackSettings::default();
+
+        if let Some(enabled) = config.toggle_feedback {
+            settings.enabled = enabled;
+        }
+        settings.rumble.extend(&config.toggle_rumble);
+
+        settings
+    }
+
+    pub fn link_key(&self, config: &SurfaceConfig) -> anyhow::Result<Option<LinkKey>> {
+        self.common.link_key(config.link_key_file.as_deref())
+    }
+
+    pub fn over_run_settings(&self, config: &SurfaceConfig) -> anyhow::Result<OverRunSettings> {
+        self.common.over_run_settings(
+            config.frame_budget_ms,
+            OverRunSettings {
+                max_time: Duration::from_secs_f32(1.0 / 60.0),
+                tracy_frame_mark: false,
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use clap::{CommandFactory, Parser};
+    use common::types::ids::CameraId;
+    use networking::PresharedKey;
+
+    use crate::{
+        attitude::AttitudeReferenceFrame,
+        hud_layout::HudSettings,
+        toggle_feedback::{FeedbackClass, ToggleFeedbackSettings},
+    };
+
+    use super::{SurfaceArgs, SurfaceConfig, SurfaceSettings};
+
+    fn parse(args: &[&str]) -> SurfaceArgs {
+        SurfaceArgs::try_parse_from(["surface"].iter().chain(args)).unwrap()
+    }
+
+    #[test]
+    fn cli_overrides_config() {
+        let empty = SurfaceConfig::default();
+        assert_eq!(parse(&[]).settings(&empty), SurfaceSettings::default());
+        assert_eq!(
+            parse(&["--dark-mode", "--headless"]).settings(&empty),
+            SurfaceSettings {
+                dark_mode: true,
+                headless: true,
+            }
+        );
+
+        let config: SurfaceConfig =
+            toml::from_str("dark_mode = true\nframe_budget_ms = 40").unwrap();
+        let settings = parse(&[]).settings(&config);
+        assert!(settings.dark_mode);
+        assert!(!settings.headless);
+        assert_eq!(
+            parse(&[]).over_run_settings(&config).unwrap().max_time,
+            Duration::from_millis(40)
+        );
+        assert_eq!(
+            parse(&["--frame-budget-ms", "25"])
+                .over_run_settings(&config)
+                .unwrap()
+                .max_time,
+            Duration::from_millis(25)
+        );
+
+        // Unset falls back to the defaults
+        let over_run = parse(&[]).over_run_settings(&empty).unwrap();
+        assert_eq!(over_run.max_time, Duration::from_secs_f32(1.0 / 60.0));
+        assert!(!over_run.tracy_frame_mark);
+
+        let config: SurfaceConfig = toml::from_str("hud_attitude_size = 180").unwrap();
+        assert_eq!(parse(&[]).hud_settings(&config).attitude_size, 180.0);
+        assert_eq!(parse(&[]).hud_settings(&empty), HudSettings::default());
+    }
+
+    #[test]
+    fn attitude_frame_from_config() {
+        let empty = SurfaceConfig::default();
+        assert_eq!(
+            parse(&[]).attitude_frame(&empty),
+            AttitudeReferenceFrame::ImuBody
+        );
+
+        let config: SurfaceConfig = toml::from_str("hud_attitude_frame = \"imu_body\"").unwrap();
+        assert_eq!(
+            parse(&[]).attitude_frame(&config),
+            AttitudeReferenceFrame::ImuBody
+        );
+
+        let config: SurfaceConfig =
+            toml::from_str("hud_attitude_frame = { camera = \"Top\" }").unwrap();
+        assert_eq!(
+            parse(&[]).attitude_frame(&config),
+            AttitudeReferenceFrame::Camera(CameraId::from_static("Top"))
+        );
+    }
+
+    #[test]
+    fn toggle_feedback_from_config() {
+        let empty = SurfaceConfig::default();
+        assert_eq!(
+            parse(&[]).toggle_feedback_settings(&empty),
+            ToggleFeedbackSettings::default()
+        );
+
+        let config: SurfaceConfig = toml::from_str(
+            "toggle_feedback = false\n\
+             [toggle_rumble]\n\
+             depth_hold = 0.8\n\
+             servo = 0",
+        )
+        .unwrap();
+        let settings = parse(&[]).toggle_feedback_settings(&config);
+        assert!(!settings.enabled);
+        assert_eq!(settings.rumble(FeedbackClass::DepthHold), 0.8);
+        assert_eq!(settings.rumble(FeedbackClass::Servo), 0.0);
+
+        // Classes left out keep their default
+        assert_eq!(
+            settings.rumble(FeedbackClass::Leveling),
+            ToggleFeedbackSettings::default().rumble(FeedbackClass::Leveling)
+        );
+    }
+
+    #[test]
+    fn link_key_from_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("link.key");
+        std::fs::write(&path, "secret").unwrap();
+
+        assert_eq!(
+            parse(&[]).link_key(&SurfaceConfig::default()).unwrap(),
+            None
+        );
+
+        let config: SurfaceConfig =
+            toml::from_str(&format!("link_key_file = {:?}", path.display().to_string())).unwrap();
+        let key = parse(&[]).link_key(&config).unwrap().unwrap();
+        assert_eq!(Some(key.0), PresharedKey::from_secret(b"secret"));
+    }
+
+    #[test]
+    fn help_is_stable() {
+        let help = SurfaceArgs::command().render_help().to_string();
+
+        assert_eq!(help, include_str!("../snapshots/help.txt"));
+    }
+}
@@ -0,0 +1,848 @@
+//! Searchable list of everything the operator can do from the surface
+//!
+//! Plugins register [`SurfaceCommand`]s, which are listed in the topbar menus and in a palette
+//! opened with [`PALETTE_CHORD`]. The menus, the palette and keybindings all run commands by
+//! sending [`InvokeCommand`], so a command that asks for confirmation asks no matter where it was
+//! run from.
+
+use std::{cmp::Reverse, fmt, sync::Arc};
+
+use bevy::{ecs::query::QueryFilter, prelude::*};
+use bevy_egui::EguiContexts;
+use common::{ecs_sync::scoped::Scoped, events::AckedCommand};
+use egui::{Align2, Key};
+
+use crate::{pending_commands::PendingCommands, selected_robot::SelectedRobot};
+
+pub struct CommandPalettePlugin;
+
+impl Plugin for CommandPalettePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<CommandRegistry>()
+            .init_resource::<CommandPalette>()
+            .add_event::<InvokeCommand>()
+            .add_systems(PreUpdate, refresh_commands)
+            .add_systems(
+                Update,
+                (
+                    keybindings,
+                    palette_window.after(keybindings),
+                    confirmation_window,
+                )
+                    .in_set(CommandPaletteSet),
+            )
+            .add_systems(PostUpdate, run_commands);
+    }
+}
+
+/// Opens and closes the palette
+pub const PALETTE_CHORD: KeyChord = KeyChord::ctrl(KeyCode::KeyP);
+
+/// Most matches listed in the palette at once
+const MAX_MATCHES: usize = 12;
+
+/// The palette's windows, systems reading the keyboard that clash with it run before
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CommandPaletteSet;
+
+type CommandAction = Arc<dyn Fn(&mut World) + Send + Sync>;
+type CommandPredicate = Box<dyn Fn(&mut World) -> bool + Send + Sync>;
+
+/// Something the operator can do, listed in the palette and optionally in a topbar menu
+pub struct SurfaceCommand {
+    name: String,
+    menu: Option<&'static str>,
+    group: &'static str,
+    keybinding: Option<KeyChord>,
+    confirmation: Option<String>,
+
+    action: CommandAction,
+    enabled_if: Option<CommandPredicate>,
+    checked_if: Option<CommandPredicate>,
+    pending_if: Option<CommandPredicate>,
+
+    /// As of the last [`refresh_commands`]
+    enabled: bool,
+    checked: Option<bool>,
+    pending: bool,
+}
+
+impl SurfaceCommand {
+    pub fn new(
+        name: impl Into<String>,
+        action: impl Fn(&mut World) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            menu: None,
+            group: "",
+            keybinding: None,
+            confirmation: None,
+            action: Arc::new(action),
+            enabled_if: None,
+            checked_if: None,
+            pending_if: None,
+            enabled: true,
+            checked: None,
+            pending: false,
+        }
+    }
+
+    /// A command sending `E`
+    pub fn event<E: Event + Default>(name: impl Into<String>) -> Self {
+        Self::new(name, |world| {
+            world.send_event(E::default());
+        })
+    }
+
+    /// A command sending `E` to the [`SelectedRobot`], disabled while none is selected
+    pub fn scoped_event<E: Event + Default>(name: impl Into<String>) -> Self {
+        Self::new(name, |world| {
+            if let Some(robot) = world.resource::<SelectedRobot>().0 {
+                world.send_event(Scoped::new(robot, E::default()));
+            }
+        })
+        .enabled_if(|world| world.resource::<SelectedRobot>().0.is_some())
+    }
+
+    /// A command sending `E` to the [`SelectedRobot`] that is pending until the robot reports
+    /// `command` as done, see [`PendingCommands`]
+    pub fn acked_event<E: Event + Default>(command: AckedCommand) -> Self {
+        Self::new(command.name(), move |world| {
+            let Some(robot) = world.resource::<SelectedRobot>().0 else {
+                return;
+            };
+            let now = world.resource::<Time<Real>>().elapsed();
+
+            world.send_event(Scoped::new(robot, E::default()));
+            world
+                .resource_mut::<PendingCommands>()
+                .sent(robot, command, now);
+        })
+        .enabled_if(|world| world.resource::<SelectedRobot>().0.is_some())
+        .pending_if(move |world| {
+            let robot = world.resource::<SelectedRobot>().0;
+            robot.is_some_and(|robot| {
+                world
+                    .resource::<PendingCommands>()
+                    .is_pending(robot, command)
+            })
+        })
+    }
+
+    /// Lists the command in the topbar menu named `menu` as well as in the palette
+    pub fn in_menu(mut self, menu: &'static str) -> Self {
+        self.menu = Some(menu);
+        self
+    }
+
+    /// Menus put a separator between commands of different groups
+    pub fn in_group(mut self, group: &'static str) -> Self {
+        self.group = group;
+        self
+    }
+
+    pub fn with_keybinding(mut self, chord: KeyChord) -> Self {
+        self.keybinding = Some(chord);
+        self
+    }
+
+    /// Asks the operator to confirm with `prompt` before running the command
+    pub fn with_confirmation(mut self, prompt: impl Into<String>) -> Self {
+        self.confirmation = Some(prompt.into());
+        self
+    }
+
+    /// Only lets the command run while `predicate` holds, disabled commands are greyed out in
+    /// menus and left out of the palette
+    pub fn enabled_if(
+        mut self,
+        predicate: impl Fn(&mut World) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.enabled_if = Some(Box::new(predicate));
+        self.enabled = false;
+        self
+    }
+
+    /// Shows the command as an on/off toggle that is on while `predicate` holds
+    pub fn checked_if(
+        mut self,
+        predicate: impl Fn(&mut World) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.checked_if = Some(Box::new(predicate));
+        self.checked = Some(false);
+        self
+    }
+
+    /// Shows a spinner next to the command while `predicate` holds, for commands that take a
+    /// while to finish
+    pub fn pending_if(
+        mut self,
+        predicate: impl Fn(&mut World) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.pending_if = Some(Box::new(predicate));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn keybinding(&self) -> Option<KeyChord> {
+        self.keybinding
+    }
+
+    pub fn confirmation(&self) -> Option<&str> {
+        self.confirmation.as_deref()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Whether a toggle is on, `None` for commands that aren't toggles
+    pub fn checked(&self) -> Option<bool> {
+        self.checked
+    }
+
+    /// Whether the command was run and hasn't finished yet
+    pub fn is_pending(&self) -> bool {
+        self.pending
+    }
+}
+
+/// Whether any entity matches `F`, for use in [`SurfaceCommand::enabled_if`]
+pub fn any_matching<F: QueryFilter>(world: &mut World) -> bool {
+    world.query_filtered::<(), F>().iter(world).next().is_some()
+}
+
+/// Every registered command, in registration order
+#[derive(Resource, Default)]
+pub struct CommandRegistry {
+    commands: Vec<SurfaceCommand>,
+}
+
+impl CommandRegistry {
+    /// Adds `command`, replacing any command with the same name
+    pub fn register(&mut self, command: SurfaceCommand) {
+        match self.commands.iter_mut().find(|it| it.name == command.name) {
+            Some(existing) => *existing = command,
+            None => self.commands.push(command),
+        }
+    }
+
+    /// Replaces every command in `group`, for commands that come and go like one per camera
+    pub fn set_group(
+        &mut self,
+        group: &'static str,
+        commands: impl IntoIterator<Item = SurfaceCommand>,
+    ) {
+        self.commands.retain(|it| it.group != group);
+
+        for command in commands {
+            self.register(command.in_group(group));
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SurfaceCommand> {
+        self.commands.iter().find(|it| it.name == name)
+    }
+
+    pub fn commands(&self) -> impl Iterator<Item = &SurfaceCommand> {
+        self.commands.iter()
+    }
+
+    /// Commands listed in the topbar menu named `menu`
+    pub fn menu<'a>(&'a self, menu: &'a str) -> impl Iterator<Item = &'a SurfaceCommand> {
+        self.commands.iter().filter(move |it| it.menu == Some(menu))
+    }
+
+    /// Enabled commands matching `query`, best match first
+    ///
+    /// Commands that match equally well stay in registration order, an empty query lists every
+    /// enabled command
+    pub fn search(&self, query: &str) -> Vec<&SurfaceCommand> {
+        let mut matches: Vec<(i32, &SurfaceCommand)> = self
+            .commands
+            .iter()
+            .filter(|it| it.enabled)
+            .filter_map(|it| Some((fuzzy_score(query, &it.name)?, it)))
+            .collect();
+
+        matches.sort_by_key(|&(score, _)| Reverse(score));
+        matches.into_iter().map(|(_, it)| it).collect()
+    }
+}
+
+pub trait AppCommandExt {
+    fn add_command(&mut self, command: SurfaceCommand) -> &mut Self;
+}
+
+impl AppCommandExt for App {
+    fn add_command(&mut self, command: SurfaceCommand) -> &mut Self {
+        self.world
+            .get_resource_or_insert_with(CommandRegistry::default)
+            .register(command);
+
+        self
+    }
+}
+
+/// Points for each matched character
+const MATCH_SCORE: i32 = 1;
+/// Extra points for a match at the start of a word, so initials like "csl" find
+/// "Calibrate Sea Level"
+const WORD_START_BONUS: i32 = 8;
+/// Extra points for a match right after the previous one
+const CONSECUTIVE_BONUS: i32 = 5;
+/// Most points lost to the characters skipped before a match
+const MAX_GAP_PENALTY: i32 = 4;
+
+/// How well `query` matches `candidate`, `None` if it doesn't
+///
+/// Every character of the query has to appear in the candidate in order, ignoring case and
+/// spaces in the query. Of all the ways the characters can line up, the best scoring one counts.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i32> {
+    let query: Vec<char> = query
+        .chars()
+        .filter(|it| !it.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    let candidate: Vec<char> = candidate.chars().collect();
+
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let lowercase: Vec<char> = candidate
+        .iter()
+        .map(|it| it.to_lowercase().next().unwrap_or(*it))
+        .collect();
+    let word_start = |idx: usize| {
+        let Some(&previous) = idx.checked_sub(1).and_then(|it| candidate.get(it)) else {
+            return true;
+        };
+
+        !previous.is_alphanumeric() || (previous.is_lowercase() && candidate[idx].is_uppercase())
+    };
+    let gap_penalty = |skipped: usize| (skipped as i32).min(MAX_GAP_PENALTY);
+
+    // Best score for the query so far with its last character matched at each position
+    let mut best: Vec<Option<i32>> = vec![None; candidate.len()];
+
+    for (query_idx, &query_char) in query.iter().enumerate() {
+        let mut next = vec![None; candidate.len()];
+
+        for (idx, &char) in lowercase.iter().enumerate() {
+            if char != query_char {
+                continue;
+            }
+
+            let here = MATCH_SCORE + if word_start(idx) { WORD_START_BONUS } else { 0 };
+
+            next[idx] = if query_idx == 0 {
+                Some(here - gap_penalty(idx))
+            } else {
+                best[..idx]
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(previous, score)| {
+                        let bonus = if previous + 1 == idx {
+                            CONSECUTIVE_BONUS
+                        } else {
+                            -gap_penalty(idx - previous - 1)
+                        };
+
+                        Some((*score)? + here + bonus)
+                    })
+                    .max()
+            };
+        }
+
+        best = next;
+    }
+
+    best.into_iter().flatten().max()
+}
+
+/// A key pressed while holding modifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyChord {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub key: KeyCode,
+}
+
+impl KeyChord {
+    pub const fn ctrl(key: KeyCode) -> Self {
+        Self {
+            ctrl: true,
+            shift: false,
+            key,
+        }
+    }
+
+    pub const fn ctrl_shift(key: KeyCode) -> Self {
+        Self {
+            ctrl: true,
+            shift: true,
+            key,
+        }
+    }
+
+    /// Pressed this frame with exactly these modifiers held
+    pub fn just_pressed(&self, keys: &ButtonInput<KeyCode>) -> bool {
+        let ctrl = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+        let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+        ctrl == self.ctrl && shift == self.shift && keys.just_pressed(self.key)
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.ctrl {
+            write!(f, "Ctrl+")?;
+        }
+        if self.shift {
+            write!(f, "Shift+")?;
+        }
+
+        let key = format!("{:?}", self.key);
+        let key = key
+            .strip_prefix("Key")
+            .or_else(|| key.strip_prefix("Digit"))
+            .unwrap_or(&key);
+
+        write!(f, "{key}")
+    }
+}
+
+/// Runs the named command, after asking for confirmation if it needs it
+#[derive(Event, Debug, Clone, PartialEq, Eq)]
+pub struct InvokeCommand {
+    pub name: String,
+    confirmed: bool,
+}
+
+impl InvokeCommand {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            confirmed: false,
+        }
+    }
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct CommandPalette {
+    open: bool,
+    query: String,
+    selected: usize,
+    /// Command waiting on the operator to confirm it
+    confirming: Option<String>,
+}
+
+impl CommandPalette {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub fn open(&mut self) {
+        self.open = true;
+    }
+
+    /// Closes the palette, clearing the search
+    pub fn close(&mut self) {
+        self.open = false;
+        self.query.clear();
+        self.selected = 0;
+    }
+
+    pub fn toggle(&mut self) {
+        if self.open {
+            self.close();
+        } else {
+            self.open();
+        }
+    }
+
+    pub fn confirming(&self) -> Option<&str> {
+        self.confirming.as_deref()
+    }
+}
+
+/// Lists the commands of `menu`, put where the menu's other items are drawn
+pub fn menu_items(
+    ui: &mut egui::Ui,
+    registry: &CommandRegistry,
+    menu: &str,
+    invoke: &mut EventWriter<InvokeCommand>,
+) {
+    let mut last_group = None;
+
+    for command in registry.menu(menu) {
+        if last_group.is_some_and(|it| it != command.group) {
+            ui.separator();
+        }
+        last_group = Some(command.group);
+
+        let response = match command.checked {
+            Some(checked) => ui.add_enabled(
+                command.enabled,
+                egui::SelectableLabel::new(checked, command.name()),
+            ),
+            None => {
+                let mut button = egui::Button::new(command.name());
+                if let Some(chord) = command.keybinding {
+                    button = button.shortcut_text(chord.to_string());
+                }
+
+                if command.pending {
+                    ui.horizontal(|ui| {
+                        let response = ui.add_enabled(command.enabled, button);
+                        ui.spinner();
+
+                        response
+                    })
+                    .inner
+                } else {
+                    ui.add_enabled(command.enabled, button)
+                }
+            }
+        };
+
+        if response.clicked() {
+            invoke.send(InvokeCommand::new(command.name()));
+        }
+    }
+}
+
+fn refresh_commands(world: &mut World) {
+    world.resource_scope(|world, mut registry: Mut<CommandRegistry>| {
+        for command in &mut registry.commands {
+            command.enabled = command.enabled_if.as_ref().is_none_or(|it| it(world));
+            command.checked = command.checked_if.as_ref().map(|it| it(world));
+            command.pending = command.pending_if.as_ref().is_some_and(|it| it(world));
+        }
+    });
+}
+
+fn keybindings(
+    mut contexts: EguiContexts,
+    keys: Res<ButtonInput<KeyCode>>,
+    registry: Res<CommandRegistry>,
+    mut palette: ResMut<CommandPalette>,
+    mut invoke: EventWriter<InvokeCommand>,
+) {
+    // Keys pressed while typing into a text field, like the palette's search, aren't meant as
+    // commands
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    if PALETTE_CHORD.just_pressed(&keys) {
+        palette.toggle();
+        return;
+    }
+
+    for command in registry.commands() {
+        if command.keybinding.is_some_and(|it| it.just_pressed(&keys)) {
+            invoke.send(InvokeCommand::new(command.name()));
+        }
+    }
+}
+
+fn palette_window(
+    mut contexts: EguiContexts,
+    registry: Res<CommandRegistry>,
+    mut palette: ResMut<CommandPalette>,
+    mut invoke: EventWriter<InvokeCommand>,
+) {
+    if !palette.open {
+        return;
+    }
+
+    let ctx = contexts.ctx_mut();
+    let (up, down, enter, escape) = ctx.input(|it| {
+        (
+            it.key_pressed(Key::ArrowUp),
+            it.key_pressed(Key::ArrowDown),
+            it.key_pressed(Key::Enter),
+            it.key_pressed(Key::Escape),
+        )
+    });
+
+    if escape {
+        palette.close();
+        return;
+    }
+
+    let palette = &mut *palette;
+    let mut matches = registry.search(&palette.query);
+    matches.truncate(MAX_MATCHES);
+
+    if down {
+        palette.selected += 1;
+    }
+    if up {
+        palette.selected = palette.selected.saturating_sub(1);
+    }
+    palette.selected = palette.selected.min(matches.len().saturating_sub(1));
+
+    let mut run = matches
+        .get(palette.selected)
+        .filter(|_| enter)
+        .map(|it| it.name().to_owned());
+
+    egui::Window::new("Command Palette")
+        .title_bar(false)
+        .anchor(Align2::CENTER_TOP, [0.0, 80.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(ctx, |ui| {
+            let search = ui.add(
+                egui::TextEdit::singleline(&mut palette.query)
+                    .hint_text("Search commands")
+                    .desired_width(400.0),
+            );
+            search.request_focus();
+            if search.changed() {
+                palette.selected = 0;
+            }
+
+            ui.separator();
+
+            if matches.is_empty() {
+                ui.label("No matching commands");
+            }
+
+            for (idx, command) in matches.iter().enumerate() {
+                let mut text = command.name().to_owned();
+                if command.checked == Some(true) {
+                    text.push_str(" (On)");
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.selectable_label(idx == palette.selected, text).clicked() {
+                        run = Some(command.name().to_owned());
+                    }
+
+                    if let Some(chord) = command.keybinding {
+                        ui.weak(chord.to_string());
+                    }
+                });
+            }
+        });
+
+    if let Some(name) = run {
+        palette.close();
+        invoke.send(InvokeCommand::new(name));
+    }
+}
+
+fn confirmation_window(
+    mut contexts: EguiContexts,
+    registry: Res<CommandRegistry>,
+    mut palette: ResMut<CommandPalette>,
+    mut invoke: EventWriter<InvokeCommand>,
+) {
+    let Some(name) = palette.confirming.clone() else {
+        return;
+    };
+    let Some(prompt) = registry.get(&name).and_then(|it| it.confirmation()) else {
+        palette.confirming = None;
+        return;
+    };
+
+    let mut confirmed = None;
+
+    // Only a click confirms, enter could still be held from picking the command in the palette
+    egui::Window::new(format!("{name}?"))
+        .anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            ui.label(prompt);
+
+            ui.horizontal(|ui| {
+                if ui.button(name.as_str()).clicked() {
+                    confirmed = Some(true);
+                }
+                if ui.button("Cancel").clicked() {
+                    confirmed = Some(false);
+                }
+            });
+        });
+
+    match confirmed {
+        Some(true) => {
+            palette.confirming = None;
+            invoke.send(InvokeCommand {
+                name,
+                confirmed: true,
+            });
+        }
+        Some(false) => palette.confirming = None,
+        None => {}
+    }
+}
+
+fn run_commands(world: &mut World) {
+    let invoked: Vec<InvokeCommand> = world
+        .resource_mut::<Events<InvokeCommand>>()
+        .drain()
+        .collect();
+
+    for invoke in invoked {
+        let Some(command) = world.resource::<CommandRegistry>().get(&invoke.name) else {
+            warn!("No command named {:?}", invoke.name);
+            continue;
+        };
+        let (enabled, needs_confirmation) = (command.enabled, command.confirmation.is_some());
+        let action = command.action.clone();
+
+        if !enabled {
+            warn!("{} is unavailable right now", invoke.name);
+            continue;
+        }
+
+        if needs_confirmation && !invoke.confirmed {
+            world.resource_mut::<CommandPalette>().confirming = Some(invoke.name);
+            continue;
+        }
+
+        info!("Running {}", invoke.name);
+        action(world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use super::{
+        fuzzy_score, refresh_commands, run_commands, CommandPalette, CommandRegistry,
+        InvokeCommand, SurfaceCommand,
+    };
+
+    fn registry(names: &[&str]) -> CommandRegistry {
+        let mut registry = CommandRegistry::default();
+        for name in names {
+            registry.register(SurfaceCommand::new(*name, |_| {}));
+        }
+
+        registry
+    }
+
+    fn names(matches: Vec<&SurfaceCommand>) -> Vec<&str> {
+        matches.into_iter().map(|it| it.name()).collect()
+    }
+
+    #[test]
+    fn fuzzy_ranking() {
+        let registry = registry(&[
+            "Disarm",
+            "Reset Tether Turns",
+            "Arm",
+            "Calibrate Sea Level",
+            "Resync Cameras",
+            "Reset Servos",
+        ]);
+
+        // Matching from the start of a word beats matching inside one
+        assert_eq!(names(registry.search("arm")), ["Arm", "Disarm"]);
+        assert_eq!(names(registry.search("ARM")), ["Arm", "Disarm"]);
+
+        // Initials find commands
+        assert_eq!(names(registry.search("csl")), ["Calibrate Sea Level"]);
+        assert_eq!(names(registry.search("rs"))[0], "Reset Servos");
+        assert_eq!(names(registry.search("reset s"))[0], "Reset Servos");
+
+        assert!(registry.search("xyz").is_empty());
+        assert_eq!(registry.search("").len(), 6);
+
+        // Characters have to appear in order
+        assert_eq!(fuzzy_score("mra", "Arm"), None);
+        assert!(
+            fuzzy_score("sea", "Calibrate Sea Level") > fuzzy_score("sea", "Reset Servos Area")
+        );
+    }
+
+    #[derive(Resource, Default)]
+    struct Ran(Vec<&'static str>);
+
+    #[derive(Component)]
+    struct Thing;
+
+    fn command_app() -> App {
+        let mut app = App::new();
+        app.init_resource::<Ran>()
+            .init_resource::<CommandPalette>()
+            .add_event::<InvokeCommand>()
+            .add_systems(PreUpdate, refresh_commands)
+            .add_systems(PostUpdate, run_commands);
+
+        let mut registry = CommandRegistry::default();
+        registry.register(
+            SurfaceCommand::new("Needs Thing", |world| {
+                world.resource_mut::<Ran>().0.push("Needs Thing");
+            })
+            .enabled_if(super::any_matching::<With<Thing>>),
+        );
+        registry.register(
+            SurfaceCommand::new("Dangerous", |world| {
+                world.resource_mut::<Ran>().0.push("Dangerous");
+            })
+            .with_confirmation("Really?"),
+        );
+        app.insert_resource(registry);
+
+        app
+    }
+
+    #[test]
+    fn disabled_commands_are_filtered() {
+        let mut app = command_app();
+        app.update();
+
+        let registry = app.world.resource::<CommandRegistry>();
+        assert!(!registry.get("Needs Thing").unwrap().is_enabled());
+        assert_eq!(names(registry.search("")), ["Dangerous"]);
+        assert!(registry.search("thing").is_empty());
+
+        // Running it anyway does nothing
+        app.world.send_event(InvokeCommand::new("Needs Thing"));
+        app.update();
+        assert!(app.world.resource::<Ran>().0.is_empty());
+
+        app.world.spawn(Thing);
+        app.update();
+        let registry = app.world.resource::<CommandRegistry>();
+        assert_eq!(names(registry.search("thing")), ["Needs Thing"]);
+
+        app.world.send_event(InvokeCommand::new("Needs Thing"));
+        app.update();
+        assert_eq!(app.world.resource::<Ran>().0, ["Needs Thing"]);
+    }
+
+    #[test]
+    fn dangerous_commands_wait_for_confirmation() {
+        let mut app = command_app();
+
+        app.world.send_event(InvokeCommand::new("Dangerous"));
+        app.update();
+        assert!(app.world.resource::<Ran>().0.is_empty());
+        assert_eq!(
+            app.world.resource::<CommandPalette>().confirming(),
+            Some("Dangerous")
+        );
+
+        app.world.send_event(InvokeCommand {
+            name: "Dangerous".to_owned(),
+            confirmed: true,
+        });
+        app.update();
+        assert_eq!(app.world.resource::<Ran>().0, ["Dangerous"]);
+    }
+}
@@ -0,0 +1,775 @@
+//! Works out why the surface can't reach the robot
+//!
+//! Diagnosing runs a fixed sequence of [`CheckKind`]s against the address typed into the Not
+//! Connected window, each one passes, fails with a suggested fix, or is skipped when a check it
+//! depends on didn't pass. [`Diagnosis`] only sequences the steps, the checks themselves run on
+//! the Tokio runtime and report back through the main thread. Cancelling aborts the task, a
+//! probe already in flight gives up on its own after [`PROBE_TIMEOUT`].
+//!
+//! The reachability check opens and drops a raw connection, the robot logs it as a failed
+//! handshake.
+
+use std::{
+    io::ErrorKind,
+    net::SocketAddr,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use bevy::prelude::*;
+use bevy_tokio_tasks::{TaskContext, TokioTasksRuntime};
+use common::{
+    protocol::Protocol,
+    sync::{LinkKey, MdnsPeers},
+};
+use egui::{Color32, RichText};
+use networking::{error::NetError, Event as NetEvent, Networking, NetworkingOptions, PresharedKey};
+use tokio::{net::TcpStream, task::JoinHandle, time::timeout};
+
+pub const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub struct ConnectionWizardPlugin;
+
+impl Plugin for ConnectionWizardPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ConnectionWizard>();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CheckKind {
+    /// The address resolves, through DNS or an mDNS announcement
+    Resolve,
+    /// Something accepts TCP connections on the sync port
+    Reach,
+    /// The robot's mDNS announcement reaches this machine
+    Announce,
+    /// The robot answers a probe and no other surface is connected to it
+    Probe,
+}
+
+impl CheckKind {
+    pub const ALL: [CheckKind; 4] = [
+        CheckKind::Resolve,
+        CheckKind::Reach,
+        CheckKind::Announce,
+        CheckKind::Probe,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CheckKind::Resolve => "Resolve address",
+            CheckKind::Reach => "Reach sync port",
+            CheckKind::Announce => "mDNS announcement",
+            CheckKind::Probe => "Robot answers",
+        }
+    }
+
+    /// The check that has to pass before this one can run
+    pub fn requires(&self) -> Option<CheckKind> {
+        match self {
+            CheckKind::Resolve | CheckKind::Announce => None,
+            CheckKind::Reach => Some(CheckKind::Resolve),
+            CheckKind::Probe => Some(CheckKind::Reach),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Failure {
+    MissingPort,
+    Unresolved {
+        /// Whether it was a `.local` name, which only resolves with mDNS support
+        mdns: bool,
+    },
+    Refused,
+    TimedOut,
+    Unreachable(String),
+    /// No announcements at all were seen
+    NoAnnouncements,
+    /// Announcements were seen, none of them from the target
+    NotAnnounced {
+        others: Vec<String>,
+    },
+    /// Other surfaces are already connected
+    Taken(Vec<String>),
+    LinkKey(String),
+    NoReply,
+}
+
+impl Failure {
+    pub fn describe(&self) -> String {
+        match self {
+            Failure::MissingPort => "No port given".to_owned(),
+            Failure::Unresolved { .. } => "Could not resolve the address".to_owned(),
+            Failure::Refused => "Connection refused".to_owned(),
+            Failure::TimedOut => "Connection timed out".to_owned(),
+            Failure::Unreachable(err) => format!("Could not connect: {err}"),
+            Failure::NoAnnouncements => "No announcements seen".to_owned(),
+            Failure::NotAnnounced { others } => {
+                format!("Not announced, only saw {}", others.join(", "))
+            }
+            Failure::Taken(others) => format!("Already connected to {}", others.join(", ")),
+            Failure::LinkKey(err) => err.clone(),
+            Failure::NoReply => "No reply to the probe".to_owned(),
+        }
+    }
+
+    /// What to try next
+    pub fn suggestion(&self) -> &'static str {
+        match self {
+            Failure::MissingPort => "Add the sync port after the address, like 10.0.0.2:44444",
+            Failure::Unresolved { mdns: true } => {
+                ".local names need avahi or Bonjour on this machine, try the robot's IP instead"
+            }
+            Failure::Unresolved { mdns: false } => "Check the name for typos or use the robot's IP",
+            Failure::Refused => {
+                "Nothing is listening there, check the robot software is running and the port"
+            }
+            Failure::TimedOut => {
+                "Nothing answered, check the tether, the IP, and that this machine is on the \
+                 robot's subnet"
+            }
+            Failure::Unreachable(_) => {
+                "This machine has no route there, check its network settings"
+            }
+            Failure::NoAnnouncements => {
+                "Announcements aren't reaching this machine, multicast may be blocked, connect \
+                 by IP"
+            }
+            Failure::NotAnnounced { .. } => "Check this is the right robot's address",
+            Failure::Taken(_) => "Disconnect the other surface first",
+            Failure::LinkKey(_) => "Give the robot and this surface the same link key",
+            Failure::NoReply => "The robot may be overloaded or running older software",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepStatus {
+    Pending,
+    Running,
+    Passed(String),
+    Failed(Failure),
+    /// A check it depends on didn't pass
+    Skipped,
+    Cancelled,
+}
+
+/// Sequences a diagnosis, the checks are run by whoever drives it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnosis {
+    pub target: String,
+    steps: Vec<(CheckKind, StepStatus)>,
+}
+
+impl Diagnosis {
+    pub fn new(target: String) -> Self {
+        Self {
+            target,
+            steps: CheckKind::ALL
+                .into_iter()
+                .map(|kind| (kind, StepStatus::Pending))
+                .collect(),
+        }
+    }
+
+    pub fn steps(&self) -> &[(CheckKind, StepStatus)] {
+        &self.steps
+    }
+
+    pub fn status(&self, kind: CheckKind) -> Option<&StepStatus> {
+        self.steps
+            .iter()
+            .find(|(it, _)| *it == kind)
+            .map(|(_, status)| status)
+    }
+
+    /// Marks the next check as running, skipping those that can't run, `None` once done
+    pub fn next(&mut self) -> Option<CheckKind> {
+        if self.is_running() {
+            return None;
+        }
+
+        for idx in 0..self.steps.len() {
+            let (kind, status) = &self.steps[idx];
+            if *status != StepStatus::Pending {
+                continue;
+            }
+
+            let blocked = kind.requires().is_some_and(|required| {
+                !matches!(self.status(required), Some(StepStatus::Passed(_)))
+            });
+            if blocked {
+                self.steps[idx].1 = StepStatus::Skipped;
+                continue;
+            }
+
+            let kind = *kind;
+            self.steps[idx].1 = StepStatus::Running;
+            return Some(kind);
+        }
+
+        None
+    }
+
+    /// Records the outcome of a running check, anything else is ignored
+    pub fn record(&mut self, kind: CheckKind, result: Result<String, Failure>) {
+        let Some((_, status)) = self.steps.iter_mut().find(|(it, _)| *it == kind) else {
+            return;
+        };
+
+        if *status == StepStatus::Running {
+            *status = match result {
+                Ok(detail) => StepStatus::Passed(detail),
+                Err(failure) => StepStatus::Failed(failure),
+            };
+        }
+    }
+
+    pub fn cancel(&mut self) {
+        for (_, status) in &mut self.steps {
+            if matches!(status, StepStatus::Pending | StepStatus::Running) {
+                *status = StepStatus::Cancelled;
+            }
+        }
+    }
+
+    fn is_running(&self) -> bool {
+        self.steps
+            .iter()
+            .any(|(_, status)| *status == StepStatus::Running)
+    }
+
+    /// Whether no check is left to run
+    pub fn is_finished(&self) -> bool {
+        self.steps
+            .iter()
+            .all(|(_, status)| !matches!(status, StepStatus::Pending | StepStatus::Running))
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct ConnectionWizard {
+    pub diagnosis: Option<Diagnosis>,
+    /// Tells results from a cancelled run apart from the current one
+    run: u64,
+    task: Option<JoinHandle<()>>,
+}
+
+impl ConnectionWizard {
+    fn next(&mut self, run: u64) -> Option<CheckKind> {
+        if run != self.run {
+            return None;
+        }
+
+        self.diagnosis.as_mut()?.next()
+    }
+
+    fn record(&mut self, run: u64, kind: CheckKind, result: Result<String, Failure>) {
+        if run != self.run {
+            return;
+        }
+
+        if let Some(diagnosis) = &mut self.diagnosis {
+            diagnosis.record(kind, result);
+        }
+    }
+
+    pub fn cancel(&mut self) {
+        self.run += 1;
+
+        if let Some(task) = self.task.take() {
+            task.abort();
+        }
+        if let Some(diagnosis) = &mut self.diagnosis {
+            diagnosis.cancel();
+        }
+    }
+}
+
+/// A robot's mDNS announcement, copied out of [`MdnsPeers`] when a diagnosis starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    pub name: String,
+    pub hostname: String,
+    pub addresses: Vec<SocketAddr>,
+}
+
+/// Starts diagnosing `target`, replacing any diagnosis already running
+pub fn start(world: &mut World, target: String) {
+    let announcements = world
+        .get_resource::<MdnsPeers>()
+        .map(|peers| {
+            peers
+                .0
+                .values()
+                .map(|peer| Announcement {
+                    name: peer
+                        .info
+                        .get_fullname()
+                        .split('.')
+                        .next()
+                        .unwrap_or("Unknown")
+                        .to_owned(),
+                    hostname: peer.info.get_hostname().trim_end_matches('.').to_owned(),
+                    addresses: peer.addresses.clone(),
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let key = world.get_resource::<LinkKey>().map(|it| it.0);
+
+    let mut wizard = world.resource_mut::<ConnectionWizard>();
+    wizard.cancel();
+    wizard.diagnosis = Some(Diagnosis::new(target.clone()));
+    let run = wizard.run;
+
+    let task = world
+        .resource::<TokioTasksRuntime>()
+        .spawn_background_task(move |ctx| diagnose(ctx, run, target, announcements, key));
+    world.resource_mut::<ConnectionWizard>().task = Some(task);
+}
+
+pub fn cancel(world: &mut World) {
+    world.resource_mut::<ConnectionWizard>().cancel();
+}
+
+async fn diagnose(
+    mut ctx: TaskContext,
+    run: u64,
+    target: String,
+    announcements: Vec<Announcement>,
+    key: Option<PresharedKey>,
+) {
+    let mut resolved = Vec::new();
+    let mut reachable = None;
+
+    loop {
+        let next = ctx
+            .run_on_main_thread(move |ctx| ctx.world.resource_mut::<ConnectionWizard>().next(run))
+            .await;
+        let Some(kind) = next else {
+            break;
+        };
+
+        let result = match kind {
+            CheckKind::Resolve => resolve(&target, &announcements).await.map(|addrs| {
+                resolved = addrs;
+                format!("Resolved to {}", resolved[0].ip())
+            }),
+            CheckKind::Reach => reach(&resolved).await.map(|addr| {
+                reachable = Some(addr);
+                format!("Connected to {addr}")
+            }),
+            CheckKind::Announce => check_announced(&target, &resolved, &announcements),
+            CheckKind::Probe => match reachable {
+                Some(addr) => tokio::task::spawn_blocking(move || probe(addr, key))
+                    .await
+                    .unwrap_or(Err(Failure::NoReply))
+                    .and_then(probe_outcome),
+                None => Err(Failure::NoReply),
+            },
+        };
+
+        ctx.run_on_main_thread(move |ctx| {
+            ctx.world
+                .resource_mut::<ConnectionWizard>()
+                .record(run, kind, result);
+        })
+        .await;
+    }
+}
+
+/// Splits off the host, `None` when no port is given
+fn host_of(target: &str) -> Option<&str> {
+    let (host, port) = target.rsplit_once(':')?;
+    port.parse::<u16>().ok()?;
+
+    Some(host.trim_start_matches('[').trim_end_matches(']'))
+}
+
+async fn resolve(target: &str, announcements: &[Announcement]) -> Result<Vec<SocketAddr>, Failure> {
+    let host = host_of(target).ok_or(Failure::MissingPort)?;
+
+    let resolved = timeout(RESOLVE_TIMEOUT, tokio::net::lookup_host(target)).await;
+    if let Ok(Ok(addrs)) = resolved {
+        let addrs = addrs.collect::<Vec<_>>();
+        if !addrs.is_empty() {
+            return Ok(addrs);
+        }
+    }
+
+    // The OS may not do mDNS even though we saw the announcement ourselves
+    let port = target
+        .rsplit_once(':')
+        .and_then(|(_, port)| port.parse::<u16>().ok())
+        .ok_or(Failure::MissingPort)?;
+    let announced = announcements
+        .iter()
+        .filter(|it| it.hostname.eq_ignore_ascii_case(host))
+        .flat_map(|it| &it.addresses)
+        .map(|addr| SocketAddr::new(addr.ip(), port))
+        .collect::<Vec<_>>();
+
+    if announced.is_empty() {
+        Err(Failure::Unresolved {
+            mdns: host.ends_with(".local"),
+        })
+    } else {
+        Ok(announced)
+    }
+}
+
+async fn reach(addrs: &[SocketAddr]) -> Result<SocketAddr, Failure> {
+    let mut failure = Failure::TimedOut;
+
+    for addr in addrs {
+        failure = match timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(Ok(_)) => return Ok(*addr),
+            Ok(Err(err)) if err.kind() == ErrorKind::ConnectionRefused => Failure::Refused,
+            Ok(Err(err)) if err.kind() == ErrorKind::TimedOut => Failure::TimedOut,
+            Ok(Err(err)) => Failure::Unreachable(err.to_string()),
+            Err(_) => Failure::TimedOut,
+        };
+    }
+
+    Err(failure)
+}
+
+pub fn check_announced(
+    target: &str,
+    resolved: &[SocketAddr],
+    announcements: &[Announcement],
+) -> Result<String, Failure> {
+    if announcements.is_empty() {
+        return Err(Failure::NoAnnouncements);
+    }
+
+    let host = host_of(target).unwrap_or(target);
+    let announced = announcements.iter().find(|it| {
+        it.hostname.eq_ignore_ascii_case(host)
+            || it
+                .addresses
+                .iter()
+                .any(|addr| resolved.iter().any(|it| it.ip() == addr.ip()))
+    });
+
+    match announced {
+        Some(announcement) => Ok(format!("Announced as {}", announcement.name)),
+        None => Err(Failure::NotAnnounced {
+            others: announcements.iter().map(|it| it.name.clone()).collect(),
+        }),
+    }
+}
+
+pub fn probe_outcome(others: Vec<String>) -> Result<String, Failure> {
+    if others.is_empty() {
+        Ok("No other surface connected".to_owned())
+    } else {
+        Err(Failure::Taken(others))
+    }
+}
+
+/// Connects with a throwaway [`Networking`] and asks who else is connected
+fn probe(addr: SocketAddr, key: Option<PresharedKey>) -> Result<Vec<String>, Failure> {
+    let networking = Networking::<Protocol>::with_options(NetworkingOptions {
+        preshared_key: key,
+        ..Default::default()
+    })
+    .map_err(|_| Failure::NoReply)?;
+    let messenger = networking.messenger();
+
+    let (tx, rx) = mpsc::channel();
+    let worker = thread::Builder::new()
+        .name("Probe".to_owned())
+        .spawn(move || {
+            networking.start(|event| {
+                let _ = tx.send(event);
+            })
+        })
+        .map_err(|_| Failure::NoReply)?;
+
+    let _ = messenger.connect_to(addr);
+    let _ = messenger.wake();
+
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    let result = loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+
+        match rx.recv_timeout(remaining) {
+            Ok(NetEvent::Conected(token, _)) => {
+                let _ = messenger.send_packet(token, Protocol::Probe);
+                let _ = messenger.wake();
+            }
+            Ok(NetEvent::Data(_, Protocol::ProbeReply { peers })) => break Ok(peers),
+            Ok(NetEvent::Error(_, err)) if is_key_error(&err) => {
+                break Err(Failure::LinkKey(err.to_string()));
+            }
+            Ok(NetEvent::Disconnect(_)) | Err(_) => break Err(Failure::NoReply),
+            Ok(_) => {}
+        }
+    };
+
+    let _ = messenger.shutdown();
+    let _ = messenger.wake();
+    let _ = worker.join();
+
+    result
+}
+
+fn is_key_error(err: &NetError) -> bool {
+    match err {
+        NetError::KeyRequired
+        | NetError::PeerUnencrypted
+        | NetError::KeyMismatch
+        | NetError::Unauthenticated => true,
+        NetError::Chain(_, err) => is_key_error(err),
+        _ => false,
+    }
+}
+
+/// Lists the steps of the current diagnosis, with a way to cancel or dismiss it
+pub fn draw(ui: &mut egui::Ui, wizard: &ConnectionWizard, cmds: &mut Commands) {
+    let Some(diagnosis) = &wizard.diagnosis else {
+        return;
+    };
+
+    ui.add_space(10.0);
+    ui.horizontal(|ui| {
+        ui.heading(format!("Diagnosing {}", diagnosis.target));
+
+        if diagnosis.is_finished() {
+            if ui.button("Close").clicked() {
+                cmds.add(|world: &mut World| {
+                    world.resource_mut::<ConnectionWizard>().diagnosis = None;
+                });
+            }
+        } else if ui.button("Cancel").clicked() {
+            cmds.add(cancel);
+        }
+    });
+
+    for (kind, status) in diagnosis.steps() {
+        let (icon, color) = match status {
+            StepStatus::Pending => ("·", Color32::GRAY),
+            StepStatus::Running => ("…", Color32::YELLOW),
+            StepStatus::Passed(_) => ("✔", Color32::GREEN),
+            StepStatus::Failed(_) => ("✖", Color32::RED),
+            StepStatus::Skipped | StepStatus::Cancelled => ("–", Color32::GRAY),
+        };
+
+        ui.horizontal(|ui| {
+            ui.label(RichText::new(icon).color(color));
+            ui.label(kind.label());
+
+            match status {
+                StepStatus::Passed(detail) => {
+                    ui.label(RichText::new(detail).weak());
+                }
+                StepStatus::Failed(failure) => {
+                    ui.label(RichText::new(failure.describe()).color(color));
+                }
+                StepStatus::Skipped => {
+                    ui.label(RichText::new("Skipped").weak());
+                }
+                StepStatus::Cancelled => {
+                    ui.label(RichText::new("Cancelled").weak());
+                }
+                StepStatus::Pending | StepStatus::Running => {}
+            }
+        });
+
+        if let StepStatus::Failed(failure) = status {
+            ui.indent(kind.label(), |ui| {
+                ui.label(RichText::new(failure.suggestion()).italics());
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::{
+        check_announced, host_of, probe_outcome, Announcement, CheckKind, ConnectionWizard,
+        Diagnosis, Failure, StepStatus,
+    };
+
+    fn passed() -> Result<String, Failure> {
+        Ok("Ok".to_owned())
+    }
+
+    fn statuses(diagnosis: &Diagnosis) -> Vec<StepStatus> {
+        diagnosis
+            .steps()
+            .iter()
+            .map(|(_, status)| status.clone())
+            .collect()
+    }
+
+    #[test]
+    fn runs_every_check_in_order() {
+        let mut diagnosis = Diagnosis::new("robot.local:44444".to_owned());
+
+        for kind in CheckKind::ALL {
+            assert_eq!(diagnosis.next(), Some(kind));
+
+            // One at a time
+            assert_eq!(diagnosis.next(), None);
+            assert!(!diagnosis.is_finished());
+
+            diagnosis.record(kind, passed());
+        }
+
+        assert_eq!(diagnosis.next(), None);
+        assert!(diagnosis.is_finished());
+    }
+
+    #[test]
+    fn failures_skip_what_depends_on_them() {
+        let mut diagnosis = Diagnosis::new("robot.local:44444".to_owned());
+
+        assert_eq!(diagnosis.next(), Some(CheckKind::Resolve));
+        diagnosis.record(CheckKind::Resolve, Err(Failure::Unresolved { mdns: true }));
+
+        // Reaching needs an address but the announcement can still be checked
+        assert_eq!(diagnosis.next(), Some(CheckKind::Announce));
+        diagnosis.record(CheckKind::Announce, Err(Failure::NoAnnouncements));
+
+        assert_eq!(diagnosis.next(), None);
+        assert!(diagnosis.is_finished());
+        assert_eq!(
+            statuses(&diagnosis),
+            [
+                StepStatus::Failed(Failure::Unresolved { mdns: true }),
+                StepStatus::Skipped,
+                StepStatus::Failed(Failure::NoAnnouncements),
+                StepStatus::Skipped,
+            ]
+        );
+
+        let mut diagnosis = Diagnosis::new("10.0.0.2:44444".to_owned());
+        diagnosis.next();
+        diagnosis.record(CheckKind::Resolve, passed());
+        diagnosis.next();
+        diagnosis.record(CheckKind::Reach, Err(Failure::Refused));
+        diagnosis.next();
+        diagnosis.record(CheckKind::Announce, passed());
+
+        assert_eq!(diagnosis.next(), None);
+        assert_eq!(
+            diagnosis.status(CheckKind::Probe),
+            Some(&StepStatus::Skipped)
+        );
+    }
+
+    #[test]
+    fn only_running_checks_are_recorded() {
+        let mut diagnosis = Diagnosis::new("10.0.0.2:44444".to_owned());
+
+        diagnosis.record(CheckKind::Reach, passed());
+        assert_eq!(
+            diagnosis.status(CheckKind::Reach),
+            Some(&StepStatus::Pending)
+        );
+
+        diagnosis.next();
+        diagnosis.record(CheckKind::Resolve, passed());
+        diagnosis.record(CheckKind::Resolve, Err(Failure::MissingPort));
+        assert!(matches!(
+            diagnosis.status(CheckKind::Resolve),
+            Some(StepStatus::Passed(_))
+        ));
+    }
+
+    #[test]
+    fn cancelling_stops_the_run() {
+        let mut wizard = ConnectionWizard {
+            diagnosis: Some(Diagnosis::new("10.0.0.2:44444".to_owned())),
+            ..Default::default()
+        };
+        let run = wizard.run;
+
+        assert_eq!(wizard.next(run), Some(CheckKind::Resolve));
+        wizard.cancel();
+
+        // The aborted task's result arrives late
+        wizard.record(run, CheckKind::Resolve, passed());
+        assert_eq!(wizard.next(run), None);
+
+        let diagnosis = wizard.diagnosis.as_ref().unwrap();
+        assert!(diagnosis.is_finished());
+        assert_eq!(statuses(diagnosis), [StepStatus::Cancelled; 4]);
+    }
+
+    #[test]
+    fn suggestions() {
+        assert_eq!(
+            Failure::MissingPort.suggestion(),
+            "Add the sync port after the address, like 10.0.0.2:44444"
+        );
+        assert!(Failure::Unresolved { mdns: true }
+            .suggestion()
+            .contains("avahi"));
+        assert!(!Failure::Unresolved { mdns: false }
+            .suggestion()
+            .contains("avahi"));
+        assert!(Failure::Refused.suggestion().contains("robot software"));
+        assert!(Failure::TimedOut.suggestion().contains("tether"));
+        assert!(Failure::NoAnnouncements.suggestion().contains("multicast"));
+        assert!(Failure::Taken(vec![])
+            .suggestion()
+            .contains("other surface"));
+        assert!(Failure::LinkKey(String::new())
+            .suggestion()
+            .contains("link key"));
+
+        assert_eq!(
+            Failure::Taken(vec!["Pilot".to_owned(), "Copilot".to_owned()]).describe(),
+            "Already connected to Pilot, Copilot"
+        );
+    }
+
+    #[test]
+    fn announcements() {
+        let robot = Announcement {
+            name: "ROV".to_owned(),
+            hostname: "raspberrypi.local".to_owned(),
+            addresses: vec!["10.0.0.2:44444".parse().unwrap()],
+        };
+        let resolved: Vec<SocketAddr> = vec!["10.0.0.2:44444".parse().unwrap()];
+
+        assert_eq!(
+            check_announced("10.0.0.9:44444", &[], &[]),
+            Err(Failure::NoAnnouncements)
+        );
+        assert_eq!(
+            check_announced("raspberrypi.local:44444", &[], &[robot.clone()]),
+            Ok("Announced as ROV".to_owned())
+        );
+        assert_eq!(
+            check_announced("10.0.0.2:44444", &resolved, &[robot.clone()]),
+            Ok("Announced as ROV".to_owned())
+        );
+        assert_eq!(
+            check_announced("10.0.0.9:44444", &[], &[robot]),
+            Err(Failure::NotAnnounced {
+                others: vec!["ROV".to_owned()]
+            })
+        );
+
+        assert_eq!(host_of("[::1]:44444"), Some("::1"));
+        assert_eq!(host_of("robot.local"), None);
+
+        assert!(probe_outcome(vec![]).is_ok());
+        assert_eq!(
+            probe_outcome(vec!["Pilot".to_owned()]),
+            Err(Failure::Taken(vec!["Pilot".to_owned()]))
+        );
+    }
+}
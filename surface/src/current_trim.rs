@@ -0,0 +1,293 @@
+//! Captures [`CurrentTrim`] from what the pilot has been holding against the current
+//!
+//! The pilot's contribution is sampled in world frame while armed, capturing adds the average of
+//! the last [`CAPTURE_WINDOW`] to the robot's trim so the stick can be let go. The commands act on
+//! the [`SelectedRobot`].
+
+use std::{collections::VecDeque, time::Duration};
+
+use ahash::HashMap;
+use bevy::{ecs::system::RunSystemOnce, math::Vec3A, prelude::*};
+use common::{
+    components::{Armed, CurrentTrim, MovementContribution, Orientation, Robot, RobotId},
+    ecs_sync::NetId,
+};
+
+use crate::{
+    command_palette::{AppCommandExt, SurfaceCommand},
+    input::InputMarker,
+    selected_robot::SelectedRobot,
+};
+
+/// How far back a capture averages the pilot's input
+pub const CAPTURE_WINDOW: Duration = Duration::from_secs(3);
+
+pub struct CurrentTrimPlugin;
+
+impl Plugin for CurrentTrimPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TrimCapture>()
+            .add_systems(Update, sample_pilot)
+            .add_command(
+                SurfaceCommand::new("Capture Current Trim", |world| {
+                    if let Some(robot) = world.resource::<SelectedRobot>().0 {
+                        world.run_system_once_with(robot, capture);
+                    }
+                })
+                .enabled_if(|world| {
+                    world
+                        .resource::<SelectedRobot>()
+                        .0
+                        .is_some_and(|RobotId(robot)| {
+                            world.resource::<TrimCapture>().window(robot).is_some()
+                        })
+                }),
+            )
+            .add_command(
+                SurfaceCommand::new("Clear Current Trim", |world| {
+                    if let Some(robot) = world.resource::<SelectedRobot>().0 {
+                        world.run_system_once_with(robot, clear);
+                    }
+                })
+                .enabled_if(|world| {
+                    let selected = world.resource::<SelectedRobot>().0;
+
+                    world
+                        .query_filtered::<(&RobotId, &CurrentTrim), With<Robot>>()
+                        .iter(world)
+                        .any(|(robot, trim)| Some(*robot) == selected && trim.0 != Vec3A::ZERO)
+                }),
+            );
+    }
+}
+
+/// World frame forces the pilot held over the last [`CAPTURE_WINDOW`], oldest first
+#[derive(Debug, Default)]
+pub struct ForceWindow {
+    samples: VecDeque<(Duration, Vec3A)>,
+}
+
+impl ForceWindow {
+    pub fn push(&mut self, now: Duration, force: Vec3A) {
+        self.samples.push_back((now, force));
+        self.expire(now);
+    }
+
+    /// Mean force over the window ending at `now`, `None` if nothing was sampled in it
+    pub fn average(&self, now: Duration) -> Option<Vec3A> {
+        let start = now.saturating_sub(CAPTURE_WINDOW);
+
+        let (sum, count) = self
+            .samples
+            .iter()
+            .filter(|(at, _)| *at >= start)
+            .fold((Vec3A::ZERO, 0), |(sum, count), (_, force)| {
+                (sum + *force, count + 1)
+            });
+
+        (count > 0).then(|| sum / count as f32)
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    fn expire(&mut self, now: Duration) {
+        let start = now.saturating_sub(CAPTURE_WINDOW);
+
+        while self.samples.front().is_some_and(|(at, _)| *at < start) {
+            self.samples.pop_front();
+        }
+    }
+}
+
+/// The pilot's recent input for each armed robot
+#[derive(Resource, Debug, Default)]
+pub struct TrimCapture {
+    windows: HashMap<NetId, ForceWindow>,
+}
+
+impl TrimCapture {
+    pub fn window(&self, robot: NetId) -> Option<&ForceWindow> {
+        self.windows.get(&robot).filter(|it| !it.is_empty())
+    }
+}
+
+/// The trim that takes over the pilot's average input on top of the `current` trim
+pub fn captured_trim(current: Vec3A, window: &ForceWindow, now: Duration) -> Option<Vec3A> {
+    window.average(now).map(|pilot| current + pilot)
+}
+
+/// The world frame force of a body frame `force`, or the force unchanged without an orientation
+fn to_world(force: Vec3A, orientation: Option<&Orientation>) -> Vec3A {
+    match orientation {
+        Some(orientation) => orientation.rotation * force,
+        None => force,
+    }
+}
+
+fn sample_pilot(
+    mut capture: ResMut<TrimCapture>,
+    time: Res<Time<Real>>,
+    robots: Query<(&NetId, &Armed, Option<&Orientation>), With<Robot>>,
+    inputs: Query<(&RobotId, &MovementContribution), With<InputMarker>>,
+) {
+    let now = time.elapsed();
+
+    capture
+        .windows
+        .retain(|robot, _| robots.iter().any(|(id, ..)| id == robot));
+
+    for (&robot, armed, orientation) in &robots {
+        let window = capture.windows.entry(robot).or_default();
+
+        // Input while disarmed doesn't push against anything
+        if *armed != Armed::Armed {
+            window.clear();
+            continue;
+        }
+
+        let force = inputs
+            .iter()
+            .filter(|(RobotId(id), _)| *id == robot)
+            .map(|(_, contribution)| contribution.0.force)
+            .sum::<Vec3A>();
+
+        window.push(now, to_world(force, orientation));
+    }
+}
+
+/// Adds the pilot's recent input to `robot`'s trim
+pub fn capture(
+    In(robot): In<RobotId>,
+    mut cmds: Commands,
+    capture: Res<TrimCapture>,
+    time: Res<Time<Real>>,
+    robots: Query<(Entity, &RobotId, Option<&CurrentTrim>), With<Robot>>,
+) {
+    let Some((entity, _, trim)) = robots.iter().find(|(_, id, _)| **id == robot) else {
+        return;
+    };
+    let Some(window) = capture.window(robot.0) else {
+        return;
+    };
+    let current = trim.map(|it| it.0).unwrap_or_default();
+
+    if let Some(trim) = captured_trim(current, window, time.elapsed()) {
+        info!("Captured current trim of {trim}");
+        cmds.entity(entity).insert(CurrentTrim(trim));
+    }
+}
+
+/// Zeroes `robot`'s trim
+pub fn clear(
+    In(robot): In<RobotId>,
+    mut cmds: Commands,
+    robots: Query<(Entity, &RobotId), With<Robot>>,
+) {
+    for (entity, _) in robots.iter().filter(|(_, id)| **id == robot) {
+        cmds.entity(entity).insert(CurrentTrim::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{
+        ecs::system::RunSystemOnce,
+        math::{Quat, Vec3A},
+        prelude::*,
+    };
+    use common::{
+        components::{CurrentTrim, Orientation, Robot, RobotId},
+        ecs_sync::NetId,
+    };
+
+    use super::{captured_trim, clear, to_world, ForceWindow, CAPTURE_WINDOW};
+
+    fn secs(secs: f32) -> Duration {
+        Duration::from_secs_f32(secs)
+    }
+
+    #[test]
+    fn capture_averages_the_last_window() {
+        let mut window = ForceWindow::default();
+        assert_eq!(window.average(secs(0.0)), None);
+
+        // A gust before the window shouldn't count
+        window.push(secs(0.0), Vec3A::new(0.0, 100.0, 0.0));
+        for step in 1..=30 {
+            let force = if step % 2 == 0 { 4.0 } else { 6.0 };
+            window.push(secs(1.0 + step as f32 * 0.1), Vec3A::new(force, 0.0, 0.0));
+        }
+
+        let now = secs(4.0);
+        let average = window.average(now).unwrap();
+        assert!(
+            average.abs_diff_eq(Vec3A::new(5.0, 0.0, 0.0), 1e-4),
+            "{average}"
+        );
+
+        // Old samples are dropped as new ones arrive
+        assert!(window
+            .samples
+            .iter()
+            .all(|(at, _)| *at >= now - CAPTURE_WINDOW));
+
+        // Nothing left once the pilot stops being sampled for a whole window
+        assert_eq!(window.average(secs(10.0)), None);
+    }
+
+    #[test]
+    fn capture_adds_to_the_current_trim() {
+        let mut window = ForceWindow::default();
+        window.push(secs(1.0), Vec3A::new(1.0, 2.0, 0.0));
+        window.push(secs(2.0), Vec3A::new(3.0, 2.0, 0.0));
+
+        let current = Vec3A::new(10.0, 0.0, -1.0);
+        let trim = captured_trim(current, &window, secs(2.0)).unwrap();
+        assert!(
+            trim.abs_diff_eq(Vec3A::new(12.0, 2.0, -1.0), 1e-5),
+            "{trim}"
+        );
+
+        window.clear();
+        assert_eq!(captured_trim(current, &window, secs(2.0)), None);
+    }
+
+    #[test]
+    fn samples_are_taken_in_world_frame() {
+        // Facing west and pushing forward pushes west
+        let yawed = Orientation {
+            rotation: Quat::from_rotation_z(90f32.to_radians()),
+            capture_time: 0.0,
+        };
+        let world = to_world(Vec3A::Y, Some(&yawed));
+        assert!(world.abs_diff_eq(Vec3A::NEG_X, 1e-5), "{world}");
+
+        assert_eq!(to_world(Vec3A::Y, None), Vec3A::Y);
+    }
+
+    #[test]
+    fn clearing_leaves_other_robots_alone() {
+        let mut world = World::new();
+        let trim = CurrentTrim(Vec3A::new(3.0, 0.0, 0.0));
+
+        let (a, b) = (RobotId(NetId::random()), RobotId(NetId::random()));
+        let first = world.spawn((Robot, a, trim)).id();
+        let second = world.spawn((Robot, b, trim)).id();
+
+        world.run_system_once_with(a, clear);
+
+        assert_eq!(
+            world.get::<CurrentTrim>(first),
+            Some(&CurrentTrim::default())
+        );
+        assert_eq!(world.get::<CurrentTrim>(second), Some(&trim));
+    }
+}
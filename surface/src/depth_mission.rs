@@ -0,0 +1,614 @@
+//! Follows a depth profile for vertical profiling tasks
+//!
+//! A profile is a list of segments, each ramping the depth target to a depth at a fixed rate then
+//! holding there for a while. The mission runs on the surface and only ever writes the robot's
+//! [`DepthTarget`], so depth hold on the robot does the actual flying.
+//!
+//! Losing the robot pauses the mission where it was rather than skipping ahead. Disarming, a leak
+//! or the pilot taking over heave ends it, leaving depth hold at the last setpoint.
+
+use std::{
+    fmt::{self, Display},
+    fs,
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use bevy::prelude::*;
+use common::{
+    components::{Armed, DepthTarget, Leak, Robot, RobotId},
+    ecs_sync::NetId,
+    types::units::Meters,
+};
+use leafwing_input_manager::action_state::ActionState;
+use serde::{Deserialize, Serialize};
+
+use crate::input::{Action, InputMarker};
+
+/// Where the profile is saved to and loaded from by default
+pub const DEPTH_MISSION_PATH: &str = "depth_mission.toml";
+/// Heave input past which the pilot is taking over from the mission
+pub const MANUAL_HEAVE_ABORT: f32 = 0.3;
+
+pub struct DepthMissionPlugin;
+
+impl Plugin for DepthMissionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DepthMission>()
+            .add_systems(Update, run_depth_mission);
+    }
+}
+
+/// One step of a profile
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct DepthSegment {
+    pub target: Meters,
+    /// Time to stay at `target` once it is reached
+    #[serde(with = "secs")]
+    pub hold: Duration,
+    /// Meters per second the depth target moves towards `target`
+    pub rate: f32,
+}
+
+impl Default for DepthSegment {
+    fn default() -> Self {
+        Self {
+            target: Meters(1.0),
+            hold: Duration::from_secs(30),
+            rate: 0.1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct DepthProfile {
+    pub segments: Vec<DepthSegment>,
+}
+
+impl DepthProfile {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.segments.is_empty() {
+            bail!("Profile has no segments");
+        }
+
+        for (idx, segment) in self.segments.iter().enumerate() {
+            let number = idx + 1;
+
+            if !(segment.target.0.is_finite() && segment.target.0 >= 0.0) {
+                bail!("Segment {number} has a depth above the surface");
+            }
+            if !(segment.rate.is_finite() && segment.rate > 0.0) {
+                bail!("Segment {number} needs a positive rate");
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let profile = fs::read_to_string(path).context("Read depth profile")?;
+        let profile: Self = toml::from_str(&profile).context("Parse depth profile")?;
+        profile.validate()?;
+
+        Ok(profile)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let profile = toml::to_string(self).context("Serialize depth profile")?;
+        fs::write(path, profile).context("Write depth profile")
+    }
+}
+
+/// What the current segment is doing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SegmentPhase {
+    Ramping,
+    Holding,
+}
+
+/// Where a running mission is in its profile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MissionProgress {
+    pub segment: usize,
+    /// Depth currently commanded
+    pub setpoint: Meters,
+    /// Seconds spent at the current segment's target
+    pub held: f32,
+}
+
+impl MissionProgress {
+    /// Starts from wherever the robot is so the first ramp is smooth
+    pub fn start(depth: Meters) -> Self {
+        Self {
+            segment: 0,
+            setpoint: depth,
+            held: 0.0,
+        }
+    }
+
+    /// Moves the mission on by `dt`, returns true once every segment is done
+    ///
+    /// Time left over from finishing a segment carries into the next one
+    pub fn advance(&mut self, profile: &DepthProfile, dt: Duration) -> bool {
+        let mut dt = dt.as_secs_f32();
+
+        while let Some(segment) = profile.segments.get(self.segment) {
+            let to_go = segment.target.0 - self.setpoint.0;
+            let ramp = to_go.abs() / segment.rate;
+            if dt < ramp {
+                self.setpoint.0 += to_go.signum() * segment.rate * dt;
+                return false;
+            }
+            self.setpoint = segment.target;
+            dt -= ramp;
+
+            let hold = segment.hold.as_secs_f32() - self.held;
+            if dt < hold {
+                self.held += dt;
+                return false;
+            }
+            dt -= hold;
+
+            self.segment += 1;
+            self.held = 0.0;
+        }
+
+        true
+    }
+
+    pub fn phase(&self, profile: &DepthProfile) -> Option<SegmentPhase> {
+        let segment = profile.segments.get(self.segment)?;
+
+        if self.setpoint == segment.target {
+            Some(SegmentPhase::Holding)
+        } else {
+            Some(SegmentPhase::Ramping)
+        }
+    }
+
+    /// Time until the current segment is done
+    pub fn segment_remaining(&self, profile: &DepthProfile) -> Duration {
+        let Some(segment) = profile.segments.get(self.segment) else {
+            return Duration::ZERO;
+        };
+
+        let ramp = (segment.target.0 - self.setpoint.0).abs() / segment.rate;
+        let hold = segment.hold.as_secs_f32() - self.held;
+
+        Duration::from_secs_f32((ramp + hold).max(0.0))
+    }
+
+    /// Time until the whole mission is done
+    pub fn remaining(&self, profile: &DepthProfile) -> Duration {
+        let mut remaining = self.segment_remaining(profile);
+
+        let mut depth = profile.segments.get(self.segment).map(|it| it.target);
+        for segment in profile.segments.iter().skip(self.segment + 1) {
+            let from = depth.unwrap_or(segment.target);
+            let ramp = (segment.target.0 - from.0).abs() / segment.rate;
+
+            remaining += Duration::from_secs_f32(ramp) + segment.hold;
+            depth = Some(segment.target);
+        }
+
+        remaining
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AbortReason {
+    Disarmed,
+    Leak,
+    ManualHeave,
+}
+
+impl Display for AbortReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AbortReason::Disarmed => write!(f, "Robot disarmed"),
+            AbortReason::Leak => write!(f, "Leak detected"),
+            AbortReason::ManualHeave => write!(f, "Pilot took over heave"),
+        }
+    }
+}
+
+/// Why a running mission has to stop, if it does
+pub fn abort_reason(armed: Armed, leak: bool, heave: f32) -> Option<AbortReason> {
+    if armed != Armed::Armed {
+        Some(AbortReason::Disarmed)
+    } else if leak {
+        Some(AbortReason::Leak)
+    } else if heave.abs() > MANUAL_HEAVE_ABORT {
+        Some(AbortReason::ManualHeave)
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissionOutcome {
+    Finished,
+    Stopped,
+    Aborted(AbortReason),
+}
+
+impl Display for MissionOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MissionOutcome::Finished => write!(f, "Finished"),
+            MissionOutcome::Stopped => write!(f, "Stopped"),
+            MissionOutcome::Aborted(reason) => write!(f, "Aborted: {reason}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MissionRun {
+    pub robot: NetId,
+    pub progress: MissionProgress,
+    /// The robot is gone, progress is frozen until it is back
+    pub paused: bool,
+}
+
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct DepthMission {
+    pub profile: DepthProfile,
+    /// Where the profile is saved to and loaded from
+    pub path: String,
+    pub run: Option<MissionRun>,
+    /// How the last run ended
+    pub outcome: Option<MissionOutcome>,
+}
+
+impl Default for DepthMission {
+    fn default() -> Self {
+        Self {
+            profile: DepthProfile::default(),
+            path: DEPTH_MISSION_PATH.to_owned(),
+            run: None,
+            outcome: None,
+        }
+    }
+}
+
+impl DepthMission {
+    pub fn start(&mut self, robot: NetId, depth: Meters) -> anyhow::Result<()> {
+        self.profile.validate()?;
+
+        self.run = Some(MissionRun {
+            robot,
+            progress: MissionProgress::start(depth),
+            paused: false,
+        });
+        self.outcome = None;
+
+        Ok(())
+    }
+
+    pub fn stop(&mut self, outcome: MissionOutcome) {
+        if self.run.take().is_some() {
+            self.outcome = Some(outcome);
+        }
+    }
+}
+
+fn run_depth_mission(
+    mut cmds: Commands,
+    mut mission: ResMut<DepthMission>,
+    robots: Query<
+        (
+            Entity,
+            &RobotId,
+            &Armed,
+            Option<&Leak>,
+            Option<&DepthTarget>,
+        ),
+        With<Robot>,
+    >,
+    inputs: Query<(&RobotId, &ActionState<Action>), With<InputMarker>>,
+    time: Res<Time>,
+) {
+    let DepthMission { profile, run, .. } = &mut *mission;
+    let Some(active) = run else {
+        return;
+    };
+
+    let robot = robots
+        .iter()
+        .find(|&(_, robot, ..)| robot.0 == active.robot);
+    let Some((robot, _, &armed, leak, depth_target)) = robot else {
+        if !active.paused {
+            warn!("Lost the robot, pausing depth mission");
+            active.paused = true;
+        }
+
+        return;
+    };
+
+    if active.paused {
+        info!("Robot is back, resuming depth mission");
+        active.paused = false;
+    }
+
+    let heave = inputs
+        .iter()
+        .filter(|&(input, _)| input.0 == active.robot)
+        .map(|(_, state)| state.value(&Action::Heave) - state.value(&Action::HeaveInverted))
+        .fold(0.0f32, |max, heave| max.max(heave.abs()));
+
+    if let Some(reason) = abort_reason(armed, leak.is_some_and(|it| it.0), heave) {
+        warn!("Depth mission aborted: {reason}");
+        mission.stop(MissionOutcome::Aborted(reason));
+
+        return;
+    }
+
+    let finished = active.progress.advance(profile, time.delta());
+
+    // Also restores the setpoint after the link comes back
+    let target = DepthTarget(active.progress.setpoint);
+    if depth_target != Some(&target) {
+        cmds.entity(robot).insert(target);
+    }
+
+    if finished {
+        info!("Depth mission finished");
+        mission.stop(MissionOutcome::Finished);
+    }
+}
+
+/// Durations as fractional seconds, easier to edit by hand
+mod secs {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f32(duration.as_secs_f32())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let secs = f32::deserialize(deserializer)?;
+
+        Duration::try_from_secs_f32(secs).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{app::App, time::Time};
+    use common::{
+        components::{Armed, DepthTarget, Leak, Robot, RobotId},
+        ecs_sync::NetId,
+        types::units::Meters,
+    };
+
+    use super::{
+        abort_reason, run_depth_mission, AbortReason, DepthMission, DepthProfile, DepthSegment,
+        MissionOutcome, MissionProgress, SegmentPhase, MANUAL_HEAVE_ABORT,
+    };
+
+    fn secs(secs: f32) -> Duration {
+        Duration::from_secs_f32(secs)
+    }
+
+    fn segment(target: f32, hold: f32, rate: f32) -> DepthSegment {
+        DepthSegment {
+            target: Meters(target),
+            hold: secs(hold),
+            rate,
+        }
+    }
+
+    /// Down to 2m, hold, up to 1m, hold, then back to the surface
+    fn profile() -> DepthProfile {
+        DepthProfile {
+            segments: vec![
+                segment(2.0, 10.0, 0.2),
+                segment(1.0, 5.0, 0.5),
+                segment(0.0, 0.0, 0.25),
+            ],
+        }
+    }
+
+    fn assert_depth(actual: Meters, expected: f32) {
+        assert!(
+            (actual.0 - expected).abs() < 1e-4,
+            "{actual:?} != {expected}"
+        );
+    }
+
+    #[test]
+    fn ramps_at_the_segment_rate() {
+        let profile = profile();
+        let mut progress = MissionProgress::start(Meters(0.5));
+
+        assert!(!progress.advance(&profile, secs(2.5)));
+        assert_depth(progress.setpoint, 1.0);
+        assert_eq!(progress.phase(&profile), Some(SegmentPhase::Ramping));
+
+        // Reaches 2m 5s later then starts holding
+        assert!(!progress.advance(&profile, secs(6.0)));
+        assert_depth(progress.setpoint, 2.0);
+        assert_eq!(progress.phase(&profile), Some(SegmentPhase::Holding));
+        assert_eq!(progress.segment, 0);
+
+        // Going up is a ramp too
+        assert!(!progress.advance(&profile, secs(10.0)));
+        assert_eq!(progress.segment, 1);
+        assert_depth(progress.setpoint, 1.5);
+    }
+
+    #[test]
+    fn holds_then_moves_on() {
+        let profile = profile();
+        let mut progress = MissionProgress::start(Meters(2.0));
+
+        assert!(!progress.advance(&profile, secs(9.0)));
+        assert_eq!(progress.segment, 0);
+        assert_eq!(progress.remaining(&profile), secs(1.0 + 2.0 + 5.0 + 4.0));
+
+        // Leftover time carries into the next segment
+        assert!(!progress.advance(&profile, secs(1.5)));
+        assert_eq!(progress.segment, 1);
+        assert_depth(progress.setpoint, 1.75);
+        assert!((progress.segment_remaining(&profile).as_secs_f32() - 6.5).abs() < 1e-4);
+
+        // Small steps land in the same place as big ones
+        for _ in 0..100 {
+            progress.advance(&profile, secs(0.01));
+        }
+        assert_depth(progress.setpoint, 1.25);
+
+        assert!(progress.advance(&profile, secs(20.0)));
+        assert_depth(progress.setpoint, 0.0);
+        assert_eq!(progress.phase(&profile), None);
+        assert_eq!(progress.remaining(&profile), Duration::ZERO);
+    }
+
+    #[test]
+    fn rejects_bad_profiles() {
+        assert!(profile().validate().is_ok());
+        assert!(DepthProfile::default().validate().is_err());
+
+        for bad in [
+            segment(1.0, 1.0, 0.0),
+            segment(1.0, 1.0, -0.1),
+            segment(1.0, 1.0, f32::NAN),
+            segment(-1.0, 1.0, 0.1),
+            segment(f32::INFINITY, 1.0, 0.1),
+        ] {
+            let profile = DepthProfile {
+                segments: vec![segment(1.0, 1.0, 0.1), bad],
+            };
+            assert!(profile.validate().is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn profiles_roundtrip() {
+        let profile = profile();
+        let toml = toml::to_string(&profile).unwrap();
+
+        assert!(toml.contains("hold = 10.0"), "{toml}");
+        assert_eq!(toml::from_str::<DepthProfile>(&toml).unwrap(), profile);
+    }
+
+    #[test]
+    fn abort_conditions() {
+        assert_eq!(abort_reason(Armed::Armed, false, 0.0), None);
+        assert_eq!(abort_reason(Armed::Armed, false, MANUAL_HEAVE_ABORT), None);
+
+        assert_eq!(
+            abort_reason(Armed::Disarmed, false, 0.0),
+            Some(AbortReason::Disarmed)
+        );
+        assert_eq!(
+            abort_reason(Armed::Armed, true, 0.0),
+            Some(AbortReason::Leak)
+        );
+        assert_eq!(
+            abort_reason(Armed::Armed, false, -0.8),
+            Some(AbortReason::ManualHeave)
+        );
+    }
+
+    fn mission_app() -> (App, NetId) {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .init_resource::<DepthMission>()
+            .add_systems(bevy::app::Update, run_depth_mission);
+
+        let robot = NetId::random();
+        app.world.resource_mut::<DepthMission>().profile = profile();
+        app.world
+            .resource_mut::<DepthMission>()
+            .start(robot, Meters(2.0))
+            .unwrap();
+
+        (app, robot)
+    }
+
+    fn step(app: &mut App, dt: f32) {
+        app.world.resource_mut::<Time>().advance_by(secs(dt));
+        app.update();
+    }
+
+    #[test]
+    fn pauses_while_the_robot_is_gone() {
+        let (mut app, robot_id) = mission_app();
+        let robot = app
+            .world
+            .spawn((Robot, RobotId(robot_id), Armed::Armed))
+            .id();
+
+        step(&mut app, 9.0);
+        assert_eq!(
+            app.world.get::<DepthTarget>(robot),
+            Some(&DepthTarget(Meters(2.0)))
+        );
+
+        // Link drop, time passes but the mission doesn't
+        app.world.despawn(robot);
+        step(&mut app, 60.0);
+        let run = app.world.resource::<DepthMission>().run.unwrap();
+        assert!(run.paused);
+        assert_eq!(run.progress.segment, 0);
+
+        // The robot comes back as a new entity, the setpoint is sent again
+        let robot = app
+            .world
+            .spawn((Robot, RobotId(robot_id), Armed::Armed))
+            .id();
+        step(&mut app, 0.5);
+        let run = app.world.resource::<DepthMission>().run.unwrap();
+        assert!(!run.paused);
+        assert_eq!(run.progress.segment, 0);
+        assert_eq!(
+            app.world.get::<DepthTarget>(robot),
+            Some(&DepthTarget(Meters(2.0)))
+        );
+
+        step(&mut app, 20.0);
+        let mission = app.world.resource::<DepthMission>();
+        assert_eq!(mission.run, None);
+        assert_eq!(mission.outcome, Some(MissionOutcome::Finished));
+        assert_eq!(
+            app.world.get::<DepthTarget>(robot),
+            Some(&DepthTarget(Meters(0.0)))
+        );
+    }
+
+    #[test]
+    fn aborts_on_leak_and_disarm() {
+        let (mut app, robot_id) = mission_app();
+        let robot = app
+            .world
+            .spawn((Robot, RobotId(robot_id), Armed::Armed, Leak(false)))
+            .id();
+
+        step(&mut app, 10.5);
+        app.world.entity_mut(robot).insert(Leak(true));
+        step(&mut app, 1.0);
+
+        let mission = app.world.resource::<DepthMission>();
+        assert_eq!(
+            mission.outcome,
+            Some(MissionOutcome::Aborted(AbortReason::Leak))
+        );
+        // Depth hold stays where the mission left it
+        let DepthTarget(target) = *app.world.get::<DepthTarget>(robot).unwrap();
+        assert_depth(target, 1.75);
+
+        let (mut app, robot_id) = mission_app();
+        app.world.spawn((Robot, RobotId(robot_id), Armed::Disarmed));
+        step(&mut app, 1.0);
+        assert_eq!(
+            app.world.resource::<DepthMission>().outcome,
+            Some(MissionOutcome::Aborted(AbortReason::Disarmed))
+        );
+    }
+}
@@ -0,0 +1,630 @@
+//! Mappings for joysticks, throttles and other devices that do not fit the default gamepad bindings
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ahash::HashMap;
+use anyhow::Context;
+use bevy::{
+    input::gamepad::{GamepadConnection, GamepadConnectionEvent},
+    prelude::*,
+};
+use common::{
+    bundles::MovementContributionBundle,
+    components::{MovementContribution, Robot, RobotId, ServoContribution},
+    ecs_sync::{NetId, Replicate},
+};
+use leafwing_input_manager::{
+    action_state::ActionState, input_map::InputMap, plugin::InputManagerSystem, InputManagerBundle,
+};
+use motor_math::Movement;
+use serde::{Deserialize, Serialize};
+
+use crate::input::{Action, InputInterpolation, InputMarker, SelectedServo};
+
+pub struct DeviceProfilePlugin;
+
+impl Plugin for DeviceProfilePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(DeviceProfiles::load(PROFILES_DIR))
+            .init_resource::<DeviceAssignments>()
+            .add_systems(
+                PreUpdate,
+                apply_profile_axes.in_set(InputManagerSystem::ManualControl),
+            )
+            .add_systems(
+                Update,
+                (
+                    assign_connected_devices,
+                    sync_profile_inputs.after(assign_connected_devices),
+                    route_default_bindings.after(assign_connected_devices),
+                ),
+            );
+    }
+}
+
+/// Where device profiles are read from, one toml file per device type
+pub const PROFILES_DIR: &str = "input_profiles";
+
+/// Maps the raw axes and buttons of a device type to [`Action`]s
+///
+/// ```toml
+/// name = "Flight Stick"
+/// devices = ["Thrustmaster T.16000M"]
+///
+/// [[axes]]
+/// axis = "LeftStickY"
+/// action = "Surge"
+/// calibration = { min = -1.0, center = 0.04, max = 1.0, deadband = 0.05 }
+///
+/// [[buttons]]
+/// button = "South"
+/// action = "Arm"
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceProfile {
+    pub name: String,
+    /// Device names this profile is picked for automatically when they connect
+    #[serde(default)]
+    pub devices: Vec<String>,
+    #[serde(default)]
+    pub axes: Vec<AxisBinding>,
+    #[serde(default)]
+    pub buttons: Vec<ButtonBinding>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AxisBinding {
+    pub axis: RawAxis,
+    pub action: Action,
+    #[serde(default)]
+    pub calibration: AxisCalibration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ButtonBinding {
+    pub button: RawButton,
+    pub action: Action,
+}
+
+/// Raw readings at the ends and rest position of an axis
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AxisCalibration {
+    pub min: f32,
+    pub center: f32,
+    pub max: f32,
+    /// Fraction of travel around `center` that reads as zero
+    pub deadband: f32,
+    #[serde(default)]
+    pub inverted: bool,
+}
+
+impl Default for AxisCalibration {
+    fn default() -> Self {
+        Self {
+            min: -1.0,
+            center: 0.0,
+            max: 1.0,
+            deadband: 0.05,
+            inverted: false,
+        }
+    }
+}
+
+impl AxisCalibration {
+    /// Maps a raw reading to -1..=1
+    ///
+    /// Each side of `center` is scaled separately so off center sticks still reach full travel,
+    /// the output is rescaled past the deadband so it does not jump when leaving it
+    pub fn normalize(&self, raw: f32) -> f32 {
+        let span = if raw >= self.center {
+            self.max - self.center
+        } else {
+            self.center - self.min
+        };
+
+        if span <= 0.0 || !raw.is_finite() {
+            return 0.0;
+        }
+
+        let value = ((raw - self.center) / span).clamp(-1.0, 1.0);
+        let deadband = self.deadband.clamp(0.0, 0.99);
+
+        let value = if value.abs() <= deadband {
+            0.0
+        } else {
+            (value.abs() - deadband) / (1.0 - deadband) * value.signum()
+        };
+
+        if self.inverted {
+            -value
+        } else {
+            value
+        }
+    }
+}
+
+/// Serializable mirror of [`GamepadAxisType`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RawAxis {
+    LeftStickX,
+    LeftStickY,
+    LeftZ,
+    RightStickX,
+    RightStickY,
+    RightZ,
+    Other(u8),
+}
+
+impl From<RawAxis> for GamepadAxisType {
+    fn from(axis: RawAxis) -> Self {
+        match axis {
+            RawAxis::LeftStickX => GamepadAxisType::LeftStickX,
+            RawAxis::LeftStickY => GamepadAxisType::LeftStickY,
+            RawAxis::LeftZ => GamepadAxisType::LeftZ,
+            RawAxis::RightStickX => GamepadAxisType::RightStickX,
+            RawAxis::RightStickY => GamepadAxisType::RightStickY,
+            RawAxis::RightZ => GamepadAxisType::RightZ,
+            RawAxis::Other(id) => GamepadAxisType::Other(id),
+        }
+    }
+}
+
+/// Serializable mirror of [`GamepadButtonType`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RawButton {
+    South,
+    East,
+    North,
+    West,
+    C,
+    Z,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    Mode,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    Other(u8),
+}
+
+impl From<RawButton> for GamepadButtonType {
+    fn from(button: RawButton) -> Self {
+        match button {
+            RawButton::South => GamepadButtonType::South,
+            RawButton::East => GamepadButtonType::East,
+            RawButton::North => GamepadButtonType::North,
+            RawButton::West => GamepadButtonType::West,
+            RawButton::C => GamepadButtonType::C,
+            RawButton::Z => GamepadButtonType::Z,
+            RawButton::LeftTrigger => GamepadButtonType::LeftTrigger,
+            RawButton::LeftTrigger2 => GamepadButtonType::LeftTrigger2,
+            RawButton::RightTrigger => GamepadButtonType::RightTrigger,
+            RawButton::RightTrigger2 => GamepadButtonType::RightTrigger2,
+            RawButton::Select => GamepadButtonType::Select,
+            RawButton::Start => GamepadButtonType::Start,
+            RawButton::Mode => GamepadButtonType::Mode,
+            RawButton::LeftThumb => GamepadButtonType::LeftThumb,
+            RawButton::RightThumb => GamepadButtonType::RightThumb,
+            RawButton::DPadUp => GamepadButtonType::DPadUp,
+            RawButton::DPadDown => GamepadButtonType::DPadDown,
+            RawButton::DPadLeft => GamepadButtonType::DPadLeft,
+            RawButton::DPadRight => GamepadButtonType::DPadRight,
+            RawButton::Other(id) => GamepadButtonType::Other(id),
+        }
+    }
+}
+
+impl DeviceProfile {
+    /// Button bindings for `gamepad`, axes are applied by [`apply_profile_axes`] since they need calibration
+    pub fn input_map(&self, gamepad: Gamepad) -> InputMap<Action> {
+        let mut input_map = InputMap::default();
+
+        for binding in &self.buttons {
+            input_map.insert(binding.action, GamepadButtonType::from(binding.button));
+        }
+
+        input_map.set_gamepad(gamepad);
+        input_map
+    }
+
+    /// Calibrated value of every action bound to an axis, actions bound more than once are summed
+    pub fn axis_values(&self, raw: impl Fn(RawAxis) -> Option<f32>) -> HashMap<Action, f32> {
+        let mut values = HashMap::default();
+
+        for binding in &self.axes {
+            let value = raw(binding.axis)
+                .map(|raw| binding.calibration.normalize(raw))
+                .unwrap_or(0.0);
+
+            *values.entry(binding.action).or_default() += value;
+        }
+
+        values
+    }
+}
+
+/// Profiles found in [`PROFILES_DIR`], keyed by name
+#[derive(Resource, Debug, Default)]
+pub struct DeviceProfiles(pub BTreeMap<String, DeviceProfile>);
+
+impl DeviceProfiles {
+    pub fn load(dir: impl AsRef<Path>) -> Self {
+        let files = match list_profiles(dir.as_ref()) {
+            Ok(files) => files,
+            Err(err) => {
+                warn!("Could not list device profiles: {err:?}");
+                Vec::new()
+            }
+        };
+
+        let mut profiles = BTreeMap::new();
+        for path in files {
+            match load_profile(&path) {
+                Ok(profile) => {
+                    profiles.insert(profile.name.clone(), profile);
+                }
+                Err(err) => warn!("Could not load device profile {}: {err:?}", path.display()),
+            }
+        }
+
+        Self(profiles)
+    }
+
+    /// The profile made for a device with this name, if any
+    pub fn for_device(&self, device: &str) -> Option<&DeviceProfile> {
+        self.0
+            .values()
+            .find(|profile| profile.devices.iter().any(|it| it == device))
+    }
+}
+
+fn list_profiles(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .context("Read profiles dir")?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|it| it == "toml"))
+        .collect();
+    files.sort();
+
+    Ok(files)
+}
+
+fn load_profile(path: &Path) -> anyhow::Result<DeviceProfile> {
+    let profile = fs::read_to_string(path).context("Read device profile")?;
+    toml::from_str(&profile).context("Parse device profile")
+}
+
+/// Profile chosen for each connected device, devices without one use the default gamepad bindings
+#[derive(Resource, Debug, Default)]
+pub struct DeviceAssignments(pub HashMap<Gamepad, String>);
+
+/// Input entity driven by a profiled device
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct ProfileInput {
+    pub gamepad: Gamepad,
+    pub profile: String,
+}
+
+fn assign_connected_devices(
+    mut events: EventReader<GamepadConnectionEvent>,
+    profiles: Res<DeviceProfiles>,
+    mut assignments: ResMut<DeviceAssignments>,
+) {
+    for event in events.read() {
+        match &event.connection {
+            GamepadConnection::Connected(info) => {
+                if let Some(profile) = profiles.for_device(&info.name) {
+                    info!("Using profile {} for {}", profile.name, info.name);
+
+                    assignments.0.insert(event.gamepad, profile.name.clone());
+                }
+            }
+            GamepadConnection::Disconnected => {
+                assignments.0.remove(&event.gamepad);
+            }
+        }
+    }
+}
+
+/// Keeps one input entity per robot for every profiled device
+fn sync_profile_inputs(
+    mut cmds: Commands,
+    assignments: Res<DeviceAssignments>,
+    profiles: Res<DeviceProfiles>,
+    robots: Query<(&NetId, &Name), With<Robot>>,
+    inputs: Query<(Entity, &ProfileInput, &RobotId)>,
+) {
+    let wanted = |input: &ProfileInput| {
+        assignments.0.get(&input.gamepad) == Some(&input.profile)
+            && profiles.0.contains_key(&input.profile)
+    };
+
+    for (entity, input, _) in &inputs {
+        if !wanted(input) {
+            cmds.entity(entity).despawn();
+        }
+    }
+
+    for (&gamepad, profile_name) in &assignments.0 {
+        let Some(profile) = profiles.0.get(profile_name) else {
+            continue;
+        };
+
+        for (&robot, name) in &robots {
+            let exists = inputs.iter().any(|(_, input, &RobotId(input_robot))| {
+                input.gamepad == gamepad && input.profile == *profile_name && input_robot == robot
+            });
+
+            if exists {
+                continue;
+            }
+
+            cmds.spawn((
+                SelectedServo::default(),
+                InputManagerBundle::<Action> {
+                    action_state: ActionState::default(),
+                    input_map: profile.input_map(gamepad),
+                },
+                MovementContributionBundle {
+                    name: Name::new(format!("{} {name}", profile.name)),
+                    contribution: MovementContribution(Movement::default()),
+                    robot: RobotId(robot),
+                },
+                ServoContribution(Default::default()),
+                InputInterpolation::normal(),
+                InputMarker,
+                ProfileInput {
+                    gamepad,
+                    profile: profile_name.clone(),
+                },
+                Replicate,
+            ));
+        }
+    }
+}
+
+/// Stops the default bindings from also reading devices that have a profile
+fn route_default_bindings(
+    gamepads: Res<Gamepads>,
+    assignments: Res<DeviceAssignments>,
+    mut inputs: Query<&mut InputMap<Action>, (With<InputMarker>, Without<ProfileInput>)>,
+) {
+    let wanted = default_gamepad(gamepads.iter(), &assignments);
+
+    for mut input_map in &mut inputs {
+        if input_map.gamepad() == wanted {
+            continue;
+        }
+
+        match wanted {
+            Some(gamepad) => input_map.set_gamepad(gamepad),
+            None => input_map.clear_gamepad(),
+        };
+    }
+}
+
+/// Gamepad read by the default bindings, `None` reads every gamepad
+fn default_gamepad(
+    mut connected: impl Iterator<Item = Gamepad>,
+    assignments: &DeviceAssignments,
+) -> Option<Gamepad> {
+    if assignments.0.is_empty() {
+        return None;
+    }
+
+    Some(
+        connected
+            .find(|it| !assignments.0.contains_key(it))
+            // Every connected device has a profile, point the defaults at one that does not exist
+            .unwrap_or(Gamepad::new(usize::MAX)),
+    )
+}
+
+fn apply_profile_axes(
+    profiles: Res<DeviceProfiles>,
+    axes: Res<Axis<GamepadAxis>>,
+    mut inputs: Query<(&ProfileInput, &mut ActionState<Action>)>,
+) {
+    for (input, mut action_state) in &mut inputs {
+        let Some(profile) = profiles.0.get(&input.profile) else {
+            continue;
+        };
+
+        let values =
+            profile.axis_values(|axis| axes.get(GamepadAxis::new(input.gamepad, axis.into())));
+
+        for (action, value) in values {
+            action_state.action_data_mut_or_default(&action).value = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::input::gamepad::Gamepad;
+
+    use crate::input::{Action, LevelingType};
+
+    use super::{
+        default_gamepad, AxisBinding, AxisCalibration, ButtonBinding, DeviceAssignments,
+        DeviceProfile, RawAxis, RawButton,
+    };
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn calibration_centers_and_scales() {
+        // Throttle reporting 0..1 that rests at 0.55
+        let calibration = AxisCalibration {
+            min: 0.0,
+            center: 0.55,
+            max: 1.0,
+            deadband: 0.0,
+            inverted: false,
+        };
+
+        assert!(close(calibration.normalize(0.55), 0.0));
+        assert!(close(calibration.normalize(1.0), 1.0));
+        assert!(close(calibration.normalize(0.0), -1.0));
+        // Each side is scaled on its own
+        assert!(close(calibration.normalize(0.775), 0.5));
+        assert!(close(calibration.normalize(0.275), -0.5));
+        // Readings past the calibrated range saturate
+        assert!(close(calibration.normalize(1.2), 1.0));
+        assert!(close(calibration.normalize(-0.3), -1.0));
+    }
+
+    #[test]
+    fn calibration_deadband() {
+        let calibration = AxisCalibration {
+            deadband: 0.1,
+            ..Default::default()
+        };
+
+        assert_eq!(calibration.normalize(0.05), 0.0);
+        assert_eq!(calibration.normalize(-0.1), 0.0);
+        // Output is continuous at the edge of the deadband and still reaches full travel
+        assert!(close(calibration.normalize(0.1001), 0.0001 / 0.9));
+        assert!(close(calibration.normalize(0.55), 0.5));
+        assert!(close(calibration.normalize(-1.0), -1.0));
+
+        let inverted = AxisCalibration {
+            inverted: true,
+            ..calibration
+        };
+        assert!(close(inverted.normalize(0.55), -0.5));
+    }
+
+    #[test]
+    fn degenerate_calibration_reads_zero() {
+        let calibration = AxisCalibration {
+            min: 0.5,
+            center: 0.5,
+            max: 0.5,
+            deadband: 0.0,
+            inverted: false,
+        };
+
+        assert_eq!(calibration.normalize(0.7), 0.0);
+        assert_eq!(calibration.normalize(0.2), 0.0);
+        assert_eq!(AxisCalibration::default().normalize(f32::NAN), 0.0);
+    }
+
+    #[test]
+    fn profile_roundtrip() {
+        let profile = DeviceProfile {
+            name: "Flight Stick".to_owned(),
+            devices: vec!["Thrustmaster T.16000M".to_owned()],
+            axes: vec![
+                AxisBinding {
+                    axis: RawAxis::LeftStickY,
+                    action: Action::Surge,
+                    calibration: AxisCalibration {
+                        min: -0.98,
+                        center: 0.04,
+                        max: 1.0,
+                        deadband: 0.05,
+                        inverted: false,
+                    },
+                },
+                AxisBinding {
+                    axis: RawAxis::Other(5),
+                    action: Action::Heave,
+                    calibration: AxisCalibration::default(),
+                },
+            ],
+            buttons: vec![
+                ButtonBinding {
+                    button: RawButton::South,
+                    action: Action::Arm,
+                },
+                ButtonBinding {
+                    button: RawButton::Other(12),
+                    action: Action::ToggleLeveling(LevelingType::Inverted),
+                },
+            ],
+        };
+
+        let serialized = toml::to_string(&profile).unwrap();
+        let deserialized: DeviceProfile = toml::from_str(&serialized).unwrap();
+
+        assert_eq!(deserialized, profile);
+    }
+
+    #[test]
+    fn handwritten_profile_parses() {
+        let profile: DeviceProfile = toml::from_str(
+            r#"
+            name = "Throttle"
+
+            [[axes]]
+            axis = "LeftZ"
+            action = "Heave"
+            calibration = { min = 0.0, center = 0.5, max = 1.0, deadband = 0.02, inverted = true }
+
+            [[axes]]
+            axis = "RightZ"
+            action = "Yaw"
+
+            [[buttons]]
+            button = { Other = 3 }
+            action = "ToggleDepthHold"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(profile.name, "Throttle");
+        assert!(profile.devices.is_empty());
+        assert_eq!(profile.axes[0].axis, RawAxis::LeftZ);
+        assert!(profile.axes[0].calibration.inverted);
+        // Missing calibrations fall back to a centered -1..1 axis
+        assert_eq!(profile.axes[1].calibration, AxisCalibration::default());
+        assert_eq!(profile.buttons[0].button, RawButton::Other(3));
+        assert_eq!(profile.buttons[0].action, Action::ToggleDepthHold);
+
+        let values = profile.axis_values(|axis| match axis {
+            RawAxis::LeftZ => Some(1.0),
+            _ => None,
+        });
+        assert!(close(values[&Action::Heave], -1.0));
+        assert_eq!(values[&Action::Yaw], 0.0);
+    }
+
+    #[test]
+    fn defaults_skip_profiled_devices() {
+        let pads = [Gamepad::new(0), Gamepad::new(1)];
+
+        let mut assignments = DeviceAssignments::default();
+        // Nothing profiled, the defaults read every gamepad like before
+        assert_eq!(default_gamepad(pads.into_iter(), &assignments), None);
+
+        assignments
+            .0
+            .insert(Gamepad::new(0), "Flight Stick".to_owned());
+        assert_eq!(
+            default_gamepad(pads.into_iter(), &assignments),
+            Some(Gamepad::new(1))
+        );
+
+        assignments.0.insert(Gamepad::new(1), "Throttle".to_owned());
+        let wanted = default_gamepad(pads.into_iter(), &assignments);
+        assert!(wanted.is_some_and(|it| !pads.contains(&it)));
+    }
+}
@@ -0,0 +1,350 @@
+//! Keeps egui windows on screen as the surface window is resized or moved between monitors
+//!
+//! Each placed window remembers where it sits relative to the nearest top corner, in points. When
+//! the window is resized or its scale factor changes, windows are put back relative to that corner
+//! and clamped so none end up partially off screen.
+
+use bevy::{
+    app::{App, Plugin, Update},
+    ecs::{
+        event::EventReader,
+        system::{ResMut, Resource},
+    },
+    utils::{HashMap, HashSet},
+    window::{WindowResized, WindowScaleFactorChanged},
+};
+use egui::{Context, Id, InnerResponse, Pos2, Rect, ScrollArea, Ui, Vec2, WidgetText};
+
+pub struct HudLayoutPlugin;
+
+impl Plugin for HudLayoutPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<WindowLayouts>()
+            .add_systems(Update, reclamp_on_resize);
+    }
+}
+
+/// Gap kept between windows and the edge of the screen
+pub const MARGIN: f32 = 20.0;
+/// Menus never get shorter than this, even when there is no room below them
+const MIN_MENU_HEIGHT: f32 = 100.0;
+/// The attitude display never gets smaller than this
+const MIN_ATTITUDE_SIZE: f32 = 96.0;
+
+/// Settings for the HUD
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct HudSettings {
+    /// Largest width and height of the attitude display, in points
+    pub attitude_size: f32,
+}
+
+impl Default for HudSettings {
+    fn default() -> Self {
+        Self {
+            attitude_size: 230.0,
+        }
+    }
+}
+
+/// Size of the attitude display, shrinking on small windows so the HUD still fits
+pub fn attitude_size(max: f32, bounds: Rect) -> f32 {
+    max.min(bounds.width() / 4.0)
+        .min(bounds.height() / 2.0)
+        .max(MIN_ATTITUDE_SIZE)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+}
+
+/// Where a window prefers to be
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowPlacement {
+    pub corner: Corner,
+    /// From `corner` of the bounds to the same corner of the window
+    pub offset: Vec2,
+    /// Last size the window was shown at
+    pub size: Vec2,
+}
+
+impl WindowPlacement {
+    pub fn anchored(corner: Corner) -> Self {
+        Self {
+            corner,
+            offset: Vec2::ZERO,
+            size: Vec2::ZERO,
+        }
+    }
+
+    /// Remembers a window relative to whichever top corner it is closer to
+    pub fn from_rect(rect: Rect, bounds: Rect) -> Self {
+        let (corner, offset) = if rect.center().x > bounds.center().x {
+            (Corner::TopRight, rect.right_top() - bounds.right_top())
+        } else {
+            (Corner::TopLeft, rect.left_top() - bounds.left_top())
+        };
+
+        Self {
+            corner,
+            offset,
+            size: rect.size(),
+        }
+    }
+
+    /// Top left of the window, kept inside `bounds`
+    pub fn resolve(&self, bounds: Rect) -> Pos2 {
+        let pos = match self.corner {
+            Corner::TopLeft => bounds.left_top() + self.offset,
+            Corner::TopRight => bounds.right_top() + self.offset - Vec2::new(self.size.x, 0.0),
+        };
+
+        clamp(pos, self.size, bounds)
+    }
+}
+
+/// Moves a window's top left so as much of it as possible is inside `bounds`
+///
+/// Windows larger than the bounds are kept at the top left so their title bar stays reachable
+pub fn clamp(pos: Pos2, size: Vec2, bounds: Rect) -> Pos2 {
+    Pos2::new(
+        pos.x.min(bounds.max.x - size.x).max(bounds.min.x),
+        pos.y.min(bounds.max.y - size.y).max(bounds.min.y),
+    )
+}
+
+/// Preferred placement of every placed window, by egui id
+#[derive(Resource, Default, Debug)]
+pub struct WindowLayouts {
+    placements: HashMap<Id, WindowPlacement>,
+    /// Windows that need to be put back where they prefer to be
+    stale: HashSet<Id>,
+}
+
+impl WindowLayouts {
+    /// Positions a window from its preferred placement, placing it at `corner` the first time
+    pub fn place<'a>(
+        &mut self,
+        window: egui::Window<'a>,
+        id: Id,
+        corner: Corner,
+        bounds: Rect,
+    ) -> egui::Window<'a> {
+        let window = window.id(id).constrain_to(bounds).max_size(bounds.size());
+
+        let placement = *self
+            .placements
+            .entry(id)
+            .or_insert_with(|| WindowPlacement::anchored(corner));
+        let pos = placement.resolve(bounds);
+
+        if self.stale.remove(&id) {
+            window.current_pos(pos)
+        } else {
+            window.default_pos(pos)
+        }
+    }
+
+    /// Records the size of a placed window, and its position if the user moved it
+    ///
+    /// Positions the window was only clamped to are not recorded, so it returns to where it was
+    /// once there is room again
+    pub fn record<R>(&mut self, id: Id, response: &Option<InnerResponse<R>>, bounds: Rect) {
+        let Some(response) = response else {
+            return;
+        };
+        let rect = response.response.rect;
+
+        let placement = self
+            .placements
+            .entry(id)
+            .or_insert_with(|| WindowPlacement::from_rect(rect, bounds));
+
+        if response.response.dragged() {
+            *placement = WindowPlacement::from_rect(rect, bounds);
+        } else {
+            placement.size = rect.size();
+        }
+    }
+
+    pub fn placement(&self, id: Id) -> Option<&WindowPlacement> {
+        self.placements.get(&id)
+    }
+
+    /// Puts every window back where it prefers to be on its next frame
+    pub fn mark_stale(&mut self) {
+        self.stale.extend(self.placements.keys().copied());
+    }
+}
+
+/// The area windows are kept inside of
+pub fn window_bounds(context: &Context) -> Rect {
+    context.available_rect().shrink(MARGIN)
+}
+
+/// Like [`Ui::menu_button`] but its contents scroll instead of running off the bottom of small
+/// windows
+pub fn menu_button<R>(
+    ui: &mut Ui,
+    title: impl Into<WidgetText>,
+    add_contents: impl FnOnce(&mut Ui) -> R,
+) -> InnerResponse<Option<R>> {
+    ui.menu_button(title, |ui| {
+        let max_height = menu_height(ui.ctx().screen_rect(), ui.cursor().top());
+
+        ScrollArea::vertical()
+            .max_height(max_height)
+            .show(ui, add_contents)
+            .inner
+    })
+}
+
+/// Room left for a menu starting at `top`
+pub fn menu_height(screen: Rect, top: f32) -> f32 {
+    (screen.bottom() - top - MARGIN).max(MIN_MENU_HEIGHT)
+}
+
+fn reclamp_on_resize(
+    mut layouts: ResMut<WindowLayouts>,
+    mut resized: EventReader<WindowResized>,
+    mut rescaled: EventReader<WindowScaleFactorChanged>,
+) {
+    let resized = resized.read().count() > 0;
+    let rescaled = rescaled.read().count() > 0;
+
+    if resized || rescaled {
+        layouts.mark_stale();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use egui::{Id, Pos2, Rect, Vec2};
+
+    use super::{
+        attitude_size, clamp, menu_height, Corner, WindowLayouts, WindowPlacement, MARGIN,
+    };
+
+    fn screen(width: f32, height: f32) -> Rect {
+        Rect::from_min_size(Pos2::ZERO, Vec2::new(width, height)).shrink(MARGIN)
+    }
+
+    #[test]
+    fn clamps_into_bounds() {
+        let bounds = screen(1000.0, 800.0);
+        let size = Vec2::new(300.0, 200.0);
+
+        assert_eq!(
+            clamp(Pos2::new(100.0, 100.0), size, bounds),
+            Pos2::new(100.0, 100.0)
+        );
+        assert_eq!(
+            clamp(Pos2::new(900.0, 700.0), size, bounds),
+            Pos2::new(680.0, 580.0)
+        );
+        assert_eq!(
+            clamp(Pos2::new(-50.0, -50.0), size, bounds),
+            Pos2::new(MARGIN, MARGIN)
+        );
+
+        // Too large to fit, the title bar stays reachable
+        assert_eq!(
+            clamp(Pos2::new(500.0, 500.0), Vec2::new(2000.0, 2000.0), bounds),
+            Pos2::new(MARGIN, MARGIN)
+        );
+    }
+
+    #[test]
+    fn top_right_follows_resizes() {
+        let placement = WindowPlacement {
+            size: Vec2::new(480.0, 300.0),
+            ..WindowPlacement::anchored(Corner::TopRight)
+        };
+
+        assert_eq!(
+            placement.resolve(screen(1920.0, 1080.0)),
+            Pos2::new(1920.0 - MARGIN - 480.0, MARGIN)
+        );
+        assert_eq!(
+            placement.resolve(screen(1280.0, 720.0)),
+            Pos2::new(1280.0 - MARGIN - 480.0, MARGIN)
+        );
+    }
+
+    #[test]
+    fn moved_windows_keep_their_corner() {
+        let bounds = screen(1920.0, 1080.0);
+
+        // Dragged near the top right corner
+        let rect = Rect::from_min_size(Pos2::new(1300.0, 100.0), Vec2::new(400.0, 300.0));
+        let placement = WindowPlacement::from_rect(rect, bounds);
+        assert_eq!(placement.corner, Corner::TopRight);
+        assert_eq!(placement.resolve(bounds), rect.min);
+
+        // Same distance from the right edge after shrinking
+        let smaller = screen(1600.0, 900.0);
+        assert_eq!(placement.resolve(smaller), Pos2::new(980.0, 100.0));
+
+        // Clamping on a tiny screen doesn't forget where it was
+        assert_ne!(placement.resolve(screen(800.0, 600.0)), rect.min);
+        assert_eq!(placement.resolve(bounds), rect.min);
+
+        // Dragged near the left
+        let rect = Rect::from_min_size(Pos2::new(200.0, 50.0), Vec2::new(400.0, 300.0));
+        let placement = WindowPlacement::from_rect(rect, bounds);
+        assert_eq!(placement.corner, Corner::TopLeft);
+        assert_eq!(placement.resolve(smaller), rect.min);
+    }
+
+    #[test]
+    fn scale_factor_changes_stay_on_screen() {
+        // A 4K monitor at 100% then 150%, the same window is fewer points wide
+        let native = screen(3840.0, 2160.0);
+        let scaled = screen(3840.0 / 1.5, 2160.0 / 1.5);
+
+        let rect = Rect::from_min_size(Pos2::new(1500.0, 1700.0), Vec2::new(600.0, 400.0));
+        let placement = WindowPlacement::from_rect(rect, native);
+
+        let pos = placement.resolve(scaled);
+        let resolved = Rect::from_min_size(pos, placement.size);
+        assert!(scaled.contains_rect(resolved), "{resolved:?}");
+    }
+
+    #[test]
+    fn resizes_force_the_preferred_position() {
+        let bounds = screen(1920.0, 1080.0);
+        let id = Id::new("HUD");
+        let mut layouts = WindowLayouts::default();
+
+        let _ = layouts.place(egui::Window::new("HUD"), id, Corner::TopRight, bounds);
+        assert_eq!(
+            layouts.placement(id),
+            Some(&WindowPlacement::anchored(Corner::TopRight))
+        );
+        assert!(layouts.stale.is_empty());
+
+        layouts.mark_stale();
+        assert!(layouts.stale.contains(&id));
+
+        // Forced once, then left to the user again
+        let _ = layouts.place(egui::Window::new("HUD"), id, Corner::TopRight, bounds);
+        assert!(layouts.stale.is_empty());
+    }
+
+    #[test]
+    fn attitude_is_sized_from_its_max() {
+        assert_eq!(attitude_size(230.0, screen(3840.0, 2160.0)), 230.0);
+        assert_eq!(attitude_size(400.0, screen(1920.0, 1080.0)), 400.0);
+        assert_eq!(attitude_size(230.0, screen(640.0, 480.0)), 150.0);
+        assert_eq!(attitude_size(230.0, screen(200.0, 200.0)), 96.0);
+    }
+
+    #[test]
+    fn menus_fit_below_their_button() {
+        let screen = Rect::from_min_size(Pos2::ZERO, Vec2::new(800.0, 400.0));
+
+        assert_eq!(menu_height(screen, 30.0), 350.0);
+        assert_eq!(menu_height(screen, 350.0), 100.0);
+    }
+}
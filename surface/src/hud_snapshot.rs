@@ -0,0 +1,686 @@
+//! What the HUD shows about the robot, formatted only when it changes
+//!
+//! The HUD is drawn every frame but most of what it shows changes a few times a second at most.
+//! [`RobotHudSnapshot`] is rebuilt when one of its source components changes, so drawing it is
+//! just handing cached strings to egui. Sensor readings and link stats that stream in every frame
+//! only trigger a rebuild every [`TELEMETRY_REFRESH`], and system stats are only reformatted every
+//! [`SYSTEM_REFRESH`] so they stay readable.
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+use common::{
+    components::{
+        Armed, CpuTotal, CurrentDraw, Depth, DepthTarget, Inertial, LoadAverage, MeasuredVoltage,
+        Memory, OrientationTarget, PowerMode, PowerModeOverride, PowerModeReason, Robot, RobotId,
+        Temperatures,
+    },
+    sync::{Latency, Peer, PeerTickRate},
+};
+use egui::{Color32, RichText};
+use networking::Token;
+
+use crate::theme::{Palette, Theme};
+
+/// How often CPU, load and memory are reformatted while they keep changing
+pub const SYSTEM_REFRESH: Duration = Duration::from_millis(500);
+/// How often streaming IMU, depth and link readings can trigger a rebuild on their own
+pub const TELEMETRY_REFRESH: Duration = Duration::from_millis(100);
+
+pub struct HudSnapshotPlugin;
+
+impl Plugin for HudSnapshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<RobotHudSnapshot>()
+            .add_systems(Update, update_hud_snapshot);
+    }
+}
+
+/// A line of HUD text, `None` uses egui's text color
+#[derive(Debug, Clone, PartialEq)]
+pub struct HudText {
+    pub text: String,
+    pub color: Option<Color32>,
+}
+
+impl HudText {
+    pub fn plain(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            color: None,
+        }
+    }
+
+    pub fn colored(text: impl Into<String>, color: Color32) -> Self {
+        Self {
+            text: text.into(),
+            color: Some(color),
+        }
+    }
+
+    pub fn rich(&self, size: f32) -> RichText {
+        let text = RichText::new(&self.text).size(size);
+
+        match self.color {
+            Some(color) => text.color(color),
+            None => text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeerHud {
+    pub token: Token,
+    pub addrs: String,
+    pub ping: Option<HudText>,
+    pub frame_time: Option<HudText>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RobotHud {
+    pub entity: Entity,
+    pub robot_id: RobotId,
+    pub name: String,
+
+    pub status: Option<HudText>,
+    /// Voltage then current
+    pub power: Option<[HudText; 2]>,
+    /// The robot's power mode and why it is in it
+    pub power_mode: Option<HudText>,
+    /// Mode the surface pinned, see [`PowerModeOverride`]
+    pub power_override: Option<PowerMode>,
+    /// CPU, load and memory, refreshed every [`SYSTEM_REFRESH`] at most
+    pub system: Vec<HudText>,
+    pub peer: Option<PeerHud>,
+    /// IMU, component and water temperatures
+    pub temperatures: Vec<HudText>,
+    pub depth: Option<HudText>,
+    pub depth_target: Option<HudText>,
+    pub orientation_control: bool,
+}
+
+/// The HUD's view of the robot, `None` while there isn't exactly one robot
+#[derive(Resource, Debug, Default)]
+pub struct RobotHudSnapshot {
+    pub robot: Option<RobotHud>,
+
+    rebuilds: u64,
+    /// Which optional source components were present at the last rebuild
+    present: u32,
+    /// When the system stats were last formatted
+    system_at: Option<Duration>,
+    /// System stats changed since they were last formatted
+    system_stale: bool,
+    /// When the snapshot last picked up the streaming readings
+    telemetry_at: Option<Duration>,
+    /// Streaming readings changed since the last rebuild
+    telemetry_stale: bool,
+}
+
+impl RobotHudSnapshot {
+    /// How many times the snapshot was rebuilt
+    pub fn rebuilds(&self) -> u64 {
+        self.rebuilds
+    }
+}
+
+pub fn status_text(armed: Armed, palette: &Palette) -> HudText {
+    let label = match armed {
+        Armed::Armed => "Armed",
+        Armed::Disarmed => "Disarmed",
+    };
+
+    HudText::colored(label, palette.armed(armed))
+}
+
+pub fn power_text(
+    voltage: &MeasuredVoltage,
+    current: &CurrentDraw,
+    palette: &Palette,
+) -> [HudText; 2] {
+    [
+        HudText::colored(voltage.0.to_string(), palette.voltage(voltage.0 .0)),
+        HudText::colored(current.0.to_string(), palette.current(current.0 .0)),
+    ]
+}
+
+pub fn power_mode_label(mode: PowerMode) -> &'static str {
+    match mode {
+        PowerMode::Normal => "Normal",
+        PowerMode::LowPower => "Low Power",
+        PowerMode::Critical => "Critical",
+    }
+}
+
+pub fn power_mode_text(
+    mode: PowerMode,
+    reason: Option<&PowerModeReason>,
+    palette: &Palette,
+) -> HudText {
+    let label = power_mode_label(mode);
+    let text = match reason {
+        Some(reason) if !reason.0.is_empty() => format!("{label} ({})", reason.0),
+        _ => label.to_owned(),
+    };
+
+    HudText::colored(text, palette.power_mode(mode))
+}
+
+pub fn cpu_text(cpu: &CpuTotal) -> String {
+    format!("CPU: {:.2}%", cpu.0.usage)
+}
+
+pub fn load_text(load: &LoadAverage) -> String {
+    format!(
+        "Load: {:.2}, {:.2}, {:.2}",
+        load.one_min, load.five_min, load.fifteen_min
+    )
+}
+
+pub fn memory_text(memory: &Memory) -> String {
+    if memory.total_mem == 0 {
+        return "RAM: --".to_owned();
+    }
+
+    let usage = memory.used_mem as f64 / memory.total_mem as f64 * 100.0;
+    format!("RAM: {usage:.2}%")
+}
+
+pub fn ping_text(ping: u32) -> HudText {
+    HudText::plain(format!("Ping: {ping} frames"))
+}
+
+pub fn frame_time_text(tick_rate: &PeerTickRate) -> HudText {
+    let text = format!("Frame time: {:.1?}", tick_rate.frame_time);
+
+    if tick_rate.is_slow() {
+        HudText::colored(text, Color32::YELLOW)
+    } else {
+        HudText::plain(text)
+    }
+}
+
+pub fn temperature_lines(
+    inertial: Option<&Inertial>,
+    temps: Option<&Temperatures>,
+    depth: Option<&Depth>,
+) -> Vec<HudText> {
+    let mut lines = Vec::new();
+
+    if let Some(inertial) = inertial {
+        lines.push(HudText::plain(format!(
+            "IMU Temp: {}",
+            inertial.0.tempature
+        )));
+    }
+
+    if let Some(temps) = temps {
+        for temp in temps.0.iter() {
+            lines.push(HudText::plain(format!("{}: {}", temp.name, temp.tempature)));
+        }
+    }
+
+    if let Some(depth) = depth {
+        lines.push(HudText::plain(format!(
+            "Water Temp: {}",
+            depth.0.temperature
+        )));
+    }
+
+    lines
+}
+
+fn system_lines(
+    cpu: Option<&CpuTotal>,
+    load: Option<&LoadAverage>,
+    memory: Option<&Memory>,
+) -> Vec<HudText> {
+    [
+        cpu.map(cpu_text),
+        load.map(load_text),
+        memory.map(memory_text),
+    ]
+    .into_iter()
+    .flatten()
+    .map(HudText::plain)
+    .collect()
+}
+
+fn changed<T: Component>(component: &Option<Ref<T>>) -> bool {
+    component.as_ref().is_some_and(|it| it.is_changed())
+}
+
+type HudSources = (
+    Entity,
+    Ref<'static, Name>,
+    Ref<'static, RobotId>,
+    (
+        Option<Ref<'static, Armed>>,
+        Option<Ref<'static, MeasuredVoltage>>,
+        Option<Ref<'static, CurrentDraw>>,
+        Option<Ref<'static, PowerMode>>,
+        Option<Ref<'static, PowerModeReason>>,
+        Option<Ref<'static, PowerModeOverride>>,
+    ),
+    (
+        Option<Ref<'static, Inertial>>,
+        Option<Ref<'static, Temperatures>>,
+        Option<Ref<'static, Depth>>,
+        Option<Ref<'static, DepthTarget>>,
+        Option<Ref<'static, OrientationTarget>>,
+    ),
+    (
+        Option<Ref<'static, Peer>>,
+        Option<Ref<'static, Latency>>,
+        Option<Ref<'static, PeerTickRate>>,
+    ),
+    (
+        Option<Ref<'static, CpuTotal>>,
+        Option<Ref<'static, LoadAverage>>,
+        Option<Ref<'static, Memory>>,
+    ),
+);
+
+pub fn update_hud_snapshot(
+    mut snapshot: ResMut<RobotHudSnapshot>,
+    theme: Res<Theme>,
+    time: Res<Time>,
+    robots: Query<HudSources, With<Robot>>,
+) {
+    let Ok(robot) = robots.get_single() else {
+        if snapshot.robot.is_some() {
+            snapshot.robot = None;
+            snapshot.rebuilds += 1;
+        }
+
+        return;
+    };
+
+    let (
+        entity,
+        name,
+        robot_id,
+        (armed, voltage, current, power_mode, power_reason, power_override),
+        (inertial, temps, depth, depth_target, orientation_target),
+        (peer, latency, tick_rate),
+        (cpu, load, memory),
+    ) = robot;
+
+    let present = [
+        armed.is_some(),
+        voltage.is_some(),
+        current.is_some(),
+        power_mode.is_some(),
+        power_reason.is_some(),
+        power_override.is_some(),
+        inertial.is_some(),
+        temps.is_some(),
+        depth.is_some(),
+        depth_target.is_some(),
+        orientation_target.is_some(),
+        peer.is_some(),
+        latency.is_some(),
+        tick_rate.is_some(),
+        cpu.is_some(),
+        load.is_some(),
+        memory.is_some(),
+    ]
+    .into_iter()
+    .enumerate()
+    .fold(0, |mask, (bit, present)| mask | ((present as u32) << bit));
+
+    let now = time.elapsed();
+    let snapshot = &mut *snapshot;
+
+    // Something appeared, went away or needs recoloring, everything is redone
+    let reshaped = snapshot.robot.as_ref().map(|it| it.entity) != Some(entity)
+        || snapshot.present != present
+        || theme.is_changed();
+
+    let dirty = reshaped
+        || name.is_changed()
+        || robot_id.is_changed()
+        || changed(&armed)
+        || changed(&voltage)
+        || changed(&current)
+        || changed(&power_mode)
+        || changed(&power_reason)
+        || changed(&power_override)
+        || changed(&temps)
+        || changed(&depth_target)
+        || changed(&orientation_target)
+        || changed(&peer);
+
+    // These change every frame while the robot is streaming, rebuilding for each one would make
+    // the snapshot pointless
+    snapshot.telemetry_stale |=
+        changed(&inertial) || changed(&depth) || changed(&latency) || changed(&tick_rate);
+    let telemetry_due = match snapshot.telemetry_at {
+        Some(at) => now.saturating_sub(at) >= TELEMETRY_REFRESH,
+        None => true,
+    };
+    let dirty = dirty || (snapshot.telemetry_stale && telemetry_due);
+
+    snapshot.system_stale |= changed(&cpu) || changed(&load) || changed(&memory);
+    let system_due = match snapshot.system_at {
+        Some(at) => now.saturating_sub(at) >= SYSTEM_REFRESH,
+        None => true,
+    };
+    let refresh_system = reshaped || (snapshot.system_stale && system_due);
+
+    if !dirty && !refresh_system {
+        return;
+    }
+
+    let system = match (&snapshot.robot, refresh_system) {
+        (Some(previous), false) => previous.system.clone(),
+        _ => {
+            snapshot.system_at = Some(now);
+            snapshot.system_stale = false;
+
+            system_lines(cpu.as_deref(), load.as_deref(), memory.as_deref())
+        }
+    };
+
+    // Everything else is formatted from the latest values
+    snapshot.telemetry_at = Some(now);
+    snapshot.telemetry_stale = false;
+
+    let palette = &theme.palette;
+    snapshot.robot = Some(RobotHud {
+        entity,
+        robot_id: *robot_id,
+        name: name.as_str().to_owned(),
+
+        status: armed.map(|armed| status_text(*armed, palette)),
+        power: voltage
+            .as_deref()
+            .zip(current.as_deref())
+            .map(|(voltage, current)| power_text(voltage, current, palette)),
+        power_mode: power_mode.map(|mode| power_mode_text(*mode, power_reason.as_deref(), palette)),
+        power_override: power_override.and_then(|it| it.0),
+        system,
+        peer: peer
+            .as_deref()
+            .zip(latency.as_deref())
+            .map(|(peer, latency)| PeerHud {
+                token: peer.token,
+                addrs: peer.addrs.to_string(),
+                ping: latency.ping.map(ping_text),
+                frame_time: tick_rate.as_deref().map(frame_time_text),
+            }),
+        temperatures: temperature_lines(inertial.as_deref(), temps.as_deref(), depth.as_deref()),
+        depth: depth
+            .as_deref()
+            .map(|depth| HudText::plain(format!("Depth: {}", depth.0.depth))),
+        depth_target: depth_target
+            .as_deref()
+            .map(|target| HudText::plain(format!("Depth Target: {}", target.0))),
+        orientation_control: orientation_target.is_some(),
+    });
+    snapshot.present = present;
+    snapshot.rebuilds += 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{app::App, prelude::*};
+    use common::{
+        components::{
+            Armed, CpuTotal, CurrentDraw, Depth, LoadAverage, MeasuredVoltage, Memory, PowerMode,
+            Inertial, PowerModeOverride, PowerModeReason, Robot, RobotId,
+        },
+        ecs_sync::NetId,
+        types::{
+            hw::{DepthFrame, InertialFrame},
+            system::Cpu,
+            units::{Amperes, Celsius, Volts},
+        },
+    };
+
+    use crate::theme::{Palette, Theme, ThemeMode};
+
+    use super::{
+        cpu_text, load_text, memory_text, power_text, status_text, update_hud_snapshot, HudText,
+        RobotHudSnapshot, SYSTEM_REFRESH, TELEMETRY_REFRESH,
+    };
+
+    fn cpu(usage: f32) -> CpuTotal {
+        CpuTotal(Cpu {
+            frequency: 1500,
+            usage,
+            name: "cpu".to_owned(),
+        })
+    }
+
+    fn memory(used_mem: u64, total_mem: u64) -> Memory {
+        Memory {
+            total_mem,
+            used_mem,
+            free_mem: total_mem - used_mem,
+            total_swap: 0,
+            used_swap: 0,
+            free_swap: 0,
+        }
+    }
+
+    #[test]
+    fn formatting() {
+        let palette = Palette::dark();
+
+        assert_eq!(
+            status_text(Armed::Armed, &palette),
+            HudText::colored("Armed", palette.good)
+        );
+        assert_eq!(
+            power_text(
+                &MeasuredVoltage(Volts(11.0)),
+                &CurrentDraw(Amperes(5.0)),
+                &palette
+            )
+            .map(|it| it.color),
+            [Some(palette.bad), Some(palette.good)]
+        );
+
+        assert_eq!(cpu_text(&cpu(12.345)), "CPU: 12.35%");
+        assert_eq!(
+            load_text(&LoadAverage {
+                one_min: 1.0,
+                five_min: 0.5,
+                fifteen_min: 0.25,
+            }),
+            "Load: 1.00, 0.50, 0.25"
+        );
+        assert_eq!(memory_text(&memory(256, 1024)), "RAM: 25.00%");
+        assert_eq!(memory_text(&memory(0, 0)), "RAM: --");
+    }
+
+    fn app() -> (App, Entity) {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .insert_resource(Theme::new(ThemeMode::Dark))
+            .init_resource::<RobotHudSnapshot>()
+            .add_systems(Update, update_hud_snapshot);
+
+        let robot = app
+            .world
+            .spawn((
+                Robot,
+                RobotId(NetId::random()),
+                Name::new("ROV"),
+                Armed::Disarmed,
+                Depth(DepthFrame::default()),
+                cpu(10.0),
+            ))
+            .id();
+
+        (app, robot)
+    }
+
+    fn step(app: &mut App, millis: u64) {
+        app.world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_millis(millis));
+        app.update();
+    }
+
+    fn rebuilds(app: &App) -> u64 {
+        app.world.resource::<RobotHudSnapshot>().rebuilds()
+    }
+
+    fn system(app: &App) -> Vec<String> {
+        let snapshot = app.world.resource::<RobotHudSnapshot>();
+        let robot = snapshot.robot.as_ref().unwrap();
+
+        robot.system.iter().map(|it| it.text.clone()).collect()
+    }
+
+    #[test]
+    fn only_rebuilds_on_change() {
+        let (mut app, robot) = app();
+
+        step(&mut app, 10);
+        assert_eq!(rebuilds(&app), 1);
+
+        for _ in 0..5 {
+            step(&mut app, 10);
+        }
+        assert_eq!(rebuilds(&app), 1);
+
+        app.world.entity_mut(robot).insert(Armed::Armed);
+        step(&mut app, 10);
+        assert_eq!(rebuilds(&app), 2);
+
+        let snapshot = app.world.resource::<RobotHudSnapshot>();
+        let status = snapshot.robot.as_ref().unwrap().status.as_ref().unwrap();
+        assert_eq!(status.text, "Armed");
+
+        // Losing a component changes what is shown too
+        app.world.entity_mut(robot).remove::<Depth>();
+        step(&mut app, 10);
+        assert_eq!(rebuilds(&app), 3);
+
+        app.world.despawn(robot);
+        step(&mut app, 10);
+        assert_eq!(rebuilds(&app), 4);
+        assert!(app.world.resource::<RobotHudSnapshot>().robot.is_none());
+
+        step(&mut app, 10);
+        assert_eq!(rebuilds(&app), 4);
+    }
+
+    #[test]
+    fn power_mode_shows_its_reason() {
+        let (mut app, robot) = app();
+        let palette = Palette::dark();
+
+        app.world.entity_mut(robot).insert((
+            PowerMode::LowPower,
+            PowerModeReason("Battery below 10.5V".to_owned()),
+        ));
+        step(&mut app, 10);
+
+        let snapshot = app.world.resource::<RobotHudSnapshot>();
+        let hud = snapshot.robot.as_ref().unwrap();
+        assert_eq!(
+            hud.power_mode,
+            Some(HudText::colored(
+                "Low Power (Battery below 10.5V)",
+                palette.warning
+            ))
+        );
+        assert_eq!(hud.power_override, None);
+
+        app.world.entity_mut(robot).insert((
+            PowerMode::Critical,
+            PowerModeReason("Set from the surface".to_owned()),
+            PowerModeOverride(Some(PowerMode::Critical)),
+        ));
+        step(&mut app, 10);
+
+        let snapshot = app.world.resource::<RobotHudSnapshot>();
+        let hud = snapshot.robot.as_ref().unwrap();
+        assert_eq!(
+            hud.power_mode,
+            Some(HudText::colored(
+                "Critical (Set from the surface)",
+                palette.bad
+            ))
+        );
+        assert_eq!(hud.power_override, Some(PowerMode::Critical));
+    }
+
+    #[test]
+    fn system_stats_are_debounced() {
+        let (mut app, robot) = app();
+        step(&mut app, 10);
+        assert_eq!(system(&app), ["CPU: 10.00%"]);
+
+        // Changes faster than the refresh wait for it
+        app.world.entity_mut(robot).insert(cpu(20.0));
+        step(&mut app, 100);
+        assert_eq!(rebuilds(&app), 1);
+        assert_eq!(system(&app), ["CPU: 10.00%"]);
+
+        // Even when nothing changes in the meantime
+        let refresh = SYSTEM_REFRESH.as_millis() as u64;
+        step(&mut app, refresh);
+        assert_eq!(rebuilds(&app), 2);
+        assert_eq!(system(&app), ["CPU: 20.00%"]);
+
+        // Other changes don't let them through early
+        app.world
+            .entity_mut(robot)
+            .insert((cpu(30.0), Armed::Armed));
+        step(&mut app, 10);
+        assert_eq!(rebuilds(&app), 3);
+        assert_eq!(system(&app), ["CPU: 20.00%"]);
+
+        step(&mut app, refresh);
+        assert_eq!(system(&app), ["CPU: 30.00%"]);
+
+        // Settled values are left alone
+        let settled = rebuilds(&app);
+        step(&mut app, refresh);
+        assert_eq!(rebuilds(&app), settled);
+    }
+
+    #[test]
+    fn streaming_telemetry_is_debounced() {
+        let (mut app, robot) = app();
+        step(&mut app, 10);
+        assert_eq!(rebuilds(&app), 1);
+
+        let tempature = |frame: u32| Celsius(20.0 + frame as f32 * 0.01);
+
+        // A new IMU reading every frame for a second at 60 fps
+        for frame in 0..60 {
+            app.world.entity_mut(robot).insert(Inertial(InertialFrame {
+                tempature: tempature(frame),
+                capture_time: frame as f64 / 60.0,
+                ..default()
+            }));
+            step(&mut app, 16);
+        }
+
+        // At most one rebuild per refresh instead of one per reading
+        let periods = 60 * 16 / TELEMETRY_REFRESH.as_millis() as u64;
+        let streamed = rebuilds(&app) - 1;
+        assert!(
+            (1..=periods).contains(&streamed),
+            "{streamed} rebuilds for {periods} refresh periods"
+        );
+
+        // The last reading still makes it once the stream stops
+        step(&mut app, TELEMETRY_REFRESH.as_millis() as u64);
+        let snapshot = app.world.resource::<RobotHudSnapshot>();
+        let temperatures = &snapshot.robot.as_ref().unwrap().temperatures;
+        assert_eq!(temperatures[0].text, format!("IMU Temp: {}", tempature(59)));
+
+        let settled = rebuilds(&app);
+        step(&mut app, TELEMETRY_REFRESH.as_millis() as u64);
+        assert_eq!(rebuilds(&app), settled);
+    }
+}
@@ -1,26 +1,34 @@
-use std::{borrow::Cow, mem};
+use std::{mem, time::Duration};
 
-use ahash::HashSet;
+use ahash::{HashMap, HashSet};
 use bevy::{
+    ecs::system::{EntityCommands, RunSystemOnce, SystemParam},
     math::{vec3a, Vec3A},
     prelude::*,
 };
 use common::{
     bundles::MovementContributionBundle,
     components::{
-        Armed, Depth, DepthTarget, MovementAxisMaximums, MovementContribution, Orientation,
-        OrientationTarget, Robot, RobotId, ServoContribution, Servos,
+        ArmAuthority, Armed, Depth, DepthTarget, MovementAxisMaximums, MovementContribution,
+        Orientation, OrientationTarget, Robot, RobotId, ServoContribution, Servos, Surface,
     },
     ecs_sync::{NetId, Replicate},
-    events::ResetServo,
-    types::units::Meters,
+    events::{ArmRequest, DisarmRequest, ReleaseArmAuthority, RequestArmAuthority, ResetServo},
+    types::{ids::ServoId, units::Meters},
 };
-use egui::TextBuffer;
 use leafwing_input_manager::{
     action_state::ActionState, axislike::SingleAxis, input_map::InputMap,
     plugin::InputManagerPlugin, Actionlike, InputManagerBundle,
 };
 use motor_math::{solve::reverse::Axis, Movement};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    command_palette::{
+        any_matching, AppCommandExt, CommandPalette, CommandPaletteSet, SurfaceCommand,
+    },
+    surface::LocalSurfaceMarker,
+};
 
 // TODO(low): Handle multiple gamepads better
 pub struct InputPlugin;
@@ -28,6 +36,7 @@ pub struct InputPlugin;
 impl Plugin for InputPlugin {
     fn build(&self, app: &mut App) {
         app.register_type::<InputInterpolation>()
+            .add_event::<NudgeSetpoint>()
             .add_plugins(InputManagerPlugin::<Action>::default())
             .add_systems(
                 Update,
@@ -35,7 +44,8 @@ impl Plugin for InputPlugin {
                     attach_to_new_robots,
                     handle_disconnected_robots,
                     movement,
-                    arm,
+                    // Enter both arms and picks a command in the palette
+                    arm.before(CommandPaletteSet),
                     depth_hold,
                     leveling,
                     trim_orientation,
@@ -43,14 +53,38 @@ impl Plugin for InputPlugin {
                     servos,
                     robot_mode,
                     switch_pitch_roll,
+                    nudge_setpoints,
+                    apply_nudges.after(nudge_setpoints),
                 ),
+            )
+            .add_command(
+                SurfaceCommand::new("Arm", |world| world.run_system_once(arm_all))
+                    .with_confirmation("The thrusters respond to input as soon as the robot arms.")
+                    .enabled_if(|world| robots_in(world, Armed::Disarmed)),
+            )
+            .add_command(
+                SurfaceCommand::new("Disarm", |world| world.run_system_once(disarm_all))
+                    .enabled_if(|world| robots_in(world, Armed::Armed)),
+            )
+            .add_command(
+                SurfaceCommand::new("Toggle Depth Hold", |world| {
+                    world.run_system_once(toggle_all_depth_holds)
+                })
+                .enabled_if(any_matching::<(With<Robot>, With<Depth>)>),
             );
     }
 }
 
+fn robots_in(world: &mut World, state: Armed) -> bool {
+    world
+        .query_filtered::<&Armed, With<Robot>>()
+        .iter(world)
+        .any(|it| *it == state)
+}
+
 #[derive(Component, Debug, Clone, Default, Reflect)]
 pub struct SelectedServo {
-    pub servo: Option<Cow<'static, str>>,
+    pub servo: Option<ServoId>,
 }
 
 #[derive(Component, Debug, Clone, Copy, Reflect, PartialEq)]
@@ -78,6 +112,17 @@ impl InputInterpolation {
         }
     }
 
+    /// Passes input through unchanged, for inputs that are already shaped like the keyboard
+    pub const fn linear() -> Self {
+        Self {
+            depth_mps: 0.3,
+            trim_dps: 60.0,
+            servo_rate: 5.0,
+            power: 1.0,
+            scale: 1.0,
+        }
+    }
+
     pub const fn precision() -> Self {
         Self {
             depth_mps: 0.1,
@@ -89,7 +134,7 @@ impl InputInterpolation {
     }
 }
 
-#[derive(Actionlike, PartialEq, Eq, Hash, Clone, Copy, Debug, Reflect)]
+#[derive(Actionlike, PartialEq, Eq, Hash, Clone, Copy, Debug, Reflect, Serialize, Deserialize)]
 pub enum Action {
     Arm,
     Disarm,
@@ -124,9 +169,16 @@ pub enum Action {
     SelectImportantServo,
 
     SwitchPitchRoll,
+
+    NudgeDepthUp,
+    NudgeDepthDown,
+    NudgeHeadingLeft,
+    NudgeHeadingRight,
 }
 
-#[derive(Actionlike, PartialEq, Eq, Hash, Clone, Copy, Debug, Reflect, Default)]
+#[derive(
+    Actionlike, PartialEq, Eq, Hash, Clone, Copy, Debug, Reflect, Default, Serialize, Deserialize,
+)]
 pub enum LevelingType {
     #[default]
     Upright,
@@ -136,6 +188,35 @@ pub enum LevelingType {
 #[derive(Component)]
 pub struct InputMarker;
 
+/// Pitch and roll have been switched with [`Action::SwitchPitchRoll`]
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PitchRollSwapped(pub bool);
+
+/// Servo picked first by [`Action::SelectImportantServo`]
+pub const CLAW: ServoId = ServoId::from_static("Claw1");
+/// Servo picked by [`Action::SelectImportantServo`] when [`CLAW`] is already selected
+pub const CAMERA_TILT: ServoId = ServoId::from_static("FrontCameraRotate");
+
+/// Depth change of a single nudge, positive is up
+pub const DEPTH_NUDGE: Meters = Meters(0.1);
+/// Heading change of a single nudge in degrees, positive is left
+pub const HEADING_NUDGE: f32 = 5.0;
+
+/// Discrete bump of a hold setpoint, in multiples of [`DEPTH_NUDGE`] or [`HEADING_NUDGE`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nudge {
+    /// Positive moves the target up
+    Depth(i32),
+    /// Positive turns the target left
+    Heading(i32),
+}
+
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct NudgeSetpoint {
+    pub robot: RobotId,
+    pub nudge: Nudge,
+}
+
 fn attach_to_new_robots(mut cmds: Commands, new_robots: Query<(&NetId, &Name), Added<Robot>>) {
     for (robot, name) in &new_robots {
         let mut input_map = InputMap::default();
@@ -196,6 +277,12 @@ fn attach_to_new_robots(mut cmds: Commands, new_robots: Query<(&NetId, &Name), A
         input_map.insert(Action::ToggleRobotMode, GamepadButtonType::DPadDown);
 
         input_map.insert(Action::ToggleRobotMode, GamepadButtonType::Mode);
+
+        input_map.insert(Action::NudgeDepthUp, KeyCode::PageUp);
+        input_map.insert(Action::NudgeDepthDown, KeyCode::PageDown);
+        // Brackets move the servo under keyboard control
+        input_map.insert(Action::NudgeHeadingLeft, KeyCode::Comma);
+        input_map.insert(Action::NudgeHeadingRight, KeyCode::Period);
         // input_map.insert(Action::ToggleRobotMode, GamepadButtonType::West);
 
         // input_map.insert(
@@ -237,6 +324,7 @@ fn attach_to_new_robots(mut cmds: Commands, new_robots: Query<(&NetId, &Name), A
             },
             ServoContribution(Default::default()),
             InputInterpolation::normal(),
+            PitchRollSwapped::default(),
             InputMarker,
             Replicate,
         ));
@@ -312,7 +400,7 @@ fn movement(
 
         let force = if depth_target.is_some() {
             if let Some(orientation) = orientation {
-                let mut yaw = orientation.0;
+                let mut yaw = orientation.rotation;
                 if yaw.z.abs() * yaw.z.abs() + yaw.w.abs() * yaw.w.abs() > 0.1 {
                     yaw.x = 0.0;
                     yaw.y = 0.0;
@@ -327,7 +415,7 @@ fn movement(
 
                 let world_force = yaw * vec3a(x, y, 0.0);
 
-                orientation.0.inverse() * world_force
+                orientation.rotation.inverse() * world_force
             } else {
                 vec3a(x, y, 0.0)
             }
@@ -347,24 +435,41 @@ fn movement(
     }
 }
 
+/// Holding arm this long asks the robot to skip non-critical pre-arm checks
+pub const FORCE_ARM_HOLD: Duration = Duration::from_millis(1500);
+
 fn arm(
-    mut cmds: Commands,
-    inputs: Query<(&RobotId, &ActionState<Action>), With<InputMarker>>,
-    robots: Query<(Entity, &RobotId), With<Robot>>,
+    mut forced: Local<HashSet<Entity>>,
+    inputs: Query<(Entity, &RobotId, &ActionState<Action>), With<InputMarker>>,
+    robots: Query<&RobotId, With<Robot>>,
+    palette: Res<CommandPalette>,
+    mut arm_control: ArmControl,
 ) {
-    for (robot, action_state) in &inputs {
+    for (input, robot, action_state) in &inputs {
         let disarm = action_state.just_pressed(&Action::Disarm);
-        let arm = action_state.just_pressed(&Action::Arm);
+        // Enter picks a command while the palette is open
+        let arm = action_state.just_pressed(&Action::Arm) && !palette.is_open();
+
+        // Only sent once per press
+        let force = action_state.pressed(&Action::Arm)
+            && action_state.current_duration(&Action::Arm) >= FORCE_ARM_HOLD
+            && forced.insert(input);
+        if action_state.released(&Action::Arm) {
+            forced.remove(&input);
+        }
 
-        let robot = robots.iter().find(|&(_, other_robot)| robot == other_robot);
+        let robot = robots.iter().find(|&other_robot| robot == other_robot);
 
-        if let Some((robot, _)) = robot {
+        if let Some(robot) = robot {
             if disarm {
                 info!("Disarming");
-                cmds.entity(robot).insert(Armed::Disarmed);
+                arm_control.disarm();
+            } else if force {
+                warn!("Requesting forced arm");
+                arm_control.arm(*robot, true);
             } else if arm {
-                info!("Arming");
-                cmds.entity(robot).insert(Armed::Armed);
+                info!("Requesting arm");
+                arm_control.arm(*robot, false);
             }
         } else if arm || disarm {
             warn!("No ROV attached");
@@ -372,6 +477,77 @@ fn arm(
     }
 }
 
+/// Who holds a robot's [`ArmAuthority`], from this surface's point of view
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthorityHolder {
+    Nobody,
+    ThisSurface,
+    /// Another surface, by name when it has one
+    Other(Option<String>),
+}
+
+/// Sends arm and disarm requests, asking for [`ArmAuthority`] first when this surface lacks it
+#[derive(SystemParam)]
+pub struct ArmControl<'w, 's> {
+    local_surface: Query<'w, 's, &'static NetId, With<LocalSurfaceMarker>>,
+    surfaces: Query<'w, 's, (&'static NetId, Option<&'static Name>), With<Surface>>,
+    robots: Query<'w, 's, (&'static RobotId, &'static ArmAuthority), With<Robot>>,
+
+    arm: EventWriter<'w, ArmRequest>,
+    disarm: EventWriter<'w, DisarmRequest>,
+    request: EventWriter<'w, RequestArmAuthority>,
+    release: EventWriter<'w, ReleaseArmAuthority>,
+}
+
+impl ArmControl<'_, '_> {
+    pub fn holder(&self, robot: RobotId) -> AuthorityHolder {
+        let holder = self
+            .robots
+            .iter()
+            .find(|(id, _)| **id == robot)
+            .and_then(|(_, authority)| authority.holder);
+
+        let Some(holder) = holder else {
+            return AuthorityHolder::Nobody;
+        };
+
+        if self.local_surface.iter().any(|it| *it == holder) {
+            return AuthorityHolder::ThisSurface;
+        }
+
+        let name = self
+            .surfaces
+            .iter()
+            .find(|(surface, _)| **surface == holder)
+            .and_then(|(_, name)| name.map(|it| it.to_string()));
+        AuthorityHolder::Other(name)
+    }
+
+    /// Asks to arm, taking arm authority first if nobody holds it
+    ///
+    /// Authority isn't taken from another surface here, that has to be asked for on purpose
+    pub fn arm(&mut self, robot: RobotId, force: bool) {
+        if self.holder(robot) == AuthorityHolder::Nobody {
+            info!("Requesting arm authority");
+            self.request.send(RequestArmAuthority::default());
+        }
+
+        self.arm.send(ArmRequest::new(force));
+    }
+
+    pub fn disarm(&mut self) {
+        self.disarm.send(DisarmRequest::default());
+    }
+
+    pub fn request_authority(&mut self) {
+        self.request.send(RequestArmAuthority::default());
+    }
+
+    pub fn release_authority(&mut self) {
+        self.release.send(ReleaseArmAuthority::default());
+    }
+}
+
 fn depth_hold(
     mut cmds: Commands,
     inputs: Query<(&RobotId, &ActionState<Action>), With<InputMarker>>,
@@ -386,18 +562,7 @@ fn depth_hold(
 
         if let Some((robot, depth, depth_target, _)) = robot {
             if toggle {
-                match depth_target {
-                    Some(_) => {
-                        info!("Clear Depth Hold");
-                        cmds.entity(robot).remove::<DepthTarget>();
-                    }
-                    None => {
-                        let depth = depth.0.depth;
-
-                        info!("Set Depth Hold: {:.2}", depth);
-                        cmds.entity(robot).insert(DepthTarget(depth));
-                    }
-                }
+                toggle_depth_hold(cmds.entity(robot), depth, depth_target);
             }
         } else if toggle {
             warn!("No ROV attached");
@@ -405,6 +570,43 @@ fn depth_hold(
     }
 }
 
+/// Holds the robot at its current depth, or lets go if it already was
+fn toggle_depth_hold(mut robot: EntityCommands, depth: &Depth, depth_target: Option<&DepthTarget>) {
+    match depth_target {
+        Some(_) => {
+            info!("Clear Depth Hold");
+            robot.remove::<DepthTarget>();
+        }
+        None => {
+            let depth = depth.0.depth;
+
+            info!("Set Depth Hold: {:.2}", depth);
+            robot.insert(DepthTarget(depth));
+        }
+    }
+}
+
+fn toggle_all_depth_holds(
+    mut cmds: Commands,
+    robots: Query<(Entity, &Depth, Option<&DepthTarget>), With<Robot>>,
+) {
+    for (robot, depth, depth_target) in &robots {
+        toggle_depth_hold(cmds.entity(robot), depth, depth_target);
+    }
+}
+
+fn arm_all(mut arm_control: ArmControl, robots: Query<&RobotId, With<Robot>>) {
+    for robot in &robots {
+        info!("Requesting arm");
+        arm_control.arm(*robot, false);
+    }
+}
+
+fn disarm_all(mut arm_control: ArmControl) {
+    info!("Disarming");
+    arm_control.disarm();
+}
+
 fn leveling(
     mut cmds: Commands,
     inputs: Query<(&RobotId, &ActionState<Action>), With<InputMarker>>,
@@ -422,7 +624,7 @@ fn leveling(
 
         if let Some((robot, orientation, orientation_target, _)) = robot {
             if toggle_upright || toggle_inverted {
-                let mut new_target = orientation.0;
+                let mut new_target = orientation.rotation;
 
                 // Only keep yaw component
                 new_target.x = 0.0;
@@ -535,7 +737,7 @@ fn trim_depth(
                 let mut input = z * interpolation.depth_mps * time.delta_seconds();
 
                 if let Some(orientation) = orientation {
-                    input *= (orientation.0 * Vec3A::Z).z.signum();
+                    input *= (orientation.rotation * Vec3A::Z).z.signum();
                 }
 
                 depth_target -= input;
@@ -583,16 +785,12 @@ fn servos(
             };
 
             if select_important {
-                if selected_servo.servo.as_ref().map(|it| it.as_str()) != Some("Claw1") {
-                    if servos.servos.iter().any(|it| it.as_str() == "Claw1") {
-                        selected_servo.servo = Some("Claw1".into());
+                if selected_servo.servo != Some(CLAW) {
+                    if servos.servos.contains(&CLAW) {
+                        selected_servo.servo = Some(CLAW);
                     }
-                } else if servos
-                    .servos
-                    .iter()
-                    .any(|it| it.as_str() == "FrontCameraRotate")
-                {
-                    selected_servo.servo = Some("FrontCameraRotate".into());
+                } else if servos.servos.contains(&CAMERA_TILT) {
+                    selected_servo.servo = Some(CAMERA_TILT);
                 }
             } else if (switch || switch_inverted || selected_servo.servo.is_none())
                 && !servos.servos.is_empty()
@@ -600,9 +798,7 @@ fn servos(
                 let idx = servos
                     .servos
                     .iter()
-                    .position(|it| {
-                        Some(it.as_str()) == selected_servo.servo.as_ref().map(|it| it.as_str())
-                    })
+                    .position(|it| Some(it) == selected_servo.servo.as_ref())
                     .map(|it| (it + offset) % servos.servos.len())
                     .unwrap_or(0);
 
@@ -641,12 +837,21 @@ fn robot_mode(
 }
 
 fn switch_pitch_roll(
-    mut inputs: Query<(&ActionState<Action>, &mut InputMap<Action>), With<InputMarker>>,
+    mut inputs: Query<
+        (
+            &ActionState<Action>,
+            &mut InputMap<Action>,
+            &mut PitchRollSwapped,
+        ),
+        With<InputMarker>,
+    >,
 ) {
-    for (action_state, mut input_map) in &mut inputs {
+    for (action_state, mut input_map, mut swapped) in &mut inputs {
         let toggle = action_state.just_pressed(&Action::SwitchPitchRoll);
 
         if toggle {
+            swapped.0 = !swapped.0;
+
             // Me when no proper remove api
             let pitch = input_map.get(&Action::Pitch).cloned();
             let pitch_inverted = input_map.get(&Action::PitchInverted).cloned();
@@ -684,3 +889,186 @@ fn switch_pitch_roll(
         }
     }
 }
+
+fn nudge_setpoints(
+    inputs: Query<(&RobotId, &ActionState<Action>), With<InputMarker>>,
+    mut nudges: EventWriter<NudgeSetpoint>,
+) {
+    for (robot, action_state) in &inputs {
+        let depth = action_state.just_pressed(&Action::NudgeDepthUp) as i32
+            - action_state.just_pressed(&Action::NudgeDepthDown) as i32;
+        let heading = action_state.just_pressed(&Action::NudgeHeadingLeft) as i32
+            - action_state.just_pressed(&Action::NudgeHeadingRight) as i32;
+
+        if depth != 0 {
+            nudges.send(NudgeSetpoint {
+                robot: *robot,
+                nudge: Nudge::Depth(depth),
+            });
+        }
+
+        if heading != 0 {
+            nudges.send(NudgeSetpoint {
+                robot: *robot,
+                nudge: Nudge::Heading(heading),
+            });
+        }
+    }
+}
+
+fn apply_nudges(
+    mut cmds: Commands,
+    mut nudges: EventReader<NudgeSetpoint>,
+    robots: Query<
+        (
+            Entity,
+            Option<&DepthTarget>,
+            Option<&OrientationTarget>,
+            &RobotId,
+        ),
+        With<Robot>,
+    >,
+) {
+    // Sum up every nudge this frame so repeated presses are not lost to stale targets
+    let mut totals: HashMap<NetId, (i32, i32)> = HashMap::default();
+    for nudge in nudges.read() {
+        let (depth, heading) = totals.entry(nudge.robot.0).or_default();
+
+        match nudge.nudge {
+            Nudge::Depth(steps) => *depth += steps,
+            Nudge::Heading(steps) => *heading += steps,
+        }
+    }
+
+    for (robot, (depth, heading)) in totals {
+        let robot = robots
+            .iter()
+            .find(|&(_, _, _, other_robot)| robot == other_robot.0);
+
+        let Some((robot, depth_target, orientation_target, _)) = robot else {
+            warn!("No ROV attached");
+            continue;
+        };
+
+        if depth != 0 {
+            match nudge_depth(depth_target.copied(), depth) {
+                Some(target) => {
+                    info!("Nudge Depth Hold: {}", target.0);
+                    cmds.entity(robot).insert(target);
+                }
+                None => warn!("Depth hold not active, ignoring nudge"),
+            }
+        }
+
+        if heading != 0 {
+            match nudge_heading(orientation_target.copied(), heading) {
+                Some(target) => {
+                    info!("Nudge Heading Hold");
+                    cmds.entity(robot).insert(target);
+                }
+                None => warn!("Orientation hold not active, ignoring nudge"),
+            }
+        }
+    }
+}
+
+/// Moves a depth target by `steps` nudges, returns None if depth hold is not active
+pub fn nudge_depth(target: Option<DepthTarget>, steps: i32) -> Option<DepthTarget> {
+    let DepthTarget(Meters(depth)) = target?;
+
+    // Depth is positive down
+    let depth = depth - DEPTH_NUDGE.0 * steps as f32;
+
+    Some(DepthTarget(Meters(depth.max(0.0))))
+}
+
+/// Turns an orientation target by `steps` nudges, returns None if orientation hold is not active
+pub fn nudge_heading(target: Option<OrientationTarget>, steps: i32) -> Option<OrientationTarget> {
+    let OrientationTarget(orientation) = target?;
+
+    let angle = (HEADING_NUDGE * steps as f32).to_radians();
+
+    Some(OrientationTarget(
+        Quat::from_rotation_z(angle) * orientation,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{app::App, math::Quat};
+    use common::{
+        components::{DepthTarget, OrientationTarget, Robot, RobotId},
+        ecs_sync::NetId,
+        types::units::Meters,
+    };
+
+    use super::{apply_nudges, nudge_depth, nudge_heading, Nudge, NudgeSetpoint};
+
+    #[test]
+    fn depth_nudges() {
+        let target = Some(DepthTarget(Meters(1.0)));
+
+        let up = nudge_depth(target, 1).unwrap();
+        assert!((up.0 .0 - 0.9).abs() < 1e-5);
+
+        let down = nudge_depth(target, -3).unwrap();
+        assert!((down.0 .0 - 1.3).abs() < 1e-5);
+
+        // Cannot go above the surface
+        let surfaced = nudge_depth(Some(DepthTarget(Meters(0.05))), 1).unwrap();
+        assert_eq!(surfaced, DepthTarget(Meters(0.0)));
+
+        // Hold not active
+        assert_eq!(nudge_depth(None, 1), None);
+    }
+
+    #[test]
+    fn heading_nudges() {
+        let target = Some(OrientationTarget(Quat::IDENTITY));
+
+        let left = nudge_heading(target, 2).unwrap();
+        let (_, _, yaw) = left.0.to_euler(bevy::math::EulerRot::XYZ);
+        assert!((yaw.to_degrees() - 10.0).abs() < 1e-3);
+
+        let back = nudge_heading(Some(left), -2).unwrap();
+        assert!(back.0.angle_between(Quat::IDENTITY) < 1e-3);
+
+        // Hold not active
+        assert_eq!(nudge_heading(None, 1), None);
+    }
+
+    #[test]
+    fn nudges_accumulate_within_a_frame() {
+        let mut app = App::new();
+        app.add_event::<NudgeSetpoint>()
+            .add_systems(bevy::app::Update, apply_nudges);
+
+        let robot_id = RobotId(NetId::random());
+        let robot = app
+            .world
+            .spawn((Robot, robot_id, DepthTarget(Meters(1.0))))
+            .id();
+
+        app.world.send_event_batch([
+            NudgeSetpoint {
+                robot: robot_id,
+                nudge: Nudge::Depth(1),
+            },
+            NudgeSetpoint {
+                robot: robot_id,
+                nudge: Nudge::Depth(1),
+            },
+            NudgeSetpoint {
+                robot: robot_id,
+                nudge: Nudge::Heading(1),
+            },
+        ]);
+        app.update();
+
+        let target = app.world.get::<DepthTarget>(robot).unwrap();
+        assert!((target.0 .0 - 0.8).abs() < 1e-5);
+
+        // No orientation hold, so the heading nudge is dropped
+        assert!(app.world.get::<OrientationTarget>(robot).is_none());
+    }
+}
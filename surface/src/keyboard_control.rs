@@ -0,0 +1,372 @@
+//! Flying with only a keyboard, for when the gamepad dies mid-run
+//!
+//! Off by default so typing into a text field can't move the robot. While on, every robot gets an
+//! extra input entity driven by [`keyboard_input_map`]. Keys are all or nothing, so how long one
+//! has been held is turned into an axis value by [`KeyRamp`]: taps give small corrections and
+//! holding ramps up to a fraction of the axis maximums.
+
+use std::time::Duration;
+
+use ahash::HashMap;
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+use common::{
+    bundles::MovementContributionBundle,
+    components::{MovementContribution, Robot, RobotId, ServoContribution},
+    ecs_sync::{NetId, Replicate},
+};
+use egui::{Color32, Id, RichText};
+use leafwing_input_manager::{
+    action_state::ActionState, input_map::InputMap, plugin::InputManagerSystem, InputManagerBundle,
+};
+use motor_math::Movement;
+
+use crate::{
+    command_palette::{AppCommandExt, SurfaceCommand},
+    input::{Action, InputInterpolation, InputMarker, SelectedServo},
+};
+
+pub struct KeyboardControlPlugin;
+
+impl Plugin for KeyboardControlPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<KeyboardControl>()
+            .add_systems(
+                PreUpdate,
+                ramp_keyboard_axes.in_set(InputManagerSystem::ManualControl),
+            )
+            .add_systems(Update, (sync_keyboard_inputs, keyboard_indicator))
+            .add_command(
+                SurfaceCommand::new("Keyboard Control", |world| {
+                    world.resource_mut::<KeyboardControl>().toggle()
+                })
+                .in_menu("Input Devices")
+                .checked_if(|world| world.resource::<KeyboardControl>().enabled),
+            );
+    }
+}
+
+/// Actions the keyboard drives as axes
+pub const KEYBOARD_AXES: [Action; 14] = [
+    Action::Surge,
+    Action::SurgeInverted,
+    Action::Sway,
+    Action::SwayInverted,
+    Action::Heave,
+    Action::HeaveInverted,
+    Action::Yaw,
+    Action::YawInverted,
+    Action::Pitch,
+    Action::PitchInverted,
+    Action::Roll,
+    Action::RollInverted,
+    Action::Servo,
+    Action::ServoInverted,
+];
+
+/// WASD to move, Q/E to yaw, R/F to heave, arrows to trim pitch and roll and brackets for the
+/// selected servo
+pub fn keyboard_input_map() -> InputMap<Action> {
+    let mut input_map = InputMap::default();
+
+    input_map.insert(Action::Surge, KeyCode::KeyW);
+    input_map.insert(Action::SurgeInverted, KeyCode::KeyS);
+    input_map.insert(Action::Sway, KeyCode::KeyD);
+    input_map.insert(Action::SwayInverted, KeyCode::KeyA);
+    input_map.insert(Action::YawInverted, KeyCode::KeyQ);
+    input_map.insert(Action::Yaw, KeyCode::KeyE);
+    input_map.insert(Action::Heave, KeyCode::KeyR);
+    input_map.insert(Action::HeaveInverted, KeyCode::KeyF);
+
+    input_map.insert(Action::Pitch, KeyCode::ArrowUp);
+    input_map.insert(Action::PitchInverted, KeyCode::ArrowDown);
+    input_map.insert(Action::Roll, KeyCode::ArrowRight);
+    input_map.insert(Action::RollInverted, KeyCode::ArrowLeft);
+
+    input_map.insert(Action::Servo, KeyCode::BracketRight);
+    input_map.insert(Action::ServoInverted, KeyCode::BracketLeft);
+
+    input_map
+}
+
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub struct KeyboardControl {
+    pub enabled: bool,
+    pub ramp: KeyRamp,
+}
+
+impl KeyboardControl {
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    /// Whether keys reach the robot, not while egui is taking keyboard input
+    pub fn is_live(&self, typing: bool) -> bool {
+        self.enabled && !typing
+    }
+}
+
+/// Turns how long a key has been held into an axis value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeyRamp {
+    /// Fraction of the full value a key gives as soon as it is pressed
+    pub tap: f32,
+    /// How long a key has to be held to reach the full value
+    pub ramp_time: Duration,
+    /// Full value, as a fraction of the axis maximums
+    pub max_fraction: f32,
+}
+
+impl Default for KeyRamp {
+    fn default() -> Self {
+        Self {
+            tap: 0.15,
+            ramp_time: Duration::from_secs(1),
+            max_fraction: 0.5,
+        }
+    }
+}
+
+impl KeyRamp {
+    pub fn value(&self, held: Duration) -> f32 {
+        let progress = if self.ramp_time.is_zero() {
+            1.0
+        } else {
+            (held.as_secs_f32() / self.ramp_time.as_secs_f32()).min(1.0)
+        };
+        let tap = self.tap.clamp(0.0, 1.0);
+
+        (tap + (1.0 - tap) * progress) * self.max_fraction.clamp(0.0, 1.0)
+    }
+}
+
+/// Input entity driven by the keyboard
+#[derive(Component, Debug, Clone, Copy)]
+pub struct KeyboardInput;
+
+/// When each held key in [`KEYBOARD_AXES`] was pressed
+#[derive(Component, Debug, Clone, Default)]
+pub struct KeyHolds(HashMap<Action, Duration>);
+
+impl KeyHolds {
+    /// Value of each keyboard axis, nothing counts as held while not `live`
+    ///
+    /// A key still down once the keyboard is live again starts its ramp over.
+    pub fn update(
+        &mut self,
+        ramp: &KeyRamp,
+        live: bool,
+        now: Duration,
+        pressed: impl Fn(Action) -> bool,
+    ) -> [(Action, f32); KEYBOARD_AXES.len()] {
+        KEYBOARD_AXES.map(|action| {
+            if live && pressed(action) {
+                let since = *self.0.entry(action).or_insert(now);
+                (action, ramp.value(now.saturating_sub(since)))
+            } else {
+                self.0.remove(&action);
+                (action, 0.0)
+            }
+        })
+    }
+}
+
+/// Keeps one keyboard input entity per robot while keyboard control is on
+fn sync_keyboard_inputs(
+    mut cmds: Commands,
+    control: Res<KeyboardControl>,
+    robots: Query<(&NetId, &Name), With<Robot>>,
+    inputs: Query<(Entity, &RobotId), With<KeyboardInput>>,
+) {
+    if !control.enabled {
+        for (entity, _) in &inputs {
+            cmds.entity(entity).despawn();
+        }
+
+        return;
+    }
+
+    for (&robot, name) in &robots {
+        if inputs.iter().any(|(_, &RobotId(it))| it == robot) {
+            continue;
+        }
+
+        cmds.spawn((
+            SelectedServo::default(),
+            InputManagerBundle::<Action> {
+                action_state: ActionState::default(),
+                input_map: keyboard_input_map(),
+            },
+            MovementContributionBundle {
+                name: Name::new(format!("Keyboard {name}")),
+                contribution: MovementContribution(Movement::default()),
+                robot: RobotId(robot),
+            },
+            ServoContribution(Default::default()),
+            // The ramp already shapes the input
+            InputInterpolation::linear(),
+            InputMarker,
+            KeyboardInput,
+            KeyHolds::default(),
+            Replicate,
+        ));
+    }
+}
+
+fn ramp_keyboard_axes(
+    mut contexts: EguiContexts,
+    control: Res<KeyboardControl>,
+    mut inputs: Query<(&mut ActionState<Action>, &mut KeyHolds), With<KeyboardInput>>,
+    time: Res<Time<Real>>,
+) {
+    let live = control.is_live(contexts.ctx_mut().wants_keyboard_input());
+
+    for (mut action_state, mut holds) in &mut inputs {
+        let values = holds.update(&control.ramp, live, time.elapsed(), |action| {
+            action_state.pressed(&action)
+        });
+
+        for (action, value) in values {
+            action_state.action_data_mut_or_default(&action).value = value;
+        }
+    }
+}
+
+fn keyboard_indicator(mut contexts: EguiContexts, mut control: ResMut<KeyboardControl>) {
+    if !control.enabled {
+        return;
+    }
+
+    let ctx = contexts.ctx_mut();
+    let typing = ctx.wants_keyboard_input();
+
+    egui::Area::new(Id::new("Keyboard Control"))
+        .anchor(egui::Align2::CENTER_TOP, egui::vec2(0.0, 40.0))
+        .show(ctx, |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                if control.is_live(typing) {
+                    ui.label(
+                        RichText::new("Keyboard control live")
+                            .strong()
+                            .color(Color32::YELLOW),
+                    );
+                } else {
+                    ui.label(RichText::new("Keyboard control paused while typing").strong());
+                }
+
+                ui.add(
+                    egui::Slider::new(&mut control.ramp.max_fraction, 0.1..=1.0)
+                        .text("Max")
+                        .custom_formatter(|it, _| format!("{:.0}%", it * 100.0)),
+                );
+                ui.label(
+                    RichText::new("WASD move, Q/E yaw, R/F heave, arrows trim, [ ] servo").weak(),
+                );
+            });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::input::Action;
+
+    use super::{KeyHolds, KeyRamp, KeyboardControl, KEYBOARD_AXES};
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn value(values: &[(Action, f32)], action: Action) -> f32 {
+        values
+            .iter()
+            .find(|(it, _)| *it == action)
+            .map(|(_, value)| *value)
+            .unwrap()
+    }
+
+    #[test]
+    fn holds_ramp_up_to_the_max_fraction() {
+        let ramp = KeyRamp {
+            tap: 0.2,
+            ramp_time: ms(1000),
+            max_fraction: 0.5,
+        };
+
+        assert!(close(ramp.value(Duration::ZERO), 0.1));
+        assert!(close(ramp.value(ms(500)), 0.3));
+        assert!(close(ramp.value(ms(1000)), 0.5));
+        assert!(close(ramp.value(ms(5000)), 0.5));
+
+        // Never past the axis maximums
+        let ramp = KeyRamp {
+            max_fraction: 2.0,
+            ..ramp
+        };
+        assert!(close(ramp.value(ms(5000)), 1.0));
+
+        let instant = KeyRamp {
+            ramp_time: Duration::ZERO,
+            ..KeyRamp::default()
+        };
+        assert!(close(instant.value(Duration::ZERO), instant.max_fraction));
+    }
+
+    #[test]
+    fn keys_ramp_while_held() {
+        let ramp = KeyRamp::default();
+        let mut holds = KeyHolds::default();
+        let surge = |action: Action| action == Action::Surge;
+
+        let values = holds.update(&ramp, true, ms(100), surge);
+        assert!(close(
+            value(&values, Action::Surge),
+            ramp.value(Duration::ZERO)
+        ));
+        assert_eq!(value(&values, Action::Sway), 0.0);
+        assert_eq!(values.len(), KEYBOARD_AXES.len());
+
+        let values = holds.update(&ramp, true, ms(600), surge);
+        assert!(close(value(&values, Action::Surge), ramp.value(ms(500))));
+
+        // Letting go resets the ramp
+        holds.update(&ramp, true, ms(700), |_| false);
+        let values = holds.update(&ramp, true, ms(800), surge);
+        assert!(close(
+            value(&values, Action::Surge),
+            ramp.value(Duration::ZERO)
+        ));
+    }
+
+    #[test]
+    fn typing_suppresses_keys() {
+        let control = KeyboardControl {
+            enabled: true,
+            ..Default::default()
+        };
+        assert!(control.is_live(false));
+        assert!(!control.is_live(true));
+        assert!(!KeyboardControl::default().is_live(false));
+
+        let mut holds = KeyHolds::default();
+        let heave = |action: Action| action == Action::Heave;
+
+        holds.update(&control.ramp, control.is_live(false), ms(0), heave);
+
+        // Focusing a text field mid hold drops the key
+        let values = holds.update(&control.ramp, control.is_live(true), ms(2000), heave);
+        assert!(values.iter().all(|(_, value)| *value == 0.0));
+
+        // Still down after the field loses focus, starts again from a tap instead of jumping
+        let values = holds.update(&control.ramp, control.is_live(false), ms(2100), heave);
+        assert!(close(
+            value(&values, Action::Heave),
+            control.ramp.value(Duration::ZERO)
+        ));
+    }
+}
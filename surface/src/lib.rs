@@ -0,0 +1,36 @@
+#![feature(iter_intersperse, try_blocks, test)]
+
+pub mod alerts;
+pub mod attitude;
+pub mod bookmarks;
+pub mod checklist;
+pub mod cli;
+pub mod command_palette;
+pub mod connection_wizard;
+pub mod current_trim;
+pub mod depth_mission;
+pub mod device_profiles;
+pub mod hud_layout;
+pub mod hud_snapshot;
+pub mod input;
+pub mod keyboard_control;
+pub mod movement_controller;
+pub mod pending_commands;
+pub mod pipeline_results;
+pub mod robot_files;
+pub mod selected_robot;
+pub mod session;
+pub mod surface;
+pub mod theme;
+pub mod toggle_feedback;
+pub mod ui;
+pub mod video_conversion;
+pub mod video_detach;
+pub mod video_display_2d_master;
+pub mod video_display_2d_tile;
+pub mod video_display_3d;
+pub mod video_downscale;
+pub mod video_fallback;
+pub mod video_overlay;
+pub mod video_pipelines;
+pub mod video_stream;
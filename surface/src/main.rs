@@ -1,66 +1,87 @@
-#![feature(iter_intersperse, try_blocks)]
-
-pub mod attitude;
-pub mod input;
-pub mod surface;
-pub mod ui;
-pub mod video_display_2d_master;
-pub mod video_display_2d_tile;
-pub mod video_display_3d;
-pub mod video_pipelines;
-pub mod video_stream;
-
-use std::time::Duration;
-
-use anyhow::Context;
-use attitude::AttitudePlugin;
 use bevy::{
     diagnostic::{EntityCountDiagnosticsPlugin, FrameTimeDiagnosticsPlugin, LogDiagnosticsPlugin},
     prelude::*,
+    window::ExitCondition,
 };
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_mod_picking::{highlight::DefaultHighlightingPlugin, DefaultPickingPlugins};
 use bevy_panorbit_camera::PanOrbitCameraPlugin;
 use bevy_tokio_tasks::TokioTasksPlugin;
-use common::{over_run::OverRunSettings, sync::SyncRole, CommonPlugins};
-use crossbeam::channel::unbounded;
-use input::InputPlugin;
-use opencv::{highgui, imgcodecs};
-use surface::SurfacePlugin;
-use ui::{EguiUiPlugin, ShowInspector};
-// use video_display_2d_tile::{VideoDisplay2DPlugin, VideoDisplay2DSettings};
-use video_display_2d_master::{VideoDisplay2DPlugin, VideoDisplay2DSettings};
-// use video_display_3d::{VideoDisplay3DPlugin, VideoDisplay3DSettings};
-use video_stream::VideoStreamPlugin;
-
-use crate::video_pipelines::{
-    edges::EdgesPipeline,
-    marker::MarkerPipeline,
-    measure::{MeasurePipeline, MeasurementTarget},
-    Pipeline, PipelineCallbacks, SerialPipeline, VideoPipelinePlugins,
+use clap::Parser;
+use common::{sync::SyncRole, CommonPlugins};
+use surface::{
+    alerts::AlertPlugin,
+    attitude::AttitudePlugin,
+    bookmarks::BookmarksPlugin,
+    checklist::ChecklistPlugin,
+    cli::SurfaceArgs,
+    command_palette::CommandPalettePlugin,
+    connection_wizard::ConnectionWizardPlugin,
+    current_trim::CurrentTrimPlugin,
+    depth_mission::DepthMissionPlugin,
+    device_profiles::DeviceProfilePlugin,
+    hud_layout::HudLayoutPlugin,
+    hud_snapshot::HudSnapshotPlugin,
+    input::InputPlugin,
+    keyboard_control::KeyboardControlPlugin,
+    movement_controller::MovementControllerPlugin,
+    pending_commands::PendingCommandsPlugin,
+    pipeline_results::PipelineResultsPlugin,
+    robot_files::RobotFilesPlugin,
+    selected_robot::SelectedRobotPlugin,
+    session::SessionPlugin,
+    surface::SurfacePlugin,
+    theme::ThemePlugin,
+    toggle_feedback::ToggleFeedbackPlugin,
+    ui::{panel_open, EguiUiPlugin, Panel},
+    // video_display_3d::{VideoDisplay3DPlugin, VideoDisplay3DSettings},
+    video_conversion::{VideoConversionMode, VideoConversionPlugin},
+    video_detach::VideoDetachPlugin,
+    // video_display_2d_tile::{VideoDisplay2DPlugin, VideoDisplay2DSettings},
+    video_display_2d_master::{MakeMaster, VideoDisplay2DPlugin, VideoDisplay2DSettings},
+    video_fallback::VideoFallbackPlugin,
+    video_pipelines::{VideoPipelinePlugins, VideoPipelines},
+    video_stream::VideoStreamPlugin,
 };
 
-pub const DARK_MODE: bool = false;
-
 fn main() -> anyhow::Result<()> {
     info!("---------- Starting Control Station ----------");
 
+    let args = SurfaceArgs::parse();
+    let config = args.read_config()?;
+    let settings = args.settings(&config);
+    let over_run = args.over_run_settings(&config)?;
+    let hud = args.hud_settings(&config);
+    let attitude_frame = args.attitude_frame(&config);
+    let toggle_feedback = args.toggle_feedback_settings(&config);
+    let link_key = args.link_key(&config)?;
+
+    let default_plugins = DefaultPlugins.set(WindowPlugin {
+        // Detached video windows shouldn't keep the surface running on their own
+        exit_condition: ExitCondition::OnPrimaryClosed,
+        ..default()
+    });
+    #[cfg(not(feature = "audio"))]
+    let default_plugins = default_plugins.disable::<bevy::audio::AudioPlugin>();
+
     // FIXME(high): Times out when focus is lost
-    App::new()
-        .insert_resource(OverRunSettings {
-            max_time: Duration::from_secs_f32(1.0 / 60.0),
-            tracy_frame_mark: false,
-        })
+    let mut app = App::new();
+
+    if let Some(key) = link_key {
+        app.insert_resource(key);
+    }
+
+    app.insert_resource(settings)
+        .insert_resource(over_run)
+        .insert_resource(hud)
+        .insert_resource(attitude_frame)
+        .insert_resource(toggle_feedback)
         .insert_resource(VideoDisplay2DSettings { enabled: true })
+        .insert_resource(VideoConversionMode::Gpu)
         // .insert_resource(VideoDisplay3DSettings { enabled: true })
-        .insert_resource(if DARK_MODE {
-            ClearColor(Color::rgb_u8(33, 34, 37))
-        } else {
-            ClearColor(Color::rgb_u8(240, 238, 233))
-        })
         .add_plugins((
             // Bevy Core
-            DefaultPlugins.build().disable::<bevy::audio::AudioPlugin>(),
+            default_plugins,
             // .set(TaskPoolPlugin {
             //     task_pool_options: TaskPoolOptions {
             //         compute: TaskPoolThreadAssignmentPolicy {
@@ -87,13 +108,31 @@ fn main() -> anyhow::Result<()> {
                     role: SyncRole::Client,
                 },
                 SurfacePlugin,
+                ThemePlugin,
                 InputPlugin,
+                CurrentTrimPlugin,
+                DeviceProfilePlugin,
+                DepthMissionPlugin,
+                ChecklistPlugin,
+                AlertPlugin,
+                ToggleFeedbackPlugin,
+            ),
+            // MATE, continued since bevy only takes so many plugins in one tuple
+            (
                 EguiUiPlugin,
+                MovementControllerPlugin,
+                RobotFilesPlugin,
+                CommandPalettePlugin,
+                SelectedRobotPlugin,
+                HudLayoutPlugin,
+                HudSnapshotPlugin,
+                SessionPlugin,
                 AttitudePlugin,
-                VideoStreamPlugin,
-                VideoDisplay2DPlugin,
-                // VideoDisplay3DPlugin,
-                VideoPipelinePlugins,
+                ConnectionWizardPlugin,
+                PendingCommandsPlugin,
+                PipelineResultsPlugin,
+                KeyboardControlPlugin,
+                BookmarksPlugin,
             ),
             // 3rd Party
             (
@@ -101,46 +140,30 @@ fn main() -> anyhow::Result<()> {
                     .build()
                     .disable::<DefaultHighlightingPlugin>(),
                 TokioTasksPlugin::default(),
-                // TODO(high): Way to close and re open
-                WorldInspectorPlugin::default().run_if(resource_exists::<ShowInspector>),
+                WorldInspectorPlugin::default().run_if(panel_open(Panel::Inspector)),
                 PanOrbitCameraPlugin,
             ),
-        ))
-        .run();
+        ));
 
-    info!("---------- Control Station Exited Cleanly ----------");
+    if settings.headless {
+        // Other plugins still read these
+        app.add_event::<MakeMaster>()
+            .init_resource::<VideoPipelines>();
+    } else {
+        app.add_plugins((
+            VideoStreamPlugin,
+            VideoFallbackPlugin,
+            VideoConversionPlugin,
+            VideoDisplay2DPlugin,
+            VideoDetachPlugin,
+            // VideoDisplay3DPlugin,
+            VideoPipelinePlugins,
+        ));
+    }
 
-    Ok(())
-}
+    app.run();
 
-fn opencv() -> anyhow::Result<()> {
-    let mut img = imgcodecs::imread_def("test.jpg").context("Read image")?;
-
-    let (cmds_tx, cmds_rx) = unbounded();
-    let mut should_end = false;
-    let mut cmds = PipelineCallbacks {
-        cmds_tx: &cmds_tx,
-        pipeline_entity: Entity::PLACEHOLDER,
-        camera_entity: Entity::PLACEHOLDER,
-        should_end: &mut should_end,
-    };
-
-    // let mut pipeline: FullMeasurePipeline = SerialPipeline(Default::default());
-    let mut pipeline: MeasurePipeline = Default::default();
-    let out = pipeline
-        .process(
-            &mut cmds,
-            &Some(MeasurementTarget {
-                poi: Vec2::new(643.0 / 1920.0, 913.0 / 1080.0),
-                left: Vec2::default(),
-                right: Vec2::default(),
-            }),
-            &mut img,
-        )
-        .context("Process")?;
-
-    highgui::imshow("Image", out).context("Gui")?;
-    highgui::wait_key_def().context("Wait key")?;
+    info!("---------- Control Station Exited Cleanly ----------");
 
     Ok(())
 }
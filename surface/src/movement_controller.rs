@@ -0,0 +1,301 @@
+//! Manual movement controllers opened from the View menu
+//!
+//! A controller window only edits its [`ControllerInput`], [`smooth_controllers`] scales that by
+//! the controller's gain and ramps the emitted [`MovementContribution`] toward it so grabbing or
+//! letting go of a slider never jumps straight to full force. Every controller ramps on its own,
+//! any number of them can drive the same robot.
+
+use bevy::{math::Vec3A, prelude::*};
+use common::{
+    bundles::MovementContributionBundle,
+    components::{MovementContribution, RobotId},
+    ecs_sync::{NetId, Replicate},
+};
+use motor_math::Movement;
+
+use crate::command_palette::{AppCommandExt, SurfaceCommand};
+
+pub struct MovementControllerPlugin;
+
+impl Plugin for MovementControllerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, smooth_controllers).add_command(
+            SurfaceCommand::new("Movement Controller", |world| {
+                world.spawn((
+                    MovementController,
+                    ControllerSettings::default(),
+                    ControllerInput::default(),
+                    MovementContributionBundle {
+                        name: Name::new("Manual Movement Controller"),
+                        contribution: Default::default(),
+                        robot: RobotId(NetId::invalid()),
+                    },
+                    Replicate,
+                ));
+            })
+            .in_menu("View")
+            .in_group("Controllers"),
+        );
+    }
+}
+
+#[derive(Component)]
+pub struct MovementController;
+
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct ControllerSettings {
+    /// How fast the emitted force follows the setpoint, in N/s, 0 follows it right away
+    pub force_ramp: f32,
+    /// How fast the emitted torque follows the setpoint, in Nm/s, 0 follows it right away
+    pub torque_ramp: f32,
+    /// Scales the setpoint before it is ramped to
+    pub gain: f32,
+    /// Only emit while a slider is held, letting go cuts the output
+    pub hold_to_apply: bool,
+}
+
+impl Default for ControllerSettings {
+    fn default() -> Self {
+        Self {
+            force_ramp: 100.0,
+            torque_ramp: 20.0,
+            gain: 1.0,
+            hold_to_apply: false,
+        }
+    }
+}
+
+/// What the controller's window is asking for
+#[derive(Component, Debug, Clone, Copy, PartialEq, Default)]
+pub struct ControllerInput {
+    pub setpoint: Movement,
+    /// Whether the pilot is holding one of the sliders
+    pub held: bool,
+}
+
+/// Moves each axis of `current` toward `target` by at most its ramp rate over `dt` seconds
+pub fn ramp(
+    current: Movement,
+    target: Movement,
+    settings: &ControllerSettings,
+    dt: f32,
+) -> Movement {
+    Movement {
+        force: ramp_axes(current.force, target.force, settings.force_ramp, dt),
+        torque: ramp_axes(current.torque, target.torque, settings.torque_ramp, dt),
+    }
+}
+
+fn ramp_axes(current: Vec3A, target: Vec3A, rate: f32, dt: f32) -> Vec3A {
+    // A ramp of 0 would never get anywhere, including back to stopped
+    if rate <= 0.0 {
+        return target;
+    }
+
+    let max_step = Vec3A::splat((rate * dt).max(0.0));
+    current + (target - current).clamp(-max_step, max_step)
+}
+
+/// The contribution a controller emits `dt` seconds after emitting `output`
+pub fn next_output(
+    output: Movement,
+    input: &ControllerInput,
+    settings: &ControllerSettings,
+    dt: f32,
+) -> Movement {
+    // Letting go is a dead man's switch, it doesn't ramp
+    if settings.hold_to_apply && !input.held {
+        return Movement::default();
+    }
+
+    ramp(output, input.setpoint * settings.gain, settings, dt)
+}
+
+pub fn smooth_controllers(
+    time: Res<Time>,
+    mut controllers: Query<
+        (
+            &ControllerInput,
+            &ControllerSettings,
+            &mut MovementContribution,
+        ),
+        With<MovementController>,
+    >,
+) {
+    let dt = time.delta_seconds();
+
+    for (input, settings, mut contribution) in &mut controllers {
+        let output = next_output(contribution.0, input, settings, dt);
+
+        if output != contribution.0 {
+            contribution.0 = output;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::{app::App, prelude::*};
+    use common::components::MovementContribution;
+    use motor_math::Movement;
+
+    use super::{
+        next_output, ramp, smooth_controllers, ControllerInput, ControllerSettings,
+        MovementController,
+    };
+
+    fn settings() -> ControllerSettings {
+        ControllerSettings {
+            force_ramp: 10.0,
+            torque_ramp: 2.0,
+            gain: 1.0,
+            hold_to_apply: false,
+        }
+    }
+
+    fn movement(force: [f32; 3], torque: [f32; 3]) -> Movement {
+        Movement {
+            force: force.into(),
+            torque: torque.into(),
+        }
+    }
+
+    fn assert_movement(actual: Movement, expected: Movement) {
+        assert!(
+            actual.force.abs_diff_eq(expected.force, 1e-4)
+                && actual.torque.abs_diff_eq(expected.torque, 1e-4),
+            "{actual:?} != {expected:?}"
+        );
+    }
+
+    #[test]
+    fn ramps_each_axis_at_its_rate() {
+        let target = movement([20.0, -5.0, 0.0], [1.0, 0.0, -3.0]);
+        let mut output = Movement::default();
+
+        // 10 N/s and 2 Nm/s over 0.25s frames
+        output = ramp(output, target, &settings(), 0.25);
+        assert_movement(output, movement([2.5, -2.5, 0.0], [0.5, 0.0, -0.5]));
+
+        output = ramp(output, target, &settings(), 0.25);
+        assert_movement(output, movement([5.0, -5.0, 0.0], [1.0, 0.0, -1.0]));
+
+        // Axes that arrived stay put while the rest keep going
+        for _ in 0..6 {
+            output = ramp(output, target, &settings(), 0.25);
+        }
+        assert_movement(output, movement([20.0, -5.0, 0.0], [1.0, 0.0, -3.0]));
+
+        // And back down at the same rate
+        output = ramp(output, Movement::default(), &settings(), 0.5);
+        assert_movement(output, movement([15.0, 0.0, 0.0], [0.0, 0.0, -2.0]));
+    }
+
+    #[test]
+    fn zero_ramp_follows_the_setpoint() {
+        let settings = ControllerSettings {
+            force_ramp: 0.0,
+            torque_ramp: 0.0,
+            ..settings()
+        };
+        let target = movement([20.0, -5.0, 0.0], [1.0, 0.0, -3.0]);
+
+        let output = ramp(Movement::default(), target, &settings, 0.25);
+        assert_movement(output, target);
+
+        // Letting go of the slider stops the robot
+        let output = ramp(output, Movement::default(), &settings, 0.25);
+        assert_movement(output, Movement::default());
+    }
+
+    #[test]
+    fn gain_scales_the_setpoint() {
+        let settings = ControllerSettings {
+            gain: 0.5,
+            ..settings()
+        };
+        let input = ControllerInput {
+            setpoint: movement([8.0, 0.0, 0.0], [0.0, 0.0, 0.0]),
+            held: false,
+        };
+
+        let mut output = Movement::default();
+        for _ in 0..10 {
+            output = next_output(output, &input, &settings, 0.1);
+        }
+        assert_movement(output, movement([4.0, 0.0, 0.0], [0.0; 3]));
+    }
+
+    #[test]
+    fn releasing_a_hold_to_apply_controller_zeroes_the_output() {
+        let settings = ControllerSettings {
+            hold_to_apply: true,
+            ..settings()
+        };
+        let held = ControllerInput {
+            setpoint: movement([5.0, 0.0, 0.0], [0.0, 0.0, 1.0]),
+            held: true,
+        };
+        let released = ControllerInput {
+            held: false,
+            ..held
+        };
+
+        // Nothing until it's held
+        let output = next_output(Movement::default(), &released, &settings, 1.0);
+        assert_movement(output, Movement::default());
+
+        let output = next_output(output, &held, &settings, 0.2);
+        assert_movement(output, movement([2.0, 0.0, 0.0], [0.0, 0.0, 0.4]));
+
+        // Cut at once rather than ramped down
+        let output = next_output(output, &released, &settings, 0.01);
+        assert_movement(output, Movement::default());
+    }
+
+    #[test]
+    fn controllers_smooth_independently() {
+        let mut app = App::new();
+        app.init_resource::<Time>()
+            .add_systems(Update, smooth_controllers);
+
+        let setpoint = movement([10.0, 0.0, 0.0], [0.0; 3]);
+        let mut spawn = |settings: ControllerSettings| {
+            app.world
+                .spawn((
+                    MovementController,
+                    settings,
+                    ControllerInput {
+                        setpoint,
+                        held: false,
+                    },
+                    MovementContribution::default(),
+                ))
+                .id()
+        };
+        let slow = spawn(settings());
+        let fast = spawn(ControllerSettings {
+            force_ramp: 40.0,
+            ..settings()
+        });
+
+        app.world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_millis(100));
+        app.update();
+
+        let output = |app: &App, entity| app.world.get::<MovementContribution>(entity).unwrap().0;
+        assert_movement(output(&app, slow), movement([1.0, 0.0, 0.0], [0.0; 3]));
+        assert_movement(output(&app, fast), movement([4.0, 0.0, 0.0], [0.0; 3]));
+
+        app.world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_millis(200));
+        app.update();
+
+        assert_movement(output(&app, slow), movement([3.0, 0.0, 0.0], [0.0; 3]));
+        assert_movement(output(&app, fast), setpoint);
+    }
+}
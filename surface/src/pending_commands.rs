@@ -0,0 +1,275 @@
+//! Keeps track of one-shot commands until the robot says they are done
+//!
+//! Commands registered with [`crate::command_palette::SurfaceCommand::acked_event`] show a
+//! spinner in their menu while they are in flight. When the robot answers with a
+//! [`CommandComplete`], or doesn't answer within [`COMMAND_TIMEOUT`], the outcome is shown in a
+//! toast.
+
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+use common::{
+    components::RobotId,
+    events::{AckedCommand, CommandComplete},
+};
+use egui::{Color32, Id, RichText};
+
+/// How long the robot gets to finish a command before it is given up on
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long an outcome stays on screen
+pub const OUTCOME_TOAST: Duration = Duration::from_secs(4);
+
+pub struct PendingCommandsPlugin;
+
+impl Plugin for PendingCommandsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PendingCommands>().add_systems(
+            Update,
+            (collect_completions, expire_commands, outcome_toast).chain(),
+        );
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub command: AckedCommand,
+    pub ok: bool,
+    pub detail: String,
+    /// Real time the outcome was known
+    pub at: Duration,
+}
+
+/// Commands in flight, and how recent ones turned out
+#[derive(Resource, Debug, Default)]
+pub struct PendingCommands {
+    /// Robot it was sent to, what was sent and when
+    in_flight: Vec<(RobotId, AckedCommand, Duration)>,
+    /// Oldest first
+    outcomes: VecDeque<Outcome>,
+}
+
+impl PendingCommands {
+    /// Starts waiting on `command`, sending it again restarts the wait
+    pub fn sent(&mut self, robot: RobotId, command: AckedCommand, now: Duration) {
+        self.in_flight
+            .retain(|(it_robot, it, _)| (*it_robot, *it) != (robot, command));
+        self.in_flight.push((robot, command, now));
+    }
+
+    pub fn is_pending(&self, robot: RobotId, command: AckedCommand) -> bool {
+        self.in_flight
+            .iter()
+            .any(|(it_robot, it, _)| (*it_robot, *it) == (robot, command))
+    }
+
+    /// Stops waiting on the command `completion` is for, `false` if nothing was waiting on it
+    ///
+    /// The robot also reports work it started itself, like the camera resync it does on startup,
+    /// those are not shown.
+    pub fn complete(&mut self, completion: &CommandComplete, now: Duration) -> bool {
+        let before = self.in_flight.len();
+        self.in_flight.retain(|(robot, command, _)| {
+            (*robot, *command) != (completion.robot, completion.command)
+        });
+
+        if self.in_flight.len() == before {
+            return false;
+        }
+
+        self.outcomes.push_back(Outcome {
+            command: completion.command,
+            ok: completion.ok,
+            detail: completion.detail.clone(),
+            at: now,
+        });
+
+        true
+    }
+
+    /// Gives up on commands sent more than [`COMMAND_TIMEOUT`] ago, returns those given up on
+    pub fn expire(&mut self, now: Duration) -> Vec<AckedCommand> {
+        let mut expired = Vec::new();
+
+        self.in_flight.retain(|(_, command, sent)| {
+            let timed_out = now.saturating_sub(*sent) > COMMAND_TIMEOUT;
+            if timed_out {
+                expired.push(*command);
+            }
+
+            !timed_out
+        });
+
+        for command in &expired {
+            self.outcomes.push_back(Outcome {
+                command: *command,
+                ok: false,
+                detail: format!(
+                    "No answer from the robot after {}s",
+                    COMMAND_TIMEOUT.as_secs()
+                ),
+                at: now,
+            });
+        }
+
+        while self
+            .outcomes
+            .front()
+            .is_some_and(|it| now.saturating_sub(it.at) > OUTCOME_TOAST)
+        {
+            self.outcomes.pop_front();
+        }
+
+        expired
+    }
+
+    /// Outcomes that are still shown
+    pub fn outcomes(&self) -> impl Iterator<Item = &Outcome> {
+        self.outcomes.iter()
+    }
+}
+
+fn collect_completions(
+    mut pending: ResMut<PendingCommands>,
+    mut completions: EventReader<CommandComplete>,
+    time: Res<Time<Real>>,
+) {
+    for completion in completions.read() {
+        let name = completion.command.name();
+
+        if completion.ok {
+            info!("{name} done: {}", completion.detail);
+        } else {
+            warn!("{name} failed: {}", completion.detail);
+        }
+
+        pending.complete(completion, time.elapsed());
+    }
+}
+
+fn expire_commands(mut pending: ResMut<PendingCommands>, time: Res<Time<Real>>) {
+    for command in pending.expire(time.elapsed()) {
+        warn!("{} timed out", command.name());
+    }
+}
+
+fn outcome_toast(mut contexts: EguiContexts, pending: Res<PendingCommands>) {
+    if pending.outcomes().next().is_none() {
+        return;
+    }
+
+    egui::Area::new(Id::new("Command Outcomes"))
+        .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-10.0, -40.0))
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                for outcome in pending.outcomes() {
+                    let (status, color) = if outcome.ok {
+                        ("done", Color32::GREEN)
+                    } else {
+                        ("failed", Color32::RED)
+                    };
+
+                    ui.label(
+                        RichText::new(format!("{} {status}", outcome.command.name()))
+                            .strong()
+                            .color(color),
+                    );
+                    ui.label(&outcome.detail);
+                }
+            });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use common::{
+        components::RobotId,
+        ecs_sync::NetId,
+        events::{AckedCommand, CommandComplete},
+    };
+
+    use super::{PendingCommands, COMMAND_TIMEOUT, OUTCOME_TOAST};
+
+    fn completion(robot: RobotId, command: AckedCommand, ok: bool) -> CommandComplete {
+        CommandComplete {
+            robot,
+            command,
+            ok,
+            detail: "Detail".to_owned(),
+        }
+    }
+
+    #[test]
+    fn completion_clears_pending() {
+        let robot = RobotId(NetId::random());
+        let mut pending = PendingCommands::default();
+
+        pending.sent(robot, AckedCommand::CalibrateSeaLevel, Duration::ZERO);
+        pending.sent(robot, AckedCommand::ResetYaw, Duration::ZERO);
+        assert!(pending.is_pending(robot, AckedCommand::CalibrateSeaLevel));
+
+        let done = completion(robot, AckedCommand::CalibrateSeaLevel, true);
+        assert!(pending.complete(&done, Duration::from_secs(2)));
+
+        assert!(!pending.is_pending(robot, AckedCommand::CalibrateSeaLevel));
+        assert!(pending.is_pending(robot, AckedCommand::ResetYaw));
+
+        let outcomes = pending.outcomes().collect::<Vec<_>>();
+        assert_eq!(outcomes.len(), 1);
+        assert!(outcomes[0].ok);
+        assert_eq!(outcomes[0].command, AckedCommand::CalibrateSeaLevel);
+    }
+
+    #[test]
+    fn unrequested_completions_are_ignored() {
+        let (robot, other) = (RobotId(NetId::random()), RobotId(NetId::random()));
+        let mut pending = PendingCommands::default();
+
+        // The resync the robot does on startup
+        let resynced = completion(robot, AckedCommand::ResyncCameras, true);
+        assert!(!pending.complete(&resynced, Duration::ZERO));
+
+        // Another robot finishing the same command
+        pending.sent(robot, AckedCommand::ResyncCameras, Duration::ZERO);
+        let resynced = completion(other, AckedCommand::ResyncCameras, true);
+        assert!(!pending.complete(&resynced, Duration::ZERO));
+        assert!(pending.is_pending(robot, AckedCommand::ResyncCameras));
+
+        assert_eq!(pending.outcomes().count(), 0);
+    }
+
+    #[test]
+    fn commands_time_out() {
+        let robot = RobotId(NetId::random());
+        let mut pending = PendingCommands::default();
+
+        pending.sent(robot, AckedCommand::ResetYaw, Duration::ZERO);
+        assert!(pending.expire(COMMAND_TIMEOUT).is_empty());
+
+        // Sending again restarts the wait
+        pending.sent(robot, AckedCommand::ResetYaw, Duration::from_secs(1));
+        let timeout = COMMAND_TIMEOUT + Duration::from_millis(500);
+        assert!(pending.expire(timeout).is_empty());
+        assert!(pending.is_pending(robot, AckedCommand::ResetYaw));
+
+        let timeout = COMMAND_TIMEOUT + Duration::from_millis(1500);
+        assert_eq!(pending.expire(timeout), [AckedCommand::ResetYaw]);
+        assert!(!pending.is_pending(robot, AckedCommand::ResetYaw));
+
+        let outcomes = pending.outcomes().collect::<Vec<_>>();
+        assert_eq!(outcomes.len(), 1);
+        assert!(!outcomes[0].ok);
+
+        // A late answer isn't shown, the command was already given up on
+        let late = completion(robot, AckedCommand::ResetYaw, true);
+        assert!(!pending.complete(&late, timeout));
+
+        // The toast goes away on its own
+        pending.expire(timeout + OUTCOME_TOAST);
+        assert_eq!(pending.outcomes().count(), 1);
+        pending.expire(timeout + OUTCOME_TOAST + Duration::from_millis(1));
+        assert_eq!(pending.outcomes().count(), 0);
+    }
+}
@@ -0,0 +1,438 @@
+//! Records the numbers video pipelines publish so they can be used after the run
+//!
+//! Pipelines insert a [`PipelineResult`] on their entity every frame they have something to
+//! report. While recording, each one becomes a row in a CSV file in [`RESULTS_DIR`]. Files are
+//! written on a worker thread and continued in a new part once they reach [`MAX_FILE_SIZE`].
+//!
+//! Rows carry the robot's clock next to the surface's so they line up with its sensor readings.
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::Context;
+use bevy::prelude::*;
+use common::{
+    clock::{self, ClockOffset},
+    components::Robot,
+    error::ErrorEvent,
+};
+use crossbeam::channel::{self, Receiver, Sender};
+use egui::{Color32, RichText};
+
+use crate::{
+    checklist::{self, csv_field},
+    video_pipelines::PipelineCamera,
+    video_stream::VideoProcessorFactory,
+};
+
+/// Where recorded pipeline results are saved
+pub const RESULTS_DIR: &str = "pipeline_results";
+/// Size a results file may grow to before the next part is started
+pub const MAX_FILE_SIZE: u64 = 8 * 1024 * 1024;
+
+const HEADER: &str = "timestamp,robot_time,camera,pipeline,result\n";
+
+pub struct PipelineResultsPlugin;
+
+impl Plugin for PipelineResultsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PipelineResultLog>().add_systems(
+            Update,
+            (
+                log_pipeline_results,
+                flush_ended_pipelines,
+                collect_writer_events,
+            )
+                .chain(),
+        );
+    }
+}
+
+/// Latest output of a pipeline, inserted on the pipeline entity every processed frame
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct PipelineResult(pub serde_json::Value);
+
+/// Whether results are being recorded, and where to
+#[derive(Resource, Default)]
+pub struct PipelineResultLog {
+    writer: Option<ResultWriter>,
+    /// Rows sent to the writer since recording started
+    rows: u64,
+    /// File the writer is currently appending to
+    path: Option<PathBuf>,
+    /// Why recording last stopped on its own
+    error: Option<String>,
+}
+
+impl PipelineResultLog {
+    pub fn is_recording(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// Starts a new session of files in `dir`
+    pub fn start(&mut self, dir: impl Into<PathBuf>) {
+        self.writer = Some(ResultWriter::spawn(
+            dir.into(),
+            checklist::now().as_secs(),
+            MAX_FILE_SIZE,
+        ));
+        self.rows = 0;
+        self.path = None;
+        self.error = None;
+    }
+
+    /// Stops recording once everything already sent is written
+    pub fn stop(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            writer.finish();
+        }
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    fn send(&mut self, command: WriterCommand) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+
+        if let WriterCommand::Row(_) = command {
+            self.rows += 1;
+        }
+
+        // A writer that hung up has an error waiting in `events`
+        let _ = writer.commands.send(command);
+    }
+}
+
+/// `timestamp,robot_time,camera,pipeline,result` with the timestamp in seconds since the unix
+/// epoch
+///
+/// `robot_time` is on the clock the robot stamps its sensor readings with, left empty until the
+/// clock offset is known.
+pub fn result_row(
+    timestamp: Duration,
+    robot_time: Option<f64>,
+    camera: &str,
+    pipeline: &str,
+    result: &serde_json::Value,
+) -> String {
+    format!(
+        "{:.3},{},{},{},{}\n",
+        timestamp.as_secs_f64(),
+        robot_time.map(|it| format!("{it:.3}")).unwrap_or_default(),
+        csv_field(camera),
+        csv_field(pipeline),
+        csv_field(&result.to_string())
+    )
+}
+
+/// A session's results, split into numbered parts of at most `max_size` bytes
+///
+/// A single row larger than `max_size` still gets a part of its own.
+struct ResultFile {
+    dir: PathBuf,
+    session: u64,
+    max_size: u64,
+
+    part: u32,
+    size: u64,
+    file: Option<BufWriter<File>>,
+}
+
+impl ResultFile {
+    fn new(dir: PathBuf, session: u64, max_size: u64) -> Self {
+        Self {
+            dir,
+            session,
+            max_size,
+
+            part: 0,
+            size: 0,
+            file: None,
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.dir
+            .join(format!("results_{}_{}.csv", self.session, self.part))
+    }
+
+    /// Appends `row`, returns the path of the part it started if it had to start one
+    fn write(&mut self, row: &str) -> anyhow::Result<Option<PathBuf>> {
+        let has_rows = self.size > HEADER.len() as u64;
+        let full = has_rows && self.size + row.len() as u64 > self.max_size;
+
+        let opened = if self.file.is_none() || full {
+            if self.file.is_some() {
+                self.flush()?;
+                self.part += 1;
+            }
+
+            Some(self.open()?)
+        } else {
+            None
+        };
+
+        let file = self.file.as_mut().context("No results file")?;
+        file.write_all(row.as_bytes())
+            .context("Write pipeline result")?;
+        self.size += row.len() as u64;
+
+        Ok(opened)
+    }
+
+    fn open(&mut self) -> anyhow::Result<PathBuf> {
+        let path = self.path();
+
+        fs::create_dir_all(&self.dir).context("Create results dir")?;
+        let mut file = BufWriter::new(File::create(&path).context("Create results file")?);
+        file.write_all(HEADER.as_bytes())
+            .context("Write results header")?;
+
+        self.file = Some(file);
+        self.size = HEADER.len() as u64;
+
+        Ok(path)
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        if let Some(file) = &mut self.file {
+            file.flush().context("Flush results file")?;
+        }
+
+        Ok(())
+    }
+}
+
+enum WriterCommand {
+    Row(String),
+    /// Sent when a pipeline ends so its last rows are on disk
+    Flush,
+}
+
+enum WriterEvent {
+    Opened(PathBuf),
+    Error(anyhow::Error),
+}
+
+/// Keeps file IO off the main thread
+struct ResultWriter {
+    commands: Sender<WriterCommand>,
+    events: Receiver<WriterEvent>,
+    thread: JoinHandle<()>,
+}
+
+impl ResultWriter {
+    fn spawn(dir: PathBuf, session: u64, max_size: u64) -> Self {
+        let (commands, commands_rx) = channel::unbounded();
+        let (events_tx, events) = channel::unbounded();
+
+        let thread = thread::spawn(move || {
+            let mut file = ResultFile::new(dir, session, max_size);
+
+            // Ends once the log drops its sender
+            for command in commands_rx {
+                let rst = match command {
+                    WriterCommand::Row(row) => file.write(&row).map(|opened| {
+                        if let Some(path) = opened {
+                            let _ = events_tx.send(WriterEvent::Opened(path));
+                        }
+                    }),
+                    WriterCommand::Flush => file.flush(),
+                };
+
+                if let Err(err) = rst {
+                    let _ = events_tx.send(WriterEvent::Error(err));
+                    return;
+                }
+            }
+
+            if let Err(err) = file.flush() {
+                let _ = events_tx.send(WriterEvent::Error(err));
+            }
+        });
+
+        Self {
+            commands,
+            events,
+            thread,
+        }
+    }
+
+    /// Waits for queued rows to be written
+    fn finish(self) {
+        drop(self.commands);
+
+        if self.thread.join().is_err() {
+            error!("Pipeline results writer panicked");
+        }
+    }
+}
+
+fn log_pipeline_results(
+    mut log: ResMut<PipelineResultLog>,
+    results: Query<(&PipelineResult, &PipelineCamera), Changed<PipelineResult>>,
+    cameras: Query<(&Name, Option<&VideoProcessorFactory>)>,
+    robot: Query<&ClockOffset, With<Robot>>,
+) {
+    if !log.is_recording() {
+        return;
+    }
+
+    let now = checklist::now();
+    let robot_time = robot
+        .get_single()
+        .ok()
+        .map(|offset| offset.to_remote(clock::monotonic_seconds()));
+
+    for (result, camera) in &results {
+        let (camera, pipeline) = match cameras.get(camera.camera()) {
+            Ok((name, factory)) => (name.as_str(), factory.map(|it| &*it.name)),
+            Err(_) => ("Unknown", None),
+        };
+
+        let pipeline = pipeline.unwrap_or("Unknown");
+        let row = result_row(now, robot_time, camera, pipeline, &result.0);
+        log.send(WriterCommand::Row(row));
+    }
+}
+
+fn flush_ended_pipelines(
+    mut log: ResMut<PipelineResultLog>,
+    mut ended: RemovedComponents<PipelineResult>,
+) {
+    if ended.read().count() > 0 {
+        log.send(WriterCommand::Flush);
+    }
+}
+
+fn collect_writer_events(mut log: ResMut<PipelineResultLog>, mut errors: EventWriter<ErrorEvent>) {
+    let Some(writer) = &log.writer else {
+        return;
+    };
+
+    let events = writer.events.try_iter().collect::<Vec<_>>();
+    for event in events {
+        match event {
+            WriterEvent::Opened(path) => {
+                info!("Recording pipeline results to {}", path.display());
+                log.path = Some(path);
+            }
+            WriterEvent::Error(err) => {
+                log.error = Some(format!("{err:#}"));
+                log.stop();
+
+                errors.send(err.context("Record pipeline results").into());
+            }
+        }
+    }
+}
+
+/// Recording toggle and status, shown in the Cameras menu
+pub fn menu(ui: &mut egui::Ui, log: &mut PipelineResultLog) {
+    let mut recording = log.is_recording();
+    if ui
+        .checkbox(&mut recording, "Record Pipeline Results")
+        .changed()
+    {
+        if recording {
+            log.start(RESULTS_DIR);
+        } else {
+            log.stop();
+        }
+    }
+
+    if let Some(error) = &log.error {
+        ui.label(RichText::new(error).color(Color32::RED));
+    } else if log.is_recording() {
+        let path = log
+            .path()
+            .map(|it| it.display().to_string())
+            .unwrap_or_else(|| "Waiting for results".to_owned());
+
+        ui.label(RichText::new(format!("{} rows, {path}", log.rows())).weak());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use serde_json::json;
+
+    use super::{result_row, ResultFile, HEADER};
+
+    #[test]
+    fn rows_are_quoted() {
+        let row = result_row(
+            Duration::from_millis(1_700_000_000_250),
+            Some(512.25),
+            "Front",
+            "Measure Pipeline",
+            &json!({ "width_px": 12.5, "label": "a \"b\"" }),
+        );
+
+        assert_eq!(
+            row,
+            "1700000000.250,512.250,Front,Measure Pipeline,\
+             \"{\"\"label\"\":\"\"a \\\"\"b\\\"\"\"\",\"\"width_px\"\":12.5}\"\n"
+        );
+
+        let row = result_row(Duration::ZERO, None, "Down, Left", "Squares", &json!(3));
+        assert_eq!(row, "0.000,,\"Down, Left\",Squares,3\n");
+    }
+
+    #[test]
+    fn files_rotate_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let row = result_row(Duration::ZERO, None, "Front", "Squares", &json!([1, 2, 3]));
+        let max_size = (HEADER.len() + row.len() * 3) as u64;
+        let mut file = ResultFile::new(dir.path().to_owned(), 42, max_size);
+
+        let mut opened = Vec::new();
+        for _ in 0..7 {
+            opened.extend(file.write(&row).unwrap());
+        }
+        file.flush().unwrap();
+
+        assert_eq!(
+            opened,
+            [0, 1, 2].map(|part| dir.path().join(format!("results_42_{part}.csv")))
+        );
+
+        let rows = opened
+            .iter()
+            .map(|path| {
+                let contents = fs::read_to_string(path).unwrap();
+                assert!(contents.starts_with(HEADER));
+                assert!(contents.len() as u64 <= max_size);
+
+                contents.lines().skip(1).count()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(rows, [3, 3, 1]);
+
+        // A row too big for any part still gets written
+        let huge = result_row(
+            Duration::ZERO,
+            None,
+            "Front",
+            "Squares",
+            &json!("x".repeat(256)),
+        );
+        assert!(file.write(&huge).unwrap().is_some());
+        assert!(file.write(&row).unwrap().is_some());
+    }
+}
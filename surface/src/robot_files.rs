@@ -0,0 +1,527 @@
+//! Browses the directories the robot offers, downloading and deleting files in them
+//!
+//! Downloads are kept in [`DOWNLOADS_DIR`] under the robot's name and the path they have on the
+//! robot. One that is cut off by a disconnect keeps its partial file and picks up from the last
+//! whole chunk once that robot is back.
+
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use common::{
+    components::{Robot, RobotId},
+    ecs_sync::rpc::{RpcHandle, RpcResult, RpcSender},
+    events::{DeleteFile, DownloadFile, DownloadStarted, FileDeleted, FileListing, ListFiles},
+    file_transfer::{partial_chunks, DownloadDir, FileReceived, Reassembler, TransferKind},
+    sync::Peer,
+};
+use networking::Token as NetToken;
+
+/// Where files downloaded from the robot are saved
+pub const DOWNLOADS_DIR: &str = "downloads";
+
+pub struct RobotFilesPlugin;
+
+impl Plugin for RobotFilesPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(DownloadDir(DOWNLOADS_DIR.into()))
+            .init_resource::<RobotFiles>()
+            .init_resource::<Downloads>()
+            .add_systems(
+                Update,
+                (
+                    collect_listings,
+                    collect_deletions,
+                    collect_download_results,
+                    track_downloads,
+                    interrupt_downloads,
+                    resume_downloads.after(interrupt_downloads),
+                ),
+            );
+    }
+}
+
+/// What the Robot Files panel shows
+#[derive(Resource, Default)]
+pub struct RobotFiles {
+    /// Browsable directory being shown
+    pub dir: Option<String>,
+    pub listing: Option<FileListing>,
+    pub pending_listing: Option<RpcHandle<FileListing>>,
+    /// File the operator asked to delete, waiting on confirmation
+    pub confirm_delete: Option<String>,
+    pub pending_delete: Option<RpcHandle<FileDeleted>>,
+    /// Why the last listing or delete failed
+    pub error: Option<String>,
+}
+
+impl RobotFiles {
+    /// Asks for the listing of the shown directory
+    pub fn refresh(&mut self, listings: &mut RpcSender<ListFiles, FileListing>) {
+        if let Some(dir) = &self.dir {
+            let handle = listings.send(ListFiles { dir: dir.clone() });
+            self.pending_listing = Some(handle);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DownloadState {
+    /// Waiting on the robot to accept the request
+    Requested,
+    Receiving,
+    /// Cut off before it finished, resumes once the robot is back
+    Interrupted,
+    Done(PathBuf),
+    Failed(String),
+}
+
+/// The robot a download comes from
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadSource {
+    pub robot: RobotId,
+    /// Connection the file arrives over, replaced when the robot comes back
+    pub peer: NetToken,
+    /// Where files from this robot are saved, with a directory for each browsable directory
+    pub save_dir: PathBuf,
+}
+
+#[derive(Debug)]
+pub struct Download {
+    pub source: DownloadSource,
+    /// Browsable directory the file is in
+    pub dir: String,
+    pub name: String,
+    /// Chunks on disk
+    pub received: u32,
+    /// Chunks in the whole file, 0 until the first one arrives
+    pub total: u32,
+    pub state: DownloadState,
+    handle: Option<RpcHandle<DownloadStarted>>,
+}
+
+impl Download {
+    /// Share of the file received, from 0 to 1
+    pub fn fraction(&self) -> f32 {
+        match self.state {
+            DownloadState::Done(_) => 1.0,
+            _ if self.total == 0 => 0.0,
+            _ => self.received as f32 / self.total as f32,
+        }
+    }
+
+    pub fn can_resume(&self) -> bool {
+        matches!(
+            self.state,
+            DownloadState::Interrupted | DownloadState::Failed(_)
+        )
+    }
+
+    /// Directory the file is saved in
+    pub fn save_dir(&self) -> PathBuf {
+        self.source.save_dir.join(&self.dir)
+    }
+
+    fn is(&self, robot: RobotId, dir: &str, name: &str) -> bool {
+        self.source.robot == robot && self.dir == dir && self.name == name
+    }
+
+    fn saves_to(&self, path: &Path) -> bool {
+        self.save_dir().join(&self.name) == path
+    }
+}
+
+/// Downloads from the robots, keyed by robot and the file's path on it
+#[derive(Resource, Debug, Default)]
+pub struct Downloads {
+    downloads: Vec<Download>,
+}
+
+impl Downloads {
+    /// Starts downloading `name` from `source`, or resumes it if part of it is already on disk
+    ///
+    /// Returns the request to send, see [`Downloads::sent`]
+    pub fn request(&mut self, source: DownloadSource, dir: &str, name: &str) -> DownloadFile {
+        let robot = source.robot;
+        let first_chunk = partial_chunks(&source.save_dir.join(dir), name);
+
+        let download = Download {
+            source,
+            dir: dir.to_owned(),
+            name: name.to_owned(),
+            received: first_chunk,
+            total: 0,
+            state: DownloadState::Requested,
+            handle: None,
+        };
+
+        match self.downloads.iter_mut().find(|it| it.is(robot, dir, name)) {
+            Some(existing) => *existing = download,
+            None => self.downloads.push(download),
+        }
+
+        DownloadFile {
+            dir: dir.to_owned(),
+            name: name.to_owned(),
+            first_chunk,
+        }
+    }
+
+    pub fn sent(
+        &mut self,
+        robot: RobotId,
+        dir: &str,
+        name: &str,
+        handle: RpcHandle<DownloadStarted>,
+    ) {
+        if let Some(download) = self.downloads.iter_mut().find(|it| it.is(robot, dir, name)) {
+            download.handle = Some(handle);
+        }
+    }
+
+    /// Applies the robot's answer to a request
+    pub fn answered(&mut self, result: &RpcResult<DownloadStarted>) {
+        let Some(download) = self.downloads.iter_mut().find(|it| {
+            it.handle
+                .as_ref()
+                .is_some_and(|handle| handle.is_for(result))
+        }) else {
+            return;
+        };
+        download.handle = None;
+
+        download.state = match &result.result {
+            Ok(DownloadStarted { error: None, .. }) => DownloadState::Receiving,
+            Ok(DownloadStarted {
+                error: Some(err), ..
+            }) => DownloadState::Failed(err.clone()),
+            // The request or its answer was lost, asking again picks up where it left off
+            Err(_) => DownloadState::Interrupted,
+        };
+    }
+
+    /// Updates the download being saved to `path`
+    pub fn update_progress(&mut self, peer: NetToken, path: &Path, received: u32, total: u32) {
+        if let Some(download) = self.get_mut(peer, path) {
+            download.received = received;
+            download.total = total;
+        }
+    }
+
+    pub fn finished(&mut self, peer: NetToken, path: &Path) {
+        if let Some(download) = self.get_mut(peer, path) {
+            download.received = download.total;
+            download.state = DownloadState::Done(path.to_owned());
+        }
+    }
+
+    /// Marks the downloads still in flight over connections that don't match `connected` as cut
+    /// off
+    pub fn interrupt(&mut self, mut connected: impl FnMut(NetToken) -> bool) {
+        for download in &mut self.downloads {
+            if matches!(
+                download.state,
+                DownloadState::Requested | DownloadState::Receiving
+            ) && !connected(download.source.peer)
+            {
+                download.state = DownloadState::Interrupted;
+                download.handle = None;
+            }
+        }
+    }
+
+    /// Interrupted downloads from `robot`
+    pub fn interrupted(&self, robot: RobotId) -> impl Iterator<Item = &Download> {
+        self.downloads
+            .iter()
+            .filter(move |it| it.source.robot == robot && it.state == DownloadState::Interrupted)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Download> {
+        self.downloads.iter()
+    }
+
+    /// Forgets downloads that are done
+    pub fn clear_finished(&mut self) {
+        self.downloads
+            .retain(|it| !matches!(it.state, DownloadState::Done(_)));
+    }
+
+    fn get_mut(&mut self, peer: NetToken, path: &Path) -> Option<&mut Download> {
+        self.downloads
+            .iter_mut()
+            .find(|it| it.source.peer == peer && it.saves_to(path))
+    }
+}
+
+/// Sends the request for `name`, starting or resuming the download
+///
+/// Chunks of a download that wasn't asked for are dropped, so `reassembler` is told to expect it.
+pub fn start_download(
+    downloads: &mut Downloads,
+    reassembler: &mut Reassembler,
+    sender: &mut RpcSender<DownloadFile, DownloadStarted>,
+    source: DownloadSource,
+    dir: &str,
+    name: &str,
+) {
+    let (robot, peer) = (source.robot, source.peer);
+    let request = downloads.request(source, dir, name);
+    info!(
+        "Downloading {} from {} at chunk {}",
+        name, dir, request.first_chunk
+    );
+
+    if let Some(download) = downloads.iter().find(|it| it.is(robot, dir, name)) {
+        reassembler.expect_download(peer, request.transfer_name(), download.save_dir());
+    }
+
+    let handle = sender.send(request);
+    downloads.sent(robot, dir, name, handle);
+}
+
+/// Size in bytes with a binary unit picked to keep the number short
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+fn collect_listings(
+    mut files: ResMut<RobotFiles>,
+    mut results: EventReader<RpcResult<FileListing>>,
+) {
+    for result in results.read() {
+        // Answers for a directory that is no longer shown
+        if !files
+            .pending_listing
+            .as_ref()
+            .is_some_and(|pending| pending.is_for(result))
+        {
+            continue;
+        }
+        files.pending_listing = None;
+
+        match &result.result {
+            Ok(listing) => {
+                files.error = listing.error.clone();
+                files.listing = Some(listing.clone());
+            }
+            Err(err) => files.error = Some(err.to_string()),
+        }
+    }
+}
+
+fn collect_deletions(
+    mut files: ResMut<RobotFiles>,
+    mut results: EventReader<RpcResult<FileDeleted>>,
+    mut listings: RpcSender<ListFiles, FileListing>,
+) {
+    for result in results.read() {
+        if !files
+            .pending_delete
+            .as_ref()
+            .is_some_and(|pending| pending.is_for(result))
+        {
+            continue;
+        }
+        files.pending_delete = None;
+
+        files.error = match &result.result {
+            Ok(deleted) => deleted.error.clone(),
+            Err(err) => Some(err.to_string()),
+        };
+
+        files.refresh(&mut listings);
+    }
+}
+
+fn collect_download_results(
+    mut downloads: ResMut<Downloads>,
+    mut results: EventReader<RpcResult<DownloadStarted>>,
+) {
+    for result in results.read() {
+        downloads.answered(result);
+    }
+}
+
+fn track_downloads(
+    mut downloads: ResMut<Downloads>,
+    reassembler: Res<Reassembler>,
+    mut received: EventReader<FileReceived>,
+) {
+    for progress in reassembler.progress() {
+        if progress.kind == TransferKind::Download {
+            downloads.update_progress(
+                progress.peer,
+                &progress.path,
+                progress.received,
+                progress.total,
+            );
+        }
+    }
+
+    for file in received.read() {
+        if file.kind == TransferKind::Download {
+            downloads.finished(file.peer, &file.path);
+        }
+    }
+}
+
+fn interrupt_downloads(mut downloads: ResMut<Downloads>, robots: Query<&Peer, With<Robot>>) {
+    downloads.interrupt(|peer| robots.iter().any(|it| it.token == peer));
+}
+
+fn resume_downloads(
+    (mut downloads, mut reassembler): (ResMut<Downloads>, ResMut<Reassembler>),
+    robots: Query<(&RobotId, &Peer), (With<Robot>, Added<Peer>)>,
+    mut sender: RpcSender<DownloadFile, DownloadStarted>,
+) {
+    for (&robot, peer) in &robots {
+        let interrupted = downloads
+            .interrupted(robot)
+            .map(|it| (it.source.clone(), it.dir.clone(), it.name.clone()))
+            .collect::<Vec<_>>();
+
+        for (source, robot_dir, name) in interrupted {
+            let source = DownloadSource {
+                peer: peer.token,
+                ..source
+            };
+
+            start_download(
+                &mut downloads,
+                &mut reassembler,
+                &mut sender,
+                source,
+                &robot_dir,
+                &name,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::Path};
+
+    use common::{components::RobotId, ecs_sync::NetId, file_transfer::CHUNK_SIZE};
+    use networking::Token as NetToken;
+
+    use super::{format_size, DownloadSource, DownloadState, Downloads};
+
+    fn source(robot: RobotId, peer: usize, save_dir: &Path) -> DownloadSource {
+        DownloadSource {
+            robot,
+            peer: NetToken(peer),
+            save_dir: save_dir.to_owned(),
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn downloads_resume_from_partial_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("logs")).unwrap();
+
+        let robot = RobotId(NetId::random());
+        let path = dir.path().join("logs").join("run.csv");
+        let mut downloads = Downloads::default();
+
+        // Nothing on disk, start from the beginning
+        let request = downloads.request(source(robot, 1, dir.path()), "logs", "run.csv");
+        assert_eq!(request.first_chunk, 0);
+        assert_eq!(request.dir, "logs");
+
+        downloads.update_progress(NetToken(1), &path, 2, 5);
+        let download = downloads.iter().next().unwrap();
+        assert!((download.fraction() - 0.4).abs() < 1e-6);
+
+        // Cut off with two and a half chunks written
+        downloads.interrupt(|_| false);
+        assert_eq!(downloads.interrupted(robot).count(), 1);
+        fs::write(
+            dir.path().join("logs/run.csv.part"),
+            vec![0; CHUNK_SIZE * 5 / 2],
+        )
+        .unwrap();
+
+        // Asking again over the new connection only asks for what is missing
+        let request = downloads.request(source(robot, 2, dir.path()), "logs", "run.csv");
+        assert_eq!(request.first_chunk, 2);
+        assert_eq!(downloads.iter().count(), 1);
+        assert_eq!(downloads.interrupted(robot).count(), 0);
+
+        // The old connection is gone
+        downloads.finished(NetToken(1), &path);
+        assert_eq!(
+            downloads.iter().next().unwrap().state,
+            DownloadState::Requested
+        );
+
+        downloads.finished(NetToken(2), &path);
+        let download = downloads.iter().next().unwrap();
+        assert_eq!(download.state, DownloadState::Done(path.clone()));
+        assert_eq!(download.fraction(), 1.0);
+
+        // Finished downloads aren't interrupted by a disconnect
+        downloads.interrupt(|_| false);
+        assert_eq!(downloads.interrupted(robot).count(), 0);
+
+        downloads.clear_finished();
+        assert_eq!(downloads.iter().count(), 0);
+    }
+
+    #[test]
+    fn downloads_are_kept_apart_by_robot_and_path() {
+        let dir = Path::new("missing");
+        let (a, b) = (RobotId(NetId::random()), RobotId(NetId::random()));
+        let mut downloads = Downloads::default();
+
+        downloads.request(source(a, 1, &dir.join("a")), "logs", "run.csv");
+        downloads.request(source(a, 1, &dir.join("a")), "stills", "run.csv");
+        downloads.request(source(b, 2, &dir.join("b")), "logs", "run.csv");
+        assert_eq!(downloads.iter().count(), 3);
+
+        downloads.update_progress(NetToken(1), &dir.join("a/stills/run.csv"), 1, 4);
+        let received = downloads.iter().map(|it| it.received).collect::<Vec<_>>();
+        assert_eq!(received, [0, 1, 0]);
+
+        // Only the robot that left is interrupted
+        downloads.interrupt(|peer| peer == NetToken(2));
+        assert_eq!(downloads.interrupted(a).count(), 2);
+        assert_eq!(downloads.interrupted(b).count(), 0);
+    }
+
+    #[test]
+    fn lost_requests_can_be_resumed() {
+        let robot = RobotId(NetId::random());
+        let mut downloads = Downloads::default();
+        downloads.request(source(robot, 1, Path::new("missing")), "stills", "a.jpg");
+
+        let download = downloads.iter().next().unwrap();
+        assert!(!download.can_resume());
+
+        downloads.interrupt(|_| false);
+        let download = downloads.iter().next().unwrap();
+        assert!(download.can_resume());
+    }
+}
@@ -0,0 +1,69 @@
+//! The robot that commands without a robot of their own are sent to
+//!
+//! Follows the robot the pilot's input is assigned to, or the only connected robot when no input
+//! is assigned, so a menu command never acts on every connected robot at once.
+
+use bevy::prelude::*;
+use common::components::{Robot, RobotId};
+
+use crate::input::InputMarker;
+
+pub struct SelectedRobotPlugin;
+
+impl Plugin for SelectedRobotPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SelectedRobot>()
+            .add_systems(PreUpdate, update_selected_robot);
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SelectedRobot(pub Option<RobotId>);
+
+/// The robot the first input is driving, or the only robot when no input drives one
+pub fn select_robot(robots: &[RobotId], inputs: &[RobotId]) -> Option<RobotId> {
+    if let Some(robot) = inputs.iter().find(|it| robots.contains(it)) {
+        return Some(*robot);
+    }
+
+    match robots {
+        [robot] => Some(*robot),
+        _ => None,
+    }
+}
+
+fn update_selected_robot(
+    mut selected: ResMut<SelectedRobot>,
+    robots: Query<&RobotId, With<Robot>>,
+    inputs: Query<&RobotId, With<InputMarker>>,
+) {
+    let robots = robots.iter().copied().collect::<Vec<_>>();
+    let inputs = inputs.iter().copied().collect::<Vec<_>>();
+
+    selected.set_if_neq(SelectedRobot(select_robot(&robots, &inputs)));
+}
+
+#[cfg(test)]
+mod tests {
+    use common::{components::RobotId, ecs_sync::NetId};
+
+    use super::select_robot;
+
+    #[test]
+    fn selection_follows_the_pilot() {
+        let a = RobotId(NetId::random());
+        let b = RobotId(NetId::random());
+        let unassigned = RobotId(NetId::invalid());
+
+        // The only robot is picked even without an input
+        assert_eq!(select_robot(&[a], &[]), Some(a));
+        assert_eq!(select_robot(&[a], &[unassigned]), Some(a));
+
+        // With several robots only an input decides
+        assert_eq!(select_robot(&[a, b], &[]), None);
+        assert_eq!(select_robot(&[a, b], &[unassigned, b]), Some(b));
+
+        // Inputs for robots that went away are ignored
+        assert_eq!(select_robot(&[], &[a]), None);
+    }
+}
@@ -0,0 +1,460 @@
+//! Saves enough of the surface's setup to pick back up after a restart
+//!
+//! The previous session is only restored when the pilot asks for it. Anything tied to a camera is
+//! applied once a camera with the same name shows up, or dropped after [`REATTACH_TIMEOUT`].
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use bevy::{app::AppExit, prelude::*};
+use bevy_egui::EguiContexts;
+use common::{
+    components::Camera,
+    error::ErrorEvent,
+    sync::{ConnectToPeer, Peer},
+};
+use networking::PeerAddr;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ui::{Panel, PanelManager},
+    video_display_2d_master::{DisplayMarker, MakeMaster},
+    video_pipelines::VideoPipelines,
+    video_stream::{VideoProcessorFactory, VideoThread},
+};
+
+/// Where the last session is kept between runs
+pub const SESSION_PATH: &str = "surface_session.toml";
+/// Bumped whenever [`SessionState`] changes incompatibly
+pub const SESSION_VERSION: u32 = 1;
+/// How often the session is written to disk
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(10);
+/// How long restored pipelines wait for their camera
+pub const REATTACH_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct SessionPlugin;
+
+impl Plugin for SessionPlugin {
+    fn build(&self, app: &mut App) {
+        match SessionState::load(SESSION_PATH) {
+            Ok(Some(session)) => {
+                app.insert_resource(SavedSession(session));
+            }
+            Ok(None) => {}
+            Err(err) => warn!("Ignoring saved session: {err:?}"),
+        }
+
+        app.add_systems(
+            Update,
+            (
+                restore_prompt.run_if(resource_exists::<SavedSession>),
+                reattach.run_if(resource_exists::<PendingReattachments>),
+                // Dont overwrite the last session before the pilot had a chance to restore it
+                save_session.run_if(not(resource_exists::<SavedSession>)),
+            ),
+        )
+        .add_systems(
+            Last,
+            save_on_exit.run_if(not(resource_exists::<SavedSession>)),
+        );
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct SessionState {
+    pub version: u32,
+    #[serde(default, with = "peer_addr")]
+    pub peer: Option<PeerAddr>,
+    /// Name of the camera shown as the master feed
+    pub pinned_feed: Option<String>,
+    pub panels: BTreeSet<Panel>,
+    /// Camera name to the name of the video pipeline running on it
+    pub pipelines: BTreeMap<String, String>,
+}
+
+impl SessionState {
+    pub fn new() -> Self {
+        Self {
+            version: SESSION_VERSION,
+            ..default()
+        }
+    }
+
+    pub fn from_toml(state: &str) -> anyhow::Result<Self> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u32,
+        }
+
+        let Versioned { version } = toml::from_str(state).context("Parse session version")?;
+        if version != SESSION_VERSION {
+            bail!("Session is version {version}, expected {SESSION_VERSION}");
+        }
+
+        toml::from_str(state).context("Parse session")
+    }
+
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        toml::to_string(self).context("Serialize session")
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Option<Self>> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let state = fs::read_to_string(path).context("Read session")?;
+        Self::from_toml(&state).map(Some)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        fs::write(path, self.to_toml()?).context("Write session")
+    }
+}
+
+/// The session from the last run, waiting on the pilot to restore or discard it
+#[derive(Resource)]
+pub struct SavedSession(pub SessionState);
+
+/// Parts of a restored session waiting for their camera to appear
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct PendingReattachments {
+    pipelines: BTreeMap<String, String>,
+    pinned_feed: Option<String>,
+    deadline: Duration,
+}
+
+impl PendingReattachments {
+    pub fn new(session: &SessionState, now: Duration) -> Self {
+        Self {
+            pipelines: session.pipelines.clone(),
+            pinned_feed: session.pinned_feed.clone(),
+            deadline: now + REATTACH_TIMEOUT,
+        }
+    }
+
+    /// The pipeline to attach to the camera named `camera`, only handed out once
+    pub fn take_pipeline(&mut self, camera: &str) -> Option<String> {
+        self.pipelines.remove(camera)
+    }
+
+    /// Camera that should be the master feed
+    ///
+    /// Kept until the timeout since the layout is rebuilt whenever a camera joins
+    pub fn pinned_feed(&self) -> Option<&str> {
+        self.pinned_feed.as_deref()
+    }
+
+    pub fn is_expired(&self, now: Duration) -> bool {
+        now >= self.deadline
+    }
+
+    /// Cameras that still have a pipeline waiting on them
+    pub fn unmatched(&self) -> impl Iterator<Item = &str> {
+        self.pipelines.keys().map(|it| it.as_str())
+    }
+}
+
+fn restore_prompt(
+    mut cmds: Commands,
+    mut contexts: EguiContexts,
+    saved: Res<SavedSession>,
+    mut panels: ResMut<PanelManager>,
+    mut connect: EventWriter<ConnectToPeer>,
+    time: Res<Time<Real>>,
+) {
+    let session = &saved.0;
+    let mut restore = None;
+
+    egui::Window::new("Restore Previous Session")
+        .collapsible(false)
+        .resizable(false)
+        .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+        .show(contexts.ctx_mut(), |ui| {
+            match &session.peer {
+                Some(peer) => ui.label(format!("Robot: {peer}")),
+                None => ui.label("Robot: None"),
+            };
+            ui.label(format!("Panels: {}", session.panels.len()));
+            for (camera, pipeline) in &session.pipelines {
+                ui.label(format!("{camera}: {pipeline}"));
+            }
+            if let Some(pinned) = &session.pinned_feed {
+                ui.label(format!("Pinned: {pinned}"));
+            }
+
+            ui.horizontal(|ui| {
+                if ui.button("Restore").clicked() {
+                    restore = Some(true);
+                }
+                if ui.button("Start Fresh").clicked() {
+                    restore = Some(false);
+                }
+            });
+        });
+
+    let Some(restore) = restore else {
+        return;
+    };
+
+    if restore {
+        info!("Restoring previous session");
+
+        if let Some(peer) = session.peer.clone() {
+            connect.send(ConnectToPeer(peer));
+        }
+
+        for panel in Panel::ALL {
+            if session.panels.contains(&panel) {
+                panels.open(panel);
+            } else {
+                panels.close(panel);
+            }
+        }
+
+        cmds.insert_resource(PendingReattachments::new(session, time.elapsed()));
+    }
+
+    cmds.remove_resource::<SavedSession>();
+}
+
+fn reattach(
+    mut cmds: Commands,
+    mut pending: ResMut<PendingReattachments>,
+    pipelines: Res<VideoPipelines>,
+    cameras: Query<(Entity, &Name, Option<&DisplayMarker>), (With<Camera>, With<VideoThread>)>,
+    mut make_master: EventWriter<MakeMaster>,
+    time: Res<Time<Real>>,
+) {
+    for (camera, name, display) in &cameras {
+        if let Some(pipeline_name) = pending.take_pipeline(name.as_str()) {
+            match pipelines.0.iter().find(|it| it.name == pipeline_name) {
+                Some(pipeline) => {
+                    info!("Reattaching {pipeline_name} to {name}");
+                    cmds.entity(camera).insert(pipeline.factory.clone());
+                }
+                None => warn!("Could not reattach unknown pipeline {pipeline_name} to {name}"),
+            }
+        }
+
+        if pending.pinned_feed() == Some(name.as_str()) {
+            if let Some(display) = display {
+                if display.0 != 0 {
+                    make_master.send(MakeMaster(camera));
+                }
+            }
+        }
+    }
+
+    if pending.is_expired(time.elapsed()) {
+        for camera in pending.unmatched() {
+            warn!("Camera {camera} did not appear, dropping its pipeline");
+        }
+
+        cmds.remove_resource::<PendingReattachments>();
+    }
+}
+
+fn capture_session(
+    peers: &Query<&Peer>,
+    panels: &PanelManager,
+    cameras: &Query<
+        (
+            &Name,
+            Option<&VideoProcessorFactory>,
+            Option<&DisplayMarker>,
+        ),
+        With<Camera>,
+    >,
+) -> SessionState {
+    let mut session = SessionState::new();
+
+    session.peer = peers.iter().next().map(|it| it.addrs.clone());
+    session.panels = panels.open_panels().collect();
+
+    for (name, factory, display) in cameras {
+        if let Some(factory) = factory {
+            session
+                .pipelines
+                .insert(name.to_string(), factory.name.to_string());
+        }
+
+        if display.is_some_and(|it| it.0 == 0) {
+            session.pinned_feed = Some(name.to_string());
+        }
+    }
+
+    session
+}
+
+fn save_session(
+    mut last_save: Local<Option<Duration>>,
+    mut last_session: Local<Option<SessionState>>,
+    peers: Query<&Peer>,
+    panels: Res<PanelManager>,
+    cameras: Query<
+        (
+            &Name,
+            Option<&VideoProcessorFactory>,
+            Option<&DisplayMarker>,
+        ),
+        With<Camera>,
+    >,
+    time: Res<Time<Real>>,
+    mut errors: EventWriter<ErrorEvent>,
+) {
+    let now = time.elapsed();
+    if last_save.is_some_and(|last| now.saturating_sub(last) < SAVE_INTERVAL) {
+        return;
+    }
+    *last_save = Some(now);
+
+    let session = capture_session(&peers, &panels, &cameras);
+    if last_session.as_ref() == Some(&session) {
+        return;
+    }
+
+    if let Err(err) = session.save(SESSION_PATH) {
+        errors.send(ErrorEvent(err));
+    }
+
+    *last_session = Some(session);
+}
+
+fn save_on_exit(
+    mut exit: EventReader<AppExit>,
+    peers: Query<&Peer>,
+    panels: Res<PanelManager>,
+    cameras: Query<
+        (
+            &Name,
+            Option<&VideoProcessorFactory>,
+            Option<&DisplayMarker>,
+        ),
+        With<Camera>,
+    >,
+) {
+    for _event in exit.read() {
+        let session = capture_session(&peers, &panels, &cameras);
+
+        if let Err(err) = session.save(SESSION_PATH) {
+            error!("Could not save session: {err:?}");
+        }
+    }
+}
+
+/// Peers are written in their display form, which for TCP peers is the plain socket address
+mod peer_addr {
+    use networking::PeerAddr;
+    use serde::{de::Error, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        peer: &Option<PeerAddr>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        peer.as_ref().map(ToString::to_string).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<PeerAddr>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|peer| peer.parse().map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use networking::PeerAddr;
+
+    use crate::ui::Panel;
+
+    use super::{PendingReattachments, SessionState, REATTACH_TIMEOUT, SESSION_VERSION};
+
+    fn session() -> SessionState {
+        let mut session = SessionState::new();
+        session.peer = Some("192.168.1.2:44445".parse().unwrap());
+        session.pinned_feed = Some("Front".to_owned());
+        session.panels = [Panel::Timer, Panel::Servos].into();
+        session
+            .pipelines
+            .insert("Front".to_owned(), "Measure".to_owned());
+        session
+            .pipelines
+            .insert("Top".to_owned(), "Edges".to_owned());
+
+        session
+    }
+
+    #[test]
+    fn round_trips() {
+        let session = session();
+        let toml = session.to_toml().unwrap();
+
+        assert_eq!(SessionState::from_toml(&toml).unwrap(), session);
+
+        let empty = SessionState::new();
+        assert_eq!(
+            SessionState::from_toml(&empty.to_toml().unwrap()).unwrap(),
+            empty
+        );
+
+        let mut local = session();
+        local.peer = Some(PeerAddr::Unix("/tmp/mate-rov.sock".into()));
+        let toml = local.to_toml().unwrap();
+        assert!(toml.contains("peer = \"unix:/tmp/mate-rov.sock\""));
+        assert_eq!(SessionState::from_toml(&toml).unwrap(), local);
+    }
+
+    #[test]
+    fn rejects_other_versions_and_garbage() {
+        let toml = session().to_toml().unwrap();
+
+        let old = toml.replace(
+            &format!("version = {SESSION_VERSION}"),
+            &format!("version = {}", SESSION_VERSION - 1),
+        );
+        assert_ne!(old, toml);
+        assert!(SessionState::from_toml(&old).is_err());
+
+        // Files from before sessions were versioned
+        assert!(SessionState::from_toml("peer = \"192.168.1.2:44445\"").is_err());
+
+        assert!(SessionState::from_toml("version = 1\npanels = 7").is_err());
+        assert!(SessionState::from_toml("\u{0}garbage[[").is_err());
+        assert!(SessionState::from_toml("").is_err());
+    }
+
+    #[test]
+    fn reattaches_by_name_once() {
+        let start = Duration::from_secs(100);
+        let mut pending = PendingReattachments::new(&session(), start);
+
+        assert_eq!(pending.take_pipeline("Bottom"), None);
+        assert_eq!(pending.take_pipeline("Front").as_deref(), Some("Measure"));
+        assert_eq!(pending.take_pipeline("Front"), None);
+
+        assert_eq!(pending.unmatched().collect::<Vec<_>>(), ["Top"]);
+        assert_eq!(pending.pinned_feed(), Some("Front"));
+    }
+
+    #[test]
+    fn reattachment_times_out() {
+        let start = Duration::from_secs(100);
+        let pending = PendingReattachments::new(&session(), start);
+
+        assert!(!pending.is_expired(start));
+        assert!(!pending.is_expired(start + REATTACH_TIMEOUT - Duration::from_millis(1)));
+        assert!(pending.is_expired(start + REATTACH_TIMEOUT));
+    }
+}
@@ -1,7 +1,11 @@
-use bevy::prelude::*;
+use std::time::Duration;
+
+use bevy::{prelude::*, time::common_conditions::on_timer, window::PrimaryWindow};
 use common::{
     components::{Singleton, Surface},
     ecs_sync::Replicate,
+    events::OperatorHeartbeat,
+    over_run::OverRunState,
     InstanceName,
 };
 
@@ -18,7 +22,10 @@ pub struct LocalSurface {
 
 impl Plugin for SurfacePlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(PreStartup, setup_surface);
+        app.add_systems(PreStartup, setup_surface).add_systems(
+            Update,
+            operator_heartbeat.run_if(on_timer(HEARTBEAT_INTERVAL)),
+        );
     }
 }
 
@@ -35,3 +42,23 @@ fn setup_surface(mut cmds: Commands, name: Res<InstanceName>) {
 
     cmds.insert_resource(LocalSurface { entity: surface })
 }
+
+/// Heartbeats keep the robot's thrusters live, see the robot's heartbeat interlock
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(200);
+
+fn operator_heartbeat(
+    mut seq: Local<u32>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    over_run: Res<OverRunState>,
+    mut heartbeats: EventWriter<OperatorHeartbeat>,
+) {
+    let focused = windows.iter().any(|window| window.focused);
+
+    // Only vouch for an operator that can see an up to date ui
+    if !focused || over_run.over_running {
+        return;
+    }
+
+    heartbeats.send(OperatorHeartbeat { seq: *seq });
+    *seq = seq.wrapping_add(1);
+}
@@ -0,0 +1,314 @@
+//! Colors used across the surface, switchable between light and dark at runtime
+//!
+//! Colors that carry meaning, like the armed state or battery voltage, come from a [`Palette`]
+//! instead of being picked where they are drawn, so a palette for colorblind pilots only has to
+//! be added here.
+
+use std::{fs, path::Path};
+
+use anyhow::Context;
+use bevy::prelude::*;
+use common::{
+    components::{Armed, PowerMode},
+    error::ErrorEvent,
+};
+use egui::{Color32, Visuals};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cli::SurfaceSettings,
+    command_palette::{AppCommandExt, SurfaceCommand},
+};
+
+/// Where the theme picked from the View menu is kept between runs
+pub const THEME_PATH: &str = "surface_theme.toml";
+
+/// Battery voltage below which the power readout warns
+pub const LOW_VOLTAGE: f32 = 12.5;
+/// Battery voltage below which the power readout is critical
+pub const CRITICAL_VOLTAGE: f32 = 11.5;
+/// Current draw above which the power readout warns
+pub const HIGH_CURRENT: f32 = 15.0;
+/// Current draw above which the power readout is critical
+pub const CRITICAL_CURRENT: f32 = 20.0;
+
+pub struct ThemePlugin;
+
+impl Plugin for ThemePlugin {
+    fn build(&self, app: &mut App) {
+        let dark_mode = app
+            .world
+            .get_resource::<SurfaceSettings>()
+            .is_some_and(|it| it.dark_mode);
+
+        // A theme picked in the UI wins over the settings
+        let mode = match ThemeMode::load(THEME_PATH) {
+            Ok(Some(mode)) => mode,
+            Ok(None) => ThemeMode::from_dark(dark_mode),
+            Err(err) => {
+                warn!("Ignoring saved theme: {err:?}");
+                ThemeMode::from_dark(dark_mode)
+            }
+        };
+        let theme = Theme::new(mode);
+
+        app.insert_resource(theme)
+            .insert_resource(theme.clear_color())
+            .add_systems(
+                Update,
+                (apply_clear_color, save_theme).run_if(resource_changed::<Theme>),
+            )
+            .add_command(
+                SurfaceCommand::new("Dark Mode", |world| world.resource_mut::<Theme>().toggle())
+                    .in_menu("View")
+                    .in_group("Theme")
+                    .checked_if(|world| world.resource::<Theme>().is_dark()),
+            );
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ThemeMode {
+    #[default]
+    Light,
+    Dark,
+}
+
+impl ThemeMode {
+    pub fn from_dark(dark: bool) -> Self {
+        if dark {
+            ThemeMode::Dark
+        } else {
+            ThemeMode::Light
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Option<Self>> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let saved = fs::read_to_string(path).context("Read theme")?;
+        let saved: SavedTheme = toml::from_str(&saved).context("Parse theme")?;
+
+        Ok(Some(saved.mode))
+    }
+
+    pub fn save(self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let saved = toml::to_string(&SavedTheme { mode: self }).context("Serialize theme")?;
+        fs::write(path, saved).context("Write theme")
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedTheme {
+    mode: ThemeMode,
+}
+
+/// Colors with a meaning
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    /// Plain text drawn outside of egui's own widgets
+    pub text: Color32,
+    /// Behind everything egui doesn't cover
+    pub background: Color,
+
+    /// Armed, and readings in their normal range
+    pub good: Color32,
+    pub warning: Color32,
+    /// Disarmed, errors and readings out of range
+    pub bad: Color32,
+
+    pub orientation_hold: Color32,
+    pub depth_hold: Color32,
+}
+
+impl Palette {
+    pub fn light() -> Self {
+        Self {
+            text: Color32::BLACK,
+            background: Color::rgb_u8(240, 238, 233),
+            good: Color32::GREEN,
+            warning: Color32::YELLOW,
+            bad: Color32::RED,
+            orientation_hold: Color32::from_rgb(66, 145, 247),
+            depth_hold: Color32::from_rgb(216, 123, 2),
+        }
+    }
+
+    pub fn dark() -> Self {
+        Self {
+            text: Color32::WHITE,
+            background: Color::rgb_u8(33, 34, 37),
+            ..Self::light()
+        }
+    }
+
+    pub fn armed(&self, armed: Armed) -> Color32 {
+        match armed {
+            Armed::Armed => self.good,
+            Armed::Disarmed => self.bad,
+        }
+    }
+
+    pub fn voltage(&self, volts: f32) -> Color32 {
+        if volts < CRITICAL_VOLTAGE {
+            self.bad
+        } else if volts < LOW_VOLTAGE {
+            self.warning
+        } else {
+            self.good
+        }
+    }
+
+    pub fn power_mode(&self, mode: PowerMode) -> Color32 {
+        match mode {
+            PowerMode::Normal => self.good,
+            PowerMode::LowPower => self.warning,
+            PowerMode::Critical => self.bad,
+        }
+    }
+
+    pub fn current(&self, amps: f32) -> Color32 {
+        if amps < HIGH_CURRENT {
+            self.good
+        } else if amps < CRITICAL_CURRENT {
+            self.warning
+        } else {
+            self.bad
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    pub mode: ThemeMode,
+    pub palette: Palette,
+}
+
+impl Theme {
+    pub fn new(mode: ThemeMode) -> Self {
+        let palette = match mode {
+            ThemeMode::Light => Palette::light(),
+            ThemeMode::Dark => Palette::dark(),
+        };
+
+        Self { mode, palette }
+    }
+
+    pub fn is_dark(&self) -> bool {
+        self.mode == ThemeMode::Dark
+    }
+
+    /// Switches between light and dark, dropping any changes made to the palette
+    pub fn toggle(&mut self) {
+        *self = Self::new(match self.mode {
+            ThemeMode::Light => ThemeMode::Dark,
+            ThemeMode::Dark => ThemeMode::Light,
+        });
+    }
+
+    pub fn visuals(&self) -> Visuals {
+        match self.mode {
+            ThemeMode::Light => Visuals::light(),
+            ThemeMode::Dark => Visuals::dark(),
+        }
+    }
+
+    pub fn clear_color(&self) -> ClearColor {
+        ClearColor(self.palette.background)
+    }
+}
+
+fn apply_clear_color(mut clear_color: ResMut<ClearColor>, theme: Res<Theme>) {
+    *clear_color = theme.clear_color();
+}
+
+fn save_theme(theme: Res<Theme>, mut errors: EventWriter<ErrorEvent>) {
+    // Only choices made in the UI are saved, not the theme from the settings
+    if theme.is_added() {
+        return;
+    }
+
+    if let Err(err) = theme.mode.save(THEME_PATH) {
+        errors.send(ErrorEvent(err));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+    use common::components::{Armed, PowerMode};
+
+    use super::{apply_clear_color, Palette, Theme, ThemeMode};
+
+    #[test]
+    fn status_colors() {
+        let palette = Palette::dark();
+
+        assert_eq!(palette.armed(Armed::Armed), palette.good);
+        assert_eq!(palette.armed(Armed::Disarmed), palette.bad);
+
+        assert_eq!(palette.voltage(11.0), palette.bad);
+        assert_eq!(palette.voltage(12.0), palette.warning);
+        assert_eq!(palette.voltage(12.5), palette.good);
+
+        assert_eq!(palette.power_mode(PowerMode::Normal), palette.good);
+        assert_eq!(palette.power_mode(PowerMode::LowPower), palette.warning);
+        assert_eq!(palette.power_mode(PowerMode::Critical), palette.bad);
+
+        assert_eq!(palette.current(5.0), palette.good);
+        assert_eq!(palette.current(15.0), palette.warning);
+        assert_eq!(palette.current(25.0), palette.bad);
+    }
+
+    #[test]
+    fn toggle_switches_palettes() {
+        let mut theme = Theme::new(ThemeMode::Light);
+        assert!(!theme.is_dark());
+        assert_eq!(theme.palette, Palette::light());
+
+        theme.toggle();
+        assert!(theme.is_dark());
+        assert_eq!(theme.palette, Palette::dark());
+
+        theme.toggle();
+        assert_eq!(theme, Theme::new(ThemeMode::Light));
+    }
+
+    #[test]
+    fn switching_updates_the_clear_color() {
+        let light = Theme::new(ThemeMode::Light);
+
+        let mut app = App::new();
+        app.insert_resource(light)
+            .insert_resource(light.clear_color())
+            .add_systems(Update, apply_clear_color.run_if(resource_changed::<Theme>));
+        app.update();
+        assert_eq!(
+            app.world.resource::<ClearColor>().0,
+            Palette::light().background
+        );
+
+        app.world.resource_mut::<Theme>().toggle();
+        app.update();
+        assert_eq!(
+            app.world.resource::<ClearColor>().0,
+            Palette::dark().background
+        );
+    }
+
+    #[test]
+    fn saved_theme_round_trips() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("theme.toml");
+
+        assert_eq!(ThemeMode::load(&path).unwrap(), None);
+
+        ThemeMode::Dark.save(&path).unwrap();
+        assert_eq!(ThemeMode::load(&path).unwrap(), Some(ThemeMode::Dark));
+    }
+}
@@ -0,0 +1,465 @@
+//! Confirms mode toggles with a short centered overlay and an optional rumble
+//!
+//! The overlay describes the state the toggle ended up in rather than what was asked for. Toggles
+//! that change the robot wait for the change to come back from the robot, so a toggle the robot
+//! didn't accept shows up as such instead of looking like it worked.
+
+use std::{collections::BTreeMap, collections::VecDeque, time::Duration};
+
+use bevy::{
+    input::gamepad::{GamepadRumbleIntensity, GamepadRumbleRequest},
+    math::Vec3A,
+    prelude::*,
+};
+use bevy_egui::EguiContexts;
+use common::components::{DepthTarget, OrientationTarget, Robot, RobotId};
+use egui::{Align2, Color32, Id, RichText};
+use leafwing_input_manager::action_state::ActionState;
+use serde::{Deserialize, Serialize};
+
+use crate::input::{
+    Action, InputInterpolation, InputMarker, LevelingType, PitchRollSwapped, SelectedServo,
+};
+
+/// How long each confirmation stays on screen
+pub const OVERLAY_DURATION: Duration = Duration::from_secs(1);
+/// How long robot side toggles get to make the round trip before their state is read back
+const ROBOT_SETTLE: Duration = Duration::from_millis(300);
+/// Length of the rumble pulse confirming a toggle
+const RUMBLE_PULSE: Duration = Duration::from_millis(120);
+
+pub struct ToggleFeedbackPlugin;
+
+impl Plugin for ToggleFeedbackPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ToggleFeedbackSettings>()
+            .init_resource::<PendingToggles>()
+            .init_resource::<OverlayQueue>()
+            .add_systems(
+                Update,
+                (detect_toggles, resolve_toggles, show_overlay).chain(),
+            );
+    }
+}
+
+/// Kinds of toggle that get confirmed, each can have its own rumble strength
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedbackClass {
+    DepthHold,
+    Leveling,
+    RobotMode,
+    PitchRoll,
+    Servo,
+}
+
+impl FeedbackClass {
+    pub const ALL: [FeedbackClass; 5] = [
+        FeedbackClass::DepthHold,
+        FeedbackClass::Leveling,
+        FeedbackClass::RobotMode,
+        FeedbackClass::PitchRoll,
+        FeedbackClass::Servo,
+    ];
+
+    /// The actions confirmed as this class
+    fn actions(&self) -> &'static [Action] {
+        match self {
+            FeedbackClass::DepthHold => &[Action::ToggleDepthHold],
+            FeedbackClass::Leveling => &[
+                Action::ToggleLeveling(LevelingType::Upright),
+                Action::ToggleLeveling(LevelingType::Inverted),
+            ],
+            FeedbackClass::RobotMode => &[Action::ToggleRobotMode],
+            FeedbackClass::PitchRoll => &[Action::SwitchPitchRoll],
+            FeedbackClass::Servo => &[
+                Action::SwitchServo,
+                Action::SwitchServoInverted,
+                Action::SelectImportantServo,
+            ],
+        }
+    }
+
+    /// How long to wait before reading back the toggled state
+    ///
+    /// Anything on the robot has to be replicated there and back, the rest is local
+    fn settle(&self) -> Duration {
+        match self {
+            FeedbackClass::DepthHold | FeedbackClass::Leveling => ROBOT_SETTLE,
+            FeedbackClass::RobotMode | FeedbackClass::PitchRoll | FeedbackClass::Servo => {
+                Duration::ZERO
+            }
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct ToggleFeedbackSettings {
+    pub enabled: bool,
+    /// Rumble strength from 0 to 1 for each class, zero or missing doesn't rumble
+    pub rumble: BTreeMap<FeedbackClass, f32>,
+}
+
+impl ToggleFeedbackSettings {
+    pub fn rumble(&self, class: FeedbackClass) -> f32 {
+        self.rumble
+            .get(&class)
+            .copied()
+            .unwrap_or(0.0)
+            .clamp(0.0, 1.0)
+    }
+}
+
+impl Default for ToggleFeedbackSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rumble: FeedbackClass::ALL
+                .into_iter()
+                .map(|class| (class, 0.3))
+                .collect(),
+        }
+    }
+}
+
+/// A toggle's resulting state, as shown in the overlay
+#[derive(Debug, Clone, PartialEq)]
+pub struct Confirmation {
+    pub class: FeedbackClass,
+    pub text: String,
+    /// The toggle didn't do what a press normally does, such as with no robot attached
+    pub failed: bool,
+}
+
+/// Confirmations shown one at a time so they never overlap
+#[derive(Resource, Debug, Default)]
+pub struct OverlayQueue {
+    /// What is on screen and since when
+    shown: Option<(Confirmation, Duration)>,
+    /// At most one per class
+    queued: VecDeque<Confirmation>,
+}
+
+impl OverlayQueue {
+    /// Queues behind what is on screen
+    ///
+    /// A newer confirmation of the same class replaces the older one instead of queueing behind
+    /// it, so a quick double press shows where it ended up right away
+    pub fn push(&mut self, confirmation: Confirmation, now: Duration) {
+        if let Some((shown, shown_at)) = &mut self.shown {
+            if shown.class == confirmation.class {
+                *shown = confirmation;
+                *shown_at = now;
+
+                return;
+            }
+        }
+
+        if let Some(queued) = self
+            .queued
+            .iter_mut()
+            .find(|it| it.class == confirmation.class)
+        {
+            *queued = confirmation;
+        } else {
+            self.queued.push_back(confirmation);
+        }
+    }
+
+    /// What to show at `now`, moving on to the next confirmation once one has been up long enough
+    pub fn current(&mut self, now: Duration) -> Option<&Confirmation> {
+        if let Some((_, shown_at)) = self.shown {
+            if now.saturating_sub(shown_at) >= OVERLAY_DURATION {
+                self.shown = None;
+            }
+        }
+
+        if self.shown.is_none() {
+            self.shown = self.queued.pop_front().map(|it| (it, now));
+        }
+
+        self.shown.as_ref().map(|(it, _)| it)
+    }
+
+    pub fn clear(&mut self) {
+        self.shown = None;
+        self.queued.clear();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct PendingToggle {
+    class: FeedbackClass,
+    input: Entity,
+    /// When the toggle was pressed
+    at: Duration,
+}
+
+/// Toggles waiting for their result to settle
+#[derive(Resource, Debug, Default)]
+struct PendingToggles(Vec<PendingToggle>);
+
+fn detect_toggles(
+    mut contexts: EguiContexts,
+    mut pending: ResMut<PendingToggles>,
+    inputs: Query<(Entity, &ActionState<Action>), With<InputMarker>>,
+    settings: Res<ToggleFeedbackSettings>,
+    gamepads: Res<Gamepads>,
+    mut rumble: EventWriter<GamepadRumbleRequest>,
+    time: Res<Time<Real>>,
+) {
+    if !settings.enabled {
+        return;
+    }
+
+    // Keys pressed while typing into a text field aren't meant as toggles
+    if contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    for (input, action_state) in &inputs {
+        for class in FeedbackClass::ALL {
+            let pressed = class
+                .actions()
+                .iter()
+                .any(|action| action_state.just_pressed(action));
+            if !pressed {
+                continue;
+            }
+
+            pending.0.push(PendingToggle {
+                class,
+                input,
+                at: time.elapsed(),
+            });
+
+            let intensity = settings.rumble(class);
+            if intensity > 0.0 {
+                for gamepad in gamepads.iter() {
+                    rumble.send(GamepadRumbleRequest::Add {
+                        duration: RUMBLE_PULSE,
+                        intensity: GamepadRumbleIntensity::weak_motor(intensity),
+                        gamepad,
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn resolve_toggles(
+    mut pending: ResMut<PendingToggles>,
+    mut overlay: ResMut<OverlayQueue>,
+    inputs: Query<
+        (
+            &RobotId,
+            &InputInterpolation,
+            &SelectedServo,
+            Option<&PitchRollSwapped>,
+        ),
+        With<InputMarker>,
+    >,
+    robots: Query<(&RobotId, Option<&DepthTarget>, Option<&OrientationTarget>), With<Robot>>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed();
+
+    // Always waits at least a frame so the input systems have handled the press
+    let (ready, waiting) = pending
+        .0
+        .drain(..)
+        .partition::<Vec<_>, _>(|it| now > it.at && now.saturating_sub(it.at) >= it.class.settle());
+    pending.0 = waiting;
+
+    for toggle in ready {
+        let Ok((robot_id, interpolation, servo, swapped)) = inputs.get(toggle.input) else {
+            continue;
+        };
+        let robot = robots.iter().find(|(other, ..)| *other == robot_id);
+
+        let confirmation = match toggle.class {
+            FeedbackClass::DepthHold => match robot {
+                Some((_, Some(target), _)) => confirm(
+                    toggle.class,
+                    format!("Depth Hold: ON @ {:.1} m", target.0 .0),
+                ),
+                Some((_, None, _)) => confirm(toggle.class, "Depth Hold: OFF".to_owned()),
+                None => failed(toggle.class, "Depth Hold: No ROV attached".to_owned()),
+            },
+            FeedbackClass::Leveling => match robot {
+                Some((_, _, Some(target))) => {
+                    let upright = (target.0 * Vec3A::Z).z >= 0.0;
+                    let text = if upright { "UPRIGHT" } else { "INVERTED" };
+
+                    confirm(toggle.class, format!("Leveling: {text}"))
+                }
+                Some((_, _, None)) => confirm(toggle.class, "Leveling: OFF".to_owned()),
+                None => failed(toggle.class, "Leveling: No ROV attached".to_owned()),
+            },
+            FeedbackClass::RobotMode => {
+                let mode = if *interpolation == InputInterpolation::precision() {
+                    "PRECISION"
+                } else {
+                    "NORMAL"
+                };
+
+                confirm(toggle.class, format!("Mode: {mode}"))
+            }
+            FeedbackClass::PitchRoll => {
+                let swapped = swapped.is_some_and(|it| it.0);
+                let text = if swapped { "SWAPPED" } else { "NORMAL" };
+
+                confirm(toggle.class, format!("Pitch/Roll: {text}"))
+            }
+            FeedbackClass::Servo => match &servo.servo {
+                Some(servo) => confirm(toggle.class, format!("Servo: {servo}")),
+                None => failed(toggle.class, "Servo: None selected".to_owned()),
+            },
+        };
+
+        overlay.push(confirmation, now);
+    }
+}
+
+fn confirm(class: FeedbackClass, text: String) -> Confirmation {
+    Confirmation {
+        class,
+        text,
+        failed: false,
+    }
+}
+
+fn failed(class: FeedbackClass, text: String) -> Confirmation {
+    Confirmation {
+        class,
+        text,
+        failed: true,
+    }
+}
+
+fn show_overlay(
+    mut contexts: EguiContexts,
+    mut overlay: ResMut<OverlayQueue>,
+    settings: Res<ToggleFeedbackSettings>,
+    time: Res<Time<Real>>,
+) {
+    if !settings.enabled {
+        overlay.clear();
+        return;
+    }
+
+    let Some(confirmation) = overlay.current(time.elapsed()) else {
+        return;
+    };
+
+    let color = if confirmation.failed {
+        Color32::RED
+    } else {
+        contexts.ctx_mut().style().visuals.strong_text_color()
+    };
+
+    egui::Area::new(Id::new("Toggle Feedback"))
+        .anchor(Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+        .interactable(false)
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.label(
+                    RichText::new(&confirmation.text)
+                        .size(28.0)
+                        .strong()
+                        .color(color),
+                );
+            });
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Confirmation, FeedbackClass, OverlayQueue, OVERLAY_DURATION};
+
+    fn confirmation(class: FeedbackClass, text: &str) -> Confirmation {
+        Confirmation {
+            class,
+            text: text.to_owned(),
+            failed: false,
+        }
+    }
+
+    fn shown(queue: &mut OverlayQueue, now: Duration) -> Option<String> {
+        queue.current(now).map(|it| it.text.clone())
+    }
+
+    #[test]
+    fn confirmations_queue_and_expire() {
+        let mut queue = OverlayQueue::default();
+        assert_eq!(shown(&mut queue, Duration::ZERO), None);
+
+        let start = Duration::from_secs(10);
+        queue.push(confirmation(FeedbackClass::DepthHold, "Depth"), start);
+        queue.push(confirmation(FeedbackClass::Leveling, "Level"), start);
+        queue.push(confirmation(FeedbackClass::RobotMode, "Mode"), start);
+
+        // One at a time, each for the full duration
+        assert_eq!(shown(&mut queue, start), Some("Depth".to_owned()));
+        let half = start + OVERLAY_DURATION / 2;
+        assert_eq!(shown(&mut queue, half), Some("Depth".to_owned()));
+
+        let second = start + OVERLAY_DURATION;
+        assert_eq!(shown(&mut queue, second), Some("Level".to_owned()));
+        assert_eq!(
+            shown(&mut queue, second + OVERLAY_DURATION / 2),
+            Some("Level".to_owned())
+        );
+
+        // Timed from when it was first shown, not when it was queued
+        let third = second + OVERLAY_DURATION;
+        assert_eq!(shown(&mut queue, third), Some("Mode".to_owned()));
+
+        assert_eq!(shown(&mut queue, third + OVERLAY_DURATION), None);
+    }
+
+    #[test]
+    fn repeated_classes_replace_instead_of_queueing() {
+        let mut queue = OverlayQueue::default();
+        let start = Duration::from_secs(10);
+
+        queue.push(confirmation(FeedbackClass::DepthHold, "Depth: ON"), start);
+        assert_eq!(shown(&mut queue, start), Some("Depth: ON".to_owned()));
+
+        // Toggled straight back, shows the new state at once for a full duration
+        let later = start + OVERLAY_DURATION / 2;
+        queue.push(confirmation(FeedbackClass::DepthHold, "Depth: OFF"), later);
+        assert_eq!(shown(&mut queue, later), Some("Depth: OFF".to_owned()));
+        assert_eq!(
+            shown(&mut queue, start + OVERLAY_DURATION),
+            Some("Depth: OFF".to_owned())
+        );
+
+        // Queued ones are replaced in place
+        queue.push(confirmation(FeedbackClass::Servo, "Servo: Claw1"), later);
+        queue.push(confirmation(FeedbackClass::RobotMode, "Mode"), later);
+        queue.push(confirmation(FeedbackClass::Servo, "Servo: Claw2"), later);
+
+        let next = later + OVERLAY_DURATION;
+        assert_eq!(shown(&mut queue, next), Some("Servo: Claw2".to_owned()));
+        assert_eq!(
+            shown(&mut queue, next + OVERLAY_DURATION),
+            Some("Mode".to_owned())
+        );
+        assert_eq!(shown(&mut queue, next + OVERLAY_DURATION * 2), None);
+    }
+
+    #[test]
+    fn clearing_hides_everything() {
+        let mut queue = OverlayQueue::default();
+        let now = Duration::from_secs(1);
+
+        queue.push(confirmation(FeedbackClass::DepthHold, "Depth"), now);
+        queue.push(confirmation(FeedbackClass::Servo, "Servo"), now);
+        queue.clear();
+
+        assert_eq!(shown(&mut queue, now), None);
+    }
+}
@@ -1,67 +1,608 @@
-use std::time::Duration;
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
 
-use bevy::{app::AppExit, prelude::*};
+use bevy::{
+    app::AppExit, diagnostic::DiagnosticsStore, ecs::system::RunSystemOnce, math::Vec3A, prelude::*,
+};
 use bevy_egui::{EguiContexts, EguiPlugin};
 use bevy_tokio_tasks::TokioTasksRuntime;
 use common::{
-    bundles::MovementContributionBundle,
     components::{
-        Armed, Camera, CpuTotal, CurrentDraw, Depth, DepthTarget, Inertial, LoadAverage,
-        MeasuredVoltage, Memory, MovementAxisMaximums, MovementContribution, OrientationTarget,
-        PwmChannel, PwmManualControl, PwmSignal, Robot, RobotId, RobotStatus, Temperatures,
+        ActiveContributions, Armed, AuthorityLimit, AvailableBehaviors, BuildInfo, Camera,
+        CameraFallbackStream, CameraManagerState, CenterOfMassOffset, ConfigSnapshot,
+        ContributionMuted, ControlGated, CrashReport, CurrentTrim, Depth, DepthTarget,
+        DepthTemperatureProfile, DisabledMotors, FileRoots, LedBrightness, LedMode,
+        MissionChecklist, MotorConfigReport, MotorDataSource, MotorDataStatus, MotorDefinition,
+        Motors, MovementAxisMaximums, MovementContribution, OperatingSystem, Orientation,
+        OrientationTarget, PidConfig, PidResult, PositionEstimate, PowerMode, PowerModeOverride,
+        PreviousCrashReport, PwmChannel, PwmHealth, PwmManualControl, PwmSignal, RemoteSyncStats,
+        Robot, RobotId, RobotStatus, RunningBehavior, ServoTargets, Servos, TargetForce,
+        TetherTurns, Uptime,
+    },
+    ecs_sync::{
+        apply_changes::{ApplyStats, RedundantApplies},
+        diagnostics::{BYTES_SERIALIZED, CHANGES_EMITTED, ENTITIES_APPLIED},
+        quarantine::{ClearQuarantine, QuarantinedTypes},
+        rpc::{RpcHandle, RpcResult, RpcSender},
+        scoped::Scoped,
+        stats::{SyncStats, STATS_HISTORY},
+        NetId,
     },
-    ecs_sync::{NetId, Replicate},
-    events::{CalibrateSeaLevel, ResetServos, ResetYaw, ResyncCameras},
-    sync::{ConnectToPeer, DisconnectPeer, Latency, MdnsPeers, Peer},
+    error::ErrorEvent,
+    events::{
+        AckedCommand, ArchiveCrashReport, ArmRejected, CalibrateSeaLevel, CaptureStill,
+        ClearProfile, ConfigUploadResult, DeleteFile, DownloadFile, DownloadStarted, FileDeleted,
+        FileListing, ListFiles, LoadChecklist, LogLevel, ReloadMotorData, RemoteLogRecord,
+        RequestConfig, ResetPosition, ResetServo, ResetServos, ResetTetherTurns, ResetYaw,
+        ResyncCameras, ServoCommand, SetChecklistItem, StartBehavior, StopBehavior, UploadConfig,
+        MAX_CONFIG_TEXT,
+    },
+    file_transfer::{DownloadDir, FileReceived, FileTransferDir, Reassembler, TransferKind},
+    sync::{ConnectToPeer, DisconnectPeer, MdnsPeers, NetErrorReport, Peer, PeerTypeMismatch},
+    types::{checklist::ChecklistItem, hw::Rgb8},
 };
 use egui::{
     load::SizedTexture, text::LayoutJob, widgets, Align, Color32, Id, Label, Layout, RichText,
-    TextBuffer, TextFormat, Visuals,
+    Sense, Stroke, TextBuffer, TextFormat,
 };
+use egui_plot::{Arrows, HLine, Legend, Line, MarkerShape, Plot, PlotPoints, Points};
 use leafwing_input_manager::input_map::InputMap;
 use motor_math::{solve::reverse::Axis, Movement};
+use networking::PeerAddr;
+use opencv::{imgcodecs, prelude::MatTraitConst};
+use serde::{Deserialize, Serialize};
 use tokio::net::lookup_host;
 
 use crate::{
-    attitude::OrientationDisplay,
-    input::{Action, InputInterpolation, InputMarker, SelectedServo},
+    attitude::{self, AttitudeReadout, AttitudeReferenceFrame, OrientationDisplay},
+    bookmarks::{self, BookmarkDraft, Bookmarks},
+    checklist::{
+        export_checklist, format_timestamp, ChecklistDefinition, ChecklistFile, CHECKLISTS_DIR,
+    },
+    command_palette::{
+        self, AppCommandExt, CommandPaletteSet, CommandRegistry, InvokeCommand, SurfaceCommand,
+    },
+    connection_wizard::{self, ConnectionWizard},
+    current_trim::{self, TrimCapture},
+    depth_mission::{DepthMission, DepthProfile, DepthSegment, MissionOutcome, SegmentPhase},
+    device_profiles::{DeviceAssignments, DeviceProfiles},
+    hud_layout::{self, Corner, HudSettings, WindowLayouts},
+    hud_snapshot::{self, power_mode_label, RobotHudSnapshot},
+    input::{
+        self, Action, ArmControl, AuthorityHolder, InputInterpolation, InputMarker, Nudge,
+        NudgeSetpoint, SelectedServo,
+    },
+    movement_controller::{self, ControllerInput, ControllerSettings, MovementController},
+    pipeline_results::{self, PipelineResultLog},
+    robot_files::{self, DownloadSource, DownloadState, Downloads, RobotFiles},
+    selected_robot::SelectedRobot,
+    theme::Theme,
+    video_fallback,
+    video_overlay::{self, FeedOverlayConfig, OverlayAnchor, OverlayWidget},
     video_pipelines::VideoPipelines,
-    video_stream::{VideoProcessorFactory, VideoThread},
-    DARK_MODE,
+    video_stream::{self, VideoProcessorFactory, VideoThread},
 };
 
 pub struct EguiUiPlugin;
 
 impl Plugin for EguiUiPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, set_style);
+        let panels = match PanelManager::load(PANEL_STATE_PATH) {
+            Ok(panels) => panels,
+            Err(err) => {
+                warn!("Could not restore open panels: {err:?}");
+                PanelManager::default()
+            }
+        };
+
+        app.insert_resource(panels)
+            .init_resource::<PwmControl>()
+            .init_resource::<TimerUi>()
+            .init_resource::<RobotLogs>()
+            .init_resource::<ArmRejectionToast>()
+            .init_resource::<NetErrorToast>()
+            .init_resource::<TypeMismatchToast>()
+            .init_resource::<TetherWarning>()
+            .init_resource::<AboutRobot>()
+            .init_resource::<RobotConfigUi>()
+            .init_resource::<PositionTrack>()
+            .insert_resource(FileTransferDir(STILLS_DIR.into()))
+            .insert_resource(Stills::load(STILLS_DIR));
+
         app.add_plugins(EguiPlugin).add_systems(
             Update,
             (
+                apply_visuals.run_if(resource_changed::<Theme>),
                 topbar,
-                hud.after(topbar),
-                movement_control.after(topbar),
-                pwm_control
+                hud.after(topbar).after(hud_snapshot::update_hud_snapshot),
+                movement_control
                     .after(topbar)
-                    .run_if(resource_exists::<PwmControl>),
-                cleanup_pwm_control
+                    .before(movement_controller::smooth_controllers),
+                collect_robot_logs,
+                collect_stills,
+                collect_arm_rejections,
+                arm_rejection_toast
                     .after(topbar)
-                    .run_if(resource_removed::<PwmControl>()),
-                timer.after(topbar).run_if(resource_exists::<TimerUi>),
+                    .after(collect_arm_rejections),
+                collect_net_errors,
+                net_error_toast.after(topbar).after(collect_net_errors),
+                (
+                    collect_type_mismatches,
+                    type_mismatch_toast
+                        .after(topbar)
+                        .after(collect_type_mismatches),
+                ),
+                apply_panel_transitions.after(topbar),
+                about_robot.after(topbar),
+                crash_report_dialog.after(topbar),
+                motor_data_warning.after(topbar),
+                pwm_health_warning.after(topbar),
+                collect_config_results,
+                collect_position_track,
+                // Panels, kept apart since bevy only takes so many systems in one tuple
+                (
+                    pwm_control.run_if(panel_open(Panel::PwmControl)),
+                    timer.run_if(panel_open(Panel::Timer)),
+                    thruster_view.run_if(panel_open(Panel::Thrusters)),
+                    quarantine_view.run_if(panel_open(Panel::Quarantine)),
+                    servo_view.run_if(panel_open(Panel::Servos)),
+                    robot_logs
+                        .after(collect_robot_logs)
+                        .run_if(panel_open(Panel::RobotLogs)),
+                    contributions_view.run_if(panel_open(Panel::Contributions)),
+                    sync_stats_view.run_if(panel_open(Panel::SyncStats)),
+                    depth_profile_view.run_if(panel_open(Panel::DepthProfile)),
+                    controllers_view.run_if(panel_open(Panel::Controllers)),
+                    depth_mission_view.run_if(panel_open(Panel::DepthMission)),
+                    center_of_mass_view.run_if(panel_open(Panel::CenterOfMass)),
+                    checklist_view.run_if(panel_open(Panel::Checklist)),
+                    robot_config_view
+                        .after(collect_config_results)
+                        .run_if(panel_open(Panel::RobotConfig)),
+                    stills_view
+                        .after(collect_stills)
+                        .run_if(panel_open(Panel::Stills)),
+                    feed_overlay_view.run_if(panel_open(Panel::FeedOverlay)),
+                    position_view
+                        .after(collect_position_track)
+                        .run_if(panel_open(Panel::Position)),
+                    robot_files_view.run_if(panel_open(Panel::RobotFiles)),
+                    current_trim_view.run_if(panel_open(Panel::CurrentTrim)),
+                )
+                    .after(topbar),
             ),
         );
+
+        app.configure_sets(Update, CommandPaletteSet.after(topbar));
+
+        app.add_command(
+            SurfaceCommand::new("About Robot", |world| {
+                world.resource_mut::<AboutRobot>().open = true;
+            })
+            .in_menu("File"),
+        )
+        .add_command(
+            SurfaceCommand::new("Exit", |world| {
+                world.send_event(AppExit);
+            })
+            .in_menu("File")
+            .with_confirmation("The robot holds its thrusters at neutral once heartbeats stop."),
+        )
+        .add_command(
+            SurfaceCommand::acked_event::<CalibrateSeaLevel>(AckedCommand::CalibrateSeaLevel)
+                .in_menu("Sensors"),
+        )
+        .add_command(SurfaceCommand::scoped_event::<ResetServos>("Reset Servos").in_menu("Sensors"))
+        .add_command(
+            SurfaceCommand::acked_event::<ResetYaw>(AckedCommand::ResetYaw).in_menu("Sensors"),
+        )
+        .add_command(
+            SurfaceCommand::scoped_event::<ResetTetherTurns>("Reset Tether Turns")
+                .in_menu("Sensors"),
+        )
+        .add_command(
+            SurfaceCommand::scoped_event::<ResetPosition>("Reset Position").in_menu("Sensors"),
+        )
+        .add_command(
+            SurfaceCommand::acked_event::<ResyncCameras>(AckedCommand::ResyncCameras)
+                .in_menu("Cameras"),
+        );
+
+        for panel in Panel::ALL {
+            app.add_command(
+                SurfaceCommand::new(panel.name(), move |world| {
+                    world.resource_mut::<PanelManager>().toggle(panel);
+                })
+                .in_menu("View")
+                .in_group("Panels")
+                .checked_if(move |world| world.resource::<PanelManager>().is_open(panel)),
+            );
+        }
     }
 }
 
-#[derive(Resource)]
-pub struct ShowInspector;
+/// Where the set of open panels is kept between runs
+pub const PANEL_STATE_PATH: &str = "surface_panels.toml";
+/// Where stills received from the robot are saved
+pub const STILLS_DIR: &str = "stills";
+/// Where exported depth profiles are saved
+pub const PROFILES_DIR: &str = "profiles";
+/// Where configs downloaded from the robot are saved
+pub const ROBOT_CONFIGS_DIR: &str = "robot_configs";
 
-#[derive(Resource)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Panel {
+    Inspector,
+    PwmControl,
+    Timer,
+    Thrusters,
+    Quarantine,
+    RobotLogs,
+    Servos,
+    Stills,
+    Contributions,
+    SyncStats,
+    DepthProfile,
+    Controllers,
+    DepthMission,
+    CenterOfMass,
+    Checklist,
+    RobotConfig,
+    FeedOverlay,
+    Position,
+    RobotFiles,
+    CurrentTrim,
+}
+
+impl Panel {
+    pub const ALL: [Panel; 20] = [
+        Panel::Inspector,
+        Panel::PwmControl,
+        Panel::Timer,
+        Panel::Thrusters,
+        Panel::Quarantine,
+        Panel::RobotLogs,
+        Panel::Servos,
+        Panel::Stills,
+        Panel::Contributions,
+        Panel::SyncStats,
+        Panel::DepthProfile,
+        Panel::Controllers,
+        Panel::DepthMission,
+        Panel::CenterOfMass,
+        Panel::Checklist,
+        Panel::RobotConfig,
+        Panel::FeedOverlay,
+        Panel::Position,
+        Panel::RobotFiles,
+        Panel::CurrentTrim,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Panel::Inspector => "ECS Inspector",
+            Panel::PwmControl => "PWM Control",
+            Panel::Timer => "Timer",
+            Panel::Thrusters => "Thrusters",
+            Panel::Quarantine => "Quarantined Types",
+            Panel::RobotLogs => "Robot Logs",
+            Panel::Servos => "Servos",
+            Panel::Stills => "Stills",
+            Panel::Contributions => "Movement Sources",
+            Panel::SyncStats => "Sync Stats",
+            Panel::DepthProfile => "Depth Profile",
+            Panel::Controllers => "PID Controllers",
+            Panel::DepthMission => "Depth Mission",
+            Panel::CenterOfMass => "Center of Mass Trim",
+            Panel::Checklist => "Mission Checklist",
+            Panel::RobotConfig => "Robot Config",
+            Panel::FeedOverlay => "Feed Overlay",
+            Panel::Position => "Position Estimate",
+            Panel::RobotFiles => "Robot Files",
+            Panel::CurrentTrim => "Current Trim",
+        }
+    }
+
+    /// Resets any state owned by the panel
+    fn on_open(&self, world: &mut World) {
+        match self {
+            Panel::PwmControl => world.insert_resource(PwmControl::default()),
+            Panel::Timer => world.insert_resource(TimerUi::default()),
+            Panel::Inspector
+            | Panel::Thrusters
+            | Panel::Quarantine
+            | Panel::RobotLogs
+            | Panel::Servos
+            | Panel::Stills
+            | Panel::Contributions
+            | Panel::SyncStats
+            | Panel::DepthProfile
+            | Panel::Controllers
+            | Panel::DepthMission
+            | Panel::CenterOfMass
+            | Panel::Checklist
+            | Panel::RobotConfig
+            | Panel::FeedOverlay
+            | Panel::Position
+            | Panel::RobotFiles
+            | Panel::CurrentTrim => {}
+        }
+    }
+
+    /// Undoes any side effects the panel had on the robot
+    fn on_close(&self, world: &mut World) {
+        match self {
+            Panel::PwmControl => {
+                info!("Disabled manual control");
+
+                let robots: Vec<Entity> = world
+                    .query_filtered::<Entity, With<Robot>>()
+                    .iter(world)
+                    .collect();
+
+                for robot in robots {
+                    world.entity_mut(robot).remove::<PwmManualControl>();
+                }
+            }
+            Panel::Inspector
+            | Panel::Timer
+            | Panel::Thrusters
+            | Panel::Quarantine
+            | Panel::RobotLogs
+            | Panel::Servos
+            | Panel::Stills
+            | Panel::Contributions
+            | Panel::SyncStats
+            | Panel::DepthProfile
+            | Panel::Controllers
+            | Panel::DepthMission
+            | Panel::CenterOfMass
+            | Panel::Checklist
+            | Panel::RobotConfig
+            | Panel::FeedOverlay
+            | Panel::Position
+            | Panel::RobotFiles
+            | Panel::CurrentTrim => {}
+        }
+    }
+}
+
+/// Tracks which UI panels are open
+#[derive(Resource, Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct PanelManager {
+    open: BTreeSet<Panel>,
+}
+
+impl PanelManager {
+    pub fn is_open(&self, panel: Panel) -> bool {
+        self.open.contains(&panel)
+    }
+
+    pub fn open(&mut self, panel: Panel) {
+        self.open.insert(panel);
+    }
+
+    pub fn close(&mut self, panel: Panel) {
+        self.open.remove(&panel);
+    }
+
+    pub fn toggle(&mut self, panel: Panel) {
+        if !self.open.remove(&panel) {
+            self.open.insert(panel);
+        }
+    }
+
+    pub fn open_panels(&self) -> impl Iterator<Item = Panel> + '_ {
+        self.open.iter().copied()
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let state = fs::read_to_string(path).context("Read panel state")?;
+        toml::from_str(&state).context("Parse panel state")
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let state = toml::to_string(self).context("Serialize panel state")?;
+        fs::write(path, state).context("Write panel state")
+    }
+}
+
+pub fn panel_open(panel: Panel) -> impl FnMut(Res<PanelManager>) -> bool + Clone {
+    move |panels: Res<PanelManager>| panels.is_open(panel)
+}
+
+#[derive(Resource, Default)]
 pub struct PwmControl(bool);
 
 #[derive(Resource)]
 pub struct TimerUi(TimerState, TimerType);
 
+/// Net tether turns past which the HUD warns the pilot to unwind
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct TetherWarning {
+    pub warn_turns: f32,
+}
+
+impl Default for TetherWarning {
+    fn default() -> Self {
+        Self { warn_turns: 2.0 }
+    }
+}
+
+/// Records kept in the robot log scrollback
+const ROBOT_LOG_SCROLLBACK: usize = 500;
+
+#[derive(Resource)]
+pub struct RobotLogs {
+    records: VecDeque<RemoteLogRecord>,
+    dropped: u64,
+
+    /// Records are still collected while paused but not shown
+    paused: Option<usize>,
+    max_level: LogLevel,
+}
+
+impl Default for RobotLogs {
+    fn default() -> Self {
+        Self {
+            records: VecDeque::new(),
+            dropped: 0,
+            paused: None,
+            max_level: LogLevel::Warn,
+        }
+    }
+}
+
+/// Points kept in the position track
+const POSITION_TRACK_LEN: usize = 2000;
+/// Meters the estimate has to move before another point is added to the track
+const POSITION_TRACK_SPACING: f32 = 0.05;
+
+/// Where the selected robot's [`PositionEstimate`] has been since it was last reset, oldest first
+#[derive(Resource, Default)]
+pub struct PositionTrack {
+    robot: Option<RobotId>,
+    points: VecDeque<Vec2>,
+    last_confidence: Option<f32>,
+}
+
+impl PositionTrack {
+    pub fn record(&mut self, robot: RobotId, estimate: &PositionEstimate) {
+        // Another robot's track has nothing to do with this one
+        if self.robot != Some(robot) {
+            self.robot = Some(robot);
+            self.points.clear();
+            self.last_confidence = None;
+        }
+
+        // Confidence only goes back up on a reset, the old track is relative to another origin
+        if self
+            .last_confidence
+            .is_some_and(|last| estimate.confidence_decay > last)
+        {
+            self.points.clear();
+        }
+        self.last_confidence = Some(estimate.confidence_decay);
+
+        if self
+            .points
+            .back()
+            .is_some_and(|last| last.distance(estimate.xy) < POSITION_TRACK_SPACING)
+        {
+            return;
+        }
+
+        if self.points.len() >= POSITION_TRACK_LEN {
+            self.points.pop_front();
+        }
+        self.points.push_back(estimate.xy);
+    }
+
+    pub fn points(&self) -> impl Iterator<Item = Vec2> + '_ {
+        self.points.iter().copied()
+    }
+}
+
+/// Stills received from the robot, oldest first
+#[derive(Resource, Default)]
+pub struct Stills {
+    files: Vec<PathBuf>,
+    selected: Option<PathBuf>,
+
+    /// The selected still, loaded for display
+    shown: Option<ShownStill>,
+}
+
+struct ShownStill {
+    path: PathBuf,
+    handle: Handle<Image>,
+    texture: egui::TextureId,
+    size: egui::Vec2,
+}
+
+impl Stills {
+    /// Lists the stills saved by previous runs
+    pub fn load(dir: impl AsRef<Path>) -> Self {
+        let files = match list_stills(dir.as_ref()) {
+            Ok(files) => files,
+            Err(err) => {
+                warn!("Could not list stills: {err:?}");
+                Vec::new()
+            }
+        };
+
+        Self { files, ..default() }
+    }
+}
+
+fn list_stills(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    // Partial transfers end in `.part` and are left out
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .context("Read stills dir")?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|it| it == "jpg"))
+        .collect();
+    files.sort_by_key(|path| fs::metadata(path).and_then(|it| it.modified()).ok());
+
+    Ok(files)
+}
+
+fn load_still(path: &Path) -> anyhow::Result<Image> {
+    let mat = imgcodecs::imread_def(&path.to_string_lossy()).context("Read still")?;
+    if mat.empty() {
+        anyhow::bail!("Could not decode {}", path.display());
+    }
+
+    let mut image = Image::default();
+    video_stream::mat_to_image(&mat, &mut image).context("Mat to image")?;
+
+    Ok(image)
+}
+
+impl TimerUi {
+    /// Time left on the timer at `now`
+    pub fn remaining(&self, now: Duration) -> Duration {
+        let elapsed = match self.0 {
+            TimerState::Running { start, offset } => now.saturating_sub(start) + offset,
+            TimerState::Paused { elapsed } => elapsed,
+        };
+
+        self.1.duration().saturating_sub(elapsed)
+    }
+
+    /// Whether the timer has been started since it was last reset
+    pub fn started(&self) -> bool {
+        !matches!(self.0, TimerState::Paused { elapsed } if elapsed.is_zero())
+    }
+}
+
+impl Default for TimerUi {
+    fn default() -> Self {
+        Self(
+            TimerState::Paused {
+                elapsed: Duration::ZERO,
+            },
+            TimerType::Setup,
+        )
+    }
+}
+
 pub enum TimerState {
     Running { start: Duration, offset: Duration },
     Paused { elapsed: Duration },
@@ -74,15 +615,18 @@ pub enum TimerType {
     Cleanup,
 }
 
-#[derive(Component)]
-pub struct MovementController;
+impl TimerType {
+    pub fn duration(&self) -> Duration {
+        match self {
+            TimerType::Setup => Duration::from_secs(5 * 60),
+            TimerType::Run => Duration::from_secs(15 * 60),
+            TimerType::Cleanup => Duration::from_secs(5 * 60),
+        }
+    }
+}
 
-fn set_style(mut contexts: EguiContexts) {
-    contexts.ctx_mut().set_visuals(if DARK_MODE {
-        Visuals::dark()
-    } else {
-        Visuals::light()
-    });
+fn apply_visuals(mut contexts: EguiContexts, theme: Res<Theme>) {
+    contexts.ctx_mut().set_visuals(theme.visuals());
 }
 
 fn topbar(
@@ -95,36 +639,54 @@ fn topbar(
             &RobotStatus,
             Option<&DepthTarget>,
             Option<&OrientationTarget>,
+            Option<&CameraManagerState>,
         ),
         With<Robot>,
     >,
 
     cameras: Query<
-        (Entity, &Name, Option<&VideoProcessorFactory>),
-        (With<Camera>, With<VideoThread>),
+        (
+            Entity,
+            &Name,
+            &Camera,
+            Option<&RobotId>,
+            Option<&VideoProcessorFactory>,
+        ),
+        With<VideoThread>,
     >,
-    pipelines: Res<VideoPipelines>,
+    (pipelines, mut results_log, fallback_requests): (
+        Res<VideoPipelines>,
+        ResMut<PipelineResultLog>,
+        Query<(Entity, &CameraFallbackStream, &RobotId)>,
+    ),
+
+    lights: Query<(Entity, &Name, &LedMode, Option<&LedBrightness>), With<Robot>>,
 
-    inspector: Option<Res<ShowInspector>>,
-    pwm_control: Option<Res<PwmControl>>,
-    timer_ui: Option<Res<TimerUi>>,
+    behaviors: Query<(&Name, &AvailableBehaviors), With<Robot>>,
+    running_behaviors: Query<&RunningBehavior>,
+
+    gamepads: Res<Gamepads>,
+    profiles: Res<DeviceProfiles>,
+    mut assignments: ResMut<DeviceAssignments>,
 
     peers: Query<(&Peer, Option<&Name>)>,
     mut disconnect: EventWriter<DisconnectPeer>,
+
+    registry: Res<CommandRegistry>,
+    mut invoke: EventWriter<InvokeCommand>,
+
+    theme: Res<Theme>,
 ) {
+    let palette = theme.palette;
+    let text_color = palette.text;
+
     egui::TopBottomPanel::top("Top Bar").show(contexts.ctx_mut(), |ui| {
         egui::menu::bar(ui, |ui| {
-            ui.menu_button("File", |ui| {
+            hud_layout::menu_button(ui, "File", |ui| {
                 ui.menu_button("Disconnect", |ui| {
                     if !peers.is_empty() {
                         for (peer, name) in &peers {
-                            let text = if let Some(name) = name {
-                                format!("{} ({})", name.as_str(), peer.token.0)
-                            } else {
-                                format!("{} ({})", peer.addrs, peer.token.0)
-                            };
-
-                            if ui.button(text).clicked() {
+                            if ui.button(peer.describe(name)).clicked() {
                                 disconnect.send(DisconnectPeer(peer.token));
                             }
                         }
@@ -133,46 +695,52 @@ fn topbar(
                     }
                 });
 
-                if ui.button("Exit").clicked() {
-                    cmds.add(|world: &mut World| {
-                        world.send_event(AppExit);
-                    })
-                }
+                command_palette::menu_items(ui, &registry, "File", &mut invoke);
             });
 
-            ui.menu_button("Sensors", |ui| {
-                if ui.button("Calibrate Sea Level").clicked() {
-                    cmds.add(|world: &mut World| {
-                        world.send_event(CalibrateSeaLevel);
-                    })
-                }
-
-                if ui.button("Reset Servos").clicked() {
-                    cmds.add(|world: &mut World| {
-                        world.send_event(ResetServos);
-                    })
-                }
-
-                if ui.button("Reset Yaw").clicked() {
-                    cmds.add(|world: &mut World| {
-                        world.send_event(ResetYaw);
-                    })
-                }
+            hud_layout::menu_button(ui, "Sensors", |ui| {
+                command_palette::menu_items(ui, &registry, "Sensors", &mut invoke);
             });
 
-            ui.menu_button("Cameras", |ui| {
-                if ui.button("Resync Cameras").clicked() {
-                    cmds.add(|world: &mut World| {
-                        world.send_event(ResyncCameras);
-                    })
+            hud_layout::menu_button(ui, "Cameras", |ui| {
+                for (robot, .., camera_state) in &robots {
+                    match camera_state {
+                        Some(CameraManagerState::Restarting) => {
+                            ui.label(format!("{}: Restarting streams", robot.as_str()));
+                        }
+                        Some(CameraManagerState::Error(err)) => {
+                            ui.label(
+                                RichText::new(format!("{}: {err}", robot.as_str()))
+                                    .color(Color32::RED),
+                            );
+                        }
+                        Some(CameraManagerState::Idle) | None => {}
+                    }
                 }
 
+                command_palette::menu_items(ui, &registry, "Cameras", &mut invoke);
+                pipeline_results::menu(ui, &mut results_log);
+
                 // TODO: Hide/Show All
 
-                for (entity, name, processor) in &cameras {
+                for (entity, name, camera, robot, processor) in &cameras {
                     ui.menu_button(name.as_str(), |ui| {
                         // TODO: Hide/Show
 
+                        if ui.button("Capture Still").clicked() {
+                            let camera = name.as_str().to_owned();
+
+                            cmds.add(|world: &mut World| {
+                                world.send_event(CaptureStill { camera });
+                            })
+                        }
+
+                        if let Some(&robot) = robot {
+                            video_fallback::menu(ui, &mut cmds, &fallback_requests, camera, robot);
+                        }
+
+                        ui.separator();
+
                         let processor_name = processor.map(|it| &it.name);
 
                         for pipeline in &pipelines.0 {
@@ -192,53 +760,88 @@ fn topbar(
                 }
             });
 
-            ui.menu_button("View", |ui| {
-                if ui
-                    .selectable_label(inspector.is_some(), "ECS Inspector")
-                    .clicked()
-                {
-                    if inspector.is_some() {
-                        cmds.remove_resource::<ShowInspector>()
-                    } else {
-                        cmds.insert_resource(ShowInspector);
-                    }
+            hud_layout::menu_button(ui, "Input Devices", |ui| {
+                command_palette::menu_items(ui, &registry, "Input Devices", &mut invoke);
+
+                if gamepads.iter().next().is_none() {
+                    ui.label("No Devices");
                 }
 
-                if ui.button("Movement Controller").clicked() {
-                    cmds.spawn((
-                        MovementController,
-                        MovementContributionBundle {
-                            name: Name::new("Manual Movement Controller"),
-                            contribution: Default::default(),
-                            robot: RobotId(NetId::invalid()),
-                        },
-                        Replicate,
-                    ));
+                for gamepad in gamepads.iter() {
+                    let name = gamepads.name(gamepad).unwrap_or("Unknown Device");
+                    let text = format!("{name} ({})", gamepad.id);
+
+                    ui.menu_button(text, |ui| {
+                        let current = assignments.0.get(&gamepad);
+
+                        if ui
+                            .selectable_label(current.is_none(), "Default Bindings")
+                            .clicked()
+                        {
+                            assignments.0.remove(&gamepad);
+                        }
+
+                        for profile in profiles.0.keys() {
+                            if ui
+                                .selectable_label(current == Some(profile), profile.as_str())
+                                .clicked()
+                            {
+                                assignments.0.insert(gamepad, profile.clone());
+                            }
+                        }
+                    });
                 }
+            });
 
-                if ui
-                    .selectable_label(pwm_control.is_some(), "PWM Control")
-                    .clicked()
-                {
-                    if pwm_control.is_some() {
-                        cmds.remove_resource::<PwmControl>()
-                    } else {
-                        cmds.insert_resource(PwmControl(false));
-                    }
+            hud_layout::menu_button(ui, "Lights", |ui| {
+                if lights.is_empty() {
+                    ui.label("No Robots");
                 }
 
-                if ui.selectable_label(timer_ui.is_some(), "Timer").clicked() {
-                    if timer_ui.is_some() {
-                        cmds.remove_resource::<TimerUi>()
-                    } else {
-                        cmds.insert_resource(TimerUi(
-                            TimerState::Paused {
-                                elapsed: Duration::ZERO,
-                            },
-                            TimerType::Setup,
-                        ));
-                    }
+                for (robot, name, &mode, brightness) in &lights {
+                    ui.menu_button(name.as_str(), |ui| {
+                        lights_menu(ui, &mut cmds, robot, mode, brightness.copied());
+                    });
+                }
+            });
+
+            hud_layout::menu_button(ui, "Autonomy", |ui| {
+                if behaviors.is_empty() {
+                    ui.label("No Robots");
                 }
+
+                for (name, available) in &behaviors {
+                    ui.menu_button(name.as_str(), |ui| {
+                        if available.0.is_empty() {
+                            ui.label("No Behaviors");
+                        }
+
+                        for behavior in &available.0 {
+                            let running = running_behaviors.iter().any(|it| &it.0 == behavior);
+
+                            if ui.selectable_label(running, behavior.as_str()).clicked() {
+                                let name = behavior.clone();
+
+                                if !running {
+                                    cmds.add(|world: &mut World| {
+                                        world.send_event(StartBehavior {
+                                            name,
+                                            params: serde_json::Value::Null,
+                                        });
+                                    })
+                                } else {
+                                    cmds.add(|world: &mut World| {
+                                        world.send_event(StopBehavior { name });
+                                    })
+                                }
+                            }
+                        }
+                    });
+                }
+            });
+
+            hud_layout::menu_button(ui, "View", |ui| {
+                command_palette::menu_items(ui, &registry, "View", &mut invoke);
             });
 
             // RTL needs reverse order
@@ -246,16 +849,12 @@ fn topbar(
                 if !robots.is_empty() {
                     let mut layout_job = LayoutJob::default();
 
-                    for (robot, state, depth_target, orientation_target) in &robots {
+                    for (robot, state, depth_target, orientation_target, camera_state) in &robots {
                         layout_job.append(
                             robot.as_str(),
                             20.0,
                             TextFormat {
-                                color: if DARK_MODE {
-                                    Color32::WHITE
-                                } else {
-                                    Color32::BLACK
-                                },
+                                color: text_color,
                                 ..default()
                             },
                         );
@@ -263,11 +862,7 @@ fn topbar(
                             ":",
                             0.0,
                             TextFormat {
-                                color: if DARK_MODE {
-                                    Color32::WHITE
-                                } else {
-                                    Color32::BLACK
-                                },
+                                color: text_color,
                                 ..default()
                             },
                         );
@@ -278,11 +873,7 @@ fn topbar(
                                     "Unknown",
                                     7.0,
                                     TextFormat {
-                                        color: if DARK_MODE {
-                                            Color32::WHITE
-                                        } else {
-                                            Color32::BLACK
-                                        },
+                                        color: text_color,
                                         ..default()
                                     },
                                 );
@@ -292,7 +883,7 @@ fn topbar(
                                     "Disarmed",
                                     7.0,
                                     TextFormat {
-                                        color: Color32::RED,
+                                        color: palette.armed(Armed::Disarmed),
                                         ..default()
                                     },
                                 );
@@ -302,7 +893,7 @@ fn topbar(
                                     "Armed",
                                     7.0,
                                     TextFormat {
-                                        color: Color32::GREEN,
+                                        color: palette.armed(Armed::Armed),
                                         ..default()
                                     },
                                 );
@@ -312,7 +903,7 @@ fn topbar(
                                         "Orientation Hold",
                                         7.0,
                                         TextFormat {
-                                            color: Color32::from_rgb(66, 145, 247),
+                                            color: palette.orientation_hold,
                                             ..default()
                                         },
                                     );
@@ -323,28 +914,128 @@ fn topbar(
                                         "Depth Hold",
                                         7.0,
                                         TextFormat {
-                                            color: Color32::from_rgb(216, 123, 2),
+                                            color: palette.depth_hold,
                                             ..default()
                                         },
                                     );
                                 }
                             }
                         };
+
+                        match camera_state {
+                            Some(CameraManagerState::Restarting) => {
+                                layout_job.append(
+                                    "Cameras Restarting",
+                                    7.0,
+                                    TextFormat {
+                                        color: palette.warning,
+                                        ..default()
+                                    },
+                                );
+                            }
+                            Some(CameraManagerState::Error(_)) => {
+                                layout_job.append(
+                                    "Cameras Failed",
+                                    7.0,
+                                    TextFormat {
+                                        color: palette.bad,
+                                        ..default()
+                                    },
+                                );
+                            }
+                            Some(CameraManagerState::Idle) | None => {}
+                        }
                     }
 
                     ui.label(layout_job);
                 } else {
-                    ui.label(RichText::new(format!("No Robot")).color(if DARK_MODE {
-                        Color32::WHITE
-                    } else {
-                        Color32::BLACK
-                    }));
+                    ui.label(RichText::new(format!("No Robot")).color(text_color));
                 }
             })
         });
     });
 }
 
+const DEFAULT_LIGHT_COLOR: Rgb8 = Rgb8::new(255, 255, 255);
+const DEFAULT_STROBE_PERIOD: Duration = Duration::from_millis(500);
+
+fn lights_menu(
+    ui: &mut egui::Ui,
+    cmds: &mut Commands,
+    robot: Entity,
+    mode: LedMode,
+    brightness: Option<LedBrightness>,
+) {
+    let (color, period) = match mode {
+        LedMode::Solid(color) => (color, DEFAULT_STROBE_PERIOD),
+        LedMode::Strobe { color, period } => (color, period),
+        _ => (DEFAULT_LIGHT_COLOR, DEFAULT_STROBE_PERIOD),
+    };
+
+    let modes = [
+        ("Status", LedMode::Status),
+        ("Thrusters", LedMode::ThrusterViz),
+        ("Solid", LedMode::Solid(color)),
+        ("Strobe", LedMode::Strobe { color, period }),
+        ("Rainbow", LedMode::Rainbow),
+        ("Off", LedMode::Off),
+    ];
+
+    let mut new_mode = None;
+
+    for (name, option) in modes {
+        let selected = std::mem::discriminant(&mode) == std::mem::discriminant(&option);
+        if ui.selectable_label(selected, name).clicked() && !selected {
+            new_mode = Some(option);
+        }
+    }
+
+    if let LedMode::Solid(_) | LedMode::Strobe { .. } = mode {
+        ui.separator();
+
+        let mut srgb = [color.r, color.g, color.b];
+        ui.horizontal(|ui| {
+            ui.label("Color");
+            if egui::color_picker::color_edit_button_srgb(ui, &mut srgb).changed() {
+                let color = Rgb8::new(srgb[0], srgb[1], srgb[2]);
+
+                new_mode = Some(match mode {
+                    LedMode::Strobe { period, .. } => LedMode::Strobe { color, period },
+                    _ => LedMode::Solid(color),
+                });
+            }
+        });
+    }
+
+    if let LedMode::Strobe { color, period } = mode {
+        let mut secs = period.as_secs_f32();
+        if ui
+            .add(widgets::Slider::new(&mut secs, 0.1..=2.0).text("Period (s)"))
+            .changed()
+        {
+            new_mode = Some(LedMode::Strobe {
+                color,
+                period: Duration::from_secs_f32(secs),
+            });
+        }
+    }
+
+    ui.separator();
+
+    let mut level = brightness.map(|it| it.0).unwrap_or(0.5);
+    if ui
+        .add(widgets::Slider::new(&mut level, 0.0..=1.0).text("Brightness"))
+        .changed()
+    {
+        cmds.entity(robot)
+            .insert(LedBrightness(level.clamp(0.0, 1.0)));
+    }
+
+    if let Some(mode) = new_mode {
+        cmds.entity(robot).insert(mode);
+    }
+}
+
 fn hud(
     mut cmds: Commands,
 
@@ -352,27 +1043,18 @@ fn hud(
     runtime: ResMut<TokioTasksRuntime>,
 
     mut contexts: EguiContexts,
-    attitude: Option<Res<OrientationDisplay>>,
-    robots: Query<
-        (
-            &Name,
-            Option<&Armed>,
-            Option<&MeasuredVoltage>,
-            Option<&CurrentDraw>,
-            Option<&CpuTotal>,
-            Option<&Inertial>,
-            Option<&LoadAverage>,
-            Option<&Memory>,
-            Option<&Temperatures>,
-            Option<&Depth>,
-            Option<&DepthTarget>,
-            Option<&OrientationTarget>,
-            Option<&Peer>,
-            Option<&Latency>,
-            &RobotId,
-        ),
-        With<Robot>,
-    >,
+    (attitude, attitude_readout, mut attitude_frame, theme): (
+        Option<Res<OrientationDisplay>>,
+        Res<AttitudeReadout>,
+        ResMut<AttitudeReferenceFrame>,
+        Res<Theme>,
+    ),
+    snapshot: Res<RobotHudSnapshot>,
+
+    (control_gated, mut arm_control): (Query<(&ControlGated, &RobotId), With<Robot>>, ArmControl),
+    tether: Query<(&TetherTurns, &RobotId), With<Robot>>,
+    tether_warning: Res<TetherWarning>,
+    current_trim: Query<(&CurrentTrim, &RobotId), With<Robot>>,
 
     inputs: Query<
         (
@@ -387,48 +1069,58 @@ fn hud(
     peers: Option<Res<MdnsPeers>>,
 
     mut disconnect: EventWriter<DisconnectPeer>,
+
+    mut layouts: ResMut<WindowLayouts>,
+    hud_settings: Res<HudSettings>,
+    (wizard, bookmarks, profiles, mut draft): (
+        Res<ConnectionWizard>,
+        Res<Bookmarks>,
+        Res<DeviceProfiles>,
+        Local<BookmarkDraft>,
+    ),
 ) {
     let context = contexts.ctx_mut();
+    let hud_id = Id::new("HUD");
+    let bounds = hud_layout::window_bounds(context);
 
     // TODO(low): Support multiple robots
-    if let Ok((
-        robot_name,
-        armed,
-        voltage,
-        current_draw,
-        cpu,
-        inertial,
-        load,
-        memory,
-        temps,
-        depth,
-        depth_target,
-        orientation_target,
-        peer,
-        latency,
-        robot_id,
-    )) = robots.get_single()
-    {
+    if let Some(robot) = &snapshot.robot {
+        let robot_id = &robot.robot_id;
         let mut open = true;
 
-        let window = egui::Window::new(robot_name.as_str())
-            .id("HUD".into())
-            .default_pos(context.screen_rect().right_top())
-            .constrain_to(context.available_rect().shrink(20.0));
+        let window = layouts.place(
+            egui::Window::new(robot.name.as_str()),
+            hud_id,
+            Corner::TopRight,
+            bounds,
+        );
         // .movable(false);
 
-        let window = if let Some(_peer) = peer {
+        let window = if let Some(_peer) = &robot.peer {
             window.open(&mut open)
         } else {
             window
         };
 
-        window.show(context, |ui| {
+        let attitude_size = hud_layout::attitude_size(hud_settings.attitude_size, bounds);
+
+        let response = window.show(context, |ui| {
             let size = 20.0;
 
             ui.horizontal(|ui| {
                 if let Some(attitude) = attitude {
-                    ui.image(SizedTexture::new(attitude.1, (230.0, 230.0)));
+                    ui.vertical(|ui| {
+                        ui.image(SizedTexture::new(
+                            attitude.1,
+                            (attitude_size, attitude_size),
+                        ));
+                        attitude::readout_ui(
+                            ui,
+                            &attitude_readout,
+                            &mut attitude_frame,
+                            &theme.palette,
+                        );
+                    });
 
                     ui.add_space(10.0);
                 }
@@ -436,24 +1128,49 @@ fn hud(
                 ui.vertical(|ui| {
                     ui.allocate_space((230.0, 0.0).into());
 
-                    if let Some(armed) = armed {
+                    if let Some(status) = &robot.status {
                         ui.horizontal(|ui| {
                             ui.label(RichText::new("Status:").size(size));
-                            match armed {
-                                Armed::Armed => {
-                                    ui.label(
-                                        RichText::new("Armed").size(size).color(Color32::GREEN),
-                                    );
-                                }
-                                Armed::Disarmed => {
-                                    ui.label(
-                                        RichText::new("Disarmed").size(size).color(Color32::RED),
-                                    );
-                                }
+                            ui.label(status.rich(size));
+
+                            let gated = control_gated
+                                .iter()
+                                .any(|(gated, id)| gated.0 && id == robot_id);
+                            if gated {
+                                ui.label(
+                                    RichText::new("Stale Control")
+                                        .size(size)
+                                        .color(Color32::YELLOW),
+                                );
                             }
                         });
                     }
 
+                    ui.horizontal(|ui| {
+                        ui.label(RichText::new("Authority:").size(size));
+
+                        let holder = arm_control.holder(*robot_id);
+                        let label = match &holder {
+                            AuthorityHolder::Nobody => RichText::new("None").weak(),
+                            AuthorityHolder::ThisSurface => {
+                                RichText::new("This Station").color(Color32::GREEN)
+                            }
+                            AuthorityHolder::Other(name) => {
+                                RichText::new(name.as_deref().unwrap_or("Other Station"))
+                                    .color(Color32::YELLOW)
+                            }
+                        };
+                        ui.label(label.size(size));
+
+                        if holder == AuthorityHolder::ThisSurface {
+                            if ui.button("Release").clicked() {
+                                arm_control.release_authority();
+                            }
+                        } else if ui.button("Request").clicked() {
+                            arm_control.request_authority();
+                        }
+                    });
+
                     if let Some((selected_servo, input_interpolation, input_map, _)) =
                         inputs.iter().find(|(_, _, _, robot)| **robot == *robot_id)
                     {
@@ -497,7 +1214,7 @@ fn hud(
                             ui.label(RichText::new("Servo:").size(size));
                             if let Some(selected_servo) = &selected_servo.servo {
                                 ui.label(
-                                    RichText::new(selected_servo.clone())
+                                    RichText::new(selected_servo.to_string())
                                         .size(size)
                                         .color(Color32::GREEN),
                                 );
@@ -509,62 +1226,55 @@ fn hud(
 
                     ui.add_space(10.0);
 
-                    if let (Some(voltage), Some(current)) = (voltage, current_draw) {
+                    if let Some([voltage, current]) = &robot.power {
                         ui.horizontal(|ui| {
                             ui.label(RichText::new("Power:").size(size));
+                            ui.label(voltage.rich(size));
+                            ui.label(current.rich(size));
+                        });
 
-                            let voltage_color;
-                            if voltage.0 .0 < 11.5 {
-                                voltage_color = Color32::RED;
-                            } else if voltage.0 .0 < 12.5 {
-                                voltage_color = Color32::YELLOW;
-                            } else {
-                                voltage_color = Color32::GREEN;
-                            }
+                        ui.add_space(10.0);
+                    }
 
-                            let current_color;
-                            if current.0 .0 < 15.0 {
-                                current_color = Color32::GREEN;
-                            } else if current.0 .0 < 20.0 {
-                                current_color = Color32::YELLOW;
-                            } else {
-                                current_color = Color32::RED;
-                            }
+                    if let Some(power_mode) = &robot.power_mode {
+                        ui.horizontal(|ui| {
+                            ui.label(RichText::new("Power Mode:").size(size));
+                            ui.label(power_mode.rich(size));
+                        });
 
-                            ui.label(
-                                RichText::new(format!("{}", voltage.0))
-                                    .size(size)
-                                    .color(voltage_color),
-                            );
-                            ui.label(
-                                RichText::new(format!("{}", current.0))
-                                    .size(size)
-                                    .color(current_color),
-                            );
+                        ui.horizontal(|ui| {
+                            ui.label("Set by:");
+
+                            let mut selected = robot.power_override;
+                            egui::ComboBox::from_id_source("Power Mode Override")
+                                .selected_text(selected.map_or("Battery", power_mode_label))
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut selected, None, "Battery");
+
+                                    for mode in [
+                                        PowerMode::Normal,
+                                        PowerMode::LowPower,
+                                        PowerMode::Critical,
+                                    ] {
+                                        let label = power_mode_label(mode);
+                                        ui.selectable_value(&mut selected, Some(mode), label);
+                                    }
+                                });
+
+                            if selected != robot.power_override {
+                                cmds.entity(robot.entity)
+                                    .insert(PowerModeOverride(selected));
+                            }
                         });
 
                         ui.add_space(10.0);
                     }
 
-                    if let Some(cpu) = cpu {
-                        ui.label(RichText::new(format!("CPU: {:.2}%", cpu.0.usage)).size(size));
-                    }
-                    if let Some(load) = load {
-                        ui.label(
-                            RichText::new(format!(
-                                "Load: {:.2}, {:.2}, {:.2}",
-                                load.one_min, load.five_min, load.fifteen_min
-                            ))
-                            .size(size),
-                        );
-                    }
-
-                    if let Some(memory) = memory {
-                        let ram_usage = memory.used_mem as f64 / memory.total_mem as f64 * 100.0;
-                        ui.label(RichText::new(format!("RAM: {:.2}%", ram_usage)).size(size));
+                    for line in &robot.system {
+                        ui.label(line.rich(size));
                     }
 
-                    if cpu.is_some() || load.is_some() || memory.is_some() {
+                    if !robot.system.is_empty() {
                         ui.add_space(10.0);
                     }
                 });
@@ -572,152 +1282,242 @@ fn hud(
                 ui.vertical(|ui| {
                     ui.allocate_space((230.0, 0.0).into());
 
-                    if let (Some(peer), Some(latency)) = (peer, latency) {
+                    if let Some(peer) = &robot.peer {
                         ui.horizontal(|ui| {
-                            ui.label(RichText::new("Address:").size(size));
-                            ui.label(RichText::new(format!("{:?}", peer.addrs)).size(size * 0.75));
+                            ui.label(RichText::new("Peer:").size(size));
+                            ui.label(RichText::new(robot.name.as_str()).size(size));
+                            ui.label(RichText::new(peer.addrs.as_str()).size(size * 0.75));
                         });
 
-                        if let Some(ping) = latency.ping {
-                            ui.label(
-                                RichText::new(format!("Ping: {:.2?} frames", ping)).size(size),
-                            );
+                        if let Some(ping) = &peer.ping {
+                            ui.label(ping.rich(size));
+                        }
+
+                        if let Some(frame_time) = &peer.frame_time {
+                            ui.label(frame_time.rich(size));
                         }
 
                         ui.add_space(10.0);
                     }
 
-                    if let Some(inertial) = inertial {
-                        ui.label(
-                            RichText::new(format!("IMU Temp: {}", inertial.0.tempature)).size(size),
-                        );
+                    for line in &robot.temperatures {
+                        ui.label(line.rich(size));
                     }
 
-                    if let Some(temps) = temps {
-                        for temp in &temps.0 {
-                            ui.label(
-                                RichText::new(format!("{}: {}", temp.name, temp.tempature))
-                                    .size(size),
-                            );
-                        }
+                    if !robot.temperatures.is_empty() {
+                        ui.add_space(10.0);
                     }
 
-                    if let Some(depth) = depth {
-                        ui.label(
-                            RichText::new(format!("Water Temp: {}", depth.0.temperature))
-                                .size(size),
-                        );
-                    }
+                    if let Some(depth) = &robot.depth {
+                        ui.label(depth.rich(size));
+
+                        if let Some(depth_target) = &robot.depth_target {
+                            ui.horizontal(|ui| {
+                                ui.label(depth_target.rich(size));
+
+                                // Depth is positive down
+                                nudge_buttons(ui, &mut cmds, *robot_id, Nudge::Depth);
+                            });
+                        }
 
-                    if inertial.is_some() || temps.is_some() {
                         ui.add_space(10.0);
                     }
 
-                    if let Some(depth) = depth {
-                        ui.label(RichText::new(format!("Depth: {}", depth.0.depth)).size(size));
+                    let turns = tether
+                        .iter()
+                        .find(|(_, id)| *id == robot_id)
+                        .map(|(turns, _)| turns.0);
+                    if let Some(turns) = turns {
+                        ui.horizontal(|ui| {
+                            let text =
+                                RichText::new(format!("Tether: {turns:+.2} turns")).size(size);
+                            if turns.abs() > tether_warning.warn_turns {
+                                ui.label(text.color(Color32::RED));
+                            } else {
+                                ui.label(text);
+                            }
+
+                            if ui.button("Reset").clicked() {
+                                let robot = *robot_id;
+                                cmds.add(move |world: &mut World| {
+                                    world.send_event(Scoped::new(robot, ResetTetherTurns));
+                                });
+                            }
+                        });
+
+                        ui.add_space(10.0);
+                    }
 
-                        if let Some(depth_target) = depth_target {
+                    let trim = current_trim
+                        .iter()
+                        .find(|(trim, id)| *id == robot_id && trim.0 != Vec3A::ZERO)
+                        .map(|(trim, _)| trim.0);
+                    if let Some(trim) = trim {
+                        ui.horizontal(|ui| {
                             ui.label(
-                                RichText::new(format!("Depth Target: {}", depth_target.0))
-                                    .size(size),
+                                RichText::new(format!("Current Trim: {:.1}N", trim.length()))
+                                    .size(size)
+                                    .color(Color32::GOLD),
                             );
-                        }
+
+                            if ui.button("Clear").clicked() {
+                                let robot = *robot_id;
+                                cmds.add(move |world: &mut World| {
+                                    world.run_system_once_with(robot, current_trim::clear);
+                                });
+                            }
+                        });
 
                         ui.add_space(10.0);
                     }
 
-                    if let Some(_orientation_target) = orientation_target {
-                        ui.label(RichText::new("Orientation Control").size(size));
+                    if robot.orientation_control {
+                        ui.horizontal(|ui| {
+                            ui.label(RichText::new("Orientation Control").size(size));
+
+                            // Heading reads like a compass, + turns right which is a negative
+                            // Nudge::Heading
+                            nudge_buttons(ui, &mut cmds, *robot_id, Nudge::Heading);
+                        });
                     }
                 });
 
                 ui.allocate_space((0.0, 0.0).into());
             });
         });
+        layouts.record(hud_id, &response, bounds);
 
-        if let Some(peer) = peer {
+        if let Some(peer) = &robot.peer {
             if !open {
                 disconnect.send(DisconnectPeer(peer.token));
             }
         }
     } else {
-        egui::Window::new("Not Connected")
-            .id("HUD".into())
-            .default_pos(context.screen_rect().right_top())
-            .constrain_to(context.available_rect().shrink(20.0))
-            // .movable(false)
-            .show(contexts.ctx_mut(), |ui| {
-                ui.horizontal(|ui| {
-                    ui.label("Connect To:");
-                    let line_response = ui.text_edit_singleline(&mut *host);
-                    let button_response = ui.button("Connect");
-
-                    if line_response.lost_focus() || button_response.clicked() {
-                        let host = host.clone();
-                        runtime.spawn_background_task(|mut ctx| async move {
-                            let resolve = lookup_host(host).await;
-                            let addrs = resolve.ok().and_then(|mut it| it.next());
-
-                            if let Some(addrs) = addrs {
-                                ctx.run_on_main_thread(move |ctx| {
-                                    let world = ctx.world;
-                                    let count = world.query::<&Robot>().iter(world).count();
-
-                                    if count == 0 {
-                                        info!("Peer ip resolved to {:?}", addrs);
-                                        world.send_event(ConnectToPeer(addrs));
-                                    } else {
-                                        warn!("Already connected to peer");
-                                    }
-                                })
-                                .await;
-                            } else {
-                                error!("Could not resolve host");
-                            }
-                        });
+        let window = layouts.place(
+            egui::Window::new("Not Connected"),
+            hud_id,
+            Corner::TopRight,
+            bounds,
+        );
+
+        // .movable(false)
+        let response = window.show(contexts.ctx_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Connect To:");
+                let line_response = ui.text_edit_singleline(&mut *host);
+                let button_response = ui.button("Connect");
+
+                if line_response.lost_focus() || button_response.clicked() {
+                    if let Ok(addrs @ PeerAddr::Unix(_)) = host.parse::<PeerAddr>() {
+                        // Local sockets need no resolving
+                        cmds.add(move |world: &mut World| connect_if_idle(world, addrs));
+                    } else {
+                        connect_host(&runtime, host.clone());
                     }
-                });
+                }
+
+                if ui.button("Diagnose").clicked() {
+                    let target = host.clone();
+                    cmds.add(move |world: &mut World| connection_wizard::start(world, target));
+                }
+            });
 
-                if let Some(peers) = peers {
-                    let peers = &peers.0;
+            connection_wizard::draw(ui, &wizard, &mut cmds);
 
-                    if !peers.is_empty() {
-                        ui.add_space(15.0);
+            ui.add_space(15.0);
+            bookmarks::draw(ui, &bookmarks, &profiles, &mut draft, &mut cmds);
+
+            if let Some(peers) = peers {
+                let peers = &peers.0;
 
-                        ui.heading("Peers:");
+                if !peers.is_empty() {
+                    ui.add_space(15.0);
 
-                        for peer in peers.values() {
-                            let name = peer
-                                .info
-                                .get_fullname()
-                                .split('.')
-                                .next()
-                                .unwrap_or("Unknown");
-                            let host = peer.info.get_hostname();
+                    ui.heading("Peers:");
 
+                    for peer in peers.values() {
+                        let name = peer
+                            .info
+                            .get_fullname()
+                            .split('.')
+                            .next()
+                            .unwrap_or("Unknown");
+                        let host = peer.info.get_hostname();
+
+                        ui.horizontal(|ui| {
                             ui.label(format!("{}@{}local", name, host));
 
-                            ui.indent(peer.info.get_fullname(), |ui| {
-                                for addrs in &peer.addresses {
-                                    let addrs = *addrs;
+                            let addrs = peer.addresses.first().copied();
+                            bookmarks::bookmark_button(ui, &bookmarks, name, addrs, &mut cmds);
+                        });
 
-                                    if ui.button(format!("{}", addrs.ip())).clicked() {
-                                        cmds.add(move |world: &mut World| {
-                                            world.send_event(ConnectToPeer(addrs));
-                                        });
-                                    }
+                        ui.indent(peer.info.get_fullname(), |ui| {
+                            for addrs in &peer.addresses {
+                                let addrs = *addrs;
+
+                                if ui.button(format!("{}", addrs.ip())).clicked() {
+                                    cmds.add(move |world: &mut World| {
+                                        world.send_event(ConnectToPeer(addrs.into()));
+                                    });
                                 }
-                            });
-                        }
+                            }
+                        });
                     }
                 }
+            }
+        });
+        layouts.record(hud_id, &response, bounds);
+    }
+}
+
+pub(crate) fn connect_if_idle(world: &mut World, addrs: PeerAddr) {
+    let count = world.query::<&Robot>().iter(world).count();
+
+    if count == 0 {
+        world.send_event(ConnectToPeer(addrs));
+    } else {
+        warn!("Already connected to peer");
+    }
+}
+
+/// Resolves `host` in the background then connects to it
+pub(crate) fn connect_host(runtime: &TokioTasksRuntime, host: String) {
+    runtime.spawn_background_task(|mut ctx| async move {
+        let resolve = lookup_host(host).await;
+        let addrs = resolve.ok().and_then(|mut it| it.next());
+
+        if let Some(addrs) = addrs {
+            ctx.run_on_main_thread(move |ctx| {
+                info!("Peer ip resolved to {:?}", addrs);
+                connect_if_idle(ctx.world, addrs.into());
+            })
+            .await;
+        } else {
+            error!("Could not resolve host");
+        }
+    });
+}
+
+/// Small +/- buttons that bump a hold setpoint, + increases the displayed value
+fn nudge_buttons(ui: &mut egui::Ui, cmds: &mut Commands, robot: RobotId, nudge: fn(i32) -> Nudge) {
+    for (label, steps) in [("-", 1), ("+", -1)] {
+        if ui.small_button(label).clicked() {
+            let nudge = NudgeSetpoint {
+                robot,
+                nudge: nudge(steps),
+            };
+
+            cmds.add(move |world: &mut World| {
+                world.send_event(nudge);
             });
+        }
     }
 }
 
 fn pwm_control(
     mut cmds: Commands,
     mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
     mut pwm_control: ResMut<PwmControl>,
     robots: Query<(Entity, Option<&PwmManualControl>, &RobotId), With<Robot>>,
     motors: Query<(Entity, Option<&PwmSignal>, &PwmChannel, &RobotId)>,
@@ -778,14 +1578,7 @@ fn pwm_control(
         });
 
     if !open {
-        cmds.remove_resource::<PwmControl>()
-    }
-}
-
-fn cleanup_pwm_control(mut cmds: Commands, robots: Query<Entity, With<Robot>>) {
-    info!("Disabled manual control");
-    for robot in &robots {
-        cmds.entity(robot).remove::<PwmManualControl>();
+        panels.close(Panel::PwmControl);
     }
 }
 
@@ -794,13 +1587,18 @@ fn movement_control(
     mut contexts: EguiContexts,
 
     mut controllers: Query<
-        (Entity, &mut RobotId, &mut MovementContribution),
+        (
+            Entity,
+            &mut RobotId,
+            &mut ControllerInput,
+            &mut ControllerSettings,
+            &MovementContribution,
+        ),
         (With<MovementController>, Without<Robot>),
     >,
     robots: Query<(&Name, &RobotId, &MovementAxisMaximums), With<Robot>>,
-    // motors: Query<(Entity, Option<&PwmSignal>, &PwmChannel, &RobotId)>,
 ) {
-    for (contoller, mut selected_robot, mut contribution) in &mut controllers {
+    for (contoller, mut selected_robot, mut input, mut settings, contribution) in &mut controllers {
         let mut open = true;
 
         let context = contexts.ctx_mut();
@@ -809,8 +1607,13 @@ fn movement_control(
             .constrain_to(context.available_rect().shrink(20.0))
             .open(&mut open)
             .show(context, |ui| {
+                let mut new_input = ControllerInput {
+                    held: false,
+                    ..*input
+                };
+
                 ui.label("Robot:");
-                let Some(maximums) = ui
+                let maximums = ui
                     .horizontal(|ui| {
                         let mut maximums = None;
 
@@ -829,57 +1632,83 @@ fn movement_control(
                             None
                         }
                     })
-                    .inner
-                else {
-                    return;
-                };
+                    .inner;
 
-                let mut movement = contribution.0;
+                if let Some(maximums) = maximums {
+                    let setpoint = &mut new_input.setpoint;
+                    let axes = [
+                        ("X:", Axis::X, &mut setpoint.force.x),
+                        ("Y:", Axis::Y, &mut setpoint.force.y),
+                        ("Z:", Axis::Z, &mut setpoint.force.z),
+                        ("Pitch:", Axis::XRot, &mut setpoint.torque.x),
+                        ("Roll:", Axis::YRot, &mut setpoint.torque.y),
+                        ("Yaw:", Axis::ZRot, &mut setpoint.torque.z),
+                    ];
 
-                ui.horizontal(|ui| {
-                    ui.add_sized([40.0, 0.0], Label::new("X:"));
-                    let max = maximums[&Axis::X].0;
-                    ui.add(widgets::Slider::new(&mut movement.force.x, -max..=max));
-                });
+                    for (label, axis, value) in axes {
+                        ui.horizontal(|ui| {
+                            ui.add_sized([40.0, 0.0], Label::new(label));
+                            let max = maximums[&axis].0;
+                            let response = ui.add(widgets::Slider::new(value, -max..=max));
 
-                ui.horizontal(|ui| {
-                    ui.add_sized([40.0, 0.0], Label::new("Y:"));
-                    let max = maximums[&Axis::Y].0;
-                    ui.add(widgets::Slider::new(&mut movement.force.y, -max..=max));
-                });
+                            new_input.held |=
+                                response.dragged() || response.is_pointer_button_down_on();
+                        });
+                    }
 
-                ui.horizontal(|ui| {
-                    ui.add_sized([40.0, 0.0], Label::new("Z:"));
-                    let max = maximums[&Axis::Z].0;
-                    ui.add(widgets::Slider::new(&mut movement.force.z, -max..=max));
-                });
+                    ui.add_space(7.0);
 
-                ui.horizontal(|ui| {
-                    ui.add_sized([40.0, 0.0], Label::new("Pitch"));
-                    let max = maximums[&Axis::XRot].0;
-                    ui.add(widgets::Slider::new(&mut movement.torque.x, -max..=max));
-                });
+                    if ui.button("Clear").clicked() {
+                        new_input.setpoint = Movement::default();
+                    }
+                }
 
-                ui.horizontal(|ui| {
-                    ui.add_sized([40.0, 0.0], Label::new("Roll:"));
-                    let max = maximums[&Axis::YRot].0;
-                    ui.add(widgets::Slider::new(&mut movement.torque.y, -max..=max));
-                });
+                // Momentary sliders spring back when let go
+                if settings.hold_to_apply && !new_input.held {
+                    new_input.setpoint = Movement::default();
+                }
 
-                ui.horizontal(|ui| {
-                    ui.add_sized([40.0, 0.0], Label::new("Yaw:"));
-                    let max = maximums[&Axis::ZRot].0;
-                    ui.add(widgets::Slider::new(&mut movement.torque.z, -max..=max));
-                });
+                ui.collapsing("Settings", |ui| {
+                    let mut new_settings = *settings;
 
-                ui.add_space(7.0);
+                    ui.horizontal(|ui| {
+                        ui.label("Gain:");
+                        ui.add(widgets::Slider::new(&mut new_settings.gain, 0.0..=1.0));
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Force Ramp:");
+                        ui.add(
+                            widgets::DragValue::new(&mut new_settings.force_ramp)
+                                .clamp_range(0.0..=f32::INFINITY)
+                                .suffix(" N/s"),
+                        )
+                        .on_hover_text("0 follows the sliders without ramping");
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Torque Ramp:");
+                        ui.add(
+                            widgets::DragValue::new(&mut new_settings.torque_ramp)
+                                .clamp_range(0.0..=f32::INFINITY)
+                                .suffix(" Nm/s"),
+                        )
+                        .on_hover_text("0 follows the sliders without ramping");
+                    });
+                    ui.checkbox(&mut new_settings.hold_to_apply, "Hold to apply");
 
-                if ui.button("Clear").clicked() {
-                    movement = Movement::default();
-                }
+                    if new_settings != *settings {
+                        *settings = new_settings;
+                    }
+                });
+
+                let output = contribution.0;
+                ui.label(format!(
+                    "Output: {:.1} N, {:.1} Nm",
+                    output.force.length(),
+                    output.torque.length()
+                ));
 
-                if movement != contribution.0 {
-                    contribution.0 = movement;
+                if new_input != *input {
+                    *input = new_input;
                 }
             });
 
@@ -890,8 +1719,8 @@ fn movement_control(
 }
 
 fn timer(
-    mut cmds: Commands,
     mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
     mut timer: ResMut<TimerUi>,
     time: Res<Time<Real>>,
 ) {
@@ -910,20 +1739,7 @@ fn timer(
                 ui.selectable_value(current_value, TimerType::Cleanup, "Cleanup");
             });
 
-            let total_duration = match current_value {
-                TimerType::Setup => Duration::from_secs_f64(5.0 * 60.0),
-                TimerType::Run => Duration::from_secs_f64(15.0 * 60.0),
-                TimerType::Cleanup => Duration::from_secs_f64(5.0 * 60.0),
-            };
-
-            let remaining_duration = match timer.0 {
-                TimerState::Running { start, offset } => {
-                    total_duration.saturating_sub((time.elapsed() - start) + offset)
-                }
-                TimerState::Paused { elapsed } => total_duration - elapsed,
-            };
-
-            let remaining_sec = remaining_duration.as_secs();
+            let remaining_sec = timer.remaining(time.elapsed()).as_secs();
 
             let min = remaining_sec / 60;
             let sec = remaining_sec % 60;
@@ -963,6 +1779,2850 @@ fn timer(
         });
 
     if !open {
-        cmds.remove_resource::<TimerUi>();
+        panels.close(Panel::Timer);
+    }
+}
+
+fn thruster_view(
+    mut cmds: Commands,
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    robots: Query<
+        (
+            Entity,
+            &Name,
+            &Motors,
+            &RobotId,
+            Option<&DisabledMotors>,
+            Option<&MotorConfigReport>,
+        ),
+        With<Robot>,
+    >,
+    motors: Query<(&MotorDefinition, &TargetForce, &RobotId, Option<&Name>)>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    egui::Window::new("Thrusters")
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            if robots.is_empty() {
+                ui.label("No robot");
+                return;
+            }
+
+            for (robot, name, Motors(motor_config), robot_id, disabled, report) in &robots {
+                let forces: BTreeMap<_, _> = motors
+                    .iter()
+                    .filter(|(_, _, other_robot, _)| *other_robot == robot_id)
+                    .map(|(MotorDefinition(id, _), force, _, _)| (*id, force.0 .0))
+                    .collect();
+
+                let summary = motor_config.summary();
+                let max_force = forces
+                    .values()
+                    .fold(1.0f32, |max, force| max.max(force.abs()));
+                let max_extent = summary
+                    .motors
+                    .iter()
+                    .map(|it| it.motor.position.abs().max_element())
+                    .fold(0.01f32, f32::max);
+
+                ui.heading(name.as_str());
+
+                ui.horizontal(|ui| {
+                    for (label, project) in [
+                        (
+                            "Top",
+                            (|it: Vec3A| egui::vec2(it.x, -it.y)) as fn(Vec3A) -> egui::Vec2,
+                        ),
+                        ("Side", |it: Vec3A| egui::vec2(it.y, -it.z)),
+                    ] {
+                        ui.vertical(|ui| {
+                            ui.label(label);
+
+                            let size = 200.0;
+                            let (response, painter) =
+                                ui.allocate_painter(egui::vec2(size, size), Sense::hover());
+                            let rect = response.rect;
+                            let center = rect.center();
+                            let scale = size * 0.35 / max_extent;
+
+                            painter.rect_stroke(rect, 4.0, Stroke::new(1.0, Color32::GRAY));
+
+                            for motor in &summary.motors {
+                                let force = forces.get(&motor.id).copied().unwrap_or(0.0);
+                                let origin = center + project(motor.motor.position) * scale;
+                                let arrow = project(motor.motor.orientation)
+                                    * (force / max_force)
+                                    * size
+                                    * 0.25;
+
+                                let color = if force >= 0.0 {
+                                    Color32::GREEN
+                                } else {
+                                    Color32::RED
+                                };
+
+                                painter.circle_filled(origin, 3.0, Color32::GRAY);
+                                painter.arrow(origin, arrow, Stroke::new(2.0, color));
+                            }
+                        });
+                    }
+                });
+
+                ui.add_space(7.0);
+
+                for axis in Axis::ALL {
+                    let net = motor_config
+                        .axis_row(axis)
+                        .map(|(id, contribution)| {
+                            contribution * forces.get(id).copied().unwrap_or(0.0)
+                        })
+                        .sum::<f32>();
+
+                    ui.horizontal(|ui| {
+                        ui.add_sized([40.0, 0.0], Label::new(format!("{axis:?}:")));
+                        ui.label(format!("{net:.2}"));
+                    });
+                }
+
+                ui.add_space(7.0);
+                ui.label("Enabled motors");
+
+                // The robot's motor config only has the enabled motors, the definitions have all
+                let mut definitions: Vec<_> = motors
+                    .iter()
+                    .filter(|(_, _, other_robot, _)| *other_robot == robot_id)
+                    .map(|(MotorDefinition(id, _), _, _, name)| (*id, name))
+                    .collect();
+                definitions.sort_by_key(|(id, _)| *id);
+                let motor_count = definitions.len();
+
+                let mut disabled = disabled.map(|it| it.0.clone()).unwrap_or_default();
+                let mut changed = false;
+
+                for (id, motor_name) in definitions {
+                    let label = match motor_name {
+                        Some(name) => name.as_str().to_owned(),
+                        None => format!("Motor {id}"),
+                    };
+
+                    let mut enabled = !disabled.contains(&id);
+                    if ui.checkbox(&mut enabled, label).changed() {
+                        if enabled {
+                            disabled.remove(&id);
+                        } else {
+                            disabled.insert(id);
+                        }
+
+                        changed = true;
+                    }
+                }
+
+                if !disabled.is_empty() {
+                    ui.colored_label(
+                        Color32::YELLOW,
+                        format!(
+                            "Flying on {} of {motor_count} motors, authority is reduced",
+                            motor_count.saturating_sub(disabled.len())
+                        ),
+                    );
+                }
+
+                if changed {
+                    cmds.entity(robot).insert(DisabledMotors(disabled));
+                }
+
+                if let Some(report) = report {
+                    ui.add_space(7.0);
+                    motor_config_report(ui, robot, report);
+                }
+            }
+        });
+
+    if !open {
+        panels.close(Panel::Thrusters);
+    }
+}
+
+/// The robot's startup motor config checks, errors keep it from arming
+fn motor_config_report(ui: &mut egui::Ui, robot: Entity, report: &MotorConfigReport) {
+    for error in &report.errors {
+        ui.colored_label(Color32::RED, error);
+    }
+    for warning in &report.warnings {
+        ui.colored_label(Color32::YELLOW, warning);
+    }
+
+    egui::CollapsingHeader::new("Config Checks")
+        .id_source(("Motor Config Checks", robot))
+        .show(ui, |ui| {
+            egui::Grid::new(("Motor Config Motors", robot))
+                .num_columns(4)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Motor");
+                    ui.label("PWM");
+                    ui.label("Lever Arm (m)");
+                    ui.label("Orientation");
+                    ui.end_row();
+
+                    for motor in &report.motors {
+                        ui.label(&motor.name);
+                        ui.label(motor.pwm_channel.to_string());
+                        for vec in [motor.lever_arm, motor.orientation] {
+                            ui.label(format!("({:.2}, {:.2}, {:.2})", vec.x, vec.y, vec.z));
+                        }
+                        ui.end_row();
+                    }
+                });
+
+            ui.add_space(7.0);
+
+            egui::Grid::new(("Motor Config Axes", robot))
+                .num_columns(3)
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.label("Axis");
+                    ui.label("Maximum");
+                    ui.label("Authority");
+                    ui.end_row();
+
+                    for axis in &report.axes {
+                        let name = if axis.required {
+                            RichText::new(&axis.name).strong()
+                        } else {
+                            RichText::new(&axis.name).weak()
+                        };
+
+                        ui.label(name);
+                        ui.label(format!("{:.1}", axis.maximum.0));
+                        ui.label(format!("{:.0}%", axis.authority * 100.0));
+                        ui.end_row();
+                    }
+                });
+
+            ui.label(format!("Condition number: {:.1}", report.condition_number));
+        });
+}
+
+fn quarantine_view(
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    quarantine: Res<QuarantinedTypes>,
+    mut clear: EventWriter<ClearQuarantine>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    egui::Window::new("Quarantined Types")
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            let mut quarantined: Vec<_> = quarantine.quarantined().collect();
+            quarantined.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+            if quarantined.is_empty() {
+                ui.label("No quarantined types");
+                return;
+            }
+
+            for (token, info) in quarantined {
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new(token.as_ref()).strong());
+
+                    if ui.button("Clear").clicked() {
+                        clear.send(ClearQuarantine(token.clone()));
+                    }
+                });
+                ui.label(format!("Failures: {}", info.failures));
+                ui.label(RichText::new(&info.last_error).color(Color32::RED));
+                ui.separator();
+            }
+        });
+
+    if !open {
+        panels.close(Panel::Quarantine);
+    }
+}
+
+fn servo_view(
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    robots: Query<(&Name, &Servos, &ServoTargets), With<Robot>>,
+    mut servo_commands: EventWriter<ServoCommand>,
+    mut reset: EventWriter<ResetServo>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    egui::Window::new("Servos")
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            if robots.is_empty() {
+                ui.label("No robot");
+                return;
+            }
+
+            for (name, servos, targets) in &robots {
+                ui.heading(name.as_str());
+
+                let mut servos = servos.servos.clone();
+                servos.sort();
+
+                for servo in servos {
+                    let mut position = targets.0.get(&servo).copied().unwrap_or(0.0);
+
+                    ui.horizontal(|ui| {
+                        ui.add_sized([120.0, 0.0], Label::new(servo.as_str()));
+
+                        let slider = egui::Slider::new(&mut position, -1.0..=1.0).fixed_decimals(2);
+                        if ui.add(slider).changed() {
+                            servo_commands.send(ServoCommand {
+                                servo: servo.clone(),
+                                target: position,
+                            });
+                        }
+
+                        if ui.button("Center").clicked() {
+                            reset.send(ResetServo(servo.clone()));
+                        }
+                    });
+                }
+            }
+        });
+
+    if !open {
+        panels.close(Panel::Servos);
+    }
+}
+
+fn contributions_view(
+    mut cmds: Commands,
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    robots: Query<(&Name, &NetId, Option<&ActiveContributions>), With<Robot>>,
+    contributions: Query<(
+        Entity,
+        Option<&Name>,
+        &RobotId,
+        &MovementContribution,
+        Has<ContributionMuted>,
+    )>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    egui::Window::new("Movement Sources")
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            if robots.is_empty() {
+                ui.label("No robot");
+                return;
+            }
+
+            for (name, net_id, active) in &robots {
+                ui.heading(name.as_str());
+
+                let mut sources = contributions
+                    .iter()
+                    .filter(|(_, _, robot, ..)| robot.0 == *net_id)
+                    .collect::<Vec<_>>();
+                sources.sort_by_key(|(_, name, ..)| name.map(|it| it.as_str().to_owned()));
+
+                if sources.is_empty() {
+                    ui.label("No sources");
+                }
+
+                for (entity, source, _, movement, muted) in sources {
+                    ui.horizontal(|ui| {
+                        let mut mute = muted;
+                        if ui.checkbox(&mut mute, "Mute").changed() {
+                            if mute {
+                                cmds.entity(entity).insert(ContributionMuted);
+                            } else {
+                                cmds.entity(entity).remove::<ContributionMuted>();
+                            }
+                        }
+
+                        let source = source.map(|it| it.as_str()).unwrap_or("Unnamed");
+                        ui.add_sized([200.0, 0.0], Label::new(source));
+
+                        let text = RichText::new(format_movement(&movement.0)).monospace();
+                        if muted {
+                            ui.label(text.weak());
+                        } else {
+                            ui.label(text);
+                        }
+                    });
+                }
+
+                ui.separator();
+
+                ui.label("Applied by robot");
+                match active {
+                    Some(active) if !active.0.is_empty() => {
+                        for (source, movement) in &active.0 {
+                            ui.horizontal(|ui| {
+                                ui.add_sized([200.0, 0.0], Label::new(source.as_str()));
+                                ui.label(RichText::new(format_movement(movement)).monospace());
+                            });
+                        }
+                    }
+                    _ => {
+                        ui.label("Nothing");
+                    }
+                }
+            }
+        });
+
+    if !open {
+        panels.close(Panel::Contributions);
+    }
+}
+
+fn controllers_view(
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    robots: Query<(&Name, &NetId), With<Robot>>,
+    controllers: Query<(
+        Option<&Name>,
+        &RobotId,
+        &PidConfig,
+        Option<&PidResult>,
+        Option<&AuthorityLimit>,
+    )>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    egui::Window::new("PID Controllers")
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            if robots.is_empty() {
+                ui.label("No robot");
+                return;
+            }
+
+            for (name, net_id) in &robots {
+                ui.heading(name.as_str());
+
+                let mut robot_controllers = controllers
+                    .iter()
+                    .filter(|(_, robot, ..)| robot.0 == *net_id)
+                    .collect::<Vec<_>>();
+                robot_controllers.sort_by_key(|(name, ..)| name.map(|it| it.as_str().to_owned()));
+
+                if robot_controllers.is_empty() {
+                    ui.label("No controllers");
+                    continue;
+                }
+
+                egui::Grid::new(("PID Controllers", *net_id))
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for header in [
+                            "Controller",
+                            "kP",
+                            "kI",
+                            "kD",
+                            "kT",
+                            "Max I",
+                            "Authority",
+                            "Correction",
+                        ] {
+                            ui.strong(header);
+                        }
+                        ui.end_row();
+
+                        for (name, _, config, result, limit) in robot_controllers {
+                            ui.label(name.map(|it| it.as_str()).unwrap_or("Unnamed"));
+
+                            for value in [
+                                config.kp,
+                                config.ki,
+                                config.kd,
+                                config.kt,
+                                config.max_integral,
+                            ] {
+                                ui.monospace(format!("{value:.2}"));
+                            }
+
+                            match limit {
+                                Some(AuthorityLimit(limit)) if limit.0.is_finite() => {
+                                    ui.monospace(format!("{limit}"));
+                                }
+                                _ => {
+                                    ui.label("Unlimited");
+                                }
+                            }
+
+                            match (result, limit) {
+                                (Some(result), Some(AuthorityLimit(limit)))
+                                    if result.correction.abs() > limit.0 =>
+                                {
+                                    ui.label(
+                                        RichText::new(format!(
+                                            "{:.2} (clamped)",
+                                            result.correction
+                                        ))
+                                        .monospace()
+                                        .color(Color32::YELLOW),
+                                    );
+                                }
+                                (Some(result), _) => {
+                                    ui.monospace(format!("{:.2}", result.correction));
+                                }
+                                (None, _) => {
+                                    ui.label("Inactive");
+                                }
+                            }
+
+                            ui.end_row();
+                        }
+                    });
+
+                ui.separator();
+            }
+        });
+
+    if !open {
+        panels.close(Panel::Controllers);
+    }
+}
+
+/// Largest center of mass offset the trim sliders allow, in meters
+const MAX_COM_TRIM: f32 = 0.1;
+
+fn center_of_mass_view(
+    mut cmds: Commands,
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    robots: Query<(Entity, &Name, Option<&CenterOfMassOffset>), With<Robot>>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    egui::Window::new("Center of Mass Trim")
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            if robots.is_empty() {
+                ui.label("No robot");
+                return;
+            }
+
+            for (robot, name, offset) in &robots {
+                ui.heading(name.as_str());
+
+                let mut offset = offset.map(|it| it.0).unwrap_or_default();
+                let mut changed = false;
+
+                for (axis, value) in [
+                    ("X (m)", &mut offset.x),
+                    ("Y (m)", &mut offset.y),
+                    ("Z (m)", &mut offset.z),
+                ] {
+                    let slider = widgets::Slider::new(value, -MAX_COM_TRIM..=MAX_COM_TRIM)
+                        .fixed_decimals(3)
+                        .text(axis);
+                    changed |= ui.add(slider).changed();
+                }
+
+                if ui.button("Reset").clicked() {
+                    offset = Vec3A::ZERO;
+                    changed = true;
+                }
+
+                if changed {
+                    cmds.entity(robot).insert(CenterOfMassOffset(offset));
+                }
+
+                ui.separator();
+            }
+        });
+
+    if !open {
+        panels.close(Panel::CenterOfMass);
+    }
+}
+
+fn current_trim_view(
+    mut cmds: Commands,
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    capture: Res<TrimCapture>,
+    robots: Query<
+        (
+            Entity,
+            &Name,
+            &RobotId,
+            Option<&CurrentTrim>,
+            Option<&MovementAxisMaximums>,
+            Option<&Armed>,
+        ),
+        With<Robot>,
+    >,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    egui::Window::new("Current Trim")
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            if robots.is_empty() {
+                ui.label("No robot");
+                return;
+            }
+
+            ui.label("World frame force held against the current, cleared on disarm");
+
+            for (robot, name, &robot_id, trim, maximums, armed) in &robots {
+                ui.heading(name.as_str());
+
+                let mut trim = trim.map(|it| it.0).unwrap_or_default();
+                let mut changed = false;
+
+                let limit = CurrentTrim::limit(maximums);
+
+                for (axis, value) in [
+                    ("East (N)", &mut trim.x),
+                    ("North (N)", &mut trim.y),
+                    ("Up (N)", &mut trim.z),
+                ] {
+                    let slider = widgets::Slider::new(value, -limit..=limit)
+                        .fixed_decimals(1)
+                        .text(axis);
+                    changed |= ui.add(slider).changed();
+                }
+
+                ui.horizontal(|ui| {
+                    let capture_button = ui
+                        .add_enabled(
+                            capture.window(robot_id.0).is_some(),
+                            egui::Button::new("Capture"),
+                        )
+                        .on_hover_text(format!(
+                            "Adds the pilot's average input over the last {:?}",
+                            current_trim::CAPTURE_WINDOW
+                        ))
+                        .on_disabled_hover_text("Arm and hold against the current first");
+                    if capture_button.clicked() {
+                        cmds.add(move |world: &mut World| {
+                            world.run_system_once_with(robot_id, current_trim::capture);
+                        });
+                    }
+
+                    if ui.button("Clear").clicked() {
+                        trim = Vec3A::ZERO;
+                        changed = true;
+                    }
+                });
+
+                if armed != Some(&Armed::Armed) && trim != Vec3A::ZERO {
+                    ui.colored_label(Color32::YELLOW, "Only applied while armed");
+                }
+
+                if changed {
+                    cmds.entity(robot).insert(CurrentTrim(trim));
+                }
+
+                ui.separator();
+            }
+        });
+
+    if !open {
+        panels.close(Panel::CurrentTrim);
+    }
+}
+
+fn sync_stats_view(
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    local: Res<SyncStats>,
+    redundant: Res<RedundantApplies>,
+    applied: Res<ApplyStats>,
+    diagnostics: Res<DiagnosticsStore>,
+    robots: Query<(&Name, &RemoteSyncStats), With<Robot>>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    egui::Window::new("Sync Stats")
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            ui.heading("Surface");
+
+            ui.label(format!(
+                "{} redundant inbound updates skipped",
+                redundant.total
+            ));
+            ui.label(format!("{} outbound updates coalesced", local.coalesced()));
+            ui.label(format!(
+                "{} entities applied last frame, {} at most, {} changes waiting",
+                applied.last_frame, applied.peak, applied.waiting
+            ));
+
+            pipeline_plot(ui, &diagnostics);
+
+            let rates = local.by_rate();
+            if rates.is_empty() {
+                ui.label("Nothing sent");
+            }
+
+            for (name, stats) in rates {
+                sync_rate_row(
+                    ui,
+                    name,
+                    stats.per_second,
+                    stats.bytes_per_second,
+                    stats.history.iter().copied(),
+                );
+            }
+
+            for (name, stats) in &robots {
+                ui.separator();
+                ui.heading(name.as_str());
+
+                if stats.0.is_empty() {
+                    ui.label("Nothing sent");
+                }
+
+                for rate in &stats.0 {
+                    sync_rate_row(
+                        ui,
+                        &rate.name,
+                        rate.per_second,
+                        rate.bytes_per_second,
+                        rate.history.iter().copied(),
+                    );
+                }
+            }
+        });
+
+    if !open {
+        panels.close(Panel::SyncStats);
+    }
+}
+
+/// Recent per frame counts from the sync pipeline diagnostics
+fn pipeline_plot(ui: &mut egui::Ui, diagnostics: &DiagnosticsStore) {
+    let series = [
+        ("Changes emitted", &CHANGES_EMITTED),
+        ("Bytes serialized", &BYTES_SERIALIZED),
+        ("Entities applied", &ENTITIES_APPLIED),
+    ];
+
+    Plot::new("Sync Pipeline Plot")
+        .height(120.0)
+        .x_axis_label("Frame")
+        .legend(Legend::default())
+        .allow_scroll(false)
+        .show(ui, |plot| {
+            for (name, path) in series {
+                let Some(diagnostic) = diagnostics.get(path) else {
+                    continue;
+                };
+
+                let points: PlotPoints = diagnostic
+                    .values()
+                    .enumerate()
+                    .map(|(frame, value)| [frame as f64, *value])
+                    .collect();
+
+                plot.line(Line::new(points).name(name));
+            }
+        });
+}
+
+fn sync_rate_row(
+    ui: &mut egui::Ui,
+    name: &str,
+    per_second: u32,
+    bytes_per_second: u64,
+    history: impl ExactSizeIterator<Item = u32> + Clone,
+) {
+    ui.horizontal(|ui| {
+        ui.add_sized([200.0, 0.0], Label::new(name));
+        ui.label(RichText::new(format!("{per_second:>6}/s {bytes_per_second:>8} B/s")).monospace());
+
+        let (rect, _) = ui.allocate_exact_size(egui::vec2(90.0, 16.0), Sense::hover());
+        let peak = history.clone().max().unwrap_or(0).max(1) as f32;
+        let step = rect.width() / (STATS_HISTORY - 1) as f32;
+        let offset = STATS_HISTORY.saturating_sub(history.len());
+
+        let points = history
+            .enumerate()
+            .map(|(idx, rate)| {
+                egui::pos2(
+                    rect.left() + (offset + idx) as f32 * step,
+                    rect.bottom() - rate as f32 / peak * rect.height(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        ui.painter().add(egui::Shape::line(
+            points,
+            Stroke::new(1.0, ui.visuals().text_color()),
+        ));
+    });
+}
+
+fn depth_profile_view(
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    selected: Res<SelectedRobot>,
+    robots: Query<(&RobotId, &Name, &DepthTemperatureProfile, Option<&Depth>), With<Robot>>,
+    mut clear: EventWriter<Scoped<ClearProfile>>,
+    mut errors: EventWriter<ErrorEvent>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    egui::Window::new("Depth Profile")
+        .default_size((400.0, 500.0))
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            let robot = robots
+                .iter()
+                .find(|(robot, ..)| Some(**robot) == selected.0);
+            let Some((&robot, name, profile, depth)) = robot else {
+                ui.label("No profile");
+                return;
+            };
+
+            ui.horizontal(|ui| {
+                ui.label(format!("{} samples", profile.0.len()));
+
+                if ui.button("Clear").clicked() {
+                    clear.send(Scoped::new(robot, ClearProfile));
+                }
+
+                if ui.button("Export CSV").clicked() {
+                    match export_profile(Path::new(PROFILES_DIR), name.as_str(), profile) {
+                        Ok(path) => info!("Exported depth profile to {}", path.display()),
+                        Err(err) => errors.send(err.context("Export depth profile").into()),
+                    }
+                }
+            });
+
+            // Depth is positive down, plotted negated so deeper is lower
+            let points: Vec<[f64; 2]> = profile
+                .0
+                .iter()
+                .map(|(depth, temperature)| [temperature.0 as f64, -depth.0 as f64])
+                .collect();
+
+            Plot::new("Depth Profile Plot")
+                .x_axis_label("Temperature (°C)")
+                .y_axis_label("Depth (m)")
+                .y_axis_formatter(|mark, _, _| format!("{:.1}", -mark.value))
+                .show(ui, |plot| {
+                    plot.line(Line::new(PlotPoints::from(points.clone())));
+                    plot.points(Points::new(points).radius(2.0));
+
+                    if let Some(depth) = depth {
+                        plot.hline(
+                            HLine::new(-depth.0.depth.0 as f64)
+                                .name(format!("Current depth {}", depth.0.depth)),
+                        );
+                    }
+                });
+        });
+
+    if !open {
+        panels.close(Panel::DepthProfile);
+    }
+}
+
+fn profile_csv(profile: &DepthTemperatureProfile) -> String {
+    let mut csv = String::from("depth_m,temperature_c\n");
+
+    for (depth, temperature) in &profile.0 {
+        csv.push_str(&format!("{:.3},{:.3}\n", depth.0, temperature.0));
+    }
+
+    csv
+}
+
+/// Writes the profile to a new file in `dir`, named after the robot and the current time
+fn export_profile(
+    dir: &Path,
+    robot: &str,
+    profile: &DepthTemperatureProfile,
+) -> anyhow::Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("System time before epoch")?
+        .as_secs();
+    let path = dir.join(format!("{}_{timestamp}.csv", robot.replace(' ', "_")));
+
+    fs::create_dir_all(dir).context("Create profiles dir")?;
+    fs::write(&path, profile_csv(profile)).context("Write profile")?;
+
+    Ok(path)
+}
+
+fn collect_position_track(
+    mut track: ResMut<PositionTrack>,
+    selected: Res<SelectedRobot>,
+    robots: Query<(&RobotId, Ref<PositionEstimate>), With<Robot>>,
+) {
+    let robot = robots.iter().find(|(robot, _)| Some(**robot) == selected.0);
+
+    if let Some((&robot, estimate)) = robot {
+        if estimate.is_changed() || track.robot != Some(robot) {
+            track.record(robot, &estimate);
+        }
+    }
+}
+
+fn position_view(
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    track: Res<PositionTrack>,
+    selected: Res<SelectedRobot>,
+    robots: Query<(&RobotId, &PositionEstimate, Option<&Orientation>), With<Robot>>,
+    mut reset: EventWriter<Scoped<ResetPosition>>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    let robot = robots
+        .iter()
+        .find(|(robot, ..)| Some(**robot) == selected.0);
+
+    egui::Window::new("Position Estimate")
+        .default_size((400.0, 450.0))
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            let Some((&robot, estimate, orientation)) = robot else {
+                ui.label("No position estimate");
+                return;
+            };
+
+            let confidence = estimate.confidence_decay.clamp(0.0, 1.0);
+            let confidence_color = if confidence > 0.7 {
+                Color32::GREEN
+            } else if confidence > 0.3 {
+                Color32::YELLOW
+            } else {
+                Color32::RED
+            };
+
+            ui.horizontal(|ui| {
+                ui.label("Confidence");
+                ui.add(
+                    egui::ProgressBar::new(confidence)
+                        .desired_width(150.0)
+                        .fill(confidence_color)
+                        .text(format!("{:.0}%", confidence * 100.0)),
+                );
+
+                if ui.button("Reset").clicked() {
+                    reset.send(Scoped::new(robot, ResetPosition));
+                }
+            });
+            ui.label(
+                RichText::new(
+                    "Dead reckoned from thrust and heading, this drifts. Reset it at a known spot.",
+                )
+                .weak(),
+            );
+
+            let xy = estimate.xy;
+            let heading = orientation.map(|it| video_overlay::heading_degrees(it.rotation));
+            ui.label(match heading {
+                Some(heading) => format!("X {:.1} m, Y {:.1} m, heading {heading:.0}°", xy.x, xy.y),
+                None => format!("X {:.1} m, Y {:.1} m", xy.x, xy.y),
+            });
+
+            let points: Vec<[f64; 2]> = track
+                .points()
+                .chain([xy])
+                .map(|it| [it.x as f64, it.y as f64])
+                .collect();
+
+            let response = Plot::new("Position Plot")
+                .data_aspect(1.0)
+                .x_axis_label("East (m)")
+                .y_axis_label("North (m)")
+                .legend(Legend::default())
+                .show(ui, |plot| {
+                    plot.line(Line::new(PlotPoints::from(points)).name("Track"));
+                    plot.points(
+                        Points::new(vec![[0.0, 0.0]])
+                            .shape(MarkerShape::Square)
+                            .radius(5.0)
+                            .name("Start"),
+                    );
+                    plot.points(
+                        Points::new(vec![[xy.x as f64, xy.y as f64]])
+                            .radius(5.0)
+                            .color(confidence_color)
+                            .name("Robot"),
+                    );
+
+                    if let Some(heading) = heading {
+                        // Compass headings turn clockwise from north
+                        let heading = heading.to_radians();
+                        let tip = xy + Vec2::new(heading.sin(), heading.cos()) * 0.5;
+
+                        plot.arrows(
+                            Arrows::new(
+                                vec![[xy.x as f64, xy.y as f64]],
+                                vec![[tip.x as f64, tip.y as f64]],
+                            )
+                            .color(confidence_color)
+                            .name("Heading"),
+                        );
+                    }
+                })
+                .response;
+
+            // North is always up on the plot
+            let base = response.rect.right_top() + egui::vec2(-20.0, 40.0);
+            let stroke = Stroke::new(2.0, ui.visuals().text_color());
+            ui.painter().arrow(base, egui::vec2(0.0, -20.0), stroke);
+            ui.painter().text(
+                base + egui::vec2(0.0, 4.0),
+                egui::Align2::CENTER_TOP,
+                "N",
+                egui::FontId::proportional(14.0),
+                ui.visuals().text_color(),
+            );
+        });
+
+    if !open {
+        panels.close(Panel::Position);
+    }
+}
+
+fn depth_mission_view(
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    mut mission: ResMut<DepthMission>,
+    selected: Res<SelectedRobot>,
+    robots: Query<(&Name, &RobotId, &Armed, Option<&Depth>), With<Robot>>,
+    mut errors: EventWriter<ErrorEvent>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    egui::Window::new("Depth Mission")
+        .default_size((400.0, 400.0))
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            let mission = &mut *mission;
+            let running = mission.run.is_some();
+
+            ui.horizontal(|ui| {
+                ui.label("File");
+                ui.add_enabled(!running, egui::TextEdit::singleline(&mut mission.path));
+
+                if ui
+                    .add_enabled(!running, egui::Button::new("Load"))
+                    .clicked()
+                {
+                    match DepthProfile::load(&mission.path) {
+                        Ok(profile) => mission.profile = profile,
+                        Err(err) => errors.send(err.context("Load depth mission").into()),
+                    }
+                }
+
+                if ui.button("Save").clicked() {
+                    if let Err(err) = mission.profile.save(&mission.path) {
+                        errors.send(err.context("Save depth mission").into());
+                    }
+                }
+            });
+
+            ui.separator();
+
+            // The profile can't change under a running mission
+            let current = mission.run.map(|run| run.progress.segment);
+            ui.add_enabled_ui(!running, |ui| {
+                segment_editor(ui, &mut mission.profile.segments, current);
+            });
+
+            ui.separator();
+
+            if let Some(run) = mission.run {
+                let profile = &mission.profile;
+                let progress = run.progress;
+
+                if let Some(segment) = profile.segments.get(progress.segment) {
+                    ui.label(format!(
+                        "Segment {} of {}",
+                        progress.segment + 1,
+                        profile.segments.len()
+                    ));
+
+                    match progress.phase(profile) {
+                        Some(SegmentPhase::Ramping) => ui.label(format!(
+                            "Ramping to {}, setpoint {}",
+                            segment.target, progress.setpoint
+                        )),
+                        _ => ui.label(format!("Holding at {}", segment.target)),
+                    };
+                }
+
+                ui.label(format!(
+                    "Segment remaining: {:.0}s",
+                    progress.segment_remaining(profile).as_secs_f32()
+                ));
+                ui.label(format!(
+                    "Mission remaining: {:.0}s",
+                    progress.remaining(profile).as_secs_f32()
+                ));
+
+                if run.paused {
+                    ui.label(RichText::new("Paused, waiting for the robot").color(Color32::YELLOW));
+                }
+
+                if ui.button("Stop").clicked() {
+                    mission.stop(MissionOutcome::Stopped);
+                }
+            } else {
+                let robot = robots
+                    .iter()
+                    .find(|(_, robot, ..)| Some(**robot) == selected.0);
+                let ready = robot.and_then(|(name, robot_id, armed, depth)| {
+                    (*armed == Armed::Armed)
+                        .then_some(depth)
+                        .flatten()
+                        .map(|depth| (name, robot_id, depth))
+                });
+
+                ui.horizontal(|ui| {
+                    let start = ui.add_enabled(ready.is_some(), egui::Button::new("Start"));
+                    let start =
+                        start.on_disabled_hover_text("Robot must be armed with a depth reading");
+
+                    if let (true, Some((name, robot_id, depth))) = (start.clicked(), ready) {
+                        match mission.start(robot_id.0, depth.0.depth) {
+                            Ok(()) => info!("Started depth mission on {name}"),
+                            Err(err) => errors.send(err.context("Start depth mission").into()),
+                        }
+                    }
+                });
+
+                if let Some(outcome) = mission.outcome {
+                    let color = match outcome {
+                        MissionOutcome::Aborted(_) => Color32::RED,
+                        _ => ui.visuals().text_color(),
+                    };
+
+                    ui.label(RichText::new(format!("Last run: {outcome}")).color(color));
+                }
+            }
+        });
+
+    if !open {
+        panels.close(Panel::DepthMission);
+    }
+}
+
+/// Table of the mission's segments with controls to reorder, remove and add them
+///
+/// `current` is highlighted as the segment being run
+fn segment_editor(ui: &mut egui::Ui, segments: &mut Vec<DepthSegment>, current: Option<usize>) {
+    enum Edit {
+        Up(usize),
+        Down(usize),
+        Remove(usize),
+    }
+
+    let mut edit = None;
+    let count = segments.len();
+
+    egui::Grid::new("Depth Mission Segments")
+        .striped(true)
+        .show(ui, |ui| {
+            ui.strong("#");
+            ui.strong("Depth (m)");
+            ui.strong("Hold (s)");
+            ui.strong("Rate (m/s)");
+            ui.end_row();
+
+            for (idx, segment) in segments.iter_mut().enumerate() {
+                let label = RichText::new(format!("{}", idx + 1));
+                if current == Some(idx) {
+                    ui.label(label.strong().color(Color32::GREEN));
+                } else {
+                    ui.label(label);
+                }
+
+                ui.add(
+                    widgets::DragValue::new(&mut segment.target.0)
+                        .speed(0.05)
+                        .clamp_range(0.0..=100.0)
+                        .max_decimals(2),
+                );
+
+                let mut hold = segment.hold.as_secs_f32();
+                let response = ui.add(
+                    widgets::DragValue::new(&mut hold)
+                        .speed(1.0)
+                        .clamp_range(0.0..=3600.0)
+                        .max_decimals(1),
+                );
+                if response.changed() {
+                    segment.hold = Duration::from_secs_f32(hold);
+                }
+
+                ui.add(
+                    widgets::DragValue::new(&mut segment.rate)
+                        .speed(0.01)
+                        .clamp_range(0.01..=2.0)
+                        .max_decimals(2),
+                );
+
+                ui.horizontal(|ui| {
+                    if ui.add_enabled(idx > 0, egui::Button::new("Up")).clicked() {
+                        edit = Some(Edit::Up(idx));
+                    }
+                    if ui
+                        .add_enabled(idx + 1 < count, egui::Button::new("Down"))
+                        .clicked()
+                    {
+                        edit = Some(Edit::Down(idx));
+                    }
+                    if ui.button("Remove").clicked() {
+                        edit = Some(Edit::Remove(idx));
+                    }
+                });
+                ui.end_row();
+            }
+        });
+
+    match edit {
+        Some(Edit::Up(idx)) => segments.swap(idx - 1, idx),
+        Some(Edit::Down(idx)) => segments.swap(idx, idx + 1),
+        Some(Edit::Remove(idx)) => {
+            segments.remove(idx);
+        }
+        None => {}
+    }
+
+    if ui.button("Add Segment").clicked() {
+        // Continuing from the last segment is usually closest to what is wanted
+        let segment = segments.last().copied().unwrap_or_default();
+        segments.push(segment);
+    }
+}
+
+fn checklist_view(
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    mut file: ResMut<ChecklistFile>,
+    selected: Res<SelectedRobot>,
+    robots: Query<(&RobotId, Option<&MissionChecklist>), With<Robot>>,
+    mut loads: EventWriter<Scoped<LoadChecklist>>,
+    mut sets: EventWriter<Scoped<SetChecklistItem>>,
+    mut errors: EventWriter<ErrorEvent>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    egui::Window::new("Mission Checklist")
+        .default_size((400.0, 500.0))
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            let robot = robots
+                .iter()
+                .find(|(robot, ..)| Some(**robot) == selected.0);
+            let Some((&robot, checklist)) = robot else {
+                ui.label("No robot");
+                return;
+            };
+
+            ui.horizontal(|ui| {
+                ui.label("File");
+                ui.text_edit_singleline(&mut file.path);
+
+                let load = ui
+                    .button("Load")
+                    .on_hover_text("Replaces the checklist on the robot, clearing its progress");
+                if load.clicked() {
+                    match ChecklistDefinition::load(&file.path) {
+                        Ok(definition) => {
+                            let loaded = LoadChecklist(definition.to_checklist());
+                            loads.send(Scoped::new(robot, loaded));
+                        }
+                        Err(err) => errors.send(err.context("Load checklist").into()),
+                    }
+                }
+            });
+
+            ui.separator();
+
+            let Some(checklist) = checklist else {
+                ui.label("No checklist loaded");
+                return;
+            };
+
+            ui.horizontal(|ui| {
+                ui.heading(format!(
+                    "Points: {} / {}",
+                    checklist.0.points(),
+                    checklist.0.max_points()
+                ));
+
+                if ui.button("Export CSV").clicked() {
+                    match export_checklist(Path::new(CHECKLISTS_DIR), checklist) {
+                        Ok(path) => info!("Exported checklist to {}", path.display()),
+                        Err(err) => errors.send(err.context("Export checklist").into()),
+                    }
+                }
+            });
+
+            ui.separator();
+
+            // Only sends requests, the boxes change once the robot sends the checklist back
+            let mut item_row = |ui: &mut egui::Ui,
+                                item: ChecklistItem,
+                                label: String,
+                                checked: Option<Duration>| {
+                ui.horizontal(|ui| {
+                    let mut value = checked.is_some();
+                    if ui.checkbox(&mut value, label).changed() {
+                        let set = SetChecklistItem {
+                            item,
+                            checked: value,
+                            timestamp: crate::checklist::now(),
+                        };
+                        sets.send(Scoped::new(robot, set));
+                    }
+
+                    if let Some(checked) = checked {
+                        ui.weak(format_timestamp(checked));
+                    }
+                });
+            };
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                for (task_idx, task) in checklist.0.tasks.iter().enumerate() {
+                    item_row(
+                        ui,
+                        ChecklistItem {
+                            task: task_idx,
+                            step: None,
+                        },
+                        format!("{} ({} pts)", task.name, task.points),
+                        task.checked,
+                    );
+
+                    ui.indent(("Checklist Steps", task_idx), |ui| {
+                        for (step_idx, step) in task.steps.iter().enumerate() {
+                            item_row(
+                                ui,
+                                ChecklistItem {
+                                    task: task_idx,
+                                    step: Some(step_idx),
+                                },
+                                step.name.clone(),
+                                step.checked,
+                            );
+                        }
+                    });
+                }
+            });
+        });
+
+    if !open {
+        panels.close(Panel::Checklist);
+    }
+}
+
+#[derive(Resource, Default, Debug)]
+struct RobotConfigUi {
+    /// File uploaded to the robot
+    upload_path: String,
+    pending_upload: Option<RpcHandle<ConfigUploadResult>>,
+    last_result: Option<ConfigUploadResult>,
+}
+
+fn collect_config_results(
+    mut state: ResMut<RobotConfigUi>,
+    mut results: EventReader<RpcResult<ConfigUploadResult>>,
+) {
+    for result in results.read() {
+        // Answers to uploads that were replaced by a newer one
+        if !state
+            .pending_upload
+            .as_ref()
+            .is_some_and(|pending| pending.is_for(result))
+        {
+            continue;
+        }
+        state.pending_upload = None;
+
+        let result = match &result.result {
+            Ok(result) => result.clone(),
+            Err(err) => ConfigUploadResult {
+                ok: false,
+                errors: vec![err.to_string()],
+            },
+        };
+
+        if result.ok {
+            info!("Robot accepted the uploaded config");
+        } else {
+            warn!(
+                "Robot rejected the uploaded config: {}",
+                result.errors.join(", ")
+            );
+        }
+
+        state.last_result = Some(result);
+    }
+}
+
+/// Saves a config downloaded from `robot` into `dir`
+fn save_robot_config(dir: &Path, robot: &str, toml_text: &str) -> anyhow::Result<PathBuf> {
+    let robot = robot.replace(|it: char| !it.is_ascii_alphanumeric(), "_");
+    let path = dir.join(format!(
+        "{robot}_{}.toml",
+        crate::checklist::now().as_secs()
+    ));
+
+    fs::create_dir_all(dir).context("Create robot configs dir")?;
+    fs::write(&path, toml_text).context("Write robot config")?;
+
+    Ok(path)
+}
+
+fn robot_config_view(
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    mut state: ResMut<RobotConfigUi>,
+    selected: Res<SelectedRobot>,
+    robots: Query<(&RobotId, &Name, Option<&ConfigSnapshot>, Option<&Armed>), With<Robot>>,
+    mut requests: EventWriter<Scoped<RequestConfig>>,
+    mut uploads: RpcSender<UploadConfig, ConfigUploadResult>,
+    mut errors: EventWriter<ErrorEvent>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    egui::Window::new("Robot Config")
+        .default_size((500.0, 600.0))
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            let robot = robots
+                .iter()
+                .find(|(robot, ..)| Some(**robot) == selected.0);
+            let Some((&robot, name, snapshot, armed)) = robot else {
+                ui.label("No robot");
+                return;
+            };
+
+            ui.horizontal(|ui| {
+                if ui.button("Refresh").clicked() {
+                    requests.send(Scoped::new(robot, RequestConfig));
+                }
+
+                let download = ui.add_enabled(
+                    snapshot.is_some_and(|it| !it.toml_text.is_empty()),
+                    egui::Button::new("Download"),
+                );
+                if download.clicked() {
+                    if let Some(snapshot) = snapshot {
+                        let dir = Path::new(ROBOT_CONFIGS_DIR);
+                        match save_robot_config(dir, name.as_str(), &snapshot.toml_text) {
+                            Ok(path) => info!("Saved robot config to {}", path.display()),
+                            Err(err) => errors.send(err.context("Download robot config").into()),
+                        }
+                    }
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("File");
+                ui.text_edit_singleline(&mut state.upload_path);
+
+                // The robot refuses too, this only saves a round trip
+                let armed = armed == Some(&Armed::Armed);
+                let upload = ui
+                    .add_enabled(
+                        !armed && !state.upload_path.is_empty() && state.pending_upload.is_none(),
+                        egui::Button::new("Upload"),
+                    )
+                    .on_hover_text("Replaces the robot's config, it applies after a restart")
+                    .on_disabled_hover_text("Disarm before uploading a config");
+                if upload.clicked() {
+                    match fs::read_to_string(&state.upload_path) {
+                        Ok(toml_text) if toml_text.len() > MAX_CONFIG_TEXT => {
+                            errors.send(
+                                anyhow::anyhow!(
+                                    "Config is {} bytes, at most {MAX_CONFIG_TEXT} can be uploaded",
+                                    toml_text.len()
+                                )
+                                .into(),
+                            );
+                        }
+                        Ok(toml_text) => {
+                            // RPCs aren't addressed to a robot, every connected robot gets it
+                            state.last_result = None;
+                            state.pending_upload = Some(uploads.send(UploadConfig { toml_text }));
+                        }
+                        Err(err) => errors.send(
+                            anyhow::Error::from(err)
+                                .context("Read config to upload")
+                                .into(),
+                        ),
+                    }
+                }
+            });
+
+            if state.pending_upload.is_some() {
+                ui.label("Uploading...");
+            }
+
+            match &state.last_result {
+                Some(ConfigUploadResult { ok: true, .. }) => {
+                    ui.colored_label(
+                        Color32::GREEN,
+                        "Config uploaded, restart the robot code to apply it",
+                    );
+                }
+                Some(ConfigUploadResult { ok: false, errors }) => {
+                    ui.colored_label(Color32::RED, "Config rejected");
+                    for error in errors {
+                        ui.label(RichText::new(error).color(Color32::RED));
+                    }
+                }
+                None => {}
+            }
+
+            ui.separator();
+
+            let Some(snapshot) = snapshot else {
+                ui.label("Press refresh to fetch the robot's config");
+                return;
+            };
+
+            ui.label(format!("Read from {}", snapshot.path));
+            if let Some(error) = &snapshot.error {
+                ui.colored_label(Color32::RED, error);
+            }
+
+            egui::Grid::new("Robot Config Summary")
+                .num_columns(2)
+                .striped(true)
+                .show(ui, |ui| {
+                    for (label, value) in &snapshot.summary {
+                        ui.label(label);
+                        ui.label(value);
+                        ui.end_row();
+                    }
+                });
+
+            ui.separator();
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                // Read only, edits go through a file and upload
+                let mut text = snapshot.toml_text.as_str();
+                ui.add(
+                    egui::TextEdit::multiline(&mut text)
+                        .code_editor()
+                        .desired_width(f32::INFINITY),
+                );
+            });
+        });
+
+    if !open {
+        panels.close(Panel::RobotConfig);
+    }
+}
+
+/// How long the reasons an arm was rejected stay on screen
+const ARM_REJECTION_TOAST: Duration = Duration::from_secs(6);
+
+#[derive(Resource, Default, Debug)]
+struct ArmRejectionToast {
+    reasons: Vec<String>,
+    /// Real time the rejection arrived
+    at: Duration,
+}
+
+fn collect_arm_rejections(
+    mut toast: ResMut<ArmRejectionToast>,
+    mut rejections: EventReader<ArmRejected>,
+    time: Res<Time<Real>>,
+) {
+    for rejection in rejections.read() {
+        warn!("Arming rejected: {}", rejection.reasons.join(", "));
+
+        toast.reasons = rejection.reasons.clone();
+        toast.at = time.elapsed();
+    }
+}
+
+fn arm_rejection_toast(
+    mut contexts: EguiContexts,
+    toast: Res<ArmRejectionToast>,
+    time: Res<Time<Real>>,
+) {
+    if toast.reasons.is_empty() || time.elapsed().saturating_sub(toast.at) > ARM_REJECTION_TOAST {
+        return;
+    }
+
+    egui::Area::new(Id::new("Arm Rejected"))
+        .anchor(egui::Align2::CENTER_BOTTOM, egui::vec2(0.0, -40.0))
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.label(
+                    RichText::new("Arming rejected")
+                        .strong()
+                        .color(Color32::RED),
+                );
+
+                for reason in &toast.reasons {
+                    ui.label(reason);
+                }
+
+                ui.label(
+                    RichText::new(format!(
+                        "Hold arm for {:.1}s to force",
+                        input::FORCE_ARM_HOLD.as_secs_f32()
+                    ))
+                    .weak(),
+                );
+            });
+        });
+}
+
+/// How long the last network error stays on screen
+const NET_ERROR_TOAST: Duration = Duration::from_secs(8);
+
+#[derive(Resource, Default, Debug)]
+struct NetErrorToast {
+    report: Option<NetErrorReport>,
+    /// Real time the error arrived
+    at: Duration,
+}
+
+fn collect_net_errors(
+    mut toast: ResMut<NetErrorToast>,
+    mut reports: EventReader<NetErrorReport>,
+    time: Res<Time<Real>>,
+) {
+    if let Some(report) = reports.read().last() {
+        toast.report = Some(report.clone());
+        toast.at = time.elapsed();
+    }
+}
+
+fn net_error_toast(mut contexts: EguiContexts, toast: Res<NetErrorToast>, time: Res<Time<Real>>) {
+    let Some(report) = &toast.report else {
+        return;
+    };
+    if time.elapsed().saturating_sub(toast.at) > NET_ERROR_TOAST {
+        return;
+    }
+
+    let color = if report.class.is_retryable() {
+        Color32::YELLOW
+    } else {
+        Color32::RED
+    };
+
+    egui::Area::new(Id::new("Network Error"))
+        .anchor(egui::Align2::RIGHT_BOTTOM, egui::vec2(-20.0, -40.0))
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.label(
+                    RichText::new(format!("{} error", report.class))
+                        .strong()
+                        .color(color),
+                );
+
+                if let Some(peer) = &report.peer {
+                    ui.label(format!("Peer: {peer}"));
+                }
+                ui.label(&report.message);
+                ui.label(RichText::new(report.class.hint()).weak());
+            });
+        });
+}
+
+/// How long a peer's type registration mismatch stays on screen
+const TYPE_MISMATCH_TOAST: Duration = Duration::from_secs(10);
+
+#[derive(Resource, Default, Debug)]
+struct TypeMismatchToast {
+    /// The peer's description and how its types differ
+    mismatch: Option<(String, PeerTypeMismatch)>,
+    /// Real time the mismatch was found
+    at: Duration,
+}
+
+fn collect_type_mismatches(
+    mut toast: ResMut<TypeMismatchToast>,
+    peers: Query<(&Peer, &PeerTypeMismatch, Option<&Name>), Changed<PeerTypeMismatch>>,
+    time: Res<Time<Real>>,
+) {
+    for (peer, mismatch, name) in &peers {
+        if mismatch.is_empty() {
+            continue;
+        }
+
+        toast.mismatch = Some((peer.describe(name), mismatch.clone()));
+        toast.at = time.elapsed();
+    }
+}
+
+fn type_mismatch_toast(
+    mut contexts: EguiContexts,
+    toast: Res<TypeMismatchToast>,
+    time: Res<Time<Real>>,
+) {
+    let Some((peer, mismatch)) = &toast.mismatch else {
+        return;
+    };
+    if time.elapsed().saturating_sub(toast.at) > TYPE_MISMATCH_TOAST {
+        return;
+    }
+
+    egui::Area::new(Id::new("Type Mismatch"))
+        .anchor(egui::Align2::LEFT_BOTTOM, egui::vec2(20.0, -40.0))
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Frame::popup(ui.style()).show(ui, |ui| {
+                ui.label(
+                    RichText::new(format!("{peer} is running a different build"))
+                        .strong()
+                        .color(Color32::YELLOW),
+                );
+
+                if !mismatch.missing_remotely.is_empty() {
+                    ui.label("Only known here:");
+                    for token in &mismatch.missing_remotely {
+                        ui.label(RichText::new(token.as_ref()).monospace());
+                    }
+                }
+                if !mismatch.missing_locally.is_empty() {
+                    ui.label("Only known by the peer:");
+                    for token in &mismatch.missing_locally {
+                        ui.label(RichText::new(token.as_ref()).monospace());
+                    }
+                }
+
+                ui.label(RichText::new("These types are not replicated").weak());
+            });
+        });
+}
+
+#[derive(Resource, Default)]
+struct AboutRobot {
+    open: bool,
+}
+
+fn about_robot(
+    mut contexts: EguiContexts,
+    mut about: ResMut<AboutRobot>,
+    robots: Query<
+        (
+            &Name,
+            Option<&BuildInfo>,
+            Option<&OperatingSystem>,
+            Option<&Uptime>,
+        ),
+        With<Robot>,
+    >,
+) {
+    if !about.open {
+        return;
+    }
+
+    let context = contexts.ctx_mut();
+
+    egui::Window::new("About Robot")
+        .constrain_to(context.available_rect().shrink(20.0))
+        .collapsible(false)
+        .open(&mut about.open)
+        .show(context, |ui| {
+            if robots.is_empty() {
+                ui.label("No Robot");
+            }
+
+            for (name, build, os, uptime) in &robots {
+                let rows = about_rows(name.as_str(), build, os, uptime);
+
+                egui::Grid::new(name.as_str())
+                    .num_columns(2)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for (label, value) in &rows {
+                            ui.label(*label);
+                            ui.label(value);
+                            ui.end_row();
+                        }
+                    });
+
+                if ui.button("Copy to Clipboard").clicked() {
+                    let text = about_text(&rows);
+                    ui.output_mut(|output| output.copied_text = text);
+                }
+
+                ui.separator();
+            }
+        });
+}
+
+/// Label and value of each line in the about dialog
+///
+/// Robots running older builds may not publish everything
+fn about_rows(
+    name: &str,
+    build: Option<&BuildInfo>,
+    os: Option<&OperatingSystem>,
+    uptime: Option<&Uptime>,
+) -> Vec<(&'static str, String)> {
+    const UNKNOWN: &str = "Unknown";
+
+    fn or_unknown(value: Option<&String>) -> String {
+        value.cloned().unwrap_or_else(|| UNKNOWN.to_owned())
+    }
+
+    let mut rows = vec![("Robot", name.to_owned())];
+
+    if let Some(build) = build {
+        rows.extend([
+            ("Commit", build.git_hash.clone()),
+            ("Built", format!("{} UTC", build.build_timestamp)),
+            ("Profile", build.profile.clone()),
+        ]);
+    } else {
+        rows.push((
+            "Build",
+            "Unknown, robot build predates build info".to_owned(),
+        ));
+    }
+
+    if let Some(os) = os {
+        rows.extend([
+            ("Host", or_unknown(os.host_name.as_ref())),
+            (
+                "OS",
+                or_unknown(os.os_version.as_ref().or(os.name.as_ref())),
+            ),
+            ("Kernel", or_unknown(os.kernel_version.as_ref())),
+        ]);
+    } else {
+        rows.push(("OS", UNKNOWN.to_owned()));
+    }
+
+    rows.push((
+        "Uptime",
+        uptime
+            .map(|it| format_uptime(it.0))
+            .unwrap_or_else(|| UNKNOWN.to_owned()),
+    ));
+
+    rows
+}
+
+fn about_text(rows: &[(&str, String)]) -> String {
+    rows.iter()
+        .map(|(label, value)| format!("{label}: {value}\n"))
+        .collect()
+}
+
+fn crash_report_dialog(
+    mut contexts: EguiContexts,
+    robots: Query<(&Name, &PreviousCrashReport), With<Robot>>,
+    mut archive: EventWriter<ArchiveCrashReport>,
+) {
+    if robots.is_empty() {
+        return;
+    }
+
+    let context = contexts.ctx_mut();
+
+    egui::Window::new("Robot Crashed")
+        .constrain_to(context.available_rect().shrink(20.0))
+        .collapsible(false)
+        .show(context, |ui| {
+            for (name, PreviousCrashReport(report)) in &robots {
+                ui.label(
+                    RichText::new(format!("{name} panicked during its last run"))
+                        .color(Color32::YELLOW)
+                        .strong(),
+                );
+
+                let rows = crash_report_rows(report);
+                egui::Grid::new(("Crash Report", name.as_str()))
+                    .num_columns(2)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for (label, value) in &rows {
+                            ui.label(*label);
+                            ui.label(value);
+                            ui.end_row();
+                        }
+                    });
+
+                ui.label(RichText::new(&report.message).monospace());
+
+                ui.collapsing("Backtrace", |ui| {
+                    egui::ScrollArea::vertical()
+                        .max_height(300.0)
+                        .show(ui, |ui| {
+                            ui.label(RichText::new(&report.backtrace).monospace().small());
+                        });
+                });
+
+                ui.horizontal(|ui| {
+                    if ui.button("Copy to Clipboard").clicked() {
+                        let text = format!(
+                            "{}\n{}\n\n{}",
+                            about_text(&rows),
+                            report.message,
+                            report.backtrace
+                        );
+                        ui.output_mut(|output| output.copied_text = text);
+                    }
+
+                    // The robot renames the report so it isn't shown again
+                    if ui.button("Dismiss and Archive").clicked() {
+                        archive.send(ArchiveCrashReport);
+                    }
+                });
+
+                ui.separator();
+            }
+        });
+}
+
+/// Shown while a robot runs without its real motor data, or skipped part of it
+fn motor_data_warning(
+    mut contexts: EguiContexts,
+    robots: Query<(&Name, &MotorDataStatus), With<Robot>>,
+    mut reload: EventWriter<ReloadMotorData>,
+    // Skipped rows can be hidden until the status changes, the fallback can't
+    mut hidden: Local<Vec<MotorDataStatus>>,
+) {
+    hidden.retain(|status| robots.iter().any(|(_, it)| it == status));
+
+    let degraded = robots
+        .iter()
+        .filter(|(_, status)| status.is_degraded() && !hidden.contains(status))
+        .collect::<Vec<_>>();
+    if degraded.is_empty() {
+        return;
+    }
+
+    let context = contexts.ctx_mut();
+
+    egui::Window::new("Thrust Calibration Degraded")
+        .constrain_to(context.available_rect().shrink(20.0))
+        .collapsible(false)
+        .show(context, |ui| {
+            for (name, status) in degraded {
+                match &status.source {
+                    MotorDataSource::Fallback => {
+                        ui.label(
+                            RichText::new(format!(
+                                "{name} could not load its motor data and is using the built in table"
+                            ))
+                            .color(Color32::RED)
+                            .strong(),
+                        );
+                        ui.label("Thrust and current estimates are approximate");
+                    }
+                    MotorDataSource::FileOk(path) => {
+                        ui.label(
+                            RichText::new(format!("{name} skipped rows of {path}"))
+                                .color(Color32::YELLOW)
+                                .strong(),
+                        );
+                    }
+                }
+
+                if let Some(error) = &status.error {
+                    ui.label(RichText::new(error).monospace().small());
+                }
+
+                ui.horizontal(|ui| {
+                    // The robot retries every candidate path and replicates the new status
+                    if ui.button("Reload").clicked() {
+                        reload.send(ReloadMotorData);
+                    }
+
+                    if matches!(status.source, MotorDataSource::FileOk(_))
+                        && ui.button("Hide").clicked()
+                    {
+                        hidden.push(status.clone());
+                    }
+                });
+
+                ui.separator();
+            }
+        });
+}
+
+/// Shown while a robot's PWM chip doesn't hold the outputs it was given
+fn pwm_health_warning(mut contexts: EguiContexts, robots: Query<(&Name, &PwmHealth), With<Robot>>) {
+    let failed = robots
+        .iter()
+        .filter_map(|(name, health)| match health {
+            PwmHealth::Failed(mismatch) => Some((name, mismatch)),
+            PwmHealth::Healthy | PwmHealth::Recovered(_) => None,
+        })
+        .collect::<Vec<_>>();
+    if failed.is_empty() {
+        return;
+    }
+
+    let context = contexts.ctx_mut();
+
+    egui::Window::new("PWM Outputs Lost")
+        .constrain_to(context.available_rect().shrink(20.0))
+        .collapsible(false)
+        .show(context, |ui| {
+            for (name, mismatch) in failed {
+                ui.label(
+                    RichText::new(format!("{name}'s PWM chip is not holding its outputs"))
+                        .color(Color32::RED)
+                        .strong(),
+                );
+                ui.label("The robot was disarmed and can't arm until the chip recovers");
+                ui.label(RichText::new(mismatch).monospace().small());
+
+                ui.separator();
+            }
+        });
+}
+
+/// Label and value of each line describing when and what crashed
+fn crash_report_rows(report: &CrashReport) -> Vec<(&'static str, String)> {
+    let crashed = time::OffsetDateTime::from_unix_timestamp(report.timestamp as i64)
+        .map(|it| {
+            format!(
+                "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+                it.year(),
+                u8::from(it.month()),
+                it.day(),
+                it.hour(),
+                it.minute(),
+                it.second()
+            )
+        })
+        .unwrap_or_else(|_| "Unknown".to_owned());
+
+    vec![
+        ("Crashed", crashed),
+        ("Uptime", format_uptime(report.uptime)),
+        ("Commit", report.build.git_hash.clone()),
+        ("Built", format!("{} UTC", report.build.build_timestamp)),
+        ("Profile", report.build.profile.clone()),
+    ]
+}
+
+fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    let (days, hours, mins, secs) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+
+    if days > 0 {
+        format!("{days}d {hours}h {mins}m {secs}s")
+    } else if hours > 0 {
+        format!("{hours}h {mins}m {secs}s")
+    } else if mins > 0 {
+        format!("{mins}m {secs}s")
+    } else {
+        format!("{secs}s")
+    }
+}
+
+fn format_movement(movement: &Movement) -> String {
+    let Movement { force, torque } = movement;
+
+    format!(
+        "F {:+6.2} {:+6.2} {:+6.2}  T {:+6.2} {:+6.2} {:+6.2}",
+        force.x, force.y, force.z, torque.x, torque.y, torque.z
+    )
+}
+
+fn collect_robot_logs(mut logs: ResMut<RobotLogs>, mut records: EventReader<RemoteLogRecord>) {
+    for record in records.read() {
+        logs.dropped += record.dropped;
+
+        if logs.records.len() >= ROBOT_LOG_SCROLLBACK {
+            logs.records.pop_front();
+
+            // Keep the paused view on the same records
+            if let Some(paused) = &mut logs.paused {
+                *paused = paused.saturating_sub(1);
+            }
+        }
+
+        logs.records.push_back(record.clone());
+    }
+}
+
+fn robot_logs(
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    mut logs: ResMut<RobotLogs>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    egui::Window::new("Robot Logs")
+        .default_size((600.0, 300.0))
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            let logs = &mut *logs;
+
+            ui.horizontal(|ui| {
+                let paused = logs.paused.is_some();
+                if ui.selectable_label(paused, "Pause").clicked() {
+                    logs.paused = if paused {
+                        None
+                    } else {
+                        Some(logs.records.len())
+                    };
+                }
+
+                ui.separator();
+
+                ui.selectable_value(&mut logs.max_level, LogLevel::Error, "Error");
+                ui.selectable_value(&mut logs.max_level, LogLevel::Warn, "Warn");
+
+                ui.separator();
+
+                if ui.button("Clear").clicked() {
+                    logs.records.clear();
+                    logs.dropped = 0;
+                    logs.paused = logs.paused.map(|_| 0);
+                }
+
+                if logs.dropped > 0 {
+                    ui.label(
+                        RichText::new(format!("{} dropped", logs.dropped)).color(Color32::YELLOW),
+                    );
+                }
+            });
+
+            ui.separator();
+
+            let shown = logs.paused.unwrap_or(logs.records.len());
+
+            egui::ScrollArea::vertical()
+                .auto_shrink(false)
+                .stick_to_bottom(logs.paused.is_none())
+                .show(ui, |ui| {
+                    for record in logs.records.iter().take(shown) {
+                        if record.level > logs.max_level {
+                            continue;
+                        }
+
+                        let color = match record.level {
+                            LogLevel::Error => Color32::RED,
+                            LogLevel::Warn => Color32::YELLOW,
+                            _ => ui.visuals().text_color(),
+                        };
+
+                        let secs = record.timestamp.as_secs();
+                        let (hour, min, sec) = ((secs / 3600) % 24, (secs / 60) % 60, secs % 60);
+
+                        ui.label(
+                            RichText::new(format!(
+                                "{hour:02}:{min:02}:{sec:02} {:?} {}: {}",
+                                record.level, record.target, record.message
+                            ))
+                            .color(color)
+                            .monospace(),
+                        );
+                    }
+                });
+        });
+
+    if !open {
+        panels.close(Panel::RobotLogs);
+    }
+}
+
+fn collect_stills(
+    mut stills: ResMut<Stills>,
+    mut panels: ResMut<PanelManager>,
+    mut received: EventReader<FileReceived>,
+) {
+    for FileReceived { path, kind, .. } in received.read() {
+        // Downloads are tracked by the Robot Files panel
+        if *kind != TransferKind::Push {
+            continue;
+        }
+
+        if !stills.files.contains(path) {
+            stills.files.push(path.clone());
+        }
+
+        stills.selected = Some(path.clone());
+        panels.open(Panel::Stills);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn robot_files_view(
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    mut files: ResMut<RobotFiles>,
+    (mut downloads, mut reassembler, download_dir): (
+        ResMut<Downloads>,
+        ResMut<Reassembler>,
+        Res<DownloadDir>,
+    ),
+    (selected, robots): (
+        Res<SelectedRobot>,
+        Query<(&RobotId, &Name, &Peer, Option<&FileRoots>), With<Robot>>,
+    ),
+    mut listings: RpcSender<ListFiles, FileListing>,
+    mut download_requests: RpcSender<DownloadFile, DownloadStarted>,
+    mut deletions: RpcSender<DeleteFile, FileDeleted>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+    let files = &mut *files;
+
+    let robot = robots
+        .iter()
+        .find(|(robot, ..)| Some(**robot) == selected.0);
+
+    egui::Window::new("Robot Files")
+        .default_size((600.0, 500.0))
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            let Some((&robot_id, name, peer, roots)) = robot else {
+                ui.label("No robot selected");
+                return;
+            };
+            let roots = roots.map(|it| it.0.as_slice()).unwrap_or_default();
+            let source = DownloadSource {
+                robot: robot_id,
+                peer: peer.token,
+                save_dir: download_dir.0.join(name.as_str()),
+            };
+
+            if roots.is_empty() {
+                ui.label("The robot has no browsable directories, add some under file_roots");
+                return;
+            }
+
+            ui.horizontal(|ui| {
+                let before = files.dir.clone();
+                egui::ComboBox::from_id_source("Robot Files Root")
+                    .selected_text(files.dir.as_deref().unwrap_or("Pick a directory"))
+                    .show_ui(ui, |ui| {
+                        for root in roots {
+                            ui.selectable_value(&mut files.dir, Some(root.clone()), root);
+                        }
+                    });
+
+                let refresh = ui.add_enabled(files.dir.is_some(), egui::Button::new("Refresh"));
+                if refresh.clicked() || files.dir != before {
+                    files.listing = None;
+                    files.error = None;
+                    files.refresh(&mut listings);
+                }
+
+                if files.pending_listing.is_some() {
+                    ui.spinner();
+                }
+            });
+
+            if let Some(error) = &files.error {
+                ui.colored_label(Color32::RED, error);
+            }
+
+            let mut any_download = false;
+            for download in downloads.iter() {
+                any_download = true;
+
+                ui.horizontal(|ui| {
+                    ui.label(&download.name);
+
+                    match &download.state {
+                        DownloadState::Requested => {
+                            ui.spinner();
+                        }
+                        DownloadState::Receiving => {
+                            ui.add(
+                                widgets::ProgressBar::new(download.fraction())
+                                    .show_percentage()
+                                    .desired_width(200.0),
+                            );
+                        }
+                        DownloadState::Interrupted => {
+                            ui.label(format!(
+                                "Interrupted at {:.0}%, resumes once the robot is back",
+                                download.fraction() * 100.0
+                            ));
+                        }
+                        DownloadState::Done(path) => {
+                            ui.label(format!("Saved to {}", path.display()));
+                        }
+                        DownloadState::Failed(err) => {
+                            ui.colored_label(Color32::RED, err);
+                        }
+                    }
+                });
+            }
+
+            // Downloads from other robots resume once that robot is back
+            let resume = downloads
+                .iter()
+                .filter(|it| it.can_resume() && it.source.robot == robot_id)
+                .map(|it| (it.dir.clone(), it.name.clone()))
+                .collect::<Vec<_>>();
+
+            if any_download {
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(!resume.is_empty(), egui::Button::new("Resume"))
+                        .clicked()
+                    {
+                        for (dir, name) in &resume {
+                            robot_files::start_download(
+                                &mut downloads,
+                                &mut reassembler,
+                                &mut download_requests,
+                                source.clone(),
+                                dir,
+                                name,
+                            );
+                        }
+                    }
+
+                    if ui.button("Clear Finished").clicked() {
+                        downloads.clear_finished();
+                    }
+                });
+            }
+
+            ui.separator();
+
+            let (Some(dir), Some(listing)) = (files.dir.clone(), &files.listing) else {
+                ui.label("Pick a directory to list its files");
+                return;
+            };
+
+            if listing.truncated {
+                ui.label(format!(
+                    "Only the newest {} files are shown",
+                    listing.entries.len()
+                ));
+            }
+
+            if listing.entries.is_empty() && listing.error.is_none() {
+                ui.label("No files");
+            }
+
+            let mut download = None;
+            let mut delete = None;
+
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::Grid::new("Robot Files")
+                    .num_columns(4)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        ui.label(RichText::new("Name").strong());
+                        ui.label(RichText::new("Size").strong());
+                        ui.label(RichText::new("Modified").strong());
+                        ui.label("");
+                        ui.end_row();
+
+                        for entry in &listing.entries {
+                            ui.label(&entry.name);
+                            ui.label(robot_files::format_size(entry.size));
+                            ui.label(format_timestamp(Duration::from_secs(entry.modified)));
+
+                            ui.horizontal(|ui| {
+                                if ui.button("Download").clicked() {
+                                    download = Some(entry.name.clone());
+                                }
+                                if ui.button("Delete").clicked() {
+                                    delete = Some(entry.name.clone());
+                                }
+                            });
+                            ui.end_row();
+                        }
+                    });
+            });
+
+            if let Some(name) = download {
+                robot_files::start_download(
+                    &mut downloads,
+                    &mut reassembler,
+                    &mut download_requests,
+                    source,
+                    &dir,
+                    &name,
+                );
+            }
+            if delete.is_some() {
+                files.confirm_delete = delete;
+            }
+        });
+
+    if let (Some(dir), Some(name)) = (files.dir.clone(), files.confirm_delete.clone()) {
+        let mut decided = false;
+
+        egui::Window::new("Delete File")
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .show(context, |ui| {
+                ui.label(format!("Delete {name} from {dir} on the robot?"));
+                ui.label(RichText::new("This can't be undone").weak());
+
+                ui.horizontal(|ui| {
+                    if ui.button("Delete").clicked() {
+                        files.error = None;
+                        files.pending_delete = Some(deletions.send(DeleteFile {
+                            dir: dir.clone(),
+                            name: name.clone(),
+                        }));
+                        decided = true;
+                    }
+                    if ui.button("Cancel").clicked() {
+                        decided = true;
+                    }
+                });
+            });
+
+        if decided {
+            files.confirm_delete = None;
+        }
+    }
+
+    if !open {
+        panels.close(Panel::RobotFiles);
+    }
+}
+
+fn feed_overlay_view(
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    mut config: ResMut<FeedOverlayConfig>,
+) {
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    // Only touch the config on edits so it is not saved every frame
+    let mut edited = config.clone();
+
+    egui::Window::new("Feed Overlay")
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            ui.checkbox(&mut edited.pinned_only, "Only over the master feed");
+            ui.separator();
+
+            egui::Grid::new("Feed Overlay Widgets")
+                .striped(true)
+                .show(ui, |ui| {
+                    for widget in OverlayWidget::ALL {
+                        let widget_config = edited.widget_mut(widget);
+
+                        ui.checkbox(&mut widget_config.enabled, widget.name());
+
+                        egui::ComboBox::from_id_source(("Feed Overlay Anchor", widget))
+                            .selected_text(widget_config.anchor.name())
+                            .show_ui(ui, |ui| {
+                                for anchor in OverlayAnchor::ALL {
+                                    ui.selectable_value(
+                                        &mut widget_config.anchor,
+                                        anchor,
+                                        anchor.name(),
+                                    );
+                                }
+                            });
+                        ui.end_row();
+                    }
+                });
+
+            if ui.button("Reset").clicked() {
+                edited = FeedOverlayConfig::default();
+            }
+        });
+
+    if edited != *config {
+        *config = edited;
+    }
+
+    if !open {
+        panels.close(Panel::FeedOverlay);
+    }
+}
+
+fn stills_view(
+    mut contexts: EguiContexts,
+    mut panels: ResMut<PanelManager>,
+    mut stills: ResMut<Stills>,
+    mut images: ResMut<Assets<Image>>,
+    cameras: Query<&Name, With<Camera>>,
+    mut capture: EventWriter<CaptureStill>,
+    mut errors: EventWriter<ErrorEvent>,
+) {
+    let stills = &mut *stills;
+
+    let shown_path = stills.shown.as_ref().map(|it| &it.path);
+    if stills.selected.as_ref() != shown_path {
+        if let Some(old) = stills.shown.take() {
+            contexts.remove_image(&old.handle);
+            images.remove(&old.handle);
+        }
+
+        if let Some(path) = &stills.selected {
+            match load_still(path) {
+                Ok(image) => {
+                    let size = image.size_f32();
+                    let handle = images.add(image);
+                    let texture = contexts.add_image(handle.clone_weak());
+
+                    stills.shown = Some(ShownStill {
+                        path: path.clone(),
+                        handle,
+                        texture,
+                        size: (size.x, size.y).into(),
+                    });
+                }
+                Err(err) => {
+                    errors.send(err.context(format!("Show {}", path.display())).into());
+                    stills.selected = None;
+                }
+            }
+        }
+    }
+
+    let context = contexts.ctx_mut();
+    let mut open = true;
+
+    egui::Window::new("Stills")
+        .default_size((800.0, 500.0))
+        .constrain_to(context.available_rect().shrink(20.0))
+        .open(&mut open)
+        .show(context, |ui| {
+            ui.horizontal_wrapped(|ui| {
+                if cameras.is_empty() {
+                    ui.label("No cameras");
+                }
+
+                for name in &cameras {
+                    if ui.button(format!("Capture {}", name.as_str())).clicked() {
+                        capture.send(CaptureStill {
+                            camera: name.as_str().to_owned(),
+                        });
+                    }
+                }
+            });
+
+            ui.separator();
+
+            ui.horizontal_top(|ui| {
+                egui::ScrollArea::vertical()
+                    .id_source("Still List")
+                    .max_width(200.0)
+                    .show(ui, |ui| {
+                        if stills.files.is_empty() {
+                            ui.label("No stills");
+                        }
+
+                        for path in stills.files.iter().rev() {
+                            let name = path
+                                .file_name()
+                                .map(|it| it.to_string_lossy().into_owned())
+                                .unwrap_or_default();
+                            let selected = stills.selected.as_ref() == Some(path);
+
+                            if ui.selectable_label(selected, name).clicked() {
+                                stills.selected = Some(path.clone());
+                            }
+                        }
+                    });
+
+                ui.separator();
+
+                if let Some(shown) = &stills.shown {
+                    let available = ui.available_size();
+                    let scale = (available.x / shown.size.x)
+                        .min(available.y / shown.size.y)
+                        .min(1.0);
+
+                    ui.image(SizedTexture::new(shown.texture, shown.size * scale));
+                }
+            });
+        });
+
+    if !open {
+        panels.close(Panel::Stills);
+    }
+}
+
+/// Runs the open and close hooks of panels whose state changed and persists the open panels
+fn apply_panel_transitions(world: &mut World, mut last_open: Local<Option<BTreeSet<Panel>>>) {
+    let open: BTreeSet<Panel> = world.resource::<PanelManager>().open_panels().collect();
+
+    let Some(last) = last_open.replace(open.clone()) else {
+        // Panels restored from the last run
+        for panel in &open {
+            panel.on_open(world);
+        }
+
+        return;
+    };
+
+    if last == open {
+        return;
+    }
+
+    for panel in open.difference(&last) {
+        panel.on_open(world);
+    }
+    for panel in last.difference(&open) {
+        panel.on_close(world);
+    }
+
+    let rst = world.resource::<PanelManager>().save(PANEL_STATE_PATH);
+    if let Err(err) = rst {
+        world.send_event(ErrorEvent(err));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::world::World;
+    use std::time::Duration;
+
+    use bevy::math::Vec2;
+    use common::{
+        components::{
+            BuildInfo, CrashReport, DepthTemperatureProfile, OperatingSystem, PositionEstimate,
+            PwmManualControl, Robot, RobotId, Uptime,
+        },
+        ecs_sync::NetId,
+        types::units::{Celsius, Meters},
+    };
+
+    use super::{
+        about_rows, about_text, crash_report_rows, format_uptime, profile_csv, Panel, PanelManager,
+        PositionTrack, PwmControl, POSITION_TRACK_LEN,
+    };
+
+    #[test]
+    fn panel_transitions() {
+        let mut panels = PanelManager::default();
+        assert_eq!(panels.open_panels().count(), 0);
+
+        panels.open(Panel::Timer);
+        panels.open(Panel::Timer);
+        assert!(panels.is_open(Panel::Timer));
+        assert_eq!(panels.open_panels().count(), 1);
+
+        panels.toggle(Panel::PwmControl);
+        assert!(panels.is_open(Panel::PwmControl));
+        panels.toggle(Panel::PwmControl);
+        assert!(!panels.is_open(Panel::PwmControl));
+
+        panels.close(Panel::Timer);
+        panels.close(Panel::Inspector);
+        assert_eq!(panels.open_panels().count(), 0);
+    }
+
+    #[test]
+    fn panel_persistence_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("panels.toml");
+
+        let mut panels = PanelManager::default();
+        panels.open(Panel::Inspector);
+        panels.open(Panel::Thrusters);
+
+        panels.save(&path).unwrap();
+        let loaded = PanelManager::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, panels);
+
+        // A missing file means a fresh install
+        assert_eq!(PanelManager::load(&path).unwrap(), PanelManager::default());
+    }
+
+    #[test]
+    fn closing_pwm_control_releases_manual_control() {
+        let mut world = World::new();
+        let robot = world.spawn((Robot, PwmManualControl)).id();
+
+        Panel::PwmControl.on_open(&mut world);
+        assert!(!world.resource::<PwmControl>().0);
+
+        Panel::PwmControl.on_close(&mut world);
+        assert!(world.get::<PwmManualControl>(robot).is_none());
+    }
+
+    #[test]
+    fn depth_profile_csv() {
+        let profile = DepthTemperatureProfile(vec![
+            (Meters(0.1), Celsius(20.5)),
+            (Meters(0.2), Celsius(19.25)),
+        ]);
+
+        assert_eq!(
+            profile_csv(&profile),
+            "depth_m,temperature_c\n0.100,20.500\n0.200,19.250\n"
+        );
+        assert_eq!(
+            profile_csv(&DepthTemperatureProfile::default()),
+            "depth_m,temperature_c\n"
+        );
+    }
+
+    #[test]
+    fn uptime_formatting() {
+        assert_eq!(format_uptime(Duration::from_secs(42)), "42s");
+        assert_eq!(format_uptime(Duration::from_secs(5 * 60 + 3)), "5m 3s");
+        assert_eq!(format_uptime(Duration::from_secs(2 * 3600 + 7)), "2h 0m 7s");
+        assert_eq!(
+            format_uptime(Duration::from_secs(3 * 86400 + 4 * 3600 + 12 * 60 + 5)),
+            "3d 4h 12m 5s"
+        );
+    }
+
+    #[test]
+    fn about_text_handles_missing_components() {
+        let rows = about_rows("Robot", None, None, None);
+        assert_eq!(
+            about_text(&rows),
+            "Robot: Robot\n\
+             Build: Unknown, robot build predates build info\n\
+             OS: Unknown\n\
+             Uptime: Unknown\n"
+        );
+
+        let build = BuildInfo {
+            git_hash: "abc1234".to_owned(),
+            build_timestamp: "2024-05-01 12:00:00".to_owned(),
+            profile: "release".to_owned(),
+        };
+        let os = OperatingSystem {
+            name: Some("Debian".to_owned()),
+            kernel_version: Some("6.1.0-rpi7".to_owned()),
+            os_version: None,
+            distro: Some("debian".to_owned()),
+            host_name: None,
+        };
+        let rows = about_rows(
+            "Robot",
+            Some(&build),
+            Some(&os),
+            Some(&Uptime(Duration::from_secs(90))),
+        );
+        assert_eq!(
+            about_text(&rows),
+            "Robot: Robot\n\
+             Commit: abc1234\n\
+             Built: 2024-05-01 12:00:00 UTC\n\
+             Profile: release\n\
+             Host: Unknown\n\
+             OS: Debian\n\
+             Kernel: 6.1.0-rpi7\n\
+             Uptime: 1m 30s\n"
+        );
+    }
+
+    #[test]
+    fn crash_report_rows_format_the_report() {
+        let report = CrashReport {
+            message: "panicked at src/main.rs:1:1:\nOh no".to_owned(),
+            backtrace: String::new(),
+            timestamp: 1_714_564_800,
+            build: BuildInfo {
+                git_hash: "abc1234".to_owned(),
+                build_timestamp: "2024-05-01 12:00:00".to_owned(),
+                profile: "release".to_owned(),
+            },
+            uptime: Duration::from_secs(3725),
+        };
+
+        assert_eq!(
+            about_text(&crash_report_rows(&report)),
+            "Crashed: 2024-05-01 12:00:00 UTC\n\
+             Uptime: 1h 2m 5s\n\
+             Commit: abc1234\n\
+             Built: 2024-05-01 12:00:00 UTC\n\
+             Profile: release\n"
+        );
+    }
+
+    fn estimate(x: f32, y: f32, confidence_decay: f32) -> PositionEstimate {
+        PositionEstimate {
+            xy: Vec2::new(x, y),
+            confidence_decay,
+        }
+    }
+
+    #[test]
+    fn position_track_spacing_and_length() {
+        let mut track = PositionTrack::default();
+        let robot = RobotId(NetId::random());
+
+        // Jitter under the spacing is dropped
+        track.record(robot, &estimate(0.0, 0.0, 1.0));
+        track.record(robot, &estimate(0.01, 0.0, 1.0));
+        track.record(robot, &estimate(0.1, 0.0, 1.0));
+        assert_eq!(track.points().count(), 2);
+
+        for step in 0..POSITION_TRACK_LEN * 2 {
+            track.record(robot, &estimate(0.0, step as f32, 0.5));
+        }
+        assert_eq!(track.points().count(), POSITION_TRACK_LEN);
+        assert_eq!(
+            track.points().last(),
+            Some(Vec2::new(0.0, (POSITION_TRACK_LEN * 2 - 1) as f32))
+        );
+    }
+
+    #[test]
+    fn position_track_clears_on_reset() {
+        let mut track = PositionTrack::default();
+        let robot = RobotId(NetId::random());
+
+        track.record(robot, &estimate(0.0, 0.0, 0.9));
+        track.record(robot, &estimate(5.0, 5.0, 0.8));
+
+        // The robot reset its estimate, confidence jumped back up
+        track.record(robot, &estimate(0.0, 0.0, 1.0));
+        assert_eq!(track.points().collect::<Vec<_>>(), [Vec2::ZERO]);
+    }
+
+    #[test]
+    fn position_track_follows_one_robot() {
+        let mut track = PositionTrack::default();
+        let robot = RobotId(NetId::random());
+        let other = RobotId(NetId::random());
+
+        track.record(robot, &estimate(0.0, 0.0, 0.9));
+        track.record(robot, &estimate(5.0, 5.0, 0.8));
+
+        // A less confident robot doesn't continue the first one's track
+        track.record(other, &estimate(1.0, 1.0, 0.5));
+        assert_eq!(track.points().collect::<Vec<_>>(), [Vec2::ONE]);
     }
 }
@@ -0,0 +1,518 @@
+//! BGR to RGBA conversion of video frames
+//!
+//! Opencv hands us BGR frames but bevy wants RGBA. The CPU path converts with `cvt_color` on the
+//! video thread, the GPU path only copies the BGR bytes and swizzles them in a compute shader
+//! that writes straight into the camera's `Image`.
+
+use std::{mem, sync::Arc};
+
+use ahash::{HashMap, HashSet};
+use anyhow::{bail, Context};
+use bevy::{
+    asset::load_internal_asset,
+    prelude::*,
+    render::{
+        graph::CameraDriverLabel,
+        render_asset::{RenderAssetUsages, RenderAssets},
+        render_graph::{self, RenderGraph, RenderLabel},
+        render_resource::{
+            binding_types::{storage_buffer_read_only, texture_storage_2d},
+            BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, Buffer,
+            BufferDescriptor, BufferUsages, CachedComputePipelineId, ComputePassDescriptor,
+            ComputePipelineDescriptor, Extent3d, PipelineCache, ShaderStages, StorageTextureAccess,
+            TextureDimension, TextureFormat, TextureUsages, TextureViewDescriptor,
+        },
+        renderer::{RenderAdapter, RenderContext, RenderDevice, RenderQueue},
+        Extract, Render, RenderApp, RenderSet,
+    },
+};
+use opencv::{core::CV_8UC3, prelude::*};
+
+use crate::video_stream::{self, VideoThread};
+
+pub struct VideoConversionPlugin;
+
+impl Plugin for VideoConversionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<VideoConversionMode>();
+
+        load_internal_asset!(
+            app,
+            CONVERSION_SHADER,
+            "video_conversion.wgsl",
+            Shader::from_wgsl
+        );
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app
+            .init_resource::<ConversionBuffers>()
+            .init_resource::<PreparedConversions>()
+            .add_systems(ExtractSchedule, extract_conversions)
+            .add_systems(
+                Render,
+                prepare_conversions.in_set(RenderSet::PrepareBindGroups),
+            );
+
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        render_graph.add_node(ConversionLabel, ConversionNode);
+        render_graph.add_node_edge(ConversionLabel, CameraDriverLabel);
+    }
+
+    fn finish(&self, app: &mut App) {
+        let supported = match app.get_sub_app_mut(RenderApp) {
+            Ok(render_app) => {
+                let supported = render_app
+                    .world
+                    .get_resource::<RenderAdapter>()
+                    // Needs compute shaders and sRGB views of RGBA textures
+                    .is_some_and(|adapter| {
+                        adapter.get_downlevel_capabilities().is_webgpu_compliant()
+                    });
+
+                if supported {
+                    render_app.init_resource::<ConversionPipeline>();
+                }
+
+                supported
+            }
+            Err(_) => false,
+        };
+
+        let mut mode = app.world.resource_mut::<VideoConversionMode>();
+        if !supported && *mode == VideoConversionMode::Gpu {
+            warn!("GPU video conversion is unavailable, falling back to the CPU");
+            *mode = VideoConversionMode::Cpu;
+        }
+    }
+}
+
+/// Where video frames get converted from BGR to RGBA
+///
+/// Read when a camera's video thread starts
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoConversionMode {
+    #[default]
+    Cpu,
+    Gpu,
+}
+
+const CONVERSION_SHADER: Handle<Shader> =
+    Handle::weak_from_u128(0x5f1c_92d4_0a7e_4b3c_8e61_d2f0_47a9_b815);
+const WORKGROUP_SIZE: u32 = 8;
+
+/// A frame as it leaves the video thread
+pub enum VideoFrame {
+    /// Already converted on the video thread
+    Rgba(Image),
+    /// Still needs to be converted on the GPU
+    Bgr(BgrFrame),
+}
+
+impl VideoFrame {
+    pub fn empty(mode: VideoConversionMode) -> Self {
+        match mode {
+            VideoConversionMode::Cpu => VideoFrame::Rgba(Image::default()),
+            VideoConversionMode::Gpu => VideoFrame::Bgr(BgrFrame::default()),
+        }
+    }
+
+    /// Resolution of the frame in pixels
+    pub fn size(&self) -> UVec2 {
+        match self {
+            VideoFrame::Rgba(image) => image.size(),
+            VideoFrame::Bgr(frame) => UVec2::new(frame.width, frame.height),
+        }
+    }
+
+    /// Copies `mat` into this frame, reusing its allocation
+    pub fn fill(&mut self, mat: &Mat) -> anyhow::Result<()> {
+        match self {
+            VideoFrame::Rgba(image) => video_stream::mat_to_image(mat, image),
+            VideoFrame::Bgr(frame) => mat_to_bgr_frame(mat, frame),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BgrFrame {
+    pub width: u32,
+    pub height: u32,
+    /// Tightly packed BGR bytes, zero padded to a whole number of `u32`s for the shader
+    pub data: Vec<u8>,
+}
+
+/// Copies the bytes of a BGR `Mat` without converting them
+pub fn mat_to_bgr_frame(mat: &Mat, frame: &mut BgrFrame) -> anyhow::Result<()> {
+    if mat.typ() != CV_8UC3 {
+        bail!("Expected a BGR frame, got mat type {}", mat.typ());
+    }
+
+    let size = mat.size().context("Get size")?;
+    frame.width = size.width as u32;
+    frame.height = size.height as u32;
+    frame.data.clear();
+
+    if mat.is_continuous() {
+        frame
+            .data
+            .extend_from_slice(mat.data_bytes().context("Get frame bytes")?);
+    } else {
+        // Cloning drops the row padding
+        let mat = mat.try_clone().context("Copy frame")?;
+        frame
+            .data
+            .extend_from_slice(mat.data_bytes().context("Get frame bytes")?);
+    }
+
+    frame.data.resize(frame.data.len().next_multiple_of(4), 0);
+
+    Ok(())
+}
+
+/// The image the GPU path writes into
+///
+/// Stored as plain RGBA so it can be bound as a storage texture but sampled as sRGB, the same as
+/// images from the CPU path
+pub fn conversion_target(width: u32, height: u32) -> Image {
+    let mut image = Image::new_fill(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0, 0, 0, 255],
+        TextureFormat::Rgba8Unorm,
+        RenderAssetUsages::RENDER_WORLD | RenderAssetUsages::MAIN_WORLD,
+    );
+
+    image.texture_descriptor.usage =
+        TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING | TextureUsages::COPY_DST;
+    image.texture_descriptor.view_formats = &[TextureFormat::Rgba8UnormSrgb];
+    image.texture_view_descriptor = Some(TextureViewDescriptor {
+        format: Some(TextureFormat::Rgba8UnormSrgb),
+        ..default()
+    });
+
+    image
+}
+
+pub fn is_conversion_target(image: &Image, width: u32, height: u32) -> bool {
+    let size = image.texture_descriptor.size;
+
+    image.texture_descriptor.format == TextureFormat::Rgba8Unorm
+        && size.width == width
+        && size.height == height
+}
+
+/// The latest unconverted frame for a camera's `Handle<Image>`
+#[derive(Component)]
+pub struct PendingConversion(pub Arc<BgrFrame>);
+
+impl PendingConversion {
+    /// Swaps in a new frame, returning the old one if nothing else holds it
+    pub fn replace(&mut self, frame: BgrFrame) -> Option<BgrFrame> {
+        let old = mem::replace(&mut self.0, Arc::new(frame));
+        Arc::into_inner(old)
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+struct ConversionLabel;
+
+#[derive(Resource, Default)]
+struct ExtractedConversions {
+    frames: Vec<(AssetId<Image>, Arc<BgrFrame>)>,
+    /// Images of cameras that still have a video thread
+    live: HashSet<AssetId<Image>>,
+}
+
+/// Upload buffer of each camera image, dropped along with the camera's video thread
+#[derive(Resource, Default)]
+struct ConversionBuffers(HashMap<AssetId<Image>, Buffer>);
+
+struct PreparedConversion {
+    bind_group: BindGroup,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Resource, Default)]
+struct PreparedConversions(Vec<PreparedConversion>);
+
+#[derive(Resource)]
+struct ConversionPipeline {
+    layout: BindGroupLayout,
+    pipeline: CachedComputePipelineId,
+}
+
+impl FromWorld for ConversionPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+
+        let layout = render_device.create_bind_group_layout(
+            "video_conversion_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    storage_buffer_read_only::<u32>(false),
+                    texture_storage_2d(TextureFormat::Rgba8Unorm, StorageTextureAccess::WriteOnly),
+                ),
+            ),
+        );
+
+        let pipeline =
+            world
+                .resource::<PipelineCache>()
+                .queue_compute_pipeline(ComputePipelineDescriptor {
+                    label: Some("video_conversion_pipeline".into()),
+                    layout: vec![layout.clone()],
+                    push_constant_ranges: Vec::new(),
+                    shader: CONVERSION_SHADER,
+                    shader_defs: Vec::new(),
+                    entry_point: "convert".into(),
+                });
+
+        Self { layout, pipeline }
+    }
+}
+
+fn extract_conversions(
+    mut cmds: Commands,
+    frames: Extract<Query<(&Handle<Image>, &PendingConversion), Changed<PendingConversion>>>,
+    cameras: Extract<Query<&Handle<Image>, With<VideoThread>>>,
+) {
+    cmds.insert_resource(ExtractedConversions {
+        frames: frames
+            .iter()
+            .map(|(handle, frame)| (handle.id(), frame.0.clone()))
+            .collect(),
+        live: cameras.iter().map(Handle::id).collect(),
+    });
+}
+
+fn prepare_conversions(
+    mut prepared: ResMut<PreparedConversions>,
+    mut buffers: ResMut<ConversionBuffers>,
+    extracted: Option<Res<ExtractedConversions>>,
+    pipeline: Option<Res<ConversionPipeline>>,
+    images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+) {
+    prepared.0.clear();
+
+    let (Some(extracted), Some(pipeline)) = (extracted, pipeline) else {
+        return;
+    };
+
+    buffers.0.retain(|image, _| extracted.live.contains(image));
+
+    for (image, frame) in &extracted.frames {
+        // The target is recreated when the resolution changes, skip frames until it catches up
+        let Some(gpu_image) = images.get(*image) else {
+            continue;
+        };
+        if gpu_image.size != Vec2::new(frame.width as f32, frame.height as f32)
+            || frame.data.is_empty()
+        {
+            continue;
+        }
+
+        let buffer = buffers.0.entry(*image).or_insert_with(|| {
+            render_device.create_buffer(&BufferDescriptor {
+                label: Some("video_conversion_frame"),
+                size: frame.data.len() as u64,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        });
+        if buffer.size() != frame.data.len() as u64 {
+            *buffer = render_device.create_buffer(&BufferDescriptor {
+                label: Some("video_conversion_frame"),
+                size: frame.data.len() as u64,
+                usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+        }
+
+        render_queue.write_buffer(buffer, 0, &frame.data);
+
+        // The default view is sRGB which cant be used for storage
+        let view = gpu_image.texture.create_view(&TextureViewDescriptor {
+            format: Some(TextureFormat::Rgba8Unorm),
+            ..default()
+        });
+
+        let bind_group = render_device.create_bind_group(
+            "video_conversion_bind_group",
+            &pipeline.layout,
+            &BindGroupEntries::sequential((buffer.as_entire_binding(), &view)),
+        );
+
+        prepared.0.push(PreparedConversion {
+            bind_group,
+            width: frame.width,
+            height: frame.height,
+        });
+    }
+}
+
+struct ConversionNode;
+
+impl render_graph::Node for ConversionNode {
+    fn run(
+        &self,
+        _graph: &mut render_graph::RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), render_graph::NodeRunError> {
+        let Some(pipeline) = world.get_resource::<ConversionPipeline>() else {
+            return Ok(());
+        };
+        let Some(compute_pipeline) = world
+            .resource::<PipelineCache>()
+            .get_compute_pipeline(pipeline.pipeline)
+        else {
+            return Ok(());
+        };
+
+        let prepared = world.resource::<PreparedConversions>();
+        if prepared.0.is_empty() {
+            return Ok(());
+        }
+
+        let mut pass =
+            render_context
+                .command_encoder()
+                .begin_compute_pass(&ComputePassDescriptor {
+                    label: Some("video_conversion"),
+                    ..default()
+                });
+        pass.set_pipeline(compute_pipeline);
+
+        for conversion in &prepared.0 {
+            pass.set_bind_group(0, &conversion.bind_group, &[]);
+            pass.dispatch_workgroups(
+                conversion.width.div_ceil(WORKGROUP_SIZE),
+                conversion.height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use bevy::render::texture::Image;
+    use opencv::{
+        core::{Scalar, CV_8UC3},
+        prelude::*,
+    };
+    use test::Bencher;
+
+    use crate::video_stream::mat_to_image;
+
+    use super::{mat_to_bgr_frame, BgrFrame};
+
+    /// Every channel gets a different gradient so swapped channels show up
+    fn test_pattern(width: i32, height: i32) -> Mat {
+        let mut mat =
+            Mat::new_rows_cols_with_default(height, width, CV_8UC3, Scalar::all(0.0)).unwrap();
+
+        let bytes = mat.data_bytes_mut().unwrap();
+        for (pixel, bgr) in bytes.chunks_exact_mut(3).enumerate() {
+            let x = pixel as i32 % width;
+            let y = pixel as i32 / width;
+
+            bgr[0] = (x * 7 % 256) as u8;
+            bgr[1] = (y * 13 % 256) as u8;
+            bgr[2] = ((x + y) * 3 % 256) as u8;
+        }
+
+        mat
+    }
+
+    /// The arithmetic of `video_conversion.wgsl` for a single pixel, including the unorm
+    /// round trip the texture store does
+    fn shader_pixel(words: &[u32], width: u32, x: u32, y: u32) -> [u8; 4] {
+        let byte_at = |index: u32| (words[(index / 4) as usize] >> ((index % 4) * 8)) & 0xff;
+        let unorm = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+
+        let base = (y * width + x) * 3;
+        let b = byte_at(base) as f32 / 255.0;
+        let g = byte_at(base + 1) as f32 / 255.0;
+        let r = byte_at(base + 2) as f32 / 255.0;
+
+        [unorm(r), unorm(g), unorm(b), unorm(1.0)]
+    }
+
+    fn words(frame: &BgrFrame) -> Vec<u32> {
+        frame
+            .data
+            .chunks_exact(4)
+            .map(|it| u32::from_le_bytes(it.try_into().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn gpu_path_matches_cpu_path() {
+        // Odd sizes exercise the word padding
+        for (width, height) in [(64, 48), (33, 7)] {
+            let mat = test_pattern(width, height);
+
+            let mut image = Image::default();
+            mat_to_image(&mat, &mut image).unwrap();
+
+            let mut frame = BgrFrame::default();
+            mat_to_bgr_frame(&mat, &mut frame).unwrap();
+            assert_eq!(frame.data.len() % 4, 0);
+            assert_eq!((frame.width, frame.height), (width as u32, height as u32));
+
+            let words = words(&frame);
+            for y in 0..frame.height {
+                for x in 0..frame.width {
+                    let idx = ((y * frame.width + x) * 4) as usize;
+                    let cpu = &image.data[idx..idx + 4];
+                    let gpu = shader_pixel(&words, frame.width, x, y);
+
+                    for (cpu, gpu) in cpu.iter().zip(gpu) {
+                        assert!(cpu.abs_diff(gpu) <= 1, "Pixel {x}, {y}: {cpu:?} != {gpu:?}");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_non_bgr_mats() {
+        let mat =
+            Mat::new_rows_cols_with_default(4, 4, opencv::core::CV_8UC1, Scalar::all(0.0)).unwrap();
+
+        assert!(mat_to_bgr_frame(&mat, &mut BgrFrame::default()).is_err());
+    }
+
+    // Video thread side of each path at 1080p, the shader itself runs on the GPU
+    #[bench]
+    fn bench_cpu_conversion(b: &mut Bencher) {
+        let mat = test_pattern(1920, 1080);
+        let mut image = Image::default();
+
+        b.iter(|| mat_to_image(&mat, &mut image).unwrap());
+    }
+
+    #[bench]
+    fn bench_gpu_upload(b: &mut Bencher) {
+        let mat = test_pattern(1920, 1080);
+        let mut frame = BgrFrame::default();
+
+        b.iter(|| mat_to_bgr_frame(&mat, &mut frame).unwrap());
+    }
+}
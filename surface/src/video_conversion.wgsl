@@ -0,0 +1,24 @@
+// Converts tightly packed BGR bytes into an RGBA texture
+
+@group(0) @binding(0) var<storage, read> bgr: array<u32>;
+@group(0) @binding(1) var output: texture_storage_2d<rgba8unorm, write>;
+
+fn byte_at(index: u32) -> u32 {
+    return (bgr[index / 4u] >> ((index % 4u) * 8u)) & 0xffu;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn convert(@builtin(global_invocation_id) id: vec3<u32>) {
+    let size = textureDimensions(output);
+    if id.x >= size.x || id.y >= size.y {
+        return;
+    }
+
+    let base = (id.y * size.x + id.x) * 3u;
+    let b = byte_at(base);
+    let g = byte_at(base + 1u);
+    let r = byte_at(base + 2u);
+
+    let color = vec4<f32>(f32(r), f32(g), f32(b), 255.0) / 255.0;
+    textureStore(output, vec2<i32>(id.xy), color);
+}
@@ -0,0 +1,479 @@
+//! Video feeds in windows of their own
+//!
+//! Double clicking a feed opens it in a separate OS window, for putting a camera on a second
+//! monitor. Double clicking it again, closing the window or the camera going away puts things
+//! back. Windows are opened through [`FeedWindows`] so the bookkeeping runs headless in tests.
+
+use std::time::Duration;
+
+use ahash::HashMap;
+use bevy::{
+    prelude::*,
+    render::{
+        camera::{Camera as BevyCamera, RenderTarget},
+        view::RenderLayers,
+    },
+    window::{WindowCloseRequested, WindowRef},
+};
+use bevy_mod_picking::prelude::*;
+use common::components::Camera;
+
+use crate::{
+    command_palette::{AppCommandExt, SurfaceCommand},
+    video_display_2d_master::DisplayMarker,
+};
+
+/// Nothing else is drawn on this layer, the windows' cameras only draw their feed
+const RENDER_LAYERS: RenderLayers = RenderLayers::layer(4);
+/// Longest gap between the clicks of a double click
+pub const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(400);
+
+pub struct VideoDetachPlugin;
+
+impl Plugin for VideoDetachPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DetachSettings>()
+            .init_resource::<OsFeedWindows>()
+            .add_event::<DetachFeed>()
+            .add_systems(
+                Update,
+                (
+                    detect_double_clicks,
+                    toggle_detached::<OsFeedWindows>,
+                    close_detached::<OsFeedWindows>,
+                )
+                    .chain(),
+            )
+            .add_systems(Update, fit_detached_images)
+            .add_command(
+                SurfaceCommand::new("Hide Detached Feeds", |world| {
+                    let mut settings = world.resource_mut::<DetachSettings>();
+                    settings.hide_in_layout = !settings.hide_in_layout;
+                })
+                .in_menu("Cameras")
+                .checked_if(|world| world.resource::<DetachSettings>().hide_in_layout),
+            );
+    }
+}
+
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub struct DetachSettings {
+    /// Hide feeds in the main layout while they have a window
+    pub hide_in_layout: bool,
+}
+
+/// Opens the given camera's feed in a window, or closes the window if it has one
+#[derive(Event, Debug, Clone, Copy, PartialEq)]
+pub struct DetachFeed(pub Entity);
+
+/// A camera feed shown in its own window
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct DetachedFeed {
+    pub camera_entity: Entity,
+    pub window_entity: Entity,
+}
+
+/// Creates and destroys the windows detached feeds are shown in
+pub trait FeedWindows: Resource {
+    /// Opens a window showing `texture`, returns the window's entity
+    fn open(&mut self, cmds: &mut Commands, title: &str, texture: Handle<Image>) -> Entity;
+    /// Despawns everything opened with `window`, which may already be gone
+    fn close(&mut self, cmds: &mut Commands, window: Entity);
+}
+
+/// Real OS windows, each with a camera drawing a full window image of the feed
+#[derive(Resource, Default)]
+pub struct OsFeedWindows {
+    /// View camera and image of each window
+    windows: HashMap<Entity, [Entity; 2]>,
+}
+
+impl FeedWindows for OsFeedWindows {
+    fn open(&mut self, cmds: &mut Commands, title: &str, texture: Handle<Image>) -> Entity {
+        let window = cmds
+            .spawn(Window {
+                title: title.to_owned(),
+                ..default()
+            })
+            .id();
+
+        let view = cmds
+            .spawn((
+                Camera2dBundle {
+                    camera: BevyCamera {
+                        target: RenderTarget::Window(WindowRef::Entity(window)),
+                        ..default()
+                    },
+                    ..default()
+                },
+                RENDER_LAYERS,
+            ))
+            .id();
+
+        let root = cmds
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        ..default()
+                    },
+                    background_color: BackgroundColor(Color::BLACK),
+                    ..default()
+                },
+                TargetCamera(view),
+            ))
+            .with_children(|builder| {
+                builder.spawn((
+                    ImageBundle {
+                        style: Style {
+                            height: Val::Percent(100.0),
+                            max_width: Val::Percent(100.0),
+                            aspect_ratio: Some(16.0 / 9.0),
+                            ..default()
+                        },
+                        image: UiImage::new(texture),
+                        ..default()
+                    },
+                    DetachedImage,
+                ));
+            })
+            .id();
+
+        self.windows.insert(window, [view, root]);
+
+        window
+    }
+
+    fn close(&mut self, cmds: &mut Commands, window: Entity) {
+        let spawned = self.windows.remove(&window).unwrap_or([window; 2]);
+
+        // Bevy despawns windows that were closed by the user itself
+        cmds.add(move |world: &mut World| {
+            for entity in [window].into_iter().chain(spawned) {
+                if let Some(entity) = world.get_entity_mut(entity) {
+                    entity.despawn_recursive();
+                }
+            }
+        });
+    }
+}
+
+#[derive(Component)]
+struct DetachedImage;
+
+/// Pairs up clicks on the same entity into double clicks
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DoubleClicks {
+    last: Option<(Entity, Duration)>,
+}
+
+impl DoubleClicks {
+    /// Whether this click finishes a double click, a third click starts a new one
+    pub fn click(&mut self, target: Entity, now: Duration) -> bool {
+        match self.last.take() {
+            Some((last, at)) if last == target && now.saturating_sub(at) <= DOUBLE_CLICK_TIME => {
+                true
+            }
+            _ => {
+                self.last = Some((target, now));
+                false
+            }
+        }
+    }
+}
+
+fn detect_double_clicks(
+    mut clicks: EventReader<Pointer<Click>>,
+    feeds: Query<(), With<DisplayMarker>>,
+    time: Res<Time<Real>>,
+    mut double_clicks: Local<DoubleClicks>,
+    mut detach: EventWriter<DetachFeed>,
+) {
+    for click in clicks.read() {
+        if click.event.button != PointerButton::Primary || !feeds.contains(click.target) {
+            continue;
+        }
+
+        if double_clicks.click(click.target, time.elapsed()) {
+            detach.send(DetachFeed(click.target));
+        }
+    }
+}
+
+fn toggle_detached<W: FeedWindows>(
+    mut cmds: Commands,
+    mut events: EventReader<DetachFeed>,
+    mut windows: ResMut<W>,
+    detached: Query<(Entity, &DetachedFeed)>,
+    mut cameras: Query<(&Handle<Image>, Option<&Name>, Option<&mut Visibility>), With<Camera>>,
+    settings: Res<DetachSettings>,
+) {
+    // Cameras toggled this frame, the commands spawning their windows haven't applied yet
+    let mut toggled = Vec::new();
+
+    for &DetachFeed(camera) in events.read() {
+        if toggled.contains(&camera) {
+            continue;
+        }
+        toggled.push(camera);
+
+        if let Some((entity, feed)) = detached.iter().find(|(_, it)| it.camera_entity == camera) {
+            windows.close(&mut cmds, feed.window_entity);
+            cmds.entity(entity).despawn();
+
+            if let Ok((_, _, Some(mut visibility))) = cameras.get_mut(camera) {
+                *visibility = Visibility::Inherited;
+            }
+
+            continue;
+        }
+
+        let Ok((texture, name, visibility)) = cameras.get_mut(camera) else {
+            continue;
+        };
+
+        let title = name.map(|it| it.as_str()).unwrap_or("Camera");
+        let window_entity = windows.open(&mut cmds, title, texture.clone_weak());
+        cmds.spawn((
+            Name::new(format!("Detached {title}")),
+            DetachedFeed {
+                camera_entity: camera,
+                window_entity,
+            },
+        ));
+
+        if let (true, Some(mut visibility)) = (settings.hide_in_layout, visibility) {
+            *visibility = Visibility::Hidden;
+        }
+    }
+}
+
+/// Cleans up after windows the user closed and cameras that went away
+fn close_detached<W: FeedWindows>(
+    mut cmds: Commands,
+    mut close_requests: EventReader<WindowCloseRequested>,
+    mut windows: ResMut<W>,
+    detached: Query<(Entity, &DetachedFeed)>,
+    cameras: Query<(), With<Camera>>,
+    mut visibility: Query<&mut Visibility, With<Camera>>,
+) {
+    let closed = close_requests
+        .read()
+        .map(|it| it.window)
+        .collect::<Vec<_>>();
+
+    for (entity, feed) in &detached {
+        let camera_lost = !cameras.contains(feed.camera_entity);
+        if !camera_lost && !closed.contains(&feed.window_entity) {
+            continue;
+        }
+
+        windows.close(&mut cmds, feed.window_entity);
+        cmds.entity(entity).despawn();
+
+        if let Ok(mut visibility) = visibility.get_mut(feed.camera_entity) {
+            *visibility = Visibility::Inherited;
+        }
+    }
+}
+
+fn fit_detached_images(
+    mut displays: Query<(&mut Style, &UiImage), With<DetachedImage>>,
+    images: Res<Assets<Image>>,
+) {
+    for (mut style, image) in &mut displays {
+        let Some(image) = images.get(&image.texture) else {
+            continue;
+        };
+
+        let aspect_ratio = f32::from(image.aspect_ratio());
+        if style.aspect_ratio != Some(aspect_ratio) {
+            style.aspect_ratio = Some(aspect_ratio);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use bevy::{prelude::*, window::WindowCloseRequested};
+    use common::{components::Camera, types::ids::CameraId};
+
+    use super::{
+        close_detached, toggle_detached, DetachFeed, DetachSettings, DetachedFeed, DoubleClicks,
+        FeedWindows, DOUBLE_CLICK_TIME,
+    };
+
+    /// Windows that are only entities
+    #[derive(Resource, Default)]
+    struct FakeWindows {
+        open: Vec<(Entity, String)>,
+    }
+
+    impl FeedWindows for FakeWindows {
+        fn open(&mut self, cmds: &mut Commands, title: &str, _texture: Handle<Image>) -> Entity {
+            let window = cmds.spawn_empty().id();
+            self.open.push((window, title.to_owned()));
+
+            window
+        }
+
+        fn close(&mut self, cmds: &mut Commands, window: Entity) {
+            self.open.retain(|(it, _)| *it != window);
+            cmds.add(move |world: &mut World| {
+                if let Some(window) = world.get_entity_mut(window) {
+                    window.despawn();
+                }
+            });
+        }
+    }
+
+    fn test_app(hide_in_layout: bool) -> (App, Entity) {
+        let mut app = App::new();
+        app.add_event::<DetachFeed>()
+            .add_event::<WindowCloseRequested>()
+            .init_resource::<FakeWindows>()
+            .insert_resource(DetachSettings { hide_in_layout })
+            .add_systems(
+                Update,
+                (
+                    toggle_detached::<FakeWindows>,
+                    close_detached::<FakeWindows>,
+                )
+                    .chain(),
+            );
+
+        let camera = app
+            .world
+            .spawn((
+                Camera {
+                    id: CameraId::from_static("Front"),
+                    location: SocketAddr::from(([127, 0, 0, 1], 0)),
+                },
+                Name::new("Front"),
+                Handle::<Image>::default(),
+                Visibility::Inherited,
+            ))
+            .id();
+
+        (app, camera)
+    }
+
+    fn detached(app: &mut App) -> Vec<DetachedFeed> {
+        app.world
+            .query::<&DetachedFeed>()
+            .iter(&app.world)
+            .copied()
+            .collect()
+    }
+
+    fn windows(app: &App) -> Vec<Entity> {
+        let windows = app.world.resource::<FakeWindows>();
+        windows.open.iter().map(|(it, _)| *it).collect()
+    }
+
+    fn detach(app: &mut App, camera: Entity) -> DetachedFeed {
+        app.world.send_event(DetachFeed(camera));
+        app.update();
+
+        let feeds = detached(app);
+        assert_eq!(feeds.len(), 1);
+        assert_eq!(feeds[0].camera_entity, camera);
+        assert_eq!(windows(app), [feeds[0].window_entity]);
+
+        feeds[0]
+    }
+
+    #[test]
+    fn double_clicks() {
+        let ms = Duration::from_millis;
+        let (a, b) = (Entity::from_raw(1), Entity::from_raw(2));
+        let mut clicks = DoubleClicks::default();
+
+        assert!(!clicks.click(a, ms(0)));
+        assert!(clicks.click(a, ms(100)));
+
+        // A third click starts over
+        assert!(!clicks.click(a, ms(200)));
+        assert!(clicks.click(a, ms(200) + DOUBLE_CLICK_TIME));
+
+        // Too slow or on another feed
+        assert!(!clicks.click(a, ms(1000)));
+        assert!(!clicks.click(a, ms(1001) + DOUBLE_CLICK_TIME));
+        assert!(!clicks.click(b, ms(1100) + DOUBLE_CLICK_TIME));
+        assert!(clicks.click(b, ms(1200) + DOUBLE_CLICK_TIME));
+    }
+
+    #[test]
+    fn detach_and_toggle_back() {
+        let (mut app, camera) = test_app(false);
+
+        detach(&mut app, camera);
+        assert_eq!(app.world.resource::<FakeWindows>().open[0].1, "Front");
+        // Still shown in the layout
+        assert_eq!(app.world.get::<Visibility>(camera), Some(&Visibility::Inherited));
+
+        app.world.send_event(DetachFeed(camera));
+        app.update();
+        assert!(detached(&mut app).is_empty());
+        assert!(windows(&app).is_empty());
+
+        // Double clicking twice in one frame opens one window
+        app.world.send_event(DetachFeed(camera));
+        app.world.send_event(DetachFeed(camera));
+        app.update();
+        assert_eq!(detached(&mut app).len(), 1);
+        assert_eq!(windows(&app).len(), 1);
+    }
+
+    #[test]
+    fn closing_the_window_reattaches() {
+        let (mut app, camera) = test_app(true);
+
+        let feed = detach(&mut app, camera);
+        assert_eq!(app.world.get::<Visibility>(camera), Some(&Visibility::Hidden));
+
+        app.world.send_event(WindowCloseRequested {
+            window: feed.window_entity,
+        });
+        app.update();
+
+        assert!(detached(&mut app).is_empty());
+        assert!(windows(&app).is_empty());
+        assert!(app.world.get_entity(feed.window_entity).is_none());
+        assert_eq!(app.world.get::<Visibility>(camera), Some(&Visibility::Inherited));
+
+        // Can be detached again
+        detach(&mut app, camera);
+    }
+
+    #[test]
+    fn losing_the_camera_closes_its_window() {
+        let (mut app, camera) = test_app(true);
+
+        let feed = detach(&mut app, camera);
+        app.world.despawn(camera);
+        app.update();
+
+        assert!(detached(&mut app).is_empty());
+        assert!(windows(&app).is_empty());
+        assert!(app.world.get_entity(feed.window_entity).is_none());
+
+        // Cameras that are gone can't be detached
+        app.world.send_event(DetachFeed(camera));
+        app.update();
+        assert!(detached(&mut app).is_empty());
+
+        // A camera losing its component counts as lost too
+        let (mut app, camera) = test_app(false);
+        detach(&mut app, camera);
+        app.world.entity_mut(camera).remove::<Camera>();
+        app.update();
+        assert!(detached(&mut app).is_empty());
+        assert!(windows(&app).is_empty());
+    }
+}
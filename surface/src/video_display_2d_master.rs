@@ -2,12 +2,19 @@ use bevy::{
     math::f32,
     prelude::*,
     render::{camera::Camera as BevyCamera, view::RenderLayers},
-    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+    sprite::{Anchor, MaterialMesh2dBundle, Mesh2dHandle},
 };
 use bevy_mod_picking::prelude::*;
 use common::components::Camera;
 
-const RENDER_LAYERS: RenderLayers = RenderLayers::layer(2);
+use crate::{
+    command_palette::{CommandRegistry, SurfaceCommand},
+    video_downscale::DesiredDisplaySize,
+    video_overlay::VideoOverlayPlugin,
+    video_stream::VideoStats,
+};
+
+pub(crate) const RENDER_LAYERS: RenderLayers = RenderLayers::layer(2);
 
 pub struct VideoDisplay2DPlugin;
 
@@ -16,19 +23,27 @@ impl Plugin for VideoDisplay2DPlugin {
         app.init_resource::<VideoDisplay2DSettings>()
             // .init_resource::<VideoTree>()
             .add_event::<MakeMaster>()
+            .add_plugins(VideoOverlayPlugin)
             .add_systems(Startup, setup)
             .add_systems(
                 Update,
                 (
-                    create_display,
-                    update_aspect_ratio.after(create_display),
+                    (create_display, update_aspect_ratio)
+                        .chain()
+                        .in_set(FeedLayoutSet),
+                    update_badges.after(FeedLayoutSet),
                     handle_new_masters,
                     enable_camera,
+                    pin_camera_commands,
                 ),
             );
     }
 }
 
+/// Places the feeds, anything drawn over them should run after
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FeedLayoutSet;
+
 #[derive(Resource)]
 struct MeshResource(Handle<Mesh>);
 
@@ -41,12 +56,21 @@ struct Video {
 #[derive(Component, Clone, Copy)]
 struct DisplayCamera;
 #[derive(Component, Clone, Copy)]
-struct DisplayParent;
+pub(crate) struct DisplayParent;
+/// Position of a feed in the layout, 0 is the large master feed
 #[derive(Component, Clone, Copy)]
-struct DisplayMarker(u16);
+pub struct DisplayMarker(pub u16);
+/// Frame rate and resolution text drawn over the top left of a camera's feed
+#[derive(Component, Clone, Copy)]
+struct VideoBadge(Entity);
+
+/// Gap between a feed's corner and its badge
+const BADGE_PADDING: f32 = 4.0;
+const BADGE_FONT_SIZE: f32 = 14.0;
 
+/// Swaps the given camera into the master position
 #[derive(Event, Clone, Copy)]
-struct MakeMaster(Entity);
+pub struct MakeMaster(pub Entity);
 
 impl From<ListenerInput<Pointer<Click>>> for MakeMaster {
     fn from(value: ListenerInput<Pointer<Click>>) -> Self {
@@ -97,6 +121,7 @@ fn create_display(
     mut lost_cameras: RemovedComponents<Camera>,
 
     cameras: Query<&Handle<Image>>,
+    badges: Query<(Entity, &VideoBadge)>,
     mut parent: Query<(Entity, &mut Video), With<DisplayParent>>,
 ) {
     let (parent, mut tree) = parent.single_mut();
@@ -108,6 +133,26 @@ fn create_display(
             tree.master_camera = Some(entity);
         }
         tree_changed = true;
+
+        let badge = cmds
+            .spawn((
+                Text2dBundle {
+                    text: Text::from_section(
+                        "",
+                        TextStyle {
+                            font_size: BADGE_FONT_SIZE,
+                            color: Color::WHITE,
+                            ..default()
+                        },
+                    ),
+                    text_anchor: Anchor::TopLeft,
+                    ..default()
+                },
+                VideoBadge(entity),
+                RENDER_LAYERS,
+            ))
+            .id();
+        cmds.entity(parent).add_child(badge);
     }
 
     for entity in lost_cameras.read() {
@@ -116,6 +161,12 @@ fn create_display(
             tree.master_camera = tree.cameras.iter().cloned().next()
         }
         tree_changed = true;
+
+        for (badge, &VideoBadge(camera)) in &badges {
+            if camera == entity {
+                cmds.entity(badge).despawn_recursive();
+            }
+        }
     }
 
     if tree_changed {
@@ -144,7 +195,14 @@ fn create_display(
 }
 
 fn update_aspect_ratio(
-    mut displays: Query<(&Handle<Image>, &DisplayMarker, &mut Transform)>,
+    mut cmds: Commands,
+    mut displays: Query<(
+        Entity,
+        &Handle<Image>,
+        &DisplayMarker,
+        &mut Transform,
+        Option<&DesiredDisplaySize>,
+    )>,
     images: Res<Assets<Image>>,
 
     camera: Query<&BevyCamera, With<DisplayCamera>>,
@@ -152,6 +210,7 @@ fn update_aspect_ratio(
     // TODO: Handle Errors
     let camera = camera.single();
     let logical = camera.logical_viewport_size().unwrap();
+    let scale_factor = camera.target_scaling_factor().unwrap_or(1.0);
 
     let other_max_width_pct = 1.0 / 3.0;
 
@@ -161,7 +220,7 @@ fn update_aspect_ratio(
     let mut other_aspect_ratio = 0.0f32;
     let mut count = 0;
 
-    for (handle, display, _transform) in &displays {
+    for (_entity, handle, display, _transform, _desired) in &displays {
         let Some(image) = images.get(handle) else {
             continue;
         };
@@ -199,7 +258,7 @@ fn update_aspect_ratio(
         master_width_needed
     };
 
-    for (handle, display, mut transform) in &mut displays {
+    for (entity, handle, display, mut transform, desired) in &mut displays {
         let Some(image) = images.get(handle) else {
             continue;
         };
@@ -240,6 +299,47 @@ fn update_aspect_ratio(
                     1.0,
                 ));
         }
+
+        // Thumbnails let their video thread shrink frames, the master feed stays at full
+        // resolution
+        if display.0 != 0 {
+            let size = (transform.scale.xy() * scale_factor).round().as_uvec2();
+            if desired.map(|it| it.0) != Some(size) {
+                cmds.entity(entity).insert(DesiredDisplaySize(size));
+            }
+        } else if desired.is_some() {
+            cmds.entity(entity).remove::<DesiredDisplaySize>();
+        }
+    }
+}
+
+fn update_badges(
+    mut badges: Query<(&VideoBadge, &mut Text, &mut Transform, &mut Visibility)>,
+    cameras: Query<(&Transform, Option<&VideoStats>), Without<VideoBadge>>,
+) {
+    for (&VideoBadge(camera), mut text, mut transform, mut visibility) in &mut badges {
+        let Ok((camera_transform, stats)) = cameras.get(camera) else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+        let Some(stats) = stats.filter(|it| it.source != UVec2::ZERO) else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+
+        let size = camera_transform.scale.xy();
+        let corner = camera_transform.translation.xy() + Vec2::new(-size.x, size.y) / 2.0;
+        transform.translation = (corner + Vec2::new(BADGE_PADDING, -BADGE_PADDING)).extend(1.0);
+
+        let mut badge = format!("{:.0} fps {}x{}", stats.fps, stats.source.x, stats.source.y);
+        if stats.shown != stats.source {
+            badge += &format!(" ({}x{})", stats.shown.x, stats.shown.y);
+        }
+
+        if text.sections[0].value != badge {
+            text.sections[0].value = badge;
+        }
+        *visibility = Visibility::Inherited;
     }
 }
 
@@ -259,6 +359,39 @@ fn handle_new_masters(mut events: EventReader<MakeMaster>, mut query: Query<&mut
     }
 }
 
+/// Keeps a palette command to make each feed the master
+fn pin_camera_commands(
+    mut last: Local<Vec<(Entity, String)>>,
+    mut registry: ResMut<CommandRegistry>,
+    cameras: Query<(Entity, &Name), With<DisplayMarker>>,
+) {
+    let mut current: Vec<_> = cameras
+        .iter()
+        .map(|(entity, name)| (entity, name.to_string()))
+        .collect();
+    current.sort_by(|a, b| a.1.cmp(&b.1));
+
+    if *last == current {
+        return;
+    }
+
+    registry.set_group(
+        "Pin Camera",
+        current.iter().map(|&(entity, ref name)| {
+            SurfaceCommand::new(format!("Pin Camera: {name}"), move |world| {
+                world.send_event(MakeMaster(entity));
+            })
+            .enabled_if(move |world| {
+                world
+                    .get::<DisplayMarker>(entity)
+                    .is_some_and(|it| it.0 != 0)
+            })
+        }),
+    );
+
+    *last = current;
+}
+
 fn enable_camera(
     mut last: Local<bool>,
     mut camera: Query<&mut BevyCamera, With<DisplayCamera>>,
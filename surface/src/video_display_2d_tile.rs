@@ -1,10 +1,13 @@
-use std::mem;
+use std::{fs, path::Path};
 
+use anyhow::Context;
 use bevy::{
     prelude::*,
     render::{camera::Camera as BevyCamera, view::RenderLayers},
 };
-use common::components::Camera;
+use bevy_mod_picking::prelude::*;
+use common::{components::Camera, error::ErrorEvent};
+use serde::{Deserialize, Serialize};
 
 const RENDER_LAYERS: RenderLayers = RenderLayers::layer(2);
 
@@ -12,22 +15,106 @@ pub struct VideoDisplay2DPlugin;
 
 impl Plugin for VideoDisplay2DPlugin {
     fn build(&self, app: &mut App) {
+        let prefs = match VideoLayoutPrefs::load(VIDEO_LAYOUT_PATH) {
+            Ok(prefs) => prefs,
+            Err(err) => {
+                warn!("Could not restore video layout: {err:?}");
+                VideoLayoutPrefs::default()
+            }
+        };
+
         app.init_resource::<VideoDisplay2DSettings>()
+            .insert_resource(prefs)
             // .init_resource::<VideoTree>()
+            .add_event::<SwapFeeds>()
             .add_systems(Startup, setup)
             .add_systems(
                 Update,
                 (
+                    swap_feeds.before(create_display),
                     create_display,
                     update_aspect_ratio.after(create_display),
+                    save_layout
+                        .after(create_display)
+                        .run_if(resource_changed::<VideoLayoutPrefs>),
                     enable_camera,
                 ),
             );
     }
 }
 
+/// Where the video tile arrangement is kept between runs
+pub const VIDEO_LAYOUT_PATH: &str = "surface_video_layout.toml";
+
+/// The order cameras are placed into tiles, by name
+#[derive(Resource, Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct VideoLayoutPrefs {
+    pub order: Vec<String>,
+}
+
+impl VideoLayoutPrefs {
+    /// Adds cameras without a saved position to the end of the order
+    pub fn remember<'a>(&mut self, names: impl IntoIterator<Item = &'a str>) {
+        for name in names {
+            if !self.order.iter().any(|it| it == name) {
+                self.order.push(name.to_owned());
+            }
+        }
+    }
+
+    /// Swaps the saved positions of two cameras
+    pub fn swap(&mut self, a: &str, b: &str) {
+        self.remember([a, b]);
+
+        let a = self.order.iter().position(|it| it == a);
+        let b = self.order.iter().position(|it| it == b);
+
+        if let (Some(a), Some(b)) = (a, b) {
+            self.order.swap(a, b);
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let state = fs::read_to_string(path).context("Read video layout")?;
+        toml::from_str(&state).context("Parse video layout")
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let state = toml::to_string(self).context("Serialize video layout")?;
+        fs::write(path, state).context("Write video layout")
+    }
+}
+
+/// Orders `cameras` by their saved position
+///
+/// Cameras without a saved position go last, keeping the order they were given in
+pub fn resolve_order<T: Copy>(prefs: &[String], cameras: &[(T, &str)]) -> Vec<T> {
+    let mut ordered: Vec<_> = cameras
+        .iter()
+        .map(|&(camera, name)| {
+            let position = prefs.iter().position(|it| it == name).unwrap_or(usize::MAX);
+            (position, camera)
+        })
+        .collect();
+
+    // Stable, so unknown cameras keep their relative order
+    ordered.sort_by_key(|(position, _)| *position);
+
+    ordered.into_iter().map(|(_, camera)| camera).collect()
+}
+
 #[derive(Default, Component)]
-struct VideoTree(VideoNode);
+struct VideoTree {
+    root: VideoNode,
+    /// Cameras in the order they appeared
+    cameras: Vec<Entity>,
+}
 enum VideoNode {
     Branch(Vec<VideoNode>),
     Leaf(Entity),
@@ -66,29 +153,6 @@ impl VideoNode {
         }
     }
 
-    fn remove(&mut self, entity: Entity) {
-        match self {
-            VideoNode::Branch(children) => {
-                for child in &mut *children {
-                    child.remove(entity);
-                }
-
-                children.retain(
-                    |child| !matches!(child, VideoNode::Branch(children) if children.is_empty()),
-                );
-
-                if let [child] = children.as_mut_slice() {
-                    *self = mem::take(child);
-                }
-            }
-            VideoNode::Leaf(this) => {
-                if entity == *this {
-                    *self = VideoNode::Branch(vec![]);
-                }
-            }
-        }
-    }
-
     fn count_children(&self) -> u32 {
         match self {
             VideoNode::Branch(children) => children.iter().map(|it| it.count_children()).sum(),
@@ -127,8 +191,25 @@ struct DisplayCamera;
 struct DisplayParent;
 #[derive(Component)]
 struct DisplayMarker;
+/// Displays the video feed of the contained camera entity
 #[derive(Component)]
-struct VideoFeedDisplay;
+struct VideoFeedDisplay(Entity);
+
+/// A feed was dropped onto another
+#[derive(Event, Clone, Copy)]
+struct SwapFeeds {
+    target: Entity,
+    dropped: Entity,
+}
+
+impl From<ListenerInput<Pointer<Drop>>> for SwapFeeds {
+    fn from(value: ListenerInput<Pointer<Drop>>) -> Self {
+        SwapFeeds {
+            target: value.listener(),
+            dropped: value.dropped,
+        }
+    }
+}
 
 #[derive(Resource, Default)]
 pub struct VideoDisplay2DSettings {
@@ -167,24 +248,47 @@ fn create_display(
     mut lost_cameras: RemovedComponents<Camera>,
 
     cameras: Query<&Handle<Image>>,
+    names: Query<&Name>,
+    mut prefs: ResMut<VideoLayoutPrefs>,
     mut parent: Query<(Entity, &mut VideoTree), With<DisplayParent>>,
 ) {
     let (parent, mut tree) = parent.single_mut();
-    let mut tree_changed = false;
+    let mut tree_changed = prefs.is_changed();
 
     for entity in &new_cameras {
-        tree.0.insert(entity);
+        tree.cameras.push(entity);
         tree_changed = true;
     }
 
     for entity in lost_cameras.read() {
-        tree.0.remove(entity);
+        tree.cameras.retain(|it| *it != entity);
         tree_changed = true;
     }
 
     if tree_changed {
+        let named: Vec<_> = tree
+            .cameras
+            .iter()
+            .map(|&camera| (camera, names.get(camera).map(Name::as_str).unwrap_or("")))
+            .collect();
+
+        // Only touch prefs when needed, its change detection triggers a save
+        if named
+            .iter()
+            .any(|(_, name)| !prefs.order.iter().any(|it| it == name))
+        {
+            prefs.remember(named.iter().map(|(_, name)| *name));
+        }
+
+        // Rebuilding from the resolved order keeps removals from shuffling the other feeds
+        let mut root = VideoNode::default();
+        for camera in resolve_order(&prefs.order, &named) {
+            root.insert(camera);
+        }
+        tree.root = root;
+
         let layout = VideoLayout::default();
-        let depth = tree.0.max_depth() as i32 - 1;
+        let depth = tree.root.max_depth() as i32 - 1;
 
         let size_hint = match layout {
             VideoLayout::Horizontal => (
@@ -201,12 +305,40 @@ fn create_display(
             .despawn_descendants()
             .with_children(move |builder| {
                 builder.spawn(root(layout)).with_children(|builder| {
-                    build_tree(builder, &tree.0, &cameras, layout, size_hint);
+                    build_tree(builder, &tree.root, &cameras, layout, size_hint);
                 });
             });
     }
 }
 
+fn swap_feeds(
+    mut events: EventReader<SwapFeeds>,
+    feeds: Query<&VideoFeedDisplay>,
+    names: Query<&Name>,
+    mut prefs: ResMut<VideoLayoutPrefs>,
+) {
+    for event in events.read() {
+        let Ok([VideoFeedDisplay(target), VideoFeedDisplay(dropped)]) =
+            feeds.get_many([event.target, event.dropped])
+        else {
+            continue;
+        };
+
+        let (Ok(target), Ok(dropped)) = (names.get(*target), names.get(*dropped)) else {
+            continue;
+        };
+
+        prefs.swap(target.as_str(), dropped.as_str());
+    }
+}
+
+fn save_layout(prefs: Res<VideoLayoutPrefs>, mut errors: EventWriter<ErrorEvent>) {
+    let rst = prefs.save(VIDEO_LAYOUT_PATH);
+    if let Err(err) = rst {
+        errors.send(ErrorEvent(err));
+    }
+}
+
 // FIXME: Approch in display_3d is a bit cleaner and perhaps more efficient
 fn update_aspect_ratio(
     mut displays: Query<(&mut Style, &UiImage), With<VideoFeedDisplay>>,
@@ -280,7 +412,12 @@ fn build_tree(
                 .spawn(container(layout))
                 // TODO: video feed image
                 .with_children(|builder| {
-                    builder.spawn(feed(layout, weak_texture, size_hint));
+                    builder.spawn((
+                        feed(layout, weak_texture, size_hint),
+                        VideoFeedDisplay(*camera_entity),
+                        PickableBundle::default(),
+                        On::<Pointer<Drop>>::send_event::<SwapFeeds>(),
+                    ));
                 });
         }
     }
@@ -433,7 +570,6 @@ fn feed(layout: VideoLayout, texture: Handle<Image>, size_hint: (f32, f32)) -> i
             },
             RENDER_LAYERS,
             DisplayMarker,
-            VideoFeedDisplay,
         ),
         VideoLayout::Vertical => (
             ImageBundle {
@@ -453,7 +589,6 @@ fn feed(layout: VideoLayout, texture: Handle<Image>, size_hint: (f32, f32)) -> i
             },
             RENDER_LAYERS,
             DisplayMarker,
-            VideoFeedDisplay,
         ),
     }
 }
@@ -490,3 +625,57 @@ fn separator(layout: VideoLayout) -> impl Bundle {
         ),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_order, VideoLayoutPrefs};
+
+    fn prefs(order: &[&str]) -> Vec<String> {
+        order.iter().map(|it| it.to_string()).collect()
+    }
+
+    #[test]
+    fn saved_order_wins() {
+        let prefs = prefs(&["Top", "Front", "Claw"]);
+        let cameras = [(0, "Claw"), (1, "Front"), (2, "Top")];
+
+        assert_eq!(resolve_order(&prefs, &cameras), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn unknown_cameras_go_last() {
+        let prefs = prefs(&["Top", "Front"]);
+        let cameras = [(0, "New B"), (1, "Front"), (2, "New A"), (3, "Top")];
+
+        assert_eq!(resolve_order(&prefs, &cameras), vec![3, 1, 0, 2]);
+        assert_eq!(resolve_order(&[], &cameras), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn removal_keeps_relative_order() {
+        let prefs = prefs(&["A", "B", "C", "D"]);
+
+        let all = [(3, "D"), (0, "A"), (2, "C"), (1, "B")];
+        assert_eq!(resolve_order(&prefs, &all), vec![0, 1, 2, 3]);
+
+        let without_b = [(3, "D"), (0, "A"), (2, "C")];
+        assert_eq!(resolve_order(&prefs, &without_b), vec![0, 2, 3]);
+    }
+
+    #[test]
+    fn swapping_feeds() {
+        let mut layout = VideoLayoutPrefs {
+            order: prefs(&["A", "B", "C"]),
+        };
+
+        layout.swap("A", "C");
+        assert_eq!(layout.order, prefs(&["C", "B", "A"]));
+
+        // Cameras without a saved position get one
+        layout.swap("B", "D");
+        assert_eq!(layout.order, prefs(&["C", "D", "A", "B"]));
+
+        layout.remember(["A", "E"]);
+        assert_eq!(layout.order, prefs(&["C", "D", "A", "B", "E"]));
+    }
+}
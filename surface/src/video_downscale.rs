@@ -0,0 +1,219 @@
+//! Downscaling of feeds that are drawn much smaller than their source
+//!
+//! A thumbnail doesn't need every pixel of a 1080p feed. The display plugin publishes how large
+//! each camera is drawn and its video thread shrinks frames before they are converted and
+//! uploaded. Frames handed to video pipelines are always full resolution.
+
+use anyhow::Context;
+use bevy::prelude::*;
+use opencv::{core::Size, imgproc, prelude::*};
+
+/// Physical pixels a camera's feed is drawn at
+///
+/// Cameras without one, such as the master feed, stay at full resolution
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DesiredDisplaySize(pub UVec2);
+
+/// Largest factor frames are shrunk by
+const MAX_DIVISOR: u32 = 8;
+/// How far below a step the drawn size has to be before frames shrink to it
+///
+/// Growing happens as soon as the drawn size needs it, so the feed never looks blurry
+const HYSTERESIS: f32 = 0.15;
+
+/// Decides the resolution a camera's frames are handed to the UI at
+///
+/// Frames are shrunk by powers of two so small changes to the drawn size don't change the texture
+/// size every frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayScale {
+    divisor: u32,
+}
+
+impl Default for DisplayScale {
+    fn default() -> Self {
+        Self { divisor: 1 }
+    }
+}
+
+impl DisplayScale {
+    /// Updates the decision for the latest sizes, returns the size to shrink frames to
+    pub fn update(&mut self, source: UVec2, display: Option<UVec2>) -> Option<UVec2> {
+        let Some(display) = display else {
+            self.divisor = 1;
+            return None;
+        };
+
+        if source.x == 0 || source.y == 0 {
+            return None;
+        }
+
+        // Feeds keep their aspect ratio, whichever axis is drawn larger decides
+        let ratio = f32::max(
+            display.x as f32 / source.x as f32,
+            display.y as f32 / source.y as f32,
+        );
+
+        let needed = divisor_for(ratio);
+        if needed < self.divisor {
+            self.divisor = needed;
+        } else {
+            self.divisor = self.divisor.max(divisor_for(ratio / (1.0 - HYSTERESIS)));
+        }
+
+        self.target(source)
+    }
+
+    pub fn divisor(&self) -> u32 {
+        self.divisor
+    }
+
+    fn target(&self, source: UVec2) -> Option<UVec2> {
+        if self.divisor == 1 {
+            return None;
+        }
+
+        Some((source / self.divisor).max(UVec2::ONE))
+    }
+}
+
+/// The largest divisor that still leaves at least `ratio` of the source's pixels
+fn divisor_for(ratio: f32) -> u32 {
+    let mut divisor = 1;
+
+    while divisor < MAX_DIVISOR && 1.0 / (divisor * 2) as f32 >= ratio {
+        divisor *= 2;
+    }
+
+    divisor
+}
+
+/// Shrinks `mat` into `scratch` when there is a target size, returning the frame to display
+pub fn scale_for_display<'a>(
+    mat: &'a Mat,
+    target: Option<UVec2>,
+    scratch: &'a mut Mat,
+) -> anyhow::Result<&'a Mat> {
+    let Some(target) = target else {
+        return Ok(mat);
+    };
+
+    imgproc::resize(
+        mat,
+        scratch,
+        Size::new(target.x as i32, target.y as i32),
+        0.0,
+        0.0,
+        imgproc::INTER_AREA,
+    )
+    .context("Downscale frame")?;
+
+    Ok(scratch)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::UVec2;
+    use opencv::{
+        core::{Scalar, CV_8UC3},
+        prelude::*,
+    };
+
+    use crate::video_conversion::{VideoConversionMode, VideoFrame};
+
+    use super::{scale_for_display, DisplayScale};
+
+    const SOURCE: UVec2 = UVec2::new(1920, 1080);
+
+    fn width(display: u32) -> Option<UVec2> {
+        Some(UVec2::new(display, display * 9 / 16))
+    }
+
+    #[test]
+    fn thumbnails_are_shrunk() {
+        let mut scale = DisplayScale::default();
+
+        assert_eq!(scale.update(SOURCE, width(1920)), None);
+        assert_eq!(scale.update(SOURCE, width(1280)), None);
+        assert_eq!(scale.update(SOURCE, width(640)), Some(UVec2::new(960, 540)));
+        assert_eq!(scale.update(SOURCE, width(400)), Some(UVec2::new(480, 270)));
+
+        // Never below the largest divisor
+        assert_eq!(scale.update(SOURCE, width(16)), Some(UVec2::new(240, 135)));
+    }
+
+    #[test]
+    fn no_display_size_is_full_resolution() {
+        let mut scale = DisplayScale::default();
+
+        assert!(scale.update(SOURCE, width(400)).is_some());
+        assert_eq!(scale.update(SOURCE, None), None);
+        assert_eq!(scale.divisor(), 1);
+    }
+
+    #[test]
+    fn small_changes_dont_thrash() {
+        let mut scale = DisplayScale::default();
+
+        // Just under half size isn't far enough to shrink
+        for display in [950, 930, 900, 950, 1000] {
+            assert_eq!(scale.update(SOURCE, width(display)), None, "{display}");
+        }
+
+        assert!(scale.update(SOURCE, width(800)).is_some());
+
+        // Wobbling back up stays shrunk until the drawn size needs more pixels
+        for display in [850, 900, 950, 960] {
+            assert_eq!(scale.divisor(), 2, "{display}");
+            scale.update(SOURCE, width(display));
+        }
+        assert_eq!(scale.divisor(), 2);
+
+        assert_eq!(scale.update(SOURCE, width(1000)), None);
+    }
+
+    #[test]
+    fn growing_is_immediate() {
+        let mut scale = DisplayScale::default();
+
+        scale.update(SOURCE, width(200));
+        assert_eq!(scale.divisor(), 8);
+
+        // Pinned to the master position
+        assert_eq!(scale.update(SOURCE, width(1500)), None);
+    }
+
+    #[test]
+    fn downscaled_frames_convert_at_the_target_size() {
+        let mat = Mat::new_rows_cols_with_default(
+            SOURCE.y as i32,
+            SOURCE.x as i32,
+            CV_8UC3,
+            Scalar::all(127.0),
+        )
+        .unwrap();
+
+        let mut scale = DisplayScale::default();
+        let mut scratch = Mat::default();
+
+        for (display, expected) in [(width(400), UVec2::new(480, 270)), (None, SOURCE)] {
+            let target = scale.update(SOURCE, display);
+            let scaled = scale_for_display(&mat, target, &mut scratch).unwrap();
+
+            for mode in [VideoConversionMode::Cpu, VideoConversionMode::Gpu] {
+                let mut frame = VideoFrame::empty(mode);
+                frame.fill(scaled).unwrap();
+                assert_eq!(frame.size(), expected);
+
+                match frame {
+                    VideoFrame::Rgba(image) => {
+                        assert_eq!(image.data.len() as u32, expected.x * expected.y * 4);
+                    }
+                    VideoFrame::Bgr(frame) => {
+                        assert!(frame.data.len() as u32 >= expected.x * expected.y * 3);
+                    }
+                }
+            }
+        }
+    }
+}
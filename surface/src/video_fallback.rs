@@ -0,0 +1,271 @@
+//! Manual switch to a robot's fallback video, for networks that drop the camera streams
+//!
+//! Turning it on for a camera spawns a replicated [`CameraFallbackStream`]. The robot then sends
+//! JPEG frames over the control link which are handed to the camera's video thread through its
+//! [`FallbackFeed`], so they land in the same image as streamed frames.
+
+use ahash::{HashMap, HashSet};
+use bevy::prelude::*;
+use common::{
+    components::{Camera, CameraFallbackStream, Robot, RobotId},
+    ecs_sync::{NetId, Replicate},
+    sync::Peer,
+    video_frames::VideoFrameIn,
+};
+use crossbeam::channel::Sender;
+
+use crate::video_stream::VideoThread;
+
+/// Frame rate fallback video is requested at
+pub const FALLBACK_FPS: u32 = 5;
+/// JPEG quality fallback video is requested at
+pub const FALLBACK_QUALITY: u8 = 60;
+/// Frames waiting for a video thread, later ones are dropped
+pub const FEED_BACKLOG: usize = 2;
+
+pub struct VideoFallbackPlugin;
+
+impl Plugin for VideoFallbackPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                route_frames,
+                restart_video_threads,
+                handle_disconnected_robots,
+            ),
+        );
+    }
+}
+
+/// Hands fallback frames to a camera's video thread, present while fallback video is requested
+#[derive(Component)]
+pub struct FallbackFeed(pub Sender<Vec<u8>>);
+
+/// Whether fallback video is requested for `camera`
+pub fn is_requested<'a>(
+    mut requests: impl Iterator<Item = (&'a CameraFallbackStream, &'a RobotId)>,
+    camera: &Camera,
+    robot: &RobotId,
+) -> bool {
+    requests.any(|(request, request_robot)| request.camera == camera.id && request_robot == robot)
+}
+
+/// The fallback checkbox in a camera's menu
+pub fn menu(
+    ui: &mut egui::Ui,
+    cmds: &mut Commands,
+    requests: &Query<(Entity, &CameraFallbackStream, &RobotId)>,
+    camera: &Camera,
+    robot: RobotId,
+) {
+    let request = requests
+        .iter()
+        .find(|(_, request, request_robot)| request.camera == camera.id && **request_robot == robot)
+        .map(|(entity, ..)| entity);
+
+    let mut enabled = request.is_some();
+    let toggled = ui
+        .checkbox(&mut enabled, "Fallback Video")
+        .on_hover_text("Low rate frames over the control link, for when the stream is blocked")
+        .changed();
+    if !toggled {
+        return;
+    }
+
+    match request {
+        Some(entity) => cmds.entity(entity).despawn(),
+        None => {
+            cmds.spawn((
+                CameraFallbackStream {
+                    camera: camera.id.clone(),
+                    fps: FALLBACK_FPS,
+                    quality: FALLBACK_QUALITY,
+                },
+                robot,
+                Name::new(format!("Fallback {}", camera.id)),
+                Replicate,
+            ));
+        }
+    }
+}
+
+fn route_frames(
+    mut frames: EventReader<VideoFrameIn>,
+    robots: Query<(&NetId, &Peer), With<Robot>>,
+    cameras: Query<(&Camera, &RobotId, &FallbackFeed)>,
+) {
+    if frames.is_empty() {
+        return;
+    }
+
+    // Every robot has cameras with the same names
+    let robots: HashMap<_, _> = robots
+        .iter()
+        .map(|(&net_id, peer)| (peer.token, RobotId(net_id)))
+        .collect();
+
+    for frame in frames.read() {
+        let Some(robot) = robots.get(&frame.peer) else {
+            continue;
+        };
+
+        for (camera, camera_robot, feed) in &cameras {
+            if camera.id == frame.camera && camera_robot == robot {
+                // Only the latest frame is shown anyway
+                let _ = feed.0.try_send(frame.jpeg.clone());
+            }
+        }
+    }
+}
+
+/// Video threads pick their source when their camera changes
+fn restart_video_threads(
+    requests: Query<(&CameraFallbackStream, &RobotId)>,
+    mut cameras: Query<(&mut Camera, &RobotId, Has<FallbackFeed>), With<VideoThread>>,
+) {
+    for (mut camera, robot, has_feed) in &mut cameras {
+        // Already being restarted
+        if camera.is_changed() {
+            continue;
+        }
+
+        if is_requested(requests.iter(), &camera, robot) != has_feed {
+            camera.set_changed();
+        }
+    }
+}
+
+fn handle_disconnected_robots(
+    mut cmds: Commands,
+    robots: Query<&NetId, With<Robot>>,
+    requests: Query<(Entity, &RobotId), With<CameraFallbackStream>>,
+    mut removed_robots: RemovedComponents<Robot>,
+) {
+    if removed_robots.read().count() == 0 {
+        return;
+    }
+
+    let robots: HashSet<NetId> = robots.iter().copied().collect();
+    requests
+        .iter()
+        .filter(|(_, &RobotId(robot))| !robots.contains(&robot))
+        .for_each(|(entity, _)| cmds.entity(entity).despawn());
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::app::{App, Update};
+    use common::{
+        components::{Camera, CameraFallbackStream, Robot, RobotId},
+        ecs_sync::NetId,
+        sync::Peer,
+        types::ids::CameraId,
+        video_frames::VideoFrameIn,
+    };
+    use crossbeam::channel;
+    use networking::{PeerAddr, Token as NetToken};
+
+    use super::{is_requested, route_frames, FallbackFeed, FEED_BACKLOG};
+
+    fn camera(id: &'static str) -> Camera {
+        Camera {
+            id: CameraId::from_static(id),
+            location: "0.0.0.0:0".parse().unwrap(),
+        }
+    }
+
+    fn frame(peer: NetToken, camera: &'static str, seq: u32) -> VideoFrameIn {
+        VideoFrameIn {
+            peer,
+            camera: CameraId::from_static(camera),
+            seq,
+            jpeg: vec![seq as u8],
+        }
+    }
+
+    fn spawn_robot(app: &mut App, token: NetToken) -> RobotId {
+        let net_id = NetId::random();
+        app.world.spawn((
+            Robot,
+            net_id,
+            Peer {
+                addrs: PeerAddr::Tcp("127.0.0.1:0".parse().unwrap()),
+                token,
+            },
+        ));
+
+        RobotId(net_id)
+    }
+
+    #[test]
+    fn requests_match_camera_and_robot() {
+        let robot = RobotId(NetId::random());
+        let request = CameraFallbackStream {
+            camera: CameraId::from_static("Front"),
+            fps: 5,
+            quality: 60,
+        };
+        let requests = [(&request, &robot)];
+
+        assert!(is_requested(requests.into_iter(), &camera("Front"), &robot));
+        assert!(!is_requested(requests.into_iter(), &camera("Top"), &robot));
+
+        let other = RobotId(NetId::random());
+        assert!(!is_requested(
+            requests.into_iter(),
+            &camera("Front"),
+            &other
+        ));
+    }
+
+    #[test]
+    fn frames_reach_their_camera_without_queueing() {
+        let (tx_front, rx_front) = channel::bounded(FEED_BACKLOG);
+        let (tx_top, rx_top) = channel::bounded(FEED_BACKLOG);
+
+        let mut app = App::new();
+        app.add_event::<VideoFrameIn>()
+            .add_systems(Update, route_frames);
+        let robot = spawn_robot(&mut app, NetToken(1));
+        app.world.spawn((camera("Front"), robot, FallbackFeed(tx_front)));
+        app.world.spawn((camera("Top"), robot, FallbackFeed(tx_top)));
+        // Cameras without a feed are streaming
+        app.world.spawn((camera("Down"), robot));
+
+        for seq in 0..4 {
+            app.world.send_event(frame(NetToken(1), "Front", seq));
+        }
+        app.world.send_event(frame(NetToken(1), "Top", 7));
+        app.update();
+
+        // A stalled video thread only gets the frames it has room for
+        assert_eq!(rx_front.try_iter().collect::<Vec<_>>(), [vec![0], vec![1]]);
+        assert_eq!(rx_top.try_iter().collect::<Vec<_>>(), [vec![7]]);
+    }
+
+    #[test]
+    fn frames_only_reach_the_sending_robots_camera() {
+        let (tx_first, rx_first) = channel::bounded(FEED_BACKLOG);
+        let (tx_second, rx_second) = channel::bounded(FEED_BACKLOG);
+
+        let mut app = App::new();
+        app.add_event::<VideoFrameIn>()
+            .add_systems(Update, route_frames);
+        let first = spawn_robot(&mut app, NetToken(1));
+        let second = spawn_robot(&mut app, NetToken(2));
+        app.world
+            .spawn((camera("Front"), first, FallbackFeed(tx_first)));
+        app.world
+            .spawn((camera("Front"), second, FallbackFeed(tx_second)));
+
+        app.world.send_event(frame(NetToken(1), "Front", 1));
+        app.world.send_event(frame(NetToken(2), "Front", 2));
+        // A peer that isn't a robot
+        app.world.send_event(frame(NetToken(3), "Front", 3));
+        app.update();
+
+        assert_eq!(rx_first.try_iter().collect::<Vec<_>>(), [vec![1]]);
+        assert_eq!(rx_second.try_iter().collect::<Vec<_>>(), [vec![2]]);
+    }
+}
@@ -0,0 +1,639 @@
+//! Telemetry drawn over the 2D video feeds, like the on screen display of an FPV feed
+//!
+//! Widgets follow the feed they are drawn over and scale with it. Widgets whose source has not
+//! been replicated yet, like depth before the robot connects, are hidden and the rest close up.
+
+use std::{fs, path::Path, time::Duration};
+
+use anyhow::Context;
+use bevy::{math::EulerRot, prelude::*, sprite::Anchor, utils::HashMap};
+use common::{
+    components::{Armed, Depth, Orientation, Robot, RobotId},
+    error::ErrorEvent,
+    sync::Latency,
+    types::units::Meters,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    selected_robot::SelectedRobot,
+    ui::TimerUi,
+    video_display_2d_master::{DisplayMarker, DisplayParent, FeedLayoutSet, RENDER_LAYERS},
+};
+
+pub struct VideoOverlayPlugin;
+
+impl Plugin for VideoOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        let config = match FeedOverlayConfig::load(FEED_OVERLAY_PATH) {
+            Ok(config) => config,
+            Err(err) => {
+                warn!("Could not restore feed overlay: {err:?}");
+                FeedOverlayConfig::default()
+            }
+        };
+
+        app.insert_resource(config).add_systems(
+            Update,
+            (
+                (spawn_overlays, update_overlays)
+                    .chain()
+                    .after(FeedLayoutSet),
+                save_overlay_config.run_if(resource_changed::<FeedOverlayConfig>),
+            ),
+        );
+    }
+}
+
+/// Where the feed overlay settings are kept between runs
+pub const FEED_OVERLAY_PATH: &str = "surface_feed_overlay.toml";
+
+/// Overlay text height as a fraction of the feed's height
+const FONT_FRACTION: f32 = 0.035;
+const MIN_FONT_SIZE: f32 = 12.0;
+const MAX_FONT_SIZE: f32 = 32.0;
+/// Gap between the edge of a feed and its widgets, as a fraction of the font size
+const PADDING_FRACTION: f32 = 0.5;
+/// Distance between widgets stacked at the same anchor, as a fraction of the font size
+const LINE_FRACTION: f32 = 1.25;
+/// Feeds shorter than this have no room for an overlay
+pub const MIN_TILE_HEIGHT: f32 = 120.0;
+
+/// Degrees either side of the heading shown on the compass strip
+const COMPASS_SPAN: i32 = 45;
+/// Degrees between marks on the compass strip
+const COMPASS_STEP: i32 = 15;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OverlayWidget {
+    Depth,
+    Heading,
+    Armed,
+    Timer,
+    Latency,
+}
+
+impl OverlayWidget {
+    pub const ALL: [OverlayWidget; 5] = [
+        OverlayWidget::Depth,
+        OverlayWidget::Heading,
+        OverlayWidget::Armed,
+        OverlayWidget::Timer,
+        OverlayWidget::Latency,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OverlayWidget::Depth => "Depth",
+            OverlayWidget::Heading => "Heading",
+            OverlayWidget::Armed => "Armed",
+            OverlayWidget::Timer => "Timer",
+            OverlayWidget::Latency => "Latency",
+        }
+    }
+}
+
+/// Edge of a feed a widget is drawn along
+///
+/// The top left is left to the feed's frame rate badge
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OverlayAnchor {
+    TopCenter,
+    TopRight,
+    BottomLeft,
+    BottomCenter,
+    BottomRight,
+}
+
+impl OverlayAnchor {
+    pub const ALL: [OverlayAnchor; 5] = [
+        OverlayAnchor::TopCenter,
+        OverlayAnchor::TopRight,
+        OverlayAnchor::BottomLeft,
+        OverlayAnchor::BottomCenter,
+        OverlayAnchor::BottomRight,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            OverlayAnchor::TopCenter => "Top Center",
+            OverlayAnchor::TopRight => "Top Right",
+            OverlayAnchor::BottomLeft => "Bottom Left",
+            OverlayAnchor::BottomCenter => "Bottom Center",
+            OverlayAnchor::BottomRight => "Bottom Right",
+        }
+    }
+
+    /// Point of the text placed at the widget's position
+    pub fn text_anchor(&self) -> Anchor {
+        match self {
+            OverlayAnchor::TopCenter => Anchor::TopCenter,
+            OverlayAnchor::TopRight => Anchor::TopRight,
+            OverlayAnchor::BottomLeft => Anchor::BottomLeft,
+            OverlayAnchor::BottomCenter => Anchor::BottomCenter,
+            OverlayAnchor::BottomRight => Anchor::BottomRight,
+        }
+    }
+
+    fn is_top(&self) -> bool {
+        matches!(self, OverlayAnchor::TopCenter | OverlayAnchor::TopRight)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WidgetConfig {
+    pub enabled: bool,
+    pub anchor: OverlayAnchor,
+}
+
+impl WidgetConfig {
+    const fn at(anchor: OverlayAnchor) -> Self {
+        Self {
+            enabled: true,
+            anchor,
+        }
+    }
+}
+
+/// Which widgets are drawn over the feeds and where
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct FeedOverlayConfig {
+    /// Only draw the overlay over the large master feed
+    pub pinned_only: bool,
+
+    pub depth: WidgetConfig,
+    pub heading: WidgetConfig,
+    pub armed: WidgetConfig,
+    pub timer: WidgetConfig,
+    pub latency: WidgetConfig,
+}
+
+impl Default for FeedOverlayConfig {
+    fn default() -> Self {
+        Self {
+            pinned_only: true,
+            depth: WidgetConfig::at(OverlayAnchor::BottomLeft),
+            heading: WidgetConfig::at(OverlayAnchor::TopCenter),
+            armed: WidgetConfig::at(OverlayAnchor::TopRight),
+            timer: WidgetConfig::at(OverlayAnchor::BottomRight),
+            latency: WidgetConfig::at(OverlayAnchor::BottomRight),
+        }
+    }
+}
+
+impl FeedOverlayConfig {
+    pub fn widget(&self, widget: OverlayWidget) -> &WidgetConfig {
+        match widget {
+            OverlayWidget::Depth => &self.depth,
+            OverlayWidget::Heading => &self.heading,
+            OverlayWidget::Armed => &self.armed,
+            OverlayWidget::Timer => &self.timer,
+            OverlayWidget::Latency => &self.latency,
+        }
+    }
+
+    pub fn widget_mut(&mut self, widget: OverlayWidget) -> &mut WidgetConfig {
+        match widget {
+            OverlayWidget::Depth => &mut self.depth,
+            OverlayWidget::Heading => &mut self.heading,
+            OverlayWidget::Armed => &mut self.armed,
+            OverlayWidget::Timer => &mut self.timer,
+            OverlayWidget::Latency => &mut self.latency,
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let state = fs::read_to_string(path).context("Read feed overlay")?;
+        toml::from_str(&state).context("Parse feed overlay")
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let state = toml::to_string(self).context("Serialize feed overlay")?;
+        fs::write(path, state).context("Write feed overlay")
+    }
+}
+
+/// Values the widgets are drawn from, `None` when there is nothing to show
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OverlaySources {
+    pub depth: Option<Meters>,
+    /// Compass heading in degrees, clockwise from the robot's startup heading
+    pub heading: Option<f32>,
+    pub armed: Option<Armed>,
+    /// Time left on the competition timer, only while it is in use
+    pub timer: Option<Duration>,
+    /// Round trip time to the robot, in frames
+    pub ping: Option<u32>,
+}
+
+impl OverlaySources {
+    pub fn from_components(
+        depth: Option<&Depth>,
+        orientation: Option<&Orientation>,
+        armed: Option<&Armed>,
+        latency: Option<&Latency>,
+        timer: Option<Duration>,
+    ) -> Self {
+        Self {
+            depth: depth.map(|it| it.0.depth),
+            heading: orientation.map(|it| heading_degrees(it.rotation)),
+            armed: armed.copied(),
+            timer,
+            ping: latency.and_then(|it| it.ping),
+        }
+    }
+}
+
+/// Heading of `orientation` in compass degrees, from 0 up to 360
+///
+/// Positive rotations about Z turn the robot left, so they count down on a compass
+pub fn heading_degrees(orientation: Quat) -> f32 {
+    let (yaw, _, _) = orientation.to_euler(EulerRot::ZYX);
+    let heading = (-yaw.to_degrees()).rem_euclid(360.0);
+
+    // Rounding can land exactly on 360
+    if heading >= 360.0 {
+        0.0
+    } else {
+        heading
+    }
+}
+
+/// Label of a compass mark, cardinal and intercardinal directions get their letters
+fn compass_label(degrees: i32) -> String {
+    match degrees.rem_euclid(360) {
+        0 => "N".to_owned(),
+        45 => "NE".to_owned(),
+        90 => "E".to_owned(),
+        135 => "SE".to_owned(),
+        180 => "S".to_owned(),
+        225 => "SW".to_owned(),
+        270 => "W".to_owned(),
+        315 => "NW".to_owned(),
+        other => format!("{other:03}"),
+    }
+}
+
+/// A strip of compass marks around `heading` with the exact heading in the middle
+pub fn compass_strip(heading: f32) -> String {
+    let heading = heading.round() as i32;
+
+    // Marks strictly to the left and right of the heading
+    let first = (heading - COMPASS_SPAN).div_euclid(COMPASS_STEP) * COMPASS_STEP;
+    let marks = (0..)
+        .map(|step| first + step * COMPASS_STEP)
+        .skip_while(|&mark| mark < heading - COMPASS_SPAN)
+        .take_while(|&mark| mark <= heading + COMPASS_SPAN);
+
+    let mut left = Vec::new();
+    let mut right = Vec::new();
+    for mark in marks {
+        if mark < heading {
+            left.push(compass_label(mark));
+        } else if mark > heading {
+            right.push(compass_label(mark));
+        }
+    }
+
+    format!(
+        "{} [{:03}] {}",
+        left.join(" "),
+        heading.rem_euclid(360),
+        right.join(" ")
+    )
+}
+
+/// Text of a widget, `None` hides it
+pub fn widget_text(widget: OverlayWidget, sources: &OverlaySources) -> Option<String> {
+    match widget {
+        OverlayWidget::Depth => sources.depth.map(|depth| format!("Depth {depth}")),
+        OverlayWidget::Heading => sources.heading.map(compass_strip),
+        OverlayWidget::Armed => sources.armed.map(|armed| match armed {
+            Armed::Armed => "ARMED".to_owned(),
+            Armed::Disarmed => "DISARMED".to_owned(),
+        }),
+        OverlayWidget::Timer => sources.timer.map(|remaining| {
+            let remaining = remaining.as_secs();
+            format!("{:02}:{:02}", remaining / 60, remaining % 60)
+        }),
+        OverlayWidget::Latency => sources.ping.map(|ping| format!("Ping {ping} frames")),
+    }
+}
+
+pub fn widget_color(widget: OverlayWidget, sources: &OverlaySources) -> Color {
+    match (widget, sources.armed) {
+        (OverlayWidget::Armed, Some(Armed::Armed)) => Color::GREEN,
+        (OverlayWidget::Armed, Some(Armed::Disarmed)) => Color::RED,
+        _ => Color::WHITE,
+    }
+}
+
+/// Font size of widgets drawn over a feed `tile_height` tall
+pub fn font_size(tile_height: f32) -> f32 {
+    (tile_height * FONT_FRACTION).clamp(MIN_FONT_SIZE, MAX_FONT_SIZE)
+}
+
+/// Where a widget is drawn over a feed centered on `center` with size `size`
+///
+/// `slot` is the number of widgets shown before it at the same anchor, later widgets stack
+/// towards the middle of the feed
+pub fn widget_position(anchor: OverlayAnchor, slot: usize, center: Vec2, size: Vec2) -> Vec2 {
+    let font_size = font_size(size.y);
+    let padding = font_size * PADDING_FRACTION;
+    let stacked = slot as f32 * font_size * LINE_FRACTION;
+    let half = size / 2.0;
+
+    let x = match anchor {
+        OverlayAnchor::BottomLeft => -half.x + padding,
+        OverlayAnchor::TopCenter | OverlayAnchor::BottomCenter => 0.0,
+        OverlayAnchor::TopRight | OverlayAnchor::BottomRight => half.x - padding,
+    };
+    let y = if anchor.is_top() {
+        half.y - padding - stacked
+    } else {
+        -half.y + padding + stacked
+    };
+
+    center + Vec2::new(x, y)
+}
+
+/// One widget drawn over a camera's feed
+#[derive(Component, Clone, Copy)]
+struct FeedOverlay {
+    camera: Entity,
+    widget: OverlayWidget,
+}
+
+fn spawn_overlays(
+    mut cmds: Commands,
+    new_feeds: Query<Entity, Added<DisplayMarker>>,
+    parent: Query<Entity, With<DisplayParent>>,
+) {
+    let Ok(parent) = parent.get_single() else {
+        return;
+    };
+
+    for camera in &new_feeds {
+        for widget in OverlayWidget::ALL {
+            let overlay = cmds
+                .spawn((
+                    Text2dBundle {
+                        text: Text::from_section("", TextStyle::default()),
+                        visibility: Visibility::Hidden,
+                        ..default()
+                    },
+                    FeedOverlay { camera, widget },
+                    RENDER_LAYERS,
+                ))
+                .id();
+            cmds.entity(parent).add_child(overlay);
+        }
+    }
+}
+
+type OverlayState<'a> = (
+    Entity,
+    &'a FeedOverlay,
+    &'a mut Text,
+    &'a mut Anchor,
+    &'a mut Transform,
+    &'a mut Visibility,
+);
+type OverlayRobot<'a> = (
+    &'a RobotId,
+    Option<&'a Depth>,
+    Option<&'a Orientation>,
+    Option<&'a Armed>,
+    Option<&'a Latency>,
+);
+
+fn update_overlays(
+    mut cmds: Commands,
+    config: Res<FeedOverlayConfig>,
+    timer: Res<TimerUi>,
+    time: Res<Time<Real>>,
+
+    mut overlays: Query<OverlayState<'static>>,
+    feeds: Query<(&Transform, &DisplayMarker), Without<FeedOverlay>>,
+    selected: Res<SelectedRobot>,
+    robots: Query<OverlayRobot<'static>, With<Robot>>,
+) {
+    let timer = timer.started().then(|| timer.remaining(time.elapsed()));
+
+    let robot = robots
+        .iter()
+        .find(|(robot, ..)| Some(**robot) == selected.0);
+    let sources = match robot {
+        Some((_, depth, orientation, armed, latency)) => {
+            OverlaySources::from_components(depth, orientation, armed, latency, timer)
+        }
+        None => OverlaySources { timer, ..default() },
+    };
+
+    // Widgets shown so far at each anchor of each feed
+    let mut slots = HashMap::<(Entity, OverlayAnchor), usize>::new();
+
+    let mut overlays = overlays.iter_mut().collect::<Vec<_>>();
+    overlays.sort_by_key(|(_, overlay, ..)| (overlay.camera, overlay.widget));
+
+    for (entity, overlay, mut text, mut anchor, mut transform, mut visibility) in overlays {
+        let Ok((feed, display)) = feeds.get(overlay.camera) else {
+            cmds.entity(entity).despawn_recursive();
+            continue;
+        };
+
+        let widget_config = config.widget(overlay.widget);
+        let size = feed.scale.xy();
+
+        let shown = widget_config.enabled
+            && (display.0 == 0 || !config.pinned_only)
+            && size.y >= MIN_TILE_HEIGHT;
+        let value = shown
+            .then(|| widget_text(overlay.widget, &sources))
+            .flatten();
+
+        let Some(value) = value else {
+            *visibility = Visibility::Hidden;
+            continue;
+        };
+
+        let slot = slots
+            .entry((overlay.camera, widget_config.anchor))
+            .or_default();
+        let position = widget_position(widget_config.anchor, *slot, feed.translation.xy(), size);
+        *slot += 1;
+
+        transform.translation = position.extend(1.0);
+        *anchor = widget_config.anchor.text_anchor();
+
+        let section = &mut text.sections[0];
+        if section.value != value {
+            section.value = value;
+        }
+        section.style.font_size = font_size(size.y);
+        section.style.color = widget_color(overlay.widget, &sources);
+
+        *visibility = Visibility::Inherited;
+    }
+}
+
+fn save_overlay_config(config: Res<FeedOverlayConfig>, mut errors: EventWriter<ErrorEvent>) {
+    let rst = config.save(FEED_OVERLAY_PATH);
+    if let Err(err) = rst {
+        errors.send(ErrorEvent(err));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::math::{Quat, Vec2};
+    use common::{
+        components::{Armed, Depth, Orientation},
+        sync::Latency,
+        types::{hw::DepthFrame, units::Meters},
+    };
+
+    use super::{
+        compass_strip, font_size, heading_degrees, widget_position, widget_text, FeedOverlayConfig,
+        OverlayAnchor, OverlaySources, OverlayWidget, MAX_FONT_SIZE, MIN_FONT_SIZE,
+    };
+
+    #[test]
+    fn missing_sources_hide_widgets() {
+        let sources = OverlaySources::default();
+
+        for widget in OverlayWidget::ALL {
+            assert_eq!(widget_text(widget, &sources), None, "{widget:?}");
+        }
+    }
+
+    #[test]
+    fn widgets_bind_to_components() {
+        let depth = Depth(DepthFrame {
+            depth: Meters(1.5),
+            ..Default::default()
+        });
+        let orientation = Orientation {
+            rotation: Quat::from_rotation_z(-90f32.to_radians()),
+            capture_time: 0.0,
+        };
+        let latency = Latency {
+            ping: Some(3),
+            ..Default::default()
+        };
+
+        let sources = OverlaySources::from_components(
+            Some(&depth),
+            Some(&orientation),
+            Some(&Armed::Armed),
+            Some(&latency),
+            Some(Duration::from_secs(125)),
+        );
+
+        let text = |widget| widget_text(widget, &sources).unwrap();
+        assert_eq!(text(OverlayWidget::Depth), "Depth 1.50M");
+        assert!(text(OverlayWidget::Heading).contains("[090]"));
+        assert_eq!(text(OverlayWidget::Armed), "ARMED");
+        assert_eq!(text(OverlayWidget::Timer), "02:05");
+        assert_eq!(text(OverlayWidget::Latency), "Ping 3 frames");
+
+        // A connected robot that has not been pinged yet
+        let sources = OverlaySources::from_components(
+            None,
+            None,
+            Some(&Armed::Disarmed),
+            Some(&Latency::default()),
+            None,
+        );
+        assert_eq!(
+            widget_text(OverlayWidget::Armed, &sources).as_deref(),
+            Some("DISARMED")
+        );
+        assert_eq!(widget_text(OverlayWidget::Latency, &sources), None);
+    }
+
+    #[test]
+    fn headings_wrap() {
+        assert!(heading_degrees(Quat::IDENTITY).abs() < 0.01);
+
+        let left = heading_degrees(Quat::from_rotation_z(10f32.to_radians()));
+        assert!((left - 350.0).abs() < 0.01, "{left}");
+
+        let right = heading_degrees(Quat::from_rotation_z(-10f32.to_radians()));
+        assert!((right - 10.0).abs() < 0.01, "{right}");
+    }
+
+    #[test]
+    fn compass_strip_marks() {
+        assert_eq!(compass_strip(0.0), "NW 330 345 [000] 015 030 NE");
+        assert_eq!(compass_strip(359.6), "NW 330 345 [000] 015 030 NE");
+        assert_eq!(compass_strip(100.0), "060 075 E [100] 105 120 SE");
+        assert_eq!(compass_strip(260.0), "SW 240 255 [260] W 285 300");
+    }
+
+    #[test]
+    fn fonts_scale_with_tiles() {
+        assert_eq!(font_size(100.0), MIN_FONT_SIZE);
+        assert_eq!(font_size(10_000.0), MAX_FONT_SIZE);
+
+        let small = font_size(500.0);
+        let large = font_size(800.0);
+        assert!(small > MIN_FONT_SIZE && small < large && large < MAX_FONT_SIZE);
+    }
+
+    #[test]
+    fn widgets_sit_inside_their_tile() {
+        let center = Vec2::new(-200.0, 50.0);
+        let size = Vec2::new(800.0, 600.0);
+        let padding = font_size(size.y) / 2.0;
+
+        let top_right = widget_position(OverlayAnchor::TopRight, 0, center, size);
+        assert_eq!(top_right, Vec2::new(200.0 - padding, 350.0 - padding));
+
+        let bottom_left = widget_position(OverlayAnchor::BottomLeft, 0, center, size);
+        assert_eq!(bottom_left, Vec2::new(-600.0 + padding, -250.0 + padding));
+
+        let top_center = widget_position(OverlayAnchor::TopCenter, 0, center, size);
+        assert_eq!(top_center.x, center.x);
+
+        // Stacked widgets move towards the middle of the tile
+        let second = widget_position(OverlayAnchor::TopRight, 1, center, size);
+        assert_eq!(second.x, top_right.x);
+        assert!(second.y < top_right.y);
+
+        let second = widget_position(OverlayAnchor::BottomLeft, 1, center, size);
+        assert!(second.y > bottom_left.y);
+
+        // Positions follow the tile as it grows
+        let doubled = widget_position(OverlayAnchor::TopRight, 0, Vec2::ZERO, size * 2.0);
+        let padding = font_size(size.y * 2.0) / 2.0;
+        assert_eq!(doubled, Vec2::new(800.0 - padding, 600.0 - padding));
+    }
+
+    #[test]
+    fn config_round_trip() {
+        let mut config = FeedOverlayConfig {
+            pinned_only: false,
+            ..Default::default()
+        };
+        config.widget_mut(OverlayWidget::Depth).enabled = false;
+        config.widget_mut(OverlayWidget::Timer).anchor = OverlayAnchor::TopCenter;
+
+        let text = toml::to_string(&config).unwrap();
+        let parsed: FeedOverlayConfig = toml::from_str(&text).unwrap();
+        assert_eq!(parsed, config);
+
+        // Widgets added later get their defaults
+        let parsed: FeedOverlayConfig = toml::from_str("pinned_only = false").unwrap();
+        assert_eq!(parsed.heading, FeedOverlayConfig::default().heading);
+    }
+}
@@ -1,9 +1,13 @@
 pub mod edges;
+pub mod harness;
+pub mod intrinsics;
 pub mod marker;
 pub mod measure;
 pub mod save;
 pub mod scale;
 pub mod squares;
+pub mod station_keep;
+pub mod stereo;
 pub mod undistort;
 
 use std::{
@@ -37,7 +41,8 @@ use tracing::{debug, error};
 use crate::{
     video_pipelines::{
         edges::EdgesPipelinePlugin, marker::MarkerPipelinePlugin, save::SavePipelinePlugin,
-        squares::SquarePipelinePlugin,
+        squares::SquarePipelinePlugin, station_keep::StationKeepPipelinePlugin,
+        stereo::StereoMeasurePipelinePlugin, undistort::UndistortPipelinePlugin,
     },
     video_stream::{VideoProcessor, VideoProcessorFactory},
 };
@@ -55,7 +60,10 @@ impl PluginGroup for VideoPipelinePlugins {
             .add(EdgesPipelinePlugin)
             .add(MarkerPipelinePlugin)
             .add(SquarePipelinePlugin)
+            .add(StationKeepPipelinePlugin)
             .add(SavePipelinePlugin)
+            .add(StereoMeasurePipelinePlugin)
+            .add(UndistortPipelinePlugin)
     }
 }
 
@@ -0,0 +1,298 @@
+//! Runs a pipeline against a minimal world, without the app or a camera
+//!
+//! The pipeline is built with [`FromWorldEntity`] like a video thread would build it, and the
+//! world callbacks it sends are applied after every frame so pipelines that publish into the ECS
+//! see their own results. The world holds one armed robot and one camera attached to it.
+//! Used by the `pipelines` bench, see `surface/benches/pipelines.rs`.
+
+use std::{net::SocketAddr, time::Duration};
+
+use anyhow::{bail, Context};
+use bevy::{
+    ecs::{entity::Entity, event::Events, world::World},
+    math::Quat,
+};
+use common::{
+    components::{Armed, Camera, Depth, Orientation, Robot, RobotId, ServoTargets},
+    ecs_sync::NetId,
+    error::ErrorEvent,
+    types::{hw::DepthFrame, ids::CameraId},
+};
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use opencv::{
+    core::{self, Scalar, CV_8UC3},
+    imgproc,
+    objdetect::{self, PredefinedDictionaryType},
+    prelude::*,
+};
+
+use crate::{
+    video_pipelines::{
+        stereo::StereoCalibration, FromWorldEntity, Pipeline, PipelineCallbacks, PipelineCamera,
+        WorldCallback,
+    },
+    video_stream::LatestFrames,
+};
+
+pub const CAMERA: CameraId = CameraId::from_static("Bench");
+
+pub struct PipelineHarness<P: Pipeline> {
+    world: World,
+    pipeline: P,
+
+    robot: Entity,
+    camera: Entity,
+    pipeline_entity: Entity,
+
+    cmds_tx: Sender<WorldCallback>,
+    cmds_rx: Receiver<WorldCallback>,
+    should_end: bool,
+}
+
+impl<P: Pipeline + FromWorldEntity> PipelineHarness<P> {
+    pub fn new() -> anyhow::Result<Self> {
+        let mut world = World::new();
+        world.init_resource::<Events<ErrorEvent>>();
+        world.init_resource::<LatestFrames>();
+        world.init_resource::<StereoCalibration>();
+
+        let robot_id = RobotId(NetId::random());
+        let robot = world
+            .spawn((
+                Robot,
+                robot_id,
+                Orientation {
+                    rotation: Quat::IDENTITY,
+                    capture_time: 0.0,
+                },
+                Depth(DepthFrame::default()),
+                ServoTargets::default(),
+                Armed::Armed,
+            ))
+            .id();
+        let camera = world
+            .spawn((
+                Camera {
+                    id: CAMERA,
+                    location: SocketAddr::from(([127, 0, 0, 1], 0)),
+                },
+                robot_id,
+            ))
+            .id();
+
+        let pipeline = P::from(&mut world, camera).context("Build pipeline")?;
+        let pipeline_entity = world.spawn((PipelineCamera(camera), robot_id)).id();
+
+        let (cmds_tx, cmds_rx) = unbounded();
+
+        Ok(Self {
+            world,
+            pipeline,
+
+            robot,
+            camera,
+            pipeline_entity,
+
+            cmds_tx,
+            cmds_rx,
+            should_end: false,
+        })
+    }
+}
+
+impl<P: Pipeline> PipelineHarness<P> {
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    /// For adding whatever else the pipeline reads, like a measurement target
+    pub fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    pub fn robot(&self) -> Entity {
+        self.robot
+    }
+
+    pub fn camera(&self) -> Entity {
+        self.camera
+    }
+
+    pub fn pipeline_entity(&self) -> Entity {
+        self.pipeline_entity
+    }
+
+    /// Whether the pipeline asked to be stopped
+    pub fn should_end(&self) -> bool {
+        self.should_end
+    }
+
+    /// What a video thread would hand the pipeline for the next frame
+    pub fn collect_inputs(&self) -> P::Input {
+        P::collect_inputs(&self.world, &self.world.entity(self.pipeline_entity))
+    }
+
+    /// Runs the pipeline on `img` alone, world callbacks wait for [`Self::apply_callbacks`]
+    pub fn process(&mut self, input: &P::Input, img: &mut Mat) -> anyhow::Result<()> {
+        let mut callbacks = PipelineCallbacks {
+            cmds_tx: &self.cmds_tx,
+
+            pipeline_entity: self.pipeline_entity,
+            camera_entity: self.camera,
+
+            should_end: &mut self.should_end,
+        };
+
+        self.pipeline
+            .process(&mut callbacks, input, img)
+            .context("Process")?;
+
+        Ok(())
+    }
+
+    /// Applies the world callbacks sent since the last call
+    pub fn apply_callbacks(&mut self) {
+        for callback in self.cmds_rx.try_iter() {
+            (callback)(&mut self.world);
+        }
+    }
+
+    /// One whole frame, returns how long the pipeline itself took
+    pub fn run_frame(&mut self, img: &mut Mat) -> anyhow::Result<Duration> {
+        let input = self.collect_inputs();
+
+        let start = std::time::Instant::now();
+        self.process(&input, img)?;
+        let elapsed = start.elapsed();
+
+        self.apply_callbacks();
+
+        Ok(elapsed)
+    }
+}
+
+/// Black and white squares of `square` pixels
+pub fn checkerboard(width: i32, height: i32, square: i32) -> anyhow::Result<Mat> {
+    let mut mat = Mat::new_rows_cols_with_default(height, width, CV_8UC3, Scalar::all(255.0))
+        .context("Create frame")?;
+
+    let bytes = mat.data_bytes_mut().context("Get frame data")?;
+    for (pixel, bgr) in bytes.chunks_exact_mut(3).enumerate() {
+        let x = pixel as i32 % width;
+        let y = pixel as i32 / width;
+
+        if (x / square + y / square) % 2 == 0 {
+            bgr.fill(0);
+        }
+    }
+
+    Ok(mat)
+}
+
+/// A red square in blue water, what the square tracker looks for
+pub fn red_square_scene(width: i32, height: i32) -> anyhow::Result<Mat> {
+    let mut mat = Mat::new_rows_cols_with_default(
+        height,
+        width,
+        CV_8UC3,
+        Scalar::new(120.0, 80.0, 20.0, 0.0),
+    )
+    .context("Create frame")?;
+
+    let side = height / 3;
+    let (left, top) = ((width - side) / 2, (height - side) / 2);
+
+    let bytes = mat.data_bytes_mut().context("Get frame data")?;
+    for (pixel, bgr) in bytes.chunks_exact_mut(3).enumerate() {
+        let x = pixel as i32 % width;
+        let y = pixel as i32 / width;
+
+        if (left..left + side).contains(&x) && (top..top + side).contains(&y) {
+            bgr.copy_from_slice(&[20, 20, 220]);
+        }
+    }
+
+    Ok(mat)
+}
+
+/// Marker `id` of the dictionary the marker pipeline uses, centered on a white frame
+pub fn marker_scene(width: i32, height: i32, id: i32) -> anyhow::Result<Mat> {
+    let side = height / 2;
+    if side <= 0 || width < side {
+        bail!("Frame is too small for a marker");
+    }
+
+    let dictionary = objdetect::get_predefined_dictionary(PredefinedDictionaryType::DICT_4X4_50)
+        .context("Get marker dictionary")?;
+
+    let mut marker = Mat::default();
+    objdetect::generate_image_marker_def(&dictionary, id, side, &mut marker)
+        .context("Generate marker")?;
+
+    let mut bgr = Mat::default();
+    imgproc::cvt_color_def(&marker, &mut bgr, imgproc::COLOR_GRAY2BGR).context("Convert to BGR")?;
+
+    let (left, top) = ((width - side) / 2, (height - side) / 2);
+    let mut mat = Mat::default();
+    core::copy_make_border(
+        &bgr,
+        &mut mat,
+        top,
+        height - side - top,
+        left,
+        width - side - left,
+        core::BORDER_CONSTANT,
+        Scalar::all(255.0),
+    )
+    .context("Pad marker")?;
+
+    Ok(mat)
+}
+
+#[cfg(test)]
+mod tests {
+    use opencv::prelude::*;
+
+    use crate::video_pipelines::{
+        edges::EdgesPipeline,
+        marker::{MarkerDetection, MarkerPipeline},
+    };
+
+    use super::{checkerboard, marker_scene, red_square_scene, PipelineHarness};
+
+    #[test]
+    fn fixtures_have_the_requested_size() {
+        for fixture in [
+            checkerboard(640, 480, 40).unwrap(),
+            red_square_scene(640, 480).unwrap(),
+            marker_scene(640, 480, 7).unwrap(),
+        ] {
+            assert_eq!((fixture.cols(), fixture.rows()), (640, 480));
+            assert_eq!(fixture.channels(), 3);
+        }
+    }
+
+    #[test]
+    fn marker_fixture_is_detected() {
+        let mut harness = PipelineHarness::<MarkerPipeline>::new().unwrap();
+        let mut frame = marker_scene(640, 480, 7).unwrap();
+
+        harness.run_frame(&mut frame).unwrap();
+
+        let mut detections = harness.world_mut().query::<&MarkerDetection>();
+        let ids = detections
+            .iter(harness.world())
+            .map(|it| it.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![7]);
+    }
+
+    #[test]
+    fn stateless_pipelines_run() {
+        let mut harness = PipelineHarness::<EdgesPipeline>::new().unwrap();
+        let mut frame = checkerboard(64, 48, 8).unwrap();
+
+        harness.run_frame(&mut frame).unwrap();
+        assert!(!harness.should_end());
+    }
+}
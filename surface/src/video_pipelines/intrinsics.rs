@@ -0,0 +1,242 @@
+//! Where pipelines get their camera intrinsics from
+//!
+//! The robot ships each camera's calibration as a [`CameraIntrinsics`] component. Cameras without
+//! one fall back to the surface's [`StereoCalibration`], and then to [`DEFAULT_INTRINSICS`] with a
+//! warning. Intrinsics are rescaled to whatever resolution frames actually arrive at.
+
+use std::fmt::{self, Display};
+
+use anyhow::Context;
+use bevy::prelude::{EntityRef, World};
+use common::components::{Camera, CameraIntrinsics};
+use opencv::{core::Size, prelude::*, types::VectorOff64};
+use tracing::{info, warn};
+
+use crate::video_pipelines::{stereo::StereoCalibration, PipelineCamera};
+
+/// The robot's front camera as calibrated for the 2024 competition, at 1080p
+pub const DEFAULT_INTRINSICS: CameraIntrinsics = CameraIntrinsics {
+    resolution: (1920, 1080),
+    fx: 1.28191219e+03,
+    fy: 1.28020562e+03,
+    cx: 1.01414124e+03,
+    cy: 5.30598083e+02,
+    distortion: [
+        -4.01928524e-01,
+        2.05847758e-01,
+        -1.51617786e-04,
+        7.81120105e-04,
+        -5.77244616e-02,
+    ],
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IntrinsicsSource {
+    /// Shipped by the robot with the camera
+    Camera,
+    /// The surface's [`StereoCalibration`]
+    CalibrationStore,
+    /// [`DEFAULT_INTRINSICS`]
+    #[default]
+    Default,
+}
+
+impl Display for IntrinsicsSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntrinsicsSource::Camera => write!(f, "the robot"),
+            IntrinsicsSource::CalibrationStore => write!(f, "the stereo calibration"),
+            IntrinsicsSource::Default => write!(f, "the defaults"),
+        }
+    }
+}
+
+/// Intrinsics for a camera along with where they came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResolvedIntrinsics {
+    pub intrinsics: CameraIntrinsics,
+    pub source: IntrinsicsSource,
+}
+
+impl Default for ResolvedIntrinsics {
+    fn default() -> Self {
+        Self {
+            intrinsics: DEFAULT_INTRINSICS,
+            source: IntrinsicsSource::Default,
+        }
+    }
+}
+
+/// The first of the camera's own intrinsics and those from the calibration store
+pub fn resolve(
+    from_camera: Option<CameraIntrinsics>,
+    from_store: Option<CameraIntrinsics>,
+) -> ResolvedIntrinsics {
+    match (from_camera, from_store) {
+        (Some(intrinsics), _) => ResolvedIntrinsics {
+            intrinsics,
+            source: IntrinsicsSource::Camera,
+        },
+        (None, Some(intrinsics)) => ResolvedIntrinsics {
+            intrinsics,
+            source: IntrinsicsSource::CalibrationStore,
+        },
+        (None, None) => ResolvedIntrinsics::default(),
+    }
+}
+
+/// Intrinsics of the camera the pipeline on `entity` is running on
+///
+/// For use in [`Pipeline::collect_inputs`](super::Pipeline::collect_inputs) so intrinsics that
+/// arrive after the pipeline started are picked up
+pub fn collect_intrinsics(world: &World, entity: &EntityRef) -> ResolvedIntrinsics {
+    let Some(camera) = entity.get::<PipelineCamera>().map(|it| it.camera()) else {
+        return ResolvedIntrinsics::default();
+    };
+
+    let from_camera = world.get::<CameraIntrinsics>(camera).copied();
+    let from_store = world.get::<Camera>(camera).and_then(|camera| {
+        world
+            .get_resource::<StereoCalibration>()?
+            .cameras
+            .get(&camera.id)
+            .copied()
+    });
+
+    resolve(from_camera, from_store)
+}
+
+/// Keeps the resolved intrinsics scaled to the size of the frames being processed
+#[derive(Debug, Default)]
+pub struct FrameIntrinsics {
+    source: Option<IntrinsicsSource>,
+    base: Option<CameraIntrinsics>,
+    scaled: Option<(Size, CameraIntrinsics)>,
+}
+
+impl FrameIntrinsics {
+    /// `resolved` at `size`, logs whenever the source changes
+    pub fn update(&mut self, resolved: &ResolvedIntrinsics, size: Size) -> CameraIntrinsics {
+        if self.source != Some(resolved.source) {
+            match resolved.source {
+                IntrinsicsSource::Default => {
+                    warn!("Camera is not calibrated, using default intrinsics")
+                }
+                source => info!("Using camera intrinsics from {source}"),
+            }
+
+            self.source = Some(resolved.source);
+        }
+
+        if self.base != Some(resolved.intrinsics) {
+            self.base = Some(resolved.intrinsics);
+            self.scaled = None;
+        }
+
+        match self.scaled {
+            Some((scaled_size, scaled)) if scaled_size == size => scaled,
+            _ => {
+                let scaled = resolved
+                    .intrinsics
+                    .scaled_to(size.width as u32, size.height as u32);
+                self.scaled = Some((size, scaled));
+
+                scaled
+            }
+        }
+    }
+
+    /// Where the intrinsics of the last frame came from
+    pub fn source(&self) -> Option<IntrinsicsSource> {
+        self.source
+    }
+}
+
+/// The OpenCV forms of [`CameraIntrinsics`]
+pub trait IntrinsicsExt {
+    fn camera_matrix(&self) -> anyhow::Result<Mat>;
+    fn distortion(&self) -> VectorOff64;
+}
+
+impl IntrinsicsExt for CameraIntrinsics {
+    fn camera_matrix(&self) -> anyhow::Result<Mat> {
+        #[rustfmt::skip]
+        let matrix = [
+            self.fx, 0.0, self.cx,
+            0.0, self.fy, self.cy,
+            0.0, 0.0, 1.0,
+        ];
+
+        Mat::from_slice_rows_cols(&matrix, 3, 3).context("Create camera matrix")
+    }
+
+    fn distortion(&self) -> VectorOff64 {
+        VectorOff64::from_slice(&self.distortion)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common::components::CameraIntrinsics;
+    use opencv::core::Size;
+
+    use super::{resolve, FrameIntrinsics, IntrinsicsSource, DEFAULT_INTRINSICS};
+
+    const CAMERA: CameraIntrinsics = CameraIntrinsics {
+        resolution: (1280, 720),
+        fx: 800.0,
+        fy: 790.0,
+        cx: 640.0,
+        cy: 360.0,
+        distortion: [0.1, -0.2, 0.0, 0.0, 0.05],
+    };
+
+    const STORE: CameraIntrinsics = CameraIntrinsics {
+        fx: 900.0,
+        fy: 900.0,
+        ..CAMERA
+    };
+
+    #[test]
+    fn camera_intrinsics_take_precedence() {
+        let resolved = resolve(Some(CAMERA), Some(STORE));
+        assert_eq!(resolved.intrinsics, CAMERA);
+        assert_eq!(resolved.source, IntrinsicsSource::Camera);
+
+        let resolved = resolve(None, Some(STORE));
+        assert_eq!(resolved.intrinsics, STORE);
+        assert_eq!(resolved.source, IntrinsicsSource::CalibrationStore);
+
+        let resolved = resolve(None, None);
+        assert_eq!(resolved.intrinsics, DEFAULT_INTRINSICS);
+        assert_eq!(resolved.source, IntrinsicsSource::Default);
+    }
+
+    #[test]
+    fn intrinsics_follow_the_frame_size() {
+        let mut frame = FrameIntrinsics::default();
+        let resolved = resolve(Some(CAMERA), None);
+
+        // Native resolution is untouched
+        assert_eq!(frame.update(&resolved, Size::new(1280, 720)), CAMERA);
+        assert_eq!(frame.source(), Some(IntrinsicsSource::Camera));
+
+        // Half resolution halves everything in pixels but the distortion
+        let half = frame.update(&resolved, Size::new(640, 360));
+        assert_eq!(half.resolution, (640, 360));
+        assert_eq!(
+            (half.fx, half.fy, half.cx, half.cy),
+            (400.0, 395.0, 320.0, 180.0)
+        );
+        assert_eq!(half.distortion, CAMERA.distortion);
+
+        // Stretched frames scale each axis on its own
+        let stretched = frame.update(&resolved, Size::new(1920, 720));
+        assert_eq!((stretched.fx, stretched.cx), (1200.0, 960.0));
+        assert_eq!((stretched.fy, stretched.cy), (790.0, 360.0));
+
+        // New intrinsics arriving from the robot replace the cached ones
+        let resolved = resolve(Some(STORE), None);
+        assert_eq!(frame.update(&resolved, Size::new(1920, 720)).fy, 900.0);
+    }
+}
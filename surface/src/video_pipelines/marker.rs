@@ -1,15 +1,36 @@
-use anyhow::Context;
+//! Detects ArUco markers and publishes them into the ECS
+//!
+//! Each visible marker gets a child entity of the pipeline entity named `aruco-<id>` holding its
+//! [`MarkerDetection`] and a camera relative [`Transform`] when a pose could be estimated. Markers
+//! not seen for [`MAX_MISSED_FRAMES`] frames are despawned.
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{bail, Context};
 use bevy::{
     app::{App, Plugin},
+    core::Name,
+    ecs::{component::Component, entity::Entity},
+    hierarchy::{BuildWorldChildren, Children, DespawnRecursiveExt},
+    math::{Quat, Vec2, Vec3},
     prelude::{EntityRef, EntityWorldMut, World},
+    transform::components::Transform,
 };
+use common::components::CameraIntrinsics;
 use opencv::{
-    core::{Point, Scalar},
-    imgproc,
+    calib3d,
+    core::{Point, Point2f, Point3f, Scalar},
+    objdetect::{self, ArucoDetector, DetectorParameters, PredefinedDictionaryType},
     prelude::*,
+    types::{VectorOfPoint2f, VectorOfPoint3f, VectorOfVectorOfPoint2f, VectorOff64, VectorOfi32},
 };
+use tracing::{debug_span, instrument, warn};
 
-use crate::video_pipelines::{AppPipelineExt, Pipeline, PipelineCallbacks};
+use crate::video_pipelines::{
+    intrinsics::{self, FrameIntrinsics, IntrinsicsExt, IntrinsicsSource, ResolvedIntrinsics},
+    stereo::put_label,
+    AppPipelineExt, FromWorldEntity, Pipeline, PipelineCallbacks,
+};
 
 pub struct MarkerPipelinePlugin;
 
@@ -19,29 +40,454 @@ impl Plugin for MarkerPipelinePlugin {
     }
 }
 
-#[derive(Default)]
-pub struct MarkerPipeline;
+/// Frames a marker may go unseen before its entity is despawned
+pub const MAX_MISSED_FRAMES: u32 = 15;
+/// Meters, side length of the printed markers' black border
+pub const MARKER_SIZE: f32 = 0.1;
+/// Meters, length of the axes drawn on posed markers
+const AXIS_LENGTH: f32 = MARKER_SIZE / 2.0;
+
+/// A marker seen in the last frame it was visible in
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct MarkerDetection {
+    pub id: i32,
+    /// Pixels, clockwise from the marker's top left corner
+    pub corners: [Vec2; 4],
+    /// Pixels, RMS distance between the detected corners and those of the estimated pose
+    ///
+    /// `None` when no pose could be estimated
+    pub reprojection_error: Option<f32>,
+}
+
+/// Camera relative pose of a marker, +X is right, +Y is down and +Z is forward
+pub struct MarkerPose {
+    pub transform: Transform,
+    pub reprojection_error: f32,
+
+    rvec: VectorOff64,
+    tvec: VectorOff64,
+}
+
+/// Corners of a marker in its own frame, in the order the detector reports them
+fn marker_points(marker_size: f32) -> VectorOfPoint3f {
+    let half = marker_size / 2.0;
+
+    VectorOfPoint3f::from_iter([
+        Point3f::new(-half, half, 0.0),
+        Point3f::new(half, half, 0.0),
+        Point3f::new(half, -half, 0.0),
+        Point3f::new(-half, -half, 0.0),
+    ])
+}
+
+/// Solves for the pose of a `marker_size` marker seen at `corners`
+pub fn estimate_pose(
+    corners: &[Vec2; 4],
+    intrinsics: &CameraIntrinsics,
+    marker_size: f32,
+) -> anyhow::Result<MarkerPose> {
+    let camera_matrix = intrinsics.camera_matrix()?;
+    let distortion = intrinsics.distortion();
+
+    let object_points = marker_points(marker_size);
+    let image_points = VectorOfPoint2f::from_iter(
+        corners
+            .iter()
+            .map(|corner| Point2f::new(corner.x, corner.y)),
+    );
+
+    let mut rvec = VectorOff64::new();
+    let mut tvec = VectorOff64::new();
+    let success = calib3d::solve_pnp(
+        &object_points,
+        &image_points,
+        &camera_matrix,
+        &distortion,
+        &mut rvec,
+        &mut tvec,
+        false,
+        calib3d::SOLVEPNP_IPPE_SQUARE,
+    )
+    .context("Solve PnP")?;
+
+    if !success {
+        bail!("Bad PnP");
+    }
+
+    let mut projected = VectorOfPoint2f::new();
+    calib3d::project_points_def(
+        &object_points,
+        &rvec,
+        &tvec,
+        &camera_matrix,
+        &distortion,
+        &mut projected,
+    )
+    .context("Project marker points")?;
+
+    let projected: Vec<Vec2> = projected
+        .iter()
+        .map(|point| Vec2::new(point.x, point.y))
+        .collect();
+    let reprojection_error = reprojection_error(corners, &projected);
+
+    let rotation = Vec3::new(
+        rvec.get(0)? as f32,
+        rvec.get(1)? as f32,
+        rvec.get(2)? as f32,
+    );
+    let translation = Vec3::new(
+        tvec.get(0)? as f32,
+        tvec.get(1)? as f32,
+        tvec.get(2)? as f32,
+    );
+
+    Ok(MarkerPose {
+        transform: Transform::from_translation(translation)
+            .with_rotation(Quat::from_scaled_axis(rotation)),
+        reprojection_error,
+
+        rvec,
+        tvec,
+    })
+}
+
+/// RMS distance between matching points
+pub fn reprojection_error(detected: &[Vec2], projected: &[Vec2]) -> f32 {
+    if detected.is_empty() || detected.len() != projected.len() {
+        return f32::INFINITY;
+    }
+
+    let squared: f32 = detected
+        .iter()
+        .zip(projected)
+        .map(|(a, b)| a.distance_squared(*b))
+        .sum();
+
+    (squared / detected.len() as f32).sqrt()
+}
+
+/// Frames since each marker was last seen
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MarkerTracker {
+    missed: BTreeMap<i32, u32>,
+}
+
+impl MarkerTracker {
+    /// Records the markers visible this frame, returns those that went stale
+    pub fn update(&mut self, visible: &[i32], max_missed: u32) -> Vec<i32> {
+        for missed in self.missed.values_mut() {
+            *missed += 1;
+        }
+        for &id in visible {
+            self.missed.insert(id, 0);
+        }
+
+        let stale = self
+            .missed
+            .iter()
+            .filter(|(_, &missed)| missed > max_missed)
+            .map(|(&id, _)| id)
+            .collect::<Vec<_>>();
+
+        for id in &stale {
+            self.missed.remove(id);
+        }
+
+        stale
+    }
+}
+
+pub struct MarkerPipeline {
+    detector: ArucoDetector,
+    intrinsics: FrameIntrinsics,
+    tracker: MarkerTracker,
+
+    corners: VectorOfVectorOfPoint2f,
+    ids: VectorOfi32,
+    rejected: VectorOfVectorOfPoint2f,
+}
+
+impl FromWorldEntity for MarkerPipeline {
+    fn from(_world: &mut World, _camera: Entity) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let dictionary =
+            objdetect::get_predefined_dictionary(PredefinedDictionaryType::DICT_4X4_50)
+                .context("Get marker dictionary")?;
+        let detector = ArucoDetector::new(
+            &dictionary,
+            &DetectorParameters::default().context("Create detector parameters")?,
+            objdetect::RefineParameters::new(10.0, 3.0, true)
+                .context("Create refine parameters")?,
+        )
+        .context("Create marker detector")?;
+
+        Ok(Self {
+            detector,
+            intrinsics: FrameIntrinsics::default(),
+            tracker: MarkerTracker::default(),
+
+            corners: VectorOfVectorOfPoint2f::new(),
+            ids: VectorOfi32::new(),
+            rejected: VectorOfVectorOfPoint2f::new(),
+        })
+    }
+}
 
 impl Pipeline for MarkerPipeline {
-    type Input = ();
+    type Input = ResolvedIntrinsics;
 
-    fn collect_inputs(_world: &World, _entity: &EntityRef) -> Self::Input {
-        // No-op
+    fn collect_inputs(world: &World, entity: &EntityRef) -> Self::Input {
+        intrinsics::collect_intrinsics(world, entity)
     }
 
+    #[instrument(level = "debug", skip_all)]
     fn process<'b, 'a: 'b>(
         &'a mut self,
-        _cmds: &mut PipelineCallbacks,
-        _data: &Self::Input,
+        cmds: &mut PipelineCallbacks,
+        data: &Self::Input,
         img: &'b mut Mat,
     ) -> anyhow::Result<&'b mut Mat> {
-        imgproc::draw_marker_def(img, Point::new(720, 480), Scalar::new(0.5, 1.0, 0.75, 1.0))
-            .context("Draw marker")?;
+        let size = img.size().context("Get image size")?;
+        let intrinsics = self.intrinsics.update(data, size);
+
+        debug_span!("detect").in_scope(|| {
+            self.detector
+                .detect_markers(img, &mut self.corners, &mut self.ids, &mut self.rejected)
+                .context("Detect markers")
+        })?;
+
+        let pose_span = debug_span!("pose").entered();
+        let mut detections = Vec::with_capacity(self.ids.len());
+        for (idx, id) in self.ids.iter().enumerate() {
+            let points = self.corners.get(idx).context("Get marker corners")?;
+            let mut corners = [Vec2::ZERO; 4];
+            for (corner, point) in corners.iter_mut().zip(points.iter()) {
+                *corner = Vec2::new(point.x, point.y);
+            }
+
+            let pose = match estimate_pose(&corners, &intrinsics, MARKER_SIZE) {
+                Ok(pose) => Some(pose),
+                Err(err) => {
+                    warn!("Could not estimate pose of marker {id}: {err:?}");
+                    None
+                }
+            };
+
+            if let Some(pose) = &pose {
+                calib3d::draw_frame_axes(
+                    img,
+                    &intrinsics.camera_matrix()?,
+                    &intrinsics.distortion(),
+                    &pose.rvec,
+                    &pose.tvec,
+                    AXIS_LENGTH,
+                    3,
+                )
+                .context("Draw marker axes")?;
+            }
+
+            detections.push((
+                MarkerDetection {
+                    id,
+                    corners,
+                    reprojection_error: pose.as_ref().map(|it| it.reprojection_error),
+                },
+                pose.map(|it| it.transform),
+            ));
+        }
+        pose_span.exit();
+
+        let _draw_span = debug_span!("draw").entered();
+        objdetect::draw_detected_markers(
+            img,
+            &self.corners,
+            &self.ids,
+            Scalar::new(0.0, 255.0, 0.0, 0.0),
+        )
+        .context("Draw markers")?;
+
+        if self.intrinsics.source() == Some(IntrinsicsSource::Default) {
+            put_label(
+                img,
+                "Not calibrated, marker poses are approximate",
+                Point::new(10, 30),
+                Scalar::new(0.0, 255.0, 255.0, 0.0),
+            )?;
+        }
+
+        let visible = detections
+            .iter()
+            .map(|(detection, _)| detection.id)
+            .collect::<Vec<_>>();
+        let stale = self.tracker.update(&visible, MAX_MISSED_FRAMES);
+
+        if !detections.is_empty() || !stale.is_empty() {
+            publish(cmds, detections, stale);
+        }
 
         Ok(img)
     }
 
     fn cleanup(_entity_world: &mut EntityWorldMut) {
-        // No-op
+        // Marker entities are children of the pipeline entity and are despawned with it
+    }
+}
+
+/// Updates the marker entities under the pipeline entity
+fn publish(
+    cmds: &mut PipelineCallbacks,
+    detections: Vec<(MarkerDetection, Option<Transform>)>,
+    stale: Vec<i32>,
+) {
+    cmds.pipeline(move |mut pipeline| {
+        let pipeline_entity = pipeline.id();
+
+        pipeline.world_scope(|world| {
+            let mut markers: HashMap<i32, Entity> = world
+                .get::<Children>(pipeline_entity)
+                .into_iter()
+                .flat_map(|children| children.iter())
+                .filter_map(|&child| {
+                    world
+                        .get::<MarkerDetection>(child)
+                        .map(|detection| (detection.id, child))
+                })
+                .collect();
+
+            for id in stale {
+                if let Some(marker) = markers.remove(&id) {
+                    world.entity_mut(marker).despawn_recursive();
+                }
+            }
+
+            for (detection, transform) in detections {
+                let mut marker = match markers.get(&detection.id) {
+                    Some(&marker) => world.entity_mut(marker),
+                    None => {
+                        let mut marker = world.spawn(Name::new(format!("aruco-{}", detection.id)));
+                        marker.set_parent(pipeline_entity);
+
+                        marker
+                    }
+                };
+
+                marker.insert(detection);
+                match transform {
+                    Some(transform) => {
+                        marker.insert(transform);
+                    }
+                    None => {
+                        marker.remove::<Transform>();
+                    }
+                }
+            }
+        });
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{Quat, Vec2, Vec3};
+    use common::components::CameraIntrinsics;
+
+    use super::{estimate_pose, reprojection_error, MarkerTracker};
+
+    const INTRINSICS: CameraIntrinsics = CameraIntrinsics {
+        resolution: (1280, 720),
+        fx: 800.0,
+        fy: 800.0,
+        cx: 640.0,
+        cy: 360.0,
+        distortion: [0.0; 5],
+    };
+
+    /// Where the corners of a `size` marker at `rotation` and `translation` land in the frame
+    fn project(rotation: Quat, translation: Vec3, size: f32) -> [Vec2; 4] {
+        let half = size / 2.0;
+        let corners = [
+            Vec3::new(-half, half, 0.0),
+            Vec3::new(half, half, 0.0),
+            Vec3::new(half, -half, 0.0),
+            Vec3::new(-half, -half, 0.0),
+        ];
+
+        corners.map(|corner| {
+            let point = rotation * corner + translation;
+
+            Vec2::new(
+                INTRINSICS.fx as f32 * point.x / point.z + INTRINSICS.cx as f32,
+                INTRINSICS.fy as f32 * point.y / point.z + INTRINSICS.cy as f32,
+            )
+        })
+    }
+
+    #[test]
+    fn poses_synthetic_markers() {
+        let poses = [
+            (Quat::IDENTITY, Vec3::new(0.0, 0.0, 1.0)),
+            (Quat::from_rotation_y(0.4), Vec3::new(0.2, -0.1, 0.8)),
+            (
+                Quat::from_rotation_x(-0.3) * Quat::from_rotation_z(1.2),
+                Vec3::new(-0.3, 0.15, 1.6),
+            ),
+        ];
+
+        for (rotation, translation) in poses {
+            let corners = project(rotation, translation, 0.1);
+            let pose = estimate_pose(&corners, &INTRINSICS, 0.1).unwrap();
+
+            assert!(
+                pose.transform.translation.distance(translation) < 1e-3,
+                "{} != {translation}",
+                pose.transform.translation
+            );
+            assert!(
+                pose.transform.rotation.angle_between(rotation) < 1e-2,
+                "{} != {rotation}",
+                pose.transform.rotation
+            );
+            assert!(
+                pose.reprojection_error < 0.01,
+                "{}",
+                pose.reprojection_error
+            );
+        }
+    }
+
+    #[test]
+    fn reprojection_error_is_rms() {
+        let detected = [Vec2::ZERO, Vec2::new(10.0, 0.0)];
+
+        assert_eq!(reprojection_error(&detected, &detected), 0.0);
+        assert_eq!(
+            reprojection_error(&detected, &[Vec2::new(3.0, 4.0), Vec2::new(10.0, 5.0)]),
+            5.0
+        );
+        assert_eq!(reprojection_error(&detected, &detected[..1]), f32::INFINITY);
+    }
+
+    #[test]
+    fn stale_markers_expire() {
+        let mut tracker = MarkerTracker::default();
+
+        assert!(tracker.update(&[1, 2], 2).is_empty());
+        assert!(tracker.update(&[1], 2).is_empty());
+        assert!(tracker.update(&[1], 2).is_empty());
+        // 2 has now been missed for three frames
+        assert_eq!(tracker.update(&[1], 2), [2]);
+
+        // Seeing a marker again resets it
+        assert!(tracker.update(&[], 2).is_empty());
+        assert!(tracker.update(&[], 2).is_empty());
+        assert!(tracker.update(&[1, 3], 2).is_empty());
+        assert!(tracker.update(&[3], 2).is_empty());
+        assert!(tracker.update(&[3], 2).is_empty());
+        assert_eq!(tracker.update(&[3], 2), [1]);
+
+        // Expired markers aren't reported twice
+        assert!(tracker.update(&[3], 2).is_empty());
     }
 }
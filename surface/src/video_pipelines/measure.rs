@@ -15,10 +15,15 @@ use opencv::{
     prelude::*,
     types::{VectorOfVectorOfPoint, VectorOfVectorOfPoint2f},
 };
-
-use crate::video_pipelines::{
-    edges::EdgesPipeline, scale::ScalePipeline, undistort::UndistortPipeline, AppPipelineExt,
-    Pipeline, PipelineCallbacks, SerialPipeline,
+use serde_json::json;
+use tracing::{debug_span, instrument};
+
+use crate::{
+    pipeline_results::PipelineResult,
+    video_pipelines::{
+        edges::EdgesPipeline, scale::ScalePipeline, undistort::UndistortPipeline, AppPipelineExt,
+        Pipeline, PipelineCallbacks, SerialPipeline,
+    },
 };
 
 pub struct MeasurePipelinePlugin;
@@ -74,9 +79,10 @@ impl Pipeline for MeasurePipeline {
     }
 
     // TODO: Make the api useful for breaking this up
+    #[instrument(level = "debug", skip_all)]
     fn process<'b, 'a: 'b>(
         &'a mut self,
-        _cmds: &mut PipelineCallbacks,
+        cmds: &mut PipelineCallbacks,
         data: &Self::Input,
         img: &'b mut Mat,
     ) -> anyhow::Result<&'b mut Mat> {
@@ -100,15 +106,18 @@ impl Pipeline for MeasurePipeline {
         };
 
         // imgproc::blur_def(img, &mut self.blur, Size::new(3, 3)).context("Blur")?;
-        imgproc::canny_def(img, &mut self.edges, 100.0, 100.0).context("Canny")?;
-        imgproc::find_contours_def(
-            &self.edges,
-            &mut self.contours,
-            imgproc::RETR_LIST,
-            // TODO: Are the other approximation modes better
-            imgproc::CHAIN_APPROX_SIMPLE,
-        )
-        .context("Find contours")?;
+        debug_span!("edges")
+            .in_scope(|| imgproc::canny_def(img, &mut self.edges, 100.0, 100.0).context("Canny"))?;
+        debug_span!("contours").in_scope(|| {
+            imgproc::find_contours_def(
+                &self.edges,
+                &mut self.contours,
+                imgproc::RETR_LIST,
+                // TODO: Are the other approximation modes better
+                imgproc::CHAIN_APPROX_SIMPLE,
+            )
+            .context("Find contours")
+        })?;
 
         println!("Found {} contours", self.contours.len());
 
@@ -176,6 +185,7 @@ impl Pipeline for MeasurePipeline {
                 .context("Draw centroid")?;
 
                 let mut rect = imgproc::min_area_rect(&contour).context("Get Rotated Rect")?;
+                let (width, height) = (rect.size.width, rect.size.height);
                 if rect.size.width > rect.size.height {
                     rect.size.width *= ROI_FACTOR;
                 } else {
@@ -207,6 +217,16 @@ impl Pipeline for MeasurePipeline {
                     (255, 255, 0).into(),
                 )
                 .context("Draw Centerline")?;
+
+                let result = json!({
+                    "centroid_px": [c_x, c_y],
+                    "area_px": moments.m00,
+                    "size_px": [width, height],
+                    "angle_deg": line[1].atan2(line[0]).to_degrees(),
+                });
+                cmds.pipeline(move |mut entity| {
+                    entity.insert(PipelineResult(result));
+                });
             }
         }
 
@@ -5,6 +5,7 @@ use bevy::{
 };
 use opencv::{imgcodecs, prelude::*};
 use time::format_description::well_known::Iso8601;
+use tracing::instrument;
 
 use crate::video_pipelines::{AppPipelineExt, Pipeline, PipelineCallbacks};
 
@@ -26,6 +27,7 @@ impl Pipeline for SavePipeline {
         // No-op
     }
 
+    #[instrument(level = "debug", skip_all)]
     fn process<'b, 'a: 'b>(
         &'a mut self,
         cmds: &mut PipelineCallbacks,
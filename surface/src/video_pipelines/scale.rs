@@ -4,6 +4,7 @@ use bevy::{
     prelude::{EntityRef, EntityWorldMut, World},
 };
 use opencv::{core::Size, imgproc, prelude::*};
+use tracing::instrument;
 
 use crate::video_pipelines::{AppPipelineExt, Pipeline, PipelineCallbacks};
 
@@ -27,6 +28,7 @@ impl<const BASE: u32, const EXPONENT: i32> Pipeline for ScalePipeline<BASE, EXPO
         // No-op
     }
 
+    #[instrument(level = "debug", skip_all)]
     fn process<'b, 'a: 'b>(
         &'a mut self,
         _cmds: &mut PipelineCallbacks,
@@ -23,9 +23,17 @@ use opencv::{
         VectorOfVectorOfPoint, VectorOff64,
     },
 };
-use tracing::error;
-
-use crate::video_pipelines::{AppPipelineExt, Pipeline, PipelineCallbacks};
+use serde_json::json;
+use tracing::{debug_span, error, instrument};
+
+use crate::{
+    input,
+    pipeline_results::PipelineResult,
+    video_pipelines::{
+        intrinsics::{self, FrameIntrinsics, IntrinsicsExt, ResolvedIntrinsics},
+        AppPipelineExt, Pipeline, PipelineCallbacks,
+    },
+};
 
 // Autonomous pipeline for brain coral transplantation
 pub struct SquarePipelinePlugin;
@@ -63,6 +71,8 @@ pub struct SquareTrackingPipeline {
     rvec: VectorOff64,
     // Computed translation relative to the square
     tvec: VectorOff64,
+    // Camera intrinsics at the current frame size
+    intrinsics: FrameIntrinsics,
     // rotation_mat: Mat,
 }
 
@@ -75,13 +85,22 @@ enum InternalState {
     ReleasePayload,
 }
 
-impl Pipeline for SquareTrackingPipeline {
-    // (robot, robot_orientation,)
-    type Input = Option<(Entity, Orientation, Depth, ServoTargets)>;
+impl InternalState {
+    fn name(&self) -> &'static str {
+        match self {
+            InternalState::MoveAboveTarget => "Move Above Target",
+            InternalState::LowerDepth => "Lower Depth",
+            InternalState::ReleasePayload => "Release Payload",
+        }
+    }
+}
 
-    // Extracts the necessary data from the ECS world
-    // Runs on the main thread
-    fn collect_inputs(world: &World, entity: &EntityRef) -> Self::Input {
+impl SquareTrackingPipeline {
+    // Reads the state of the robot the camera is attached to
+    fn collect_robot(
+        world: &World,
+        entity: &EntityRef,
+    ) -> Option<(Entity, Orientation, Depth, ServoTargets)> {
         // Get id of attached robot
         let robot_id = entity.get::<RobotId>()?;
 
@@ -101,9 +120,27 @@ impl Pipeline for SquareTrackingPipeline {
 
         Some((robot.id(), orientation, depth, servos))
     }
+}
+
+impl Pipeline for SquareTrackingPipeline {
+    // (camera_intrinsics, (robot, robot_orientation, robot_depth, servo_targets))
+    type Input = (
+        ResolvedIntrinsics,
+        Option<(Entity, Orientation, Depth, ServoTargets)>,
+    );
+
+    // Extracts the necessary data from the ECS world
+    // Runs on the main thread
+    fn collect_inputs(world: &World, entity: &EntityRef) -> Self::Input {
+        // Find the calibration of the camera
+        let intrinsics = intrinsics::collect_intrinsics(world, entity);
+
+        (intrinsics, Self::collect_robot(world, entity))
+    }
 
     // Process the latest frame from the camera
     // Runs async
+    #[instrument(level = "debug", skip_all)]
     fn process<'b, 'a: 'b>(
         &'a mut self,
         cmds: &mut PipelineCallbacks,
@@ -111,12 +148,17 @@ impl Pipeline for SquareTrackingPipeline {
         img: &'b mut Mat,
     ) -> anyhow::Result<&'b mut Mat> {
         // Make sure we have know the robot orientation
-        let Some((robot, orientation, depth, ref servos)) = *data else {
+        let (ref intrinsics, Some((robot, orientation, depth, ref servos))) = *data else {
             return Ok(img);
         };
 
+        // Whether the target was found in this frame, otherwise `tvec` is from an earlier one
+        let mut located = false;
+
         // Try to run the image processing pipeline
         let res: Result<_, anyhow::Error> = try {
+            let threshold_span = debug_span!("threshold").entered();
+
             // Use HSV to better differentiate colors
             imgproc::cvt_color_def(img, &mut self.hsv, imgproc::COLOR_BGR2HSV)
                 .context("Convert to HSV")?;
@@ -150,6 +192,9 @@ impl Pipeline for SquareTrackingPipeline {
             // core::bitwise_and(img, img, &mut self.color_mask, &self.mask).context("Color mask")?;
             // return Ok(&mut self.color_mask);
 
+            threshold_span.exit();
+            let contours_span = debug_span!("contours").entered();
+
             // Find contours in mask
             self.contours.clear();
             imgproc::find_contours_def(
@@ -187,6 +232,8 @@ impl Pipeline for SquareTrackingPipeline {
                 }
             }
 
+            contours_span.exit();
+
             // Choose best square based on
             // - Size
             // - Proximity to best square in previous frame (improves temporal consistancy)
@@ -238,54 +285,36 @@ impl Pipeline for SquareTrackingPipeline {
                 let img_points: VectorOfPoint2f =
                     square.iter().flat_map(|it| it.to::<f32>()).collect();
 
-                // Tempoary hard coded camera martix
-                let camera_matrix = Mat::from_slice_rows_cols(
-                    &[
-                        1.28191219e+03,
-                        0.00000000e+00,
-                        1.01414124e+03,
-                        0.00000000e+00,
-                        1.28020562e+03,
-                        5.30598083e+02,
-                        0.00000000e+00,
-                        0.00000000e+00,
-                        1.00000000e+00,
-                    ],
-                    3,
-                    3,
-                )
-                .context("Create temp camera matrix")?;
-
-                // Tempoary hard coded distortion coefficients
-                let dist_coeffs = VectorOff64::from_slice(&[
-                    -4.01928524e-01,
-                    2.05847758e-01,
-                    -1.51617786e-04,
-                    7.81120105e-04,
-                    -5.77244616e-02,
-                ]);
+                // Calibration of the camera at the size of this frame
+                let size = img.size().context("Get image size")?;
+                let intrinsics = self.intrinsics.update(intrinsics, size);
+                let camera_matrix = intrinsics.camera_matrix()?;
+                let dist_coeffs = intrinsics.distortion();
 
                 println!("square: {square:?}");
                 println!("obj: {obj_points:.2?}");
 
                 // Use the known dimensions of the square to determine where it is in 3D space
                 // relative to the ROV
-                let success = calib3d::solve_pnp(
-                    &obj_points,
-                    &img_points,
-                    &camera_matrix,
-                    &dist_coeffs,
-                    &mut self.rvec,
-                    &mut self.tvec,
-                    false,
-                    calib3d::SOLVEPNP_IPPE_SQUARE,
-                )
-                .context("Solve PnP")?;
+                let success = debug_span!("pose").in_scope(|| {
+                    calib3d::solve_pnp(
+                        &obj_points,
+                        &img_points,
+                        &camera_matrix,
+                        &dist_coeffs,
+                        &mut self.rvec,
+                        &mut self.tvec,
+                        false,
+                        calib3d::SOLVEPNP_IPPE_SQUARE,
+                    )
+                    .context("Solve PnP")
+                })?;
 
                 // Make sure it actually worked
                 if !success {
                     bail!("Bad PnP");
                 }
+                located = true;
 
                 // Draw 3D axis on screen
                 {
@@ -382,6 +411,17 @@ impl Pipeline for SquareTrackingPipeline {
 
         println!("delta: {position_delta:.2?}");
 
+        // Record where the target was for the report
+        let result = json!({
+            "located": located,
+            "offset_m": position_delta.to_array(),
+            "depth_m": depth.0.depth.0,
+            "state": self.state.name(),
+        });
+        cmds.pipeline(move |mut entity| {
+            entity.insert(PipelineResult(result));
+        });
+
         // Movement
         //
         // PLAN:
@@ -392,7 +432,7 @@ impl Pipeline for SquareTrackingPipeline {
 
         // Need to always keep the target in the center of the camera's view
         // Calcualte the robot orientation necessary for that
-        let robot_orientation = orientation.0;
+        let robot_orientation = orientation.rotation;
         // TODO: Scale down the adjustment?
         // TODO: Implement smoothening
         let new_orientation_target =
@@ -469,11 +509,11 @@ impl Pipeline for SquareTrackingPipeline {
             InternalState::ReleasePayload => {
                 // Slowly open claw
                 cmds.pipeline(move |mut entity| {
-                    entity.insert(ServoContribution([("Claw1".into(), -0.1)].into()));
+                    entity.insert(ServoContribution([(input::CLAW, -0.1)].into()));
                 });
 
                 // If claw is open, end the pipeline
-                if servos.0.get("Claw1").iter().any(|&&val| val < -0.8) {
+                if servos.0.get(&input::CLAW).iter().any(|&&val| val < -0.8) {
                     cmds.should_end();
                 }
             }
@@ -0,0 +1,573 @@
+//! Holds the robot's planar position using optical flow from a downward facing camera
+//!
+//! Features are tracked between frames with pyramidal Lucas-Kanade. The median flow, less what
+//! the robot's change in pitch and roll explains, is scaled by the camera's distance to the floor
+//! into a drift in the robot's body frame. Drift is accumulated in the world's horizontal plane
+//! and countered with a [`MovementContribution`] on the pipeline entity.
+//!
+//! Tuning lives in [`StationKeepSettings`] on the pipeline entity. Corrections are suspended
+//! whenever the flow can't be trusted, and the pipeline ends when the robot is disarmed.
+
+use std::time::Instant;
+
+use anyhow::Context;
+use bevy::{
+    app::{App, Plugin},
+    ecs::{component::Component, entity::Entity},
+    math::{Quat, Vec2, Vec3, Vec3A},
+    prelude::{EntityRef, EntityWorldMut, World},
+    reflect::Reflect,
+};
+use common::components::{Armed, Camera, Depth, MovementContribution, Orientation, Robot, RobotId};
+use motor_math::Movement;
+use opencv::{
+    core::{Point, Scalar},
+    imgproc,
+    prelude::*,
+    types::{VectorOfPoint2f, VectorOff32, VectorOfu8},
+    video,
+};
+use tracing::{instrument, warn};
+
+use crate::video_pipelines::{
+    stereo::{put_label, StereoCalibration},
+    AppPipelineExt, FromWorldEntity, Pipeline, PipelineCallbacks,
+};
+
+pub struct StationKeepPipelinePlugin;
+
+impl Plugin for StationKeepPipelinePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<StationKeepSettings>()
+            .register_video_pipeline::<StationKeepPipeline>("Station Keep Pipeline");
+    }
+}
+
+/// Radians, horizontal field of view assumed for cameras without a calibration
+const ASSUMED_HFOV: f32 = 1.1;
+/// Most features tracked per frame
+const MAX_FEATURES: i32 = 200;
+/// Pixels, how far a feature's flow may be from the median and still agree with it
+const AGREEMENT_RADIUS: f32 = 2.0;
+/// Cosine of the steepest tilt the floor is still considered to be below the camera
+const MIN_TILT_COS: f32 = 0.5;
+
+/// Tuning for station keeping, insert on the pipeline entity to override the defaults
+#[derive(Component, Debug, Clone, Copy, Reflect, PartialEq)]
+pub struct StationKeepSettings {
+    /// Newtons per meter of accumulated drift
+    pub gain: f32,
+    /// Newtons per meter per second of drift velocity
+    pub damping: f32,
+    /// Newtons, largest correction ever requested
+    pub max_correction: f32,
+
+    /// Meters, distance between the camera and the floor
+    pub altitude: f32,
+    /// Meters, depth of the floor below the surface
+    ///
+    /// When set, altitude is derived from the robot's depth instead of `altitude`
+    pub floor_depth: Option<f32>,
+
+    /// Fewest features that must be tracked before corrections are made
+    pub min_features: usize,
+    /// Fraction of tracked features that must agree with the median flow
+    pub min_agreement: f32,
+}
+
+impl Default for StationKeepSettings {
+    fn default() -> Self {
+        Self {
+            gain: 40.0,
+            damping: 15.0,
+            max_correction: 20.0,
+
+            altitude: 0.5,
+            floor_depth: None,
+
+            min_features: 20,
+            min_agreement: 0.6,
+        }
+    }
+}
+
+impl StationKeepSettings {
+    /// Meters, vertical distance between the camera and the floor
+    pub fn altitude(&self, depth: Option<&Depth>) -> f32 {
+        match (self.floor_depth, depth) {
+            (Some(floor_depth), Some(depth)) => (floor_depth - depth.0.depth.0).max(0.0),
+            _ => self.altitude,
+        }
+    }
+}
+
+/// Agreement between the flow vectors of one frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlowEstimate {
+    /// Pixels, +X is right and +Y is down
+    pub median: Vec2,
+    pub tracked: usize,
+    /// Fraction of the tracked features within [`AGREEMENT_RADIUS`] of `median`
+    pub agreement: f32,
+}
+
+/// Why no correction was made this frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Suspended {
+    TooFewFeatures(usize),
+    LowAgreement(f32),
+    BadAltitude,
+}
+
+impl Suspended {
+    fn describe(&self) -> String {
+        match self {
+            Suspended::TooFewFeatures(tracked) => format!("Suspended: {tracked} features"),
+            Suspended::LowAgreement(agreement) => {
+                format!("Suspended: {:.0}% agreement", agreement * 100.0)
+            }
+            Suspended::BadAltitude => "Suspended: no altitude".to_owned(),
+        }
+    }
+}
+
+/// Summarizes the flow of the features tracked this frame
+pub fn estimate_flow(flow: &[Vec2]) -> Option<FlowEstimate> {
+    if flow.is_empty() {
+        return None;
+    }
+
+    let median = Vec2::new(
+        median(flow.iter().map(|it| it.x)),
+        median(flow.iter().map(|it| it.y)),
+    );
+    let agreeing = flow
+        .iter()
+        .filter(|it| it.distance(median) <= AGREEMENT_RADIUS)
+        .count();
+
+    Some(FlowEstimate {
+        median,
+        tracked: flow.len(),
+        agreement: agreeing as f32 / flow.len() as f32,
+    })
+}
+
+/// Decides whether `flow` is trustworthy enough to act on
+pub fn gate_flow(flow: &[Vec2], settings: &StationKeepSettings) -> Result<FlowEstimate, Suspended> {
+    let Some(estimate) = estimate_flow(flow) else {
+        return Err(Suspended::TooFewFeatures(0));
+    };
+
+    if estimate.tracked < settings.min_features {
+        return Err(Suspended::TooFewFeatures(estimate.tracked));
+    }
+    if estimate.agreement < settings.min_agreement {
+        return Err(Suspended::LowAgreement(estimate.agreement));
+    }
+
+    Ok(estimate)
+}
+
+fn median(values: impl Iterator<Item = f32>) -> f32 {
+    let mut values: Vec<f32> = values.collect();
+    values.sort_unstable_by(f32::total_cmp);
+
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Converts the median flow between two frames into how far the robot moved, in meters
+///
+/// The camera is assumed to look straight down the robot's -Z axis with the top of the image
+/// towards the robot's front. The returned vector is in the robot's body frame, +X is right and
+/// +Y is forward.
+pub fn flow_to_body_drift(
+    flow: Vec2,
+    focal_length: f32,
+    altitude: f32,
+    last_orientation: Quat,
+    orientation: Quat,
+) -> Option<Vec2> {
+    // The floor is further away along the camera's axis when the robot is tilted
+    let tilt_cos = (orientation * Vec3::Z).z;
+    if tilt_cos < MIN_TILT_COS || altitude <= 0.0 || focal_length <= 0.0 {
+        return None;
+    }
+    let range = altitude / tilt_cos;
+
+    // Pitching and rolling sweeps the image even when the robot hasn't moved
+    let rotation = (last_orientation.inverse() * orientation).to_scaled_axis();
+    let rotational_flow = Vec2::new(rotation.y, rotation.x) * focal_length;
+    let flow = flow - rotational_flow;
+
+    // The floor moves opposite to the robot, and image +Y is the robot's -Y
+    Some(Vec2::new(-flow.x, flow.y) * range / focal_length)
+}
+
+/// Force needed to bring the robot back to where it started, in the body frame
+pub fn correction(
+    drift_world: Vec2,
+    velocity_body: Vec2,
+    orientation: Quat,
+    settings: &StationKeepSettings,
+) -> Vec2 {
+    let drift_body = (orientation.inverse() * drift_world.extend(0.0)).truncate();
+
+    (-drift_body * settings.gain - velocity_body * settings.damping)
+        .clamp_length_max(settings.max_correction)
+}
+
+/// Rotates a body frame drift into the world's horizontal plane
+fn body_to_world(drift_body: Vec2, orientation: Quat) -> Vec2 {
+    (orientation * drift_body.extend(0.0)).truncate()
+}
+
+pub struct StationKeepPipeline {
+    /// Pixels, focal length at the calibrated resolution and that resolution's width
+    calibration: Option<(f32, u32)>,
+
+    gray: Mat,
+    last_gray: Mat,
+    last_orientation: Option<Quat>,
+    last_frame: Option<Instant>,
+
+    /// Meters, how far the robot has drifted since corrections last resumed
+    drift: Vec2,
+
+    features: VectorOfPoint2f,
+    tracked: VectorOfPoint2f,
+    status: VectorOfu8,
+    error: VectorOff32,
+}
+
+impl FromWorldEntity for StationKeepPipeline {
+    fn from(world: &mut World, camera: Entity) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let name = world
+            .get::<Camera>(camera)
+            .context("Not a camera")?
+            .id
+            .clone();
+        let calibration = world
+            .get_resource::<StereoCalibration>()
+            .and_then(|calibration| calibration.cameras.get(&name).copied())
+            .map(|intrinsics| (intrinsics.fx as f32, intrinsics.resolution.0));
+
+        if calibration.is_none() {
+            warn!("{name} is not calibrated, assuming a {ASSUMED_HFOV} radian field of view");
+        }
+
+        Ok(Self {
+            calibration,
+
+            gray: Mat::default(),
+            last_gray: Mat::default(),
+            last_orientation: None,
+            last_frame: None,
+
+            drift: Vec2::ZERO,
+
+            features: VectorOfPoint2f::new(),
+            tracked: VectorOfPoint2f::new(),
+            status: VectorOfu8::new(),
+            error: VectorOff32::new(),
+        })
+    }
+}
+
+impl Pipeline for StationKeepPipeline {
+    type Input = Option<(Orientation, Option<Depth>, Armed, StationKeepSettings)>;
+
+    fn collect_inputs(world: &World, entity: &EntityRef) -> Self::Input {
+        let robot_id = entity.get::<RobotId>()?;
+        let robot = world.iter_entities().find(|entity| {
+            entity.contains::<Robot>() && entity.get::<RobotId>() == Some(robot_id)
+        })?;
+
+        let &orientation = robot.get::<Orientation>()?;
+        let depth = robot.get::<Depth>().copied();
+        let &armed = robot.get::<Armed>()?;
+        let settings = entity
+            .get::<StationKeepSettings>()
+            .copied()
+            .unwrap_or_default();
+
+        Some((orientation, depth, armed, settings))
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    fn process<'b, 'a: 'b>(
+        &'a mut self,
+        cmds: &mut PipelineCallbacks,
+        data: &Self::Input,
+        img: &'b mut Mat,
+    ) -> anyhow::Result<&'b mut Mat> {
+        let Some((orientation, ref depth, armed, settings)) = *data else {
+            return Ok(img);
+        };
+
+        if armed != Armed::Armed {
+            cmds.pipeline(|mut entity| {
+                entity.remove::<MovementContribution>();
+            });
+            cmds.should_end();
+
+            return Ok(img);
+        }
+
+        imgproc::cvt_color_def(img, &mut self.gray, imgproc::COLOR_BGR2GRAY)
+            .context("Convert to grayscale")?;
+
+        let now = Instant::now();
+        let orientation = orientation.rotation;
+        let flow = self.track_features()?;
+
+        let last_orientation = self.last_orientation.replace(orientation);
+        let last_frame = self.last_frame.replace(now);
+        std::mem::swap(&mut self.gray, &mut self.last_gray);
+
+        // The first frame only seeds the tracker
+        let (Some(last_orientation), Some(last_frame)) = (last_orientation, last_frame) else {
+            return Ok(img);
+        };
+
+        let width = img.cols() as f32;
+        let focal_length = match self.calibration {
+            Some((fx, calibrated_width)) => fx * width / calibrated_width as f32,
+            None => width / 2.0 / (ASSUMED_HFOV / 2.0).tan(),
+        };
+
+        let drift = gate_flow(&flow, &settings).and_then(|estimate| {
+            flow_to_body_drift(
+                estimate.median,
+                focal_length,
+                settings.altitude(depth.as_ref()),
+                last_orientation,
+                orientation,
+            )
+            .ok_or(Suspended::BadAltitude)
+        });
+
+        match drift {
+            Ok(drift_body) => {
+                let dt = now
+                    .duration_since(last_frame)
+                    .as_secs_f32()
+                    .max(f32::EPSILON);
+
+                self.drift += body_to_world(drift_body, orientation);
+                let force = correction(self.drift, drift_body / dt, orientation, &settings);
+
+                cmds.pipeline(move |mut entity| {
+                    entity.insert(MovementContribution(Movement {
+                        force: force.extend(0.0).into(),
+                        torque: Vec3A::ZERO,
+                    }));
+                });
+
+                put_label(
+                    img,
+                    &format!("Drift: {:.2}m, {:.2}m", self.drift.x, self.drift.y),
+                    Point::new(10, 30),
+                    Scalar::new(0.0, 255.0, 0.0, 0.0),
+                )?;
+            }
+            Err(reason) => {
+                // Whatever happened while blind can't be undone, hold the new position instead
+                self.drift = Vec2::ZERO;
+
+                cmds.pipeline(|mut entity| {
+                    entity.remove::<MovementContribution>();
+                });
+
+                put_label(
+                    img,
+                    &reason.describe(),
+                    Point::new(10, 30),
+                    Scalar::new(0.0, 0.0, 255.0, 0.0),
+                )?;
+            }
+        }
+
+        Ok(img)
+    }
+
+    fn cleanup(entity_world: &mut EntityWorldMut) {
+        entity_world.remove::<MovementContribution>();
+    }
+}
+
+impl StationKeepPipeline {
+    /// Tracks features found in the last frame into the current one, returns their flow
+    #[instrument(level = "debug", skip_all)]
+    fn track_features(&mut self) -> anyhow::Result<Vec<Vec2>> {
+        if self.last_gray.empty() || self.last_gray.size()? != self.gray.size()? {
+            return Ok(Vec::new());
+        }
+
+        imgproc::good_features_to_track_def(
+            &self.last_gray,
+            &mut self.features,
+            MAX_FEATURES,
+            0.01,
+            10.0,
+        )
+        .context("Find features")?;
+
+        if self.features.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        video::calc_optical_flow_pyr_lk_def(
+            &self.last_gray,
+            &self.gray,
+            &self.features,
+            &mut self.tracked,
+            &mut self.status,
+            &mut self.error,
+        )
+        .context("Track features")?;
+
+        Ok(self
+            .features
+            .iter()
+            .zip(self.tracked.iter())
+            .zip(self.status.iter())
+            .filter(|(_, status)| *status != 0)
+            .map(|((from, to), _)| Vec2::new(to.x - from.x, to.y - from.y))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{Quat, Vec2};
+
+    use super::{correction, flow_to_body_drift, gate_flow, StationKeepSettings, Suspended};
+
+    const FOCAL_LENGTH: f32 = 800.0;
+
+    fn uniform(flow: Vec2, count: usize) -> Vec<Vec2> {
+        vec![flow; count]
+    }
+
+    #[test]
+    fn level_flow_scales_with_altitude() {
+        // Floor slides left and towards the top of the image, the robot moved right and backward
+        let drift = flow_to_body_drift(
+            Vec2::new(-80.0, -40.0),
+            FOCAL_LENGTH,
+            2.0,
+            Quat::IDENTITY,
+            Quat::IDENTITY,
+        )
+        .unwrap();
+
+        assert!(drift.abs_diff_eq(Vec2::new(0.2, -0.1), 1e-5), "{drift}");
+    }
+
+    #[test]
+    fn rotation_is_not_drift() {
+        let pitch = Quat::from_rotation_x(0.05);
+        let roll = Quat::from_rotation_y(-0.03);
+
+        for rotation in [pitch, roll, pitch * roll] {
+            let angles = rotation.to_scaled_axis();
+            let flow = Vec2::new(angles.y, angles.x) * FOCAL_LENGTH;
+
+            let drift =
+                flow_to_body_drift(flow, FOCAL_LENGTH, 1.0, Quat::IDENTITY, rotation).unwrap();
+            assert!(drift.abs_diff_eq(Vec2::ZERO, 1e-4), "{drift}");
+        }
+    }
+
+    #[test]
+    fn tilt_lengthens_range() {
+        let tilt = Quat::from_rotation_x(55f32.to_radians());
+
+        let drift =
+            flow_to_body_drift(Vec2::new(-100.0, 0.0), FOCAL_LENGTH, 1.0, tilt, tilt).unwrap();
+        let expected = Vec2::new(0.125 / 55f32.to_radians().cos(), 0.0);
+        assert!(drift.abs_diff_eq(expected, 1e-4), "{drift}");
+
+        let too_steep = Quat::from_rotation_x(70f32.to_radians());
+        assert_eq!(
+            flow_to_body_drift(Vec2::ONE, FOCAL_LENGTH, 1.0, too_steep, too_steep),
+            None
+        );
+        assert_eq!(
+            flow_to_body_drift(Vec2::ONE, FOCAL_LENGTH, 0.0, Quat::IDENTITY, Quat::IDENTITY),
+            None
+        );
+    }
+
+    #[test]
+    fn correction_opposes_drift() {
+        let settings = StationKeepSettings::default();
+
+        let force = correction(Vec2::new(0.1, 0.0), Vec2::ZERO, Quat::IDENTITY, &settings);
+        assert!(force.abs_diff_eq(Vec2::new(-4.0, 0.0), 1e-5), "{force}");
+
+        // Drifted east while facing north-east, that is ahead and to the right of the robot
+        let yaw = Quat::from_rotation_z(-45f32.to_radians());
+        let force = correction(Vec2::new(0.1, 0.0), Vec2::ZERO, yaw, &settings);
+        let expected = Vec2::new(-1.0, -1.0).normalize() * 4.0;
+        assert!(force.abs_diff_eq(expected, 1e-4), "{force}");
+    }
+
+    #[test]
+    fn correction_is_clamped() {
+        let settings = StationKeepSettings::default();
+
+        let force = correction(Vec2::new(5.0, 5.0), Vec2::ONE, Quat::IDENTITY, &settings);
+        assert!((force.length() - settings.max_correction).abs() < 1e-4);
+        assert!(force.x < 0.0 && force.y < 0.0);
+    }
+
+    #[test]
+    fn consistent_flow_passes() {
+        let settings = StationKeepSettings::default();
+
+        let mut flow = uniform(Vec2::new(3.0, -1.0), 40);
+        // A few bad tracks shouldn't move the median
+        flow.extend(uniform(Vec2::new(50.0, 50.0), 5));
+
+        let estimate = gate_flow(&flow, &settings).unwrap();
+        assert_eq!(estimate.median, Vec2::new(3.0, -1.0));
+        assert_eq!(estimate.tracked, 45);
+        assert!((estimate.agreement - 40.0 / 45.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn textureless_water_is_suspended() {
+        let settings = StationKeepSettings::default();
+
+        assert_eq!(gate_flow(&[], &settings), Err(Suspended::TooFewFeatures(0)));
+        assert_eq!(
+            gate_flow(&uniform(Vec2::X, 5), &settings),
+            Err(Suspended::TooFewFeatures(5))
+        );
+    }
+
+    #[test]
+    fn noisy_flow_is_suspended() {
+        let settings = StationKeepSettings::default();
+
+        // Features scattered in every direction, as particles in the water would be
+        let flow: Vec<Vec2> = (0..40)
+            .map(|idx| Vec2::from_angle(idx as f32 * 0.7) * (5.0 + idx as f32))
+            .collect();
+
+        let Err(Suspended::LowAgreement(agreement)) = gate_flow(&flow, &settings) else {
+            panic!("Noisy flow was trusted");
+        };
+        assert!(agreement < settings.min_agreement);
+    }
+}
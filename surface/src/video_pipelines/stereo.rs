@@ -0,0 +1,958 @@
+//! Measures distances and lengths using a calibrated pair of cameras
+//!
+//! Both frames are rectified so matching points share a row, then the disparity at each selected
+//! point gives its depth. Without a usable second feed or calibration it falls back to assuming
+//! every point is a fixed distance from the primary camera.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use bevy::{
+    app::{App, Plugin, Update},
+    core::Name,
+    ecs::{
+        component::Component,
+        entity::Entity,
+        query::With,
+        system::{Commands, Query, Resource},
+    },
+    math::{DVec2, DVec3, Vec2},
+    prelude::{EntityRef, EntityWorldMut, World},
+};
+use bevy_egui::EguiContexts;
+use common::{
+    components::{Camera, CameraIntrinsics},
+    types::ids::CameraId,
+};
+use egui::{Color32, DragValue};
+use opencv::{
+    calib3d,
+    core::{self, Point, Point2f, Rect, Scalar, Size},
+    imgproc,
+    prelude::*,
+    types::VectorOff64,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{instrument, warn};
+
+use crate::{
+    video_pipelines::{
+        intrinsics::IntrinsicsExt, AppPipelineExt, FromWorldEntity, Pipeline, PipelineCallbacks,
+        PipelineCamera,
+    },
+    video_stream::{FrameSubscription, LatestFrames},
+};
+
+pub struct StereoMeasurePipelinePlugin;
+
+impl Plugin for StereoMeasurePipelinePlugin {
+    fn build(&self, app: &mut App) {
+        let calibration = match StereoCalibration::load(STEREO_CALIBRATION_PATH) {
+            Ok(calibration) => calibration,
+            Err(err) => {
+                warn!("Could not load stereo calibration: {err:?}");
+                StereoCalibration::default()
+            }
+        };
+
+        app.insert_resource(calibration)
+            .register_video_pipeline::<StereoMeasurePipeline>("Stereo Measure Pipeline")
+            .add_systems(Update, stereo_measure_window);
+    }
+}
+
+pub const STEREO_CALIBRATION_PATH: &str = "stereo_calibration.toml";
+
+/// Secondary frames older than this are too far out of sync to match against
+const MAX_FRAME_AGE: Duration = Duration::from_millis(100);
+/// Side length of the patch matched between the two frames
+const PATCH_SIZE: i32 = 15;
+const MAX_DISPARITY: i32 = 256;
+const MIN_MATCH_SCORE: f64 = 0.6;
+/// Smaller disparities put the point effectively at infinity
+const MIN_DISPARITY: f64 = 0.5;
+
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StereoCalibration {
+    /// Intrinsics keyed by camera id
+    #[serde(default)]
+    pub cameras: BTreeMap<CameraId, CameraIntrinsics>,
+    #[serde(default)]
+    pub rigs: Vec<StereoRig>,
+    /// Meters, distance assumed for every point when only one camera is usable
+    #[serde(default = "default_fallback_distance")]
+    pub fallback_distance: f64,
+}
+
+fn default_fallback_distance() -> f64 {
+    1.0
+}
+
+impl Default for StereoCalibration {
+    fn default() -> Self {
+        Self {
+            cameras: BTreeMap::new(),
+            rigs: Vec::new(),
+            fallback_distance: default_fallback_distance(),
+        }
+    }
+}
+
+impl StereoCalibration {
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let calibration = fs::read_to_string(path).context("Read stereo calibration")?;
+        toml::from_str(&calibration).context("Parse stereo calibration")
+    }
+
+    pub fn rig_for(&self, primary: &CameraId) -> Option<&StereoRig> {
+        self.rigs.iter().find(|rig| rig.primary == *primary)
+    }
+}
+
+/// Two cameras facing the same way, `secondary` is `baseline` meters to the right of `primary`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StereoRig {
+    pub primary: CameraId,
+    pub secondary: CameraId,
+    pub baseline: f64,
+}
+
+/// A rectified camera pair, both share these intrinsics and differ only by the baseline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RectifiedPair {
+    pub focal: f64,
+    pub cx: f64,
+    pub cy: f64,
+    pub baseline: f64,
+}
+
+/// Meters from the primary camera to a point seen with `disparity` pixels between the frames
+pub fn depth_from_disparity(disparity: f64, focal: f64, baseline: f64) -> Option<f64> {
+    if disparity < MIN_DISPARITY || !disparity.is_finite() {
+        return None;
+    }
+
+    Some(focal * baseline / disparity)
+}
+
+/// Position of a pixel in the rectified primary frame relative to the primary camera
+///
+/// +X is right, +Y is down and +Z is forward
+pub fn triangulate(pixel: DVec2, disparity: f64, pair: &RectifiedPair) -> Option<DVec3> {
+    let z = depth_from_disparity(disparity, pair.focal, pair.baseline)?;
+
+    Some(DVec3::new(
+        (pixel.x - pair.cx) * z / pair.focal,
+        (pixel.y - pair.cy) * z / pair.focal,
+        z,
+    ))
+}
+
+/// Position of a pixel assumed to be `distance` meters in front of the camera
+pub fn back_project(pixel: DVec2, intrinsics: &CameraIntrinsics, distance: f64) -> DVec3 {
+    DVec3::new(
+        (pixel.x - intrinsics.cx) * distance / intrinsics.fx,
+        (pixel.y - intrinsics.cy) * distance / intrinsics.fy,
+        distance,
+    )
+}
+
+/// Offset of the true peak from `center` given the scores on either side of it
+pub fn subpixel_peak(left: f64, center: f64, right: f64) -> f64 {
+    let denominator = left - 2.0 * center + right;
+
+    if denominator.abs() < f64::EPSILON {
+        return 0.0;
+    }
+
+    (0.5 * (left - right) / denominator).clamp(-0.5, 0.5)
+}
+
+/// Distances between consecutive points
+pub fn segment_lengths(points: &[Option<DVec3>]) -> Vec<Option<f64>> {
+    points
+        .windows(2)
+        .map(|pair| match pair {
+            [Some(a), Some(b)] => Some(a.distance(*b)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Points to measure, as fractions of the frame
+#[derive(Component, Debug, Clone, Default, PartialEq)]
+pub struct StereoMeasurePoints(pub Vec<Vec2>);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeasureMode {
+    Stereo,
+    /// Why stereo could not be used
+    SingleCamera(String),
+}
+
+/// Results of the last processed frame
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct StereoMeasurement {
+    pub mode: MeasureMode,
+    /// Meters from the primary camera, `None` when no match was found
+    pub points: Vec<Option<DVec3>>,
+    pub lengths: Vec<Option<f64>>,
+}
+
+struct StereoSetup {
+    secondary: FrameSubscription<Mat>,
+    baseline: f64,
+    primary_intrinsics: CameraIntrinsics,
+    secondary_intrinsics: CameraIntrinsics,
+}
+
+struct Rectification {
+    size: Size,
+    pair: RectifiedPair,
+
+    primary_map: (Mat, Mat),
+    secondary_map: (Mat, Mat),
+}
+
+pub struct StereoMeasurePipeline {
+    stereo: Result<StereoSetup, String>,
+    primary_intrinsics: Option<CameraIntrinsics>,
+    fallback_distance: f64,
+
+    rectification: Option<Rectification>,
+
+    secondary: Mat,
+    rectified_primary: Mat,
+    rectified_secondary: Mat,
+    gray_primary: Mat,
+    gray_secondary: Mat,
+    scores: Mat,
+}
+
+impl FromWorldEntity for StereoMeasurePipeline {
+    fn from(world: &mut World, camera: Entity) -> anyhow::Result<Self>
+    where
+        Self: Sized,
+    {
+        let calibration = world.resource::<StereoCalibration>().clone();
+        let latest_frames = world.resource::<LatestFrames>().clone();
+
+        let name = world
+            .get::<Camera>(camera)
+            .context("Not a camera")?
+            .id
+            .clone();
+        let primary_intrinsics = calibration.cameras.get(&name).copied();
+
+        let stereo = (|| {
+            let rig = calibration
+                .rig_for(&name)
+                .ok_or_else(|| format!("No stereo rig for {name}"))?;
+            let primary_intrinsics =
+                primary_intrinsics.ok_or_else(|| format!("{name} is not calibrated"))?;
+            let secondary_intrinsics = *calibration
+                .cameras
+                .get(&rig.secondary)
+                .ok_or_else(|| format!("{} is not calibrated", rig.secondary))?;
+
+            let secondary = world
+                .query::<(Entity, &Camera)>()
+                .iter(world)
+                .find(|(_, camera)| camera.id == rig.secondary)
+                .map(|(entity, _)| entity)
+                .ok_or_else(|| format!("No camera named {}", rig.secondary))?;
+
+            Ok(StereoSetup {
+                secondary: latest_frames.0.subscribe(secondary),
+                baseline: rig.baseline,
+                primary_intrinsics,
+                secondary_intrinsics,
+            })
+        })();
+
+        if let Err(reason) = &stereo {
+            warn!("Stereo measure on {name} is using a single camera: {reason}");
+        }
+
+        Ok(Self {
+            stereo,
+            primary_intrinsics,
+            fallback_distance: calibration.fallback_distance,
+
+            rectification: None,
+
+            secondary: Mat::default(),
+            rectified_primary: Mat::default(),
+            rectified_secondary: Mat::default(),
+            gray_primary: Mat::default(),
+            gray_secondary: Mat::default(),
+            scores: Mat::default(),
+        })
+    }
+}
+
+impl Pipeline for StereoMeasurePipeline {
+    type Input = StereoMeasurePoints;
+
+    fn collect_inputs(_world: &World, entity: &EntityRef) -> Self::Input {
+        entity
+            .get::<StereoMeasurePoints>()
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    fn process<'b, 'a: 'b>(
+        &'a mut self,
+        cmds: &mut PipelineCallbacks,
+        data: &Self::Input,
+        img: &'b mut Mat,
+    ) -> anyhow::Result<&'b mut Mat> {
+        match self.process_stereo(data, img) {
+            Ok(measurement) => {
+                draw_measurement(&mut self.rectified_primary, data, &measurement)
+                    .context("Draw measurement")?;
+                publish(cmds, measurement);
+
+                Ok(&mut self.rectified_primary)
+            }
+            Err(reason) => {
+                let measurement = self.measure_single(data, img, reason)?;
+                draw_measurement(img, data, &measurement).context("Draw measurement")?;
+                publish(cmds, measurement);
+
+                Ok(img)
+            }
+        }
+    }
+
+    fn cleanup(entity_world: &mut EntityWorldMut) {
+        entity_world.remove::<StereoMeasurement>();
+    }
+}
+
+fn publish(cmds: &mut PipelineCallbacks, measurement: StereoMeasurement) {
+    cmds.pipeline(move |mut entity| {
+        entity.insert(measurement);
+    });
+}
+
+impl StereoMeasurePipeline {
+    /// Returns why stereo can't be used for this frame on failure
+    fn process_stereo(
+        &mut self,
+        data: &StereoMeasurePoints,
+        img: &Mat,
+    ) -> Result<StereoMeasurement, String> {
+        let stereo = self.stereo.as_ref().map_err(Clone::clone)?;
+
+        let copied = stereo
+            .secondary
+            .read_latest(Instant::now(), MAX_FRAME_AGE, |frame| {
+                frame.copy_to(&mut self.secondary)
+            })
+            .ok_or_else(|| "Secondary feed is stale".to_owned())?;
+        copied.map_err(|err| format!("Copy secondary frame: {err}"))?;
+
+        let size = img.size().map_err(|err| format!("Get image size: {err}"))?;
+        let secondary_size = self
+            .secondary
+            .size()
+            .map_err(|err| format!("Get image size: {err}"))?;
+        if size != secondary_size {
+            return Err("Camera resolutions differ".to_owned());
+        }
+
+        if self.rectification.as_ref().map(|it| it.size) != Some(size) {
+            let rectification = rectify(stereo, size)
+                .map_err(|err| format!("Could not rectify cameras: {err:?}"))?;
+            self.rectification = Some(rectification);
+        }
+        let Some(rectification) = &self.rectification else {
+            unreachable!()
+        };
+
+        self.match_points(rectification, data, img)
+            .map_err(|err| format!("Stereo matching failed: {err:?}"))
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    fn match_points(
+        &mut self,
+        rectification: &Rectification,
+        data: &StereoMeasurePoints,
+        img: &Mat,
+    ) -> anyhow::Result<StereoMeasurement> {
+        let (map_x, map_y) = &rectification.primary_map;
+        imgproc::remap_def(
+            img,
+            &mut self.rectified_primary,
+            map_x,
+            map_y,
+            imgproc::INTER_LINEAR,
+        )
+        .context("Remap primary")?;
+        let (map_x, map_y) = &rectification.secondary_map;
+        imgproc::remap_def(
+            &self.secondary,
+            &mut self.rectified_secondary,
+            map_x,
+            map_y,
+            imgproc::INTER_LINEAR,
+        )
+        .context("Remap secondary")?;
+
+        imgproc::cvt_color_def(
+            &self.rectified_primary,
+            &mut self.gray_primary,
+            imgproc::COLOR_BGR2GRAY,
+        )
+        .context("Gray primary")?;
+        imgproc::cvt_color_def(
+            &self.rectified_secondary,
+            &mut self.gray_secondary,
+            imgproc::COLOR_BGR2GRAY,
+        )
+        .context("Gray secondary")?;
+
+        let width = rectification.size.width as f64;
+        let height = rectification.size.height as f64;
+
+        let mut points = Vec::with_capacity(data.0.len());
+        for point in &data.0 {
+            let pixel = DVec2::new(point.x as f64 * width, point.y as f64 * height);
+
+            let disparity = find_disparity(
+                &self.gray_primary,
+                &self.gray_secondary,
+                pixel,
+                &mut self.scores,
+            )
+            .context("Find disparity")?;
+
+            points.push(disparity.and_then(|it| triangulate(pixel, it, &rectification.pair)));
+        }
+
+        Ok(StereoMeasurement {
+            mode: MeasureMode::Stereo,
+            lengths: segment_lengths(&points),
+            points,
+        })
+    }
+
+    #[instrument(level = "debug", skip_all)]
+    fn measure_single(
+        &self,
+        data: &StereoMeasurePoints,
+        img: &Mat,
+        reason: String,
+    ) -> anyhow::Result<StereoMeasurement> {
+        let size = img.size().context("Get image size")?;
+
+        let points: Vec<_> = match self.primary_intrinsics {
+            Some(intrinsics) => {
+                let intrinsics = intrinsics.scaled_to(size.width as u32, size.height as u32);
+
+                data.0
+                    .iter()
+                    .map(|point| {
+                        let pixel = DVec2::new(
+                            point.x as f64 * size.width as f64,
+                            point.y as f64 * size.height as f64,
+                        );
+
+                        Some(back_project(pixel, &intrinsics, self.fallback_distance))
+                    })
+                    .collect()
+            }
+            None => vec![None; data.0.len()],
+        };
+
+        Ok(StereoMeasurement {
+            mode: MeasureMode::SingleCamera(reason),
+            lengths: segment_lengths(&points),
+            points,
+        })
+    }
+}
+
+fn rectify(stereo: &StereoSetup, size: Size) -> anyhow::Result<Rectification> {
+    let primary = stereo
+        .primary_intrinsics
+        .scaled_to(size.width as u32, size.height as u32);
+    let secondary = stereo
+        .secondary_intrinsics
+        .scaled_to(size.width as u32, size.height as u32);
+
+    let primary_matrix = primary.camera_matrix()?;
+    let secondary_matrix = secondary.camera_matrix()?;
+    let primary_distortion = primary.distortion();
+    let secondary_distortion = secondary.distortion();
+
+    // The cameras are assumed to be mounted parallel, only offset by the baseline
+    let rotation = Mat::eye(3, 3, core::CV_64F)
+        .context("Create rotation")?
+        .to_mat()
+        .context("Create rotation")?;
+    let translation = VectorOff64::from_slice(&[-stereo.baseline, 0.0, 0.0]);
+
+    let mut r1 = Mat::default();
+    let mut r2 = Mat::default();
+    let mut p1 = Mat::default();
+    let mut p2 = Mat::default();
+    let mut q = Mat::default();
+    calib3d::stereo_rectify_def(
+        &primary_matrix,
+        &primary_distortion,
+        &secondary_matrix,
+        &secondary_distortion,
+        size,
+        &rotation,
+        &translation,
+        &mut r1,
+        &mut r2,
+        &mut p1,
+        &mut p2,
+        &mut q,
+    )
+    .context("Stereo rectify")?;
+
+    let primary_map = rectify_map(&primary_matrix, &primary_distortion, &r1, &p1, size)?;
+    let secondary_map = rectify_map(&secondary_matrix, &secondary_distortion, &r2, &p2, size)?;
+
+    let pair = RectifiedPair {
+        focal: *p1.at_2d::<f64>(0, 0).context("Read focal length")?,
+        cx: *p1.at_2d::<f64>(0, 2).context("Read principal point")?,
+        cy: *p1.at_2d::<f64>(1, 2).context("Read principal point")?,
+        baseline: stereo.baseline,
+    };
+
+    Ok(Rectification {
+        size,
+        pair,
+        primary_map,
+        secondary_map,
+    })
+}
+
+fn rectify_map(
+    matrix: &Mat,
+    distortion: &VectorOff64,
+    rotation: &Mat,
+    projection: &Mat,
+    size: Size,
+) -> anyhow::Result<(Mat, Mat)> {
+    let mut map_x = Mat::default();
+    let mut map_y = Mat::default();
+
+    calib3d::init_undistort_rectify_map(
+        matrix,
+        distortion,
+        rotation,
+        projection,
+        size,
+        core::CV_32FC1,
+        &mut map_x,
+        &mut map_y,
+    )
+    .context("Init rectify map")?;
+
+    Ok((map_x, map_y))
+}
+
+/// Finds how far `pixel` in the rectified primary frame moved left in the secondary frame
+fn find_disparity(
+    primary: &Mat,
+    secondary: &Mat,
+    pixel: DVec2,
+    scores: &mut Mat,
+) -> anyhow::Result<Option<f64>> {
+    let half = PATCH_SIZE / 2;
+    let (x, y) = (pixel.x.round() as i32, pixel.y.round() as i32);
+    let (cols, rows) = (primary.cols(), primary.rows());
+
+    if x - half < 0 || y - half < 0 || x + half >= cols || y + half >= rows {
+        return Ok(None);
+    }
+
+    let template = Mat::roi(
+        primary,
+        Rect::new(x - half, y - half, PATCH_SIZE, PATCH_SIZE),
+    )
+    .context("Template")?;
+
+    // Matches can only be to the left in the secondary frame
+    let search_start = (x - half - MAX_DISPARITY).max(0);
+    let search_end = x + half + 1;
+    let strip = Mat::roi(
+        secondary,
+        Rect::new(
+            search_start,
+            y - half,
+            search_end - search_start,
+            PATCH_SIZE,
+        ),
+    )
+    .context("Search strip")?;
+
+    imgproc::match_template_def(&strip, &template, scores, imgproc::TM_CCOEFF_NORMED)
+        .context("Match template")?;
+
+    let mut best = 0.0;
+    let mut best_loc = Point::default();
+    core::min_max_loc(
+        scores,
+        None,
+        Some(&mut best),
+        None,
+        Some(&mut best_loc),
+        &core::no_array(),
+    )
+    .context("Find best match")?;
+
+    if best < MIN_MATCH_SCORE {
+        return Ok(None);
+    }
+
+    let offset = if best_loc.x > 0 && best_loc.x < scores.cols() - 1 {
+        let score = |x| scores.at_2d::<f32>(0, x).map(|it| *it as f64);
+
+        subpixel_peak(
+            score(best_loc.x - 1).context("Read score")?,
+            best,
+            score(best_loc.x + 1).context("Read score")?,
+        )
+    } else {
+        0.0
+    };
+
+    let matched_x = (search_start + best_loc.x + half) as f64 + offset;
+
+    Ok(Some(x as f64 - matched_x))
+}
+
+fn draw_measurement(
+    img: &mut Mat,
+    data: &StereoMeasurePoints,
+    measurement: &StereoMeasurement,
+) -> anyhow::Result<()> {
+    let size = img.size().context("Get image size")?;
+    let pixel = |point: Vec2| {
+        Point::new(
+            (point.x * size.width as f32) as i32,
+            (point.y * size.height as f32) as i32,
+        )
+    };
+
+    let color = match measurement.mode {
+        MeasureMode::Stereo => Scalar::new(0.0, 255.0, 0.0, 0.0),
+        MeasureMode::SingleCamera(_) => Scalar::new(0.0, 255.0, 255.0, 0.0),
+    };
+
+    for (idx, (&point, position)) in data.0.iter().zip(&measurement.points).enumerate() {
+        let point = pixel(point);
+
+        imgproc::draw_marker_def(img, point, color).context("Draw point")?;
+
+        let label = match position {
+            Some(position) => format!("{idx}: {:.2}m", position.z),
+            None => format!("{idx}: ?"),
+        };
+        put_label(img, &label, point + Point::new(8, -8), color)?;
+    }
+
+    for (idx, length) in measurement.lengths.iter().enumerate() {
+        let (a, b) = (pixel(data.0[idx]), pixel(data.0[idx + 1]));
+
+        imgproc::line(img, a, b, color, 2, imgproc::LINE_AA, 0).context("Draw segment")?;
+
+        if let Some(length) = length {
+            let middle = Point2f::new((a.x + b.x) as f32 / 2.0, (a.y + b.y) as f32 / 2.0);
+            let middle = Point::new(middle.x as i32, middle.y as i32);
+
+            put_label(img, &format!("{:.1}cm", length * 100.0), middle, color)?;
+        }
+    }
+
+    if let MeasureMode::SingleCamera(reason) = &measurement.mode {
+        put_label(
+            img,
+            &format!("Single camera: {reason}"),
+            Point::new(10, 30),
+            color,
+        )?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn put_label(
+    img: &mut Mat,
+    text: &str,
+    origin: Point,
+    color: Scalar,
+) -> anyhow::Result<()> {
+    imgproc::put_text(
+        img,
+        text,
+        origin,
+        imgproc::FONT_HERSHEY_SIMPLEX,
+        0.7,
+        color,
+        2,
+        imgproc::LINE_AA,
+        false,
+    )
+    .context("Draw label")
+}
+
+fn stereo_measure_window(
+    mut cmds: Commands,
+    mut contexts: EguiContexts,
+    pipelines: Query<(
+        Entity,
+        &PipelineCamera,
+        &StereoMeasurement,
+        Option<&StereoMeasurePoints>,
+    )>,
+    cameras: Query<&Name, With<Camera>>,
+) {
+    for (entity, camera, measurement, points) in &pipelines {
+        let name = cameras
+            .get(camera.camera())
+            .map(|it| it.as_str())
+            .unwrap_or("Unknown Camera");
+        let mut edited = points.cloned().unwrap_or_default();
+
+        egui::Window::new(format!("Stereo Measure: {name}"))
+            .id(egui::Id::new(("Stereo Measure", entity)))
+            .show(contexts.ctx_mut(), |ui| {
+                match &measurement.mode {
+                    MeasureMode::Stereo => ui.label("Stereo"),
+                    MeasureMode::SingleCamera(reason) => {
+                        ui.colored_label(Color32::YELLOW, format!("Single camera: {reason}"))
+                    }
+                };
+
+                ui.separator();
+
+                let mut remove = None;
+                egui::Grid::new("Points").show(ui, |ui| {
+                    for (idx, point) in edited.0.iter_mut().enumerate() {
+                        ui.label(format!("{idx}"));
+                        ui.add(
+                            DragValue::new(&mut point.x)
+                                .clamp_range(0.0..=1.0)
+                                .speed(0.002)
+                                .prefix("x: "),
+                        );
+                        ui.add(
+                            DragValue::new(&mut point.y)
+                                .clamp_range(0.0..=1.0)
+                                .speed(0.002)
+                                .prefix("y: "),
+                        );
+
+                        match measurement.points.get(idx).copied().flatten() {
+                            Some(position) => ui.label(format!("{:.2}m", position.z)),
+                            None => ui.label("No match"),
+                        };
+
+                        if ui.button("Remove").clicked() {
+                            remove = Some(idx);
+                        }
+
+                        ui.end_row();
+                    }
+                });
+
+                if let Some(idx) = remove {
+                    edited.0.remove(idx);
+                }
+
+                if ui.button("Add Point").clicked() {
+                    edited.0.push(Vec2::splat(0.5));
+                }
+
+                if !measurement.lengths.is_empty() {
+                    ui.separator();
+                }
+
+                for (idx, length) in measurement.lengths.iter().enumerate() {
+                    let text = match length {
+                        Some(length) => format!("{idx} to {}: {:.1}cm", idx + 1, length * 100.0),
+                        None => format!("{idx} to {}: ?", idx + 1),
+                    };
+
+                    ui.label(text);
+                }
+            });
+
+        if points != Some(&edited) {
+            cmds.entity(entity).insert(edited);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::math::{DVec2, DVec3};
+    use common::types::ids::CameraId;
+
+    use super::{
+        back_project, depth_from_disparity, segment_lengths, subpixel_peak, triangulate,
+        CameraIntrinsics, RectifiedPair, StereoCalibration,
+    };
+
+    const PAIR: RectifiedPair = RectifiedPair {
+        focal: 800.0,
+        cx: 640.0,
+        cy: 360.0,
+        baseline: 0.12,
+    };
+
+    /// Where `point` lands in the rectified primary and secondary frames
+    fn project(point: DVec3) -> (DVec2, DVec2) {
+        let primary = DVec2::new(
+            PAIR.focal * point.x / point.z + PAIR.cx,
+            PAIR.focal * point.y / point.z + PAIR.cy,
+        );
+        let secondary = DVec2::new(
+            PAIR.focal * (point.x - PAIR.baseline) / point.z + PAIR.cx,
+            primary.y,
+        );
+
+        (primary, secondary)
+    }
+
+    fn assert_close(a: DVec3, b: DVec3) {
+        assert!(a.distance(b) < 1e-9, "{a} != {b}");
+    }
+
+    #[test]
+    fn disparity_to_depth() {
+        assert!((depth_from_disparity(96.0, 800.0, 0.12).unwrap() - 1.0).abs() < 1e-12);
+        assert!((depth_from_disparity(48.0, 800.0, 0.12).unwrap() - 2.0).abs() < 1e-12);
+
+        assert_eq!(depth_from_disparity(0.0, 800.0, 0.12), None);
+        assert_eq!(depth_from_disparity(-3.0, 800.0, 0.12), None);
+        assert_eq!(depth_from_disparity(f64::NAN, 800.0, 0.12), None);
+    }
+
+    #[test]
+    fn triangulates_synthetic_points() {
+        let points = [
+            DVec3::new(0.0, 0.0, 1.0),
+            DVec3::new(0.25, -0.1, 0.8),
+            DVec3::new(-0.4, 0.3, 2.5),
+            DVec3::new(0.05, 0.02, 0.35),
+        ];
+
+        for point in points {
+            let (primary, secondary) = project(point);
+            let disparity = primary.x - secondary.x;
+
+            let triangulated = triangulate(primary, disparity, &PAIR).unwrap();
+            assert_close(triangulated, point);
+        }
+    }
+
+    #[test]
+    fn measures_lengths() {
+        let a = DVec3::new(-0.1, 0.0, 1.2);
+        let b = DVec3::new(0.2, 0.05, 1.1);
+        let c = DVec3::new(0.2, 0.05, 1.5);
+
+        let points: Vec<_> = [a, b, c]
+            .into_iter()
+            .map(|point| {
+                let (primary, secondary) = project(point);
+                triangulate(primary, primary.x - secondary.x, &PAIR)
+            })
+            .collect();
+
+        let lengths = segment_lengths(&points);
+        assert_eq!(lengths.len(), 2);
+        assert!((lengths[0].unwrap() - a.distance(b)).abs() < 1e-9);
+        assert!((lengths[1].unwrap() - 0.4).abs() < 1e-9);
+
+        // Unmatched points break the segments on either side
+        assert_eq!(segment_lengths(&[points[0], None, points[2]]), [None, None]);
+        assert!(segment_lengths(&points[..1]).is_empty());
+    }
+
+    #[test]
+    fn subpixel_refinement() {
+        assert_eq!(subpixel_peak(0.5, 1.0, 0.5), 0.0);
+        assert_eq!(subpixel_peak(1.0, 1.0, 1.0), 0.0);
+
+        // Samples of -(x - 0.25)^2 at -1, 0 and 1
+        let f = |x: f64| -(x - 0.25) * (x - 0.25);
+        assert!((subpixel_peak(f(-1.0), f(0.0), f(1.0)) - 0.25).abs() < 1e-12);
+    }
+
+    #[test]
+    fn single_camera_back_projection() {
+        let intrinsics = CameraIntrinsics {
+            resolution: (1280, 720),
+            fx: PAIR.focal,
+            fy: PAIR.focal,
+            cx: PAIR.cx,
+            cy: PAIR.cy,
+            distortion: [0.0; 5],
+        };
+
+        let point = DVec3::new(0.3, -0.2, 1.5);
+        let (primary, _) = project(point);
+        assert_close(back_project(primary, &intrinsics, 1.5), point);
+
+        // Intrinsics follow the stream resolution
+        let half = intrinsics.scaled_to(640, 360);
+        assert_eq!((half.fx, half.cx, half.cy), (400.0, 320.0, 180.0));
+        assert_close(
+            back_project(primary / 2.0, &half, 1.5),
+            back_project(primary, &intrinsics, 1.5),
+        );
+    }
+
+    #[test]
+    fn parses_calibration() {
+        let calibration: StereoCalibration = toml::from_str(
+            r#"
+            [[rigs]]
+            primary = "Front Left"
+            secondary = "Front Right"
+            baseline = 0.12
+
+            [cameras."Front Left"]
+            resolution = [1920, 1080]
+            fx = 1281.9
+            fy = 1280.2
+            cx = 1014.1
+            cy = 530.6
+            distortion = [-0.4, 0.2, 0.0, 0.0, -0.05]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(calibration.fallback_distance, 1.0);
+        let left = CameraId::from_static("Front Left");
+        let right = CameraId::from_static("Front Right");
+
+        assert_eq!(calibration.rig_for(&left).unwrap().baseline, 0.12);
+        assert!(calibration.rig_for(&right).is_none());
+        assert!(calibration.cameras.contains_key(&left));
+        assert!(!calibration.cameras.contains_key(&right));
+    }
+}
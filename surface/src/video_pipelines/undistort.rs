@@ -1,16 +1,21 @@
 use anyhow::Context;
 use bevy::{
     app::{App, Plugin},
-    prelude::{Entity, EntityRef, EntityWorldMut, World},
+    prelude::{EntityRef, EntityWorldMut, World},
 };
+use common::components::CameraIntrinsics;
 use opencv::{
     calib3d,
     core::{self, Range, Rect, Scalar, Size},
     imgproc,
     prelude::*,
 };
+use tracing::{debug_span, instrument};
 
-use crate::video_pipelines::{AppPipelineExt, FromWorldEntity, Pipeline, PipelineCallbacks};
+use crate::video_pipelines::{
+    intrinsics::{self, FrameIntrinsics, IntrinsicsExt, ResolvedIntrinsics},
+    AppPipelineExt, Pipeline, PipelineCallbacks,
+};
 
 pub struct UndistortPipelinePlugin;
 
@@ -20,18 +25,19 @@ impl Plugin for UndistortPipelinePlugin {
     }
 }
 
+#[derive(Default)]
 pub struct UndistortPipeline {
     undistorted: Mat,
     cropped: Mat,
 
-    mtx: Mat,
-    dist: Mat,
+    intrinsics: FrameIntrinsics,
 
     remap: Option<RemapData>,
 }
 
 struct RemapData {
     size: Size,
+    intrinsics: CameraIntrinsics,
 
     map_x: Mat,
     map_y: Mat,
@@ -41,22 +47,24 @@ struct RemapData {
 }
 
 impl Pipeline for UndistortPipeline {
-    type Input = ();
+    type Input = ResolvedIntrinsics;
 
-    fn collect_inputs(_world: &World, _entity: &EntityRef) -> Self::Input {
-        // No-op
+    fn collect_inputs(world: &World, entity: &EntityRef) -> Self::Input {
+        intrinsics::collect_intrinsics(world, entity)
     }
 
+    #[instrument(level = "debug", skip_all)]
     fn process<'b, 'a: 'b>(
         &'a mut self,
         _cmds: &mut PipelineCallbacks,
-        _data: &Self::Input,
+        data: &Self::Input,
         img: &'b mut Mat,
     ) -> anyhow::Result<&'b mut Mat> {
         let size = img.size().context("Get image size")?;
+        let intrinsics = self.intrinsics.update(data, size);
 
         if let Some(ref mut remap) = self.remap {
-            if remap.size != size {
+            if remap.size != size || remap.intrinsics != intrinsics {
                 self.remap = None;
             }
         }
@@ -64,9 +72,8 @@ impl Pipeline for UndistortPipeline {
         let UndistortPipeline {
             undistorted,
             cropped,
-            mtx,
-            dist,
             remap,
+            ..
         } = self;
 
         let RemapData {
@@ -78,10 +85,13 @@ impl Pipeline for UndistortPipeline {
         } = match remap {
             Some(remap) => remap,
             None => {
+                let mtx = intrinsics.camera_matrix()?;
+                let dist = intrinsics.distortion();
+
                 let mut roi = Rect::default();
                 let new_mtx = calib3d::get_optimal_new_camera_matrix(
-                    mtx,
-                    dist,
+                    &mtx,
+                    &dist,
                     size,
                     0.0,
                     size,
@@ -94,8 +104,8 @@ impl Pipeline for UndistortPipeline {
                 let mut map_y = Mat::default();
                 // TODO: What does the 5 mean? taken from https://docs.opencv.org/4.x/dc/dbb/tutorial_py_calibration.html
                 calib3d::init_undistort_rectify_map(
-                    mtx,
-                    dist,
+                    &mtx,
+                    &dist,
                     &Mat::default(),
                     &new_mtx,
                     size,
@@ -107,6 +117,7 @@ impl Pipeline for UndistortPipeline {
 
                 remap.insert(RemapData {
                     size,
+                    intrinsics,
                     map_x,
                     map_y,
                     rows: Range::new(roi.x, roi.x + roi.width).context("Rows Range")?,
@@ -115,8 +126,10 @@ impl Pipeline for UndistortPipeline {
             }
         };
 
-        imgproc::remap_def(img, undistorted, map_x, map_y, imgproc::INTER_LINEAR)
-            .context("Remap")?;
+        debug_span!("remap").in_scope(|| {
+            imgproc::remap_def(img, undistorted, map_x, map_y, imgproc::INTER_LINEAR)
+                .context("Remap")
+        })?;
 
         *cropped = undistorted.row_range(rows).context("Crop Rows")?;
         *cropped = cropped.col_range(cols).context("Crop Cols")?;
@@ -128,23 +141,3 @@ impl Pipeline for UndistortPipeline {
         // No-op
     }
 }
-
-impl FromWorldEntity for UndistortPipeline {
-    fn from(world: &mut World, camera: Entity) -> anyhow::Result<Self>
-    where
-        Self: Sized,
-    {
-        // TODO: Store these values on the robot and grab them from the ecs here
-        let mtx = Mat::default();
-        let dist = Mat::default();
-
-        // Self {
-        //     undistorted: Mat::default(),
-        //     cropped: Mat::default(),
-        //     mtx,
-        //     dist,
-        //     remap: None,
-        // };
-        todo!("Get real data")
-    }
-}
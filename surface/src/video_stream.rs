@@ -1,6 +1,14 @@
-use std::{borrow::Cow, ffi::c_void, mem, sync::Arc, thread};
+use std::{
+    borrow::Cow,
+    ffi::c_void,
+    mem,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
 
-use anyhow::{anyhow, Context};
+use ahash::HashMap;
+use anyhow::{anyhow, bail, Context};
 use bevy::{
     prelude::*,
     render::{
@@ -9,22 +17,32 @@ use bevy::{
     },
 };
 use common::{
-    components::Camera,
+    components::{Camera, CameraFallbackStream, RobotId},
     error::{self, ErrorEvent, Errors},
 };
-use crossbeam::channel::{self, Receiver, Sender};
+use crossbeam::channel::{self, Receiver, RecvTimeoutError, Sender};
 use opencv::{
-    imgproc,
+    core::Vector,
+    imgcodecs, imgproc,
     platform_types::size_t,
     prelude::*,
     videoio::{self, VideoCapture},
 };
 
+use crate::{
+    video_conversion::{self, PendingConversion, VideoConversionMode, VideoFrame},
+    video_downscale::{self, DesiredDisplaySize, DisplayScale},
+    video_fallback::{self, FallbackFeed, FEED_BACKLOG},
+};
+
+/// How long a fallback video thread waits for a frame before checking if it should stop
+const FALLBACK_POLL: Duration = Duration::from_millis(100);
+
 pub struct VideoStreamPlugin;
 
 impl Plugin for VideoStreamPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
+        app.init_resource::<LatestFrames>().add_systems(
             Update,
             (
                 handle_added_camera
@@ -32,6 +50,7 @@ impl Plugin for VideoStreamPlugin {
                     .before(handle_frames),
                 handle_frames,
                 handle_video_processors,
+                forward_display_sizes,
             ),
         );
     }
@@ -67,46 +86,262 @@ impl VideoProcessorFactory {
     }
 }
 
+/// The latest raw frame of every camera something is subscribed to
+///
+/// Lets a pipeline running on one camera's thread read another camera's frames
+#[derive(Resource, Clone, Default)]
+pub struct LatestFrames(pub FrameCache<Mat>);
+
+/// Latest value per camera, only kept while something is subscribed
+pub struct FrameCache<T>(Arc<Mutex<HashMap<Entity, CachedFrame<T>>>>);
+
+struct CachedFrame<T> {
+    subscribers: usize,
+    frame: Option<(T, Instant)>,
+}
+
+impl<T> FrameCache<T> {
+    pub fn subscribe(&self, camera: Entity) -> FrameSubscription<T> {
+        let mut frames = self.0.lock().expect("Lock frame cache");
+        frames
+            .entry(camera)
+            .or_insert(CachedFrame {
+                subscribers: 0,
+                frame: None,
+            })
+            .subscribers += 1;
+
+        FrameSubscription {
+            cache: self.clone(),
+            camera,
+        }
+    }
+
+    /// Stores a new frame for `camera` with `write`, which is only called if someone subscribed
+    ///
+    /// The previous frame is handed to `write` so its allocation can be reused
+    pub fn publish(
+        &self,
+        camera: Entity,
+        captured: Instant,
+        write: impl FnOnce(&mut T) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()>
+    where
+        T: Default,
+    {
+        let mut frames = self.0.lock().expect("Lock frame cache");
+        let Some(cached) = frames.get_mut(&camera) else {
+            return Ok(());
+        };
+
+        let (frame, timestamp) = cached.frame.get_or_insert_with(|| (T::default(), captured));
+        *timestamp = captured;
+
+        let res = write(frame);
+        if res.is_err() {
+            cached.frame = None;
+        }
+
+        res
+    }
+
+    /// Reads the latest frame of `camera`, if there is one no older than `max_age`
+    pub fn read_latest<R>(
+        &self,
+        camera: Entity,
+        now: Instant,
+        max_age: Duration,
+        read: impl FnOnce(&T) -> R,
+    ) -> Option<R> {
+        let frames = self.0.lock().expect("Lock frame cache");
+        let (frame, captured) = frames.get(&camera)?.frame.as_ref()?;
+
+        if now.saturating_duration_since(*captured) > max_age {
+            return None;
+        }
+
+        Some(read(frame))
+    }
+}
+
+impl<T> Clone for FrameCache<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Default for FrameCache<T> {
+    fn default() -> Self {
+        Self(Default::default())
+    }
+}
+
+/// Keeps frames from a camera cached until dropped
+pub struct FrameSubscription<T> {
+    cache: FrameCache<T>,
+    camera: Entity,
+}
+
+impl<T> FrameSubscription<T> {
+    pub fn camera(&self) -> Entity {
+        self.camera
+    }
+
+    pub fn read_latest<R>(
+        &self,
+        now: Instant,
+        max_age: Duration,
+        read: impl FnOnce(&T) -> R,
+    ) -> Option<R> {
+        self.cache.read_latest(self.camera, now, max_age, read)
+    }
+}
+
+impl<T> Drop for FrameSubscription<T> {
+    fn drop(&mut self) {
+        let Ok(mut frames) = self.cache.0.lock() else {
+            return;
+        };
+
+        if let Some(cached) = frames.get_mut(&self.camera) {
+            cached.subscribers -= 1;
+
+            if cached.subscribers == 0 {
+                frames.remove(&self.camera);
+            }
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct VideoThread(
     // Used by the video thread to detect when its handle is droped from the ECS
     Arc<()>,
-    // Channels for displaying and reusing bevy images
-    Sender<Image>,
-    Receiver<Image>,
+    // Channels for displaying and reusing frames, along with the source resolution
+    Sender<VideoFrame>,
+    Receiver<(VideoFrame, UVec2)>,
     // Channel to update the thread's VideoProcessor
     Sender<Option<BoxedVideoProcessor>>,
+    // Channel to update the size the feed is drawn at
+    Sender<Option<UVec2>>,
 );
 
+/// Frame rate and resolution of a camera's feed as the UI receives it
+#[derive(Component, Debug, Clone, Copy, PartialEq, Default)]
+pub struct VideoStats {
+    pub fps: f32,
+    /// Resolution the camera sends
+    pub source: UVec2,
+    /// Resolution handed to the UI, smaller than `source` when downscaled
+    pub shown: UVec2,
+
+    window_start: Duration,
+    window_frames: u32,
+}
+
+/// How often [`VideoStats::fps`] is recalculated
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+
+impl VideoStats {
+    fn record(&mut self, frames: u32, source: UVec2, shown: UVec2, now: Duration) {
+        self.source = source;
+        self.shown = shown;
+        self.window_frames += frames;
+
+        let elapsed = now.saturating_sub(self.window_start);
+        if elapsed >= FPS_WINDOW {
+            self.fps = self.window_frames as f32 / elapsed.as_secs_f32();
+            self.window_start = now;
+            self.window_frames = 0;
+        }
+    }
+}
+
+/// Where a video thread reads frames from
+enum FrameSource {
+    /// The camera's gstreamer stream
+    Stream(VideoCapture),
+    /// JPEG frames sent over the control link, see [`video_fallback`]
+    Fallback(Receiver<Vec<u8>>),
+}
+
+impl FrameSource {
+    /// Reads the next frame into `mat`, returns false if there wasn't one
+    fn read(&mut self, mat: &mut Mat) -> anyhow::Result<bool> {
+        match self {
+            FrameSource::Stream(src) => src.read(mat).context("Read video frame"),
+            FrameSource::Fallback(rx) => match rx.recv_timeout(FALLBACK_POLL) {
+                Ok(jpeg) => decode_jpeg(&jpeg, mat).map(|()| true),
+                // The feed is dropped along with the thread's handle
+                Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => Ok(false),
+            },
+        }
+    }
+}
+
+/// Decodes a fallback frame into `mat`
+pub fn decode_jpeg(jpeg: &[u8], mat: &mut Mat) -> anyhow::Result<()> {
+    *mat = imgcodecs::imdecode(&Vector::from_slice(jpeg), imgcodecs::IMREAD_COLOR)
+        .context("Decode fallback frame")?;
+
+    if mat.empty() {
+        bail!("Fallback frame is not a JPEG");
+    }
+
+    Ok(())
+}
+
 fn handle_added_camera(
     mut cmds: Commands,
-    cameras: Query<(Entity, &Camera), Changed<Camera>>,
+    cameras: Query<(Entity, &Camera, Option<&RobotId>), Changed<Camera>>,
+    requests: Query<(&CameraFallbackStream, &RobotId)>,
     mut images: ResMut<Assets<Image>>,
     errors: Res<Errors>,
+    mode: Res<VideoConversionMode>,
+    latest_frames: Res<LatestFrames>,
 ) -> anyhow::Result<()> {
-    for (entity, camera) in &cameras {
-        cmds.entity(entity).remove::<VideoThread>();
+    for (entity, camera, robot) in &cameras {
+        cmds.entity(entity).remove::<(VideoThread, FallbackFeed)>();
+
+        let fallback =
+            robot.is_some_and(|robot| video_fallback::is_requested(requests.iter(), camera, robot));
+        let rx_fallback = fallback.then(|| {
+            let (tx_fallback, rx_fallback) = channel::bounded(FEED_BACKLOG);
+            cmds.entity(entity).insert(FallbackFeed(tx_fallback));
+
+            rx_fallback
+        });
 
         let handle = Arc::new(());
         let (tx_cv, rx_cv) = channel::bounded(10);
         let (tx_bevy, rx_bevy) = channel::bounded(10);
         let (tx_proc, rx_proc) = channel::bounded(10);
+        // Unbounded so resizing the window never blocks on a stalled camera
+        let (tx_display, rx_display) = channel::unbounded();
 
         cmds.entity(entity).insert((
-            VideoThread(handle.clone(), tx_bevy, rx_cv, tx_proc),
+            VideoThread(handle.clone(), tx_bevy, rx_cv, tx_proc, tx_display),
+            VideoStats::default(),
             images.add(Image::default()),
         ));
 
         let camera = camera.clone();
         let errors = errors.0.clone();
+        let mode = *mode;
+        let latest_frames = latest_frames.0.clone();
         thread::Builder::new()
             .name("Video Thread".to_owned())
             .spawn(move || {
                 let handle = Arc::downgrade(&handle);
-                let mut images: Vec<Image> = Vec::new();
+                let mut frames: Vec<VideoFrame> = Vec::new();
 
-                let src = VideoCapture::from_file(&gen_src(&camera), videoio::CAP_GSTREAMER);
-                let mut src = match src.context("Open video capture") {
+                let src = match rx_fallback {
+                    Some(rx_fallback) => Ok(FrameSource::Fallback(rx_fallback)),
+                    None => VideoCapture::from_file(&gen_src(&camera), videoio::CAP_GSTREAMER)
+                        .map(FrameSource::Stream)
+                        .context("Open video capture"),
+                };
+                let mut src = match src {
                     Ok(src) => src,
                     Err(err) => {
                         let _ = errors.send(err);
@@ -116,10 +351,14 @@ fn handle_added_camera(
 
                 // Loop until the VideoThread component is dropped
                 let mut mat = Mat::default();
+                let mut scaled = Mat::default();
                 let mut proc: Option<BoxedVideoProcessor> = None;
 
+                let mut display_size = None;
+                let mut display_scale = DisplayScale::default();
+
                 while handle.strong_count() > 0 {
-                    let res = src.read(&mut mat).context("Read video frame");
+                    let res = src.read(&mut mat);
 
                     let new_frame = match res {
                         Ok(ret) => ret,
@@ -141,7 +380,19 @@ fn handle_added_camera(
                         proc = new_proc;
                     }
 
+                    if let Some(new_size) = rx_display.try_iter().last() {
+                        display_size = new_size;
+                    }
+
                     if new_frame {
+                        // Publish before any processor modifies the frame
+                        let res = latest_frames.publish(entity, Instant::now(), |frame| {
+                            mat.copy_to(frame).context("Copy latest frame")
+                        });
+                        if let Err(err) = res {
+                            let _ = errors.send(err);
+                        }
+
                         let mat = if let Some(proc_local) = &mut proc {
                             if !proc_local.should_end() {
                                 let res = proc_local.process(&mut mat);
@@ -163,17 +414,36 @@ fn handle_added_camera(
                             &mat
                         };
 
-                        images.extend(rx_bevy.try_iter());
-                        images.truncate(15);
-                        let mut image = images.pop().unwrap_or_default();
+                        let source = match mat.size().context("Get frame size") {
+                            Ok(size) => UVec2::new(size.width as u32, size.height as u32),
+                            Err(err) => {
+                                let _ = errors.send(err);
+                                continue;
+                            }
+                        };
 
-                        let res = mat_to_image(mat, &mut image).context("Mat to image");
+                        // Shrink before converting so small tiles don't cost full size uploads
+                        let target = display_scale.update(source, display_size);
+                        let mat = match video_downscale::scale_for_display(mat, target, &mut scaled)
+                        {
+                            Ok(mat) => mat,
+                            Err(err) => {
+                                let _ = errors.send(err);
+                                mat
+                            }
+                        };
+
+                        frames.extend(rx_bevy.try_iter());
+                        frames.truncate(15);
+                        let mut frame = frames.pop().unwrap_or_else(|| VideoFrame::empty(mode));
+
+                        let res = frame.fill(mat).context("Mat to frame");
                         if let Err(err) = res {
                             let _ = errors.send(err);
                             continue;
                         }
 
-                        let _ = tx_cv.send(image);
+                        let _ = tx_cv.send((frame, source));
                     }
                 }
 
@@ -188,10 +458,14 @@ fn handle_added_camera(
 }
 
 fn handle_frames(
-    cameras: Query<
+    mut cmds: Commands,
+    mut cameras: Query<
         (
+            Entity,
             &VideoThread,
             &Handle<Image>,
+            Option<&mut PendingConversion>,
+            Option<&mut VideoStats>,
             Option<&Handle<StandardMaterial>>,
             Option<&Handle<ColorMaterial>>,
         ),
@@ -200,23 +474,63 @@ fn handle_frames(
     mut images: ResMut<Assets<Image>>,
     mut image_events1: EventWriter<AssetEvent<StandardMaterial>>,
     mut image_events2: EventWriter<AssetEvent<ColorMaterial>>,
+    time: Res<Time<Real>>,
 ) {
-    for (thread, handle, material, color) in &cameras {
+    for (entity, thread, handle, pending, stats, material, color) in &mut cameras {
+        let mut received = 0;
         let latest = thread.2.try_iter().fold(None, |last, next| {
-            if let Some(last) = last {
+            received += 1;
+
+            if let Some((last, _)) = last {
                 let _ = thread.1.send(last);
             }
 
             Some(next)
         });
 
-        if let Some(latest) = latest {
-            let Some(image) = images.get_mut(handle) else {
+        if let Some((latest, source)) = latest {
+            if let Some(mut stats) = stats {
+                stats.record(received, source, latest.size(), time.elapsed());
+            }
+
+            // Mutable access marks the image modified and reuploads it, so only take it when the
+            // image is actually written here
+            let Some(image) = images.get(handle) else {
                 warn!("Couldnt get render asset for image");
                 continue;
             };
-            let old = mem::replace(image, latest);
-            let _ = thread.1.send(old);
+
+            match latest {
+                VideoFrame::Rgba(latest) => {
+                    let image = images.get_mut(handle).expect("Image exists");
+                    let old = mem::replace(image, latest);
+                    let _ = thread.1.send(VideoFrame::Rgba(old));
+                }
+                VideoFrame::Bgr(latest) => {
+                    let (width, height) = (latest.width, latest.height);
+                    let resized = !video_conversion::is_conversion_target(image, width, height);
+
+                    match pending {
+                        Some(mut pending) => {
+                            if let Some(old) = pending.replace(latest) {
+                                let _ = thread.1.send(VideoFrame::Bgr(old));
+                            }
+                        }
+                        None => {
+                            cmds.entity(entity)
+                                .insert(PendingConversion(Arc::new(latest)));
+                        }
+                    }
+
+                    // The image is written on the gpu, only touch it when the resolution changes
+                    if !resized {
+                        continue;
+                    }
+
+                    let image = images.get_mut(handle).expect("Image exists");
+                    *image = video_conversion::conversion_target(width, height);
+                }
+            }
 
             // This shouldnt be the responsibility of this system but oh well
             if let Some(material) = material {
@@ -280,6 +594,26 @@ fn handle_video_processors(
     }
 }
 
+fn forward_display_sizes(
+    cameras: Query<&VideoThread, With<Camera>>,
+    resized: Query<(&VideoThread, &DesiredDisplaySize), Changed<DesiredDisplaySize>>,
+    mut removed: RemovedComponents<DesiredDisplaySize>,
+    mut errors: EventWriter<ErrorEvent>,
+) {
+    let sizes = removed
+        .read()
+        .filter_map(|entity| cameras.get(entity).ok())
+        .map(|thread| (thread, None))
+        .chain(resized.iter().map(|(thread, size)| (thread, Some(size.0))));
+
+    for (thread, size) in sizes {
+        let rst = thread.4.send(size);
+        if rst.is_err() {
+            errors.send(anyhow!("Could not send display size to video thread").into());
+        }
+    }
+}
+
 /// Generates the gstreamer pipeline to recieve data from `camera`
 fn gen_src(camera: &Camera) -> String {
     let ip = camera.location.ip();
@@ -290,7 +624,7 @@ fn gen_src(camera: &Camera) -> String {
 }
 
 /// Efficiently converts opencv `Mat`s to bevy `Image`s
-fn mat_to_image(mat: &Mat, image: &mut Image) -> anyhow::Result<()> {
+pub fn mat_to_image(mat: &Mat, image: &mut Image) -> anyhow::Result<()> {
     // Convert opencv size to bevy size
     let size = mat.size().context("Get size")?;
     let extent = Extent3d {
@@ -332,3 +666,313 @@ fn mat_to_image(mat: &Mat, image: &mut Image) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use std::{
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use bevy::{
+        app::{App, PostUpdate, Update},
+        asset::{AssetEvent, Assets, Handle},
+        ecs::{entity::Entity, event::Events, system::IntoSystem},
+        math::UVec2,
+        pbr::StandardMaterial,
+        render::texture::Image,
+        sprite::ColorMaterial,
+        time::{Real, Time},
+    };
+    use crossbeam::channel::{self, Receiver, Sender};
+    use common::{
+        components::{Camera, CameraFallbackStream, RobotId},
+        ecs_sync::NetId,
+        error::{self, ErrorPlugin},
+        types::ids::CameraId,
+    };
+    use opencv::{
+        core::{Scalar, Vector, CV_8UC3},
+        imgcodecs,
+        prelude::*,
+    };
+    use test::Bencher;
+
+    use crate::{
+        video_conversion::{VideoConversionMode, VideoFrame},
+        video_fallback::FallbackFeed,
+    };
+
+    use super::{
+        decode_jpeg, handle_added_camera, handle_frames, FrameCache, LatestFrames, VideoThread,
+    };
+
+    const MAX_AGE: Duration = Duration::from_millis(100);
+
+    fn publish(cache: &FrameCache<u32>, camera: Entity, captured: Instant, value: u32) {
+        cache
+            .publish(camera, captured, |frame| {
+                *frame = value;
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn only_subscribed_cameras_are_cached() {
+        let cache = FrameCache::<u32>::default();
+        let camera = Entity::from_raw(1);
+        let now = Instant::now();
+
+        publish(&cache, camera, now, 1);
+        assert_eq!(cache.read_latest(camera, now, MAX_AGE, |it| *it), None);
+
+        let subscription = cache.subscribe(camera);
+        assert_eq!(subscription.read_latest(now, MAX_AGE, |it| *it), None);
+
+        publish(&cache, camera, now, 2);
+        assert_eq!(subscription.read_latest(now, MAX_AGE, |it| *it), Some(2));
+
+        let other = cache.subscribe(camera);
+        drop(subscription);
+        assert_eq!(other.read_latest(now, MAX_AGE, |it| *it), Some(2));
+
+        drop(other);
+        assert_eq!(cache.read_latest(camera, now, MAX_AGE, |it| *it), None);
+    }
+
+    #[test]
+    fn stale_frames_are_ignored() {
+        let cache = FrameCache::<u32>::default();
+        let camera = Entity::from_raw(1);
+        let start = Instant::now();
+
+        let subscription = cache.subscribe(camera);
+        publish(&cache, camera, start, 1);
+
+        assert_eq!(
+            subscription.read_latest(start + MAX_AGE, MAX_AGE, |it| *it),
+            Some(1)
+        );
+        assert_eq!(
+            subscription.read_latest(start + MAX_AGE * 2, MAX_AGE, |it| *it),
+            None
+        );
+
+        // A new frame makes it fresh again
+        publish(&cache, camera, start + MAX_AGE * 2, 3);
+        assert_eq!(
+            subscription.read_latest(start + MAX_AGE * 2, MAX_AGE, |it| *it),
+            Some(3)
+        );
+    }
+
+    /// A 64x48 frame of one BGR color
+    fn jpeg(color: [f64; 3]) -> Vec<u8> {
+        let color = Scalar::new(color[0], color[1], color[2], 0.0);
+        let mat = Mat::new_rows_cols_with_default(48, 64, CV_8UC3, color).unwrap();
+
+        let mut jpeg = Vector::<u8>::new();
+        imgcodecs::imencode_def(".jpg", &mat, &mut jpeg).unwrap();
+
+        jpeg.to_vec()
+    }
+
+    #[test]
+    fn fallback_frames_decode() {
+        let mut mat = Mat::default();
+
+        decode_jpeg(&jpeg([200.0, 100.0, 50.0]), &mut mat).unwrap();
+        assert_eq!((mat.cols(), mat.rows()), (64, 48));
+
+        assert!(decode_jpeg(b"not a jpeg", &mut mat).is_err());
+        assert!(decode_jpeg(&[], &mut mat).is_err());
+    }
+
+    #[test]
+    fn fallback_frames_fill_the_camera_image() {
+        let mut app = App::new();
+        app.add_plugins(ErrorPlugin)
+            .init_resource::<Assets<Image>>()
+            .init_resource::<VideoConversionMode>()
+            .init_resource::<LatestFrames>()
+            .init_resource::<Time<Real>>()
+            .add_event::<AssetEvent<StandardMaterial>>()
+            .add_event::<AssetEvent<ColorMaterial>>()
+            .add_systems(
+                Update,
+                (
+                    handle_added_camera.pipe(error::handle_errors),
+                    handle_frames,
+                ),
+            );
+
+        let robot = RobotId(NetId::random());
+        app.world.spawn((
+            CameraFallbackStream {
+                camera: CameraId::from_static("Front"),
+                fps: 5,
+                quality: 60,
+            },
+            robot,
+        ));
+        let camera = app
+            .world
+            .spawn((
+                Camera {
+                    id: CameraId::from_static("Front"),
+                    location: "0.0.0.0:0".parse().unwrap(),
+                },
+                robot,
+            ))
+            .id();
+        app.update();
+
+        let feed = app.world.get::<FallbackFeed>(camera).unwrap();
+        feed.0.send(jpeg([0.0, 0.0, 255.0])).unwrap();
+
+        // The video thread decodes it into the image streamed frames would use
+        let mut size = UVec2::ONE;
+        for _ in 0..200 {
+            app.update();
+
+            let handle = app.world.get::<Handle<Image>>(camera).unwrap();
+            let image = app.world.resource::<Assets<Image>>().get(handle).unwrap();
+            size = image.size();
+
+            // Starts out as a 1x1 placeholder
+            if size == UVec2::new(64, 48) {
+                // Red, as RGBA
+                let pixel = &image.data[..4];
+                assert!(
+                    pixel[0] > 230 && pixel[1] < 25 && pixel[2] < 25,
+                    "{pixel:?}"
+                );
+                break;
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(size, UVec2::new(64, 48));
+    }
+
+    #[test]
+    fn failed_writes_drop_the_frame() {
+        let cache = FrameCache::<u32>::default();
+        let camera = Entity::from_raw(1);
+        let now = Instant::now();
+
+        let subscription = cache.subscribe(camera);
+        publish(&cache, camera, now, 1);
+
+        let res = cache.publish(camera, now, |_| Err(anyhow::anyhow!("Bad frame")));
+        assert!(res.is_err());
+        assert_eq!(subscription.read_latest(now, MAX_AGE, |it| *it), None);
+    }
+
+    /// Runs `handle_frames` for one camera fed from the returned channels instead of a video
+    /// thread, recycled frames come back on the receiver
+    fn frame_app() -> (
+        App,
+        Handle<Image>,
+        Sender<(VideoFrame, UVec2)>,
+        Receiver<VideoFrame>,
+    ) {
+        let mut app = App::new();
+        app.init_resource::<Assets<Image>>()
+            .init_resource::<Time<Real>>()
+            .add_event::<AssetEvent<Image>>()
+            .add_event::<AssetEvent<StandardMaterial>>()
+            .add_event::<AssetEvent<ColorMaterial>>()
+            .add_systems(Update, handle_frames)
+            .add_systems(PostUpdate, Assets::<Image>::asset_events);
+
+        let (tx_cv, rx_cv) = channel::bounded(10);
+        let (tx_bevy, rx_bevy) = channel::bounded(10);
+        let (tx_proc, _) = channel::bounded(10);
+        let (tx_display, _) = channel::unbounded();
+
+        let image = app
+            .world
+            .resource_mut::<Assets<Image>>()
+            .add(Image::default());
+        app.world.spawn((
+            Camera {
+                id: CameraId::from_static("Front"),
+                location: "0.0.0.0:0".parse().unwrap(),
+            },
+            VideoThread(Arc::new(()), tx_bevy, rx_cv, tx_proc, tx_display),
+            image.clone(),
+        ));
+
+        (app, image, tx_cv, rx_bevy)
+    }
+
+    fn modified(app: &mut App, image: &Handle<Image>) -> usize {
+        app.world
+            .resource_mut::<Events<AssetEvent<Image>>>()
+            .drain()
+            .filter(|it| it.is_modified(image))
+            .count()
+    }
+
+    #[test]
+    fn gpu_frames_only_touch_the_image_on_resize() {
+        let mat = Mat::new_rows_cols_with_default(48, 64, CV_8UC3, Scalar::all(90.0)).unwrap();
+
+        for (mode, expected) in [
+            (VideoConversionMode::Cpu, [1, 1, 1]),
+            (VideoConversionMode::Gpu, [1, 0, 0]),
+        ] {
+            let (mut app, image, tx_frames, rx_recycled) = frame_app();
+            app.update();
+            modified(&mut app, &image);
+
+            for (idx, expected) in expected.into_iter().enumerate() {
+                let mut frame = rx_recycled
+                    .try_recv()
+                    .unwrap_or_else(|_| VideoFrame::empty(mode));
+                frame.fill(&mat).unwrap();
+                tx_frames.send((frame, UVec2::new(64, 48))).unwrap();
+
+                app.update();
+                assert_eq!(
+                    modified(&mut app, &image),
+                    expected,
+                    "{mode:?} frame {idx}"
+                );
+            }
+        }
+    }
+
+    /// Video thread and `handle_frames` side of a 1080p frame, the GPU path's shader dispatch and
+    /// the render world's upload of modified images aren't included
+    fn bench_frame_path(b: &mut Bencher, mode: VideoConversionMode) {
+        let mat = Mat::new_rows_cols_with_default(1080, 1920, CV_8UC3, Scalar::all(90.0)).unwrap();
+        let (mut app, _, tx_frames, rx_recycled) = frame_app();
+
+        b.iter(|| {
+            let mut frame = rx_recycled
+                .try_recv()
+                .unwrap_or_else(|_| VideoFrame::empty(mode));
+            frame.fill(&mat).unwrap();
+            tx_frames.send((frame, UVec2::new(1920, 1080))).unwrap();
+
+            app.update();
+        });
+    }
+
+    #[bench]
+    fn bench_cpu_frame_path(b: &mut Bencher) {
+        bench_frame_path(b, VideoConversionMode::Cpu);
+    }
+
+    #[bench]
+    fn bench_gpu_frame_path(b: &mut Bencher) {
+        bench_frame_path(b, VideoConversionMode::Gpu);
+    }
+}
@@ -0,0 +1,326 @@
+//! Replays a recorded run's movement requests through the motor allocator
+//!
+//! Writes what each motor would have been commanded and sums up the run, `--compare` replays the
+//! same run through a second robot config to see how a change to the solver or config would have
+//! played out
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use clap::Parser;
+use glam::Vec3A;
+use motor_math::{
+    blue_rov::HeavyMotorId,
+    motor_preformance::{self, Extrapolation, Interpolation, MotorData},
+    replay::{self, Allocation, AllocatorSettings, MovementSample, ReplaySummary},
+    x3d::X3dMotorId,
+    ErasedMotorId, Motor, MotorConfig,
+};
+use serde::Deserialize;
+
+#[derive(Parser, Debug, Clone, PartialEq)]
+#[command(
+    name = "solve-replay",
+    about = "Replays recorded movements through the motor allocator"
+)]
+struct Args {
+    /// Movement log, a CSV with `time,force_x,force_y,force_z,torque_x,torque_y,torque_z` columns
+    log: PathBuf,
+    /// Robot config to take the motor config, amperage budget and jerk limit from
+    #[arg(long, default_value = "robot/robot.toml")]
+    config: PathBuf,
+    /// Motor performance table
+    #[arg(long, default_value = "robot/motor_data.csv")]
+    motor_data: PathBuf,
+    /// Where to write each motor's commands as a CSV
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// Robot config to replay the same log through and compare against
+    #[arg(long)]
+    compare: Option<PathBuf>,
+    /// Motor performance table used with `--compare`, defaults to `--motor-data`
+    #[arg(long, requires = "compare")]
+    compare_motor_data: Option<PathBuf>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let samples = replay::read_movement_log(&args.log)
+        .with_context(|| format!("Read {}", args.log.display()))?;
+    let allocator = Allocator::load(&args.config, &args.motor_data)?;
+
+    let allocations = allocator.replay(&samples);
+    let summary = ReplaySummary::new(&allocations);
+
+    if let Some(output) = &args.output {
+        fs::write(output, commands_csv(&allocator.names, &allocations))
+            .with_context(|| format!("Write {}", output.display()))?;
+    }
+
+    match &args.compare {
+        Some(compare) => {
+            let motor_data = args.compare_motor_data.as_ref().unwrap_or(&args.motor_data);
+            let other = Allocator::load(compare, motor_data)?;
+            let other_summary = ReplaySummary::new(&other.replay(&samples));
+
+            print!("{}", compare_text(&summary, &other_summary));
+        }
+        None => print!("{}", summary_text(&summary)),
+    }
+
+    Ok(())
+}
+
+/// The parts of a robot config the allocator depends on, the rest is ignored
+#[derive(Debug, Clone, Deserialize)]
+struct AllocatorDefinition {
+    motor_config: MotorConfigDefinition,
+    motor_amperage_budget: f32,
+    #[serde(default)]
+    smooth_motor_curves: bool,
+    #[serde(default)]
+    motor_extrapolation: Extrapolation,
+    jerk_limit: f32,
+    center_of_mass: Vec3A,
+}
+
+/// Mirrors the robot's motor config definition without the PWM channels
+#[derive(Debug, Clone, Deserialize)]
+enum MotorConfigDefinition {
+    X3d {
+        seed_motor: Motor,
+    },
+    BlueRov {
+        vertical_seed_motor: Motor,
+        lateral_seed_motor: Motor,
+    },
+    Custom {
+        motors: BTreeMap<String, CustomMotor>,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CustomMotor {
+    motor: Motor,
+}
+
+impl MotorConfigDefinition {
+    /// Builds the motor config the same way the robot does, also returns each motor's name in
+    /// index order
+    fn to_motor_config(&self, center_mass: Vec3A) -> (MotorConfig<ErasedMotorId>, Vec<String>) {
+        match self {
+            MotorConfigDefinition::X3d { seed_motor } => {
+                let config = MotorConfig::<X3dMotorId>::new(*seed_motor, center_mass);
+                let names = config.motors().map(|(id, _)| format!("{id:?}")).collect();
+
+                (config.erase(), names)
+            }
+            MotorConfigDefinition::BlueRov {
+                vertical_seed_motor,
+                lateral_seed_motor,
+            } => {
+                let config = MotorConfig::<HeavyMotorId>::new(
+                    *lateral_seed_motor,
+                    *vertical_seed_motor,
+                    center_mass,
+                );
+                let names = config.motors().map(|(id, _)| format!("{id:?}")).collect();
+
+                (config.erase(), names)
+            }
+            MotorConfigDefinition::Custom { motors } => {
+                let config = MotorConfig::new_raw(
+                    motors
+                        .values()
+                        .enumerate()
+                        .map(|(idx, it)| (idx as ErasedMotorId, it.motor)),
+                    center_mass,
+                );
+                let names = motors.keys().cloned().collect();
+
+                (config, names)
+            }
+        }
+    }
+}
+
+struct Allocator {
+    motor_config: MotorConfig<ErasedMotorId>,
+    /// Motor names in [`MotorConfig::index_of`] order
+    names: Vec<String>,
+    motor_data: MotorData,
+    settings: AllocatorSettings,
+}
+
+impl Allocator {
+    fn load(config: &Path, motor_data: &Path) -> anyhow::Result<Self> {
+        let definition =
+            fs::read_to_string(config).with_context(|| format!("Read {}", config.display()))?;
+        let definition: AllocatorDefinition =
+            toml::from_str(&definition).with_context(|| format!("Parse {}", config.display()))?;
+
+        let motor_data = motor_preformance::read_motor_data(motor_data)
+            .with_context(|| format!("Read {}", motor_data.display()))?
+            .with_extrapolation(definition.motor_extrapolation);
+
+        let (motor_config, names) = definition
+            .motor_config
+            .to_motor_config(definition.center_of_mass);
+
+        Ok(Self {
+            motor_config,
+            names,
+            motor_data,
+            settings: AllocatorSettings {
+                amperage_cap: definition.motor_amperage_budget,
+                jerk_limit: Some(definition.jerk_limit),
+                interpolation: if definition.smooth_motor_curves {
+                    Interpolation::Cubic
+                } else {
+                    Interpolation::Lerp
+                },
+            },
+        })
+    }
+
+    fn replay(&self, samples: &[MovementSample]) -> Vec<Allocation> {
+        replay::replay(samples, &self.motor_config, &self.motor_data, self.settings)
+    }
+}
+
+fn commands_csv(names: &[String], allocations: &[Allocation]) -> String {
+    let mut csv = String::from("time,clamped");
+    for name in names {
+        let _ = write!(csv, ",{name}_pwm,{name}_force_n,{name}_current_a");
+    }
+    csv.push('\n');
+
+    for allocation in allocations {
+        let _ = write!(csv, "{:.3},{}", allocation.time, allocation.clamped);
+        for cmd in &allocation.motor_cmds {
+            let _ = write!(csv, ",{:.0},{:.3},{:.3}", cmd.pwm, cmd.force, cmd.current);
+        }
+        csv.push('\n');
+    }
+
+    csv
+}
+
+fn summary_text(summary: &ReplaySummary) -> String {
+    format!(
+        "Samples:         {}\n\
+         Energy:          {:.1} J\n\
+         Max current:     {:.2} A\n\
+         Clamped samples: {}\n",
+        summary.samples, summary.energy, summary.max_current, summary.clamped_samples
+    )
+}
+
+fn compare_text(base: &ReplaySummary, other: &ReplaySummary) -> String {
+    let clamped_delta = other.clamped_samples as i64 - base.clamped_samples as i64;
+
+    format!(
+        "                 {:>10} {:>10} {:>10}\n\
+         Energy (J):      {:>10.1} {:>10.1} {:>+10.1}\n\
+         Max current (A): {:>10.2} {:>10.2} {:>+10.2}\n\
+         Clamped samples: {:>10} {:>10} {:>+10}\n",
+        "Base",
+        "Compare",
+        "Delta",
+        base.energy,
+        other.energy,
+        other.energy - base.energy,
+        base.max_current,
+        other.max_current,
+        other.max_current - base.max_current,
+        base.clamped_samples,
+        other.clamped_samples,
+        clamped_delta,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use motor_math::replay::{self, ReplaySummary, AMPERAGE_EPSILON};
+
+    use super::{commands_csv, compare_text, Allocator};
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures");
+    const MOTOR_DATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../robot/motor_data.csv");
+
+    fn fixture() -> (Allocator, Vec<replay::MovementSample>) {
+        let fixtures = Path::new(FIXTURES);
+        let allocator =
+            Allocator::load(&fixtures.join("robot.toml"), Path::new(MOTOR_DATA)).unwrap();
+        let samples = replay::read_movement_log(fixtures.join("movement_log.csv")).unwrap();
+
+        (allocator, samples)
+    }
+
+    #[test]
+    fn fixture_summary() {
+        let (allocator, samples) = fixture();
+        let allocations = allocator.replay(&samples);
+        let summary = ReplaySummary::new(&allocations);
+
+        assert_eq!(summary.samples, 6);
+        // Only the two 400 N requests are more than the robot can supply
+        assert_eq!(summary.clamped_samples, 2);
+        assert!(allocations[3].clamped && allocations[4].clamped);
+        assert!((summary.max_current - 25.0).abs() < AMPERAGE_EPSILON);
+
+        // Idle samples draw nothing and the last sample has no duration, so the energy is two
+        // seconds at the cap plus two seconds of light thrust
+        assert_eq!(
+            allocations[0]
+                .motor_cmds
+                .iter()
+                .map(|it| it.current)
+                .sum::<f32>(),
+            0.0
+        );
+        assert!(summary.energy > 2.0 * 24.0 * 12.0, "{summary:?}");
+        assert!(summary.energy < 2.0 * 26.0 * 12.0 + 100.0, "{summary:?}");
+
+        let csv = commands_csv(&allocator.names, &allocations);
+        let mut lines = csv.lines();
+        let header = lines.next().unwrap();
+        assert_eq!(header.split(',').count(), 2 + 3 * 8);
+        assert!(header.contains("FrontRightTop_current_a"));
+        assert_eq!(lines.count(), 6);
+    }
+
+    #[test]
+    fn compare_reports_deltas() {
+        let (allocator, samples) = fixture();
+        let base = ReplaySummary::new(&allocator.replay(&samples));
+
+        // The same config changes nothing
+        let same = ReplaySummary::new(&allocator.replay(&samples));
+        assert_eq!(base, same);
+        assert!(compare_text(&base, &same).contains("+0"));
+
+        // A tighter budget clamps more and draws less
+        let tight = Allocator {
+            settings: replay::AllocatorSettings {
+                amperage_cap: 15.0,
+                ..allocator.settings
+            },
+            ..allocator
+        };
+        let tight = ReplaySummary::new(&tight.replay(&samples));
+
+        assert!(tight.clamped_samples >= base.clamped_samples);
+        assert!((tight.max_current - 15.0).abs() < AMPERAGE_EPSILON);
+        assert!(tight.energy < base.energy);
+        assert!(compare_text(&base, &tight).contains("Clamped samples"));
+    }
+}